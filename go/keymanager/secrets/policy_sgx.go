@@ -13,6 +13,9 @@ import (
 // PolicySGXSignatureContext is the context used to sign PolicySGX documents.
 var PolicySGXSignatureContext = signature.NewContext("oasis-core/keymanager: policy")
 
+// KeyNamespace is an application key namespace.
+type KeyNamespace [32]byte
+
 // PolicySGX is a key manager access control policy for the replicated
 // SGX key manager.
 type PolicySGX struct {
@@ -31,6 +34,10 @@ type PolicySGX struct {
 
 	// MaxEphemeralSecretAge is the maximum age of an ephemeral secret in the number of epochs.
 	MaxEphemeralSecretAge beacon.EpochTime `json:"max_ephemeral_secret_age,omitempty"`
+
+	// Revoked is the emergency revocation list of enclave identities which must immediately
+	// be denied sessions and key material, regardless of the rest of the policy.
+	Revoked []sgx.EnclaveIdentity `json:"revoked,omitempty"`
 }
 
 // EnclavePolicySGX is the per-SGX key manager enclave ID access control policy.
@@ -48,6 +55,12 @@ type EnclavePolicySGX struct {
 	// NOTE: Each enclave ID may always implicitly replicate from other
 	// instances of itself.
 	MayReplicate []sgx.EnclaveIdentity `json:"may_replicate"`
+
+	// MayQueryNamespaces is the map of runtime IDs to application key namespaces and the
+	// vector of enclave IDs that may query private key material in each namespace.
+	//
+	// Private keys of runtimes listed here may only be queried within a namespace.
+	MayQueryNamespaces map[common.Namespace]map[KeyNamespace][]sgx.EnclaveIdentity `json:"may_query_namespaces,omitempty"`
 }
 
 // SignedPolicySGX is a signed SGX key manager access control policy.
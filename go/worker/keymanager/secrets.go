@@ -261,6 +261,11 @@ func (w *secretsWorker) authorizeNode(ctx context.Context, peerID core.PeerID, k
 					return nil
 				}
 			}
+			for rtID := range enc.MayQueryNamespaces {
+				if rts.Contains(rtID) {
+					return nil
+				}
+			}
 		}
 		return fmt.Errorf("query not allowed")
 	default:
@@ -1,6 +1,11 @@
 use thiserror::Error;
 
-use oasis_core_runtime::consensus::{state::StateError, verifier};
+use oasis_core_runtime::{
+    common::crypto::signature::PublicKey,
+    consensus::{state::StateError, verifier},
+};
+
+use crate::crypto::DerivationScheme;
 
 /// Key manager error.
 #[derive(Error, Debug)]
@@ -9,6 +14,10 @@ pub enum KeyManagerError {
     NotAuthenticated,
     #[error("client is not authorized")]
     NotAuthorized,
+    #[error("client enclave identity revoked")]
+    EnclaveRevoked,
+    #[error("key namespace required")]
+    KeyNamespaceRequired,
     #[error("invalid epoch: expected {0}, got {1}")]
     InvalidEpoch(u64, u64),
     #[error("invalid generation: expected {0}, got {1}")]
@@ -31,8 +40,12 @@ pub enum KeyManagerError {
     PolicyChanged,
     #[error("policy has invalid runtime")]
     PolicyInvalidRuntime,
-    #[error("insufficient key shares")]
-    InsufficientKeyShares,
+    #[error("insufficient key shares: expected {0}, got {1}")]
+    InsufficientKeyShares(usize, usize),
+    #[error("invalid key share from node {0}")]
+    InvalidKeyShare(PublicKey),
+    #[error("invalid verification matrix")]
+    InvalidVerificationMatrix,
     #[error("insufficient signatures")]
     InsufficientSignatures,
     #[error("runtime signing key missing")]
@@ -57,8 +70,18 @@ pub enum KeyManagerError {
     EphemeralSecretNotPublished,
     #[error("ephemeral secret checksum mismatch")]
     EphemeralSecretChecksumMismatch,
+    #[error("ephemeral key for epoch {0} expired")]
+    EphemeralKeyExpired(u64),
+    #[error("long-term keys for rotation epoch {0} no longer retrievable")]
+    RotationEpochExpired(u64),
+    #[error("rotation of master secret generation {0} not recorded")]
+    RotationRecordMissing(u64),
+    #[error("key release not allowed in restricted mode")]
+    RestrictedMode,
     #[error("invalid ciphertext")]
     InvalidCiphertext,
+    #[error("derivation scheme mismatch: expected {0:?}, got {1:?}")]
+    DerivationSchemeMismatch(DerivationScheme, DerivationScheme),
     #[error("status not found")]
     StatusNotFound,
     #[error("runtime mismatch")]
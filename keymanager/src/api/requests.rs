@@ -14,10 +14,23 @@ use oasis_core_runtime::{
     },
 };
 
-use crate::crypto::{KeyPairId, Secret};
+use crate::crypto::{DerivationScheme, KeyNamespace, KeyPairId, Secret};
+
+/// Maximum age of an ephemeral key in the number of epochs.
+///
+/// Ephemeral keys can be derived for epochs at most this many epochs in the past, relative to
+/// the current epoch of the consensus layer.
+pub const MAX_EPHEMERAL_KEY_AGE: EpochTime = 10;
+
+/// Maximum number of master secret generations prior to the latest one whose long-term keys can
+/// be retrieved by rotation epoch.
+///
+/// This bounds the window during which runtimes can migrate state encrypted under keys of past
+/// generations.
+pub const MAX_KEY_ROTATION_AGE: u64 = 2;
 
 /// Context used for the init response signature.
-const INIT_RESPONSE_CONTEXT: &[u8] = b"oasis-core/keymanager: init response";
+pub(crate) const INIT_RESPONSE_CONTEXT: &[u8] = b"oasis-core/keymanager: init response";
 
 /// Key manager initialization request.
 #[derive(Clone, Default, cbor::Encode, cbor::Decode)]
@@ -166,6 +179,27 @@ pub struct LongTermKeyRequest {
     /// Generation.
     #[cbor(optional)]
     pub generation: u64,
+    /// Whether the public key should be signed with a validity window, allowing clients to
+    /// cache it until the signature expires.
+    #[cbor(optional)]
+    pub with_validity: bool,
+    /// Application key namespace.
+    #[cbor(optional)]
+    pub namespace: Option<KeyNamespace>,
+    /// Key derivation scheme.
+    ///
+    /// Key managers not supporting versioned derivation ignore the scheme and derive keys with
+    /// the legacy scheme, which is reflected in the response.
+    #[cbor(optional)]
+    pub scheme: DerivationScheme,
+}
+
+impl LongTermKeyRequest {
+    /// Key pair ID the keys are derived for.
+    pub fn derived_key_pair_id(&self) -> KeyPairId {
+        self.scheme
+            .key_pair_id(self.key_pair_id.namespaced(self.namespace.as_ref()))
+    }
 }
 
 /// Ephemeral key request for private/public key generation and retrieval.
@@ -183,4 +217,14 @@ pub struct EphemeralKeyRequest {
     pub key_pair_id: KeyPairId,
     /// Epoch time.
     pub epoch: EpochTime,
+    /// Application key namespace.
+    #[cbor(optional)]
+    pub namespace: Option<KeyNamespace>,
+}
+
+impl EphemeralKeyRequest {
+    /// Key pair ID the keys are derived for.
+    pub fn derived_key_pair_id(&self) -> KeyPairId {
+        self.key_pair_id.namespaced(self.namespace.as_ref())
+    }
 }
@@ -41,6 +41,8 @@ pub enum Error {
     InvalidShareholder,
     #[error("invalid verification matrix checksum")]
     InvalidVerificationMatrixChecksum,
+    #[error("key share proof decoding failed")]
+    KeyShareProofDecodingFailed,
     #[error("not authenticated")]
     NotAuthenticated,
     #[error("not authorized")]
@@ -53,6 +55,8 @@ pub enum Error {
     PolicyRollback,
     #[error("polynomial decoding failed")]
     PolynomialDecodingFailed,
+    #[error("key release not allowed in restricted mode")]
+    RestrictedMode,
     #[error("runtime mismatch")]
     RuntimeMismatch,
     #[error("shareholder mismatch")]
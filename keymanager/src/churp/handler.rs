@@ -8,13 +8,13 @@ use std::{
 
 use anyhow::Result;
 use group::{Group, GroupEncoding};
-use rand::rngs::OsRng;
 use sp800_185::KMac;
 
 use oasis_core_runtime::{
     common::{
         crypto::{
             hash::Hash,
+            rng::SecureRng,
             signature::{PublicKey, Signer},
         },
         namespace::Namespace,
@@ -51,21 +51,21 @@ use crate::{
 };
 
 use super::{
-    storage::Storage, ApplicationRequest, ConfirmationRequest, EncodedEncryptedPoint,
+    storage::Storage, ApplicationRequest, ConfirmationRequest, EncodedKeyShare,
     EncodedVerifiableSecretShare, Error, FetchRequest, FetchResponse, HandoffRequest,
-    KeyShareRequest, QueryRequest, SignedApplicationRequest, SignedConfirmationRequest,
-    State as ChurpState, VerifiedPolicies,
+    KeyShareProof, KeyShareRequest, QueryRequest, SignedApplicationRequest,
+    SignedConfirmationRequest, State as ChurpState, VerifiedPolicies,
 };
 
 /// A handoff interval that disables handoffs.
 pub const HANDOFFS_DISABLED: EpochTime = 0xffffffffffffffff;
 
 /// Signature context for signing application requests.
-const APPLICATION_REQUEST_SIGNATURE_CONTEXT: &[u8] =
+pub(crate) const APPLICATION_REQUEST_SIGNATURE_CONTEXT: &[u8] =
     b"oasis-core/keymanager/churp: application request";
 
 /// Signature context for signing confirmation requests.
-const CONFIRMATION_REQUEST_SIGNATURE_CONTEXT: &[u8] =
+pub(crate) const CONFIRMATION_REQUEST_SIGNATURE_CONTEXT: &[u8] =
     b"oasis-core/keymanager/churp: confirmation request";
 
 /// Custom KMAC domain separation for checksums of verification matrices.
@@ -93,6 +93,10 @@ const ENCODE_SGX_POLICY_KEY_ID_CONTEXT: &[u8] =
 const ENCODE_CUSTOM_POLICY_KEY_ID_CONTEXT: &[u8] =
     b"oasis-core/keymanager/churp: encode custom policy key ID";
 
+/// Domain separation tag for proofs that key shares were derived from
+/// the committed secret shares.
+const KEY_SHARE_PROOF_CONTEXT: &[u8] = b"oasis-core/keymanager/churp: key share proof";
+
 /// The runtime separator used to add additional domain separation based
 /// on the runtime ID.
 const RUNTIME_CONTEXT_SEPARATOR: &[u8] = b" for runtime ";
@@ -236,13 +240,16 @@ pub(crate) trait Handler: Send + Sync {
     ///     KS_i = s_i * H(key_id)
     /// ```
     ///
+    /// The key share is accompanied by a proof that it was derived from
+    /// the secret share committed to in the verification matrix.
+    ///
     /// WARNING: This method must be called over a secure channel as the key
     /// share needs to be kept secret and generated only for authorized nodes.
     fn sgx_policy_key_share(
         &self,
         ctx: &RpcContext,
         req: &KeyShareRequest,
-    ) -> Result<EncodedEncryptedPoint>;
+    ) -> Result<EncodedKeyShare>;
 
     /// Prepare CHURP for participation in the given handoff of the protocol.
     ///
@@ -377,6 +384,15 @@ impl Churp {
         }
     }
 
+    /// Ensure the runtime identity is not restricted, as restricted key managers must not
+    /// release any shares.
+    fn ensure_unrestricted(&self) -> Result<()> {
+        if self.identity.is_restricted() {
+            return Err(Error::RestrictedMode.into());
+        }
+        Ok(())
+    }
+
     fn get_instance(&self, churp_id: u8, runtime_id: Namespace) -> Result<Arc<dyn Handler>> {
         // Ensure runtime_id matches.
         if self.runtime_id != runtime_id {
@@ -424,6 +440,7 @@ impl Handler for Churp {
         ctx: &RpcContext,
         req: &QueryRequest,
     ) -> Result<Vec<u8>> {
+        self.ensure_unrestricted()?;
         let instance = self.get_instance(req.id, req.runtime_id)?;
         instance.share_reduction_switch_point(ctx, req)
     }
@@ -433,6 +450,7 @@ impl Handler for Churp {
         ctx: &RpcContext,
         req: &QueryRequest,
     ) -> Result<Vec<u8>> {
+        self.ensure_unrestricted()?;
         let instance = self.get_instance(req.id, req.runtime_id)?;
         instance.share_distribution_switch_point(ctx, req)
     }
@@ -442,6 +460,7 @@ impl Handler for Churp {
         ctx: &RpcContext,
         req: &QueryRequest,
     ) -> Result<EncodedVerifiableSecretShare> {
+        self.ensure_unrestricted()?;
         let instance = self.get_instance(req.id, req.runtime_id)?;
         instance.bivariate_share(ctx, req)
     }
@@ -450,7 +469,8 @@ impl Handler for Churp {
         &self,
         ctx: &RpcContext,
         req: &KeyShareRequest,
-    ) -> Result<EncodedEncryptedPoint> {
+    ) -> Result<EncodedKeyShare> {
+        self.ensure_unrestricted()?;
         let instance = self.get_instance(req.id, req.runtime_id)?;
         instance.sgx_policy_key_share(ctx, req)
     }
@@ -530,6 +550,8 @@ struct Instance<S: Suite> {
     /// Domain separation tag for encoding key identifiers for key share
     /// derivation approved by an SGX policy.
     sgx_policy_key_id_dst: Vec<u8>,
+    /// Domain separation tag for proofs of key share derivation.
+    key_share_proof_dst: Vec<u8>,
 }
 
 impl<S: Suite> Instance<S> {
@@ -556,10 +578,9 @@ impl<S: Suite> Instance<S> {
         let dealer = Mutex::new(None);
         let handoff = Mutex::new(None);
 
-        let shareholder_dst =
-            Self::domain_separation_tag(ENCODE_SHAREHOLDER_CONTEXT, &runtime_id, churp_id);
-        let sgx_policy_key_id_dst =
-            Self::domain_separation_tag(ENCODE_SGX_POLICY_KEY_ID_CONTEXT, &runtime_id, churp_id);
+        let shareholder_dst = shareholder_domain_separation_tag(&runtime_id, churp_id);
+        let sgx_policy_key_id_dst = sgx_policy_key_id_domain_separation_tag(&runtime_id, churp_id);
+        let key_share_proof_dst = key_share_proof_domain_separation_tag(&runtime_id, churp_id);
 
         Self {
             churp_id,
@@ -579,6 +600,7 @@ impl<S: Suite> Instance<S> {
             policies,
             shareholder_dst,
             sgx_policy_key_id_dst,
+            key_share_proof_dst,
         }
     }
 
@@ -880,8 +902,8 @@ impl<S: Suite> Instance<S> {
     ) -> Result<Arc<Dealer<S::Group>>> {
         // Create a new dealer.
         let dealer = match dealing_phase {
-            true => Dealer::new(threshold, &mut OsRng),
-            false => Dealer::new_proactive(threshold, &mut OsRng),
+            true => Dealer::new(threshold, &mut SecureRng),
+            false => Dealer::new_proactive(threshold, &mut SecureRng),
         }?;
         let dealer = Arc::new(dealer);
 
@@ -1110,16 +1132,14 @@ impl<S: Suite> Instance<S> {
 
     /// Returns the session RAK of the remote enclave.
     fn remote_rak(ctx: &RpcContext) -> Result<PublicKey> {
-        let si = ctx.session_info.as_ref();
-        let si = si.ok_or(Error::NotAuthenticated)?;
-        Ok(si.rak_binding.rak_pub())
+        let caller = ctx.caller().ok_or(Error::NotAuthenticated)?;
+        Ok(caller.rak)
     }
 
     /// Returns the identity of the remote enclave.
     fn remote_enclave(ctx: &RpcContext) -> Result<&EnclaveIdentity> {
-        let si = ctx.session_info.as_ref();
-        let si = si.ok_or(Error::NotAuthenticated)?;
-        Ok(&si.verified_attestation.quote.identity)
+        let caller = ctx.caller().ok_or(Error::NotAuthenticated)?;
+        Ok(&caller.enclave_identity)
     }
 
     /// Returns true if key manager policies should be ignored.
@@ -1169,13 +1189,7 @@ impl<S: Suite> Instance<S> {
 
     /// Computes the checksum of the verification matrix bytes.
     fn checksum_verification_matrix_bytes(&self, bytes: &Vec<u8>, epoch: EpochTime) -> Hash {
-        let mut checksum = [0u8; 32];
-        let mut f = KMac::new_kmac256(bytes, CHECKSUM_VERIFICATION_MATRIX_CUSTOM);
-        f.update(&self.runtime_id.0);
-        f.update(&[self.churp_id]);
-        f.update(&epoch.to_le_bytes());
-        f.finalize(&mut checksum);
-        Hash(checksum)
+        checksum_verification_matrix_bytes(bytes, &self.runtime_id, self.churp_id, epoch)
     }
 
     /// Returns the type of the next handoff depending on which nodes submitted
@@ -1196,17 +1210,57 @@ impl<S: Suite> Instance<S> {
         }
         HandoffKind::CommitteeChanged
     }
+}
 
-    /// Extends the given domain separation tag with key manager runtime ID
-    /// and churp ID.
-    fn domain_separation_tag(context: &[u8], runtime_id: &Namespace, churp_id: u8) -> Vec<u8> {
-        let mut dst = context.to_vec();
-        dst.extend(RUNTIME_CONTEXT_SEPARATOR);
-        dst.extend(runtime_id.0);
-        dst.extend(CHURP_CONTEXT_SEPARATOR);
-        dst.extend(&[churp_id]);
-        dst
-    }
+/// Extends the given domain separation tag with key manager runtime ID
+/// and churp ID.
+fn domain_separation_tag(context: &[u8], runtime_id: &Namespace, churp_id: u8) -> Vec<u8> {
+    let mut dst = context.to_vec();
+    dst.extend(RUNTIME_CONTEXT_SEPARATOR);
+    dst.extend(runtime_id.0);
+    dst.extend(CHURP_CONTEXT_SEPARATOR);
+    dst.extend(&[churp_id]);
+    dst
+}
+
+/// Returns the domain separation tag for encoding shareholder identifiers
+/// of the given key manager runtime and churp instance.
+pub(crate) fn shareholder_domain_separation_tag(runtime_id: &Namespace, churp_id: u8) -> Vec<u8> {
+    domain_separation_tag(ENCODE_SHAREHOLDER_CONTEXT, runtime_id, churp_id)
+}
+
+/// Returns the domain separation tag for encoding key identifiers for key
+/// share derivation approved by an SGX policy.
+pub(crate) fn sgx_policy_key_id_domain_separation_tag(
+    runtime_id: &Namespace,
+    churp_id: u8,
+) -> Vec<u8> {
+    domain_separation_tag(ENCODE_SGX_POLICY_KEY_ID_CONTEXT, runtime_id, churp_id)
+}
+
+/// Returns the domain separation tag for proofs of key share derivation.
+pub(crate) fn key_share_proof_domain_separation_tag(
+    runtime_id: &Namespace,
+    churp_id: u8,
+) -> Vec<u8> {
+    domain_separation_tag(KEY_SHARE_PROOF_CONTEXT, runtime_id, churp_id)
+}
+
+/// Computes the checksum of the verification matrix bytes of the given
+/// key manager runtime, churp instance and handoff.
+pub(crate) fn checksum_verification_matrix_bytes(
+    bytes: &[u8],
+    runtime_id: &Namespace,
+    churp_id: u8,
+    epoch: EpochTime,
+) -> Hash {
+    let mut checksum = [0u8; 32];
+    let mut f = KMac::new_kmac256(bytes, CHECKSUM_VERIFICATION_MATRIX_CUSTOM);
+    f.update(&runtime_id.0);
+    f.update(&[churp_id]);
+    f.update(&epoch.to_le_bytes());
+    f.finalize(&mut checksum);
+    Hash(checksum)
 }
 
 impl<S: Suite> Handler for Instance<S> {
@@ -1339,7 +1393,7 @@ impl<S: Suite> Handler for Instance<S> {
         &self,
         ctx: &RpcContext,
         req: &KeyShareRequest,
-    ) -> Result<EncodedEncryptedPoint> {
+    ) -> Result<EncodedKeyShare> {
         let status = self.churp_state.status(self.runtime_id, self.churp_id)?;
         let status = if status.handoff != req.epoch {
             // Allow querying past key shares if the client is a few blocks behind.
@@ -1363,8 +1417,17 @@ impl<S: Suite> Handler for Instance<S> {
         // Prepare key share.
         let shareholder = self.get_shareholder(status.handoff)?;
         let point = shareholder.make_key_share::<S>(&req.key_id.0, &self.sgx_policy_key_id_dst)?;
-
-        Ok((&point).into())
+        let proof = KeyShareProof::<S>::prove(
+            shareholder.verifiable_share().secret_share().y(),
+            &req.key_id.0,
+            &self.sgx_policy_key_id_dst,
+            &self.key_share_proof_dst,
+        )?;
+
+        Ok(EncodedKeyShare {
+            share: (&point).into(),
+            proof: (&proof).into(),
+        })
     }
 
     fn apply(&self, req: &HandoffRequest) -> Result<SignedApplicationRequest> {
@@ -39,6 +39,8 @@ impl RpcHandler for Churp {
                 RpcMethodDescriptor {
                     name: METHOD_VERIFICATION_MATRIX.to_string(),
                     kind: RpcKind::InsecureQuery,
+                    allow_anonymous: false,
+                    cacheable: false,
                 },
                 move |_ctx: &_, req: &_| self.verification_matrix(req),
             ),
@@ -47,6 +49,8 @@ impl RpcHandler for Churp {
                 RpcMethodDescriptor {
                     name: METHOD_SHARE_REDUCTION_POINT.to_string(),
                     kind: RpcKind::NoiseSession,
+                    allow_anonymous: false,
+                    cacheable: false,
                 },
                 move |ctx: &_, req: &_| self.share_reduction_switch_point(ctx, req),
             ),
@@ -54,6 +58,8 @@ impl RpcHandler for Churp {
                 RpcMethodDescriptor {
                     name: METHOD_SHARE_DISTRIBUTION_POINT.to_string(),
                     kind: RpcKind::NoiseSession,
+                    allow_anonymous: false,
+                    cacheable: false,
                 },
                 move |ctx: &_, req: &_| self.share_distribution_switch_point(ctx, req),
             ),
@@ -61,6 +67,8 @@ impl RpcHandler for Churp {
                 RpcMethodDescriptor {
                     name: METHOD_BIVARIATE_SHARE.to_string(),
                     kind: RpcKind::NoiseSession,
+                    allow_anonymous: false,
+                    cacheable: false,
                 },
                 move |ctx: &_, req: &_| self.bivariate_share(ctx, req),
             ),
@@ -68,6 +76,8 @@ impl RpcHandler for Churp {
                 RpcMethodDescriptor {
                     name: METHOD_SGX_POLICY_KEY_SHARE.to_string(),
                     kind: RpcKind::NoiseSession,
+                    allow_anonymous: false,
+                    cacheable: false,
                 },
                 move |ctx: &_, req: &_| self.sgx_policy_key_share(ctx, req),
             ),
@@ -76,6 +86,8 @@ impl RpcHandler for Churp {
                 RpcMethodDescriptor {
                     name: METHOD_APPLY.to_string(),
                     kind: RpcKind::LocalQuery,
+                    allow_anonymous: false,
+                    cacheable: false,
                 },
                 move |_ctx: &_, req: &_| self.apply(req),
             ),
@@ -83,6 +95,8 @@ impl RpcHandler for Churp {
                 RpcMethodDescriptor {
                     name: METHOD_SHARE_REDUCTION.to_string(),
                     kind: RpcKind::LocalQuery,
+                    allow_anonymous: false,
+                    cacheable: false,
                 },
                 move |_ctx: &_, req: &_| self.share_reduction(req),
             ),
@@ -90,6 +104,8 @@ impl RpcHandler for Churp {
                 RpcMethodDescriptor {
                     name: METHOD_SHARE_DISTRIBUTION.to_string(),
                     kind: RpcKind::LocalQuery,
+                    allow_anonymous: false,
+                    cacheable: false,
                 },
                 move |_ctx: &_, req: &_| self.share_distribution(req),
             ),
@@ -97,6 +113,8 @@ impl RpcHandler for Churp {
                 RpcMethodDescriptor {
                     name: METHOD_PROACTIVIZATION.to_string(),
                     kind: RpcKind::LocalQuery,
+                    allow_anonymous: false,
+                    cacheable: false,
                 },
                 move |_ctx: &_, req: &_| self.proactivization(req),
             ),
@@ -104,6 +122,8 @@ impl RpcHandler for Churp {
                 RpcMethodDescriptor {
                     name: METHOD_CONFIRM.to_string(),
                     kind: RpcKind::LocalQuery,
+                    allow_anonymous: false,
+                    cacheable: false,
                 },
                 move |_ctx: &_, req: &_| self.confirmation(req),
             ),
@@ -111,6 +131,8 @@ impl RpcHandler for Churp {
                 RpcMethodDescriptor {
                     name: METHOD_FINALIZE.to_string(),
                     kind: RpcKind::LocalQuery,
+                    allow_anonymous: false,
+                    cacheable: false,
                 },
                 move |_ctx: &_, req: &_| self.finalize(req),
             ),
@@ -6,9 +6,12 @@ mod handler;
 mod kdf;
 mod methods;
 mod policy;
+mod proof;
 mod state;
 mod storage;
 mod types;
 
 // Re-exports.
-pub use self::{errors::*, handler::*, kdf::*, methods::*, policy::*, state::*, types::*};
+pub use self::{
+    errors::*, handler::*, kdf::*, methods::*, policy::*, proof::*, state::*, types::*,
+};
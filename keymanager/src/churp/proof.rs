@@ -0,0 +1,131 @@
+//! Proofs of correct key share derivation.
+//!
+//! A key share is the encoded key identifier `H`, a point on the curve, multiplied by the secret
+//! share `y` of the shareholder. The commitment `y * G` to the secret share can be computed by
+//! anyone from the published verification matrix, so a shareholder proves that its key share
+//! was derived correctly by proving that the key share and the commitment share the same
+//! discrete logarithm (Chaum-Pedersen proof), without revealing the secret share.
+use anyhow::Result;
+use group::{ff::Field, Group, GroupEncoding};
+use zeroize::Zeroize;
+
+use oasis_core_runtime::common::crypto::rng::SecureRng;
+use secret_sharing::{
+    suites::{FieldDigest, GroupDigest, Suite},
+    vss::VerificationMatrix,
+};
+
+/// Scalar field of the group used by the given suite.
+type Scalar<S> = <<S as Suite>::Group as Group>::Scalar;
+
+/// Proof that a key share was derived from the secret share committed to in the verification
+/// matrix.
+pub struct KeyShareProof<S: Suite> {
+    /// Challenge.
+    pub(super) c: Scalar<S>,
+    /// Response.
+    pub(super) r: Scalar<S>,
+}
+
+impl<S: Suite> KeyShareProof<S> {
+    /// Prove that the key share of the given key was derived from the given secret share.
+    pub fn prove(y: &Scalar<S>, key_id: &[u8], key_id_dst: &[u8], dst: &[u8]) -> Result<Self> {
+        let h = S::hash_to_group(key_id, key_id_dst)?;
+        let commitment = S::Group::generator() * y;
+        let z = h * y;
+
+        let mut k = Scalar::<S>::random(&mut SecureRng);
+        let a1 = S::Group::generator() * k;
+        let a2 = h * k;
+        let c = Self::challenge(&h, &commitment, &z, &a1, &a2, dst)?;
+        let r = k - c * y;
+        k.zeroize();
+
+        Ok(Self { c, r })
+    }
+
+    /// Verify that the key share `z` of the given key was derived from the secret share with
+    /// the given commitment.
+    pub fn verify(
+        &self,
+        commitment: &S::Group,
+        z: &S::Group,
+        key_id: &[u8],
+        key_id_dst: &[u8],
+        dst: &[u8],
+    ) -> bool {
+        let h = match S::hash_to_group(key_id, key_id_dst) {
+            Ok(h) => h,
+            Err(_) => return false,
+        };
+        let a1 = S::Group::generator() * self.r + *commitment * self.c;
+        let a2 = h * self.r + *z * self.c;
+
+        match Self::challenge(&h, commitment, z, &a1, &a2, dst) {
+            Ok(c) => c == self.c,
+            Err(_) => false,
+        }
+    }
+
+    fn challenge(
+        h: &S::Group,
+        commitment: &S::Group,
+        z: &S::Group,
+        a1: &S::Group,
+        a2: &S::Group,
+        dst: &[u8],
+    ) -> Result<Scalar<S>> {
+        let mut transcript = vec![];
+        for point in [h, commitment, z, a1, a2] {
+            transcript.extend_from_slice(point.to_bytes().as_ref());
+        }
+
+        S::hash_to_field(&transcript, dst)
+    }
+}
+
+/// Commitment `B(x, 0) * G` to the secret share of the shareholder with the given encoded
+/// identity, computed from the verification matrix of the bivariate polynomial `B(x, y)`.
+pub fn share_commitment<G: Group + GroupEncoding>(vm: &VerificationMatrix<G>, x: &G::Scalar) -> G {
+    let (rows, _) = vm.dimensions();
+    (0..rows).rev().fold(G::identity(), |acc, i| {
+        acc * x + vm.element(i, 0).expect("row should exist")
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use secret_sharing::{
+        poly::BivariatePolynomial,
+        suites::{self, p384},
+    };
+
+    use super::*;
+
+    // Types used in tests.
+    type Suite = p384::Sha3_384;
+    type Group = <Suite as suites::Suite>::Group;
+    type PrimeField = <Group as group::Group>::Scalar;
+
+    #[test]
+    fn test_key_share_proof() {
+        let (key_id, key_id_dst, dst) = (b"key id", b"key id dst", b"proof dst");
+        let bp = BivariatePolynomial::<PrimeField>::random(2, 4, &mut SecureRng);
+        let vm = VerificationMatrix::<Group>::from(&bp);
+        let x = PrimeField::from_u64(7);
+        let y = bp.eval_x(&x).eval(&PrimeField::ZERO);
+        let commitment = share_commitment(&vm, &x);
+        assert!(commitment == Group::generator() * y);
+
+        let h = <Suite as GroupDigest>::hash_to_group(key_id, key_id_dst).unwrap();
+        let z = h * y;
+        let proof = KeyShareProof::<Suite>::prove(&y, key_id, key_id_dst, dst).unwrap();
+        assert!(proof.verify(&commitment, &z, key_id, key_id_dst, dst));
+
+        // Key shares derived from other secret shares or for other keys are rejected.
+        assert!(!proof.verify(&commitment, &(z + h), key_id, key_id_dst, dst));
+        assert!(!proof.verify(&commitment, &z, b"other key id", key_id_dst, dst));
+        let other = share_commitment(&vm, &PrimeField::from_u64(8));
+        assert!(!proof.verify(&other, &z, key_id, key_id_dst, dst));
+    }
+}
@@ -18,12 +18,13 @@ use oasis_core_runtime::{
 use secret_sharing::{
     churp::{SecretShare, VerifiableSecretShare},
     poly::{scalar_from_bytes, scalar_to_bytes, EncryptedPoint, Polynomial},
+    suites::Suite,
     vss::VerificationMatrix,
 };
 
 use crate::crypto::KeyPairId;
 
-use super::Error;
+use super::{Error, KeyShareProof};
 
 /// Handoff request.
 #[derive(Clone, Debug, Default, cbor::Encode, cbor::Decode)]
@@ -289,3 +290,43 @@ where
         Ok(point)
     }
 }
+
+/// Encoded key share proof.
+#[derive(Clone, Default, cbor::Encode, cbor::Decode)]
+pub struct EncodedKeyShareProof {
+    /// Encoded challenge.
+    pub c: Vec<u8>,
+
+    /// Encoded response.
+    pub r: Vec<u8>,
+}
+
+impl<S: Suite> From<&KeyShareProof<S>> for EncodedKeyShareProof {
+    fn from(proof: &KeyShareProof<S>) -> Self {
+        Self {
+            c: scalar_to_bytes(&proof.c),
+            r: scalar_to_bytes(&proof.r),
+        }
+    }
+}
+
+impl<S: Suite> TryFrom<EncodedKeyShareProof> for KeyShareProof<S> {
+    type Error = Error;
+
+    fn try_from(encoded: EncodedKeyShareProof) -> Result<Self, Self::Error> {
+        let c = scalar_from_bytes(&encoded.c).ok_or(Error::KeyShareProofDecodingFailed)?;
+        let r = scalar_from_bytes(&encoded.r).ok_or(Error::KeyShareProofDecodingFailed)?;
+        Ok(Self { c, r })
+    }
+}
+
+/// Encoded key share, together with the proof that it was derived correctly.
+#[derive(Clone, Default, cbor::Encode, cbor::Decode)]
+pub struct EncodedKeyShare {
+    /// Encoded key share.
+    pub share: EncodedEncryptedPoint,
+
+    /// Encoded proof that the key share was derived from the secret share committed to
+    /// in the verification matrix.
+    pub proof: EncodedKeyShareProof,
+}
@@ -0,0 +1,60 @@
+//! Key manager client setup for runtimes assembled with a [`RuntimeBuilder`].
+use std::sync::Arc;
+
+use futures::executor::block_on;
+use oasis_core_runtime::builder::{NoWorkload, RuntimeBuilder};
+
+use crate::policy::TrustedSigners;
+
+use super::RemoteClient;
+
+/// Extension of [`RuntimeBuilder`] setting up a key manager client.
+pub trait KeyManagerBuilderExt {
+    /// Set up a client of the key manager used by the runtime, trusting key manager policies
+    /// signed by the given signers and caching up to the given number of keys of each kind.
+    ///
+    /// The client follows status and quote policy updates of the key manager and is handed to
+    /// the transaction dispatcher factory as the `Arc<RemoteClient>` component.
+    fn key_manager(
+        self,
+        signers: TrustedSigners,
+        keys_cache_sizes: usize,
+    ) -> RuntimeBuilder<NoWorkload, Arc<RemoteClient>>;
+}
+
+impl KeyManagerBuilderExt for RuntimeBuilder<NoWorkload, ()> {
+    fn key_manager(
+        self,
+        signers: TrustedSigners,
+        keys_cache_sizes: usize,
+    ) -> RuntimeBuilder<NoWorkload, Arc<RemoteClient>> {
+        self.setup(move |state, ()| {
+            let hi = state.protocol.get_host_info();
+            let client = Arc::new(RemoteClient::new_runtime(
+                hi.runtime_id,
+                state.protocol.clone(),
+                state.consensus_verifier.clone(),
+                state.identity.clone(),
+                keys_cache_sizes,
+                signers,
+            ));
+
+            let key_manager = client.clone();
+            state
+                .rpc_dispatcher
+                .set_keymanager_status_update_handler(Some(Box::new(move |status| {
+                    block_on(key_manager.set_status(status))
+                        .expect("failed to update km client status");
+                })));
+
+            let key_manager = client.clone();
+            state
+                .rpc_dispatcher
+                .set_keymanager_quote_policy_update_handler(Some(Box::new(move |policy| {
+                    block_on(key_manager.set_quote_policy(policy));
+                })));
+
+            client
+        })
+    }
+}
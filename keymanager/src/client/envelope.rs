@@ -0,0 +1,75 @@
+//! Encrypted call envelopes using ephemeral key manager keys.
+//!
+//! Calls are encrypted to the ephemeral public key of the given key pair for the epoch they are
+//! made in, and the runtime opens them with the corresponding ephemeral private key obtained
+//! from the key manager.
+use oasis_core_runtime::{
+    consensus::beacon::EpochTime,
+    transaction::envelope::{CallEnvelope, ClientKeys, RuntimeKeys},
+};
+
+use crate::{api::KeyManagerError, crypto::KeyPairId};
+
+use super::KeyManagerClient;
+
+/// Encrypt a call to the ephemeral public key of the given key pair for the given epoch.
+///
+/// The public key is fetched via the given client, which is expected to verify its signature.
+pub async fn seal_call(
+    client: &dyn KeyManagerClient,
+    key_pair_id: KeyPairId,
+    epoch: EpochTime,
+    call: Vec<u8>,
+) -> Result<(CallEnvelope, ClientKeys), KeyManagerError> {
+    let signed_pk = client.get_public_ephemeral_key(key_pair_id, epoch).await?;
+    let sealed = CallEnvelope::seal(call, &signed_pk.key, epoch)?;
+
+    Ok(sealed)
+}
+
+/// Decrypt a call using the ephemeral private key of the given key pair for the envelope's
+/// epoch.
+pub async fn open_call(
+    client: &dyn KeyManagerClient,
+    key_pair_id: KeyPairId,
+    envelope: &CallEnvelope,
+) -> Result<(Vec<u8>, RuntimeKeys), KeyManagerError> {
+    let keys = client
+        .get_or_create_ephemeral_keys(key_pair_id, envelope.epoch)
+        .await?;
+    let opened = envelope.open(&keys.input_keypair.sk)?;
+
+    Ok(opened)
+}
+
+#[cfg(test)]
+mod test {
+    use futures::executor::block_on;
+
+    use super::*;
+    use crate::client::MockClient;
+
+    #[test]
+    fn test_envelope_key_manager() {
+        let client = MockClient::new();
+        let key_pair_id = KeyPairId::from(vec![1u8; 32]);
+
+        let (envelope, client_keys) =
+            block_on(seal_call(&client, key_pair_id, 5, b"call".to_vec())).unwrap();
+        let (call, runtime_keys) = block_on(open_call(&client, key_pair_id, &envelope)).unwrap();
+        assert_eq!(call, b"call".to_vec());
+
+        let result = runtime_keys.seal_result(b"result".to_vec()).unwrap();
+        assert_eq!(
+            client_keys.open_result(&result).unwrap(),
+            b"result".to_vec()
+        );
+
+        // Keys of other epochs can't open the call.
+        let other_epoch = CallEnvelope {
+            epoch: 6,
+            ..envelope
+        };
+        assert!(block_on(open_call(&client, key_pair_id, &other_epoch)).is_err());
+    }
+}
@@ -1,5 +1,5 @@
 //! Key manager client.
-use std::sync::Arc;
+use std::{ops::RangeInclusive, sync::Arc};
 
 use async_trait::async_trait;
 
@@ -40,6 +40,27 @@ pub trait KeyManagerClient: Send + Sync {
         generation: u64,
     ) -> Result<KeyPair, KeyManagerError>;
 
+    /// Get or create named long-term key pair of the master secret generation which was the
+    /// latest one at the given epoch.
+    ///
+    /// Keys can be retrieved for generations at most `MAX_KEY_ROTATION_AGE` generations older
+    /// than the latest one, so that runtimes can migrate state encrypted under keys of past
+    /// generations. Rotation epochs are only known for rotations the client has seen in verified
+    /// key manager statuses, and retrievable ones can be enumerated with `rotation_epochs`.
+    async fn get_or_create_keys_for_epoch(
+        &self,
+        key_pair_id: KeyPairId,
+        epoch: EpochTime,
+    ) -> Result<KeyPair, KeyManagerError>;
+
+    /// Epochs at which master secret generations whose long-term keys are still retrievable
+    /// became the latest one, in ascending order.
+    async fn rotation_epochs(&self) -> Result<Vec<EpochTime>, KeyManagerError>;
+
+    /// Master secret generations whose long-term keys are still retrievable, as determined by
+    /// the verified key manager status and policy.
+    async fn retrievable_generations(&self) -> Result<RangeInclusive<u64>, KeyManagerError>;
+
     /// Get long-term public key for a key pair id.
     async fn get_public_key(
         &self,
@@ -47,6 +68,19 @@ pub trait KeyManagerClient: Send + Sync {
         generation: u64,
     ) -> Result<SignedPublicKey, KeyManagerError>;
 
+    /// Get long-term public key for a key pair id, signed with a validity window.
+    ///
+    /// The key is served from the local cache as long as its signature has not expired and it
+    /// was issued at most `max_staleness` epochs ago, so callers (e.g. encrypting transactions)
+    /// can avoid querying the key manager on every call while bounding how long a key remains
+    /// in use.
+    async fn get_public_key_cached(
+        &self,
+        key_pair_id: KeyPairId,
+        generation: u64,
+        max_staleness: EpochTime,
+    ) -> Result<SignedPublicKey, KeyManagerError>;
+
     /// Get or create named ephemeral key pair for given epoch.
     ///
     /// If the key does not yet exist, the key manager will generate one. If
@@ -65,6 +99,34 @@ pub trait KeyManagerClient: Send + Sync {
         epoch: EpochTime,
     ) -> Result<SignedPublicKey, KeyManagerError>;
 
+    /// Get ephemeral public key for an epoch and a key pair id, tolerating a stale key.
+    ///
+    /// In case the key of the given epoch is not cached, the cached key of the most recent epoch
+    /// at most `max_staleness` epochs older is served instead, while the key of the given epoch is
+    /// fetched in the background. This smooths latency spikes at epoch boundaries and should only
+    /// be used by non-critical callers which can use keys of past epochs (e.g. for encryption).
+    ///
+    /// Clients without a cache of ephemeral public keys always fetch the key of the given epoch.
+    async fn get_public_ephemeral_key_stale(
+        &self,
+        key_pair_id: KeyPairId,
+        epoch: EpochTime,
+        _max_staleness: EpochTime,
+    ) -> Result<SignedPublicKey, KeyManagerError> {
+        self.get_public_ephemeral_key(key_pair_id, epoch).await
+    }
+
+    /// Get ephemeral public key of a past (or the current) epoch and a key pair id.
+    ///
+    /// Keys can be retrieved for epochs at most `MAX_EPHEMERAL_KEY_AGE` epochs in the past,
+    /// relative to the current consensus layer epoch, so that runtimes can process artifacts
+    /// produced in previous epochs (e.g. while catching up).
+    async fn get_past_public_ephemeral_key(
+        &self,
+        key_pair_id: KeyPairId,
+        epoch: EpochTime,
+    ) -> Result<SignedPublicKey, KeyManagerError>;
+
     /// Get a copy of the master secret for replication.
     async fn replicate_master_secret(
         &self,
@@ -146,6 +208,22 @@ impl<T: ?Sized + KeyManagerClient> KeyManagerClient for Arc<T> {
         KeyManagerClient::get_or_create_keys(&**self, key_pair_id, generation).await
     }
 
+    async fn get_or_create_keys_for_epoch(
+        &self,
+        key_pair_id: KeyPairId,
+        epoch: EpochTime,
+    ) -> Result<KeyPair, KeyManagerError> {
+        KeyManagerClient::get_or_create_keys_for_epoch(&**self, key_pair_id, epoch).await
+    }
+
+    async fn rotation_epochs(&self) -> Result<Vec<EpochTime>, KeyManagerError> {
+        KeyManagerClient::rotation_epochs(&**self).await
+    }
+
+    async fn retrievable_generations(&self) -> Result<RangeInclusive<u64>, KeyManagerError> {
+        KeyManagerClient::retrievable_generations(&**self).await
+    }
+
     async fn get_public_key(
         &self,
         key_pair_id: KeyPairId,
@@ -154,6 +232,16 @@ impl<T: ?Sized + KeyManagerClient> KeyManagerClient for Arc<T> {
         KeyManagerClient::get_public_key(&**self, key_pair_id, generation).await
     }
 
+    async fn get_public_key_cached(
+        &self,
+        key_pair_id: KeyPairId,
+        generation: u64,
+        max_staleness: EpochTime,
+    ) -> Result<SignedPublicKey, KeyManagerError> {
+        KeyManagerClient::get_public_key_cached(&**self, key_pair_id, generation, max_staleness)
+            .await
+    }
+
     async fn get_or_create_ephemeral_keys(
         &self,
         key_pair_id: KeyPairId,
@@ -170,6 +258,24 @@ impl<T: ?Sized + KeyManagerClient> KeyManagerClient for Arc<T> {
         KeyManagerClient::get_public_ephemeral_key(&**self, key_pair_id, epoch).await
     }
 
+    async fn get_public_ephemeral_key_stale(
+        &self,
+        key_pair_id: KeyPairId,
+        epoch: EpochTime,
+        max_staleness: EpochTime,
+    ) -> Result<SignedPublicKey, KeyManagerError> {
+        KeyManagerClient::get_public_ephemeral_key_stale(&**self, key_pair_id, epoch, max_staleness)
+            .await
+    }
+
+    async fn get_past_public_ephemeral_key(
+        &self,
+        key_pair_id: KeyPairId,
+        epoch: EpochTime,
+    ) -> Result<SignedPublicKey, KeyManagerError> {
+        KeyManagerClient::get_past_public_ephemeral_key(&**self, key_pair_id, epoch).await
+    }
+
     async fn replicate_master_secret(
         &self,
         generation: u64,
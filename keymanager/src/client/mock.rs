@@ -1,5 +1,12 @@
 //! Mock key manager client which stores everything locally.
-use std::{collections::HashMap, sync::Mutex};
+use std::{
+    collections::HashMap,
+    ops::RangeInclusive,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Mutex,
+    },
+};
 
 use async_trait::async_trait;
 
@@ -24,15 +31,20 @@ use super::KeyManagerClient;
 pub struct MockClient {
     longterm_keys: Mutex<HashMap<(KeyPairId, u64), KeyPair>>,
     ephemeral_keys: Mutex<HashMap<(KeyPairId, EpochTime), KeyPair>>,
+    generation: AtomicU64,
 }
 
 impl MockClient {
     /// Create a new mock key manager client.
     pub fn new() -> Self {
-        Self {
-            longterm_keys: Mutex::new(HashMap::new()),
-            ephemeral_keys: Mutex::new(HashMap::new()),
-        }
+        Self::default()
+    }
+
+    /// Rotate the master secret, returning the new latest generation.
+    ///
+    /// Keys of all past generations remain retrievable.
+    pub fn rotate(&self) -> u64 {
+        self.generation.fetch_add(1, Ordering::SeqCst) + 1
     }
 }
 
@@ -62,6 +74,24 @@ impl KeyManagerClient for MockClient {
         Ok(key)
     }
 
+    async fn get_or_create_keys_for_epoch(
+        &self,
+        key_pair_id: KeyPairId,
+        _epoch: EpochTime,
+    ) -> Result<KeyPair, KeyManagerError> {
+        // Epochs are not tracked, so the latest generation is used.
+        let generation = self.generation.load(Ordering::SeqCst);
+        self.get_or_create_keys(key_pair_id, generation).await
+    }
+
+    async fn rotation_epochs(&self) -> Result<Vec<EpochTime>, KeyManagerError> {
+        Ok(vec![0])
+    }
+
+    async fn retrievable_generations(&self) -> Result<RangeInclusive<u64>, KeyManagerError> {
+        Ok(0..=self.generation.load(Ordering::SeqCst))
+    }
+
     async fn get_public_key(
         &self,
         key_pair_id: KeyPairId,
@@ -73,9 +103,20 @@ impl KeyManagerClient for MockClient {
             checksum: vec![],
             signature: Signature::default(),
             expiration: None,
+            issued: None,
+            scheme: ck.scheme,
         })
     }
 
+    async fn get_public_key_cached(
+        &self,
+        key_pair_id: KeyPairId,
+        generation: u64,
+        _max_staleness: EpochTime,
+    ) -> Result<SignedPublicKey, KeyManagerError> {
+        self.get_public_key(key_pair_id, generation).await
+    }
+
     async fn get_or_create_ephemeral_keys(
         &self,
         key_pair_id: KeyPairId,
@@ -103,9 +144,19 @@ impl KeyManagerClient for MockClient {
             checksum: vec![],
             signature: Signature::default(),
             expiration: None,
+            issued: None,
+            scheme: ck.scheme,
         })
     }
 
+    async fn get_past_public_ephemeral_key(
+        &self,
+        key_pair_id: KeyPairId,
+        epoch: EpochTime,
+    ) -> Result<SignedPublicKey, KeyManagerError> {
+        self.get_public_ephemeral_key(key_pair_id, epoch).await
+    }
+
     async fn replicate_master_secret(
         &self,
         _generation: u64,
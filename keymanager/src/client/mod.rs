@@ -1,7 +1,17 @@
 //! Key manager client.
+mod builder;
+pub mod envelope;
 mod interface;
 mod mock;
+pub mod reencryption;
 mod remote;
+mod shares;
 
 // Re-exports.
-pub use self::{interface::KeyManagerClient, mock::MockClient, remote::RemoteClient};
+pub use self::{
+    builder::KeyManagerBuilderExt,
+    interface::KeyManagerClient,
+    mock::MockClient,
+    remote::{GenerationChange, GenerationChangeHandler, RemoteClient},
+    shares::KeyShares,
+};
@@ -0,0 +1,316 @@
+//! Re-encryption of state after master secret rotations.
+//!
+//! State encrypted via a [`DecryptingTree`] remains readable after a master secret rotation only
+//! as long as state keys of past generations can be retrieved from the key manager. A
+//! [`ReencryptionJob`] walks the configured key prefixes in bounded chunks, one per round,
+//! re-encrypting entries under the state key of the latest generation. Progress is checkpointed
+//! in the state itself, so all replicas executing the same rounds re-encrypt the same entries and
+//! the job resumes where it left off. A [`ReencryptingDispatcher`] runs the job as part of each
+//! executed batch.
+use std::{
+    collections::BTreeMap,
+    sync::{atomic::AtomicBool, Arc},
+};
+
+use oasis_core_runtime::{
+    common::crypto::hash::Hash,
+    consensus::roothash,
+    future::block_on,
+    storage::mkvs::{
+        encrypted::{DecryptingTree, DecryptingTreeError},
+        MKVS,
+    },
+    transaction::{
+        context::Context,
+        dispatcher::{Dispatcher, ExecuteBatchResult},
+        types::TxnBatch,
+    },
+    types::{CheckTxResult, Error as RuntimeError},
+};
+
+use crate::{
+    api::KeyManagerError,
+    crypto::{KeyPairId, STATE_KEY_SIZE},
+};
+
+use super::KeyManagerClient;
+
+/// Fetch the state keys of the given key pair for all generations whose keys are still
+/// retrievable from the key manager, suitable for constructing a [`DecryptingTree`].
+pub async fn state_keys(
+    client: &dyn KeyManagerClient,
+    key_pair_id: KeyPairId,
+) -> Result<BTreeMap<u64, [u8; STATE_KEY_SIZE]>, KeyManagerError> {
+    let mut keys = BTreeMap::new();
+    for generation in client.retrievable_generations().await? {
+        let key_pair = client.get_or_create_keys(key_pair_id, generation).await?;
+        keys.insert(generation, key_pair.state_key.0);
+    }
+    Ok(keys)
+}
+
+/// Progress of a re-encryption job, as checkpointed in state.
+#[derive(Clone, Debug, Default, PartialEq, Eq, cbor::Encode, cbor::Decode)]
+pub struct ReencryptionProgress {
+    /// Key generation entries are re-encrypted under.
+    pub generation: u64,
+    /// Index of the prefix currently being walked.
+    pub prefix: u32,
+    /// Key to continue from within the current prefix.
+    #[cbor(optional)]
+    pub cursor: Option<Vec<u8>>,
+    /// Number of entries re-encrypted so far.
+    pub reencrypted: u64,
+    /// Whether all prefixes have been walked.
+    pub completed: bool,
+}
+
+/// Re-encryption job walking the configured key prefixes after master secret rotations.
+#[derive(Clone, Debug)]
+pub struct ReencryptionJob {
+    progress_key: Vec<u8>,
+    prefixes: Vec<Vec<u8>>,
+    chunk_size: usize,
+}
+
+impl ReencryptionJob {
+    /// Create a new job re-encrypting entries with the given key prefixes, visiting at most
+    /// `chunk_size` entries per round and checkpointing progress under the given key.
+    ///
+    /// The progress is stored encrypted via the tree like any other entry, so the progress key
+    /// may be within the walked prefixes.
+    pub fn new(progress_key: &[u8], prefixes: Vec<Vec<u8>>, chunk_size: usize) -> Self {
+        Self {
+            progress_key: progress_key.to_vec(),
+            prefixes,
+            chunk_size,
+        }
+    }
+
+    /// Checkpointed progress of the job, if it was ever started.
+    pub fn progress<M: MKVS>(
+        &self,
+        tree: &DecryptingTree<M>,
+    ) -> Result<Option<ReencryptionProgress>, KeyManagerError> {
+        match tree.get(&self.progress_key).map_err(tree_error)? {
+            Some(raw) => cbor::from_slice(&raw)
+                .map(Some)
+                .map_err(|_| KeyManagerError::StateCorrupted),
+            None => Ok(None),
+        }
+    }
+
+    /// Perform the work of a single round, returning the updated progress.
+    ///
+    /// This must be called once per round by all replicas, before the state is committed. A new
+    /// walk starts whenever the latest key generation of the tree changes, and work is skipped
+    /// once no entries are encrypted under past generations.
+    ///
+    /// Fails in case any of the visited entries can't be decrypted, without re-encrypting any
+    /// entries in that round.
+    pub fn run_round<M: MKVS>(
+        &self,
+        tree: &mut DecryptingTree<M>,
+    ) -> Result<ReencryptionProgress, KeyManagerError> {
+        let latest = tree.latest_generation();
+        let checkpoint = self.progress(tree)?;
+        let mut progress = match &checkpoint {
+            Some(progress) if progress.generation == latest => progress.clone(),
+            _ => ReencryptionProgress {
+                generation: latest,
+                ..Default::default()
+            },
+        };
+
+        let mut budget = self.chunk_size;
+        while !progress.completed && budget > 0 {
+            let index = progress.prefix as usize;
+            if index >= self.prefixes.len() || tree.pending() == 0 {
+                progress.completed = true;
+                progress.cursor = None;
+                break;
+            }
+
+            let prefix = &self.prefixes[index];
+            let start = progress.cursor.clone().unwrap_or_else(|| prefix.clone());
+            let chunk = tree.reencrypt(prefix, &start, budget).map_err(tree_error)?;
+            budget -= chunk.visited.min(budget);
+            progress.reencrypted += chunk.reencrypted as u64;
+            progress.cursor = chunk.next;
+            if progress.cursor.is_none() {
+                progress.prefix += 1;
+            }
+        }
+        // The budget may have run out exactly at the end of the walk.
+        if !progress.completed
+            && (progress.prefix as usize >= self.prefixes.len() || tree.pending() == 0)
+        {
+            progress.completed = true;
+            progress.cursor = None;
+        }
+
+        if checkpoint.as_ref() != Some(&progress) {
+            tree.insert(&self.progress_key, &cbor::to_vec(progress.clone()))
+                .map_err(tree_error)?;
+        }
+        Ok(progress)
+    }
+}
+
+/// Transaction dispatcher wrapper which runs a round of a re-encryption job before each
+/// executed batch.
+pub struct ReencryptingDispatcher {
+    inner: Box<dyn Dispatcher>,
+    client: Arc<dyn KeyManagerClient>,
+    key_pair_id: KeyPairId,
+    generations_key: Vec<u8>,
+    job: ReencryptionJob,
+}
+
+impl ReencryptingDispatcher {
+    /// Wrap the given dispatcher, re-encrypting runtime state encrypted via a [`DecryptingTree`]
+    /// with the state keys of the given key pair, and with the number of entries of each
+    /// generation stored under the given key.
+    pub fn new(
+        inner: Box<dyn Dispatcher>,
+        client: Arc<dyn KeyManagerClient>,
+        key_pair_id: KeyPairId,
+        generations_key: &[u8],
+        job: ReencryptionJob,
+    ) -> Self {
+        Self {
+            inner,
+            client,
+            key_pair_id,
+            generations_key: generations_key.to_vec(),
+            job,
+        }
+    }
+
+    fn run_round(&self, state: &mut dyn MKVS) -> Result<(), RuntimeError> {
+        let keys =
+            block_on(state_keys(&*self.client, self.key_pair_id)).map_err(anyhow::Error::from)?;
+        let mut tree =
+            DecryptingTree::new(state, &self.generations_key, keys).map_err(anyhow::Error::from)?;
+        self.job.run_round(&mut tree).map_err(anyhow::Error::from)?;
+        Ok(())
+    }
+}
+
+impl Dispatcher for ReencryptingDispatcher {
+    fn is_supported(&self) -> bool {
+        self.inner.is_supported()
+    }
+
+    fn execute_batch(
+        &self,
+        mut ctx: Context,
+        batch: &TxnBatch,
+        in_msgs: &[roothash::IncomingMessage],
+    ) -> Result<ExecuteBatchResult, RuntimeError> {
+        self.run_round(&mut *ctx.runtime_state)?;
+        self.inner.execute_batch(ctx, batch, in_msgs)
+    }
+
+    fn schedule_and_execute_batch(
+        &self,
+        mut ctx: Context,
+        initial_batch: &mut TxnBatch,
+        in_msgs: &[roothash::IncomingMessage],
+    ) -> Result<ExecuteBatchResult, RuntimeError> {
+        self.run_round(&mut *ctx.runtime_state)?;
+        self.inner
+            .schedule_and_execute_batch(ctx, initial_batch, in_msgs)
+    }
+
+    fn check_batch(
+        &self,
+        ctx: Context,
+        batch: &TxnBatch,
+    ) -> Result<Vec<CheckTxResult>, RuntimeError> {
+        self.inner.check_batch(ctx, batch)
+    }
+
+    fn finalize(&self, new_storage_root: Hash) {
+        self.inner.finalize(new_storage_root)
+    }
+
+    fn set_abort_batch_flag(&mut self, abort_batch: Arc<AtomicBool>) {
+        self.inner.set_abort_batch_flag(abort_batch)
+    }
+
+    fn query(&self, ctx: Context, method: &str, args: Vec<u8>) -> Result<Vec<u8>, RuntimeError> {
+        self.inner.query(ctx, method, args)
+    }
+}
+
+fn tree_error(err: DecryptingTreeError) -> KeyManagerError {
+    KeyManagerError::Other(err.into())
+}
+
+#[cfg(test)]
+mod test {
+    use futures::executor::block_on;
+    use oasis_core_runtime::storage::mkvs::{sync::NoopReadSyncer, OverlayTree, RootType, Tree};
+
+    use super::*;
+    use crate::client::MockClient;
+
+    const GENERATIONS: &[u8] = b"\xffgenerations";
+    const PROGRESS: &[u8] = b"\xfeprogress";
+
+    #[test]
+    fn test_reencryption_job() {
+        let client = MockClient::new();
+        let key_pair_id = KeyPairId::from(vec![1u8; 32]);
+        let keys = block_on(state_keys(&client, key_pair_id)).unwrap();
+        assert_eq!(keys.len(), 1);
+
+        let tree = Tree::builder()
+            .with_root_type(RootType::State)
+            .build(Box::new(NoopReadSyncer));
+        let mut tree = DecryptingTree::new(OverlayTree::new(tree), GENERATIONS, keys).unwrap();
+        for i in 0..5u8 {
+            tree.insert(&[b'a', i], b"value").unwrap();
+            tree.insert(&[b'b', i], b"value").unwrap();
+            tree.insert(&[b'c', i], b"value").unwrap();
+        }
+
+        let job = ReencryptionJob::new(PROGRESS, vec![b"a".to_vec(), b"b".to_vec()], 4);
+        let progress = job.run_round(&mut tree).unwrap();
+        assert!(progress.completed);
+        assert_eq!(progress.reencrypted, 0);
+
+        // Rotate the master secret.
+        assert_eq!(client.rotate(), 1);
+        let keys = block_on(state_keys(&client, key_pair_id)).unwrap();
+        assert_eq!(keys.len(), 2);
+        let mut tree = DecryptingTree::new(tree.into_inner(), GENERATIONS, keys.clone()).unwrap();
+        assert_eq!(tree.pending(), 16);
+
+        let progress = job.run_round(&mut tree).unwrap();
+        assert_eq!(
+            progress,
+            ReencryptionProgress {
+                generation: 1,
+                prefix: 0,
+                cursor: Some(vec![b'a', 4]),
+                reencrypted: 4,
+                completed: false,
+            }
+        );
+        // Progress is resumed from the checkpoint.
+        let mut tree = DecryptingTree::new(tree.into_inner(), GENERATIONS, keys).unwrap();
+        assert_eq!(job.progress(&tree).unwrap(), Some(progress));
+
+        assert_eq!(job.run_round(&mut tree).unwrap().reencrypted, 8);
+        let progress = job.run_round(&mut tree).unwrap();
+        assert!(progress.completed);
+        assert_eq!(progress.reencrypted, 10);
+
+        // Entries outside of the prefixes are not re-encrypted.
+        assert_eq!(tree.pending(), 5);
+        assert_eq!(tree.get(&[b'a', 0]).unwrap(), Some(b"value".to_vec()));
+        assert_eq!(job.run_round(&mut tree).unwrap(), progress);
+    }
+}
@@ -1,23 +1,27 @@
 //! Key manager client which talks to a remote key manager enclave.
 use std::{
-    collections::HashSet,
-    convert::TryInto,
+    collections::{BTreeMap, HashSet},
     iter::FromIterator,
     num::NonZeroUsize,
-    sync::{Arc, RwLock},
+    ops::RangeInclusive,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex, RwLock,
+    },
 };
 
 use anyhow::anyhow;
 use async_trait::async_trait;
 use futures::stream::{FuturesUnordered, StreamExt};
-use group::GroupEncoding;
 use lru::LruCache;
-use rand::{prelude::SliceRandom, rngs::OsRng};
+use rand::prelude::SliceRandom;
+use slog::{warn, Logger};
 
 use oasis_core_runtime::{
     common::{
-        crypto::signature::PublicKey,
-        namespace::{Namespace, NAMESPACE_SIZE},
+        crypto::{rng::SecureRng, signature::PublicKey},
+        logger::get_logger,
+        namespace::Namespace,
         sgx::{EnclaveIdentity, QuotePolicy},
     },
     consensus::{
@@ -35,32 +39,32 @@ use oasis_core_runtime::{
     protocol::Protocol,
 };
 use secret_sharing::{
-    churp::{HandoffKind, Player},
-    kdc::KeyRecoverer,
-    poly::EncryptedPoint,
     suites::{p384, Suite},
+    vss::VerificationMatrix,
 };
 
 use crate::{
     api::{
         EphemeralKeyRequest, KeyManagerError, LongTermKeyRequest, ReplicateEphemeralSecretRequest,
         ReplicateEphemeralSecretResponse, ReplicateMasterSecretRequest,
-        ReplicateMasterSecretResponse, METHOD_GET_OR_CREATE_EPHEMERAL_KEYS,
-        METHOD_GET_OR_CREATE_KEYS, METHOD_GET_PUBLIC_EPHEMERAL_KEY, METHOD_GET_PUBLIC_KEY,
-        METHOD_REPLICATE_EPHEMERAL_SECRET, METHOD_REPLICATE_MASTER_SECRET,
+        ReplicateMasterSecretResponse, MAX_EPHEMERAL_KEY_AGE, MAX_KEY_ROTATION_AGE,
+        METHOD_GET_OR_CREATE_EPHEMERAL_KEYS, METHOD_GET_OR_CREATE_KEYS,
+        METHOD_GET_PUBLIC_EPHEMERAL_KEY, METHOD_GET_PUBLIC_KEY, METHOD_REPLICATE_EPHEMERAL_SECRET,
+        METHOD_REPLICATE_MASTER_SECRET,
     },
     churp::{
-        EncodedEncryptedPoint, EncodedVerifiableSecretShare, Kdf, KeyShareRequest, QueryRequest,
+        EncodedKeyShare, EncodedVerifiableSecretShare, KeyShareRequest, QueryRequest,
         METHOD_BIVARIATE_SHARE, METHOD_SGX_POLICY_KEY_SHARE, METHOD_SHARE_DISTRIBUTION_POINT,
         METHOD_SHARE_REDUCTION_POINT, METHOD_VERIFICATION_MATRIX,
     },
     crypto::{
-        KeyPair, KeyPairId, Secret, SignedPublicKey, StateKey, VerifiableSecret, KEY_PAIR_ID_SIZE,
+        DerivationScheme, KeyNamespace, KeyPair, KeyPairId, Secret, SignedPublicKey, StateKey,
+        VerifiableSecret,
     },
     policy::{set_trusted_signers, verify_data_and_trusted_signers, Policy, TrustedSigners},
 };
 
-use super::KeyManagerClient;
+use super::{shares::verify_verification_matrix, KeyManagerClient, KeyShares};
 
 /// Key manager RPC endpoint.
 const KEY_MANAGER_ENDPOINT: &str = "key-manager";
@@ -73,28 +77,216 @@ const RPC_MAX_SESSIONS_PER_PEER: usize = 2;
 /// seconds can be closed to make room for new sessions.
 const RPC_STALE_SESSION_TIMEOUT_SECS: i64 = 10;
 
+/// Change of the key manager master secret generation.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct GenerationChange {
+    /// Previously known generation of the latest master secret.
+    pub old: u64,
+    /// New generation of the latest master secret.
+    pub new: u64,
+}
+
+/// Master secret generation change handler callback.
+///
+/// Handlers are invoked once the key manager status has been verified and applied, before the
+/// status update completes, so any long-running work (e.g. state re-encryption) should be
+/// scheduled in the background.
+pub type GenerationChangeHandler = dyn Fn(GenerationChange) + Send + Sync;
+
+/// Cache of ephemeral public keys.
+type EphemeralPublicKeys = RwLock<LruCache<(KeyPairId, EpochTime), SignedPublicKey>>;
+
+/// Fetcher of ephemeral public keys, which can be moved into background refreshes.
+#[derive(Clone)]
+struct EphemeralKeyFetcher {
+    runtime_id: Namespace,
+    namespace: Option<KeyNamespace>,
+    rsk: Option<PublicKey>,
+    rpc_client: Arc<RpcClient>,
+    consensus_verifier: Arc<dyn Verifier>,
+    cache: Arc<EphemeralPublicKeys>,
+}
+
+impl EphemeralKeyFetcher {
+    /// Fetch the ephemeral public key of the given epoch from the key manager, verify it at the
+    /// given consensus epoch and add it to the cache.
+    async fn fetch(
+        &self,
+        key_pair_id: KeyPairId,
+        epoch: EpochTime,
+        consensus_epoch: EpochTime,
+    ) -> Result<SignedPublicKey, KeyManagerError> {
+        let height = self
+            .consensus_verifier
+            .latest_height()
+            .await
+            .map_err(|err| KeyManagerError::Other(err.into()))?;
+
+        let key: SignedPublicKey = self
+            .rpc_client
+            .insecure_call(
+                METHOD_GET_PUBLIC_EPHEMERAL_KEY,
+                EphemeralKeyRequest {
+                    height: Some(height),
+                    runtime_id: self.runtime_id,
+                    key_pair_id,
+                    epoch,
+                    namespace: self.namespace,
+                },
+                vec![],
+            )
+            .await
+            .into_result_with_feedback()
+            .await
+            .map_err(|err| KeyManagerError::Other(err.into()))?;
+
+        // Verify the signature.
+        verify_public_key(
+            &key,
+            self.runtime_id,
+            self.namespace.as_ref(),
+            self.rsk.as_ref(),
+            key_pair_id,
+            Some(epoch),
+            Some(consensus_epoch),
+        )?;
+
+        // Cache key.
+        let mut cache = self.cache.write().unwrap();
+        cache.put((key_pair_id, epoch), key.clone());
+
+        Ok(key)
+    }
+}
+
+/// Latest master secret generation, together with the handlers notified about its changes.
+#[derive(Default)]
+struct GenerationTracker {
+    /// Generation of the latest master secret, as seen in the last key manager status.
+    generation: RwLock<Option<u64>>,
+    /// Registered master secret generation change handlers.
+    handlers: RwLock<Vec<Arc<GenerationChangeHandler>>>,
+}
+
+impl GenerationTracker {
+    /// Register a handler which is notified whenever the generation changes.
+    fn add_handler(&self, f: Arc<GenerationChangeHandler>) {
+        self.handlers.write().unwrap().push(f);
+    }
+
+    /// Record the given generation as the latest one, returning the change if the generation
+    /// differs from the previously recorded one.
+    fn update(&self, generation: u64) -> Option<GenerationChange> {
+        let old = self.generation.write().unwrap().replace(generation);
+        match old {
+            Some(old) if old != generation => Some(GenerationChange {
+                old,
+                new: generation,
+            }),
+            _ => None,
+        }
+    }
+
+    /// Notify all registered handlers about the given change.
+    fn notify(&self, change: GenerationChange) {
+        // Release the lock before invoking the handlers so that they can register new ones.
+        let handlers = self.handlers.read().unwrap().clone();
+        for handler in handlers {
+            handler(change);
+        }
+    }
+}
+
+/// Master secret rotations, as recorded in verified key manager statuses.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+struct RotationSchedule {
+    /// Epochs at which master secret generations became the latest one, by generation.
+    records: BTreeMap<u64, EpochTime>,
+}
+
+impl RotationSchedule {
+    /// Record the given generation becoming the latest one at the given epoch, forgetting
+    /// generations whose long-term keys are no longer retrievable.
+    fn record(&mut self, generation: u64, rotation_epoch: EpochTime) {
+        self.records.insert(generation, rotation_epoch);
+        self.records = self
+            .records
+            .split_off(&generation.saturating_sub(MAX_KEY_ROTATION_AGE));
+    }
+
+    /// Generation of the latest master secret.
+    fn latest(&self) -> Option<u64> {
+        self.records.keys().next_back().copied()
+    }
+
+    /// Rotation epochs and generations of master secrets whose long-term keys are still
+    /// retrievable and whose rotations have been recorded, in ascending order.
+    ///
+    /// The key manager status only records the last rotation, so rotation epochs of past
+    /// generations are only known in case the client has seen the statuses of their rotations.
+    fn retrievable(&self) -> Vec<(EpochTime, u64)> {
+        self.records
+            .iter()
+            .map(|(generation, rotation_epoch)| (*rotation_epoch, *generation))
+            .collect()
+    }
+
+    /// Generation of the master secret which was the latest one at the given epoch.
+    ///
+    /// Fails in case the rotation of any generation which may have been the latest one at the
+    /// given epoch has not been recorded.
+    fn generation_at(&self, epoch: EpochTime) -> Result<u64, KeyManagerError> {
+        let latest = self.latest().ok_or(KeyManagerError::NotInitialized)?;
+        for generation in (latest.saturating_sub(MAX_KEY_ROTATION_AGE)..=latest).rev() {
+            let rotation_epoch = self
+                .records
+                .get(&generation)
+                .ok_or(KeyManagerError::RotationRecordMissing(generation))?;
+            if *rotation_epoch <= epoch {
+                return Ok(generation);
+            }
+        }
+        Err(KeyManagerError::RotationEpochExpired(epoch))
+    }
+}
+
 /// A key manager client which talks to a remote key manager enclave.
 pub struct RemoteClient {
+    logger: Logger,
     /// Runtime identifier for which we are going to request keys.
     runtime_id: Namespace,
+    /// Application key namespace within which we are going to request keys.
+    namespace: Option<KeyNamespace>,
+    /// Scheme with which we are going to request long-term keys.
+    scheme: DerivationScheme,
+    /// Number of long-term keys received which were derived under a deprecated scheme.
+    deprecated_keys: AtomicU64,
     /// RPC client.
-    rpc_client: RpcClient,
+    rpc_client: Arc<RpcClient>,
     /// Consensus verifier.
     consensus_verifier: Arc<dyn Verifier>,
     /// Local cache for the long-term private keys.
     longterm_private_keys: RwLock<LruCache<(KeyPairId, u64), KeyPair>>,
     /// Local cache for the long-term public keys.
     longterm_public_keys: RwLock<LruCache<(KeyPairId, u64), SignedPublicKey>>,
+    /// Local cache for the long-term public keys signed with a validity window.
+    validated_public_keys: RwLock<LruCache<(KeyPairId, u64), SignedPublicKey>>,
     /// Local cache for the ephemeral private keys.
     ephemeral_private_keys: RwLock<LruCache<(KeyPairId, EpochTime), KeyPair>>,
     /// Local cache for the ephemeral public keys.
-    ephemeral_public_keys: RwLock<LruCache<(KeyPairId, EpochTime), SignedPublicKey>>,
+    ephemeral_public_keys: Arc<EphemeralPublicKeys>,
+    /// Ephemeral public keys being refreshed in the background.
+    refreshing_ephemeral_keys: Arc<Mutex<HashSet<(KeyPairId, EpochTime)>>>,
     /// Local cache for the state keys.
     state_keys: RwLock<LruCache<(KeyPairId, u8), StateKey>>,
     /// Key manager runtime ID.
     key_manager_id: RwLock<Option<Namespace>>,
     /// Key manager's runtime signing key.
     rsk: RwLock<Option<PublicKey>>,
+    /// Generation of the latest master secret and its change handlers.
+    generation: GenerationTracker,
+    /// Master secret rotation schedule, as seen in the last key manager status.
+    rotation: RwLock<Option<RotationSchedule>>,
 }
 
 impl RemoteClient {
@@ -107,16 +299,24 @@ impl RemoteClient {
         let cap = NonZeroUsize::new(keys_cache_sizes).unwrap();
 
         Self {
+            logger: get_logger("keymanager/client"),
             runtime_id,
-            rpc_client,
+            namespace: None,
+            scheme: DerivationScheme::default(),
+            deprecated_keys: AtomicU64::new(0),
+            rpc_client: Arc::new(rpc_client),
             consensus_verifier,
             longterm_private_keys: RwLock::new(LruCache::new(cap)),
             longterm_public_keys: RwLock::new(LruCache::new(cap)),
+            validated_public_keys: RwLock::new(LruCache::new(cap)),
             ephemeral_private_keys: RwLock::new(LruCache::new(cap)),
-            ephemeral_public_keys: RwLock::new(LruCache::new(cap)),
+            ephemeral_public_keys: Arc::new(RwLock::new(LruCache::new(cap))),
+            refreshing_ephemeral_keys: Arc::new(Mutex::new(HashSet::new())),
             state_keys: RwLock::new(LruCache::new(cap)),
             key_manager_id: RwLock::new(None),
             rsk: RwLock::new(None),
+            generation: GenerationTracker::default(),
+            rotation: RwLock::new(None),
         }
     }
 
@@ -138,7 +338,8 @@ impl RemoteClient {
             .quote_policy(policy)
             .local_identity(identity)
             .consensus_verifier(Some(consensus_verifier.clone()))
-            .remote_runtime_id(km_runtime_id);
+            .remote_runtime_id(km_runtime_id)
+            .post_quantum_policy(protocol.get_config().rpc_post_quantum_policy);
 
         Self::new(
             runtime_id,
@@ -201,30 +402,111 @@ impl RemoteClient {
         )
     }
 
+    /// Request all keys within the given application key namespace.
+    ///
+    /// The key manager policy determines which enclaves may query private keys in which
+    /// namespaces.
+    pub fn with_key_namespace(mut self, namespace: KeyNamespace) -> Self {
+        self.namespace = Some(namespace);
+        self
+    }
+
+    /// Request all long-term keys derived with the given scheme.
+    ///
+    /// Keys derived under different schemes are unrelated, so any state encrypted under keys
+    /// derived with the previous scheme needs to be re-encrypted. Keys derived under any other
+    /// scheme are rejected. Defaults to the legacy scheme.
+    pub fn with_derivation_scheme(mut self, scheme: DerivationScheme) -> Self {
+        self.scheme = scheme;
+        self
+    }
+
+    /// Number of long-term keys received from the key manager which were derived under a scheme
+    /// scheduled for deprecation.
+    pub fn deprecated_keys(&self) -> u64 {
+        self.deprecated_keys.load(Ordering::Relaxed)
+    }
+
+    /// Make sure long-term keys have been derived under the requested scheme and warn about
+    /// keys derived under a scheme scheduled for deprecation.
+    fn check_derivation_scheme(
+        &self,
+        key_pair_id: KeyPairId,
+        generation: u64,
+        scheme: DerivationScheme,
+    ) -> Result<(), KeyManagerError> {
+        if scheme != self.scheme {
+            return Err(KeyManagerError::DerivationSchemeMismatch(
+                self.scheme,
+                scheme,
+            ));
+        }
+        if !scheme.is_deprecated() {
+            return Ok(());
+        }
+        self.deprecated_keys.fetch_add(1, Ordering::Relaxed);
+
+        warn!(self.logger, "Received keys derived under a deprecated scheme";
+            "key_pair_id" => ?key_pair_id,
+            "generation" => generation,
+            "scheme" => ?scheme,
+            "requested_scheme" => ?self.scheme,
+            "latest_scheme" => ?DerivationScheme::LATEST,
+        );
+
+        Ok(())
+    }
+
+    /// Register a handler which is notified whenever the master secret generation changes.
+    ///
+    /// The handler is not invoked for the generation seen in the first key manager status, only
+    /// for subsequent changes.
+    pub fn add_generation_change_handler(&self, f: Box<GenerationChangeHandler>) {
+        self.generation.add_handler(Arc::from(f));
+    }
+
     /// Set allowed enclaves and runtime signing key from key manager status.
     pub async fn set_status(&self, status: KeyManagerStatus) -> Result<(), KeyManagerError> {
+        // Verify the policy, if set, before applying any part of the status.
+        let policy = match &status.policy {
+            Some(untrusted_policy) => Some(verify_data_and_trusted_signers(untrusted_policy)?),
+            None => None,
+        };
+
         // Set runtime signing key.
         if let Some(rsk) = status.rsk {
             self.rsk.write().unwrap().replace(rsk);
         }
 
+        // Record master secret rotations.
+        let change = match status.is_initialized {
+            true => {
+                self.rotation
+                    .write()
+                    .unwrap()
+                    .get_or_insert_with(RotationSchedule::default)
+                    .record(status.generation, status.rotation_epoch);
+                self.generation.update(status.generation)
+            }
+            false => None,
+        };
+
         // Set key manager runtime ID.
         *self.key_manager_id.write().unwrap() = Some(status.id);
         self.rpc_client.update_runtime_id(Some(status.id)).await;
 
-        // Verify and apply the policy, if set.
-        let untrusted_policy = match status.policy {
-            Some(policy) => policy,
-            None => return Ok(()),
-        };
-
-        let policy = verify_data_and_trusted_signers(&untrusted_policy)?;
-
         // Set client allowed enclaves from key manager policy.
-        if !Policy::unsafe_skip() {
-            let enclaves: HashSet<EnclaveIdentity> =
-                HashSet::from_iter(policy.enclaves.keys().cloned());
-            self.rpc_client.update_enclaves(Some(enclaves)).await;
+        if let Some(policy) = policy {
+            if !Policy::unsafe_skip() {
+                let enclaves: HashSet<EnclaveIdentity> =
+                    HashSet::from_iter(policy.enclaves.keys().cloned());
+                self.rpc_client.update_enclaves(Some(enclaves)).await;
+            }
+        }
+
+        // Notify handlers about master secret rotations, only once the status has been applied.
+        if let Some(change) = change {
+            self.generation.notify(change);
         }
 
         Ok(())
@@ -242,11 +524,64 @@ impl RemoteClient {
         epoch: Option<EpochTime>,
         now: Option<EpochTime>,
     ) -> Result<(), KeyManagerError> {
-        let pk = self.rsk.read().unwrap();
-        let pk = pk.as_ref().ok_or(KeyManagerError::RSKMissing)?;
+        verify_public_key(
+            key,
+            self.runtime_id,
+            self.namespace.as_ref(),
+            self.rsk.read().unwrap().as_ref(),
+            key_pair_id,
+            epoch,
+            now,
+        )
+    }
+
+    fn ephemeral_key_fetcher(&self) -> EphemeralKeyFetcher {
+        EphemeralKeyFetcher {
+            runtime_id: self.runtime_id,
+            namespace: self.namespace,
+            rsk: *self.rsk.read().unwrap(),
+            rpc_client: self.rpc_client.clone(),
+            consensus_verifier: self.consensus_verifier.clone(),
+            cache: self.ephemeral_public_keys.clone(),
+        }
+    }
+
+    /// Refresh the ephemeral public key of the given epoch in the background, unless a refresh
+    /// is already running.
+    fn refresh_public_ephemeral_key(
+        &self,
+        key_pair_id: KeyPairId,
+        epoch: EpochTime,
+        consensus_epoch: EpochTime,
+    ) {
+        let id = (key_pair_id, epoch);
+        if !self.refreshing_ephemeral_keys.lock().unwrap().insert(id) {
+            return;
+        }
+
+        let fetcher = self.ephemeral_key_fetcher();
+        let refreshing = self.refreshing_ephemeral_keys.clone();
+        let logger = self.logger.clone();
+        tokio::spawn(async move {
+            if let Err(err) = fetcher.fetch(key_pair_id, epoch, consensus_epoch).await {
+                warn!(logger, "Failed to refresh ephemeral public key";
+                    "key_pair_id" => ?key_pair_id,
+                    "epoch" => epoch,
+                    "err" => %err,
+                );
+            }
+            refreshing.lock().unwrap().remove(&id);
+        });
+    }
+
+    async fn consensus_epoch(&self) -> Result<EpochTime, KeyManagerError> {
+        let consensus_state = self.consensus_verifier.latest_state().await?;
+        let consensus_epoch = tokio::task::block_in_place(move || {
+            let beacon_state = BeaconState::new(&consensus_state);
+            beacon_state.epoch()
+        })?;
 
-        key.verify(self.runtime_id, key_pair_id, epoch, now, pk)
-            .map_err(KeyManagerError::InvalidSignature)
+        Ok(consensus_epoch)
     }
 
     async fn churp_recover_state_key<S: Suite>(
@@ -254,28 +589,27 @@ impl RemoteClient {
         key_id: KeyPairId,
         status: churp::Status,
     ) -> Result<StateKey, KeyManagerError> {
-        // Fault detection and blame assignment are not supported,
-        // so the minimal number of key shares will suffice.
-        let kind = HandoffKind::CommitteeUnchanged;
-        let player = Player::new(status.threshold, kind);
-        let min_shares = player.min_shares();
-        let mut shares = Vec::with_capacity(min_shares);
-
         // Fetch key shares in random order.
-        let mut committee = status.committee;
-        committee.shuffle(&mut OsRng);
+        let mut committee = status.committee.clone();
+        committee.shuffle(&mut SecureRng);
+
+        // Key shares are verified against the published verification matrix.
+        let vm = self
+            .churp_verified_verification_matrix::<S>(&status, &committee)
+            .await?;
+        let mut shares = KeyShares::<S>::new(&status, key_id, vm);
 
         // Fetch key shares concurrently.
         let mut futures = FuturesUnordered::new();
 
-        loop {
+        while !shares.is_complete() {
             // Continuously add new key share requests until the required
             // number of key shares is received, ensuring the future queue
             // remains filled even if some requests fail.
-            while shares.len() + futures.len() < min_shares {
+            while shares.received() + futures.len() < shares.required() {
                 let node_id = match committee.pop() {
                     Some(node_id) => node_id,
-                    None => return Err(KeyManagerError::InsufficientKeyShares),
+                    None => break,
                 };
 
                 let future = self.rpc_client.secure_call(
@@ -290,11 +624,11 @@ impl RemoteClient {
                     vec![node_id],
                 );
 
-                futures.push(future);
+                futures.push(async move { (node_id, future.await) });
             }
 
             // Wait for the next future to finish.
-            let response = match futures.next().await {
+            let (node_id, response) = match futures.next().await {
                 Some(response) => response,
                 None => break,
             };
@@ -302,38 +636,47 @@ impl RemoteClient {
             // Send back peer feedback.
             let response = response.into_result_with_feedback().await;
 
-            // Decode the response.
-            let encoded_share: EncodedEncryptedPoint = match response {
+            // Decode and verify the response.
+            let encoded_share: EncodedKeyShare = match response {
                 Ok(encoded_share) => encoded_share,
                 Err(_) => continue, // Ignore error and skip this share.
             };
-            let share: EncryptedPoint<S::Group> = match encoded_share.try_into() {
-                Ok(share) => share,
-                Err(_) => continue, // Ignore error and skip this share.
-            };
-
-            shares.push(share);
-        }
-
-        // Abort if we don't have enough shares.
-        if shares.len() != min_shares {
-            return Err(KeyManagerError::InsufficientKeyShares);
+            if let Err(err) = shares.add(node_id, encoded_share) {
+                warn!(self.logger, "Ignoring invalid key share"; "err" => %err);
+            }
         }
 
-        // Prepare salt for key derivation (runtime id || churp id || key id).
-        let mut salt = [0; NAMESPACE_SIZE + 1 + KEY_PAIR_ID_SIZE];
-        salt[..NAMESPACE_SIZE].copy_from_slice(&status.runtime_id.0);
-        salt[NAMESPACE_SIZE] = status.id;
-        salt[NAMESPACE_SIZE + 1..].copy_from_slice(&key_id.0);
+        // Aborts if we don't have enough shares.
+        shares.recover_state_key()
+    }
 
-        // Recover the secret and derive the state key from it.
-        // NOTE: Elliptic curve points in projective form are first converted
-        // to affine form, and then encoded to bytes using point compression.
-        let key = player.recover_key(&shares)?;
-        let secret = key.to_bytes();
-        let state_key = Kdf::state_key(secret.as_ref(), &salt);
+    /// Fetch the verification matrix of the given instance from its committee members,
+    /// until one of them serves a matrix matching the published checksum.
+    async fn churp_verified_verification_matrix<S: Suite>(
+        &self,
+        status: &churp::Status,
+        committee: &[PublicKey],
+    ) -> Result<VerificationMatrix<S::Group>, KeyManagerError> {
+        for node_id in committee {
+            let bytes = match self
+                .churp_verification_matrix(status.id, status.handoff, vec![*node_id])
+                .await
+            {
+                Ok(bytes) => bytes,
+                Err(_) => continue, // Ignore error and try another member.
+            };
+            match verify_verification_matrix::<S>(status, &bytes) {
+                Ok(vm) => return Ok(vm),
+                Err(err) => {
+                    warn!(self.logger, "Ignoring invalid verification matrix";
+                        "node_id" => ?node_id,
+                        "err" => %err,
+                    );
+                }
+            }
+        }
 
-        Ok(state_key)
+        Err(KeyManagerError::InvalidVerificationMatrix)
     }
 }
 
@@ -358,6 +701,10 @@ impl KeyManagerClient for RemoteClient {
         cache.clear();
         drop(cache);
 
+        let mut cache = self.validated_public_keys.write().unwrap();
+        cache.clear();
+        drop(cache);
+
         let mut cache = self.ephemeral_private_keys.write().unwrap();
         cache.clear();
         drop(cache);
@@ -398,6 +745,9 @@ impl KeyManagerClient for RemoteClient {
                     runtime_id: self.runtime_id,
                     key_pair_id,
                     generation,
+                    with_validity: false,
+                    namespace: self.namespace,
+                    scheme: self.scheme,
                 },
                 vec![],
             )
@@ -406,6 +756,8 @@ impl KeyManagerClient for RemoteClient {
             .await
             .map_err(|err| KeyManagerError::Other(err.into()))?;
 
+        self.check_derivation_scheme(key_pair_id, generation, keys.scheme)?;
+
         // Cache key.
         let mut cache = self.longterm_private_keys.write().unwrap();
         cache.put(id, keys.clone());
@@ -413,6 +765,54 @@ impl KeyManagerClient for RemoteClient {
         Ok(keys)
     }
 
+    async fn get_or_create_keys_for_epoch(
+        &self,
+        key_pair_id: KeyPairId,
+        epoch: EpochTime,
+    ) -> Result<KeyPair, KeyManagerError> {
+        let rotation = self
+            .rotation
+            .read()
+            .unwrap()
+            .clone()
+            .ok_or(KeyManagerError::NotInitialized)?;
+
+        let consensus_epoch = self.consensus_epoch().await?;
+        if epoch > consensus_epoch {
+            return Err(KeyManagerError::InvalidEpoch(consensus_epoch, epoch));
+        }
+        let generation = rotation.generation_at(epoch)?;
+
+        self.get_or_create_keys(key_pair_id, generation).await
+    }
+
+    async fn rotation_epochs(&self) -> Result<Vec<EpochTime>, KeyManagerError> {
+        let rotation = self
+            .rotation
+            .read()
+            .unwrap()
+            .clone()
+            .ok_or(KeyManagerError::NotInitialized)?;
+
+        Ok(rotation
+            .retrievable()
+            .into_iter()
+            .map(|(rotation_epoch, _)| rotation_epoch)
+            .collect())
+    }
+
+    async fn retrievable_generations(&self) -> Result<RangeInclusive<u64>, KeyManagerError> {
+        // Keys are retrieved by generation, so rotation records are not needed.
+        let latest = self
+            .rotation
+            .read()
+            .unwrap()
+            .as_ref()
+            .and_then(RotationSchedule::latest)
+            .ok_or(KeyManagerError::NotInitialized)?;
+        Ok(latest.saturating_sub(MAX_KEY_ROTATION_AGE)..=latest)
+    }
+
     async fn get_public_key(
         &self,
         key_pair_id: KeyPairId,
@@ -449,6 +849,9 @@ impl KeyManagerClient for RemoteClient {
                     runtime_id: self.runtime_id,
                     key_pair_id,
                     generation,
+                    with_validity: false,
+                    namespace: self.namespace,
+                    scheme: self.scheme,
                 },
                 vec![],
             )
@@ -459,6 +862,7 @@ impl KeyManagerClient for RemoteClient {
 
         // Verify the signature.
         self.verify_public_key(&key, key_pair_id, None, None)?;
+        self.check_derivation_scheme(key_pair_id, generation, key.scheme)?;
 
         // Cache key.
         let mut cache = self.longterm_public_keys.write().unwrap();
@@ -467,6 +871,73 @@ impl KeyManagerClient for RemoteClient {
         Ok(key)
     }
 
+    async fn get_public_key_cached(
+        &self,
+        key_pair_id: KeyPairId,
+        generation: u64,
+        max_staleness: EpochTime,
+    ) -> Result<SignedPublicKey, KeyManagerError> {
+        let id = (key_pair_id, generation);
+
+        // Fetch current epoch.
+        let consensus_epoch = self.consensus_epoch().await?;
+
+        // First try to fetch from cache. Keys without a validity window (e.g. from key managers
+        // not supporting them) are never served from the cache.
+        {
+            let mut cache = self.validated_public_keys.write().unwrap();
+            if let Some(key) = cache.get(&id) {
+                let fresh = key
+                    .issued
+                    .map(|issued| consensus_epoch <= issued.saturating_add(max_staleness))
+                    .unwrap_or(false);
+                match self.verify_public_key(key, key_pair_id, None, Some(consensus_epoch)) {
+                    Ok(()) if fresh => return Ok(key.clone()),
+                    _ => {
+                        cache.pop(&id);
+                    }
+                }
+            }
+        }
+
+        // No fresh entry in cache, fetch from key manager.
+        let height = self
+            .consensus_verifier
+            .latest_height()
+            .await
+            .map_err(|err| KeyManagerError::Other(err.into()))?;
+
+        let key: SignedPublicKey = self
+            .rpc_client
+            .insecure_call(
+                METHOD_GET_PUBLIC_KEY,
+                LongTermKeyRequest {
+                    height: Some(height),
+                    runtime_id: self.runtime_id,
+                    key_pair_id,
+                    generation,
+                    with_validity: true,
+                    namespace: self.namespace,
+                    scheme: self.scheme,
+                },
+                vec![],
+            )
+            .await
+            .into_result_with_feedback()
+            .await
+            .map_err(|err| KeyManagerError::Other(err.into()))?;
+
+        // Verify the signature.
+        self.verify_public_key(&key, key_pair_id, None, Some(consensus_epoch))?;
+        self.check_derivation_scheme(key_pair_id, generation, key.scheme)?;
+
+        // Cache key.
+        let mut cache = self.validated_public_keys.write().unwrap();
+        cache.put(id, key.clone());
+
+        Ok(key)
+    }
+
     async fn get_or_create_ephemeral_keys(
         &self,
         key_pair_id: KeyPairId,
@@ -498,6 +969,7 @@ impl KeyManagerClient for RemoteClient {
                     runtime_id: self.runtime_id,
                     key_pair_id,
                     epoch,
+                    namespace: self.namespace,
                 },
                 vec![],
             )
@@ -521,11 +993,7 @@ impl KeyManagerClient for RemoteClient {
         let id = (key_pair_id, epoch);
 
         // Fetch current epoch.
-        let consensus_state = self.consensus_verifier.latest_state().await?;
-        let consensus_epoch = tokio::task::block_in_place(move || {
-            let beacon_state = BeaconState::new(&consensus_state);
-            beacon_state.epoch()
-        })?;
+        let consensus_epoch = self.consensus_epoch().await?;
 
         // First try to fetch from cache.
         {
@@ -541,37 +1009,54 @@ impl KeyManagerClient for RemoteClient {
         }
 
         // No entry in cache, fetch from key manager.
-        let height = self
-            .consensus_verifier
-            .latest_height()
-            .await
-            .map_err(|err| KeyManagerError::Other(err.into()))?;
-
-        let key: SignedPublicKey = self
-            .rpc_client
-            .insecure_call(
-                METHOD_GET_PUBLIC_EPHEMERAL_KEY,
-                EphemeralKeyRequest {
-                    height: Some(height),
-                    runtime_id: self.runtime_id,
-                    key_pair_id,
-                    epoch,
-                },
-                vec![],
-            )
-            .await
-            .into_result_with_feedback()
+        self.ephemeral_key_fetcher()
+            .fetch(key_pair_id, epoch, consensus_epoch)
             .await
-            .map_err(|err| KeyManagerError::Other(err.into()))?;
+    }
 
-        // Verify the signature.
-        self.verify_public_key(&key, key_pair_id, Some(epoch), Some(consensus_epoch))?;
+    async fn get_public_ephemeral_key_stale(
+        &self,
+        key_pair_id: KeyPairId,
+        epoch: EpochTime,
+        max_staleness: EpochTime,
+    ) -> Result<SignedPublicKey, KeyManagerError> {
+        // Fetch current epoch.
+        let consensus_epoch = self.consensus_epoch().await?;
+
+        // Find the most recent valid cached key, starting with the given epoch.
+        let cached = find_cached_key(
+            &mut self.ephemeral_public_keys.write().unwrap(),
+            key_pair_id,
+            epoch,
+            consensus_epoch,
+            max_staleness,
+            |key, key_epoch| {
+                self.verify_public_key(key, key_pair_id, Some(key_epoch), Some(consensus_epoch))
+                    .is_ok()
+            },
+        );
+
+        match cached {
+            Some(CachedKey::Current(key)) => Ok(key),
+            Some(CachedKey::Stale(key)) => {
+                // Serve the stale key while the key of the given epoch is fetched.
+                self.refresh_public_ephemeral_key(key_pair_id, epoch, consensus_epoch);
+                Ok(key)
+            }
+            None => self.get_public_ephemeral_key(key_pair_id, epoch).await,
+        }
+    }
 
-        // Cache key.
-        let mut cache = self.ephemeral_public_keys.write().unwrap();
-        cache.put(id, key.clone());
+    async fn get_past_public_ephemeral_key(
+        &self,
+        key_pair_id: KeyPairId,
+        epoch: EpochTime,
+    ) -> Result<SignedPublicKey, KeyManagerError> {
+        // Reject epochs outside the retention window upfront, as the key manager won't serve them.
+        let consensus_epoch = self.consensus_epoch().await?;
+        validate_past_epoch(epoch, consensus_epoch)?;
 
-        Ok(key)
+        self.get_public_ephemeral_key(key_pair_id, epoch).await
     }
 
     async fn replicate_master_secret(
@@ -773,3 +1258,234 @@ impl KeyManagerClient for RemoteClient {
         Ok(state_key)
     }
 }
+
+/// Verify the signature of the given public key by the key manager's runtime signing key.
+fn verify_public_key(
+    key: &SignedPublicKey,
+    runtime_id: Namespace,
+    namespace: Option<&KeyNamespace>,
+    rsk: Option<&PublicKey>,
+    key_pair_id: KeyPairId,
+    epoch: Option<EpochTime>,
+    now: Option<EpochTime>,
+) -> Result<(), KeyManagerError> {
+    let pk = rsk.ok_or(KeyManagerError::RSKMissing)?;
+
+    let key_pair_id = key.scheme.key_pair_id(key_pair_id.namespaced(namespace));
+    key.verify(runtime_id, key_pair_id, epoch, now, pk)
+        .map_err(KeyManagerError::InvalidSignature)
+}
+
+/// Epochs whose ephemeral keys may be served in place of the key of the given epoch at the given
+/// consensus epoch, from the most recent one. Stale keys are only served while the key manager
+/// retains them for at least one more epoch, so that data encrypted with them can still be
+/// decrypted once the next epoch starts.
+fn stale_key_epochs(
+    epoch: EpochTime,
+    consensus_epoch: EpochTime,
+    max_staleness: EpochTime,
+) -> impl Iterator<Item = EpochTime> {
+    let retained = consensus_epoch
+        .saturating_add(1)
+        .saturating_sub(MAX_EPHEMERAL_KEY_AGE);
+    let oldest = epoch.saturating_sub(max_staleness).max(retained).min(epoch);
+    (oldest..=epoch).rev()
+}
+
+/// Cached ephemeral public key served for a requested epoch.
+#[derive(Debug, PartialEq, Eq)]
+enum CachedKey {
+    /// The key of the requested epoch.
+    Current(SignedPublicKey),
+    /// The key of a past epoch, served while the key of the requested epoch is fetched.
+    Stale(SignedPublicKey),
+}
+
+/// Find the most recent cached ephemeral public key which may be served for the given epoch and
+/// passes the given verification, see [`stale_key_epochs`].
+fn find_cached_key<F>(
+    cache: &mut LruCache<(KeyPairId, EpochTime), SignedPublicKey>,
+    key_pair_id: KeyPairId,
+    epoch: EpochTime,
+    consensus_epoch: EpochTime,
+    max_staleness: EpochTime,
+    verify: F,
+) -> Option<CachedKey>
+where
+    F: Fn(&SignedPublicKey, EpochTime) -> bool,
+{
+    stale_key_epochs(epoch, consensus_epoch, max_staleness).find_map(|key_epoch| {
+        let key = cache.get(&(key_pair_id, key_epoch))?;
+        if !verify(key, key_epoch) {
+            return None;
+        }
+        if key_epoch == epoch {
+            Some(CachedKey::Current(key.clone()))
+        } else {
+            Some(CachedKey::Stale(key.clone()))
+        }
+    })
+}
+
+/// Validate that ephemeral keys of the given epoch are still retained by the key manager at the
+/// given consensus epoch.
+fn validate_past_epoch(
+    epoch: EpochTime,
+    consensus_epoch: EpochTime,
+) -> Result<(), KeyManagerError> {
+    if epoch > consensus_epoch {
+        return Err(KeyManagerError::InvalidEpoch(consensus_epoch, epoch));
+    }
+    if consensus_epoch - epoch > MAX_EPHEMERAL_KEY_AGE {
+        return Err(KeyManagerError::EphemeralKeyExpired(epoch));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_validate_past_epoch() {
+        assert!(validate_past_epoch(20, 20).is_ok());
+        assert!(validate_past_epoch(10, 20).is_ok());
+        assert!(matches!(
+            validate_past_epoch(9, 20),
+            Err(KeyManagerError::EphemeralKeyExpired(9))
+        ));
+        assert!(matches!(
+            validate_past_epoch(21, 20),
+            Err(KeyManagerError::InvalidEpoch(20, 21))
+        ));
+    }
+
+    #[test]
+    fn test_stale_key_epochs() {
+        assert_eq!(stale_key_epochs(20, 20, 0).collect::<Vec<_>>(), vec![20]);
+        assert_eq!(
+            stale_key_epochs(20, 20, 2).collect::<Vec<_>>(),
+            vec![20, 19, 18]
+        );
+        assert_eq!(stale_key_epochs(1, 1, 5).collect::<Vec<_>>(), vec![1, 0]);
+
+        // Keys at the edge of the retention window are never served.
+        assert_eq!(
+            stale_key_epochs(100, 100, EpochTime::MAX).count() as u64,
+            MAX_EPHEMERAL_KEY_AGE
+        );
+        assert_eq!(
+            stale_key_epochs(100, 105, EpochTime::MAX).count() as u64,
+            MAX_EPHEMERAL_KEY_AGE - 5
+        );
+        assert_eq!(
+            stale_key_epochs(90, 100, EpochTime::MAX).collect::<Vec<_>>(),
+            vec![90]
+        );
+    }
+
+    #[test]
+    fn test_find_cached_key() {
+        let key_pair_id = KeyPairId::from(vec![1u8; 32]);
+        let key = |epoch: EpochTime| SignedPublicKey {
+            checksum: epoch.to_le_bytes().to_vec(),
+            ..Default::default()
+        };
+        let mut cache = LruCache::new(NonZeroUsize::new(10).unwrap());
+        cache.put((key_pair_id, 18), key(18));
+        cache.put((key_pair_id, 19), key(19));
+
+        // The most recent valid key is served while the key of the epoch is missing.
+        assert_eq!(
+            find_cached_key(&mut cache, key_pair_id, 20, 20, 2, |_, _| true),
+            Some(CachedKey::Stale(key(19)))
+        );
+        assert_eq!(
+            find_cached_key(&mut cache, key_pair_id, 20, 20, 2, |_, epoch| epoch != 19),
+            Some(CachedKey::Stale(key(18)))
+        );
+        assert_eq!(
+            find_cached_key(&mut cache, key_pair_id, 20, 20, 0, |_, _| true),
+            None
+        );
+
+        // Once refreshed, the key of the epoch is served.
+        cache.put((key_pair_id, 20), key(20));
+        assert_eq!(
+            find_cached_key(&mut cache, key_pair_id, 20, 20, 2, |_, _| true),
+            Some(CachedKey::Current(key(20)))
+        );
+    }
+
+    #[test]
+    fn test_generation_tracker() {
+        let tracker = Arc::new(GenerationTracker::default());
+        let changes = Arc::new(Mutex::new(Vec::new()));
+
+        let recorded = changes.clone();
+        let inner = tracker.clone();
+        tracker.add_handler(Arc::new(move |change| {
+            recorded.lock().unwrap().push(change);
+            // Handlers may register further handlers without deadlocking.
+            inner.add_handler(Arc::new(|_| ()));
+        }));
+
+        // The first generation is not a change.
+        assert_eq!(tracker.update(1), None);
+
+        // A generation change notifies the handlers.
+        let change = tracker.update(2).expect("generation should change");
+        assert_eq!(change, GenerationChange { old: 1, new: 2 });
+        tracker.notify(change);
+        assert_eq!(*changes.lock().unwrap(), vec![change]);
+        assert_eq!(tracker.handlers.read().unwrap().len(), 2);
+
+        // A repeated generation does not.
+        assert_eq!(tracker.update(2), None);
+        assert_eq!(*changes.lock().unwrap(), vec![change]);
+    }
+
+    #[test]
+    fn test_rotation_schedule() {
+        let mut rotation = RotationSchedule::default();
+        assert!(matches!(
+            rotation.generation_at(0),
+            Err(KeyManagerError::NotInitialized)
+        ));
+
+        // Rotations not seen by the client are unknown.
+        rotation.record(3, 30);
+        rotation.record(5, 50);
+        assert_eq!(rotation.retrievable(), vec![(30, 3), (50, 5)]);
+        assert_eq!(rotation.generation_at(55).unwrap(), 5);
+        assert_eq!(rotation.generation_at(50).unwrap(), 5);
+        assert!(matches!(
+            rotation.generation_at(49),
+            Err(KeyManagerError::RotationRecordMissing(4))
+        ));
+
+        rotation.record(4, 40);
+        assert_eq!(rotation.retrievable(), vec![(30, 3), (40, 4), (50, 5)]);
+        assert_eq!(rotation.generation_at(49).unwrap(), 4);
+        assert_eq!(rotation.generation_at(30).unwrap(), 3);
+        assert!(matches!(
+            rotation.generation_at(29),
+            Err(KeyManagerError::RotationEpochExpired(29))
+        ));
+
+        // Generations whose keys are no longer retrievable are forgotten.
+        rotation.record(6, 60);
+        assert_eq!(rotation.retrievable(), vec![(40, 4), (50, 5), (60, 6)]);
+        assert!(matches!(
+            rotation.generation_at(39),
+            Err(KeyManagerError::RotationEpochExpired(39))
+        ));
+
+        // Generations don't precede the first one.
+        let mut rotation = RotationSchedule::default();
+        rotation.record(0, 0);
+        rotation.record(1, 5);
+        assert_eq!(rotation.generation_at(0).unwrap(), 0);
+        assert_eq!(rotation.generation_at(4).unwrap(), 0);
+    }
+}
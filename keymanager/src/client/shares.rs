@@ -0,0 +1,290 @@
+//! Recovery of keys from key shares.
+//!
+//! Keys derived by a CHURP instance are never held by a single key manager enclave. Instead,
+//! each member of the instance's committee serves a share of the key, and the key is recovered
+//! once shares from enough distinct members have been collected. Shares are verified to have
+//! been derived for the member serving them, so that a member can't serve shares of others in
+//! order to prevent recovery, and to have been derived from the member's secret share committed
+//! to in the verification matrix, so that a member can't serve invalid shares either.
+use std::{collections::HashSet, convert::TryInto};
+
+use group::GroupEncoding;
+
+use oasis_core_runtime::{
+    common::{
+        crypto::signature::PublicKey,
+        namespace::{Namespace, NAMESPACE_SIZE},
+    },
+    consensus::keymanager::churp::Status,
+};
+use secret_sharing::{
+    churp::{encode_shareholder, HandoffKind, Player},
+    kdc::KeyRecoverer,
+    poly::EncryptedPoint,
+    suites::Suite,
+    vss::VerificationMatrix,
+};
+
+use crate::{
+    api::KeyManagerError,
+    churp::{
+        checksum_verification_matrix_bytes, key_share_proof_domain_separation_tag,
+        sgx_policy_key_id_domain_separation_tag, share_commitment,
+        shareholder_domain_separation_tag, EncodedKeyShare, Kdf, KeyShareProof,
+    },
+    crypto::{KeyPairId, StateKey, KEY_PAIR_ID_SIZE},
+};
+
+/// Decode the verification matrix of the instance with the given status, verifying it against
+/// the published checksum.
+pub fn verify_verification_matrix<S: Suite>(
+    status: &Status,
+    bytes: &[u8],
+) -> Result<VerificationMatrix<S::Group>, KeyManagerError> {
+    let checksum =
+        checksum_verification_matrix_bytes(bytes, &status.runtime_id, status.id, status.handoff);
+    if status.checksum != Some(checksum) {
+        return Err(KeyManagerError::InvalidVerificationMatrix);
+    }
+
+    VerificationMatrix::from_bytes(bytes).ok_or(KeyManagerError::InvalidVerificationMatrix)
+}
+
+/// Key shares of a single key, collected from the committee of a CHURP instance.
+pub struct KeyShares<S: Suite> {
+    churp_id: u8,
+    runtime_id: Namespace,
+    key_id: KeyPairId,
+    committee: HashSet<PublicKey>,
+    verification_matrix: VerificationMatrix<S::Group>,
+    shareholder_dst: Vec<u8>,
+    key_id_dst: Vec<u8>,
+    proof_dst: Vec<u8>,
+    player: Player,
+    members: HashSet<PublicKey>,
+    shares: Vec<EncryptedPoint<S::Group>>,
+}
+
+impl<S: Suite> KeyShares<S> {
+    /// Start collecting shares of the given key from the committee of the instance with the
+    /// given status and verification matrix.
+    pub fn new(
+        status: &Status,
+        key_id: KeyPairId,
+        verification_matrix: VerificationMatrix<S::Group>,
+    ) -> Self {
+        // Fault detection and blame assignment are not supported,
+        // so the minimal number of key shares will suffice.
+        let player = Player::new(status.threshold, HandoffKind::CommitteeUnchanged);
+
+        Self {
+            churp_id: status.id,
+            runtime_id: status.runtime_id,
+            key_id,
+            committee: status.committee.iter().copied().collect(),
+            verification_matrix,
+            shareholder_dst: shareholder_domain_separation_tag(&status.runtime_id, status.id),
+            key_id_dst: sgx_policy_key_id_domain_separation_tag(&status.runtime_id, status.id),
+            proof_dst: key_share_proof_domain_separation_tag(&status.runtime_id, status.id),
+            player,
+            members: HashSet::new(),
+            shares: Vec::new(),
+        }
+    }
+
+    /// Number of shares required to recover the key.
+    pub fn required(&self) -> usize {
+        self.player.min_shares()
+    }
+
+    /// Number of verified shares collected so far.
+    pub fn received(&self) -> usize {
+        self.shares.len()
+    }
+
+    /// Whether enough shares have been collected to recover the key.
+    pub fn is_complete(&self) -> bool {
+        self.received() >= self.required()
+    }
+
+    /// Verify and add the key share served by the given committee member.
+    pub fn add(
+        &mut self,
+        node_id: PublicKey,
+        share: EncodedKeyShare,
+    ) -> Result<(), KeyManagerError> {
+        if !self.committee.contains(&node_id) || self.members.contains(&node_id) {
+            return Err(KeyManagerError::InvalidKeyShare(node_id));
+        }
+        let proof: KeyShareProof<S> = share
+            .proof
+            .try_into()
+            .map_err(|_| KeyManagerError::InvalidKeyShare(node_id))?;
+        let share: EncryptedPoint<S::Group> = share
+            .share
+            .try_into()
+            .map_err(|_| KeyManagerError::InvalidKeyShare(node_id))?;
+        let x = encode_shareholder::<S>(&node_id.0, &self.shareholder_dst)
+            .map_err(|_| KeyManagerError::InvalidKeyShare(node_id))?;
+        if share.x() != &x {
+            return Err(KeyManagerError::InvalidKeyShare(node_id));
+        }
+
+        // Verify that the share was derived from the member's committed secret share.
+        let commitment = share_commitment(&self.verification_matrix, &x);
+        if !proof.verify(
+            &commitment,
+            share.z(),
+            &self.key_id.0,
+            &self.key_id_dst,
+            &self.proof_dst,
+        ) {
+            return Err(KeyManagerError::InvalidKeyShare(node_id));
+        }
+
+        self.members.insert(node_id);
+        self.shares.push(share);
+        Ok(())
+    }
+
+    /// Recover the secret from the collected shares and derive the state key from it.
+    pub fn recover_state_key(&self) -> Result<StateKey, KeyManagerError> {
+        if !self.is_complete() {
+            return Err(KeyManagerError::InsufficientKeyShares(
+                self.required(),
+                self.received(),
+            ));
+        }
+
+        // Prepare salt for key derivation (runtime id || churp id || key id).
+        let mut salt = [0; NAMESPACE_SIZE + 1 + KEY_PAIR_ID_SIZE];
+        salt[..NAMESPACE_SIZE].copy_from_slice(&self.runtime_id.0);
+        salt[NAMESPACE_SIZE] = self.churp_id;
+        salt[NAMESPACE_SIZE + 1..].copy_from_slice(&self.key_id.0);
+
+        // Recover the secret and derive the state key from it.
+        // NOTE: Elliptic curve points in projective form are first converted
+        // to affine form, and then encoded to bytes using point compression.
+        let key = self.player.recover_key(&self.shares[..self.required()])?;
+        let secret = key.to_bytes();
+        let state_key = Kdf::state_key(secret.as_ref(), &salt);
+
+        Ok(state_key)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use group::ff::Field;
+    use rand::rngs::OsRng;
+
+    use oasis_core_runtime::common::crypto::signature::PrivateKey;
+    use secret_sharing::{
+        poly::BivariatePolynomial,
+        suites::{self, p384, GroupDigest},
+    };
+
+    use super::*;
+
+    // Types used in tests.
+    type Suite = p384::Sha3_384;
+    type Group = <Suite as suites::Suite>::Group;
+    type PrimeField = <Group as group::Group>::Scalar;
+
+    struct Dealer {
+        status: Status,
+        bp: BivariatePolynomial<PrimeField>,
+        key_id: KeyPairId,
+    }
+
+    impl Dealer {
+        fn share(&self, node_id: &PublicKey, y_offset: u64) -> EncodedKeyShare {
+            let runtime_id = &self.status.runtime_id;
+            let dst = shareholder_domain_separation_tag(runtime_id, self.status.id);
+            let key_id_dst = sgx_policy_key_id_domain_separation_tag(runtime_id, self.status.id);
+            let proof_dst = key_share_proof_domain_separation_tag(runtime_id, self.status.id);
+
+            let x = encode_shareholder::<Suite>(&node_id.0, &dst).unwrap();
+            let y = self.bp.eval_x(&x).eval(&PrimeField::ZERO) + PrimeField::from_u64(y_offset);
+            let h = <Suite as GroupDigest>::hash_to_group(&self.key_id.0, &key_id_dst).unwrap();
+            let proof =
+                KeyShareProof::<Suite>::prove(&y, &self.key_id.0, &key_id_dst, &proof_dst).unwrap();
+
+            EncodedKeyShare {
+                share: (&EncryptedPoint::new(x, h * y)).into(),
+                proof: (&proof).into(),
+            }
+        }
+    }
+
+    #[test]
+    fn test_key_shares() {
+        let committee: Vec<_> = (0..3)
+            .map(|_| PrivateKey::generate().public_key())
+            .collect();
+        let status = Status {
+            threshold: 1,
+            committee: committee.clone(),
+            ..Default::default()
+        };
+        let dealer = Dealer {
+            status: status.clone(),
+            bp: BivariatePolynomial::random(1, 2, &mut OsRng),
+            key_id: KeyPairId::default(),
+        };
+        let vm = VerificationMatrix::from(&dealer.bp);
+        let mut shares = KeyShares::<Suite>::new(&status, dealer.key_id, vm);
+        assert_eq!(shares.required(), 2);
+
+        shares
+            .add(committee[0], dealer.share(&committee[0], 0))
+            .unwrap();
+        assert!(matches!(
+            shares.recover_state_key(),
+            Err(KeyManagerError::InsufficientKeyShares(2, 1))
+        ));
+
+        // Shares of other members, duplicate shares and shares of non-members are rejected.
+        assert!(matches!(
+            shares.add(committee[1], dealer.share(&committee[2], 0)),
+            Err(KeyManagerError::InvalidKeyShare(_))
+        ));
+        assert!(shares
+            .add(committee[0], dealer.share(&committee[0], 0))
+            .is_err());
+        let other = PrivateKey::generate().public_key();
+        assert!(shares.add(other, dealer.share(&other, 0)).is_err());
+
+        // Shares not derived from the committed secret shares are rejected.
+        assert!(matches!(
+            shares.add(committee[1], dealer.share(&committee[1], 1)),
+            Err(KeyManagerError::InvalidKeyShare(_))
+        ));
+        assert_eq!(shares.received(), 1);
+
+        shares
+            .add(committee[1], dealer.share(&committee[1], 0))
+            .unwrap();
+        assert!(shares.is_complete());
+        shares.recover_state_key().unwrap();
+    }
+
+    #[test]
+    fn test_verify_verification_matrix() {
+        let bp = BivariatePolynomial::<PrimeField>::random(1, 2, &mut OsRng);
+        let bytes = VerificationMatrix::<Group>::from(&bp).to_bytes();
+        let mut status = Status::default();
+        assert!(verify_verification_matrix::<Suite>(&status, &bytes).is_err());
+
+        status.checksum = Some(checksum_verification_matrix_bytes(
+            &bytes,
+            &status.runtime_id,
+            status.id,
+            status.handoff,
+        ));
+        verify_verification_matrix::<Suite>(&status, &bytes).unwrap();
+
+        status.handoff += 1;
+        assert!(verify_verification_matrix::<Suite>(&status, &bytes).is_err());
+    }
+}
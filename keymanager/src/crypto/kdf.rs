@@ -27,8 +27,8 @@ use oasis_core_runtime::{
 use crate::{
     api::KeyManagerError,
     crypto::{
-        pack_runtime_id_generation, unpack_encrypted_secret_nonce, KeyPair, KeyPairId, Secret,
-        SignedPublicKey, StateKey,
+        pack_runtime_id_generation, unpack_encrypted_secret_nonce, DerivationScheme, KeyPair,
+        KeyPairId, Secret, SignedPublicKey, StateKey,
     },
     secrets::SecretProvider,
 };
@@ -158,7 +158,7 @@ struct Inner {
     /// the proposal for the next master secret.
     next_signing_key: Option<signature::PublicKey>,
     /// Local cache for the long-term private keys.
-    longterm_keys: LruCache<(Vec<u8>, u64), KeyPair>,
+    longterm_keys: LruCache<(Vec<u8>, u64, DerivationScheme), KeyPair>,
     /// Local cache for the ephemeral private keys.
     ephemeral_keys: LruCache<(Vec<u8>, EpochTime), KeyPair>,
 }
@@ -532,13 +532,34 @@ impl Kdf {
         inner.get_runtime_id()
     }
 
-    /// Get or create long-term keys.
+    /// Get or create long-term keys, derived with the legacy scheme.
     pub fn get_or_create_longterm_keys(
         &self,
         storage: &dyn KeyValue,
         runtime_id: Namespace,
         key_pair_id: KeyPairId,
         generation: u64,
+    ) -> Result<KeyPair> {
+        self.get_or_create_versioned_longterm_keys(
+            storage,
+            runtime_id,
+            key_pair_id,
+            generation,
+            DerivationScheme::Legacy,
+        )
+    }
+
+    /// Get or create long-term keys, derived with the given scheme.
+    ///
+    /// The key pair ID should already be bound to the scheme, see
+    /// [`DerivationScheme::key_pair_id`].
+    pub fn get_or_create_versioned_longterm_keys(
+        &self,
+        storage: &dyn KeyValue,
+        runtime_id: Namespace,
+        key_pair_id: KeyPairId,
+        generation: u64,
+        scheme: DerivationScheme,
     ) -> Result<KeyPair> {
         // Construct a seed that must be unique for every key request.
         // Long-term keys: seed = runtime_id || key_pair_id
@@ -554,7 +575,7 @@ impl Kdf {
         }
 
         // Check to see if the cached value exists.
-        let id = (seed, generation);
+        let id = (seed, generation, scheme);
         if let Some(keys) = inner.longterm_keys.get(&id) {
             return Ok(keys.clone());
         };
@@ -574,8 +595,11 @@ impl Kdf {
 
         // Generate keys.
         let secret = inner.derive_longterm_secret(&RUNTIME_KDF_CUSTOM, &id.0, id.1)?;
-        // FIXME: Replace KDF custom with XOF custom when possible.
-        let keys = inner.derive_keys(secret, &RUNTIME_KDF_CUSTOM)?;
+        let xof_custom = match scheme {
+            DerivationScheme::Legacy => &RUNTIME_KDF_CUSTOM,
+            DerivationScheme::V1 => &RUNTIME_XOF_CUSTOM,
+        };
+        let keys = inner.derive_keys(secret, xof_custom)?.with_scheme(scheme);
 
         // Insert into the cache.
         inner.longterm_keys.put(id, keys.clone());
@@ -622,8 +646,31 @@ impl Kdf {
         key_pair_id: KeyPairId,
         generation: u64,
     ) -> Result<x25519::PublicKey> {
-        let keys =
-            self.get_or_create_longterm_keys(storage, runtime_id, key_pair_id, generation)?;
+        self.get_public_versioned_longterm_key(
+            storage,
+            runtime_id,
+            key_pair_id,
+            generation,
+            DerivationScheme::Legacy,
+        )
+    }
+
+    /// Get the public part of the long-term key derived with the given scheme.
+    pub fn get_public_versioned_longterm_key(
+        &self,
+        storage: &dyn KeyValue,
+        runtime_id: Namespace,
+        key_pair_id: KeyPairId,
+        generation: u64,
+        scheme: DerivationScheme,
+    ) -> Result<x25519::PublicKey> {
+        let keys = self.get_or_create_versioned_longterm_keys(
+            storage,
+            runtime_id,
+            key_pair_id,
+            generation,
+            scheme,
+        )?;
         Ok(keys.input_keypair.pk)
     }
 
@@ -656,6 +703,25 @@ impl Kdf {
         SignedPublicKey::new(key, checksum, runtime_id, key_pair_id, epoch, signer)
     }
 
+    /// Signs the long-term public key using the key manager key, with a validity window
+    /// starting at the given epoch.
+    pub fn sign_public_key_with_validity(
+        &self,
+        key: x25519::PublicKey,
+        runtime_id: Namespace,
+        key_pair_id: KeyPairId,
+        issued: EpochTime,
+    ) -> Result<SignedPublicKey> {
+        let inner = self.inner.read().unwrap();
+        let checksum = inner.get_checksum()?;
+        let signer = inner
+            .signer
+            .as_ref()
+            .ok_or(KeyManagerError::NotInitialized)?;
+
+        SignedPublicKey::new_with_validity(key, checksum, runtime_id, key_pair_id, issued, signer)
+    }
+
     /// Replicate master secret.
     pub fn replicate_master_secret(
         &self,
@@ -986,7 +1052,7 @@ mod tests {
                 MASTER_SECRET_CHECKSUM_STORAGE_KEY_PREFIX, MASTER_SECRET_STORAGE_KEY_PREFIX,
                 RUNTIME_SIGNING_KEY_CUSTOM,
             },
-            KeyPairId, Secret, SECRET_SIZE,
+            DerivationScheme, KeyPairId, Secret, SECRET_SIZE,
         },
         secrets::MockSecretProvider,
     };
@@ -1469,6 +1535,57 @@ mod tests {
         assert_eq!(sk.input_keypair.pk, pk);
     }
 
+    #[test]
+    fn versioned_keys_are_unique() {
+        let kdf = Kdf::default();
+        let storage = UntrustedInMemoryStorage::new();
+        let runtime_id = Namespace::from(vec![1u8; 32]);
+        let key_pair_id = KeyPairId::from(vec![2u8; 32]);
+        let generation = 0;
+
+        // Legacy keys are retained.
+        let legacy = kdf
+            .get_or_create_versioned_longterm_keys(
+                &storage,
+                runtime_id,
+                key_pair_id,
+                generation,
+                DerivationScheme::Legacy,
+            )
+            .expect("private key should be created");
+        let sk = kdf
+            .get_or_create_longterm_keys(&storage, runtime_id, key_pair_id, generation)
+            .expect("private key should be created");
+        assert_eq!(legacy.scheme, DerivationScheme::Legacy);
+        assert_eq!(legacy.input_keypair.pk, sk.input_keypair.pk);
+
+        // Keys derived under different schemes are unrelated, even for the same key pair ID.
+        let v1 = kdf
+            .get_or_create_versioned_longterm_keys(
+                &storage,
+                runtime_id,
+                key_pair_id,
+                generation,
+                DerivationScheme::V1,
+            )
+            .expect("private key should be created");
+        assert_eq!(v1.scheme, DerivationScheme::V1);
+        assert_ne!(v1.input_keypair.pk, legacy.input_keypair.pk);
+        assert_ne!(v1.state_key.0, legacy.state_key.0);
+
+        kdf.clear_cache();
+        let pk = kdf
+            .get_public_versioned_longterm_key(
+                &storage,
+                runtime_id,
+                key_pair_id,
+                generation,
+                DerivationScheme::V1,
+            )
+            .unwrap();
+        assert_eq!(v1.input_keypair.pk, pk);
+    }
+
     #[test]
     fn public_key_signature_is_valid() {
         let kdf = Kdf::default();
@@ -1,13 +1,15 @@
 use std::sync::Arc;
 
 use anyhow::Result;
-use rand::{rngs::OsRng, Rng};
+use rand::Rng;
 use thiserror::Error;
 use zeroize::{Zeroize, ZeroizeOnDrop};
 
 use oasis_core_runtime::{
     common::{
         crypto::{
+            hash::Hash,
+            rng::SecureRng,
             signature::{self, Signature, Signer},
             x25519,
         },
@@ -17,12 +19,21 @@ use oasis_core_runtime::{
     impl_bytes,
 };
 
+pub use oasis_core_runtime::consensus::keymanager::KeyNamespace;
+
 /// Context used for the public key signature.
-const PUBLIC_KEY_SIGNATURE_CONTEXT: &[u8] = b"oasis-core/keymanager: pk signature";
+pub(crate) const PUBLIC_KEY_SIGNATURE_CONTEXT: &[u8] = b"oasis-core/keymanager: pk signature";
+
+/// Context used for the public key signature with a validity window.
+pub(crate) const PUBLIC_KEY_VALIDITY_SIGNATURE_CONTEXT: &[u8] =
+    b"oasis-core/keymanager: pk validity signature";
 
 /// Maximum age of a signed ephemeral public key in the number of epochs.
 const MAX_SIGNED_EPHEMERAL_PUBLIC_KEY_AGE: EpochTime = 10;
 
+/// Length of the validity window of a signed long-term public key in the number of epochs.
+const SIGNED_PUBLIC_KEY_VALIDITY: EpochTime = 10;
+
 /// The size of the key manager state checksum.
 const CHECKSUM_SIZE: usize = 32;
 
@@ -35,12 +46,85 @@ pub const SECRET_SIZE: usize = 32;
 /// The size of the key pair identifier.
 pub const KEY_PAIR_ID_SIZE: usize = 32;
 
+/// Context used to derive key pair identifiers within an application key namespace.
+const NAMESPACED_KEY_PAIR_ID_CONTEXT: &[u8] = b"oasis-core/keymanager: namespaced key pair id";
+
+/// Context used to derive key pair identifiers bound to a key derivation scheme.
+const VERSIONED_KEY_PAIR_ID_CONTEXT: &[u8] = b"oasis-core/keymanager: versioned key pair id";
+
 impl_bytes!(
     KeyPairId,
     KEY_PAIR_ID_SIZE,
     "A 256-bit key pair identifier."
 );
 
+impl KeyPairId {
+    /// Return the identifier the keys are derived for when requested within the given
+    /// application key namespace, if any.
+    ///
+    /// Namespaced identifiers are hashes, so they can only be obtained through the namespace.
+    pub fn namespaced(&self, namespace: Option<&KeyNamespace>) -> KeyPairId {
+        match namespace {
+            Some(namespace) => KeyPairId(
+                Hash::digest_bytes_list(&[
+                    NAMESPACED_KEY_PAIR_ID_CONTEXT,
+                    namespace.as_ref(),
+                    self.as_ref(),
+                ])
+                .into(),
+            ),
+            None => *self,
+        }
+    }
+}
+
+/// Scheme used to derive long-term keys from the master secret.
+///
+/// Keys derived under different schemes are unrelated, so runtimes switching to a newer scheme
+/// need to re-encrypt any state encrypted under keys derived with the old one. Schemes scheduled
+/// for deprecation will be removed in a future key manager upgrade.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash, cbor::Encode, cbor::Decode)]
+#[repr(u8)]
+pub enum DerivationScheme {
+    /// Original scheme, which uses the secret derivation customization string also to expand
+    /// the derived secret into keys.
+    #[default]
+    Legacy = 0,
+    /// Scheme using dedicated customization strings for secret derivation and key expansion,
+    /// with key pair identifiers bound to the scheme.
+    V1 = 1,
+}
+
+impl DerivationScheme {
+    /// Latest derivation scheme.
+    pub const LATEST: DerivationScheme = DerivationScheme::V1;
+
+    /// Whether the scheme is scheduled for deprecation.
+    pub fn is_deprecated(&self) -> bool {
+        match self {
+            DerivationScheme::Legacy => true,
+            DerivationScheme::V1 => false,
+        }
+    }
+
+    /// Return the identifier the keys are derived for under this scheme.
+    ///
+    /// Legacy keys are derived for the given identifier, so that existing keys are retained.
+    pub fn key_pair_id(&self, key_pair_id: KeyPairId) -> KeyPairId {
+        match self {
+            DerivationScheme::Legacy => key_pair_id,
+            _ => KeyPairId(
+                Hash::digest_bytes_list(&[
+                    VERSIONED_KEY_PAIR_ID_CONTEXT,
+                    &[*self as u8],
+                    key_pair_id.as_ref(),
+                ])
+                .into(),
+            ),
+        }
+    }
+}
+
 /// A state encryption key.
 #[derive(Clone, Default, cbor::Encode, cbor::Decode, Zeroize, ZeroizeOnDrop)]
 #[cbor(transparent)]
@@ -59,7 +143,7 @@ pub struct Secret(pub [u8; SECRET_SIZE]);
 
 impl Secret {
     pub fn generate() -> Self {
-        let mut rng = OsRng {};
+        let mut rng = SecureRng;
         let mut secret = Secret::default();
         rng.fill(&mut secret.0);
 
@@ -91,6 +175,9 @@ pub struct KeyPair {
     pub state_key: StateKey,
     /// Checksum of the key manager state.
     pub checksum: Vec<u8>,
+    /// Scheme the keys were derived with.
+    #[cbor(optional)]
+    pub scheme: DerivationScheme,
 }
 
 impl KeyPair {
@@ -99,7 +186,7 @@ impl KeyPair {
         let sk = x25519::PrivateKey::generate();
         let pk = x25519::PublicKey::from(&sk);
 
-        let mut rng = OsRng {};
+        let mut rng = SecureRng;
         let mut state_key = StateKey::default();
         rng.fill(&mut state_key.0);
 
@@ -117,9 +204,16 @@ impl KeyPair {
             input_keypair: InputKeyPair { pk, sk },
             state_key,
             checksum,
+            scheme: DerivationScheme::default(),
         }
     }
 
+    /// Set the scheme the keys were derived with.
+    pub fn with_scheme(mut self, scheme: DerivationScheme) -> Self {
+        self.scheme = scheme;
+        self
+    }
+
     /// Create a `KeyPair` with only the public key.
     pub fn from_public_key(pk: x25519::PublicKey, checksum: Vec<u8>) -> Self {
         Self {
@@ -152,6 +246,8 @@ enum SignedPublicKeyError {
     SignatureFromFuture,
     #[error("signature expired")]
     SignatureExpired,
+    #[error("epoch not expected for keys with a validity window")]
+    UnexpectedEpoch,
 }
 
 /// Signed public key.
@@ -167,6 +263,17 @@ pub struct SignedPublicKey {
     /// Expiration epoch.
     #[cbor(optional)]
     pub expiration: Option<EpochTime>,
+    /// Epoch at which the validity window of the signature starts, if the key was signed with
+    /// one. In that case, the signature is instead computed over
+    /// (key || checksum || runtime id || key pair id || issue epoch || expiration epoch).
+    #[cbor(optional)]
+    pub issued: Option<EpochTime>,
+    /// Scheme the key was derived with.
+    ///
+    /// The scheme is not signed, but the signature covers the key pair identifier bound to the
+    /// scheme, see [`DerivationScheme::key_pair_id`].
+    #[cbor(optional)]
+    pub scheme: DerivationScheme,
 }
 
 impl SignedPublicKey {
@@ -192,6 +299,45 @@ impl SignedPublicKey {
             checksum,
             signature,
             expiration,
+            issued: None,
+            scheme: DerivationScheme::default(),
+        })
+    }
+
+    /// Create a new signed long-term public key, valid for a limited number of epochs starting
+    /// at the given epoch.
+    ///
+    /// Such keys can be cached by clients until the signature expires.
+    pub fn new_with_validity(
+        key: x25519::PublicKey,
+        checksum: Vec<u8>,
+        runtime_id: Namespace,
+        key_pair_id: KeyPairId,
+        issued: EpochTime,
+        signer: &Arc<dyn Signer>,
+    ) -> Result<Self> {
+        if checksum.len() != CHECKSUM_SIZE {
+            return Err(SignedPublicKeyError::InvalidChecksum.into());
+        }
+
+        let expiration = issued + SIGNED_PUBLIC_KEY_VALIDITY;
+        let body = Self::body(
+            key,
+            &checksum,
+            runtime_id,
+            key_pair_id,
+            Some(issued),
+            Some(expiration),
+        );
+        let signature = signer.sign(PUBLIC_KEY_VALIDITY_SIGNATURE_CONTEXT, &body)?;
+
+        Ok(SignedPublicKey {
+            key,
+            checksum,
+            signature,
+            expiration: Some(expiration),
+            issued: Some(issued),
+            scheme: DerivationScheme::default(),
         })
     }
 
@@ -209,8 +355,8 @@ impl SignedPublicKey {
             return Err(SignedPublicKeyError::InvalidChecksum.into());
         }
 
-        // Cache validation for ephemeral keys.
-        if let Some(epoch) = epoch {
+        // Cache validation for ephemeral keys and keys signed with a validity window.
+        if let Some(epoch) = epoch.or(self.issued) {
             let now = now.ok_or(SignedPublicKeyError::CurrentEpochRequired)?;
             if now < epoch {
                 return Err(SignedPublicKeyError::SignatureFromFuture.into());
@@ -223,6 +369,12 @@ impl SignedPublicKey {
             }
         }
 
+        // Validity windows are only used for long-term keys.
+        let (context, epoch) = match self.issued {
+            Some(_) if epoch.is_some() => return Err(SignedPublicKeyError::UnexpectedEpoch.into()),
+            Some(issued) => (PUBLIC_KEY_VALIDITY_SIGNATURE_CONTEXT, Some(issued)),
+            None => (PUBLIC_KEY_SIGNATURE_CONTEXT, epoch),
+        };
         let body = Self::body(
             self.key,
             &self.checksum,
@@ -232,8 +384,7 @@ impl SignedPublicKey {
             self.expiration,
         );
 
-        self.signature
-            .verify(pk, PUBLIC_KEY_SIGNATURE_CONTEXT, &body)
+        self.signature.verify(pk, context, &body)
     }
 
     fn body(
@@ -274,8 +425,8 @@ mod test {
     };
 
     use crate::crypto::{
-        types::MAX_SIGNED_EPHEMERAL_PUBLIC_KEY_AGE, KeyPairId, Secret, SignedPublicKey, StateKey,
-        SECRET_SIZE, STATE_KEY_SIZE,
+        types::{MAX_SIGNED_EPHEMERAL_PUBLIC_KEY_AGE, SIGNED_PUBLIC_KEY_VALIDITY},
+        KeyNamespace, KeyPairId, Secret, SignedPublicKey, StateKey, SECRET_SIZE, STATE_KEY_SIZE,
     };
 
     #[test]
@@ -399,6 +550,8 @@ mod test {
             checksum: signed_pk.checksum.clone(),
             signature: signed_pk.signature.clone(),
             expiration: signed_pk.expiration,
+            issued: signed_pk.issued,
+            scheme: signed_pk.scheme,
         };
         let result = invalid_signed_pk.verify(runtime_id, key_pair_id, epoch, now, &pk);
         assert!(
@@ -413,6 +566,8 @@ mod test {
             checksum: [2u8; 32].to_vec(),
             signature: signed_pk.signature.clone(),
             expiration: signed_pk.expiration,
+            issued: signed_pk.issued,
+            scheme: signed_pk.scheme,
         };
         let result = invalid_signed_pk.verify(runtime_id, key_pair_id, epoch, now, &pk);
         assert!(
@@ -427,6 +582,8 @@ mod test {
             checksum: [1u8; 30].to_vec(),
             signature: signed_pk.signature.clone(),
             expiration: signed_pk.expiration,
+            issued: signed_pk.issued,
+            scheme: signed_pk.scheme,
         };
         let result = invalid_signed_pk.verify(runtime_id, key_pair_id, epoch, now, &pk);
         assert!(
@@ -441,6 +598,8 @@ mod test {
             checksum: signed_pk.checksum.clone(),
             signature: signed_pk.signature.clone(),
             expiration: Some(100),
+            issued: signed_pk.issued,
+            scheme: signed_pk.scheme,
         };
         let result = invalid_signed_pk.verify(runtime_id, key_pair_id, epoch, Some(15), &pk);
         assert!(
@@ -450,6 +609,77 @@ mod test {
         assert_eq!(result.unwrap_err().to_string(), "invalid signature");
     }
 
+    #[test]
+    fn test_signed_public_key_with_validity() {
+        let sk = Arc::new(signature::PrivateKey::from_test_seed("seed".to_string()));
+        let pk = sk.public_key();
+
+        let key = x25519::PublicKey::from([1u8; 32]);
+        let checksum = [1u8; 32].to_vec();
+        let runtime_id = Namespace::from(vec![1u8; 32]);
+        let key_pair_id = KeyPairId::from(vec![1u8; 32]);
+        let signer: Arc<dyn Signer> = sk;
+
+        let issued = 10;
+        let signed_pk = SignedPublicKey::new_with_validity(
+            key,
+            checksum,
+            runtime_id,
+            key_pair_id,
+            issued,
+            &signer,
+        )
+        .expect("signing public key should work");
+        assert_eq!(signed_pk.issued, Some(issued));
+        assert_eq!(
+            signed_pk.expiration,
+            Some(issued + SIGNED_PUBLIC_KEY_VALIDITY)
+        );
+
+        // Verify the signature within the validity window.
+        for now in [issued, issued + SIGNED_PUBLIC_KEY_VALIDITY] {
+            let result = signed_pk.verify(runtime_id, key_pair_id, None, Some(now), &pk);
+            assert!(result.is_ok(), "verification should succeed");
+        }
+
+        // Verify the signature outside the validity window.
+        let result = signed_pk.verify(runtime_id, key_pair_id, None, Some(issued - 1), &pk);
+        assert_eq!(result.unwrap_err().to_string(), "signature from the future");
+        let result = signed_pk.verify(
+            runtime_id,
+            key_pair_id,
+            None,
+            Some(issued + SIGNED_PUBLIC_KEY_VALIDITY + 1),
+            &pk,
+        );
+        assert_eq!(result.unwrap_err().to_string(), "signature expired");
+        let result = signed_pk.verify(runtime_id, key_pair_id, None, None, &pk);
+        assert_eq!(result.unwrap_err().to_string(), "current epoch required");
+
+        // Verify the signature as an ephemeral key.
+        let result = signed_pk.verify(runtime_id, key_pair_id, Some(issued), Some(issued), &pk);
+        assert_eq!(
+            result.unwrap_err().to_string(),
+            "epoch not expected for keys with a validity window"
+        );
+
+        // Verify the signature without the validity window.
+        let invalid_signed_pk = SignedPublicKey {
+            issued: None,
+            ..signed_pk.clone()
+        };
+        let result = invalid_signed_pk.verify(runtime_id, key_pair_id, None, Some(issued), &pk);
+        assert_eq!(result.unwrap_err().to_string(), "invalid signature");
+
+        // Verify the signature with a different validity window.
+        let invalid_signed_pk = SignedPublicKey {
+            issued: Some(issued - 1),
+            ..signed_pk
+        };
+        let result = invalid_signed_pk.verify(runtime_id, key_pair_id, None, Some(issued), &pk);
+        assert_eq!(result.unwrap_err().to_string(), "invalid signature");
+    }
+
     #[test]
     fn test_zeroize_on_drop() {
         // Prepare secret and state key.
@@ -474,4 +704,36 @@ mod test {
             }
         }
     }
+
+    #[test]
+    fn test_namespaced_key_pair_id() {
+        let key_pair_id = KeyPairId::from(vec![1u8; 32]);
+        let namespace1 = KeyNamespace::from(vec![1u8; 32]);
+        let namespace2 = KeyNamespace::from(vec![2u8; 32]);
+
+        assert_eq!(key_pair_id.namespaced(None), key_pair_id);
+
+        let namespaced1 = key_pair_id.namespaced(Some(&namespace1));
+        let namespaced2 = key_pair_id.namespaced(Some(&namespace2));
+        assert_ne!(namespaced1, key_pair_id);
+        assert_ne!(namespaced1, namespaced2);
+        assert_eq!(namespaced1, key_pair_id.namespaced(Some(&namespace1)));
+    }
+
+    #[test]
+    fn test_versioned_key_pair_id() {
+        let key_pair_id = KeyPairId::from(vec![1u8; 32]);
+
+        assert_eq!(
+            DerivationScheme::Legacy.key_pair_id(key_pair_id),
+            key_pair_id
+        );
+
+        let versioned = DerivationScheme::V1.key_pair_id(key_pair_id);
+        assert_ne!(versioned, key_pair_id);
+        assert_eq!(versioned, DerivationScheme::V1.key_pair_id(key_pair_id));
+
+        assert!(DerivationScheme::Legacy.is_deprecated());
+        assert!(!DerivationScheme::LATEST.is_deprecated());
+    }
 }
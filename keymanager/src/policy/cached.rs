@@ -22,7 +22,7 @@ use oasis_core_runtime::{
     storage::KeyValue,
 };
 
-use crate::api::KeyManagerError;
+use crate::{api::KeyManagerError, crypto::KeyNamespace};
 
 use super::verify_data_and_trusted_signers;
 
@@ -112,11 +112,12 @@ impl Policy {
     }
 
     /// Check if the MRSIGNER/MRENCLAVE may query keys for the given
-    /// runtime ID/contract ID.
+    /// runtime ID/contract ID within the given application key namespace.
     pub fn may_get_or_create_keys(
         &self,
         remote_enclave: &EnclaveIdentity,
         runtime_id: &Namespace,
+        namespace: Option<&KeyNamespace>,
     ) -> Result<()> {
         let inner = self.inner.read().unwrap();
         let policy = inner
@@ -124,14 +125,26 @@ impl Policy {
             .as_ref()
             .ok_or(KeyManagerError::NotAuthorized)?;
 
-        match policy.may_get_or_create_keys(remote_enclave, runtime_id) {
-            true => Ok(()),
-            false => Err(KeyManagerError::NotAuthorized.into()),
+        if policy.is_revoked(remote_enclave) {
+            return Err(KeyManagerError::EnclaveRevoked.into());
         }
+
+        policy
+            .may_get_or_create_keys(remote_enclave, runtime_id, namespace)
+            .map_err(Into::into)
     }
 
     /// Check if the MRENCLAVE/MRSIGNER may replicate.
     pub fn may_replicate_secret(&self, remote_enclave: &EnclaveIdentity) -> Result<()> {
+        let inner = self.inner.read().unwrap();
+
+        // Never replicate to revoked enclaves, not even to other instances of ourselves.
+        if let Some(policy) = inner.policy.as_ref() {
+            if policy.is_revoked(remote_enclave) {
+                return Err(KeyManagerError::EnclaveRevoked.into());
+            }
+        }
+
         // Always allow replication to ourselves, if it is possible to do so in
         // an authenticated manner.
         #[cfg(any(target_env = "sgx", feature = "debug-mock-sgx"))]
@@ -142,7 +155,6 @@ impl Policy {
             }
         }
 
-        let inner = self.inner.read().unwrap();
         let policy = inner
             .policy
             .as_ref()
@@ -160,7 +172,7 @@ impl Policy {
         let mut src_set = inner
             .policy
             .as_ref()
-            .map(|policy| policy.may_replicate_from.clone())
+            .map(|policy| &policy.may_replicate_from - &policy.revoked)
             .unwrap_or_default();
 
         if let Some(id) = EnclaveIdentity::current() {
@@ -200,10 +212,12 @@ struct CachedPolicy {
     pub serial: u32,
     pub runtime_id: Namespace,
     pub may_query: HashMap<Namespace, HashSet<EnclaveIdentity>>,
+    pub may_query_namespaces: HashMap<Namespace, HashMap<KeyNamespace, HashSet<EnclaveIdentity>>>,
     pub may_replicate: HashSet<EnclaveIdentity>,
     pub may_replicate_from: HashSet<EnclaveIdentity>,
     pub master_secret_rotation_interval: EpochTime,
     pub max_ephemeral_secret_age: EpochTime,
+    pub revoked: HashSet<EnclaveIdentity>,
 }
 
 impl CachedPolicy {
@@ -220,6 +234,7 @@ impl CachedPolicy {
         cached_policy.serial = policy.serial;
         cached_policy.runtime_id = policy.id;
         cached_policy.checksum = checksum;
+        cached_policy.revoked = policy.revoked.iter().cloned().collect();
 
         // Convert the policy into a cached one.
         let enclave_identity = match EnclaveIdentity::current() {
@@ -237,6 +252,15 @@ impl CachedPolicy {
             }
             cached_policy.may_query.insert(*rt_id, query_ids);
         }
+        for (rt_id, namespaces) in &enclave_policy.may_query_namespaces {
+            let namespaces = namespaces
+                .iter()
+                .map(|(namespace, ids)| (*namespace, ids.iter().cloned().collect()))
+                .collect();
+            cached_policy
+                .may_query_namespaces
+                .insert(*rt_id, namespaces);
+        }
         for e_id in &enclave_policy.may_replicate {
             cached_policy.may_replicate.insert(e_id.clone());
         }
@@ -256,12 +280,25 @@ impl CachedPolicy {
         &self,
         remote_enclave: &EnclaveIdentity,
         runtime_id: &Namespace,
-    ) -> bool {
-        let may_query = match self.may_query.get(runtime_id) {
-            Some(may_query) => may_query,
-            None => return false,
+        namespace: Option<&KeyNamespace>,
+    ) -> Result<(), KeyManagerError> {
+        let may_query = match (self.may_query_namespaces.get(runtime_id), namespace) {
+            // Keys of runtimes with namespaces may only be queried within a namespace, as
+            // namespaced key pair IDs could otherwise be requested directly.
+            (Some(_), None) => return Err(KeyManagerError::KeyNamespaceRequired),
+            (Some(namespaces), Some(namespace)) => namespaces.get(namespace),
+            (None, Some(_)) => None,
+            (None, None) => self.may_query.get(runtime_id),
         };
-        may_query.contains(remote_enclave)
+
+        match may_query {
+            Some(may_query) if may_query.contains(remote_enclave) => Ok(()),
+            _ => Err(KeyManagerError::NotAuthorized),
+        }
+    }
+
+    fn is_revoked(&self, remote_enclave: &EnclaveIdentity) -> bool {
+        self.revoked.contains(remote_enclave)
     }
 
     fn may_replicate_secret(&self, remote_enclave: &EnclaveIdentity) -> bool {
@@ -276,3 +313,69 @@ impl CachedPolicy {
         k.to_vec()
     }
 }
+
+#[cfg(test)]
+mod test {
+    use oasis_core_runtime::common::sgx::{MrEnclave, MrSigner};
+
+    use super::*;
+
+    fn enclave(seed: u8) -> EnclaveIdentity {
+        EnclaveIdentity {
+            mr_enclave: MrEnclave::from(vec![seed; 32]),
+            mr_signer: MrSigner::from(vec![seed; 32]),
+        }
+    }
+
+    #[test]
+    fn test_may_get_or_create_keys_namespaces() {
+        let runtime = Namespace::from(vec![1u8; 32]);
+        let namespaced_runtime = Namespace::from(vec![2u8; 32]);
+        let (app1, app2) = (enclave(1), enclave(2));
+        let (namespace1, namespace2) = (
+            KeyNamespace::from(vec![1u8; 32]),
+            KeyNamespace::from(vec![2u8; 32]),
+        );
+
+        let mut policy = CachedPolicy::default();
+        policy
+            .may_query
+            .insert(runtime, HashSet::from([app1.clone()]));
+        policy.may_query.insert(
+            namespaced_runtime,
+            HashSet::from([app1.clone(), app2.clone()]),
+        );
+        policy.may_query_namespaces.insert(
+            namespaced_runtime,
+            HashMap::from([
+                (namespace1, HashSet::from([app1.clone()])),
+                (namespace2, HashSet::from([app2.clone()])),
+            ]),
+        );
+
+        // Runtimes without namespaces.
+        assert!(policy.may_get_or_create_keys(&app1, &runtime, None).is_ok());
+        assert!(policy
+            .may_get_or_create_keys(&app2, &runtime, None)
+            .is_err());
+        assert!(policy
+            .may_get_or_create_keys(&app1, &runtime, Some(&namespace1))
+            .is_err());
+
+        // Runtimes with namespaces.
+        assert!(matches!(
+            policy.may_get_or_create_keys(&app1, &namespaced_runtime, None),
+            Err(KeyManagerError::KeyNamespaceRequired)
+        ));
+        assert!(policy
+            .may_get_or_create_keys(&app1, &namespaced_runtime, Some(&namespace1))
+            .is_ok());
+        assert!(policy
+            .may_get_or_create_keys(&app2, &namespaced_runtime, Some(&namespace2))
+            .is_ok());
+        assert!(matches!(
+            policy.may_get_or_create_keys(&app2, &namespaced_runtime, Some(&namespace1)),
+            Err(KeyManagerError::NotAuthorized)
+        ));
+    }
+}
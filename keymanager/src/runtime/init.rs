@@ -1,19 +1,38 @@
 use futures::executor::block_on;
 use oasis_core_runtime::{
+    common::crypto::context::ContextRegistry,
     dispatcher::{Initializer, PostInitState, PreInitState},
     enclave_rpc::dispatcher::Handler,
     host::Host,
+    signature_contexts,
 };
 
 use crate::{
-    churp::Churp,
+    api::INIT_RESPONSE_CONTEXT,
+    churp::{Churp, APPLICATION_REQUEST_SIGNATURE_CONTEXT, CONFIRMATION_REQUEST_SIGNATURE_CONTEXT},
+    crypto::{PUBLIC_KEY_SIGNATURE_CONTEXT, PUBLIC_KEY_VALIDITY_SIGNATURE_CONTEXT},
     policy::{set_trusted_signers, TrustedSigners},
 };
 
 use super::secrets::Secrets;
 
+signature_contexts!(
+    KEYMANAGER_SIGNATURE_CONTEXTS = [
+        INIT_RESPONSE_CONTEXT,
+        PUBLIC_KEY_SIGNATURE_CONTEXT,
+        PUBLIC_KEY_VALIDITY_SIGNATURE_CONTEXT,
+        APPLICATION_REQUEST_SIGNATURE_CONTEXT,
+        CONFIRMATION_REQUEST_SIGNATURE_CONTEXT,
+    ]
+);
+
 /// Initialize a keymanager with trusted signers.
 pub fn new_keymanager(signers: TrustedSigners) -> Box<dyn Initializer> {
+    // Make sure the key manager does not reuse any signature contexts of the runtime.
+    ContextRegistry::global()
+        .register_all(KEYMANAGER_SIGNATURE_CONTEXTS)
+        .expect("key manager signature contexts must be unique");
+
     // Initializer.
     let init = move |state: PreInitState<'_>| -> PostInitState {
         // It's not the most elegant solution, but it gets the job done.
@@ -24,7 +43,7 @@ pub fn new_keymanager(signers: TrustedSigners) -> Box<dyn Initializer> {
         // Initialize the set of trusted signers.
         set_trusted_signers(signers.clone());
 
-        let secrets = Box::leak(Box::new(Secrets::new(
+        let secrets: &'static Secrets = Box::leak(Box::new(Secrets::new(
             state.identity.clone(),
             state.consensus_verifier.clone(),
             state.protocol.clone(),
@@ -40,6 +59,13 @@ pub fn new_keymanager(signers: TrustedSigners) -> Box<dyn Initializer> {
         state.rpc_dispatcher.add_methods(secrets.methods());
         state.rpc_dispatcher.add_methods(churp.methods());
 
+        // Invalidate cached responses once the consensus epoch changes.
+        state
+            .rpc_dispatcher
+            .set_response_cache_epoch_source(Some(Box::new(move || {
+                secrets.consensus_epoch().ok()
+            })));
+
         // No transaction dispatcher.
         PostInitState::default()
     };
@@ -35,6 +35,7 @@ use oasis_core_runtime::{
     },
     enclave_rpc::{
         dispatcher::{Handler, Method as RpcMethod, MethodDescriptor as RpcMethodDescriptor},
+        revocation::RevocationList,
         types::Kind as RpcKind,
         Context as RpcContext,
     },
@@ -54,7 +55,7 @@ use crate::{
         ReplicateMasterSecretRequest, ReplicateMasterSecretResponse, SignedInitResponse,
         LOCAL_METHOD_GENERATE_EPHEMERAL_SECRET, LOCAL_METHOD_GENERATE_MASTER_SECRET,
         LOCAL_METHOD_INIT, LOCAL_METHOD_LOAD_EPHEMERAL_SECRET, LOCAL_METHOD_LOAD_MASTER_SECRET,
-        METHOD_GET_OR_CREATE_EPHEMERAL_KEYS, METHOD_GET_OR_CREATE_KEYS,
+        MAX_EPHEMERAL_KEY_AGE, METHOD_GET_OR_CREATE_EPHEMERAL_KEYS, METHOD_GET_OR_CREATE_KEYS,
         METHOD_GET_PUBLIC_EPHEMERAL_KEY, METHOD_GET_PUBLIC_KEY, METHOD_REPLICATE_EPHEMERAL_SECRET,
         METHOD_REPLICATE_MASTER_SECRET,
     },
@@ -62,14 +63,12 @@ use crate::{
     crypto::{
         kdf::{Kdf, State},
         pack_runtime_id_epoch, pack_runtime_id_generation_epoch, unpack_encrypted_secret_nonce,
-        KeyPair, Secret, SignedPublicKey, SECRET_SIZE,
+        KeyNamespace, KeyPair, Secret, SignedPublicKey, SECRET_SIZE,
     },
     policy::Policy,
     secrets::{KeyManagerSecretProvider, SecretProvider},
 };
 
-/// Maximum age of an ephemeral key in the number of epochs.
-const MAX_EPHEMERAL_KEY_AGE: EpochTime = 10;
 /// Maximum age of a fresh height in the number of blocks.
 ///
 /// A height is considered fresh if it is not more than specified amount
@@ -115,7 +114,10 @@ impl Secrets {
 
         // Empty policies are allowed only in unsafe builds.
         let policy = Policy::global();
-        let policy_checksum = policy.init(&self.storage, status.policy)?;
+        let policy_checksum = policy.init(&self.storage, status.policy.clone())?;
+
+        // Apply any emergency revocations to RPC sessions right away.
+        RevocationList::global().update_from_policy(status.policy.as_ref());
 
         // Initialize or update the KDF.
         let generation = status.generation;
@@ -145,14 +147,16 @@ impl Secrets {
         ctx: &RpcContext,
         req: &LongTermKeyRequest,
     ) -> Result<KeyPair> {
-        Self::authorize_private_key_generation(ctx, &req.runtime_id)?;
+        self.ensure_unrestricted()?;
+        Self::authorize_private_key_generation(ctx, &req.runtime_id, req.namespace.as_ref())?;
         self.validate_height_freshness(req.height)?;
 
-        Kdf::global().get_or_create_longterm_keys(
+        Kdf::global().get_or_create_versioned_longterm_keys(
             &self.storage,
             req.runtime_id,
-            req.key_pair_id,
+            req.derived_key_pair_id(),
             req.generation,
+            req.scheme,
         )
     }
 
@@ -162,13 +166,21 @@ impl Secrets {
         // Absolutely anyone is allowed to query public long-term keys.
 
         let kdf = Kdf::global();
-        let pk = kdf.get_public_longterm_key(
+        let key_pair_id = req.derived_key_pair_id();
+        let pk = kdf.get_public_versioned_longterm_key(
             &self.storage,
             req.runtime_id,
-            req.key_pair_id,
+            key_pair_id,
             req.generation,
+            req.scheme,
         )?;
-        let sig = kdf.sign_public_key(pk, req.runtime_id, req.key_pair_id, None)?;
+        let mut sig = if req.with_validity {
+            let epoch = self.consensus_epoch()?;
+            kdf.sign_public_key_with_validity(pk, req.runtime_id, key_pair_id, epoch)?
+        } else {
+            kdf.sign_public_key(pk, req.runtime_id, key_pair_id, None)?
+        };
+        sig.scheme = req.scheme;
         Ok(sig)
     }
 
@@ -178,11 +190,16 @@ impl Secrets {
         ctx: &RpcContext,
         req: &EphemeralKeyRequest,
     ) -> Result<KeyPair> {
-        Self::authorize_private_key_generation(ctx, &req.runtime_id)?;
+        self.ensure_unrestricted()?;
+        Self::authorize_private_key_generation(ctx, &req.runtime_id, req.namespace.as_ref())?;
         self.validate_ephemeral_key_epoch(req.epoch)?;
         self.validate_height_freshness(req.height)?;
 
-        Kdf::global().get_or_create_ephemeral_keys(req.runtime_id, req.key_pair_id, req.epoch)
+        Kdf::global().get_or_create_ephemeral_keys(
+            req.runtime_id,
+            req.derived_key_pair_id(),
+            req.epoch,
+        )
     }
 
     /// See `Kdf::get_public_ephemeral_key`.
@@ -192,8 +209,9 @@ impl Secrets {
         self.validate_ephemeral_key_epoch(req.epoch)?;
 
         let kdf = Kdf::global();
-        let pk = kdf.get_public_ephemeral_key(req.runtime_id, req.key_pair_id, req.epoch)?;
-        let sig = kdf.sign_public_key(pk, req.runtime_id, req.key_pair_id, Some(req.epoch))?;
+        let key_pair_id = req.derived_key_pair_id();
+        let pk = kdf.get_public_ephemeral_key(req.runtime_id, key_pair_id, req.epoch)?;
+        let sig = kdf.sign_public_key(pk, req.runtime_id, key_pair_id, Some(req.epoch))?;
 
         Ok(sig)
     }
@@ -204,6 +222,7 @@ impl Secrets {
         ctx: &RpcContext,
         req: &ReplicateMasterSecretRequest,
     ) -> Result<ReplicateMasterSecretResponse> {
+        self.ensure_unrestricted()?;
         Self::authorize_secret_replication(ctx)?;
         self.validate_height_freshness(req.height)?;
 
@@ -222,6 +241,7 @@ impl Secrets {
         ctx: &RpcContext,
         req: &ReplicateEphemeralSecretRequest,
     ) -> Result<ReplicateEphemeralSecretResponse> {
+        self.ensure_unrestricted()?;
         Self::authorize_secret_replication(ctx)?;
         self.validate_height_freshness(req.height)?;
 
@@ -490,12 +510,16 @@ impl Secrets {
     }
 
     /// Authorize the remote enclave so that the private keys are never released to an incorrect enclave.
-    fn authorize_private_key_generation(ctx: &RpcContext, runtime_id: &Namespace) -> Result<()> {
+    fn authorize_private_key_generation(
+        ctx: &RpcContext,
+        runtime_id: &Namespace,
+        namespace: Option<&KeyNamespace>,
+    ) -> Result<()> {
         if Policy::unsafe_skip() {
             return Ok(()); // Authorize unsafe builds always.
         }
         let remote_enclave = Self::authenticate(ctx)?;
-        Policy::global().may_get_or_create_keys(remote_enclave, runtime_id)
+        Policy::global().may_get_or_create_keys(remote_enclave, runtime_id, namespace)
     }
 
     /// Authorize the remote enclave so that the master and ephemeral secrets are never replicated
@@ -510,13 +534,12 @@ impl Secrets {
 
     /// Authenticate the remote enclave based on the MRSIGNER/MRENCLAVE/request.
     fn authenticate(ctx: &RpcContext) -> Result<&EnclaveIdentity> {
-        let si = ctx.session_info.as_ref();
-        let si = si.ok_or(KeyManagerError::NotAuthenticated)?;
-        Ok(&si.verified_attestation.quote.identity)
+        let caller = ctx.caller().ok_or(KeyManagerError::NotAuthenticated)?;
+        Ok(&caller.enclave_identity)
     }
 
     /// Fetch current epoch from the consensus layer.
-    fn consensus_epoch(&self) -> Result<EpochTime> {
+    pub(crate) fn consensus_epoch(&self) -> Result<EpochTime> {
         let consensus_state = block_on(self.consensus_verifier.latest_state())?;
         let beacon_state = BeaconState::new(&consensus_state);
         let consensus_epoch = beacon_state.epoch()?;
@@ -532,6 +555,15 @@ impl Secrets {
         verifier.verify_key_manager_status(status, self.runtime_id)
     }
 
+    /// Validate that the key manager is not running in restricted mode, in which case it was
+    /// attested in audit mode and must not release any keys or secrets.
+    fn ensure_unrestricted(&self) -> Result<()> {
+        if self.identity.is_restricted() {
+            return Err(KeyManagerError::RestrictedMode.into());
+        }
+        Ok(())
+    }
+
     /// Validate that the epoch used for derivation of ephemeral private keys is not
     /// too far in the future or too far back in the past.
     fn validate_ephemeral_key_epoch(&self, epoch: EpochTime) -> Result<()> {
@@ -666,6 +698,8 @@ impl Handler for Secrets {
                 RpcMethodDescriptor {
                     name: METHOD_GET_OR_CREATE_KEYS.to_string(),
                     kind: RpcKind::NoiseSession,
+                    allow_anonymous: false,
+                    cacheable: false,
                 },
                 move |ctx: &_, req: &_| self.get_or_create_keys(ctx, req),
             ),
@@ -673,6 +707,8 @@ impl Handler for Secrets {
                 RpcMethodDescriptor {
                     name: METHOD_GET_PUBLIC_KEY.to_string(),
                     kind: RpcKind::InsecureQuery,
+                    allow_anonymous: false,
+                    cacheable: true,
                 },
                 move |_ctx: &_, req: &_| self.get_public_key(req),
             ),
@@ -680,6 +716,8 @@ impl Handler for Secrets {
                 RpcMethodDescriptor {
                     name: METHOD_GET_OR_CREATE_EPHEMERAL_KEYS.to_string(),
                     kind: RpcKind::NoiseSession,
+                    allow_anonymous: false,
+                    cacheable: false,
                 },
                 move |ctx: &_, req: &_| self.get_or_create_ephemeral_keys(ctx, req),
             ),
@@ -687,6 +725,8 @@ impl Handler for Secrets {
                 RpcMethodDescriptor {
                     name: METHOD_GET_PUBLIC_EPHEMERAL_KEY.to_string(),
                     kind: RpcKind::InsecureQuery,
+                    allow_anonymous: false,
+                    cacheable: false,
                 },
                 move |_ctx: &_, req: &_| self.get_public_ephemeral_key(req),
             ),
@@ -694,6 +734,8 @@ impl Handler for Secrets {
                 RpcMethodDescriptor {
                     name: METHOD_REPLICATE_MASTER_SECRET.to_string(),
                     kind: RpcKind::NoiseSession,
+                    allow_anonymous: false,
+                    cacheable: false,
                 },
                 move |ctx: &_, req: &_| self.replicate_master_secret(ctx, req),
             ),
@@ -701,6 +743,8 @@ impl Handler for Secrets {
                 RpcMethodDescriptor {
                     name: METHOD_REPLICATE_EPHEMERAL_SECRET.to_string(),
                     kind: RpcKind::NoiseSession,
+                    allow_anonymous: false,
+                    cacheable: false,
                 },
                 move |ctx: &_, req: &_| self.replicate_ephemeral_secret(ctx, req),
             ),
@@ -709,6 +753,8 @@ impl Handler for Secrets {
                 RpcMethodDescriptor {
                     name: LOCAL_METHOD_INIT.to_string(),
                     kind: RpcKind::LocalQuery,
+                    allow_anonymous: false,
+                    cacheable: false,
                 },
                 move |_ctx: &_, req: &_| self.init_kdf(req),
             ),
@@ -716,6 +762,8 @@ impl Handler for Secrets {
                 RpcMethodDescriptor {
                     name: LOCAL_METHOD_GENERATE_MASTER_SECRET.to_string(),
                     kind: RpcKind::LocalQuery,
+                    allow_anonymous: false,
+                    cacheable: false,
                 },
                 move |_ctx: &_, req: &_| self.generate_master_secret(req),
             ),
@@ -723,6 +771,8 @@ impl Handler for Secrets {
                 RpcMethodDescriptor {
                     name: LOCAL_METHOD_GENERATE_EPHEMERAL_SECRET.to_string(),
                     kind: RpcKind::LocalQuery,
+                    allow_anonymous: false,
+                    cacheable: false,
                 },
                 move |_ctx: &_, req: &_| self.generate_ephemeral_secret(req),
             ),
@@ -730,6 +780,8 @@ impl Handler for Secrets {
                 RpcMethodDescriptor {
                     name: LOCAL_METHOD_LOAD_MASTER_SECRET.to_string(),
                     kind: RpcKind::LocalQuery,
+                    allow_anonymous: false,
+                    cacheable: false,
                 },
                 move |_ctx: &_, req: &_| self.load_master_secret(req),
             ),
@@ -737,6 +789,8 @@ impl Handler for Secrets {
                 RpcMethodDescriptor {
                     name: LOCAL_METHOD_LOAD_EPHEMERAL_SECRET.to_string(),
                     kind: RpcKind::LocalQuery,
+                    allow_anonymous: false,
+                    cacheable: false,
                 },
                 move |_ctx: &_, req: &_| self.load_ephemeral_secret(req),
             ),
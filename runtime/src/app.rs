@@ -1,12 +1,12 @@
 //! Runtime apps.
-use std::sync::Arc;
+use std::{sync::Arc, time::Duration};
 
 use anyhow::{bail, Result};
 use async_trait::async_trait;
 
 use crate::{
     common::sgx,
-    consensus::roothash,
+    consensus::{events::RelevantEvent, roothash},
     dispatcher::{Initializer, PostInitState, PreInitState},
     host::Host,
 };
@@ -38,6 +38,7 @@ pub trait App: Send + Sync {
                 // Allow TDX since that is not part of the default policy.
                 tdx: Some(sgx::pcs::TdxQuotePolicy {
                     allowed_tdx_modules: vec![],
+                    ..Default::default()
                 }),
                 ..Default::default()
             }),
@@ -60,6 +61,23 @@ pub trait App: Send + Sync {
         Ok(())
     }
 
+    /// Called on consensus events relevant to the runtime being detected.
+    ///
+    /// See [`RelevantEvent`] for which events are verified.
+    async fn on_consensus_events(&self, height: u64, events: &[RelevantEvent]) -> Result<()> {
+        // Default implementation does nothing.
+        Ok(())
+    }
+
+    /// Called when the host notifies of an impending shutdown.
+    ///
+    /// The application should persist any in-memory state (e.g., flush intent logs or seal state
+    /// to volumes) within the given grace period, after which the runtime is terminated.
+    async fn on_shutdown(&self, grace_period: Duration) -> Result<()> {
+        // Default implementation does nothing.
+        Ok(())
+    }
+
     /// Called for runtime queries.
     async fn query(&self, method: &str, args: Vec<u8>) -> Result<Vec<u8>> {
         // Default implementation rejects all requests.
@@ -1,14 +1,22 @@
 //! Functionality related to the enclave attestation flow.
 use std::sync::Arc;
 
-use anyhow::{bail, Result};
-use slog::{info, Logger};
+use anyhow::{anyhow, bail, Result};
+use slog::{info, warn, Logger};
 
 use crate::{
     app::App,
     common::{
-        crypto::signature::Signer, logger::get_logger, namespace::Namespace, panic::AbortOnPanic,
-        sgx::Quote, version::Version,
+        crypto::signature::{PublicKey, Signature, Signer},
+        logger::get_logger,
+        namespace::Namespace,
+        panic::AbortOnPanic,
+        sgx::{
+            pcs::{CollateralBundle, CollateralCache},
+            EnclaveIdentity, Quote,
+        },
+        time::insecure_posix_time,
+        version::Version,
     },
     consensus::{
         registry::{EndorsedCapabilityTEE, SGXAttestation, ATTESTATION_SIGNATURE_CONTEXT},
@@ -16,10 +24,66 @@ use crate::{
     },
     host::Host,
     identity::Identity,
+    metrics::{MetricsRegistry, METRIC_ATTESTATION_REFRESHES},
     policy::PolicyVerifier,
     types::Body,
 };
 
+/// Signature context used for attestation audit reports.
+pub const AUDIT_REPORT_SIGNATURE_CONTEXT: &[u8] = b"oasis-core/runtime: attestation audit report";
+
+/// Report of the policy violations found while verifying the runtime's own quote in audit mode.
+#[derive(Clone, Debug, Default, PartialEq, Eq, cbor::Encode, cbor::Decode)]
+pub struct AuditReport {
+    /// Identifier of the runtime.
+    pub runtime_id: Namespace,
+    /// Version of the runtime.
+    pub version: Version,
+    /// Identity of the enclave as reported by the quote.
+    pub enclave_identity: EnclaveIdentity,
+    /// Runtime attestation key the quote is bound to.
+    pub rak: PublicKey,
+    /// Time at which the quote was verified.
+    pub timestamp: i64,
+    /// Descriptions of the policy violations.
+    pub violations: Vec<String>,
+}
+
+/// Audit report signed by the runtime attestation key.
+#[derive(Clone, Debug, Default, PartialEq, Eq, cbor::Encode, cbor::Decode)]
+pub struct SignedAuditReport {
+    /// The audit report.
+    pub report: AuditReport,
+    /// RAK signature over the report.
+    pub signature: Signature,
+}
+
+impl SignedAuditReport {
+    /// Sign the given audit report with the given signer.
+    pub fn new(report: AuditReport, signer: &dyn Signer) -> Result<Self> {
+        let signature = signer.sign(
+            AUDIT_REPORT_SIGNATURE_CONTEXT,
+            &cbor::to_vec(report.clone()),
+        )?;
+        Ok(Self { report, signature })
+    }
+
+    /// Verify the signature of the report against the RAK it contains.
+    ///
+    /// Note that this does not establish that the RAK belongs to an enclave with the reported
+    /// identity, as the quote binding it did not pass verification.
+    pub fn verify(&self) -> Result<&AuditReport> {
+        self.signature
+            .verify(
+                &self.report.rak,
+                AUDIT_REPORT_SIGNATURE_CONTEXT,
+                &cbor::to_vec(self.report.clone()),
+            )
+            .map_err(|_| anyhow!("attestation: invalid audit report signature"))?;
+        Ok(&self.report)
+    }
+}
+
 /// Attestation flow handler.
 #[derive(Clone)]
 pub struct Handler {
@@ -28,6 +92,7 @@ pub struct Handler {
     consensus_verifier: Arc<dyn Verifier>,
     runtime_id: Namespace,
     version: Version,
+    audit_mode: bool,
     app: Arc<dyn App>,
     logger: Logger,
 }
@@ -40,6 +105,7 @@ impl Handler {
         consensus_verifier: Arc<dyn Verifier>,
         runtime_id: Namespace,
         version: Version,
+        audit_mode: bool,
         app: Arc<dyn App>,
     ) -> Self {
         Self {
@@ -48,6 +114,7 @@ impl Handler {
             consensus_verifier,
             runtime_id,
             version,
+            audit_mode,
             app,
             logger: get_logger("runtime/attestation"),
         }
@@ -70,6 +137,9 @@ impl Handler {
             Body::RuntimeCapabilityTEEUpdateEndorsementRequest { ect } => {
                 self.update_endorsement(ect).await
             }
+            Body::RuntimeCapabilityTEECollateralLoadRequest { bundles } => {
+                self.load_collateral(bundles)
+            }
 
             _ => bail!("unsupported attestation request"),
         }
@@ -138,7 +208,25 @@ impl Handler {
 
         // Configure the quote and policy on the identity.
         let node_id = self.host.identity().await?;
-        let verified_quote = self.identity.set_quote(node_id, quote)?;
+        let (verified_quote, violations) =
+            self.identity.set_quote(node_id, quote, self.audit_mode)?;
+        let audit_report = if violations.is_empty() {
+            None
+        } else {
+            warn!(self.logger, "Quote violates policy, continuing in restricted mode";
+                "violations" => ?violations,
+            );
+
+            let report = AuditReport {
+                runtime_id: self.runtime_id,
+                version: self.version,
+                enclave_identity: verified_quote.identity.clone(),
+                rak: self.identity.public_rak(),
+                timestamp: insecure_posix_time(),
+                violations,
+            };
+            Some(SignedAuditReport::new(report, self.identity.as_ref())?)
+        };
 
         // Sign the report data, latest verified consensus height, REK and host node ID.
         let consensus_state = self.consensus_verifier.latest_state().await?;
@@ -146,8 +234,13 @@ impl Handler {
         let rek = self.identity.public_rek();
         let h = SGXAttestation::hash(&verified_quote.report_data, &node_id, height, &rek);
         let signature = self.identity.sign(ATTESTATION_SIGNATURE_CONTEXT, &h)?;
+        MetricsRegistry::global().inc(METRIC_ATTESTATION_REFRESHES, 1);
 
-        Ok(Body::RuntimeCapabilityTEERakQuoteResponse { height, signature })
+        Ok(Body::RuntimeCapabilityTEERakQuoteResponse {
+            height,
+            signature,
+            audit_report,
+        })
     }
 
     async fn update_endorsement(&self, ect: EndorsedCapabilityTEE) -> Result<Body> {
@@ -158,4 +251,40 @@ impl Handler {
 
         Ok(Body::RuntimeCapabilityTEEUpdateEndorsementResponse {})
     }
+
+    fn load_collateral(&self, bundles: Vec<CollateralBundle>) -> Result<Body> {
+        info!(self.logger, "Loading quote verification collateral";
+            "count" => bundles.len(),
+        );
+
+        // Collateral is verified before it is loaded, so a bundle failing verification aborts
+        // loading of the remaining bundles.
+        let now = insecure_posix_time();
+        for bundle in bundles {
+            CollateralCache::global().load(bundle, now)?;
+        }
+
+        Ok(Body::RuntimeCapabilityTEECollateralLoadResponse {})
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_signed_audit_report() {
+        let identity = Identity::new();
+        let report = AuditReport {
+            rak: identity.public_rak(),
+            violations: vec!["TCB is out of date".to_string()],
+            ..Default::default()
+        };
+        let signed = SignedAuditReport::new(report.clone(), &identity).unwrap();
+        assert_eq!(signed.verify().unwrap(), &report);
+
+        let mut tampered = signed.clone();
+        tampered.report.violations.clear();
+        assert!(tampered.verify().is_err());
+    }
 }
@@ -0,0 +1,192 @@
+//! Signed runtime build information.
+//!
+//! Clients and monitoring can query the runtime for a structured description of its build (the
+//! protocol and runtime versions, enabled features, crate versions, the TEE type and a summary of
+//! its attestation) via the `METHOD_BUILD_INFO` query. As the host relaying the query is
+//! untrusted, the report is signed by the RAK so that anyone holding an attestation of the RAK
+//! can verify exactly what they are talking to.
+use std::collections::BTreeMap;
+
+use anyhow::Result;
+use thiserror::Error;
+
+use crate::{
+    common::{
+        crypto::signature::{PublicKey, SignatureBundle, Signer},
+        namespace::Namespace,
+        version::Version,
+    },
+    identity::Identity,
+    protocol::Protocol,
+    types::Features,
+    TeeType, BUILD_INFO,
+};
+
+/// Name of the query method returning the signed build information.
+pub const METHOD_BUILD_INFO: &str = "runtime.BuildInfo";
+
+/// Signature context used for signing build information reports.
+pub const BUILD_INFO_SIGNATURE_CONTEXT: &[u8] = b"oasis-core/runtime: build info";
+
+/// Build information verification errors.
+#[derive(Error, Debug, PartialEq, Eq)]
+pub enum BuildInfoError {
+    #[error("build information not signed by the expected RAK")]
+    UnexpectedSigner,
+    #[error("invalid build information signature")]
+    InvalidSignature,
+}
+
+/// Summary of the runtime's attestation status.
+#[derive(Clone, Debug, Default, PartialEq, Eq, cbor::Encode, cbor::Decode)]
+pub struct AttestationSummary {
+    /// Time after which the current quote is no longer considered fresh, if there is a quote.
+    #[cbor(optional)]
+    pub quote_expiration: Option<i64>,
+    /// Identity of the node hosting the runtime, if known.
+    #[cbor(optional)]
+    pub node_id: Option<PublicKey>,
+    /// Whether the node has endorsed the runtime's TEE capability.
+    #[cbor(optional)]
+    pub endorsed: bool,
+}
+
+/// Structured runtime build information.
+#[derive(Clone, Debug, Default, PartialEq, Eq, cbor::Encode, cbor::Decode)]
+pub struct BuildInfoReport {
+    /// Runtime identifier.
+    pub runtime_id: Namespace,
+    /// Runtime host protocol version supported by the runtime.
+    pub protocol_version: Version,
+    /// Version of the runtime.
+    pub runtime_version: Version,
+    /// Consensus protocol version provided by the host.
+    pub consensus_protocol_version: Version,
+    /// TEE type the runtime was built for.
+    pub tee_type: String,
+    /// Whether the build can provide integrity and confidentiality.
+    pub is_secure: bool,
+    /// Enabled build features.
+    #[cbor(optional)]
+    pub build_features: Vec<String>,
+    /// Versions of the core crates the runtime was built with.
+    pub crate_versions: BTreeMap<String, String>,
+    /// Features supported by the runtime.
+    pub features: Features,
+    /// Attestation status summary.
+    pub attestation: AttestationSummary,
+}
+
+impl BuildInfoReport {
+    /// Describe the build of the runtime with the given protocol instance and identity.
+    pub fn new(protocol: &Protocol, identity: &Identity) -> Self {
+        let host_info = protocol.get_host_info();
+        let config = protocol.get_config();
+
+        let tee_type = match BUILD_INFO.tee_type {
+            TeeType::None => "none",
+            TeeType::Sgx => "sgx",
+            TeeType::Tdx => "tdx",
+        };
+        let build_features = [
+            ("debug-mock-sgx", cfg!(feature = "debug-mock-sgx")),
+            ("tdx", cfg!(feature = "tdx")),
+            ("wasm", cfg!(feature = "wasm")),
+        ]
+        .into_iter()
+        .filter(|(_, enabled)| *enabled)
+        .map(|(feature, _)| feature.to_string())
+        .collect();
+        let crate_versions = [(env!("CARGO_PKG_NAME"), env!("CARGO_PKG_VERSION"))]
+            .into_iter()
+            .map(|(name, version)| (name.to_string(), version.to_string()))
+            .collect();
+
+        Self {
+            runtime_id: host_info.runtime_id,
+            protocol_version: BUILD_INFO.protocol_version,
+            runtime_version: config.version,
+            consensus_protocol_version: host_info.consensus_protocol_version,
+            tee_type: tee_type.to_string(),
+            is_secure: BUILD_INFO.is_secure,
+            build_features,
+            crate_versions,
+            features: config.features.clone(),
+            attestation: AttestationSummary {
+                quote_expiration: identity.quote_expiration(),
+                node_id: identity.node_identity(),
+                endorsed: identity.endorsed_capability_tee().is_some(),
+            },
+        }
+    }
+
+    /// Sign the build information with the given RAK.
+    pub fn sign(self, rak: &dyn Signer) -> Result<SignedBuildInfoReport> {
+        let signature = rak.sign(BUILD_INFO_SIGNATURE_CONTEXT, &cbor::to_vec(self.clone()))?;
+
+        Ok(SignedBuildInfoReport {
+            report: self,
+            signature: SignatureBundle {
+                public_key: rak.public(),
+                signature,
+            },
+        })
+    }
+}
+
+/// Build information signed by the runtime's RAK.
+#[derive(Clone, Debug, Default, PartialEq, Eq, cbor::Encode, cbor::Decode)]
+pub struct SignedBuildInfoReport {
+    /// Build information.
+    pub report: BuildInfoReport,
+    /// Signature over the build information by the RAK.
+    pub signature: SignatureBundle,
+}
+
+impl SignedBuildInfoReport {
+    /// Verify the build information signature against the given (attested) RAK.
+    pub fn verify(&self, rak: &PublicKey) -> Result<&BuildInfoReport, BuildInfoError> {
+        if &self.signature.public_key != rak {
+            return Err(BuildInfoError::UnexpectedSigner);
+        }
+        if !self.signature.verify(
+            BUILD_INFO_SIGNATURE_CONTEXT,
+            &cbor::to_vec(self.report.clone()),
+        ) {
+            return Err(BuildInfoError::InvalidSignature);
+        }
+        Ok(&self.report)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::common::crypto::signature::PrivateKey;
+
+    #[test]
+    fn test_signed_build_info() {
+        let rak = PrivateKey::generate();
+        let report = BuildInfoReport {
+            protocol_version: BUILD_INFO.protocol_version,
+            tee_type: "none".to_string(),
+            ..Default::default()
+        };
+
+        let signed = report.clone().sign(&rak).unwrap();
+        assert_eq!(signed.verify(&rak.public_key()), Ok(&report));
+
+        let mut tampered = signed.clone();
+        tampered.report.is_secure = true;
+        assert_eq!(
+            tampered.verify(&rak.public_key()),
+            Err(BuildInfoError::InvalidSignature)
+        );
+
+        let other = PrivateKey::generate();
+        assert_eq!(
+            signed.verify(&other.public_key()),
+            Err(BuildInfoError::UnexpectedSigner)
+        );
+    }
+}
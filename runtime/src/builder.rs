@@ -0,0 +1,410 @@
+//! High-level runtime builder.
+//!
+//! Runtimes are started by [`start_runtime`] with an [`Initializer`] wiring up the dispatchers
+//! and a [`Config`]. A [`RuntimeBuilder`] assembles both from individual pieces with defaults
+//! for everything that is not specific to the runtime, e.g.
+//!
+//! ```rust,ignore
+//! RuntimeBuilder::new(version)
+//!     .trust_root(trust_root)
+//!     .rpc_service(MyService::new())
+//!     .transactions(|state, _| Box::new(MyDispatcher::new(state.consensus_verifier.clone())))
+//!     .start();
+//! ```
+//!
+//! The builder tracks whether the runtime has been given a workload (a transaction dispatcher, a
+//! ROFL application or only RPC methods), so that a runtime which would silently do nothing
+//! fails to compile instead of failing at run time. Likewise, components set up during
+//! initialization with [`RuntimeBuilder::setup`] are carried in the type of the builder and
+//! handed to the transaction dispatcher factory.
+use std::{future::Future, time::Duration};
+
+use crate::{
+    app::App,
+    common::version::Version,
+    config::Config,
+    consensus::verifier::TrustRoot,
+    dispatcher::{Initializer, PostInitState, PreInitState},
+    enclave_rpc::{
+        dispatcher::{Dispatcher as RpcDispatcher, Method},
+        service::Service,
+    },
+    init::start_runtime,
+    shutdown::Stage as ShutdownStage,
+    transaction::{
+        authenticator::Authenticator,
+        dispatcher::Dispatcher as TxnDispatcher,
+        pipeline::{PipelineDispatcher, Stage, TxExecutor},
+        scheduler::BatchScheduler,
+    },
+    types::Features,
+};
+
+/// Setup of the components used by the transaction dispatcher, invoked during initialization
+/// of the dispatchers.
+type Setup<C> = Box<dyn FnOnce(&mut PreInitState<'_>) -> C + Send + Sync>;
+
+/// Factory of the transaction dispatcher, given the components set up during initialization.
+type TxnDispatcherFactory<C> =
+    Box<dyn FnOnce(&PreInitState<'_>, C) -> Box<dyn TxnDispatcher> + Send + Sync>;
+
+/// Registration of an RPC service.
+type ServiceRegistration = Box<dyn FnOnce(&mut RpcDispatcher) + Send + Sync>;
+
+/// Marker of a builder of a runtime without a workload.
+pub struct NoWorkload;
+
+/// Workload of a runtime using components of type `C`.
+pub enum Workload<C> {
+    /// Transactions executed by a transaction dispatcher.
+    Transactions(TxnDispatcherFactory<C>),
+    /// A ROFL application.
+    App(Box<dyn App>),
+    /// RPC methods only.
+    Rpc,
+}
+
+/// Builder of a runtime.
+///
+/// Components set up during initialization, e.g. a key manager client, are carried in the type
+/// `C` of the builder and handed to the transaction dispatcher factory as they are, so that a
+/// missing component fails to compile instead of failing at run time.
+pub struct RuntimeBuilder<W = NoWorkload, C = ()> {
+    config: Config,
+    rpc_methods: Vec<Method>,
+    rpc_services: Vec<ServiceRegistration>,
+    setup: Setup<C>,
+    authenticator: Option<Box<dyn Authenticator>>,
+    scheduler: Option<Box<dyn BatchScheduler>>,
+    workload: W,
+}
+
+impl RuntimeBuilder<NoWorkload, ()> {
+    /// Create a new builder of a runtime with the given version and the default configuration.
+    pub fn new(version: Version) -> Self {
+        Self {
+            config: Config {
+                version,
+                ..Default::default()
+            },
+            rpc_methods: vec![],
+            rpc_services: vec![],
+            setup: Box::new(|_| ()),
+            authenticator: None,
+            scheduler: None,
+            workload: NoWorkload,
+        }
+    }
+}
+
+impl<C: 'static> RuntimeBuilder<NoWorkload, C> {
+    /// Set up components during initialization of the dispatchers, given the components set up
+    /// so far. The components returned by the given function replace the previous ones.
+    pub fn setup<T, F>(self, f: F) -> RuntimeBuilder<NoWorkload, T>
+    where
+        F: FnOnce(&mut PreInitState<'_>, C) -> T + Send + Sync + 'static,
+    {
+        let setup = self.setup;
+        RuntimeBuilder {
+            config: self.config,
+            rpc_methods: self.rpc_methods,
+            rpc_services: self.rpc_services,
+            setup: Box::new(move |state| {
+                let components = setup(state);
+                f(state, components)
+            }),
+            authenticator: self.authenticator,
+            scheduler: self.scheduler,
+            workload: NoWorkload,
+        }
+    }
+
+    /// Execute transactions with the dispatcher created by the given factory from the set up
+    /// components, once all initialization hooks have been invoked.
+    pub fn transactions<F>(self, factory: F) -> RuntimeBuilder<Workload<C>, C>
+    where
+        F: FnOnce(&PreInitState<'_>, C) -> Box<dyn TxnDispatcher> + Send + Sync + 'static,
+    {
+        self.with_workload(Workload::Transactions(Box::new(factory)))
+    }
+
+    /// Execute transactions with a pipeline running the given stages, in order, around the
+    /// executor created by the given factory from the set up components, once all
+    /// initialization hooks have been invoked.
+    pub fn pipeline<F>(
+        self,
+        stages: Vec<Box<dyn Stage>>,
+        factory: F,
+    ) -> RuntimeBuilder<Workload<C>, C>
+    where
+        F: FnOnce(&PreInitState<'_>, C) -> Box<dyn TxExecutor> + Send + Sync + 'static,
+    {
+        self.transactions(move |state, components| {
+            let executor = factory(state, components);
+            Box::new(stages.into_iter().fold(
+                PipelineDispatcher::new(executor),
+                PipelineDispatcher::with_stage,
+            ))
+        })
+    }
+
+    /// Run the given ROFL application.
+    pub fn app(self, app: Box<dyn App>) -> RuntimeBuilder<Workload<C>, C> {
+        self.with_workload(Workload::App(app))
+    }
+
+    /// Only serve the registered RPC methods and services.
+    pub fn rpc_only(self) -> RuntimeBuilder<Workload<C>, C> {
+        self.with_workload(Workload::Rpc)
+    }
+
+    fn with_workload(self, workload: Workload<C>) -> RuntimeBuilder<Workload<C>, C> {
+        RuntimeBuilder {
+            config: self.config,
+            rpc_methods: self.rpc_methods,
+            rpc_services: self.rpc_services,
+            setup: self.setup,
+            authenticator: self.authenticator,
+            scheduler: self.scheduler,
+            workload,
+        }
+    }
+}
+
+impl<W, C: 'static> RuntimeBuilder<W, C> {
+    /// Configure the trust root for consensus layer integrity verification.
+    pub fn trust_root(mut self, trust_root: Option<TrustRoot>) -> Self {
+        self.config.trust_root = trust_root;
+        self
+    }
+
+    /// Configure whether the consensus verifier should keep its view up to date as consensus
+    /// blocks are produced.
+    pub fn proactive_consensus_sync(mut self, enabled: bool) -> Self {
+        self.config.proactive_consensus_sync = enabled;
+        self
+    }
+
+    /// Configure the advertised runtime features.
+    pub fn features(mut self, features: Features) -> Self {
+        self.config.features = features;
+        self
+    }
+
+    /// Configure the maximum number of tree nodes and the total size of values, in bytes, held
+    /// by the storage cache. Zero values denote unlimited capacity.
+    pub fn storage_cache(mut self, node_capacity: usize, value_capacity: usize) -> Self {
+        self.config.storage.cache_node_capacity = node_capacity;
+        self.config.storage.cache_value_capacity = value_capacity;
+        self
+    }
+
+    /// Adjust any other part of the configuration.
+    pub fn configure<F: FnOnce(&mut Config)>(mut self, f: F) -> Self {
+        f(&mut self.config);
+        self
+    }
+
+    /// Register the given RPC method.
+    pub fn rpc_method(mut self, method: Method) -> Self {
+        self.rpc_methods.push(method);
+        self
+    }
+
+    /// Register the given RPC service, which is started once the runtime is ready to serve
+    /// requests and stopped when it shuts down.
+    pub fn rpc_service<S: Service + 'static>(mut self, service: S) -> Self {
+        self.rpc_services
+            .push(Box::new(move |dispatcher| dispatcher.add_service(service)));
+        self
+    }
+
+    /// Authenticate inbound calls with the given authenticator.
+    pub fn authenticator(mut self, authenticator: Box<dyn Authenticator>) -> Self {
+        self.authenticator = Some(authenticator);
+        self
+    }
+
+    /// Order initial batches with the given scheduler in schedule execution mode.
+    pub fn batch_scheduler(mut self, scheduler: Box<dyn BatchScheduler>) -> Self {
+        self.scheduler = Some(scheduler);
+        self
+    }
+
+    /// Invoke the given hook during initialization of the dispatchers, in order of
+    /// registration together with component setups and before the transaction dispatcher is
+    /// created.
+    pub fn on_init<F>(mut self, hook: F) -> Self
+    where
+        F: FnOnce(&mut PreInitState<'_>) + Send + Sync + 'static,
+    {
+        let setup = self.setup;
+        self.setup = Box::new(move |state| {
+            let components = setup(state);
+            hook(state);
+            components
+        });
+        self
+    }
+
+    /// Run the given step in the given stage of each shutdown, see
+    /// [`ShutdownHooks::register`](crate::shutdown::ShutdownHooks::register).
+    pub fn on_shutdown<F, Fut>(self, stage: ShutdownStage, name: &'static str, hook: F) -> Self
+    where
+        F: Fn(Duration) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = anyhow::Result<()>> + Send + 'static,
+    {
+        self.on_init(move |state| state.shutdown_hooks.register(stage, name, hook))
+    }
+}
+
+impl<C: 'static> RuntimeBuilder<Workload<C>, C> {
+    /// Build the initializer and the configuration to start the runtime with.
+    pub fn build(self) -> (Box<dyn Initializer>, Config) {
+        let Self {
+            config,
+            rpc_methods,
+            rpc_services,
+            setup,
+            authenticator,
+            scheduler,
+            workload,
+        } = self;
+
+        let init = move |mut state: PreInitState<'_>| -> PostInitState {
+            let components = setup(&mut state);
+
+            state.rpc_dispatcher.add_methods(rpc_methods);
+            for register in rpc_services {
+                register(state.rpc_dispatcher);
+            }
+
+            let mut post_init_state = PostInitState {
+                authenticator,
+                scheduler,
+                ..Default::default()
+            };
+            match workload {
+                Workload::Transactions(factory) => {
+                    post_init_state.txn_dispatcher = Some(factory(&state, components));
+                }
+                Workload::App(app) => post_init_state.app = Some(app),
+                Workload::Rpc => {}
+            }
+            post_init_state
+        };
+
+        (Box::new(init), config)
+    }
+
+    /// Start the runtime.
+    ///
+    /// This blocks the calling thread until the runtime shuts down.
+    pub fn start(self) {
+        let (initializer, config) = self.build();
+        start_runtime(initializer, config);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::{Arc, Mutex};
+
+    use super::*;
+    use crate::{
+        consensus::{tendermint::verifier::NopVerifier, verifier::Verifier},
+        enclave_rpc::{demux::Demux as RpcDemux, session},
+        future::new_tokio_runtime,
+        identity::Identity,
+        protocol::{HostInfo, Protocol},
+        shutdown::{Orchestrator, ShutdownHooks},
+        tasks::Scheduler,
+        transaction::dispatcher::NoopDispatcher,
+    };
+
+    #[test]
+    fn test_build() {
+        let events = Arc::new(Mutex::new(vec![]));
+        let record = |event: &'static str| {
+            let events = events.clone();
+            move || events.lock().unwrap().push(event)
+        };
+        let (on_init, on_setup, on_factory) = (record("init"), record("setup"), record("factory"));
+        let on_shutdown = record("shutdown");
+
+        let (initializer, config) = RuntimeBuilder::new(Version::new(1, 0, 0))
+            .on_init(move |_| on_init())
+            .setup(move |_, ()| {
+                on_setup();
+                42u64
+            })
+            .on_shutdown(ShutdownStage::CachePersist, "test", move |_| {
+                on_shutdown();
+                async { Ok(()) }
+            })
+            .transactions(move |_, components| {
+                assert_eq!(components, 42);
+                on_factory();
+                Box::new(NoopDispatcher::default())
+            })
+            .build();
+
+        let tokio_runtime = new_tokio_runtime();
+        let protocol = Arc::new(Protocol::offline(
+            tokio_runtime.handle().clone(),
+            Arc::new(Identity::new()),
+            config,
+            HostInfo {
+                runtime_id: Default::default(),
+                consensus_backend: "tendermint".to_string(),
+                consensus_protocol_version: Default::default(),
+                consensus_chain_context: "test".to_string(),
+                local_config: Default::default(),
+                features: Default::default(),
+            },
+        ));
+        let identity = Arc::new(Identity::new());
+        let mut rpc_demux = RpcDemux::new(session::Builder::default(), 1, 1, 60);
+        let mut rpc_dispatcher = RpcDispatcher::default();
+        let consensus_verifier: Arc<dyn Verifier> = Arc::new(NopVerifier::new(protocol.clone()));
+        let scheduler = Arc::new(Scheduler::new(tokio_runtime.handle().clone(), 1));
+        let shutdown_hooks = Arc::new(ShutdownHooks::default());
+        let post_init_state = initializer.init(PreInitState {
+            protocol: &protocol,
+            identity: &identity,
+            rpc_demux: &mut rpc_demux,
+            rpc_dispatcher: &mut rpc_dispatcher,
+            consensus_verifier: &consensus_verifier,
+            scheduler: &scheduler,
+            shutdown_hooks: &shutdown_hooks,
+        });
+
+        // Hooks and setups run in order of registration, before the dispatcher is created.
+        assert!(post_init_state.txn_dispatcher.is_some());
+        assert_eq!(*events.lock().unwrap(), vec!["init", "setup", "factory"]);
+
+        // Shutdown hooks are registered and run on shutdown.
+        let mut orchestrator = Orchestrator::new(Duration::from_secs(1));
+        orchestrator.add_hooks(&shutdown_hooks);
+        tokio_runtime.block_on(orchestrator.run());
+        assert_eq!(
+            *events.lock().unwrap(),
+            vec!["init", "setup", "factory", "shutdown"]
+        );
+    }
+
+    #[test]
+    fn test_builder_config() {
+        let version = Version::new(1, 2, 3);
+        let (_, config) = RuntimeBuilder::new(version)
+            .storage_cache(10, 0)
+            .configure(|config| config.call_trace_capacity = 4)
+            .rpc_only()
+            .build();
+        assert_eq!(config.version, version);
+        assert_eq!(config.storage.cache_node_capacity, 10);
+        assert_eq!(config.storage.cache_value_capacity, 0);
+        assert_eq!(config.call_trace_capacity, 4);
+        assert!(config.trust_root.is_none());
+    }
+}
@@ -3,13 +3,23 @@ use std::{
     cell::RefCell,
     num::NonZeroUsize,
     rc::Rc,
-    sync::{Arc, Mutex, MutexGuard},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex, MutexGuard,
+    },
 };
 
+use anyhow::Result;
+use slog::warn;
+
 use crate::{
-    common::crypto::hash::Hash,
+    common::{crypto::hash::Hash, logger::get_logger},
     protocol::Protocol,
-    storage::mkvs::{sync::HostReadSyncer, Root, Tree},
+    storage::mkvs::{
+        integrity::IntegrityReport,
+        sync::{HostReadSyncer, ReadSync, SharedCacheReadSyncer, SharedNodeCache},
+        Prefix, Root, Tree,
+    },
     types::HostStorageEndpoint,
 };
 
@@ -24,34 +34,70 @@ thread_local! {
 ///
 /// * **Queries** have a thread-local cache as there can be multiple queries running at any given
 ///   time and having a global lock would kill concurrency.
+///
+/// All caches fetch nodes through a shared, lock-sharded node cache (if enabled), so that nodes
+/// fetched by one of them don't need to be fetched from the host again by the others.
+///
+/// All caches can be flushed at once by bumping the cache generation, in which case each cache is
+/// rebuilt the next time it is used.
+///
+/// The execution and check caches preload the configured key prefixes when they are first used
+/// and after each flush.
 #[derive(Clone)]
 pub struct CacheSet {
     protocol: Arc<Protocol>,
+    shared: Option<Arc<SharedNodeCache>>,
     execute: Arc<Mutex<Cache>>,
     check: Arc<Mutex<Cache>>,
+    generation: Arc<AtomicU64>,
+}
+
+/// Report of a storage resync.
+#[derive(Clone, Debug, Default, PartialEq, Eq, cbor::Encode, cbor::Decode)]
+pub struct StorageResyncReport {
+    /// Root the caches were resynced to.
+    pub root: Root,
+    /// Cache generation after the resync.
+    pub generation: u64,
+    /// Number of nodes dropped from the shared node cache.
+    pub dropped_shared_nodes: u64,
 }
 
 impl CacheSet {
     /// Create a new empty cache set.
     pub fn new(protocol: Arc<Protocol>) -> Self {
+        let shared = NonZeroUsize::new(protocol.get_config().storage.shared_cache_node_capacity)
+            .map(|capacity| Arc::new(SharedNodeCache::new(capacity)));
         Self {
-            execute: Arc::new(Mutex::new(Cache::new(&protocol))),
-            check: Arc::new(Mutex::new(Cache::new(&protocol))),
+            execute: Arc::new(Mutex::new(Cache::new(&protocol, &shared))),
+            check: Arc::new(Mutex::new(Cache::new(&protocol, &shared))),
+            shared,
             protocol,
+            generation: Arc::new(AtomicU64::new(0)),
         }
     }
 
     /// Cache used for executing transactions.
     pub fn execute(&self, root: Root) -> MutexGuard<'_, Cache> {
         let mut cache = self.execute.lock().unwrap();
-        cache.maybe_replace(&self.protocol, root);
+        cache.maybe_replace(&self.protocol, &self.shared, root, self.generation());
+        cache.maybe_preload(&self.protocol);
         cache
     }
 
     /// Cache used for checking transactions.
     pub fn check(&self, root: Root) -> MutexGuard<'_, Cache> {
         let mut cache = self.check.lock().unwrap();
-        cache.maybe_replace(&self.protocol, root);
+        cache.maybe_replace(&self.protocol, &self.shared, root, self.generation());
+        cache.maybe_preload(&self.protocol);
+        cache
+    }
+
+    /// Fresh cache used for shadow execution of transactions, so that it cannot affect the
+    /// caches used for executing transactions.
+    pub fn shadow(&self, root: Root) -> Cache {
+        let mut cache = Cache::new(&self.protocol, &None);
+        cache.maybe_replace(&self.protocol, &None, root, self.generation());
         cache
     }
 
@@ -63,12 +109,65 @@ impl CacheSet {
                 return cache.clone();
             }
 
-            let cache = Rc::new(RefCell::new(Cache::new(&self.protocol)));
+            let cache = Rc::new(RefCell::new(Cache::new(&self.protocol, &self.shared)));
             caches.put(root.version, cache.clone());
             cache
         });
-        cache.borrow_mut().maybe_replace(&self.protocol, root);
         cache
+            .borrow_mut()
+            .maybe_replace(&self.protocol, &self.shared, root, self.generation());
+        cache
+    }
+
+    /// Drop all cached nodes and roots and resync the execution and check caches from the given
+    /// root, e.g. after storage has been repaired by the host.
+    ///
+    /// Query caches are thread-local, so they are rebuilt the next time they are used. This
+    /// fetches the root node from the host and must not be called from an async context.
+    pub fn resync(&self, root: Root) -> Result<StorageResyncReport> {
+        let generation = self.generation.fetch_add(1, Ordering::SeqCst) + 1;
+        let dropped_shared_nodes = self
+            .shared
+            .as_ref()
+            .map(|shared| shared.clear())
+            .unwrap_or_default();
+
+        for cache in [&self.execute, &self.check] {
+            let mut cache = cache.lock().unwrap();
+            cache.maybe_replace(&self.protocol, &self.shared, root, generation);
+
+            // Make sure that the host can serve the new root.
+            let mut it = cache.tree.iter();
+            it.rewind();
+            if let Some(err) = it.error() {
+                return Err(anyhow::anyhow!("failed to sync root: {err}"));
+            }
+            drop(it);
+            cache.maybe_preload(&self.protocol);
+        }
+
+        Ok(StorageResyncReport {
+            root,
+            generation,
+            dropped_shared_nodes: dropped_shared_nodes as u64,
+        })
+    }
+
+    /// Re-hash a random sample of at most the given number of cached nodes of both the execution
+    /// and check caches, returning a report for each checked cache.
+    ///
+    /// Caches which are currently in use are skipped rather than waited for, as are the
+    /// short-lived query caches.
+    pub fn verify_integrity(&self, sample: usize) -> Vec<IntegrityReport> {
+        [&self.execute, &self.check]
+            .into_iter()
+            .filter_map(|cache| cache.try_lock().ok())
+            .map(|cache| cache.tree.verify_cache_sample(sample))
+            .collect()
+    }
+
+    fn generation(&self) -> u64 {
+        self.generation.load(Ordering::SeqCst)
     }
 }
 
@@ -76,35 +175,76 @@ impl CacheSet {
 pub struct Cache {
     root: Root,
     tree: Tree,
+    generation: u64,
+    preloaded: bool,
 }
 
 impl Cache {
-    fn new(protocol: &Arc<Protocol>) -> Self {
+    fn new(protocol: &Arc<Protocol>, shared: &Option<Arc<SharedNodeCache>>) -> Self {
         Self {
             root: Default::default(),
-            tree: Self::build(protocol, Default::default()),
+            tree: Self::build(protocol, shared, Default::default()),
+            generation: 0,
+            preloaded: false,
         }
     }
 
-    fn build(protocol: &Arc<Protocol>, root: Root) -> Tree {
+    fn build(protocol: &Arc<Protocol>, shared: &Option<Arc<SharedNodeCache>>, root: Root) -> Tree {
         let config = protocol.get_config();
-        let read_syncer = HostReadSyncer::new(protocol.clone(), HostStorageEndpoint::Runtime);
+        let host_read_syncer = HostReadSyncer::new(protocol.clone(), HostStorageEndpoint::Runtime);
+        let read_syncer: Box<dyn ReadSync> = match shared {
+            Some(shared) => Box::new(SharedCacheReadSyncer::new(host_read_syncer, shared.clone())),
+            None => Box::new(host_read_syncer),
+        };
         Tree::builder()
             .with_capacity(
                 config.storage.cache_node_capacity,
                 config.storage.cache_value_capacity,
             )
+            .with_cache_config(config.storage.cache)
             .with_root(root)
-            .build(Box::new(read_syncer))
+            .build(read_syncer)
     }
 
-    fn maybe_replace(&mut self, protocol: &Arc<Protocol>, root: Root) {
-        if self.root == root {
+    fn maybe_replace(
+        &mut self,
+        protocol: &Arc<Protocol>,
+        shared: &Option<Arc<SharedNodeCache>>,
+        root: Root,
+        generation: u64,
+    ) {
+        if self.root == root && self.generation == generation {
             return;
         }
 
-        self.tree = Self::build(protocol, root);
+        self.tree = Self::build(protocol, shared, root);
         self.root = root;
+        if self.generation != generation {
+            self.generation = generation;
+            self.preloaded = false;
+        }
+    }
+
+    /// Preload the configured key prefixes into the tree, unless already done since the cache
+    /// was created or last flushed.
+    ///
+    /// Preloading is best effort, failures are logged and not retried until the next flush.
+    fn maybe_preload(&mut self, protocol: &Arc<Protocol>) {
+        let config = &protocol.get_config().storage;
+        if self.preloaded || config.preload_prefixes.is_empty() || self.root.hash.is_empty() {
+            return;
+        }
+        self.preloaded = true;
+
+        let prefixes: Vec<Prefix> = config
+            .preload_prefixes
+            .iter()
+            .map(|prefix| prefix.clone().into())
+            .collect();
+        if let Err(err) = self.tree.prefetch_prefixes(&prefixes, config.preload_limit) {
+            let logger = get_logger("runtime/cache");
+            warn!(logger, "Failed to preload state"; "err" => ?err, "root" => ?self.root);
+        }
     }
 
     /// Reference to the cached tree.
@@ -0,0 +1,171 @@
+//! Pluggable AEAD cipher suites.
+//!
+//! Deoxys-II-256-128 is nonce misuse resistant, but it has no hardware support, so workloads
+//! encrypting large amounts of data benefit from using AES-256-GCM on CPUs with AES-NI instead.
+//! Encrypted data carries the identifier of the suite used to produce it, so that data encrypted
+//! under any of the suites remains readable after switching suites.
+use aes_gcm::{
+    aead::{Aead, Payload},
+    Aes256Gcm, KeyInit, Nonce,
+};
+use anyhow::{anyhow, Result};
+use rand::Rng;
+
+use super::{
+    mrae::deoxysii::{DeoxysII, NONCE_SIZE as DEOXYSII_NONCE_SIZE},
+    rng::SecureRng,
+};
+
+/// Size of the keys of all cipher suites.
+pub const KEY_SIZE: usize = 32;
+
+/// AEAD cipher suite.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+#[repr(u8)]
+pub enum CipherSuite {
+    /// Deoxys-II-256-128.
+    #[default]
+    DeoxysII = 0,
+    /// AES-256-GCM.
+    Aes256Gcm = 1,
+}
+
+impl CipherSuite {
+    /// All supported cipher suites.
+    pub const ALL: &'static [CipherSuite] = &[Self::DeoxysII, Self::Aes256Gcm];
+
+    /// Identifier of the cipher suite.
+    pub fn id(&self) -> u8 {
+        *self as u8
+    }
+
+    /// Cipher suite with the given identifier.
+    pub fn from_id(id: u8) -> Option<Self> {
+        Self::ALL.iter().copied().find(|suite| suite.id() == id)
+    }
+
+    /// Size of the nonces of the cipher suite.
+    pub fn nonce_size(&self) -> usize {
+        match self {
+            Self::DeoxysII => DEOXYSII_NONCE_SIZE,
+            Self::Aes256Gcm => 12,
+        }
+    }
+
+    /// Size of the authentication tags of the cipher suite.
+    pub fn tag_size(&self) -> usize {
+        16
+    }
+
+    /// Generate a random nonce for the cipher suite.
+    ///
+    /// AES-256-GCM is not nonce misuse resistant, so random nonces should only be used when the
+    /// number of messages encrypted under the same key is bounded.
+    pub fn generate_nonce(&self) -> Vec<u8> {
+        let mut nonce = vec![0u8; self.nonce_size()];
+        SecureRng.fill(&mut nonce[..]);
+        nonce
+    }
+}
+
+/// A cipher suite instance initialized with a key.
+pub enum Cipher {
+    /// Deoxys-II-256-128 instance.
+    DeoxysII(Box<DeoxysII>),
+    /// AES-256-GCM instance.
+    Aes256Gcm(Box<Aes256Gcm>),
+}
+
+impl Cipher {
+    /// Create a new instance of the given cipher suite.
+    pub fn new(suite: CipherSuite, key: &[u8; KEY_SIZE]) -> Self {
+        match suite {
+            CipherSuite::DeoxysII => Self::DeoxysII(Box::new(DeoxysII::new(key))),
+            CipherSuite::Aes256Gcm => Self::Aes256Gcm(Box::new(Aes256Gcm::new(key.into()))),
+        }
+    }
+
+    /// Cipher suite of the instance.
+    pub fn suite(&self) -> CipherSuite {
+        match self {
+            Self::DeoxysII(_) => CipherSuite::DeoxysII,
+            Self::Aes256Gcm(_) => CipherSuite::Aes256Gcm,
+        }
+    }
+
+    /// Encrypt and authenticate the plaintext and authenticate the additional data.
+    pub fn seal(&self, nonce: &[u8], plaintext: &[u8], additional_data: &[u8]) -> Result<Vec<u8>> {
+        self.check_nonce(nonce)?;
+        match self {
+            Self::DeoxysII(d2) => Ok(d2.seal(
+                nonce.try_into().unwrap(),
+                plaintext.to_vec(),
+                additional_data.to_vec(),
+            )),
+            Self::Aes256Gcm(aes) => aes
+                .encrypt(
+                    Nonce::from_slice(nonce),
+                    Payload {
+                        msg: plaintext,
+                        aad: additional_data,
+                    },
+                )
+                .map_err(|_| anyhow!("aead: encryption failed")),
+        }
+    }
+
+    /// Decrypt and authenticate the ciphertext and authenticate the additional data.
+    pub fn open(&self, nonce: &[u8], ciphertext: &[u8], additional_data: &[u8]) -> Result<Vec<u8>> {
+        self.check_nonce(nonce)?;
+        match self {
+            Self::DeoxysII(d2) => d2
+                .open(
+                    nonce.try_into().unwrap(),
+                    ciphertext.to_vec(),
+                    additional_data.to_vec(),
+                )
+                .map_err(|err| err.into()),
+            Self::Aes256Gcm(aes) => aes
+                .decrypt(
+                    Nonce::from_slice(nonce),
+                    Payload {
+                        msg: ciphertext,
+                        aad: additional_data,
+                    },
+                )
+                .map_err(|_| anyhow!("aead: decryption failed")),
+        }
+    }
+
+    fn check_nonce(&self, nonce: &[u8]) -> Result<()> {
+        if nonce.len() != self.suite().nonce_size() {
+            return Err(anyhow!("aead: invalid nonce size"));
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_cipher_suites() {
+        let key = [42u8; KEY_SIZE];
+        for suite in CipherSuite::ALL {
+            assert_eq!(CipherSuite::from_id(suite.id()), Some(*suite));
+
+            let cipher = Cipher::new(*suite, &key);
+            let nonce = suite.generate_nonce();
+            let ciphertext = cipher.seal(&nonce, b"plaintext", b"ad").unwrap();
+            assert_eq!(ciphertext.len(), b"plaintext".len() + suite.tag_size());
+            assert_eq!(
+                cipher.open(&nonce, &ciphertext, b"ad").unwrap(),
+                b"plaintext"
+            );
+            assert!(cipher.open(&nonce, &ciphertext, b"other").is_err());
+            assert!(cipher.seal(&nonce[1..], b"plaintext", b"ad").is_err());
+        }
+        assert_eq!(CipherSuite::from_id(0xff), None);
+    }
+}
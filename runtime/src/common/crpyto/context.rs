@@ -0,0 +1,214 @@
+//! Registry of signature domain separation contexts.
+//!
+//! A signature is only bound to the protocol it was produced for by its context, so two
+//! protocols sharing a context could accept each other's signatures. Context lists declared via
+//! `signature_contexts!` are checked for duplicates at compile time, while the global registry
+//! detects collisions between lists declared in different crates (e.g. a downstream runtime
+//! reusing a context of this crate) at runtime, when the lists are registered.
+use std::{collections::HashMap, sync::Mutex};
+
+use thiserror::Error;
+
+use crate::{
+    build_info,
+    common::sgx::migration,
+    consensus::{
+        checkpoint,
+        keymanager::{self, churp},
+        registry, roothash, transaction,
+    },
+    handshake,
+    host::secrets,
+};
+
+/// A list of named signature contexts.
+pub type SignatureContexts = [(&'static str, &'static [u8])];
+
+/// Declare a list of named signature contexts, failing compilation in case any two of them
+/// are the same.
+///
+/// The list should be registered in the global registry via `ContextRegistry::register_all`
+/// during initialization to also detect collisions with other lists.
+///
+/// # Examples
+///
+/// ```rust,ignore
+/// signature_contexts!(MY_SIGNATURE_CONTEXTS = [
+///     FOO_SIGNATURE_CONTEXT,
+///     bar::BAR_SIGNATURE_CONTEXT,
+/// ]);
+/// ```
+#[macro_export]
+macro_rules! signature_contexts {
+    ($vis:vis $name:ident = [$($context:path),+ $(,)?]) => {
+        $vis const $name: &$crate::common::crypto::context::SignatureContexts = &[
+            $((stringify!($context), $context)),+
+        ];
+
+        const _: () = assert!(
+            $crate::common::crypto::context::contexts_unique($name),
+            concat!("duplicate signature context in ", stringify!($name)),
+        );
+    };
+}
+
+signature_contexts!(
+    RUNTIME_SIGNATURE_CONTEXTS = [
+        transaction::SIGNATURE_CONTEXT,
+        roothash::COMPUTE_RESULTS_HEADER_SIGNATURE_CONTEXT,
+        roothash::EXECUTOR_COMMITMENT_SIGNATURE_CONTEXT,
+        roothash::PROPOSAL_SIGNATURE_CONTEXT,
+        registry::ATTESTATION_SIGNATURE_CONTEXT,
+        registry::ENDORSE_CAPABILITY_TEE_SIGNATURE_CONTEXT,
+        keymanager::POLICY_SIGNATURE_CONTEXT,
+        keymanager::ENCRYPTED_MASTER_SECRET_SIGNATURE_CONTEXT,
+        keymanager::ENCRYPTED_EPHEMERAL_SECRET_SIGNATURE_CONTEXT,
+        churp::POLICY_SIGNATURE_CONTEXT,
+        checkpoint::CHECKPOINT_SIGNATURE_CONTEXT,
+        migration::MIGRATION_REQUEST_SIGNATURE_CONTEXT,
+        migration::MIGRATION_RESPONSE_SIGNATURE_CONTEXT,
+        handshake::HANDSHAKE_TRANSCRIPT_SIGNATURE_CONTEXT,
+        build_info::BUILD_INFO_SIGNATURE_CONTEXT,
+        secrets::SECRET_CONTEXT,
+    ]
+);
+
+lazy_static! {
+    static ref SIGNATURE_CONTEXT_REGISTRY: ContextRegistry = {
+        let global = ContextRegistry::new();
+        global
+            .register_all(RUNTIME_SIGNATURE_CONTEXTS)
+            .expect("runtime signature contexts must be unique");
+        global
+    };
+}
+
+/// Signature context registry errors.
+#[derive(Error, Debug, PartialEq, Eq)]
+pub enum ContextError {
+    #[error("signature context {context:?} of {name} is already used by {existing}")]
+    Duplicate {
+        context: String,
+        name: &'static str,
+        existing: &'static str,
+    },
+}
+
+/// Registry of the signature contexts in use.
+#[derive(Default)]
+pub struct ContextRegistry {
+    contexts: Mutex<HashMap<&'static [u8], &'static str>>,
+}
+
+impl ContextRegistry {
+    /// Create a new empty registry.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Global signature context registry, containing the contexts of this crate.
+    pub fn global() -> &'static ContextRegistry {
+        &SIGNATURE_CONTEXT_REGISTRY
+    }
+
+    /// Register a named signature context.
+    ///
+    /// Registering the same context under the same name again is a no-op, while registering
+    /// it under a different name fails.
+    pub fn register(&self, name: &'static str, context: &'static [u8]) -> Result<(), ContextError> {
+        let mut contexts = self.contexts.lock().unwrap();
+        match contexts.get(context) {
+            Some(existing) if *existing == name => Ok(()),
+            Some(existing) => Err(ContextError::Duplicate {
+                context: String::from_utf8_lossy(context).into_owned(),
+                name,
+                existing,
+            }),
+            None => {
+                contexts.insert(context, name);
+                Ok(())
+            }
+        }
+    }
+
+    /// Register all signature contexts in the given list, stopping at the first collision.
+    pub fn register_all(&self, contexts: &SignatureContexts) -> Result<(), ContextError> {
+        for &(name, context) in contexts {
+            self.register(name, context)?;
+        }
+        Ok(())
+    }
+
+    /// Whether the given signature context is registered.
+    pub fn is_registered(&self, context: &[u8]) -> bool {
+        self.contexts.lock().unwrap().contains_key(context)
+    }
+}
+
+/// Whether all signature contexts in the given list are distinct.
+///
+/// This is a `const fn` so that lists can be checked at compile time.
+pub const fn contexts_unique(contexts: &SignatureContexts) -> bool {
+    let mut i = 0;
+    while i < contexts.len() {
+        let mut j = i + 1;
+        while j < contexts.len() {
+            if bytes_eq(contexts[i].1, contexts[j].1) {
+                return false;
+            }
+            j += 1;
+        }
+        i += 1;
+    }
+    true
+}
+
+const fn bytes_eq(a: &[u8], b: &[u8]) -> bool {
+    if a.len() != b.len() {
+        return false;
+    }
+    let mut i = 0;
+    while i < a.len() {
+        if a[i] != b[i] {
+            return false;
+        }
+        i += 1;
+    }
+    true
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const FOO_CONTEXT: &[u8] = b"test: foo";
+    const BAR_CONTEXT: &[u8] = b"test: bar";
+
+    signature_contexts!(TEST_SIGNATURE_CONTEXTS = [FOO_CONTEXT, BAR_CONTEXT]);
+
+    #[test]
+    fn test_context_registry() {
+        assert!(contexts_unique(RUNTIME_SIGNATURE_CONTEXTS));
+        assert!(!contexts_unique(&[
+            ("a", &b"same"[..]),
+            ("b", &b"same"[..])
+        ]));
+
+        let global = ContextRegistry::global();
+        assert!(global.is_registered(transaction::SIGNATURE_CONTEXT));
+        assert!(global.register_all(RUNTIME_SIGNATURE_CONTEXTS).is_ok());
+
+        let local = ContextRegistry::new();
+        local.register_all(TEST_SIGNATURE_CONTEXTS).unwrap();
+        assert!(local.register_all(TEST_SIGNATURE_CONTEXTS).is_ok());
+        assert_eq!(
+            local.register("OTHER_CONTEXT", FOO_CONTEXT),
+            Err(ContextError::Duplicate {
+                context: "test: foo".to_string(),
+                name: "OTHER_CONTEXT",
+                existing: "FOO_CONTEXT",
+            })
+        );
+        assert!(!local.is_registered(b"test: baz"));
+    }
+}
@@ -1,6 +1,9 @@
 //! Cryptographic primitives.
 
+pub mod aead;
+pub mod context;
 pub mod hash;
 pub mod mrae;
+pub mod rng;
 pub mod signature;
 pub mod x25519;
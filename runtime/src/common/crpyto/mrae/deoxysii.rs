@@ -1,10 +1,11 @@
 //! Deoxys-II-256-128 MRAE primitives implementation.
 use anyhow::Result;
 use hmac::{Hmac, Mac};
-use rand::rngs::OsRng;
 use sha2::Sha512_256;
 use x25519_dalek::{PublicKey, StaticSecret};
 
+use crate::common::crypto::rng::SecureRng;
+
 pub use deoxysii::{DeoxysII, KEY_SIZE, NONCE_SIZE, TAG_SIZE};
 
 type Kdf = Hmac<Sha512_256>;
@@ -40,7 +41,7 @@ fn derive_symmetric_key(public: &PublicKey, private: &StaticSecret) -> [u8; KEY_
 /// Generates a public/private key pair suitable for use with
 /// `derive_symmetric_key`, `box_seal`, and `box_open`.
 pub fn generate_key_pair() -> (PublicKey, StaticSecret) {
-    let sk = StaticSecret::random_from_rng(OsRng);
+    let sk = StaticSecret::random_from_rng(SecureRng);
     let pk = PublicKey::from(&sk);
 
     (pk, sk)
@@ -91,7 +92,7 @@ mod tests {
 
     use self::test::{black_box, Bencher};
     use super::*;
-    use rand::RngCore;
+    use rand::{rngs::OsRng, RngCore};
 
     #[test]
     fn test_mrae_asymmetric() {
@@ -3,7 +3,9 @@ use std::ops::Deref;
 
 use anyhow::{anyhow, Result};
 use byteorder::{BigEndian, ReadBytesExt, WriteBytesExt};
-use rand::{rngs::OsRng, Rng};
+use rand::Rng;
+
+use crate::common::crypto::rng::SecureRng;
 
 /// Size of the nonce in bytes.
 pub use super::deoxysii::NONCE_SIZE;
@@ -35,7 +37,7 @@ impl Nonce {
 
     /// Generate a random nonce.
     pub fn generate() -> Self {
-        let mut rng = OsRng {};
+        let mut rng = SecureRng;
         let mut start_value = [0u8; NONCE_SIZE];
         rng.fill(&mut start_value);
 
@@ -0,0 +1,290 @@
+//! Cryptographically secure random number generator.
+//!
+//! All key and nonce generation in the crate goes through `SecureRng` so that the entropy path
+//! can be audited in one place. Output is produced by an HMAC-DRBG (NIST SP 800-90A) using
+//! SHA-512/256. The DRBG is seeded from hardware entropy (RDRAND when running in an SGX enclave,
+//! the operating system otherwise) and reseeded after `RESEED_INTERVAL` requests. All entropy
+//! is subjected to continuous health tests before being used and entropy source failures are
+//! reported instead of silently producing weak output.
+use std::sync::Mutex;
+
+use hmac::{Hmac, Mac};
+use rand::{CryptoRng, RngCore};
+use sha2::Sha512_256;
+use thiserror::Error;
+use zeroize::Zeroize;
+
+/// Size of the DRBG state and of the seed material requested from the entropy source.
+const SEED_SIZE: usize = 32;
+/// Size of the entropy samples checked by the health test.
+const SAMPLE_SIZE: usize = 8;
+/// Number of generate requests after which the DRBG is reseeded.
+pub const RESEED_INTERVAL: u64 = 1 << 20;
+/// Maximum number of bytes produced by a single generate request.
+const MAX_REQUEST_SIZE: usize = 1 << 16;
+/// Number of times a hardware entropy read is retried before giving up.
+#[cfg(target_env = "sgx")]
+const RDRAND_RETRIES: usize = 10;
+
+lazy_static! {
+    static ref GLOBAL_DRBG: Mutex<Option<HmacDrbg>> = Mutex::new(None);
+    static ref HEALTH_TEST: Mutex<HealthTest> = Mutex::new(HealthTest::default());
+}
+
+/// Random number generator errors.
+#[derive(Error, Debug)]
+pub enum RngError {
+    #[error("entropy source failure")]
+    EntropySource,
+
+    #[error("entropy health test failed")]
+    HealthTest,
+}
+
+/// Cryptographically secure random number generator backed by the global DRBG.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct SecureRng;
+
+impl RngCore for SecureRng {
+    fn next_u32(&mut self) -> u32 {
+        let mut buf = [0u8; 4];
+        self.fill_bytes(&mut buf);
+        u32::from_le_bytes(buf)
+    }
+
+    fn next_u64(&mut self) -> u64 {
+        let mut buf = [0u8; 8];
+        self.fill_bytes(&mut buf);
+        u64::from_le_bytes(buf)
+    }
+
+    fn fill_bytes(&mut self, dest: &mut [u8]) {
+        self.try_fill_bytes(dest)
+            .expect("secure random number generator failure")
+    }
+
+    fn try_fill_bytes(&mut self, dest: &mut [u8]) -> Result<(), rand::Error> {
+        fill(dest).map_err(rand::Error::new)
+    }
+}
+
+impl CryptoRng for SecureRng {}
+
+/// Fill the destination with random bytes from the global DRBG.
+pub fn fill(dest: &mut [u8]) -> Result<(), RngError> {
+    let mut guard = GLOBAL_DRBG.lock().unwrap();
+    let drbg = match guard.as_mut() {
+        Some(drbg) => drbg,
+        None => guard.insert(HmacDrbg::new(&seed()?)),
+    };
+
+    for chunk in dest.chunks_mut(MAX_REQUEST_SIZE) {
+        if drbg.needs_reseed() {
+            drbg.reseed(&seed()?);
+        }
+        drbg.generate(chunk);
+    }
+
+    Ok(())
+}
+
+/// Obtain health-tested seed material from the entropy source.
+fn seed() -> Result<[u8; SEED_SIZE], RngError> {
+    let mut seed = [0u8; SEED_SIZE];
+    hardware_entropy(&mut seed)?;
+    HEALTH_TEST.lock().unwrap().check(&seed)?;
+    Ok(seed)
+}
+
+/// Continuous health test of the raw entropy.
+///
+/// The entropy is split into 64-bit samples and the test fails in case any sample repeats its
+/// predecessor or is stuck at all zeros or all ones (repetition count test). The last sample is
+/// kept across reads, so the first sample of a read is compared against the last sample of the
+/// previous one. For a working source the probability of a false positive is negligible.
+#[derive(Default)]
+struct HealthTest {
+    previous: Option<[u8; SAMPLE_SIZE]>,
+}
+
+impl HealthTest {
+    fn check(&mut self, entropy: &[u8]) -> Result<(), RngError> {
+        for chunk in entropy.chunks(SAMPLE_SIZE) {
+            if chunk.iter().all(|b| *b == 0x00) || chunk.iter().all(|b| *b == 0xff) {
+                return Err(RngError::HealthTest);
+            }
+            let mut sample = [0u8; SAMPLE_SIZE];
+            sample[..chunk.len()].copy_from_slice(chunk);
+            if self.previous == Some(sample) {
+                return Err(RngError::HealthTest);
+            }
+            self.previous = Some(sample);
+        }
+        Ok(())
+    }
+}
+
+impl Drop for HealthTest {
+    fn drop(&mut self) {
+        if let Some(previous) = self.previous.as_mut() {
+            previous.zeroize();
+        }
+    }
+}
+
+#[cfg(target_env = "sgx")]
+fn hardware_entropy(dest: &mut [u8]) -> Result<(), RngError> {
+    use std::arch::x86_64::_rdrand64_step;
+
+    for chunk in dest.chunks_mut(SAMPLE_SIZE) {
+        let mut word = 0u64;
+        // SAFETY: RDRAND is always available on SGX-capable CPUs.
+        let ok = (0..RDRAND_RETRIES).any(|_| unsafe { _rdrand64_step(&mut word) } == 1);
+        if !ok {
+            return Err(RngError::EntropySource);
+        }
+        chunk.copy_from_slice(&word.to_le_bytes()[..chunk.len()]);
+    }
+    Ok(())
+}
+
+#[cfg(not(target_env = "sgx"))]
+fn hardware_entropy(dest: &mut [u8]) -> Result<(), RngError> {
+    rand::rngs::OsRng
+        .try_fill_bytes(dest)
+        .map_err(|_| RngError::EntropySource)
+}
+
+/// HMAC-DRBG as specified in NIST SP 800-90A, without prediction resistance.
+struct HmacDrbg {
+    key: [u8; SEED_SIZE],
+    value: [u8; SEED_SIZE],
+    reseed_counter: u64,
+}
+
+impl HmacDrbg {
+    fn new(seed: &[u8]) -> Self {
+        let mut drbg = Self {
+            key: [0x00; SEED_SIZE],
+            value: [0x01; SEED_SIZE],
+            reseed_counter: 1,
+        };
+        drbg.update(seed);
+        drbg
+    }
+
+    fn needs_reseed(&self) -> bool {
+        self.reseed_counter > RESEED_INTERVAL
+    }
+
+    fn reseed(&mut self, seed: &[u8]) {
+        self.update(seed);
+        self.reseed_counter = 1;
+    }
+
+    fn generate(&mut self, dest: &mut [u8]) {
+        for chunk in dest.chunks_mut(SEED_SIZE) {
+            self.value = self.hmac(&[&self.value]);
+            chunk.copy_from_slice(&self.value[..chunk.len()]);
+        }
+        self.update(&[]);
+        self.reseed_counter += 1;
+    }
+
+    fn update(&mut self, provided: &[u8]) {
+        self.key = self.hmac(&[&self.value, &[0x00], provided]);
+        self.value = self.hmac(&[&self.value]);
+        if provided.is_empty() {
+            return;
+        }
+        self.key = self.hmac(&[&self.value, &[0x01], provided]);
+        self.value = self.hmac(&[&self.value]);
+    }
+
+    fn hmac(&self, parts: &[&[u8]]) -> [u8; SEED_SIZE] {
+        let mut mac = Hmac::<Sha512_256>::new_from_slice(&self.key).expect("Hmac::new_from_slice");
+        for part in parts {
+            mac.update(part);
+        }
+        let mut out = [0u8; SEED_SIZE];
+        out.copy_from_slice(&mac.finalize().into_bytes());
+        out
+    }
+}
+
+impl Drop for HmacDrbg {
+    fn drop(&mut self) {
+        self.key.zeroize();
+        self.value.zeroize();
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_hmac_drbg() {
+        let seed = [0x42; SEED_SIZE];
+        let mut a = HmacDrbg::new(&seed);
+        let mut b = HmacDrbg::new(&seed);
+
+        // Same seed must produce the same output and output must not repeat.
+        let (mut out_a, mut out_b) = ([0u8; 100], [0u8; 100]);
+        a.generate(&mut out_a);
+        b.generate(&mut out_b);
+        assert_eq!(out_a, out_b);
+        let mut next = [0u8; 100];
+        a.generate(&mut next);
+        assert_ne!(out_a, next);
+
+        // Reseeding must change the output.
+        b.reseed(&[0x43; SEED_SIZE]);
+        b.generate(&mut out_b);
+        assert_ne!(next, out_b);
+        assert_eq!(b.reseed_counter, 2);
+
+        a.reseed_counter = RESEED_INTERVAL + 1;
+        assert!(a.needs_reseed());
+    }
+
+    #[test]
+    fn test_health_test() {
+        let mut entropy = [0u8; SEED_SIZE];
+        hardware_entropy(&mut entropy).unwrap();
+        assert!(HealthTest::default().check(&entropy).is_ok());
+
+        assert!(HealthTest::default().check(&[0x00; SEED_SIZE]).is_err());
+        assert!(HealthTest::default().check(&[0xff; SEED_SIZE]).is_err());
+        let mut repeated = entropy;
+        repeated.copy_within(0..SAMPLE_SIZE, SAMPLE_SIZE);
+        assert!(HealthTest::default().check(&repeated).is_err());
+
+        // The test runs continuously across reads.
+        let mut health_test = HealthTest::default();
+        assert!(health_test.check(&entropy).is_ok());
+        let mut next = [0u8; SEED_SIZE];
+        hardware_entropy(&mut next).unwrap();
+        assert!(health_test.check(&next).is_ok());
+        next[..SAMPLE_SIZE].copy_from_slice(&entropy[SEED_SIZE - SAMPLE_SIZE..]);
+        let mut stuck = HealthTest::default();
+        assert!(stuck.check(&entropy).is_ok());
+        assert!(stuck.check(&next).is_err());
+    }
+
+    #[test]
+    fn test_secure_rng() {
+        let mut a = [0u8; 64];
+        let mut b = [0u8; 64];
+        SecureRng.fill_bytes(&mut a);
+        SecureRng.fill_bytes(&mut b);
+        assert_ne!(a, b);
+
+        let mut large = vec![0u8; 3 * MAX_REQUEST_SIZE + 1];
+        SecureRng.fill_bytes(&mut large);
+        assert_ne!(
+            large[..SEED_SIZE],
+            large[MAX_REQUEST_SIZE..MAX_REQUEST_SIZE + SEED_SIZE]
+        );
+    }
+}
@@ -0,0 +1,417 @@
+//! BLS signatures over BLS12-381.
+//!
+//! Unlike Ed25519 signatures, BLS signatures made by different keys can be aggregated into a
+//! single signature of constant size, which is verified against the public keys of all signers at
+//! once. This makes attestations of whole committees about as compact as a single signature.
+//!
+//! Public keys are G1 points and signatures are G2 points, hashed to the curve as specified by the
+//! proof of possession scheme of the IRTF BLS signature draft. Signatures over distinct messages
+//! can always be aggregated and verified with `aggregate_verify`. Signatures over the same message
+//! are verified with `fast_aggregate_verify` instead, which is only secure against rogue public
+//! keys in case the proof of possession of each key has been verified, e.g. when it is registered.
+//! As with Ed25519 signatures, signatures are made over the hash of the context and the message.
+use std::{collections::HashSet, convert::TryInto};
+
+use anyhow::Result;
+use bls12_381::{
+    hash_to_curve::{ExpandMsgXmd, HashToCurve},
+    multi_miller_loop, G1Affine, G1Projective, G2Affine, G2Prepared, G2Projective, Gt, Scalar,
+};
+use rand::RngCore;
+use thiserror::Error;
+use zeroize::Zeroize;
+
+use super::{
+    super::{hash::Hash, rng::SecureRng},
+    Signer,
+};
+
+/// Domain separation tag used when hashing messages to G2.
+pub const DOMAIN_SEPARATION_TAG: &[u8] = b"BLS_SIG_BLS12381G2_XMD:SHA-256_SSWU_RO_POP_";
+/// Domain separation tag used when hashing public keys to G2 for proofs of possession.
+pub const POP_DOMAIN_SEPARATION_TAG: &[u8] = b"BLS_POP_BLS12381G2_XMD:SHA-256_SSWU_RO_POP_";
+
+impl_bytes!(
+    PublicKey,
+    48,
+    "A BLS12-381 public key (compressed G1 point)."
+);
+
+impl_bytes!(
+    Signature,
+    96,
+    "A BLS12-381 signature (compressed G2 point)."
+);
+
+/// BLS signature error.
+#[derive(Error, Debug)]
+enum BlsError {
+    #[error("malformed public key")]
+    MalformedPublicKey,
+    #[error("malformed signature")]
+    MalformedSignature,
+    #[error("nothing to aggregate")]
+    EmptyAggregate,
+    #[error("duplicate message in aggregate")]
+    DuplicateMessage,
+    #[error("invalid signature")]
+    InvalidSignature,
+    #[error("invalid proof of possession")]
+    InvalidProofOfPossession,
+}
+
+/// A BLS12-381 private key.
+pub struct PrivateKey(Scalar);
+
+impl PrivateKey {
+    /// Generates a new private key pair.
+    pub fn generate() -> Self {
+        let mut seed = [0u8; 64];
+        SecureRng.fill_bytes(&mut seed);
+        let sk = PrivateKey(Scalar::from_bytes_wide(&seed));
+        seed.zeroize();
+
+        sk
+    }
+
+    /// Convert this private key into bytes.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = self.0.to_bytes();
+        let bvec = bytes.to_vec();
+        bytes.zeroize();
+        bvec
+    }
+
+    /// Construct a private key from bytes returned by `to_bytes`.
+    ///
+    /// # Panics
+    ///
+    /// This method will panic in case the passed bytes do not encode a valid scalar.
+    pub fn from_bytes(bytes: Vec<u8>) -> PrivateKey {
+        let mut sk: [u8; 32] = bytes.try_into().unwrap();
+        let secret = Option::from(Scalar::from_bytes(&sk)).expect("invalid BLS private key");
+        sk.zeroize();
+
+        PrivateKey(secret)
+    }
+
+    /// Generate a new private key from a test key seed.
+    pub fn from_test_seed(seed: String) -> Self {
+        let mut wide = [0u8; 64];
+        wide[..32].copy_from_slice(Hash::digest_bytes_list(&[seed.as_bytes(), b"0"]).as_ref());
+        wide[32..].copy_from_slice(Hash::digest_bytes_list(&[seed.as_bytes(), b"1"]).as_ref());
+        let sk = PrivateKey(Scalar::from_bytes_wide(&wide));
+        wide.zeroize();
+
+        sk
+    }
+
+    /// Returns the public key.
+    pub fn public_key(&self) -> PublicKey {
+        PublicKey(G1Affine::from(G1Affine::generator() * self.0).to_compressed())
+    }
+
+    /// Generates a proof of possession of this private key, to be verified by anyone accepting
+    /// the public key for verifying aggregate signatures over the same message.
+    pub fn prove_possession(&self) -> Signature {
+        let point = hash_public_key_to_g2(&self.public_key()) * self.0;
+        Signature(G2Affine::from(point).to_compressed())
+    }
+}
+
+impl Signer<PublicKey, Signature> for PrivateKey {
+    fn public(&self) -> PublicKey {
+        self.public_key()
+    }
+
+    fn sign(&self, context: &[u8], message: &[u8]) -> Result<Signature> {
+        let point = hash_to_g2(context, message) * self.0;
+        Ok(Signature(G2Affine::from(point).to_compressed()))
+    }
+}
+
+impl PublicKey {
+    fn decompress(&self) -> Result<G1Affine> {
+        let point: Option<G1Affine> = G1Affine::from_compressed(&self.0).into();
+        match point {
+            Some(point) if !bool::from(point.is_identity()) => Ok(point),
+            _ => Err(BlsError::MalformedPublicKey.into()),
+        }
+    }
+
+    /// Verify the proof of possession of the private key corresponding to this public key.
+    pub fn verify_possession(&self, proof: &Signature) -> Result<()> {
+        let point = G2Affine::from(hash_public_key_to_g2(self));
+        match pairing_check(&[(self.decompress()?, point)], proof)? {
+            true => Ok(()),
+            false => Err(BlsError::InvalidProofOfPossession.into()),
+        }
+    }
+}
+
+impl Signature {
+    /// Aggregate the given signatures into a single signature.
+    pub fn aggregate(signatures: &[Signature]) -> Result<Signature> {
+        if signatures.is_empty() {
+            return Err(BlsError::EmptyAggregate.into());
+        }
+
+        let mut aggregate = G2Projective::identity();
+        for signature in signatures {
+            aggregate += signature.decompress()?;
+        }
+        Ok(Signature(G2Affine::from(aggregate).to_compressed()))
+    }
+
+    /// Verify signature.
+    pub fn verify(&self, pk: &PublicKey, context: &[u8], message: &[u8]) -> Result<()> {
+        self.aggregate_verify(&[(pk, context, message)])
+    }
+
+    /// Verify an aggregate signature over the given distinct contexts and messages, each signed
+    /// by the given public key.
+    ///
+    /// Use `fast_aggregate_verify` for aggregate signatures over the same message.
+    pub fn aggregate_verify(&self, items: &[(&PublicKey, &[u8], &[u8])]) -> Result<()> {
+        if items.is_empty() {
+            return Err(BlsError::EmptyAggregate.into());
+        }
+
+        let mut digests = HashSet::with_capacity(items.len());
+        let mut terms = Vec::with_capacity(items.len());
+        for (pk, context, message) in items {
+            if !digests.insert(Hash::digest_bytes_list(&[*context, *message])) {
+                return Err(BlsError::DuplicateMessage.into());
+            }
+            terms.push((
+                pk.decompress()?,
+                G2Affine::from(hash_to_g2(context, message)),
+            ));
+        }
+
+        match pairing_check(&terms, self)? {
+            true => Ok(()),
+            false => Err(BlsError::InvalidSignature.into()),
+        }
+    }
+
+    /// Verify an aggregate signature over the given context and message, signed by all of the
+    /// given public keys.
+    ///
+    /// The proofs of possession of all public keys must have been verified before with
+    /// `PublicKey::verify_possession`, as otherwise a rogue public key derived from the other
+    /// keys could be used to forge the aggregate signature.
+    pub fn fast_aggregate_verify(
+        &self,
+        pks: &[PublicKey],
+        context: &[u8],
+        message: &[u8],
+    ) -> Result<()> {
+        if pks.is_empty() {
+            return Err(BlsError::EmptyAggregate.into());
+        }
+
+        let mut aggregate = G1Projective::identity();
+        for pk in pks {
+            aggregate += pk.decompress()?;
+        }
+        let terms = [(
+            G1Affine::from(aggregate),
+            G2Affine::from(hash_to_g2(context, message)),
+        )];
+
+        match pairing_check(&terms, self)? {
+            true => Ok(()),
+            false => Err(BlsError::InvalidSignature.into()),
+        }
+    }
+
+    fn decompress(&self) -> Result<G2Affine> {
+        Option::from(G2Affine::from_compressed(&self.0))
+            .ok_or_else(|| BlsError::MalformedSignature.into())
+    }
+}
+
+/// Check that e(p_1, q_1) * ... * e(p_n, q_n) = e(g1, sig).
+fn pairing_check(terms: &[(G1Affine, G2Affine)], signature: &Signature) -> Result<bool> {
+    let mut prepared: Vec<_> = terms
+        .iter()
+        .map(|(p, q)| (*p, G2Prepared::from(*q)))
+        .collect();
+    prepared.push((
+        -G1Affine::generator(),
+        G2Prepared::from(signature.decompress()?),
+    ));
+
+    let prepared: Vec<_> = prepared.iter().map(|(p, q)| (p, q)).collect();
+    Ok(multi_miller_loop(&prepared).final_exponentiation() == Gt::identity())
+}
+
+/// Hash the given public key to a G2 point for proofs of possession.
+fn hash_public_key_to_g2(pk: &PublicKey) -> G2Projective {
+    <G2Projective as HashToCurve<ExpandMsgXmd<sha2::Sha256>>>::hash_to_curve(
+        pk.as_ref(),
+        POP_DOMAIN_SEPARATION_TAG,
+    )
+}
+
+/// Hash the given context and message to a G2 point.
+fn hash_to_g2(context: &[u8], message: &[u8]) -> G2Projective {
+    let digest = Hash::digest_bytes_list(&[context, message]);
+    <G2Projective as HashToCurve<ExpandMsgXmd<sha2::Sha256>>>::hash_to_curve(
+        digest.as_ref(),
+        DOMAIN_SEPARATION_TAG,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sign_verify() {
+        let sk = PrivateKey::from_test_seed("bls".to_string());
+        let pk = sk.public_key();
+        let sig = sk.sign(b"test context", b"message").unwrap();
+
+        assert!(sig.verify(&pk, b"test context", b"message").is_ok());
+        assert!(sig.verify(&pk, b"other context", b"message").is_err());
+        assert!(sig.verify(&pk, b"test context", b"other").is_err());
+        let other = PrivateKey::generate().public_key();
+        assert!(sig.verify(&other, b"test context", b"message").is_err());
+
+        // Malformed and identity public keys are rejected.
+        assert!(sig
+            .verify(&PublicKey::default(), b"test context", b"message")
+            .is_err());
+        let identity = PublicKey(G1Affine::identity().to_compressed());
+        assert!(sig.verify(&identity, b"test context", b"message").is_err());
+
+        // Keys and signatures round-trip through their encodings.
+        let decoded = PrivateKey::from_bytes(sk.to_bytes());
+        assert_eq!(decoded.public_key(), pk);
+        let decoded: Signature = cbor::from_slice(&cbor::to_vec(sig)).unwrap();
+        assert_eq!(decoded, sig);
+        let decoded: PublicKey = cbor::from_slice(&cbor::to_vec(pk)).unwrap();
+        assert_eq!(decoded, pk);
+    }
+
+    #[test]
+    fn test_aggregate_verify() {
+        let keys: Vec<_> = (0..4)
+            .map(|i| PrivateKey::from_test_seed(format!("aggregate {i}")))
+            .collect();
+        let public_keys: Vec<_> = keys.iter().map(|sk| sk.public_key()).collect();
+        let messages: Vec<_> = (0..4)
+            .map(|i| format!("message {i}").into_bytes())
+            .collect();
+        let signatures: Vec<_> = keys
+            .iter()
+            .zip(&messages)
+            .map(|(sk, msg)| sk.sign(b"test context", msg).unwrap())
+            .collect();
+        let items: Vec<_> = (0..4)
+            .map(|i| (&public_keys[i], &b"test context"[..], &messages[i][..]))
+            .collect();
+
+        let aggregate = Signature::aggregate(&signatures).unwrap();
+        assert!(aggregate.aggregate_verify(&items).is_ok());
+        assert!(Signature::aggregate(&[]).is_err());
+        assert!(aggregate.aggregate_verify(&[]).is_err());
+
+        // The aggregate doesn't verify against a subset or with swapped keys.
+        assert!(aggregate.aggregate_verify(&items[1..]).is_err());
+        let mut invalid = items.clone();
+        invalid.swap(0, 1);
+        invalid[0].2 = &messages[0][..];
+        invalid[1].2 = &messages[1][..];
+        assert!(aggregate.aggregate_verify(&invalid).is_err());
+
+        // Messages must be distinct.
+        let same: Vec<_> = keys
+            .iter()
+            .map(|sk| sk.sign(b"test context", b"same").unwrap())
+            .collect();
+        let aggregate = Signature::aggregate(&same).unwrap();
+        let items: Vec<_> = public_keys
+            .iter()
+            .map(|pk| (pk, &b"test context"[..], &b"same"[..]))
+            .collect();
+        assert!(aggregate.aggregate_verify(&items).is_err());
+    }
+
+    #[test]
+    fn test_proof_of_possession() {
+        let sk = PrivateKey::from_test_seed("pop".to_string());
+        let pk = sk.public_key();
+        let proof = sk.prove_possession();
+        assert!(pk.verify_possession(&proof).is_ok());
+
+        // Proofs don't verify for other keys.
+        let other = PrivateKey::generate();
+        assert!(other.public_key().verify_possession(&proof).is_err());
+        assert!(pk.verify_possession(&other.prove_possession()).is_err());
+
+        // Signatures over the public key are not proofs of possession.
+        let sig = sk.sign(b"", pk.as_ref()).unwrap();
+        assert!(pk.verify_possession(&sig).is_err());
+        assert!(proof.verify(&pk, b"", pk.as_ref()).is_err());
+    }
+
+    #[test]
+    fn test_fast_aggregate_verify() {
+        let keys: Vec<_> = (0..4)
+            .map(|i| PrivateKey::from_test_seed(format!("same message {i}")))
+            .collect();
+        let public_keys: Vec<_> = keys.iter().map(|sk| sk.public_key()).collect();
+        for (sk, pk) in keys.iter().zip(&public_keys) {
+            assert!(pk.verify_possession(&sk.prove_possession()).is_ok());
+        }
+        let signatures: Vec<_> = keys
+            .iter()
+            .map(|sk| sk.sign(b"test context", b"same").unwrap())
+            .collect();
+        let aggregate = Signature::aggregate(&signatures).unwrap();
+
+        assert!(aggregate
+            .fast_aggregate_verify(&public_keys, b"test context", b"same")
+            .is_ok());
+        assert!(signatures[0]
+            .fast_aggregate_verify(&public_keys[..1], b"test context", b"same")
+            .is_ok());
+        assert!(aggregate
+            .fast_aggregate_verify(&[], b"test context", b"same")
+            .is_err());
+
+        // The aggregate doesn't verify against a subset, other keys or another message.
+        assert!(aggregate
+            .fast_aggregate_verify(&public_keys[1..], b"test context", b"same")
+            .is_err());
+        let mut other = public_keys.clone();
+        other[0] = PrivateKey::generate().public_key();
+        assert!(aggregate
+            .fast_aggregate_verify(&other, b"test context", b"same")
+            .is_err());
+        assert!(aggregate
+            .fast_aggregate_verify(&public_keys, b"test context", b"other")
+            .is_err());
+        assert!(aggregate
+            .fast_aggregate_verify(&public_keys, b"other context", b"same")
+            .is_err());
+
+        // A rogue key cancelling out the victim's key forges an aggregate signature over the same
+        // message, but its proof of possession can't be produced.
+        let victim = public_keys[0].decompress().unwrap();
+        let x = PrivateKey::from_test_seed("rogue".to_string());
+        let rogue = PublicKey(
+            G1Affine::from(G1Affine::generator() * x.0 - G1Projective::from(victim))
+                .to_compressed(),
+        );
+        let forged = x.sign(b"test context", b"same").unwrap();
+        assert!(forged
+            .fast_aggregate_verify(&[public_keys[0], rogue], b"test context", b"same")
+            .is_ok());
+        let proof = Signature(G2Affine::from(hash_public_key_to_g2(&rogue) * x.0).to_compressed());
+        assert!(rogue.verify_possession(&proof).is_err());
+    }
+}
@@ -1,9 +1,8 @@
 //! CBOR serializable X25519 types.
 use anyhow::Result;
-use rand::rngs::OsRng;
 use zeroize::{Zeroize, ZeroizeOnDrop};
 
-use super::hash::Hash;
+use super::{hash::Hash, rng::SecureRng};
 
 /// The length of an X25519 private key, in bytes.
 pub const PRIVATE_KEY_LENGTH: usize = 32;
@@ -18,7 +17,7 @@ pub struct PrivateKey(pub x25519_dalek::StaticSecret);
 impl PrivateKey {
     /// Generate a new private key.
     pub fn generate() -> Self {
-        PrivateKey(x25519_dalek::StaticSecret::random_from_rng(OsRng))
+        PrivateKey(x25519_dalek::StaticSecret::random_from_rng(SecureRng))
     }
 
     /// Compute corresponding public key.
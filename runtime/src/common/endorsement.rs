@@ -0,0 +1,371 @@
+//! Verification of endorsement chains.
+//!
+//! Off-chain runtime components prove that an artifact originates from them by signing it with
+//! their RAK, which is only trusted in case it is endorsed by a node which in turn is controlled
+//! by a known entity. Services accepting such artifacts need to verify each link of the chain
+//! (the entity listing the node in its signed descriptor, the node signing its own descriptor,
+//! the node endorsing the component's TEE capability and the RAK signing the artifact). An
+//! [`EndorsementChain`] bundles all links, so that the whole chain is verified in one call, with
+//! failures reporting exactly which link is broken.
+use thiserror::Error;
+
+use crate::{
+    common::{
+        crypto::signature::{MultiSigned, PublicKey, Signature, Signed},
+        sgx::QuotePolicy,
+    },
+    consensus::{
+        beacon::EpochTime,
+        registry::{
+            EndorsedCapabilityTEE, Entity, Node, VerifiedAttestation,
+            REGISTER_ENTITY_SIGNATURE_CONTEXT, REGISTER_NODE_SIGNATURE_CONTEXT,
+        },
+    },
+};
+
+/// Endorsement chain verification errors.
+#[derive(Error, Debug)]
+pub enum EndorsementChainError {
+    #[error("malformed entity descriptor: {0}")]
+    MalformedEntity(#[source] cbor::DecodeError),
+    #[error("invalid entity signature over entity descriptor")]
+    InvalidEntitySignature,
+    #[error("unexpected entity (expected: {expected} got: {actual})")]
+    UnexpectedEntity {
+        expected: PublicKey,
+        actual: PublicKey,
+    },
+    #[error("malformed node descriptor: {0}")]
+    MalformedNode(#[source] cbor::DecodeError),
+    #[error("node {0} not controlled by entity")]
+    NodeNotListed(PublicKey),
+    #[error("node descriptor not signed by node {0}")]
+    MissingNodeSignature(PublicKey),
+    #[error("invalid node signature over node descriptor")]
+    InvalidNodeSignature,
+    #[error("node descriptor expired (expiration: {expiration} epoch: {epoch})")]
+    NodeExpired {
+        expiration: EpochTime,
+        epoch: EpochTime,
+    },
+    #[error("node descriptor expires too late (expiration: {expiration} max: {max})")]
+    NodeExpirationTooFar {
+        expiration: EpochTime,
+        max: EpochTime,
+    },
+    #[error("TEE capability endorsed by unexpected node (expected: {expected} got: {actual})")]
+    UnexpectedNode {
+        expected: PublicKey,
+        actual: PublicKey,
+    },
+    #[error("invalid node endorsement signature")]
+    InvalidNodeEndorsement,
+    #[error("invalid RAK signature over message")]
+    InvalidMessageSignature,
+    #[error("invalid TEE capability: {0}")]
+    InvalidAttestation(#[source] anyhow::Error),
+}
+
+/// Endorsement chain of a message signed by a runtime component.
+#[derive(Clone, Debug, Default, PartialEq, Eq, cbor::Encode, cbor::Decode)]
+pub struct EndorsementChain {
+    /// Entity descriptor, signed by the entity and listing the nodes it controls.
+    pub signed_entity: Signed,
+    /// Node descriptor, signed by the node.
+    pub signed_node: MultiSigned,
+    /// TEE capability of the runtime component, endorsed by the node.
+    pub endorsed_capability_tee: EndorsedCapabilityTEE,
+    /// Signature over the message, made by the RAK of the runtime component.
+    pub signature: Signature,
+}
+
+/// A verified endorsement chain.
+#[derive(Clone, Debug)]
+pub struct VerifiedEndorsementChain {
+    /// Identifier of the entity controlling the endorsing node.
+    pub entity_id: PublicKey,
+    /// Identifier of the endorsing node.
+    pub node_id: PublicKey,
+    /// Runtime attestation key of the runtime component.
+    pub rak: PublicKey,
+    /// Verified TEE remote attestation of the runtime component.
+    pub verified_attestation: VerifiedAttestation,
+}
+
+impl EndorsementChain {
+    /// Verify that the message was signed, using the given context, by a runtime component whose
+    /// TEE capability is endorsed by a node controlled by the given entity.
+    ///
+    /// As node descriptors declare their own expiration, the descriptor must not have expired as
+    /// of the given epoch and must expire at most `max_node_expiration` epochs after it.
+    ///
+    /// All signatures are verified before the remote attestation, which is the most expensive.
+    pub fn verify(
+        &self,
+        context: &[u8],
+        message: &[u8],
+        entity_id: &PublicKey,
+        epoch: EpochTime,
+        max_node_expiration: EpochTime,
+        policy: &QuotePolicy,
+    ) -> Result<VerifiedEndorsementChain, EndorsementChainError> {
+        // Entity lists node.
+        if &self.signed_entity.signature.public_key != entity_id {
+            return Err(EndorsementChainError::UnexpectedEntity {
+                expected: *entity_id,
+                actual: self.signed_entity.signature.public_key,
+            });
+        }
+        if !self
+            .signed_entity
+            .signature
+            .verify(REGISTER_ENTITY_SIGNATURE_CONTEXT, &self.signed_entity.blob)
+        {
+            return Err(EndorsementChainError::InvalidEntitySignature);
+        }
+        let entity: Entity = cbor::from_slice_non_strict(&self.signed_entity.blob)
+            .map_err(EndorsementChainError::MalformedEntity)?;
+        if &entity.id != entity_id {
+            return Err(EndorsementChainError::UnexpectedEntity {
+                expected: *entity_id,
+                actual: entity.id,
+            });
+        }
+
+        // Node signs its descriptor.
+        let node: Node = cbor::from_slice_non_strict(&self.signed_node.blob)
+            .map_err(EndorsementChainError::MalformedNode)?;
+        if node.entity_id != entity.id {
+            return Err(EndorsementChainError::UnexpectedEntity {
+                expected: entity.id,
+                actual: node.entity_id,
+            });
+        }
+        if !entity.nodes.contains(&node.id) {
+            return Err(EndorsementChainError::NodeNotListed(node.id));
+        }
+        let node_signature = self
+            .signed_node
+            .signatures
+            .iter()
+            .find(|sig| sig.public_key == node.id)
+            .ok_or(EndorsementChainError::MissingNodeSignature(node.id))?;
+        if !node_signature.verify(REGISTER_NODE_SIGNATURE_CONTEXT, &self.signed_node.blob) {
+            return Err(EndorsementChainError::InvalidNodeSignature);
+        }
+        if node.expiration < epoch {
+            return Err(EndorsementChainError::NodeExpired {
+                expiration: node.expiration,
+                epoch,
+            });
+        }
+        let max = epoch.saturating_add(max_node_expiration);
+        if node.expiration > max {
+            return Err(EndorsementChainError::NodeExpirationTooFar {
+                expiration: node.expiration,
+                max,
+            });
+        }
+
+        // Node endorses component RAK.
+        let endorsing_node = self.endorsed_capability_tee.node_endorsement.public_key;
+        if endorsing_node != node.id {
+            return Err(EndorsementChainError::UnexpectedNode {
+                expected: node.id,
+                actual: endorsing_node,
+            });
+        }
+        self.endorsed_capability_tee
+            .verify_endorsement()
+            .map_err(|_| EndorsementChainError::InvalidNodeEndorsement)?;
+
+        // RAK signs message.
+        let rak = self.endorsed_capability_tee.capability_tee.rak;
+        self.signature
+            .verify(&rak, context, message)
+            .map_err(|_| EndorsementChainError::InvalidMessageSignature)?;
+
+        // Verify the remote attestation binding the RAK to the component.
+        let verified = self
+            .endorsed_capability_tee
+            .verify(policy)
+            .map_err(EndorsementChainError::InvalidAttestation)?;
+
+        Ok(VerifiedEndorsementChain {
+            entity_id: node.entity_id,
+            node_id: node.id,
+            rak,
+            verified_attestation: verified.verified_attestation,
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{
+        common::crypto::signature::{PrivateKey, SignatureBundle, Signer},
+        consensus::registry::ENDORSE_CAPABILITY_TEE_SIGNATURE_CONTEXT,
+    };
+
+    const CONTEXT: &[u8] = b"test: endorsed message";
+
+    struct Keys {
+        entity: PrivateKey,
+        node: PrivateKey,
+        rak: PrivateKey,
+    }
+
+    fn chain(keys: &Keys, message: &[u8]) -> EndorsementChain {
+        let entity = Entity {
+            id: keys.entity.public_key(),
+            nodes: vec![keys.node.public_key()],
+            ..Default::default()
+        };
+        let blob = cbor::to_vec(entity);
+        let signed_entity = Signed {
+            signature: SignatureBundle {
+                public_key: keys.entity.public_key(),
+                signature: keys
+                    .entity
+                    .sign(REGISTER_ENTITY_SIGNATURE_CONTEXT, &blob)
+                    .unwrap(),
+            },
+            blob,
+        };
+
+        let node = Node {
+            id: keys.node.public_key(),
+            entity_id: keys.entity.public_key(),
+            expiration: 10,
+            ..Default::default()
+        };
+        let blob = cbor::to_vec(node);
+        let signed_node = MultiSigned {
+            signatures: vec![SignatureBundle {
+                public_key: keys.node.public_key(),
+                signature: keys
+                    .node
+                    .sign(REGISTER_NODE_SIGNATURE_CONTEXT, &blob)
+                    .unwrap(),
+            }],
+            blob,
+        };
+
+        let mut endorsed_capability_tee = EndorsedCapabilityTEE::default();
+        endorsed_capability_tee.capability_tee.rak = keys.rak.public_key();
+        endorsed_capability_tee.node_endorsement = SignatureBundle {
+            public_key: keys.node.public_key(),
+            signature: keys
+                .node
+                .sign(
+                    ENDORSE_CAPABILITY_TEE_SIGNATURE_CONTEXT,
+                    &cbor::to_vec(endorsed_capability_tee.capability_tee.clone()),
+                )
+                .unwrap(),
+        };
+
+        EndorsementChain {
+            signed_entity,
+            signed_node,
+            endorsed_capability_tee,
+            signature: keys.rak.sign(CONTEXT, message).unwrap(),
+        }
+    }
+
+    #[test]
+    fn test_endorsement_chain() {
+        let keys = Keys {
+            entity: PrivateKey::generate(),
+            node: PrivateKey::generate(),
+            rak: PrivateKey::generate(),
+        };
+        let entity_id = keys.entity.public_key();
+        let policy = QuotePolicy::default();
+        let chain = chain(&keys, b"message");
+        let verify = |chain: &EndorsementChain, message: &[u8], entity_id: &PublicKey, epoch| {
+            chain.verify(CONTEXT, message, entity_id, epoch, 5, &policy)
+        };
+
+        // All signatures are valid, so verification fails at the (missing) attestation.
+        let result = verify(&chain, b"message", &entity_id, 5);
+        assert!(matches!(
+            result,
+            Err(EndorsementChainError::InvalidAttestation(_))
+        ));
+
+        // Each broken link is reported.
+        let result = verify(&chain, b"other", &entity_id, 5);
+        assert!(matches!(
+            result,
+            Err(EndorsementChainError::InvalidMessageSignature)
+        ));
+        let result = verify(&chain, b"message", &entity_id, 11);
+        assert!(matches!(
+            result,
+            Err(EndorsementChainError::NodeExpired { .. })
+        ));
+        let result = verify(&chain, b"message", &entity_id, 4);
+        assert!(matches!(
+            result,
+            Err(EndorsementChainError::NodeExpirationTooFar { .. })
+        ));
+        let other = PrivateKey::generate().public_key();
+        let result = verify(&chain, b"message", &other, 5);
+        assert!(matches!(
+            result,
+            Err(EndorsementChainError::UnexpectedEntity { .. })
+        ));
+
+        let mut tampered = chain.clone();
+        tampered.signed_entity.signature.signature = Signature::default();
+        let result = verify(&tampered, b"message", &entity_id, 5);
+        assert!(matches!(
+            result,
+            Err(EndorsementChainError::InvalidEntitySignature)
+        ));
+
+        // Nodes not listed by the entity are rejected, even if they name the entity.
+        let mut tampered = chain.clone();
+        let mut node: Node = cbor::from_slice(&tampered.signed_node.blob).unwrap();
+        node.id = other;
+        tampered.signed_node.blob = cbor::to_vec(node);
+        let result = verify(&tampered, b"message", &entity_id, 5);
+        assert!(matches!(
+            result,
+            Err(EndorsementChainError::NodeNotListed(_))
+        ));
+
+        let mut tampered = chain.clone();
+        tampered.signed_node.signatures[0].signature = Signature::default();
+        let result = verify(&tampered, b"message", &entity_id, 5);
+        assert!(matches!(
+            result,
+            Err(EndorsementChainError::InvalidNodeSignature)
+        ));
+
+        let mut tampered = chain.clone();
+        tampered.signed_node.signatures.clear();
+        let result = verify(&tampered, b"message", &entity_id, 5);
+        assert!(matches!(
+            result,
+            Err(EndorsementChainError::MissingNodeSignature(_))
+        ));
+
+        let mut tampered = chain.clone();
+        tampered.endorsed_capability_tee.capability_tee.rak = other;
+        let result = verify(&tampered, b"message", &entity_id, 5);
+        assert!(matches!(
+            result,
+            Err(EndorsementChainError::InvalidNodeEndorsement)
+        ));
+
+        let mut tampered = chain.clone();
+        tampered.endorsed_capability_tee.node_endorsement.public_key = other;
+        let result = verify(&tampered, b"message", &entity_id, 5);
+        assert!(matches!(
+            result,
+            Err(EndorsementChainError::UnexpectedNode { .. })
+        ));
+    }
+}
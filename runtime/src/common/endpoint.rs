@@ -0,0 +1,513 @@
+//! Network endpoints of peers.
+//!
+//! Addresses of peers (e.g. RPC nodes or egress targets) are provided by the host, which is not
+//! trusted. Instead of passing raw strings around, they are parsed into typed [`Endpoint`]s and
+//! [`MultiAddr`]s, rejecting anything malformed. As the host could also redirect connections to
+//! endpoints it controls, connections to peers should be authenticated by pinning the peer
+//! certificates via a [`PinSet`], e.g. to the TLS public keys from the registry.
+use std::{
+    fmt,
+    net::{IpAddr, Ipv4Addr, Ipv6Addr},
+    str::FromStr,
+};
+
+use sha2::{Digest, Sha256};
+use thiserror::Error;
+
+use crate::{
+    common::crypto::signature::PublicKey,
+    consensus::registry::{TCPAddress, TLSAddress},
+};
+
+/// DER prefix of an Ed25519 SubjectPublicKeyInfo, followed by the 32-byte public key.
+const ED25519_SPKI_PREFIX: [u8; 12] = [
+    0x30, 0x2a, 0x30, 0x05, 0x06, 0x03, 0x2b, 0x65, 0x70, 0x03, 0x21, 0x00,
+];
+
+/// Maximum length of a domain name.
+const MAX_DOMAIN_LENGTH: usize = 253;
+/// Maximum length of a domain name label.
+const MAX_LABEL_LENGTH: usize = 63;
+
+/// Endpoint errors.
+#[derive(Error, Debug, PartialEq, Eq)]
+pub enum EndpointError {
+    #[error("malformed address '{0}'")]
+    Malformed(String),
+
+    #[error("invalid IP address")]
+    InvalidIp,
+
+    #[error("invalid port")]
+    InvalidPort,
+
+    #[error("invalid domain name '{0}'")]
+    InvalidDomain(String),
+
+    #[error("unsupported protocol '{0}'")]
+    UnsupportedProtocol(String),
+
+    #[error("certificate not pinned")]
+    NotPinned,
+}
+
+/// Host part of an endpoint.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub enum Host {
+    /// An IP address.
+    Ip(IpAddr),
+    /// A domain name, resolved by the host.
+    Domain(String),
+}
+
+impl Host {
+    /// Create a host from a domain name, ensuring that it is valid.
+    pub fn domain(name: &str) -> Result<Self, EndpointError> {
+        parse_domain(name).map(Host::Domain)
+    }
+}
+
+fn parse_domain(name: &str) -> Result<String, EndpointError> {
+    let invalid = || EndpointError::InvalidDomain(name.to_string());
+    let name = name.strip_suffix('.').unwrap_or(name);
+    if name.is_empty() || name.len() > MAX_DOMAIN_LENGTH {
+        return Err(invalid());
+    }
+    for label in name.split('.') {
+        if label.is_empty()
+            || label.len() > MAX_LABEL_LENGTH
+            || label.starts_with('-')
+            || label.ends_with('-')
+            || !label
+                .bytes()
+                .all(|b| b.is_ascii_alphanumeric() || b == b'-')
+        {
+            return Err(invalid());
+        }
+    }
+    Ok(name.to_ascii_lowercase())
+}
+
+impl fmt::Display for Host {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Host::Ip(IpAddr::V6(ip)) => write!(f, "[{ip}]"),
+            Host::Ip(ip) => write!(f, "{ip}"),
+            Host::Domain(name) => write!(f, "{name}"),
+        }
+    }
+}
+
+/// A network endpoint, i.e. a host and a port.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct Endpoint {
+    /// Host of the endpoint.
+    pub host: Host,
+    /// Port of the endpoint, never zero.
+    pub port: u16,
+}
+
+impl Endpoint {
+    /// Create a new endpoint, ensuring that the port is valid.
+    pub fn new(host: Host, port: u16) -> Result<Self, EndpointError> {
+        if port == 0 {
+            return Err(EndpointError::InvalidPort);
+        }
+        if matches!(host, Host::Ip(ip) if ip.is_unspecified()) {
+            return Err(EndpointError::InvalidIp);
+        }
+        Ok(Self { host, port })
+    }
+}
+
+impl FromStr for Endpoint {
+    type Err = EndpointError;
+
+    /// Parse an endpoint of the form `host:port`, where IPv6 addresses must be enclosed in
+    /// brackets.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let malformed = || EndpointError::Malformed(s.to_string());
+        let (host, port) = s.rsplit_once(':').ok_or_else(malformed)?;
+        let port = parse_port(port)?;
+
+        let host = if let Some(ip) = host.strip_prefix('[') {
+            let ip = ip.strip_suffix(']').ok_or_else(malformed)?;
+            Host::Ip(IpAddr::V6(
+                ip.parse().map_err(|_| EndpointError::InvalidIp)?,
+            ))
+        } else if host.contains(':') {
+            // Unbracketed IPv6 addresses are ambiguous.
+            return Err(malformed());
+        } else if let Ok(ip) = host.parse::<Ipv4Addr>() {
+            Host::Ip(IpAddr::V4(ip))
+        } else {
+            Host::domain(host)?
+        };
+
+        Endpoint::new(host, port)
+    }
+}
+
+impl fmt::Display for Endpoint {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}", self.host, self.port)
+    }
+}
+
+impl TryFrom<&TCPAddress> for Endpoint {
+    type Error = EndpointError;
+
+    fn try_from(address: &TCPAddress) -> Result<Self, Self::Error> {
+        let ip = match address.ip.len() {
+            4 => IpAddr::V4(<[u8; 4]>::try_from(&address.ip[..]).unwrap().into()),
+            16 => {
+                let ip = Ipv6Addr::from(<[u8; 16]>::try_from(&address.ip[..]).unwrap());
+                // Addresses may be encoded as IPv4-mapped IPv6 addresses.
+                ip.to_ipv4_mapped()
+                    .map(IpAddr::V4)
+                    .unwrap_or(IpAddr::V6(ip))
+            }
+            _ => return Err(EndpointError::InvalidIp),
+        };
+        let port = u16::try_from(address.port).map_err(|_| EndpointError::InvalidPort)?;
+
+        Endpoint::new(Host::Ip(ip), port)
+    }
+}
+
+/// A component of a multi-address.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub enum Protocol {
+    /// An IPv4 address.
+    Ip4(Ipv4Addr),
+    /// An IPv6 address.
+    Ip6(Ipv6Addr),
+    /// A domain name.
+    Dns(String),
+    /// A TCP port.
+    Tcp(u16),
+    /// TLS on top of the preceding transport.
+    Tls,
+}
+
+/// A multi-address, e.g. `/ip4/127.0.0.1/tcp/443/tls`.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct MultiAddr(Vec<Protocol>);
+
+impl MultiAddr {
+    /// Components of the multi-address.
+    pub fn protocols(&self) -> &[Protocol] {
+        &self.0
+    }
+
+    /// Endpoint the multi-address refers to.
+    pub fn endpoint(&self) -> Endpoint {
+        let host = match &self.0[0] {
+            Protocol::Ip4(ip) => Host::Ip(IpAddr::V4(*ip)),
+            Protocol::Ip6(ip) => Host::Ip(IpAddr::V6(*ip)),
+            Protocol::Dns(name) => Host::Domain(name.clone()),
+            _ => unreachable!("multi-address always starts with a host"),
+        };
+        let port = match self.0[1] {
+            Protocol::Tcp(port) => port,
+            _ => unreachable!("multi-address host is always followed by a port"),
+        };
+        Endpoint { host, port }
+    }
+
+    /// Whether connections to the multi-address use TLS.
+    pub fn is_tls(&self) -> bool {
+        self.0.contains(&Protocol::Tls)
+    }
+}
+
+impl FromStr for MultiAddr {
+    type Err = EndpointError;
+
+    /// Parse a multi-address consisting of a host (`ip4`, `ip6` or `dns`), a `tcp` port and
+    /// optionally `tls`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let malformed = || EndpointError::Malformed(s.to_string());
+        let mut parts = s.strip_prefix('/').ok_or_else(malformed)?.split('/');
+        let mut value = || parts.next().ok_or_else(malformed);
+
+        let host = match value()? {
+            "ip4" => Protocol::Ip4(value()?.parse().map_err(|_| EndpointError::InvalidIp)?),
+            "ip6" => Protocol::Ip6(value()?.parse().map_err(|_| EndpointError::InvalidIp)?),
+            "dns" => Protocol::Dns(parse_domain(value()?)?),
+            other => return Err(EndpointError::UnsupportedProtocol(other.to_string())),
+        };
+        let port = match value()? {
+            "tcp" => Protocol::Tcp(parse_port(value()?)?),
+            other => return Err(EndpointError::UnsupportedProtocol(other.to_string())),
+        };
+        let mut protocols = vec![host, port];
+        match parts.next() {
+            None => {}
+            Some("tls") => protocols.push(Protocol::Tls),
+            Some(other) => return Err(EndpointError::UnsupportedProtocol(other.to_string())),
+        }
+        if parts.next().is_some() {
+            return Err(malformed());
+        }
+
+        let addr = MultiAddr(protocols);
+        let endpoint = addr.endpoint();
+        Endpoint::new(endpoint.host, endpoint.port)?;
+        Ok(addr)
+    }
+}
+
+impl fmt::Display for MultiAddr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for protocol in &self.0 {
+            match protocol {
+                Protocol::Ip4(ip) => write!(f, "/ip4/{ip}")?,
+                Protocol::Ip6(ip) => write!(f, "/ip6/{ip}")?,
+                Protocol::Dns(name) => write!(f, "/dns/{name}")?,
+                Protocol::Tcp(port) => write!(f, "/tcp/{port}")?,
+                Protocol::Tls => write!(f, "/tls")?,
+            }
+        }
+        Ok(())
+    }
+}
+
+fn parse_port(port: &str) -> Result<u16, EndpointError> {
+    // Reject signs and leading zeros, which the host has no reason to send.
+    if port.is_empty() || port.starts_with(['+', '0']) {
+        return Err(EndpointError::InvalidPort);
+    }
+    port.parse().map_err(|_| EndpointError::InvalidPort)
+}
+
+/// A pin of a peer certificate.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub enum CertificatePin {
+    /// The certificate must be for the given Ed25519 public key (e.g. a node TLS key).
+    PublicKey(PublicKey),
+    /// The SHA-256 hash of the certificate's DER-encoded SubjectPublicKeyInfo must match.
+    SpkiSha256([u8; 32]),
+}
+
+impl CertificatePin {
+    /// Create a pin for the given DER-encoded SubjectPublicKeyInfo.
+    pub fn from_spki(spki: &[u8]) -> Self {
+        CertificatePin::SpkiSha256(Sha256::digest(spki).into())
+    }
+
+    /// Whether the given DER-encoded SubjectPublicKeyInfo of a peer certificate matches the pin.
+    pub fn matches(&self, spki: &[u8]) -> bool {
+        match self {
+            CertificatePin::PublicKey(pk) => ed25519_from_spki(spki) == Some(*pk),
+            CertificatePin::SpkiSha256(hash) => Sha256::digest(spki).as_slice() == hash,
+        }
+    }
+}
+
+/// A set of accepted certificate pins.
+#[derive(Clone, Debug, Default)]
+pub struct PinSet {
+    pins: Vec<CertificatePin>,
+}
+
+impl PinSet {
+    /// Create a new pin set accepting certificates matching any of the given pins.
+    pub fn new(pins: Vec<CertificatePin>) -> Self {
+        Self { pins }
+    }
+
+    /// Verify that the given DER-encoded SubjectPublicKeyInfo of a peer certificate matches any
+    /// of the pins. An empty pin set accepts no certificates.
+    pub fn verify(&self, spki: &[u8]) -> Result<(), EndpointError> {
+        if !self.pins.iter().any(|pin| pin.matches(spki)) {
+            return Err(EndpointError::NotPinned);
+        }
+        Ok(())
+    }
+}
+
+/// Ed25519 public key in the given DER-encoded SubjectPublicKeyInfo, if any.
+pub fn ed25519_from_spki(spki: &[u8]) -> Option<PublicKey> {
+    let key = spki.strip_prefix(&ED25519_SPKI_PREFIX[..])?;
+    (key.len() == 32).then(|| PublicKey::from(key))
+}
+
+/// DER-encoded SubjectPublicKeyInfo of the given Ed25519 public key.
+pub fn ed25519_to_spki(pk: &PublicKey) -> Vec<u8> {
+    [&ED25519_SPKI_PREFIX[..], pk.as_ref()].concat()
+}
+
+/// A peer reachable at an endpoint using TLS with a pinned certificate.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct TlsPeer {
+    /// Endpoint of the peer.
+    pub endpoint: Endpoint,
+    /// Pin of the peer certificate.
+    pub pin: CertificatePin,
+}
+
+impl TryFrom<&TLSAddress> for TlsPeer {
+    type Error = EndpointError;
+
+    fn try_from(address: &TLSAddress) -> Result<Self, Self::Error> {
+        Ok(Self {
+            endpoint: Endpoint::try_from(&address.address)?,
+            pin: CertificatePin::PublicKey(address.pub_key),
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_endpoint() {
+        for (raw, expected) in [
+            ("127.0.0.1:443", Host::Ip("127.0.0.1".parse().unwrap())),
+            ("[::1]:443", Host::Ip("::1".parse().unwrap())),
+            ("Example.COM.:443", Host::Domain("example.com".to_string())),
+        ] {
+            let endpoint: Endpoint = raw.parse().unwrap();
+            assert_eq!(endpoint.host, expected);
+            assert_eq!(endpoint.port, 443);
+        }
+        assert_eq!(
+            "[::1]:80".parse::<Endpoint>().unwrap().to_string(),
+            "[::1]:80"
+        );
+
+        for (raw, err) in [
+            (
+                "127.0.0.1",
+                EndpointError::Malformed("127.0.0.1".to_string()),
+            ),
+            ("::1:443", EndpointError::Malformed("::1:443".to_string())),
+            ("127.0.0.1:0", EndpointError::InvalidPort),
+            ("127.0.0.1:080", EndpointError::InvalidPort),
+            ("127.0.0.1:65536", EndpointError::InvalidPort),
+            ("0.0.0.0:443", EndpointError::InvalidIp),
+            ("[1.2.3.4]:443", EndpointError::InvalidIp),
+            (
+                "exa_mple.com:443",
+                EndpointError::InvalidDomain("exa_mple.com".to_string()),
+            ),
+            (
+                "-a.com:443",
+                EndpointError::InvalidDomain("-a.com".to_string()),
+            ),
+            (":443", EndpointError::InvalidDomain("".to_string())),
+        ] {
+            assert_eq!(raw.parse::<Endpoint>(), Err(err), "{raw}");
+        }
+
+        let address = TCPAddress {
+            ip: Ipv4Addr::new(10, 0, 0, 1)
+                .to_ipv6_mapped()
+                .octets()
+                .to_vec(),
+            port: 26656,
+            ..Default::default()
+        };
+        assert_eq!(
+            Endpoint::try_from(&address).unwrap().to_string(),
+            "10.0.0.1:26656"
+        );
+        let address = TCPAddress {
+            ip: vec![1, 2, 3],
+            port: 26656,
+            ..Default::default()
+        };
+        assert_eq!(Endpoint::try_from(&address), Err(EndpointError::InvalidIp));
+        let address = TCPAddress {
+            ip: vec![1, 2, 3, 4],
+            port: 1 << 16,
+            ..Default::default()
+        };
+        assert_eq!(
+            Endpoint::try_from(&address),
+            Err(EndpointError::InvalidPort)
+        );
+    }
+
+    #[test]
+    fn test_multi_addr() {
+        let addr: MultiAddr = "/ip4/10.0.0.1/tcp/443/tls".parse().unwrap();
+        assert!(addr.is_tls());
+        assert_eq!(addr.endpoint().to_string(), "10.0.0.1:443");
+        assert_eq!(addr.to_string(), "/ip4/10.0.0.1/tcp/443/tls");
+
+        let addr: MultiAddr = "/dns/Node.example.com/tcp/8080".parse().unwrap();
+        assert!(!addr.is_tls());
+        assert_eq!(addr.endpoint().to_string(), "node.example.com:8080");
+        let addr: MultiAddr = "/ip6/::1/tcp/1".parse().unwrap();
+        assert_eq!(addr.protocols()[0], Protocol::Ip6(Ipv6Addr::LOCALHOST));
+
+        for (raw, err) in [
+            (
+                "ip4/10.0.0.1/tcp/443",
+                EndpointError::Malformed("ip4/10.0.0.1/tcp/443".to_string()),
+            ),
+            (
+                "/ip4/10.0.0.1",
+                EndpointError::Malformed("/ip4/10.0.0.1".to_string()),
+            ),
+            ("/ip4/::1/tcp/443", EndpointError::InvalidIp),
+            ("/ip4/0.0.0.0/tcp/443", EndpointError::InvalidIp),
+            ("/ip4/10.0.0.1/tcp/0", EndpointError::InvalidPort),
+            (
+                "/ip4/10.0.0.1/udp/443",
+                EndpointError::UnsupportedProtocol("udp".to_string()),
+            ),
+            (
+                "/unix/tmp/sock",
+                EndpointError::UnsupportedProtocol("unix".to_string()),
+            ),
+            (
+                "/ip4/10.0.0.1/tcp/443/ws",
+                EndpointError::UnsupportedProtocol("ws".to_string()),
+            ),
+            (
+                "/ip4/10.0.0.1/tcp/443/tls/tls",
+                EndpointError::Malformed("/ip4/10.0.0.1/tcp/443/tls/tls".to_string()),
+            ),
+        ] {
+            assert_eq!(raw.parse::<MultiAddr>(), Err(err), "{raw}");
+        }
+    }
+
+    #[test]
+    fn test_certificate_pins() {
+        let pk =
+            PublicKey::from("7992f73b0b1b5bebdb3d9b63e1d0988c3fa3e4c0be94d4f8adbf1c3a9c2dc04f");
+        let other =
+            PublicKey::from("a8e265ab1d5ad1cd1f01d428c6623f4df68a1b0433f4a01e2e6a2ab455f9f6f1");
+        let spki = ed25519_to_spki(&pk);
+        assert_eq!(ed25519_from_spki(&spki), Some(pk));
+        assert_eq!(ed25519_from_spki(&spki[1..]), None);
+
+        let pins = PinSet::new(vec![CertificatePin::PublicKey(pk)]);
+        assert!(pins.verify(&spki).is_ok());
+        assert_eq!(
+            pins.verify(&ed25519_to_spki(&other)),
+            Err(EndpointError::NotPinned)
+        );
+        let pins = PinSet::new(vec![CertificatePin::from_spki(&ed25519_to_spki(&other))]);
+        assert!(pins.verify(&ed25519_to_spki(&other)).is_ok());
+        assert!(pins.verify(&spki).is_err());
+        assert!(PinSet::default().verify(&spki).is_err());
+
+        let address = TLSAddress {
+            pub_key: pk,
+            address: TCPAddress {
+                ip: vec![10, 0, 0, 1],
+                port: 443,
+                ..Default::default()
+            },
+        };
+        let peer = TlsPeer::try_from(&address).unwrap();
+        assert_eq!(peer.endpoint.to_string(), "10.0.0.1:443");
+        assert!(peer.pin.matches(&spki));
+    }
+}
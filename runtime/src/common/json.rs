@@ -0,0 +1,249 @@
+//! Bridging of CBOR values and JSON.
+//!
+//! Protocol payloads are CBOR-encoded, which humans and standard tools can't inspect without
+//! custom decoders. Values are rendered as JSON using the following conventions:
+//!
+//! * Integers are rendered as numbers, simple values as booleans or null.
+//! * Text strings are rendered as strings and byte strings as `{"$bytes": "<base64>"}`.
+//! * Big integers (tags 2 and 3) are rendered as `{"$bigint": "<decimal>"}`.
+//! * Other tagged values are rendered as `{"$tag": <tag>, "$value": <value>}`.
+//! * Maps with text keys are rendered as objects. Maps with other keys or with keys starting with
+//!   `$` are rendered as `{"$map": [[<key>, <value>], ...]}`.
+//!
+//! Renderings can be converted back, which is lossless except for undefined values (rendered as
+//! null) and the order of object keys.
+use base64::prelude::*;
+use num_bigint::{BigInt, BigUint, Sign};
+use serde_json::{json, Map, Number, Value as JsonValue};
+use thiserror::Error;
+
+/// Name of the query method rendering another diagnostic query result as JSON.
+pub const METHOD_DIAGNOSTICS_JSON: &str = "runtime.DiagnosticsJSON";
+
+/// CBOR tag of positive big integers.
+const TAG_POSITIVE_BIGINT: u64 = 2;
+/// CBOR tag of negative big integers.
+const TAG_NEGATIVE_BIGINT: u64 = 3;
+
+/// CBOR/JSON bridging errors.
+#[derive(Error, Debug)]
+pub enum Error {
+    #[error("malformed CBOR: {0}")]
+    MalformedCbor(#[from] cbor::DecodeError),
+
+    #[error("malformed JSON: {0}")]
+    MalformedJson(#[from] serde_json::Error),
+
+    #[error("unsupported JSON value: {0}")]
+    Unsupported(String),
+}
+
+/// Render the given CBOR value as JSON.
+pub fn to_json(value: &cbor::Value) -> JsonValue {
+    match value {
+        cbor::Value::Unsigned(n) => JsonValue::from(*n),
+        cbor::Value::Negative(n) => JsonValue::from(*n),
+        cbor::Value::ByteString(data) => json!({ "$bytes": BASE64_STANDARD.encode(data) }),
+        cbor::Value::TextString(text) => JsonValue::String(text.clone()),
+        cbor::Value::Array(items) => JsonValue::Array(items.iter().map(to_json).collect()),
+        cbor::Value::Map(items) => map_to_json(items),
+        cbor::Value::Tag(tag, inner) => match (*tag, inner.as_ref()) {
+            (TAG_POSITIVE_BIGINT, cbor::Value::ByteString(data)) => {
+                json!({ "$bigint": BigUint::from_bytes_be(data).to_string() })
+            }
+            (TAG_NEGATIVE_BIGINT, cbor::Value::ByteString(data)) => {
+                let value = -BigInt::from(BigUint::from_bytes_be(data)) - 1;
+                json!({ "$bigint": value.to_string() })
+            }
+            (tag, inner) => json!({ "$tag": tag, "$value": to_json(inner) }),
+        },
+        cbor::Value::Simple(cbor::SimpleValue::FalseValue) => JsonValue::Bool(false),
+        cbor::Value::Simple(cbor::SimpleValue::TrueValue) => JsonValue::Bool(true),
+        cbor::Value::Simple(_) => JsonValue::Null,
+    }
+}
+
+fn map_to_json(items: &[(cbor::Value, cbor::Value)]) -> JsonValue {
+    let is_object = items
+        .iter()
+        .all(|(key, _)| matches!(key, cbor::Value::TextString(key) if !key.starts_with('$')));
+    if !is_object {
+        let pairs = items
+            .iter()
+            .map(|(key, value)| json!([to_json(key), to_json(value)]))
+            .collect();
+        return json!({ "$map": JsonValue::Array(pairs) });
+    }
+
+    let object = items
+        .iter()
+        .filter_map(|(key, value)| match key {
+            cbor::Value::TextString(key) => Some((key.clone(), to_json(value))),
+            _ => None,
+        })
+        .collect();
+    JsonValue::Object(object)
+}
+
+/// Convert the given JSON rendering back to a CBOR value.
+pub fn from_json(value: &JsonValue) -> Result<cbor::Value, Error> {
+    match value {
+        JsonValue::Null => Ok(cbor::Value::Simple(cbor::SimpleValue::NullValue)),
+        JsonValue::Bool(false) => Ok(cbor::Value::Simple(cbor::SimpleValue::FalseValue)),
+        JsonValue::Bool(true) => Ok(cbor::Value::Simple(cbor::SimpleValue::TrueValue)),
+        JsonValue::Number(n) => number_from_json(n),
+        JsonValue::String(text) => Ok(cbor::Value::TextString(text.clone())),
+        JsonValue::Array(items) => Ok(cbor::Value::Array(
+            items.iter().map(from_json).collect::<Result<_, _>>()?,
+        )),
+        JsonValue::Object(object) => object_from_json(object),
+    }
+}
+
+fn number_from_json(n: &Number) -> Result<cbor::Value, Error> {
+    if let Some(n) = n.as_u64() {
+        return Ok(cbor::Value::Unsigned(n));
+    }
+    match n.as_i64() {
+        Some(n) => Ok(cbor::Value::Negative(n)),
+        None => Err(Error::Unsupported(format!("non-integer number {n}"))),
+    }
+}
+
+fn object_from_json(object: &Map<String, JsonValue>) -> Result<cbor::Value, Error> {
+    let mut keys: Vec<&str> = object.keys().map(String::as_str).collect();
+    keys.sort_unstable();
+    match (keys.as_slice(), object.values().next()) {
+        (["$bytes"], Some(JsonValue::String(data))) => BASE64_STANDARD
+            .decode(data)
+            .map(cbor::Value::ByteString)
+            .map_err(|err| Error::Unsupported(format!("malformed bytes: {err}"))),
+        (["$bigint"], Some(JsonValue::String(value))) => bigint_from_json(value),
+        (["$map"], Some(JsonValue::Array(pairs))) => pairs
+            .iter()
+            .map(|pair| match pair {
+                JsonValue::Array(pair) if pair.len() == 2 => {
+                    Ok((from_json(&pair[0])?, from_json(&pair[1])?))
+                }
+                _ => Err(Error::Unsupported("malformed map entry".to_string())),
+            })
+            .collect::<Result<_, _>>()
+            .map(cbor::Value::Map),
+        (["$tag", "$value"], _) => match &object["$tag"] {
+            JsonValue::Number(tag) if tag.is_u64() => Ok(cbor::Value::Tag(
+                tag.as_u64().unwrap(),
+                Box::new(from_json(&object["$value"])?),
+            )),
+            _ => Err(Error::Unsupported("malformed tag".to_string())),
+        },
+        _ if keys.iter().any(|key| key.starts_with('$')) => {
+            Err(Error::Unsupported(format!("unknown convention {keys:?}")))
+        }
+        _ => object
+            .iter()
+            .map(|(key, value)| Ok((cbor::Value::TextString(key.clone()), from_json(value)?)))
+            .collect::<Result<_, _>>()
+            .map(cbor::Value::Map),
+    }
+}
+
+fn bigint_from_json(value: &str) -> Result<cbor::Value, Error> {
+    let value: BigInt = value
+        .parse()
+        .map_err(|err| Error::Unsupported(format!("malformed big integer: {err}")))?;
+    let (tag, magnitude) = match value.sign() {
+        Sign::Minus => (TAG_NEGATIVE_BIGINT, (-value - 1).magnitude().clone()),
+        _ => (TAG_POSITIVE_BIGINT, value.magnitude().clone()),
+    };
+    Ok(cbor::Value::Tag(
+        tag,
+        Box::new(cbor::Value::ByteString(magnitude.to_bytes_be())),
+    ))
+}
+
+/// Render the given encodable value as JSON.
+pub fn render<T: cbor::Encode>(value: T) -> JsonValue {
+    to_json(&value.into_cbor_value())
+}
+
+/// Render the given CBOR-encoded payload as JSON.
+pub fn cbor_to_json(data: &[u8]) -> Result<JsonValue, Error> {
+    let value: cbor::Value = cbor::from_slice(data)?;
+    Ok(to_json(&value))
+}
+
+/// Encode the given JSON rendering as CBOR.
+pub fn json_to_cbor(text: &str) -> Result<Vec<u8>, Error> {
+    let value: JsonValue = serde_json::from_str(text)?;
+    Ok(cbor::to_vec(from_json(&value)?))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_round_trip() {
+        let value = cbor::Value::Map(vec![
+            (
+                cbor::Value::TextString("bytes".to_string()),
+                cbor::Value::ByteString(vec![1, 2, 3]),
+            ),
+            (
+                cbor::Value::TextString("ints".to_string()),
+                cbor::Value::Array(vec![
+                    cbor::Value::Unsigned(u64::MAX),
+                    cbor::Value::Negative(-5),
+                ]),
+            ),
+            (
+                cbor::Value::TextString("big".to_string()),
+                cbor::Value::Tag(2, Box::new(cbor::Value::ByteString(vec![1, 0]))),
+            ),
+            (
+                cbor::Value::TextString("neg".to_string()),
+                cbor::Value::Tag(3, Box::new(cbor::Value::ByteString(vec![1, 0]))),
+            ),
+            (
+                cbor::Value::TextString("other".to_string()),
+                cbor::Value::Map(vec![(
+                    cbor::Value::Unsigned(1),
+                    cbor::Value::Simple(cbor::SimpleValue::TrueValue),
+                )]),
+            ),
+        ]);
+
+        let rendered = to_json(&value);
+        assert_eq!(
+            rendered,
+            json!({
+                "bytes": {"$bytes": "AQID"},
+                "ints": [u64::MAX, -5],
+                "big": {"$bigint": "256"},
+                "neg": {"$bigint": "-257"},
+                "other": {"$map": [[1, true]]},
+            })
+        );
+
+        let converted = from_json(&rendered).unwrap();
+        assert_eq!(to_json(&converted), rendered);
+        let data = json_to_cbor(&rendered.to_string()).unwrap();
+        assert_eq!(cbor_to_json(&data).unwrap(), rendered);
+    }
+
+    #[test]
+    fn test_conventions() {
+        // Text maps which could be confused with conventions are rendered as generic maps.
+        let value = cbor::Value::Map(vec![(
+            cbor::Value::TextString("$bytes".to_string()),
+            cbor::Value::TextString("AQID".to_string()),
+        )]);
+        let rendered = to_json(&value);
+        assert_eq!(rendered, json!({"$map": [["$bytes", "AQID"]]}));
+        assert_eq!(from_json(&rendered).unwrap(), value);
+
+        assert!(from_json(&json!({"$unknown": 1})).is_err());
+        assert!(from_json(&json!(1.5)).is_err());
+        assert!(from_json(&json!({"$bytes": "!"})).is_err());
+    }
+}
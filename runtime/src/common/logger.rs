@@ -1,13 +1,19 @@
 //! Logging subsystem for runtimes.
-use std::sync::{Mutex, Once};
+use std::{
+    collections::BTreeMap,
+    fmt,
+    str::FromStr,
+    sync::{Mutex, Once, RwLock},
+};
 
 use lazy_static::lazy_static;
 use log::Level;
 use slog::{o, Drain};
+use thiserror::Error;
 
 lazy_static! {
     static ref LOGGER: slog::Logger = slog::Logger::root(
-        Mutex::new(slog_json::Json::default(std::io::stderr())).map(slog::Fuse),
+        slog::Fuse(FilterDrain(Mutex::new(slog_json::Json::default(std::io::stderr())))),
         o!()
     );
 
@@ -16,6 +22,12 @@ lazy_static! {
 
     /// Prevents the global logger from being dropped.
     static ref GLOBAL_LOGGER_SCOPE_GUARD: Mutex<Option<slog_scope::GlobalLoggerGuard>> = Mutex::new(None);
+
+    /// Filter applied to all log records.
+    static ref LOG_FILTER: RwLock<LogFilter> = RwLock::new(LogFilter::default());
+
+    /// Sink additionally receiving all log records passing the filter.
+    static ref LOG_SINK: RwLock<Option<&'static dyn LogSink>> = RwLock::new(None);
 }
 
 /// Get the logger.
@@ -34,3 +46,232 @@ pub fn init_logger(level: Level) {
         slog_stdlog::init_with_level(level).unwrap();
     });
 }
+
+/// Log filter errors.
+#[derive(Error, Debug, PartialEq, Eq)]
+pub enum LogFilterError {
+    #[error("invalid log level: {0}")]
+    InvalidLevel(String),
+}
+
+/// Filter deciding which log records are emitted, based on their level and module.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct LogFilter {
+    /// Minimum level of emitted records for modules without a more specific filter.
+    pub level: slog::Level,
+    /// Minimum level of emitted records per module. A filter for a module also applies to its
+    /// submodules (e.g. `runtime` applies to `runtime/dispatcher`), with the most specific
+    /// filter taking precedence.
+    pub modules: BTreeMap<String, slog::Level>,
+}
+
+impl Default for LogFilter {
+    fn default() -> Self {
+        Self {
+            level: slog::Level::Trace,
+            modules: BTreeMap::new(),
+        }
+    }
+}
+
+impl LogFilter {
+    /// Parse a filter from the given default level and per-module levels (e.g. `debug`).
+    pub fn parse(level: &str, modules: &BTreeMap<String, String>) -> Result<Self, LogFilterError> {
+        let parse_level = |level: &str| {
+            slog::Level::from_str(level)
+                .map_err(|_| LogFilterError::InvalidLevel(level.to_string()))
+        };
+
+        Ok(Self {
+            level: parse_level(level)?,
+            modules: modules
+                .iter()
+                .map(|(module, level)| Ok((module.clone(), parse_level(level)?)))
+                .collect::<Result<_, LogFilterError>>()?,
+        })
+    }
+
+    /// Minimum level of emitted records for the given module.
+    pub fn level_for(&self, module: Option<&str>) -> slog::Level {
+        let module = match module {
+            Some(module) => module,
+            None => return self.level,
+        };
+
+        self.modules
+            .iter()
+            .filter(|(name, _)| {
+                module == name.as_str()
+                    || (module.starts_with(name.as_str()) && module[name.len()..].starts_with('/'))
+            })
+            .max_by_key(|(name, _)| name.len())
+            .map(|(_, level)| *level)
+            .unwrap_or(self.level)
+    }
+}
+
+/// Replace the filter applied to all log records.
+pub fn set_log_filter(filter: LogFilter) {
+    // Also propagate the most verbose level to records emitted via the log crate.
+    let max_level = std::iter::once(filter.level)
+        .chain(filter.modules.values().copied())
+        .max_by_key(|level| level.as_usize())
+        .unwrap();
+    log::set_max_level(match max_level {
+        slog::Level::Critical | slog::Level::Error => log::LevelFilter::Error,
+        slog::Level::Warning => log::LevelFilter::Warn,
+        slog::Level::Info => log::LevelFilter::Info,
+        slog::Level::Debug => log::LevelFilter::Debug,
+        slog::Level::Trace => log::LevelFilter::Trace,
+    });
+
+    *LOG_FILTER.write().unwrap() = filter;
+}
+
+/// Filter currently applied to all log records.
+pub fn log_filter() -> LogFilter {
+    LOG_FILTER.read().unwrap().clone()
+}
+
+/// Structured log record.
+#[derive(Clone, Debug, Default, PartialEq, Eq, cbor::Encode, cbor::Decode)]
+pub struct LogRecord {
+    /// Record level (e.g. `INFO`).
+    pub level: String,
+    /// Module the logger was created for (e.g. `runtime/dispatcher`), if any.
+    #[cbor(optional)]
+    pub target: Option<String>,
+    /// Record message.
+    pub msg: String,
+    /// Record fields, including those of the logger, except for the module.
+    pub fields: BTreeMap<String, String>,
+    /// Round of the last batch executed before the record was emitted, if any.
+    #[cbor(optional)]
+    pub round: Option<u64>,
+}
+
+impl LogRecord {
+    fn new(record: &slog::Record<'_>, values: &slog::OwnedKVList) -> Self {
+        let mut fields = FieldCollector::default();
+        let _ = slog::KV::serialize(&record.kv(), record, &mut fields);
+        let _ = slog::KV::serialize(values, record, &mut fields);
+
+        Self {
+            level: record.level().as_str().to_string(),
+            target: fields.module,
+            msg: record.msg().to_string(),
+            fields: fields.fields,
+            round: None,
+        }
+    }
+}
+
+/// Sink receiving all log records passing the filter, in addition to the standard error output.
+pub trait LogSink: Send + Sync {
+    /// Handle a log record.
+    ///
+    /// Sinks must not emit log records while handling one.
+    fn log(&self, record: LogRecord);
+}
+
+/// Replace the sink additionally receiving all log records passing the filter.
+pub fn set_log_sink(sink: Option<&'static dyn LogSink>) {
+    *LOG_SINK.write().unwrap() = sink;
+}
+
+/// Drain dropping records not passing the global log filter.
+struct FilterDrain<D>(D);
+
+impl<D: Drain> Drain for FilterDrain<D> {
+    type Ok = Option<D::Ok>;
+    type Err = D::Err;
+
+    fn log(
+        &self,
+        record: &slog::Record<'_>,
+        values: &slog::OwnedKVList,
+    ) -> Result<Self::Ok, Self::Err> {
+        let filter = LOG_FILTER.read().unwrap();
+        let level = if filter.modules.is_empty() {
+            filter.level
+        } else {
+            let mut module = ModuleExtractor(None);
+            let _ = slog::KV::serialize(values, record, &mut module);
+            filter.level_for(module.0.as_deref())
+        };
+        drop(filter);
+
+        if !record.level().is_at_least(level) {
+            return Ok(None);
+        }
+        if let Some(sink) = *LOG_SINK.read().unwrap() {
+            sink.log(LogRecord::new(record, values));
+        }
+        self.0.log(record, values).map(Some)
+    }
+}
+
+/// Serializer extracting the module a logger was created for.
+struct ModuleExtractor(Option<String>);
+
+impl slog::Serializer for ModuleExtractor {
+    fn emit_arguments(&mut self, key: slog::Key, val: &fmt::Arguments<'_>) -> slog::Result {
+        // Values of child loggers come first, so keep the most specific module.
+        if key == "module" && self.0.is_none() {
+            self.0 = Some(val.to_string());
+        }
+        Ok(())
+    }
+}
+
+/// Serializer collecting the fields of a record.
+#[derive(Default)]
+struct FieldCollector {
+    module: Option<String>,
+    fields: BTreeMap<String, String>,
+}
+
+impl slog::Serializer for FieldCollector {
+    fn emit_arguments(&mut self, key: slog::Key, val: &fmt::Arguments<'_>) -> slog::Result {
+        // Record fields and values of child loggers come first, so keep the most specific ones.
+        if key == "module" {
+            self.module.get_or_insert_with(|| val.to_string());
+        } else {
+            self.fields
+                .entry(key.to_string())
+                .or_insert_with(|| val.to_string());
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_log_filter() {
+        let mut modules = BTreeMap::new();
+        modules.insert("runtime".to_string(), "debug".to_string());
+        modules.insert("runtime/dispatcher".to_string(), "error".to_string());
+        let filter = LogFilter::parse("warn", &modules).unwrap();
+
+        assert_eq!(filter.level_for(None), slog::Level::Warning);
+        assert_eq!(filter.level_for(Some("runtime")), slog::Level::Debug);
+        assert_eq!(
+            filter.level_for(Some("runtime/storage")),
+            slog::Level::Debug
+        );
+        assert_eq!(
+            filter.level_for(Some("runtime/dispatcher")),
+            slog::Level::Error
+        );
+        assert_eq!(filter.level_for(Some("runtimes")), slog::Level::Warning);
+
+        modules.insert("other".to_string(), "loud".to_string());
+        assert_eq!(
+            LogFilter::parse("info", &modules),
+            Err(LogFilterError::InvalidLevel("loud".to_string()))
+        );
+    }
+}
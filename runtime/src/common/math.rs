@@ -0,0 +1,437 @@
+//! Deterministic fixed-point arithmetic.
+//!
+//! Floating point arithmetic is not guaranteed to produce bit-identical results across
+//! platforms, compilers and optimization levels, which makes it unsuitable for use in runtime
+//! logic where all nodes must agree on the outcome. This module provides a decimal fixed-point
+//! number type together with deterministic approximations of common transcendental functions,
+//! all implemented using integer arithmetic only.
+//!
+//! All operations round towards zero unless noted otherwise. Error bounds are stated in terms
+//! of the ULP (unit in the last place), which is `10^-18`.
+use std::{fmt, str::FromStr};
+
+use num_bigint::{BigInt, BigUint};
+use num_traits::ToPrimitive;
+use thiserror::Error;
+
+/// Number of decimal places.
+pub const DECIMALS: u32 = 18;
+
+/// Scaling factor of the raw representation.
+const SCALE: i128 = 1_000_000_000_000_000_000;
+
+/// Raw representation of `ln(2)`, rounded to nearest.
+const LN_2: i128 = 693_147_180_559_945_309;
+
+/// Maximum number of series terms evaluated by approximations.
+const MAX_SERIES_TERMS: i128 = 64;
+
+/// Fixed-point parsing error.
+#[derive(Error, Debug, PartialEq, Eq)]
+pub enum ParseError {
+    #[error("invalid number")]
+    Invalid,
+    #[error("too many decimal places")]
+    TooManyDecimals,
+    #[error("number out of range")]
+    OutOfRange,
+}
+
+/// A signed decimal fixed-point number with 18 decimal places.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Fixed(i128);
+
+impl Fixed {
+    /// Zero.
+    pub const ZERO: Fixed = Fixed(0);
+    /// One.
+    pub const ONE: Fixed = Fixed(SCALE);
+    /// Smallest representable positive number.
+    pub const EPSILON: Fixed = Fixed(1);
+    /// Largest representable number.
+    pub const MAX: Fixed = Fixed(i128::MAX);
+    /// Smallest representable number.
+    pub const MIN: Fixed = Fixed(i128::MIN);
+
+    /// Create a fixed-point number from its raw representation (the value multiplied by
+    /// `10^18`).
+    pub const fn from_raw(raw: i128) -> Self {
+        Fixed(raw)
+    }
+
+    /// Raw representation of the number (the value multiplied by `10^18`).
+    pub const fn raw(&self) -> i128 {
+        self.0
+    }
+
+    /// Create a fixed-point number from an integer.
+    pub fn from_integer(v: i64) -> Self {
+        Fixed(v as i128 * SCALE)
+    }
+
+    /// Create a fixed-point number representing the ratio `numerator / denominator`.
+    ///
+    /// Returns `None` in case the denominator is zero or the result overflows.
+    pub fn from_ratio(numerator: i64, denominator: i64) -> Option<Self> {
+        mul_div(numerator as i128, SCALE, denominator as i128).map(Fixed)
+    }
+
+    /// Integer part of the number, rounded towards zero.
+    pub fn trunc(&self) -> i128 {
+        self.0 / SCALE
+    }
+
+    /// Whether the number is negative.
+    pub fn is_negative(&self) -> bool {
+        self.0 < 0
+    }
+
+    /// Absolute value. Returns `None` on overflow.
+    pub fn checked_abs(&self) -> Option<Self> {
+        self.0.checked_abs().map(Fixed)
+    }
+
+    /// Adds two numbers, returning `None` on overflow.
+    pub fn checked_add(&self, other: Fixed) -> Option<Self> {
+        self.0.checked_add(other.0).map(Fixed)
+    }
+
+    /// Subtracts two numbers, returning `None` on overflow.
+    pub fn checked_sub(&self, other: Fixed) -> Option<Self> {
+        self.0.checked_sub(other.0).map(Fixed)
+    }
+
+    /// Multiplies two numbers, returning `None` on overflow.
+    ///
+    /// The result is exact up to truncation, so the error is less than one ULP.
+    pub fn checked_mul(&self, other: Fixed) -> Option<Self> {
+        mul_div(self.0, other.0, SCALE).map(Fixed)
+    }
+
+    /// Divides two numbers, returning `None` on overflow or division by zero.
+    ///
+    /// The result is exact up to truncation, so the error is less than one ULP.
+    pub fn checked_div(&self, other: Fixed) -> Option<Self> {
+        mul_div(self.0, SCALE, other.0).map(Fixed)
+    }
+
+    /// Raises the number to an integer power using exponentiation by squaring, returning `None`
+    /// on overflow.
+    ///
+    /// Each multiplication truncates, so the relative error grows with the number of
+    /// multiplications (at most `2 * log2(exp)`) as well as with the magnitude of the result.
+    pub fn checked_powi(&self, mut exp: u32) -> Option<Self> {
+        let mut base = *self;
+        let mut result = Fixed::ONE;
+        while exp > 0 {
+            if exp & 1 == 1 {
+                result = result.checked_mul(base)?;
+            }
+            exp >>= 1;
+            if exp > 0 {
+                base = base.checked_mul(base)?;
+            }
+        }
+        Some(result)
+    }
+
+    /// Square root, rounded down.
+    ///
+    /// Returns `None` for negative numbers. The result is the exact square root truncated to 18
+    /// decimal places, so the error is less than one ULP.
+    pub fn sqrt(&self) -> Option<Self> {
+        if self.0 < 0 {
+            return None;
+        }
+        let v = BigUint::from(self.0 as u128) * BigUint::from(SCALE as u128);
+        v.sqrt().to_i128().map(Fixed)
+    }
+
+    /// Natural exponential function.
+    ///
+    /// Returns `None` in case the result overflows (for arguments larger than roughly `46.6`).
+    /// The relative error of the result is less than `10^-16`, plus at most one ULP of absolute
+    /// error for results smaller than one.
+    pub fn exp(&self) -> Option<Self> {
+        // Range reduction: x = k * ln(2) + r, with |r| <= ln(2) / 2.
+        let k = div_round(self.0, LN_2);
+        let r = self.0 - k * LN_2;
+
+        // Taylor series for e^r.
+        let mut sum = SCALE;
+        let mut term = SCALE;
+        let mut n = 1;
+        while n <= MAX_SERIES_TERMS {
+            term = term * r / SCALE / n;
+            if term == 0 {
+                break;
+            }
+            sum += term;
+            n += 1;
+        }
+
+        // Scale by 2^k.
+        match k {
+            k if k >= 0 => {
+                if k >= 127 || sum.leading_zeros() <= k as u32 {
+                    return None;
+                }
+                Some(Fixed(sum << k))
+            }
+            k if k > -127 => Some(Fixed(sum >> (-k))),
+            _ => Some(Fixed::ZERO),
+        }
+    }
+
+    /// Natural logarithm.
+    ///
+    /// Returns `None` for non-positive numbers. The absolute error of the result is less than
+    /// `10^-16`.
+    pub fn ln(&self) -> Option<Self> {
+        if self.0 <= 0 {
+            return None;
+        }
+
+        // Normalize: x = m * 2^k, with 1 <= m < 2.
+        let x_bits = 128 - self.0.leading_zeros() as i128;
+        let one_bits = 128 - SCALE.leading_zeros() as i128;
+        let mut k = x_bits - one_bits;
+        let mut m = shift(self.0, -k);
+        if m < SCALE {
+            k -= 1;
+            m = shift(self.0, -k);
+        } else if m >= 2 * SCALE {
+            k += 1;
+            m = shift(self.0, -k);
+        }
+
+        // ln(m) = 2 * atanh(z), with z = (m - 1) / (m + 1) in [0, 1/3).
+        let z = (m - SCALE) * SCALE / (m + SCALE);
+        let z2 = z * z / SCALE;
+        let mut sum = 0;
+        let mut power = z;
+        let mut n = 1;
+        while n <= 2 * MAX_SERIES_TERMS {
+            let term = power / n;
+            if term == 0 {
+                break;
+            }
+            sum += term;
+            power = power * z2 / SCALE;
+            n += 2;
+        }
+
+        Some(Fixed(k * LN_2 + 2 * sum))
+    }
+
+    /// Raises the number to an arbitrary power, computed as `exp(y * ln(x))`.
+    ///
+    /// Returns `None` in case the base is negative, in case zero is raised to a negative power
+    /// or in case the result overflows. The error of the result is dominated by the error of
+    /// `ln` scaled by `y`, so the relative error is less than `|y| * 10^-16 + 10^-16`.
+    pub fn pow(&self, y: Fixed) -> Option<Self> {
+        if y.0 == 0 {
+            return Some(Fixed::ONE);
+        }
+        match self.0 {
+            x if x < 0 => None,
+            0 if y.0 < 0 => None,
+            0 => Some(Fixed::ZERO),
+            SCALE => Some(Fixed::ONE),
+            _ => self.ln()?.checked_mul(y)?.exp(),
+        }
+    }
+}
+
+impl From<i64> for Fixed {
+    fn from(v: i64) -> Self {
+        Self::from_integer(v)
+    }
+}
+
+impl fmt::Display for Fixed {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let sign = if self.0 < 0 { "-" } else { "" };
+        let abs = self.0.unsigned_abs();
+        let scale = SCALE as u128;
+        let frac = format!("{:018}", abs % scale);
+        let frac = frac.trim_end_matches('0');
+        if frac.is_empty() {
+            write!(f, "{}{}", sign, abs / scale)
+        } else {
+            write!(f, "{}{}.{}", sign, abs / scale, frac)
+        }
+    }
+}
+
+impl FromStr for Fixed {
+    type Err = ParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (negative, s) = match s.strip_prefix('-') {
+            Some(s) => (true, s),
+            None => (false, s),
+        };
+        let (int, frac) = s.split_once('.').unwrap_or((s, ""));
+        if int.is_empty() || !int.bytes().chain(frac.bytes()).all(|b| b.is_ascii_digit()) {
+            return Err(ParseError::Invalid);
+        }
+        if frac.len() > DECIMALS as usize {
+            return Err(ParseError::TooManyDecimals);
+        }
+
+        let int: i128 = int.parse().map_err(|_| ParseError::OutOfRange)?;
+        let frac: i128 = if frac.is_empty() {
+            0
+        } else {
+            frac.parse::<i128>().map_err(|_| ParseError::Invalid)?
+                * 10i128.pow(DECIMALS - frac.len() as u32)
+        };
+        let raw = int
+            .checked_mul(SCALE)
+            .and_then(|v| v.checked_add(frac))
+            .ok_or(ParseError::OutOfRange)?;
+
+        Ok(Fixed(if negative { -raw } else { raw }))
+    }
+}
+
+/// Computes `a * b / c`, truncated towards zero, without intermediate overflow.
+fn mul_div(a: i128, b: i128, c: i128) -> Option<i128> {
+    if c == 0 {
+        return None;
+    }
+    match a.checked_mul(b) {
+        Some(v) => v.checked_div(c),
+        None => (BigInt::from(a) * BigInt::from(b) / BigInt::from(c)).to_i128(),
+    }
+}
+
+/// Computes `a / b`, rounded to nearest.
+fn div_round(a: i128, b: i128) -> i128 {
+    let q = a / b;
+    let r = a % b;
+    if 2 * r.abs() >= b.abs() {
+        q + a.signum() * b.signum()
+    } else {
+        q
+    }
+}
+
+/// Multiplies the value by `2^k`, truncating in case `k` is negative.
+fn shift(v: i128, k: i128) -> i128 {
+    if k >= 0 {
+        v << k
+    } else {
+        v >> (-k)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn fixed(s: &str) -> Fixed {
+        s.parse().unwrap()
+    }
+
+    fn assert_close(actual: Fixed, expected: f64, tolerance: f64) {
+        let actual = actual.raw() as f64 / SCALE as f64;
+        let error = if expected.abs() > 1.0 {
+            ((actual - expected) / expected).abs()
+        } else {
+            (actual - expected).abs()
+        };
+        assert!(
+            error < tolerance,
+            "expected {expected}, got {actual} (error {error})"
+        );
+    }
+
+    #[test]
+    fn test_parse_format() {
+        for s in ["0", "1", "-1", "1.5", "-0.000000000000000001", "123456.789"] {
+            assert_eq!(fixed(s).to_string(), s);
+        }
+        assert_eq!(fixed("2.50").to_string(), "2.5");
+        assert_eq!("".parse::<Fixed>(), Err(ParseError::Invalid));
+        assert_eq!(".5".parse::<Fixed>(), Err(ParseError::Invalid));
+        assert_eq!("1.2.3".parse::<Fixed>(), Err(ParseError::Invalid));
+        assert_eq!(
+            "0.0000000000000000001".parse::<Fixed>(),
+            Err(ParseError::TooManyDecimals)
+        );
+        assert_eq!(
+            "1000000000000000000000".parse::<Fixed>(),
+            Err(ParseError::OutOfRange)
+        );
+    }
+
+    #[test]
+    fn test_arithmetic() {
+        assert_eq!(fixed("1.5").checked_mul(fixed("2.5")), Some(fixed("3.75")));
+        assert_eq!(
+            fixed("1").checked_div(fixed("3")),
+            Some(fixed("0.333333333333333333"))
+        );
+        assert_eq!(fixed("1").checked_div(Fixed::ZERO), None);
+        assert_eq!(Fixed::MAX.checked_add(Fixed::EPSILON), None);
+        assert_eq!(Fixed::MAX.checked_mul(fixed("2")), None);
+        // Large intermediate products must not overflow.
+        assert_eq!(
+            fixed("100000000000000000000").checked_mul(fixed("0.5")),
+            Some(fixed("50000000000000000000"))
+        );
+        assert_eq!(Fixed::from_ratio(1, 4), Some(fixed("0.25")));
+        assert_eq!(fixed("1.1").checked_powi(2), Some(fixed("1.21")));
+        assert_eq!(fixed("2").checked_powi(10), Some(fixed("1024")));
+        assert_eq!(fixed("-2.7").trunc(), -2);
+    }
+
+    #[test]
+    fn test_sqrt() {
+        assert_eq!(fixed("4").sqrt(), Some(fixed("2")));
+        assert_eq!(fixed("2").sqrt(), Some(fixed("1.414213562373095048")));
+        assert_eq!(Fixed::ZERO.sqrt(), Some(Fixed::ZERO));
+        assert_eq!(fixed("-1").sqrt(), None);
+    }
+
+    #[test]
+    fn test_exp() {
+        assert_eq!(Fixed::ZERO.exp(), Some(Fixed::ONE));
+        assert_close(Fixed::ONE.exp().unwrap(), std::f64::consts::E, 1e-15);
+        for x in [-40.0, -10.0, -1.5, -0.1, 0.1, 0.5, 2.0, 10.0, 30.0, 46.0] {
+            let v = fixed(&format!("{x}"));
+            assert_close(v.exp().unwrap(), f64::exp(x), 1e-14);
+        }
+        assert_eq!(fixed("-100").exp(), Some(Fixed::ZERO));
+        assert_eq!(fixed("50").exp(), None);
+    }
+
+    #[test]
+    fn test_ln() {
+        assert_eq!(Fixed::ONE.ln(), Some(Fixed::ZERO));
+        assert_eq!(fixed("2").ln(), Some(Fixed::from_raw(LN_2)));
+        for x in [1e-9, 0.001, 0.5, 1.5, 2.718281828, 10.0, 12345.678, 1e18] {
+            let v = fixed(&format!("{x}"));
+            assert_close(v.ln().unwrap(), f64::ln(x), 1e-14);
+        }
+        assert_eq!(Fixed::ZERO.ln(), None);
+        assert_eq!(fixed("-1").ln(), None);
+    }
+
+    #[test]
+    fn test_pow() {
+        assert_eq!(fixed("5").pow(Fixed::ZERO), Some(Fixed::ONE));
+        assert_eq!(Fixed::ZERO.pow(fixed("2")), Some(Fixed::ZERO));
+        assert_eq!(Fixed::ZERO.pow(fixed("-2")), None);
+        assert_eq!(fixed("-2").pow(fixed("2")), None);
+        assert_close(fixed("2").pow(fixed("0.5")).unwrap(), 2f64.sqrt(), 1e-15);
+        assert_close(
+            fixed("1.0001").pow(fixed("10000")).unwrap(),
+            2.718145927,
+            1e-9,
+        );
+        assert_close(fixed("10").pow(fixed("-3")).unwrap(), 0.001, 1e-15);
+    }
+}
@@ -3,12 +3,18 @@
 #[macro_use]
 pub mod bytes;
 pub mod crypto;
+pub mod endorsement;
+pub mod endpoint;
+pub mod json;
 pub mod key_format;
 pub mod logger;
+pub mod math;
 pub mod namespace;
+pub mod pagination;
 pub mod panic;
 pub mod process;
 pub mod quantity;
+pub mod rate_limit;
 pub mod sgx;
 #[cfg(feature = "tdx")]
 pub mod tdx;
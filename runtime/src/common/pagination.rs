@@ -0,0 +1,234 @@
+//! Pagination of listing requests.
+//!
+//! All listing requests sent to the host carry a `Pagination` which bounds the number of items
+//! the host may return in a single response. Hosts return a continuation token in case more
+//! items are available, which can be used to request the next page. This makes sure that large
+//! listings cannot exhaust enclave memory and gives all listing APIs the same semantics.
+use thiserror::Error;
+
+/// Number of items returned in a page when no limit is given.
+pub const DEFAULT_PAGE_SIZE: u32 = 100;
+/// Maximum number of items that can be returned in a single page.
+pub const MAX_PAGE_SIZE: u32 = 1000;
+/// Maximum number of pages fetched for a single listing.
+pub const MAX_PAGES: usize = 10_000;
+
+/// Pagination errors.
+#[derive(Error, Debug, PartialEq, Eq)]
+pub enum PaginationError {
+    #[error("page too large (got {got} items, limit is {limit})")]
+    PageTooLarge { got: usize, limit: u32 },
+
+    #[error("malformed continuation token")]
+    MalformedToken,
+
+    #[error("continuation token did not advance")]
+    NoProgress,
+
+    #[error("too many pages (limit is {limit})")]
+    TooManyPages { limit: usize },
+}
+
+/// Pagination parameters of a listing request.
+#[derive(Clone, Debug, Default, PartialEq, Eq, cbor::Encode, cbor::Decode)]
+pub struct Pagination {
+    /// Number of items to skip. Ignored in case a continuation token is given.
+    #[cbor(optional)]
+    pub offset: u64,
+    /// Maximum number of items to return. Zero means `DEFAULT_PAGE_SIZE` and values above
+    /// `MAX_PAGE_SIZE` are capped.
+    #[cbor(optional)]
+    pub limit: u32,
+    /// Opaque token returned by a previous listing request, identifying the next page.
+    #[cbor(optional)]
+    pub continuation_token: Option<Vec<u8>>,
+}
+
+impl Pagination {
+    /// Pagination returning at most `limit` items starting at the first item.
+    pub fn with_limit(limit: u32) -> Self {
+        Self {
+            limit,
+            ..Default::default()
+        }
+    }
+
+    /// Maximum number of items that may be returned, taking defaults and caps into account.
+    pub fn effective_limit(&self) -> u32 {
+        match self.limit {
+            0 => DEFAULT_PAGE_SIZE,
+            limit => limit.min(MAX_PAGE_SIZE),
+        }
+    }
+
+    /// Pagination with the effective limit filled in, as sent to the host.
+    pub fn normalize(&self) -> Self {
+        Self {
+            limit: self.effective_limit(),
+            ..self.clone()
+        }
+    }
+
+    /// Ensure that a page returned for this pagination respects the limit.
+    pub fn check(&self, count: usize) -> Result<(), PaginationError> {
+        let limit = self.effective_limit();
+        if count > limit as usize {
+            return Err(PaginationError::PageTooLarge { got: count, limit });
+        }
+        Ok(())
+    }
+
+    /// Pagination for the page following the one that returned the given continuation token,
+    /// or `None` in case there are no more pages.
+    ///
+    /// Fails in case the continuation token is the same as the one used to request this page,
+    /// as following it would request the same page again.
+    pub fn next(
+        &self,
+        continuation_token: Option<Vec<u8>>,
+    ) -> Result<Option<Self>, PaginationError> {
+        let token = match continuation_token {
+            Some(token) => token,
+            None => return Ok(None),
+        };
+        if self.continuation_token.as_ref() == Some(&token) {
+            return Err(PaginationError::NoProgress);
+        }
+
+        Ok(Some(Self {
+            offset: 0,
+            limit: self.limit,
+            continuation_token: Some(token),
+        }))
+    }
+
+    /// Select the requested page from a complete list of items.
+    ///
+    /// Returns the page and the continuation token for the next page, if any. This is meant
+    /// for host implementations which hold the complete listing; the continuation token encodes
+    /// the offset of the next page.
+    pub fn paginate<T: Clone>(
+        &self,
+        items: &[T],
+    ) -> Result<(Vec<T>, Option<Vec<u8>>), PaginationError> {
+        let offset = match self.continuation_token {
+            Some(ref token) => u64::from_be_bytes(
+                token
+                    .as_slice()
+                    .try_into()
+                    .map_err(|_| PaginationError::MalformedToken)?,
+            ),
+            None => self.offset,
+        };
+        let start = usize::try_from(offset)
+            .unwrap_or(usize::MAX)
+            .min(items.len());
+        let end = start
+            .saturating_add(self.effective_limit() as usize)
+            .min(items.len());
+
+        let token = (end < items.len()).then(|| (end as u64).to_be_bytes().to_vec());
+        Ok((items[start..end].to_vec(), token))
+    }
+}
+
+/// Pages of a single listing, making sure that fetching them terminates.
+pub struct Pages {
+    next: Option<Pagination>,
+    fetched: usize,
+}
+
+impl Pages {
+    /// Pages of a listing starting with the given pagination.
+    pub fn new(first: Pagination) -> Self {
+        Self {
+            next: Some(first),
+            fetched: 0,
+        }
+    }
+
+    /// Pagination of the next page to fetch, or `None` in case all pages have been fetched.
+    pub fn next_page(&self) -> Option<Pagination> {
+        self.next.clone()
+    }
+
+    /// Advance to the page identified by the continuation token returned for the last page.
+    ///
+    /// Fails in case the token does not advance or more than `MAX_PAGES` pages were fetched.
+    pub fn advance(&mut self, continuation_token: Option<Vec<u8>>) -> Result<(), PaginationError> {
+        let current = match self.next.take() {
+            Some(current) => current,
+            None => return Ok(()),
+        };
+        self.fetched += 1;
+        self.next = current.next(continuation_token)?;
+        if self.next.is_some() && self.fetched >= MAX_PAGES {
+            return Err(PaginationError::TooManyPages { limit: MAX_PAGES });
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_pagination() {
+        assert_eq!(Pagination::default().effective_limit(), DEFAULT_PAGE_SIZE);
+        assert_eq!(Pagination::with_limit(5).effective_limit(), 5);
+        assert_eq!(
+            Pagination::with_limit(u32::MAX).effective_limit(),
+            MAX_PAGE_SIZE
+        );
+        assert_eq!(Pagination::default().normalize().limit, DEFAULT_PAGE_SIZE);
+
+        let items: Vec<u32> = (0..7).collect();
+        let mut pages = Pages::new(Pagination::with_limit(3));
+        let mut fetched = vec![];
+        while let Some(pagination) = pages.next_page() {
+            let (page, token) = pagination.paginate(&items).unwrap();
+            assert!(pagination.check(page.len()).is_ok());
+            fetched.push(page);
+            pages.advance(token).unwrap();
+        }
+        assert_eq!(fetched, vec![vec![0, 1, 2], vec![3, 4, 5], vec![6]]);
+
+        // Hosts returning the same continuation token again are rejected.
+        let mut pages = Pages::new(Pagination::default());
+        pages.advance(Some(vec![1])).unwrap();
+        assert_eq!(
+            pages.advance(Some(vec![1])),
+            Err(PaginationError::NoProgress)
+        );
+
+        // Hosts returning an endless listing are rejected.
+        let mut pages = Pages::new(Pagination::default());
+        let result = (0..=MAX_PAGES as u64)
+            .map(|page| pages.advance(Some(page.to_be_bytes().to_vec())))
+            .find(Result::is_err);
+        assert_eq!(
+            result,
+            Some(Err(PaginationError::TooManyPages { limit: MAX_PAGES }))
+        );
+
+        let pagination = Pagination {
+            offset: 5,
+            ..Pagination::with_limit(10)
+        };
+        assert_eq!(pagination.paginate(&items).unwrap(), (vec![5, 6], None));
+
+        assert_eq!(
+            Pagination::with_limit(2).check(3),
+            Err(PaginationError::PageTooLarge { got: 3, limit: 2 })
+        );
+        let pagination = Pagination {
+            continuation_token: Some(vec![1, 2, 3]),
+            ..Default::default()
+        };
+        assert_eq!(
+            pagination.paginate(&items),
+            Err(PaginationError::MalformedToken)
+        );
+    }
+}
@@ -0,0 +1,210 @@
+//! Token bucket rate limiting keyed by the source of requests.
+//!
+//! Each source is limited by a token bucket refilled at a sustained rate up to its burst, where
+//! each request is charged its cost, as well as by the number of its requests being handled
+//! concurrently. The number of tracked sources is bounded, with the least recently seen idle
+//! source forgotten once the bound is exceeded.
+use std::{
+    collections::HashMap,
+    hash::Hash,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+/// Limits of a single source.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Limit {
+    /// The sustained cost of requests per second. A zero value denotes no limit.
+    pub cost_per_second: u32,
+    /// The maximum cost of requests in a burst. In case it is zero, `cost_per_second` is used.
+    pub burst: u32,
+    /// The maximum number of requests being handled concurrently. A zero value denotes no limit.
+    pub max_concurrent: u32,
+}
+
+impl Limit {
+    fn is_unlimited(&self) -> bool {
+        self.cost_per_second == 0 && self.max_concurrent == 0
+    }
+}
+
+/// Reason for rejecting a request.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Rejection {
+    /// The source exceeded its rate and may retry after the given time.
+    RateLimited { retry_after: Duration },
+    /// The source has too many requests being handled concurrently.
+    TooManyConcurrent,
+}
+
+/// State of a single source.
+struct Bucket {
+    tokens: f64,
+    refilled_at: Instant,
+    in_flight: u32,
+}
+
+type Buckets<K> = Arc<Mutex<HashMap<K, Bucket>>>;
+
+/// Admitted request, counted towards the concurrency limit of its source until dropped.
+pub struct Permit<K: Hash + Eq> {
+    buckets: Buckets<K>,
+    source: Option<K>,
+}
+
+impl<K: Hash + Eq> Drop for Permit<K> {
+    fn drop(&mut self) {
+        let source = match self.source.take() {
+            Some(source) => source,
+            None => return,
+        };
+        if let Some(bucket) = self.buckets.lock().unwrap().get_mut(&source) {
+            bucket.in_flight = bucket.in_flight.saturating_sub(1);
+        }
+    }
+}
+
+/// Rate limiter of requests keyed by their source.
+pub struct RateLimiter<K: Hash + Eq> {
+    max_sources: usize,
+    buckets: Buckets<K>,
+}
+
+impl<K: Clone + Hash + Eq> RateLimiter<K> {
+    /// Create a new rate limiter tracking at most the given number of sources at once.
+    pub fn new(max_sources: usize) -> Self {
+        Self {
+            max_sources,
+            buckets: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+
+    /// Admit a request of the given cost by the given source, subject to the given limits.
+    ///
+    /// Requests costing more than the burst of their source are charged the whole burst.
+    pub fn admit(&self, source: K, limit: Limit, cost: u32) -> Result<Permit<K>, Rejection> {
+        self.admit_at(source, limit, cost, Instant::now())
+    }
+
+    /// Admit a request at the given time, see [`RateLimiter::admit`].
+    pub fn admit_at(
+        &self,
+        source: K,
+        limit: Limit,
+        cost: u32,
+        now: Instant,
+    ) -> Result<Permit<K>, Rejection> {
+        if limit.is_unlimited() {
+            return Ok(Permit {
+                buckets: self.buckets.clone(),
+                source: None,
+            });
+        }
+        let rate = limit.cost_per_second as f64;
+        let burst = match limit.burst {
+            0 => rate,
+            burst => burst as f64,
+        };
+        let cost = (cost as f64).min(burst);
+
+        let mut buckets = self.buckets.lock().unwrap();
+        if !buckets.contains_key(&source) && buckets.len() >= self.max_sources {
+            // Forget the least recently seen source, preferring sources without requests in
+            // flight.
+            let oldest = buckets
+                .iter()
+                .min_by_key(|(_, bucket)| (bucket.in_flight > 0, bucket.refilled_at))
+                .map(|(source, _)| source.clone());
+            if let Some(oldest) = oldest {
+                buckets.remove(&oldest);
+            }
+        }
+        let bucket = buckets.entry(source.clone()).or_insert(Bucket {
+            tokens: burst,
+            refilled_at: now,
+            in_flight: 0,
+        });
+
+        let elapsed = now.saturating_duration_since(bucket.refilled_at);
+        bucket.refilled_at = now;
+        bucket.tokens = (bucket.tokens + elapsed.as_secs_f64() * rate).min(burst);
+        if limit.max_concurrent > 0 && bucket.in_flight >= limit.max_concurrent {
+            return Err(Rejection::TooManyConcurrent);
+        }
+        if rate > 0.0 {
+            if bucket.tokens < cost {
+                return Err(Rejection::RateLimited {
+                    retry_after: Duration::from_secs_f64((cost - bucket.tokens) / rate),
+                });
+            }
+            bucket.tokens -= cost;
+        }
+        bucket.in_flight += 1;
+
+        Ok(Permit {
+            buckets: self.buckets.clone(),
+            source: Some(source),
+        })
+    }
+
+    /// Whether the given source is currently tracked.
+    pub fn is_tracked(&self, source: &K) -> bool {
+        self.buckets.lock().unwrap().contains_key(source)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_rate_limiter() {
+        let limiter = RateLimiter::new(2);
+        let limit = Limit {
+            cost_per_second: 2,
+            burst: 4,
+            max_concurrent: 2,
+        };
+        let now = Instant::now();
+
+        // Requests are charged their cost, capped at the burst.
+        let first = limiter.admit_at("abusive", limit, 3, now).unwrap();
+        assert_eq!(
+            limiter.admit_at("abusive", limit, 3, now).err(),
+            Some(Rejection::RateLimited {
+                retry_after: Duration::from_millis(1000),
+            })
+        );
+        drop(limiter.admit_at("other", limit, 100, now).unwrap());
+
+        // Concurrent requests are limited until admitted requests complete.
+        let second = limiter.admit_at("abusive", limit, 1, now).unwrap();
+        assert_eq!(
+            limiter.admit_at("abusive", limit, 0, now).err(),
+            Some(Rejection::TooManyConcurrent)
+        );
+        drop((first, second));
+
+        // Tokens are refilled at the sustained rate.
+        let later = now + Duration::from_millis(500);
+        drop(limiter.admit_at("abusive", limit, 1, later).unwrap());
+        assert!(limiter.admit_at("abusive", limit, 1, later).is_err());
+
+        // Unlimited sources are not tracked.
+        for _ in 0..100 {
+            drop(
+                limiter
+                    .admit_at("trusted", Limit::default(), 1, later)
+                    .unwrap(),
+            );
+        }
+        assert!(!limiter.is_tracked(&"trusted"));
+
+        // The least recently seen idle source is forgotten.
+        let _busy = limiter.admit_at("abusive", limit, 0, later).unwrap();
+        drop(limiter.admit_at("new", limit, 1, later).unwrap());
+        assert!(!limiter.is_tracked(&"other"));
+        assert!(limiter.is_tracked(&"abusive"));
+        assert!(limiter.is_tracked(&"new"));
+    }
+}
@@ -15,7 +15,7 @@ use x509_parser::prelude::*;
 
 use crate::common::{
     sgx::{EnclaveIdentity, MrEnclave, MrSigner, VerifiedQuote},
-    time::{insecure_posix_time, update_insecure_posix_time},
+    time::{clock_skew_tolerance, insecure_posix_time, update_insecure_posix_time},
 };
 
 /// AVR verification error.
@@ -511,7 +511,7 @@ fn check_certificate_rsa_signature(cert: &X509Certificate, public_key: &RsaPubli
 /// Return true iff the (POXIX) timestamp is considered "fresh" for the purposes
 /// of a cached AVR, given the current time.
 pub(crate) fn timestamp_is_fresh(now: i64, timestamp: i64) -> bool {
-    (now - timestamp).abs() < 60 * 60 * 24
+    (now - timestamp).abs() < 60 * 60 * 24 + clock_skew_tolerance()
 }
 
 #[cfg(test)]
@@ -0,0 +1,506 @@
+//! Migration of sealed secrets between enclave versions.
+//!
+//! Secrets sealed under the `MRENCLAVE` key policy are bound to a specific enclave build and
+//! would become inaccessible after an upgrade. The migration protocol allows the old enclave
+//! version to hand designated secrets over to the new version:
+//!
+//! 1. The new enclave creates a [`MigrationRequest`] which binds its REK to its attested RAK.
+//! 2. The host relays the request to the old enclave (see `BundleManager::bundle_migrate`) by
+//!    calling its [`METHOD_MIGRATE`] local RPC method, served by a [`MigrationHandler`].
+//! 3. The old enclave verifies the attestation of the new enclave against its
+//!    [`MigrationPolicy`], unseals the designated secrets and encrypts them to the REK of the
+//!    new enclave. The resulting [`MigrationResponse`] is signed by the RAK of the old enclave.
+//! 4. The new enclave verifies the attestation of the old enclave against its policy, decrypts
+//!    the secrets and re-seals them under its own identity.
+use std::sync::Arc;
+
+use anyhow::{anyhow, Result};
+use rand::Rng;
+use sgx_isa::Keypolicy;
+use thiserror::Error;
+
+use crate::{
+    common::{
+        crypto::{
+            mrae::deoxysii::{self, Opener, NONCE_SIZE},
+            rng::SecureRng,
+            signature::{PublicKey, Signature, Signer},
+            x25519,
+        },
+        sgx::{
+            seal::{seal, unseal},
+            EnclaveIdentity, Quote, QuotePolicy, VerifiedQuote,
+        },
+    },
+    enclave_rpc::{
+        dispatcher::{Method, MethodDescriptor},
+        types::Kind as RpcKind,
+        Context as RpcContext,
+    },
+    identity::Identity,
+};
+
+/// Name of the local RPC method answering migration requests relayed by the host.
+pub const METHOD_MIGRATE: &str = "runtime.MigrateSecrets";
+
+/// Signature context used for signing migration requests.
+pub(crate) const MIGRATION_REQUEST_SIGNATURE_CONTEXT: &[u8] =
+    b"oasis-core/sgx: secret migration request";
+/// Signature context used for signing migration responses.
+pub(crate) const MIGRATION_RESPONSE_SIGNATURE_CONTEXT: &[u8] =
+    b"oasis-core/sgx: secret migration response";
+/// Additional data used when encrypting migrated secrets.
+const MIGRATION_AD_CONTEXT: &[u8] = b"oasis-core/sgx: secret migration";
+
+/// Secret migration error.
+#[derive(Error, Debug)]
+pub enum MigrationError {
+    #[error("quote not available")]
+    QuoteNotAvailable,
+    #[error("enclave identity not allowed by migration policy")]
+    IdentityNotAllowed,
+    #[error("invalid signature")]
+    InvalidSignature,
+    #[error("secret not found: {0}")]
+    SecretNotFound(String),
+}
+
+/// Verifier of remote attestation quotes of peer enclaves.
+type QuoteVerifier = dyn Fn(&Quote, &QuotePolicy) -> Result<VerifiedQuote> + Send + Sync;
+
+/// Policy governing which enclave versions may take part in a secret migration.
+#[derive(Clone, Debug, Default, PartialEq, Eq, cbor::Encode, cbor::Decode)]
+pub struct MigrationPolicy {
+    /// Quote policy used to verify attestations of the peer enclave.
+    pub quote_policy: QuotePolicy,
+    /// Enclave identities that the secrets may be migrated to.
+    #[cbor(optional)]
+    pub allowed_targets: Vec<EnclaveIdentity>,
+    /// Enclave identities that secrets may be migrated from.
+    #[cbor(optional)]
+    pub allowed_sources: Vec<EnclaveIdentity>,
+}
+
+impl MigrationPolicy {
+    /// Verify the attestation of a peer enclave and make sure that its identity is among the
+    /// allowed identities.
+    fn verify_peer(
+        &self,
+        verifier: &QuoteVerifier,
+        quote: &Quote,
+        rak: &PublicKey,
+        allowed: &[EnclaveIdentity],
+    ) -> Result<VerifiedQuote> {
+        let verified_quote = verifier(quote, &self.quote_policy)?;
+        Identity::verify_binding(&verified_quote, rak)?;
+        if !allowed.contains(&verified_quote.identity) {
+            return Err(MigrationError::IdentityNotAllowed.into());
+        }
+
+        Ok(verified_quote)
+    }
+}
+
+/// A secret sealed under the `MRENCLAVE` key policy.
+#[derive(Clone, Debug, Default, PartialEq, Eq, cbor::Encode, cbor::Decode)]
+pub struct SealedSecret {
+    /// Domain separation context used when sealing the secret.
+    pub context: Vec<u8>,
+    /// Sealed ciphertext.
+    pub ciphertext: Vec<u8>,
+}
+
+impl SealedSecret {
+    /// Seal a secret under the `MRENCLAVE` key policy of the current enclave.
+    pub fn seal(context: &[u8], data: &[u8]) -> Self {
+        Self {
+            context: context.to_vec(),
+            ciphertext: seal(Keypolicy::MRENCLAVE, context, data),
+        }
+    }
+
+    /// Unseal a secret previously sealed under the `MRENCLAVE` key policy of the current
+    /// enclave.
+    pub fn unseal(&self) -> Result<Vec<u8>> {
+        unseal(Keypolicy::MRENCLAVE, &self.context, &self.ciphertext)?.ok_or_else(|| {
+            MigrationError::SecretNotFound(String::from_utf8_lossy(&self.context).into_owned())
+                .into()
+        })
+    }
+}
+
+/// Request for migration of sealed secrets, created by the new enclave version.
+#[derive(Clone, Debug, cbor::Encode, cbor::Decode)]
+pub struct MigrationRequest {
+    /// Public RAK of the new enclave.
+    pub rak: PublicKey,
+    /// Quote binding the RAK to the new enclave.
+    pub quote: Quote,
+    /// Public REK of the new enclave that the secrets should be encrypted to.
+    pub rek: x25519::PublicKey,
+    /// RAK signature over the REK.
+    pub signature: Signature,
+}
+
+impl MigrationRequest {
+    /// Create a new migration request for the given runtime identity.
+    pub fn new(identity: &Identity) -> Result<Self> {
+        let quote = identity.quote().ok_or(MigrationError::QuoteNotAvailable)?;
+        let rek = identity.public_rek();
+        let signature = identity.sign(MIGRATION_REQUEST_SIGNATURE_CONTEXT, rek.0.as_bytes())?;
+
+        Ok(Self {
+            rak: identity.public_rak(),
+            quote: (*quote).clone(),
+            rek,
+            signature,
+        })
+    }
+
+    /// Verify the request against the given policy.
+    pub fn verify(&self, policy: &MigrationPolicy) -> Result<VerifiedQuote> {
+        self.verify_with(policy, &Quote::verify)
+    }
+
+    fn verify_with(
+        &self,
+        policy: &MigrationPolicy,
+        verifier: &QuoteVerifier,
+    ) -> Result<VerifiedQuote> {
+        let verified_quote =
+            policy.verify_peer(verifier, &self.quote, &self.rak, &policy.allowed_targets)?;
+        self.signature
+            .verify(
+                &self.rak,
+                MIGRATION_REQUEST_SIGNATURE_CONTEXT,
+                self.rek.0.as_bytes(),
+            )
+            .map_err(|_| MigrationError::InvalidSignature)?;
+
+        Ok(verified_quote)
+    }
+}
+
+/// Migrated secrets encrypted to the REK of the new enclave version.
+#[derive(Clone, Debug, Default, PartialEq, Eq, cbor::Encode, cbor::Decode)]
+pub struct EncryptedSecrets {
+    /// Ephemeral public key used for encryption.
+    pub public_key: x25519::PublicKey,
+    /// Encryption nonce.
+    pub nonce: Vec<u8>,
+    /// Encrypted CBOR-serialized list of secrets.
+    pub ciphertext: Vec<u8>,
+}
+
+impl EncryptedSecrets {
+    /// Encrypt the given secrets to the given public key.
+    fn encrypt(secrets: &[MigratedSecret], rek: &x25519::PublicKey) -> Result<Self> {
+        let (public_key, private_key) = deoxysii::generate_key_pair();
+        let mut nonce = [0u8; NONCE_SIZE];
+        SecureRng.fill(&mut nonce);
+        let ciphertext = deoxysii::box_seal(
+            &nonce,
+            cbor::to_vec(secrets.to_vec()),
+            MIGRATION_AD_CONTEXT.to_vec(),
+            &rek.0,
+            &private_key,
+        )?;
+
+        Ok(Self {
+            public_key: x25519::PublicKey(public_key),
+            nonce: nonce.to_vec(),
+            ciphertext,
+        })
+    }
+
+    /// Decrypt the secrets using the given opener.
+    fn decrypt(&self, opener: &dyn Opener) -> Result<Vec<MigratedSecret>> {
+        let nonce: [u8; NONCE_SIZE] = self
+            .nonce
+            .as_slice()
+            .try_into()
+            .map_err(|_| anyhow!("malformed nonce"))?;
+        let plaintext = opener.box_open(
+            &nonce,
+            self.ciphertext.clone(),
+            MIGRATION_AD_CONTEXT.to_vec(),
+            &self.public_key.0,
+        )?;
+
+        Ok(cbor::from_slice(&plaintext)?)
+    }
+}
+
+/// A plaintext secret being migrated.
+#[derive(Clone, Debug, Default, PartialEq, Eq, cbor::Encode, cbor::Decode)]
+struct MigratedSecret {
+    context: Vec<u8>,
+    data: Vec<u8>,
+}
+
+/// Response to a migration request, created by the old enclave version.
+#[derive(Clone, Debug, cbor::Encode, cbor::Decode)]
+pub struct MigrationResponse {
+    /// Public RAK of the old enclave.
+    pub rak: PublicKey,
+    /// Quote binding the RAK to the old enclave.
+    pub quote: Quote,
+    /// Encrypted secrets.
+    pub secrets: EncryptedSecrets,
+    /// RAK signature over the encrypted secrets.
+    pub signature: Signature,
+}
+
+impl MigrationResponse {
+    /// Verify the migration request and re-encrypt the designated secrets for the enclave that
+    /// created it.
+    ///
+    /// This should be called by the old enclave version.
+    pub fn new(
+        identity: &Identity,
+        policy: &MigrationPolicy,
+        request: &MigrationRequest,
+        secrets: &[SealedSecret],
+    ) -> Result<Self> {
+        Self::new_with(identity, policy, request, secrets, &Quote::verify)
+    }
+
+    fn new_with(
+        identity: &Identity,
+        policy: &MigrationPolicy,
+        request: &MigrationRequest,
+        secrets: &[SealedSecret],
+        verifier: &QuoteVerifier,
+    ) -> Result<Self> {
+        request.verify_with(policy, verifier)?;
+
+        let quote = identity.quote().ok_or(MigrationError::QuoteNotAvailable)?;
+        let secrets = secrets
+            .iter()
+            .map(|secret| {
+                Ok(MigratedSecret {
+                    context: secret.context.clone(),
+                    data: secret.unseal()?,
+                })
+            })
+            .collect::<Result<Vec<_>>>()?;
+        let secrets = EncryptedSecrets::encrypt(&secrets, &request.rek)?;
+        let signature = identity.sign(
+            MIGRATION_RESPONSE_SIGNATURE_CONTEXT,
+            &cbor::to_vec(secrets.clone()),
+        )?;
+
+        Ok(Self {
+            rak: identity.public_rak(),
+            quote: (*quote).clone(),
+            secrets,
+            signature,
+        })
+    }
+
+    /// Verify the response, decrypt the migrated secrets and re-seal them under the identity of
+    /// the current enclave.
+    ///
+    /// This should be called by the new enclave version.
+    pub fn reseal(
+        &self,
+        identity: &Identity,
+        policy: &MigrationPolicy,
+    ) -> Result<Vec<SealedSecret>> {
+        self.reseal_with(identity, policy, &Quote::verify)
+    }
+
+    fn reseal_with(
+        &self,
+        identity: &Identity,
+        policy: &MigrationPolicy,
+        verifier: &QuoteVerifier,
+    ) -> Result<Vec<SealedSecret>> {
+        policy.verify_peer(verifier, &self.quote, &self.rak, &policy.allowed_sources)?;
+        self.signature
+            .verify(
+                &self.rak,
+                MIGRATION_RESPONSE_SIGNATURE_CONTEXT,
+                &cbor::to_vec(self.secrets.clone()),
+            )
+            .map_err(|_| MigrationError::InvalidSignature)?;
+
+        let secrets = self.secrets.decrypt(identity)?;
+
+        Ok(secrets
+            .into_iter()
+            .map(|secret| SealedSecret::seal(&secret.context, &secret.data))
+            .collect())
+    }
+}
+
+/// Handler answering migration requests relayed by the host to the old enclave version.
+pub struct MigrationHandler {
+    identity: Arc<Identity>,
+    policy: MigrationPolicy,
+    secrets: Box<dyn Fn() -> Result<Vec<SealedSecret>> + Send + Sync>,
+    verifier: Box<QuoteVerifier>,
+}
+
+impl MigrationHandler {
+    /// Create a new handler migrating the secrets returned by the given function to the enclaves
+    /// allowed by the given policy.
+    pub fn new<F>(identity: Arc<Identity>, policy: MigrationPolicy, secrets: F) -> Self
+    where
+        F: Fn() -> Result<Vec<SealedSecret>> + Send + Sync + 'static,
+    {
+        Self {
+            identity,
+            policy,
+            secrets: Box::new(secrets),
+            verifier: Box::new(Quote::verify),
+        }
+    }
+
+    /// Verify the given migration request and answer it with the designated secrets.
+    pub fn handle(&self, request: &MigrationRequest) -> Result<MigrationResponse> {
+        let secrets = (self.secrets)()?;
+        MigrationResponse::new_with(
+            &self.identity,
+            &self.policy,
+            request,
+            &secrets,
+            self.verifier.as_ref(),
+        )
+    }
+
+    /// Local RPC method answering migration requests relayed by the host.
+    ///
+    /// Runtimes whose secrets may be migrated to newer versions should register it.
+    pub fn rpc_method(self) -> Method {
+        Method::new(
+            MethodDescriptor {
+                name: METHOD_MIGRATE.to_string(),
+                kind: RpcKind::LocalQuery,
+                allow_anonymous: false,
+                cacheable: false,
+            },
+            move |_ctx: &RpcContext, request: &MigrationRequest| -> Result<MigrationResponse> {
+                self.handle(request)
+            },
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::common::sgx::MrEnclave;
+
+    #[test]
+    fn test_seal_unseal() {
+        let secret = SealedSecret::seal(b"test context", b"secret");
+        assert_eq!(secret.unseal().unwrap(), b"secret".to_vec());
+
+        let mut corrupted = secret.clone();
+        corrupted.context = b"other context".to_vec();
+        assert!(corrupted.unseal().is_err());
+    }
+
+    #[test]
+    fn test_encrypt_decrypt() {
+        let identity = Identity::new();
+        let secrets = vec![
+            MigratedSecret {
+                context: b"a".to_vec(),
+                data: b"first secret".to_vec(),
+            },
+            MigratedSecret {
+                context: b"b".to_vec(),
+                data: b"second secret".to_vec(),
+            },
+        ];
+
+        let encrypted = EncryptedSecrets::encrypt(&secrets, &identity.public_rek()).unwrap();
+        let decrypted = encrypted.decrypt(&identity).unwrap();
+        assert_eq!(decrypted, secrets);
+
+        // Secrets encrypted to a different key must not be decryptable.
+        let other = x25519::PrivateKey::generate().public_key();
+        let encrypted = EncryptedSecrets::encrypt(&secrets, &other).unwrap();
+        assert!(encrypted.decrypt(&identity).is_err());
+    }
+
+    /// Create an identity with a mock quote for the given enclave.
+    fn attested_identity(mr_enclave: u8) -> (Arc<Identity>, Quote, VerifiedQuote) {
+        let identity = Arc::new(Identity::new());
+        let enclave = EnclaveIdentity::fortanix_test(MrEnclave::from([mr_enclave; 32].to_vec()));
+        let (quote, verified_quote) = identity.set_mock_quote(enclave);
+        (identity, quote, verified_quote)
+    }
+
+    #[test]
+    fn test_migration_round_trip() {
+        let (old, old_quote, old_verified) = attested_identity(1);
+        let (new, new_quote, new_verified) = attested_identity(2);
+        let (other, other_quote, other_verified) = attested_identity(3);
+        let quotes = vec![
+            (old_quote, old_verified.clone()),
+            (new_quote, new_verified.clone()),
+            (other_quote.clone(), other_verified),
+        ];
+        let verifier = move |quote: &Quote, _: &QuotePolicy| -> Result<VerifiedQuote> {
+            quotes
+                .iter()
+                .find(|(q, _)| q == quote)
+                .map(|(_, verified_quote)| verified_quote.clone())
+                .ok_or_else(|| anyhow!("invalid quote"))
+        };
+        let policy = MigrationPolicy {
+            allowed_targets: vec![new_verified.identity.clone()],
+            allowed_sources: vec![old_verified.identity.clone()],
+            ..Default::default()
+        };
+
+        let secret = SealedSecret::seal(b"test context", b"secret");
+        let mut handler =
+            MigrationHandler::new(old, policy.clone(), move || Ok(vec![secret.clone()]));
+        handler.verifier = Box::new(verifier.clone());
+
+        // The new enclave receives the secrets from the old one.
+        let request = MigrationRequest::new(&new).unwrap();
+        let response = handler.handle(&request).unwrap();
+        let resealed = response.reseal_with(&new, &policy, &verifier).unwrap();
+        assert_eq!(resealed.len(), 1);
+        assert_eq!(resealed[0].context, b"test context".to_vec());
+        assert_eq!(resealed[0].unseal().unwrap(), b"secret".to_vec());
+
+        // Requests from enclaves not allowed by the policy are rejected.
+        let request = MigrationRequest::new(&other).unwrap();
+        let err = handler.handle(&request).unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<MigrationError>(),
+            Some(MigrationError::IdentityNotAllowed)
+        ));
+
+        // Requests whose REK is not signed by the attested RAK are rejected.
+        let mut request = MigrationRequest::new(&new).unwrap();
+        request.rek = other.public_rek();
+        let err = handler.handle(&request).unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<MigrationError>(),
+            Some(MigrationError::InvalidSignature)
+        ));
+
+        // Requests whose quote doesn't bind the RAK are rejected.
+        let mut request = MigrationRequest::new(&new).unwrap();
+        request.rak = other.public_rak();
+        assert!(handler.handle(&request).is_err());
+
+        // Responses from enclaves not allowed by the policy are rejected.
+        let request = MigrationRequest::new(&new).unwrap();
+        let mut response = handler.handle(&request).unwrap();
+        response.rak = other.public_rak();
+        response.quote = other_quote;
+        let err = response.reseal_with(&new, &policy, &verifier).unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<MigrationError>(),
+            Some(MigrationError::IdentityNotAllowed)
+        ));
+    }
+}
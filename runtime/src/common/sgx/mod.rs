@@ -2,13 +2,16 @@
 
 pub mod egetkey;
 pub mod ias;
+pub mod migration;
 pub mod pcs;
 pub mod seal;
 
+use std::borrow::Cow;
+
 use anyhow::Result;
 use chrono::prelude::*;
 
-use crate::common::time::{insecure_posix_time, update_insecure_posix_time};
+use crate::common::time::{clock_skew_tolerance, insecure_posix_time, update_insecure_posix_time};
 
 /// Maximum age of a quote from the viewpoint of the enclave.
 pub const MAX_QUOTE_AGE: i64 = 24 * 60 * 60; // 24 hours
@@ -73,32 +76,88 @@ pub enum Quote {
 impl Quote {
     /// Verify the remote attestation quote.
     pub fn verify(&self, policy: &QuotePolicy) -> Result<VerifiedQuote> {
-        let mut verified_quote = match self {
-            Quote::Ias(avr) => ias::verify(avr, &policy.ias.clone().unwrap_or_default()),
+        self.verify_inner(policy, false)
+            .map(|(verified_quote, _)| verified_quote)
+    }
+
+    /// Verify the remote attestation quote in audit mode.
+    ///
+    /// Returns the verified quote together with descriptions of the policy violations which would
+    /// have caused verification to fail. Audit mode is only supported for PCS quotes.
+    pub fn verify_audit(&self, policy: &QuotePolicy) -> Result<(VerifiedQuote, Vec<String>)> {
+        self.verify_inner(policy, true)
+    }
+
+    fn verify_inner(
+        &self,
+        policy: &QuotePolicy,
+        audit: bool,
+    ) -> Result<(VerifiedQuote, Vec<String>)> {
+        let (mut verified_quote, violations) = match self {
+            Quote::Ias(avr) => (
+                ias::verify(avr, &policy.ias.clone().unwrap_or_default())?,
+                Vec::new(),
+            ),
             Quote::Pcs(qb) => {
                 let now = Utc.timestamp_opt(insecure_posix_time(), 0).unwrap();
-                Ok(qb.verify(&policy.pcs.clone().unwrap_or_default(), now)?)
+                let policy = policy.pcs.clone().unwrap_or_default();
+
+                // Use cached or pinned collateral where needed.
+                let collateral = pcs::CollateralCache::global();
+                let (qb, policy) = collateral.prepare(qb, &policy, now.timestamp())?;
+                let (verified_quote, violations) = if audit {
+                    let (verified_quote, violations) = qb.verify_audit(&policy, now)?;
+                    let violations: Vec<String> =
+                        violations.iter().map(ToString::to_string).collect();
+                    (verified_quote, violations)
+                } else {
+                    (qb.verify(&policy, now)?, Vec::new())
+                };
+
+                // Cache the collateral accompanying the quote, once verified without violations.
+                if let Cow::Borrowed(qb) = qb {
+                    if violations.is_empty() {
+                        collateral.insert(qb, now.timestamp())?;
+                    }
+                }
+
+                (verified_quote, violations)
             }
-        }?;
+        };
 
         // Force-ratchet the clock forward, to at least the time in the verified quote.
         update_insecure_posix_time(verified_quote.timestamp);
         verified_quote.timestamp = insecure_posix_time();
 
-        Ok(verified_quote)
+        Ok((verified_quote, violations))
     }
 
     /// Whether the quote should be considered fresh.
     pub fn is_fresh(&self, now: i64, ts: i64, policy: &QuotePolicy) -> bool {
         // Check general freshness requirement.
-        if (now - ts).abs() > MAX_QUOTE_AGE {
+        if (now - ts).abs() > MAX_QUOTE_AGE + clock_skew_tolerance() {
             return false;
         }
 
         // Check quote-specific expiration policy.
         match self {
             Quote::Ias(_) => true, // No additional checks for IAS quotes.
-            Quote::Pcs(_) => !policy.pcs.clone().unwrap_or_default().is_expired(now, ts),
+            Quote::Pcs(qb) => match pcs::CollateralCache::global().offline_expiration(qb) {
+                Some(expiration) => now <= expiration,
+                None => !policy.pcs.clone().unwrap_or_default().is_expired(now, ts),
+            },
+        }
+    }
+
+    /// Time after which the quote with timestamp `ts` is no longer considered fresh.
+    pub fn expiration(&self, ts: i64, policy: &QuotePolicy) -> i64 {
+        let expiration = ts + MAX_QUOTE_AGE + clock_skew_tolerance();
+        match self {
+            Quote::Ias(_) => expiration,
+            Quote::Pcs(qb) => match pcs::CollateralCache::global().offline_expiration(qb) {
+                Some(offline_expiration) => expiration.min(offline_expiration),
+                None => expiration.min(policy.pcs.clone().unwrap_or_default().expiration(ts)),
+            },
         }
     }
 }
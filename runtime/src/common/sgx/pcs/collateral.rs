@@ -0,0 +1,456 @@
+//! Caching of quote verification collateral.
+//!
+//! Quote bundles carry the collateral (TCB info, QE identity and the TCB signing certificate
+//! chain) required for their verification, which the host fetches from PCS. To avoid depending on
+//! fresh collateral accompanying every quote, verified collateral is cached by TEE type and FMSPC,
+//! so that quote bundles without collateral can be verified against cached collateral of the same
+//! platform. Collateral can also be pre-loaded via the host and pinned until an explicit expiry,
+//! e.g. in air-gapped deployments where PCS can't be reached. In offline mode, pinned collateral
+//! is preferred and accepted past the TCB validity period of the quote policy until it expires.
+//! As the expiry is chosen by the host, pinned collateral is never accepted for longer than the
+//! configured maximum age, counted from when it was issued.
+use std::{borrow::Cow, collections::HashMap, sync::Mutex, time::Duration};
+
+use chrono::prelude::*;
+use rustc_hex::ToHex;
+
+use super::{
+    policy::QuotePolicy,
+    quote::{Quote, QuoteBundle, TeeType},
+    tcb::{TCBBundle, TCBInfo, TCBInfoID},
+    Error,
+};
+
+lazy_static! {
+    static ref COLLATERAL_CACHE: CollateralCache =
+        CollateralCache::new(CollateralCacheConfig::default());
+}
+
+/// Collateral cache configuration.
+#[derive(Clone, Debug)]
+pub struct CollateralCacheConfig {
+    /// Time for which collateral is cached after it was last seen accompanying a verified quote.
+    pub ttl: Duration,
+    /// Maximum number of cached collateral bundles, excluding pinned ones.
+    pub max_entries: usize,
+    /// Whether offline verification mode is enabled.
+    ///
+    /// In offline mode, pinned collateral replaces the collateral accompanying quotes of the same
+    /// platform and is accepted past the TCB validity period of the quote policy, until the
+    /// collateral expires.
+    pub offline: bool,
+    /// Maximum age of pinned collateral. Collateral issued longer ago is rejected and pinned
+    /// collateral expires after this time at the latest.
+    pub max_pinned_age: Duration,
+}
+
+impl Default for CollateralCacheConfig {
+    fn default() -> Self {
+        Self {
+            ttl: Duration::from_secs(24 * 60 * 60),
+            max_entries: 64,
+            offline: false,
+            max_pinned_age: Duration::from_secs(90 * 24 * 60 * 60),
+        }
+    }
+}
+
+impl CollateralCacheConfig {
+    /// Maximum age of pinned collateral as a TCB validity period, in days.
+    fn pinned_validity_period(&self) -> u16 {
+        (self.max_pinned_age.as_secs() / (24 * 60 * 60))
+            .try_into()
+            .unwrap_or(u16::MAX)
+    }
+}
+
+/// Collateral bundle pre-loaded via the host.
+#[derive(Clone, Debug, Default, PartialEq, Eq, cbor::Encode, cbor::Decode)]
+pub struct CollateralBundle {
+    /// TCB bundle.
+    pub tcb: TCBBundle,
+
+    /// Time (UNIX timestamp) until which the collateral is pinned. In case it is `None`, the
+    /// collateral is cached as if it accompanied a verified quote.
+    ///
+    /// The time is capped at the configured maximum age of pinned collateral.
+    #[cbor(optional)]
+    pub pinned_until: Option<i64>,
+}
+
+/// Platform the collateral applies to.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+struct CollateralKey {
+    tee_type: TeeType,
+    /// Upper case hex-encoded FMSPC.
+    fmspc: String,
+}
+
+impl CollateralKey {
+    /// Key of the platform which generated the quote in the given bundle.
+    fn of(qb: &QuoteBundle) -> Result<Self, Error> {
+        let quote = Quote::parse(&qb.quote)?;
+        Ok(Self {
+            tee_type: quote.header().tee_type(),
+            fmspc: quote.fmspc()?.to_hex::<String>().to_uppercase(),
+        })
+    }
+}
+
+/// Collateral together with the time (UNIX timestamp) after which it expires.
+type Entry = (TCBBundle, i64);
+
+struct Inner {
+    config: CollateralCacheConfig,
+    cached: HashMap<CollateralKey, Entry>,
+    pinned: HashMap<CollateralKey, Entry>,
+}
+
+impl Inner {
+    /// Remove expired collateral.
+    fn evict(&mut self, now: i64) {
+        self.cached.retain(|_, (_, expires_at)| *expires_at > now);
+        self.pinned.retain(|_, (_, expires_at)| *expires_at > now);
+    }
+
+    fn cache(&mut self, key: CollateralKey, tcb: TCBBundle, now: i64) {
+        if self.config.max_entries == 0 {
+            return;
+        }
+        if !self.cached.contains_key(&key) && self.cached.len() >= self.config.max_entries {
+            // Evict the collateral expiring first.
+            let first = self
+                .cached
+                .iter()
+                .min_by_key(|(_, (_, expires_at))| *expires_at)
+                .map(|(key, _)| key.clone());
+            if let Some(first) = first {
+                self.cached.remove(&first);
+            }
+        }
+
+        let ttl: i64 = self.config.ttl.as_secs().try_into().unwrap_or(i64::MAX);
+        self.cached.insert(key, (tcb, now.saturating_add(ttl)));
+    }
+}
+
+/// Cache of quote verification collateral.
+pub struct CollateralCache {
+    inner: Mutex<Inner>,
+}
+
+impl CollateralCache {
+    /// Create a new empty cache with the given configuration.
+    pub fn new(config: CollateralCacheConfig) -> Self {
+        Self {
+            inner: Mutex::new(Inner {
+                config,
+                cached: HashMap::new(),
+                pinned: HashMap::new(),
+            }),
+        }
+    }
+
+    /// Global collateral cache instance.
+    pub fn global() -> &'static CollateralCache {
+        &COLLATERAL_CACHE
+    }
+
+    /// Replace the cache configuration.
+    pub fn configure(&self, config: CollateralCacheConfig) {
+        self.inner.lock().unwrap().config = config;
+    }
+
+    /// Cache the collateral accompanying the given quote bundle.
+    ///
+    /// The quote bundle must have been verified using its own collateral.
+    pub fn insert(&self, qb: &QuoteBundle, now: i64) -> Result<(), Error> {
+        let key = CollateralKey::of(qb)?;
+
+        let mut inner = self.inner.lock().unwrap();
+        inner.evict(now);
+        inner.cache(key, qb.tcb.clone(), now);
+        Ok(())
+    }
+
+    /// Load the given collateral bundle, pre-loaded via the host.
+    ///
+    /// The signatures of the collateral are verified before it is loaded. Collateral which is
+    /// already expired is rejected, as is collateral to be pinned which was issued longer ago
+    /// than the configured maximum age.
+    pub fn load(&self, bundle: CollateralBundle, now: i64) -> Result<(), Error> {
+        let mut inner = self.inner.lock().unwrap();
+        // The age of cached collateral is checked when it is used.
+        let validity_period = match bundle.pinned_until {
+            Some(_) => inner.config.pinned_validity_period(),
+            None => u16::MAX,
+        };
+        let key = verify_collateral(&bundle.tcb, now, validity_period)?;
+
+        inner.evict(now);
+        match bundle.pinned_until {
+            Some(pinned_until) if pinned_until <= now => return Err(Error::CollateralExpired),
+            Some(pinned_until) => {
+                let max_age: i64 = inner
+                    .config
+                    .max_pinned_age
+                    .as_secs()
+                    .try_into()
+                    .unwrap_or(i64::MAX);
+                let pinned_until = pinned_until.min(now.saturating_add(max_age));
+                inner.pinned.insert(key, (bundle.tcb, pinned_until));
+            }
+            None => inner.cache(key, bundle.tcb, now),
+        }
+        Ok(())
+    }
+
+    /// Prepare the given quote bundle for verification against the given policy.
+    ///
+    /// Quote bundles without collateral are completed with cached (or pinned) collateral of the
+    /// same platform. In offline mode, pinned collateral of the same platform replaces the
+    /// collateral of the bundle and the TCB validity period of the policy is extended to the
+    /// maximum age of pinned collateral, as pinned collateral is only available until it expires.
+    ///
+    /// Returns the quote bundle and the policy to verify it against. The quote bundle is borrowed
+    /// iff its own collateral is to be used.
+    pub fn prepare<'a>(
+        &self,
+        qb: &'a QuoteBundle,
+        policy: &'a QuotePolicy,
+        now: i64,
+    ) -> Result<(Cow<'a, QuoteBundle>, Cow<'a, QuotePolicy>), Error> {
+        let has_collateral = !qb.tcb.certificates.is_empty();
+        let mut inner = self.inner.lock().unwrap();
+        if has_collateral && !inner.config.offline {
+            return Ok((Cow::Borrowed(qb), Cow::Borrowed(policy)));
+        }
+
+        let key = CollateralKey::of(qb)?;
+        inner.evict(now);
+        let with_collateral = |tcb: &TCBBundle| QuoteBundle {
+            quote: qb.quote.clone(),
+            tcb: tcb.clone(),
+        };
+
+        if inner.config.offline {
+            if let Some((tcb, _)) = inner.pinned.get(&key) {
+                let policy = QuotePolicy {
+                    tcb_validity_period: policy
+                        .tcb_validity_period
+                        .max(inner.config.pinned_validity_period()),
+                    ..policy.clone()
+                };
+                return Ok((Cow::Owned(with_collateral(tcb)), Cow::Owned(policy)));
+            }
+        }
+        if has_collateral {
+            return Ok((Cow::Borrowed(qb), Cow::Borrowed(policy)));
+        }
+
+        match inner.cached.get(&key).or_else(|| inner.pinned.get(&key)) {
+            Some((tcb, _)) => Ok((Cow::Owned(with_collateral(tcb)), Cow::Borrowed(policy))),
+            None => Err(Error::CollateralNotAvailable),
+        }
+    }
+
+    /// Time (UNIX timestamp) after which the pinned collateral used to verify the given quote
+    /// bundle in offline mode expires.
+    ///
+    /// Returns `None` in case offline mode is disabled or no collateral is pinned for the platform
+    /// which generated the quote.
+    pub fn offline_expiration(&self, qb: &QuoteBundle) -> Option<i64> {
+        let inner = self.inner.lock().unwrap();
+        if !inner.config.offline {
+            return None;
+        }
+        let key = CollateralKey::of(qb).ok()?;
+        inner.pinned.get(&key).map(|(_, expires_at)| *expires_at)
+    }
+}
+
+/// Verify the signatures of the given collateral, issued within the given TCB validity period,
+/// and return the platform it applies to.
+fn verify_collateral(
+    tcb: &TCBBundle,
+    now: i64,
+    tcb_validity_period: u16,
+) -> Result<CollateralKey, Error> {
+    let ts = Utc
+        .timestamp_opt(now, 0)
+        .single()
+        .ok_or(Error::CollateralExpired)?;
+
+    // Determine the TEE type before opening the collateral, as it is validated against it.
+    let tcb_info: TCBInfo = serde_json::from_str(tcb.tcb_info.tcb_info.get())
+        .map_err(|err| Error::TCBParseError(err.into()))?;
+    let tee_type = match tcb_info.id {
+        TCBInfoID::SGX => TeeType::SGX,
+        TCBInfoID::TDX => TeeType::TDX,
+        _ => {
+            return Err(Error::TCBParseError(anyhow::anyhow!(
+                "unexpected TCB info identifier"
+            )))
+        }
+    };
+
+    let policy = QuotePolicy {
+        tcb_validity_period,
+        ..Default::default()
+    };
+    let mut tcb_cert = tcb.verify_certificates(ts)?;
+    tcb.qe_identity
+        .open(tee_type, ts, &policy, tcb_cert.public_key_mut())?;
+    let tcb_info = tcb
+        .tcb_info
+        .open(tee_type, ts, &policy, tcb_cert.public_key_mut())?;
+
+    Ok(CollateralKey {
+        tee_type,
+        fmspc: tcb_info.fmspc.to_uppercase(),
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const RAW_QUOTE: &[u8] =
+        include_bytes!("../../../../testdata/quote_v3_ecdsa_p256_pck_chain.bin");
+    const RAW_TCB_INFO: &[u8] =
+        include_bytes!("../../../../testdata/tcb_info_v3_fmspc_00606A000000.json");
+    const RAW_CERTS: &[u8] =
+        include_bytes!("../../../../testdata/tcb_info_v3_fmspc_00606A000000_certs.pem");
+    const RAW_QE_IDENTITY: &[u8] = include_bytes!("../../../../testdata/qe_identity_v2.json");
+    const NOW: i64 = 1671497404;
+
+    fn bundles() -> (QuoteBundle, QuoteBundle) {
+        let qb = QuoteBundle {
+            quote: RAW_QUOTE.to_owned(),
+            tcb: TCBBundle {
+                tcb_info: serde_json::from_slice(RAW_TCB_INFO).unwrap(),
+                qe_identity: serde_json::from_slice(RAW_QE_IDENTITY).unwrap(),
+                certificates: RAW_CERTS.to_owned(),
+            },
+        };
+        let bare = QuoteBundle {
+            quote: RAW_QUOTE.to_owned(),
+            ..Default::default()
+        };
+        (qb, bare)
+    }
+
+    fn ts(now: i64) -> DateTime<Utc> {
+        Utc.timestamp_opt(now, 0).unwrap()
+    }
+
+    #[test]
+    fn test_collateral_cache() {
+        let (qb, bare) = bundles();
+        let policy = QuotePolicy::default();
+        let cache = CollateralCache::new(CollateralCacheConfig::default());
+
+        // Quote bundles without collateral can't be verified until collateral is cached.
+        assert!(matches!(
+            cache.prepare(&bare, &policy, NOW),
+            Err(Error::CollateralNotAvailable)
+        ));
+        let (prepared, _) = cache.prepare(&qb, &policy, NOW).unwrap();
+        assert!(matches!(prepared, Cow::Borrowed(_)));
+        prepared.verify(&policy, ts(NOW)).unwrap();
+        cache.insert(&qb, NOW).unwrap();
+
+        let (prepared, _) = cache.prepare(&bare, &policy, NOW + 60).unwrap();
+        assert_eq!(prepared.tcb, qb.tcb);
+        prepared.verify(&policy, ts(NOW + 60)).unwrap();
+
+        // Cached collateral expires.
+        let expired = NOW + CollateralCacheConfig::default().ttl.as_secs() as i64;
+        assert!(matches!(
+            cache.prepare(&bare, &policy, expired),
+            Err(Error::CollateralNotAvailable)
+        ));
+    }
+
+    #[test]
+    fn test_collateral_cache_offline() {
+        let (qb, bare) = bundles();
+        let policy = QuotePolicy::default();
+        let cache = CollateralCache::new(CollateralCacheConfig {
+            offline: true,
+            ..Default::default()
+        });
+
+        // Expired collateral is rejected.
+        let result = cache.load(
+            CollateralBundle {
+                tcb: qb.tcb.clone(),
+                pinned_until: Some(NOW),
+            },
+            NOW,
+        );
+        assert!(matches!(result, Err(Error::CollateralExpired)));
+
+        // Tampered collateral is rejected.
+        let mut tampered = qb.tcb.clone();
+        tampered.tcb_info.signature = "00".repeat(64);
+        let result = cache.load(
+            CollateralBundle {
+                tcb: tampered,
+                pinned_until: None,
+            },
+            NOW,
+        );
+        assert!(matches!(result, Err(Error::TCBVerificationFailed)));
+
+        // Pinned collateral is accepted past the TCB validity period until it expires.
+        let later = NOW + 60 * 24 * 60 * 60;
+        let pinned_until = NOW + 90 * 24 * 60 * 60;
+        cache
+            .load(
+                CollateralBundle {
+                    tcb: qb.tcb.clone(),
+                    pinned_until: Some(pinned_until),
+                },
+                NOW,
+            )
+            .unwrap();
+        assert_eq!(cache.offline_expiration(&bare), Some(pinned_until));
+        assert!(matches!(
+            qb.verify(&policy, ts(later)),
+            Err(Error::TCBExpired)
+        ));
+        for qb in [&qb, &bare] {
+            let (prepared, prepared_policy) = cache.prepare(qb, &policy, later).unwrap();
+            assert!(matches!(prepared, Cow::Owned(_)));
+            prepared.verify(&prepared_policy, ts(later)).unwrap();
+        }
+        assert!(matches!(
+            cache.prepare(&bare, &policy, pinned_until),
+            Err(Error::CollateralNotAvailable)
+        ));
+
+        // The expiry of pinned collateral is capped at the maximum age.
+        let max_age = CollateralCacheConfig::default().max_pinned_age.as_secs() as i64;
+        cache
+            .load(
+                CollateralBundle {
+                    tcb: qb.tcb.clone(),
+                    pinned_until: Some(NOW + 10 * max_age),
+                },
+                NOW,
+            )
+            .unwrap();
+        assert_eq!(cache.offline_expiration(&bare), Some(NOW + max_age));
+
+        // Collateral issued longer ago than the maximum age can't be pinned.
+        let result = cache.load(
+            CollateralBundle {
+                tcb: qb.tcb.clone(),
+                pinned_until: Some(NOW + 2 * max_age),
+            },
+            NOW + max_age + 24 * 60 * 60,
+        );
+        assert!(matches!(result, Err(Error::TCBExpired)));
+    }
+}
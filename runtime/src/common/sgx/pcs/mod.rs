@@ -1,6 +1,7 @@
 //! Intel Provisioning Certification Services (PCS) quote handling.
 
 mod certificates;
+mod collateral;
 mod constants;
 mod policy;
 mod quote;
@@ -55,12 +56,21 @@ pub enum Error {
     TeeTypeNotAllowed,
     #[error("TDX module not allowed by policy")]
     TdxModuleNotAllowed,
+    #[error("TD measurement not allowed by policy")]
+    TdxMrTdNotAllowed,
+    #[error("TEE TCB SVN lower than allowed by policy")]
+    TdxTcbSvnTooLow,
+    #[error("collateral not available")]
+    CollateralNotAvailable,
+    #[error("collateral is expired")]
+    CollateralExpired,
     #[error("PCS quotes are disabled by policy")]
     Disabled,
     #[error(transparent)]
     Other(#[from] anyhow::Error),
 }
 
+pub use collateral::{CollateralBundle, CollateralCache, CollateralCacheConfig};
 pub use policy::{QuotePolicy, TdxModulePolicy, TdxQuotePolicy};
 pub use quote::{Quote, QuoteBundle};
 pub use report::{td_enclave_identity, TdAttributes, TdReport};
@@ -139,6 +149,19 @@ mod tests {
             "63d522d975f7de879a8f3368b4f32dd1e8db635f5a24b651ce8ff81705028813".into()
         );
 
+        // Ensure expired collateral is only reported in audit mode.
+        let later = now + chrono::Duration::try_days(60).unwrap();
+        let result = qb.verify(&policy, later);
+        assert!(matches!(result, Err(Error::TCBExpired)));
+        let (verified_quote, violations) = qb.verify_audit(&policy, later).unwrap();
+        assert_eq!(
+            verified_quote.identity.mr_enclave,
+            "63d522d975f7de879a8f3368b4f32dd1e8db635f5a24b651ce8ff81705028813".into()
+        );
+        assert!(matches!(violations[..], [Error::TCBExpired]));
+        let (_, violations) = qb.verify_audit(&policy, now).unwrap();
+        assert!(violations.is_empty());
+
         // Ensure TDX quote verification fails in case it is not allowed.
         let policy = QuotePolicy {
             tdx: None,
@@ -155,12 +178,52 @@ mod tests {
                     mr_seam: None,
                     mr_signer_seam: [1; 48],
                 }],
+                ..Default::default()
             }),
             ..Default::default()
         };
 
         let result = qb.verify(&policy, now);
         assert!(matches!(result, Err(Error::TdxModuleNotAllowed)));
+
+        // Ensure TDX quote verification fails in case the given TD measurement is not allowed.
+        let policy = QuotePolicy {
+            tdx: Some(TdxQuotePolicy {
+                allowed_mr_td: vec![[1; 48]],
+                ..Default::default()
+            }),
+            ..Default::default()
+        };
+
+        let result = qb.verify(&policy, now);
+        assert!(matches!(result, Err(Error::TdxMrTdNotAllowed)));
+
+        let quote = Quote::parse(RAW_QUOTE).unwrap();
+        let report = match quote.report_body() {
+            quote::ReportBody::Tdx(report) => report,
+            _ => panic!("expected a TD report"),
+        };
+        let policy = QuotePolicy {
+            tdx: Some(TdxQuotePolicy {
+                allowed_mr_td: vec![[1; 48], report.mr_td],
+                min_tee_tcb_svn: Some(report.tee_tcb_svn),
+                ..Default::default()
+            }),
+            ..Default::default()
+        };
+        qb.verify(&policy, now).unwrap();
+
+        // Ensure TDX quote verification fails in case the TEE TCB SVN is too low.
+        let policy = QuotePolicy {
+            tdx: Some(TdxQuotePolicy {
+                min_tee_tcb_svn: Some([u8::MAX; 16]),
+                ..Default::default()
+            }),
+            ..Default::default()
+        };
+
+        let result = qb.verify(&policy, now);
+        assert!(matches!(result, Err(Error::TdxTcbSvnTooLow)));
     }
 
     #[test]
@@ -1,5 +1,6 @@
 //! Quote policy.
 use super::{constants::*, report::TdReport, Error};
+use crate::common::time::clock_skew_tolerance;
 
 /// Quote validity policy.
 #[derive(Clone, Debug, PartialEq, Eq, cbor::Encode, cbor::Decode)]
@@ -45,9 +46,18 @@ impl QuotePolicy {
         }
 
         now.checked_sub(ts)
-            .map(|d| d > 60 * 60 * 24 * (self.tcb_validity_period as i64))
+            .map(|d| d > 60 * 60 * 24 * (self.tcb_validity_period as i64) + clock_skew_tolerance())
             .expect("quote timestamp is in the future") // This should never happen.
     }
+
+    /// Time after which the quote with timestamp `ts` is expired.
+    pub fn expiration(&self, ts: i64) -> i64 {
+        if self.disabled {
+            return ts;
+        }
+
+        ts + 60 * 60 * 24 * (self.tcb_validity_period as i64) + clock_skew_tolerance()
+    }
 }
 
 /// TDX-specific quote policy.
@@ -55,12 +65,46 @@ impl QuotePolicy {
 pub struct TdxQuotePolicy {
     /// Allowed TDX modules. Empty to allow ANY Intel-signed module.
     pub allowed_tdx_modules: Vec<TdxModulePolicy>,
+
+    /// Allowed measurements of the initial contents of the TD (MRTD). Empty to allow ANY
+    /// measurement.
+    #[cbor(optional)]
+    pub allowed_mr_td: Vec<[u8; 48]>,
+
+    /// Optional minimum TEE TCB SVN, compared component-wise. In case it is `None`, any TEE TCB
+    /// SVN accepted by the TCB evaluation is allowed.
+    #[cbor(optional)]
+    pub min_tee_tcb_svn: Option<[u8; 16]>,
 }
 
 impl TdxQuotePolicy {
     /// Verify whether the TDX policy is satisfied for the given report.
     pub fn verify(&self, report: &TdReport) -> Result<(), Error> {
         self.verify_tdx_module(report)?;
+        self.verify_mr_td(report)?;
+        self.verify_tee_tcb_svn(report)?;
+        Ok(())
+    }
+
+    fn verify_mr_td(&self, report: &TdReport) -> Result<(), Error> {
+        if self.allowed_mr_td.is_empty() || self.allowed_mr_td.contains(&report.mr_td) {
+            return Ok(());
+        }
+        Err(Error::TdxMrTdNotAllowed)
+    }
+
+    fn verify_tee_tcb_svn(&self, report: &TdReport) -> Result<(), Error> {
+        let min_tee_tcb_svn = match self.min_tee_tcb_svn {
+            Some(min_tee_tcb_svn) => min_tee_tcb_svn,
+            None => return Ok(()),
+        };
+
+        // All components must be at least the minimum.
+        for (svn, min_svn) in report.tee_tcb_svn.iter().zip(min_tee_tcb_svn.iter()) {
+            if svn < min_svn {
+                return Err(Error::TdxTcbSvnTooLow);
+            }
+        }
         Ok(())
     }
 
@@ -37,6 +37,41 @@ pub struct QuoteBundle {
 impl QuoteBundle {
     /// Verify the quote bundle.
     pub fn verify(&self, policy: &QuotePolicy, ts: DateTime<Utc>) -> Result<VerifiedQuote, Error> {
+        self.verify_inner(policy, ts, None)
+    }
+
+    /// Verify the quote bundle in audit mode.
+    ///
+    /// Expired TCB collateral, an out of date TCB level and debug (or production) enclaves where
+    /// they are not allowed are reported as policy violations instead of failing verification.
+    /// All other failures are still fatal.
+    pub fn verify_audit(
+        &self,
+        policy: &QuotePolicy,
+        ts: DateTime<Utc>,
+    ) -> Result<(VerifiedQuote, Vec<Error>), Error> {
+        let mut violations = Vec::new();
+        let verified_quote = self.verify_inner(policy, ts, Some(&mut violations))?;
+        Ok((verified_quote, violations))
+    }
+
+    fn verify_inner(
+        &self,
+        policy: &QuotePolicy,
+        ts: DateTime<Utc>,
+        mut violations: Option<&mut Vec<Error>>,
+    ) -> Result<VerifiedQuote, Error> {
+        // Record the violation in audit mode, fail otherwise.
+        let mut violation = |err: Error| -> Result<(), Error> {
+            match violations.as_mut() {
+                Some(violations) => {
+                    violations.push(err);
+                    Ok(())
+                }
+                None => Err(err),
+            }
+        };
+
         if policy.disabled {
             return Err(Error::Disabled);
         }
@@ -64,14 +99,29 @@ impl QuoteBundle {
 
         // Verify TCB bundle and get TCB info and QE identity.
         let mut tcb_cert = self.tcb.verify_certificates(ts)?;
-        let qe_identity =
-            self.tcb
-                .qe_identity
-                .open(tee_type, ts, policy, tcb_cert.public_key_mut())?;
-        let tcb_info = self
-            .tcb
-            .tcb_info
-            .open(tee_type, ts, policy, tcb_cert.public_key_mut())?;
+        let mut open = |policy: &QuotePolicy| -> Result<(QEIdentity, TCBInfo), Error> {
+            let qe_identity =
+                self.tcb
+                    .qe_identity
+                    .open(tee_type, ts, policy, tcb_cert.public_key_mut())?;
+            let tcb_info =
+                self.tcb
+                    .tcb_info
+                    .open(tee_type, ts, policy, tcb_cert.public_key_mut())?;
+            Ok((qe_identity, tcb_info))
+        };
+        let (qe_identity, tcb_info) = match open(policy) {
+            Err(Error::TCBExpired) => {
+                violation(Error::TCBExpired)?;
+
+                // Accept collateral of any age.
+                open(&QuotePolicy {
+                    tcb_validity_period: u16::MAX,
+                    ..policy.clone()
+                })?
+            }
+            result => result?,
+        };
 
         // We use the TCB info issue date as the timestamp.
         let timestamp = NaiveDateTime::parse_from_str(&tcb_info.issue_date, PCS_TS_FMT)
@@ -90,9 +140,7 @@ impl QuoteBundle {
                 | TCBStatus::ConfigurationNeeded
                 | TCBStatus::OutOfDateConfigurationNeeded
                     if unsafe_lax_quote_verification => {}
-                _ => {
-                    return Err(Error::TCBOutOfDate);
-                }
+                _ => violation(Error::TCBOutOfDate)?,
             }
         }
 
@@ -101,9 +149,9 @@ impl QuoteBundle {
         let is_debug = quote.report_body().is_debug();
         let allow_debug = option_env!("OASIS_UNSAFE_ALLOW_DEBUG_ENCLAVES").is_some();
         if is_debug && !allow_debug {
-            return Err(Error::DebugEnclave);
+            violation(Error::DebugEnclave)?;
         } else if !is_debug && allow_debug {
-            return Err(Error::ProductionEnclave);
+            violation(Error::ProductionEnclave)?;
         }
 
         // Verify report against TDX policy.
@@ -249,6 +297,23 @@ impl<'a> Quote<'a> {
         &self.report_body
     }
 
+    /// FMSPC of the platform which generated the quote, as contained in its PCK certificate.
+    ///
+    /// The PCK certificate chain is not verified.
+    pub fn fmspc(&self) -> Result<Vec<u8>, Error> {
+        let certs = self
+            .signature
+            .qe
+            .certification_data::<CertificationDataPckCertificateChain>()?
+            .certs;
+        let raw_cert = certs.first().ok_or(Error::UnexpectedCertificateChain)?;
+        let raw_cert = CString::new(raw_cert.as_ref()).map_err(|_| Error::MalformedPCK)?;
+        let pck_cert =
+            Certificate::from_pem(raw_cert.as_bytes_with_nul()).map_err(|_| Error::MalformedPCK)?;
+        let (fmspc, _, _) = pck_tcb_parameters(&pck_cert)?;
+        Ok(fmspc)
+    }
+
     /// Verify quote.
     pub fn verify(&self, tcb_info: TCBInfo, qe_identity: QEIdentity) -> Result<TCBLevel, Error> {
         let tdx_comp_svn = self.report_body.tdx_comp_svn();
@@ -847,70 +912,12 @@ impl QuoteSignatureEcdsaP256Verifier for QeEcdsaP256Verifier {
 
         // Extract TCB parameters from the PCK certificate.
         let mut pck_cert = cert_chain.pop_front().unwrap();
-
-        let sgx_extensions = pck_cert
-            .extensions()
-            .map_err(|_| Error::MalformedPCK)?
-            .into_iter()
-            .find(|ext| ext.oid.as_ref() == PCK_SGX_EXTENSIONS_OID)
-            .ok_or(Error::TCBVerificationFailed)?;
-        let mut fmspc: Option<Vec<u8>> = None;
-        let mut tcb_comp_svn: Option<[u32; 16]> = None;
-        let mut pcesvn: Option<u32> = None;
-        yasna::parse_der(&sgx_extensions.value, |reader| {
-            reader.read_sequence_of(|reader| {
-                reader.read_sequence(|reader| {
-                    match reader.next().read_oid()?.as_ref() {
-                        PCK_SGX_EXTENSIONS_FMSPC_OID => {
-                            // FMSPC
-                            let raw_fmspc = reader.next().read_bytes()?;
-                            if raw_fmspc.len() != 6 {
-                                return Err(yasna::ASN1Error::new(yasna::ASN1ErrorKind::Invalid));
-                            }
-                            fmspc = Some(raw_fmspc);
-                        }
-                        PCK_SGX_EXTENSIONS_TCB_OID => {
-                            // TCB
-                            reader.next().read_sequence_of(|reader| {
-                                reader.read_sequence(|reader| {
-                                    let comp_id =
-                                        *reader.next().read_oid()?.as_ref().last().unwrap();
-                                    if (1..=16).contains(&comp_id) {
-                                        // TCB Component SVNs
-                                        tcb_comp_svn.get_or_insert([0; 16])
-                                            [(comp_id - 1) as usize] = reader.next().read_u32()?;
-                                    } else if comp_id == 17 {
-                                        // PCESVN
-                                        pcesvn = Some(reader.next().read_u32()?);
-                                    } else if comp_id == 18 {
-                                        // CPUSVN
-                                        reader.next().read_bytes()?;
-                                    }
-                                    Ok(())
-                                })
-                            })?;
-                        }
-                        _ => {
-                            reader.next().read_der()?;
-                        }
-                    }
-
-                    Ok(())
-                })
-            })
-        })
-        .map_err(|_| Error::MalformedPCK)?;
-        if fmspc.is_none() || tcb_comp_svn.is_none() || pcesvn.is_none() {
-            return Err(Error::MalformedPCK);
-        }
+        let (fmspc, tcb_comp_svn, pcesvn) = pck_tcb_parameters(&pck_cert)?;
 
         // Verify TCB level.
-        let tcb_level = self.tcb_info.verify(
-            &fmspc.unwrap(),
-            &tcb_comp_svn.unwrap(),
-            self.tdx_comp_svn.as_ref(),
-            pcesvn.unwrap(),
-        )?;
+        let tcb_level =
+            self.tcb_info
+                .verify(&fmspc, &tcb_comp_svn, self.tdx_comp_svn.as_ref(), pcesvn)?;
         self.tcb_level = Some(tcb_level);
 
         // Extract PCK public key.
@@ -933,3 +940,62 @@ impl QuoteSignatureEcdsaP256Verifier for QeEcdsaP256Verifier {
         Ok(())
     }
 }
+
+/// Extract the FMSPC, the TCB component SVNs and the PCESVN from the given PCK certificate.
+fn pck_tcb_parameters(pck_cert: &Certificate) -> Result<(Vec<u8>, [u32; 16], u32), Error> {
+    let sgx_extensions = pck_cert
+        .extensions()
+        .map_err(|_| Error::MalformedPCK)?
+        .into_iter()
+        .find(|ext| ext.oid.as_ref() == PCK_SGX_EXTENSIONS_OID)
+        .ok_or(Error::TCBVerificationFailed)?;
+    let mut fmspc: Option<Vec<u8>> = None;
+    let mut tcb_comp_svn: Option<[u32; 16]> = None;
+    let mut pcesvn: Option<u32> = None;
+    yasna::parse_der(&sgx_extensions.value, |reader| {
+        reader.read_sequence_of(|reader| {
+            reader.read_sequence(|reader| {
+                match reader.next().read_oid()?.as_ref() {
+                    PCK_SGX_EXTENSIONS_FMSPC_OID => {
+                        // FMSPC
+                        let raw_fmspc = reader.next().read_bytes()?;
+                        if raw_fmspc.len() != 6 {
+                            return Err(yasna::ASN1Error::new(yasna::ASN1ErrorKind::Invalid));
+                        }
+                        fmspc = Some(raw_fmspc);
+                    }
+                    PCK_SGX_EXTENSIONS_TCB_OID => {
+                        // TCB
+                        reader.next().read_sequence_of(|reader| {
+                            reader.read_sequence(|reader| {
+                                let comp_id = *reader.next().read_oid()?.as_ref().last().unwrap();
+                                if (1..=16).contains(&comp_id) {
+                                    // TCB Component SVNs
+                                    tcb_comp_svn.get_or_insert([0; 16])[(comp_id - 1) as usize] =
+                                        reader.next().read_u32()?;
+                                } else if comp_id == 17 {
+                                    // PCESVN
+                                    pcesvn = Some(reader.next().read_u32()?);
+                                } else if comp_id == 18 {
+                                    // CPUSVN
+                                    reader.next().read_bytes()?;
+                                }
+                                Ok(())
+                            })
+                        })?;
+                    }
+                    _ => {
+                        reader.next().read_der()?;
+                    }
+                }
+
+                Ok(())
+            })
+        })
+    })
+    .map_err(|_| Error::MalformedPCK)?;
+    match (fmspc, tcb_comp_svn, pcesvn) {
+        (Some(fmspc), Some(tcb_comp_svn), Some(pcesvn)) => Ok((fmspc, tcb_comp_svn, pcesvn)),
+        _ => Err(Error::MalformedPCK),
+    }
+}
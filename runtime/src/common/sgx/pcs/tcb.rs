@@ -10,6 +10,7 @@ use sgx_isa::Report;
 use super::{
     certificates::PCS_TRUST_ROOT, constants::*, policy::QuotePolicy, quote::TeeType, Error,
 };
+use crate::common::time::clock_skew_tolerance;
 
 /// The TCB bundle contains all the required components to verify a quote's TCB.
 #[derive(Clone, Debug, Default, PartialEq, Eq, cbor::Encode, cbor::Decode)]
@@ -114,6 +115,27 @@ fn open_signed_tcb<'a, T: serde::Deserialize<'a>>(
     serde_json::from_str(data).map_err(|err| Error::TCBParseError(err.into()))
 }
 
+/// Validate that collateral issued at the given date is valid at the given time, allowing for the
+/// configured clock skew.
+fn validate_issue_date(
+    issue_date: DateTime<Utc>,
+    ts: DateTime<Utc>,
+    policy: &QuotePolicy,
+) -> Result<(), Error> {
+    let skew = Duration::try_seconds(clock_skew_tolerance()).unwrap_or_default();
+    if issue_date > ts + skew {
+        return Err(Error::TCBExpired);
+    }
+    if ts - issue_date
+        > Duration::try_days(policy.tcb_validity_period.into())
+            .unwrap_or(DEFAULT_TCB_VALIDITY_PERIOD)
+            + skew
+    {
+        return Err(Error::TCBExpired);
+    }
+    Ok(())
+}
+
 impl SignedTCBInfo {
     pub fn open(
         &self,
@@ -207,15 +229,7 @@ impl TCBInfo {
         let _next_update = NaiveDateTime::parse_from_str(&self.next_update, PCS_TS_FMT)
             .map_err(|err| Error::TCBParseError(err.into()))?
             .and_utc();
-        if issue_date > ts {
-            return Err(Error::TCBExpired);
-        }
-        if ts - issue_date
-            > Duration::try_days(policy.tcb_validity_period.into())
-                .unwrap_or(DEFAULT_TCB_VALIDITY_PERIOD)
-        {
-            return Err(Error::TCBExpired);
-        }
+        validate_issue_date(issue_date, ts, policy)?;
 
         if self.tcb_evaluation_data_number < policy.min_tcb_evaluation_data_number {
             return Err(Error::TCBEvaluationDataNumberInvalid);
@@ -550,15 +564,7 @@ impl QEIdentity {
         let _next_update = NaiveDateTime::parse_from_str(&self.next_update, PCS_TS_FMT)
             .map_err(|err| Error::TCBParseError(err.into()))?
             .and_utc();
-        if issue_date > ts {
-            return Err(Error::TCBExpired);
-        }
-        if ts - issue_date
-            > Duration::try_days(policy.tcb_validity_period.into())
-                .unwrap_or(DEFAULT_TCB_VALIDITY_PERIOD)
-        {
-            return Err(Error::TCBExpired);
-        }
+        validate_issue_date(issue_date, ts, policy)?;
 
         if self.tcb_evaluation_data_number < policy.min_tcb_evaluation_data_number {
             return Err(Error::TCBEvaluationDataNumberInvalid);
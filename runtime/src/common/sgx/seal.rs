@@ -1,19 +1,56 @@
 //! Wrappers for sealing secrets to the enclave in cold storage.
+//!
+//! Secrets sealed using [`seal`] are encrypted using Deoxys-II and have no header, unless a cipher
+//! suite has been configured via the `seal_cipher_suite` runtime configuration. Secrets sealed
+//! using an explicit cipher suite start with a header identifying the suite, which is bound to
+//! the ciphertext, and are encrypted using a key derived for that suite only. All secrets can be
+//! unsealed using [`unseal`].
+use std::sync::Mutex;
+
 use anyhow::{format_err, Error};
-use rand::{rngs::OsRng, Rng};
+use rand::Rng;
 use sgx_isa::Keypolicy;
 use zeroize::Zeroize;
 
 use crate::common::{
-    crypto::mrae::deoxysii::{DeoxysII, NONCE_SIZE, TAG_SIZE},
+    crypto::{
+        aead::{Cipher, CipherSuite},
+        mrae::deoxysii::{DeoxysII, NONCE_SIZE, TAG_SIZE},
+        rng::SecureRng,
+    },
     sgx::egetkey::egetkey,
 };
 
-/// Seal a secret to the enclave.
+/// Magic starting the header of secrets sealed using an explicit cipher suite.
+const SEALED_HEADER_MAGIC: &[u8] = b"EkSealed";
+
+lazy_static! {
+    static ref SEAL_CIPHER_SUITE: Mutex<Option<CipherSuite>> = Mutex::new(None);
+}
+
+/// Configure the cipher suite used to seal secrets via [`seal`].
+pub(crate) fn set_seal_cipher_suite(suite: CipherSuite) {
+    *SEAL_CIPHER_SUITE.lock().unwrap() = Some(suite);
+}
+
+/// Seal a secret to the enclave, using the configured cipher suite if any.
 ///
 /// The `context` field is a domain separation tag.
 pub fn seal(key_policy: Keypolicy, context: &[u8], data: &[u8]) -> Vec<u8> {
-    let mut rng = OsRng {};
+    let suite = *SEAL_CIPHER_SUITE.lock().unwrap();
+    seal_with(suite, key_policy, context, data)
+}
+
+fn seal_with(
+    suite: Option<CipherSuite>,
+    key_policy: Keypolicy,
+    context: &[u8],
+    data: &[u8],
+) -> Vec<u8> {
+    if let Some(suite) = suite {
+        return seal_with_suite(suite, key_policy, context, data);
+    }
+    let mut rng = SecureRng;
 
     // Encrypt the raw policy.
     let mut nonce = [0u8; NONCE_SIZE];
@@ -25,6 +62,28 @@ pub fn seal(key_policy: Keypolicy, context: &[u8], data: &[u8]) -> Vec<u8> {
     ciphertext
 }
 
+/// Seal a secret to the enclave using the given cipher suite.
+///
+/// The `context` field is a domain separation tag.
+pub fn seal_with_suite(
+    suite: CipherSuite,
+    key_policy: Keypolicy,
+    context: &[u8],
+    data: &[u8],
+) -> Vec<u8> {
+    let header = sealed_header(suite);
+    let nonce = suite.generate_nonce();
+    let ciphertext = new_cipher(suite, key_policy, context)
+        .seal(&nonce, data, &header)
+        .expect("sealing with a valid nonce must succeed");
+
+    let mut sealed = Vec::with_capacity(header.len() + nonce.len() + ciphertext.len());
+    sealed.extend_from_slice(&header);
+    sealed.extend_from_slice(&nonce);
+    sealed.extend_from_slice(&ciphertext);
+    sealed
+}
+
 /// Unseal a previously sealed secret to the enclave.
 ///
 /// The `context` field is a domain separation tag.
@@ -37,6 +96,12 @@ pub fn unseal(
     if ct_len == 0 {
         return Ok(None);
     }
+
+    // Secrets sealed without a header may start with the magic by chance, so fall back to
+    // unsealing them in case unsealing with the header fails.
+    if let Some(plaintext) = unseal_with_header(key_policy, context, ciphertext) {
+        return Ok(Some(plaintext));
+    }
     if ct_len < TAG_SIZE + NONCE_SIZE {
         return Err(format_err!("ciphertext is corrupted: invalid size"));
     }
@@ -55,6 +120,39 @@ pub fn unseal(
     }
 }
 
+/// Unseal a secret sealed using an explicit cipher suite, if it has a valid header.
+fn unseal_with_header(key_policy: Keypolicy, context: &[u8], sealed: &[u8]) -> Option<Vec<u8>> {
+    let rest = sealed.strip_prefix(SEALED_HEADER_MAGIC)?;
+    let (id, rest) = rest.split_first()?;
+    let suite = CipherSuite::from_id(*id)?;
+    if rest.len() < suite.nonce_size() + suite.tag_size() {
+        return None;
+    }
+    let (nonce, ciphertext) = rest.split_at(suite.nonce_size());
+
+    new_cipher(suite, key_policy, context)
+        .open(nonce, ciphertext, &sealed_header(suite))
+        .ok()
+}
+
+/// Header of secrets sealed using the given cipher suite.
+fn sealed_header(suite: CipherSuite) -> Vec<u8> {
+    [SEALED_HEADER_MAGIC, &[suite.id()]].concat()
+}
+
+/// Creates a new instance of the given cipher suite initialized with an SGX sealing key derived
+/// from the results of the `EGETKEY` instruction.
+///
+/// The `context` field is a domain separation tag. The key is derived from the context and the
+/// cipher suite, so keys are never shared between cipher suites nor with [`new_deoxysii`].
+pub fn new_cipher(suite: CipherSuite, key_policy: Keypolicy, context: &[u8]) -> Cipher {
+    let mut seal_key = egetkey(key_policy, &[&sealed_header(suite), context].concat());
+    let cipher = Cipher::new(suite, &seal_key);
+    seal_key.zeroize();
+
+    cipher
+}
+
 /// Creates a new Deoxys-II instance initialized with an SGX sealing key derived
 /// from the results of the `EGETKEY`instruction.
 ///
@@ -87,6 +185,67 @@ mod tests {
         assert_eq!(unsealed_c.unwrap(), None);
     }
 
+    #[test]
+    fn test_seal_with_suite() {
+        for suite in CipherSuite::ALL {
+            let sealed = seal_with_suite(*suite, Keypolicy::MRENCLAVE, b"MRENCLAVE", b"secret");
+            let unsealed = unseal(Keypolicy::MRENCLAVE, b"MRENCLAVE", &sealed);
+            assert_eq!(unsealed.unwrap(), Some(b"secret".to_vec()));
+
+            let unsealed = unseal(Keypolicy::MRENCLAVE, b"MRENCLAVE2", &sealed);
+            assert!(unsealed.is_err());
+        }
+
+        // Secrets sealed without a header remain readable.
+        let sealed = seal(Keypolicy::MRENCLAVE, b"MRENCLAVE", b"secret");
+        let unsealed = unseal(Keypolicy::MRENCLAVE, b"MRENCLAVE", &sealed);
+        assert_eq!(unsealed.unwrap(), Some(b"secret".to_vec()));
+
+        // Tampered headers are detected.
+        let mut sealed = seal_with_suite(
+            CipherSuite::Aes256Gcm,
+            Keypolicy::MRENCLAVE,
+            b"MRENCLAVE",
+            b"secret",
+        );
+        sealed[SEALED_HEADER_MAGIC.len()] = CipherSuite::DeoxysII.id();
+        assert!(unseal(Keypolicy::MRENCLAVE, b"MRENCLAVE", &sealed).is_err());
+
+        // Secrets sealed using a suite are not encrypted with the key of headerless secrets.
+        let sealed = seal_with_suite(
+            CipherSuite::DeoxysII,
+            Keypolicy::MRENCLAVE,
+            b"MRENCLAVE",
+            b"secret",
+        );
+        let (nonce, ciphertext) = sealed[SEALED_HEADER_MAGIC.len() + 1..].split_at(NONCE_SIZE);
+        let nonce: [u8; NONCE_SIZE] = nonce.try_into().unwrap();
+        let d2 = new_deoxysii(Keypolicy::MRENCLAVE, b"MRENCLAVE");
+        assert!(d2
+            .open(
+                &nonce,
+                ciphertext.to_vec(),
+                sealed_header(CipherSuite::DeoxysII)
+            )
+            .is_err());
+    }
+
+    #[test]
+    fn test_seal_configured_suite() {
+        let sealed = seal_with(
+            Some(CipherSuite::Aes256Gcm),
+            Keypolicy::MRENCLAVE,
+            b"MRENCLAVE",
+            b"secret",
+        );
+        assert!(sealed.starts_with(&sealed_header(CipherSuite::Aes256Gcm)));
+        let unsealed = unseal(Keypolicy::MRENCLAVE, b"MRENCLAVE", &sealed);
+        assert_eq!(unsealed.unwrap(), Some(b"secret".to_vec()));
+
+        let sealed = seal_with(None, Keypolicy::MRENCLAVE, b"MRENCLAVE", b"secret");
+        assert!(!sealed.starts_with(SEALED_HEADER_MAGIC));
+    }
+
     #[test]
     fn test_incorrect_context() {
         // Test incorrect context.
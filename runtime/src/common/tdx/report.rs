@@ -158,6 +158,15 @@ impl RawTdReport {
 
 /// Generates a TD report with the given report data.
 pub fn get_report(report_data: &[u8]) -> Result<RawTdReport> {
+    let report = get_raw_report(report_data)?;
+    RawTdReport::parse(&report)
+}
+
+/// Generates a TD report with the given report data and returns the raw TDREPORT_STRUCT.
+///
+/// The raw report can be passed to the host which then generates a quote using its quote
+/// generation service in case the ConfigFS TSM report subsystem is not available in the guest.
+pub fn get_raw_report(report_data: &[u8]) -> Result<Vec<u8>> {
     if report_data.len() != TDX_REPORTDATA_LEN {
         return Err(anyhow!("invalid report data length"));
     }
@@ -182,10 +191,7 @@ pub fn get_report(report_data: &[u8]) -> Result<RawTdReport> {
         )?;
     }
 
-    // Read and parse output.
-    let report = RawTdReport::parse(&request.tdreport)?;
-
-    Ok(report)
+    Ok(request.tdreport.to_vec())
 }
 
 /// First generates a TD report with the given report data and then uses it to generate a quote.
@@ -11,12 +11,38 @@ use crate::common::{logger::get_logger, process};
 
 const INITIAL_MINIMUM_TIME: i64 = 1704067200; // Mon, 01 Jan 2024 00:00:00 UTC
 
+/// Default tolerated clock skew, in seconds. Deployments whose hosts are known to have worse
+/// clocks can configure a larger tolerance.
+pub const DEFAULT_CLOCK_SKEW_TOLERANCE: i64 = 10;
+
 struct TimeSource {
     inner: Mutex<Inner>,
 }
 
 struct Inner {
     timestamp: i64,
+    skew_tolerance: i64,
+    skew: ClockSkew,
+}
+
+impl Inner {
+    fn record_skew(&mut self, skew: i64) {
+        self.skew.last = skew;
+        self.skew.max = self.skew.max.max(skew.abs());
+    }
+}
+
+/// Clock skew measurements, in seconds.
+///
+/// Skew is measured whenever the local clock is compared against a semi-trusted time source
+/// (e.g. the timestamp of a verified quote), with positive values denoting that the local clock
+/// is behind.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct ClockSkew {
+    /// Most recent measurement.
+    pub last: i64,
+    /// Largest absolute measurement.
+    pub max: i64,
 }
 
 /// Returns the number of seconds since the UNIX epoch.  The time returned
@@ -25,18 +51,25 @@ struct Inner {
 ///
 /// The returned timestamp MUST NOT be trusted on in any way, as the underlying
 /// time source is reliant on the host operating system.
+///
+/// In case the local clock is behind the minimum timestamp by at most the clock
+/// skew tolerance, the minimum timestamp is returned instead.
 pub fn insecure_posix_time() -> i64 {
     let mut inner = TIME_SOURCE.inner.lock().unwrap();
 
-    let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap();
-    let now = now.as_secs() as i64;
-
+    let now = local_posix_time();
     if now < inner.timestamp {
-        error!(
-            get_logger("runtime/time"),
-            "clock appeared to have ran backwards"
-        );
-        process::abort();
+        let skew = inner.timestamp - now;
+        inner.record_skew(skew);
+        if skew > inner.skew_tolerance {
+            error!(
+                get_logger("runtime/time"),
+                "clock appeared to have ran backwards";
+                "skew" => skew,
+            );
+            process::abort();
+        }
+        return inner.timestamp;
     }
     inner.timestamp = now;
 
@@ -54,6 +87,7 @@ pub fn insecure_posix_system_time() -> SystemTime {
 pub(crate) fn update_insecure_posix_time(timestamp: i64) {
     let mut inner = TIME_SOURCE.inner.lock().unwrap();
 
+    inner.record_skew(timestamp - local_posix_time());
     if timestamp > inner.timestamp {
         inner.timestamp = timestamp;
     }
@@ -64,10 +98,36 @@ pub(crate) fn update_insecure_posix_time(timestamp: i64) {
     // 1 RTT in the past.
 }
 
+/// Tolerated clock skew between the local clock and semi-trusted time sources, in
+/// seconds.
+///
+/// The tolerance is applied by all time-based validity checks (e.g. quote freshness
+/// and TCB validity).
+pub fn clock_skew_tolerance() -> i64 {
+    TIME_SOURCE.inner.lock().unwrap().skew_tolerance
+}
+
+/// Configure the tolerated clock skew.
+pub(crate) fn set_clock_skew_tolerance(tolerance: Duration) {
+    TIME_SOURCE.inner.lock().unwrap().skew_tolerance = tolerance.as_secs() as i64;
+}
+
+/// Clock skew measurements made so far.
+pub fn clock_skew() -> ClockSkew {
+    TIME_SOURCE.inner.lock().unwrap().skew
+}
+
+fn local_posix_time() -> i64 {
+    let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap();
+    now.as_secs() as i64
+}
+
 lazy_static! {
     static ref TIME_SOURCE: TimeSource = TimeSource {
         inner: Mutex::new(Inner {
             timestamp: INITIAL_MINIMUM_TIME,
+            skew_tolerance: DEFAULT_CLOCK_SKEW_TOLERANCE,
+            skew: ClockSkew::default(),
         })
     };
 }
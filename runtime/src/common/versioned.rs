@@ -47,3 +47,140 @@ impl<T: cbor::Decode> cbor::Decode for Versioned<T> {
         }
     }
 }
+
+/// A structure with a versioned serialized form that can be upgraded from older versions.
+///
+/// Implement it via the `versioned!` macro.
+pub trait Upgrade: cbor::Decode + cbor::EncodeAsMap {
+    /// Current version of the serialized form.
+    const VERSION: u16;
+
+    /// Upgrade the serialized form (without the version key) from the given version to the
+    /// next one.
+    fn upgrade(version: u16, value: cbor::Value) -> Result<cbor::Value, cbor::DecodeError>;
+
+    /// Serialize the structure, tagged with its current version.
+    fn to_versioned_vec(self) -> Vec<u8> {
+        cbor::to_vec(Versioned::new(Self::VERSION, self))
+    }
+
+    /// Deserialize a versioned structure, upgrading it from older versions as needed.
+    fn from_versioned_slice(data: &[u8]) -> Result<Self, cbor::DecodeError> {
+        Self::from_versioned(cbor::from_slice(data)?)
+    }
+
+    /// Decode a versioned structure, upgrading it from older versions as needed.
+    ///
+    /// Versions newer than the current one are rejected.
+    fn from_versioned(versioned: Versioned<cbor::Value>) -> Result<Self, cbor::DecodeError> {
+        let Versioned {
+            mut version,
+            mut inner,
+        } = versioned;
+        if version > Self::VERSION {
+            return Err(cbor::DecodeError::ParsingFailed);
+        }
+        while version < Self::VERSION {
+            inner = Self::upgrade(version, inner)?;
+            version += 1;
+        }
+        cbor::Decode::try_from_cbor_value(inner)
+    }
+}
+
+/// Implement `Upgrade` for a structure, registering the functions upgrading its serialized form
+/// from each of the older versions to the next one.
+///
+/// # Examples
+///
+/// ```rust,ignore
+/// versioned!(MyType, 2, {
+///     0 => upgrade_v0,
+///     1 => upgrade_v1,
+/// });
+/// ```
+#[macro_export]
+macro_rules! versioned {
+    ($name:ty, $version:expr) => {
+        $crate::versioned!($name, $version, {});
+    };
+    ($name:ty, $version:expr, { $($from:literal => $upgrade:expr),* $(,)? }) => {
+        impl $crate::common::versioned::Upgrade for $name {
+            const VERSION: u16 = $version;
+
+            fn upgrade(
+                version: u16,
+                value: $crate::cbor::Value,
+            ) -> Result<$crate::cbor::Value, $crate::cbor::DecodeError> {
+                match version {
+                    $($from => ($upgrade)(value),)*
+                    _ => Err($crate::cbor::DecodeError::ParsingFailed),
+                }
+            }
+        }
+    };
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    /// Version 1 of the test structure.
+    #[derive(cbor::Encode, cbor::Decode)]
+    struct ConfigV1 {
+        limit: u32,
+    }
+
+    /// Current version of the test structure.
+    #[derive(Debug, PartialEq, Eq, cbor::Encode, cbor::Decode)]
+    struct Config {
+        limit: u64,
+        name: String,
+    }
+
+    fn upgrade_v0(_value: cbor::Value) -> Result<cbor::Value, cbor::DecodeError> {
+        Ok(cbor::to_value(ConfigV1 { limit: 10 }))
+    }
+
+    fn upgrade_v1(value: cbor::Value) -> Result<cbor::Value, cbor::DecodeError> {
+        let old: ConfigV1 = cbor::from_value(value)?;
+        Ok(cbor::to_value(Config {
+            limit: old.limit.into(),
+            name: "default".to_string(),
+        }))
+    }
+
+    versioned!(Config, 2, {
+        0 => upgrade_v0,
+        1 => upgrade_v1,
+    });
+
+    #[test]
+    fn test_upgrade() {
+        let current = Config {
+            limit: 1,
+            name: "test".to_string(),
+        };
+        let enc = current.to_versioned_vec();
+        let dec = Config::from_versioned_slice(&enc).unwrap();
+        assert_eq!(dec.name, "test");
+
+        let enc = cbor::to_vec(Versioned::new(1, ConfigV1 { limit: 5 }));
+        let dec = Config::from_versioned_slice(&enc).unwrap();
+        assert_eq!(
+            dec,
+            Config {
+                limit: 5,
+                name: "default".to_string(),
+            }
+        );
+
+        let enc = cbor::to_vec(Versioned::new(0, ConfigV1 { limit: 5 }));
+        let dec = Config::from_versioned_slice(&enc).unwrap();
+        assert_eq!(dec.limit, 10);
+
+        // Versions from the future are rejected.
+        let enc = cbor::to_vec(Versioned::new(3, ConfigV1 { limit: 5 }));
+        assert!(Config::from_versioned_slice(&enc).is_err());
+    }
+}
@@ -1,5 +1,32 @@
 //! Runtime configuration.
-use crate::{common::version::Version, consensus::verifier::TrustRoot, types::Features};
+use std::{
+    collections::{BTreeMap, HashMap, HashSet},
+    fmt,
+    time::Duration,
+};
+
+use thiserror::Error;
+
+use crate::{
+    common::{
+        crypto::{aead::CipherSuite, signature::PublicKey, x25519},
+        sgx::{pcs::CollateralCacheConfig, EnclaveIdentity},
+        version::Version,
+    },
+    consensus::{
+        checkpoint::CheckpointPolicy, tendermint::verifier::MultiHeadConfig, verifier::TrustRoot,
+    },
+    enclave_rpc::{codec::FrameVersion, session::PostQuantumPolicy},
+    host::{
+        bundle_manager::BundleTrustRoot, feed::FeedSigners, http::HttpPolicy,
+        signer::SignerTrustRoot, RetryPolicy,
+    },
+    storage::mkvs::{
+        sync::{CircuitBreakerConfig, SyncTimeouts},
+        CacheConfig,
+    },
+    types::{self, Features},
+};
 
 /// Global runtime configuration.
 #[derive(Clone, Debug, Default)]
@@ -8,13 +35,110 @@ pub struct Config {
     pub version: Version,
     /// Optional trust root for consensus layer integrity verification.
     pub trust_root: Option<TrustRoot>,
+    /// Optional configuration of the multi-head consensus verifier, for chains with short reorgs.
+    /// In case it is not set, the consensus layer chain is assumed to be linear.
+    pub multi_head_verifier: Option<MultiHeadConfig>,
+    /// Optional policy for initializing the consensus verifier from signed checkpoints provided
+    /// by the host when they are newer than the trusted state. In case it is not set, the verifier
+    /// always syncs from the embedded or stored trust root.
+    pub consensus_checkpoint: Option<CheckpointPolicy>,
     /// Storage configuration.
     pub storage: Storage,
+    /// Protocol-level size limits.
+    pub limits: Limits,
+    /// Per-subsystem limits on calls made to the host.
+    pub host_call_limits: HostCallLimits,
     /// Advertised runtime features.
     pub features: Features,
     /// Whether storage state should be persisted between transaction check invocations. The state
     /// is invalidated on the next round.
     pub persist_check_tx_state: bool,
+    /// Number of workers executing transaction batches in parallel, in case the transaction
+    /// dispatcher supports it. With less than two workers, batches are executed serially.
+    pub parallel_execution_workers: usize,
+    /// Whether the consensus verifier should subscribe to consensus blocks as they are produced,
+    /// keeping its view up to date instead of verifying lazily when state is first accessed.
+    pub proactive_consensus_sync: bool,
+    /// Number of recent call summaries retained for the call trace RPC method. A zero value
+    /// disables call tracing.
+    pub call_trace_capacity: usize,
+    /// Callers allowed to access the call trace RPC method.
+    pub call_trace_access: CallTraceAccess,
+    /// Number of responses to cacheable EnclaveRPC methods retained in the response cache. A
+    /// zero value disables response caching.
+    pub rpc_response_cache_capacity: usize,
+    /// Draining of EnclaveRPC calls on shutdown.
+    pub rpc_drain: RpcDrain,
+    /// Admission control of incoming EnclaveRPC calls, per peer.
+    pub rpc_admission: RpcAdmission,
+    /// Coordinated shutdown of the runtime.
+    pub shutdown: Shutdown,
+    /// Resumption of EnclaveRPC sessions.
+    pub rpc_session_resumption: RpcSessionResumption,
+    /// Size of the anti-replay window of EnclaveRPC sessions, i.e. the number of messages behind
+    /// the latest one received that are still accepted out of order. Only sessions whose
+    /// initiators are configured with a window use explicit nonces, all other sessions require
+    /// messages to be received in order.
+    pub rpc_replay_window: Option<u64>,
+    /// Policy for using the hybrid post-quantum key exchange in EnclaveRPC sessions, both those
+    /// accepted by the runtime and those initiated to the key manager.
+    pub rpc_post_quantum_policy: PostQuantumPolicy,
+    /// Whether the high-water marks of anti-replay windows of EnclaveRPC sessions are persisted
+    /// sealed in the untrusted local storage.
+    pub rpc_persist_replay_windows: bool,
+    /// Codecs of EnclaveRPC frames.
+    pub rpc_frame_codecs: RpcFrameCodecs,
+    /// Interval at which health reports are pushed to the host. In case it is not set, health
+    /// reports are only available via the health query.
+    pub health_report_interval: Option<Duration>,
+    /// Interval at which metrics snapshots are pushed to the host. In case it is not set, metrics
+    /// are not exported.
+    pub metrics_push_interval: Option<Duration>,
+    /// Forwarding of log records to the host. In case it is not set or the host doesn't support
+    /// it, log records are only written to the standard error output.
+    pub log_forwarding: Option<LogForwarding>,
+    /// Tolerated clock skew between the local clock and semi-trusted time sources, applied by
+    /// all time-based validity checks. In case it is not set, `DEFAULT_CLOCK_SKEW_TOLERANCE` is
+    /// used.
+    pub clock_skew_tolerance: Option<Duration>,
+    /// Policy for retrying idempotent local RPC queries to the host.
+    pub host_query_retry: RetryPolicy,
+    /// Trust root that bundle manifests must be signed by before bundles are added to the host.
+    /// In case it is not set, bundle manifests are not verified.
+    pub bundle_trust_root: Option<BundleTrustRoot>,
+    /// Trust root that signer endpoints holding signing keys on behalf of the runtime must attest
+    /// the keys with. In case it is not set, only signing keys with pinned public keys can be
+    /// used via `HostSigner`.
+    pub signer_trust_root: Option<SignerTrustRoot>,
+    /// Resource limits of a single query.
+    pub query_limits: QueryLimits,
+    /// Rate limits of queries forwarded by the host, per source.
+    pub query_rate_limits: QueryRateLimits,
+    /// Whether quote verification failures caused by expired TCB collateral, an out of date TCB
+    /// or debug enclaves should only be reported in a signed audit report, with the runtime
+    /// continuing in restricted mode. This is meant for staging environments mirroring the
+    /// production policy and must never be enabled in production.
+    pub attestation_audit_mode: bool,
+    /// Caching of quote verification collateral, including whether pinned collateral pre-loaded
+    /// via the host is accepted in offline verification mode.
+    pub attestation_collateral: CollateralCacheConfig,
+    /// Transport used to communicate with the runtime host.
+    pub host_transport: HostTransport,
+    /// Bounded queues of requests received from the host.
+    pub host_message_queues: MessageQueues,
+    /// Signer sets of the external data feeds, by feed name. Data pushed by the host for other
+    /// feeds is rejected.
+    pub external_data_feeds: BTreeMap<String, FeedSigners>,
+    /// Endpoints that outbound HTTPS requests tunnelled through the host may be made to, and the
+    /// CA certificates their server certificates are validated against. By default, no requests
+    /// are allowed.
+    pub http_policy: HttpPolicy,
+    /// Cipher suite used to seal secrets to the enclave. In case it is not set, secrets are sealed
+    /// using Deoxys-II without a header, so they remain readable by older runtime versions.
+    pub seal_cipher_suite: Option<CipherSuite>,
+    /// Public key of the operator that provisioned secrets must be sealed by. Secrets sealed by
+    /// any other key are rejected, and in case it is not set, no secrets are available.
+    pub secrets_operator_key: Option<x25519::PublicKey>,
 }
 
 /// Storage-related configuration.
@@ -26,6 +150,32 @@ pub struct Storage {
     /// The total size, in bytes, of values held by the cache before eviction.
     /// A zero value denotes unlimited capacity.
     pub cache_value_capacity: usize,
+    /// Limits applying to all nodes held by the cache and the cache eviction policy.
+    pub cache: CacheConfig,
+    /// The maximum number of tree nodes held by the node cache shared between all trees, in
+    /// addition to the nodes held by each tree. A zero value disables the shared cache, which is
+    /// the default.
+    pub shared_cache_node_capacity: usize,
+    /// Key prefixes preloaded into the execution and check caches at startup and after the
+    /// caches are flushed, so that the first rounds don't run against a cold cache.
+    pub preload_prefixes: Vec<Vec<u8>>,
+    /// The maximum number of keys preloaded per prefix.
+    pub preload_limit: u16,
+    /// The maximum number of per-round dirty sets buffered for each subscriber before it lags
+    /// behind. A zero value disables publishing of dirty sets.
+    pub dirty_set_capacity: usize,
+    /// Timeouts of host storage sync requests.
+    pub sync_timeouts: SyncTimeouts,
+    /// Circuit breaker degrading storage after repeated host storage sync failures.
+    pub circuit_breaker: CircuitBreakerConfig,
+    /// The number of recent rounds whose locally committed roots are kept to verify the commit
+    /// acknowledgements of the host. A zero value disables verification.
+    pub commit_ack_rounds: usize,
+    /// Interval at which cached nodes of the execution and check trees are re-hashed to detect
+    /// memory corruption. If not specified, no integrity self-checks are performed.
+    pub integrity_check_interval: Option<Duration>,
+    /// The maximum number of cached nodes of each tree re-hashed per integrity self-check.
+    pub integrity_check_sample: usize,
 }
 
 impl Default for Storage {
@@ -33,6 +183,579 @@ impl Default for Storage {
         Self {
             cache_node_capacity: 100_000,
             cache_value_capacity: 32 * 1024 * 1024, // 32 MiB
+            cache: CacheConfig::default(),
+            shared_cache_node_capacity: 0,
+            preload_prefixes: Vec::new(),
+            preload_limit: 10_000,
+            dirty_set_capacity: 16,
+            sync_timeouts: SyncTimeouts::default(),
+            circuit_breaker: CircuitBreakerConfig::default(),
+            commit_ack_rounds: 64,
+            integrity_check_interval: Some(Duration::from_secs(60)),
+            integrity_check_sample: 256,
+        }
+    }
+}
+
+/// EnclaveRPC drain configuration.
+///
+/// When the runtime is shut down (e.g., for an upgrade), the EnclaveRPC server stops accepting
+/// calls and waits for in-flight calls to complete before the sessions are closed.
+#[derive(Clone, Debug)]
+pub struct RpcDrain {
+    /// The maximum time to wait for in-flight calls to complete.
+    pub timeout: Duration,
+    /// Time after which peers whose calls are rejected while draining are advised to retry.
+    pub retry_after: Duration,
+}
+
+impl Default for RpcDrain {
+    fn default() -> Self {
+        Self {
+            timeout: Duration::from_secs(10),
+            retry_after: Duration::from_secs(30),
+        }
+    }
+}
+
+/// Coordinated shutdown configuration.
+///
+/// When the host requests a shutdown, the runtime tears down its subsystems in stages (see
+/// [`crate::shutdown`]) before acknowledging that it is ready to be terminated. In-flight RPC
+/// calls are drained within the RPC drain timeout and host calls are settled within the host call
+/// timeout, while the other stages have their own timeouts. All stages are additionally bounded by
+/// the remaining grace period.
+#[derive(Clone, Debug)]
+pub struct Shutdown {
+    /// The maximum time to prepare for a shutdown. Shutdowns requested without a prior notice use
+    /// all of it, while the grace periods carried by shutdown notices are capped at it.
+    pub timeout: Duration,
+    /// The maximum time to wait for in-flight host calls to complete before they are cancelled.
+    pub host_call_timeout: Duration,
+    /// The maximum time to wait for running background tasks to stop.
+    pub tasks_timeout: Duration,
+    /// The maximum time to flush write-ahead logs.
+    pub wal_flush_timeout: Duration,
+    /// The maximum time to persist caches and application state.
+    pub cache_persist_timeout: Duration,
+}
+
+impl Default for Shutdown {
+    fn default() -> Self {
+        Self {
+            timeout: Duration::from_secs(30),
+            host_call_timeout: Duration::from_secs(5),
+            tasks_timeout: Duration::from_secs(5),
+            wal_flush_timeout: Duration::from_secs(10),
+            cache_persist_timeout: Duration::from_secs(30),
         }
     }
 }
+
+/// Log forwarding configuration.
+#[derive(Clone, Debug)]
+pub struct LogForwarding {
+    /// Interval at which buffered records are forwarded to the host.
+    pub interval: Duration,
+    /// The maximum number of records forwarded in a single batch.
+    pub max_batch_size: usize,
+    /// The maximum number of buffered records. Once reached, the oldest records are dropped.
+    pub max_buffered: usize,
+    /// The maximum number of records captured per second. A zero value disables rate limiting.
+    pub max_records_per_second: u32,
+    /// Fields whose values are forwarded as is. The values of all other fields are redacted.
+    pub allowed_fields: Vec<String>,
+    /// Whether record messages are forwarded as is. Messages may embed formatted confidential
+    /// values, so they are redacted by default.
+    pub allow_messages: bool,
+}
+
+impl Default for LogForwarding {
+    fn default() -> Self {
+        Self {
+            interval: Duration::from_secs(1),
+            max_batch_size: 512,
+            max_buffered: 4096,
+            max_records_per_second: 100,
+            allowed_fields: Vec::new(),
+            allow_messages: false,
+        }
+    }
+}
+
+/// EnclaveRPC frame codec configuration.
+///
+/// To migrate to a newer codec version, servers first accept both versions, then clients switch
+/// to the newer version and finally servers stop accepting the older one.
+#[derive(Clone, Debug)]
+pub struct RpcFrameCodecs {
+    /// Codec version used to encode frames sent to other runtimes.
+    pub version: FrameVersion,
+    /// Codec versions of frames accepted from peers.
+    pub accepted: Vec<FrameVersion>,
+}
+
+impl Default for RpcFrameCodecs {
+    fn default() -> Self {
+        Self {
+            version: FrameVersion::V0,
+            accepted: FrameVersion::ALL.to_vec(),
+        }
+    }
+}
+
+/// EnclaveRPC session resumption configuration.
+///
+/// Peers that established a session can later resume it within the ticket time-to-live using a
+/// short handshake, without repeating remote attestation.
+#[derive(Clone, Debug, Default)]
+pub struct RpcSessionResumption {
+    /// Time-to-live of issued session tickets. A zero value disables session resumption.
+    pub ticket_ttl: Duration,
+    /// Number of messages after which session keys are ratcheted. Peers must be configured with
+    /// the same interval. A zero value disables key ratcheting.
+    pub ratchet_interval: u64,
+}
+
+/// Callers allowed to access the call trace RPC method.
+#[derive(Clone, Debug, Default)]
+pub enum CallTraceAccess {
+    /// Only local queries made by the host.
+    #[default]
+    Local,
+    /// Only calls over sessions with attested enclaves hosted by the given operator nodes.
+    Operators(HashSet<PublicKey>),
+}
+
+/// Transport used to communicate with the runtime host.
+#[derive(Clone, Debug, Default)]
+pub enum HostTransport {
+    /// Transport determined by the TEE type. SGX enclaves connect to the `worker-host` TCP
+    /// address, TDX guests accept the first VSOCK connection on port 1 and all other runtimes
+    /// connect to the Unix domain socket given by the `OASIS_WORKER_HOST` environment variable.
+    #[default]
+    Default,
+    /// Connect to the Unix domain socket at the given path.
+    Unix(String),
+    /// Connect to the given TCP address.
+    Tcp(String),
+    /// Connect to the given TCP address using mutually-authenticated TLS.
+    Tls(TlsTransport),
+    /// Connect to the given TCP address using a Noise channel authenticated by pinned static
+    /// keys. Meant for runtimes running outside of a TEE on a different machine than the host.
+    Noise(NoiseTransport),
+    /// Accept the first VSOCK connection on the given port.
+    Vsock(u32),
+    /// Connect to the given VSOCK address, retrying failed attempts. Used by TDX containers, where
+    /// the host listens for connections instead of passing a connected socket.
+    VsockConnect(VsockTransport),
+}
+
+/// Connecting VSOCK transport configuration.
+#[derive(Clone, Debug)]
+pub struct VsockTransport {
+    /// Context identifier of the host.
+    pub cid: u32,
+    /// Port the host listens on.
+    pub port: u32,
+    /// Maximum number of connection attempts. A zero value retries forever.
+    pub max_attempts: usize,
+    /// Delay before the first retry, doubled after each further failed attempt.
+    pub backoff: Duration,
+    /// Maximum delay between attempts.
+    pub max_backoff: Duration,
+}
+
+impl Default for VsockTransport {
+    fn default() -> Self {
+        Self {
+            cid: 2, // VMADDR_CID_HOST
+            port: 1,
+            max_attempts: 10,
+            backoff: Duration::from_millis(100),
+            max_backoff: Duration::from_secs(5),
+        }
+    }
+}
+
+/// Mutually-authenticated TLS transport configuration.
+#[derive(Clone, Default)]
+pub struct TlsTransport {
+    /// TCP address of the host.
+    pub address: String,
+    /// Name the host certificate must be issued for.
+    pub server_name: String,
+    /// PEM-encoded certificates of the authorities the host certificate must be issued by.
+    pub ca_certificates: Vec<u8>,
+    /// PEM-encoded certificate chain presented to the host.
+    pub certificate_chain: Vec<u8>,
+    /// PEM-encoded private key of the presented certificate.
+    pub private_key: Vec<u8>,
+}
+
+impl fmt::Debug for TlsTransport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("TlsTransport")
+            .field("address", &self.address)
+            .field("server_name", &self.server_name)
+            .finish_non_exhaustive()
+    }
+}
+
+/// Noise transport configuration.
+///
+/// Both sides are authenticated by their static X25519 keys, which are pinned in advance so that
+/// no certificate authorities are needed.
+#[derive(Clone, Default)]
+pub struct NoiseTransport {
+    /// TCP address of the host.
+    pub address: String,
+    /// Static X25519 private key of the runtime.
+    pub private_key: Vec<u8>,
+    /// Pinned static X25519 public key of the host.
+    pub host_public_key: Vec<u8>,
+}
+
+impl fmt::Debug for NoiseTransport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("NoiseTransport")
+            .field("address", &self.address)
+            .field("host_public_key", &self.host_public_key)
+            .finish_non_exhaustive()
+    }
+}
+
+/// Host call rate, accounted over one second windows.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Rate {
+    /// The maximum number of calls per second. A zero value denotes no limit.
+    pub max_calls: u64,
+    /// The maximum number of bytes per second, counting both requests and responses. A zero
+    /// value denotes no limit.
+    pub max_bytes: u64,
+}
+
+impl Rate {
+    /// Whether the given number of calls or bytes exceeds this rate.
+    pub fn is_exceeded(&self, calls: u64, bytes: u64) -> bool {
+        (self.max_calls > 0 && calls > self.max_calls)
+            || (self.max_bytes > 0 && bytes > self.max_bytes)
+    }
+}
+
+/// Host call limits of a single subsystem.
+#[derive(Clone, Debug, Default)]
+pub struct SubsystemLimits {
+    /// Rate past which a warning is emitted.
+    pub soft: Rate,
+    /// Rate past which low-priority calls are rejected.
+    pub hard: Rate,
+}
+
+/// Per-subsystem limits on calls made to the host.
+///
+/// All limits are disabled by default.
+#[derive(Clone, Debug, Default)]
+pub struct HostCallLimits {
+    /// Limits of storage sync requests.
+    pub storage_sync: SubsystemLimits,
+    /// Limits of EnclaveRPC calls to remote nodes.
+    pub rpc: SubsystemLimits,
+    /// Limits of transaction submissions.
+    pub tx_submit: SubsystemLimits,
+}
+
+/// Policy for requests received from the host while their queue is full.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum OverflowPolicy {
+    /// Reject the new request, signaling backpressure to the host so that it retries the request
+    /// later. Hosts not supporting backpressure signaling receive an error instead.
+    #[default]
+    Backpressure,
+    /// Drop the oldest waiting request, responding to it with an error.
+    DropOldest,
+    /// Drop the new request, responding to it with an error.
+    DropNewest,
+}
+
+/// Bounded queue of requests of a single message class.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct MessageQueue {
+    /// The maximum number of requests being handled concurrently, as well as the maximum number
+    /// of further requests waiting to be handled. A zero value denotes no limit.
+    pub capacity: usize,
+    /// Policy for requests received while the queue is full.
+    pub overflow: OverflowPolicy,
+}
+
+/// Bounded queues of requests received from the host, per message class.
+///
+/// All queues are unbounded by default.
+#[derive(Clone, Debug, Default)]
+pub struct MessageQueues {
+    /// Queue of transaction batch checks and execution requests.
+    pub execution: MessageQueue,
+    /// Queue of runtime and consensus layer notifications.
+    pub notification: MessageQueue,
+    /// Queue of queries and EnclaveRPC calls.
+    pub query: MessageQueue,
+}
+
+/// Resource limits of a single query, separate from any transaction gas accounting.
+///
+/// All limits are disabled by default.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct QueryLimits {
+    /// The maximum number of storage reads, counting lookups and visited iterator entries. A zero
+    /// value denotes no limit.
+    pub max_storage_reads: u64,
+    /// The maximum number of bytes read from storage, counting both keys and values. A zero value
+    /// denotes no limit.
+    pub max_storage_read_bytes: u64,
+    /// The maximum time a query may run for. In case it is not set, there is no limit.
+    pub max_time: Option<Duration>,
+    /// The maximum size, in bytes, of a query response. A zero value denotes no limit.
+    pub max_response_size: usize,
+}
+
+/// Quota of queries of a single source.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct QueryQuota {
+    /// The sustained number of queries per second. A zero value denotes no limit.
+    pub queries_per_second: u32,
+    /// The maximum number of queries in a burst. In case it is zero, `queries_per_second` is used.
+    pub burst: u32,
+}
+
+/// Rate limits of queries forwarded by the host, keyed by the source identifier provided by the
+/// host (e.g. the address of the client calling the node's query endpoint).
+///
+/// All quotas are disabled by default.
+#[derive(Clone, Debug)]
+pub struct QueryRateLimits {
+    /// Quota of all sources without a specific quota, including queries without a source.
+    pub default_quota: QueryQuota,
+    /// Quotas of specific sources.
+    pub quotas: BTreeMap<String, QueryQuota>,
+    /// The maximum number of sources tracked at once. Once exceeded, the least recently seen
+    /// source is forgotten.
+    pub max_sources: usize,
+}
+
+impl Default for QueryRateLimits {
+    fn default() -> Self {
+        Self {
+            default_quota: QueryQuota::default(),
+            quotas: BTreeMap::new(),
+            max_sources: 10_000,
+        }
+    }
+}
+
+/// Quota of EnclaveRPC calls of a single peer.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct RpcQuota {
+    /// The sustained cost of calls per second. A zero value denotes no limit.
+    pub cost_per_second: u32,
+    /// The maximum cost of calls in a burst. In case it is zero, `cost_per_second` is used.
+    pub burst: u32,
+    /// The maximum number of calls being handled concurrently. A zero value denotes no limit.
+    pub max_concurrent: u32,
+}
+
+/// Admission control of incoming EnclaveRPC calls. Peers are limited independently per node
+/// hosting their enclave, with quotas keyed by the attested enclave identity of the peer. Local
+/// calls made by the host are not subject to admission control.
+///
+/// All quotas are disabled by default.
+#[derive(Clone, Debug)]
+pub struct RpcAdmission {
+    /// Quota of all attested peers without a specific quota.
+    pub default_quota: RpcQuota,
+    /// Quota shared by all callers without an attested identity.
+    pub anonymous_quota: RpcQuota,
+    /// Quotas of specific enclave identities.
+    pub quotas: HashMap<EnclaveIdentity, RpcQuota>,
+    /// Cost of calls to specific methods. Calls to other methods cost one.
+    pub method_costs: BTreeMap<String, u32>,
+    /// The maximum number of peers tracked at once. Once exceeded, the least recently seen idle
+    /// peer is forgotten.
+    pub max_peers: usize,
+}
+
+impl Default for RpcAdmission {
+    fn default() -> Self {
+        Self {
+            default_quota: RpcQuota::default(),
+            anonymous_quota: RpcQuota::default(),
+            quotas: HashMap::new(),
+            method_costs: BTreeMap::new(),
+            max_peers: 10_000,
+        }
+    }
+}
+
+/// Error returned when a query exceeds its resource limits.
+#[derive(Error, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum QueryLimitError {
+    #[error("query exceeded the storage read limit")]
+    StorageReads,
+
+    #[error("query exceeded the storage read bytes limit")]
+    StorageReadBytes,
+
+    #[error("query exceeded the time limit")]
+    Time,
+
+    #[error("query response too large")]
+    ResponseSize,
+}
+
+impl From<QueryLimitError> for types::Error {
+    fn from(err: QueryLimitError) -> Self {
+        Self {
+            module: "query".to_string(),
+            code: err as u32 + 1,
+            message: err.to_string(),
+        }
+    }
+}
+
+/// Identifier of a protocol-level size limit.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Limit {
+    /// Size of a runtime host protocol message.
+    Message,
+    /// Size of a transaction.
+    Transaction,
+    /// Total size of the entries of a storage proof.
+    Proof,
+    /// Size of an EnclaveRPC frame.
+    RpcFrame,
+    /// Size of an event payload.
+    EventPayload,
+}
+
+impl fmt::Display for Limit {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Limit::Message => "message",
+            Limit::Transaction => "transaction",
+            Limit::Proof => "proof",
+            Limit::RpcFrame => "rpc frame",
+            Limit::EventPayload => "event payload",
+        };
+        f.write_str(name)
+    }
+}
+
+/// Error returned when a size limit is exceeded.
+#[derive(Error, Debug, Clone, PartialEq, Eq)]
+#[error("{limit} too large ({size} bytes, maximum is {max} bytes)")]
+pub struct LimitError {
+    /// Limit that was exceeded.
+    pub limit: Limit,
+    /// Actual size in bytes.
+    pub size: usize,
+    /// Maximum allowed size in bytes.
+    pub max: usize,
+}
+
+impl From<LimitError> for types::Error {
+    fn from(err: LimitError) -> Self {
+        Self {
+            module: "limits".to_string(),
+            code: err.limit as u32 + 1,
+            message: err.to_string(),
+        }
+    }
+}
+
+/// Protocol-level size limits.
+///
+/// All limits are enforced when the corresponding data is decoded, before it is processed.
+#[derive(Clone, Debug)]
+pub struct Limits {
+    /// The maximum size, in bytes, of a runtime host protocol message.
+    pub max_message_size: usize,
+    /// The maximum size, in bytes, of a transaction. Oversized transactions are rejected during
+    /// transaction checks.
+    pub max_tx_size: usize,
+    /// The maximum total size, in bytes, of the entries of a storage proof received from the
+    /// host.
+    pub max_proof_size: usize,
+    /// The maximum size, in bytes, of an EnclaveRPC frame.
+    pub max_rpc_frame_size: usize,
+    /// The maximum size, in bytes, of an event payload emitted by a transaction.
+    pub max_event_payload_size: usize,
+}
+
+impl Limits {
+    /// Maximum size in bytes for the given limit.
+    pub fn max(&self, limit: Limit) -> usize {
+        match limit {
+            Limit::Message => self.max_message_size,
+            Limit::Transaction => self.max_tx_size,
+            Limit::Proof => self.max_proof_size,
+            Limit::RpcFrame => self.max_rpc_frame_size,
+            Limit::EventPayload => self.max_event_payload_size,
+        }
+    }
+
+    /// Ensure that the given size does not exceed the given limit.
+    pub fn check(&self, limit: Limit, size: usize) -> Result<(), LimitError> {
+        let max = self.max(limit);
+        if size > max {
+            return Err(LimitError { limit, size, max });
+        }
+        Ok(())
+    }
+}
+
+impl Default for Limits {
+    fn default() -> Self {
+        // Nothing can be larger than a protocol message, so default to that.
+        let max_message_size = 16 * 1024 * 1024; // 16 MiB
+
+        Self {
+            max_message_size,
+            max_tx_size: max_message_size,
+            max_proof_size: max_message_size,
+            max_rpc_frame_size: max_message_size,
+            max_event_payload_size: max_message_size,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_limits() {
+        let limits = Limits {
+            max_tx_size: 100,
+            ..Default::default()
+        };
+
+        assert!(limits.check(Limit::Transaction, 100).is_ok());
+        let err = limits.check(Limit::Transaction, 101).unwrap_err();
+        assert_eq!(
+            err,
+            LimitError {
+                limit: Limit::Transaction,
+                size: 101,
+                max: 100,
+            }
+        );
+        assert_eq!(
+            err.to_string(),
+            "transaction too large (101 bytes, maximum is 100 bytes)"
+        );
+        assert!(limits.check(Limit::Proof, 101).is_ok());
+
+        let err: types::Error = err.into();
+        assert_eq!(err.module, "limits");
+        assert_eq!(err.code, 2);
+    }
+}
@@ -0,0 +1,220 @@
+//! Signed consensus checkpoints.
+//!
+//! After a long downtime, syncing the consensus verifier from its trust root requires verifying
+//! a long chain of light blocks. Instead, the verifier can be initialized from a recent checkpoint
+//! of the consensus layer chain, signed by a configured set of checkpoint signers (e.g. operators
+//! of well-known validators). Checkpoints are provided by the (untrusted) host, so they are only
+//! used after verifying that enough distinct signers signed them, that they belong to the trusted
+//! chain, that they are recent enough and that they are not older than the trusted state.
+use std::{collections::BTreeSet, time::Duration};
+
+use thiserror::Error;
+
+use crate::common::{
+    crypto::signature::{PublicKey, SignatureBundle},
+    namespace::Namespace,
+};
+
+use super::verifier::TrustRoot;
+
+/// Signature context used for consensus checkpoints.
+pub const CHECKPOINT_SIGNATURE_CONTEXT: &[u8] = b"oasis-core/consensus: checkpoint";
+
+/// Checkpoint verification error.
+#[derive(Error, Debug)]
+pub enum CheckpointError {
+    #[error("no quorum (got: {got} required: {required})")]
+    NoQuorum { got: usize, required: usize },
+
+    #[error("chain context mismatch (expected: {expected} got: {got})")]
+    ChainContextMismatch { expected: String, got: String },
+
+    #[error("checkpoint too old (age: {age:?} max: {max_age:?})")]
+    TooOld { age: Duration, max_age: Duration },
+
+    #[error("checkpoint older than trusted state (time: {time} trusted: {trusted_time})")]
+    OlderThanTrusted { time: i64, trusted_time: i64 },
+}
+
+/// A checkpoint of the consensus layer chain.
+#[derive(Clone, Debug, Default, PartialEq, Eq, cbor::Encode, cbor::Decode)]
+pub struct Checkpoint {
+    /// Height of the checkpointed block.
+    pub height: u64,
+    /// Hex-encoded hash of the checkpointed block header.
+    pub hash: String,
+    /// Consensus chain context.
+    pub chain_context: String,
+    /// Time of the checkpointed block (in seconds since the UNIX epoch).
+    pub time: i64,
+}
+
+/// A checkpoint together with the signatures of the checkpoint signers.
+#[derive(Clone, Debug, Default, PartialEq, Eq, cbor::Encode, cbor::Decode)]
+pub struct SignedCheckpoint {
+    /// Checkpoint.
+    pub checkpoint: Checkpoint,
+    /// Signatures over the checkpoint.
+    pub signatures: Vec<SignatureBundle>,
+}
+
+/// Policy for accepting consensus checkpoints.
+#[derive(Clone, Debug, Default)]
+pub struct CheckpointPolicy {
+    /// Public keys of the checkpoint signers.
+    pub signers: BTreeSet<PublicKey>,
+    /// Number of distinct signers required to sign each checkpoint.
+    pub threshold: usize,
+    /// Maximum age of accepted checkpoints.
+    pub max_age: Duration,
+}
+
+impl CheckpointPolicy {
+    /// Verify the given checkpoint against the policy, returning the trust root it represents.
+    ///
+    /// The checkpoint must belong to the chain with the given trusted chain context. Its age is
+    /// determined relative to the given (untrusted) time, while it may never be older than the
+    /// given time of the latest trusted block, if any (both in seconds since the UNIX epoch).
+    /// Signatures by non-signers and invalid signatures are ignored.
+    pub fn verify(
+        &self,
+        signed: &SignedCheckpoint,
+        runtime_id: Namespace,
+        chain_context: &str,
+        now: i64,
+        trusted_time: Option<i64>,
+    ) -> Result<TrustRoot, CheckpointError> {
+        let checkpoint = &signed.checkpoint;
+        let message = cbor::to_vec(checkpoint.clone());
+        let valid: BTreeSet<_> = signed
+            .signatures
+            .iter()
+            .filter(|sig| self.signers.contains(&sig.public_key))
+            .filter(|sig| sig.verify(CHECKPOINT_SIGNATURE_CONTEXT, &message))
+            .map(|sig| sig.public_key)
+            .collect();
+
+        // A zero threshold would accept unsigned checkpoints.
+        let required = self.threshold.max(1);
+        if valid.len() < required {
+            return Err(CheckpointError::NoQuorum {
+                got: valid.len(),
+                required,
+            });
+        }
+
+        if checkpoint.chain_context != chain_context {
+            return Err(CheckpointError::ChainContextMismatch {
+                expected: chain_context.to_string(),
+                got: checkpoint.chain_context.clone(),
+            });
+        }
+
+        // The local clock is controlled by the host, so also compare against the trusted state.
+        if let Some(trusted_time) = trusted_time {
+            if checkpoint.time < trusted_time {
+                return Err(CheckpointError::OlderThanTrusted {
+                    time: checkpoint.time,
+                    trusted_time,
+                });
+            }
+        }
+
+        let age = Duration::from_secs(now.saturating_sub(checkpoint.time).max(0) as u64);
+        if age > self.max_age {
+            return Err(CheckpointError::TooOld {
+                age,
+                max_age: self.max_age,
+            });
+        }
+
+        Ok(TrustRoot {
+            height: checkpoint.height,
+            hash: checkpoint.hash.clone(),
+            runtime_id,
+            chain_context: checkpoint.chain_context.clone(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::common::crypto::signature::{PrivateKey, Signer};
+
+    fn sign(sk: &PrivateKey, checkpoint: &Checkpoint) -> SignatureBundle {
+        SignatureBundle {
+            public_key: sk.public_key(),
+            signature: Signer::sign(
+                sk,
+                CHECKPOINT_SIGNATURE_CONTEXT,
+                &cbor::to_vec(checkpoint.clone()),
+            )
+            .unwrap(),
+        }
+    }
+
+    #[test]
+    fn test_checkpoint_policy() {
+        let keys: Vec<_> = (0..3)
+            .map(|i| PrivateKey::from_test_seed(format!("checkpoint signer {i}")))
+            .collect();
+        let policy = CheckpointPolicy {
+            signers: keys.iter().map(|sk| sk.public_key()).collect(),
+            threshold: 2,
+            max_age: Duration::from_secs(3600),
+        };
+        let runtime_id = Namespace::from(vec![1; 32]);
+        let checkpoint = Checkpoint {
+            height: 1000,
+            hash: "ab".repeat(32),
+            chain_context: "chain".to_string(),
+            time: 10_000,
+        };
+        let mut signed = SignedCheckpoint {
+            checkpoint: checkpoint.clone(),
+            signatures: vec![sign(&keys[0], &checkpoint), sign(&keys[0], &checkpoint)],
+        };
+
+        // Duplicate signatures don't count.
+        assert!(matches!(
+            policy.verify(&signed, runtime_id, "chain", 10_000, None),
+            Err(CheckpointError::NoQuorum {
+                got: 1,
+                required: 2
+            })
+        ));
+
+        signed.signatures.push(sign(&keys[2], &checkpoint));
+        let trust_root = policy
+            .verify(&signed, runtime_id, "chain", 12_000, None)
+            .unwrap();
+        assert_eq!(trust_root.height, 1000);
+        assert_eq!(trust_root.hash, checkpoint.hash);
+        assert_eq!(trust_root.runtime_id, runtime_id);
+        assert_eq!(trust_root.chain_context, "chain");
+
+        // Checkpoints must belong to the current chain and be recent enough.
+        assert!(matches!(
+            policy.verify(&signed, runtime_id, "other", 12_000, None),
+            Err(CheckpointError::ChainContextMismatch { .. })
+        ));
+        assert!(matches!(
+            policy.verify(&signed, runtime_id, "chain", 13_601, None),
+            Err(CheckpointError::TooOld { .. })
+        ));
+        assert!(matches!(
+            policy.verify(&signed, runtime_id, "chain", 12_000, Some(10_001)),
+            Err(CheckpointError::OlderThanTrusted { .. })
+        ));
+        assert!(policy
+            .verify(&signed, runtime_id, "chain", 12_000, Some(10_000))
+            .is_ok());
+
+        // Signatures are bound to the checkpoint.
+        signed.checkpoint.height = 2000;
+        assert!(policy
+            .verify(&signed, runtime_id, "chain", 12_000, None)
+            .is_err());
+    }
+}
@@ -0,0 +1,308 @@
+//! Typed client for verified consensus layer state queries.
+//!
+//! The client obtains the consensus layer state from the consensus verifier, so the state root
+//! is verified by the light client and all state fetched from the host is checked against proofs
+//! for that root. This saves runtimes from decoding the state wrappers by hand.
+use std::{collections::BTreeMap, sync::Arc};
+
+use anyhow::anyhow;
+
+use crate::common::{crypto::signature::PublicKey, namespace::Namespace};
+
+use super::{
+    address::Address,
+    params::RuntimeParameters,
+    registry::{Node, Runtime},
+    roothash::RuntimeState,
+    staking::{Account, ConsensusParameters as StakingParameters, DebondingDelegation, Delegation},
+    state::{
+        registry::ImmutableState as RegistryState, roothash::ImmutableState as RoothashState,
+        staking::ImmutableState as StakingState, ConsensusState, StateError,
+    },
+    verifier::{Error, Verifier},
+    HEIGHT_LATEST,
+};
+
+/// Maximum number of delegations scanned to find the active delegations of a runtime's own
+/// account, as delegations are stored by escrow account.
+pub const MAX_DELEGATIONS_SCANNED: usize = 100_000;
+
+/// Consensus layer staking state of a runtime's own account.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct RuntimeAccount {
+    /// Consensus layer height the state was read at.
+    pub height: u64,
+    /// Address of the runtime's account.
+    pub address: Address,
+    /// Staking account, including its balance and nonce.
+    pub account: Account,
+    /// Active delegations of the runtime, by escrow account.
+    pub delegations: BTreeMap<Address, Delegation>,
+    /// Debonding delegations of the runtime, by escrow account.
+    pub debonding_delegations: BTreeMap<Address, Vec<DebondingDelegation>>,
+}
+
+/// Client for verified queries of the consensus layer state.
+///
+/// All queries take the consensus layer height to query the state at, or `HEIGHT_LATEST` for the
+/// latest verified state.
+///
+/// # Warning
+///
+/// The latest verified state is not verified to be fresh. Use `verify_state_freshness` to
+/// perform this verification manually if needed.
+#[derive(Clone)]
+pub struct ConsensusClient {
+    verifier: Arc<dyn Verifier>,
+}
+
+impl ConsensusClient {
+    /// Create a new client querying the state verified by the given verifier.
+    pub fn new(verifier: Arc<dyn Verifier>) -> Self {
+        Self { verifier }
+    }
+
+    /// Latest known consensus layer height.
+    pub async fn latest_height(&self) -> Result<u64, Error> {
+        self.verifier.latest_height().await
+    }
+
+    /// Staking account with the given address.
+    pub async fn account(&self, height: u64, address: Address) -> Result<Account, Error> {
+        self.query(height, move |state| {
+            StakingState::new(state)
+                .account(address)
+                .map_err(|err| Error::VerificationFailed(err.into()))
+        })
+        .await
+    }
+
+    /// Delegation from the given delegator to the given escrow account.
+    pub async fn delegation(
+        &self,
+        height: u64,
+        delegator: Address,
+        escrow: Address,
+    ) -> Result<Delegation, Error> {
+        self.query(height, move |state| {
+            StakingState::new(state)
+                .delegation(delegator, escrow)
+                .map_err(|err| Error::VerificationFailed(err.into()))
+        })
+        .await
+    }
+
+    /// Staking state of the account of the runtime with the given identifier, read from a single
+    /// state snapshot.
+    ///
+    /// This is meant for runtimes paying for their own consensus messages, e.g. to determine the
+    /// nonce and balance available for the messages emitted in a round.
+    pub async fn runtime_account(
+        &self,
+        height: u64,
+        id: Namespace,
+    ) -> Result<RuntimeAccount, Error> {
+        self.query(height, move |state| {
+            let address = Address::from_runtime_id(&id);
+            let staking = StakingState::new(state);
+            let map_err = |err: StateError| Error::VerificationFailed(err.into());
+
+            Ok(RuntimeAccount {
+                height: state.height(),
+                account: staking.account(address.clone()).map_err(map_err)?,
+                delegations: staking
+                    .delegations_for(&address, MAX_DELEGATIONS_SCANNED)
+                    .map_err(map_err)?,
+                debonding_delegations: staking
+                    .debonding_delegations_for(&address)
+                    .map_err(map_err)?,
+                address,
+            })
+        })
+        .await
+    }
+
+    /// Staking consensus parameters, including the gas costs of staking operations.
+    pub async fn staking_parameters(&self, height: u64) -> Result<StakingParameters, Error> {
+        self.query(height, move |state| {
+            StakingState::new(state)
+                .parameters()
+                .map_err(|err| Error::VerificationFailed(err.into()))
+        })
+        .await
+    }
+
+    /// Descriptor of the runtime with the given identifier, including suspended runtimes.
+    pub async fn runtime(&self, height: u64, id: Namespace) -> Result<Option<Runtime>, Error> {
+        self.query(height, move |state| {
+            RegistryState::new(state)
+                .runtime(&id)
+                .map_err(|err| Error::VerificationFailed(err.into()))
+        })
+        .await
+    }
+
+    /// Executor and transaction scheduler parameters of the runtime with the given identifier.
+    pub async fn runtime_parameters(
+        &self,
+        height: u64,
+        id: Namespace,
+    ) -> Result<Option<RuntimeParameters>, Error> {
+        self.query(height, move |state| {
+            RuntimeParameters::from_state(state, &id)
+                .map_err(|err| Error::VerificationFailed(err.into()))
+        })
+        .await
+    }
+
+    /// Descriptor of the node with the given identifier.
+    pub async fn node(&self, height: u64, id: PublicKey) -> Result<Option<Node>, Error> {
+        self.query(height, move |state| {
+            RegistryState::new(state)
+                .node(&id)
+                .map_err(|err| Error::VerificationFailed(err.into()))
+        })
+        .await
+    }
+
+    /// Roothash state of the runtime with the given identifier.
+    pub async fn runtime_state(&self, height: u64, id: Namespace) -> Result<RuntimeState, Error> {
+        self.query(height, move |state| {
+            RoothashState::new(state)
+                .runtime_state(id)
+                .map_err(|err| Error::VerificationFailed(anyhow!(err)))
+        })
+        .await
+    }
+
+    async fn query<R, F>(&self, height: u64, f: F) -> Result<R, Error>
+    where
+        F: FnOnce(&ConsensusState) -> Result<R, Error>,
+    {
+        let state = match height {
+            HEIGHT_LATEST => self.verifier.latest_state().await?,
+            height => self.verifier.state_at(height).await?,
+        };
+        // Reading the state may fetch nodes from the host.
+        tokio::task::block_in_place(move || f(&state))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{common::quantity::Quantity, host::Host, testkit::host::MockHost};
+
+    /// Prefix of staking account keys.
+    const ACCOUNTS_PREFIX: u8 = 0x50;
+    /// Prefix of delegation keys.
+    const DELEGATIONS_PREFIX: u8 = 0x53;
+    /// Prefix of debonding delegation keys.
+    const DEBONDING_DELEGATIONS_PREFIX: u8 = 0x54;
+
+    fn runtime_id() -> Namespace {
+        Namespace::from(&[1u8; 32][..])
+    }
+
+    fn state_entries(account: &Account) -> BTreeMap<Vec<u8>, Vec<u8>> {
+        let mut entries = BTreeMap::new();
+        let key = [&[ACCOUNTS_PREFIX][..], Address::default().as_ref()].concat();
+        entries.insert(key, cbor::to_vec(account.clone()));
+
+        // Delegations of the runtime and of another delegator to the same escrow account.
+        let runtime = Address::from_runtime_id(&runtime_id());
+        let other = Address::from_runtime_id(&Namespace::default());
+        let escrow = Address::default();
+        let key = [&[ACCOUNTS_PREFIX][..], runtime.as_ref()].concat();
+        entries.insert(key, cbor::to_vec(account.clone()));
+        for (delegator, shares) in [(&runtime, 10u128), (&other, 20u128)] {
+            let key = [
+                &[DELEGATIONS_PREFIX][..],
+                escrow.as_ref(),
+                delegator.as_ref(),
+            ]
+            .concat();
+            let delegation = Delegation {
+                shares: Quantity::from(shares),
+            };
+            entries.insert(key, cbor::to_vec(delegation));
+            let key = [
+                &[DEBONDING_DELEGATIONS_PREFIX][..],
+                delegator.as_ref(),
+                escrow.as_ref(),
+                &5u64.to_be_bytes(),
+            ]
+            .concat();
+            let debonding = DebondingDelegation {
+                shares: Quantity::from(shares),
+                debond_end_time: 5,
+            };
+            entries.insert(key, cbor::to_vec(debonding));
+        }
+
+        entries
+    }
+
+    #[test]
+    fn test_consensus_client() {
+        let rt = tokio::runtime::Builder::new_multi_thread().build().unwrap();
+        let mut account = Account::default();
+        account.general.balance = Quantity::from(100u128);
+        let host = MockHost::new();
+        for height in 0..=10 {
+            host.set_consensus_state(height, state_entries(&account));
+        }
+        let client = host.consensus().unwrap();
+
+        rt.block_on(async {
+            assert_eq!(client.latest_height().await.unwrap(), 10);
+            let result = client
+                .account(HEIGHT_LATEST, Address::default())
+                .await
+                .unwrap();
+            assert_eq!(result, account);
+            let result = client.account(5, Address::default()).await.unwrap();
+            assert_eq!(result, account);
+            assert!(client.account(11, Address::default()).await.is_err());
+
+            // Missing entries are reported as such.
+            let other = Address::from_runtime_id(&Namespace::default());
+            let result = client.account(HEIGHT_LATEST, other).await.unwrap();
+            assert_eq!(result, Account::default());
+            let result = client.runtime(HEIGHT_LATEST, Namespace::default()).await;
+            assert!(result.unwrap().is_none());
+            let result = client
+                .runtime_state(HEIGHT_LATEST, Namespace::default())
+                .await;
+            assert!(result.is_err());
+
+            // The runtime's account only includes its own delegations.
+            let result = client
+                .runtime_account(HEIGHT_LATEST, runtime_id())
+                .await
+                .unwrap();
+            assert_eq!(result.height, 10);
+            assert_eq!(result.address, Address::from_runtime_id(&runtime_id()));
+            assert_eq!(result.account, account);
+            assert_eq!(
+                result.delegations,
+                BTreeMap::from([(
+                    Address::default(),
+                    Delegation {
+                        shares: Quantity::from(10u128)
+                    }
+                )])
+            );
+            assert_eq!(
+                result.debonding_delegations,
+                BTreeMap::from([(
+                    Address::default(),
+                    vec![DebondingDelegation {
+                        shares: Quantity::from(10u128),
+                        debond_end_time: 5,
+                    }]
+                )])
+            );
+        });
+    }
+}
@@ -0,0 +1,247 @@
+//! Verified executor committee information.
+use anyhow::anyhow;
+
+use crate::{
+    common::{crypto::signature::PublicKey, namespace::Namespace},
+    consensus::{
+        beacon::EpochTime,
+        scheduler::{Committee, CommitteeKind, CommitteeNode, Role},
+        state::{beacon::ImmutableState as BeaconState, roothash::ImmutableState as RoothashState},
+        verifier::{Error, Verifier},
+    },
+};
+
+/// Verified view of the executor committee of a runtime, as seen by the local node.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct CommitteeInfo {
+    /// Consensus layer height at which the committee was observed.
+    pub height: u64,
+    /// Current epoch.
+    pub epoch: EpochTime,
+    /// Round for which the committee is currently collecting commitments.
+    pub round: u64,
+    /// The executor committee.
+    pub committee: Committee,
+    /// Identifier of the local node.
+    pub node_id: PublicKey,
+}
+
+impl CommitteeInfo {
+    /// Create a new committee view for the given node and round.
+    pub fn new(
+        height: u64,
+        epoch: EpochTime,
+        round: u64,
+        committee: Committee,
+        node_id: PublicKey,
+    ) -> Self {
+        Self {
+            height,
+            epoch,
+            round,
+            committee,
+            node_id,
+        }
+    }
+
+    /// Epoch for which the committee is valid.
+    pub fn valid_for(&self) -> EpochTime {
+        self.committee.valid_for
+    }
+
+    /// Committee members.
+    pub fn members(&self) -> &[CommitteeNode] {
+        &self.committee.members
+    }
+
+    /// Roles of the local node in the committee.
+    ///
+    /// A node may be elected both as a worker and as a backup worker, in which case both roles are
+    /// returned. An empty list means that the node is not a member of the committee.
+    pub fn own_roles(&self) -> Vec<Role> {
+        self.committee
+            .members
+            .iter()
+            .filter(|member| member.public_key == self.node_id)
+            .map(|member| member.role.clone())
+            .collect()
+    }
+
+    /// Whether the local node is a member of the committee.
+    pub fn is_member(&self) -> bool {
+        self.committee.member(&self.node_id).is_some()
+    }
+
+    /// Whether the local node is a worker in the committee.
+    pub fn is_worker(&self) -> bool {
+        self.own_roles().contains(&Role::Worker)
+    }
+
+    /// Whether the local node is a backup worker in the committee.
+    pub fn is_backup_worker(&self) -> bool {
+        self.own_roles().contains(&Role::BackupWorker)
+    }
+
+    /// The primary (transaction scheduler) node for the current round.
+    pub fn primary(&self) -> Option<&CommitteeNode> {
+        self.committee.transaction_scheduler(self.round).ok()
+    }
+
+    /// Whether the local node is the primary (transaction scheduler) for the current round.
+    pub fn is_primary(&self) -> bool {
+        self.primary()
+            .map(|primary| primary.public_key == self.node_id)
+            .unwrap_or(false)
+    }
+}
+
+/// Fetch the executor committee of the given runtime from the latest verified consensus layer
+/// state and return a view of it from the perspective of the given node.
+pub async fn committee_info(
+    consensus_verifier: &dyn Verifier,
+    runtime_id: Namespace,
+    node_id: PublicKey,
+) -> Result<CommitteeInfo, Error> {
+    let consensus_state = consensus_verifier.latest_state().await?;
+    // TODO: Make this access async.
+    tokio::task::block_in_place(move || {
+        let height = consensus_state.height();
+        let epoch = BeaconState::new(&consensus_state)
+            .epoch()
+            .map_err(|err| Error::VerificationFailed(err.into()))?;
+        let runtime_state = RoothashState::new(&consensus_state)
+            .runtime_state(runtime_id)
+            .map_err(|err| Error::VerificationFailed(err.into()))?;
+        let committee = runtime_state.commitee.ok_or_else(|| {
+            Error::VerificationFailed(anyhow!("no executor committee configured"))
+        })?;
+        if committee.kind != CommitteeKind::ComputeExecutor {
+            return Err(Error::VerificationFailed(anyhow!("invalid committee kind")));
+        }
+        let round = runtime_state.last_block.header.round + 1;
+
+        Ok(CommitteeInfo::new(height, epoch, round, committee, node_id))
+    })
+}
+
+/// Tracker that detects executor committee changes at epoch transitions.
+#[derive(Default)]
+pub struct CommitteeWatcher {
+    current: Option<CommitteeInfo>,
+}
+
+impl CommitteeWatcher {
+    /// Create a new committee watcher.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Last observed committee information.
+    pub fn current(&self) -> Option<&CommitteeInfo> {
+        self.current.as_ref()
+    }
+
+    /// Update the watcher with freshly observed committee information.
+    ///
+    /// Returns `true` in case the committee has changed (e.g. a new committee has been elected
+    /// for a new epoch) since the last update.
+    pub fn update(&mut self, info: CommitteeInfo) -> bool {
+        let changed = match self.current {
+            Some(ref current) => current.committee != info.committee,
+            None => true,
+        };
+        self.current = Some(info);
+        changed
+    }
+
+    /// Fetch the latest committee information using the consensus verifier and update the
+    /// watcher.
+    ///
+    /// Returns the committee information in case it changed since the last update.
+    pub async fn refresh(
+        &mut self,
+        consensus_verifier: &dyn Verifier,
+        runtime_id: Namespace,
+        node_id: PublicKey,
+    ) -> Result<Option<&CommitteeInfo>, Error> {
+        let info = committee_info(consensus_verifier, runtime_id, node_id).await?;
+        if self.update(info) {
+            return Ok(self.current.as_ref());
+        }
+        Ok(None)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn node(id: u8) -> PublicKey {
+        PublicKey([id; 32])
+    }
+
+    fn committee(valid_for: EpochTime) -> Committee {
+        Committee {
+            kind: CommitteeKind::ComputeExecutor,
+            members: vec![
+                CommitteeNode {
+                    role: Role::Worker,
+                    public_key: node(1),
+                },
+                CommitteeNode {
+                    role: Role::Worker,
+                    public_key: node(2),
+                },
+                CommitteeNode {
+                    role: Role::BackupWorker,
+                    public_key: node(2),
+                },
+                CommitteeNode {
+                    role: Role::BackupWorker,
+                    public_key: node(3),
+                },
+            ],
+            runtime_id: Default::default(),
+            valid_for,
+        }
+    }
+
+    #[test]
+    fn test_committee_info_roles() {
+        let info = CommitteeInfo::new(10, 1, 4, committee(1), node(2));
+        assert!(info.is_member());
+        assert!(info.is_worker());
+        assert!(info.is_backup_worker());
+        assert_eq!(info.own_roles(), vec![Role::Worker, Role::BackupWorker]);
+
+        // Round 4 with 2 workers selects the first worker as the primary.
+        assert_eq!(info.primary().unwrap().public_key, node(1));
+        assert!(!info.is_primary());
+        let info = CommitteeInfo::new(10, 1, 5, committee(1), node(2));
+        assert!(info.is_primary());
+
+        let info = CommitteeInfo::new(10, 1, 5, committee(1), node(3));
+        assert!(info.is_member());
+        assert!(!info.is_worker());
+        assert!(info.is_backup_worker());
+        assert!(!info.is_primary());
+
+        let info = CommitteeInfo::new(10, 1, 5, committee(1), node(4));
+        assert!(!info.is_member());
+        assert!(info.own_roles().is_empty());
+    }
+
+    #[test]
+    fn test_committee_watcher() {
+        let mut watcher = CommitteeWatcher::new();
+        assert!(watcher.current().is_none());
+
+        assert!(watcher.update(CommitteeInfo::new(10, 1, 4, committee(1), node(1))));
+        // Same committee in a later round should not be reported as a change.
+        assert!(!watcher.update(CommitteeInfo::new(11, 1, 5, committee(1), node(1))));
+        assert_eq!(watcher.current().unwrap().round, 5);
+        // New committee elected at an epoch transition.
+        assert!(watcher.update(CommitteeInfo::new(20, 2, 6, committee(2), node(1))));
+        assert_eq!(watcher.current().unwrap().valid_for(), 2);
+    }
+}
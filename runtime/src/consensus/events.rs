@@ -0,0 +1,147 @@
+//! Consensus layer events relevant to the runtime.
+//!
+//! The host notifies the runtime about the kinds of relevant events that occurred at a given
+//! height (see `RegisterNotifyOpts::consensus_event`), so that runtimes need not poll consensus
+//! state for changes the node already knows about. As notifications are untrusted, the events
+//! are resolved against verified consensus state before being delivered to the application.
+//!
+//! Consensus events themselves cannot currently be verified (see `Verifier::events_at`), so
+//! events derived from them are delivered as untrusted.
+use anyhow::Result;
+
+use crate::{
+    common::{namespace::Namespace, quantity::Quantity},
+    consensus::{
+        address::Address,
+        registry::Runtime,
+        staking::EscrowEvent,
+        state::{
+            keymanager::{ImmutableState as KeyManagerState, Status as KeyManagerStatus},
+            registry::ImmutableState as RegistryState,
+        },
+        verifier::Verifier,
+        Event,
+    },
+    types::{ConsensusEventKind, EventKind},
+};
+
+/// Consensus layer event relevant to the runtime.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum RelevantEvent {
+    /// Stake escrowed into the runtime account.
+    ///
+    /// # Warning
+    ///
+    /// Escrow events are reported by the host and are not verified. They must only be used as a
+    /// hint, e.g. to trigger a query of the account in verified consensus state.
+    UntrustedRuntimeAccountEscrow { owner: Address, amount: Quantity },
+    /// Runtime descriptor, as updated, read from verified consensus state.
+    RuntimeDescriptorUpdate(Runtime),
+    /// Status of the runtime's key manager, as updated, read from verified consensus state.
+    KeyManagerStatusUpdate(KeyManagerStatus),
+}
+
+/// Resolve the relevant events of the given kinds that occurred at the given height, using
+/// verified consensus state where possible.
+pub async fn resolve(
+    verifier: &dyn Verifier,
+    runtime_id: &Namespace,
+    height: u64,
+    kinds: &[ConsensusEventKind],
+) -> Result<Vec<RelevantEvent>> {
+    let mut relevant = Vec::new();
+
+    if kinds.contains(&ConsensusEventKind::RuntimeAccountEscrow) {
+        let events = verifier.events_at(height, EventKind::Staking).await?;
+        relevant.extend(runtime_account_escrows(
+            events,
+            &Address::from_runtime_id(runtime_id),
+        ));
+    }
+
+    let descriptor_update = kinds.contains(&ConsensusEventKind::RuntimeDescriptorUpdate);
+    let km_status_update = kinds.contains(&ConsensusEventKind::KeyManagerStatusUpdate);
+    if descriptor_update || km_status_update {
+        let consensus_state = verifier.state_at(height).await?;
+        let runtime = RegistryState::new(&consensus_state).runtime(runtime_id)?;
+
+        if let Some(runtime) = runtime {
+            if let Some(km_id) = runtime.key_manager.filter(|_| km_status_update) {
+                if let Some(status) = KeyManagerState::new(&consensus_state).status(km_id)? {
+                    relevant.push(RelevantEvent::KeyManagerStatusUpdate(status));
+                }
+            }
+            if descriptor_update {
+                relevant.push(RelevantEvent::RuntimeDescriptorUpdate(runtime));
+            }
+        }
+    }
+
+    Ok(relevant)
+}
+
+/// Extract the stake escrowed into the given runtime account from the given events.
+fn runtime_account_escrows(events: Vec<Event>, address: &Address) -> Vec<RelevantEvent> {
+    events
+        .into_iter()
+        .filter_map(|event| match event {
+            Event::Staking(event) => event.escrow,
+        })
+        .filter_map(|escrow| match escrow {
+            EscrowEvent::Add {
+                owner,
+                escrow,
+                amount,
+                ..
+            } if &escrow == address => {
+                Some(RelevantEvent::UntrustedRuntimeAccountEscrow { owner, amount })
+            }
+            _ => None,
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::consensus::staking;
+
+    #[test]
+    fn test_runtime_account_escrows() {
+        let runtime = Address::from_runtime_id(&Namespace::from(vec![1; 32]));
+        let other = Address::from_runtime_id(&Namespace::from(vec![2; 32]));
+        let owner = Address::from_runtime_id(&Namespace::from(vec![3; 32]));
+
+        let escrow = |escrow: &Address, amount: u64| {
+            Event::Staking(staking::Event {
+                escrow: Some(EscrowEvent::Add {
+                    owner: owner.clone(),
+                    escrow: escrow.clone(),
+                    amount: amount.into(),
+                    new_shares: amount.into(),
+                }),
+                ..Default::default()
+            })
+        };
+        let events = vec![
+            escrow(&runtime, 10),
+            escrow(&other, 20),
+            Event::Staking(staking::Event::default()),
+            escrow(&runtime, 30),
+        ];
+
+        assert_eq!(
+            runtime_account_escrows(events, &runtime),
+            vec![
+                RelevantEvent::UntrustedRuntimeAccountEscrow {
+                    owner: owner.clone(),
+                    amount: 10u64.into(),
+                },
+                RelevantEvent::UntrustedRuntimeAccountEscrow {
+                    owner,
+                    amount: 30u64.into(),
+                },
+            ]
+        );
+    }
+}
@@ -5,7 +5,7 @@ use thiserror::Error;
 
 use crate::common::{
     crypto::{
-        signature::{Signature, SignatureBundle, Signer},
+        signature::{self, Signature, SignatureBundle, Signer},
         x25519,
     },
     namespace::Namespace,
@@ -17,16 +17,25 @@ use super::beacon::EpochTime;
 pub mod churp;
 
 /// Context used to sign key manager policies.
-const POLICY_SIGNATURE_CONTEXT: &[u8] = b"oasis-core/keymanager: policy";
+pub(crate) const POLICY_SIGNATURE_CONTEXT: &[u8] = b"oasis-core/keymanager: policy";
 
 /// Context used to sign encrypted key manager master secrets.
-const ENCRYPTED_MASTER_SECRET_SIGNATURE_CONTEXT: &[u8] =
+pub(crate) const ENCRYPTED_MASTER_SECRET_SIGNATURE_CONTEXT: &[u8] =
     b"oasis-core/keymanager: encrypted master secret";
 
 /// Context used to sign encrypted key manager ephemeral secrets.
-const ENCRYPTED_EPHEMERAL_SECRET_SIGNATURE_CONTEXT: &[u8] =
+pub(crate) const ENCRYPTED_EPHEMERAL_SECRET_SIGNATURE_CONTEXT: &[u8] =
     b"oasis-core/keymanager: encrypted ephemeral secret";
 
+/// The size of an application key namespace.
+pub const KEY_NAMESPACE_SIZE: usize = 32;
+
+impl_bytes!(
+    KeyNamespace,
+    KEY_NAMESPACE_SIZE,
+    "A 256-bit application key namespace."
+);
+
 /// Errors emitted by the key manager module.
 #[derive(Error, Debug)]
 pub enum Error {
@@ -44,6 +53,10 @@ pub struct PolicySGX {
     pub master_secret_rotation_interval: EpochTime,
     #[cbor(optional)]
     pub max_ephemeral_secret_age: EpochTime,
+    /// Emergency revocation list of enclave identities which must immediately be denied
+    /// sessions and key material, regardless of the rest of the policy.
+    #[cbor(optional)]
+    pub revoked: Vec<EnclaveIdentity>,
 }
 
 /// Per enclave key manager access control policy.
@@ -58,6 +71,14 @@ pub struct EnclavePolicySGX {
     /// NOTE: Each enclave ID may always implicitly replicate from other
     /// instances of itself.
     pub may_replicate: Vec<EnclaveIdentity>,
+
+    /// A map of runtime IDs to application key namespaces and the vector of enclave IDs that
+    /// may query private key material in each namespace.
+    ///
+    /// Private keys of runtimes listed here may only be queried within a namespace, so that
+    /// applications sharing a runtime cannot query each other's keys.
+    #[cbor(optional)]
+    pub may_query_namespaces: HashMap<Namespace, HashMap<KeyNamespace, Vec<EnclaveIdentity>>>,
 }
 
 /// Signed key manager access control policy.
@@ -133,6 +154,20 @@ impl SignedEncryptedMasterSecret {
         )?;
         Ok(Self { secret, signature })
     }
+
+    /// Sign the encrypted master secret using an asynchronous signer.
+    pub async fn new_async(
+        secret: EncryptedMasterSecret,
+        signer: &dyn signature::AsyncSigner,
+    ) -> Result<Self> {
+        let signature = signature::AsyncSigner::sign(
+            signer,
+            ENCRYPTED_MASTER_SECRET_SIGNATURE_CONTEXT,
+            &cbor::to_vec(secret.clone()),
+        )
+        .await?;
+        Ok(Self { secret, signature })
+    }
 }
 
 /// Signed encrypted ephemeral secret (RAK).
@@ -152,4 +187,18 @@ impl SignedEncryptedEphemeralSecret {
         )?;
         Ok(Self { secret, signature })
     }
+
+    /// Sign the encrypted ephemeral secret using an asynchronous signer.
+    pub async fn new_async(
+        secret: EncryptedEphemeralSecret,
+        signer: &dyn signature::AsyncSigner,
+    ) -> Result<Self> {
+        let signature = signature::AsyncSigner::sign(
+            signer,
+            ENCRYPTED_EPHEMERAL_SECRET_SIGNATURE_CONTEXT,
+            &cbor::to_vec(secret.clone()),
+        )
+        .await?;
+        Ok(Self { secret, signature })
+    }
 }
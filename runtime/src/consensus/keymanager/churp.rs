@@ -17,7 +17,7 @@ use crate::{
 };
 
 /// Context used to sign key manager CHURP policies.
-const POLICY_SIGNATURE_CONTEXT: &[u8] = b"oasis-core/keymanager/churp: policy";
+pub(crate) const POLICY_SIGNATURE_CONTEXT: &[u8] = b"oasis-core/keymanager/churp: policy";
 
 /// Errors emitted by the CHURP module.
 #[derive(Error, Debug)]
@@ -4,14 +4,20 @@ use crate::common::crypto::hash::Hash;
 
 pub mod address;
 pub mod beacon;
+pub mod checkpoint;
+pub mod client;
+pub mod committee;
+pub mod events;
 pub mod governance;
 pub mod keymanager;
+pub mod params;
 pub mod registry;
 pub mod roothash;
 pub mod scheduler;
 pub mod staking;
 pub mod state;
 pub mod tendermint;
+pub mod token;
 pub mod transaction;
 pub mod verifier;
 
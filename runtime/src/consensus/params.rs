@@ -0,0 +1,255 @@
+//! Verified runtime parameters.
+//!
+//! The executor and transaction scheduler parameters of a runtime (e.g. the maximum batch size or
+//! the maximum number of emitted messages) are part of its descriptor in the consensus registry
+//! and may be changed by its governance at any time. Runtimes validating against compiled-in
+//! copies of these limits would start to disagree with the consensus layer once they change, so
+//! they should use [`ConsensusClient::runtime_parameters`] to read them from verified consensus
+//! state instead, and a [`RuntimeParametersWatcher`] to be notified of changes.
+//!
+//! The limits apply to a round as registered at the consensus height of the round, so they must
+//! always be read at that height rather than at the latest height.
+use thiserror::Error;
+use tokio::sync::watch;
+
+use crate::{
+    common::namespace::Namespace,
+    types::{self, messages::MessagesBuilder},
+};
+
+use super::{
+    client::ConsensusClient,
+    registry::{ExecutorParameters, Runtime, TxnSchedulerParameters},
+    state::{registry::ImmutableState as RegistryState, ConsensusState, StateError},
+    verifier::Error,
+};
+
+/// Module of runtime limit errors.
+pub const MODULE_NAME: &str = "consensus/params";
+
+/// Errors returned when runtime limits are exceeded.
+#[derive(Error, Debug, Clone, PartialEq, Eq)]
+pub enum LimitError {
+    #[error("batch of {count} transactions exceeds the limit of {max}")]
+    BatchTooLarge { count: u64, max: u64 },
+
+    #[error("batch of {size} bytes exceeds the limit of {max} bytes")]
+    BatchTooLargeBytes { size: u64, max: u64 },
+
+    #[error("{count} incoming messages exceed the limit of {max}")]
+    TooManyIncomingMessages { count: u32, max: u32 },
+}
+
+impl LimitError {
+    fn code(&self) -> u32 {
+        match self {
+            Self::BatchTooLarge { .. } => 1,
+            Self::BatchTooLargeBytes { .. } => 2,
+            Self::TooManyIncomingMessages { .. } => 3,
+        }
+    }
+}
+
+impl From<LimitError> for types::Error {
+    fn from(err: LimitError) -> Self {
+        Self {
+            module: MODULE_NAME.to_string(),
+            code: err.code(),
+            message: err.to_string(),
+        }
+    }
+}
+
+/// Executor and transaction scheduler parameters of a runtime, as registered in the consensus
+/// layer.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct RuntimeParameters {
+    /// Consensus layer height the parameters were read at.
+    pub height: u64,
+    /// Parameters of the executor committee.
+    pub executor: ExecutorParameters,
+    /// Parameters of the transaction scheduler.
+    pub txn_scheduler: TxnSchedulerParameters,
+}
+
+impl RuntimeParameters {
+    /// Parameters of the given runtime descriptor, read at the given height.
+    pub fn from_runtime(height: u64, runtime: &Runtime) -> Self {
+        Self {
+            height,
+            executor: runtime.executor.clone(),
+            txn_scheduler: runtime.txn_scheduler.clone(),
+        }
+    }
+
+    /// Parameters of the runtime with the given identifier in the given verified consensus state,
+    /// if the runtime is registered.
+    pub fn from_state(state: &ConsensusState, id: &Namespace) -> Result<Option<Self>, StateError> {
+        let runtime = RegistryState::new(state).runtime(id)?;
+        Ok(runtime.map(|runtime| Self::from_runtime(state.height(), &runtime)))
+    }
+
+    /// Whether the given parameters are the same as these, regardless of the height they were
+    /// read at.
+    pub fn same_limits(&self, other: &RuntimeParameters) -> bool {
+        self.executor == other.executor && self.txn_scheduler == other.txn_scheduler
+    }
+
+    /// Verify that a batch of the given number of transactions with the given total size fits
+    /// into the batch limits.
+    pub fn check_batch(&self, count: usize, size: u64) -> Result<(), LimitError> {
+        let max = self.txn_scheduler.max_batch_size;
+        if count as u64 > max {
+            return Err(LimitError::BatchTooLarge {
+                count: count as u64,
+                max,
+            });
+        }
+        let max = self.txn_scheduler.max_batch_size_bytes;
+        if size > max {
+            return Err(LimitError::BatchTooLargeBytes { size, max });
+        }
+        Ok(())
+    }
+
+    /// Verify that the given number of incoming messages fits into the incoming message queue.
+    pub fn check_in_messages(&self, count: usize) -> Result<(), LimitError> {
+        let max = self.txn_scheduler.max_in_messages;
+        if count > max as usize {
+            return Err(LimitError::TooManyIncomingMessages {
+                count: count.try_into().unwrap_or(u32::MAX),
+                max,
+            });
+        }
+        Ok(())
+    }
+
+    /// Builder of the messages emitted in a round, limited to the maximum number of messages.
+    pub fn messages_builder(&self) -> MessagesBuilder {
+        MessagesBuilder::new(self.executor.max_messages)
+    }
+}
+
+/// Watcher of the verified parameters of a runtime.
+pub struct RuntimeParametersWatcher {
+    client: ConsensusClient,
+    runtime_id: Namespace,
+    tx: watch::Sender<Option<RuntimeParameters>>,
+}
+
+impl RuntimeParametersWatcher {
+    /// Create a new watcher of the parameters of the given runtime. The parameters are not known
+    /// until the watcher is first refreshed.
+    pub fn new(client: ConsensusClient, runtime_id: Namespace) -> Self {
+        Self {
+            client,
+            runtime_id,
+            tx: watch::Sender::new(None),
+        }
+    }
+
+    /// Latest known parameters, if any.
+    pub fn current(&self) -> Option<RuntimeParameters> {
+        self.tx.borrow().clone()
+    }
+
+    /// Subscribe to parameter changes.
+    ///
+    /// Subscribers are only notified when the limits change, not when the same parameters are
+    /// read at a later height.
+    pub fn subscribe(&self) -> watch::Receiver<Option<RuntimeParameters>> {
+        self.tx.subscribe()
+    }
+
+    /// Read the parameters from the verified consensus state at the given height, returning
+    /// whether they changed. The height should be the consensus height of the round being
+    /// processed, so that the published parameters are the ones applying to that round.
+    ///
+    /// Runtimes which are not registered have no parameters, so nothing is published for them.
+    pub async fn refresh(&self, height: u64) -> Result<bool, Error> {
+        let params = self
+            .client
+            .runtime_parameters(height, self.runtime_id)
+            .await?;
+        Ok(params.is_some_and(|params| publish(&self.tx, params)))
+    }
+}
+
+/// Publish the given parameters, returning whether the limits changed.
+fn publish(tx: &watch::Sender<Option<RuntimeParameters>>, params: RuntimeParameters) -> bool {
+    tx.send_if_modified(|current| match current {
+        // Keep track of the height without notifying subscribers.
+        Some(current) if current.same_limits(&params) => {
+            current.height = current.height.max(params.height);
+            false
+        }
+        // Ignore parameters read at an earlier height than the current ones.
+        Some(current) if current.height > params.height => false,
+        _ => {
+            *current = Some(params);
+            true
+        }
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn params(height: u64, max_batch_size: u64) -> RuntimeParameters {
+        RuntimeParameters {
+            height,
+            executor: ExecutorParameters {
+                max_messages: 2,
+                ..Default::default()
+            },
+            txn_scheduler: TxnSchedulerParameters {
+                max_batch_size,
+                max_batch_size_bytes: 1024,
+                max_in_messages: 4,
+                ..Default::default()
+            },
+        }
+    }
+
+    #[test]
+    fn test_limits() {
+        let params = params(1, 10);
+        assert_eq!(params.check_batch(10, 1024), Ok(()));
+        assert_eq!(
+            params.check_batch(11, 0),
+            Err(LimitError::BatchTooLarge { count: 11, max: 10 })
+        );
+        assert_eq!(
+            params.check_batch(1, 1025),
+            Err(LimitError::BatchTooLargeBytes {
+                size: 1025,
+                max: 1024
+            })
+        );
+        assert_eq!(params.check_in_messages(4), Ok(()));
+        assert!(params.check_in_messages(5).is_err());
+    }
+
+    #[test]
+    fn test_publish() {
+        let (tx, mut rx) = watch::channel(None);
+        assert!(publish(&tx, params(1, 10)));
+        assert!(rx.has_changed().unwrap());
+        rx.mark_unchanged();
+
+        // Only changes of the limits are published.
+        assert!(!publish(&tx, params(2, 10)));
+        assert!(!rx.has_changed().unwrap());
+        assert_eq!(rx.borrow().as_ref().unwrap().height, 2);
+        assert!(publish(&tx, params(3, 20)));
+        assert!(rx.has_changed().unwrap());
+
+        // Stale parameters are ignored.
+        assert!(!publish(&tx, params(2, 10)));
+        assert_eq!(
+            rx.borrow().as_ref().unwrap().txn_scheduler.max_batch_size,
+            20
+        );
+    }
+}
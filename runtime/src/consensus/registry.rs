@@ -34,6 +34,12 @@ pub const METHOD_PROVE_FRESHNESS: &str = "registry.ProveFreshness";
 /// Attestation signature context.
 pub const ATTESTATION_SIGNATURE_CONTEXT: &[u8] = b"oasis-core/node: TEE attestation signature";
 
+/// Entity descriptor registration signature context.
+pub const REGISTER_ENTITY_SIGNATURE_CONTEXT: &[u8] = b"oasis-core/registry: register entity";
+
+/// Node descriptor registration signature context.
+pub const REGISTER_NODE_SIGNATURE_CONTEXT: &[u8] = b"oasis-core/registry: register node";
+
 /// TEE capability endorsement signature context.
 pub const ENDORSE_CAPABILITY_TEE_SIGNATURE_CONTEXT: &[u8] =
     b"oasis-core/node: endorse TEE capability";
@@ -352,6 +358,20 @@ impl Default for RolesMask {
     }
 }
 
+/// Entity registry descriptor.
+#[derive(Clone, Debug, Default, PartialEq, Eq, cbor::Encode, cbor::Decode)]
+pub struct Entity {
+    /// Structure version.
+    pub v: u16,
+
+    /// Public key identifying the entity.
+    pub id: signature::PublicKey,
+
+    /// Public keys identifying the nodes controlled by the entity.
+    #[cbor(optional)]
+    pub nodes: Vec<signature::PublicKey>,
+}
+
 /// Node registry descriptor.
 #[derive(Clone, Debug, Default, PartialEq, Eq, cbor::Encode, cbor::Decode)]
 pub struct Node {
@@ -7,8 +7,8 @@ use crate::{
         crypto::{
             hash::Hash,
             signature::{
-                signature_context_with_chain_separation, signature_context_with_runtime_separation,
-                PublicKey, Signature, Signer,
+                self, signature_context_with_chain_separation,
+                signature_context_with_runtime_separation, PublicKey, Signature, Signer,
             },
         },
         namespace::Namespace,
@@ -127,6 +127,19 @@ impl ExecutorCommitmentHeader {
         signer.sign(&context, &message)
     }
 
+    /// Signs the executor commitment header using an asynchronous signer.
+    pub async fn sign_async(
+        &self,
+        signer: &(impl signature::AsyncSigner + ?Sized),
+        runtime_id: &Namespace,
+        chain_context: &String,
+    ) -> Result<Signature> {
+        let context = executor_commitment_signature_context(runtime_id, chain_context);
+        let message = cbor::to_vec(self.clone());
+
+        signature::AsyncSigner::sign(signer, &context, &message).await
+    }
+
     /// Verifies the RAK signature.
     pub fn verify_rak(&self, rak: PublicKey) -> Result<()> {
         let sig = self.rak_signature.ok_or(anyhow!("missing RAK signature"))?;
@@ -166,7 +179,30 @@ impl ExecutorCommitment {
         runtime_id: &Namespace,
         chain_context: &String,
     ) -> Result<()> {
-        let pk = signer.public();
+        self.ensure_signer(signer.public())?;
+        self.signature = self.header.sign(signer, runtime_id, chain_context)?;
+
+        Ok(())
+    }
+
+    /// Signs the executor commitment header using an asynchronous signer and sets the signature
+    /// on the commitment.
+    pub async fn sign_async(
+        &mut self,
+        signer: &(impl signature::AsyncSigner + ?Sized),
+        runtime_id: &Namespace,
+        chain_context: &String,
+    ) -> Result<()> {
+        self.ensure_signer(signature::AsyncSigner::public(signer))?;
+        self.signature = self
+            .header
+            .sign_async(signer, runtime_id, chain_context)
+            .await?;
+
+        Ok(())
+    }
+
+    fn ensure_signer(&self, pk: PublicKey) -> Result<()> {
         if self.node_id != pk {
             return Err(anyhow!(
                 "node ID does not match signer (ID: {} signer: {})",
@@ -174,9 +210,6 @@ impl ExecutorCommitment {
                 pk,
             ));
         }
-
-        self.signature = self.header.sign(signer, runtime_id, chain_context)?;
-
         Ok(())
     }
 
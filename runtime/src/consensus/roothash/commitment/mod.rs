@@ -11,10 +11,12 @@ use crate::common::crypto::hash::Hash;
 // Modules.
 mod executor;
 mod pool;
+mod proposal;
 
 // Re-exports.
 pub use executor::*;
 pub use pool::*;
+pub use proposal::*;
 
 /// Verified roothash commitment.
 pub trait OpenCommitment {
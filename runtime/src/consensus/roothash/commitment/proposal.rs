@@ -0,0 +1,84 @@
+use anyhow::{anyhow, Result};
+
+use crate::common::{
+    crypto::{
+        hash::Hash,
+        signature::{
+            signature_context_with_chain_separation, signature_context_with_runtime_separation,
+            PublicKey, Signature, Signer,
+        },
+    },
+    namespace::Namespace,
+};
+
+/// The signature context used to sign proposals.
+pub const PROPOSAL_SIGNATURE_CONTEXT: &[u8] = b"oasis-core/roothash: proposal";
+
+fn proposal_signature_context(runtime_id: &Namespace, chain_context: &String) -> Vec<u8> {
+    let context = PROPOSAL_SIGNATURE_CONTEXT.to_vec();
+    let context = signature_context_with_runtime_separation(context, runtime_id);
+    signature_context_with_chain_separation(context, chain_context)
+}
+
+/// The header of a batch proposal.
+///
+/// # Note
+///
+/// This should be kept in sync with go/roothash/api/commitment/proposal.go.
+#[derive(Clone, Debug, Default, PartialEq, Eq, cbor::Encode, cbor::Decode)]
+pub struct ProposalHeader {
+    /// Round number.
+    pub round: u64,
+    /// Hash of the previous block header the batch is proposed against.
+    pub previous_hash: Hash,
+    /// Hash of the proposed batch.
+    pub batch_hash: Hash,
+}
+
+/// A batch proposal signed by the proposer.
+#[derive(Clone, Debug, Default, PartialEq, Eq, cbor::Encode, cbor::Decode)]
+pub struct Proposal {
+    /// The public key of the node that generated this proposal.
+    pub node_id: PublicKey,
+
+    /// The proposal header.
+    pub header: ProposalHeader,
+
+    /// The proposal header signature.
+    #[cbor(rename = "sig")]
+    pub signature: Signature,
+}
+
+impl Proposal {
+    /// Signs the proposal header and sets the signature on the proposal.
+    pub fn sign(
+        &mut self,
+        signer: &impl Signer,
+        runtime_id: &Namespace,
+        chain_context: &String,
+    ) -> Result<()> {
+        if self.node_id != signer.public() {
+            return Err(anyhow!(
+                "node ID does not match signer (ID: {} signer: {})",
+                self.node_id,
+                signer.public(),
+            ));
+        }
+
+        let context = proposal_signature_context(runtime_id, chain_context);
+        let message = cbor::to_vec(self.header.clone());
+        self.signature = signer.sign(&context, &message)?;
+
+        Ok(())
+    }
+
+    /// Verifies that the header signature is valid.
+    pub fn verify(&self, runtime_id: &Namespace, chain_context: &String) -> Result<()> {
+        let context = proposal_signature_context(runtime_id, chain_context);
+        let message = cbor::to_vec(self.header.clone());
+
+        self.signature
+            .verify(&self.node_id, &context, &message)
+            .map_err(|_| anyhow!("roothash/commitment: signature verification failed"))
+    }
+}
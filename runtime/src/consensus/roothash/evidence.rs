@@ -0,0 +1,267 @@
+use anyhow::{anyhow, Result};
+
+use crate::common::{crypto::signature::PublicKey, namespace::Namespace};
+
+use super::{ExecutorCommitment, Proposal};
+
+/// Evidence of a node signing two different executor commitments for the same round.
+#[derive(Clone, Debug, Default, PartialEq, Eq, cbor::Encode, cbor::Decode)]
+pub struct EquivocationExecutorEvidence {
+    pub commit_a: ExecutorCommitment,
+    pub commit_b: ExecutorCommitment,
+}
+
+/// Evidence of a proposer signing two different batch proposals for the same round.
+#[derive(Clone, Debug, Default, PartialEq, Eq, cbor::Encode, cbor::Decode)]
+pub struct EquivocationProposalEvidence {
+    #[cbor(rename = "prop_a")]
+    pub proposal_a: Proposal,
+    #[cbor(rename = "prop_b")]
+    pub proposal_b: Proposal,
+}
+
+/// Evidence of runtime node misbehavior, submitted to the consensus layer.
+///
+/// Exactly one kind of evidence must be set.
+///
+/// # Note
+///
+/// This should be kept in sync with go/roothash/api/evidence.go.
+#[derive(Clone, Debug, Default, PartialEq, Eq, cbor::Encode, cbor::Decode)]
+pub struct Evidence {
+    /// Identifier of the runtime the evidence is for.
+    pub id: Namespace,
+
+    #[cbor(optional)]
+    pub equivocation_executor: Option<EquivocationExecutorEvidence>,
+
+    #[cbor(optional, rename = "equivocation_prop")]
+    pub equivocation_proposal: Option<EquivocationProposalEvidence>,
+}
+
+impl Evidence {
+    /// Evidence of executor commitment equivocation by a node of the given runtime.
+    pub fn executor_equivocation(
+        runtime_id: Namespace,
+        commit_a: ExecutorCommitment,
+        commit_b: ExecutorCommitment,
+    ) -> Self {
+        Self {
+            id: runtime_id,
+            equivocation_executor: Some(EquivocationExecutorEvidence {
+                // Runtime messages must be omitted for commitments submitted as evidence.
+                commit_a: ExecutorCommitment {
+                    messages: vec![],
+                    ..commit_a
+                },
+                commit_b: ExecutorCommitment {
+                    messages: vec![],
+                    ..commit_b
+                },
+            }),
+            ..Default::default()
+        }
+    }
+
+    /// Evidence of proposal equivocation by a proposer of the given runtime.
+    pub fn proposal_equivocation(
+        runtime_id: Namespace,
+        proposal_a: Proposal,
+        proposal_b: Proposal,
+    ) -> Self {
+        Self {
+            id: runtime_id,
+            equivocation_proposal: Some(EquivocationProposalEvidence {
+                proposal_a,
+                proposal_b,
+            }),
+            ..Default::default()
+        }
+    }
+
+    /// Public key of the node the evidence is against, in case exactly one kind of evidence
+    /// is set.
+    pub fn node_id(&self) -> Option<PublicKey> {
+        match (&self.equivocation_executor, &self.equivocation_proposal) {
+            (Some(ev), None) => Some(ev.commit_a.node_id),
+            (None, Some(ev)) => Some(ev.proposal_a.node_id),
+            _ => None,
+        }
+    }
+
+    /// Verifies that the evidence proves misbehavior, so that submitting it to the consensus
+    /// layer will not be rejected.
+    pub fn verify(&self, chain_context: &String) -> Result<()> {
+        match (&self.equivocation_executor, &self.equivocation_proposal) {
+            (Some(ev), None) => ev.verify(&self.id, chain_context),
+            (None, Some(ev)) => ev.verify(&self.id, chain_context),
+            _ => Err(anyhow!(
+                "roothash/evidence: exactly one kind of evidence must be set"
+            )),
+        }
+    }
+}
+
+impl EquivocationExecutorEvidence {
+    fn verify(&self, runtime_id: &Namespace, chain_context: &String) -> Result<()> {
+        let (a, b) = (&self.commit_a, &self.commit_b);
+        if a.node_id != b.node_id {
+            return Err(anyhow!(
+                "roothash/evidence: commitments from different nodes"
+            ));
+        }
+        if a.header.header.round != b.header.header.round {
+            return Err(anyhow!(
+                "roothash/evidence: commitments for different rounds"
+            ));
+        }
+        if a.header.header.previous_hash != b.header.header.previous_hash {
+            return Err(anyhow!(
+                "roothash/evidence: commitments for different previous blocks"
+            ));
+        }
+        if a.header.header == b.header.header && a.header.failure == b.header.failure {
+            return Err(anyhow!("roothash/evidence: commitments are equal"));
+        }
+        for commit in [a, b] {
+            commit.validate_basic()?;
+            commit.verify(runtime_id, chain_context)?;
+        }
+
+        Ok(())
+    }
+}
+
+impl EquivocationProposalEvidence {
+    fn verify(&self, runtime_id: &Namespace, chain_context: &String) -> Result<()> {
+        let (a, b) = (&self.proposal_a, &self.proposal_b);
+        if a.node_id != b.node_id {
+            return Err(anyhow!("roothash/evidence: proposals from different nodes"));
+        }
+        if a.header.round != b.header.round {
+            return Err(anyhow!("roothash/evidence: proposals for different rounds"));
+        }
+        if a.header.previous_hash != b.header.previous_hash {
+            return Err(anyhow!(
+                "roothash/evidence: proposals for different previous blocks"
+            ));
+        }
+        if a.header.batch_hash == b.header.batch_hash {
+            return Err(anyhow!("roothash/evidence: proposals are equal"));
+        }
+        for proposal in [a, b] {
+            proposal.verify(runtime_id, chain_context)?;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        common::crypto::{hash::Hash, signature::PrivateKey},
+        consensus::roothash::{
+            ComputeResultsHeader, ExecutorCommitmentFailure, ExecutorCommitmentHeader,
+            ProposalHeader,
+        },
+    };
+
+    #[test]
+    fn test_executor_equivocation() {
+        let runtime_id = Namespace::from(vec![1; 32]);
+        let chain_context = "chain".to_string();
+        let sk = PrivateKey::from_test_seed("executor".to_string());
+
+        let commitment = |sk: &PrivateKey, round: u64, state: &[u8]| {
+            let mut commitment = ExecutorCommitment {
+                node_id: sk.public_key(),
+                header: ExecutorCommitmentHeader {
+                    header: ComputeResultsHeader {
+                        round,
+                        previous_hash: Hash::digest_bytes(b"previous"),
+                        io_root: Some(Hash::empty_hash()),
+                        state_root: Some(Hash::digest_bytes(state)),
+                        messages_hash: Some(Hash::empty_hash()),
+                        in_msgs_hash: Some(Hash::empty_hash()),
+                        in_msgs_count: 0,
+                    },
+                    failure: ExecutorCommitmentFailure::FailureNone,
+                    rak_signature: None,
+                },
+                ..Default::default()
+            };
+            commitment.sign(sk, &runtime_id, &chain_context).unwrap();
+            commitment
+        };
+
+        let evidence = Evidence::executor_equivocation(
+            runtime_id,
+            commitment(&sk, 10, b"a"),
+            commitment(&sk, 10, b"b"),
+        );
+        assert_eq!(evidence.node_id(), Some(sk.public_key()));
+        evidence.verify(&chain_context).unwrap();
+
+        // Signatures are bound to the runtime and the chain.
+        assert!(evidence.verify(&"other".to_string()).is_err());
+        let mut other = evidence.clone();
+        other.id = Namespace::from(vec![2; 32]);
+        assert!(other.verify(&chain_context).is_err());
+
+        // Commitments must be different, for the same round and by the same node.
+        let other_sk = PrivateKey::from_test_seed("other executor".to_string());
+        for (a, b) in [
+            (commitment(&sk, 10, b"a"), commitment(&sk, 10, b"a")),
+            (commitment(&sk, 10, b"a"), commitment(&sk, 11, b"b")),
+            (commitment(&sk, 10, b"a"), commitment(&other_sk, 10, b"b")),
+        ] {
+            let evidence = Evidence::executor_equivocation(runtime_id, a, b);
+            assert!(evidence.verify(&chain_context).is_err());
+        }
+
+        // Evidence of both kinds at once is rejected.
+        let mut both = evidence;
+        both.equivocation_proposal = Some(Default::default());
+        assert_eq!(both.node_id(), None);
+        assert!(both.verify(&chain_context).is_err());
+    }
+
+    #[test]
+    fn test_proposal_equivocation() {
+        let runtime_id = Namespace::from(vec![1; 32]);
+        let chain_context = "chain".to_string();
+        let sk = PrivateKey::from_test_seed("proposer".to_string());
+
+        let proposal = |batch: &[u8]| {
+            let mut proposal = Proposal {
+                node_id: sk.public_key(),
+                header: ProposalHeader {
+                    round: 10,
+                    previous_hash: Hash::digest_bytes(b"previous"),
+                    batch_hash: Hash::digest_bytes(batch),
+                },
+                ..Default::default()
+            };
+            proposal.sign(&sk, &runtime_id, &chain_context).unwrap();
+            proposal
+        };
+
+        let evidence = Evidence::proposal_equivocation(runtime_id, proposal(b"a"), proposal(b"b"));
+        assert_eq!(evidence.node_id(), Some(sk.public_key()));
+        evidence.verify(&chain_context).unwrap();
+
+        // Signatures are bound to the runtime and the chain.
+        assert!(evidence.verify(&"other".to_string()).is_err());
+        let mut other = evidence.clone();
+        other.id = Namespace::from(vec![2; 32]);
+        assert!(other.verify(&chain_context).is_err());
+
+        // Equal proposals are not equivocation.
+        let evidence = Evidence::proposal_equivocation(runtime_id, proposal(b"a"), proposal(b"a"));
+        assert!(evidence.verify(&chain_context).is_err());
+
+        assert!(Evidence::default().verify(&chain_context).is_err());
+    }
+}
@@ -17,11 +17,13 @@ use crate::{
 // Modules.
 mod block;
 mod commitment;
+mod evidence;
 mod message;
 
 // Re-exports.
 pub use block::*;
 pub use commitment::*;
+pub use evidence::*;
 pub use message::*;
 
 /// Errors emitted by the roothash module.
@@ -69,6 +69,21 @@ impl Committee {
             .collect()
     }
 
+    /// Returns committee nodes with BackupWorker role.
+    pub fn backup_workers(&self) -> Vec<&CommitteeNode> {
+        self.members
+            .iter()
+            .filter(|&member| member.role == Role::BackupWorker)
+            .collect()
+    }
+
+    /// Returns the first committee entry of the given node, if the node is a member.
+    pub fn member(&self, public_key: &PublicKey) -> Option<&CommitteeNode> {
+        self.members
+            .iter()
+            .find(|&member| &member.public_key == public_key)
+    }
+
     /// Returns the transaction scheduler of the provided committee based on the provided round.
     pub fn transaction_scheduler(&self, round: u64) -> Result<&CommitteeNode> {
         let workers = self.workers();
@@ -8,9 +8,25 @@ use std::collections::BTreeMap;
 
 use crate::{
     common::{crypto::hash::Hash, quantity::Quantity},
-    consensus::{address::Address, beacon::EpochTime},
+    consensus::{
+        address::Address,
+        beacon::EpochTime,
+        token::{self, TokenAmount},
+        transaction::Gas,
+    },
 };
 
+/// Gas operation identifier for transfers.
+pub const GAS_OP_TRANSFER: &str = "transfer";
+/// Gas operation identifier for burns.
+pub const GAS_OP_BURN: &str = "burn";
+/// Gas operation identifier for adding escrow.
+pub const GAS_OP_ADD_ESCROW: &str = "add_escrow";
+/// Gas operation identifier for reclaiming escrow.
+pub const GAS_OP_RECLAIM_ESCROW: &str = "reclaim_escrow";
+/// Gas operation identifier for withdrawals.
+pub const GAS_OP_WITHDRAW: &str = "withdraw";
+
 /// A stake transfer.
 #[derive(Clone, Debug, Default, PartialEq, Eq, Hash, cbor::Encode, cbor::Decode)]
 pub struct Transfer {
@@ -18,6 +34,16 @@ pub struct Transfer {
     pub amount: Quantity,
 }
 
+impl Transfer {
+    /// Create a new transfer of the given token amount, converting it to consensus base units.
+    pub fn new(to: Address, amount: &TokenAmount) -> Result<Self, token::Error> {
+        Ok(Self {
+            to,
+            amount: amount.to_consensus_base_units()?,
+        })
+    }
+}
+
 /// A withdrawal from an account.
 #[derive(Clone, Debug, Default, PartialEq, Eq, Hash, cbor::Encode, cbor::Decode)]
 pub struct Withdraw {
@@ -191,6 +217,17 @@ pub struct Slash {
     pub freeze_interval: EpochTime,
 }
 
+/// Staking consensus parameters.
+///
+/// Only the parameters relevant to runtimes are decoded.
+#[derive(Clone, Debug, Default, PartialEq, Eq, cbor::Encode, cbor::Decode)]
+#[cbor(allow_unknown)]
+pub struct ConsensusParameters {
+    /// Gas costs of staking operations, by operation identifier.
+    #[cbor(optional)]
+    pub gas_costs: BTreeMap<String, Gas>,
+}
+
 /// Transfer result.
 #[derive(Clone, Debug, Default, PartialEq, Eq, Hash, cbor::Encode, cbor::Decode)]
 pub struct TransferResult {
@@ -12,7 +12,7 @@ use crate::{
         keymanager::{
             SignedEncryptedEphemeralSecret, SignedEncryptedMasterSecret, SignedPolicySGX,
         },
-        state::StateError,
+        state::{prefetch_namespace, StateError},
     },
     key_format,
     storage::mkvs::ImmutableMKVS,
@@ -72,8 +72,11 @@ impl<T: ImmutableMKVS> ImmutableState<'_, T> {
 
     /// Returns the list of all key manager statuses.
     pub fn statuses(&self) -> Result<Vec<Status>, StateError> {
+        let prefix = StatusKeyFmt::default().encode_partial(0);
+        prefetch_namespace(self.mkvs, prefix.clone());
+
         let mut it = self.mkvs.iter();
-        it.seek(&StatusKeyFmt::default().encode_partial(0));
+        it.seek(&prefix);
 
         let mut result: Vec<Status> = Vec::new();
 
@@ -245,10 +248,12 @@ mod test {
                             EnclavePolicySGX {
                                 may_query: HashMap::from([(runtime, vec![runtime_enclave])]),
                                 may_replicate: vec![keymanager_enclave2],
+                                may_query_namespaces: HashMap::new(),
                             },
                         )]),
                         master_secret_rotation_interval: 0,
                         max_ephemeral_secret_age: 10,
+                        revoked: vec![],
                     },
                     signatures: vec![
                         SignatureBundle {
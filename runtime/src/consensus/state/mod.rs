@@ -1,12 +1,16 @@
 //! Consensus state wrappers.
-use std::sync::Arc;
+use std::{
+    collections::BTreeSet,
+    sync::{Arc, Mutex},
+};
 
 use anyhow::{Error, Result};
 use thiserror::Error;
 
 use crate::{
+    common::crypto::hash::Hash,
     protocol::Protocol,
-    storage::mkvs::{sync::HostReadSyncer, ImmutableMKVS, Root, Tree},
+    storage::mkvs::{self, sync::HostReadSyncer, ImmutableMKVS, Key, Root, Tree},
     types::{self, HostStorageEndpoint},
 };
 
@@ -16,10 +20,24 @@ pub mod registry;
 pub mod roothash;
 pub mod staking;
 
+/// Maximum size of proofs fetched when prefetching all keys of a namespace.
+const NAMESPACE_PREFETCH_SIZE: u64 = 4 * 1024 * 1024;
+
+/// Prefetch all keys under the given namespace prefix, up to `NAMESPACE_PREFETCH_SIZE`.
+///
+/// This only serves to avoid fetching nodes one by one while the namespace is being iterated,
+/// so failures are ignored and any missing nodes are fetched on demand.
+fn prefetch_namespace<T: ImmutableMKVS>(mkvs: &T, prefix: Vec<u8>) {
+    let _ = mkvs.prefetch_prefix(&prefix.into(), NAMESPACE_PREFETCH_SIZE);
+}
+
 #[derive(Error, Debug)]
 pub enum StateError {
     #[error("consensus state: unavailable/corrupted state: {0}")]
     Unavailable(#[from] Error),
+
+    #[error("consensus state: too many entries to scan (limit: {0})")]
+    TooManyEntries(usize),
 }
 
 impl From<StateError> for types::Error {
@@ -38,12 +56,17 @@ pub struct ConsensusState {
     // height and the corresponding state root is a consensus backend implementation detail.
     height: u64,
     mkvs: Tree,
+    reads: Option<ReadTracker>,
 }
 
 impl ConsensusState {
     /// Creates a consensus state wrapping the provided tree.
     pub fn new(height: u64, tree: Tree) -> Self {
-        Self { height, mkvs: tree }
+        Self {
+            height,
+            mkvs: tree,
+            reads: None,
+        }
     }
 
     /// Creates consensus state using host protocol.
@@ -55,6 +78,7 @@ impl ConsensusState {
                 .with_capacity(100_000, 10_000_000)
                 .with_root(root)
                 .build(Box::new(read_syncer)),
+            reads: None,
         }
     }
 
@@ -62,14 +86,142 @@ impl ConsensusState {
     pub fn height(&self) -> u64 {
         self.height
     }
+
+    /// Start tracking the keys read from the consensus state, returning the tracker.
+    pub fn track_reads(&mut self) -> ReadTracker {
+        let height = self.height;
+        self.reads
+            .get_or_insert_with(|| ReadTracker::new(height))
+            .clone()
+    }
+
+    fn record_read(&self, key: &[u8]) {
+        if let Some(reads) = self.reads.as_ref() {
+            reads.record(key);
+        }
+    }
+
+    fn tracked_iter<'a>(&'a self) -> Box<dyn mkvs::Iterator + 'a> {
+        let inner: Box<dyn mkvs::Iterator + 'a> = Box::new(self.mkvs.iter());
+        match self.reads.as_ref() {
+            Some(reads) => Box::new(TrackingIterator {
+                inner,
+                reads: reads.clone(),
+            }),
+            None => inner,
+        }
+    }
+}
+
+/// Consensus state read during the processing of a request.
+#[derive(Clone, Debug, Default, PartialEq, Eq, cbor::Encode, cbor::Decode)]
+pub struct ReadReport {
+    /// Consensus layer height of the state.
+    pub height: u64,
+    /// Sorted keys read from the state, including keys that were looked up but not present.
+    pub keys: Vec<Vec<u8>>,
+    /// Digest of the read keys.
+    pub digest: Hash,
+}
+
+/// Tracker of the keys read from a consensus state.
+#[derive(Clone)]
+pub struct ReadTracker {
+    height: u64,
+    keys: Arc<Mutex<BTreeSet<Vec<u8>>>>,
+}
+
+impl ReadTracker {
+    fn new(height: u64) -> Self {
+        Self {
+            height,
+            keys: Arc::new(Mutex::new(BTreeSet::new())),
+        }
+    }
+
+    fn record(&self, key: &[u8]) {
+        let mut keys = self.keys.lock().unwrap();
+        if !keys.contains(key) {
+            keys.insert(key.to_vec());
+        }
+    }
+
+    /// Report of the keys read so far.
+    pub fn report(&self) -> ReadReport {
+        let keys: Vec<_> = self.keys.lock().unwrap().iter().cloned().collect();
+        let digest = Hash::digest_bytes(&cbor::to_vec(keys.clone()));
+
+        ReadReport {
+            height: self.height,
+            keys,
+            digest,
+        }
+    }
+}
+
+/// Iterator recording the keys it reads.
+struct TrackingIterator<'a> {
+    inner: Box<dyn mkvs::Iterator + 'a>,
+    reads: ReadTracker,
+}
+
+impl<'a> Iterator for TrackingIterator<'a> {
+    type Item = (Vec<u8>, Vec<u8>);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let item = Iterator::next(&mut self.inner);
+        if let Some((key, _)) = item.as_ref() {
+            self.reads.record(key);
+        }
+        item
+    }
+}
+
+impl<'a> mkvs::Iterator for TrackingIterator<'a> {
+    fn set_prefetch(&mut self, prefetch: usize) {
+        self.inner.set_prefetch(prefetch)
+    }
+
+    fn is_valid(&self) -> bool {
+        self.inner.is_valid()
+    }
+
+    fn error(&self) -> &Option<Error> {
+        self.inner.error()
+    }
+
+    fn rewind(&mut self) {
+        self.inner.rewind()
+    }
+
+    fn seek(&mut self, key: &[u8]) {
+        self.inner.seek(key)
+    }
+
+    fn get_key(&self) -> &Option<Key> {
+        self.inner.get_key()
+    }
+
+    fn get_value(&self) -> &Option<Vec<u8>> {
+        if let Some(key) = self.inner.get_key() {
+            self.reads.record(key);
+        }
+        self.inner.get_value()
+    }
+
+    fn next(&mut self) {
+        mkvs::Iterator::next(&mut *self.inner)
+    }
 }
 
 impl ImmutableMKVS for ConsensusState {
     fn get(&self, key: &[u8]) -> Result<Option<Vec<u8>>> {
+        self.record_read(key);
         self.mkvs.get(key)
     }
 
     fn get_proof(&self, key: &[u8]) -> Result<Option<crate::storage::mkvs::sync::Proof>> {
+        self.record_read(key);
         self.mkvs.get_proof(key)
     }
 
@@ -81,17 +233,23 @@ impl ImmutableMKVS for ConsensusState {
         self.mkvs.prefetch_prefixes(prefixes, limit)
     }
 
+    fn prefetch_prefix(&self, prefix: &crate::storage::mkvs::Prefix, max_size: u64) -> Result<()> {
+        self.mkvs.prefetch_prefix(prefix, max_size)
+    }
+
     fn iter(&self) -> Box<dyn crate::storage::mkvs::Iterator + '_> {
-        Box::new(self.mkvs.iter())
+        self.tracked_iter()
     }
 }
 
 impl ImmutableMKVS for &ConsensusState {
     fn get(&self, key: &[u8]) -> Result<Option<Vec<u8>>> {
+        self.record_read(key);
         self.mkvs.get(key)
     }
 
     fn get_proof(&self, key: &[u8]) -> Result<Option<crate::storage::mkvs::sync::Proof>> {
+        self.record_read(key);
         self.mkvs.get_proof(key)
     }
 
@@ -103,7 +261,61 @@ impl ImmutableMKVS for &ConsensusState {
         self.mkvs.prefetch_prefixes(prefixes, limit)
     }
 
+    fn prefetch_prefix(&self, prefix: &crate::storage::mkvs::Prefix, max_size: u64) -> Result<()> {
+        self.mkvs.prefetch_prefix(prefix, max_size)
+    }
+
     fn iter(&self) -> Box<dyn crate::storage::mkvs::Iterator + '_> {
-        Box::new(self.mkvs.iter())
+        self.tracked_iter()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{
+        consensus::{
+            beacon::EpochTimeState,
+            state::beacon::{ImmutableState as BeaconState, MutableState as BeaconMutableState},
+        },
+        storage::mkvs::{sync::NoopReadSyncer, RootType},
+    };
+
+    use super::*;
+
+    #[test]
+    fn test_read_tracking() {
+        let mut mkvs = Tree::builder()
+            .with_root_type(RootType::State)
+            .build(Box::new(NoopReadSyncer));
+        BeaconMutableState::set_epoch_state(
+            &mut mkvs,
+            EpochTimeState {
+                epoch: 10,
+                height: 100,
+            },
+        )
+        .unwrap();
+
+        let mut state = ConsensusState::new(100, mkvs);
+        let tracker = state.track_reads();
+        assert!(tracker.report().keys.is_empty());
+
+        // Iteration records the keys it visits.
+        let mut it = state.iter();
+        it.rewind();
+        assert_eq!(it.count(), 1);
+        let report = tracker.report();
+        assert_eq!(report.height, 100);
+        assert_eq!(report.keys.len(), 1);
+
+        // Repeated reads are only recorded once, while missing keys are recorded as well.
+        let epoch_state = BeaconState::new(&state).epoch_state().unwrap();
+        assert_eq!(epoch_state.epoch, 10);
+        assert_eq!(state.track_reads().report(), report);
+        assert_eq!(state.get(b"missing").unwrap(), None);
+        let report = tracker.report();
+        assert_eq!(report.keys.len(), 2);
+        assert!(report.keys.contains(&b"missing".to_vec()));
+        assert_ne!(report.digest, ReadTracker::new(100).report().digest);
     }
 }
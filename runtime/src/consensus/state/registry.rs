@@ -12,7 +12,7 @@ use crate::{
     },
     consensus::{
         registry::{Node, Runtime},
-        state::StateError,
+        state::{prefetch_namespace, StateError},
     },
     key_format,
     storage::mkvs::ImmutableMKVS,
@@ -55,8 +55,11 @@ impl<T: ImmutableMKVS> ImmutableState<'_, T> {
 
     /// Returns the list of all registered nodes.
     pub fn nodes(&self) -> Result<Vec<Node>, StateError> {
+        let prefix = SignedNodeKeyFmt::default().encode_partial(0);
+        prefetch_namespace(self.mkvs, prefix.clone());
+
         let mut it = self.mkvs.iter();
-        it.seek(&SignedNodeKeyFmt::default().encode_partial(0));
+        it.seek(&prefix);
 
         let mut result: Vec<Node> = Vec::new();
 
@@ -14,7 +14,7 @@ use crate::{
         state::StateError,
     },
     key_format,
-    storage::mkvs::ImmutableMKVS,
+    storage::mkvs::{FallibleMKVS, ImmutableMKVS},
 };
 
 /// Consensus roothash state wrapper.
@@ -113,14 +113,59 @@ impl<T: ImmutableMKVS> ImmutableState<'_, T> {
     }
 }
 
+/// Mutable consensus roothash state wrapper.
+pub struct MutableState;
+
+impl MutableState {
+    /// Set the latest state of the runtime the state is for.
+    pub fn set_runtime_state<S: FallibleMKVS>(
+        mkvs: &mut S,
+        runtime_state: RuntimeState,
+    ) -> Result<(), StateError> {
+        let id = runtime_state.runtime.id;
+        mkvs.insert(
+            &RuntimeKeyFmt(Hash::digest_bytes(id.as_ref())).encode(),
+            &cbor::to_vec(runtime_state),
+        )?;
+        Ok(())
+    }
+}
+
 #[cfg(test)]
 mod test {
-    use crate::storage::mkvs::{
-        interop::{Fixture, ProtocolServer},
-        Root, RootType, Tree,
+    use crate::{
+        consensus::registry::Runtime,
+        storage::mkvs::{
+            interop::{Fixture, ProtocolServer},
+            sync::NoopReadSyncer,
+            Root, RootType, Tree,
+        },
     };
 
     use super::*;
+
+    #[test]
+    fn test_mutable_state() {
+        let mut mkvs = Tree::builder()
+            .with_root_type(RootType::State)
+            .build(Box::new(NoopReadSyncer));
+
+        let id = Namespace::from(vec![1; 32]);
+        let runtime_state = RuntimeState {
+            runtime: Runtime {
+                id,
+                ..Default::default()
+            },
+            last_normal_round: 10,
+            ..Default::default()
+        };
+        MutableState::set_runtime_state(&mut mkvs, runtime_state.clone()).unwrap();
+
+        let roothash_state = ImmutableState::new(&mkvs);
+        assert_eq!(roothash_state.runtime_state(id).unwrap(), runtime_state);
+        assert!(roothash_state.runtime_state(Namespace::default()).is_err());
+    }
+
     #[test]
     fn test_roothash_state_interop() {
         // Keep in sync with go/consensus/cometbft/apps/roothash/state/interop/interop.go.
@@ -11,8 +11,8 @@ use crate::{
     consensus::{
         address::Address,
         beacon::EpochTime,
-        staking::{Account, DebondingDelegation, Delegation},
-        state::StateError,
+        staking::{Account, ConsensusParameters, DebondingDelegation, Delegation},
+        state::{prefetch_namespace, StateError},
     },
     key_format,
     storage::mkvs::ImmutableMKVS,
@@ -39,6 +39,7 @@ key_format!(
     0x54,
     (Address, Address, EpochTime)
 );
+key_format!(ParametersKeyFmt, 0x56, ());
 key_format!(LastBlockFees, 0x57, ());
 key_format!(GovernanceDepositsKeyFmt, 0x59, ());
 
@@ -54,6 +55,17 @@ impl<T: ImmutableMKVS> ImmutableState<'_, T> {
         }
     }
 
+    /// Returns the staking consensus parameters.
+    pub fn parameters(&self) -> Result<ConsensusParameters, StateError> {
+        match self.mkvs.get(&ParametersKeyFmt(()).encode()) {
+            Ok(Some(b)) => {
+                cbor::from_slice(&b).map_err(|err| StateError::Unavailable(anyhow!(err)))
+            }
+            Ok(None) => Ok(ConsensusParameters::default()),
+            Err(err) => Err(StateError::Unavailable(anyhow!(err))),
+        }
+    }
+
     fn load_stored_balance<K: KeyFormat>(&self, key_format: K) -> Result<Quantity, StateError> {
         match self.mkvs.get(&key_format.encode()) {
             Ok(Some(b)) => {
@@ -86,8 +98,11 @@ impl<T: ImmutableMKVS> ImmutableState<'_, T> {
 
     /// Returns the non-empty addresses from the staking ledger.
     pub fn addresses(&self) -> Result<Vec<Address>, StateError> {
+        let prefix = AccountsKeyFmt::default().encode_partial(0);
+        prefetch_namespace(self.mkvs, prefix.clone());
+
         let mut it = self.mkvs.iter();
-        it.seek(&AccountsKeyFmt::default().encode_partial(0));
+        it.seek(&prefix);
 
         Ok(it
             .map_while(|(key, _)| AccountsKeyFmt::decode(&key))
@@ -117,8 +132,11 @@ impl<T: ImmutableMKVS> ImmutableState<'_, T> {
     pub fn delegations(
         &self,
     ) -> Result<BTreeMap<Address, BTreeMap<Address, Delegation>>, StateError> {
+        let prefix = DelegationKeyFmt::default().encode_partial(0);
+        prefetch_namespace(self.mkvs, prefix.clone());
+
         let mut it = self.mkvs.iter();
-        it.seek(&DelegationKeyFmt::default().encode_partial(0));
+        it.seek(&prefix);
 
         let mut result: BTreeMap<Address, BTreeMap<Address, Delegation>> = BTreeMap::new();
 
@@ -163,8 +181,11 @@ impl<T: ImmutableMKVS> ImmutableState<'_, T> {
     pub fn debonding_delegations(
         &self,
     ) -> Result<BTreeMap<Address, BTreeMap<Address, Vec<DebondingDelegation>>>, StateError> {
+        let prefix = DebondingDelegationKeyFmt::default().encode_partial(0);
+        prefetch_namespace(self.mkvs, prefix.clone());
+
         let mut it = self.mkvs.iter();
-        it.seek(&DebondingDelegationKeyFmt::default().encode_partial(0));
+        it.seek(&prefix);
 
         let mut result: BTreeMap<Address, BTreeMap<Address, Vec<DebondingDelegation>>> =
             BTreeMap::new();
@@ -190,6 +211,72 @@ impl<T: ImmutableMKVS> ImmutableState<'_, T> {
 
         Ok(result)
     }
+
+    /// Returns all active delegations of the given delegator, by escrow account.
+    ///
+    /// Delegations are stored by escrow account, so all delegations are iterated over, failing
+    /// in case there are more than the given number of them.
+    pub fn delegations_for(
+        &self,
+        delegator_addr: &Address,
+        max_scanned: usize,
+    ) -> Result<BTreeMap<Address, Delegation>, StateError> {
+        let prefix = DelegationKeyFmt::default().encode_partial(0);
+        prefetch_namespace(self.mkvs, prefix.clone());
+
+        let mut it = self.mkvs.iter();
+        it.seek(&prefix);
+
+        let mut result = BTreeMap::new();
+        let mut scanned = 0;
+
+        while let Some((DelegationKeyFmt((escrow_addr, addr)), value)) = it
+            .next()
+            .and_then(|(key, value)| DelegationKeyFmt::decode(&key).zip(value.into()))
+        {
+            if scanned == max_scanned {
+                return Err(StateError::TooManyEntries(max_scanned));
+            }
+            scanned += 1;
+
+            if addr != *delegator_addr {
+                continue;
+            }
+            result.insert(
+                escrow_addr,
+                cbor::from_slice(&value).map_err(|err| StateError::Unavailable(anyhow!(err)))?,
+            );
+        }
+
+        Ok(result)
+    }
+
+    /// Returns all debonding delegations of the given delegator, by escrow account.
+    pub fn debonding_delegations_for(
+        &self,
+        delegator_addr: &Address,
+    ) -> Result<BTreeMap<Address, Vec<DebondingDelegation>>, StateError> {
+        let prefix = DebondingDelegationKeyFmt((delegator_addr.clone(), Address::default(), 0))
+            .encode_partial(1);
+        prefetch_namespace(self.mkvs, prefix.clone());
+
+        let mut it = self.mkvs.iter();
+        it.seek(&prefix);
+
+        let mut result: BTreeMap<Address, Vec<DebondingDelegation>> = BTreeMap::new();
+
+        while let Some((DebondingDelegationKeyFmt((_, escrow_addr, _)), value)) = it
+            .next()
+            .filter(|(key, _)| key.starts_with(&prefix))
+            .and_then(|(key, value)| DebondingDelegationKeyFmt::decode(&key).zip(value.into()))
+        {
+            result.entry(escrow_addr).or_default().push(
+                cbor::from_slice(&value).map_err(|err| StateError::Unavailable(anyhow!(err)))?,
+            );
+        }
+
+        Ok(result)
+    }
 }
 
 #[cfg(test)]
@@ -390,6 +477,21 @@ mod test {
             "expected delegations should match"
         );
 
+        // Test delegations of a single delegator.
+        let delegations = staking_state
+            .delegations_for(&addrs[0], 6)
+            .expect("delegations for query should work");
+        let expected: BTreeMap<_, _> = expected_delegations
+            .iter()
+            .filter_map(|(escrow_addr, dels)| Some((escrow_addr.clone(), dels.get(&addrs[0])?)))
+            .map(|(escrow_addr, del)| (escrow_addr, del.clone()))
+            .collect();
+        assert_eq!(expected, delegations, "delegations for should match");
+        assert!(matches!(
+            staking_state.delegations_for(&addrs[0], 5),
+            Err(StateError::TooManyEntries(5))
+        ));
+
         // Test all debonding delegations.
         let debonding_delegations = staking_state
             .debonding_delegations()
@@ -3,7 +3,10 @@ use std::num::NonZeroUsize;
 use tendermint::block::Height;
 use tendermint_light_client::types::LightBlock as TMLightBlock;
 
-use crate::common::crypto::{hash::Hash, signature::PublicKey};
+use crate::{
+    common::crypto::{hash::Hash, signature::PublicKey},
+    consensus::tendermint::LightBlockMeta,
+};
 
 /// Verifier cache.
 pub struct Cache {
@@ -11,6 +14,8 @@ pub struct Cache {
     pub last_verified_round: u64,
     pub last_verified_epoch: u64,
     pub last_verified_block: Option<TMLightBlock>,
+    /// Latest block pushed by the host, waiting for its successor to become verifiable.
+    pub pushed_block: Option<LightBlockMeta>,
     pub verified_state_roots: lru::LruCache<u64, (Hash, u64)>,
     pub host_node_id: PublicKey,
 }
@@ -43,6 +48,7 @@ impl Cache {
             last_verified_round: 0,
             last_verified_epoch: 0,
             last_verified_block: None,
+            pushed_block: None,
             verified_state_roots: lru::LruCache::new(NonZeroUsize::new(128).unwrap()),
             host_node_id,
         }
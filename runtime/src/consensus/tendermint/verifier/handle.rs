@@ -35,6 +35,15 @@ impl verifier::Verifier for Handle {
         receiver.await.map_err(|_| Error::Internal)?
     }
 
+    async fn sync_block(&self, consensus_block: LightBlock) -> Result<(), Error> {
+        let (sender, receiver) = oneshot::channel();
+        self.command_sender
+            .send(Command::SyncBlock(consensus_block, sender))
+            .map_err(|_| Error::Internal)?;
+
+        receiver.await.map_err(|_| Error::Internal)?
+    }
+
     async fn verify(
         &self,
         consensus_block: LightBlock,
@@ -11,6 +11,7 @@ use tendermint_rpc::error::Error as RpcError;
 
 use crate::{
     consensus::{
+        checkpoint::SignedCheckpoint,
         tendermint::{decode_light_block, LightBlockMeta},
         transaction::SignedTransactionWithProof,
         HEIGHT_LATEST,
@@ -66,6 +67,21 @@ impl Io {
         Ok(height)
     }
 
+    pub fn fetch_checkpoint(&self) -> Result<Option<SignedCheckpoint>, IoError> {
+        let result = self
+            .protocol
+            .call_host(Body::HostFetchConsensusCheckpointRequest {})
+            .map_err(|err| IoError::rpc(RpcError::server(err.to_string())))?;
+
+        // Extract checkpoint from response.
+        let checkpoint = match result {
+            Body::HostFetchConsensusCheckpointResponse { checkpoint } => checkpoint,
+            _ => return Err(IoError::rpc(RpcError::server("bad response".to_string()))),
+        };
+
+        Ok(checkpoint)
+    }
+
     pub fn fetch_freshness_proof(
         &self,
         nonce: &Nonce,
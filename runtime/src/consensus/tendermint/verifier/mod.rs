@@ -3,7 +3,7 @@ use std::{convert::TryInto, str::FromStr, sync::Arc, time::Duration};
 
 use anyhow::anyhow;
 use crossbeam::channel;
-use rand::{rngs::OsRng, Rng};
+use rand::Rng;
 use sha2::{Digest, Sha256};
 use slog::{debug, error, info};
 use tendermint::merkle::HASH_SIZE;
@@ -22,7 +22,14 @@ use tendermint_light_client::{
 };
 
 use crate::{
-    common::{logger::get_logger, namespace::Namespace, process, time, version::Version},
+    common::{
+        crypto::rng::SecureRng,
+        logger::get_logger,
+        namespace::Namespace,
+        pagination::{Pages, Pagination},
+        process, time,
+        version::Version,
+    },
     consensus::{
         beacon::EpochTime,
         registry::METHOD_PROVE_FRESHNESS,
@@ -43,6 +50,7 @@ use crate::{
         BlockMetadata, Event, LightBlock, HEIGHT_LATEST, METHOD_META,
     },
     future::block_on,
+    health::HealthMonitor,
     host::Host,
     protocol::Protocol,
     storage::mkvs::{Root, RootType},
@@ -60,6 +68,7 @@ mod cache;
 mod clock;
 mod handle;
 mod io;
+mod multihead;
 mod noop;
 mod predicates;
 mod signature;
@@ -67,6 +76,7 @@ mod store;
 mod types;
 
 // Re-exports.
+pub use multihead::{MultiHeadConfig, MultiHeadVerifier};
 pub use noop::NopVerifier;
 
 /// Maximum number of times to retry initialization.
@@ -150,6 +160,7 @@ impl Verifier {
 
         cache.update_verified_block(&verified_block);
         self.update_insecure_posix_time(&verified_block);
+        HealthMonitor::global().record_consensus_block(block_time(&verified_block));
 
         Ok(verified_block)
     }
@@ -166,6 +177,52 @@ impl Verifier {
         Ok(())
     }
 
+    fn sync_block(
+        &self,
+        cache: &mut Cache,
+        instance: &mut Instance,
+        consensus_block: LightBlock,
+    ) -> Result<(), Error> {
+        let block = decode_light_block(consensus_block).map_err(Error::VerificationFailed)?;
+        let height = block
+            .signed_header
+            .as_ref()
+            .ok_or_else(|| Error::VerificationFailed(anyhow!("missing signed header")))?
+            .header
+            .height
+            .value();
+        if height <= cache.latest_known_height().unwrap_or(0) {
+            // Ignore blocks which have already been verified.
+            return Ok(());
+        }
+
+        // A light block also needs the validator set of the next block, so a pushed block can
+        // only be verified once its successor has been pushed as well.
+        let previous = cache.pushed_block.replace(block.clone());
+        let previous = match previous {
+            Some(previous) => previous,
+            None => return Ok(()),
+        };
+        let signed_header = previous.signed_header.unwrap(); // Checked when pushed.
+        if signed_header.header.height.value() + 1 != height {
+            return Ok(());
+        }
+
+        let light_block = TMLightBlock {
+            signed_header,
+            validators: previous.validators,
+            next_validators: block.validators,
+            provider: PeerId::new([0; 20]),
+        };
+        let target = light_block.signed_header.header.height.value();
+        instance
+            .state
+            .light_store
+            .insert(light_block, Status::Unverified);
+
+        self.sync(cache, instance, target)
+    }
+
     fn latest_consensus_state(
         &self,
         cache: &mut Cache,
@@ -280,7 +337,7 @@ impl Verifier {
         );
 
         // Generate a random nonce for prove freshness transaction.
-        let mut rng = OsRng {};
+        let mut rng = SecureRng;
         let mut nonce = [0u8; NONCE_SIZE];
         rng.fill(&mut nonce);
 
@@ -541,37 +598,45 @@ impl Verifier {
     }
 
     fn events_at(&self, height: u64, kind: EventKind) -> Result<Vec<Event>, Error> {
-        let result = self
-            .protocol
-            .call_host(Body::HostFetchConsensusEventsRequest(
-                HostFetchConsensusEventsRequest { height, kind },
-            ))
-            .map_err(|err| Error::VerificationFailed(err.into()))?;
-        // TODO: Perform event verification once this becomes possible.
-
-        match result {
-            Body::HostFetchConsensusEventsResponse(HostFetchConsensusEventsResponse { events }) => {
-                Ok(events)
+        let mut events = vec![];
+        let mut pages = Pages::new(Pagination::default().normalize());
+        while let Some(page) = pages.next_page() {
+            let result = self
+                .protocol
+                .call_host(Body::HostFetchConsensusEventsRequest(
+                    HostFetchConsensusEventsRequest {
+                        height,
+                        kind,
+                        pagination: page.clone(),
+                    },
+                ))
+                .map_err(|err| Error::VerificationFailed(err.into()))?;
+            // TODO: Perform event verification once this becomes possible.
+
+            match result {
+                Body::HostFetchConsensusEventsResponse(HostFetchConsensusEventsResponse {
+                    events: page_events,
+                    continuation_token,
+                }) => {
+                    page.check(page_events.len())
+                        .map_err(|err| Error::VerificationFailed(err.into()))?;
+                    events.extend(page_events);
+                    pages
+                        .advance(continuation_token)
+                        .map_err(|err| Error::VerificationFailed(err.into()))?;
+                }
+                _ => return Err(Error::VerificationFailed(anyhow!("bad response from host"))),
             }
-            _ => Err(Error::VerificationFailed(anyhow!("bad response from host"))),
         }
+
+        Ok(events)
     }
 
     fn update_insecure_posix_time(&self, verified_block: &TMLightBlock) {
         // Update untrusted time if ahead. This makes sure that the enclave's sense of time is
         // synced with consensus sense of time based on the fact that consensus time is harder to
         // fake than host operating system time.
-        time::update_insecure_posix_time(
-            verified_block
-                .signed_header
-                .header
-                .time
-                .duration_since(Time::unix_epoch())
-                .unwrap()
-                .as_secs()
-                .try_into()
-                .unwrap(),
-        );
+        time::update_insecure_posix_time(block_time(verified_block));
     }
 
     /// Start the verifier in a separate thread.
@@ -661,6 +726,9 @@ impl Verifier {
             }
         };
 
+        // Fast sync from a recent checkpoint, if one is available.
+        let trusted_state = self.apply_checkpoint(trusted_state, io.as_ref());
+
         // Verify if we can trust light blocks from a new chain if the consensus
         // chain context changes.
         info!(self.logger, "Checking chain context change");
@@ -741,6 +809,11 @@ impl Verifier {
                         .send(self.sync(&mut cache, &mut instance, height))
                         .map_err(|_| Error::Internal)?;
                 }
+                Command::SyncBlock(consensus_block, sender) => {
+                    sender
+                        .send(self.sync_block(&mut cache, &mut instance, consensus_block))
+                        .map_err(|_| Error::Internal)?;
+                }
                 Command::Verify(consensus_block, runtime_header, epoch, sender, false) => {
                     sender
                         .send(self.verify(
@@ -797,6 +870,58 @@ impl Verifier {
         }
     }
 
+    fn apply_checkpoint(&self, trusted_state: TrustedState, io: &Io) -> TrustedState {
+        let policy = match &self.protocol.get_config().consensus_checkpoint {
+            Some(policy) => policy,
+            None => return trusted_state,
+        };
+
+        // Checkpoints are an optimization, so any failure falls back to the trusted state.
+        let signed = match io.fetch_checkpoint() {
+            Ok(Some(signed)) => signed,
+            Ok(None) => {
+                info!(self.logger, "No consensus checkpoint available");
+                return trusted_state;
+            }
+            Err(err) => {
+                error!(self.logger, "Failed to fetch consensus checkpoint"; "err" => %err);
+                return trusted_state;
+            }
+        };
+        // Checkpoints must extend the trusted chain, changes of the chain context are only
+        // accepted after verification in `handle_chain_context_change`.
+        let trust_root = match policy.verify(
+            &signed,
+            self.runtime_id,
+            &trusted_state.trust_root.chain_context,
+            time::insecure_posix_time(),
+            trusted_state.latest_block_time(),
+        ) {
+            Ok(trust_root) => trust_root,
+            Err(err) => {
+                error!(self.logger, "Rejected consensus checkpoint"; "err" => %err);
+                return trusted_state;
+            }
+        };
+
+        // Never move back on the chain.
+        if trusted_state.trust_root.height >= trust_root.height {
+            info!(self.logger, "Trusted state is newer than consensus checkpoint";
+                "checkpoint_height" => trust_root.height,
+            );
+            return trusted_state;
+        }
+
+        info!(self.logger, "Fast syncing from consensus checkpoint";
+            "checkpoint_height" => trust_root.height,
+            "checkpoint_hash" => ?trust_root.hash,
+        );
+        TrustedState {
+            trust_root,
+            trusted_blocks: vec![],
+        }
+    }
+
     fn handle_chain_context_change(
         &self,
         mut trusted_state: TrustedState,
@@ -925,3 +1050,16 @@ impl Verifier {
         })
     }
 }
+
+/// Time of the given block in seconds since the UNIX epoch.
+fn block_time(block: &TMLightBlock) -> i64 {
+    block
+        .signed_header
+        .header
+        .time
+        .duration_since(Time::unix_epoch())
+        .unwrap()
+        .as_secs()
+        .try_into()
+        .unwrap()
+}
@@ -0,0 +1,607 @@
+//! Consensus layer verifier tracking multiple candidate heads.
+//!
+//! The default verifier assumes a single linear chain, which doesn't hold for chains whose
+//! finality gadget lags behind block production and where short reorgs are possible. The
+//! multi-head verifier instead tracks all candidate heads pushed to it within a window of the
+//! configured maximum reorg depth, following the best (highest) head. Blocks only become final
+//! once they are buried deep enough under the best head, at which point they are handed to the
+//! inner verifier, so all state is served from final blocks only. All verifier calls go through
+//! the multi-head verifier, which rejects those for heights that are not final yet.
+//!
+//! Candidates are only trusted in case they descend from a block verified by the inner verifier
+//! against its trusted state: the first pushed block is verified by the inner verifier and
+//! anchors the tree, while each further candidate must be committed by the validator set its
+//! parent designated.
+//!
+//! Two distinct blocks at the same height, each committed by a quorum of the same trusted
+//! validator set, are proof of equivocation and are reported to the host as slashing evidence.
+use std::{
+    collections::BTreeMap,
+    sync::{Arc, Mutex},
+};
+
+use anyhow::anyhow;
+use async_trait::async_trait;
+use slog::{info, warn};
+use tendermint::Hash as TMHash;
+use tendermint_light_client::{
+    operations::{ProvidedVotingPowerCalculator, VotingPowerCalculator},
+    types::TrustThreshold,
+};
+
+use crate::{
+    common::logger::get_logger,
+    consensus::{
+        beacon::EpochTime,
+        roothash::Header,
+        state::ConsensusState,
+        tendermint::decode_light_block,
+        verifier::{Error, Verifier},
+        Event, LightBlock,
+    },
+    protocol::Protocol,
+    types::{Body, ConsensusEquivocationEvidence, EventKind},
+};
+
+use super::signature::DomSepVerifier;
+
+/// Multi-head verifier configuration.
+#[derive(Clone, Debug)]
+pub struct MultiHeadConfig {
+    /// The maximum number of blocks that can be reverted by a reorg. Blocks buried deeper under
+    /// the best head are final.
+    pub max_reorg_depth: u64,
+    /// The maximum number of candidate heads tracked at the same time.
+    pub max_heads: usize,
+    /// Whether detected equivocations should be reported to the host.
+    pub report_equivocation: bool,
+}
+
+impl Default for MultiHeadConfig {
+    fn default() -> Self {
+        Self {
+            max_reorg_depth: 8,
+            max_heads: 4,
+            report_equivocation: true,
+        }
+    }
+}
+
+/// A candidate block whose commit has been verified.
+#[derive(Clone, Debug)]
+struct Candidate {
+    height: u64,
+    hash: TMHash,
+    parent: TMHash,
+    validators_hash: TMHash,
+    next_validators_hash: TMHash,
+    block: LightBlock,
+}
+
+/// Result of inserting a candidate.
+#[derive(Debug, Default)]
+struct Insertion {
+    /// Number of blocks of the previous best chain reverted by the insertion.
+    reorg_depth: u64,
+    /// A conflicting block at the same height, in case the insertion revealed an equivocation.
+    conflict: Option<LightBlock>,
+}
+
+/// Tree of candidate heads above the last final block.
+struct Heads {
+    max_reorg_depth: u64,
+    max_heads: usize,
+    /// The last final block, which all candidates descend from.
+    anchor: Option<Candidate>,
+    /// Non-final candidates by height.
+    candidates: BTreeMap<u64, Vec<Candidate>>,
+    /// Height and hash of the best head.
+    best: Option<(u64, TMHash)>,
+}
+
+impl Heads {
+    fn new(max_reorg_depth: u64, max_heads: usize) -> Self {
+        Self {
+            max_reorg_depth,
+            max_heads,
+            anchor: None,
+            candidates: BTreeMap::new(),
+            best: None,
+        }
+    }
+
+    fn get(&self, height: u64, hash: &TMHash) -> Option<&Candidate> {
+        match self.anchor {
+            Some(ref anchor) if anchor.height == height && &anchor.hash == hash => Some(anchor),
+            _ => self
+                .candidates
+                .get(&height)?
+                .iter()
+                .find(|c| &c.hash == hash),
+        }
+    }
+
+    fn parent(&self, candidate: &Candidate) -> Option<&Candidate> {
+        self.get(candidate.height.checked_sub(1)?, &candidate.parent)
+    }
+
+    /// Anchor the tree at the given block, verified by the inner verifier.
+    fn set_anchor(&mut self, anchor: Candidate) {
+        self.candidates.clear();
+        self.best = Some((anchor.height, anchor.hash));
+        self.anchor = Some(anchor);
+    }
+
+    /// Height of the last final block, if any.
+    fn final_height(&self) -> Option<u64> {
+        self.anchor.as_ref().map(|anchor| anchor.height)
+    }
+
+    /// Candidates that have no children.
+    fn tips(&self) -> Vec<&Candidate> {
+        self.candidates
+            .values()
+            .flatten()
+            .filter(|c| {
+                !self
+                    .candidates
+                    .get(&(c.height + 1))
+                    .is_some_and(|children| children.iter().any(|child| child.parent == c.hash))
+            })
+            .collect()
+    }
+
+    /// Insert a new candidate, returning the outcome or `None` in case it is already known.
+    fn insert(&mut self, candidate: Candidate) -> Result<Option<Insertion>, Error> {
+        if self.get(candidate.height, &candidate.hash).is_some() {
+            return Ok(None);
+        }
+
+        // Any other block at the same height committed by the same validator set is a conflict.
+        let conflict = match self.anchor {
+            Some(ref anchor) if anchor.height == candidate.height => Some(anchor),
+            _ => self.candidates.get(&candidate.height).and_then(|siblings| {
+                siblings
+                    .iter()
+                    .find(|c| c.validators_hash == candidate.validators_hash)
+            }),
+        }
+        .filter(|c| c.validators_hash == candidate.validators_hash)
+        .map(|c| c.block.clone());
+        let mut insertion = Insertion {
+            conflict,
+            ..Default::default()
+        };
+
+        if self
+            .anchor
+            .as_ref()
+            .is_some_and(|anchor| candidate.height <= anchor.height)
+        {
+            // Reject blocks at final heights, but still report the conflict.
+            return Ok(Some(insertion));
+        }
+
+        match self.parent(&candidate) {
+            Some(parent) => {
+                if parent.next_validators_hash != candidate.validators_hash {
+                    return Err(Error::VerificationFailed(anyhow!(
+                        "validator set does not match parent block"
+                    )));
+                }
+                let forks = self
+                    .candidates
+                    .get(&candidate.height)
+                    .is_some_and(|siblings| siblings.iter().any(|s| s.parent == parent.hash));
+                if forks && self.tips().len() >= self.max_heads {
+                    return Err(Error::VerificationFailed(anyhow!(
+                        "too many candidate heads"
+                    )));
+                }
+            }
+            None if self.anchor.is_none() => {
+                return Err(Error::VerificationFailed(anyhow!(
+                    "no trusted anchor block"
+                )));
+            }
+            None => {
+                return Err(Error::VerificationFailed(anyhow!(
+                    "block does not extend any candidate head"
+                )));
+            }
+        }
+
+        let (height, hash) = (candidate.height, candidate.hash);
+        self.candidates.entry(height).or_default().push(candidate);
+
+        match self.best {
+            Some((best_height, _)) if best_height >= height => {}
+            Some((best_height, _)) => {
+                insertion.reorg_depth = best_height - self.fork_height(height, hash);
+                self.best = Some((height, hash));
+            }
+            None => self.best = Some((height, hash)),
+        }
+
+        Ok(Some(insertion))
+    }
+
+    /// Height of the last common block of the best chain and the chain of the given block.
+    fn fork_height(&self, height: u64, hash: TMHash) -> u64 {
+        let (mut best_height, mut best_hash) = self.best.expect("best head should exist");
+        let (mut height, mut hash) = (height, hash);
+        while best_height > height {
+            (best_height, best_hash) = self.step_back(best_height, best_hash);
+        }
+        while height > best_height {
+            (height, hash) = self.step_back(height, hash);
+        }
+        while hash != best_hash && height > 0 {
+            (height, hash) = self.step_back(height, hash);
+            (best_height, best_hash) = self.step_back(best_height, best_hash);
+        }
+        height
+    }
+
+    fn step_back(&self, height: u64, hash: TMHash) -> (u64, TMHash) {
+        let parent = self
+            .get(height, &hash)
+            .map_or(TMHash::None, |candidate| candidate.parent);
+        (height.saturating_sub(1), parent)
+    }
+
+    /// Finalize all blocks of the best chain buried deeper than the maximum reorg depth,
+    /// returning them in ascending order.
+    fn finalize(&mut self) -> Vec<LightBlock> {
+        let (best_height, best_hash) = match self.best {
+            Some(best) => best,
+            None => return vec![],
+        };
+        let Some(final_height) = best_height.checked_sub(self.max_reorg_depth) else {
+            return vec![];
+        };
+
+        // Collect the best chain down to the new final height.
+        let mut chain = vec![];
+        let (mut height, mut hash) = (best_height, best_hash);
+        while let Some(candidate) = self
+            .candidates
+            .get(&height)
+            .and_then(|cs| cs.iter().find(|c| c.hash == hash))
+        {
+            if height <= final_height {
+                chain.push(candidate.clone());
+            }
+            (height, hash) = (height.saturating_sub(1), candidate.parent);
+        }
+        chain.reverse();
+
+        let Some(anchor) = chain.last().cloned() else {
+            return vec![];
+        };
+
+        // Prune the candidates that don't descend from the new anchor.
+        let mut candidates = std::mem::take(&mut self.candidates);
+        candidates.retain(|height, _| *height > anchor.height);
+        let mut live = vec![anchor.hash];
+        for (_, cs) in candidates.iter_mut() {
+            cs.retain(|c| live.contains(&c.parent));
+            live = cs.iter().map(|c| c.hash).collect();
+        }
+        candidates.retain(|_, cs| !cs.is_empty());
+        self.candidates = candidates;
+        self.anchor = Some(anchor);
+
+        chain.into_iter().map(|c| c.block).collect()
+    }
+}
+
+/// Consensus layer verifier tracking multiple candidate heads with short reorg tolerance.
+///
+/// Pushed blocks are tracked as candidates and handed to the inner verifier once final, while
+/// all state queries are served by the inner verifier.
+pub struct MultiHeadVerifier {
+    logger: slog::Logger,
+    protocol: Arc<Protocol>,
+    inner: Arc<dyn Verifier>,
+    report_equivocation: bool,
+    heads: Mutex<Heads>,
+}
+
+impl MultiHeadVerifier {
+    /// Create a new multi-head verifier wrapping the given verifier.
+    pub fn new(protocol: Arc<Protocol>, inner: Arc<dyn Verifier>, config: MultiHeadConfig) -> Self {
+        Self {
+            logger: get_logger("consensus/cometbft/verifier/multihead"),
+            protocol,
+            inner,
+            report_equivocation: config.report_equivocation,
+            heads: Mutex::new(Heads::new(config.max_reorg_depth, config.max_heads)),
+        }
+    }
+
+    /// Heights of all candidate heads.
+    pub fn heads(&self) -> Vec<u64> {
+        let heads = self.heads.lock().unwrap();
+        heads.tips().iter().map(|c| c.height).collect()
+    }
+
+    /// Height of the last final block, failing in case the given height is not final yet.
+    fn ensure_final(&self, height: u64) -> Result<(), Error> {
+        match self.heads.lock().unwrap().final_height() {
+            Some(final_height) if height <= final_height => Ok(()),
+            _ => Err(Error::VerificationFailed(anyhow!(
+                "consensus block at height {} is not final",
+                height
+            ))),
+        }
+    }
+
+    /// Verify the commit of the given block and turn it into a candidate.
+    fn candidate(consensus_block: LightBlock) -> Result<Candidate, Error> {
+        let block =
+            decode_light_block(consensus_block.clone()).map_err(Error::VerificationFailed)?;
+        let signed_header = block
+            .signed_header
+            .ok_or_else(|| Error::VerificationFailed(anyhow!("missing signed header")))?;
+        let header = &signed_header.header;
+
+        if block.validators.hash() != header.validators_hash {
+            return Err(Error::VerificationFailed(anyhow!(
+                "validator set does not match header"
+            )));
+        }
+        let tally = ProvidedVotingPowerCalculator::<DomSepVerifier>::default()
+            .voting_power_in(
+                &signed_header,
+                &block.validators,
+                TrustThreshold::TWO_THIRDS,
+            )
+            .map_err(|err| Error::VerificationFailed(anyhow!("{}", err)))?;
+        if tally.tallied * 3 <= tally.total * 2 {
+            return Err(Error::VerificationFailed(anyhow!(
+                "insufficient voting power in commit"
+            )));
+        }
+
+        Ok(Candidate {
+            height: header.height.value(),
+            hash: header.hash(),
+            parent: header
+                .last_block_id
+                .as_ref()
+                .map_or(TMHash::None, |id| id.hash),
+            validators_hash: header.validators_hash,
+            next_validators_hash: header.next_validators_hash,
+            block: consensus_block,
+        })
+    }
+
+    /// Verify that the given evidence consists of two distinct blocks at the same height,
+    /// committed by the same validator set.
+    fn verify_evidence(evidence: &ConsensusEquivocationEvidence) -> Result<(), Error> {
+        let [first, second] = evidence.blocks.as_slice() else {
+            return Err(Error::VerificationFailed(anyhow!("malformed evidence")));
+        };
+        let (first, second) = (
+            Self::candidate(first.clone())?,
+            Self::candidate(second.clone())?,
+        );
+        if first.height != evidence.height
+            || second.height != evidence.height
+            || first.hash == second.hash
+            || first.validators_hash != second.validators_hash
+        {
+            return Err(Error::VerificationFailed(anyhow!("blocks don't conflict")));
+        }
+        Ok(())
+    }
+
+    async fn report_equivocation(&self, evidence: ConsensusEquivocationEvidence) {
+        if let Err(err) = Self::verify_evidence(&evidence) {
+            warn!(self.logger, "Ignoring invalid equivocation evidence"; "err" => ?err);
+            return;
+        }
+        warn!(self.logger, "Detected consensus layer equivocation";
+            "height" => evidence.height,
+        );
+        if !self.report_equivocation {
+            return;
+        }
+
+        let result = self
+            .protocol
+            .call_host_async(Body::HostConsensusEvidenceRequest { evidence })
+            .await;
+        if let Err(err) = result {
+            warn!(self.logger, "Failed to report equivocation evidence"; "err" => ?err);
+        }
+    }
+}
+
+#[async_trait]
+impl Verifier for MultiHeadVerifier {
+    async fn sync(&self, height: u64) -> Result<(), Error> {
+        self.ensure_final(height)?;
+        self.inner.sync(height).await
+    }
+
+    async fn sync_block(&self, consensus_block: LightBlock) -> Result<(), Error> {
+        let candidate = Self::candidate(consensus_block.clone())?;
+        let height = candidate.height;
+
+        // Anchor the tree at a block verified against the trusted state of the inner verifier.
+        let anchored = self.heads.lock().unwrap().anchor.is_some();
+        if !anchored {
+            self.inner.sync_block(consensus_block).await?;
+            let mut heads = self.heads.lock().unwrap();
+            if heads.anchor.is_none() {
+                info!(self.logger, "Anchored candidate heads"; "height" => height);
+                heads.set_anchor(candidate);
+            }
+            return Ok(());
+        }
+
+        let (insertion, finalized) = {
+            let mut heads = self.heads.lock().unwrap();
+            let insertion = heads.insert(candidate)?;
+            (insertion, heads.finalize())
+        };
+        let Some(insertion) = insertion else {
+            return Ok(());
+        };
+
+        if let Some(conflict) = insertion.conflict {
+            self.report_equivocation(ConsensusEquivocationEvidence {
+                height,
+                blocks: vec![conflict, consensus_block],
+            })
+            .await;
+        }
+        if insertion.reorg_depth > 0 {
+            info!(self.logger, "Switched to a different candidate head";
+                "height" => height,
+                "reorg_depth" => insertion.reorg_depth,
+            );
+        }
+
+        for block in finalized {
+            self.inner.sync_block(block).await?;
+        }
+
+        Ok(())
+    }
+
+    async fn verify(
+        &self,
+        consensus_block: LightBlock,
+        runtime_header: Header,
+        epoch: EpochTime,
+    ) -> Result<ConsensusState, Error> {
+        self.ensure_final(consensus_block.height)?;
+        self.inner
+            .verify(consensus_block, runtime_header, epoch)
+            .await
+    }
+
+    async fn verify_for_query(
+        &self,
+        consensus_block: LightBlock,
+        runtime_header: Header,
+        epoch: EpochTime,
+    ) -> Result<ConsensusState, Error> {
+        self.ensure_final(consensus_block.height)?;
+        self.inner
+            .verify_for_query(consensus_block, runtime_header, epoch)
+            .await
+    }
+
+    async fn unverified_state(&self, consensus_block: LightBlock) -> Result<ConsensusState, Error> {
+        self.inner.unverified_state(consensus_block).await
+    }
+
+    async fn latest_state(&self) -> Result<ConsensusState, Error> {
+        self.inner.latest_state().await
+    }
+
+    async fn state_at(&self, height: u64) -> Result<ConsensusState, Error> {
+        self.ensure_final(height)?;
+        self.inner.state_at(height).await
+    }
+
+    async fn events_at(&self, height: u64, kind: EventKind) -> Result<Vec<Event>, Error> {
+        self.ensure_final(height)?;
+        self.inner.events_at(height, kind).await
+    }
+
+    async fn latest_height(&self) -> Result<u64, Error> {
+        self.inner.latest_height().await
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn hash(n: u8) -> TMHash {
+        TMHash::Sha256([n; 32])
+    }
+
+    fn candidate(height: u64, id: u8, parent: u8) -> Candidate {
+        Candidate {
+            height,
+            hash: hash(id),
+            parent: hash(parent),
+            validators_hash: hash(0),
+            next_validators_hash: hash(0),
+            block: LightBlock {
+                height,
+                meta: vec![id],
+            },
+        }
+    }
+
+    fn ids(blocks: Vec<LightBlock>) -> Vec<u8> {
+        blocks.into_iter().map(|block| block.meta[0]).collect()
+    }
+
+    #[test]
+    fn test_heads_reorg_and_finalize() {
+        let mut heads = Heads::new(2, 2);
+
+        // Candidates must descend from a trusted anchor.
+        assert!(heads.insert(candidate(1, 1, 0)).is_err());
+        heads.set_anchor(candidate(1, 1, 0));
+        assert_eq!(heads.final_height(), Some(1));
+
+        // Linear chain 1 <- 2 <- 3.
+        for (height, id, parent) in [(2, 2, 1), (3, 3, 2)] {
+            let insertion = heads
+                .insert(candidate(height, id, parent))
+                .unwrap()
+                .unwrap();
+            assert_eq!(insertion.reorg_depth, 0);
+            assert!(insertion.conflict.is_none());
+        }
+        assert!(heads.insert(candidate(3, 3, 2)).unwrap().is_none());
+        assert!(heads.finalize().is_empty());
+
+        // Competing head 2 <- 13 is tracked and reveals a conflict.
+        let insertion = heads.insert(candidate(3, 13, 2)).unwrap().unwrap();
+        assert_eq!(insertion.conflict.unwrap().meta, vec![3]);
+        assert_eq!(heads.tips().len(), 2);
+        assert!(heads.finalize().is_empty());
+
+        // Too many heads.
+        assert!(heads.insert(candidate(3, 23, 2)).is_err());
+        // Unknown parent.
+        assert!(heads.insert(candidate(5, 5, 4)).is_err());
+
+        // The competing head overtakes the best head.
+        let insertion = heads.insert(candidate(4, 14, 13)).unwrap().unwrap();
+        assert_eq!(insertion.reorg_depth, 1);
+        assert_eq!(ids(heads.finalize()), vec![2]);
+
+        // Extending the best head finalizes the competing chain and prunes the other head.
+        heads.insert(candidate(5, 15, 14)).unwrap();
+        assert_eq!(ids(heads.finalize()), vec![13]);
+        assert_eq!(heads.final_height(), Some(3));
+        assert_eq!(heads.tips().len(), 1);
+        assert!(heads.insert(candidate(4, 4, 3)).is_err());
+
+        // Blocks at final heights are rejected but still reveal conflicts.
+        let insertion = heads.insert(candidate(3, 33, 2)).unwrap().unwrap();
+        assert_eq!(insertion.conflict.unwrap().meta, vec![13]);
+        assert!(heads.get(3, &hash(33)).is_none());
+    }
+
+    #[test]
+    fn test_heads_validator_set_linkage() {
+        let mut heads = Heads::new(2, 2);
+        heads.set_anchor(candidate(1, 1, 0));
+
+        let mut next = candidate(2, 2, 1);
+        next.validators_hash = hash(42);
+        assert!(heads.insert(next).is_err());
+    }
+}
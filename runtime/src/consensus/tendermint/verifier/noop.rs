@@ -5,7 +5,11 @@ use async_trait::async_trait;
 use slog::info;
 
 use crate::{
-    common::{logger::get_logger, namespace::Namespace},
+    common::{
+        logger::get_logger,
+        namespace::Namespace,
+        pagination::{Pages, Pagination},
+    },
     consensus::{
         beacon::EpochTime,
         roothash::Header,
@@ -159,20 +163,38 @@ impl verifier::Verifier for NopVerifier {
     }
 
     async fn events_at(&self, height: u64, kind: EventKind) -> Result<Vec<Event>, Error> {
-        let result = self
-            .protocol
-            .call_host_async(Body::HostFetchConsensusEventsRequest(
-                HostFetchConsensusEventsRequest { height, kind },
-            ))
-            .await
-            .map_err(|err| Error::VerificationFailed(err.into()))?;
-
-        match result {
-            Body::HostFetchConsensusEventsResponse(HostFetchConsensusEventsResponse { events }) => {
-                Ok(events)
+        let mut events = vec![];
+        let mut pages = Pages::new(Pagination::default().normalize());
+        while let Some(page) = pages.next_page() {
+            let result = self
+                .protocol
+                .call_host_async(Body::HostFetchConsensusEventsRequest(
+                    HostFetchConsensusEventsRequest {
+                        height,
+                        kind,
+                        pagination: page.clone(),
+                    },
+                ))
+                .await
+                .map_err(|err| Error::VerificationFailed(err.into()))?;
+
+            match result {
+                Body::HostFetchConsensusEventsResponse(HostFetchConsensusEventsResponse {
+                    events: page_events,
+                    continuation_token,
+                }) => {
+                    page.check(page_events.len())
+                        .map_err(|err| Error::VerificationFailed(err.into()))?;
+                    events.extend(page_events);
+                    pages
+                        .advance(continuation_token)
+                        .map_err(|err| Error::VerificationFailed(err.into()))?;
+                }
+                _ => return Err(Error::VerificationFailed(anyhow!("bad response from host"))),
             }
-            _ => Err(Error::VerificationFailed(anyhow!("bad response from host"))),
         }
+
+        Ok(events)
     }
 
     async fn latest_height(&self) -> Result<u64, Error> {
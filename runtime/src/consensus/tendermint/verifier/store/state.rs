@@ -69,6 +69,15 @@ pub struct TrustedState {
     pub trusted_blocks: Vec<EncodedLightBlock>,
 }
 
+impl TrustedState {
+    /// Time (in seconds since the UNIX epoch) of the latest trusted light block, if any.
+    pub fn latest_block_time(&self) -> Option<i64> {
+        self.trusted_blocks
+            .last()
+            .map(|block| block.0.signed_header.header.time.unix_timestamp())
+    }
+}
+
 /// Untrusted local storage for storing the sealed latest trusted root.
 pub struct TrustedStateStore {
     runtime_id: Namespace,
@@ -17,6 +17,7 @@ pub type Nonce = [u8; NONCE_SIZE];
 /// Command sent to the verifier thread.
 pub enum Command {
     Synchronize(u64, oneshot::Sender<Result<(), Error>>),
+    SyncBlock(LightBlock, oneshot::Sender<Result<(), Error>>),
     Verify(
         LightBlock,
         Header,
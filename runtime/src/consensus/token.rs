@@ -0,0 +1,302 @@
+//! Token amounts and denominations.
+use std::fmt;
+
+use num_traits::Zero;
+use thiserror::Error;
+
+use crate::common::quantity::Quantity;
+
+/// Symbol of the consensus layer token.
+pub const CONSENSUS_SYMBOL: &str = "ROSE";
+/// Number of decimal places of the consensus layer token.
+pub const CONSENSUS_DECIMALS: u8 = 9;
+
+/// Token amount errors.
+#[derive(Error, Debug, PartialEq, Eq)]
+pub enum Error {
+    #[error("malformed amount")]
+    MalformedAmount,
+    #[error("too many decimal places (max {0})")]
+    TooManyDecimals(u8),
+    #[error("denomination mismatch: {0} vs. {1}")]
+    DenominationMismatch(String, String),
+    #[error("conversion would lose precision")]
+    PrecisionLoss,
+    #[error("amount overflow")]
+    Overflow,
+}
+
+/// Token denomination.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Hash, cbor::Encode, cbor::Decode)]
+pub struct Denomination {
+    /// Token symbol.
+    pub symbol: String,
+    /// Number of decimal places used when formatting amounts, i.e. one token corresponds to
+    /// `10^decimals` base units.
+    pub decimals: u8,
+}
+
+impl Denomination {
+    /// Create a new denomination.
+    pub fn new(symbol: &str, decimals: u8) -> Self {
+        Self {
+            symbol: symbol.to_string(),
+            decimals,
+        }
+    }
+
+    /// Denomination of the consensus layer token.
+    pub fn consensus() -> Self {
+        Self::new(CONSENSUS_SYMBOL, CONSENSUS_DECIMALS)
+    }
+
+    /// Number of base units in one token.
+    pub fn scale(&self) -> Quantity {
+        pow10(self.decimals)
+    }
+}
+
+impl fmt::Display for Denomination {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.symbol.fmt(f)
+    }
+}
+
+/// An amount of tokens of a given denomination, stored in base units.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Hash, cbor::Encode, cbor::Decode)]
+pub struct TokenAmount {
+    /// Amount in base units.
+    pub amount: Quantity,
+    /// Denomination.
+    pub denomination: Denomination,
+}
+
+impl TokenAmount {
+    /// Create a new token amount from the given number of base units.
+    pub fn from_base_units<Q: Into<Quantity>>(amount: Q, denomination: Denomination) -> Self {
+        Self {
+            amount: amount.into(),
+            denomination,
+        }
+    }
+
+    /// Create a new consensus layer token amount from the given number of base units.
+    pub fn consensus<Q: Into<Quantity>>(amount: Q) -> Self {
+        Self::from_base_units(amount, Denomination::consensus())
+    }
+
+    /// Parse a decimal token amount (e.g. `1.5`) in the given denomination.
+    pub fn parse(s: &str, denomination: Denomination) -> Result<Self, Error> {
+        let (int, frac) = s.split_once('.').unwrap_or((s, ""));
+        if int.is_empty() || (s.contains('.') && frac.is_empty()) {
+            return Err(Error::MalformedAmount);
+        }
+        if frac.len() > denomination.decimals as usize {
+            return Err(Error::TooManyDecimals(denomination.decimals));
+        }
+
+        let padding = denomination.decimals as usize - frac.len();
+        let mut amount = Quantity::zero();
+        for c in int.chars().chain(frac.chars()) {
+            let digit = c.to_digit(10).ok_or(Error::MalformedAmount)?;
+            amount *= 10;
+            amount += digit as u64;
+        }
+        amount *= pow10(padding as u8);
+
+        Ok(Self {
+            amount,
+            denomination,
+        })
+    }
+
+    /// Number of base units.
+    pub fn base_units(&self) -> &Quantity {
+        &self.amount
+    }
+
+    /// Whether the amount is zero.
+    pub fn is_zero(&self) -> bool {
+        self.amount.is_zero()
+    }
+
+    /// Add two amounts of the same denomination.
+    pub fn checked_add(&self, other: &TokenAmount) -> Result<Self, Error> {
+        self.ensure_same_denomination(other)?;
+        Ok(Self {
+            amount: self.amount.clone() + &other.amount,
+            denomination: self.denomination.clone(),
+        })
+    }
+
+    /// Subtract two amounts of the same denomination.
+    pub fn checked_sub(&self, other: &TokenAmount) -> Result<Self, Error> {
+        self.ensure_same_denomination(other)?;
+        Ok(Self {
+            amount: self
+                .amount
+                .checked_sub(&other.amount)
+                .ok_or(Error::Overflow)?,
+            denomination: self.denomination.clone(),
+        })
+    }
+
+    /// Multiply the amount by an integer.
+    pub fn mul_u64(&self, factor: u64) -> Self {
+        Self {
+            amount: self.amount.clone() * factor,
+            denomination: self.denomination.clone(),
+        }
+    }
+
+    /// Convert the amount to a denomination with the same symbol but a different number of
+    /// decimal places.
+    ///
+    /// Fails in case the conversion would lose precision.
+    pub fn convert(&self, denomination: &Denomination) -> Result<Self, Error> {
+        if self.denomination.symbol != denomination.symbol {
+            return Err(Error::DenominationMismatch(
+                self.denomination.symbol.clone(),
+                denomination.symbol.clone(),
+            ));
+        }
+
+        let amount = if denomination.decimals >= self.denomination.decimals {
+            self.amount.clone() * pow10(denomination.decimals - self.denomination.decimals)
+        } else {
+            let divisor = pow10(self.denomination.decimals - denomination.decimals);
+            let amount = self.amount.checked_div(&divisor).ok_or(Error::Overflow)?;
+            if amount.clone() * &divisor != self.amount {
+                return Err(Error::PrecisionLoss);
+            }
+            amount
+        };
+
+        Ok(Self {
+            amount,
+            denomination: denomination.clone(),
+        })
+    }
+
+    /// Convert the amount to consensus layer base units.
+    ///
+    /// Fails in case the amount is not denominated in the consensus layer token or the
+    /// conversion would lose precision.
+    pub fn to_consensus_base_units(&self) -> Result<Quantity, Error> {
+        Ok(self.convert(&Denomination::consensus())?.amount)
+    }
+
+    fn ensure_same_denomination(&self, other: &TokenAmount) -> Result<(), Error> {
+        if self.denomination != other.denomination {
+            return Err(Error::DenominationMismatch(
+                self.denomination.symbol.clone(),
+                other.denomination.symbol.clone(),
+            ));
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for TokenAmount {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let scale = self.denomination.scale();
+        let int = self.amount.checked_div(&scale).unwrap_or_default();
+        let frac = self
+            .amount
+            .checked_sub(&(int.clone() * &scale))
+            .unwrap_or_default();
+
+        write!(f, "{}", int)?;
+        if !frac.is_zero() {
+            let frac = format!(
+                "{:0>width$}",
+                frac.to_string(),
+                width = self.denomination.decimals as usize
+            );
+            write!(f, ".{}", frac.trim_end_matches('0'))?;
+        }
+        write!(f, " {}", self.denomination)
+    }
+}
+
+/// Computes `10^exp` as a quantity.
+fn pow10(exp: u8) -> Quantity {
+    let mut result = Quantity::from(1u64);
+    for _ in 0..exp {
+        result *= 10;
+    }
+    result
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_parse_format() {
+        let denom = Denomination::consensus();
+        let cases = vec![
+            ("0", 0u64, "0 ROSE"),
+            ("1", 1_000_000_000, "1 ROSE"),
+            ("1.5", 1_500_000_000, "1.5 ROSE"),
+            ("0.000000001", 1, "0.000000001 ROSE"),
+            ("123.456", 123_456_000_000, "123.456 ROSE"),
+        ];
+        for (input, base_units, formatted) in cases {
+            let amount = TokenAmount::parse(input, denom.clone()).unwrap();
+            assert_eq!(amount.base_units(), &Quantity::from(base_units));
+            assert_eq!(amount.to_string(), formatted);
+        }
+
+        assert_eq!(
+            TokenAmount::parse("0.0000000001", denom.clone()),
+            Err(Error::TooManyDecimals(9))
+        );
+        for input in ["", ".5", "1.", "1.2.3", "-1", "1e9"] {
+            assert_eq!(
+                TokenAmount::parse(input, denom.clone()),
+                Err(Error::MalformedAmount),
+                "{input}"
+            );
+        }
+    }
+
+    #[test]
+    fn test_arithmetic() {
+        let a = TokenAmount::consensus(1_000u64);
+        let b = TokenAmount::consensus(400u64);
+        assert_eq!(a.checked_add(&b).unwrap(), TokenAmount::consensus(1_400u64));
+        assert_eq!(a.checked_sub(&b).unwrap(), TokenAmount::consensus(600u64));
+        assert_eq!(b.checked_sub(&a), Err(Error::Overflow));
+        assert_eq!(a.mul_u64(3), TokenAmount::consensus(3_000u64));
+
+        let other = TokenAmount::from_base_units(1u64, Denomination::new("TEST", 18));
+        assert!(matches!(
+            a.checked_add(&other),
+            Err(Error::DenominationMismatch(..))
+        ));
+    }
+
+    #[test]
+    fn test_convert() {
+        // Runtimes commonly use 18 decimal places for the consensus token.
+        let runtime_denom = Denomination::new(CONSENSUS_SYMBOL, 18);
+        let amount = TokenAmount::parse("1.5", runtime_denom.clone()).unwrap();
+        assert_eq!(
+            amount.to_consensus_base_units().unwrap(),
+            Quantity::from(1_500_000_000u64)
+        );
+
+        let consensus = TokenAmount::consensus(1_500_000_000u64);
+        assert_eq!(consensus.convert(&runtime_denom).unwrap(), amount);
+
+        let dust = TokenAmount::from_base_units(1u64, runtime_denom);
+        assert_eq!(dust.to_consensus_base_units(), Err(Error::PrecisionLoss));
+
+        let other = TokenAmount::from_base_units(1u64, Denomination::new("TEST", 9));
+        assert!(matches!(
+            other.to_consensus_base_units(),
+            Err(Error::DenominationMismatch(..))
+        ));
+    }
+}
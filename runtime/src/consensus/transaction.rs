@@ -1,6 +1,9 @@
-use crate::common::{
-    crypto::signature::{signature_context_with_chain_separation, Signed},
-    quantity::Quantity,
+use crate::{
+    common::{
+        crypto::signature::{signature_context_with_chain_separation, Signed},
+        quantity::Quantity,
+    },
+    consensus::token::{self, TokenAmount},
 };
 
 pub const SIGNATURE_CONTEXT: &[u8] = b"oasis-core/consensus: tx";
@@ -46,6 +49,16 @@ pub struct Fee {
     pub gas: Gas,
 }
 
+impl Fee {
+    /// Create a new fee from a token amount, converting it to consensus base units.
+    pub fn new(amount: &TokenAmount, gas: Gas) -> Result<Self, token::Error> {
+        Ok(Self {
+            amount: amount.to_consensus_base_units()?,
+            gas,
+        })
+    }
+}
+
 /// Consensus gas representation.
 pub type Gas = u64;
 
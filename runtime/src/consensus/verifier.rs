@@ -75,6 +75,15 @@ pub trait Verifier: Send + Sync {
     /// Synchronize the verifier state up to including the passed consensus height.
     async fn sync(&self, height: u64) -> Result<(), Error>;
 
+    /// Provide a consensus layer block as soon as it is produced, before it is needed.
+    ///
+    /// This allows the verifier to maintain its view proactively instead of verifying lazily
+    /// when the block is first needed. The block is not trusted and is verified the same way as
+    /// blocks fetched from the host. Verifiers that don't benefit from this may ignore it.
+    async fn sync_block(&self, _consensus_block: LightBlock) -> Result<(), Error> {
+        Ok(())
+    }
+
     /// Verify that the given runtime header is valid at the given consensus layer block and return
     /// the consensus layer state accessor for that block.
     ///
@@ -137,6 +146,10 @@ impl<T: ?Sized + Verifier> Verifier for Arc<T> {
         Verifier::sync(&**self, height).await
     }
 
+    async fn sync_block(&self, consensus_block: LightBlock) -> Result<(), Error> {
+        Verifier::sync_block(&**self, consensus_block).await
+    }
+
     async fn verify(
         &self,
         consensus_block: LightBlock,
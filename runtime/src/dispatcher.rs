@@ -1,33 +1,45 @@
 //! Runtime call dispatcher.
 use std::{
     convert::TryInto,
+    num::NonZeroUsize,
     sync::{Arc, Condvar, Mutex},
     thread,
+    time::{Duration, Instant},
 };
 
-use anyhow::Result as AnyResult;
+use anyhow::{ensure, Result as AnyResult};
 use rustc_hex::ToHex;
 use slog::{debug, error, info, warn, Logger};
-use tokio::sync::mpsc;
+use tokio::sync::{mpsc, watch, OwnedSemaphorePermit, Semaphore};
 
 use crate::{
-    app, attestation, cache,
+    app, attestation,
+    build_info::{BuildInfoReport, METHOD_BUILD_INFO},
+    cache,
     common::{
         crypto::{hash::Hash, signature::Signer},
-        logger::get_logger,
+        json::{self, METHOD_DIAGNOSTICS_JSON},
+        logger::{get_logger, set_log_filter, set_log_sink, LogFilter},
         panic::AbortOnPanic,
         sgx::QuotePolicy,
     },
+    config::{self, Limit, LimitError, LogForwarding},
     consensus::{
         beacon::EpochTime,
+        events as consensus_events,
+        params::RuntimeParameters,
         roothash::{self, ComputeResultsHeader, Header, COMPUTE_RESULTS_HEADER_SIGNATURE_CONTEXT},
-        state::keymanager::Status as KeyManagerStatus,
+        state::{
+            keymanager::Status as KeyManagerStatus, roothash::ImmutableState as RoothashState,
+        },
         verifier::Verifier,
         LightBlock,
     },
     enclave_rpc::{
         demux::Demux as RpcDemux,
         dispatcher::Dispatcher as RpcDispatcher,
+        replay::{HighWaterStore, SealedHighWaterStore},
+        resumption::{TicketStore, DEFAULT_MAX_TICKETS},
         session::{self, SessionInfo},
         types::{
             Kind as RpcKind, Message as RpcMessage, Request as RpcRequest, Response as RpcResponse,
@@ -35,17 +47,50 @@ use crate::{
         Context as RpcContext,
     },
     future::block_on,
+    health::{HealthMonitor, METHOD_HEALTH},
+    host::{
+        lanes::{self, propagate_origin},
+        logs::LogForwarder,
+        queues::MessageClass,
+        Host, RegisterNotifyOpts,
+    },
     identity::Identity,
+    metrics::{
+        MetricsRegistry, METRIC_BATCH_EXECUTION_TIME, METRIC_STORAGE_CACHE_HITS,
+        METRIC_STORAGE_CACHE_MISSES, METRIC_STORAGE_NODES_CORRUPTED, METRIC_STORAGE_NODES_VERIFIED,
+    },
     policy::PolicyVerifier,
-    protocol::Protocol,
-    storage::mkvs::{sync::NoopReadSyncer, OverlayTree, Root, RootType},
+    protocol::{Protocol, ProtocolError, ProtocolUntrustedLocalStorage},
+    shutdown::{Orchestrator, ShutdownHooks, Stage},
+    storage::mkvs::{
+        checkpoint,
+        commit::CommitError,
+        metered::{MeteredTree, ReadMeter},
+        profile,
+        recorded::{ReadSet, RecordedTree},
+        sync::{HostSyncStats, NoopReadSyncer},
+        CacheMetrics, OverlayTree, Root, RootType,
+    },
+    tasks::{Schedule, Scheduler},
     transaction::{
-        dispatcher::{Dispatcher as TxnDispatcher, NoopDispatcher as TxnNoopDispatcher},
+        authenticator::{AuthenticatingDispatcher, Authenticator},
+        dispatcher::{
+            Dispatcher as TxnDispatcher, ExecuteBatchResult, NoopDispatcher as TxnNoopDispatcher,
+        },
+        events::EventRegistry,
+        parallel::ParallelExecutor,
+        scheduler::{BatchScheduler, SchedulingDispatcher},
+        shadow::{Candidate, Divergence, ExecutionSummary},
+        tags::Tags,
+        trace::{CallKind, CallSummary, CallTracer},
         tree::Tree as TxnTree,
         types::TxnBatch,
         Context as TxnContext,
     },
-    types::{Body, ComputedBatch, Error, ExecutionMode},
+    types::{
+        Body, CheckTxResult, ComputedBatch, Error, ExecutionMode, ExecutionUtilization,
+        HostShutdownNotice, ProtocolFeature, QueryProof, RuntimeNotifyConsensusEvent, ShutdownAck,
+    },
 };
 
 /// Maximum amount of requests that can be in the dispatcher queue.
@@ -56,9 +101,24 @@ const RPC_MAX_SESSIONS: usize = 1024;
 /// Maximum concurrent EnclaveRPC sessions per peer. In case more sessions are open, old sessions
 /// will be closed to make room for new sessions.
 const RPC_MAX_SESSIONS_PER_PEER: usize = 8;
+/// Maximum concurrent authenticated EnclaveRPC sessions per remote enclave identity, so that
+/// sessions of a single remote enclave cannot exhaust all session slots.
+const RPC_MAX_SESSIONS_PER_ENCLAVE: usize = RPC_MAX_SESSIONS / 2;
 /// EnclaveRPC sessions without any processed frame for more than RPC_STALE_SESSION_TIMEOUT_SECS
 /// seconds can be closed to make room for new sessions.
 const RPC_STALE_SESSION_TIMEOUT_SECS: i64 = 10;
+/// Maximum number of concurrently running background tasks.
+const TASKS_MAX_CONCURRENT: usize = 4;
+/// Maximum number of batches shadow-executed concurrently. Batches executed while the limit is
+/// reached are not shadow-executed.
+const SHADOW_MAX_CONCURRENT: usize = 1;
+
+/// Query methods returning diagnostic information about the runtime.
+const DIAGNOSTIC_METHODS: &[&str] = &[
+    METHOD_HEALTH,
+    profile::METHOD_MKVS_PROFILE,
+    METHOD_BUILD_INFO,
+];
 
 /// Interface for dispatcher initializers.
 pub trait Initializer: Send + Sync {
@@ -87,6 +147,10 @@ pub struct PreInitState<'a> {
     pub rpc_dispatcher: &'a mut RpcDispatcher,
     /// Consensus verifier instance.
     pub consensus_verifier: &'a Arc<dyn Verifier>,
+    /// Background task scheduler instance.
+    pub scheduler: &'a Arc<Scheduler>,
+    /// Shutdown hooks instance.
+    pub shutdown_hooks: &'a Arc<ShutdownHooks>,
 }
 
 /// State returned by the initializer.
@@ -94,8 +158,14 @@ pub struct PreInitState<'a> {
 pub struct PostInitState {
     /// Optional transaction dispatcher that should be used.
     pub txn_dispatcher: Option<Box<dyn TxnDispatcher>>,
+    /// Optional authenticator of inbound calls, invoked before the transaction dispatcher.
+    pub authenticator: Option<Box<dyn Authenticator>>,
+    /// Optional scheduler ordering initial batches in schedule execution mode.
+    pub scheduler: Option<Box<dyn BatchScheduler>>,
     /// Optional ROFL application.
     pub app: Option<Box<dyn app::App>>,
+    /// Optional candidate runtime version which should shadow-execute all batches.
+    pub shadow_candidate: Option<Candidate>,
 }
 
 impl From<tokio::task::JoinError> for Error {
@@ -109,6 +179,7 @@ impl From<tokio::task::JoinError> for Error {
 }
 
 /// State related to dispatching a runtime transaction.
+#[derive(Clone)]
 struct TxDispatchState {
     mode: ExecutionMode,
     consensus_block: LightBlock,
@@ -120,6 +191,69 @@ struct TxDispatchState {
     check_only: bool,
 }
 
+/// Total size of the transactions in the given batch.
+fn batch_size_bytes(batch: &TxnBatch) -> u64 {
+    batch.iter().map(|tx| tx.len() as u64).sum()
+}
+
+/// Execute the given batch via the transaction dispatcher, across a pool of the given number of
+/// workers in case parallel execution is enabled and supported by the dispatcher.
+///
+/// Batches with incoming messages are always executed via `execute_batch`.
+fn execute_batch(
+    txn_dispatcher: &dyn TxnDispatcher,
+    mut ctx: TxnContext,
+    workers: usize,
+    batch: &TxnBatch,
+    in_msgs: &[roothash::IncomingMessage],
+) -> Result<ExecuteBatchResult, Error> {
+    if workers < 2 || !in_msgs.is_empty() || !txn_dispatcher.supports_parallel_execution() {
+        return txn_dispatcher.execute_batch(ctx, batch, in_msgs);
+    }
+
+    let result = ctx.execute_parallel(&ParallelExecutor::new(workers), batch, |tx, state| {
+        txn_dispatcher.execute_tx(tx, state)
+    })?;
+
+    Ok(ExecuteBatchResult {
+        results: result.results,
+        messages: Vec::new(),
+        in_msgs_count: 0,
+        block_tags: Tags::new(),
+        tx_reject_hashes: Vec::new(),
+    })
+}
+
+/// Drop the events of a transaction exceeding the event payload limit, so that a single oversized
+/// event only affects the transaction that emitted it instead of failing the whole batch.
+///
+/// Returns the limit errors of the dropped events.
+fn drop_oversized_events(limits: &config::Limits, tags: &mut Tags) -> Vec<LimitError> {
+    let mut errors = Vec::new();
+    tags.retain(
+        |tag| match limits.check(Limit::EventPayload, tag.value.len()) {
+            Ok(()) => true,
+            Err(err) => {
+                errors.push(err);
+                false
+            }
+        },
+    );
+    errors
+}
+
+/// Validate the messages emitted in a round against the limits of the runtime parameters.
+fn validate_messages(
+    params: &RuntimeParameters,
+    messages: Vec<roothash::Message>,
+) -> Result<Vec<roothash::Message>, Error> {
+    let mut builder = params.messages_builder();
+    for msg in messages {
+        builder.push(msg).map_err(anyhow::Error::from)?;
+    }
+    Ok(builder.build())
+}
+
 /// State provided by the protocol upon successful initialization.
 struct ProtocolState {
     protocol: Arc<Protocol>,
@@ -136,9 +270,75 @@ struct State {
     rpc_demux: Arc<RpcDemux>,
     rpc_dispatcher: Arc<RpcDispatcher>,
     txn_dispatcher: Arc<dyn TxnDispatcher>,
+    shadow_candidate: Option<Arc<Candidate>>,
+    shadow_permits: Arc<Semaphore>,
     attestation_handler: attestation::Handler,
     policy_verifier: Arc<PolicyVerifier>,
     cache_set: cache::CacheSet,
+    checkpoint_restorer: Arc<Mutex<Option<checkpoint::Restorer>>>,
+    batch_gate: Arc<BatchGate>,
+    scheduler: Arc<Scheduler>,
+    shutdown_hooks: Arc<ShutdownHooks>,
+}
+
+/// Guard of an in-flight transaction batch, which shutdown waits for.
+struct BatchGuard {
+    in_flight: Arc<watch::Sender<usize>>,
+}
+
+impl Drop for BatchGuard {
+    fn drop(&mut self) {
+        self.in_flight.send_modify(|count| *count -= 1);
+    }
+}
+
+/// Gate of transaction batches, closed once the runtime starts shutting down.
+struct BatchGate {
+    closed: Mutex<bool>,
+    in_flight: Arc<watch::Sender<usize>>,
+}
+
+impl BatchGate {
+    fn new() -> Self {
+        Self {
+            closed: Mutex::new(false),
+            in_flight: Arc::new(watch::channel(0).0),
+        }
+    }
+
+    /// Start a new batch, which is tracked until the returned guard is dropped.
+    fn begin(&self) -> Result<BatchGuard, ProtocolError> {
+        let closed = self.closed.lock().unwrap();
+        if *closed {
+            return Err(ProtocolError::ShuttingDown);
+        }
+        self.in_flight.send_modify(|count| *count += 1);
+
+        Ok(BatchGuard {
+            in_flight: self.in_flight.clone(),
+        })
+    }
+
+    /// Stop accepting batches.
+    fn close(&self) {
+        *self.closed.lock().unwrap() = true;
+    }
+
+    /// Wait for in-flight batches to complete, up to the given timeout. Returns whether all
+    /// in-flight batches completed.
+    async fn wait(&self, timeout: Duration) -> bool {
+        let mut in_flight = self.in_flight.subscribe();
+        tokio::time::timeout(timeout, in_flight.wait_for(|count| *count == 0))
+            .await
+            .is_ok()
+    }
+}
+
+/// Time to prepare for the shutdown announced by the given notice.
+///
+/// The grace period is chosen by the host, so it is capped at the configured shutdown timeout.
+fn notice_grace_period(notice: &HostShutdownNotice, config: &config::Shutdown) -> Duration {
+    Duration::from_secs(notice.grace_period).min(config.timeout)
 }
 
 #[derive(Debug)]
@@ -220,26 +420,87 @@ impl Dispatcher {
         // Ensure Tokio runtime is available during dispatcher initialization.
         let _guard = self.tokio_runtime.enter();
 
+        // Negotiate protocol features before any requests are handled.
+        self.tokio_runtime.block_on(protocol.negotiate_features());
+
         // Create actual dispatchers for RPCs and transactions.
         info!(self.logger, "Starting the runtime dispatcher");
+        let resumption = &protocol.get_config().rpc_session_resumption;
+        let ticket_store = (!resumption.ticket_ttl.is_zero()).then(|| {
+            Arc::new(TicketStore::new(
+                resumption.ticket_ttl.as_secs() as i64,
+                DEFAULT_MAX_TICKETS,
+            ))
+        });
+        let replay_store =
+            protocol
+                .get_config()
+                .rpc_persist_replay_windows
+                .then(|| -> Arc<dyn HighWaterStore> {
+                    Arc::new(SealedHighWaterStore::new(Arc::new(
+                        ProtocolUntrustedLocalStorage::new(protocol.clone()),
+                    )))
+                });
         let mut rpc_demux = RpcDemux::new(
-            session::Builder::default().local_identity(self.identity.clone()),
+            session::Builder::default()
+                .local_identity(self.identity.clone())
+                .ticket_store(ticket_store)
+                .ratchet_interval(resumption.ratchet_interval)
+                .replay_window(protocol.get_config().rpc_replay_window)
+                .post_quantum_policy(protocol.get_config().rpc_post_quantum_policy)
+                .replay_store(replay_store),
             RPC_MAX_SESSIONS,
             RPC_MAX_SESSIONS_PER_PEER,
             RPC_STALE_SESSION_TIMEOUT_SECS,
         );
+        rpc_demux.set_max_sessions_per_enclave(RPC_MAX_SESSIONS_PER_ENCLAVE);
+        rpc_demux.set_frame_versions(protocol.get_config().rpc_frame_codecs.accepted.clone());
         let mut rpc_dispatcher = RpcDispatcher::default();
+        let scheduler = Arc::new(Scheduler::new(
+            self.tokio_runtime.clone(),
+            TASKS_MAX_CONCURRENT,
+        ));
+        let shutdown_hooks = Arc::new(ShutdownHooks::default());
         let pre_init_state = PreInitState {
             protocol: &protocol,
             identity: &self.identity,
             rpc_demux: &mut rpc_demux,
             rpc_dispatcher: &mut rpc_dispatcher,
             consensus_verifier: &consensus_verifier,
+            scheduler: &scheduler,
+            shutdown_hooks: &shutdown_hooks,
         };
         let post_init_state = initializer.init(pre_init_state);
-        let txn_dispatcher = post_init_state
+
+        // Enable call tracing if configured.
+        let call_trace_capacity = protocol.get_config().call_trace_capacity;
+        if call_trace_capacity > 0 {
+            CallTracer::global().set_capacity(call_trace_capacity);
+            rpc_dispatcher.add_method(CallTracer::rpc_method(
+                &protocol.get_config().call_trace_access,
+            ));
+        }
+
+        // Expose the schemas of events registered by the runtime.
+        rpc_dispatcher.add_method(EventRegistry::rpc_method(protocol.event_registry().clone()));
+
+        // Enable EnclaveRPC response caching if configured.
+        if let Some(capacity) = NonZeroUsize::new(protocol.get_config().rpc_response_cache_capacity)
+        {
+            rpc_dispatcher.enable_response_cache(capacity);
+        }
+        rpc_dispatcher.enable_admission_control(protocol.get_config().rpc_admission.clone());
+
+        let mut txn_dispatcher = post_init_state
             .txn_dispatcher
             .unwrap_or_else(|| Box::<TxnNoopDispatcher>::default());
+        if let Some(authenticator) = post_init_state.authenticator {
+            txn_dispatcher = Box::new(AuthenticatingDispatcher::new(txn_dispatcher, authenticator));
+        }
+        // Scheduling must happen first, so that authentication results match the final batch.
+        if let Some(scheduler) = post_init_state.scheduler {
+            txn_dispatcher = Box::new(SchedulingDispatcher::new(txn_dispatcher, scheduler));
+        }
         let mut app = post_init_state
             .app
             .unwrap_or_else(|| Box::new(app::NoopApp));
@@ -258,20 +519,57 @@ impl Dispatcher {
             rpc_demux: Arc::new(rpc_demux),
             rpc_dispatcher: Arc::new(rpc_dispatcher),
             txn_dispatcher: Arc::from(txn_dispatcher),
+            shadow_candidate: post_init_state.shadow_candidate.map(Arc::new),
+            shadow_permits: Arc::new(Semaphore::new(SHADOW_MAX_CONCURRENT)),
             attestation_handler: attestation::Handler::new(
                 self.identity.clone(),
                 protocol.clone(),
                 consensus_verifier.clone(),
                 protocol.get_runtime_id(),
                 protocol.get_config().version,
+                protocol.get_config().attestation_audit_mode,
                 app,
             ),
             policy_verifier: Arc::new(PolicyVerifier::new(consensus_verifier)),
             cache_set: cache::CacheSet::new(protocol.clone()),
+            checkpoint_restorer: Arc::new(Mutex::new(None)),
+            batch_gate: Arc::new(BatchGate::new()),
+            scheduler: scheduler.clone(),
+            shutdown_hooks,
         };
 
+        // Start background tasks.
+        if let Some(interval) = protocol.get_config().health_report_interval {
+            self.push_health_reports(&scheduler, protocol.clone(), interval);
+        }
+        if let Some(interval) = protocol.get_config().metrics_push_interval {
+            self.push_metrics(&scheduler, protocol.clone(), interval);
+        }
+        if let Some(config) = protocol.get_config().log_forwarding.clone() {
+            if protocol.features().contains(ProtocolFeature::LogForwarding) {
+                self.forward_logs(&scheduler, protocol.clone(), config);
+            }
+        }
+        if let Some(interval) = protocol.get_config().storage.integrity_check_interval {
+            self.check_storage_integrity(
+                &scheduler,
+                protocol.clone(),
+                state.cache_set.clone(),
+                interval,
+            );
+        }
+
         // Start the async message processing task.
         self.tokio_runtime.block_on(async move {
+            // Start RPC services before serving any requests.
+            if let Err(err) = state.rpc_dispatcher.start_services().await {
+                error!(self.logger, "Failed to start RPC services"; "err" => ?err);
+            }
+
+            if protocol.get_config().proactive_consensus_sync {
+                self.subscribe_consensus_blocks(protocol.clone());
+            }
+
             while let Some(cmd) = rx.recv().await {
                 // Process received command.
                 match cmd {
@@ -282,7 +580,13 @@ impl Dispatcher {
                         tokio::spawn(async move {
                             let protocol = state.protocol.clone();
                             let dispatcher = state.dispatcher.clone();
-                            let result = dispatcher.handle_request(state, request).await;
+                            // Host calls made while handling the request use its lane.
+                            let class = MessageClass::of(&request);
+                            let result = lanes::with_origin(
+                                class,
+                                dispatcher.handle_request(state, request),
+                            )
+                            .await;
 
                             // Send response.
                             let response = match result {
@@ -297,6 +601,127 @@ impl Dispatcher {
         });
 
         info!(self.logger, "Runtime call dispatcher is terminating");
+        scheduler.shutdown();
+    }
+
+    /// Subscribe to consensus block notifications for the lifetime of the runtime.
+    fn subscribe_consensus_blocks(&self, protocol: Arc<Protocol>) {
+        let logger = self.logger.clone();
+        tokio::spawn(async move {
+            let _handle = match protocol
+                .register_notify(RegisterNotifyOpts {
+                    runtime_block: false,
+                    runtime_event: vec![],
+                    runtime_event_prefix: vec![],
+                    consensus_block: true,
+                    consensus_event: vec![],
+                    external_data: vec![],
+                })
+                .await
+            {
+                Ok(handle) => handle,
+                Err(err) => {
+                    error!(logger, "Failed to subscribe to consensus blocks"; "err" => ?err);
+                    return;
+                }
+            };
+
+            // Keep the registration active.
+            std::future::pending::<()>().await;
+        });
+    }
+
+    /// Periodically push health reports to the host.
+    fn push_health_reports(
+        &self,
+        scheduler: &Scheduler,
+        protocol: Arc<Protocol>,
+        interval: Duration,
+    ) {
+        let identity = self.identity.clone();
+        scheduler.spawn("health_reports", Schedule::periodic(interval), move || {
+            let protocol = protocol.clone();
+            let report = HealthMonitor::global().report(&identity);
+            async move {
+                protocol
+                    .call_host_async(Body::HostHealthReportRequest { report })
+                    .await?;
+                Ok(())
+            }
+        });
+    }
+
+    /// Periodically push metrics snapshots to the host.
+    fn push_metrics(&self, scheduler: &Scheduler, protocol: Arc<Protocol>, interval: Duration) {
+        scheduler.spawn("metrics_push", Schedule::periodic(interval), move || {
+            let protocol = protocol.clone();
+            let snapshot = MetricsRegistry::global().snapshot();
+            async move {
+                protocol
+                    .call_host_async(Body::HostMetricsPushRequest { snapshot })
+                    .await?;
+                Ok(())
+            }
+        });
+    }
+
+    /// Periodically re-hash a sample of cached storage nodes, alerting the host of corruption.
+    fn check_storage_integrity(
+        &self,
+        scheduler: &Scheduler,
+        protocol: Arc<Protocol>,
+        cache_set: cache::CacheSet,
+        interval: Duration,
+    ) {
+        let logger = self.logger.clone();
+        let sample = protocol.get_config().storage.integrity_check_sample;
+        scheduler.spawn(
+            "storage_integrity",
+            Schedule::periodic(interval),
+            move || {
+                let protocol = protocol.clone();
+                let logger = logger.clone();
+                let reports = cache_set.verify_integrity(sample);
+                async move {
+                    for report in reports {
+                        let metrics = MetricsRegistry::global();
+                        metrics.inc(METRIC_STORAGE_NODES_VERIFIED, report.checked);
+                        if !report.is_corrupted() {
+                            continue;
+                        }
+                        metrics.inc(
+                            METRIC_STORAGE_NODES_CORRUPTED,
+                            report.corrupted.len() as u64,
+                        );
+
+                        error!(logger, "Corrupted cached storage nodes detected";
+                            "root" => ?report.root,
+                            "corrupted" => ?report.corrupted,
+                        );
+                        protocol
+                            .call_host_async(Body::HostStorageCorruptionRequest { report })
+                            .await?;
+                    }
+                    Ok(())
+                }
+            },
+        );
+    }
+
+    /// Periodically forward captured log records to the host.
+    fn forward_logs(&self, scheduler: &Scheduler, protocol: Arc<Protocol>, config: LogForwarding) {
+        let interval = config.interval;
+        let forwarder = LogForwarder::global();
+        forwarder.configure(config);
+        set_log_sink(Some(forwarder));
+
+        scheduler.spawn("log_forwarding", Schedule::periodic(interval), move || {
+            let protocol = protocol.clone();
+            async move {
+                forwarder.flush(&protocol).await?;
+                Ok(())
+            }
+        });
     }
 
     async fn handle_request(self: &Arc<Self>, state: State, request: Body) -> Result<Body, Error> {
@@ -306,7 +731,8 @@ impl Dispatcher {
             | Body::RuntimeCapabilityTEERakReportRequest {}
             | Body::RuntimeCapabilityTEERakAvrRequest { .. }
             | Body::RuntimeCapabilityTEERakQuoteRequest { .. }
-            | Body::RuntimeCapabilityTEEUpdateEndorsementRequest { .. } => {
+            | Body::RuntimeCapabilityTEEUpdateEndorsementRequest { .. }
+            | Body::RuntimeCapabilityTEECollateralLoadRequest { .. } => {
                 Ok(state.attestation_handler.handle(request).await?)
             }
 
@@ -327,8 +753,20 @@ impl Dispatcher {
                     }
                     RpcKind::InsecureQuery => self.dispatch_insecure_rpc(state, request).await,
                     RpcKind::LocalQuery => self.dispatch_local_rpc(state, request).await,
+                    RpcKind::Stream => Err(Error::new(
+                        "rhp/dispatcher",
+                        1,
+                        "stream frames must be sent as stream requests",
+                    )),
                 }
             }
+            Body::RuntimeRPCStreamRequest { request, peer_id } => {
+                debug!(self.logger, "Received RPC stream request";
+                    "peer_id" => peer_id.to_hex::<String>(),
+                );
+
+                self.dispatch_secure_rpc(state, request, peer_id).await
+            }
             Body::RuntimeLocalRPCCallRequest { request } => {
                 debug!(self.logger, "Received RPC call request";
                     "kind" => ?RpcKind::LocalQuery,
@@ -351,25 +789,74 @@ impl Dispatcher {
                 max_messages,
             } => {
                 // Transaction execution.
-                self.dispatch_txn(
-                    state.cache_set,
-                    &state.txn_dispatcher,
-                    &state.protocol,
-                    io_root,
-                    inputs.unwrap_or_default(),
-                    in_msgs,
-                    TxDispatchState {
-                        mode,
-                        consensus_block,
-                        consensus_verifier: state.consensus_verifier,
-                        header: block.header,
-                        epoch,
-                        round_results,
-                        max_messages,
-                        check_only: false,
-                    },
-                )
-                .await
+                let _batch = state.batch_gate.begin()?;
+                let inputs = inputs.unwrap_or_default();
+                let tx_state = TxDispatchState {
+                    mode,
+                    consensus_block,
+                    consensus_verifier: state.consensus_verifier,
+                    header: block.header,
+                    epoch,
+                    round_results,
+                    max_messages,
+                    check_only: false,
+                };
+
+                // Batches executed in schedule mode are not shadow-executed, as the candidate
+                // could schedule them differently.
+                let shadow = state
+                    .shadow_candidate
+                    .filter(|_| tx_state.mode == ExecutionMode::Execute)
+                    .and_then(|candidate| {
+                        // Bound shadow execution so that a slow candidate can't pile up tasks.
+                        let permit = match state.shadow_permits.clone().try_acquire_owned() {
+                            Ok(permit) => permit,
+                            Err(_) => {
+                                warn!(self.logger, "Skipping shadow execution of batch";
+                                    "round" => tx_state.header.round + 1,
+                                );
+                                return None;
+                            }
+                        };
+                        Some((
+                            candidate,
+                            permit,
+                            inputs.clone(),
+                            in_msgs.clone(),
+                            tx_state.clone(),
+                        ))
+                    });
+
+                let result = self
+                    .dispatch_txn(
+                        state.cache_set.clone(),
+                        &state.txn_dispatcher,
+                        &state.protocol,
+                        io_root,
+                        inputs,
+                        in_msgs,
+                        tx_state,
+                    )
+                    .await;
+
+                if let (
+                    Ok(Body::RuntimeExecuteTxBatchResponse { batch, .. }),
+                    Some((candidate, permit, inputs, in_msgs, tx_state)),
+                ) = (&result, shadow)
+                {
+                    self.dispatch_shadow_txn(
+                        state.cache_set,
+                        candidate,
+                        permit,
+                        &state.protocol,
+                        inputs,
+                        in_msgs,
+                        tx_state,
+                        ExecutionSummary::from(&batch.header),
+                    );
+                }
+
+                result
             }
             Body::RuntimeCheckTxBatchRequest {
                 consensus_block,
@@ -379,6 +866,7 @@ impl Dispatcher {
                 max_messages,
             } => {
                 // Transaction check.
+                let _batch = state.batch_gate.begin()?;
                 self.dispatch_txn(
                     state.cache_set,
                     &state.txn_dispatcher,
@@ -399,6 +887,28 @@ impl Dispatcher {
                 )
                 .await
             }
+            Body::RuntimeQueryRequest { method, .. }
+                if DIAGNOSTIC_METHODS.contains(&method.as_str()) =>
+            {
+                // Diagnostics.
+                Ok(Body::RuntimeQueryResponse {
+                    data: cbor::to_vec(self.diagnostic(&state, &method)?),
+                    proof: None,
+                })
+            }
+            Body::RuntimeQueryRequest { method, args, .. } if method == METHOD_DIAGNOSTICS_JSON => {
+                // Diagnostics rendered as JSON, for inspection with standard tools.
+                let method: String = cbor::from_slice(&args)
+                    .map_err(|_| Error::new("rhp/dispatcher", 1, "malformed request"))?;
+                if !DIAGNOSTIC_METHODS.contains(&method.as_str()) {
+                    return Err(Error::new("rhp/dispatcher", 1, "unknown diagnostic method"));
+                }
+                let value = self.diagnostic(&state, &method)?;
+                Ok(Body::RuntimeQueryResponse {
+                    data: json::to_json(&value).to_string().into_bytes(),
+                    proof: None,
+                })
+            }
             Body::RuntimeQueryRequest {
                 consensus_block,
                 header,
@@ -406,6 +916,8 @@ impl Dispatcher {
                 max_messages,
                 method,
                 args,
+                with_proof,
+                ..
             } if state.txn_dispatcher.is_supported() => {
                 // Query.
                 self.dispatch_query(
@@ -414,6 +926,7 @@ impl Dispatcher {
                     &state.protocol,
                     method,
                     args,
+                    with_proof,
                     TxDispatchState {
                         mode: ExecutionMode::Execute,
                         consensus_block,
@@ -433,13 +946,28 @@ impl Dispatcher {
                 .app
                 .query(&method, args)
                 .await
-                .map(|data| Body::RuntimeQueryResponse { data })
+                .map(|data| Body::RuntimeQueryResponse { data, proof: None })
                 .map_err(Into::into),
             Body::RuntimeNotifyRequest {
                 runtime_block,
                 runtime_event,
+                consensus_block,
+                consensus_event,
+                external_data,
             } => {
+                if let Some(consensus_block) = consensus_block {
+                    if let Err(err) = state.consensus_verifier.sync_block(consensus_block).await {
+                        warn!(self.logger, "Consensus block notification failed"; "err" => ?err);
+                    }
+                }
+                state
+                    .protocol
+                    .notify_registry
+                    .deliver(runtime_block.as_ref(), runtime_event.as_ref());
                 if let Some(runtime_block) = runtime_block {
+                    state
+                        .rpc_dispatcher
+                        .advance_round(runtime_block.block.header.round);
                     if let Err(err) = state.app.on_runtime_block(&runtime_block).await {
                         error!(self.logger, "Application block notification failed"; "err" => ?err);
                     }
@@ -453,6 +981,19 @@ impl Dispatcher {
                         error!(self.logger, "Application event notification failed"; "err" => ?err);
                     }
                 }
+                if let Some(consensus_event) = consensus_event {
+                    self.handle_consensus_event(&state, consensus_event).await;
+                }
+                if let Some(external_data) = external_data {
+                    // The host is not trusted, so data is only delivered once verified.
+                    let storage = ProtocolUntrustedLocalStorage::new(state.protocol.clone());
+                    match state.protocol.feed_verifier.verify(external_data, &storage) {
+                        Ok(data) => state.protocol.notify_registry.deliver_feed_data(&data),
+                        Err(err) => {
+                            warn!(self.logger, "External data notification rejected"; "err" => ?err)
+                        }
+                    }
+                }
 
                 Ok(Body::Empty {})
             }
@@ -469,12 +1010,159 @@ impl Dispatcher {
                 self.handle_km_quote_policy_update(state, quote_policy)
                     .await
             }
+            Body::RuntimeLogConfigRequest { level, modules } => {
+                // Log level and filter update.
+                let filter = LogFilter::parse(&level, &modules)
+                    .map_err(|err| Error::new("rhp/dispatcher", 1, &format!("{err}")))?;
+                info!(self.logger, "Updating log filter"; "level" => %level, "modules" => ?modules);
+                set_log_filter(filter);
+
+                Ok(Body::RuntimeLogConfigResponse {})
+            }
             Body::RuntimeConsensusSyncRequest { height } => state
                 .consensus_verifier
                 .sync(height)
                 .await
                 .map_err(Into::into)
                 .map(|_| Body::RuntimeConsensusSyncResponse {}),
+            Body::RuntimeShutdownRequest {} => {
+                let grace_period = state.protocol.get_config().shutdown.timeout;
+                let ack = self.prepare_shutdown(&state, grace_period, true).await;
+                Ok(Body::RuntimeShutdownResponse { ack })
+            }
+            Body::RuntimeShutdownNoticeRequest { notice } => {
+                let grace_period =
+                    notice_grace_period(&notice, &state.protocol.get_config().shutdown);
+                info!(self.logger, "Received shutdown notice";
+                    "grace_period" => ?grace_period,
+                    "reason" => &notice.reason,
+                );
+
+                // Background tasks keep running until the actual shutdown request.
+                let ack = self.prepare_shutdown(&state, grace_period, false).await;
+                Ok(Body::RuntimeShutdownNoticeResponse { ack })
+            }
+            Body::RuntimeStorageResyncRequest { root } => {
+                // Storage cache flush and resync.
+                if root.namespace != state.protocol.get_runtime_id() {
+                    return Err(Error::new(
+                        "rhp/dispatcher",
+                        1,
+                        "root namespace does not match runtime id",
+                    ));
+                }
+                warn!(self.logger, "Resyncing storage caches"; "root" => ?root);
+
+                let cache_set = state.cache_set.clone();
+                let report = tokio::task::spawn_blocking(move || cache_set.resync(root)).await??;
+                info!(self.logger, "Storage caches resynced";
+                    "generation" => report.generation,
+                    "dropped_shared_nodes" => report.dropped_shared_nodes,
+                );
+
+                Ok(Body::RuntimeStorageResyncResponse { report })
+            }
+            Body::RuntimeStorageCommitAckRequest { ack } => {
+                // Verification of the roots applied by the host.
+                match state.protocol.local_commits().verify(&ack) {
+                    Err(err @ CommitError::Divergence { .. }) => {
+                        error!(self.logger, "Host storage diverged from local commit";
+                            "round" => ack.round,
+                            "err" => %err,
+                        );
+                        HealthMonitor::global().record_storage_divergence(ack.round);
+                        Err(err.into())
+                    }
+                    result => result
+                        .map(|_| Body::RuntimeStorageCommitAckResponse {})
+                        .map_err(Into::into),
+                }
+            }
+            Body::RuntimeCheckpointCreateRequest {
+                root,
+                chunk_size,
+                start,
+                limit,
+            } => {
+                // State checkpoint creation.
+                if root.namespace != state.protocol.get_runtime_id() {
+                    return Err(Error::new(
+                        "rhp/dispatcher",
+                        1,
+                        "root namespace does not match runtime id",
+                    ));
+                }
+
+                // Use a fresh cache so that iterating the whole state doesn't evict the nodes
+                // used for executing transactions.
+                let cache = state.cache_set.shadow(root);
+                let (metadata, chunks) = tokio::task::spawn_blocking(move || {
+                    checkpoint::create(cache.tree(), root, chunk_size, start, limit)
+                })
+                .await??;
+                info!(self.logger, "Created state checkpoint";
+                    "root" => ?root,
+                    "chunks" => metadata.chunks.len(),
+                    "start" => start,
+                    "returned" => chunks.len(),
+                );
+
+                Ok(Body::RuntimeCheckpointCreateResponse { metadata, chunks })
+            }
+            Body::RuntimeCheckpointRestoreRequest { metadata } => {
+                // Start of a state checkpoint restore, replacing any restore in progress.
+                if metadata.root.namespace != state.protocol.get_runtime_id() {
+                    return Err(Error::new(
+                        "rhp/dispatcher",
+                        1,
+                        "root namespace does not match runtime id",
+                    ));
+                }
+                info!(self.logger, "Restoring state checkpoint";
+                    "root" => ?metadata.root,
+                    "chunks" => metadata.chunks.len(),
+                );
+
+                // Only restore roots finalized in the consensus layer, as the metadata is provided
+                // by the host.
+                let root = metadata.root;
+                self.verify_finalized_state_root(&state, root).await?;
+                let restorer = checkpoint::Restorer::new(metadata, root)?;
+                *state.checkpoint_restorer.lock().unwrap() = Some(restorer);
+
+                Ok(Body::RuntimeCheckpointRestoreResponse {})
+            }
+            Body::RuntimeCheckpointRestoreChunkRequest { index, chunk } => {
+                // State checkpoint chunk restore. Invalid chunks are rejected without aborting
+                // the restore, so that the host can fetch them from elsewhere.
+                let restorer = state.checkpoint_restorer.clone();
+                let restored = tokio::task::spawn_blocking(move || -> AnyResult<Option<Root>> {
+                    let mut restorer = restorer.lock().unwrap();
+                    let done = restorer
+                        .as_mut()
+                        .ok_or_else(|| anyhow::anyhow!("no checkpoint restore in progress"))?
+                        .restore_chunk(index, &chunk)?;
+                    if !done {
+                        return Ok(None);
+                    }
+
+                    let restorer = restorer.take().unwrap();
+                    let root = restorer.metadata().root;
+                    // The restore only succeeded in case the restored tree is at the root, which
+                    // was verified against the consensus layer when the restore started.
+                    restorer.finalize()?;
+                    Ok(Some(root))
+                })
+                .await??;
+                if let Some(root) = restored {
+                    info!(self.logger, "State checkpoint restored"; "root" => ?root);
+                }
+
+                Ok(Body::RuntimeCheckpointRestoreChunkResponse {
+                    done: restored.is_some(),
+                    root: restored,
+                })
+            }
 
             _ => {
                 error!(self.logger, "Unsupported request type");
@@ -483,6 +1171,181 @@ impl Dispatcher {
         }
     }
 
+    /// Prepare for the runtime being terminated once the given grace period elapses.
+    ///
+    /// Subsystems are torn down in stages (see [`crate::shutdown`]). New transaction batches are
+    /// rejected right away and in-flight batches, which commit their state changes as part of
+    /// completing, and RPC calls are drained concurrently. Background tasks are then stopped
+    /// unless only a shutdown notice has been received, write-ahead logs flushed and the
+    /// application persists its state, and finally in-flight host calls are settled and buffered
+    /// log records and metrics are flushed to the host.
+    async fn prepare_shutdown(
+        &self,
+        state: &State,
+        grace_period: Duration,
+        stop_tasks: bool,
+    ) -> ShutdownAck {
+        let config = state.protocol.get_config();
+        // Reject new batches while in-flight RPC calls are being drained.
+        state.batch_gate.close();
+
+        // Leave at least half of the grace period to the later stages.
+        let mut shutdown = Orchestrator::new(grace_period)
+            .with_timeout(
+                Stage::RpcDrain,
+                config.rpc_drain.timeout.min(grace_period / 2),
+            )
+            .with_timeout(Stage::BackgroundTasks, config.shutdown.tasks_timeout)
+            .with_timeout(Stage::WalFlush, config.shutdown.wal_flush_timeout)
+            .with_timeout(Stage::CachePersist, config.shutdown.cache_persist_timeout)
+            .with_concurrent_steps(Stage::RpcDrain);
+
+        shutdown.step(Stage::RpcDrain, "rpc", |budget| async move {
+            let drained = self.drain_rpc(state, budget).await;
+            self.stop_rpc_services(state).await;
+            ensure!(drained, "in-flight RPC calls did not complete");
+            Ok(())
+        });
+        shutdown.step(Stage::RpcDrain, "batches", |budget| async move {
+            ensure!(
+                state.batch_gate.wait(budget).await,
+                "in-flight transaction batches did not complete"
+            );
+            Ok(())
+        });
+        if stop_tasks {
+            let scheduler = state.scheduler.clone();
+            shutdown.step(Stage::BackgroundTasks, "tasks", |_| async move {
+                tokio::task::spawn_blocking(move || scheduler.shutdown()).await?;
+                Ok(())
+            });
+        }
+        shutdown.step(Stage::CachePersist, "app", |budget| async move {
+            state.app.on_shutdown(budget).await
+        });
+        // Buffered telemetry is flushed even if host calls had to be cancelled.
+        let host_call_timeout = config.shutdown.host_call_timeout;
+        shutdown.step(Stage::ProtocolClose, "host_calls", |budget| async move {
+            ensure!(
+                state
+                    .protocol
+                    .settle_host_calls(host_call_timeout.min(budget))
+                    .await,
+                "in-flight host calls were cancelled"
+            );
+            Ok(())
+        });
+        shutdown.step(Stage::ProtocolClose, "telemetry", |_| async move {
+            ensure!(
+                self.flush_telemetry(state).await,
+                "failed to flush telemetry"
+            );
+            Ok(())
+        });
+        shutdown.add_hooks(&state.shutdown_hooks);
+
+        let stages = shutdown.run().await;
+        let failed = |step: &str| stages.iter().any(|stage| stage.has_failed(step));
+        ShutdownAck {
+            rpc_drained: !failed("rpc"),
+            app_persisted: !failed("app"),
+            batches_drained: !failed("batches"),
+            host_calls_completed: !failed("host_calls"),
+            telemetry_flushed: !failed("telemetry"),
+            stages,
+        }
+    }
+
+    /// Flush buffered log records and metrics to the host, in case they are exported.
+    async fn flush_telemetry(&self, state: &State) -> bool {
+        let protocol = &state.protocol;
+        let mut flushed = true;
+        if protocol.get_config().log_forwarding.is_some()
+            && protocol.features().contains(ProtocolFeature::LogForwarding)
+        {
+            if let Err(err) = LogForwarder::global().flush_all(protocol).await {
+                warn!(self.logger, "Failed to flush log records"; "err" => ?err);
+                flushed = false;
+            }
+        }
+        if protocol.get_config().metrics_push_interval.is_some() {
+            let snapshot = MetricsRegistry::global().snapshot();
+            if let Err(err) = protocol
+                .call_host_async(Body::HostMetricsPushRequest { snapshot })
+                .await
+            {
+                warn!(self.logger, "Failed to flush metrics"; "err" => ?err);
+                flushed = false;
+            }
+        }
+        flushed
+    }
+
+    /// Result of the given diagnostic query method.
+    fn diagnostic(&self, state: &State, method: &str) -> Result<cbor::Value, Error> {
+        match method {
+            // Health report.
+            METHOD_HEALTH => Ok(cbor::Encode::into_cbor_value(
+                HealthMonitor::global().report(&self.identity),
+            )),
+            // Storage profile.
+            profile::METHOD_MKVS_PROFILE => Ok(cbor::Encode::into_cbor_value(profile::snapshot())),
+            // Signed build information.
+            METHOD_BUILD_INFO => {
+                let report = BuildInfoReport::new(&state.protocol, &self.identity)
+                    .sign(self.identity.as_ref())?;
+                Ok(cbor::Encode::into_cbor_value(report))
+            }
+            _ => Err(Error::new("rhp/dispatcher", 1, "unknown diagnostic method")),
+        }
+    }
+
+    /// Stop accepting RPC calls and wait for in-flight calls to complete, up to the given timeout.
+    async fn drain_rpc(&self, state: &State, timeout: Duration) -> bool {
+        info!(self.logger, "Draining RPC calls before shutdown";
+            "in_flight" => state.rpc_demux.in_flight(),
+        );
+        let retry_after = state.protocol.get_config().rpc_drain.retry_after;
+        let drained = state.rpc_demux.drain(timeout, retry_after).await;
+        if !drained {
+            warn!(self.logger, "Timed out while draining RPC calls";
+                "in_flight" => state.rpc_demux.in_flight(),
+            );
+        }
+        drained
+    }
+
+    /// Verify that the given root is a state root of the runtime finalized in the consensus
+    /// layer.
+    async fn verify_finalized_state_root(&self, state: &State, root: Root) -> Result<(), Error> {
+        let runtime_id = state.protocol.get_runtime_id();
+        let consensus_state = state.consensus_verifier.latest_state().await?;
+        tokio::task::block_in_place(move || -> AnyResult<()> {
+            let roothash = RoothashState::new(&consensus_state);
+            let last_block = roothash.runtime_state(runtime_id)?.last_block;
+            let state_root = if last_block.header.round == root.version {
+                Some(last_block.header.state_root)
+            } else {
+                roothash
+                    .round_roots(runtime_id, root.version)?
+                    .map(|roots| roots.state_root)
+            };
+            ensure!(
+                root.root_type == RootType::State && state_root == Some(root.hash),
+                "root not finalized in the consensus layer"
+            );
+            Ok(())
+        })?;
+        Ok(())
+    }
+
+    /// Stop and drop RPC services, once in-flight calls have been drained.
+    async fn stop_rpc_services(&self, state: &State) {
+        if let Err(err) = state.rpc_dispatcher.stop_services().await {
+            warn!(self.logger, "Failed to stop RPC services"; "err" => ?err);
+        }
+    }
+
     #[allow(clippy::too_many_arguments)]
     async fn dispatch_query(
         &self,
@@ -491,6 +1354,7 @@ impl Dispatcher {
         protocol: &Arc<Protocol>,
         method: String,
         args: Vec<u8>,
+        with_proof: bool,
         state: TxDispatchState,
     ) -> Result<Body, Error> {
         debug!(self.logger, "Received query request";
@@ -499,6 +1363,14 @@ impl Dispatcher {
             "round" => ?state.header.round,
         );
 
+        if with_proof && !protocol.features().contains(ProtocolFeature::QueryProofs) {
+            return Err(Error::new(
+                "rhp/dispatcher",
+                1,
+                "query proofs not negotiated",
+            ));
+        }
+
         // Verify that the runtime ID matches the block's namespace. This is a protocol violation
         // as the compute node should never change the runtime ID.
         if state.header.namespace != protocol.get_runtime_id() {
@@ -523,7 +1395,7 @@ impl Dispatcher {
             .unverified_state(state.consensus_block.clone())
             .await?;
 
-        tokio::task::spawn_blocking(move || {
+        tokio::task::spawn_blocking(propagate_origin(move || {
             let cache = cache_set.query(Root {
                 namespace: state.header.namespace,
                 version: state.header.round,
@@ -531,24 +1403,56 @@ impl Dispatcher {
                 hash: state.header.state_root,
             });
             let mut cache = cache.borrow_mut();
-            let mut overlay = OverlayTree::new(cache.tree_mut());
+            let meter = ReadMeter::new(protocol.get_config().query_limits);
+            let reads = with_proof.then(ReadSet::new);
 
-            let txn_ctx = TxnContext::new(
-                protocol,
-                &state.consensus_block,
-                consensus_state,
-                &mut overlay,
-                &state.header,
-                state.epoch,
-                &state.round_results,
-                state.max_messages,
-                state.check_only,
+            let start = Instant::now();
+            let result = {
+                let mut overlay = OverlayTree::new(cache.tree_mut());
+                let mut tree =
+                    MeteredTree::new(RecordedTree::new(&mut overlay, reads.as_ref()), &meter);
+
+                let txn_ctx = TxnContext::new(
+                    protocol,
+                    &state.consensus_block,
+                    consensus_state,
+                    &mut tree,
+                    &state.header,
+                    state.epoch,
+                    &state.round_results,
+                    state.max_messages,
+                    state.check_only,
+                );
+                txn_dispatcher.query(txn_ctx, &method, args)
+            };
+
+            // Prove all reads against the state the query was executed against.
+            let result = result.and_then(|data| {
+                let proof = match reads {
+                    Some(reads) => Some(QueryProof {
+                        round: state.header.round,
+                        state_root: state.header.state_root,
+                        proof: reads.proof(cache.tree())?,
+                        partial: reads.is_partial(),
+                    }),
+                    None => None,
+                };
+                Ok((data, proof))
+            });
+            // Storage reads past the limits are hidden, so the result must be discarded.
+            let response_size = result.as_ref().map_or(0, |(data, proof)| {
+                data.len() + proof.as_ref().map_or(0, |proof| proof.proof.size())
+            });
+            let result = match meter.check(response_size) {
+                Ok(()) => result,
+                Err(err) => Err(err.into()),
+            };
+            CallTracer::global().record(
+                CallSummary::new(CallKind::Query, &method, start.elapsed()).with_result(&result),
             );
 
-            txn_dispatcher
-                .query(txn_ctx, &method, args)
-                .map(|data| Body::RuntimeQueryResponse { data })
-        })
+            result.map(|(data, proof)| Body::RuntimeQueryResponse { data, proof })
+        }))
         .await?
     }
 
@@ -587,7 +1491,38 @@ impl Dispatcher {
             state.max_messages,
             state.check_only,
         );
-        let results = txn_dispatcher.check_batch(txn_ctx, &inputs);
+        // Reject oversized transactions before they reach the transaction dispatcher.
+        let limits = &protocol.get_config().limits;
+        let rejected: Vec<_> = inputs
+            .iter()
+            .map(|tx| limits.check(Limit::Transaction, tx.len()).err())
+            .collect();
+        let results = if rejected.iter().all(Option::is_none) {
+            txn_dispatcher.check_batch(txn_ctx, &inputs)
+        } else {
+            let accepted: TxnBatch = inputs
+                .iter()
+                .zip(&rejected)
+                .filter(|(_, err)| err.is_none())
+                .map(|(tx, _)| tx.clone())
+                .collect::<Vec<_>>()
+                .into();
+            txn_dispatcher
+                .check_batch(txn_ctx, &accepted)
+                .map(|results| {
+                    let mut results = results.into_iter();
+                    rejected
+                        .into_iter()
+                        .filter_map(|err| match err {
+                            Some(err) => Some(CheckTxResult {
+                                error: err.into(),
+                                meta: None,
+                            }),
+                            None => results.next(),
+                        })
+                        .collect()
+                })
+        };
 
         if protocol.get_config().persist_check_tx_state {
             // Commit results to in-memory tree so they persist for subsequent batches that are
@@ -595,6 +1530,22 @@ impl Dispatcher {
             let _ = overlay.commit().unwrap();
         }
 
+        let results_to_trace = results
+            .as_ref()
+            .ok()
+            .filter(|_| CallTracer::global().is_enabled());
+        if let Some(results) = results_to_trace {
+            for (tx, result) in inputs.iter().zip(results) {
+                let mut summary =
+                    CallSummary::transaction(CallKind::CheckTx, Hash::digest_bytes(tx))
+                        .with_error(&result.error);
+                if let Some(ref meta) = result.meta {
+                    summary = summary.with_caller(&meta.sender);
+                }
+                CallTracer::global().record(summary);
+            }
+        }
+
         debug!(self.logger, "Transaction batch check complete");
 
         results.map(|results| Body::RuntimeCheckTxBatchResponse { results })
@@ -613,16 +1564,35 @@ impl Dispatcher {
     ) -> Result<Body, Error> {
         // Verify consensus state and runtime state root integrity before execution.
         // TODO: Make this async.
-        let consensus_state = block_on(state.consensus_verifier.verify(
+        let mut consensus_state = block_on(state.consensus_verifier.verify(
             state.consensus_block.clone(),
             state.header.clone(),
             state.epoch,
         ))?;
+        // Track which consensus state influenced the round.
+        let consensus_reads = consensus_state.track_reads();
+        LogForwarder::global().set_round(Some(state.header.round + 1));
         // Ensure the runtime is still ready to process requests.
         protocol.ensure_initialized()?;
+        let limits = protocol.get_config().limits.clone();
+        let workers = protocol.get_config().parallel_execution_workers;
 
         let header = &state.header;
 
+        // Enforce the runtime parameters registered at the consensus height of the round instead
+        // of the limits reported by the host.
+        let params = RuntimeParameters::from_state(&consensus_state, &header.namespace)?;
+        let max_messages = match params {
+            Some(ref params) => {
+                if state.mode == ExecutionMode::Execute {
+                    params.check_batch(inputs.len(), batch_size_bytes(&inputs))?;
+                }
+                params.check_in_messages(in_msgs.len())?;
+                params.executor.max_messages
+            }
+            None => state.max_messages,
+        };
+
         let mut cache = cache_set.execute(Root {
             namespace: state.header.namespace,
             version: state.header.round,
@@ -632,28 +1602,44 @@ impl Dispatcher {
         let mut overlay = OverlayTree::new(cache.tree_mut());
 
         let txn_ctx = TxnContext::new(
-            protocol,
+            protocol.clone(),
             &state.consensus_block,
             consensus_state,
             &mut overlay,
             header,
             state.epoch,
             &state.round_results,
-            state.max_messages,
+            max_messages,
             state.check_only,
         );
+        let deferred = txn_ctx.deferred.clone();
+
+        // Track the resources used by execution, so the host can adapt batch sizes.
+        let start = Instant::now();
+        HostSyncStats::take();
+        CacheMetrics::take();
 
         // Perform execution based on the passed mode.
         let mut results = match state.mode {
             ExecutionMode::Execute => {
                 // Just execute the batch.
-                txn_dispatcher.execute_batch(txn_ctx, &inputs, &in_msgs)?
+                execute_batch(txn_dispatcher, txn_ctx, workers, &inputs, &in_msgs)?
             }
             ExecutionMode::Schedule => {
                 // Allow the runtime to arbitrarily update the batch.
                 txn_dispatcher.schedule_and_execute_batch(txn_ctx, &mut inputs, &in_msgs)?
             }
         };
+        if let Some(ref params) = params {
+            if state.mode == ExecutionMode::Schedule {
+                params.check_batch(inputs.len(), batch_size_bytes(&inputs))?;
+            }
+            results.messages = validate_messages(params, results.messages)?;
+        }
+
+        // Run deferred actions before committing state.
+        let deferred_tags = deferred.run(&mut overlay)?;
+        results.block_tags.extend(deferred_tags);
 
         // Finalize state.
         let (state_write_log, new_state_root) = overlay
@@ -662,6 +1648,9 @@ impl Dispatcher {
 
         txn_dispatcher.finalize(new_state_root);
         cache.commit(header.round + 1, new_state_root);
+        protocol
+            .dirty_sets()
+            .publish(header.round + 1, new_state_root, &state_write_log);
 
         // Generate I/O root. Since we already fetched the inputs we avoid the need
         // to fetch them again by generating the previous I/O tree (generated by the
@@ -675,6 +1664,8 @@ impl Dispatcher {
                 hash: Hash::empty_hash(),
             },
         );
+        let batch_size = inputs.len().try_into().unwrap();
+        let batch_size_bytes = batch_size_bytes(&inputs);
         let mut hashes = Vec::new();
         for (batch_order, input) in inputs.drain(..).enumerate() {
             hashes.push(Hash::digest_bytes(&input));
@@ -692,10 +1683,17 @@ impl Dispatcher {
             input_io_root
         );
 
-        for (tx_hash, result) in hashes.iter().zip(results.results.drain(..)) {
+        for (tx_hash, mut result) in hashes.iter().zip(results.results.drain(..)) {
+            for err in drop_oversized_events(&limits, &mut result.tags) {
+                warn!(self.logger, "Dropping oversized event";
+                    "tx_hash" => ?tx_hash,
+                    "err" => %err,
+                );
+            }
             txn_tree
                 .add_output(*tx_hash, result.output, result.tags)
                 .expect("add transaction must succeed");
+            CallTracer::global().record(CallSummary::transaction(CallKind::ExecuteTx, *tx_hash));
         }
 
         txn_tree
@@ -703,6 +1701,12 @@ impl Dispatcher {
             .expect("adding block tags must succeed");
 
         let (io_write_log, io_root) = txn_tree.commit().expect("io commit must succeed");
+        protocol.local_commits().record(
+            header.namespace,
+            header.round + 1,
+            new_state_root,
+            io_root,
+        );
 
         let header = ComputeResultsHeader {
             round: header.round + 1,
@@ -716,12 +1720,32 @@ impl Dispatcher {
             in_msgs_count: results.in_msgs_count.try_into().unwrap(),
         };
 
+        let consensus_reads = consensus_reads.report();
+        let sync_stats = HostSyncStats::take();
+        let cache_metrics = CacheMetrics::take();
+        let utilization = ExecutionUtilization {
+            batch_size,
+            batch_size_bytes,
+            execution_time_ms: start.elapsed().as_millis() as u64,
+            storage_sync_count: sync_stats.count,
+            storage_stall_time_ms: sync_stats.duration.as_millis() as u64,
+            storage_cache_hits: cache_metrics.hits,
+            storage_cache_misses: cache_metrics.misses,
+            storage_cache_evictions: cache_metrics.evictions,
+        };
+        let metrics = MetricsRegistry::global();
+        metrics.observe_since(METRIC_BATCH_EXECUTION_TIME, start);
+        metrics.inc(METRIC_STORAGE_CACHE_HITS, cache_metrics.hits);
+        metrics.inc(METRIC_STORAGE_CACHE_MISSES, cache_metrics.misses);
+
         debug!(self.logger, "Transaction batch execution complete";
             "previous_hash" => ?header.previous_hash,
             "io_root" => ?header.io_root,
             "state_root" => ?header.state_root,
             "messages_hash" => ?header.messages_hash,
             "in_msgs_hash" => ?header.in_msgs_hash,
+            "consensus_reads_digest" => ?consensus_reads.digest,
+            "utilization" => ?utilization,
         );
 
         let rak_sig = self
@@ -732,18 +1756,132 @@ impl Dispatcher {
             )
             .unwrap();
 
+        let mut batch = ComputedBatch {
+            header,
+            io_write_log,
+            state_write_log,
+            rak_sig,
+            messages: results.messages,
+            ..Default::default()
+        };
+        // Only compress the write logs in case the host can decode them, as older hosts expect
+        // the plain encoding.
+        if protocol.get_config().features.compressed_write_logs
+            && protocol
+                .features()
+                .contains(ProtocolFeature::CompressedWriteLogs)
+        {
+            batch.compress_write_logs();
+        }
+
         Ok(Body::RuntimeExecuteTxBatchResponse {
-            batch: ComputedBatch {
-                header,
-                io_write_log,
-                state_write_log,
-                rak_sig,
-                messages: results.messages,
-            },
+            batch,
             tx_hashes: hashes,
             tx_reject_hashes: results.tx_reject_hashes,
             tx_input_root: input_io_root,
             tx_input_write_log: input_write_log,
+            consensus_state_reads: Some(consensus_reads),
+            utilization: Some(utilization),
+        })
+    }
+
+    /// Shadow-execute a batch with the candidate runtime version in the background and report
+    /// any divergence from the results of the active runtime version to the host.
+    #[allow(clippy::too_many_arguments)]
+    fn dispatch_shadow_txn(
+        self: &Arc<Self>,
+        cache_set: cache::CacheSet,
+        candidate: Arc<Candidate>,
+        permit: OwnedSemaphorePermit,
+        protocol: &Arc<Protocol>,
+        inputs: TxnBatch,
+        in_msgs: Vec<roothash::IncomingMessage>,
+        state: TxDispatchState,
+        active: ExecutionSummary,
+    ) {
+        let protocol = protocol.clone();
+        let dispatcher = self.clone();
+
+        // Panics of the candidate only abort shadow execution when panics unwind. Runtimes built
+        // with `panic = "abort"`, like SGX enclaves, are terminated like on any other panic.
+        tokio::task::spawn_blocking(move || {
+            // Release the permit once shadow execution completes.
+            let _permit = permit;
+            let round = state.header.round + 1;
+            let result = dispatcher
+                .txn_shadow_execute_batch(&protocol, cache_set, &candidate, inputs, in_msgs, state);
+            let divergence = match Divergence::check(candidate.version, round, active, result) {
+                Some(divergence) => divergence,
+                None => return,
+            };
+
+            warn!(dispatcher.logger, "Shadow execution diverged";
+                "round" => round,
+                "candidate_version" => ?divergence.candidate_version,
+                "active" => ?divergence.active,
+                "candidate" => ?divergence.candidate,
+                "err" => ?divergence.error,
+            );
+            if let Err(err) = protocol.call_host(Body::HostShadowDivergenceRequest { divergence }) {
+                error!(dispatcher.logger, "Failed to report shadow execution divergence"; "err" => ?err);
+            }
+        });
+    }
+
+    fn txn_shadow_execute_batch(
+        &self,
+        protocol: &Arc<Protocol>,
+        cache_set: cache::CacheSet,
+        candidate: &Candidate,
+        inputs: TxnBatch,
+        in_msgs: Vec<roothash::IncomingMessage>,
+        state: TxDispatchState,
+    ) -> Result<ExecutionSummary, Error> {
+        let consensus_state = block_on(state.consensus_verifier.verify(
+            state.consensus_block.clone(),
+            state.header.clone(),
+            state.epoch,
+        ))?;
+        let header = &state.header;
+        let params = RuntimeParameters::from_state(&consensus_state, &header.namespace)?;
+        let max_messages = params
+            .as_ref()
+            .map_or(state.max_messages, |params| params.executor.max_messages);
+
+        let mut cache = cache_set.shadow(Root {
+            namespace: header.namespace,
+            version: header.round,
+            root_type: RootType::State,
+            hash: header.state_root,
+        });
+        let mut overlay = OverlayTree::new(cache.tree_mut());
+
+        let txn_ctx = TxnContext::new(
+            protocol.clone(),
+            &state.consensus_block,
+            consensus_state,
+            &mut overlay,
+            header,
+            state.epoch,
+            &state.round_results,
+            max_messages,
+            false,
+        );
+        let deferred = txn_ctx.deferred.clone();
+        let mut results = candidate
+            .dispatcher
+            .execute_batch(txn_ctx, &inputs, &in_msgs)?;
+        if let Some(ref params) = params {
+            results.messages = validate_messages(params, results.messages)?;
+        }
+        deferred.run(&mut overlay)?;
+
+        let (_, state_root) = overlay.commit_both(header.namespace, header.round + 1)?;
+
+        Ok(ExecutionSummary {
+            state_root,
+            messages_hash: roothash::Message::messages_hash(&results.messages),
+            in_msgs_count: results.in_msgs_count.try_into().unwrap(),
         })
     }
 
@@ -780,11 +1918,27 @@ impl Dispatcher {
             protocol.get_runtime_id(),
         );
 
+        // Runtimes attested in audit mode are read-only.
+        if !state.check_only && self.identity.is_restricted() {
+            return Err(Error::new(
+                "dispatcher",
+                1,
+                "batch execution not allowed in restricted mode",
+            ));
+        }
+
+        // Fail fast while storage is degraded instead of waiting for each storage sync request
+        // to fail. Once a probe is due, the batch is processed and acts as the probe. The breaker
+        // is only consulted here, so batches are never failed by it once they started.
+        if !protocol.storage_breaker().admit(Instant::now()) {
+            return Err(Error::new("dispatcher", 1, "storage degraded"));
+        }
+
         let protocol = protocol.clone();
         let dispatcher = self.clone();
         let txn_dispatcher = txn_dispatcher.clone();
 
-        tokio::task::spawn_blocking(move || {
+        tokio::task::spawn_blocking(propagate_origin(move || {
             if state.check_only {
                 dispatcher.txn_check_batch(protocol, cache_set, &txn_dispatcher, inputs, state)
             } else {
@@ -798,7 +1952,7 @@ impl Dispatcher {
                     state,
                 )
             }
-        })
+        }))
         .await
         .unwrap() // Propagate panics during transaction dispatch.
     }
@@ -813,12 +1967,28 @@ impl Dispatcher {
         // serious problem and should make sure to clean up the process.
         let _guard = AbortOnPanic;
 
+        state
+            .protocol
+            .get_config()
+            .limits
+            .check(Limit::RpcFrame, request.len())?;
+
+        // Track the call so that draining waits for it to complete.
+        let _call = state.rpc_demux.begin_call()?;
+
         // Process frame.
         let mut buffer = vec![];
-        let (mut session, message) = state
+        let result = state
             .rpc_demux
             .process_frame(peer_id, request, &mut buffer)
-            .await?;
+            .await;
+
+        // Abort streams of sessions that have been removed, including evicted ones.
+        for (peer_id, session_id) in state.rpc_demux.take_closed() {
+            state.rpc_dispatcher.close_streams(&peer_id, &session_id);
+        }
+
+        let (mut session, message) = result?;
 
         if let Some(message) = message {
             // Dispatch request.
@@ -850,8 +2020,47 @@ impl Dispatcher {
                         })
                         .map(|_| Body::RuntimeRPCCallResponse { response: buffer })
                 }
+                RpcMessage::Stream(frame) => {
+                    if !state
+                        .protocol
+                        .features()
+                        .contains(ProtocolFeature::StreamingRpc)
+                    {
+                        return Err(Error::new(
+                            "rhp/dispatcher",
+                            1,
+                            "streaming RPC not negotiated",
+                        ));
+                    }
+
+                    // Stream frame, dispatch.
+                    let frame = state.rpc_dispatcher.handle_streaming(
+                        RpcContext::new(session.info()),
+                        session.get_peer_id(),
+                        *session.get_session_id(),
+                        frame,
+                    );
+                    let response = RpcMessage::Stream(frame);
+
+                    debug!(self.logger, "RPC call dispatch complete";
+                        "kind" => ?RpcKind::Stream,
+                    );
+
+                    let mut buffer = vec![];
+                    session
+                        .write_message(response, &mut buffer)
+                        .map_err(|err| {
+                            error!(self.logger, "Error while writing response"; "err" => %err);
+                            Error::new("rhp/dispatcher", 1, &format!("{err}"))
+                        })
+                        .map(|_| Body::RuntimeRPCStreamResponse { response: buffer })
+                }
                 RpcMessage::Close => {
                     // Session close.
+                    state
+                        .rpc_dispatcher
+                        .close_streams(session.get_peer_id(), session.get_session_id());
+
                     let mut buffer = vec![];
                     state
                         .rpc_demux
@@ -878,6 +2087,12 @@ impl Dispatcher {
         // serious problem and should make sure to clean up the process.
         let _guard = AbortOnPanic;
 
+        state
+            .protocol
+            .get_config()
+            .limits
+            .check(Limit::RpcFrame, request.len())?;
+
         let request: RpcRequest = cbor::from_slice(&request)
             .map_err(|_| Error::new("rhp/dispatcher", 1, "malformed request"))?;
 
@@ -901,6 +2116,12 @@ impl Dispatcher {
         // serious problem and should make sure to clean up the process.
         let _guard = AbortOnPanic;
 
+        state
+            .protocol
+            .get_config()
+            .limits
+            .check(Limit::RpcFrame, request.len())?;
+
         let request = cbor::from_slice(&request)
             .map_err(|_| Error::new("rhp/dispatcher", 1, "malformed request"))?;
 
@@ -927,15 +2148,53 @@ impl Dispatcher {
     ) -> Result<RpcResponse, Error> {
         let rpc_dispatcher = state.rpc_dispatcher.clone();
 
-        let response = tokio::task::spawn_blocking(move || {
+        let response = tokio::task::spawn_blocking(propagate_origin(move || {
             let rpc_ctx = RpcContext::new(session_info);
             rpc_dispatcher.dispatch(rpc_ctx, request, kind)
-        })
+        }))
         .await?;
 
         Ok(response)
     }
 
+    /// Resolve a consensus event notification against consensus state and deliver the resulting
+    /// events to the application.
+    async fn handle_consensus_event(
+        &self,
+        state: &State,
+        notification: RuntimeNotifyConsensusEvent,
+    ) {
+        let runtime_id = state.protocol.get_runtime_id();
+        let events = match consensus_events::resolve(
+            state.consensus_verifier.as_ref(),
+            &runtime_id,
+            notification.height,
+            &notification.kinds,
+        )
+        .await
+        {
+            Ok(events) => events,
+            Err(err) => {
+                warn!(self.logger, "Consensus event notification failed";
+                    "height" => notification.height,
+                    "err" => ?err,
+                );
+                return;
+            }
+        };
+        if events.is_empty() {
+            return;
+        }
+
+        if let Err(err) = state
+            .app
+            .on_consensus_events(notification.height, &events)
+            .await
+        {
+            error!(self.logger, "Application consensus event notification failed"; "err" => ?err);
+        }
+    }
+
     async fn handle_km_status_update(
         &self,
         state: State,
@@ -957,6 +2216,7 @@ impl Dispatcher {
                 .verify_key_manager_status(status, key_manager)?;
 
             // Dispatch the local RPC call.
+            HealthMonitor::global().record_key_manager_status(published_status.is_initialized);
             state
                 .rpc_dispatcher
                 .handle_km_status_update(published_status);
@@ -1005,3 +2265,209 @@ impl Dispatcher {
         Ok(Body::RuntimeKeyManagerQuotePolicyUpdateResponse {})
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{
+        consensus::state::ConsensusState,
+        protocol::HostInfo,
+        storage::{mkvs::Tree, MKVS},
+        testkit::host::LoopbackProtocol,
+        transaction::{dispatcher::ExecuteTxResult, tags::Tag},
+    };
+
+    /// Dispatcher which increments the counters under all keys listed in each transaction.
+    struct CounterDispatcher;
+
+    impl TxnDispatcher for CounterDispatcher {
+        fn execute_batch(
+            &self,
+            mut ctx: TxnContext,
+            batch: &TxnBatch,
+            in_msgs: &[roothash::IncomingMessage],
+        ) -> Result<ExecuteBatchResult, Error> {
+            let results = batch
+                .iter()
+                .map(|tx| self.execute_tx(tx, &mut *ctx.runtime_state))
+                .collect::<Result<_, _>>()?;
+
+            Ok(ExecuteBatchResult {
+                results,
+                messages: vec![],
+                in_msgs_count: in_msgs.len(),
+                block_tags: Tags::new(),
+                tx_reject_hashes: vec![],
+            })
+        }
+
+        fn supports_parallel_execution(&self) -> bool {
+            true
+        }
+
+        fn execute_tx(&self, tx: &[u8], state: &mut dyn MKVS) -> Result<ExecuteTxResult, Error> {
+            let mut output = vec![];
+            for key in tx.chunks(1) {
+                let counter = state.get(key).map_or(0, |value| value[0]) + 1;
+                state.insert(key, &[counter]);
+                output.push(counter);
+            }
+            Ok(ExecuteTxResult {
+                output,
+                tags: Tags::new(),
+            })
+        }
+
+        fn check_batch(
+            &self,
+            _ctx: TxnContext,
+            _batch: &TxnBatch,
+        ) -> Result<Vec<CheckTxResult>, Error> {
+            Ok(vec![])
+        }
+    }
+
+    #[test]
+    fn test_parallel_execution() {
+        let tokio_runtime = tokio::runtime::Runtime::new().unwrap();
+        let _guard = tokio_runtime.enter();
+        let host_info = HostInfo {
+            runtime_id: Default::default(),
+            consensus_backend: "tendermint".to_string(),
+            consensus_protocol_version: Default::default(),
+            consensus_chain_context: "test".to_string(),
+            local_config: Default::default(),
+            features: Default::default(),
+        };
+        let loopback = LoopbackProtocol::new(tokio_runtime.handle().clone(), host_info, |_| None);
+        let batch = TxnBatch::new(vec![
+            b"a".to_vec(),
+            b"b".to_vec(),
+            b"ab".to_vec(),
+            b"c".to_vec(),
+            b"ca".to_vec(),
+        ]);
+
+        let execute = |workers| {
+            let mut tree = Tree::builder()
+                .with_root_type(RootType::State)
+                .build(Box::new(NoopReadSyncer));
+            tree.insert(b"c", &[5]).unwrap();
+            let mut overlay = OverlayTree::new(&mut tree);
+            let consensus_tree = Tree::builder()
+                .with_root_type(RootType::State)
+                .build(Box::new(NoopReadSyncer));
+            let consensus_block = LightBlock::default();
+            let header = Header::default();
+            let round_results = Default::default();
+            let ctx = TxnContext::new(
+                loopback.protocol().clone(),
+                &consensus_block,
+                ConsensusState::new(0, consensus_tree),
+                &mut overlay,
+                &header,
+                0,
+                &round_results,
+                0,
+                false,
+            );
+            let results = execute_batch(&CounterDispatcher, ctx, workers, &batch, &[]).unwrap();
+            let outputs: Vec<_> = results.results.into_iter().map(|r| r.output).collect();
+            let (_, root) = overlay
+                .commit_both(Default::default(), 1)
+                .expect("state commit must succeed");
+            (outputs, root)
+        };
+
+        // Parallel execution yields the same results and state root as serial execution.
+        let (outputs, root) = execute(1);
+        assert_eq!(
+            outputs,
+            vec![vec![1], vec![1], vec![2, 2], vec![6], vec![7, 3]]
+        );
+        for workers in [2, 4, 16] {
+            assert_eq!(execute(workers), (outputs.clone(), root));
+        }
+    }
+
+    #[test]
+    fn test_batch_gate() {
+        let rt = tokio::runtime::Builder::new_current_thread()
+            .enable_time()
+            .build()
+            .unwrap();
+        let timeout = Duration::from_secs(10);
+
+        let gate = BatchGate::new();
+        let batch = gate.begin().unwrap();
+        gate.close();
+        let (drained, _) = rt.block_on(async {
+            tokio::join!(gate.wait(timeout), async {
+                tokio::task::yield_now().await;
+                // New batches are rejected while in-flight batches complete.
+                assert!(matches!(gate.begin(), Err(ProtocolError::ShuttingDown)));
+                drop(batch);
+            })
+        });
+        assert!(drained);
+
+        // Batches still in flight once the timeout passes are abandoned.
+        let gate = BatchGate::new();
+        let _batch = gate.begin().unwrap();
+        gate.close();
+        assert!(!rt.block_on(gate.wait(Duration::from_millis(10))));
+    }
+
+    #[test]
+    fn test_drop_oversized_events() {
+        let limits = config::Limits {
+            max_event_payload_size: 4,
+            ..Default::default()
+        };
+        let mut results: Vec<Tags> = vec![
+            vec![Tag::new(b"a".to_vec(), b"ok".to_vec())],
+            vec![
+                Tag::new(b"b".to_vec(), b"fine".to_vec()),
+                Tag::new(b"c".to_vec(), b"too large".to_vec()),
+            ],
+            vec![Tag::new(b"d".to_vec(), b"ok".to_vec())],
+        ];
+
+        let errors: Vec<_> = results
+            .iter_mut()
+            .map(|tags| drop_oversized_events(&limits, tags))
+            .collect();
+        // Only the oversized event of the second transaction is dropped.
+        assert!(errors[0].is_empty() && errors[2].is_empty());
+        assert_eq!(
+            errors[1],
+            vec![LimitError {
+                limit: Limit::EventPayload,
+                size: 9,
+                max: 4,
+            }]
+        );
+        assert_eq!(results[0].len(), 1);
+        assert_eq!(results[1].len(), 1);
+        assert_eq!(results[1][0].key, b"b".to_vec());
+        assert_eq!(results[2].len(), 1);
+    }
+
+    #[test]
+    fn test_notice_grace_period() {
+        let config = config::Shutdown::default();
+        let notice = |grace_period| HostShutdownNotice {
+            grace_period,
+            reason: "upgrade".to_string(),
+        };
+
+        assert_eq!(
+            notice_grace_period(&notice(10), &config),
+            Duration::from_secs(10)
+        );
+        assert_eq!(
+            notice_grace_period(&notice(u64::MAX), &config),
+            config.timeout
+        );
+    }
+}
@@ -0,0 +1,252 @@
+//! Admission control of incoming RPC calls.
+//!
+//! Without limits, a single misbehaving peer calling in a tight loop can starve all other callers
+//! of the endpoints served by the runtime (e.g. key manager or query methods). Each peer is
+//! therefore identified by the node hosting its enclave, or by its runtime attestation key in
+//! case the node is not known, so that distinct nodes running the same enclave build are limited
+//! independently. Each peer is limited by a token bucket sized according to the quota of its
+//! enclave identity, where each call is charged the cost of its method, as well as by the number
+//! of its calls being handled concurrently. Callers without an attested identity are
+//! indistinguishable from each other, so they share a single quota.
+//!
+//! Rejected calls fail with an [`AdmissionError`], whose module and code are returned along with
+//! the failed call so that clients can back off.
+use std::time::{Duration, Instant};
+
+use thiserror::Error;
+
+use super::{context::Context, types::ErrorCode};
+use crate::{
+    common::{
+        crypto::signature::PublicKey,
+        rate_limit::{self, Limit, RateLimiter, Rejection},
+        sgx::EnclaveIdentity,
+    },
+    config::{RpcAdmission, RpcQuota},
+};
+
+/// Module of admission control errors.
+pub const MODULE_NAME: &str = "rpc/admission";
+
+/// Error returned when a call is rejected by admission control.
+#[derive(Error, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AdmissionError {
+    #[error("rate limited, retry after {} ms", .retry_after.as_millis())]
+    RateLimited { retry_after: Duration },
+
+    #[error("too many concurrent calls")]
+    TooManyConcurrentCalls,
+}
+
+impl AdmissionError {
+    /// Module and code of the error, returned along with the failed call.
+    pub fn code(&self) -> ErrorCode {
+        let (code, retry_after_ms) = match self {
+            Self::RateLimited { retry_after } => (1, retry_after.as_millis() as u64),
+            Self::TooManyConcurrentCalls => (2, 0),
+        };
+        ErrorCode {
+            module: MODULE_NAME.to_string(),
+            code,
+            retry_after_ms,
+        }
+    }
+
+    /// Recover the admission error from the module and code of a failed call, if the call was
+    /// rejected by admission control.
+    pub fn from_code(code: &ErrorCode) -> Option<Self> {
+        if code.module != MODULE_NAME {
+            return None;
+        }
+        match code.code {
+            1 => Some(Self::RateLimited {
+                retry_after: Duration::from_millis(code.retry_after_ms),
+            }),
+            2 => Some(Self::TooManyConcurrentCalls),
+            _ => None,
+        }
+    }
+}
+
+impl From<Rejection> for AdmissionError {
+    fn from(rejection: Rejection) -> Self {
+        match rejection {
+            Rejection::RateLimited { retry_after } => Self::RateLimited { retry_after },
+            Rejection::TooManyConcurrent => Self::TooManyConcurrentCalls,
+        }
+    }
+}
+
+/// Calling peer, as far as it can be told apart from others.
+#[derive(Clone, Debug, Hash, PartialEq, Eq)]
+enum Peer {
+    /// Peer hosted by a known node.
+    Node(PublicKey),
+    /// Peer hosted by an unknown node, identified by its runtime attestation key.
+    Rak(PublicKey),
+    Anonymous,
+}
+
+impl Peer {
+    fn of(ctx: &Context) -> Self {
+        match ctx.caller() {
+            Some(caller) => match caller.node_id {
+                Some(node_id) => Peer::Node(node_id),
+                None => Peer::Rak(caller.rak),
+            },
+            None => Peer::Anonymous,
+        }
+    }
+}
+
+/// Admitted call, counted towards the concurrency limit of its peer until dropped.
+pub struct Permit {
+    _permit: rate_limit::Permit<Peer>,
+}
+
+/// Admission control of incoming RPC calls.
+pub struct AdmissionControl {
+    config: RpcAdmission,
+    limiter: RateLimiter<Peer>,
+}
+
+impl AdmissionControl {
+    /// Create a new admission control with the given limits.
+    pub fn new(config: RpcAdmission) -> Self {
+        Self {
+            limiter: RateLimiter::new(config.max_peers),
+            config,
+        }
+    }
+
+    /// Quota of the peer with the given enclave identity, if attested.
+    fn quota(&self, identity: Option<&EnclaveIdentity>) -> RpcQuota {
+        match identity {
+            Some(identity) => self
+                .config
+                .quotas
+                .get(identity)
+                .copied()
+                .unwrap_or(self.config.default_quota),
+            None => self.config.anonymous_quota,
+        }
+    }
+
+    /// Admit a call to the given method by the caller of the given context.
+    ///
+    /// Calls costing more than the burst of their peer are charged the whole burst.
+    pub fn admit(&self, ctx: &Context, method: &str) -> Result<Permit, AdmissionError> {
+        let identity = ctx.caller().map(|caller| &caller.enclave_identity);
+        self.admit_at(Peer::of(ctx), identity, method, Instant::now())
+    }
+
+    fn admit_at(
+        &self,
+        peer: Peer,
+        identity: Option<&EnclaveIdentity>,
+        method: &str,
+        now: Instant,
+    ) -> Result<Permit, AdmissionError> {
+        let quota = self.quota(identity);
+        let limit = Limit {
+            cost_per_second: quota.cost_per_second,
+            burst: quota.burst,
+            max_concurrent: quota.max_concurrent,
+        };
+        let cost = self.config.method_costs.get(method).copied().unwrap_or(1);
+
+        let permit = self.limiter.admit_at(peer, limit, cost, now)?;
+
+        Ok(Permit { _permit: permit })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::{BTreeMap, HashMap};
+
+    use super::*;
+    use crate::common::sgx::MrSigner;
+
+    #[test]
+    fn test_admission_control() {
+        let trusted = EnclaveIdentity {
+            mr_signer: MrSigner([1; 32]),
+            ..Default::default()
+        };
+        let untrusted = EnclaveIdentity::default();
+        let admission = AdmissionControl::new(RpcAdmission {
+            default_quota: RpcQuota {
+                cost_per_second: 2,
+                burst: 4,
+                max_concurrent: 2,
+            },
+            anonymous_quota: RpcQuota {
+                cost_per_second: 1,
+                burst: 1,
+                max_concurrent: 0,
+            },
+            quotas: HashMap::from([(trusted.clone(), RpcQuota::default())]),
+            method_costs: BTreeMap::from([("expensive".to_string(), 3)]),
+            max_peers: 10,
+        });
+        let abusive = Peer::Node(PublicKey([1; 32]));
+        let now = Instant::now();
+
+        // Calls are charged the cost of their method.
+        let _call = admission
+            .admit_at(abusive.clone(), Some(&untrusted), "expensive", now)
+            .unwrap();
+        assert_eq!(
+            admission
+                .admit_at(abusive.clone(), Some(&untrusted), "expensive", now)
+                .err(),
+            Some(AdmissionError::RateLimited {
+                retry_after: Duration::from_millis(1000),
+            })
+        );
+
+        // Other nodes running the same enclave are limited independently, as are peers of
+        // unknown nodes.
+        for peer in [
+            Peer::Node(PublicKey([2; 32])),
+            Peer::Rak(PublicKey([1; 32])),
+        ] {
+            admission
+                .admit_at(peer, Some(&untrusted), "expensive", now)
+                .unwrap();
+        }
+
+        // Quotas of specific enclave identities override the default and anonymous callers
+        // share a quota.
+        let calls: Vec<_> = (0..100)
+            .map(|_| admission.admit_at(abusive.clone(), Some(&trusted), "expensive", now))
+            .collect::<Result<_, _>>()
+            .unwrap();
+        drop(calls);
+        assert!(admission
+            .admit_at(Peer::Anonymous, None, "expensive", now)
+            .is_ok());
+        assert!(admission
+            .admit_at(Peer::Anonymous, None, "cheap", now)
+            .is_err());
+    }
+
+    #[test]
+    fn test_admission_error_code() {
+        for err in [
+            AdmissionError::RateLimited {
+                retry_after: Duration::from_millis(1500),
+            },
+            AdmissionError::TooManyConcurrentCalls,
+        ] {
+            assert_eq!(AdmissionError::from_code(&err.code()), Some(err));
+        }
+        let other = ErrorCode {
+            module: "other".to_string(),
+            code: 1,
+            retry_after_ms: 0,
+        };
+        assert_eq!(AdmissionError::from_code(&other), None);
+    }
+}
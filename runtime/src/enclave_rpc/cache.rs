@@ -0,0 +1,82 @@
+//! Response cache for idempotent RPC methods.
+use std::num::NonZeroUsize;
+
+use crate::{common::crypto::hash::Hash, consensus::beacon::EpochTime};
+
+use super::types::{Request, Response};
+
+/// Cache of responses to calls of methods marked as cacheable.
+///
+/// Responses are keyed by the method, the hash of the arguments and the state version and
+/// consensus epoch they were computed at. Advancing the state version or the epoch, or
+/// invalidating the cache drops all cached responses.
+pub struct ResponseCache {
+    version: u64,
+    epoch: EpochTime,
+    /// Number of explicit invalidations, so responses computed before one are never returned.
+    invalidations: u64,
+    responses: lru::LruCache<Hash, Response>,
+}
+
+impl ResponseCache {
+    /// Create a new cache holding up to `capacity` responses.
+    pub fn new(capacity: NonZeroUsize) -> Self {
+        Self {
+            version: 0,
+            epoch: 0,
+            invalidations: 0,
+            responses: lru::LruCache::new(capacity),
+        }
+    }
+
+    /// Cache key of the given request at the current state version.
+    pub fn key(&self, request: &Request) -> Hash {
+        let method = Hash::digest_bytes(request.method.as_bytes());
+        let args = Hash::digest_bytes(&cbor::to_vec(request.args.clone()));
+
+        Hash::digest_bytes_list(&[
+            &self.version.to_be_bytes(),
+            &self.epoch.to_be_bytes(),
+            &self.invalidations.to_be_bytes(),
+            method.as_ref(),
+            args.as_ref(),
+        ])
+    }
+
+    /// Look up the response cached under the given key.
+    pub fn get(&mut self, key: &Hash) -> Option<Response> {
+        self.responses.get(key).cloned()
+    }
+
+    /// Cache the response under the given key.
+    ///
+    /// Responses keyed before the state version advanced or the cache was invalidated are never
+    /// returned, so it is safe to insert them afterwards.
+    pub fn put(&mut self, key: Hash, response: Response) {
+        self.responses.put(key, response);
+    }
+
+    /// Advance the state version, dropping all cached responses in case it changed.
+    pub fn advance(&mut self, version: u64) {
+        if version == self.version {
+            return;
+        }
+        self.version = version;
+        self.responses.clear();
+    }
+
+    /// Advance the consensus epoch, dropping all cached responses in case it changed.
+    pub fn advance_epoch(&mut self, epoch: EpochTime) {
+        if epoch == self.epoch {
+            return;
+        }
+        self.epoch = epoch;
+        self.responses.clear();
+    }
+
+    /// Drop all cached responses, e.g. after state not covered by the state version changed.
+    pub fn invalidate(&mut self) {
+        self.invalidations += 1;
+        self.responses.clear();
+    }
+}
@@ -1,20 +1,22 @@
 //! Enclave RPC client.
 use std::{
-    collections::HashSet,
+    collections::{HashMap, HashSet},
     sync::{
-        atomic::{AtomicU32, Ordering},
-        Arc,
+        atomic::{AtomicU32, AtomicU64, Ordering},
+        Arc, Mutex,
     },
 };
 
 use futures::stream::{FuturesUnordered, StreamExt};
 use lazy_static::lazy_static;
 #[cfg(not(test))]
-use rand::{rngs::OsRng, RngCore};
+use rand::RngCore;
 
 use thiserror::Error;
 use tokio::sync::OwnedMutexGuard;
 
+#[cfg(not(test))]
+use crate::common::crypto::rng::SecureRng;
 use crate::{
     common::{
         crypto::signature,
@@ -27,7 +29,10 @@ use crate::{
 };
 
 use super::{
+    admission::AdmissionError,
+    resumption::SessionTicket,
     sessions::{self, MultiplexedSession, Sessions, SharedSession},
+    stream::{Driver, RpcSink, RpcStream},
     transport::{RuntimeTransport, Transport},
 };
 
@@ -44,10 +49,14 @@ lazy_static! {
 pub enum RpcClientError {
     #[error("call failed: {0}")]
     CallFailed(String),
+    #[error("call rejected: {0}")]
+    Rejected(AdmissionError),
     #[error("expected response message, received: {0:?}")]
     ExpectedResponseMessage(types::Message),
     #[error("expected close message, received: {0:?}")]
     ExpectedCloseMessage(types::Message),
+    #[error("expected stream message, received: {0:?}")]
+    ExpectedStreamMessage(types::Message),
     #[error("transport error")]
     Transport,
     #[error("unsupported RPC kind")]
@@ -71,10 +80,13 @@ pub struct Response<'a, T> {
 
 impl<T> Response<'_, T> {
     /// Report success if result was `Ok(_)` and failure if result was `Err(_)`, then return the
-    /// inner result consuming the response instance.
+    /// inner result consuming the response instance. Calls rejected by the peer's admission
+    /// control are not reported.
     pub async fn into_result_with_feedback(mut self) -> Result<T, RpcClientError> {
         match self.inner {
             Ok(_) => self.success().await,
+            // Rejections by admission control are caused by the caller, not the peer.
+            Err(RpcClientError::Rejected(_)) => {}
             Err(_) => self.failure().await,
         }
 
@@ -121,17 +133,21 @@ impl<T> Response<'_, T> {
 /// RPC client.
 pub struct RpcClient {
     /// Used transport.
-    transport: Box<dyn Transport>,
+    transport: Arc<dyn Transport>,
     /// Multiplexed sessions.
     sessions: tokio::sync::Mutex<Sessions<signature::PublicKey>>,
     /// The ID of the client.
     client_id: u32,
     /// The ID of the next transport request.
-    next_request_id: AtomicU32,
+    next_request_id: Arc<AtomicU32>,
+    /// The ID of the next stream.
+    next_stream_id: AtomicU64,
+    /// Tickets for resuming sessions, by remote node.
+    tickets: Mutex<HashMap<signature::PublicKey, SessionTicket>>,
 }
 
 impl RpcClient {
-    fn new(
+    pub(super) fn new(
         transport: Box<dyn Transport>,
         builder: Builder,
         max_sessions: usize,
@@ -140,7 +156,7 @@ impl RpcClient {
     ) -> Self {
         // Assign a unique ID to each client to avoid overlapping request IDs.
         let client_id = NEXT_CLIENT_ID.fetch_add(1, Ordering::SeqCst); // Wraps if overflows.
-        let next_request_id = AtomicU32::new(1);
+        let next_request_id = Arc::new(AtomicU32::new(1));
 
         let sessions = tokio::sync::Mutex::new(Sessions::new(
             builder,
@@ -150,10 +166,12 @@ impl RpcClient {
         ));
 
         Self {
-            transport,
+            transport: transport.into(),
             sessions,
             client_id,
             next_request_id,
+            next_stream_id: AtomicU64::new(1),
+            tickets: Mutex::new(HashMap::new()),
         }
     }
 
@@ -234,6 +252,53 @@ impl RpcClient {
             .await
     }
 
+    /// Open a bidirectional stream to a remote streaming method using an encrypted and
+    /// authenticated Noise session.
+    ///
+    /// Returns the sink for sending items to the method and the stream of items produced by it.
+    /// The stream ends once the remote method completes. Dropping the stream aborts the call.
+    pub async fn call_streaming<C, I, O>(
+        &self,
+        method: &'static str,
+        args: C,
+        nodes: Vec<signature::PublicKey>,
+    ) -> Result<(RpcSink<I>, RpcStream<O>), RpcClientError>
+    where
+        C: cbor::Encode,
+        I: cbor::Encode,
+        O: cbor::Decode,
+    {
+        let request = types::Request {
+            method: method.to_owned(),
+            args: cbor::to_value(args),
+        };
+        let session = self.connect(nodes).await?;
+
+        let driver = Driver {
+            transport: self.transport.clone(),
+            session,
+            client_id: self.client_id,
+            next_request_id: self.next_request_id.clone(),
+            stream_id: self.next_stream_id.fetch_add(1, Ordering::SeqCst),
+        };
+
+        Ok(driver.start(request))
+    }
+
+    /// Establish a session with one of the given nodes, unless one has already been established.
+    pub(super) async fn ensure_connected(
+        &self,
+        nodes: Vec<signature::PublicKey>,
+    ) -> Result<(), RpcClientError> {
+        self.connect(nodes).await.map(|_| ())
+    }
+
+    /// Gracefully close all sessions.
+    pub(super) async fn close_sessions(&self) {
+        let sessions = self.sessions.lock().await.drain();
+        self.close_all(sessions).await;
+    }
+
     async fn call<C, O>(
         &self,
         method: &'static str,
@@ -269,7 +334,10 @@ impl RpcClient {
                     cbor::from_value(value).map_err(Into::into),
                 ),
                 types::Body::Error(error) => {
-                    (Some(request_id), Err(RpcClientError::CallFailed(error)))
+                    match response.error.as_ref().and_then(AdmissionError::from_code) {
+                        Some(err) => (Some(request_id), Err(RpcClientError::Rejected(err))),
+                        None => (Some(request_id), Err(RpcClientError::CallFailed(error))),
+                    }
                 }
             },
             Err(err) => (None, Err(err)),
@@ -321,7 +389,7 @@ impl RpcClient {
         nodes: Vec<signature::PublicKey>,
     ) -> Result<SharedSession<signature::PublicKey>, RpcClientError> {
         // Create a new session.
-        let mut session = {
+        let (mut session, resuming) = {
             let mut sessions = self.sessions.lock().await;
 
             // No need to create a new session if we are connected to one of the nodes.
@@ -331,9 +399,20 @@ impl RpcClient {
 
             // Since the peer ID is not yet known, use the default value and set it later.
             let peer_id = Default::default();
-            sessions.create_initiator(peer_id)
+            let resuming = self
+                .take_ticket(&nodes)
+                .and_then(|ticket| sessions.create_resuming_initiator(peer_id, ticket).ok());
+            (sessions.create_initiator(peer_id)?, resuming)
         };
 
+        // Attempt to resume a previous session, skipping the full handshake and remote
+        // attestation. Fall back to a full handshake in case the ticket is no longer accepted.
+        if let Some(mut resuming) = resuming {
+            if let Ok(node) = self.resume(&mut resuming).await {
+                return self.add_session(resuming, node).await;
+            }
+        }
+
         // Copy session ID to avoid moved value errors.
         let session_id = *session.get_session_id();
 
@@ -434,12 +513,82 @@ impl RpcClient {
             return Err(err);
         }
 
+        self.add_session(session, rsp.node).await
+    }
+
+    /// Resume a previous session using a session ticket, returning the remote node.
+    async fn resume(
+        &self,
+        session: &mut MultiplexedSession<signature::PublicKey>,
+    ) -> Result<signature::PublicKey, RpcClientError> {
+        let session_id = *session.get_session_id();
+        let node = session.get_remote_node()?;
+
+        // Session Resume1: prepare resumption request.
+        let mut buffer = vec![];
+        session
+            .process_data(&[], &mut buffer)
+            .await
+            .map_err(|_| RpcClientError::Transport)?;
+
+        // Transport: send resumption request and receive a response.
+        let request_id = self.next_request_id();
+        let rsp = self
+            .transport
+            .write_noise_session(request_id, session_id, buffer, String::new(), vec![node])
+            .await
+            .map_err(|_| RpcClientError::Transport)?;
+        if rsp.node != node {
+            return Err(RpcClientError::Transport);
+        }
+        session.set_peer_id(rsp.node);
+
+        // Session Resume2: process resumption response.
+        session
+            .process_data(&rsp.data, vec![])
+            .await
+            .map_err(|_| RpcClientError::Transport)?;
+
+        // Skipping peer feedback, as failed resumptions are expected once tickets expire
+        // and the session is re-established using a full handshake instead.
+
+        Ok(node)
+    }
+
+    /// Take a valid ticket for resuming a session with one of the given nodes, or with any node
+    /// in case no nodes are given.
+    fn take_ticket(&self, nodes: &[signature::PublicKey]) -> Option<SessionTicket> {
+        let now = insecure_posix_time();
+        let mut tickets = self.tickets.lock().unwrap();
+        tickets.retain(|_, ticket| ticket.is_valid(now));
+
+        let node = match nodes {
+            [] => tickets.keys().next().copied(),
+            nodes => nodes
+                .iter()
+                .find(|node| tickets.contains_key(node))
+                .copied(),
+        }?;
+        tickets.remove(&node)
+    }
+
+    /// Add an established session to the set of active sessions.
+    async fn add_session(
+        &self,
+        mut session: MultiplexedSession<signature::PublicKey>,
+        node: signature::PublicKey,
+    ) -> Result<SharedSession<signature::PublicKey>, RpcClientError> {
+        // Retain the ticket so that the session can be resumed later.
+        if let Some(ticket) = session.take_ticket() {
+            self.tickets.lock().unwrap().insert(node, ticket);
+        }
+
         // The connection has been successfully established. The session can
         // be added to the set of active sessions if there is space available,
         // or if we can make space by removing a stale session.
         let now = insecure_posix_time();
         let mut sessions = self.sessions.lock().await;
-        let maybe_removed_session = match sessions.remove_for(&rsp.node, now) {
+        let maybe_removed_session = match sessions.remove_for(&node, now) {
             Ok(maybe_removed_session) => maybe_removed_session,
             Err(err) => {
                 // Unable to make space. Gracefully close the session.
@@ -618,7 +767,7 @@ impl RpcClient {
         return 0;
 
         #[cfg(not(test))]
-        OsRng.next_u32()
+        SecureRng.next_u32()
     }
 }
 
@@ -637,7 +786,10 @@ mod test {
         enclave_rpc::{demux::Demux, session, transport::EnclaveResponse, types},
     };
 
-    use super::{super::transport::Transport, RpcClient};
+    use super::{
+        super::{pool::RpcClientPool, transport::Transport},
+        RpcClient,
+    };
 
     #[derive(Clone)]
     struct MockTransport {
@@ -706,6 +858,7 @@ mod test {
                                     // Just echo back what was given.
                                     let response = types::Message::Response(types::Response {
                                         body: types::Body::Success(rq.args),
+                                        error: None,
                                     });
 
                                     session.write_message(response, &mut buffer)?;
@@ -736,14 +889,14 @@ mod test {
                     // Just echo back what was given.
                     let rq: types::Request = cbor::from_slice(&request).unwrap();
                     let body = types::Body::Success(rq.args);
-                    let response = types::Response { body };
+                    let response = types::Response { body, error: None };
                     let rsp = EnclaveResponse {
                         data: cbor::to_vec(response),
                         node: Default::default(),
                     };
                     return Ok(rsp);
                 }
-                types::Kind::LocalQuery => {
+                types::Kind::LocalQuery | types::Kind::Stream => {
                     panic!("unhandled RPC kind")
                 }
             }
@@ -878,4 +1031,56 @@ mod test {
             ]
         );
     }
+
+    #[test]
+    fn test_rpc_client_pool() {
+        let rt = tokio::runtime::Runtime::new().unwrap();
+        let _guard = rt.enter(); // Ensure Tokio runtime is available.
+        let transport = MockTransport::new();
+        let clients = (0..2)
+            .map(|_| {
+                let builder = session::Builder::default();
+                RpcClient::new(Box::new(transport.clone()), builder, 2, 2, 60)
+            })
+            .collect();
+        let pool = RpcClientPool::new(clients, 30);
+
+        // Health checks establish sessions for all clients.
+        assert_eq!(rt.block_on(pool.health_check(vec![])), 2);
+
+        // Concurrent calls are spread across the clients.
+        let results: Vec<u64> = rt.block_on(async {
+            let pool = &pool;
+            let calls = (0..4u64).map(|i| async move {
+                pool.secure_call("test", i, vec![])
+                    .await
+                    .into_result()
+                    .unwrap()
+            });
+            futures::future::join_all(calls).await
+        });
+        assert_eq!(results, vec![0, 1, 2, 3]);
+        assert_eq!(pool.in_flight(), 0);
+
+        // Failed sessions are re-established transparently.
+        transport.reset();
+        let result: u64 = rt
+            .block_on(async { pool.secure_call("test", 42, vec![]).await.into_result() })
+            .unwrap();
+        assert_eq!(result, 42);
+        assert_eq!(rt.block_on(pool.health_check(vec![])), 2);
+
+        // Idle sessions are re-established.
+        let pool = RpcClientPool::new(
+            (0..2)
+                .map(|_| {
+                    let builder = session::Builder::default();
+                    RpcClient::new(Box::new(transport.clone()), builder, 2, 2, 60)
+                })
+                .collect(),
+            0,
+        );
+        assert_eq!(rt.block_on(pool.health_check(vec![])), 2);
+        assert_eq!(rt.block_on(pool.health_check(vec![])), 2);
+    }
 }
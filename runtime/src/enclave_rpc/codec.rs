@@ -0,0 +1,146 @@
+//! Versioned frame codecs.
+//!
+//! Frames can be encoded using different codec versions, so that the frame format can evolve
+//! without all peers having to upgrade at the same time. Version 0 frames are the plain CBOR
+//! encoding of a [`Frame`], as sent by all existing peers. Frames of later versions start with a
+//! marker byte which can't start a CBOR data item, followed by the version, so that servers can
+//! accept frames of several versions side by side during a migration window. Once all clients
+//! send frames of a newer version, servers can stop accepting the older ones.
+use thiserror::Error;
+
+use super::types::{Frame, SessionID};
+
+/// Marker byte starting frames of versions other than `V0`.
+///
+/// This is the CBOR break stop code, which is not valid at the start of a data item.
+const VERSION_MARKER: u8 = 0xff;
+
+/// Size of the header of `V1` frames.
+const V1_HEADER_SIZE: usize = 2 + 32 + 2;
+
+/// Frame codec errors.
+#[derive(Error, Debug)]
+pub enum CodecError {
+    #[error("unsupported frame version: {0}")]
+    UnsupportedVersion(u8),
+    #[error("truncated frame")]
+    Truncated,
+    #[error("{0}")]
+    Decode(#[from] cbor::DecodeError),
+}
+
+/// Frame codec version.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[repr(u8)]
+pub enum FrameVersion {
+    /// CBOR-encoded frames.
+    #[default]
+    V0 = 0,
+    /// Binary frames starting with a fixed layout header, followed by the untrusted plaintext and
+    /// the payload, so that the payload is not re-encoded and can be used in place.
+    V1 = 1,
+}
+
+impl FrameVersion {
+    /// All supported versions.
+    pub const ALL: &'static [FrameVersion] = &[Self::V0, Self::V1];
+
+    /// Version of the given encoded frame.
+    pub fn of(data: &[u8]) -> Result<Self, CodecError> {
+        match data {
+            [VERSION_MARKER, 1, ..] => Ok(Self::V1),
+            [VERSION_MARKER, version, ..] => Err(CodecError::UnsupportedVersion(*version)),
+            [VERSION_MARKER] => Err(CodecError::Truncated),
+            _ => Ok(Self::V0),
+        }
+    }
+}
+
+/// Encode the given frame using the given codec version.
+pub fn encode(frame: Frame, version: FrameVersion) -> Vec<u8> {
+    match version {
+        FrameVersion::V0 => cbor::to_vec(frame),
+        FrameVersion::V1 => {
+            let plaintext = frame.untrusted_plaintext.as_bytes();
+            let plaintext = &plaintext[..plaintext.len().min(u16::MAX as usize)];
+
+            let mut data =
+                Vec::with_capacity(V1_HEADER_SIZE + plaintext.len() + frame.payload.len());
+            data.extend_from_slice(&[VERSION_MARKER, FrameVersion::V1 as u8]);
+            data.extend_from_slice(frame.session.as_ref());
+            data.extend_from_slice(&(plaintext.len() as u16).to_be_bytes());
+            data.extend_from_slice(plaintext);
+            data.extend_from_slice(&frame.payload);
+            data
+        }
+    }
+}
+
+/// Decode the given frame, in case it uses one of the accepted codec versions.
+pub fn decode(data: &[u8], accepted: &[FrameVersion]) -> Result<Frame, CodecError> {
+    let version = FrameVersion::of(data)?;
+    if !accepted.contains(&version) {
+        return Err(CodecError::UnsupportedVersion(version as u8));
+    }
+
+    match version {
+        FrameVersion::V0 => Ok(cbor::from_slice(data)?),
+        FrameVersion::V1 => {
+            if data.len() < V1_HEADER_SIZE {
+                return Err(CodecError::Truncated);
+            }
+            let session = SessionID::from(&data[2..34]);
+            let plaintext_len = u16::from_be_bytes([data[34], data[35]]) as usize;
+            let rest = &data[V1_HEADER_SIZE..];
+            if rest.len() < plaintext_len {
+                return Err(CodecError::Truncated);
+            }
+            let (plaintext, payload) = rest.split_at(plaintext_len);
+
+            Ok(Frame {
+                session,
+                untrusted_plaintext: String::from_utf8_lossy(plaintext).into_owned(),
+                payload: payload.to_vec(),
+            })
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_frame_codecs() {
+        let frame = Frame {
+            session: SessionID::random(),
+            untrusted_plaintext: "method".to_string(),
+            payload: b"payload".to_vec(),
+        };
+
+        for version in FrameVersion::ALL {
+            let data = encode(frame.clone(), *version);
+            assert_eq!(FrameVersion::of(&data).unwrap(), *version);
+
+            let decoded = decode(&data, FrameVersion::ALL).unwrap();
+            assert_eq!(decoded.session, frame.session);
+            assert_eq!(decoded.untrusted_plaintext, frame.untrusted_plaintext);
+            assert_eq!(decoded.payload, frame.payload);
+        }
+
+        // Versions which are not accepted are rejected.
+        let data = encode(frame.clone(), FrameVersion::V1);
+        assert!(matches!(
+            decode(&data, &[FrameVersion::V0]),
+            Err(CodecError::UnsupportedVersion(1))
+        ));
+        assert!(matches!(
+            decode(&[VERSION_MARKER, 9], FrameVersion::ALL),
+            Err(CodecError::UnsupportedVersion(9))
+        ));
+        assert!(matches!(
+            decode(&data[..20], FrameVersion::ALL),
+            Err(CodecError::Truncated)
+        ));
+    }
+}
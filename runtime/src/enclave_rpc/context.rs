@@ -2,16 +2,86 @@
 use std::sync::Arc;
 
 use super::session::SessionInfo;
+use crate::common::{
+    crypto::signature::PublicKey,
+    namespace::Namespace,
+    sgx::{EnclaveIdentity, MrSigner},
+};
+
+/// Verified identity of the caller of a RPC method.
+///
+/// The identity is derived from the RAK binding of the session the call was delivered over, so
+/// it has been verified during the session handshake.
+#[derive(Clone, Debug)]
+pub struct CallerContext {
+    /// Identifier of the runtime the caller belongs to, if it has been verified against the
+    /// consensus layer registry.
+    pub runtime_id: Option<Namespace>,
+    /// Identity of the caller's enclave.
+    pub enclave_identity: EnclaveIdentity,
+    /// Identifier of the application the caller's enclave belongs to.
+    ///
+    /// All enclave builds of an application are signed by the same key, so the application is
+    /// identified by the signer measurement.
+    pub app_id: MrSigner,
+    /// Runtime attestation key of the caller's enclave.
+    pub rak: PublicKey,
+    /// Identifier of the node hosting the caller's enclave, if known. This is either the node
+    /// that endorsed the caller's TEE capability or the node verified against the consensus
+    /// layer registry.
+    pub node_id: Option<PublicKey>,
+}
+
+impl CallerContext {
+    /// Derive the caller context from verified session information.
+    pub fn from_session_info(si: &SessionInfo) -> Self {
+        let enclave_identity = si.verified_attestation.quote.identity.clone();
+
+        Self {
+            runtime_id: si.runtime_id,
+            app_id: enclave_identity.mr_signer,
+            enclave_identity,
+            rak: si.rak_binding.rak_pub(),
+            node_id: si.endorsed_by.or(si.node_id),
+        }
+    }
+}
 
 /// RPC call context.
 pub struct Context {
     /// Information about the session the RPC call was delivered over.
     pub session_info: Option<Arc<SessionInfo>>,
+    /// Verified identity of the caller, in case the session has an attested remote peer.
+    pub caller: Option<CallerContext>,
+    /// Whether the RPC call was delivered over a session where the remote peer did not provide
+    /// a remote attestation.
+    ///
+    /// Such calls are only dispatched to methods explicitly marked as allowing anonymous access.
+    pub anonymous: bool,
 }
 
 impl Context {
     /// Construct new transaction context.
     pub fn new(session_info: Option<Arc<SessionInfo>>) -> Self {
-        Self { session_info }
+        let caller = session_info
+            .as_deref()
+            .map(CallerContext::from_session_info);
+
+        Self {
+            session_info,
+            caller,
+            anonymous: false,
+        }
+    }
+
+    /// Verified identity of the caller, in case the session has an attested remote peer.
+    pub fn caller(&self) -> Option<&CallerContext> {
+        self.caller.as_ref()
+    }
+
+    /// Whether the RPC call was delivered over a session where the remote peer did not provide
+    /// a remote attestation.
+    pub fn is_anonymous(&self) -> bool {
+        self.anonymous
     }
 }
@@ -1,13 +1,18 @@
 //! Session demultiplexer.
-use std::{io::Write, sync::Mutex};
+use std::{
+    io::Write,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
 
 use thiserror::Error;
-use tokio::sync::OwnedMutexGuard;
+use tokio::sync::{watch, OwnedMutexGuard};
 
 use super::{
+    codec::{self, CodecError, FrameVersion},
     session::Builder,
-    sessions::{self, MultiplexedSession, Sessions},
-    types::{Frame, Message, SessionID},
+    sessions::{self, MultiplexedSession, Rejections, Sessions},
+    types::{Message, SessionID, StreamFrame},
 };
 use crate::common::time::insecure_posix_time;
 
@@ -22,6 +27,19 @@ pub enum Error {
     SessionsError(#[from] sessions::Error),
     #[error("{0}")]
     Other(#[from] anyhow::Error),
+    #[error("draining, retry after {retry_after} seconds")]
+    Draining { retry_after: u64 },
+    #[error("malformed frame: {0}")]
+    MalformedFrame(#[source] CodecError),
+}
+
+impl From<CodecError> for Error {
+    fn from(err: CodecError) -> Self {
+        match err {
+            CodecError::Decode(err) => Error::MalformedPayload(err),
+            err => Error::MalformedFrame(err),
+        }
+    }
 }
 
 impl Error {
@@ -31,6 +49,8 @@ impl Error {
             Error::MalformedRequestMethod => 2,
             Error::SessionsError(_) => 3,
             Error::Other(_) => 4,
+            Error::Draining { .. } => 5,
+            Error::MalformedFrame(_) => 6,
         }
     }
 }
@@ -45,9 +65,27 @@ impl From<Error> for crate::types::Error {
     }
 }
 
+/// Guard of an in-flight call, which the demultiplexer waits for while draining.
+pub struct CallGuard {
+    in_flight: Arc<watch::Sender<usize>>,
+}
+
+impl Drop for CallGuard {
+    fn drop(&mut self) {
+        self.in_flight.send_modify(|count| *count -= 1);
+    }
+}
+
 /// Session demultiplexer.
 pub struct Demux {
     sessions: Mutex<Sessions<Vec<u8>>>,
+    /// Retry-after hint in seconds, set once draining has started.
+    draining: Mutex<Option<u64>>,
+    in_flight: Arc<watch::Sender<usize>>,
+    /// Accepted frame codec versions.
+    frame_versions: Mutex<Vec<FrameVersion>>,
+    /// Sessions removed since they were last taken, whose state must be released.
+    closed: Mutex<Vec<(Vec<u8>, SessionID)>>,
 }
 
 impl Demux {
@@ -65,15 +103,103 @@ impl Demux {
                 max_sessions_per_peer,
                 stale_session_timeout,
             )),
+            draining: Mutex::new(None),
+            in_flight: Arc::new(watch::channel(0).0),
+            frame_versions: Mutex::new(FrameVersion::ALL.to_vec()),
+            closed: Mutex::new(vec![]),
         }
     }
 
+    /// Take the peer and session identifiers of all sessions removed since the last call,
+    /// including sessions evicted to make room for new ones.
+    pub fn take_closed(&self) -> Vec<(Vec<u8>, SessionID)> {
+        std::mem::take(&mut *self.closed.lock().unwrap())
+    }
+
+    fn remove(
+        &self,
+        sessions: &mut Sessions<Vec<u8>>,
+        session: &OwnedMutexGuard<MultiplexedSession<Vec<u8>>>,
+    ) {
+        sessions.remove(session);
+        self.record_closed(session);
+    }
+
+    fn record_closed(&self, session: &MultiplexedSession<Vec<u8>>) {
+        self.closed
+            .lock()
+            .unwrap()
+            .push((session.get_peer_id().to_vec(), *session.get_session_id()));
+    }
+
     /// Set the session builder to use.
     pub fn set_session_builder(&self, builder: Builder) {
         let mut sessions = self.sessions.lock().unwrap();
         sessions.set_builder(builder);
     }
 
+    /// Set the maximum number of authenticated sessions per remote enclave identity.
+    pub fn set_max_sessions_per_enclave(&self, max_sessions_per_enclave: usize) {
+        let mut sessions = self.sessions.lock().unwrap();
+        sessions.set_max_sessions_per_enclave(max_sessions_per_enclave);
+    }
+
+    /// Set the accepted frame codec versions.
+    ///
+    /// All supported versions are accepted by default, so that peers can migrate to newer
+    /// versions gradually.
+    pub fn set_frame_versions(&self, versions: Vec<FrameVersion>) {
+        *self.frame_versions.lock().unwrap() = versions;
+    }
+
+    /// Number of sessions rejected due to each of the session limits.
+    pub fn rejections(&self) -> Rejections {
+        let sessions = self.sessions.lock().unwrap();
+        sessions.rejections()
+    }
+
+    /// Number of calls currently in flight.
+    pub fn in_flight(&self) -> usize {
+        *self.in_flight.borrow()
+    }
+
+    /// Whether the demultiplexer is draining and no longer accepts calls.
+    pub fn is_draining(&self) -> bool {
+        self.draining.lock().unwrap().is_some()
+    }
+
+    /// Start a new call, which is tracked until the returned guard is dropped.
+    ///
+    /// While draining, calls are rejected with an error advising the peer when to retry.
+    pub fn begin_call(&self) -> Result<CallGuard, Error> {
+        let draining = self.draining.lock().unwrap();
+        if let Some(retry_after) = *draining {
+            return Err(Error::Draining { retry_after });
+        }
+        self.in_flight.send_modify(|count| *count += 1);
+
+        Ok(CallGuard {
+            in_flight: self.in_flight.clone(),
+        })
+    }
+
+    /// Stop accepting calls and wait for in-flight calls to complete, up to the given timeout.
+    ///
+    /// Peers whose calls are rejected are advised to retry after the given duration. Once
+    /// draining completes, all sessions are reset. Returns whether all in-flight calls completed
+    /// before the timeout.
+    pub async fn drain(&self, timeout: Duration, retry_after: Duration) -> bool {
+        *self.draining.lock().unwrap() = Some(retry_after.as_secs());
+
+        let mut in_flight = self.in_flight.subscribe();
+        let completed = tokio::time::timeout(timeout, in_flight.wait_for(|count| *count == 0))
+            .await
+            .is_ok();
+        self.reset();
+
+        completed
+    }
+
     async fn get_or_create_session(
         &self,
         peer_id: Vec<u8>,
@@ -85,8 +211,10 @@ impl Demux {
                 Some(session) => session,
                 None => {
                     let now = insecure_posix_time();
-                    let _ = sessions.remove_for(&peer_id, now)?;
-                    let session = sessions.create_responder(peer_id, session_id);
+                    if let Some(evicted) = sessions.remove_for(&peer_id, now)? {
+                        self.record_closed(&evicted);
+                    }
+                    let session = sessions.create_responder(peer_id, session_id)?;
                     sessions
                         .add(session, now)
                         .expect("there should be space for the new session")
@@ -113,26 +241,41 @@ impl Demux {
         Error,
     > {
         // Decode frame.
-        let frame: Frame = cbor::from_slice(&data)?;
+        let frame = codec::decode(&data, &self.frame_versions.lock().unwrap())?;
         // Get the existing session or create a new one.
         let mut session = self.get_or_create_session(peer_id, frame.session).await?;
         // Process session data.
         match session.process_data(&frame.payload, writer).await {
             Ok(msg) => {
-                if let Some(Message::Request(ref req)) = msg {
-                    // Make sure that the untrusted_plaintext matches the request's method.
-                    if frame.untrusted_plaintext != req.method {
-                        return Err(Error::MalformedRequestMethod);
+                // Once the handshake completes, enforce the remote enclave identity limit.
+                {
+                    let mut sessions = self.sessions.lock().unwrap();
+                    if let Err(err) = sessions.authenticate(&session) {
+                        self.remove(&mut sessions, &session);
+                        return Err(err.into());
                     }
                 }
 
+                // Make sure that the untrusted_plaintext matches the request's method.
+                let method = match msg {
+                    Some(Message::Request(ref req)) => Some(&req.method),
+                    Some(Message::Stream(StreamFrame {
+                        open: Some(ref req),
+                        ..
+                    })) => Some(&req.method),
+                    _ => None,
+                };
+                if method.is_some_and(|method| &frame.untrusted_plaintext != method) {
+                    return Err(Error::MalformedRequestMethod);
+                }
+
                 Ok((session, msg))
             }
             Err(err) => {
                 // In case the session was closed, remove the session.
                 if session.is_closed() {
                     let mut sessions = self.sessions.lock().unwrap();
-                    sessions.remove(&session);
+                    self.remove(&mut sessions, &session);
                 }
                 Err(Error::Other(err))
             }
@@ -148,7 +291,7 @@ impl Demux {
         writer: W,
     ) -> Result<(), Error> {
         let mut sessions = self.sessions.lock().unwrap();
-        sessions.remove(&session);
+        self.remove(&mut sessions, &session);
 
         session.write_message(Message::Close, writer)?;
         Ok(())
@@ -160,3 +303,75 @@ impl Demux {
         let _ = sessions.drain();
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_drain() {
+        let rt = tokio::runtime::Builder::new_current_thread()
+            .enable_time()
+            .build()
+            .unwrap();
+        let timeout = Duration::from_secs(10);
+        let retry_after = Duration::from_secs(30);
+
+        let demux = Demux::new(Builder::default(), 4, 4, 60);
+        let call = demux.begin_call().unwrap();
+        assert_eq!(demux.in_flight(), 1);
+        assert!(!demux.is_draining());
+
+        let (completed, _) = rt.block_on(async {
+            tokio::join!(demux.drain(timeout, retry_after), async {
+                tokio::task::yield_now().await;
+                // New calls are rejected while in-flight calls complete.
+                assert!(demux.is_draining());
+                assert!(matches!(
+                    demux.begin_call(),
+                    Err(Error::Draining { retry_after: 30 })
+                ));
+                drop(call);
+            })
+        });
+        assert!(completed);
+        assert_eq!(demux.in_flight(), 0);
+
+        // Calls still in flight once the timeout passes are abandoned.
+        let demux = Demux::new(Builder::default(), 4, 4, 60);
+        let _call = demux.begin_call().unwrap();
+        let completed = rt.block_on(demux.drain(Duration::from_millis(10), retry_after));
+        assert!(!completed);
+    }
+
+    #[test]
+    fn test_take_closed() {
+        let rt = tokio::runtime::Builder::new_current_thread()
+            .build()
+            .unwrap();
+        let demux = Demux::new(Builder::default(), 4, 1, 60);
+        let peer_id = b"peer".to_vec();
+        let s1 = SessionID::random();
+        let s2 = SessionID::random();
+
+        rt.block_on(async {
+            drop(
+                demux
+                    .get_or_create_session(peer_id.clone(), s1)
+                    .await
+                    .unwrap(),
+            );
+            assert!(demux.take_closed().is_empty());
+
+            // Opening another session evicts the first one.
+            drop(
+                demux
+                    .get_or_create_session(peer_id.clone(), s2)
+                    .await
+                    .unwrap(),
+            );
+            assert_eq!(demux.take_closed(), vec![(peer_id, s1)]);
+            assert!(demux.take_closed().is_empty());
+        });
+    }
+}
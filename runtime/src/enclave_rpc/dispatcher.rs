@@ -1,14 +1,24 @@
 //! RPC dispatcher.
-use std::collections::HashMap;
+use std::{collections::HashMap, num::NonZeroUsize, sync::Mutex};
 
 use anyhow::{bail, Result};
 use thiserror::Error;
 
-use crate::{common::sgx::QuotePolicy, consensus::state::keymanager::Status as KeyManagerStatus};
+use crate::{
+    common::sgx::QuotePolicy,
+    config::RpcAdmission,
+    consensus::{beacon::EpochTime, state::keymanager::Status as KeyManagerStatus},
+    future::block_on,
+};
 
 use super::{
+    admission::{AdmissionControl, AdmissionError},
+    cache::ResponseCache,
     context::Context,
-    types::{Body, Kind, Request, Response},
+    revocation::RevocationList,
+    service::{Service, ServiceHost},
+    stream::{self, StreamingMethod, Streams},
+    types::{Body, Kind, Request, Response, SessionID, StreamFrame},
 };
 
 /// Dispatch error.
@@ -18,6 +28,10 @@ enum DispatchError {
     MethodNotFound { method: String },
     #[error("invalid RPC kind: {method:?} ({kind:?})")]
     InvalidRpcKind { method: String, kind: Kind },
+    #[error("method requires an attested session: {method:?}")]
+    AnonymousNotAllowed { method: String },
+    #[error("remote enclave identity revoked")]
+    EnclaveRevoked,
 }
 
 /// RPC handler.
@@ -33,6 +47,17 @@ pub struct MethodDescriptor {
     pub name: String,
     /// Specifies which kind of RPC is allowed to call the method.
     pub kind: Kind,
+    /// Whether the method may be called over Noise sessions where the remote peer did not
+    /// provide a remote attestation. Such calls are marked as anonymous in the call context.
+    ///
+    /// This should only be enabled for public read-only methods.
+    pub allow_anonymous: bool,
+    /// Whether responses may be served from the response cache (when enabled). Responses are
+    /// reused until the round or the consensus epoch advances or the key manager status changes.
+    ///
+    /// This should only be enabled for idempotent read-only methods whose responses depend on
+    /// the arguments and state alone, not on the caller.
+    pub cacheable: bool,
 }
 
 /// Handler for a RPC method.
@@ -83,6 +108,7 @@ where
 
         Ok(Response {
             body: Body::Success(cbor::to_value(response)),
+            error: None,
         })
     }
 }
@@ -119,6 +145,16 @@ impl Method {
         self.dispatcher.get_descriptor().kind
     }
 
+    /// Return whether the method may be called anonymously.
+    fn allows_anonymous(&self) -> bool {
+        self.dispatcher.get_descriptor().allow_anonymous
+    }
+
+    /// Return whether responses may be cached.
+    fn is_cacheable(&self) -> bool {
+        self.dispatcher.get_descriptor().cacheable
+    }
+
     /// Dispatch a request.
     fn dispatch(&self, ctx: &mut Context, request: Request) -> Result<Response> {
         self.dispatcher.dispatch(ctx, request)
@@ -129,6 +165,8 @@ impl Method {
 pub type KeyManagerStatusHandler = dyn Fn(KeyManagerStatus) + Send + Sync;
 /// Key manager quote policy update handler callback.
 pub type KeyManagerQuotePolicyHandler = dyn Fn(QuotePolicy) + Send + Sync;
+/// Source of the current consensus epoch, used to key cached responses.
+pub type EpochSource = dyn Fn() -> Option<EpochTime> + Send + Sync;
 
 /// RPC call dispatcher.
 #[derive(Default)]
@@ -139,6 +177,18 @@ pub struct Dispatcher {
     km_status_handler: Option<Box<KeyManagerStatusHandler>>,
     /// Registered key manager quote policy handler.
     km_quote_policy_handler: Option<Box<KeyManagerQuotePolicyHandler>>,
+    /// Cache of responses to cacheable methods, if enabled.
+    response_cache: Option<Mutex<ResponseCache>>,
+    /// Source of the consensus epoch cached responses are keyed by, if any.
+    response_cache_epoch: Option<Box<EpochSource>>,
+    /// Admission control of calls by remote peers, if enabled.
+    admission: Option<AdmissionControl>,
+    /// Registered streaming RPC methods.
+    streaming_methods: HashMap<String, StreamingMethod>,
+    /// Open streams.
+    streams: Mutex<Streams>,
+    /// Registered services.
+    services: Vec<ServiceHost>,
 }
 
 impl Dispatcher {
@@ -154,12 +204,93 @@ impl Dispatcher {
         }
     }
 
+    /// Register a new streaming method in the dispatcher.
+    pub fn add_streaming_method(&mut self, method: StreamingMethod) {
+        self.streaming_methods
+            .insert(method.get_descriptor().name.clone(), method);
+    }
+
+    /// Register a new service in the dispatcher.
+    ///
+    /// The service is started by [`Dispatcher::start_services`] and its methods fail until then.
+    pub fn add_service<S: Service + 'static>(&mut self, service: S) {
+        let methods = service.methods();
+        let host = ServiceHost::new(Box::new(service));
+        for descriptor in methods {
+            let host = host.clone();
+            let name = descriptor.name.clone();
+            self.add_method(Method::new(
+                descriptor,
+                move |ctx: &Context, args: &cbor::Value| -> Result<cbor::Value> {
+                    block_on(host.handle(ctx, &name, args.clone()))
+                },
+            ));
+        }
+        self.services.push(host);
+    }
+
+    /// Start all registered services, in order of registration.
+    ///
+    /// Services failing to start are dropped, the first error is returned once all other services
+    /// have been started.
+    pub async fn start_services(&self) -> Result<()> {
+        let mut result = Ok(());
+        for service in &self.services {
+            if let Err(err) = service.start().await {
+                result = result.and(Err(err));
+            }
+        }
+        result
+    }
+
+    /// Stop and drop all running services, in reverse order of registration.
+    ///
+    /// The first error is returned once all services have been stopped.
+    pub async fn stop_services(&self) -> Result<()> {
+        let mut result = Ok(());
+        for service in self.services.iter().rev() {
+            if let Err(err) = service.stop().await {
+                result = result.and(Err(err));
+            }
+        }
+        result
+    }
+
+    /// Enable caching of up to `capacity` responses to methods marked as cacheable.
+    pub fn enable_response_cache(&mut self, capacity: NonZeroUsize) {
+        self.response_cache = Some(Mutex::new(ResponseCache::new(capacity)));
+    }
+
+    /// Key cached responses by the consensus epoch returned by the given source, so that
+    /// responses depending on the epoch are not served once it advances. Calls are not served
+    /// from the cache while the epoch is unknown.
+    pub fn set_response_cache_epoch_source(&mut self, source: Option<Box<EpochSource>>) {
+        self.response_cache_epoch = source;
+    }
+
+    /// Enable admission control of calls by remote peers with the given limits. Local queries
+    /// are always admitted.
+    pub fn enable_admission_control(&mut self, config: RpcAdmission) {
+        self.admission = Some(AdmissionControl::new(config));
+    }
+
+    /// Notify the dispatcher that the runtime advanced to the given round, invalidating any
+    /// cached responses.
+    pub fn advance_round(&self, round: u64) {
+        if let Some(cache) = self.response_cache.as_ref() {
+            cache.lock().unwrap().advance(round);
+        }
+    }
+
     /// Dispatch request.
     pub fn dispatch(&self, mut ctx: Context, request: Request, kind: Kind) -> Response {
         match self.dispatch_fallible(&mut ctx, request, kind) {
             Ok(response) => response,
             Err(error) => Response {
                 body: Body::Error(format!("{error}")),
+                error: error
+                    .downcast_ref::<AdmissionError>()
+                    .map(AdmissionError::code),
             },
         }
     }
@@ -170,6 +301,13 @@ impl Dispatcher {
         request: Request,
         kind: Kind,
     ) -> Result<Response> {
+        // Revocations take effect immediately, also for already established sessions.
+        if let Some(caller) = ctx.caller() {
+            if RevocationList::global().is_revoked(&caller.enclave_identity) {
+                bail!(DispatchError::EnclaveRevoked);
+            }
+        }
+
         let method = match self.methods.get(&request.method) {
             Some(method) => method,
             None => bail!(DispatchError::MethodNotFound {
@@ -184,11 +322,120 @@ impl Dispatcher {
             });
         };
 
-        method.dispatch(ctx, request)
+        // Calls over sessions without an attested remote peer are only allowed for methods that
+        // explicitly opt in, in which case they are marked as such so handlers can tell.
+        ctx.anonymous = kind == Kind::NoiseSession && ctx.caller.is_none();
+        if ctx.anonymous && !method.allows_anonymous() {
+            bail!(DispatchError::AnonymousNotAllowed {
+                method: request.method,
+            });
+        }
+
+        // Calls rejected by admission control fail with a distinct error so clients can back off.
+        let _permit = match self.admission.as_ref() {
+            Some(admission) if kind != Kind::LocalQuery => {
+                Some(admission.admit(ctx, &request.method)?)
+            }
+            _ => None,
+        };
+
+        let cache = match self.response_cache.as_ref() {
+            Some(cache) if method.is_cacheable() => cache,
+            _ => return method.dispatch(ctx, request),
+        };
+        if let Some(source) = self.response_cache_epoch.as_ref() {
+            match source() {
+                Some(epoch) => cache.lock().unwrap().advance_epoch(epoch),
+                None => return method.dispatch(ctx, request),
+            }
+        }
+
+        let key = cache.lock().unwrap().key(&request);
+        if let Some(response) = cache.lock().unwrap().get(&key) {
+            return Ok(response);
+        }
+        let response = method.dispatch(ctx, request)?;
+        cache.lock().unwrap().put(key, response.clone());
+
+        Ok(response)
+    }
+
+    /// Handle a stream frame received over the given session, returning the frame to send back.
+    ///
+    /// Frames opening a stream are dispatched to the registered streaming method, which keeps
+    /// running in the background until it completes or the stream is aborted.
+    pub fn handle_streaming(
+        &self,
+        mut ctx: Context,
+        peer_id: &[u8],
+        session_id: SessionID,
+        mut frame: StreamFrame,
+    ) -> StreamFrame {
+        let mut streams = self.streams.lock().unwrap();
+
+        if let Some(request) = frame.open.take() {
+            let opened = self
+                .authorize_streaming(&mut ctx, &request.method)
+                .and_then(|method| {
+                    streams
+                        .open(ctx, peer_id, session_id, frame.stream_id, method, request)
+                        .map_err(anyhow::Error::msg)
+                });
+            if let Err(err) = opened {
+                return stream::failed(frame.stream_id, format!("{err}"));
+            }
+        }
+
+        streams.process(peer_id, session_id, frame)
+    }
+
+    fn authorize_streaming(&self, ctx: &mut Context, method: &str) -> Result<&StreamingMethod> {
+        // Revocations take effect immediately, also for already established sessions.
+        if let Some(caller) = ctx.caller() {
+            if RevocationList::global().is_revoked(&caller.enclave_identity) {
+                bail!(DispatchError::EnclaveRevoked);
+            }
+        }
+
+        let streaming_method = match self.streaming_methods.get(method) {
+            Some(streaming_method) => streaming_method,
+            None => bail!(DispatchError::MethodNotFound {
+                method: method.to_string(),
+            }),
+        };
+
+        if streaming_method.get_descriptor().kind != Kind::Stream {
+            bail!(DispatchError::InvalidRpcKind {
+                method: method.to_string(),
+                kind: Kind::Stream,
+            });
+        }
+
+        ctx.anonymous = ctx.caller.is_none();
+        if ctx.anonymous && !streaming_method.get_descriptor().allow_anonymous {
+            bail!(DispatchError::AnonymousNotAllowed {
+                method: method.to_string(),
+            });
+        }
+
+        Ok(streaming_method)
+    }
+
+    /// Abort all streams opened over the given session.
+    pub fn close_streams(&self, peer_id: &[u8], session_id: &SessionID) {
+        self.streams
+            .lock()
+            .unwrap()
+            .close_session(peer_id, session_id);
     }
 
     /// Handle key manager status update.
     pub fn handle_km_status_update(&self, status: KeyManagerStatus) {
+        RevocationList::global().update_from_policy(status.policy.as_ref());
+        if let Some(cache) = self.response_cache.as_ref() {
+            cache.lock().unwrap().invalidate();
+        }
+
         if let Some(handler) = self.km_status_handler.as_ref() {
             handler(status)
         }
@@ -217,3 +464,395 @@ impl Dispatcher {
         self.km_quote_policy_handler = f;
     }
 }
+
+#[cfg(test)]
+mod test {
+    use std::{
+        sync::{
+            atomic::{AtomicU64, Ordering},
+            Arc,
+        },
+        time::Duration,
+    };
+
+    use async_trait::async_trait;
+    use futures::{SinkExt, StreamExt};
+
+    use super::{
+        stream::{RpcSink, RpcStream, STREAM_WINDOW},
+        *,
+    };
+    use crate::config::RpcQuota;
+
+    fn dispatcher() -> Dispatcher {
+        let mut dispatcher = Dispatcher::default();
+        dispatcher.add_methods(vec![
+            Method::new(
+                MethodDescriptor {
+                    name: "public".to_string(),
+                    kind: Kind::NoiseSession,
+                    allow_anonymous: true,
+                    cacheable: false,
+                },
+                |ctx: &Context, _req: &()| -> Result<bool> { Ok(ctx.is_anonymous()) },
+            ),
+            Method::new(
+                MethodDescriptor {
+                    name: "private".to_string(),
+                    kind: Kind::NoiseSession,
+                    allow_anonymous: false,
+                    cacheable: false,
+                },
+                |ctx: &Context, _req: &()| -> Result<bool> { Ok(ctx.is_anonymous()) },
+            ),
+            Method::new(
+                MethodDescriptor {
+                    name: "insecure".to_string(),
+                    kind: Kind::InsecureQuery,
+                    allow_anonymous: false,
+                    cacheable: false,
+                },
+                |ctx: &Context, _req: &()| -> Result<bool> { Ok(ctx.is_anonymous()) },
+            ),
+        ]);
+        dispatcher
+    }
+
+    fn call(dispatcher: &Dispatcher, method: &str, kind: Kind) -> Body {
+        let request = Request {
+            method: method.to_string(),
+            args: cbor::to_value(()),
+        };
+        dispatcher.dispatch(Context::new(None), request, kind).body
+    }
+
+    #[test]
+    fn test_anonymous_dispatch() {
+        let dispatcher = dispatcher();
+
+        // Whitelisted methods are callable without attestation and the call is tagged.
+        match call(&dispatcher, "public", Kind::NoiseSession) {
+            Body::Success(value) => assert!(cbor::from_value::<bool>(value).unwrap()),
+            Body::Error(err) => panic!("anonymous call should succeed: {err}"),
+        }
+
+        // Other session methods require attestation.
+        match call(&dispatcher, "private", Kind::NoiseSession) {
+            Body::Success(_) => panic!("anonymous call should fail"),
+            Body::Error(err) => assert!(err.contains("requires an attested session")),
+        }
+
+        // Insecure queries are not affected.
+        match call(&dispatcher, "insecure", Kind::InsecureQuery) {
+            Body::Success(value) => assert!(!cbor::from_value::<bool>(value).unwrap()),
+            Body::Error(err) => panic!("insecure query should succeed: {err}"),
+        }
+    }
+
+    #[test]
+    fn test_admission_control() {
+        let mut dispatcher = dispatcher();
+        dispatcher.enable_admission_control(RpcAdmission {
+            anonymous_quota: RpcQuota {
+                cost_per_second: 1,
+                burst: 1,
+                max_concurrent: 0,
+            },
+            ..Default::default()
+        });
+
+        assert!(matches!(
+            call(&dispatcher, "insecure", Kind::InsecureQuery),
+            Body::Success(_)
+        ));
+        let request = Request {
+            method: "insecure".to_string(),
+            args: cbor::to_value(()),
+        };
+        let response = dispatcher.dispatch(Context::new(None), request, Kind::InsecureQuery);
+        assert!(matches!(response.body, Body::Error(_)));
+        assert!(matches!(
+            response.error.as_ref().and_then(AdmissionError::from_code),
+            Some(AdmissionError::RateLimited { .. })
+        ));
+    }
+
+    #[test]
+    fn test_response_cache() {
+        let calls = Arc::new(AtomicU64::new(0));
+        let counter = |calls: Arc<AtomicU64>| {
+            move |_ctx: &Context, req: &u64| -> Result<u64> {
+                Ok(req + calls.fetch_add(1, Ordering::SeqCst))
+            }
+        };
+        let method = |name: &str, cacheable| MethodDescriptor {
+            name: name.to_string(),
+            kind: Kind::InsecureQuery,
+            allow_anonymous: false,
+            cacheable,
+        };
+
+        let mut dispatcher = Dispatcher::default();
+        dispatcher.add_methods(vec![
+            Method::new(method("cached", true), counter(calls.clone())),
+            Method::new(method("uncached", false), counter(calls.clone())),
+        ]);
+        dispatcher.enable_response_cache(NonZeroUsize::new(16).unwrap());
+        let epoch = Arc::new(AtomicU64::new(0));
+        let source = epoch.clone();
+        dispatcher.set_response_cache_epoch_source(Some(Box::new(move || {
+            Some(source.load(Ordering::SeqCst))
+        })));
+
+        let call = |method: &str, arg: u64| {
+            let request = Request {
+                method: method.to_string(),
+                args: cbor::to_value(arg),
+            };
+            match dispatcher
+                .dispatch(Context::new(None), request, Kind::InsecureQuery)
+                .body
+            {
+                Body::Success(value) => cbor::from_value::<u64>(value).unwrap(),
+                Body::Error(err) => panic!("call should succeed: {err}"),
+            }
+        };
+
+        // Repeated calls with the same arguments are served from the cache.
+        assert_eq!(call("cached", 10), 10);
+        assert_eq!(call("cached", 10), 10);
+        assert_eq!(call("cached", 20), 21);
+        assert_eq!(calls.load(Ordering::SeqCst), 2);
+
+        // Methods not marked as cacheable are always dispatched.
+        assert_eq!(call("uncached", 10), 12);
+        assert_eq!(call("uncached", 10), 13);
+
+        // Advancing the round and key manager status updates invalidate the cache.
+        dispatcher.advance_round(1);
+        assert_eq!(call("cached", 10), 14);
+        assert_eq!(call("cached", 10), 14);
+        dispatcher.handle_km_status_update(Default::default());
+        assert_eq!(call("cached", 10), 15);
+
+        // Advancing the consensus epoch invalidates the cache.
+        epoch.store(1, Ordering::SeqCst);
+        assert_eq!(call("cached", 10), 16);
+        assert_eq!(call("cached", 10), 16);
+    }
+
+    struct Counter {
+        count: Option<AtomicU64>,
+        dropped: Arc<AtomicU64>,
+    }
+
+    #[async_trait]
+    impl Service for Counter {
+        fn methods(&self) -> Vec<MethodDescriptor> {
+            vec![MethodDescriptor {
+                name: "count".to_string(),
+                kind: Kind::InsecureQuery,
+                allow_anonymous: false,
+                cacheable: false,
+            }]
+        }
+
+        async fn on_ready(&mut self) -> Result<()> {
+            self.count = Some(AtomicU64::new(10));
+            Ok(())
+        }
+
+        async fn handle(
+            &self,
+            _ctx: &Context,
+            method: &str,
+            args: cbor::Value,
+        ) -> Result<cbor::Value> {
+            assert_eq!(method, "count");
+            let inc: u64 = cbor::from_value(args)?;
+            let count = self.count.as_ref().unwrap();
+            Ok(cbor::to_value(count.fetch_add(inc, Ordering::SeqCst) + inc))
+        }
+    }
+
+    impl Drop for Counter {
+        fn drop(&mut self) {
+            self.dropped.fetch_add(1, Ordering::SeqCst);
+        }
+    }
+
+    #[test]
+    fn test_services() {
+        let rt = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .unwrap();
+        let _guard = rt.enter(); // Ensure Tokio runtime is available.
+        let dropped = Arc::new(AtomicU64::new(0));
+        let mut dispatcher = Dispatcher::default();
+        dispatcher.add_service(Counter {
+            count: None,
+            dropped: dropped.clone(),
+        });
+
+        let call = |arg: u64| {
+            let request = Request {
+                method: "count".to_string(),
+                args: cbor::to_value(arg),
+            };
+            dispatcher
+                .dispatch(Context::new(None), request, Kind::InsecureQuery)
+                .body
+        };
+
+        // Methods fail until the service is started.
+        match call(1) {
+            Body::Success(_) => panic!("call should fail"),
+            Body::Error(err) => assert!(err.contains("service not running")),
+        }
+
+        // Started services serve the state initialized when starting.
+        rt.block_on(dispatcher.start_services()).unwrap();
+        rt.block_on(dispatcher.start_services()).unwrap();
+        for (arg, expected) in [(1, 11), (2, 13)] {
+            match call(arg) {
+                Body::Success(value) => {
+                    assert_eq!(cbor::from_value::<u64>(value).unwrap(), expected)
+                }
+                Body::Error(err) => panic!("call should succeed: {err}"),
+            }
+        }
+
+        // Stopped services are dropped.
+        rt.block_on(dispatcher.stop_services()).unwrap();
+        assert_eq!(dropped.load(Ordering::SeqCst), 1);
+        match call(1) {
+            Body::Success(_) => panic!("call should fail"),
+            Body::Error(err) => assert!(err.contains("service not running")),
+        }
+    }
+
+    fn streaming_dispatcher() -> Dispatcher {
+        let mut dispatcher = Dispatcher::default();
+        dispatcher.add_streaming_method(StreamingMethod::new(
+            MethodDescriptor {
+                name: "sum".to_string(),
+                kind: Kind::Stream,
+                allow_anonymous: true,
+                cacheable: false,
+            },
+            |_ctx: Context,
+             offset: u64,
+             mut inbound: RpcStream<u64>,
+             mut outbound: RpcSink<u64>| {
+                async move {
+                    // Send back the running sum of the received items.
+                    let mut sum = offset;
+                    while let Some(item) = inbound.next().await {
+                        sum += item?;
+                        outbound.send(sum).await?;
+                    }
+                    Ok::<_, anyhow::Error>(())
+                }
+            },
+        ));
+        dispatcher
+    }
+
+    fn stream_frame(stream_id: u64, items: Vec<u64>, window: u32, end: bool) -> StreamFrame {
+        StreamFrame {
+            stream_id,
+            items: items.into_iter().map(cbor::to_value).collect(),
+            window,
+            end,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_streaming() {
+        // Handlers only make progress while the runtime is being driven.
+        let rt = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .unwrap();
+        let _guard = rt.enter(); // Ensure Tokio runtime is available.
+        let dispatcher = streaming_dispatcher();
+        let session_id = SessionID::random();
+        let handle =
+            |frame| dispatcher.handle_streaming(Context::new(None), b"peer", session_id, frame);
+
+        // Poll the stream until the expected number of items was received or it finished.
+        let poll = |stream_id, count: usize| {
+            let mut items = vec![];
+            for _ in 0..100 {
+                let rsp = handle(stream_frame(stream_id, vec![], STREAM_WINDOW, false));
+                assert_eq!(rsp.error, None);
+                items.extend(
+                    rsp.items
+                        .into_iter()
+                        .map(|v| cbor::from_value::<u64>(v).unwrap()),
+                );
+                if rsp.end || items.len() >= count {
+                    return (items, rsp.end);
+                }
+                rt.block_on(tokio::time::sleep(Duration::from_millis(10)));
+            }
+            panic!("stream should make progress");
+        };
+
+        // Open a stream, without being ready to receive any items yet.
+        let mut open = stream_frame(1, vec![1, 2], 0, false);
+        open.open = Some(Request {
+            method: "sum".to_string(),
+            args: cbor::to_value(10u64),
+        });
+        let rsp = handle(open);
+        assert_eq!(rsp.error, None);
+        assert!(rsp.items.is_empty());
+        assert!(rsp.window <= STREAM_WINDOW);
+        assert_eq!(dispatcher.streams.lock().unwrap().len(), 1);
+
+        // Items flow once the window is opened.
+        assert_eq!(poll(1, 2), (vec![11, 13], false));
+        assert!(handle(stream_frame(1, vec![3], 0, false)).error.is_none());
+        assert_eq!(poll(1, 1), (vec![16], false));
+
+        // Ending the items completes the handler, which finishes the stream.
+        assert!(handle(stream_frame(1, vec![], 0, true)).error.is_none());
+        assert_eq!(poll(1, usize::MAX), (vec![], true));
+        assert!(dispatcher.streams.lock().unwrap().is_empty());
+        assert_eq!(
+            handle(stream_frame(1, vec![], 0, false)).error,
+            Some("unknown stream".to_string())
+        );
+
+        // Exceeding the flow-control window aborts the stream.
+        let mut open = stream_frame(2, vec![0; STREAM_WINDOW as usize + 1], 0, false);
+        open.open = Some(Request {
+            method: "sum".to_string(),
+            args: cbor::to_value(0u64),
+        });
+        let rsp = handle(open);
+        assert_eq!(rsp.error, Some("flow-control window exceeded".to_string()));
+        assert!(dispatcher.streams.lock().unwrap().is_empty());
+
+        // Unknown methods are rejected.
+        let mut open = stream_frame(3, vec![], 0, false);
+        open.open = Some(Request {
+            method: "unknown".to_string(),
+            args: cbor::to_value(()),
+        });
+        assert!(handle(open).error.unwrap().contains("method not found"));
+
+        // Closing the session aborts its streams.
+        let mut open = stream_frame(4, vec![], 0, false);
+        open.open = Some(Request {
+            method: "sum".to_string(),
+            args: cbor::to_value(0u64),
+        });
+        assert!(handle(open).error.is_none());
+        dispatcher.close_streams(b"peer", &session_id);
+        assert!(dispatcher.streams.lock().unwrap().is_empty());
+    }
+}
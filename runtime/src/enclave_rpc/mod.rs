@@ -1,13 +1,22 @@
 //! Secure inter-enclave RPC.
 
+pub mod admission;
+mod cache;
 pub mod client;
+pub mod codec;
 pub mod context;
 pub mod demux;
 pub mod dispatcher;
+pub mod pool;
+pub mod replay;
+pub mod resumption;
+pub mod revocation;
+pub mod service;
 pub mod session;
 pub mod sessions;
+pub mod stream;
 mod transport;
 pub mod types;
 
 // Re-exports.
-pub use self::context::Context;
+pub use self::context::{CallerContext, Context};
@@ -0,0 +1,248 @@
+//! Pool of enclave RPC clients.
+//!
+//! A single client multiplexes calls over its own sessions, but components opening clients
+//! ad-hoc pay for a full handshake whenever all sessions are busy or have been evicted by the
+//! remote side. The pool maintains a fixed number of clients with attested sessions to the same
+//! endpoint, dispatches each call to the least loaded client and keeps idle sessions healthy by
+//! re-establishing them before the remote side considers them stale.
+use std::{
+    collections::HashSet,
+    sync::{
+        atomic::{AtomicI64, AtomicUsize, Ordering},
+        Arc, Weak,
+    },
+    time::Duration,
+};
+
+use futures::future::join_all;
+
+use crate::{
+    common::{
+        crypto::signature,
+        namespace::Namespace,
+        sgx::{EnclaveIdentity, QuotePolicy},
+        time::insecure_posix_time,
+    },
+    protocol::Protocol,
+};
+
+use super::{
+    client::{Response, RpcClient},
+    session::Builder,
+    transport::RuntimeTransport,
+};
+
+/// A pooled client.
+struct PooledClient {
+    client: RpcClient,
+    /// Number of calls in progress.
+    in_flight: AtomicUsize,
+    /// Timestamp when the client was last used, either by a call or a health check.
+    last_used: AtomicI64,
+}
+
+/// Guard accounting for a call in progress.
+struct CallGuard<'a>(&'a PooledClient);
+
+impl Drop for CallGuard<'_> {
+    fn drop(&mut self) {
+        self.0
+            .last_used
+            .store(insecure_posix_time(), Ordering::SeqCst);
+        self.0.in_flight.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+/// Pool of RPC clients connected to the same endpoint.
+pub struct RpcClientPool {
+    clients: Vec<PooledClient>,
+    /// Time (in seconds) after which idle sessions are re-established.
+    max_idle: i64,
+    /// Index of the client to start selection at, used to break ties.
+    next: AtomicUsize,
+}
+
+impl RpcClientPool {
+    pub(super) fn new(clients: Vec<RpcClient>, max_idle: i64) -> Self {
+        assert!(!clients.is_empty(), "pool must contain at least one client");
+
+        let now = insecure_posix_time();
+        let clients = clients
+            .into_iter()
+            .map(|client| PooledClient {
+                client,
+                in_flight: AtomicUsize::new(0),
+                last_used: AtomicI64::new(now),
+            })
+            .collect();
+
+        Self {
+            clients,
+            max_idle,
+            next: AtomicUsize::new(0),
+        }
+    }
+
+    /// Construct a pool of `size` unconnected RPC clients with runtime-internal transport.
+    ///
+    /// Idle sessions are re-established by health checks after half of the stale session
+    /// timeout, before the remote side removes them.
+    pub fn new_runtime(
+        protocol: Arc<Protocol>,
+        endpoint: &str,
+        builder: Builder,
+        size: usize,
+        max_sessions: usize,
+        max_sessions_per_peer: usize,
+        stale_session_timeout: i64,
+    ) -> Self {
+        let clients = (0..size)
+            .map(|_| {
+                RpcClient::new(
+                    Box::new(RuntimeTransport::new(protocol.clone(), endpoint)),
+                    builder.clone(),
+                    max_sessions,
+                    max_sessions_per_peer,
+                    stale_session_timeout,
+                )
+            })
+            .collect();
+
+        Self::new(clients, stale_session_timeout / 2)
+    }
+
+    /// Number of clients in the pool.
+    pub fn size(&self) -> usize {
+        self.clients.len()
+    }
+
+    /// Number of calls in progress.
+    pub fn in_flight(&self) -> usize {
+        self.clients
+            .iter()
+            .map(|client| client.in_flight.load(Ordering::SeqCst))
+            .sum()
+    }
+
+    /// Update allowed remote enclave identities of all clients.
+    pub async fn update_enclaves(&self, enclaves: Option<HashSet<EnclaveIdentity>>) {
+        join_all(
+            self.clients
+                .iter()
+                .map(|pooled| pooled.client.update_enclaves(enclaves.clone())),
+        )
+        .await;
+    }
+
+    /// Update remote end's quote policy of all clients.
+    pub async fn update_quote_policy(&self, policy: QuotePolicy) {
+        join_all(
+            self.clients
+                .iter()
+                .map(|pooled| pooled.client.update_quote_policy(policy.clone())),
+        )
+        .await;
+    }
+
+    /// Update remote runtime id of all clients.
+    pub async fn update_runtime_id(&self, id: Option<Namespace>) {
+        join_all(
+            self.clients
+                .iter()
+                .map(|pooled| pooled.client.update_runtime_id(id)),
+        )
+        .await;
+    }
+
+    /// Call a remote method using an encrypted and authenticated Noise session of the least
+    /// loaded client.
+    pub async fn secure_call<C, O>(
+        &self,
+        method: &'static str,
+        args: C,
+        nodes: Vec<signature::PublicKey>,
+    ) -> Response<O>
+    where
+        C: cbor::Encode,
+        O: cbor::Decode + Send + 'static,
+    {
+        let (pooled, _guard) = self.select();
+        pooled.client.secure_call(method, args, nodes).await
+    }
+
+    /// Call a remote method over an insecure channel where messages are sent in plain text.
+    pub async fn insecure_call<C, O>(
+        &self,
+        method: &'static str,
+        args: C,
+        nodes: Vec<signature::PublicKey>,
+    ) -> Response<O>
+    where
+        C: cbor::Encode,
+        O: cbor::Decode + Send + 'static,
+    {
+        let (pooled, _guard) = self.select();
+        pooled.client.insecure_call(method, args, nodes).await
+    }
+
+    /// Check the sessions of all idle clients, re-establishing sessions that have been idle for
+    /// too long and establishing sessions for clients whose sessions failed.
+    ///
+    /// Returns the number of clients with an established session.
+    pub async fn health_check(&self, nodes: Vec<signature::PublicKey>) -> usize {
+        let now = insecure_posix_time();
+        let results = join_all(self.clients.iter().map(|pooled| {
+            let nodes = nodes.clone();
+            async move {
+                if pooled.in_flight.load(Ordering::SeqCst) > 0 {
+                    // Busy clients have active sessions.
+                    return true;
+                }
+                if now - pooled.last_used.load(Ordering::SeqCst) >= self.max_idle {
+                    pooled.client.close_sessions().await;
+                }
+                pooled.last_used.store(now, Ordering::SeqCst);
+                pooled.client.ensure_connected(nodes).await.is_ok()
+            }
+        }))
+        .await;
+
+        results.into_iter().filter(|ok| *ok).count()
+    }
+
+    /// Periodically run health checks of the pool in the background until the pool is dropped.
+    pub fn start_health_checks(
+        self: &Arc<Self>,
+        nodes: Vec<signature::PublicKey>,
+        interval: Duration,
+    ) {
+        let pool = Arc::downgrade(self);
+        tokio::spawn(Self::run_health_checks(pool, nodes, interval));
+    }
+
+    async fn run_health_checks(
+        pool: Weak<Self>,
+        nodes: Vec<signature::PublicKey>,
+        interval: Duration,
+    ) {
+        loop {
+            match pool.upgrade() {
+                Some(pool) => pool.health_check(nodes.clone()).await,
+                None => return,
+            };
+            tokio::time::sleep(interval).await;
+        }
+    }
+
+    /// Select the client with the least calls in progress, accounting for a new call.
+    fn select(&self) -> (&PooledClient, CallGuard<'_>) {
+        let start = self.next.fetch_add(1, Ordering::Relaxed);
+        let pooled = (0..self.clients.len())
+            .map(|i| &self.clients[(start + i) % self.clients.len()])
+            .min_by_key(|pooled| pooled.in_flight.load(Ordering::SeqCst))
+            .expect("pool must contain at least one client");
+        pooled.in_flight.fetch_add(1, Ordering::SeqCst);
+
+        (pooled, CallGuard(pooled))
+    }
+}
@@ -0,0 +1,240 @@
+//! Anti-replay windows of session messages.
+//!
+//! Messages of established sessions are protected by the nonces of the transport cipher. By
+//! default, nonces are implicit and messages must be received strictly in order, so the host
+//! reordering frames (e.g. to balance load over multiple connections) tears the session down.
+//! Sessions may instead carry explicit nonces, in which case messages are accepted out of order
+//! as long as they fall within a sliding window behind the highest nonce received so far and
+//! have not been received before.
+//!
+//! Transport keys are derived from ephemeral keys of the handshake and never leave the enclave,
+//! so messages of sessions established before a restart can't be decrypted afterwards. Windows
+//! may additionally be persisted as high-water marks sealed to the enclave, in which case all
+//! messages below the persisted mark are rejected once the window is restored. Marks are
+//! persisted ahead of the received messages, so that they only need to be written once every
+//! [`HIGH_WATER_RESERVE`] messages. As the host can roll back its local storage, persisted marks
+//! are a defense in depth only.
+use std::sync::Arc;
+
+use sgx_isa::Keypolicy;
+use thiserror::Error;
+
+use crate::{
+    common::{crypto::hash::Hash, sgx::seal},
+    storage::KeyValue,
+};
+
+/// Maximum size of an anti-replay window.
+pub const MAX_REPLAY_WINDOW: u64 = 4096;
+
+/// Number of nonces reserved ahead of the received messages when persisting a high-water mark.
+pub const HIGH_WATER_RESERVE: u64 = 1024;
+
+/// Storage key prefix of persisted high-water marks.
+const HIGH_WATER_STORAGE_KEY_PREFIX: &str = "enclave_rpc.replay.high_water";
+
+/// Domain separation context of sealed high-water marks.
+const HIGH_WATER_CONTEXT: &[u8] = b"oasis-core/enclave-rpc: replay high-water mark";
+
+/// Anti-replay window errors.
+#[derive(Error, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ReplayError {
+    #[error("message with nonce {0} has already been received")]
+    Replayed(u64),
+
+    #[error("message with nonce {0} is outside of the replay window")]
+    TooOld(u64),
+}
+
+/// Sliding window of recently received nonces.
+#[derive(Clone, Debug)]
+pub struct ReplayWindow {
+    /// Number of nonces behind the highest received nonce that are still accepted.
+    size: u64,
+    /// Nonce following the highest received nonce.
+    next: u64,
+    /// Bitmap of received nonces within the window, indexed by nonce modulo its length.
+    seen: Vec<u64>,
+}
+
+impl ReplayWindow {
+    /// Create a new window of the given size, capped at [`MAX_REPLAY_WINDOW`].
+    ///
+    /// A zero size only accepts nonces which are higher than all previously received ones.
+    pub fn new(size: u64) -> Self {
+        let size = size.min(MAX_REPLAY_WINDOW);
+        Self {
+            size,
+            next: 0,
+            seen: vec![0; size.div_ceil(64) as usize],
+        }
+    }
+
+    /// Number of nonces behind the highest received nonce that are still accepted.
+    pub fn size(&self) -> u64 {
+        self.size
+    }
+
+    /// Nonce following the highest received nonce.
+    pub fn next(&self) -> u64 {
+        self.next
+    }
+
+    fn bit(&self, nonce: u64) -> (usize, u64) {
+        let bits = self.seen.len() as u64 * 64;
+        let pos = nonce % bits;
+        ((pos / 64) as usize, 1 << (pos % 64))
+    }
+
+    /// Restore the window from a persisted high-water mark, rejecting all nonces below it.
+    pub fn restore(&mut self, high_water: u64) {
+        if high_water <= self.next {
+            return;
+        }
+        self.next = high_water;
+        self.seen.fill(u64::MAX);
+    }
+
+    /// Verify that a message with the given nonce may be accepted, without recording it.
+    pub fn check(&self, nonce: u64) -> Result<(), ReplayError> {
+        if nonce >= self.next {
+            return Ok(());
+        }
+        if self.next - nonce > self.size {
+            return Err(ReplayError::TooOld(nonce));
+        }
+        let (word, mask) = self.bit(nonce);
+        if self.seen[word] & mask != 0 {
+            return Err(ReplayError::Replayed(nonce));
+        }
+        Ok(())
+    }
+
+    /// Record the given nonce as received.
+    ///
+    /// Must only be called once the message has been authenticated, as the window would
+    /// otherwise move forward on forged nonces.
+    pub fn accept(&mut self, nonce: u64) -> Result<(), ReplayError> {
+        self.check(nonce)?;
+        if self.seen.is_empty() {
+            self.next = self.next.max(nonce.saturating_add(1));
+            return Ok(());
+        }
+
+        if nonce >= self.next {
+            // Forget the nonces which are no longer in the window.
+            if nonce - self.next >= self.seen.len() as u64 * 64 {
+                self.seen.fill(0);
+            } else {
+                for skipped in self.next..nonce {
+                    let (word, mask) = self.bit(skipped);
+                    self.seen[word] &= !mask;
+                }
+            }
+            self.next = nonce.saturating_add(1);
+        }
+        let (word, mask) = self.bit(nonce);
+        self.seen[word] |= mask;
+        Ok(())
+    }
+}
+
+/// Storage of the high-water marks of anti-replay windows.
+pub trait HighWaterStore: Send + Sync {
+    /// Load the high-water mark of the window with the given identifier, if persisted.
+    fn load(&self, id: &Hash) -> Option<u64>;
+
+    /// Persist the high-water mark of the window with the given identifier.
+    fn store(&self, id: &Hash, high_water: u64);
+}
+
+/// Storage of high-water marks sealed to the enclave in the untrusted local storage.
+pub struct SealedHighWaterStore {
+    storage: Arc<dyn KeyValue>,
+}
+
+impl SealedHighWaterStore {
+    /// Create a new store of high-water marks backed by the given untrusted local storage.
+    pub fn new(storage: Arc<dyn KeyValue>) -> Self {
+        Self { storage }
+    }
+
+    fn storage_key(id: &Hash) -> Vec<u8> {
+        format!("{HIGH_WATER_STORAGE_KEY_PREFIX}.{id:x}").into_bytes()
+    }
+}
+
+impl HighWaterStore for SealedHighWaterStore {
+    fn load(&self, id: &Hash) -> Option<u64> {
+        let sealed = self.storage.get(Self::storage_key(id)).ok()?;
+        let raw = seal::unseal(Keypolicy::MRENCLAVE, HIGH_WATER_CONTEXT, &sealed).ok()??;
+        cbor::from_slice(&raw).ok()
+    }
+
+    fn store(&self, id: &Hash, high_water: u64) {
+        let sealed = seal::seal(
+            Keypolicy::MRENCLAVE,
+            HIGH_WATER_CONTEXT,
+            &cbor::to_vec(high_water),
+        );
+        // Failing to persist the mark only weakens the protection across restarts.
+        let _ = self.storage.insert(Self::storage_key(id), sealed);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_replay_window() {
+        let mut window = ReplayWindow::new(4);
+        window.accept(0).unwrap();
+        window.accept(2).unwrap();
+        assert_eq!(window.next(), 3);
+
+        // Messages are accepted out of order, but only once.
+        assert_eq!(window.accept(2), Err(ReplayError::Replayed(2)));
+        window.accept(1).unwrap();
+        assert_eq!(window.check(1), Err(ReplayError::Replayed(1)));
+
+        // Messages behind the window are rejected.
+        window.accept(7).unwrap();
+        assert_eq!(window.check(2), Err(ReplayError::TooOld(2)));
+        window.accept(4).unwrap();
+
+        // Nonces skipped over by large jumps are accepted while within the window.
+        window.accept(1000).unwrap();
+        assert_eq!(window.check(997), Ok(()));
+        assert_eq!(window.check(996), Err(ReplayError::TooOld(996)));
+        assert_eq!(window.check(1000), Err(ReplayError::Replayed(1000)));
+    }
+
+    #[test]
+    fn test_replay_window_strict() {
+        let mut window = ReplayWindow::new(0);
+        window.accept(0).unwrap();
+        window.accept(5).unwrap();
+        assert_eq!(window.accept(5), Err(ReplayError::TooOld(5)));
+        assert_eq!(window.accept(4), Err(ReplayError::TooOld(4)));
+
+        assert_eq!(ReplayWindow::new(u64::MAX).size(), MAX_REPLAY_WINDOW);
+    }
+
+    #[test]
+    fn test_replay_window_restore() {
+        let mut window = ReplayWindow::new(4);
+        window.accept(10).unwrap();
+
+        // Restoring from a persisted high-water mark rejects all nonces below it.
+        window.restore(100);
+        assert_eq!(window.next(), 100);
+        assert_eq!(window.check(99), Err(ReplayError::Replayed(99)));
+        assert_eq!(window.check(11), Err(ReplayError::TooOld(11)));
+        window.accept(100).unwrap();
+
+        // Marks below the highest received nonce are ignored.
+        window.restore(50);
+        assert_eq!(window.next(), 101);
+    }
+}
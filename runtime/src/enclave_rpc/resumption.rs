@@ -0,0 +1,164 @@
+//! Session resumption.
+//!
+//! Establishing a session requires a full handshake including verification of the remote
+//! attestation, which is expensive to repeat after every transient disconnect. Once a session
+//! has been established, both sides derive a session ticket from the handshake, which allows the
+//! initiator to establish a new session with the same peer using a single round trip handshake
+//! keyed by the ticket, skipping remote attestation.
+//!
+//! Ticket keys are derived from the transport keys of the handshake, so that only the two parties
+//! of the original session (and not the host relaying the handshake) can resume it. Tickets can
+//! only be used once, as each resumed session derives a fresh ticket. They expire after the
+//! configured time-to-live, but never outlive the remote attestation they were derived from.
+//! Tickets of revoked enclaves are rejected, and responders verify the remote attestation of
+//! resumed sessions against their current policy again.
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+};
+
+use super::{revocation::RevocationList, session::SessionInfo};
+use crate::common::{
+    crypto::{hash::Hash, signature::PublicKey},
+    time::insecure_posix_time,
+};
+
+/// Context used to derive ticket identifiers from the handshake hash.
+const TICKET_ID_CONTEXT: &[u8] = b"oasis-core/enclave-rpc: session ticket id";
+/// Context used to derive ticket keys from the transport keys of the handshake.
+const TICKET_PSK_CONTEXT: &[u8] = b"oasis-core/enclave-rpc: session ticket psk";
+
+/// Default maximum number of tickets retained by a ticket store.
+pub const DEFAULT_MAX_TICKETS: usize = 1024;
+
+/// A session ticket, allowing a previously established session to be resumed.
+#[derive(Clone)]
+pub struct SessionTicket {
+    /// Ticket identifier.
+    pub id: Hash,
+    /// Pre-shared key used to authenticate the resumption handshake.
+    pub(super) psk: Hash,
+    /// Information about the remote peer of the original session.
+    pub(super) info: Option<Arc<SessionInfo>>,
+    /// Remote node of the original session.
+    pub(super) remote_node: Option<PublicKey>,
+    /// Whether the original session used the hybrid post-quantum key exchange.
+    pub(super) hybrid: bool,
+    /// Time after which the ticket expires.
+    pub expiration: i64,
+}
+
+impl SessionTicket {
+    /// Derive a ticket from the hash and the transport keys of a completed handshake.
+    ///
+    /// The handshake hash is known to anyone observing the handshake, so it is only used to
+    /// identify the ticket, while its key is derived from the secret transport keys.
+    pub(super) fn derive(
+        handshake_hash: &[u8],
+        transport_keys: &[u8],
+        info: Option<Arc<SessionInfo>>,
+        remote_node: Option<PublicKey>,
+        hybrid: bool,
+        expiration: i64,
+    ) -> Self {
+        // Tickets never outlive the remote attestation.
+        let expiration = match info {
+            Some(ref info) => expiration.min(info.attestation_expiration()),
+            None => expiration,
+        };
+
+        Self {
+            id: Hash::digest_bytes_list(&[TICKET_ID_CONTEXT, handshake_hash]),
+            psk: Hash::digest_bytes_list(&[TICKET_PSK_CONTEXT, transport_keys, handshake_hash]),
+            info,
+            remote_node,
+            hybrid,
+            expiration,
+        }
+    }
+
+    /// Remote node of the original session.
+    pub fn remote_node(&self) -> Option<PublicKey> {
+        self.remote_node
+    }
+
+    /// Whether the ticket may still be used at the given time.
+    pub fn is_valid(&self, now: i64) -> bool {
+        if now >= self.expiration {
+            return false;
+        }
+        match self.info {
+            Some(ref info) => {
+                !RevocationList::global().is_revoked(&info.verified_attestation.quote.identity)
+            }
+            None => true,
+        }
+    }
+}
+
+/// Store of tickets issued by responders.
+pub struct TicketStore {
+    ttl: i64,
+    max_tickets: usize,
+    tickets: Mutex<HashMap<Hash, SessionTicket>>,
+}
+
+impl TicketStore {
+    /// Create a new ticket store, issuing tickets valid for `ttl` seconds.
+    pub fn new(ttl: i64, max_tickets: usize) -> Self {
+        Self {
+            ttl,
+            max_tickets,
+            tickets: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Time-to-live of issued tickets in seconds.
+    pub fn ttl(&self) -> i64 {
+        self.ttl
+    }
+
+    /// Store the given ticket, evicting expired tickets and, if the store is full, the ticket
+    /// closest to its expiration.
+    pub(super) fn insert(&self, ticket: SessionTicket) {
+        let now = insecure_posix_time();
+        let mut tickets = self.tickets.lock().unwrap();
+        tickets.retain(|_, ticket| ticket.is_valid(now));
+        if tickets.len() >= self.max_tickets {
+            let oldest = tickets
+                .values()
+                .min_by_key(|ticket| ticket.expiration)
+                .map(|ticket| ticket.id);
+            if let Some(oldest) = oldest {
+                tickets.remove(&oldest);
+            }
+        }
+        tickets.insert(ticket.id, ticket);
+    }
+
+    /// Take the ticket with the given identifier, if it is still valid.
+    pub(super) fn take(&self, id: &Hash) -> Option<SessionTicket> {
+        let ticket = self.tickets.lock().unwrap().remove(id)?;
+        ticket.is_valid(insecure_posix_time()).then_some(ticket)
+    }
+
+    /// Remove all tickets which are no longer valid, e.g. because the remote attestation they
+    /// were derived from expired or the remote enclave has been revoked.
+    pub fn purge(&self) {
+        let now = insecure_posix_time();
+        self.tickets
+            .lock()
+            .unwrap()
+            .retain(|_, ticket| ticket.is_valid(now));
+    }
+
+    /// Number of stored tickets.
+    pub fn len(&self) -> usize {
+        self.tickets.lock().unwrap().len()
+    }
+
+    /// Whether the store is empty.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
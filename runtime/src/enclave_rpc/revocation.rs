@@ -0,0 +1,84 @@
+//! Emergency enclave revocation list.
+use std::{collections::HashSet, sync::RwLock};
+
+use crate::{common::sgx::EnclaveIdentity, consensus::keymanager::SignedPolicySGX};
+
+lazy_static! {
+    static ref REVOCATION_LIST: RevocationList = RevocationList::new();
+}
+
+/// List of enclave identities that have been revoked by the key manager policy.
+///
+/// The list is replaced whenever a new key manager policy is observed and takes effect
+/// immediately, both for new sessions and for calls over already established sessions.
+#[derive(Default)]
+pub struct RevocationList {
+    revoked: RwLock<HashSet<EnclaveIdentity>>,
+}
+
+impl RevocationList {
+    /// Create a new empty revocation list.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Global revocation list instance.
+    pub fn global() -> &'static RevocationList {
+        &REVOCATION_LIST
+    }
+
+    /// Replace the set of revoked enclave identities.
+    pub fn set<I>(&self, revoked: I)
+    where
+        I: IntoIterator<Item = EnclaveIdentity>,
+    {
+        *self.revoked.write().unwrap() = revoked.into_iter().collect();
+    }
+
+    /// Replace the set of revoked enclave identities with the one from the given policy.
+    ///
+    /// The policy is presumed trustworthy, so it's up to the caller to verify it against
+    /// the consensus layer state.
+    pub fn update_from_policy(&self, policy: Option<&SignedPolicySGX>) {
+        let revoked = policy
+            .map(|policy| policy.policy.revoked.clone())
+            .unwrap_or_default();
+        self.set(revoked);
+    }
+
+    /// Whether the given enclave identity has been revoked.
+    pub fn is_revoked(&self, enclave: &EnclaveIdentity) -> bool {
+        self.revoked.read().unwrap().contains(enclave)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::common::sgx::{MrEnclave, MrSigner};
+
+    #[test]
+    fn test_revocation_list() {
+        let revoked = EnclaveIdentity {
+            mr_enclave: MrEnclave([1u8; 32]),
+            mr_signer: MrSigner([2u8; 32]),
+        };
+        let other = EnclaveIdentity {
+            mr_enclave: MrEnclave([3u8; 32]),
+            mr_signer: MrSigner([2u8; 32]),
+        };
+
+        let list = RevocationList::new();
+        assert!(!list.is_revoked(&revoked));
+
+        let mut policy = SignedPolicySGX::default();
+        policy.policy.revoked = vec![revoked.clone()];
+        list.update_from_policy(Some(&policy));
+        assert!(list.is_revoked(&revoked));
+        assert!(!list.is_revoked(&other));
+
+        // A policy without revocations clears the list.
+        list.update_from_policy(None);
+        assert!(!list.is_revoked(&revoked));
+    }
+}
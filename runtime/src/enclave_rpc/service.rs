@@ -0,0 +1,109 @@
+//! Stateful RPC services.
+//!
+//! Methods registered via [`Handler`](super::dispatcher::Handler) must be `'static`, which forces
+//! any state they use to be leaked or kept in global singletons. Services are instead owned by
+//! the dispatcher, which starts them once the runtime is ready to serve requests (so they can
+//! initialize state that depends on e.g. the host or verified consensus state) and stops and
+//! drops them when the runtime shuts down. Calls to methods of services that are not running fail.
+use std::sync::Arc;
+
+use anyhow::Result;
+use async_trait::async_trait;
+use thiserror::Error;
+use tokio::sync::RwLock;
+
+use super::{context::Context, dispatcher::MethodDescriptor};
+
+/// Service error.
+#[derive(Error, Debug)]
+pub enum ServiceError {
+    #[error("service not running")]
+    NotRunning,
+}
+
+/// An asynchronous RPC service.
+#[allow(unused_variables)]
+#[async_trait]
+pub trait Service: Send + Sync {
+    /// Returns the descriptors of the RPC methods provided by this service.
+    fn methods(&self) -> Vec<MethodDescriptor>;
+
+    /// Called once the runtime is ready to serve requests, before any method is dispatched.
+    async fn on_ready(&mut self) -> Result<()> {
+        // Default implementation does nothing.
+        Ok(())
+    }
+
+    /// Handle a call of the given method.
+    async fn handle(&self, ctx: &Context, method: &str, args: cbor::Value) -> Result<cbor::Value>;
+
+    /// Called when the runtime shuts down, after in-flight calls completed. The service is
+    /// dropped afterwards.
+    async fn on_shutdown(&mut self) -> Result<()> {
+        // Default implementation does nothing.
+        Ok(())
+    }
+}
+
+/// Lifecycle state of a service.
+enum State {
+    /// Registered, but not yet started.
+    Registered(Box<dyn Service>),
+    /// Started and serving requests.
+    Running(Box<dyn Service>),
+    /// Stopped, either after shutdown or after failing to start.
+    Stopped,
+}
+
+/// A service registered with the dispatcher.
+#[derive(Clone)]
+pub(super) struct ServiceHost {
+    state: Arc<RwLock<State>>,
+}
+
+impl ServiceHost {
+    /// Register the given service.
+    pub(super) fn new(service: Box<dyn Service>) -> Self {
+        Self {
+            state: Arc::new(RwLock::new(State::Registered(service))),
+        }
+    }
+
+    /// Start the service, in case it has not been started yet.
+    pub(super) async fn start(&self) -> Result<()> {
+        let mut state = self.state.write().await;
+        let mut service = match std::mem::replace(&mut *state, State::Stopped) {
+            State::Registered(service) => service,
+            other => {
+                *state = other;
+                return Ok(());
+            }
+        };
+        service.on_ready().await?;
+        *state = State::Running(service);
+
+        Ok(())
+    }
+
+    /// Stop and drop the service, waiting for in-flight calls to complete.
+    pub(super) async fn stop(&self) -> Result<()> {
+        let mut state = self.state.write().await;
+        match std::mem::replace(&mut *state, State::Stopped) {
+            State::Running(mut service) => service.on_shutdown().await,
+            _ => Ok(()),
+        }
+    }
+
+    /// Dispatch a call of the given method to the service.
+    pub(super) async fn handle(
+        &self,
+        ctx: &Context,
+        method: &str,
+        args: cbor::Value,
+    ) -> Result<cbor::Value> {
+        match &*self.state.read().await {
+            State::Running(service) => service.handle(ctx, method, args).await,
+            _ => Err(ServiceError::NotRunning.into()),
+        }
+    }
+}
@@ -1,15 +1,39 @@
 //! Secure channel session.
-use std::{collections::HashSet, io::Write, mem, sync::Arc};
+use std::{
+    collections::{BTreeMap, HashSet},
+    io::Write,
+    mem,
+    sync::Arc,
+};
 
 use anyhow::Result;
+use ml_kem::{
+    kem::{Decapsulate, Encapsulate},
+    Ciphertext, Encoded, EncodedSizeUser, KemCore, MlKem768,
+};
+use snow::{
+    params::CipherChoice,
+    resolvers::{CryptoResolver, DefaultResolver},
+};
 use thiserror::Error;
+use zeroize::{Zeroize, Zeroizing};
 
-use super::types::Message;
+use super::{
+    replay::{HighWaterStore, ReplayError, ReplayWindow, HIGH_WATER_RESERVE},
+    resumption::{SessionTicket, TicketStore},
+    revocation::RevocationList,
+    types::Message,
+};
 use crate::{
     common::{
-        crypto::signature::{self, PublicKey, Signature, Signer},
+        crypto::{
+            hash::Hash,
+            rng::SecureRng,
+            signature::{self, PublicKey, Signature, Signer},
+        },
         namespace::Namespace,
-        sgx::{ias, EnclaveIdentity, Quote, QuotePolicy},
+        sgx::{ias, EnclaveIdentity, Quote, QuotePolicy, MAX_QUOTE_AGE},
+        time::{clock_skew_tolerance, insecure_posix_time},
     },
     consensus::{
         registry::{EndorsedCapabilityTEE, VerifiedAttestation, VerifiedEndorsedCapabilityTEE},
@@ -21,6 +45,46 @@ use crate::{
 
 /// Noise protocol pattern.
 const NOISE_PATTERN: &str = "Noise_XX_25519_ChaChaPoly_SHA256";
+/// Noise protocol pattern used for the hybrid X25519+ML-KEM-768 key exchange, where the
+/// pre-shared key is the ML-KEM shared secret established by the first two messages.
+const NOISE_PATTERN_HYBRID: &str = "Noise_XXpsk3_25519_ChaChaPoly_SHA256";
+/// Noise protocol pattern using AES-GCM.
+const NOISE_PATTERN_AES_GCM: &str = "Noise_XX_25519_AESGCM_SHA256";
+/// Noise protocol pattern used for the hybrid X25519+ML-KEM-768 key exchange and AES-GCM.
+const NOISE_PATTERN_HYBRID_AES_GCM: &str = "Noise_XXpsk3_25519_AESGCM_SHA256";
+/// Location of the pre-shared key holding the ML-KEM shared secret in the hybrid patterns.
+const KEM_PSK_LOCATION: usize = 3;
+/// Size of ML-KEM-768 ciphertexts.
+const KEM_CIPHERTEXT_SIZE: usize = 1088;
+/// Noise protocol pattern used when resuming a session using a session ticket.
+const NOISE_PATTERN_RESUME: &str = "Noise_NNpsk0_25519_ChaChaPoly_SHA256";
+/// Length of the initial handshake message when using the classic key exchange.
+const CLASSIC_INITIAL_MESSAGE_LEN: usize = 32;
+/// Prefix of the initial handshake message when resuming a session.
+const RESUMPTION_PREFIX: &[u8] = b"EkResume";
+/// Prefix of the initial handshake message when using a cipher other than the default one,
+/// followed by the cipher identifier.
+const CIPHER_PREFIX: &[u8] = b"EkCipher";
+/// Prefix of the initial handshake message when requesting explicit nonces for transport
+/// messages, preceding any other prefixes.
+const EXPLICIT_NONCE_PREFIX: &[u8] = b"EkNonces";
+/// Size of the explicit nonce preceding transport messages.
+const EXPLICIT_NONCE_SIZE: usize = 8;
+/// Maximum number of key ratchet steps taken to decrypt a single message with an explicit nonce.
+const MAX_RATCHET_STEPS: u64 = 16;
+/// Size of transport keys.
+const TRANSPORT_KEY_SIZE: usize = 32;
+/// Size of the authentication tag of transport messages.
+const TRANSPORT_TAG_SIZE: usize = 16;
+/// Context used to derive identifiers of anti-replay windows from the handshake hash.
+const REPLAY_WINDOW_ID_CONTEXT: &[u8] = b"oasis-core/enclave-rpc: replay window";
+
+/// Raw transport key.
+type TransportKey = Zeroizing<[u8; TRANSPORT_KEY_SIZE]>;
+/// ML-KEM-768 encapsulation key.
+type KemEncapsulationKey = <MlKem768 as KemCore>::EncapsulationKey;
+/// ML-KEM-768 decapsulation key.
+type KemDecapsulationKey = <MlKem768 as KemCore>::DecapsulationKey;
 /// RAK signature session binding context.
 const RAK_SESSION_BINDING_CONTEXT: [u8; 8] = *b"EkRakRpc";
 
@@ -47,6 +111,79 @@ enum SessionError {
     RAKNotFound,
     #[error("runtime id not set")]
     RuntimeNotSet,
+    #[error("remote enclave identity revoked")]
+    EnclaveRevoked,
+    #[error("remote attestation expired")]
+    AttestationExpired,
+    #[error("key exchange not allowed by policy")]
+    KeyExchangeNotAllowed,
+    #[error("session resumption not supported")]
+    ResumptionNotSupported,
+    #[error("invalid or expired session ticket")]
+    InvalidTicket,
+    #[error("unsupported cipher: {0}")]
+    UnsupportedCipher(u8),
+}
+
+/// Policy for using the hybrid post-quantum key exchange in session handshakes.
+///
+/// The hybrid key exchange combines X25519 with ML-KEM-768 (FIPS 203), so that sessions remain
+/// confidential even if one of the two is broken. The initiator sends an ephemeral ML-KEM
+/// encapsulation key in the first handshake message, the responder encapsulates a shared secret
+/// to it in the second one and both sides mix the shared secret into the handshake as the
+/// pre-shared key of the third one, so that the transport keys depend on both key exchanges.
+/// Responders detect the key exchange used by the initiator from its initial handshake message,
+/// so enabling the hybrid key exchange for initiators requires that the responders accept it.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum PostQuantumPolicy {
+    /// Initiate and accept only classic handshakes.
+    #[default]
+    Disabled,
+    /// Initiate classic handshakes, accept both classic and hybrid handshakes.
+    Accept,
+    /// Initiate hybrid handshakes, accept both classic and hybrid handshakes.
+    Enabled,
+    /// Initiate and accept only hybrid handshakes.
+    Required,
+}
+
+impl PostQuantumPolicy {
+    fn initiate_hybrid(&self) -> bool {
+        matches!(self, Self::Enabled | Self::Required)
+    }
+
+    fn accept_classic(&self) -> bool {
+        !matches!(self, Self::Required)
+    }
+
+    fn accept_hybrid(&self) -> bool {
+        !matches!(self, Self::Disabled)
+    }
+}
+
+/// AEAD cipher used to protect session messages.
+///
+/// Responders detect the cipher used by the initiator from its initial handshake message and
+/// accept all ciphers, so initiators can switch ciphers independently of responders. Resumed
+/// sessions always use the default cipher.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[repr(u8)]
+pub enum SessionCipher {
+    /// ChaCha20-Poly1305.
+    #[default]
+    ChaChaPoly = 0,
+    /// AES-256-GCM, which is substantially faster on CPUs with AES-NI.
+    AesGcm = 1,
+}
+
+impl SessionCipher {
+    fn from_id(id: u8) -> Result<Self> {
+        match id {
+            0 => Ok(Self::ChaChaPoly),
+            1 => Ok(Self::AesGcm),
+            id => Err(SessionError::UnsupportedCipher(id).into()),
+        }
+    }
 }
 
 /// Information about a session.
@@ -57,11 +194,66 @@ pub struct SessionInfo {
     pub verified_attestation: VerifiedAttestation,
     /// Identifier of the node that endorsed the TEE.
     pub endorsed_by: Option<PublicKey>,
+    /// Identifier of the runtime the remote RAK is registered for, if the remote node identity
+    /// has been verified against the consensus layer registry.
+    pub runtime_id: Option<Namespace>,
+    /// Identifier of the remote node, if its identity has been verified against the consensus
+    /// layer registry.
+    pub node_id: Option<PublicKey>,
+}
+
+impl SessionInfo {
+    /// Time after which the remote attestation of the session is no longer valid.
+    pub fn attestation_expiration(&self) -> i64 {
+        self.verified_attestation.quote.timestamp + MAX_QUOTE_AGE + clock_skew_tolerance()
+    }
+
+    /// Verify that the remote attestation of the session is still valid at the given time and
+    /// that the remote enclave has not been revoked since.
+    fn verify_still_valid(&self, now: i64) -> Result<()> {
+        if now >= self.attestation_expiration() {
+            return Err(SessionError::AttestationExpired.into());
+        }
+        if RevocationList::global().is_revoked(&self.verified_attestation.quote.identity) {
+            return Err(SessionError::EnclaveRevoked.into());
+        }
+        Ok(())
+    }
+}
+
+/// Key exchanges accepted by a responder, whose handshake state is built once the key exchange
+/// and cipher used by the initiator are known.
+struct Candidates {
+    private_key: Vec<u8>,
+    classic: bool,
+    hybrid: bool,
+}
+
+impl Candidates {
+    /// Build the handshake state matching the key exchange used by the initial message.
+    fn select(
+        self,
+        initial_message: &[u8],
+        cipher: SessionCipher,
+    ) -> Result<(snow::HandshakeState, bool)> {
+        let hybrid = initial_message.len() != CLASSIC_INITIAL_MESSAGE_LEN;
+        let allowed = if hybrid { self.hybrid } else { self.classic };
+        if !allowed {
+            return Err(SessionError::KeyExchangeNotAllowed.into());
+        }
+        let state = Builder::noise_builder(hybrid, cipher)?
+            .local_private_key(&self.private_key)
+            .build_responder()?;
+        Ok((state, hybrid))
+    }
 }
 
 enum State {
+    Negotiate(Candidates),
     Handshake1(snow::HandshakeState),
     Handshake2(snow::HandshakeState),
+    Resume1(snow::HandshakeState, SessionTicket),
+    Resume2(snow::HandshakeState, SessionTicket),
     Transport(snow::TransportState),
     UnauthenticatedTransport(snow::TransportState),
     Closed,
@@ -74,17 +266,44 @@ pub struct Session {
     remote_node: Option<signature::PublicKey>,
     info: Option<Arc<SessionInfo>>,
     state: State,
+    hybrid: bool,
+    /// ML-KEM decapsulation key of an initiator awaiting the encapsulated shared secret.
+    kem_key: Option<KemDecapsulationKey>,
+    cipher: SessionCipher,
+    resumed: bool,
+    ticket: Option<SessionTicket>,
+    sent: u64,
+    received: u64,
+    replay: Option<ReplayWindow>,
+    /// Incoming transport keys of the epochs within the anti-replay window, for sessions with
+    /// explicit nonces whose keys are ratcheted.
+    incoming_keys: BTreeMap<u64, TransportKey>,
+    /// Epoch of the incoming transport key loaded into the transport state.
+    incoming_epoch: u64,
+    /// Identifier and persisted high-water mark of the anti-replay window.
+    high_water: Option<(Hash, u64)>,
     buf: Vec<u8>,
 }
 
 impl Session {
-    fn new(handshake_state: snow::HandshakeState, local_static_pub: Vec<u8>, cfg: Config) -> Self {
+    fn new(state: State, hybrid: bool, local_static_pub: Vec<u8>, cfg: Config) -> Self {
         Self {
             cfg,
             local_static_pub,
             remote_node: None,
             info: None,
-            state: State::Handshake1(handshake_state),
+            state,
+            hybrid,
+            kem_key: None,
+            cipher: SessionCipher::default(),
+            resumed: false,
+            ticket: None,
+            sent: 0,
+            received: 0,
+            replay: None,
+            incoming_keys: BTreeMap::new(),
+            incoming_epoch: 0,
+            high_water: None,
             buf: vec![0u8; 65535],
         }
     }
@@ -99,38 +318,113 @@ impl Session {
         data: &[u8],
         mut writer: W,
     ) -> Result<Option<Message>> {
+        // Responders use explicit nonces in case the initiator requests them.
+        let data = match (&self.state, data.strip_prefix(EXPLICIT_NONCE_PREFIX)) {
+            (State::Negotiate(_), Some(data)) => {
+                let size = self.cfg.replay_window.unwrap_or_default();
+                self.replay = Some(ReplayWindow::new(size));
+                data
+            }
+            _ => data,
+        };
+
         // Replace the state with a closed state. In case processing fails for whatever
         // reason, this will cause the session to be torn down.
         match mem::replace(&mut self.state, State::Closed) {
-            State::Handshake1(mut state) => {
-                if state.is_initiator() {
-                    // Initiator only sends in this state.
-                    if !data.is_empty() {
-                        return Err(SessionError::InvalidInput.into());
-                    }
+            State::Negotiate(_) if data.starts_with(RESUMPTION_PREFIX) => {
+                let data = &data[RESUMPTION_PREFIX.len()..];
+                if data.len() < Hash::len() {
+                    return Err(SessionError::InvalidInput.into());
+                }
+                let (id, data) = data.split_at(Hash::len());
+                let ticket = self
+                    .cfg
+                    .tickets
+                    .as_ref()
+                    .ok_or(SessionError::ResumptionNotSupported)?
+                    .take(&Hash::from(id))
+                    .ok_or(SessionError::InvalidTicket)?;
+                let mut state = Builder::resumption_builder(&ticket)?.build_responder()?;
+
+                // <- psk, e
+                state.read_message(data, &mut self.buf)?;
+
+                // -> e, ee
+                let len = state.write_message(&[], &mut self.buf)?;
+                writer.write_all(&self.buf[..len])?;
+
+                self.resume(state, ticket).await?;
+            }
+            State::Negotiate(candidates) => {
+                let (cipher, data) = match data.strip_prefix(CIPHER_PREFIX) {
+                    Some([id, data @ ..]) => (SessionCipher::from_id(*id)?, data),
+                    Some([]) => return Err(SessionError::InvalidInput.into()),
+                    None => (SessionCipher::default(), data),
+                };
+                let (mut state, hybrid) = candidates.select(data, cipher)?;
+                self.hybrid = hybrid;
+                self.cipher = cipher;
+
+                // <- e
+                let len = state.read_message(data, &mut self.buf)?;
 
-                    // -> e
-                    let len = state.write_message(&[], &mut self.buf)?;
-                    writer.write_all(&self.buf[..len])?;
+                // -> e, ee, s, es (preceded by the ML-KEM ciphertext in case of the hybrid key
+                // exchange)
+                let mut payload = if hybrid {
+                    Self::kem_encapsulate(&mut state, &self.buf[..len])?
                 } else {
-                    // <- e
-                    state.read_message(data, &mut self.buf)?;
+                    vec![]
+                };
+                payload.extend(self.get_rak_binding());
+                let len = state.write_message(&payload, &mut self.buf)?;
+                writer.write_all(&self.buf[..len])?;
 
-                    // -> e, ee, s, es
-                    let len = state.write_message(&self.get_rak_binding(), &mut self.buf)?;
-                    writer.write_all(&self.buf[..len])?;
+                self.state = State::Handshake2(state);
+            }
+            State::Handshake1(mut state) => {
+                // Initiator only sends in this state.
+                if !data.is_empty() {
+                    return Err(SessionError::InvalidInput.into());
+                }
+
+                // -> e (with the ML-KEM encapsulation key in case of the hybrid key exchange)
+                let payload = if self.hybrid {
+                    let (dk, ek) = MlKem768::generate(&mut SecureRng);
+                    self.kem_key = Some(dk);
+                    ek.as_bytes().to_vec()
+                } else {
+                    vec![]
+                };
+                let len = state.write_message(&payload, &mut self.buf)?;
+                if self.replay.is_some() {
+                    writer.write_all(EXPLICIT_NONCE_PREFIX)?;
+                }
+                if self.cipher != SessionCipher::default() {
+                    writer.write_all(CIPHER_PREFIX)?;
+                    writer.write_all(&[self.cipher as u8])?;
                 }
+                writer.write_all(&self.buf[..len])?;
 
                 self.state = State::Handshake2(state);
             }
             State::Handshake2(mut state) => {
                 // Process data sent during Handshake1 phase.
                 let len = state.read_message(data, &mut self.buf)?;
+                let offset = match self.kem_key.take() {
+                    Some(dk) => {
+                        let ct = self.buf[..len]
+                            .get(..KEM_CIPHERTEXT_SIZE)
+                            .ok_or(SessionError::InvalidInput)?;
+                        Self::kem_decapsulate(&mut state, &dk, ct)?;
+                        KEM_CIPHERTEXT_SIZE
+                    }
+                    None => 0,
+                };
                 let remote_static = state
                     .get_remote_static()
                     .expect("dh exchange just happened");
                 let auth_info = self
-                    .verify_rak_binding(&self.buf[..len], remote_static)
+                    .verify_rak_binding(&self.buf[offset..len], remote_static)
                     .await;
 
                 if state.is_initiator() {
@@ -142,6 +436,8 @@ impl Session {
                 match auth_info {
                     Ok(auth_info) => {
                         self.info = auth_info;
+                        self.issue_ticket(&mut state);
+                        self.init_replay(&mut state);
                         self.state = State::Transport(state.into_transport_mode()?);
                     }
                     Err(_) if state.is_initiator() => {
@@ -156,9 +452,42 @@ impl Session {
                     }
                 }
             }
+            State::Resume1(mut state, ticket) => {
+                // Initiator only sends in this state.
+                if !data.is_empty() {
+                    return Err(SessionError::InvalidInput.into());
+                }
+
+                // -> psk, e
+                let len = state.write_message(&[], &mut self.buf)?;
+                if self.replay.is_some() {
+                    writer.write_all(EXPLICIT_NONCE_PREFIX)?;
+                }
+                writer.write_all(RESUMPTION_PREFIX)?;
+                writer.write_all(ticket.id.as_ref())?;
+                writer.write_all(&self.buf[..len])?;
+
+                self.state = State::Resume2(state, ticket);
+            }
+            State::Resume2(mut state, ticket) => {
+                // <- e, ee
+                state.read_message(data, &mut self.buf)?;
+
+                self.resume(state, ticket).await?;
+            }
             State::Transport(mut state) => {
-                // TODO: Restore session in case of errors.
-                let len = state.read_message(data, &mut self.buf)?;
+                // Sessions are torn down once the remote attestation expires or is revoked.
+                if let Some(ref info) = self.info {
+                    info.verify_still_valid(insecure_posix_time())?;
+                }
+
+                // TODO: Restore session in case of other errors.
+                let result = self.read_transport(&mut state, data);
+                if result.as_ref().is_err_and(|err| err.is::<ReplayError>()) {
+                    // Drop replayed messages without tearing down the session.
+                    self.state = State::Transport(state);
+                }
+                let len = result?;
                 let msg = cbor::from_slice(&self.buf[..len])?;
 
                 self.state = State::Transport(state);
@@ -185,12 +514,232 @@ impl Session {
             _ => return Err(SessionError::InvalidState.into()),
         };
 
+        let nonce = state.sending_nonce();
         let len = state.write_message(&cbor::to_vec(msg), &mut self.buf)?;
+        if self.replay.is_some() {
+            writer.write_all(&nonce.to_be_bytes())?;
+        }
         writer.write_all(&self.buf[..len])?;
 
+        // Periodically ratchet the keys of long-lived sessions.
+        self.sent += 1;
+        if self.should_ratchet(self.sent) {
+            if let State::Transport(ref mut state) = self.state {
+                state.rekey_outgoing();
+            }
+        }
+
         Ok(())
     }
 
+    /// Decrypt a transport message into the buffer, returning the length of the plaintext.
+    fn read_transport(&mut self, state: &mut snow::TransportState, data: &[u8]) -> Result<usize> {
+        let Some(ref replay) = self.replay else {
+            let len = state.read_message(data, &mut self.buf)?;
+
+            // Periodically ratchet the keys of long-lived sessions.
+            self.received += 1;
+            if self.should_ratchet(self.received) {
+                state.rekey_incoming();
+            }
+            return Ok(len);
+        };
+
+        if data.len() < EXPLICIT_NONCE_SIZE {
+            return Err(SessionError::InvalidInput.into());
+        }
+        let (nonce, data) = data.split_at(EXPLICIT_NONCE_SIZE);
+        let nonce = u64::from_be_bytes(nonce.try_into().unwrap());
+        replay.check(nonce)?;
+
+        // Load the keys of the epoch of the message. Keys of later epochs are only retained once
+        // a message encrypted with them has been authenticated, so that forged nonces can't
+        // advance the ratchet.
+        let interval = self.cfg.ratchet_interval;
+        let mut ratcheted = vec![];
+        if interval > 0 {
+            let epoch = nonce / interval;
+            ratcheted = self.ratchet_incoming_keys(epoch, nonce)?;
+            if epoch != self.incoming_epoch {
+                let key = match ratcheted.last() {
+                    Some((_, key)) => key,
+                    None => &self.incoming_keys[&epoch],
+                };
+                if state.is_initiator() {
+                    state.rekey_manually(None, Some(key.as_ref()));
+                } else {
+                    state.rekey_manually(Some(key.as_ref()), None);
+                }
+                self.incoming_epoch = epoch;
+            }
+        }
+
+        state.set_receiving_nonce(nonce);
+        let len = state.read_message(data, &mut self.buf)?;
+        let replay = self
+            .replay
+            .as_mut()
+            .expect("explicit nonces require a window");
+        replay.accept(nonce)?;
+        self.received += 1;
+
+        if interval > 0 {
+            // Forget the keys of epochs which are no longer in the window.
+            self.incoming_keys.extend(ratcheted);
+            let oldest = replay.next().saturating_sub(replay.size()) / interval;
+            self.incoming_keys = self.incoming_keys.split_off(&oldest);
+        }
+        if let (Some((id, high_water)), Some(store)) =
+            (&mut self.high_water, &self.cfg.replay_store)
+        {
+            if replay.next() > *high_water {
+                *high_water = replay.next() + HIGH_WATER_RESERVE;
+                store.store(id, *high_water);
+            }
+        }
+
+        Ok(len)
+    }
+
+    /// Derive the incoming keys of the epochs following the latest retained epoch up to the
+    /// given epoch, in case its keys are not already retained.
+    fn ratchet_incoming_keys(&self, epoch: u64, nonce: u64) -> Result<Vec<(u64, TransportKey)>> {
+        if self.incoming_keys.contains_key(&epoch) {
+            return Ok(vec![]);
+        }
+        let (&latest, key) = self
+            .incoming_keys
+            .last_key_value()
+            .ok_or(SessionError::InvalidState)?;
+        if epoch < latest {
+            // Keys of epochs which are no longer in the window have already been forgotten.
+            return Err(ReplayError::TooOld(nonce).into());
+        }
+        if epoch - latest > MAX_RATCHET_STEPS {
+            return Err(SessionError::InvalidInput.into());
+        }
+
+        let cipher = match (self.resumed, self.cipher) {
+            (false, SessionCipher::AesGcm) => CipherChoice::AESGCM,
+            _ => CipherChoice::ChaChaPoly,
+        };
+        let mut keys: Vec<(u64, TransportKey)> = vec![];
+        let mut key = key.clone();
+        for epoch in latest + 1..=epoch {
+            key = ratchet_key(cipher, &key)?;
+            keys.push((epoch, key.clone()));
+        }
+        Ok(keys)
+    }
+
+    /// Initialize the anti-replay window of a session with explicit nonces from the completed
+    /// handshake, retaining the incoming key of the first epoch and restoring the persisted
+    /// high-water mark of the window, if any.
+    fn init_replay(&mut self, state: &mut snow::HandshakeState) {
+        let Some(ref mut replay) = self.replay else {
+            return;
+        };
+
+        if self.cfg.ratchet_interval > 0 {
+            let (initiator_key, responder_key) = state.dangerously_get_raw_split();
+            let key = match state.is_initiator() {
+                true => responder_key,
+                false => initiator_key,
+            };
+            self.incoming_keys.insert(0, Zeroizing::new(key));
+        }
+
+        if let Some(ref store) = self.cfg.replay_store {
+            let id =
+                Hash::digest_bytes_list(&[REPLAY_WINDOW_ID_CONTEXT, state.get_handshake_hash()]);
+            let high_water = store.load(&id).unwrap_or_default();
+            replay.restore(high_water);
+            self.high_water = Some((id, high_water));
+        }
+    }
+
+    /// Complete a resumption handshake, restoring the remote peer information of the resumed
+    /// session and transitioning into transport mode.
+    ///
+    /// Responders verify the remote attestation of the original session against the current
+    /// quote policy and, if enabled, the consensus layer registry again, as either may have
+    /// changed since the ticket was issued.
+    async fn resume(
+        &mut self,
+        mut state: snow::HandshakeState,
+        ticket: SessionTicket,
+    ) -> Result<()> {
+        // Make sure that the ticket has not expired while the handshake was in progress.
+        if !ticket.is_valid(insecure_posix_time()) {
+            return Err(SessionError::InvalidTicket.into());
+        }
+        if !state.is_initiator() {
+            self.verify_resumed(ticket.info.as_deref()).await?;
+        }
+
+        self.info = ticket.info.clone();
+        self.hybrid = ticket.hybrid;
+        self.resumed = true;
+        self.issue_ticket(&mut state);
+        self.init_replay(&mut state);
+        self.state = State::Transport(state.into_transport_mode()?);
+        Ok(())
+    }
+
+    /// Derive a ticket for resuming the session from the completed handshake.
+    ///
+    /// Responders store the ticket in the configured ticket store, while initiators keep it so
+    /// that it can be used to resume the session later.
+    fn issue_ticket(&mut self, state: &mut snow::HandshakeState) {
+        let now = insecure_posix_time();
+        let (send, recv) = state.dangerously_get_raw_split();
+        let mut transport_keys = [send, recv].concat();
+        let handshake_hash = state.get_handshake_hash().to_vec();
+        if state.is_initiator() {
+            if self.cfg.resumption_ttl > 0 {
+                self.ticket = Some(SessionTicket::derive(
+                    &handshake_hash,
+                    &transport_keys,
+                    self.info.clone(),
+                    self.remote_node,
+                    self.hybrid,
+                    now + self.cfg.resumption_ttl,
+                ));
+            }
+        } else if let Some(ref tickets) = self.cfg.tickets {
+            tickets.insert(SessionTicket::derive(
+                &handshake_hash,
+                &transport_keys,
+                self.info.clone(),
+                None,
+                self.hybrid,
+                now + tickets.ttl(),
+            ));
+        }
+        transport_keys.zeroize();
+    }
+
+    /// Whether the keys should be ratcheted after the given number of messages.
+    fn should_ratchet(&self, messages: u64) -> bool {
+        self.cfg.ratchet_interval > 0 && messages % self.cfg.ratchet_interval == 0
+    }
+
+    /// Take the ticket for resuming the session, if one has been derived.
+    pub fn take_ticket(&mut self) -> Option<SessionTicket> {
+        self.ticket.take()
+    }
+
+    /// Whether transport messages of the session carry explicit nonces, allowing them to be
+    /// received out of order within the anti-replay window.
+    pub fn has_explicit_nonces(&self) -> bool {
+        self.replay.is_some()
+    }
+
+    /// Whether the session has been resumed using a session ticket.
+    pub fn is_resumed(&self) -> bool {
+        self.resumed
+    }
+
     /// Mark the session as closed.
     ///
     /// After the session is closed it can no longer be used to transmit
@@ -199,6 +748,33 @@ impl Session {
         self.state = State::Closed;
     }
 
+    /// Encapsulate a shared secret to the given ML-KEM encapsulation key of the initiator and use
+    /// it as the pre-shared key of the handshake, returning the ciphertext.
+    fn kem_encapsulate(state: &mut snow::HandshakeState, ek: &[u8]) -> Result<Vec<u8>> {
+        let ek =
+            Encoded::<KemEncapsulationKey>::try_from(ek).map_err(|_| SessionError::InvalidInput)?;
+        let (ct, ss) = KemEncapsulationKey::from_bytes(&ek)
+            .encapsulate(&mut SecureRng)
+            .map_err(|_| SessionError::InvalidInput)?;
+        state.set_psk(KEM_PSK_LOCATION, &ss)?;
+        Ok(ct.to_vec())
+    }
+
+    /// Decapsulate the shared secret from the given ML-KEM ciphertext of the responder and use it
+    /// as the pre-shared key of the handshake.
+    fn kem_decapsulate(
+        state: &mut snow::HandshakeState,
+        dk: &KemDecapsulationKey,
+        ct: &[u8],
+    ) -> Result<()> {
+        let ct = Ciphertext::<MlKem768>::try_from(ct).map_err(|_| SessionError::InvalidInput)?;
+        let ss = dk
+            .decapsulate(&ct)
+            .map_err(|_| SessionError::InvalidInput)?;
+        state.set_psk(KEM_PSK_LOCATION, &ss)?;
+        Ok(())
+    }
+
     fn get_rak_binding(&self) -> Vec<u8> {
         match self.cfg.identity {
             Some(ref identity) => {
@@ -254,19 +830,59 @@ impl Session {
         let rak_binding: RAKBinding = cbor::from_slice(rak_binding)?;
         let vect = rak_binding.verify(remote_static, &self.cfg.remote_enclaves, policy)?;
 
+        // Reject enclaves that have been revoked by the key manager policy.
+        if RevocationList::global().is_revoked(&vect.verified_attestation.quote.identity) {
+            return Err(SessionError::EnclaveRevoked.into());
+        }
+
         // Verify node identity if verification is enabled.
-        if self.cfg.consensus_verifier.is_some() {
+        let (runtime_id, node_id) = if self.cfg.consensus_verifier.is_some() {
             let rak = rak_binding.rak_pub();
             self.verify_node_identity(rak).await?;
-        }
+            (self.cfg.remote_runtime_id, self.remote_node)
+        } else {
+            (None, None)
+        };
 
         Ok(Some(Arc::new(SessionInfo {
             rak_binding,
             verified_attestation: vect.verified_attestation,
             endorsed_by: vect.node_id,
+            runtime_id,
+            node_id,
         })))
     }
 
+    /// Verify that the remote peer of a resumed session is still acceptable.
+    async fn verify_resumed(&self, info: Option<&SessionInfo>) -> Result<()> {
+        let info = match info {
+            Some(info) => info,
+            None if self.cfg.remote_enclaves.is_some() => {
+                return Err(SessionError::MismatchedEnclaveIdentity.into());
+            }
+            None => return Ok(()),
+        };
+
+        let policy = self
+            .cfg
+            .policy
+            .as_ref()
+            .ok_or(SessionError::MissingQuotePolicy)?;
+        let vect = info.rak_binding.verify_inner(policy)?;
+        if let Some(ref remote_enclaves) = self.cfg.remote_enclaves {
+            if !remote_enclaves.contains(&vect.verified_attestation.quote.identity) {
+                return Err(SessionError::MismatchedEnclaveIdentity.into());
+            }
+        }
+        info.verify_still_valid(insecure_posix_time())?;
+
+        if self.cfg.consensus_verifier.is_some() {
+            self.verify_node_identity(info.rak_binding.rak_pub())
+                .await?;
+        }
+        Ok(())
+    }
+
     /// Session information.
     pub fn session_info(&self) -> Option<Arc<SessionInfo>> {
         self.info.clone()
@@ -283,6 +899,20 @@ impl Session {
         nodes.iter().any(|&node| Some(node) == self.remote_node)
     }
 
+    /// Whether the session uses the hybrid post-quantum key exchange.
+    ///
+    /// For responders this is only known after the initial handshake message has been processed.
+    pub fn is_post_quantum(&self) -> bool {
+        self.hybrid
+    }
+
+    /// Cipher used to protect session messages.
+    ///
+    /// For responders this is only known after the initial handshake message has been processed.
+    pub fn cipher(&self) -> SessionCipher {
+        self.cipher
+    }
+
     /// Whether the session is in closed state.
     pub fn is_closed(&self) -> bool {
         matches!(self.state, State::Closed)
@@ -346,6 +976,27 @@ impl Session {
     }
 }
 
+/// Ratchet the given transport key to the key of the next epoch, using the `REKEY` function of
+/// the Noise protocol framework as done by `snow::TransportState::rekey_incoming`.
+fn ratchet_key(cipher: CipherChoice, key: &TransportKey) -> Result<TransportKey> {
+    let mut cipher = DefaultResolver
+        .resolve_cipher(&cipher)
+        .ok_or(SessionError::InvalidState)?;
+    cipher.set(key.as_ref());
+
+    let mut ciphertext = Zeroizing::new([0u8; TRANSPORT_KEY_SIZE + TRANSPORT_TAG_SIZE]);
+    cipher.encrypt(
+        u64::MAX,
+        &[],
+        &[0u8; TRANSPORT_KEY_SIZE],
+        ciphertext.as_mut(),
+    );
+    let mut next = Zeroizing::new([0u8; TRANSPORT_KEY_SIZE]);
+    next.copy_from_slice(&ciphertext[..TRANSPORT_KEY_SIZE]);
+
+    Ok(next)
+}
+
 /// Binding of the session's static public key to a remote attestation
 /// verification report through the use of the remote attestation key.
 ///
@@ -449,6 +1100,13 @@ struct Config {
     remote_runtime_id: Option<Namespace>,
     use_endorsement: bool,
     policy: Option<Arc<QuotePolicy>>,
+    post_quantum_policy: PostQuantumPolicy,
+    cipher: SessionCipher,
+    tickets: Option<Arc<TicketStore>>,
+    resumption_ttl: i64,
+    ratchet_interval: u64,
+    replay_window: Option<u64>,
+    replay_store: Option<Arc<dyn HighWaterStore>>,
 }
 
 /// Session builder.
@@ -514,31 +1172,370 @@ impl Builder {
         self
     }
 
-    fn build<'a>(self) -> (snow::Builder<'a>, snow::Keypair, Config) {
-        let noise_builder = snow::Builder::new(NOISE_PATTERN.parse().unwrap());
-        let keypair = noise_builder.generate_keypair().unwrap();
-        let cfg = self.cfg;
+    /// Return the post-quantum key exchange policy configured in the builder.
+    pub fn get_post_quantum_policy(&self) -> PostQuantumPolicy {
+        self.cfg.post_quantum_policy
+    }
+
+    /// Configure the policy for using the hybrid post-quantum key exchange.
+    pub fn post_quantum_policy(mut self, policy: PostQuantumPolicy) -> Self {
+        self.cfg.post_quantum_policy = policy;
+        self
+    }
+
+    /// Configure the cipher used by initiated sessions. Responders accept all ciphers.
+    pub fn cipher(mut self, cipher: SessionCipher) -> Self {
+        self.cfg.cipher = cipher;
+        self
+    }
+
+    /// Enable resumption of responder sessions using tickets from the given store.
+    pub fn ticket_store(mut self, tickets: Option<Arc<TicketStore>>) -> Self {
+        self.cfg.tickets = tickets;
+        self
+    }
+
+    /// Enable resumption of initiator sessions, retaining session tickets for the given number
+    /// of seconds. A zero value disables resumption.
+    pub fn resumption_ttl(mut self, ttl: i64) -> Self {
+        self.cfg.resumption_ttl = ttl;
+        self
+    }
+
+    /// Ratchet session keys after every `interval` messages sent in each direction. A zero
+    /// value disables ratcheting.
+    ///
+    /// Both sides of a session must be configured with the same interval.
+    pub fn ratchet_interval(mut self, interval: u64) -> Self {
+        self.cfg.ratchet_interval = interval;
+        self
+    }
+
+    /// Configure the anti-replay window of sessions.
+    ///
+    /// In case a window size is set, initiated sessions use explicit nonces for transport
+    /// messages, so that messages are accepted out of order as long as they are within the given
+    /// number of messages behind the latest one received. Responders use explicit nonces when
+    /// the initiator requests them and otherwise require messages to be received in order.
+    pub fn replay_window(mut self, size: Option<u64>) -> Self {
+        self.cfg.replay_window = size;
+        self
+    }
+
+    /// Persist the high-water marks of the anti-replay windows of sessions with explicit nonces
+    /// in the given store, so that messages below them are rejected once restored.
+    pub fn replay_store(mut self, store: Option<Arc<dyn HighWaterStore>>) -> Self {
+        self.cfg.replay_store = store;
+        self
+    }
 
-        (noise_builder, keypair, cfg)
+    fn resumption_builder(ticket: &SessionTicket) -> Result<snow::Builder<'_>> {
+        Ok(snow::Builder::new(NOISE_PATTERN_RESUME.parse()?).psk(0, ticket.psk.as_ref()))
+    }
+
+    fn noise_builder<'a>(hybrid: bool, cipher: SessionCipher) -> Result<snow::Builder<'a>> {
+        let pattern = match (hybrid, cipher) {
+            (false, SessionCipher::ChaChaPoly) => NOISE_PATTERN,
+            (true, SessionCipher::ChaChaPoly) => NOISE_PATTERN_HYBRID,
+            (false, SessionCipher::AesGcm) => NOISE_PATTERN_AES_GCM,
+            (true, SessionCipher::AesGcm) => NOISE_PATTERN_HYBRID_AES_GCM,
+        };
+        Ok(snow::Builder::new(pattern.parse()?))
     }
 
     /// Build initiator session.
-    pub fn build_initiator(self) -> Session {
-        let (builder, keypair, cfg) = self.build();
-        let session = builder
+    pub fn build_initiator(self) -> Result<Session> {
+        let hybrid = self.cfg.post_quantum_policy.initiate_hybrid();
+        let cipher = self.cfg.cipher;
+        let builder = Self::noise_builder(hybrid, cipher)?;
+        let keypair = builder.generate_keypair()?;
+        let state = builder
             .local_private_key(&keypair.private)
-            .build_initiator()
-            .unwrap();
-        Session::new(session, keypair.public, cfg)
+            .build_initiator()?;
+        let replay = self.cfg.replay_window.map(ReplayWindow::new);
+        let mut session = Session::new(State::Handshake1(state), hybrid, keypair.public, self.cfg);
+        session.cipher = cipher;
+        session.replay = replay;
+        Ok(session)
+    }
+
+    /// Build initiator session resuming a previous session using the given ticket.
+    pub fn build_resuming_initiator(self, ticket: SessionTicket) -> Result<Session> {
+        let state = Self::resumption_builder(&ticket)?.build_initiator()?;
+        let remote_node = ticket.remote_node;
+        let replay = self.cfg.replay_window.map(ReplayWindow::new);
+        let mut session = Session::new(State::Resume1(state, ticket), false, vec![], self.cfg);
+        session.remote_node = remote_node;
+        session.replay = replay;
+        Ok(session)
     }
 
     /// Build responder session.
-    pub fn build_responder(self) -> Session {
-        let (builder, keypair, cfg) = self.build();
-        let session = builder
-            .local_private_key(&keypair.private)
+    pub fn build_responder(self) -> Result<Session> {
+        let policy = self.cfg.post_quantum_policy;
+        let keypair = Self::noise_builder(false, SessionCipher::default())?.generate_keypair()?;
+        let candidates = Candidates {
+            private_key: keypair.private,
+            classic: policy.accept_classic(),
+            hybrid: policy.accept_hybrid(),
+        };
+        Ok(Session::new(
+            State::Negotiate(candidates),
+            false,
+            keypair.public,
+            self.cfg,
+        ))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{collections::HashMap, sync::Mutex};
+
+    use super::{super::resumption::DEFAULT_MAX_TICKETS, *};
+
+    /// Run a full handshake between the given sessions.
+    fn handshake(initiator: &mut Session, responder: &mut Session) -> Result<()> {
+        let rt = tokio::runtime::Builder::new_current_thread()
+            .build()
+            .unwrap();
+        rt.block_on(async {
+            let mut msg1 = Vec::new();
+            initiator.process_data(&[], &mut msg1).await?;
+            let mut msg2 = Vec::new();
+            responder.process_data(&msg1, &mut msg2).await?;
+            let mut msg3 = Vec::new();
+            initiator.process_data(&msg2, &mut msg3).await?;
+            responder.process_data(&msg3, &mut Vec::new()).await?;
+            Ok(())
+        })
+    }
+
+    #[test]
+    fn test_post_quantum_negotiation() {
+        use PostQuantumPolicy::*;
+
+        let tcs = [
+            (Disabled, Disabled, Some(false)),
+            (Disabled, Accept, Some(false)),
+            (Disabled, Required, None),
+            (Enabled, Disabled, None),
+            (Enabled, Accept, Some(true)),
+            (Required, Required, Some(true)),
+        ];
+        for (initiator_policy, responder_policy, expected) in tcs {
+            let mut initiator = Builder::default()
+                .post_quantum_policy(initiator_policy)
+                .build_initiator()
+                .unwrap();
+            let mut responder = Builder::default()
+                .post_quantum_policy(responder_policy)
+                .build_responder()
+                .unwrap();
+
+            let result = handshake(&mut initiator, &mut responder);
+            match expected {
+                Some(hybrid) => {
+                    assert!(
+                        result.is_ok(),
+                        "{:?} {:?}",
+                        initiator_policy,
+                        responder_policy
+                    );
+                    assert!(initiator.is_connected() && responder.is_connected());
+                    assert_eq!(initiator.is_post_quantum(), hybrid);
+                    assert_eq!(responder.is_post_quantum(), hybrid);
+                }
+                None => {
+                    assert!(
+                        result.is_err(),
+                        "{:?} {:?}",
+                        initiator_policy,
+                        responder_policy
+                    );
+                    assert!(!responder.is_connected());
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_cipher_negotiation() {
+        for cipher in [SessionCipher::ChaChaPoly, SessionCipher::AesGcm] {
+            for policy in [PostQuantumPolicy::Disabled, PostQuantumPolicy::Required] {
+                let mut initiator = Builder::default()
+                    .cipher(cipher)
+                    .post_quantum_policy(policy)
+                    .build_initiator()
+                    .unwrap();
+                let mut responder = Builder::default()
+                    .post_quantum_policy(policy)
+                    .build_responder()
+                    .unwrap();
+
+                handshake(&mut initiator, &mut responder).unwrap();
+                assert!(initiator.is_connected() && responder.is_connected());
+                assert_eq!(initiator.cipher(), cipher);
+                assert_eq!(responder.cipher(), cipher);
+            }
+        }
+
+        // Unknown ciphers are rejected.
+        let mut responder = Builder::default().build_responder().unwrap();
+        let rt = tokio::runtime::Builder::new_current_thread()
+            .build()
+            .unwrap();
+        let msg = [CIPHER_PREFIX, &[0xff], &[0; CLASSIC_INITIAL_MESSAGE_LEN]].concat();
+        let result = rt.block_on(responder.process_data(&msg, &mut Vec::new()));
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn test_resumption() {
+        let rt = tokio::runtime::Builder::new_current_thread()
+            .build()
+            .unwrap();
+        let tickets = Arc::new(TicketStore::new(60, DEFAULT_MAX_TICKETS));
+        let initiator_builder = Builder::default().resumption_ttl(60).ratchet_interval(2);
+        let responder_builder = Builder::default()
+            .ticket_store(Some(tickets.clone()))
+            .ratchet_interval(2);
+
+        let mut initiator = initiator_builder.clone().build_initiator().unwrap();
+        let mut responder = responder_builder.clone().build_responder().unwrap();
+        handshake(&mut initiator, &mut responder).unwrap();
+        assert!(!initiator.is_resumed() && !responder.is_resumed());
+        assert_eq!(tickets.len(), 1);
+        let ticket = initiator.take_ticket().expect("ticket should be issued");
+
+        // Resume the session.
+        let resume = |ticket: SessionTicket| {
+            let mut initiator = initiator_builder.clone().build_resuming_initiator(ticket)?;
+            let mut responder = responder_builder.clone().build_responder().unwrap();
+            rt.block_on(async {
+                let mut msg1 = Vec::new();
+                initiator.process_data(&[], &mut msg1).await?;
+                let mut msg2 = Vec::new();
+                responder.process_data(&msg1, &mut msg2).await?;
+                initiator.process_data(&msg2, &mut Vec::new()).await?;
+                Ok::<_, anyhow::Error>((initiator, responder))
+            })
+        };
+        let (mut initiator, mut responder) = resume(ticket.clone()).unwrap();
+        assert!(initiator.is_connected() && responder.is_connected());
+        assert!(initiator.is_resumed() && responder.is_resumed());
+
+        // Messages can be exchanged across key ratchets.
+        for _ in 0..5 {
+            let mut msg = Vec::new();
+            initiator.write_message(Message::Close, &mut msg).unwrap();
+            let received = rt.block_on(responder.process_data(&msg, &mut Vec::new()));
+            assert!(matches!(received, Ok(Some(Message::Close))));
+        }
+
+        // Tickets can only be used once, but resumed sessions issue new tickets.
+        assert!(resume(ticket).is_err());
+        let ticket = initiator.take_ticket().expect("ticket should be issued");
+        assert!(resume(ticket).is_ok());
+    }
+
+    #[test]
+    fn test_replay_window() {
+        let rt = tokio::runtime::Builder::new_current_thread()
+            .build()
+            .unwrap();
+        let mut initiator = Builder::default()
+            .replay_window(Some(4))
+            .ratchet_interval(4)
+            .build_initiator()
+            .unwrap();
+        let store = Arc::new(MemoryHighWaterStore::default());
+        let mut responder = Builder::default()
+            .replay_window(Some(4))
+            .ratchet_interval(4)
+            .replay_store(Some(store.clone()))
+            .build_responder()
+            .unwrap();
+        handshake(&mut initiator, &mut responder).unwrap();
+        assert!(initiator.has_explicit_nonces() && responder.has_explicit_nonces());
+
+        let msgs: Vec<Vec<u8>> = (0..10)
+            .map(|_| {
+                let mut msg = Vec::new();
+                initiator.write_message(Message::Close, &mut msg).unwrap();
+                msg
+            })
+            .collect();
+        let mut receive = |msg: &[u8]| rt.block_on(responder.process_data(msg, &mut Vec::new()));
+
+        // Messages are accepted out of order.
+        for i in [1, 0, 3, 2] {
+            assert!(matches!(receive(&msgs[i]), Ok(Some(Message::Close))));
+        }
+
+        // Replayed messages are rejected without tearing down the session, also once the keys
+        // have been ratcheted.
+        assert!(receive(&msgs[2]).is_err());
+        assert!(matches!(receive(&msgs[5]), Ok(Some(Message::Close))));
+        assert!(receive(&msgs[3]).is_err());
+        assert!(matches!(receive(&msgs[4]), Ok(Some(Message::Close))));
+        assert!(matches!(receive(&msgs[9]), Ok(Some(Message::Close))));
+
+        // Messages of earlier epochs are accepted while within the window.
+        assert!(matches!(receive(&msgs[7]), Ok(Some(Message::Close))));
+        assert!(receive(&msgs[7]).is_err());
+
+        // Forged messages don't advance the ratchet.
+        let mut forged = msgs[8].clone();
+        forged[..EXPLICIT_NONCE_SIZE].copy_from_slice(&20u64.to_be_bytes());
+        assert!(receive(&forged).is_err());
+        assert_eq!(responder.incoming_keys.keys().copied().max(), Some(2));
+
+        // High-water marks are persisted ahead of the received messages.
+        assert_eq!(
+            store
+                .marks
+                .lock()
+                .unwrap()
+                .values()
+                .copied()
+                .collect::<Vec<_>>(),
+            vec![2 + HIGH_WATER_RESERVE]
+        );
+
+        // Sessions without explicit nonces require messages to be received in order.
+        let mut initiator = Builder::default().build_initiator().unwrap();
+        let mut responder = Builder::default()
+            .replay_window(Some(4))
             .build_responder()
             .unwrap();
-        Session::new(session, keypair.public, cfg)
+        handshake(&mut initiator, &mut responder).unwrap();
+        assert!(!initiator.has_explicit_nonces() && !responder.has_explicit_nonces());
+        let msgs: Vec<Vec<u8>> = (0..2)
+            .map(|_| {
+                let mut msg = Vec::new();
+                initiator.write_message(Message::Close, &mut msg).unwrap();
+                msg
+            })
+            .collect();
+        assert!(rt
+            .block_on(responder.process_data(&msgs[1], &mut Vec::new()))
+            .is_err());
+        assert!(!responder.is_connected());
+    }
+
+    #[derive(Default)]
+    struct MemoryHighWaterStore {
+        marks: Mutex<HashMap<Hash, u64>>,
+    }
+
+    impl HighWaterStore for MemoryHighWaterStore {
+        fn load(&self, id: &Hash) -> Option<u64> {
+            self.marks.lock().unwrap().get(id).copied()
+        }
+
+        fn store(&self, id: &Hash, high_water: u64) {
+            self.marks.lock().unwrap().insert(*id, high_water);
+        }
     }
 }
@@ -8,15 +8,16 @@ use std::{
 };
 
 use anyhow::Result;
-use rand::{rngs::OsRng, Rng};
+use rand::Rng;
 use tokio::sync::OwnedMutexGuard;
 
 use super::{
+    resumption::SessionTicket,
     session::{Builder, Session, SessionInfo},
     types::{Message, SessionID},
 };
 use crate::common::{
-    crypto::signature,
+    crypto::{rng::SecureRng, signature},
     namespace::Namespace,
     sgx::{EnclaveIdentity, QuotePolicy},
     time::insecure_posix_time,
@@ -33,6 +34,21 @@ pub type SessionByTimeKey<PeerID> = (i64, PeerID, SessionID);
 pub enum Error {
     #[error("max concurrent sessions reached")]
     MaxConcurrentSessions,
+    #[error("max concurrent sessions per peer reached")]
+    MaxSessionsPerPeer,
+    #[error("max concurrent sessions per enclave identity reached")]
+    MaxSessionsPerEnclave,
+}
+
+/// Number of sessions rejected due to each of the session limits.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Rejections {
+    /// Sessions rejected due to the global session limit.
+    pub max_sessions: u64,
+    /// Sessions rejected due to the per-peer session limit.
+    pub max_sessions_per_peer: u64,
+    /// Sessions rejected due to the per-enclave identity session limit.
+    pub max_sessions_per_enclave: u64,
 }
 
 /// A multiplexed session.
@@ -107,6 +123,16 @@ impl<PeerID> MultiplexedSession<PeerID> {
         self.inner.is_unauthenticated()
     }
 
+    /// Take the ticket for resuming the session, if one has been derived.
+    pub fn take_ticket(&mut self) -> Option<SessionTicket> {
+        self.inner.take_ticket()
+    }
+
+    /// Whether the session has been resumed using a session ticket.
+    pub fn is_resumed(&self) -> bool {
+        self.inner.is_resumed()
+    }
+
     /// Mark the session as closed.
     ///
     /// After the session is closed it can no longer be used to transmit
@@ -124,6 +150,8 @@ pub struct SessionMeta<PeerID: Clone + Ord + Hash> {
     session_id: SessionID,
     /// Timestamp when the session was last accessed.
     last_access_time: i64,
+    /// Remote enclave identity, once the session has been authenticated.
+    enclave: Option<EnclaveIdentity>,
     /// The shared session pointer that needs to be locked for access.
     inner: SharedSession<PeerID>,
 }
@@ -146,6 +174,8 @@ pub struct Sessions<PeerID: Clone + Ord + Hash> {
     max_sessions: usize,
     /// Maximum number of sessions per peer.
     max_sessions_per_peer: usize,
+    /// Maximum number of sessions per remote enclave identity.
+    max_sessions_per_enclave: usize,
     /// Stale session timeout (in seconds).
     stale_session_timeout: i64,
 
@@ -153,6 +183,10 @@ pub struct Sessions<PeerID: Clone + Ord + Hash> {
     by_peer: HashMap<PeerID, HashMap<SessionID, SessionMeta<PeerID>>>,
     /// A set of all sessions, ordered by idle time.
     by_idle_time: BTreeSet<SessionByTimeKey<PeerID>>,
+    /// Number of authenticated sessions for each remote enclave identity.
+    by_enclave: HashMap<EnclaveIdentity, usize>,
+    /// Number of rejected sessions.
+    rejections: Rejections,
 }
 
 impl<PeerID> Sessions<PeerID>
@@ -170,9 +204,12 @@ where
             builder,
             max_sessions,
             max_sessions_per_peer,
+            max_sessions_per_enclave: usize::MAX,
             stale_session_timeout,
             by_peer: HashMap::new(),
             by_idle_time: BTreeSet::new(),
+            by_enclave: HashMap::new(),
+            rejections: Rejections::default(),
         }
     }
 
@@ -181,6 +218,18 @@ where
         self.builder = builder;
     }
 
+    /// Set the maximum number of authenticated sessions per remote enclave identity.
+    ///
+    /// By default, the number of sessions per enclave identity is not limited.
+    pub fn set_max_sessions_per_enclave(&mut self, max_sessions_per_enclave: usize) {
+        self.max_sessions_per_enclave = max_sessions_per_enclave;
+    }
+
+    /// Number of sessions rejected due to each of the session limits.
+    pub fn rejections(&self) -> Rejections {
+        self.rejections
+    }
+
     /// Update remote enclave identity verification in the session builder
     /// and clear all sessions if the identity has changed.
     pub fn update_enclaves(
@@ -223,7 +272,7 @@ where
         &mut self,
         peer_id: PeerID,
         session_id: SessionID,
-    ) -> MultiplexedSession<PeerID> {
+    ) -> Result<MultiplexedSession<PeerID>> {
         // If no quote policy is set, use the local one.
         if self.builder.get_quote_policy().is_none() {
             let policy = self
@@ -235,22 +284,38 @@ where
             self.builder = mem::take(&mut self.builder).quote_policy(policy);
         }
 
-        MultiplexedSession {
+        Ok(MultiplexedSession {
             peer_id: peer_id.clone(),
             session_id,
-            inner: self.builder.clone().build_responder(),
-        }
+            inner: self.builder.clone().build_responder()?,
+        })
     }
 
     /// Create a new multiplexed initiator session.
-    pub fn create_initiator(&self, peer_id: PeerID) -> MultiplexedSession<PeerID> {
+    pub fn create_initiator(&self, peer_id: PeerID) -> Result<MultiplexedSession<PeerID>> {
         let session_id = SessionID::random();
 
-        MultiplexedSession {
+        Ok(MultiplexedSession {
             peer_id: peer_id.clone(),
             session_id,
-            inner: self.builder.clone().build_initiator(),
-        }
+            inner: self.builder.clone().build_initiator()?,
+        })
+    }
+
+    /// Create a new multiplexed initiator session resuming a previous session using the given
+    /// ticket.
+    pub fn create_resuming_initiator(
+        &self,
+        peer_id: PeerID,
+        ticket: SessionTicket,
+    ) -> Result<MultiplexedSession<PeerID>> {
+        let session_id = SessionID::random();
+
+        Ok(MultiplexedSession {
+            peer_id,
+            session_id,
+            inner: self.builder.clone().build_resuming_initiator(ticket)?,
+        })
     }
 
     /// Fetch an existing session given its identifier.
@@ -301,7 +366,7 @@ where
         }
 
         // If all sessions are in use, return a random one.
-        let n = OsRng.gen_range(0..self.by_idle_time.len());
+        let n = SecureRng.gen_range(0..self.by_idle_time.len());
         let (_, peer_id, session_id) = self.by_idle_time.iter().nth(n).unwrap();
         let session = self
             .by_peer
@@ -343,7 +408,7 @@ where
         }
 
         // If all sessions are in use, return a random one.
-        let n = OsRng.gen_range(0..all_sessions.len());
+        let n = SecureRng.gen_range(0..all_sessions.len());
         let (peer_id, session_id) = all_sessions.get(n).unwrap();
         let session = self
             .by_peer
@@ -384,14 +449,17 @@ where
 
         // Check if the peer has max sessions or if no more sessions are available globally.
         // If so, remove the oldest or return an error.
-        if sessions.len() < self.max_sessions_per_peer
-            && self.by_idle_time.len() < self.max_sessions
-        {
+        let peer_limit_reached = sessions.len() >= self.max_sessions_per_peer;
+        if !peer_limit_reached && self.by_idle_time.len() < self.max_sessions {
             return Ok(None);
         }
+        let err = match peer_limit_reached {
+            true => Error::MaxSessionsPerPeer,
+            false => Error::MaxConcurrentSessions,
+        };
 
         // Force close the oldest idle session.
-        let remove_session = sessions
+        let remove_session = match sessions
             .iter()
             .min_by_key(|(_, s)| {
                 if let Ok(_inner) = s.inner.try_lock() {
@@ -401,11 +469,14 @@ where
                 }
             })
             .map(|(_, s)| s.inner.clone())
-            .ok_or(Error::MaxConcurrentSessions)?;
+        {
+            Some(session) => session,
+            None => return Err(self.reject(err)),
+        };
 
         let session = match remove_session.try_lock_owned() {
             Ok(inner) => inner,
-            Err(_) => return Err(Error::MaxConcurrentSessions), // All sessions are in use.
+            Err(_) => return Err(self.reject(err)), // All sessions are in use.
         };
 
         self.remove(&session);
@@ -430,7 +501,7 @@ where
         for (last_process_frame_time, peer_id, session_id) in self.by_idle_time.iter() {
             if now.saturating_sub(*last_process_frame_time) < self.stale_session_timeout {
                 // This is the oldest session, all next ones will be more fresh.
-                return Err(Error::MaxConcurrentSessions);
+                break;
             }
 
             // Fetch session and attempt to lock it.
@@ -447,7 +518,8 @@ where
         // Check if we found a session that can be removed.
         let session = match remove_session {
             Some(session) => session,
-            None => return Err(Error::MaxConcurrentSessions), // All stale sessions are in use.
+            // No sessions are stale or all stale sessions are in use.
+            None => return Err(self.reject(Error::MaxConcurrentSessions)),
         };
 
         self.remove(&session);
@@ -462,12 +534,12 @@ where
         now: i64,
     ) -> Result<SharedSession<PeerID>, Error> {
         if self.by_idle_time.len() >= self.max_sessions {
-            return Err(Error::MaxConcurrentSessions);
+            return Err(self.reject(Error::MaxConcurrentSessions));
         }
 
         let sessions = self.by_peer.entry(session.peer_id.clone()).or_default();
         if sessions.len() >= self.max_sessions_per_peer {
-            return Err(Error::MaxConcurrentSessions);
+            return Err(self.reject(Error::MaxSessionsPerPeer));
         }
 
         let peer_id = session.peer_id.clone();
@@ -478,6 +550,7 @@ where
             peer_id,
             session_id,
             last_access_time: now,
+            enclave: None,
         };
         let inner = session.inner.clone();
 
@@ -492,6 +565,14 @@ where
         let sessions = self.by_peer.get_mut(&session.peer_id).unwrap();
         let session_meta = sessions.get(&session.session_id).unwrap();
         let key = session_meta.by_time_key();
+        if let Some(enclave) = &session_meta.enclave {
+            if let Some(count) = self.by_enclave.get_mut(enclave) {
+                *count -= 1;
+                if *count == 0 {
+                    self.by_enclave.remove(enclave);
+                }
+            }
+        }
         sessions.remove(&session.session_id);
         self.by_idle_time.remove(&key);
 
@@ -504,6 +585,7 @@ where
     /// Removes and returns all sessions.
     pub fn drain(&mut self) -> Vec<SharedSession<PeerID>> {
         self.by_idle_time.clear();
+        self.by_enclave.clear();
 
         let mut all_sessions = vec![];
         for (_, mut sessions) in self.by_peer.drain() {
@@ -515,6 +597,61 @@ where
         all_sessions
     }
 
+    /// Account an authenticated session, which must be currently owned by the caller, towards
+    /// the session limit of its remote enclave identity.
+    ///
+    /// In case the limit has been reached, the caller should remove the session.
+    pub fn authenticate(
+        &mut self,
+        session: &OwnedMutexGuard<MultiplexedSession<PeerID>>,
+    ) -> Result<(), Error> {
+        match session.info() {
+            Some(info) => self.account_enclave(
+                &session.peer_id,
+                &session.session_id,
+                &info.verified_attestation.quote.identity,
+            ),
+            None => Ok(()),
+        }
+    }
+
+    fn account_enclave(
+        &mut self,
+        peer_id: &PeerID,
+        session_id: &SessionID,
+        enclave: &EnclaveIdentity,
+    ) -> Result<(), Error> {
+        let session = match self
+            .by_peer
+            .get_mut(peer_id)
+            .and_then(|sessions| sessions.get_mut(session_id))
+        {
+            Some(session) if session.enclave.is_none() => session,
+            _ => return Ok(()), // Unknown or already accounted for.
+        };
+
+        let count = self.by_enclave.entry(enclave.clone()).or_default();
+        if *count >= self.max_sessions_per_enclave {
+            self.rejections.max_sessions_per_enclave += 1;
+            return Err(Error::MaxSessionsPerEnclave);
+        }
+        *count += 1;
+        session.enclave = Some(enclave.clone());
+
+        Ok(())
+    }
+
+    /// Record the rejection of a session due to the given error.
+    fn reject(&mut self, err: Error) -> Error {
+        let counter = match err {
+            Error::MaxConcurrentSessions => &mut self.rejections.max_sessions,
+            Error::MaxSessionsPerPeer => &mut self.rejections.max_sessions_per_peer,
+            Error::MaxSessionsPerEnclave => &mut self.rejections.max_sessions_per_enclave,
+        };
+        *counter += 1;
+        err
+    }
+
     fn update_access_time(
         session: &mut SessionMeta<PeerID>,
         by_idle_time: &mut BTreeSet<SessionByTimeKey<PeerID>>,
@@ -542,9 +679,14 @@ where
 
 #[cfg(test)]
 mod test {
-    use crate::enclave_rpc::{session::Builder, types::SessionID};
+    use std::mem;
+
+    use crate::{
+        common::sgx::{EnclaveIdentity, MrSigner},
+        enclave_rpc::{session::Builder, types::SessionID},
+    };
 
-    use super::{Error, Sessions};
+    use super::{Error, Rejections, Sessions};
 
     fn ids() -> (Vec<Vec<u8>>, Vec<SessionID>) {
         let peer_ids: Vec<Vec<u8>> = (1..8).map(|x| vec![x]).collect();
@@ -558,35 +700,106 @@ mod test {
         let (peer_ids, session_ids) = ids();
         let mut sessions = Sessions::new(Builder::default(), 4, 2, 60);
 
+        let per_peer_limit = Some(Error::MaxSessionsPerPeer);
+        let global_limit = Some(Error::MaxConcurrentSessions);
         let test_vector = vec![
-            (&peer_ids[0], &session_ids[0], 1, 1, true),
-            (&peer_ids[0], &session_ids[1], 2, 1, true), // Different session ID.
-            (&peer_ids[0], &session_ids[2], 2, 1, false), // Too many sessions per peer.
-            (&peer_ids[1], &session_ids[0], 3, 2, true), // Different peer ID.
-            (&peer_ids[2], &session_ids[2], 4, 3, true), // Different peer ID and session ID.
-            (&peer_ids[3], &session_ids[3], 4, 3, false), // Too many sessions.
+            (&peer_ids[0], &session_ids[0], 1, 1, None),
+            (&peer_ids[0], &session_ids[1], 2, 1, None), // Different session ID.
+            (&peer_ids[0], &session_ids[2], 2, 1, per_peer_limit), // Too many sessions per peer.
+            (&peer_ids[1], &session_ids[0], 3, 2, None), // Different peer ID.
+            (&peer_ids[2], &session_ids[2], 4, 3, None), // Different peer ID and session ID.
+            (&peer_ids[3], &session_ids[3], 4, 3, global_limit), // Too many sessions.
         ];
 
         let now = 0;
-        for (peer_id, session_id, num_sessions, num_peers, created) in test_vector {
-            let session = sessions.create_responder(peer_id.clone(), session_id.clone());
+        for (peer_id, session_id, num_sessions, num_peers, rejected) in test_vector {
+            let session = sessions
+                .create_responder(peer_id.clone(), session_id.clone())
+                .unwrap();
             let res = sessions.add(session, now);
-            match created {
-                true => {
+            match rejected {
+                None => {
                     assert!(res.is_ok(), "session should be created");
                     let s = res.unwrap();
                     let s_owned = s.try_lock().unwrap();
                     assert_eq!(&s_owned.peer_id, peer_id);
                     assert_eq!(&s_owned.session_id, session_id);
                 }
-                false => {
+                Some(expected) => {
                     assert!(res.is_err(), "session should not be created");
-                    assert!(matches!(res, Err(Error::MaxConcurrentSessions)));
+                    assert_eq!(
+                        mem::discriminant(&res.err().unwrap()),
+                        mem::discriminant(&expected)
+                    );
                 }
             };
             assert_eq!(sessions.session_count(), num_sessions);
             assert_eq!(sessions.peer_count(), num_peers);
         }
+        assert_eq!(
+            sessions.rejections(),
+            Rejections {
+                max_sessions: 1,
+                max_sessions_per_peer: 1,
+                max_sessions_per_enclave: 0,
+            }
+        );
+    }
+
+    #[test]
+    fn test_max_sessions_per_enclave() {
+        let (peer_ids, session_ids) = ids();
+        let mut sessions = Sessions::new(Builder::default(), 8, 2, 60);
+        sessions.set_max_sessions_per_enclave(2);
+
+        let enclave = EnclaveIdentity::fortanix_test(Default::default());
+        let other = EnclaveIdentity {
+            mr_signer: MrSigner::from(vec![1; 32]),
+            ..enclave.clone()
+        };
+
+        let now = 0;
+        let add = |sessions: &mut Sessions<Vec<u8>>, peer_id: &Vec<u8>, session_id: &SessionID| {
+            let session = sessions
+                .create_responder(peer_id.clone(), *session_id)
+                .unwrap();
+            sessions
+                .add(session, now)
+                .unwrap()
+                .try_lock_owned()
+                .unwrap()
+        };
+        let s1 = add(&mut sessions, &peer_ids[0], &session_ids[0]);
+        let s2 = add(&mut sessions, &peer_ids[1], &session_ids[1]);
+        let s3 = add(&mut sessions, &peer_ids[2], &session_ids[2]);
+        let s4 = add(&mut sessions, &peer_ids[2], &session_ids[3]);
+
+        sessions
+            .account_enclave(&s1.peer_id, &s1.session_id, &enclave)
+            .unwrap();
+        // Sessions are only accounted for once.
+        sessions
+            .account_enclave(&s1.peer_id, &s1.session_id, &enclave)
+            .unwrap();
+        sessions
+            .account_enclave(&s2.peer_id, &s2.session_id, &enclave)
+            .unwrap();
+        let res = sessions.account_enclave(&s3.peer_id, &s3.session_id, &enclave);
+        assert!(matches!(res, Err(Error::MaxSessionsPerEnclave)));
+        // Other enclave identities are not affected.
+        sessions
+            .account_enclave(&s4.peer_id, &s4.session_id, &other)
+            .unwrap();
+
+        // Removing a session frees up a slot.
+        sessions.remove(&s3);
+        sessions.remove(&s1);
+        let s5 = add(&mut sessions, &peer_ids[3], &session_ids[4]);
+        sessions
+            .account_enclave(&s5.peer_id, &s5.session_id, &enclave)
+            .unwrap();
+
+        assert_eq!(sessions.rejections().max_sessions_per_enclave, 1);
     }
 
     #[test]
@@ -604,7 +817,9 @@ mod test {
         let now = 0;
         for (peer_id, session_id, create) in test_vector {
             if create {
-                let session = sessions.create_responder(peer_id.clone(), session_id.clone());
+                let session = sessions
+                    .create_responder(peer_id.clone(), session_id.clone())
+                    .unwrap();
                 let _ = sessions.add(session, now);
             }
 
@@ -639,7 +854,9 @@ mod test {
 
         let mut now = 0;
         for (peer_id, session_id) in test_vector {
-            let session = sessions.create_responder(peer_id.clone(), session_id.clone());
+            let session = sessions
+                .create_responder(peer_id.clone(), session_id.clone())
+                .unwrap();
             let _ = sessions.add(session, now);
             now += 1
         }
@@ -708,7 +925,9 @@ mod test {
 
         let mut now = 0;
         for (peer_id, session_id) in test_vector {
-            let session = sessions.create_responder(peer_id.clone(), session_id.clone());
+            let session = sessions
+                .create_responder(peer_id.clone(), session_id.clone())
+                .unwrap();
             let _ = sessions.add(session, now);
             now += 1
         }
@@ -775,7 +994,9 @@ mod test {
 
         let mut now = 0;
         for (peer_id, session_id) in test_vector.clone() {
-            let session = sessions.create_responder(peer_id.clone(), session_id.clone());
+            let session = sessions
+                .create_responder(peer_id.clone(), session_id.clone())
+                .unwrap();
             let _ = sessions.add(session, now);
             now += 1;
         }
@@ -842,7 +1063,9 @@ mod test {
 
         let mut now = 0;
         for (peer_id, session_id) in test_vector.clone() {
-            let session = sessions.create_responder(peer_id.clone(), session_id.clone());
+            let session = sessions
+                .create_responder(peer_id.clone(), session_id.clone())
+                .unwrap();
             let _ = sessions.add(session, now);
             now += 1;
         }
@@ -899,7 +1122,9 @@ mod test {
 
         let now = 0;
         for (peer_id, session_id, _, _) in test_vector.clone() {
-            let session = sessions.create_responder(peer_id.clone(), session_id.clone());
+            let session = sessions
+                .create_responder(peer_id.clone(), session_id.clone())
+                .unwrap();
             let _ = sessions.add(session, now);
         }
 
@@ -929,7 +1154,9 @@ mod test {
 
         let now = 0;
         for (peer_id, session_id) in test_vector.clone() {
-            let session = sessions.create_responder(peer_id.clone(), session_id.clone());
+            let session = sessions
+                .create_responder(peer_id.clone(), session_id.clone())
+                .unwrap();
             let _ = sessions.add(session, now);
         }
 
@@ -0,0 +1,639 @@
+//! Bidirectional streaming over secure sessions.
+//!
+//! A stream is opened by a client calling a streaming method and stays open until the method
+//! handler completes. Both sides get a [`RpcSink`] for sending items and a [`RpcStream`] of items
+//! sent by the other side.
+//!
+//! The underlying transport only supports request/response exchanges, so the client drives the
+//! stream by exchanging [`StreamFrame`]s with the server, each carrying the items produced since
+//! the last exchange. Every frame also advertises how many items the sender is ready to receive,
+//! so neither side ever buffers more than [`STREAM_WINDOW`] items per stream and direction.
+use std::{
+    collections::HashMap,
+    marker::PhantomData,
+    pin::Pin,
+    sync::{
+        atomic::{AtomicU32, Ordering},
+        Arc,
+    },
+    task::{Context as TaskContext, Poll},
+    time::Duration,
+};
+
+use anyhow::Result;
+use futures::{
+    channel::mpsc,
+    future::{BoxFuture, FutureExt},
+    Future, Sink, Stream, StreamExt,
+};
+use thiserror::Error;
+use tokio::{sync::mpsc as tokio_mpsc, task::JoinHandle};
+
+use crate::common::crypto::signature;
+
+use super::{
+    client::RpcClientError,
+    context::Context,
+    dispatcher::MethodDescriptor,
+    sessions::SharedSession,
+    transport::Transport,
+    types::{Message, Request, SessionID, StreamFrame},
+};
+
+/// Maximum number of items buffered per stream and direction.
+pub const STREAM_WINDOW: u32 = 64;
+/// Maximum number of concurrently open streams per session.
+pub const MAX_STREAMS_PER_SESSION: usize = 8;
+/// Maximum delay between exchanges of idle streams.
+const MAX_POLL_INTERVAL: Duration = Duration::from_secs(1);
+/// Initial delay between exchanges of idle streams.
+const MIN_POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+/// Stream error.
+#[derive(Error, Debug)]
+pub enum StreamError {
+    #[error("stream failed: {0}")]
+    Failed(String),
+    #[error("stream closed")]
+    Closed,
+    #[error("decode error: {0}")]
+    DecodeError(#[from] cbor::DecodeError),
+}
+
+/// Receiving half of a stream.
+pub struct RpcStream<T> {
+    rx: tokio_mpsc::Receiver<Result<cbor::Value, String>>,
+    failed: bool,
+    _item: PhantomData<fn() -> T>,
+}
+
+impl<T> RpcStream<T> {
+    fn new(rx: tokio_mpsc::Receiver<Result<cbor::Value, String>>) -> Self {
+        Self {
+            rx,
+            failed: false,
+            _item: PhantomData,
+        }
+    }
+}
+
+impl<T: cbor::Decode> Stream for RpcStream<T> {
+    type Item = Result<T, StreamError>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut TaskContext<'_>) -> Poll<Option<Self::Item>> {
+        if self.failed {
+            return Poll::Ready(None);
+        }
+
+        match self.rx.poll_recv(cx) {
+            Poll::Ready(Some(Ok(item))) => {
+                Poll::Ready(Some(cbor::from_value(item).map_err(Into::into)))
+            }
+            Poll::Ready(Some(Err(err))) => {
+                // Errors terminate the stream.
+                self.failed = true;
+                Poll::Ready(Some(Err(StreamError::Failed(err))))
+            }
+            Poll::Ready(None) => Poll::Ready(None),
+            Poll::Pending => Poll::Pending,
+        }
+    }
+}
+
+/// Sending half of a stream.
+///
+/// Dropping or closing the sink signals the other side that no more items will be sent.
+pub struct RpcSink<T> {
+    tx: mpsc::Sender<cbor::Value>,
+    _item: PhantomData<fn(T)>,
+}
+
+impl<T> RpcSink<T> {
+    fn new(tx: mpsc::Sender<cbor::Value>) -> Self {
+        Self {
+            tx,
+            _item: PhantomData,
+        }
+    }
+}
+
+impl<T: cbor::Encode> Sink<T> for RpcSink<T> {
+    type Error = StreamError;
+
+    fn poll_ready(
+        mut self: Pin<&mut Self>,
+        cx: &mut TaskContext<'_>,
+    ) -> Poll<Result<(), Self::Error>> {
+        self.tx.poll_ready(cx).map_err(|_| StreamError::Closed)
+    }
+
+    fn start_send(mut self: Pin<&mut Self>, item: T) -> Result<(), Self::Error> {
+        self.tx
+            .start_send(cbor::to_value(item))
+            .map_err(|_| StreamError::Closed)
+    }
+
+    fn poll_flush(
+        mut self: Pin<&mut Self>,
+        cx: &mut TaskContext<'_>,
+    ) -> Poll<Result<(), Self::Error>> {
+        Pin::new(&mut self.tx)
+            .poll_flush(cx)
+            .map_err(|_| StreamError::Closed)
+    }
+
+    fn poll_close(
+        mut self: Pin<&mut Self>,
+        cx: &mut TaskContext<'_>,
+    ) -> Poll<Result<(), Self::Error>> {
+        Pin::new(&mut self.tx)
+            .poll_close(cx)
+            .map_err(|_| StreamError::Closed)
+    }
+}
+
+/// Create the channels backing the two directions of a stream, returning the receiving half
+/// with its feeding end and the sending half with its draining end.
+#[allow(clippy::type_complexity)]
+fn channels<In, Out>() -> (
+    (
+        RpcStream<In>,
+        tokio_mpsc::Sender<Result<cbor::Value, String>>,
+    ),
+    (RpcSink<Out>, mpsc::Receiver<cbor::Value>),
+) {
+    let (inbound_tx, inbound_rx) = tokio_mpsc::channel(STREAM_WINDOW as usize);
+    // The sender always has a guaranteed slot in addition to the channel buffer.
+    let (outbound_tx, outbound_rx) = mpsc::channel(STREAM_WINDOW as usize - 1);
+
+    (
+        (RpcStream::new(inbound_rx), inbound_tx),
+        (RpcSink::new(outbound_tx), outbound_rx),
+    )
+}
+
+/// Take up to `limit` items that are ready to be sent, also returning whether the sending half
+/// has been closed and all items were taken.
+fn take_ready(rx: &mut mpsc::Receiver<cbor::Value>, limit: u32) -> (Vec<cbor::Value>, bool) {
+    let mut items = vec![];
+    while items.len() < limit as usize {
+        match rx.try_next() {
+            Ok(Some(item)) => items.push(item),
+            Ok(None) => return (items, true),
+            Err(_) => break,
+        }
+    }
+    (items, false)
+}
+
+/// Handler for a streaming RPC method.
+pub trait StreamingMethodHandler<Rq, In, Out> {
+    /// Invoke the method implementation, which receives items from the client over the given
+    /// stream and sends items to the client over the given sink.
+    ///
+    /// The stream is closed once the returned future completes.
+    fn handle(
+        &self,
+        ctx: Context,
+        request: Rq,
+        inbound: RpcStream<In>,
+        outbound: RpcSink<Out>,
+    ) -> BoxFuture<'static, Result<()>>;
+}
+
+impl<Rq, In, Out, F, Fut> StreamingMethodHandler<Rq, In, Out> for F
+where
+    F: Fn(Context, Rq, RpcStream<In>, RpcSink<Out>) -> Fut,
+    Fut: Future<Output = Result<()>> + Send + 'static,
+{
+    fn handle(
+        &self,
+        ctx: Context,
+        request: Rq,
+        inbound: RpcStream<In>,
+        outbound: RpcSink<Out>,
+    ) -> BoxFuture<'static, Result<()>> {
+        (*self)(ctx, request, inbound, outbound).boxed()
+    }
+}
+
+/// Dispatcher for a streaming RPC method.
+trait StreamingMethodHandlerDispatch {
+    /// Get method descriptor.
+    fn get_descriptor(&self) -> &MethodDescriptor;
+
+    /// Open a stream, returning the handler future driving it.
+    fn open(
+        &self,
+        ctx: Context,
+        request: Request,
+        inbound: RpcStream<cbor::Value>,
+        outbound: RpcSink<cbor::Value>,
+    ) -> Result<BoxFuture<'static, Result<()>>>;
+}
+
+struct StreamingMethodHandlerDispatchImpl<Rq, In, Out> {
+    /// Method descriptor.
+    descriptor: MethodDescriptor,
+    /// Method handler.
+    handler: Box<dyn StreamingMethodHandler<Rq, In, Out> + Send + Sync>,
+}
+
+impl<Rq, In, Out> StreamingMethodHandlerDispatch for StreamingMethodHandlerDispatchImpl<Rq, In, Out>
+where
+    Rq: cbor::Decode + 'static,
+    In: 'static,
+    Out: 'static,
+{
+    fn get_descriptor(&self) -> &MethodDescriptor {
+        &self.descriptor
+    }
+
+    fn open(
+        &self,
+        ctx: Context,
+        request: Request,
+        inbound: RpcStream<cbor::Value>,
+        outbound: RpcSink<cbor::Value>,
+    ) -> Result<BoxFuture<'static, Result<()>>> {
+        let request = cbor::from_value(request.args)?;
+
+        Ok(self.handler.handle(
+            ctx,
+            request,
+            RpcStream::new(inbound.rx),
+            RpcSink::new(outbound.tx),
+        ))
+    }
+}
+
+/// Streaming RPC method dispatcher implementation.
+pub struct StreamingMethod {
+    /// Method dispatcher.
+    dispatcher: Box<dyn StreamingMethodHandlerDispatch + Send + Sync>,
+}
+
+impl StreamingMethod {
+    /// Create a new streaming method descriptor.
+    pub fn new<Rq, In, Out, Handler>(method: MethodDescriptor, handler: Handler) -> Self
+    where
+        Rq: cbor::Decode + 'static,
+        In: cbor::Decode + 'static,
+        Out: cbor::Encode + 'static,
+        Handler: StreamingMethodHandler<Rq, In, Out> + Send + Sync + 'static,
+    {
+        StreamingMethod {
+            dispatcher: Box::new(StreamingMethodHandlerDispatchImpl {
+                descriptor: method,
+                handler: Box::new(handler),
+            }),
+        }
+    }
+
+    /// Return method descriptor.
+    pub(super) fn get_descriptor(&self) -> &MethodDescriptor {
+        self.dispatcher.get_descriptor()
+    }
+}
+
+/// Identifier of a server-side stream.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+struct StreamKey {
+    peer_id: Vec<u8>,
+    session_id: SessionID,
+    stream_id: u64,
+}
+
+/// Server-side state of an open stream.
+struct ServerStream {
+    /// Feeding end of the handler's receiving half, dropped once the client ends its items.
+    inbound: Option<tokio_mpsc::Sender<Result<cbor::Value, String>>>,
+    /// Draining end of the handler's sending half.
+    outbound: mpsc::Receiver<cbor::Value>,
+    /// Task running the handler.
+    task: JoinHandle<Result<()>>,
+}
+
+impl ServerStream {
+    /// Deliver the items received from the client to the handler.
+    fn receive(&mut self, items: Vec<cbor::Value>, end: bool) -> Result<(), String> {
+        if !items.is_empty() {
+            let inbound = self
+                .inbound
+                .as_ref()
+                .ok_or_else(|| "items received after end of stream".to_string())?;
+            for item in items {
+                match inbound.try_send(Ok(item)) {
+                    Ok(_) => {}
+                    Err(tokio_mpsc::error::TrySendError::Full(_)) => {
+                        return Err("flow-control window exceeded".to_string());
+                    }
+                    // The handler is no longer interested in items.
+                    Err(tokio_mpsc::error::TrySendError::Closed(_)) => break,
+                }
+            }
+        }
+        if end {
+            self.inbound = None;
+        }
+        Ok(())
+    }
+
+    /// Number of items the handler is ready to receive.
+    fn window(&self) -> u32 {
+        self.inbound
+            .as_ref()
+            .map_or(0, |inbound| inbound.capacity() as u32)
+    }
+}
+
+/// Registry of open server-side streams.
+#[derive(Default)]
+pub struct Streams {
+    streams: HashMap<StreamKey, ServerStream>,
+}
+
+impl Streams {
+    /// Open a new stream driven by the given method, with the given request.
+    pub(super) fn open(
+        &mut self,
+        ctx: Context,
+        peer_id: &[u8],
+        session_id: SessionID,
+        stream_id: u64,
+        method: &StreamingMethod,
+        request: Request,
+    ) -> Result<(), String> {
+        let key = StreamKey {
+            peer_id: peer_id.to_vec(),
+            session_id,
+            stream_id,
+        };
+        if self.streams.contains_key(&key) {
+            return Err("stream already open".to_string());
+        }
+        let open = self
+            .streams
+            .keys()
+            .filter(|k| k.peer_id == key.peer_id && k.session_id == key.session_id)
+            .count();
+        if open >= MAX_STREAMS_PER_SESSION {
+            return Err("too many open streams".to_string());
+        }
+
+        let ((inbound_rx, inbound), (outbound_tx, outbound)) = channels();
+        let handler = method
+            .dispatcher
+            .open(ctx, request, inbound_rx, outbound_tx)
+            .map_err(|err| format!("{err}"))?;
+        let task = tokio::spawn(handler);
+
+        self.streams.insert(
+            key,
+            ServerStream {
+                inbound: Some(inbound),
+                outbound,
+                task,
+            },
+        );
+        Ok(())
+    }
+
+    /// Process a frame received from the client, returning the frame to send back.
+    pub(super) fn process(
+        &mut self,
+        peer_id: &[u8],
+        session_id: SessionID,
+        frame: StreamFrame,
+    ) -> StreamFrame {
+        let key = StreamKey {
+            peer_id: peer_id.to_vec(),
+            session_id,
+            stream_id: frame.stream_id,
+        };
+        let stream = match self.streams.get_mut(&key) {
+            Some(stream) => stream,
+            None => return failed(frame.stream_id, "unknown stream".to_string()),
+        };
+
+        // The client aborted the stream.
+        if frame.error.is_some() {
+            self.remove(&key);
+            return StreamFrame {
+                stream_id: frame.stream_id,
+                end: true,
+                ..Default::default()
+            };
+        }
+
+        if let Err(err) = stream.receive(frame.items, frame.end) {
+            self.remove(&key);
+            return failed(frame.stream_id, err);
+        }
+
+        let (items, drained) = take_ready(&mut stream.outbound, frame.window.min(STREAM_WINDOW));
+        let mut response = StreamFrame {
+            stream_id: frame.stream_id,
+            items,
+            window: stream.window(),
+            ..Default::default()
+        };
+
+        // The stream is finished once the handler completed and all its items were sent.
+        if !drained || !stream.task.is_finished() {
+            return response;
+        }
+        match (&mut stream.task).now_or_never() {
+            Some(Ok(Ok(()))) => response.end = true,
+            Some(Ok(Err(err))) => response.error = Some(format!("{err}")),
+            Some(Err(_)) => response.error = Some("stream handler failed".to_string()),
+            None => return response,
+        }
+        self.remove(&key);
+
+        response
+    }
+
+    /// Abort all streams of the given session.
+    pub(super) fn close_session(&mut self, peer_id: &[u8], session_id: &SessionID) {
+        self.streams.retain(|key, stream| {
+            let retain = key.peer_id != peer_id || &key.session_id != session_id;
+            if !retain {
+                stream.task.abort();
+            }
+            retain
+        });
+    }
+
+    /// Number of open streams.
+    pub fn len(&self) -> usize {
+        self.streams.len()
+    }
+
+    /// Whether there are no open streams.
+    pub fn is_empty(&self) -> bool {
+        self.streams.is_empty()
+    }
+
+    fn remove(&mut self, key: &StreamKey) {
+        if let Some(stream) = self.streams.remove(key) {
+            stream.task.abort();
+        }
+    }
+}
+
+/// Frame terminating the given stream with an error.
+pub(super) fn failed(stream_id: u64, error: String) -> StreamFrame {
+    StreamFrame {
+        stream_id,
+        error: Some(error),
+        ..Default::default()
+    }
+}
+
+/// Client-side driver of a stream, exchanging frames with the server until the stream finishes.
+pub(super) struct Driver {
+    pub(super) transport: Arc<dyn Transport>,
+    pub(super) session: SharedSession<signature::PublicKey>,
+    pub(super) client_id: u32,
+    pub(super) next_request_id: Arc<AtomicU32>,
+    pub(super) stream_id: u64,
+}
+
+impl Driver {
+    /// Open a stream with the given request, returning its client-side halves.
+    pub(super) fn start<In, Out>(self, request: Request) -> (RpcSink<In>, RpcStream<Out>) {
+        let ((stream, inbound), (sink, outbound)) = channels();
+        tokio::spawn(self.run(request, inbound, outbound));
+
+        (sink, stream)
+    }
+
+    async fn run(
+        self,
+        request: Request,
+        inbound: tokio_mpsc::Sender<Result<cbor::Value, String>>,
+        mut outbound: mpsc::Receiver<cbor::Value>,
+    ) {
+        let mut open = Some(request);
+        let mut remote_window = 0;
+        let mut ended = false;
+        let mut pending = vec![];
+        let mut interval = MIN_POLL_INTERVAL;
+
+        loop {
+            // Abort the stream in case the receiving half was dropped.
+            let error = inbound.is_closed().then(|| "cancelled".to_string());
+
+            // Send as many items as the server is ready to receive.
+            let limit = remote_window.saturating_sub(pending.len() as u32);
+            let (items, drained) = take_ready(&mut outbound, limit);
+            pending.extend(items);
+            let end = drained && !ended;
+            ended |= drained;
+
+            let frame = StreamFrame {
+                stream_id: self.stream_id,
+                open: open.take(),
+                items: std::mem::take(&mut pending),
+                window: inbound.capacity() as u32,
+                end,
+                error: error.clone(),
+            };
+            let exchanged = !frame.items.is_empty();
+            let response = match self.exchange(frame).await {
+                Ok(response) => response,
+                Err(err) => {
+                    let _ = inbound.send(Err(format!("{err}"))).await;
+                    return;
+                }
+            };
+            if error.is_some() {
+                return;
+            }
+
+            remote_window = response.window;
+            let exchanged = exchanged || !response.items.is_empty();
+            for item in response.items {
+                match inbound.try_send(Ok(item)) {
+                    Ok(_) => {}
+                    Err(tokio_mpsc::error::TrySendError::Full(_)) => {
+                        // The server sent more items than advertised, abort instead of dropping.
+                        let err = "flow-control window exceeded".to_string();
+                        let _ = self.exchange(failed(self.stream_id, err.clone())).await;
+                        let _ = inbound.send(Err(err)).await;
+                        return;
+                    }
+                    // The receiving half was dropped, the stream is aborted in the next frame.
+                    Err(tokio_mpsc::error::TrySendError::Closed(_)) => break,
+                }
+            }
+            if let Some(err) = response.error {
+                let _ = inbound.send(Err(err)).await;
+                return;
+            }
+            if response.end {
+                return;
+            }
+
+            // Keep exchanging frames right away while items are flowing and back off otherwise,
+            // unless an item to send becomes available.
+            if exchanged {
+                interval = MIN_POLL_INTERVAL;
+                continue;
+            }
+            let ready = !ended && remote_window > 0;
+            tokio::select! {
+                item = outbound.next(), if ready => {
+                    // The end of items is signalled in the next frame.
+                    pending.extend(item);
+                }
+                _ = tokio::time::sleep(interval) => {
+                    interval = (interval * 2).min(MAX_POLL_INTERVAL);
+                }
+            }
+        }
+    }
+
+    async fn exchange(&self, frame: StreamFrame) -> Result<StreamFrame, RpcClientError> {
+        let untrusted_plaintext = frame
+            .open
+            .as_ref()
+            .map(|request| request.method.clone())
+            .unwrap_or_default();
+        let mut session = self.session.lock().await;
+        let session_id = *session.get_session_id();
+
+        // Session Transport: prepare the frame.
+        let mut buffer = vec![];
+        session
+            .write_message(Message::Stream(frame), &mut buffer)
+            .map_err(|_| RpcClientError::Transport)?;
+        let node = session.get_remote_node()?;
+
+        // Transport: send the frame and receive a response.
+        let request_id = ((self.client_id as u64) << 32)
+            + (self.next_request_id.fetch_add(1, Ordering::SeqCst) as u64);
+        let rsp = self
+            .transport
+            .write_stream(
+                request_id,
+                session_id,
+                buffer,
+                untrusted_plaintext,
+                vec![node],
+            )
+            .await
+            .map_err(|_| RpcClientError::Transport)?;
+
+        // Session Transport: process the response.
+        let msg = session
+            .process_data(&rsp.data, vec![])
+            .await?
+            .expect("message must be decoded if there is no error");
+        match msg {
+            Message::Stream(frame) => Ok(frame),
+            msg => Err(RpcClientError::ExpectedStreamMessage(msg)),
+        }
+    }
+}
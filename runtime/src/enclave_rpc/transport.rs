@@ -3,9 +3,17 @@ use std::sync::Arc;
 use anyhow::{anyhow, Error as AnyError};
 use async_trait::async_trait;
 
-use crate::{common::crypto::signature, types::Body, Protocol};
+use crate::{
+    common::crypto::signature,
+    host::Error as HostError,
+    types::{Body, ProtocolFeature},
+    Protocol,
+};
 
-use super::types;
+use super::{
+    codec::{self, FrameVersion},
+    types,
+};
 
 // Enclave's response.
 pub struct EnclaveResponse {
@@ -34,13 +42,36 @@ pub trait Transport: Send + Sync {
 
         self.write_message_impl(
             request_id,
-            cbor::to_vec(frame),
+            codec::encode(frame, self.frame_version()),
             types::Kind::NoiseSession,
             nodes,
         )
         .await
     }
 
+    async fn write_stream(
+        &self,
+        request_id: u64,
+        session_id: types::SessionID,
+        data: Vec<u8>,
+        untrusted_plaintext: String,
+        nodes: Vec<signature::PublicKey>,
+    ) -> Result<EnclaveResponse, AnyError> {
+        let frame = types::Frame {
+            session: session_id,
+            untrusted_plaintext,
+            payload: data,
+        };
+
+        self.write_message_impl(
+            request_id,
+            codec::encode(frame, self.frame_version()),
+            types::Kind::Stream,
+            nodes,
+        )
+        .await
+    }
+
     async fn write_insecure_query(
         &self,
         request_id: u64,
@@ -59,6 +90,11 @@ pub trait Transport: Send + Sync {
         nodes: Vec<signature::PublicKey>,
     ) -> Result<EnclaveResponse, AnyError>;
 
+    /// Codec version used to encode frames.
+    fn frame_version(&self) -> FrameVersion {
+        FrameVersion::V0
+    }
+
     async fn submit_peer_feedback(
         &self,
         request_id: u64,
@@ -71,13 +107,18 @@ pub trait Transport: Send + Sync {
 pub struct RuntimeTransport {
     pub protocol: Arc<Protocol>,
     pub endpoint: String,
+    /// Codec version used to encode frames, which all servers must accept.
+    pub frame_version: FrameVersion,
 }
 
 impl RuntimeTransport {
     pub fn new(protocol: Arc<Protocol>, endpoint: &str) -> Self {
+        let frame_version = protocol.get_config().rpc_frame_codecs.version;
+
         Self {
             protocol,
             endpoint: endpoint.to_string(),
+            frame_version,
         }
     }
 }
@@ -91,19 +132,34 @@ impl Transport for RuntimeTransport {
         kind: types::Kind,
         nodes: Vec<signature::PublicKey>,
     ) -> Result<EnclaveResponse, AnyError> {
-        let rsp = self
-            .protocol
-            .call_host_async(Body::HostRPCCallRequest {
+        // Stream frames use dedicated messages which only hosts supporting streaming know about.
+        let request = match kind {
+            types::Kind::Stream => {
+                let feature = ProtocolFeature::StreamingRpc;
+                if !self.protocol.features().contains(feature) {
+                    return Err(HostError::FeatureNotNegotiated(feature.name()).into());
+                }
+
+                Body::HostRPCStreamRequest {
+                    endpoint: self.endpoint.clone(),
+                    request_id,
+                    request: data,
+                    nodes,
+                }
+            }
+            kind => Body::HostRPCCallRequest {
                 endpoint: self.endpoint.clone(),
                 request_id,
                 request: data,
                 kind,
                 nodes,
-            })
-            .await?;
+            },
+        };
+        let rsp = self.protocol.call_host_async(request).await?;
 
         match rsp {
-            Body::HostRPCCallResponse { response, node } => Ok(EnclaveResponse {
+            Body::HostRPCCallResponse { response, node }
+            | Body::HostRPCStreamResponse { response, node } => Ok(EnclaveResponse {
                 data: response,
                 node,
             }),
@@ -111,6 +167,10 @@ impl Transport for RuntimeTransport {
         }
     }
 
+    fn frame_version(&self) -> FrameVersion {
+        self.frame_version
+    }
+
     async fn submit_peer_feedback(
         &self,
         request_id: u64,
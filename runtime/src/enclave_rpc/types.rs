@@ -1,5 +1,7 @@
 //! RPC protocol types.
-use rand::{rngs::OsRng, Rng};
+use rand::Rng;
+
+use crate::common::crypto::rng::SecureRng;
 
 impl_bytes!(
     SessionID,
@@ -12,7 +14,7 @@ impl SessionID {
     /// Generate a random session identifier.
     pub fn random() -> Self {
         let mut session_id = [0u8; 32];
-        OsRng.fill(&mut session_id);
+        SecureRng.fill(&mut session_id);
 
         SessionID(session_id)
     }
@@ -29,6 +31,8 @@ pub enum Kind {
     InsecureQuery = 1,
     /// A local RPC call.
     LocalQuery = 2,
+    /// A frame of a bidirectional stream over an encrypted and authenticated Noise session.
+    Stream = 3,
 }
 
 impl Default for Kind {
@@ -68,10 +72,50 @@ pub enum Body {
     Error(String),
 }
 
+/// Module and code identifying the cause of a failed call, for errors clients act upon.
+#[derive(Clone, Debug, Default, PartialEq, Eq, cbor::Encode, cbor::Decode)]
+pub struct ErrorCode {
+    pub module: String,
+    pub code: u32,
+    /// Time after which the call may be retried in milliseconds, for errors caused by temporary
+    /// conditions.
+    #[cbor(optional)]
+    pub retry_after_ms: u64,
+}
+
 #[derive(Clone, Debug, cbor::Encode, cbor::Decode)]
 #[cbor(no_default)]
 pub struct Response {
     pub body: Body,
+    /// Cause of the failure in case the call failed with a well-known error.
+    #[cbor(optional)]
+    pub error: Option<ErrorCode>,
+}
+
+/// Frame of a bidirectional stream.
+///
+/// Streams are driven by the client, which sends frames with the items it produced and receives
+/// the items produced by the server in the response. Each side advertises a flow-control window
+/// with every frame, which is the number of items the other side may send in the next frame.
+#[derive(Clone, Debug, Default, cbor::Encode, cbor::Decode)]
+pub struct StreamFrame {
+    /// Identifier of the stream, unique within the session.
+    pub stream_id: u64,
+    /// Request opening the stream, only present in the first frame sent by the client.
+    #[cbor(optional)]
+    pub open: Option<Request>,
+    /// Items sent over the stream.
+    #[cbor(optional)]
+    pub items: Vec<cbor::Value>,
+    /// Number of items the sender is ready to receive.
+    #[cbor(optional)]
+    pub window: u32,
+    /// Whether the sender will not send any more items.
+    #[cbor(optional)]
+    pub end: bool,
+    /// Error terminating the stream.
+    #[cbor(optional)]
+    pub error: Option<String>,
 }
 
 /// Protocol message.
@@ -80,6 +124,7 @@ pub enum Message {
     Request(Request),
     Response(Response),
     Close,
+    Stream(StreamFrame),
 }
 
 /// Feedback on the peer that handled the last EnclaveRPC call.
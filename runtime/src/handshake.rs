@@ -0,0 +1,177 @@
+//! Runtime host protocol handshake transcript.
+//!
+//! During initialization the host tells the runtime about its environment and the runtime
+//! responds with its protocol version and supported features. As the host is untrusted, it could
+//! tamper with this exchange to make external parties believe the runtime supports fewer
+//! features (or an older protocol version) than it actually does. To prevent such downgrades, the
+//! runtime signs a transcript of the handshake with its RAK. Anyone holding an attestation of the
+//! RAK can then check that the negotiated parameters are the ones the runtime actually used.
+use anyhow::Result;
+use thiserror::Error;
+
+use crate::{
+    common::{
+        crypto::signature::{PublicKey, SignatureBundle, Signer},
+        namespace::Namespace,
+        version::Version,
+    },
+    types::{Features, RuntimeInfoRequest, RuntimeInfoResponse},
+};
+
+/// Signature context used for signing handshake transcripts.
+pub const HANDSHAKE_TRANSCRIPT_SIGNATURE_CONTEXT: &[u8] =
+    b"oasis-core/runtime: handshake transcript";
+
+/// Handshake transcript verification errors.
+#[derive(Error, Debug, PartialEq, Eq)]
+pub enum HandshakeError {
+    #[error("transcript not signed by the expected RAK")]
+    UnexpectedSigner,
+    #[error("invalid transcript signature")]
+    InvalidSignature,
+    #[error("negotiated parameters do not match the transcript")]
+    Mismatch,
+}
+
+/// Transcript of the parameters negotiated during the runtime host protocol handshake.
+#[derive(Clone, Debug, Default, PartialEq, Eq, cbor::Encode, cbor::Decode)]
+pub struct HandshakeTranscript {
+    /// Runtime identifier provided by the host.
+    pub runtime_id: Namespace,
+    /// Consensus backend provided by the host.
+    pub consensus_backend: String,
+    /// Consensus protocol version provided by the host.
+    pub consensus_protocol_version: Version,
+    /// Consensus chain context provided by the host.
+    pub consensus_chain_context: String,
+    /// Runtime host protocol version supported by the runtime.
+    pub protocol_version: Version,
+    /// Version of the runtime.
+    pub runtime_version: Version,
+    /// Features supported by the runtime.
+    pub features: Features,
+}
+
+impl HandshakeTranscript {
+    /// Create a transcript of the given handshake.
+    pub fn new(request: &RuntimeInfoRequest, response: &RuntimeInfoResponse) -> Self {
+        Self {
+            runtime_id: request.runtime_id,
+            consensus_backend: request.consensus_backend.clone(),
+            consensus_protocol_version: request.consensus_protocol_version,
+            consensus_chain_context: request.consensus_chain_context.clone(),
+            protocol_version: response.protocol_version,
+            runtime_version: response.runtime_version,
+            features: response.features.clone(),
+        }
+    }
+
+    /// Sign the transcript with the given RAK.
+    pub fn sign(self, rak: &dyn Signer) -> Result<SignedHandshakeTranscript> {
+        let signature = rak.sign(
+            HANDSHAKE_TRANSCRIPT_SIGNATURE_CONTEXT,
+            &cbor::to_vec(self.clone()),
+        )?;
+
+        Ok(SignedHandshakeTranscript {
+            transcript: self,
+            signature: SignatureBundle {
+                public_key: rak.public(),
+                signature,
+            },
+        })
+    }
+}
+
+/// Handshake transcript signed by the runtime's RAK.
+#[derive(Clone, Debug, Default, PartialEq, Eq, cbor::Encode, cbor::Decode)]
+pub struct SignedHandshakeTranscript {
+    /// Handshake transcript.
+    pub transcript: HandshakeTranscript,
+    /// Signature over the transcript by the RAK.
+    pub signature: SignatureBundle,
+}
+
+impl SignedHandshakeTranscript {
+    /// Verify the transcript signature against the given (attested) RAK.
+    pub fn verify(&self, rak: &PublicKey) -> Result<&HandshakeTranscript, HandshakeError> {
+        if &self.signature.public_key != rak {
+            return Err(HandshakeError::UnexpectedSigner);
+        }
+        if !self.signature.verify(
+            HANDSHAKE_TRANSCRIPT_SIGNATURE_CONTEXT,
+            &cbor::to_vec(self.transcript.clone()),
+        ) {
+            return Err(HandshakeError::InvalidSignature);
+        }
+        Ok(&self.transcript)
+    }
+
+    /// Verify the transcript against the given RAK and make sure that the handshake reported by
+    /// the host matches it, detecting any downgrades performed by the host.
+    pub fn verify_handshake(
+        &self,
+        rak: &PublicKey,
+        request: &RuntimeInfoRequest,
+        response: &RuntimeInfoResponse,
+    ) -> Result<(), HandshakeError> {
+        if self.verify(rak)? != &HandshakeTranscript::new(request, response) {
+            return Err(HandshakeError::Mismatch);
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::common::crypto::signature::PrivateKey;
+
+    #[test]
+    fn test_handshake_transcript() {
+        let rak = PrivateKey::generate();
+        let request = RuntimeInfoRequest {
+            consensus_backend: "tendermint".to_string(),
+            consensus_chain_context: "test".to_string(),
+            ..Default::default()
+        };
+        let response = RuntimeInfoResponse {
+            protocol_version: Version::new(5, 0, 0),
+            ..Default::default()
+        };
+
+        let signed = HandshakeTranscript::new(&request, &response)
+            .sign(&rak)
+            .unwrap();
+        assert!(signed
+            .verify_handshake(&rak.public_key(), &request, &response)
+            .is_ok());
+
+        // A downgraded feature set must be detected.
+        let downgraded = RuntimeInfoResponse {
+            features: Features {
+                endorsed_capability_tee: false,
+                ..Default::default()
+            },
+            ..response.clone()
+        };
+        assert_eq!(
+            signed.verify_handshake(&rak.public_key(), &request, &downgraded),
+            Err(HandshakeError::Mismatch)
+        );
+
+        // Tampering with the transcript itself must be detected.
+        let mut tampered = signed.clone();
+        tampered.transcript.protocol_version = Version::new(4, 0, 0);
+        assert_eq!(
+            tampered.verify(&rak.public_key()),
+            Err(HandshakeError::InvalidSignature)
+        );
+
+        let other = PrivateKey::generate();
+        assert_eq!(
+            signed.verify(&other.public_key()),
+            Err(HandshakeError::UnexpectedSigner)
+        );
+    }
+}
@@ -0,0 +1,354 @@
+//! Runtime health reporting.
+//!
+//! The health monitor aggregates observations made by the different runtime subsystems (the
+//! consensus verifier, the key manager client, storage, attestation, the host connection and the
+//! local clock) into a single typed report. Reports can be retrieved via the `METHOD_HEALTH`
+//! query and are pushed to the host periodically in case `Config::health_report_interval` is set.
+use std::{
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use crate::{
+    common::time::{clock_skew, insecure_posix_time},
+    identity::Identity,
+    TeeType, BUILD_INFO,
+};
+
+/// Name of the query method returning the runtime health report.
+pub const METHOD_HEALTH: &str = "runtime.Health";
+
+lazy_static! {
+    static ref HEALTH_MONITOR: HealthMonitor = HealthMonitor::new(Thresholds::default());
+}
+
+/// Health status of a subsystem or the runtime as a whole.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, cbor::Encode, cbor::Decode)]
+#[cbor(with_default)]
+#[repr(u8)]
+pub enum Status {
+    /// Operating normally.
+    Healthy = 0,
+    /// Operating, but close to or past a threshold.
+    Degraded = 1,
+    /// Not operating.
+    Unhealthy = 2,
+}
+
+impl Default for Status {
+    fn default() -> Self {
+        Self::Healthy
+    }
+}
+
+/// Health of a single subsystem.
+#[derive(Clone, Debug, Default, PartialEq, Eq, cbor::Encode, cbor::Decode)]
+pub struct SubsystemHealth {
+    /// Health status.
+    pub status: Status,
+    /// Human readable description of the status.
+    #[cbor(optional)]
+    pub message: String,
+}
+
+impl SubsystemHealth {
+    fn new(status: Status, message: impl Into<String>) -> Self {
+        Self {
+            status,
+            message: message.into(),
+        }
+    }
+}
+
+/// Runtime health report.
+#[derive(Clone, Debug, Default, PartialEq, Eq, cbor::Encode, cbor::Decode)]
+pub struct HealthReport {
+    /// Overall status, the worst of the subsystem statuses.
+    pub status: Status,
+    /// Time at which the report was generated, as seen by the runtime.
+    pub timestamp: i64,
+    /// Freshness of the latest block verified by the consensus verifier.
+    pub consensus_verifier: SubsystemHealth,
+    /// Readiness of the key manager.
+    pub key_manager: SubsystemHealth,
+    /// Latency of storage sync requests served by the host.
+    pub storage: SubsystemHealth,
+    /// Expiration of the attestation.
+    pub attestation: SubsystemHealth,
+    /// Liveness of the host.
+    pub host: SubsystemHealth,
+    /// Skew of the local clock.
+    #[cbor(optional)]
+    pub clock: SubsystemHealth,
+}
+
+/// Thresholds past which subsystems are considered degraded.
+#[derive(Clone, Debug)]
+pub struct Thresholds {
+    /// Maximum age, in seconds, of the latest verified consensus block.
+    pub max_consensus_block_age: i64,
+    /// Maximum latency of host storage sync requests.
+    pub max_storage_sync_latency: Duration,
+    /// Minimum remaining validity, in seconds, of the attestation.
+    pub min_attestation_validity: i64,
+    /// Maximum time, in seconds, since the last message received from the host.
+    pub max_host_silence: i64,
+    /// Maximum absolute skew, in seconds, of the local clock.
+    pub max_clock_skew: i64,
+}
+
+impl Default for Thresholds {
+    fn default() -> Self {
+        Self {
+            max_consensus_block_age: 60,
+            max_storage_sync_latency: Duration::from_secs(1),
+            min_attestation_validity: 60 * 60,
+            max_host_silence: 60,
+            max_clock_skew: 60,
+        }
+    }
+}
+
+#[derive(Default)]
+struct Observations {
+    consensus_block_time: Option<i64>,
+    key_manager_initialized: Option<bool>,
+    storage_sync_latency: Option<Duration>,
+    storage_degraded: bool,
+    storage_divergence: Option<u64>,
+    host_message_time: Option<i64>,
+    clock_skew: Option<i64>,
+}
+
+/// Monitor collecting subsystem observations for health reports.
+pub struct HealthMonitor {
+    thresholds: Thresholds,
+    inner: Mutex<Observations>,
+}
+
+impl HealthMonitor {
+    /// Create a new health monitor using the given thresholds.
+    pub fn new(thresholds: Thresholds) -> Self {
+        Self {
+            thresholds,
+            inner: Mutex::new(Observations::default()),
+        }
+    }
+
+    /// Global health monitor instance.
+    pub fn global() -> &'static HealthMonitor {
+        &HEALTH_MONITOR
+    }
+
+    /// Record the time of a block verified by the consensus verifier.
+    pub fn record_consensus_block(&self, time: i64) {
+        let mut inner = self.inner.lock().unwrap();
+        if inner.consensus_block_time < Some(time) {
+            inner.consensus_block_time = Some(time);
+        }
+    }
+
+    /// Record the latest key manager status.
+    pub fn record_key_manager_status(&self, is_initialized: bool) {
+        self.inner.lock().unwrap().key_manager_initialized = Some(is_initialized);
+    }
+
+    /// Record a host storage sync request which was sent at the given instant.
+    pub fn record_storage_sync(&self, start: Instant) {
+        self.inner.lock().unwrap().storage_sync_latency = Some(start.elapsed());
+    }
+
+    /// Record whether storage is degraded after repeated host storage sync failures.
+    pub fn record_storage_degraded(&self, degraded: bool) {
+        self.inner.lock().unwrap().storage_degraded = degraded;
+    }
+
+    /// Record a divergence of the host storage from the local commit of the given round.
+    ///
+    /// Divergences are not cleared, as the host storage can't be trusted afterwards.
+    pub fn record_storage_divergence(&self, round: u64) {
+        self.inner
+            .lock()
+            .unwrap()
+            .storage_divergence
+            .get_or_insert(round);
+    }
+
+    /// Record a message received from the host.
+    pub fn record_host_message(&self) {
+        let now = insecure_posix_time();
+        self.inner.lock().unwrap().host_message_time = Some(now);
+    }
+
+    /// Generate a health report for the runtime with the given identity.
+    pub fn report(&self, identity: &Identity) -> HealthReport {
+        let quote_expiration = match BUILD_INFO.tee_type {
+            TeeType::None => None,
+            _ => Some(identity.quote_expiration()),
+        };
+        self.inner.lock().unwrap().clock_skew = Some(clock_skew().last);
+        self.report_at(insecure_posix_time(), quote_expiration)
+    }
+
+    /// Generate a health report at the given time. The quote expiration should be `None` in case
+    /// the runtime is not attested.
+    fn report_at(&self, now: i64, quote_expiration: Option<Option<i64>>) -> HealthReport {
+        let inner = self.inner.lock().unwrap();
+        let thresholds = &self.thresholds;
+
+        let consensus_verifier = match inner.consensus_block_time {
+            None => SubsystemHealth::new(Status::Unhealthy, "no verified consensus block"),
+            Some(time) if now - time > thresholds.max_consensus_block_age => SubsystemHealth::new(
+                Status::Degraded,
+                format!("latest verified consensus block is {}s old", now - time),
+            ),
+            Some(_) => SubsystemHealth::default(),
+        };
+
+        let key_manager = match inner.key_manager_initialized {
+            None => SubsystemHealth::new(Status::Healthy, "no key manager status"),
+            Some(false) => SubsystemHealth::new(Status::Unhealthy, "key manager not initialized"),
+            Some(true) => SubsystemHealth::default(),
+        };
+
+        let storage = match (inner.storage_divergence, inner.storage_sync_latency) {
+            (Some(round), _) => SubsystemHealth::new(
+                Status::Unhealthy,
+                format!("storage diverged in round {round}"),
+            ),
+            _ if inner.storage_degraded => {
+                SubsystemHealth::new(Status::Unhealthy, "storage degraded")
+            }
+            (_, Some(latency)) if latency > thresholds.max_storage_sync_latency => {
+                SubsystemHealth::new(
+                    Status::Degraded,
+                    format!("storage sync latency is {}ms", latency.as_millis()),
+                )
+            }
+            _ => SubsystemHealth::default(),
+        };
+
+        let attestation = match quote_expiration {
+            None => SubsystemHealth::new(Status::Healthy, "not attested"),
+            Some(None) => SubsystemHealth::new(Status::Unhealthy, "no fresh quote"),
+            Some(Some(expiration)) if expiration - now < thresholds.min_attestation_validity => {
+                SubsystemHealth::new(
+                    Status::Degraded,
+                    format!("quote expires in {}s", expiration - now),
+                )
+            }
+            Some(Some(_)) => SubsystemHealth::default(),
+        };
+
+        let host = match inner.host_message_time {
+            None => SubsystemHealth::new(Status::Unhealthy, "no message received from host"),
+            Some(time) if now - time > thresholds.max_host_silence => SubsystemHealth::new(
+                Status::Degraded,
+                format!("last message received from host {}s ago", now - time),
+            ),
+            Some(_) => SubsystemHealth::default(),
+        };
+
+        let clock = match inner.clock_skew {
+            Some(skew) if skew.abs() > thresholds.max_clock_skew => {
+                SubsystemHealth::new(Status::Degraded, format!("local clock skew is {skew}s"))
+            }
+            _ => SubsystemHealth::default(),
+        };
+
+        let status = [
+            &consensus_verifier,
+            &key_manager,
+            &storage,
+            &attestation,
+            &host,
+            &clock,
+        ]
+        .iter()
+        .map(|health| health.status)
+        .max()
+        .unwrap();
+
+        HealthReport {
+            status,
+            timestamp: now,
+            consensus_verifier,
+            key_manager,
+            storage,
+            attestation,
+            host,
+            clock,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_health_report() {
+        let monitor = HealthMonitor::new(Thresholds::default());
+        let now = 1_000_000;
+
+        let report = monitor.report_at(now, None);
+        assert_eq!(report.status, Status::Unhealthy);
+        assert_eq!(report.consensus_verifier.status, Status::Unhealthy);
+        assert_eq!(report.key_manager.status, Status::Healthy);
+        assert_eq!(report.storage.status, Status::Healthy);
+        assert_eq!(report.attestation.status, Status::Healthy);
+        assert_eq!(report.host.status, Status::Unhealthy);
+
+        monitor.record_consensus_block(now - 10);
+        monitor.record_key_manager_status(true);
+        monitor.inner.lock().unwrap().host_message_time = Some(now);
+        let report = monitor.report_at(now, Some(Some(now + 24 * 60 * 60)));
+        assert_eq!(report.status, Status::Healthy);
+        assert_eq!(report.timestamp, now);
+
+        // Older blocks don't affect freshness.
+        monitor.record_consensus_block(now - 1000);
+        assert_eq!(monitor.report_at(now, None).status, Status::Healthy);
+
+        // Subsystems past their thresholds are degraded.
+        let report = monitor.report_at(now + 100, Some(Some(now + 60)));
+        assert_eq!(report.status, Status::Degraded);
+        assert_eq!(report.consensus_verifier.status, Status::Degraded);
+        assert_eq!(
+            report.consensus_verifier.message,
+            "latest verified consensus block is 110s old"
+        );
+        assert_eq!(report.attestation.status, Status::Degraded);
+        assert_eq!(report.host.status, Status::Degraded);
+
+        monitor.inner.lock().unwrap().storage_sync_latency = Some(Duration::from_secs(2));
+        let report = monitor.report_at(now, None);
+        assert_eq!(report.status, Status::Degraded);
+        assert_eq!(report.storage.message, "storage sync latency is 2000ms");
+
+        monitor.record_storage_degraded(true);
+        let report = monitor.report_at(now, None);
+        assert_eq!(report.storage.status, Status::Unhealthy);
+        assert_eq!(report.storage.message, "storage degraded");
+        monitor.record_storage_degraded(false);
+
+        monitor.record_storage_divergence(7);
+        monitor.record_storage_divergence(8);
+        let report = monitor.report_at(now, None);
+        assert_eq!(report.storage.status, Status::Unhealthy);
+        assert_eq!(report.storage.message, "storage diverged in round 7");
+        monitor.inner.lock().unwrap().storage_divergence = None;
+
+        monitor.inner.lock().unwrap().clock_skew = Some(-120);
+        let report = monitor.report_at(now, None);
+        assert_eq!(report.clock.status, Status::Degraded);
+        assert_eq!(report.clock.message, "local clock skew is -120s");
+
+        // Missing quotes and uninitialized key managers are unhealthy.
+        monitor.record_key_manager_status(false);
+        let report = monitor.report_at(now, Some(None));
+        assert_eq!(report.status, Status::Unhealthy);
+        assert_eq!(report.key_manager.status, Status::Unhealthy);
+        assert_eq!(report.attestation.status, Status::Unhealthy);
+    }
+}
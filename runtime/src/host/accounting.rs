@@ -0,0 +1,300 @@
+//! Accounting of host calls per subsystem.
+//!
+//! All subsystems share a single channel to the host. To prevent one of them from starving the
+//! others, the calls made and bytes exchanged are accounted per subsystem over one second windows.
+//! Exceeding a soft limit emits a warning, while exceeding a hard limit causes further low-priority
+//! calls of the subsystem to be rejected with `ProtocolError::RateLimited` until the window ends.
+//! High-priority calls, which are needed to make progress on requests from the host (e.g. storage
+//! sync), are accounted but never rejected.
+use std::{
+    collections::BTreeMap,
+    fmt,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use slog::{warn, Logger};
+
+use crate::{
+    common::logger::get_logger,
+    config::{HostCallLimits, SubsystemLimits},
+    protocol::ProtocolError,
+    types::Body,
+};
+
+/// Length of an accounting window.
+const WINDOW: Duration = Duration::from_secs(1);
+
+/// Subsystem on whose behalf a message is exchanged with the host.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Subsystem {
+    /// Storage sync.
+    StorageSync,
+    /// EnclaveRPC calls to remote nodes.
+    Rpc,
+    /// Transaction submission.
+    TxSubmit,
+    /// Everything else, including responses to host requests.
+    Other,
+}
+
+impl Subsystem {
+    /// Subsystem of the given message body.
+    pub fn of(body: &Body) -> Self {
+        match body {
+            Body::HostStorageSyncRequest(_) | Body::HostStorageSyncResponse(_) => Self::StorageSync,
+            Body::HostRPCCallRequest { .. }
+            | Body::HostRPCCallResponse { .. }
+            | Body::HostRPCStreamRequest { .. }
+            | Body::HostRPCStreamResponse { .. }
+            | Body::HostSubmitPeerFeedbackRequest { .. }
+            | Body::HostSubmitPeerFeedbackResponse {} => Self::Rpc,
+            Body::HostSubmitTxRequest { .. }
+            | Body::HostSubmitTxResponse { .. }
+            | Body::HostSubmitTxBatchRequest { .. }
+            | Body::HostSubmitTxBatchResponse { .. } => Self::TxSubmit,
+            _ => Self::Other,
+        }
+    }
+}
+
+impl fmt::Display for Subsystem {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Subsystem::StorageSync => "storage sync",
+            Subsystem::Rpc => "rpc",
+            Subsystem::TxSubmit => "tx submit",
+            Subsystem::Other => "other",
+        };
+        f.write_str(name)
+    }
+}
+
+/// Priority of a host call.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Priority {
+    /// The call may be rejected when the subsystem exceeds its hard limit.
+    Low,
+    /// The call is never rejected.
+    High,
+}
+
+impl Priority {
+    /// Priority of the given request body.
+    pub fn of(body: &Body) -> Self {
+        match body {
+            Body::HostSubmitTxRequest { .. }
+            | Body::HostSubmitTxBatchRequest { .. }
+            | Body::HostSubmitPeerFeedbackRequest { .. }
+            | Body::HostHealthReportRequest { .. }
+            | Body::HostLogRecordsRequest { .. }
+            | Body::HostMetricsPushRequest { .. }
+            | Body::HostShadowDivergenceRequest { .. } => Self::Low,
+            _ => Self::High,
+        }
+    }
+}
+
+/// Accounting statistics of a subsystem since the runtime started.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct SubsystemStats {
+    /// Number of calls made.
+    pub calls: u64,
+    /// Number of bytes sent and received.
+    pub bytes: u64,
+    /// Number of rejected calls.
+    pub shed: u64,
+}
+
+#[derive(Default)]
+struct Usage {
+    window_start: Option<Instant>,
+    calls: u64,
+    bytes: u64,
+    warned: bool,
+    stats: SubsystemStats,
+}
+
+impl Usage {
+    /// Start a new window in case the current one has ended.
+    fn roll(&mut self, now: Instant) {
+        if matches!(self.window_start, Some(start) if now.duration_since(start) < WINDOW) {
+            return;
+        }
+        self.window_start = Some(now);
+        self.calls = 0;
+        self.bytes = 0;
+        self.warned = false;
+    }
+}
+
+/// Per-subsystem host call accounting.
+pub struct HostCallAccounting {
+    logger: Logger,
+    limits: HostCallLimits,
+    unlimited: SubsystemLimits,
+    usage: Mutex<BTreeMap<Subsystem, Usage>>,
+}
+
+impl HostCallAccounting {
+    /// Create a new accounting instance enforcing the given limits.
+    pub fn new(limits: HostCallLimits) -> Self {
+        Self {
+            logger: get_logger("runtime/protocol/accounting"),
+            limits,
+            unlimited: SubsystemLimits::default(),
+            usage: Mutex::new(BTreeMap::new()),
+        }
+    }
+
+    /// Account a call with the given request body, rejecting it in case it is low-priority and
+    /// its subsystem exceeds the hard limit.
+    pub fn admit(&self, body: &Body) -> Result<(), ProtocolError> {
+        self.admit_at(body, Instant::now())
+    }
+
+    /// Account bytes exchanged with the host on behalf of the given subsystem.
+    pub fn record_bytes(&self, subsystem: Subsystem, size: usize) {
+        self.record_bytes_at(subsystem, size, Instant::now())
+    }
+
+    /// Accounting statistics of all subsystems that exchanged messages with the host.
+    pub fn stats(&self) -> BTreeMap<Subsystem, SubsystemStats> {
+        self.usage
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|(subsystem, usage)| (*subsystem, usage.stats.clone()))
+            .collect()
+    }
+
+    fn admit_at(&self, body: &Body, now: Instant) -> Result<(), ProtocolError> {
+        let subsystem = Subsystem::of(body);
+        let limits = self.limits(subsystem);
+        let mut usage = self.usage.lock().unwrap();
+        let usage = usage.entry(subsystem).or_default();
+        usage.roll(now);
+
+        if Priority::of(body) == Priority::Low
+            && limits.hard.is_exceeded(usage.calls + 1, usage.bytes)
+        {
+            usage.stats.shed += 1;
+            return Err(ProtocolError::RateLimited(subsystem));
+        }
+
+        usage.calls += 1;
+        usage.stats.calls += 1;
+        self.check_soft_limit(subsystem, limits, usage);
+
+        Ok(())
+    }
+
+    fn record_bytes_at(&self, subsystem: Subsystem, size: usize, now: Instant) {
+        let limits = self.limits(subsystem);
+        let mut usage = self.usage.lock().unwrap();
+        let usage = usage.entry(subsystem).or_default();
+        usage.roll(now);
+
+        usage.bytes += size as u64;
+        usage.stats.bytes += size as u64;
+        self.check_soft_limit(subsystem, limits, usage);
+    }
+
+    fn check_soft_limit(&self, subsystem: Subsystem, limits: &SubsystemLimits, usage: &mut Usage) {
+        if usage.warned || !limits.soft.is_exceeded(usage.calls, usage.bytes) {
+            return;
+        }
+        usage.warned = true;
+
+        warn!(self.logger, "Host call soft limit exceeded";
+            "subsystem" => %subsystem,
+            "calls" => usage.calls,
+            "bytes" => usage.bytes,
+        );
+    }
+
+    fn limits(&self, subsystem: Subsystem) -> &SubsystemLimits {
+        match subsystem {
+            Subsystem::StorageSync => &self.limits.storage_sync,
+            Subsystem::Rpc => &self.limits.rpc,
+            Subsystem::TxSubmit => &self.limits.tx_submit,
+            Subsystem::Other => &self.unlimited,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{
+        common::namespace::Namespace,
+        config::Rate,
+        storage::mkvs::sync::GetRequest,
+        types::{HostStorageEndpoint, StorageSyncRequest, StorageSyncRequestWithEndpoint},
+    };
+
+    #[test]
+    fn test_host_call_accounting() {
+        let accounting = HostCallAccounting::new(HostCallLimits {
+            tx_submit: SubsystemLimits {
+                soft: Rate {
+                    max_calls: 1,
+                    ..Default::default()
+                },
+                hard: Rate {
+                    max_calls: 2,
+                    max_bytes: 100,
+                },
+            },
+            storage_sync: SubsystemLimits {
+                hard: Rate {
+                    max_calls: 1,
+                    ..Default::default()
+                },
+                ..Default::default()
+            },
+            ..Default::default()
+        });
+        let submit_tx = Body::HostSubmitTxRequest {
+            runtime_id: Namespace::default(),
+            data: vec![],
+            wait: false,
+            prove: false,
+        };
+        let sync = Body::HostStorageSyncRequest(StorageSyncRequestWithEndpoint {
+            endpoint: HostStorageEndpoint::Runtime,
+            request: StorageSyncRequest::SyncGet(GetRequest::default()),
+            excluded_sources: vec![],
+        });
+
+        // Low-priority calls are shed past the hard limit until the window ends.
+        let now = Instant::now();
+        assert!(accounting.admit_at(&submit_tx, now).is_ok());
+        assert!(accounting.admit_at(&submit_tx, now).is_ok());
+        assert!(matches!(
+            accounting.admit_at(&submit_tx, now),
+            Err(ProtocolError::RateLimited(Subsystem::TxSubmit))
+        ));
+        let now = now + WINDOW;
+        assert!(accounting.admit_at(&submit_tx, now).is_ok());
+        accounting.record_bytes_at(Subsystem::TxSubmit, 101, now);
+        assert!(accounting.admit_at(&submit_tx, now).is_err());
+
+        // High-priority calls are never shed.
+        for _ in 0..3 {
+            assert!(accounting.admit_at(&sync, now).is_ok());
+        }
+
+        let stats = accounting.stats();
+        assert_eq!(
+            stats[&Subsystem::TxSubmit],
+            SubsystemStats {
+                calls: 3,
+                bytes: 101,
+                shed: 2,
+            }
+        );
+        assert_eq!(stats[&Subsystem::StorageSync].calls, 3);
+        assert!(!stats.contains_key(&Subsystem::Rpc));
+    }
+}
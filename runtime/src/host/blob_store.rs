@@ -0,0 +1,146 @@
+//! Content-addressed blob store.
+//!
+//! Blob volumes store blobs addressed by the hash of their contents, so that many overlapping
+//! artifacts (e.g. model shards or bundle layers) can be stored without duplicating bytes, with
+//! the host deduplicating identical blobs. As the host is not trusted, all blobs are verified
+//! against their hashes inside the enclave.
+//!
+//! Blobs are pinned when stored and can be pinned again by each of their users. Once a blob is
+//! no longer pinned, the host may remove it.
+use std::sync::Arc;
+
+use crate::common::crypto::hash::Hash;
+
+use super::{
+    volume_manager::{
+        VolumeAddRequest, VolumeBlobGetRequest, VolumeBlobPinRequest, VolumeBlobPutRequest,
+        VolumeManager, VolumeMode,
+    },
+    Error,
+};
+
+/// A blob volume whose blobs are verified against their hashes.
+pub struct BlobStore {
+    manager: Arc<dyn VolumeManager>,
+    id: String,
+}
+
+impl BlobStore {
+    /// Open the blob volume with the given identifier.
+    pub fn open(manager: Arc<dyn VolumeManager>, id: String) -> Self {
+        Self { manager, id }
+    }
+
+    /// Add a new blob volume with the given request and open it.
+    pub async fn create(
+        manager: Arc<dyn VolumeManager>,
+        args: VolumeAddRequest,
+    ) -> Result<Self, Error> {
+        let rsp = manager
+            .volume_add(VolumeAddRequest {
+                mode: VolumeMode::Blobs,
+                ..args
+            })
+            .await?;
+        Ok(Self::open(manager, rsp.id))
+    }
+
+    /// Identifier of the volume.
+    pub fn id(&self) -> &str {
+        &self.id
+    }
+
+    /// Store the given blob, pinning it, and return its hash.
+    pub async fn put(&self, data: Vec<u8>) -> Result<Hash, Error> {
+        let hash = Hash::digest_bytes(&data);
+        let rsp = self
+            .manager
+            .volume_blob_put(VolumeBlobPutRequest {
+                id: self.id.clone(),
+                data,
+            })
+            .await?;
+        if rsp.hash != hash {
+            return Err(Error::BlobMismatch);
+        }
+
+        Ok(hash)
+    }
+
+    /// Fetch the blob with the given hash, if it is stored.
+    pub async fn get(&self, hash: &Hash) -> Result<Option<Vec<u8>>, Error> {
+        let rsp = self
+            .manager
+            .volume_blob_get(VolumeBlobGetRequest {
+                id: self.id.clone(),
+                hash: *hash,
+            })
+            .await?;
+        match rsp.data {
+            Some(data) if Hash::digest_bytes(&data) != *hash => Err(Error::BlobMismatch),
+            data => Ok(data),
+        }
+    }
+
+    /// Pin the blob with the given hash, returning the resulting number of pins.
+    pub async fn pin(&self, hash: &Hash) -> Result<u64, Error> {
+        let rsp = self
+            .manager
+            .volume_blob_pin(VolumeBlobPinRequest {
+                id: self.id.clone(),
+                hash: *hash,
+            })
+            .await?;
+        Ok(rsp.pins)
+    }
+
+    /// Unpin the blob with the given hash, returning the remaining number of pins.
+    pub async fn unpin(&self, hash: &Hash) -> Result<u64, Error> {
+        let rsp = self
+            .manager
+            .volume_blob_unpin(VolumeBlobPinRequest {
+                id: self.id.clone(),
+                hash: *hash,
+            })
+            .await?;
+        Ok(rsp.pins)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use futures::executor::block_on;
+
+    use super::*;
+    use crate::testkit::host::MockHost;
+
+    #[test]
+    fn test_blob_store() {
+        block_on(async {
+            let manager = Arc::new(MockHost::new());
+            let store = BlobStore::create(manager.clone(), VolumeAddRequest::default())
+                .await
+                .unwrap();
+            assert_eq!(store.id(), "volume-0");
+
+            // Identical blobs are stored once.
+            let hash = store.put(b"layer".to_vec()).await.unwrap();
+            assert_eq!(hash, Hash::digest_bytes(b"layer"));
+            assert_eq!(store.put(b"layer".to_vec()).await.unwrap(), hash);
+            assert_eq!(manager.volume_blob_count(store.id()), 1);
+            assert_eq!(store.get(&hash).await.unwrap(), Some(b"layer".to_vec()));
+
+            // Blobs are removed once no longer pinned.
+            assert_eq!(store.pin(&hash).await.unwrap(), 3);
+            for pins in (0..3).rev() {
+                assert_eq!(store.unpin(&hash).await.unwrap(), pins);
+            }
+            assert_eq!(store.get(&hash).await.unwrap(), None);
+
+            // Tampered blobs are rejected.
+            let hash = store.put(b"shard".to_vec()).await.unwrap();
+            manager.set_volume_blob(store.id(), hash, b"shard!".to_vec());
+            assert!(matches!(store.get(&hash).await, Err(Error::BlobMismatch)));
+        });
+    }
+}
@@ -1,10 +1,21 @@
-use std::collections::BTreeMap;
+use std::collections::{BTreeMap, BTreeSet};
 
 use async_trait::async_trait;
+use base64::prelude::*;
 
-use crate::{common::crypto::hash::Hash, protocol::Protocol};
+use crate::{
+    common::{
+        crypto::{
+            hash::Hash,
+            signature::{PublicKey, SignatureBundle},
+        },
+        pagination::Pagination,
+    },
+    protocol::Protocol,
+    types::ProtocolFeature,
+};
 
-use super::{host_rpc_call, Error};
+use super::{host_rpc_call, Error, RetryPolicy};
 
 /// Name of the local RPC endpoint for the bundle manager.
 pub const LOCAL_RPC_ENDPOINT_BUNDLE_MANAGER: &str = "bundle-manager";
@@ -17,10 +28,171 @@ pub const METHOD_BUNDLE_ADD: &str = "BundleAdd";
 pub const METHOD_BUNDLE_REMOVE: &str = "BundleRemove";
 /// Name of the BundleList method.
 pub const METHOD_BUNDLE_LIST: &str = "BundleList";
+/// Name of the BundleMigrate method.
+pub const METHOD_BUNDLE_MIGRATE: &str = "BundleMigrate";
+/// Name of the BundleFetchInfo method.
+pub const METHOD_BUNDLE_FETCH_INFO: &str = "BundleFetchInfo";
+/// Name of the BundleFetchChunk method.
+pub const METHOD_BUNDLE_FETCH_CHUNK: &str = "BundleFetchChunk";
 
 /// Name of the special label that identifies the instance.
 pub const LABEL_INSTANCE_ID: &str = "net.oasis.instance_id";
 
+/// Signature context used for detached bundle manifest signatures.
+pub const MANIFEST_SIGNATURE_CONTEXT: &[u8] = b"oasis-core/bundle: manifest";
+
+/// Name of the manifest file inside a bundle.
+pub const MANIFEST_NAME: &str = "META-INF/MANIFEST.MF";
+
+/// Bundle manifest, as far as relevant for verification.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Manifest {
+    /// Digests of all the files contained in the bundle, keyed by file name.
+    pub digests: BTreeMap<String, Hash>,
+    /// Chunks of the bundle, in case it can be fetched in chunks.
+    pub chunks: Option<ManifestChunks>,
+}
+
+/// Split of a bundle into fixed-size chunks, as declared by its manifest.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct ManifestChunks {
+    /// Size of each chunk in bytes. Only the last chunk may be smaller.
+    pub chunk_size: u32,
+    /// Total size of the bundle in bytes.
+    pub size: u64,
+    /// Digests of the chunks, in order.
+    pub digests: Vec<Hash>,
+}
+
+impl Manifest {
+    /// Parse the given raw (JSON-encoded) manifest.
+    pub fn parse(raw: &[u8]) -> Result<Self, Error> {
+        #[derive(serde::Deserialize)]
+        struct RawManifest {
+            #[serde(default)]
+            digests: BTreeMap<String, String>,
+            #[serde(default)]
+            chunks: Option<RawChunks>,
+        }
+
+        #[derive(serde::Deserialize)]
+        struct RawChunks {
+            chunk_size: u32,
+            size: u64,
+            digests: Vec<String>,
+        }
+
+        let manifest: RawManifest =
+            serde_json::from_slice(raw).map_err(|_| Error::UntrustedBundle)?;
+        let digests = manifest
+            .digests
+            .into_iter()
+            .map(|(name, digest)| Ok((name, parse_digest(&digest)?)))
+            .collect::<Result<_, Error>>()?;
+        let chunks = manifest
+            .chunks
+            .map(|chunks| {
+                let digests = chunks
+                    .digests
+                    .into_iter()
+                    .map(|digest| parse_digest(&digest))
+                    .collect::<Result<Vec<_>, Error>>()?;
+                if chunks.chunk_size == 0
+                    || digests.len() as u64 != chunks.size.div_ceil(chunks.chunk_size as u64)
+                {
+                    return Err(Error::UntrustedBundle);
+                }
+                Ok(ManifestChunks {
+                    chunk_size: chunks.chunk_size,
+                    size: chunks.size,
+                    digests,
+                })
+            })
+            .transpose()?;
+
+        Ok(Self { digests, chunks })
+    }
+}
+
+/// Parse a base64-encoded digest.
+fn parse_digest(digest: &str) -> Result<Hash, Error> {
+    BASE64_STANDARD
+        .decode(digest)
+        .ok()
+        .and_then(|digest| <[u8; 32]>::try_from(digest).ok())
+        .map(Hash)
+        .ok_or(Error::UntrustedBundle)
+}
+
+/// Set of keys trusted to sign bundle manifests, e.g. the runtime governance key or a set of
+/// bundle author keys.
+#[derive(Clone, Debug, Default)]
+pub struct BundleTrustRoot {
+    /// Public keys of the trusted signers.
+    pub signers: Vec<PublicKey>,
+    /// Number of distinct trusted signers required to sign a manifest. A zero threshold is
+    /// treated as one.
+    pub threshold: usize,
+}
+
+impl BundleTrustRoot {
+    /// Verify that the manifest with the given hash has been signed by enough trusted signers.
+    ///
+    /// Signatures by untrusted signers and invalid signatures are ignored.
+    pub fn verify(
+        &self,
+        manifest_hash: &Hash,
+        signatures: &[SignatureBundle],
+    ) -> Result<(), Error> {
+        let signers: BTreeSet<_> = signatures
+            .iter()
+            .filter(|sig| self.signers.contains(&sig.public_key))
+            .filter(|sig| sig.verify(MANIFEST_SIGNATURE_CONTEXT, manifest_hash.as_ref()))
+            .map(|sig| sig.public_key)
+            .collect();
+        if signers.len() < self.threshold.max(1) {
+            return Err(Error::UntrustedBundle);
+        }
+        Ok(())
+    }
+
+    /// Verify that the given manifest has been signed by enough trusted signers, returning its
+    /// hash and parsed contents.
+    pub fn verify_manifest(
+        &self,
+        manifest: &[u8],
+        signatures: &[SignatureBundle],
+    ) -> Result<(Hash, Manifest), Error> {
+        let manifest_hash = Hash::digest_bytes(manifest);
+        self.verify(&manifest_hash, signatures)?;
+        Ok((manifest_hash, Manifest::parse(manifest)?))
+    }
+
+    /// Verify that the bundle consisting of the given manifest and files has been signed by
+    /// enough trusted signers, returning the manifest hash.
+    ///
+    /// Every file must match its digest in the signed manifest and the manifest must not list
+    /// any files that are missing from the bundle.
+    pub fn verify_bundle(
+        &self,
+        manifest: &[u8],
+        files: &BTreeMap<String, Vec<u8>>,
+        signatures: &[SignatureBundle],
+    ) -> Result<Hash, Error> {
+        let (manifest_hash, manifest) = self.verify_manifest(manifest, signatures)?;
+        let files = files.iter().filter(|(name, _)| *name != MANIFEST_NAME);
+        if files.clone().count() != manifest.digests.len() {
+            return Err(Error::UntrustedBundle);
+        }
+        for (name, data) in files {
+            if manifest.digests.get(name) != Some(&Hash::digest_bytes(data)) {
+                return Err(Error::UntrustedBundle);
+            }
+        }
+        Ok(manifest_hash)
+    }
+}
+
 /// Bundle manager interface.
 #[async_trait]
 pub trait BundleManager: Send + Sync {
@@ -31,6 +203,12 @@ pub trait BundleManager: Send + Sync {
 
     /// Request to host to add a specific bundle to the host.
     ///
+    /// In case a bundle trust root is configured, the manifest must be included in the request
+    /// and is verified against the trust root before the request is made. The signed manifest
+    /// binds the digests of all files of the bundle, which the host verifies when opening it.
+    /// Callers holding the bundle contents should verify them via
+    /// [`BundleTrustRoot::verify_bundle`] before writing the bundle.
+    ///
     /// The `PermissionBundleAdd` permission is required to call this method.
     async fn bundle_add(&self, args: BundleAddRequest) -> Result<BundleAddResponse, Error>;
 
@@ -45,6 +223,96 @@ pub trait BundleManager: Send + Sync {
     ///
     /// The `PermissionBundleAdd` permission is required to call this method.
     async fn bundle_list(&self, args: BundleListRequest) -> Result<BundleListResponse, Error>;
+
+    /// Request to host to relay a sealed secret migration request to the component that is being
+    /// upgraded and return its response.
+    ///
+    /// See `common::sgx::migration` for details on the migration protocol.
+    ///
+    /// The `PermissionBundleAdd` permission is required to call this method.
+    async fn bundle_migrate(
+        &self,
+        args: BundleMigrateRequest,
+    ) -> Result<BundleMigrateResponse, Error>;
+
+    /// Request to host to return the manifest of a bundle, declaring how it is split into chunks.
+    ///
+    /// The `PermissionBundleAdd` permission is required to call this method.
+    async fn bundle_fetch_info(
+        &self,
+        args: BundleFetchInfoRequest,
+    ) -> Result<BundleFetchInfoResponse, Error>;
+
+    /// Request to host to return a single chunk of a bundle.
+    ///
+    /// The `PermissionBundleAdd` permission is required to call this method.
+    async fn bundle_fetch_chunk(
+        &self,
+        args: BundleFetchChunkRequest,
+    ) -> Result<BundleFetchChunkResponse, Error>;
+
+    /// Fetch a bundle from the host in fixed-size chunks, passing each chunk to the given
+    /// callback as soon as it has been verified against its digest, so that the whole bundle
+    /// never needs to be held in memory.
+    ///
+    /// The chunk digests are taken from the bundle manifest, which must match the given manifest
+    /// hash, so the chunks are as trusted as the manifest hash (e.g. when it has been verified
+    /// against a `BundleTrustRoot`). Returns the chunks declared by the manifest.
+    ///
+    /// An interrupted transfer can be resumed by setting `BundleFetchRequest::start` to the number
+    /// of chunks that have already been received.
+    ///
+    /// The `PermissionBundleAdd` permission is required to call this method.
+    async fn fetch_chunked(
+        &self,
+        args: BundleFetchRequest,
+        on_chunk: &mut (dyn FnMut(BundleChunk) + Send),
+    ) -> Result<ManifestChunks, Error> {
+        let info = self
+            .bundle_fetch_info(BundleFetchInfoRequest {
+                manifest_hash: args.manifest_hash,
+            })
+            .await?;
+        if Hash::digest_bytes(&info.manifest) != args.manifest_hash {
+            return Err(Error::BlobMismatch);
+        }
+        let chunks = Manifest::parse(&info.manifest)?
+            .chunks
+            .ok_or(Error::UntrustedBundle)?;
+        let chunk_size = chunks.chunk_size as u64;
+        let total_chunks = chunks.digests.len() as u64;
+
+        for index in args.start..total_chunks {
+            let rsp = self
+                .bundle_fetch_chunk(BundleFetchChunkRequest {
+                    manifest_hash: args.manifest_hash,
+                    chunk_size: chunks.chunk_size,
+                    index,
+                })
+                .await?;
+
+            let offset = index * chunk_size;
+            let expected_len = chunk_size.min(chunks.size - offset);
+            if rsp.data.len() as u64 != expected_len
+                || Hash::digest_bytes(&rsp.data) != chunks.digests[index as usize]
+            {
+                return Err(Error::BadResponse);
+            }
+
+            on_chunk(BundleChunk {
+                index,
+                data: rsp.data,
+                progress: FetchProgress {
+                    chunks: index + 1,
+                    total_chunks,
+                    bytes: offset + expected_len,
+                    total_bytes: chunks.size,
+                },
+            });
+        }
+
+        Ok(chunks)
+    }
 }
 
 #[async_trait]
@@ -55,16 +323,26 @@ impl BundleManager for Protocol {
             LOCAL_RPC_ENDPOINT_BUNDLE_MANAGER,
             METHOD_BUNDLE_WRITE,
             args,
+            &RetryPolicy::none(),
         )
         .await
     }
 
     async fn bundle_add(&self, args: BundleAddRequest) -> Result<BundleAddResponse, Error> {
+        if let Some(trust_root) = &self.get_config().bundle_trust_root {
+            let (manifest_hash, manifest) =
+                trust_root.verify_manifest(&args.manifest, &args.manifest_signatures)?;
+            if manifest_hash != args.manifest_hash || manifest.digests.is_empty() {
+                return Err(Error::UntrustedBundle);
+            }
+        }
+
         host_rpc_call(
             self,
             LOCAL_RPC_ENDPOINT_BUNDLE_MANAGER,
             METHOD_BUNDLE_ADD,
             args,
+            &RetryPolicy::none(),
         )
         .await
     }
@@ -78,21 +356,84 @@ impl BundleManager for Protocol {
             LOCAL_RPC_ENDPOINT_BUNDLE_MANAGER,
             METHOD_BUNDLE_REMOVE,
             args,
+            &RetryPolicy::none(),
         )
         .await
     }
 
     async fn bundle_list(&self, args: BundleListRequest) -> Result<BundleListResponse, Error> {
-        host_rpc_call(
+        let args = BundleListRequest {
+            pagination: args.pagination.normalize(),
+            ..args
+        };
+        let pagination = args.pagination.clone();
+        let rsp: BundleListResponse = host_rpc_call(
             self,
             LOCAL_RPC_ENDPOINT_BUNDLE_MANAGER,
             METHOD_BUNDLE_LIST,
             args,
+            &self.get_config().host_query_retry,
+        )
+        .await?;
+        pagination.check(rsp.bundles.len())?;
+
+        Ok(rsp)
+    }
+
+    async fn bundle_migrate(
+        &self,
+        args: BundleMigrateRequest,
+    ) -> Result<BundleMigrateResponse, Error> {
+        host_rpc_call(
+            self,
+            LOCAL_RPC_ENDPOINT_BUNDLE_MANAGER,
+            METHOD_BUNDLE_MIGRATE,
+            args,
+            &RetryPolicy::none(),
+        )
+        .await
+    }
+
+    async fn bundle_fetch_info(
+        &self,
+        args: BundleFetchInfoRequest,
+    ) -> Result<BundleFetchInfoResponse, Error> {
+        ensure_chunked_bundles(self)?;
+        host_rpc_call(
+            self,
+            LOCAL_RPC_ENDPOINT_BUNDLE_MANAGER,
+            METHOD_BUNDLE_FETCH_INFO,
+            args,
+            &self.get_config().host_query_retry,
+        )
+        .await
+    }
+
+    async fn bundle_fetch_chunk(
+        &self,
+        args: BundleFetchChunkRequest,
+    ) -> Result<BundleFetchChunkResponse, Error> {
+        ensure_chunked_bundles(self)?;
+        host_rpc_call(
+            self,
+            LOCAL_RPC_ENDPOINT_BUNDLE_MANAGER,
+            METHOD_BUNDLE_FETCH_CHUNK,
+            args,
+            &self.get_config().host_query_retry,
         )
         .await
     }
 }
 
+/// Fail in case chunked bundle transfers have not been negotiated with the host.
+fn ensure_chunked_bundles(protocol: &Protocol) -> Result<(), Error> {
+    let feature = ProtocolFeature::ChunkedBundles;
+    if !protocol.features().contains(feature) {
+        return Err(Error::FeatureNotNegotiated(feature.name()));
+    }
+    Ok(())
+}
+
 /// Request to host to write a chunk of the bundle to a temporary file.
 ///
 /// The `PermissionBundleAdd` permission is required to call this method.
@@ -133,6 +474,14 @@ pub struct BundleAddRequest {
     pub labels: BTreeMap<String, String>,
     /// Volumes to attach to the bundle.
     pub volumes: BTreeMap<String, String>,
+    /// Detached signatures over the manifest hash, required in case a bundle trust root is
+    /// configured.
+    #[cbor(optional)]
+    pub manifest_signatures: Vec<SignatureBundle>,
+    /// Raw manifest contained inside the bundle, required in case a bundle trust root is
+    /// configured. The host must refuse to add the bundle in case its manifest differs.
+    #[cbor(optional)]
+    pub manifest: Vec<u8>,
 }
 
 /// Response form the BundleAdd method.
@@ -160,6 +509,9 @@ pub struct BundleRemoveResponse {}
 pub struct BundleListRequest {
     /// Labels to filter the components by.
     pub labels: BTreeMap<String, String>,
+    /// Page of bundles to return.
+    #[cbor(optional)]
+    pub pagination: Pagination,
 }
 
 /// Response from host to list all bundles.
@@ -168,6 +520,97 @@ pub struct BundleListResponse {
     /// The resulting bundles.
     #[cbor(optional)]
     pub bundles: Vec<BundleInfo>,
+    /// Token for listing the next page of bundles, if there are more bundles.
+    #[cbor(optional)]
+    pub continuation_token: Option<Vec<u8>>,
+}
+
+/// Request to host to relay a sealed secret migration request to the component that is being
+/// upgraded, which answers it via its `common::sgx::migration::METHOD_MIGRATE` local RPC method.
+///
+/// The `PermissionBundleAdd` permission is required to call this method.
+#[derive(Clone, Debug, Default, cbor::Encode, cbor::Decode)]
+pub struct BundleMigrateRequest {
+    /// Labels identifying the component that the secrets should be migrated from.
+    pub labels: BTreeMap<String, String>,
+    /// CBOR-serialized migration request (`common::sgx::migration::MigrationRequest`).
+    pub request: Vec<u8>,
+}
+
+/// Response from the BundleMigrate method.
+#[derive(Clone, Debug, Default, cbor::Encode, cbor::Decode)]
+pub struct BundleMigrateResponse {
+    /// CBOR-serialized migration response (`common::sgx::migration::MigrationResponse`).
+    pub response: Vec<u8>,
+}
+
+/// Parameters of a chunked bundle fetch.
+#[derive(Clone, Debug, Default)]
+pub struct BundleFetchRequest {
+    /// Hash of the manifest of the bundle to fetch.
+    pub manifest_hash: Hash,
+    /// Index of the first chunk to fetch, used to resume an interrupted transfer.
+    pub start: u64,
+}
+
+/// A verified chunk of a bundle.
+#[derive(Clone, Debug, Default)]
+pub struct BundleChunk {
+    /// Index of the chunk.
+    pub index: u64,
+    /// Chunk data.
+    pub data: Vec<u8>,
+    /// Progress of the transfer including this chunk.
+    pub progress: FetchProgress,
+}
+
+/// Progress of a chunked bundle fetch.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct FetchProgress {
+    /// Number of chunks received so far, including chunks received before resuming.
+    pub chunks: u64,
+    /// Total number of chunks.
+    pub total_chunks: u64,
+    /// Number of bytes received so far, including bytes received before resuming.
+    pub bytes: u64,
+    /// Total size of the bundle in bytes.
+    pub total_bytes: u64,
+}
+
+/// Request to host to return the manifest of a bundle.
+///
+/// The `PermissionBundleAdd` permission is required to call this method.
+#[derive(Clone, Debug, Default, cbor::Encode, cbor::Decode)]
+pub struct BundleFetchInfoRequest {
+    /// Hash of the manifest of the bundle.
+    pub manifest_hash: Hash,
+}
+
+/// Response from the BundleFetchInfo method.
+#[derive(Clone, Debug, Default, cbor::Encode, cbor::Decode)]
+pub struct BundleFetchInfoResponse {
+    /// Raw manifest of the bundle, declaring its chunks.
+    pub manifest: Vec<u8>,
+}
+
+/// Request to host to return a single chunk of a bundle.
+///
+/// The `PermissionBundleAdd` permission is required to call this method.
+#[derive(Clone, Debug, Default, cbor::Encode, cbor::Decode)]
+pub struct BundleFetchChunkRequest {
+    /// Hash of the manifest of the bundle.
+    pub manifest_hash: Hash,
+    /// Size of each chunk in bytes, as declared by the manifest.
+    pub chunk_size: u32,
+    /// Index of the chunk.
+    pub index: u64,
+}
+
+/// Response from the BundleFetchChunk method.
+#[derive(Clone, Debug, Default, cbor::Encode, cbor::Decode)]
+pub struct BundleFetchChunkResponse {
+    /// Chunk data.
+    pub data: Vec<u8>,
 }
 
 /// Bundle information.
@@ -187,3 +630,156 @@ pub struct ComponentInfo {
     /// Component name.
     pub name: String,
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{
+        common::crypto::signature::{PrivateKey, Signer},
+        testkit::host::MockHost,
+    };
+
+    /// Manifest declaring the chunks of the given bundle.
+    fn chunked_manifest(bundle: &[u8], chunk_size: u32) -> Vec<u8> {
+        let digests: Vec<_> = bundle
+            .chunks(chunk_size as usize)
+            .map(|chunk| format!(r#""{}""#, BASE64_STANDARD.encode(Hash::digest_bytes(chunk))))
+            .collect();
+        format!(
+            r#"{{"chunks": {{"chunk_size": {chunk_size}, "size": {}, "digests": [{}]}}}}"#,
+            bundle.len(),
+            digests.join(", ")
+        )
+        .into_bytes()
+    }
+
+    #[test]
+    fn test_fetch_chunked() {
+        let bundle: Vec<u8> = (0..250u8).collect();
+        let manifest = chunked_manifest(&bundle, 100);
+        let manifest_hash = Hash::digest_bytes(&manifest);
+        let mut corrupted = bundle.clone();
+        corrupted[200] ^= 0xff;
+        let host = MockHost::new();
+        host.set_fetchable_bundle(manifest.clone(), corrupted);
+        let fetch = |host: &MockHost, manifest_hash, start| {
+            let mut chunks = Vec::new();
+            let result = futures::executor::block_on(host.fetch_chunked(
+                BundleFetchRequest {
+                    manifest_hash,
+                    start,
+                },
+                &mut |chunk| chunks.push(chunk),
+            ));
+            (result, chunks)
+        };
+
+        // Manifests not matching the expected hash are rejected.
+        let (result, chunks) = fetch(&host, Hash::digest_bytes(b"other manifest"), 0);
+        assert!(matches!(result, Err(Error::BlobMismatch)));
+        assert!(chunks.is_empty());
+
+        // Corrupted chunks are rejected.
+        let (result, chunks) = fetch(&host, manifest_hash, 0);
+        assert!(matches!(result, Err(Error::BadResponse)));
+        assert_eq!(chunks.len(), 2);
+        assert_eq!(
+            chunks[1].progress,
+            FetchProgress {
+                chunks: 2,
+                total_chunks: 3,
+                bytes: 200,
+                total_bytes: 250,
+            }
+        );
+
+        // The transfer can be resumed at the failed chunk.
+        host.set_fetchable_bundle(manifest, bundle.clone());
+        let (result, resumed) = fetch(&host, manifest_hash, 2);
+        assert_eq!(result.unwrap().digests.len(), 3);
+        assert_eq!(resumed.len(), 1);
+        assert_eq!(resumed[0].index, 2);
+        assert_eq!(resumed[0].progress.bytes, 250);
+
+        let fetched: Vec<u8> = chunks
+            .into_iter()
+            .chain(resumed)
+            .flat_map(|chunk| chunk.data)
+            .collect();
+        assert_eq!(fetched, bundle);
+
+        // Manifests declaring a zero chunk size are rejected.
+        let manifest = br#"{"chunks": {"chunk_size": 0, "size": 0, "digests": []}}"#;
+        assert!(matches!(
+            Manifest::parse(manifest),
+            Err(Error::UntrustedBundle)
+        ));
+    }
+
+    #[test]
+    fn test_bundle_trust_root() {
+        let authors: Vec<_> = (0..3)
+            .map(|i| PrivateKey::from_test_seed(format!("bundle author {i}")))
+            .collect();
+        let outsider = PrivateKey::from_test_seed("bundle outsider".to_string());
+        let trust_root = BundleTrustRoot {
+            signers: authors.iter().map(|sk| sk.public_key()).collect(),
+            threshold: 2,
+        };
+        let files = BTreeMap::from([("runtime.elf".to_string(), b"runtime".to_vec())]);
+        let manifest = format!(
+            r#"{{"digests": {{"runtime.elf": "{}"}}}}"#,
+            BASE64_STANDARD.encode(Hash::digest_bytes(b"runtime"))
+        );
+        let manifest = manifest.as_bytes();
+        let sign = |sk: &PrivateKey, manifest: &[u8]| SignatureBundle {
+            public_key: sk.public_key(),
+            signature: Signer::sign(
+                sk,
+                MANIFEST_SIGNATURE_CONTEXT,
+                Hash::digest_bytes(manifest).as_ref(),
+            )
+            .unwrap(),
+        };
+
+        // Duplicate, untrusted and invalid signatures don't count.
+        let mut signatures = vec![
+            sign(&authors[0], manifest),
+            sign(&authors[0], manifest),
+            sign(&outsider, manifest),
+            sign(&authors[1], b"other manifest"),
+        ];
+        assert!(matches!(
+            trust_root.verify_manifest(manifest, &signatures),
+            Err(Error::UntrustedBundle)
+        ));
+
+        signatures.push(sign(&authors[2], manifest));
+        let (manifest_hash, parsed) = trust_root.verify_manifest(manifest, &signatures).unwrap();
+        assert_eq!(manifest_hash, Hash::digest_bytes(manifest));
+        assert_eq!(parsed.digests.len(), 1);
+        assert!(trust_root
+            .verify(&Hash::digest_bytes(b"other manifest"), &signatures)
+            .is_err());
+
+        // All files must match the digests of the signed manifest.
+        let verify_bundle = |files: &BTreeMap<String, Vec<u8>>| {
+            trust_root.verify_bundle(manifest, files, &signatures)
+        };
+        assert_eq!(verify_bundle(&files).unwrap(), manifest_hash);
+        let mut other = files.clone();
+        other.insert(MANIFEST_NAME.to_string(), manifest.to_vec());
+        assert!(verify_bundle(&other).is_ok());
+        other.insert("runtime.elf".to_string(), b"other runtime".to_vec());
+        assert!(verify_bundle(&other).is_err());
+        let mut other = files.clone();
+        other.insert("extra.elf".to_string(), b"runtime".to_vec());
+        assert!(verify_bundle(&other).is_err());
+        assert!(verify_bundle(&BTreeMap::new()).is_err());
+
+        // An empty trust root trusts nobody.
+        assert!(BundleTrustRoot::default()
+            .verify_manifest(manifest, &signatures)
+            .is_err());
+    }
+}
@@ -0,0 +1,680 @@
+//! Conformance test suite for host implementations.
+//!
+//! The suite exercises a `Host` implementation against the semantics expected by the runtime
+//! so that alternative host implementations and mocks can prove compatibility. Checks which
+//! modify host state or require host-specific inputs are opt-in via `ConformanceOpts`.
+use std::collections::{BTreeMap, BTreeSet};
+
+use thiserror::Error;
+
+use crate::common::{
+    crypto::hash::Hash,
+    pagination::{Pages, Pagination},
+};
+
+use super::{
+    bundle_manager::BundleListRequest,
+    volume_manager::{
+        VolumeAddRequest, VolumeBlobGetRequest, VolumeBlobPinRequest, VolumeBlobPutRequest,
+        VolumeListRequest, VolumeMode, VolumeRemoveRequest, VolumeRestoreRequest,
+        VolumeSnapshotListRequest, VolumeSnapshotRequest, VolumeUsageRequest, VolumeWriteRequest,
+    },
+    Error as HostError, Host, RegisterNotifyOpts, SubmitTxOpts,
+};
+
+/// Label used to tag resources created by the conformance suite.
+pub const LABEL_CONFORMANCE: &str = "net.oasis.conformance";
+
+/// Conformance check failure.
+#[derive(Error, Debug)]
+pub enum ConformanceError {
+    #[error("host error: {0}")]
+    Host(#[from] HostError),
+
+    #[error("semantics violation: {0}")]
+    Violation(String),
+}
+
+fn violation<T>(msg: &str) -> Result<T, ConformanceError> {
+    Err(ConformanceError::Violation(msg.to_string()))
+}
+
+/// Conformance suite options.
+#[derive(Clone, Debug, Default)]
+pub struct ConformanceOpts {
+    /// Transaction to use for checking transaction submission. If not specified, submission
+    /// checks are skipped.
+    pub test_tx: Option<Vec<u8>>,
+    /// Whether to check the behavior of bundle manager methods.
+    pub bundle_manager: bool,
+    /// Whether to check the behavior of volume manager methods. This adds, snapshots and removes
+    /// volumes labeled with `LABEL_CONFORMANCE`.
+    pub volume_manager: bool,
+}
+
+/// Result of a single conformance check.
+#[derive(Debug)]
+pub struct CheckResult {
+    /// Name of the check.
+    pub name: &'static str,
+    /// Outcome of the check.
+    pub result: Result<(), ConformanceError>,
+}
+
+/// Results of running the conformance suite.
+#[derive(Debug, Default)]
+pub struct ConformanceReport {
+    /// Results of all executed checks.
+    pub checks: Vec<CheckResult>,
+}
+
+impl ConformanceReport {
+    /// Whether all executed checks passed.
+    pub fn is_ok(&self) -> bool {
+        self.checks.iter().all(|check| check.result.is_ok())
+    }
+
+    /// Checks that failed.
+    pub fn failures(&self) -> impl Iterator<Item = &CheckResult> {
+        self.checks.iter().filter(|check| check.result.is_err())
+    }
+
+    /// Panic in case any of the checks failed, listing all failures.
+    pub fn assert_ok(&self) {
+        let failures: Vec<_> = self
+            .failures()
+            .map(|check| format!("{}: {}", check.name, check.result.as_ref().unwrap_err()))
+            .collect();
+        assert!(
+            failures.is_empty(),
+            "host conformance checks failed:\n{}",
+            failures.join("\n")
+        );
+    }
+}
+
+/// Run the conformance suite against the given host.
+pub async fn run(host: &dyn Host, opts: &ConformanceOpts) -> ConformanceReport {
+    let mut report = ConformanceReport::default();
+    let mut record = |name: &'static str, result: Result<(), ConformanceError>| {
+        report.checks.push(CheckResult { name, result })
+    };
+
+    record("identity", check_identity(host).await);
+    record("runtime_block", check_runtime_block(host).await);
+    record("register_notify", check_register_notify(host).await);
+    if let Some(ref tx) = opts.test_tx {
+        record("submit_tx", check_submit_tx(host, tx).await);
+    }
+    if opts.bundle_manager {
+        record("bundle_list", check_bundle_list(host).await);
+    }
+    if opts.volume_manager {
+        record("volume_lifecycle", check_volume_lifecycle(host).await);
+        record("volume_pagination", check_volume_pagination(host).await);
+        record("volume_snapshots", check_volume_snapshots(host).await);
+        record("volume_usage", check_volume_usage(host).await);
+        record("volume_blobs", check_volume_blobs(host).await);
+    }
+
+    report
+}
+
+/// The host identity must be stable across calls.
+pub async fn check_identity(host: &dyn Host) -> Result<(), ConformanceError> {
+    let first = host.identity().await?;
+    let second = host.identity().await?;
+    if first != second {
+        return violation("identity changed between calls");
+    }
+    Ok(())
+}
+
+/// The latest runtime block must be the same as the block fetched by its round.
+pub async fn check_runtime_block(host: &dyn Host) -> Result<(), ConformanceError> {
+    let latest = host.runtime_block(None).await?;
+    let round = latest.block.header.round;
+    let block = host.runtime_block(Some(round)).await?;
+    if block.block.header.round != round {
+        return violation("runtime block returned for a different round");
+    }
+    if block != latest {
+        return violation("latest runtime block differs from the block fetched by round");
+    }
+    Ok(())
+}
+
+/// Notification registrations must be independent and individually removable.
+pub async fn check_register_notify(host: &dyn Host) -> Result<(), ConformanceError> {
+    let blocks = host
+        .register_notify(RegisterNotifyOpts {
+            runtime_block: true,
+            runtime_event: vec![],
+            runtime_event_prefix: vec![],
+            consensus_block: false,
+            consensus_event: vec![],
+            external_data: vec![],
+        })
+        .await?;
+    let events = host
+        .register_notify(RegisterNotifyOpts {
+            runtime_block: false,
+            runtime_event: vec![LABEL_CONFORMANCE.as_bytes().to_vec()],
+            runtime_event_prefix: vec![],
+            consensus_block: false,
+            consensus_event: vec![],
+            external_data: vec![],
+        })
+        .await?;
+
+    if blocks.id() == events.id() {
+        return violation("registrations share the same handle");
+    }
+    if !blocks.opts().runtime_block {
+        return violation("registration options were clobbered by another registration");
+    }
+    if events.opts().runtime_event != vec![LABEL_CONFORMANCE.as_bytes().to_vec()] {
+        return violation("registration options were not retained");
+    }
+
+    Ok(())
+}
+
+/// Transaction submission must only return a result when waiting for inclusion, and must
+/// include a proof when one is requested.
+pub async fn check_submit_tx(host: &dyn Host, tx: &[u8]) -> Result<(), ConformanceError> {
+    let result = host
+        .submit_tx(
+            tx.to_vec(),
+            SubmitTxOpts {
+                wait: false,
+                ..Default::default()
+            },
+        )
+        .await?;
+    if result.is_some() {
+        return violation("result returned without waiting for inclusion");
+    }
+
+    let result = host
+        .submit_tx(
+            tx.to_vec(),
+            SubmitTxOpts {
+                wait: true,
+                prove: true,
+                ..Default::default()
+            },
+        )
+        .await?;
+    match result {
+        None => violation("no result returned when waiting for inclusion"),
+        Some(result) if result.proof.is_none() => violation("no proof returned when requested"),
+        Some(_) => Ok(()),
+    }
+}
+
+/// Listing bundles must only return bundles matching the label filter.
+pub async fn check_bundle_list(host: &dyn Host) -> Result<(), ConformanceError> {
+    let labels = conformance_labels();
+    let rsp = host
+        .bundle_manager()
+        .bundle_list(BundleListRequest {
+            labels: labels.clone(),
+            pagination: Pagination::default(),
+        })
+        .await?;
+    if rsp
+        .bundles
+        .iter()
+        .any(|b| !matches_labels(&b.labels, &labels))
+    {
+        return violation("bundle list returned bundles not matching the filter");
+    }
+    Ok(())
+}
+
+/// Added volumes must be listed with their labels and must no longer be listed once removed.
+pub async fn check_volume_lifecycle(host: &dyn Host) -> Result<(), ConformanceError> {
+    let vm = host.volume_manager();
+    let labels = conformance_labels();
+
+    let added = vm
+        .volume_add(VolumeAddRequest {
+            labels: labels.clone(),
+            ..Default::default()
+        })
+        .await?;
+    if added.id.is_empty() {
+        return violation("added volume has an empty identifier");
+    }
+
+    let rsp = vm
+        .volume_list(VolumeListRequest {
+            labels: labels.clone(),
+            pagination: Pagination::default(),
+        })
+        .await?;
+    if rsp
+        .volumes
+        .iter()
+        .any(|v| !matches_labels(&v.labels, &labels))
+    {
+        return violation("volume list returned volumes not matching the filter");
+    }
+    if !rsp.volumes.iter().any(|v| v.id == added.id) {
+        return violation("added volume is not listed");
+    }
+
+    vm.volume_remove(VolumeRemoveRequest {
+        labels: labels.clone(),
+    })
+    .await?;
+    let rsp = vm
+        .volume_list(VolumeListRequest {
+            labels,
+            pagination: Pagination::default(),
+        })
+        .await?;
+    if rsp.volumes.iter().any(|v| v.id == added.id) {
+        return violation("removed volume is still listed");
+    }
+
+    Ok(())
+}
+
+/// Volume listings must respect the page size and continuation tokens must cover all volumes.
+pub async fn check_volume_pagination(host: &dyn Host) -> Result<(), ConformanceError> {
+    let vm = host.volume_manager();
+    let labels = conformance_labels();
+
+    let mut added = BTreeSet::new();
+    for _ in 0..3 {
+        let rsp = vm
+            .volume_add(VolumeAddRequest {
+                labels: labels.clone(),
+                ..Default::default()
+            })
+            .await?;
+        added.insert(rsp.id);
+    }
+
+    let mut listed = BTreeSet::new();
+    let mut pages = Pages::new(Pagination::with_limit(2));
+    let mut result = Ok(());
+    while let Some(page) = pages.next_page() {
+        let rsp = vm
+            .volume_list(VolumeListRequest {
+                labels: labels.clone(),
+                pagination: page.clone(),
+            })
+            .await?;
+        if rsp.volumes.len() > 2 {
+            result = violation("volume list returned more volumes than the page size");
+            break;
+        }
+        if rsp.volumes.is_empty() && rsp.continuation_token.is_some() {
+            result = violation("volume list returned an empty page with a continuation token");
+            break;
+        }
+        listed.extend(rsp.volumes.into_iter().map(|v| v.id));
+        if pages.advance(rsp.continuation_token).is_err() {
+            result = violation("volume list returned a continuation token that does not advance");
+            break;
+        }
+    }
+    if result.is_ok() && listed != added {
+        result = violation("paginated volume list does not match the added volumes");
+    }
+
+    vm.volume_remove(VolumeRemoveRequest { labels }).await?;
+
+    result
+}
+
+/// Snapshots must be listed for the volume they were taken of and only existing snapshots of the
+/// volume can be restored.
+pub async fn check_volume_snapshots(host: &dyn Host) -> Result<(), ConformanceError> {
+    let vm = host.volume_manager();
+    let labels = conformance_labels();
+
+    let volume = vm
+        .volume_add(VolumeAddRequest {
+            labels: labels.clone(),
+            ..Default::default()
+        })
+        .await?;
+    let other = vm
+        .volume_add(VolumeAddRequest {
+            labels: labels.clone(),
+            ..Default::default()
+        })
+        .await?;
+    let result = check_snapshots_of(host, &volume.id, &other.id).await;
+
+    vm.volume_remove(VolumeRemoveRequest { labels }).await?;
+
+    result
+}
+
+async fn check_snapshots_of(
+    host: &dyn Host,
+    volume_id: &str,
+    other_id: &str,
+) -> Result<(), ConformanceError> {
+    let vm = host.volume_manager();
+
+    let snapshot = vm
+        .volume_snapshot(VolumeSnapshotRequest {
+            id: volume_id.to_string(),
+            labels: conformance_labels(),
+        })
+        .await?;
+    if snapshot.snapshot_id.is_empty() {
+        return violation("snapshot has an empty identifier");
+    }
+
+    let rsp = vm
+        .volume_snapshot_list(VolumeSnapshotListRequest {
+            id: volume_id.to_string(),
+            pagination: Pagination::default(),
+        })
+        .await?;
+    if rsp.snapshots.iter().any(|s| s.volume_id != volume_id) {
+        return violation("snapshot list returned snapshots of other volumes");
+    }
+    if !rsp.snapshots.iter().any(|s| s.id == snapshot.snapshot_id) {
+        return violation("snapshot is not listed");
+    }
+
+    vm.volume_restore(VolumeRestoreRequest {
+        id: volume_id.to_string(),
+        snapshot_id: snapshot.snapshot_id.clone(),
+    })
+    .await?;
+    let restored = vm
+        .volume_restore(VolumeRestoreRequest {
+            id: other_id.to_string(),
+            snapshot_id: snapshot.snapshot_id,
+        })
+        .await;
+    if restored.is_ok() {
+        return violation("volume restored from a snapshot of another volume");
+    }
+
+    Ok(())
+}
+
+/// Volume usage must account written files and writes exceeding the quota must be rejected.
+pub async fn check_volume_usage(host: &dyn Host) -> Result<(), ConformanceError> {
+    let vm = host.volume_manager();
+    let labels = conformance_labels();
+
+    let volume = vm
+        .volume_add(VolumeAddRequest {
+            labels: labels.clone(),
+            bytes_quota: Some(1024),
+            ..Default::default()
+        })
+        .await?;
+    let result = check_usage_of(host, &volume.id).await;
+
+    vm.volume_remove(VolumeRemoveRequest { labels }).await?;
+
+    result
+}
+
+async fn check_usage_of(host: &dyn Host, volume_id: &str) -> Result<(), ConformanceError> {
+    let vm = host.volume_manager();
+    let usage = || {
+        vm.volume_usage(VolumeUsageRequest {
+            id: volume_id.to_string(),
+        })
+    };
+    let write = |size: usize| {
+        vm.volume_write(VolumeWriteRequest {
+            id: volume_id.to_string(),
+            path: "conformance".to_string(),
+            data: vec![0; size],
+        })
+    };
+
+    let before = usage().await?;
+    if before.bytes_quota != Some(1024) {
+        return violation("volume usage does not report the requested quota");
+    }
+    write(100).await?;
+    let after = usage().await?;
+    if after.bytes_used < before.bytes_used + 100 || after.inode_count <= before.inode_count {
+        return violation("volume usage does not account written files");
+    }
+    match write(2048).await {
+        Ok(_) => return violation("write exceeding the volume quota succeeded"),
+        Err(err) if !err.is_quota_exceeded() => {
+            return violation("write exceeding the volume quota failed with an unexpected error")
+        }
+        Err(_) => {}
+    }
+
+    Ok(())
+}
+
+/// Blob volumes must address blobs by their hash, deduplicate identical blobs and only remove
+/// blobs once they are no longer pinned.
+pub async fn check_volume_blobs(host: &dyn Host) -> Result<(), ConformanceError> {
+    let vm = host.volume_manager();
+    let labels = conformance_labels();
+
+    let volume = vm
+        .volume_add(VolumeAddRequest {
+            labels: labels.clone(),
+            mode: VolumeMode::Blobs,
+            ..Default::default()
+        })
+        .await?;
+    let result = check_blobs_of(host, &volume.id).await;
+
+    vm.volume_remove(VolumeRemoveRequest { labels }).await?;
+
+    result
+}
+
+async fn check_blobs_of(host: &dyn Host, volume_id: &str) -> Result<(), ConformanceError> {
+    let vm = host.volume_manager();
+    let data = b"conformance".to_vec();
+    let hash = Hash::digest_bytes(&data);
+    let put = || {
+        vm.volume_blob_put(VolumeBlobPutRequest {
+            id: volume_id.to_string(),
+            data: data.clone(),
+        })
+    };
+    let get = || {
+        vm.volume_blob_get(VolumeBlobGetRequest {
+            id: volume_id.to_string(),
+            hash,
+        })
+    };
+    let unpin = || {
+        vm.volume_blob_unpin(VolumeBlobPinRequest {
+            id: volume_id.to_string(),
+            hash,
+        })
+    };
+
+    let first = put().await?;
+    if first.hash != hash || first.deduplicated {
+        return violation("stored blob is not addressed by its hash");
+    }
+    if !put().await?.deduplicated {
+        return violation("identical blobs are not deduplicated");
+    }
+    if get().await?.data != Some(data.clone()) {
+        return violation("stored blob can not be fetched by its hash");
+    }
+    if unpin().await?.pins != 1 || get().await?.data.is_none() {
+        return violation("pinned blob was removed");
+    }
+    if unpin().await?.pins != 0 || get().await?.data.is_some() {
+        return violation("unpinned blob was not removed");
+    }
+
+    Ok(())
+}
+
+fn conformance_labels() -> BTreeMap<String, String> {
+    BTreeMap::from([(LABEL_CONFORMANCE.to_string(), "true".to_string())])
+}
+
+pub(crate) fn matches_labels(
+    labels: &BTreeMap<String, String>,
+    filter: &BTreeMap<String, String>,
+) -> bool {
+    filter.iter().all(|(k, v)| labels.get(k) == Some(v))
+}
+
+#[cfg(test)]
+mod test {
+    use async_trait::async_trait;
+
+    use super::*;
+    use crate::{
+        common::crypto::signature::PublicKey,
+        consensus::{
+            client::ConsensusClient,
+            roothash::{AnnotatedBlock, Evidence},
+        },
+        host::{
+            bundle_manager::BundleManager, local_storage::LocalStorage, secrets::Secrets,
+            volume_manager::VolumeManager, ConsensusGasPrice, NotificationStream, NotifyHandle,
+            TxResult,
+        },
+        testkit::host::MockHost,
+    };
+
+    #[test]
+    fn test_mock_host_conformance() {
+        let host = MockHost::default();
+        let opts = ConformanceOpts {
+            test_tx: Some(b"test".to_vec()),
+            bundle_manager: true,
+            volume_manager: true,
+        };
+
+        let report = futures::executor::block_on(run(&host, &opts));
+        report.assert_ok();
+        assert_eq!(report.checks.len(), 10);
+    }
+
+    #[test]
+    fn test_default_submit_tx_batch() {
+        let host = MockHost::default();
+        let txs = vec![b"a".to_vec(), b"b".to_vec()];
+
+        let results = futures::executor::block_on(host.submit_tx_batch(
+            txs.clone(),
+            SubmitTxOpts {
+                wait: true,
+                prove: true,
+                ..Default::default()
+            },
+        ))
+        .unwrap();
+        assert_eq!(results.len(), 2);
+        assert!(results.iter().all(|r| r.as_ref().unwrap().proof.is_some()));
+
+        let results =
+            futures::executor::block_on(host.submit_tx_batch(txs, SubmitTxOpts::default()))
+                .unwrap();
+        assert!(matches!(results[..], [None, None]));
+    }
+
+    #[test]
+    fn test_conformance_violation() {
+        struct LeakyHost(MockHost);
+
+        #[async_trait]
+        impl Host for LeakyHost {
+            async fn identity(&self) -> Result<PublicKey, HostError> {
+                self.0.identity().await
+            }
+
+            async fn runtime_block(&self, round: Option<u64>) -> Result<AnnotatedBlock, HostError> {
+                self.0.runtime_block(round).await
+            }
+
+            async fn submit_tx(
+                &self,
+                data: Vec<u8>,
+                _opts: SubmitTxOpts,
+            ) -> Result<Option<TxResult>, HostError> {
+                // Always wait, violating the expected semantics.
+                self.0
+                    .submit_tx(
+                        data,
+                        SubmitTxOpts {
+                            wait: true,
+                            ..Default::default()
+                        },
+                    )
+                    .await
+            }
+
+            async fn register_notify(
+                &self,
+                opts: RegisterNotifyOpts,
+            ) -> Result<NotifyHandle, HostError> {
+                self.0.register_notify(opts).await
+            }
+
+            async fn notifications(
+                &self,
+                opts: RegisterNotifyOpts,
+            ) -> Result<NotificationStream, HostError> {
+                self.0.notifications(opts).await
+            }
+
+            async fn relay_attestation(&self, verifier: &str) -> Result<Vec<u8>, HostError> {
+                self.0.relay_attestation(verifier).await
+            }
+
+            async fn consensus_gas_price(&self) -> Result<ConsensusGasPrice, HostError> {
+                self.0.consensus_gas_price().await
+            }
+
+            async fn submit_evidence(&self, evidence: Evidence) -> Result<(), HostError> {
+                self.0.submit_evidence(evidence).await
+            }
+
+            fn bundle_manager(&self) -> &dyn BundleManager {
+                &self.0
+            }
+
+            fn volume_manager(&self) -> &dyn VolumeManager {
+                &self.0
+            }
+
+            fn local_storage(&self) -> &dyn LocalStorage {
+                &self.0
+            }
+
+            fn secrets(&self) -> &dyn Secrets {
+                &self.0
+            }
+
+            fn consensus(&self) -> Result<ConsensusClient, HostError> {
+                self.0.consensus()
+            }
+        }
+
+        let host = LeakyHost(MockHost::default());
+        let opts = ConformanceOpts {
+            test_tx: Some(b"test".to_vec()),
+            ..Default::default()
+        };
+
+        let report = futures::executor::block_on(run(&host, &opts));
+        assert!(!report.is_ok());
+        let failures: Vec<_> = report.failures().map(|check| check.name).collect();
+        assert_eq!(failures, vec!["submit_tx"]);
+    }
+}
@@ -0,0 +1,503 @@
+//! Persistent deduplication window for off-chain inputs.
+//!
+//! Off-chain inputs (e.g. gossip messages or oracle responses) may be delivered more than once,
+//! in particular after the runtime restarts in the middle of processing. A [`DedupWindow`]
+//! remembers the identifiers of recently processed inputs across restarts, so that duplicates
+//! can be dropped.
+//!
+//! Identifiers are recorded in generations of a fixed size. The current generation is kept
+//! exactly, while each completed generation is compacted into a bloom filter, of which only the
+//! most recent ones are retained. Lookups in compacted generations may therefore report inputs
+//! which have never been recorded as duplicates, at the configured false positive rate. Bloom
+//! filters are keyed by a secret salt, so that inputs can't be crafted to collide with recorded
+//! ones.
+//!
+//! The window is persisted in an [`EncryptedVolume`], as the host could otherwise make the
+//! runtime drop arbitrary inputs by forging the window. As the host can still roll back the
+//! volume, which would make the runtime forget recently recorded identifiers, the current
+//! generation is bound to the value of a [`MonotonicCounter`] incremented on each insert, and
+//! rolled back windows fail to open (as long as the counter itself can't be rolled back).
+use std::{collections::BTreeSet, sync::Arc};
+
+use async_trait::async_trait;
+use rand::Rng;
+use thiserror::Error;
+use tokio::sync::Mutex;
+
+use crate::{
+    common::crypto::{hash::Hash, rng::SecureRng},
+    shutdown::Flush,
+};
+
+use super::{
+    encrypted_volume::{EncryptedVolume, EncryptedVolumeError},
+    local_storage::MonotonicCounter,
+};
+
+/// Size of the salt keying the bloom filters.
+const SALT_SIZE: usize = 32;
+
+/// Deduplication window errors.
+#[derive(Error, Debug)]
+pub enum DedupError {
+    #[error("volume error: {0}")]
+    Volume(#[from] EncryptedVolumeError),
+
+    #[error("malformed window: {0}")]
+    Malformed(String),
+
+    #[error("monotonic counter: {0}")]
+    Counter(#[source] anyhow::Error),
+
+    #[error("stale window (counter: {counter} window: {window})")]
+    Stale { counter: u64, window: u64 },
+}
+
+/// Deduplication window configuration.
+#[derive(Clone, Debug)]
+pub struct DedupConfig {
+    /// Number of identifiers recorded in each generation.
+    ///
+    /// The current generation is persisted in full on each insert, so large generations make
+    /// inserts more expensive.
+    pub generation_size: usize,
+    /// Number of completed generations to retain, in addition to the current one.
+    pub generations: usize,
+    /// False positive rate of lookups in completed generations.
+    pub false_positive_rate: f64,
+}
+
+impl Default for DedupConfig {
+    fn default() -> Self {
+        Self {
+            generation_size: 1024,
+            generations: 16,
+            false_positive_rate: 1e-6,
+        }
+    }
+}
+
+/// Bloom filter of a completed generation.
+#[derive(Clone, Debug, Default, cbor::Encode, cbor::Decode)]
+struct BloomFilter {
+    bits: Vec<u8>,
+    hashes: u32,
+}
+
+impl BloomFilter {
+    /// Create a new empty filter sized for the given number of items.
+    fn new(items: usize, false_positive_rate: f64) -> Self {
+        let ln2 = std::f64::consts::LN_2;
+        let items = items.max(1) as f64;
+        let bits = (-items * false_positive_rate.clamp(f64::MIN_POSITIVE, 0.5).ln() / (ln2 * ln2))
+            .ceil()
+            .max(8.0);
+        let hashes = ((bits / items) * ln2).round().max(1.0);
+
+        Self {
+            bits: vec![0; (bits as usize).div_ceil(8)],
+            hashes: hashes as u32,
+        }
+    }
+
+    /// Positions of the bits of the given item, using double hashing.
+    fn positions(&self, salt: &[u8], id: &Hash) -> impl Iterator<Item = usize> {
+        let digest = Hash::digest_bytes_list(&[salt, id.as_ref()]);
+        let h1 = u64::from_le_bytes(digest.0[..8].try_into().unwrap());
+        let h2 = u64::from_le_bytes(digest.0[8..16].try_into().unwrap()) | 1;
+        let bits = (self.bits.len() * 8) as u64;
+
+        (0..self.hashes as u64).map(move |i| (h1.wrapping_add(i.wrapping_mul(h2)) % bits) as usize)
+    }
+
+    fn insert(&mut self, salt: &[u8], id: &Hash) {
+        for pos in self.positions(salt, id).collect::<Vec<_>>() {
+            self.bits[pos / 8] |= 1 << (pos % 8);
+        }
+    }
+
+    fn contains(&self, salt: &[u8], id: &Hash) -> bool {
+        self.positions(salt, id)
+            .all(|pos| self.bits[pos / 8] & (1 << (pos % 8)) != 0)
+    }
+}
+
+/// Current generation, as persisted.
+#[derive(Clone, Debug, Default, cbor::Encode, cbor::Decode)]
+struct Current {
+    /// Number of generations completed before this one.
+    generation: u64,
+    /// Counter value the generation was written at.
+    counter: u64,
+    /// Identifiers recorded in the generation.
+    ids: Vec<Hash>,
+}
+
+/// Completed generations, as persisted.
+#[derive(Clone, Debug, Default, cbor::Encode, cbor::Decode)]
+struct Compacted {
+    salt: Vec<u8>,
+    /// Number of completed generations, including ones no longer retained.
+    generation: u64,
+    /// Bloom filters of completed generations, oldest first.
+    filters: Vec<BloomFilter>,
+}
+
+/// State of the window.
+struct State {
+    current: BTreeSet<Hash>,
+    counter: u64,
+    compacted: Compacted,
+}
+
+impl State {
+    fn contains(&self, id: &Hash) -> bool {
+        let salt = &self.compacted.salt;
+        self.current.contains(id) || self.compacted.filters.iter().any(|f| f.contains(salt, id))
+    }
+}
+
+/// Persistent window of recently processed inputs.
+pub struct DedupWindow {
+    volume: Arc<EncryptedVolume>,
+    counter: Arc<dyn MonotonicCounter>,
+    config: DedupConfig,
+    current_path: String,
+    compacted_path: String,
+    state: Mutex<State>,
+}
+
+impl DedupWindow {
+    /// Open the window with the given name, stored in the given volume and bound to the given
+    /// counter.
+    ///
+    /// Windows with different names are independent of each other. Using the same name after a
+    /// restart gives access to identifiers recorded before the restart. Each window must be
+    /// bound to its own counter, which must be used for the window across restarts.
+    pub async fn open(
+        volume: Arc<EncryptedVolume>,
+        name: &str,
+        config: DedupConfig,
+        counter: Arc<dyn MonotonicCounter>,
+    ) -> Result<Self, DedupError> {
+        let current_path = format!("dedup/{name}/current");
+        let compacted_path = format!("dedup/{name}/compacted");
+
+        let current = match volume.read(&current_path).await? {
+            Some(raw) => Some(
+                cbor::from_slice::<Current>(&raw)
+                    .map_err(|_| DedupError::Malformed("bad current generation".to_string()))?,
+            ),
+            None => None,
+        };
+        let compacted = match volume.read(&compacted_path).await? {
+            Some(raw) => cbor::from_slice::<Compacted>(&raw)
+                .ok()
+                .filter(|compacted| compacted.salt.len() == SALT_SIZE)
+                .ok_or_else(|| DedupError::Malformed("bad compacted generations".to_string()))?,
+            None => {
+                let mut salt = vec![0; SALT_SIZE];
+                SecureRng.fill(salt.as_mut_slice());
+                let compacted = Compacted {
+                    salt,
+                    generation: 0,
+                    filters: vec![],
+                };
+                volume
+                    .write(&compacted_path, &cbor::to_vec(compacted.clone()))
+                    .await?;
+                compacted
+            }
+        };
+
+        // The current generation is written before the counter is incremented, so in case of a
+        // crash in between the generation is one ahead of the counter.
+        let value = counter.read().await.map_err(DedupError::Counter)?;
+        let written = current.as_ref().map_or(0, |current| current.counter);
+        if written == value + 1 {
+            counter.increment().await.map_err(DedupError::Counter)?;
+        } else if written != value {
+            return Err(DedupError::Stale {
+                counter: value,
+                window: written,
+            });
+        }
+
+        // The completed generation is compacted before the next one is started, so in case of a
+        // crash in between the current generation has already been compacted.
+        let generation = current.as_ref().map_or(0, |current| current.generation);
+        let current = match compacted.generation.checked_sub(generation) {
+            Some(0) => current.map(|current| current.ids).unwrap_or_default(),
+            Some(1) => vec![],
+            _ => {
+                return Err(DedupError::Malformed(
+                    "compacted generations don't match current generation".to_string(),
+                ))
+            }
+        };
+
+        Ok(Self {
+            volume,
+            counter,
+            config,
+            current_path,
+            compacted_path,
+            state: Mutex::new(State {
+                current: current.into_iter().collect(),
+                counter: written,
+                compacted,
+            }),
+        })
+    }
+
+    /// Whether the input with the given identifier has been recorded.
+    pub async fn contains(&self, id: &Hash) -> bool {
+        self.state.lock().await.contains(id)
+    }
+
+    /// Record the input with the given identifier, returning `false` in case it has already been
+    /// recorded.
+    ///
+    /// The identifier is persisted before this method returns. Recording inputs before
+    /// processing them guarantees that no input is processed twice, even if the runtime crashes
+    /// during processing, as long as the counter can't be rolled back.
+    pub async fn insert(&self, id: &Hash) -> Result<bool, DedupError> {
+        let mut state = self.state.lock().await;
+        if state.contains(id) {
+            return Ok(false);
+        }
+
+        let mut current = state.current.clone();
+        current.insert(*id);
+        if current.len() < self.config.generation_size.max(1) {
+            let generation = state.compacted.generation;
+            state.counter = self
+                .write_current(generation, state.counter, &current)
+                .await?;
+            state.current = current;
+            return Ok(true);
+        }
+
+        // Compact the completed generation before starting a new one, so that a crash in between
+        // can't lose any identifiers.
+        let mut compacted = state.compacted.clone();
+        let mut filter = BloomFilter::new(current.len(), self.config.false_positive_rate);
+        for id in &current {
+            filter.insert(&compacted.salt, id);
+        }
+        compacted.filters.push(filter);
+        compacted.generation += 1;
+        let excess = compacted
+            .filters
+            .len()
+            .saturating_sub(self.config.generations);
+        compacted.filters.drain(..excess);
+        self.volume
+            .write(&self.compacted_path, &cbor::to_vec(compacted.clone()))
+            .await?;
+        state.compacted = compacted;
+
+        let generation = state.compacted.generation;
+        state.counter = self
+            .write_current(generation, state.counter, &BTreeSet::new())
+            .await?;
+        state.current = BTreeSet::new();
+
+        Ok(true)
+    }
+
+    /// Write the current generation and increment the counter, returning its new value.
+    async fn write_current(
+        &self,
+        generation: u64,
+        counter: u64,
+        ids: &BTreeSet<Hash>,
+    ) -> Result<u64, DedupError> {
+        let current = Current {
+            generation,
+            counter: counter + 1,
+            ids: ids.iter().copied().collect(),
+        };
+        self.volume
+            .write(&self.current_path, &cbor::to_vec(current))
+            .await?;
+        self.counter.increment().await.map_err(DedupError::Counter)
+    }
+}
+
+/// Identifiers are persisted before inserts return, so flushing waits for in-flight inserts.
+#[async_trait]
+impl Flush for DedupWindow {
+    async fn flush(&self) -> anyhow::Result<()> {
+        drop(self.state.lock().await);
+        self.volume.flush().await
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{
+        common::crypto::mrae::deoxysii::KEY_SIZE,
+        host::{
+            encrypted_volume::SecretKeySource, local_storage::MemoryCounter,
+            volume_manager::VolumeManager,
+        },
+        testkit::host::MockHost,
+    };
+
+    #[test]
+    fn test_bloom_filter() {
+        let salt = [1; SALT_SIZE];
+        let mut filter = BloomFilter::new(100, 0.01);
+        let ids: Vec<_> = (0..200u32)
+            .map(|i| Hash::digest_bytes(&i.to_le_bytes()))
+            .collect();
+        for id in &ids[..100] {
+            filter.insert(&salt, id);
+        }
+        assert!(ids[..100].iter().all(|id| filter.contains(&salt, id)));
+        let false_positives = ids[100..]
+            .iter()
+            .filter(|id| filter.contains(&salt, id))
+            .count();
+        assert!(false_positives < 10);
+
+        // Positions depend on the salt.
+        assert!(!ids[..100]
+            .iter()
+            .all(|id| filter.contains(&[2; SALT_SIZE], id)));
+    }
+
+    #[test]
+    fn test_dedup_window() {
+        let rt = tokio::runtime::Builder::new_current_thread()
+            .build()
+            .unwrap();
+        rt.block_on(check_dedup_window());
+    }
+
+    async fn check_dedup_window() {
+        let host: Arc<dyn VolumeManager> = Arc::new(MockHost::new());
+        let keys = SecretKeySource::new([1; KEY_SIZE]);
+        let open_volume = || async {
+            Arc::new(
+                EncryptedVolume::open(host.clone(), "vol".to_string(), &keys)
+                    .await
+                    .unwrap(),
+            )
+        };
+        let config = DedupConfig {
+            generation_size: 4,
+            generations: 2,
+            false_positive_rate: 1e-9,
+        };
+        let id = |i: u32| Hash::digest_bytes(&i.to_le_bytes());
+        let counter = Arc::new(MemoryCounter::new());
+
+        let window = DedupWindow::open(
+            open_volume().await,
+            "gossip",
+            config.clone(),
+            counter.clone(),
+        )
+        .await
+        .unwrap();
+        for i in 0..10 {
+            assert!(window.insert(&id(i)).await.unwrap());
+        }
+        assert!(!window.insert(&id(9)).await.unwrap());
+        assert!(!window.insert(&id(0)).await.unwrap());
+
+        // Recorded identifiers are remembered across restarts, in both exact and compacted
+        // generations.
+        let volume = open_volume().await;
+        let window = DedupWindow::open(volume.clone(), "gossip", config.clone(), counter.clone())
+            .await
+            .unwrap();
+        for i in 0..10 {
+            assert!(window.contains(&id(i)).await);
+        }
+        assert!(!window.contains(&id(10)).await);
+
+        // Only the most recent generations are retained.
+        for i in 10..14 {
+            assert!(window.insert(&id(i)).await.unwrap());
+        }
+        assert!(!window.contains(&id(0)).await);
+        assert!(window.contains(&id(4)).await);
+
+        // Other windows are not affected.
+        let other_counter = Arc::new(MemoryCounter::new());
+        let other = DedupWindow::open(volume.clone(), "oracle", config.clone(), other_counter)
+            .await
+            .unwrap();
+        assert!(other.insert(&id(4)).await.unwrap());
+
+        // Rolled back windows are detected.
+        assert!(matches!(
+            DedupWindow::open(volume, "gossip", config, Arc::new(MemoryCounter::new())).await,
+            Err(DedupError::Stale { counter: 0, .. })
+        ));
+    }
+
+    #[test]
+    fn test_dedup_window_interrupted_compaction() {
+        let rt = tokio::runtime::Builder::new_current_thread()
+            .build()
+            .unwrap();
+        rt.block_on(check_dedup_window_interrupted_compaction());
+    }
+
+    async fn check_dedup_window_interrupted_compaction() {
+        let host: Arc<dyn VolumeManager> = Arc::new(MockHost::new());
+        let keys = SecretKeySource::new([1; KEY_SIZE]);
+        let volume = Arc::new(
+            EncryptedVolume::open(host, "vol".to_string(), &keys)
+                .await
+                .unwrap(),
+        );
+        let config = DedupConfig {
+            generation_size: 4,
+            generations: 2,
+            false_positive_rate: 1e-9,
+        };
+        let id = |i: u32| Hash::digest_bytes(&i.to_le_bytes());
+        let counter = Arc::new(MemoryCounter::new());
+
+        let window = DedupWindow::open(volume.clone(), "gossip", config.clone(), counter.clone())
+            .await
+            .unwrap();
+        for i in 0..3 {
+            assert!(window.insert(&id(i)).await.unwrap());
+        }
+
+        // Crash after compacting the generation completed by the next insert, but before
+        // starting a new one.
+        let mut compacted = window.state.lock().await.compacted.clone();
+        let mut filter = BloomFilter::new(4, config.false_positive_rate);
+        for i in 0..4 {
+            filter.insert(&compacted.salt, &id(i));
+        }
+        compacted.filters.push(filter);
+        compacted.generation += 1;
+        volume
+            .write(&window.compacted_path, &cbor::to_vec(compacted))
+            .await
+            .unwrap();
+
+        // The already compacted generation is not compacted again, so that the next completed
+        // generation doesn't evict an extra one.
+        let window = DedupWindow::open(volume, "gossip", config, counter)
+            .await
+            .unwrap();
+        assert!(window.state.lock().await.current.is_empty());
+        for i in 4..8 {
+            assert!(window.insert(&id(i)).await.unwrap());
+        }
+        assert_eq!(window.state.lock().await.compacted.filters.len(), 2);
+        for i in 0..8 {
+            assert!(window.contains(&id(i)).await);
+        }
+    }
+}
@@ -0,0 +1,138 @@
+//! Tracking of deprecated message types.
+//!
+//! During initialization the host may announce message types which are deprecated, together with
+//! the runtime host protocol version in which they will be removed. Any continued use of such
+//! message types, in either direction, is counted and logged once per message type, so that
+//! runtime maintainers get an early warning of protocol changes before the host stops supporting
+//! the messages their runtime relies on.
+use std::{collections::BTreeMap, sync::Mutex};
+
+use slog::{warn, Logger};
+
+use crate::{
+    common::{logger::get_logger, version::Version},
+    types::{Body, MessageDeprecation},
+};
+
+/// Usage statistics of a deprecated message type.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct DeprecationStats {
+    /// Runtime host protocol version in which the message type will be removed.
+    pub removal_version: Version,
+    /// Number of messages of the type exchanged since the deprecation was announced.
+    pub uses: u64,
+}
+
+/// Tracker of the use of deprecated message types.
+pub struct DeprecationTracker {
+    logger: Logger,
+    deprecations: Mutex<BTreeMap<String, DeprecationStats>>,
+}
+
+impl DeprecationTracker {
+    /// Create a new tracker without any deprecated message types.
+    pub fn new() -> Self {
+        Self {
+            logger: get_logger("runtime/protocol/deprecation"),
+            deprecations: Mutex::new(BTreeMap::new()),
+        }
+    }
+
+    /// Configure the deprecated message types announced by the host.
+    pub fn configure(&self, deprecations: Vec<MessageDeprecation>) {
+        let mut tracked = self.deprecations.lock().unwrap();
+        for deprecation in deprecations {
+            warn!(self.logger, "Host announced deprecation of message type";
+                "message" => &deprecation.message,
+                "removal_version" => ?deprecation.removal_version,
+            );
+            tracked.insert(
+                deprecation.message,
+                DeprecationStats {
+                    removal_version: deprecation.removal_version,
+                    uses: 0,
+                },
+            );
+        }
+    }
+
+    /// Account a message with the given body, warning on the first use of a deprecated type.
+    pub fn record(&self, body: &Body) {
+        let mut tracked = self.deprecations.lock().unwrap();
+        if tracked.is_empty() {
+            return;
+        }
+
+        let message = body.name();
+        let stats = match tracked.get_mut(&message) {
+            Some(stats) => stats,
+            None => return,
+        };
+        stats.uses += 1;
+        if stats.uses == 1 {
+            warn!(self.logger, "Deprecated message type used";
+                "message" => &message,
+                "removal_version" => ?stats.removal_version,
+            );
+        }
+    }
+
+    /// Usage statistics of all deprecated message types.
+    pub fn stats(&self) -> BTreeMap<String, DeprecationStats> {
+        self.deprecations.lock().unwrap().clone()
+    }
+}
+
+impl Default for DeprecationTracker {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_deprecation_tracker() {
+        let tracker = DeprecationTracker::new();
+        tracker.record(&Body::RuntimePingRequest {});
+        assert!(tracker.stats().is_empty());
+
+        tracker.configure(vec![MessageDeprecation {
+            message: "RuntimePingRequest".to_string(),
+            removal_version: Version::new(6, 0, 0),
+        }]);
+        tracker.record(&Body::RuntimePingRequest {});
+        tracker.record(&Body::RuntimePingRequest {});
+        tracker.record(&Body::Empty {});
+
+        let stats = tracker.stats();
+        assert_eq!(stats.len(), 1);
+        assert_eq!(
+            stats["RuntimePingRequest"],
+            DeprecationStats {
+                removal_version: Version::new(6, 0, 0),
+                uses: 2,
+            }
+        );
+    }
+
+    #[test]
+    fn test_body_name() {
+        assert_eq!(Body::Empty {}.name(), "Empty");
+        assert_eq!(Body::Error(Default::default()).name(), "Error");
+        assert_eq!(
+            Body::RuntimeInfoRequest(Default::default()).name(),
+            "RuntimeInfoRequest"
+        );
+        assert_eq!(
+            Body::RuntimeLogConfigRequest {
+                level: "debug".to_string(),
+                modules: BTreeMap::new(),
+            }
+            .name(),
+            "RuntimeLogConfigRequest"
+        );
+    }
+}
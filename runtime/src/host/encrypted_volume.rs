@@ -0,0 +1,359 @@
+//! Encrypted volumes.
+//!
+//! Volumes are stored by the host, which is not trusted. An [`EncryptedVolume`] transparently
+//! seals all files written through the [`VolumeManager`] with Deoxys-II using a per-volume key
+//! obtained from a [`VolumeKeySource`] (usually backed by the key manager), binding each file to
+//! the volume and its path. In addition, an integrity manifest holding the hashes of all sealed
+//! files is stored, itself sealed, alongside the files, so that the host can't roll back, swap or
+//! delete individual files without detection.
+//!
+//! Each file is stored in one of two slots. Writes go to the slot not referenced by the manifest
+//! and the manifest is written last, so a write interrupted before the manifest has been written
+//! leaves the previous version of the file intact.
+//!
+//! The host can still roll back the whole volume to an earlier consistent state, which is
+//! indistinguishable from restoring a snapshot.
+use std::{collections::BTreeMap, sync::Arc};
+
+use async_trait::async_trait;
+use hmac::{Hmac, Mac};
+use rand::Rng;
+use sha2::Sha512_256;
+use thiserror::Error;
+use tokio::sync::Mutex;
+use zeroize::{Zeroize, ZeroizeOnDrop};
+
+use crate::common::crypto::{
+    hash::Hash,
+    mrae::deoxysii::{DeoxysII, KEY_SIZE, NONCE_SIZE},
+    rng::SecureRng,
+};
+
+use super::{
+    volume_manager::{VolumeManager, VolumeReadRequest, VolumeWriteRequest},
+    Error as HostError,
+};
+
+/// Path of the integrity manifest within an encrypted volume.
+pub const INTEGRITY_MANIFEST_PATH: &str = ".integrity";
+/// Prefix of the paths at which file slots are stored within an encrypted volume.
+const SLOTS_PREFIX: &str = ".slots/";
+
+/// Context used for deriving volume keys.
+const KEY_CONTEXT: &[u8] = b"oasis-core/runtime: volume key";
+/// Context used as a prefix of the additional data of sealed files.
+const SEAL_CONTEXT: &[u8] = b"oasis-core/runtime: encrypted volume";
+
+type Kdf = Hmac<Sha512_256>;
+
+/// Encrypted volume errors.
+#[derive(Error, Debug)]
+pub enum EncryptedVolumeError {
+    #[error("host error: {0}")]
+    Host(#[from] HostError),
+
+    #[error("failed to obtain volume key: {0}")]
+    Key(#[source] anyhow::Error),
+
+    #[error("integrity check failed for '{0}'")]
+    Integrity(String),
+
+    #[error("reserved path '{0}'")]
+    ReservedPath(String),
+}
+
+/// Source of volume encryption keys.
+#[async_trait]
+pub trait VolumeKeySource: Send + Sync {
+    /// Encryption key of the volume with the given identifier.
+    ///
+    /// The same key must be returned for the volume across restarts.
+    async fn volume_key(&self, volume_id: &str) -> anyhow::Result<[u8; KEY_SIZE]>;
+}
+
+/// Volume key source deriving per-volume keys from a single secret, e.g. one provided by the key
+/// manager.
+#[derive(Clone, Zeroize, ZeroizeOnDrop)]
+pub struct SecretKeySource {
+    secret: [u8; KEY_SIZE],
+}
+
+impl SecretKeySource {
+    /// Create a new key source deriving keys from the given secret.
+    pub fn new(secret: [u8; KEY_SIZE]) -> Self {
+        Self { secret }
+    }
+}
+
+#[async_trait]
+impl VolumeKeySource for SecretKeySource {
+    async fn volume_key(&self, volume_id: &str) -> anyhow::Result<[u8; KEY_SIZE]> {
+        let mut kdf = Kdf::new_from_slice(&self.secret).expect("Hmac::new_from_slice");
+        kdf.update(KEY_CONTEXT);
+        kdf.update(volume_id.as_bytes());
+
+        let mut key = [0u8; KEY_SIZE];
+        key.copy_from_slice(&kdf.finalize().into_bytes()[..KEY_SIZE]);
+        Ok(key)
+    }
+}
+
+/// A sealed file, as stored on the volume.
+#[derive(Clone, Debug, Default, cbor::Encode, cbor::Decode)]
+struct SealedFile {
+    nonce: Vec<u8>,
+    data: Vec<u8>,
+}
+
+/// Integrity manifest of an encrypted volume.
+#[derive(Clone, Debug, Default, cbor::Encode, cbor::Decode)]
+struct Manifest {
+    /// Current versions of the stored files by path.
+    files: BTreeMap<String, FileEntry>,
+}
+
+/// Current version of a stored file.
+#[derive(Clone, Copy, Debug, Default, cbor::Encode, cbor::Decode)]
+struct FileEntry {
+    /// Hash of the stored (sealed) file.
+    hash: Hash,
+    /// Slot holding the stored file.
+    slot: u8,
+}
+
+/// Path at which the given slot of the file at the given path is stored.
+fn slot_path(path: &str, slot: u8) -> String {
+    format!("{SLOTS_PREFIX}{slot}/{path}")
+}
+
+/// A volume whose files are transparently sealed.
+pub struct EncryptedVolume {
+    manager: Arc<dyn VolumeManager>,
+    id: String,
+    cipher: DeoxysII,
+    manifest: Mutex<Manifest>,
+}
+
+impl EncryptedVolume {
+    /// Open the volume with the given identifier, using a key from the given source.
+    ///
+    /// A volume without an integrity manifest is treated as empty.
+    pub async fn open(
+        manager: Arc<dyn VolumeManager>,
+        id: String,
+        keys: &dyn VolumeKeySource,
+    ) -> Result<Self, EncryptedVolumeError> {
+        let mut key = keys
+            .volume_key(&id)
+            .await
+            .map_err(EncryptedVolumeError::Key)?;
+        let cipher = DeoxysII::new(&key);
+        key.zeroize();
+
+        let mut volume = Self {
+            manager,
+            id,
+            cipher,
+            manifest: Mutex::new(Manifest::default()),
+        };
+        if let Some(raw) = volume.read_raw(INTEGRITY_MANIFEST_PATH).await? {
+            let manifest = volume.open_file(INTEGRITY_MANIFEST_PATH, &raw)?;
+            *volume.manifest.get_mut() = cbor::from_slice(&manifest).map_err(|_| {
+                EncryptedVolumeError::Integrity(INTEGRITY_MANIFEST_PATH.to_string())
+            })?;
+        }
+
+        Ok(volume)
+    }
+
+    /// Identifier of the volume.
+    pub fn id(&self) -> &str {
+        &self.id
+    }
+
+    /// Read the file at the given path, returning `None` if it doesn't exist.
+    pub async fn read(&self, path: &str) -> Result<Option<Vec<u8>>, EncryptedVolumeError> {
+        Self::ensure_allowed(path)?;
+
+        // Files not in the manifest have not been written through the encrypted volume.
+        let entry = match self.manifest.lock().await.files.get(path) {
+            Some(entry) => *entry,
+            None => return Ok(None),
+        };
+        let raw = self
+            .read_raw(&slot_path(path, entry.slot))
+            .await?
+            .ok_or_else(|| EncryptedVolumeError::Integrity(path.to_string()))?;
+        if Hash::digest_bytes(&raw) != entry.hash {
+            return Err(EncryptedVolumeError::Integrity(path.to_string()));
+        }
+
+        self.open_file(path, &raw).map(Some)
+    }
+
+    /// Write the file at the given path, replacing any existing file.
+    pub async fn write(&self, path: &str, data: &[u8]) -> Result<(), EncryptedVolumeError> {
+        Self::ensure_allowed(path)?;
+
+        // Hold the manifest lock during the whole write so that concurrent writes don't lose
+        // manifest updates.
+        let mut manifest = self.manifest.lock().await;
+        let slot = match manifest.files.get(path) {
+            Some(entry) => 1 - entry.slot,
+            None => 0,
+        };
+        let raw = self.seal_file(path, data);
+        let hash = Hash::digest_bytes(&raw);
+        self.write_raw(&slot_path(path, slot), raw).await?;
+
+        // Only the manifest write makes the new version current.
+        let mut updated = manifest.clone();
+        updated
+            .files
+            .insert(path.to_string(), FileEntry { hash, slot });
+        let raw = self.seal_file(INTEGRITY_MANIFEST_PATH, &cbor::to_vec(updated.clone()));
+        self.write_raw(INTEGRITY_MANIFEST_PATH, raw).await?;
+        *manifest = updated;
+
+        Ok(())
+    }
+
+    fn ensure_allowed(path: &str) -> Result<(), EncryptedVolumeError> {
+        if path == INTEGRITY_MANIFEST_PATH {
+            return Err(EncryptedVolumeError::ReservedPath(path.to_string()));
+        }
+        Ok(())
+    }
+
+    fn additional_data(&self, path: &str) -> Vec<u8> {
+        let id_len = (self.id.len() as u32).to_be_bytes();
+        [SEAL_CONTEXT, &id_len, self.id.as_bytes(), path.as_bytes()].concat()
+    }
+
+    fn seal_file(&self, path: &str, data: &[u8]) -> Vec<u8> {
+        let mut nonce = [0u8; NONCE_SIZE];
+        SecureRng.fill(&mut nonce);
+        let data = self
+            .cipher
+            .seal(&nonce, data.to_vec(), self.additional_data(path));
+
+        cbor::to_vec(SealedFile {
+            nonce: nonce.to_vec(),
+            data,
+        })
+    }
+
+    fn open_file(&self, path: &str, raw: &[u8]) -> Result<Vec<u8>, EncryptedVolumeError> {
+        let integrity = || EncryptedVolumeError::Integrity(path.to_string());
+        let file: SealedFile = cbor::from_slice(raw).map_err(|_| integrity())?;
+        let nonce: [u8; NONCE_SIZE] = file.nonce.try_into().map_err(|_| integrity())?;
+
+        self.cipher
+            .open(&nonce, file.data, self.additional_data(path))
+            .map_err(|_| integrity())
+    }
+
+    async fn read_raw(&self, path: &str) -> Result<Option<Vec<u8>>, EncryptedVolumeError> {
+        let rsp = self
+            .manager
+            .volume_read(VolumeReadRequest {
+                id: self.id.clone(),
+                path: path.to_string(),
+                ..Default::default()
+            })
+            .await?;
+        Ok(rsp.data)
+    }
+
+    async fn write_raw(&self, path: &str, data: Vec<u8>) -> Result<(), EncryptedVolumeError> {
+        self.manager
+            .volume_write(VolumeWriteRequest {
+                id: self.id.clone(),
+                path: path.to_string(),
+                data,
+            })
+            .await?;
+        Ok(())
+    }
+}
+
+/// Files are persisted before writes return, so flushing waits for in-flight writes.
+#[async_trait]
+impl Flush for EncryptedVolume {
+    async fn flush(&self) -> anyhow::Result<()> {
+        drop(self.manifest.lock().await);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::testkit::host::MockHost;
+
+    #[test]
+    fn test_encrypted_volume() {
+        let rt = tokio::runtime::Builder::new_current_thread()
+            .build()
+            .unwrap();
+        rt.block_on(check_encrypted_volume());
+    }
+
+    async fn check_encrypted_volume() {
+        let manager = Arc::new(MockHost::new());
+        let get = |path: &str| manager.volume_file("vol", path).unwrap();
+        let set = |path: &str, data| manager.set_volume_file("vol", path, data);
+        let keys = SecretKeySource::new([1; KEY_SIZE]);
+        let volume = EncryptedVolume::open(manager.clone(), "vol".to_string(), &keys)
+            .await
+            .unwrap();
+
+        volume.write("a", b"hello").await.unwrap();
+        volume.write("b", b"world").await.unwrap();
+        assert_eq!(volume.read("a").await.unwrap(), Some(b"hello".to_vec()));
+        assert_eq!(volume.read("c").await.unwrap(), None);
+        assert!(!get(&slot_path("a", 0))
+            .windows(5)
+            .any(|window| window == b"hello"));
+        assert!(matches!(
+            volume.write(INTEGRITY_MANIFEST_PATH, b"").await,
+            Err(EncryptedVolumeError::ReservedPath(_))
+        ));
+
+        // Files can be read after reopening the volume, but only with the right key.
+        let volume = EncryptedVolume::open(manager.clone(), "vol".to_string(), &keys)
+            .await
+            .unwrap();
+        assert_eq!(volume.read("b").await.unwrap(), Some(b"world".to_vec()));
+        let other_keys = SecretKeySource::new([2; KEY_SIZE]);
+        assert!(matches!(
+            EncryptedVolume::open(manager.clone(), "vol".to_string(), &other_keys).await,
+            Err(EncryptedVolumeError::Integrity(_))
+        ));
+
+        // Writes interrupted before the manifest is written leave the previous version intact.
+        let manifest = get(INTEGRITY_MANIFEST_PATH);
+        volume.write("b", b"interrupted").await.unwrap();
+        set(INTEGRITY_MANIFEST_PATH, manifest);
+        let volume = EncryptedVolume::open(manager.clone(), "vol".to_string(), &keys)
+            .await
+            .unwrap();
+        assert_eq!(volume.read("b").await.unwrap(), Some(b"world".to_vec()));
+
+        // Swapped, rolled back and unknown files are detected.
+        let (old_a, b) = (get(&slot_path("a", 0)), get(&slot_path("b", 0)));
+        set(&slot_path("a", 0), b);
+        assert!(matches!(
+            volume.read("a").await,
+            Err(EncryptedVolumeError::Integrity(_))
+        ));
+        volume.write("a", b"updated").await.unwrap();
+        set(&slot_path("a", 1), old_a);
+        assert!(matches!(
+            volume.read("a").await,
+            Err(EncryptedVolumeError::Integrity(_))
+        ));
+        set(&slot_path("c", 0), get(&slot_path("b", 0)));
+        assert_eq!(volume.read("c").await.unwrap(), None);
+    }
+}
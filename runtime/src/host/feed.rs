@@ -0,0 +1,280 @@
+//! Verified external data feeds.
+//!
+//! Some external data (e.g. exchange rates or randomness beacons) is published by a known set of
+//! signers instead of being fetched on demand via the oracle. The host pushes data published on
+//! the feeds the runtime subscribed to as notifications, each accompanied by the signatures of the
+//! feed signers. As the host is not trusted, data is only delivered to runtime code after
+//! verifying that enough distinct signers of the feed signed it and that it is newer than any data
+//! previously delivered for the same feed, so that the host can't replay stale data.
+//!
+//! The last delivered sequence numbers are persisted sealed to the enclave in the untrusted
+//! local storage before the data is delivered, so that data can't be replayed after a restart
+//! either. As the host can roll back its local storage, this is a defense in depth only.
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    sync::Mutex,
+};
+
+use rustc_hex::ToHex;
+use sgx_isa::Keypolicy;
+use thiserror::Error;
+
+use crate::{
+    common::{
+        crypto::signature::{PublicKey, SignatureBundle},
+        sgx::seal,
+    },
+    storage::KeyValue,
+};
+
+/// Signature context used for external data feed publications.
+pub const FEED_SIGNATURE_CONTEXT: &[u8] = b"oasis-core/runtime: external data feed";
+
+/// Storage key prefix of persisted last sequence numbers.
+const SEQUENCE_STORAGE_KEY_PREFIX: &str = "host.feed.last_sequence";
+
+/// Domain separation context of sealed last sequence numbers.
+const SEQUENCE_CONTEXT: &[u8] = b"oasis-core/runtime: external data feed sequence";
+
+/// External data feed errors.
+#[derive(Error, Debug)]
+pub enum FeedError {
+    #[error("unknown feed: {0}")]
+    UnknownFeed(String),
+
+    #[error("no quorum (got: {got} required: {required})")]
+    NoQuorum { got: usize, required: usize },
+
+    #[error("stale data (sequence: {sequence} last: {last})")]
+    Stale { sequence: u64, last: u64 },
+
+    #[error("failed to persist sequence: {0}")]
+    Persist(String),
+}
+
+/// Signer set of an external data feed.
+#[derive(Clone, Debug, Default)]
+pub struct FeedSigners {
+    /// Public keys of the signers.
+    pub signers: BTreeSet<PublicKey>,
+    /// Number of distinct signers required to sign each publication.
+    pub threshold: usize,
+}
+
+/// Data published on an external data feed.
+#[derive(Clone, Debug, Default, PartialEq, Eq, cbor::Encode, cbor::Decode)]
+pub struct FeedData {
+    /// Name of the feed.
+    pub feed: String,
+    /// Sequence number of the publication, increasing with each publication on the feed.
+    pub sequence: u64,
+    /// Published data.
+    pub data: Vec<u8>,
+}
+
+/// Data published on an external data feed together with the signatures of the feed signers.
+#[derive(Clone, Debug, Default, PartialEq, Eq, cbor::Encode, cbor::Decode)]
+pub struct SignedFeedData {
+    /// Published data.
+    pub data: FeedData,
+    /// Signatures over the published data.
+    pub signatures: Vec<SignatureBundle>,
+}
+
+impl SignedFeedData {
+    /// Verify that at least the threshold of distinct signers of the given signer set signed
+    /// the data, returning the signers.
+    ///
+    /// Signatures by non-signers and invalid signatures are ignored.
+    pub fn verify(&self, signers: &FeedSigners) -> Result<Vec<PublicKey>, FeedError> {
+        let message = cbor::to_vec(self.data.clone());
+        let valid: BTreeSet<_> = self
+            .signatures
+            .iter()
+            .filter(|sig| signers.signers.contains(&sig.public_key))
+            .filter(|sig| sig.verify(FEED_SIGNATURE_CONTEXT, &message))
+            .map(|sig| sig.public_key)
+            .collect();
+
+        // A zero threshold would accept unsigned data.
+        let required = signers.threshold.max(1);
+        if valid.len() < required {
+            return Err(FeedError::NoQuorum {
+                got: valid.len(),
+                required,
+            });
+        }
+        Ok(valid.into_iter().collect())
+    }
+}
+
+/// Verified data published on an external data feed.
+#[derive(Clone, Debug)]
+pub struct VerifiedFeedData {
+    /// Published data.
+    pub data: FeedData,
+    /// Signers that signed the data.
+    pub signers: Vec<PublicKey>,
+}
+
+/// Verifier of data pushed by the host for the configured feeds.
+pub struct FeedVerifier {
+    feeds: BTreeMap<String, FeedSigners>,
+    last_sequence: Mutex<BTreeMap<String, u64>>,
+}
+
+impl FeedVerifier {
+    /// Create a new verifier for the given feeds.
+    pub fn new(feeds: BTreeMap<String, FeedSigners>) -> Self {
+        Self {
+            feeds,
+            last_sequence: Mutex::new(BTreeMap::new()),
+        }
+    }
+
+    /// Verify data pushed by the host, persisting its sequence number in the given untrusted
+    /// local storage.
+    ///
+    /// Data of each feed is only accepted once and in order of increasing sequence numbers.
+    pub fn verify(
+        &self,
+        signed: SignedFeedData,
+        storage: &dyn KeyValue,
+    ) -> Result<VerifiedFeedData, FeedError> {
+        let feed = &signed.data.feed;
+        let signers = self
+            .feeds
+            .get(feed)
+            .ok_or_else(|| FeedError::UnknownFeed(feed.clone()))?;
+        let signers = signed.verify(signers)?;
+
+        let mut last_sequence = self.last_sequence.lock().unwrap();
+        let sequence = signed.data.sequence;
+        let last = match last_sequence.get(feed) {
+            Some(&last) => Some(last),
+            None => Self::load_sequence(storage, feed),
+        };
+        if let Some(last) = last {
+            if sequence <= last {
+                return Err(FeedError::Stale { sequence, last });
+            }
+        }
+        Self::store_sequence(storage, feed, sequence)?;
+        last_sequence.insert(feed.clone(), sequence);
+
+        Ok(VerifiedFeedData {
+            data: signed.data,
+            signers,
+        })
+    }
+
+    fn storage_key(feed: &str) -> Vec<u8> {
+        format!(
+            "{SEQUENCE_STORAGE_KEY_PREFIX}.{}",
+            feed.as_bytes().to_hex::<String>()
+        )
+        .into_bytes()
+    }
+
+    fn load_sequence(storage: &dyn KeyValue, feed: &str) -> Option<u64> {
+        let sealed = storage.get(Self::storage_key(feed)).ok()?;
+        let raw = seal::unseal(Keypolicy::MRENCLAVE, SEQUENCE_CONTEXT, &sealed).ok()??;
+        cbor::from_slice(&raw).ok()
+    }
+
+    fn store_sequence(storage: &dyn KeyValue, feed: &str, sequence: u64) -> Result<(), FeedError> {
+        let sealed = seal::seal(
+            Keypolicy::MRENCLAVE,
+            SEQUENCE_CONTEXT,
+            &cbor::to_vec(sequence),
+        );
+        storage
+            .insert(Self::storage_key(feed), sealed)
+            .map_err(|err| FeedError::Persist(err.to_string()))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{
+        common::crypto::signature::{PrivateKey, Signer},
+        storage::UntrustedInMemoryStorage,
+    };
+
+    fn sign(sk: &PrivateKey, data: &FeedData) -> SignatureBundle {
+        SignatureBundle {
+            public_key: sk.public_key(),
+            signature: Signer::sign(sk, FEED_SIGNATURE_CONTEXT, &cbor::to_vec(data.clone()))
+                .unwrap(),
+        }
+    }
+
+    #[test]
+    fn test_feed_verifier() {
+        let keys: Vec<_> = (0..3)
+            .map(|i| PrivateKey::from_test_seed(format!("feed signer {i}")))
+            .collect();
+        let outsider = PrivateKey::from_test_seed("feed outsider".to_string());
+        let feeds = BTreeMap::from([(
+            "prices".to_string(),
+            FeedSigners {
+                signers: keys.iter().map(|sk| sk.public_key()).collect(),
+                threshold: 2,
+            },
+        )]);
+        let verifier = FeedVerifier::new(feeds.clone());
+        let storage = UntrustedInMemoryStorage::new();
+
+        let data = FeedData {
+            feed: "prices".to_string(),
+            sequence: 5,
+            data: b"ROSE/USD 0.05".to_vec(),
+        };
+        let mut signed = SignedFeedData {
+            data: data.clone(),
+            signatures: vec![sign(&keys[0], &data), sign(&keys[0], &data)],
+        };
+
+        // Duplicate and non-signer signatures don't count.
+        signed.signatures.push(sign(&outsider, &data));
+        assert!(matches!(
+            verifier.verify(signed.clone(), &storage),
+            Err(FeedError::NoQuorum {
+                got: 1,
+                required: 2
+            })
+        ));
+
+        signed.signatures.push(sign(&keys[1], &data));
+        let verified = verifier.verify(signed.clone(), &storage).unwrap();
+        assert_eq!(verified.data, data);
+        assert_eq!(verified.signers.len(), 2);
+
+        // Data can't be replayed, including after a restart.
+        assert!(matches!(
+            verifier.verify(signed.clone(), &storage),
+            Err(FeedError::Stale {
+                sequence: 5,
+                last: 5
+            })
+        ));
+        assert!(matches!(
+            FeedVerifier::new(feeds).verify(signed.clone(), &storage),
+            Err(FeedError::Stale {
+                sequence: 5,
+                last: 5
+            })
+        ));
+
+        // Signatures are bound to the data.
+        signed.data.sequence = 6;
+        assert!(verifier.verify(signed.clone(), &storage).is_err());
+
+        signed.data.feed = "other".to_string();
+        assert!(matches!(
+            verifier.verify(signed, &storage),
+            Err(FeedError::UnknownFeed(_))
+        ));
+    }
+}
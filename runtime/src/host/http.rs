@@ -0,0 +1,701 @@
+//! Outbound HTTPS requests tunnelled through the host.
+//!
+//! Runtimes have no network access of their own, so connections to external HTTPS endpoints are
+//! opened by the host, which only relays the raw bytes. As the host is not trusted, TLS is
+//! terminated inside the runtime and server certificates are validated against the CA
+//! certificates configured in the runtime's [`HttpPolicy`], so that the host can neither read nor
+//! tamper with requests and responses. Requests can only be made to the endpoints allowed by the
+//! policy and responses larger than the endpoint's limit are rejected.
+//!
+//! As the host can close the connection at any time, responses are only accepted in case their
+//! end is authenticated by TLS, i.e. their body length is given by the `Content-Length` header or
+//! the chunked transfer encoding. Each request must complete within the policy's timeout, so that
+//! the host can't stall it by withholding data.
+use std::{
+    io::{self, Read, Write},
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use anyhow::anyhow;
+use mbedtls::{
+    ssl::{
+        config::{AuthMode, Endpoint, Preset, Transport as SslTransport},
+        Config as SslConfig, Context,
+    },
+    x509::certificate::Certificate,
+};
+use thiserror::Error;
+
+use crate::{
+    future::block_on,
+    protocol::{CallOpts, Protocol},
+    transport::{rng, with_nul},
+    types::{Body, HttpTunnelOp, ProtocolFeature},
+};
+
+use super::Error as HostError;
+
+/// Default port of HTTPS endpoints.
+const DEFAULT_PORT: u16 = 443;
+/// Default maximum time for a request to complete.
+const DEFAULT_REQUEST_TIMEOUT: Duration = Duration::from_secs(30);
+/// Maximum time for closing a tunnel.
+const CLOSE_TIMEOUT: Duration = Duration::from_secs(5);
+/// Maximum number of bytes read from the tunnel in a single host call.
+const MAX_TUNNEL_READ: usize = 32 * 1024;
+/// Headers set by the client itself, which can't be overridden by requests.
+const RESERVED_HEADERS: &[&str] = &["host", "connection", "content-length", "transfer-encoding"];
+
+/// HTTPS client errors.
+#[derive(Error, Debug)]
+pub enum HttpError {
+    #[error("host error: {0}")]
+    Host(#[from] HostError),
+
+    #[error("invalid url: {0}")]
+    InvalidUrl(String),
+
+    #[error("invalid request: {0}")]
+    InvalidRequest(String),
+
+    #[error("endpoint not allowed: {0}")]
+    NotAllowed(String),
+
+    #[error("tls error: {0}")]
+    Tls(#[source] anyhow::Error),
+
+    #[error("i/o error: {0}")]
+    Io(#[from] io::Error),
+
+    #[error("request timed out")]
+    Timeout,
+
+    #[error("response too large (limit: {limit})")]
+    ResponseTooLarge { limit: usize },
+
+    #[error("malformed response: {0}")]
+    MalformedResponse(String),
+}
+
+/// An endpoint that requests may be made to.
+#[derive(Clone, Debug)]
+pub struct HttpEndpoint {
+    /// Host name of the endpoint.
+    pub host: String,
+    /// Port of the endpoint.
+    pub port: u16,
+    /// Prefixes of the allowed request paths. In case it is empty, all paths are allowed.
+    pub path_prefixes: Vec<String>,
+    /// Maximum size of responses, including the status line and headers.
+    pub max_response_size: usize,
+}
+
+impl HttpEndpoint {
+    /// Whether requests to the given URL are allowed.
+    pub fn allows(&self, url: &Url) -> bool {
+        self.host.eq_ignore_ascii_case(&url.host)
+            && self.port == url.port
+            && (self.path_prefixes.is_empty()
+                || self
+                    .path_prefixes
+                    .iter()
+                    .any(|prefix| url.path.starts_with(prefix.as_str())))
+    }
+}
+
+/// Policy for outbound HTTPS requests.
+#[derive(Clone, Debug, Default)]
+pub struct HttpPolicy {
+    /// Endpoints requests may be made to.
+    pub endpoints: Vec<HttpEndpoint>,
+    /// PEM-encoded CA certificates that server certificates are validated against.
+    pub ca_certificates: Vec<u8>,
+    /// Maximum time for a request to complete, including connecting to the endpoint. In case it
+    /// is not set, a default of 30 seconds is used.
+    pub timeout: Option<Duration>,
+}
+
+impl HttpPolicy {
+    /// Endpoint allowing requests to the given URL.
+    pub fn endpoint(&self, url: &Url) -> Result<&HttpEndpoint, HttpError> {
+        self.endpoints
+            .iter()
+            .find(|endpoint| endpoint.allows(url))
+            .ok_or_else(|| HttpError::NotAllowed(format!("{}:{}{}", url.host, url.port, url.path)))
+    }
+}
+
+/// An HTTPS URL.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Url {
+    /// Host name, in lowercase.
+    pub host: String,
+    /// Port.
+    pub port: u16,
+    /// Path, including the query.
+    pub path: String,
+}
+
+impl Url {
+    /// Parse the given HTTPS URL.
+    ///
+    /// Only host names are supported (no IP literals or user information) and paths must not
+    /// contain dot segments, so that they can't escape the allowed path prefixes.
+    pub fn parse(url: &str) -> Result<Self, HttpError> {
+        let invalid = || HttpError::InvalidUrl(url.to_string());
+
+        let rest = url.strip_prefix("https://").ok_or_else(invalid)?;
+        if rest
+            .bytes()
+            .any(|b| b.is_ascii_whitespace() || b.is_ascii_control())
+        {
+            return Err(invalid());
+        }
+        let rest = rest.split('#').next().unwrap_or_default();
+        let (authority, path) = match rest.find(|c| c == '/' || c == '?') {
+            Some(index) if rest[index..].starts_with('?') => {
+                (&rest[..index], format!("/{}", &rest[index..]))
+            }
+            Some(index) => (&rest[..index], rest[index..].to_string()),
+            None => (rest, "/".to_string()),
+        };
+
+        let (host, port) = match authority.rsplit_once(':') {
+            Some((host, port)) => (host, port.parse().map_err(|_| invalid())?),
+            None => (authority, DEFAULT_PORT),
+        };
+        let valid_host = !host.is_empty()
+            && host
+                .bytes()
+                .all(|b| b.is_ascii_alphanumeric() || b == b'-' || b == b'.');
+        if !valid_host {
+            return Err(invalid());
+        }
+
+        let has_dot_segments = path
+            .split('?')
+            .next()
+            .unwrap_or_default()
+            .split('/')
+            .map(|segment| segment.to_ascii_lowercase().replace("%2e", "."))
+            .any(|segment| segment == "." || segment == "..");
+        if has_dot_segments {
+            return Err(invalid());
+        }
+
+        Ok(Self {
+            host: host.to_ascii_lowercase(),
+            port,
+            path,
+        })
+    }
+}
+
+/// An HTTP request.
+#[derive(Clone, Debug, Default)]
+pub struct HttpRequest {
+    /// Request method.
+    pub method: String,
+    /// HTTPS URL of the request.
+    pub url: String,
+    /// Request headers.
+    pub headers: Vec<(String, String)>,
+    /// Request body.
+    pub body: Vec<u8>,
+}
+
+impl HttpRequest {
+    /// Create a new GET request.
+    pub fn get(url: &str) -> Self {
+        Self {
+            method: "GET".to_string(),
+            url: url.to_string(),
+            ..Default::default()
+        }
+    }
+
+    /// Create a new POST request with the given body.
+    pub fn post(url: &str, body: Vec<u8>) -> Self {
+        Self {
+            method: "POST".to_string(),
+            url: url.to_string(),
+            body,
+            ..Default::default()
+        }
+    }
+
+    /// Add the given header to the request.
+    pub fn with_header(mut self, name: &str, value: &str) -> Self {
+        self.headers.push((name.to_string(), value.to_string()));
+        self
+    }
+
+    /// Encode the request as sent to the given URL.
+    fn encode(&self, url: &Url) -> Result<Vec<u8>, HttpError> {
+        if self.method.is_empty() || !self.method.bytes().all(|b| b.is_ascii_uppercase()) {
+            return Err(HttpError::InvalidRequest(format!(
+                "bad method: {}",
+                self.method
+            )));
+        }
+
+        let host = match url.port {
+            DEFAULT_PORT => url.host.clone(),
+            port => format!("{}:{}", url.host, port),
+        };
+        let mut head = format!(
+            "{} {} HTTP/1.1\r\nHost: {}\r\nConnection: close\r\nContent-Length: {}\r\n",
+            self.method,
+            url.path,
+            host,
+            self.body.len()
+        );
+        for (name, value) in &self.headers {
+            let valid_name = !name.is_empty()
+                && name
+                    .bytes()
+                    .all(|b| b.is_ascii_alphanumeric() || b"-_".contains(&b));
+            let valid_value = !value.bytes().any(|b| b == b'\r' || b == b'\n');
+            if !valid_name || !valid_value {
+                return Err(HttpError::InvalidRequest(format!("bad header: {name}")));
+            }
+            if RESERVED_HEADERS.contains(&name.to_ascii_lowercase().as_str()) {
+                return Err(HttpError::InvalidRequest(format!(
+                    "reserved header: {name}"
+                )));
+            }
+            head.push_str(&format!("{name}: {value}\r\n"));
+        }
+        head.push_str("\r\n");
+
+        let mut data = head.into_bytes();
+        data.extend_from_slice(&self.body);
+        Ok(data)
+    }
+}
+
+/// An HTTP response.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct HttpResponse {
+    /// Status code.
+    pub status: u16,
+    /// Response headers.
+    pub headers: Vec<(String, String)>,
+    /// Response body, with any transfer encoding removed.
+    pub body: Vec<u8>,
+}
+
+impl HttpResponse {
+    /// Value of the given header, matched case-insensitively.
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(n, _)| n.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+}
+
+/// A TCP connection opened by the host.
+struct Tunnel {
+    protocol: Arc<Protocol>,
+    id: u64,
+    deadline: Instant,
+}
+
+impl Tunnel {
+    /// Ask the host to open a new connection to the given URL's endpoint, which must be used
+    /// until the given deadline.
+    fn open(protocol: Arc<Protocol>, url: &Url, deadline: Instant) -> Result<Self, HttpError> {
+        let feature = ProtocolFeature::HttpTunnels;
+        if !protocol.features().contains(feature) {
+            return Err(HostError::FeatureNotNegotiated(feature.name()).into());
+        }
+
+        let op = HttpTunnelOp::Open {
+            host: url.host.clone(),
+            port: url.port,
+        };
+        match Self::call_host(&protocol, 0, deadline, op)? {
+            Body::HostHttpTunnelResponse { tunnel, .. } if tunnel != 0 => Ok(Self {
+                protocol,
+                id: tunnel,
+                deadline,
+            }),
+            _ => Err(HostError::BadResponse.into()),
+        }
+    }
+
+    fn call_host(
+        protocol: &Protocol,
+        tunnel: u64,
+        deadline: Instant,
+        op: HttpTunnelOp,
+    ) -> Result<Body, HttpError> {
+        let timeout = deadline
+            .checked_duration_since(Instant::now())
+            .filter(|timeout| !timeout.is_zero())
+            .ok_or(HttpError::Timeout)?;
+        let opts = CallOpts {
+            timeout: Some(timeout),
+            ..Default::default()
+        };
+        block_on(
+            protocol.call_host_async_with_opts(Body::HostHttpTunnelRequest { tunnel, op }, opts),
+        )
+        .map_err(|err| HostError::from(err).into())
+    }
+
+    fn call(&self, op: HttpTunnelOp) -> io::Result<Vec<u8>> {
+        match Self::call_host(&self.protocol, self.id, self.deadline, op) {
+            Ok(Body::HostHttpTunnelResponse { data, .. }) => Ok(data),
+            Ok(_) => Err(io::Error::new(io::ErrorKind::Other, HostError::BadResponse)),
+            Err(HttpError::Timeout) => {
+                Err(io::Error::new(io::ErrorKind::TimedOut, HttpError::Timeout))
+            }
+            Err(err) => Err(io::Error::new(io::ErrorKind::Other, err)),
+        }
+    }
+}
+
+impl Read for Tunnel {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let max = buf.len().min(MAX_TUNNEL_READ);
+        let data = self.call(HttpTunnelOp::Read { max: max as u32 })?;
+        if data.len() > max {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "host returned more data than requested",
+            ));
+        }
+        buf[..data.len()].copy_from_slice(&data);
+        Ok(data.len())
+    }
+}
+
+impl Write for Tunnel {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.call(HttpTunnelOp::Write { data: buf.to_vec() })?;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Drop for Tunnel {
+    fn drop(&mut self) {
+        // The host closes the connection on its own in case this fails. Closing is allowed to
+        // take place after the request deadline has passed.
+        let deadline = Instant::now() + CLOSE_TIMEOUT;
+        let _ = Self::call_host(&self.protocol, self.id, deadline, HttpTunnelOp::Close {});
+    }
+}
+
+/// HTTPS client tunnelling requests through the host.
+#[derive(Clone)]
+pub struct HttpClient {
+    protocol: Arc<Protocol>,
+    policy: Arc<HttpPolicy>,
+    tls_config: Arc<SslConfig>,
+}
+
+impl HttpClient {
+    /// Create a new client using the HTTPS policy from the runtime configuration.
+    pub fn new(protocol: Arc<Protocol>) -> Result<Self, HttpError> {
+        let policy = protocol.get_config().http_policy.clone();
+        if policy.ca_certificates.is_empty() {
+            return Err(HttpError::Tls(anyhow!("no CA certificates configured")));
+        }
+        let ca_certificates = Certificate::from_pem_multiple(&with_nul(&policy.ca_certificates))
+            .map_err(|err| HttpError::Tls(err.into()))?;
+
+        let mut tls_config =
+            SslConfig::new(Endpoint::Client, SslTransport::Stream, Preset::Default);
+        tls_config.set_rng(rng().map_err(HttpError::Tls)?);
+        tls_config.set_authmode(AuthMode::Required);
+        tls_config.set_ca_list(Arc::new(ca_certificates), None);
+
+        Ok(Self {
+            protocol,
+            policy: Arc::new(policy),
+            tls_config: Arc::new(tls_config),
+        })
+    }
+
+    /// Make the given request.
+    pub async fn request(&self, request: HttpRequest) -> Result<HttpResponse, HttpError> {
+        // NOTE: TLS connections are blocking, so they are handled in a blocking task.
+        let client = self.clone();
+        tokio::task::spawn_blocking(move || client.request_blocking(request))
+            .await
+            .map_err(|err| HttpError::Io(io::Error::new(io::ErrorKind::Other, err)))?
+    }
+
+    /// Make the given request, blocking the current thread until the response is received.
+    ///
+    /// This must not be called from an async context.
+    pub fn request_blocking(&self, request: HttpRequest) -> Result<HttpResponse, HttpError> {
+        let url = Url::parse(&request.url)?;
+        let endpoint = self.policy.endpoint(&url)?;
+        let data = request.encode(&url)?;
+
+        let timeout = self.policy.timeout.unwrap_or(DEFAULT_REQUEST_TIMEOUT);
+        let tunnel = Tunnel::open(self.protocol.clone(), &url, Instant::now() + timeout)?;
+        let mut context = Context::new(self.tls_config.clone());
+        context
+            .establish(tunnel, Some(url.host.as_str()))
+            .map_err(|err| HttpError::Tls(err.into()))?;
+        context.write_all(&data)?;
+        context.flush()?;
+
+        let response =
+            read_limited(&mut context, endpoint.max_response_size).map_err(|err| match err {
+                HttpError::Io(err) if err.kind() == io::ErrorKind::TimedOut => HttpError::Timeout,
+                err => err,
+            })?;
+        parse_response(&response)
+    }
+}
+
+/// Read until the end of the stream, failing in case more than the given number of bytes is
+/// available.
+fn read_limited<R: Read>(reader: &mut R, limit: usize) -> Result<Vec<u8>, HttpError> {
+    let mut data = Vec::new();
+    reader
+        .by_ref()
+        .take(limit as u64 + 1)
+        .read_to_end(&mut data)?;
+    if data.len() > limit {
+        return Err(HttpError::ResponseTooLarge { limit });
+    }
+    Ok(data)
+}
+
+fn malformed(reason: &str) -> HttpError {
+    HttpError::MalformedResponse(reason.to_string())
+}
+
+/// Parse a complete HTTP/1.x response.
+fn parse_response(data: &[u8]) -> Result<HttpResponse, HttpError> {
+    let header_end = data
+        .windows(4)
+        .position(|w| w == b"\r\n\r\n")
+        .ok_or_else(|| malformed("missing end of headers"))?;
+    let head = std::str::from_utf8(&data[..header_end]).map_err(|_| malformed("bad headers"))?;
+    let body = &data[header_end + 4..];
+
+    let mut lines = head.split("\r\n");
+    let mut status_line = lines.next().unwrap_or_default().splitn(3, ' ');
+    if !matches!(status_line.next(), Some("HTTP/1.1" | "HTTP/1.0")) {
+        return Err(malformed("bad status line"));
+    }
+    let status = status_line
+        .next()
+        .and_then(|status| status.parse().ok())
+        .filter(|status| (100..600).contains(status))
+        .ok_or_else(|| malformed("bad status code"))?;
+    let headers = lines
+        .map(|line| {
+            let (name, value) = line
+                .split_once(':')
+                .ok_or_else(|| malformed("bad header"))?;
+            Ok((name.trim().to_string(), value.trim().to_string()))
+        })
+        .collect::<Result<Vec<_>, HttpError>>()?;
+
+    let mut response = HttpResponse {
+        status,
+        headers,
+        body: vec![],
+    };
+    let chunked = response
+        .header("transfer-encoding")
+        .is_some_and(|encoding| encoding.eq_ignore_ascii_case("chunked"));
+    // Responses to which a body is not allowed are exempt from having their length given.
+    let bodyless = matches!(status, 100..=199 | 204 | 304);
+    response.body = if chunked {
+        decode_chunked(body)?
+    } else if let Some(length) = response.header("content-length") {
+        let length: usize = length
+            .parse()
+            .map_err(|_| malformed("bad content length"))?;
+        body.get(..length)
+            .ok_or_else(|| malformed("truncated body"))?
+            .to_vec()
+    } else if bodyless {
+        vec![]
+    } else {
+        // The body would extend until the connection is closed, which the host can do at any
+        // time to truncate it.
+        return Err(malformed("missing content length"));
+    };
+
+    Ok(response)
+}
+
+/// Decode a body with chunked transfer encoding, ignoring chunk extensions and trailers.
+fn decode_chunked(mut data: &[u8]) -> Result<Vec<u8>, HttpError> {
+    let mut body = Vec::new();
+    loop {
+        let line_end = data
+            .windows(2)
+            .position(|w| w == b"\r\n")
+            .ok_or_else(|| malformed("truncated chunk"))?;
+        let size = std::str::from_utf8(&data[..line_end])
+            .ok()
+            .and_then(|line| {
+                let size = line.split(';').next().unwrap_or_default().trim();
+                usize::from_str_radix(size, 16).ok()
+            })
+            .ok_or_else(|| malformed("bad chunk size"))?;
+        data = &data[line_end + 2..];
+        if size == 0 {
+            return Ok(body);
+        }
+
+        let rest = data
+            .get(size..)
+            .filter(|rest| rest.starts_with(b"\r\n"))
+            .ok_or_else(|| malformed("truncated chunk"))?;
+        body.extend_from_slice(&data[..size]);
+        data = &rest[2..];
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::Cursor;
+
+    use super::*;
+
+    #[test]
+    fn test_url() {
+        let url = Url::parse("https://API.example.com/v1/price?pair=ROSE#top").unwrap();
+        assert_eq!(url.host, "api.example.com");
+        assert_eq!(url.port, 443);
+        assert_eq!(url.path, "/v1/price?pair=ROSE");
+
+        let url = Url::parse("https://example.com:8443?q=1").unwrap();
+        assert_eq!(url.port, 8443);
+        assert_eq!(url.path, "/?q=1");
+        assert_eq!(Url::parse("https://example.com").unwrap().path, "/");
+
+        for url in [
+            "http://example.com/",
+            "https://user@example.com/",
+            "https://[::1]/",
+            "https://example.com:99999/",
+            "https://example.com/a b",
+            "https://example.com/v1/../admin",
+            "https://example.com/v1/%2E%2e/admin",
+            "https:///",
+        ] {
+            assert!(
+                matches!(Url::parse(url), Err(HttpError::InvalidUrl(_))),
+                "{url}"
+            );
+        }
+    }
+
+    #[test]
+    fn test_policy() {
+        let policy = HttpPolicy {
+            endpoints: vec![HttpEndpoint {
+                host: "api.example.com".to_string(),
+                port: 443,
+                path_prefixes: vec!["/v1/".to_string()],
+                max_response_size: 1024,
+            }],
+            ..Default::default()
+        };
+
+        let allowed = Url::parse("https://api.example.com/v1/price").unwrap();
+        assert_eq!(policy.endpoint(&allowed).unwrap().max_response_size, 1024);
+        for url in [
+            "https://api.example.com/v2/price",
+            "https://api.example.com:8443/v1/price",
+            "https://example.com/v1/price",
+        ] {
+            let url = Url::parse(url).unwrap();
+            assert!(matches!(
+                policy.endpoint(&url),
+                Err(HttpError::NotAllowed(_))
+            ));
+        }
+    }
+
+    #[test]
+    fn test_encode_request() {
+        let url = Url::parse("https://example.com:8443/submit").unwrap();
+        let request = HttpRequest::post("https://example.com:8443/submit", b"{}".to_vec())
+            .with_header("Content-Type", "application/json");
+        assert_eq!(
+            request.encode(&url).unwrap(),
+            b"POST /submit HTTP/1.1\r\nHost: example.com:8443\r\nConnection: close\r\n\
+              Content-Length: 2\r\nContent-Type: application/json\r\n\r\n{}"
+        );
+
+        // Headers can't be injected or override the ones set by the client.
+        for (name, value) in [("X-Evil", "a\r\nHost: other"), ("Host", "other"), ("", "a")] {
+            let request = HttpRequest::get("https://example.com/").with_header(name, value);
+            assert!(matches!(
+                request.encode(&url),
+                Err(HttpError::InvalidRequest(_))
+            ));
+        }
+    }
+
+    #[test]
+    fn test_parse_response() {
+        let response =
+            parse_response(b"HTTP/1.1 200 OK\r\nContent-Length: 5\r\nX-A: b\r\n\r\nhello").unwrap();
+        assert_eq!(response.status, 200);
+        assert_eq!(response.header("x-a"), Some("b"));
+        assert_eq!(response.body, b"hello");
+
+        let response = parse_response(
+            b"HTTP/1.1 201 Created\r\nTransfer-Encoding: chunked\r\n\r\n\
+              3;ext=1\r\nhel\r\n2\r\nlo\r\n0\r\n\r\n",
+        )
+        .unwrap();
+        assert_eq!(response.status, 201);
+        assert_eq!(response.body, b"hello");
+
+        let response =
+            parse_response(b"HTTP/1.0 404 Not Found\r\nContent-Length: 7\r\n\r\nmissing").unwrap();
+        assert_eq!(response.status, 404);
+        assert_eq!(response.body, b"missing");
+
+        let response = parse_response(b"HTTP/1.1 204 No Content\r\n\r\n").unwrap();
+        assert_eq!(response.status, 204);
+        assert!(response.body.is_empty());
+
+        for data in [
+            &b"HTTP/1.1 200 OK\r\nContent-Length: 5\r\n"[..],
+            b"HTTP/1.1 200 OK\r\nContent-Length: 6\r\n\r\nhello",
+            b"HTTP/1.0 200 OK\r\n\r\nhello",
+            b"HTTP/1.1 20 OK\r\n\r\n",
+            b"SPDY/3 200 OK\r\n\r\n",
+            b"HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\n5\r\nhel",
+            b"HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\nzz\r\n",
+        ] {
+            assert!(matches!(
+                parse_response(data),
+                Err(HttpError::MalformedResponse(_))
+            ));
+        }
+    }
+
+    #[test]
+    fn test_response_size_limit() {
+        let data = b"HTTP/1.1 200 OK\r\n\r\nhello".to_vec();
+        let limit = data.len();
+        assert_eq!(read_limited(&mut Cursor::new(&data), limit).unwrap(), data);
+        assert!(matches!(
+            read_limited(&mut Cursor::new(&data), limit - 1),
+            Err(HttpError::ResponseTooLarge { limit: l }) if l == limit - 1
+        ));
+    }
+}
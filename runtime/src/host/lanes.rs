@@ -0,0 +1,294 @@
+//! Prioritized lanes of messages sent to the host.
+//!
+//! All messages sent to the host share a single channel, so responses to heavy query traffic
+//! could delay messages needed to make progress with round execution. Messages are therefore
+//! sent over one of two logical lanes, with the writer strictly preferring the consensus-critical
+//! lane (batch execution, commitment and attestation) over the best-effort lane (queries,
+//! notifications, bundle operations and telemetry). Messages within a lane keep their order.
+//!
+//! Requests made to the host are classified by the class of the host request they are made on
+//! behalf of (e.g. storage reads while handling a query are best-effort), which is tracked per
+//! task and propagated to blocking tasks via [`propagate_origin`]. So that a steady stream of
+//! critical messages can't starve the best-effort lane, a best-effort message is sent after at
+//! most [`MAX_CRITICAL_BURST`] consecutive critical messages.
+use std::{
+    cell::Cell,
+    fmt,
+    future::Future,
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+use crossbeam::channel::{self, Receiver, Select, Sender, TryRecvError};
+
+use super::queues::MessageClass;
+use crate::{
+    enclave_rpc,
+    types::{Body, Message},
+};
+
+/// Maximum number of consecutive critical messages sent while best-effort messages are waiting.
+pub const MAX_CRITICAL_BURST: usize = 16;
+
+tokio::task_local! {
+    /// Class of the host request the current task is handling.
+    static ORIGIN: MessageClass;
+}
+
+thread_local! {
+    /// Class of the host request the current blocking task is handling.
+    static BLOCKING_ORIGIN: Cell<Option<MessageClass>> = const { Cell::new(None) };
+}
+
+/// Class of the host request the current task is handling, if any.
+pub fn current_origin() -> Option<MessageClass> {
+    ORIGIN
+        .try_with(|class| *class)
+        .ok()
+        .or_else(|| BLOCKING_ORIGIN.with(Cell::get))
+}
+
+/// Run the given future on behalf of a host request of the given class.
+pub async fn with_origin<F: Future>(class: MessageClass, future: F) -> F::Output {
+    ORIGIN.scope(class, future).await
+}
+
+/// Wrap the given closure to run on behalf of the host request the current task is handling,
+/// e.g. in a blocking task.
+pub fn propagate_origin<R>(f: impl FnOnce() -> R) -> impl FnOnce() -> R {
+    let origin = current_origin();
+    move || {
+        let previous = BLOCKING_ORIGIN.with(|cell| cell.replace(origin));
+        let result = f();
+        BLOCKING_ORIGIN.with(|cell| cell.set(previous));
+        result
+    }
+}
+
+/// Logical lane of a message sent to the host.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Lane {
+    /// Messages needed to make progress with round execution.
+    Critical,
+    /// All other messages.
+    BestEffort,
+}
+
+impl Lane {
+    /// Lane of responses to requests of the given class received from the host.
+    pub fn of_class(class: MessageClass) -> Self {
+        match class {
+            MessageClass::Control | MessageClass::Execution => Self::Critical,
+            MessageClass::Notification | MessageClass::Query => Self::BestEffort,
+        }
+    }
+
+    /// Lane of the given request made to the host on behalf of a host request of the given
+    /// class, if any.
+    pub fn of_request(body: &Body, origin: Option<MessageClass>) -> Self {
+        match body {
+            // Local RPC calls are made to host components like the bundle manager.
+            Body::HostRPCCallRequest { kind, .. }
+                if *kind == enclave_rpc::types::Kind::LocalQuery =>
+            {
+                Self::BestEffort
+            }
+            Body::HostLogRecordsRequest { .. }
+            | Body::HostMetricsPushRequest { .. }
+            | Body::HostHealthReportRequest { .. } => Self::BestEffort,
+            _ => origin.map_or(Self::Critical, Self::of_class),
+        }
+    }
+}
+
+impl fmt::Display for Lane {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Lane::Critical => "critical",
+            Lane::BestEffort => "best-effort",
+        };
+        f.write_str(name)
+    }
+}
+
+/// Outgoing messages, queued per lane.
+pub struct Lanes {
+    critical: (Sender<Message>, Receiver<Message>),
+    best_effort: (Sender<Message>, Receiver<Message>),
+    critical_burst: AtomicUsize,
+}
+
+impl Lanes {
+    /// Create new empty lanes.
+    pub fn new() -> Self {
+        Self {
+            critical: channel::unbounded(),
+            best_effort: channel::unbounded(),
+            critical_burst: AtomicUsize::new(0),
+        }
+    }
+
+    /// Queue the given message on the given lane.
+    pub fn send(&self, lane: Lane, message: Message) -> Result<(), channel::SendError<Message>> {
+        match lane {
+            Lane::Critical => self.critical.0.send(message),
+            Lane::BestEffort => self.best_effort.0.send(message),
+        }
+    }
+
+    /// Number of messages waiting to be sent on the given lane.
+    pub fn len(&self, lane: Lane) -> usize {
+        match lane {
+            Lane::Critical => self.critical.1.len(),
+            Lane::BestEffort => self.best_effort.1.len(),
+        }
+    }
+
+    /// Take the next message to send, waiting until one is available.
+    ///
+    /// Messages on the critical lane are taken first, unless [`MAX_CRITICAL_BURST`] critical
+    /// messages have been taken in a row while best-effort messages were waiting. Returns `None`
+    /// once both lanes have been closed.
+    pub fn recv(&self) -> Option<Message> {
+        loop {
+            if self.critical_burst.load(Ordering::Relaxed) >= MAX_CRITICAL_BURST {
+                if let Ok(message) = self.best_effort.1.try_recv() {
+                    self.critical_burst.store(0, Ordering::Relaxed);
+                    return Some(message);
+                }
+            }
+            let critical = match self.critical.1.try_recv() {
+                Ok(message) => {
+                    if self.best_effort.1.is_empty() {
+                        self.critical_burst.store(0, Ordering::Relaxed);
+                    } else {
+                        self.critical_burst.fetch_add(1, Ordering::Relaxed);
+                    }
+                    return Some(message);
+                }
+                Err(err) => err,
+            };
+            let best_effort = match self.best_effort.1.try_recv() {
+                Ok(message) => {
+                    self.critical_burst.store(0, Ordering::Relaxed);
+                    return Some(message);
+                }
+                Err(err) => err,
+            };
+            if critical == TryRecvError::Disconnected && best_effort == TryRecvError::Disconnected {
+                return None;
+            }
+
+            // Wait until either lane has a message, then prioritize again.
+            let mut select = Select::new();
+            select.recv(&self.critical.1);
+            select.recv(&self.best_effort.1);
+            select.ready();
+        }
+    }
+}
+
+impl Default for Lanes {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::types::MessageType;
+
+    fn message(id: u64) -> Message {
+        Message {
+            id,
+            message_type: MessageType::Response,
+            body: Body::Empty {},
+        }
+    }
+
+    #[test]
+    fn test_lanes() {
+        let lanes = Lanes::new();
+        for id in 0..3 {
+            lanes.send(Lane::BestEffort, message(id)).unwrap();
+        }
+        lanes.send(Lane::Critical, message(10)).unwrap();
+        lanes.send(Lane::Critical, message(11)).unwrap();
+        assert_eq!(lanes.len(Lane::BestEffort), 3);
+
+        // Critical messages overtake best-effort ones, the order within lanes is kept.
+        let ids: Vec<u64> = (0..5).map(|_| lanes.recv().unwrap().id).collect();
+        assert_eq!(ids, vec![10, 11, 0, 1, 2]);
+
+        // Waiting receivers are woken up by either lane.
+        std::thread::scope(|s| {
+            s.spawn(|| lanes.send(Lane::BestEffort, message(20)).unwrap());
+            assert_eq!(lanes.recv().unwrap().id, 20);
+        });
+
+        // Best-effort messages are not starved by critical ones.
+        lanes.send(Lane::BestEffort, message(30)).unwrap();
+        for id in 100..100 + MAX_CRITICAL_BURST as u64 + 1 {
+            lanes.send(Lane::Critical, message(id)).unwrap();
+        }
+        let ids: Vec<u64> = (0..MAX_CRITICAL_BURST + 2)
+            .map(|_| lanes.recv().unwrap().id)
+            .collect();
+        assert_eq!(ids[MAX_CRITICAL_BURST], 30);
+        assert_eq!(ids[MAX_CRITICAL_BURST + 1], 100 + MAX_CRITICAL_BURST as u64);
+    }
+
+    #[test]
+    fn test_lane_classification() {
+        assert_eq!(Lane::of_class(MessageClass::Execution), Lane::Critical);
+        assert_eq!(Lane::of_class(MessageClass::Query), Lane::BestEffort);
+        assert_eq!(
+            Lane::of_request(&Body::HostIdentityRequest {}, None),
+            Lane::Critical
+        );
+        assert_eq!(
+            Lane::of_request(
+                &Body::HostRPCCallRequest {
+                    endpoint: "bundle-manager".to_string(),
+                    request_id: 0,
+                    request: vec![],
+                    kind: enclave_rpc::types::Kind::LocalQuery,
+                    nodes: vec![],
+                },
+                Some(MessageClass::Execution)
+            ),
+            Lane::BestEffort
+        );
+
+        // Requests are classified by the host request they are made on behalf of.
+        let body = Body::HostIdentityRequest {};
+        assert_eq!(
+            Lane::of_request(&body, Some(MessageClass::Query)),
+            Lane::BestEffort
+        );
+        assert_eq!(
+            Lane::of_request(&body, Some(MessageClass::Execution)),
+            Lane::Critical
+        );
+    }
+
+    #[test]
+    fn test_origin() {
+        let rt = tokio::runtime::Builder::new_current_thread()
+            .build()
+            .unwrap();
+        assert_eq!(current_origin(), None);
+        let origin = rt.block_on(with_origin(MessageClass::Query, async {
+            let propagated = propagate_origin(current_origin);
+            (
+                current_origin(),
+                std::thread::spawn(propagated).join().unwrap(),
+            )
+        }));
+        assert_eq!(
+            origin,
+            (Some(MessageClass::Query), Some(MessageClass::Query))
+        );
+        assert_eq!(current_origin(), None);
+    }
+}
@@ -0,0 +1,558 @@
+//! Local storage on the host.
+//!
+//! The host provides small key/value records for non-consensus runtime data (e.g. caches or
+//! ephemeral session state) which should not live in the MKVS. As the host is not trusted,
+//! [`IntegrityProtectedStorage`] seals all records with Deoxys-II under a runtime-provided key,
+//! binding each record to its key, and stores a sealed manifest with the hashes of all records
+//! alongside them. The root hash of the manifest is bound to the value of a [`MonotonicCounter`],
+//! which is incremented on each update, so that the host can't roll back the storage to an
+//! earlier state without detection (as long as the counter itself can't be rolled back).
+use std::{
+    collections::BTreeMap,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+};
+
+use async_trait::async_trait;
+use hmac::{Hmac, Mac};
+use rand::Rng;
+use sha2::Sha512_256;
+use thiserror::Error;
+use tokio::sync::Mutex;
+use zeroize::Zeroize;
+
+use crate::{
+    common::crypto::{
+        hash::Hash,
+        mrae::deoxysii::{DeoxysII, KEY_SIZE, NONCE_SIZE},
+        rng::SecureRng,
+    },
+    protocol::Protocol,
+    shutdown::Flush,
+    types::Body,
+};
+
+use super::Error as HostError;
+
+/// Prefix of the manifest record, within the storage namespace.
+const MANIFEST_PREFIX: u8 = 0x00;
+/// Prefix of user records, within the storage namespace.
+const RECORD_PREFIX: u8 = 0x01;
+/// Context used for deriving record keys.
+const KEY_CONTEXT: &[u8] = b"oasis-core/runtime: local storage key";
+/// Context used as a prefix of the additional data of sealed records.
+const SEAL_CONTEXT: &[u8] = b"oasis-core/runtime: local storage";
+
+type Kdf = Hmac<Sha512_256>;
+
+/// Local storage errors.
+#[derive(Error, Debug)]
+pub enum LocalStorageError {
+    #[error("host error: {0}")]
+    Host(#[from] HostError),
+
+    #[error("monotonic counter: {0}")]
+    Counter(#[source] anyhow::Error),
+
+    #[error("integrity check failed")]
+    Integrity,
+
+    #[error("stale storage (counter: {counter} manifest: {manifest})")]
+    Stale { counter: u64, manifest: u64 },
+
+    #[error("local storage not supported by the host")]
+    Unsupported,
+}
+
+/// Local key/value storage.
+#[async_trait]
+pub trait LocalStorage: Send + Sync {
+    /// Fetch the value of the given key, returning `None` if it doesn't exist.
+    async fn get(&self, key: &[u8]) -> Result<Option<Vec<u8>>, LocalStorageError>;
+
+    /// Store the value of the given key, replacing any existing value.
+    async fn set(&self, key: &[u8], value: Vec<u8>) -> Result<(), LocalStorageError>;
+
+    /// Delete the value of the given key.
+    async fn delete(&self, key: &[u8]) -> Result<(), LocalStorageError>;
+}
+
+/// Host local storage.
+///
+/// Empty values and missing values are indistinguishable, so deleting a record stores an empty
+/// value.
+#[async_trait]
+impl LocalStorage for Protocol {
+    async fn get(&self, key: &[u8]) -> Result<Option<Vec<u8>>, LocalStorageError> {
+        match self
+            .call_host_async(Body::HostLocalStorageGetRequest { key: key.to_vec() })
+            .await
+            .map_err(HostError::from)?
+        {
+            Body::HostLocalStorageGetResponse { value } if value.is_empty() => Ok(None),
+            Body::HostLocalStorageGetResponse { value } => Ok(Some(value)),
+            _ => Err(HostError::BadResponse.into()),
+        }
+    }
+
+    async fn set(&self, key: &[u8], value: Vec<u8>) -> Result<(), LocalStorageError> {
+        match self
+            .call_host_async(Body::HostLocalStorageSetRequest {
+                key: key.to_vec(),
+                value,
+            })
+            .await
+            .map_err(HostError::from)?
+        {
+            Body::HostLocalStorageSetResponse {} => Ok(()),
+            _ => Err(HostError::BadResponse.into()),
+        }
+    }
+
+    async fn delete(&self, key: &[u8]) -> Result<(), LocalStorageError> {
+        self.set(key, vec![]).await
+    }
+}
+
+/// Local storage of hosts which don't support it.
+pub struct NoLocalStorage;
+
+#[async_trait]
+impl LocalStorage for NoLocalStorage {
+    async fn get(&self, _key: &[u8]) -> Result<Option<Vec<u8>>, LocalStorageError> {
+        Err(LocalStorageError::Unsupported)
+    }
+
+    async fn set(&self, _key: &[u8], _value: Vec<u8>) -> Result<(), LocalStorageError> {
+        Err(LocalStorageError::Unsupported)
+    }
+
+    async fn delete(&self, _key: &[u8]) -> Result<(), LocalStorageError> {
+        Err(LocalStorageError::Unsupported)
+    }
+}
+
+/// A monotonic counter, used to detect rollbacks of storage.
+#[async_trait]
+pub trait MonotonicCounter: Send + Sync {
+    /// Current value of the counter.
+    async fn read(&self) -> anyhow::Result<u64>;
+
+    /// Increment the counter, returning the new value.
+    async fn increment(&self) -> anyhow::Result<u64>;
+}
+
+/// Monotonic counter kept in enclave memory.
+///
+/// As the counter is lost on restart, it only detects rollbacks while the runtime is running.
+/// Storage bound to it should be opened via [`IntegrityProtectedStorage::new_with_memory_counter`],
+/// which doesn't trust records stored before the restart.
+#[derive(Default)]
+pub struct MemoryCounter {
+    value: AtomicU64,
+}
+
+impl MemoryCounter {
+    /// Create a new counter starting at zero.
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+#[async_trait]
+impl MonotonicCounter for MemoryCounter {
+    async fn read(&self) -> anyhow::Result<u64> {
+        Ok(self.value.load(Ordering::SeqCst))
+    }
+
+    async fn increment(&self) -> anyhow::Result<u64> {
+        Ok(self.value.fetch_add(1, Ordering::SeqCst) + 1)
+    }
+}
+
+/// A sealed record, as stored on the host.
+#[derive(Clone, Debug, Default, cbor::Encode, cbor::Decode)]
+struct SealedRecord {
+    nonce: Vec<u8>,
+    data: Vec<u8>,
+}
+
+/// Integrity manifest of the storage.
+#[derive(Clone, Debug, Default, cbor::Encode, cbor::Decode)]
+struct Manifest {
+    /// Counter value the manifest was written at.
+    counter: u64,
+    /// Hashes of the stored (sealed) records by their host key.
+    records: BTreeMap<Vec<u8>, Hash>,
+}
+
+impl Manifest {
+    /// Root hash over all records.
+    fn root(&self) -> Hash {
+        Hash::digest_bytes(&cbor::to_vec(self.records.clone()))
+    }
+}
+
+/// Local storage with encrypted and integrity protected records.
+pub struct IntegrityProtectedStorage {
+    backend: Arc<dyn LocalStorage>,
+    namespace: Vec<u8>,
+    key: [u8; KEY_SIZE],
+    cipher: DeoxysII,
+    counter: Arc<dyn MonotonicCounter>,
+    ephemeral: bool,
+    manifest: Mutex<Option<Manifest>>,
+}
+
+impl IntegrityProtectedStorage {
+    /// Create a new storage keeping its records in the given namespace of the backend, sealed
+    /// with the given key and bound to the given counter.
+    ///
+    /// The same key and counter must be used for the namespace across restarts.
+    pub fn new(
+        backend: Arc<dyn LocalStorage>,
+        namespace: &[u8],
+        key: &[u8; KEY_SIZE],
+        counter: Arc<dyn MonotonicCounter>,
+    ) -> Self {
+        Self {
+            backend,
+            namespace: namespace.to_vec(),
+            key: *key,
+            cipher: DeoxysII::new(key),
+            counter,
+            ephemeral: false,
+            manifest: Mutex::new(None),
+        }
+    }
+
+    /// Create a new storage bound to a counter kept in enclave memory.
+    ///
+    /// As the host could roll back records stored before a restart without detection, they are
+    /// ignored and the storage starts out empty on each restart. See [`MemoryCounter`] for the
+    /// limitations of in-memory counters.
+    pub fn new_with_memory_counter(
+        backend: Arc<dyn LocalStorage>,
+        namespace: &[u8],
+        key: &[u8; KEY_SIZE],
+    ) -> Self {
+        let mut storage = Self::new(backend, namespace, key, Arc::new(MemoryCounter::new()));
+        storage.ephemeral = true;
+        storage
+    }
+
+    /// Root hash over all records, as verified against the counter.
+    pub async fn root(&self) -> Result<Hash, LocalStorageError> {
+        let mut manifest = self.manifest.lock().await;
+        Ok(self.load_manifest(&mut manifest).await?.root())
+    }
+
+    /// Load and verify the manifest, unless it has already been loaded.
+    async fn load_manifest<'a>(
+        &self,
+        manifest: &'a mut Option<Manifest>,
+    ) -> Result<&'a mut Manifest, LocalStorageError> {
+        if manifest.is_none() {
+            let stored = match self.ephemeral {
+                true => None,
+                false => self.backend.get(&self.manifest_key()).await?,
+            };
+            let loaded = match stored {
+                Some(raw) => cbor::from_slice(&self.open(MANIFEST_PREFIX, &[], &raw)?)
+                    .map_err(|_| LocalStorageError::Integrity)?,
+                None => Manifest::default(),
+            };
+
+            // The manifest is written before the counter is incremented, so in case of a crash
+            // in between the manifest is one ahead of the counter.
+            let counter = self
+                .counter
+                .read()
+                .await
+                .map_err(LocalStorageError::Counter)?;
+            if loaded.counter == counter + 1 {
+                self.counter
+                    .increment()
+                    .await
+                    .map_err(LocalStorageError::Counter)?;
+            } else if loaded.counter != counter {
+                return Err(LocalStorageError::Stale {
+                    counter,
+                    manifest: loaded.counter,
+                });
+            }
+            *manifest = Some(loaded);
+        }
+        Ok(manifest.as_mut().unwrap())
+    }
+
+    /// Update the record with the given key, deleting it in case no value is given.
+    async fn update(&self, key: &[u8], value: Option<Vec<u8>>) -> Result<(), LocalStorageError> {
+        // Hold the manifest lock during the whole update so that concurrent updates don't lose
+        // manifest updates.
+        let mut guard = self.manifest.lock().await;
+        let manifest = self.load_manifest(&mut guard).await?;
+        let host_key = self.host_key(key);
+
+        let mut updated = manifest.clone();
+        match value {
+            Some(value) => {
+                let raw = self.seal(RECORD_PREFIX, key, &value);
+                updated
+                    .records
+                    .insert(host_key.clone(), Hash::digest_bytes(&raw));
+                self.backend.set(&host_key, raw).await?;
+            }
+            None => {
+                if updated.records.remove(&host_key).is_none() {
+                    return Ok(());
+                }
+                self.backend.delete(&host_key).await?;
+            }
+        }
+
+        updated.counter += 1;
+        let raw = self.seal(MANIFEST_PREFIX, &[], &cbor::to_vec(updated.clone()));
+        self.backend.set(&self.manifest_key(), raw).await?;
+        self.counter
+            .increment()
+            .await
+            .map_err(LocalStorageError::Counter)?;
+        *manifest = updated;
+
+        Ok(())
+    }
+
+    /// Key of the manifest record on the host.
+    fn manifest_key(&self) -> Vec<u8> {
+        [&self.namespace[..], &[MANIFEST_PREFIX]].concat()
+    }
+
+    /// Key of the record with the given key on the host, which doesn't reveal the key.
+    fn host_key(&self, key: &[u8]) -> Vec<u8> {
+        let mut kdf = Kdf::new_from_slice(&self.key).expect("Hmac::new_from_slice");
+        kdf.update(KEY_CONTEXT);
+        kdf.update(key);
+        [
+            &self.namespace[..],
+            &[RECORD_PREFIX],
+            &kdf.finalize().into_bytes()[..],
+        ]
+        .concat()
+    }
+
+    fn additional_data(&self, prefix: u8, key: &[u8]) -> Vec<u8> {
+        let ns_len = (self.namespace.len() as u32).to_be_bytes();
+        [SEAL_CONTEXT, &ns_len, &self.namespace, &[prefix], key].concat()
+    }
+
+    fn seal(&self, prefix: u8, key: &[u8], data: &[u8]) -> Vec<u8> {
+        let mut nonce = [0u8; NONCE_SIZE];
+        SecureRng.fill(&mut nonce);
+        let data = self
+            .cipher
+            .seal(&nonce, data.to_vec(), self.additional_data(prefix, key));
+
+        cbor::to_vec(SealedRecord {
+            nonce: nonce.to_vec(),
+            data,
+        })
+    }
+
+    fn open(&self, prefix: u8, key: &[u8], raw: &[u8]) -> Result<Vec<u8>, LocalStorageError> {
+        let record: SealedRecord =
+            cbor::from_slice(raw).map_err(|_| LocalStorageError::Integrity)?;
+        let nonce: [u8; NONCE_SIZE] = record
+            .nonce
+            .try_into()
+            .map_err(|_| LocalStorageError::Integrity)?;
+
+        self.cipher
+            .open(&nonce, record.data, self.additional_data(prefix, key))
+            .map_err(|_| LocalStorageError::Integrity)
+    }
+}
+
+/// Records are persisted before updates return, so flushing waits for in-flight updates.
+#[async_trait]
+impl Flush for IntegrityProtectedStorage {
+    async fn flush(&self) -> anyhow::Result<()> {
+        drop(self.manifest.lock().await);
+        Ok(())
+    }
+}
+
+impl Drop for IntegrityProtectedStorage {
+    fn drop(&mut self) {
+        self.key.zeroize();
+    }
+}
+
+#[async_trait]
+impl LocalStorage for IntegrityProtectedStorage {
+    async fn get(&self, key: &[u8]) -> Result<Option<Vec<u8>>, LocalStorageError> {
+        let host_key = self.host_key(key);
+        let expected = {
+            let mut manifest = self.manifest.lock().await;
+            match self
+                .load_manifest(&mut manifest)
+                .await?
+                .records
+                .get(&host_key)
+            {
+                Some(hash) => *hash,
+                None => return Ok(None),
+            }
+        };
+
+        let raw = self
+            .backend
+            .get(&host_key)
+            .await?
+            .ok_or(LocalStorageError::Integrity)?;
+        if Hash::digest_bytes(&raw) != expected {
+            return Err(LocalStorageError::Integrity);
+        }
+
+        self.open(RECORD_PREFIX, key, &raw).map(Some)
+    }
+
+    async fn set(&self, key: &[u8], value: Vec<u8>) -> Result<(), LocalStorageError> {
+        self.update(key, Some(value)).await
+    }
+
+    async fn delete(&self, key: &[u8]) -> Result<(), LocalStorageError> {
+        self.update(key, None).await
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::Mutex as StdMutex;
+
+    use super::*;
+
+    #[derive(Default)]
+    struct MockStorage {
+        records: StdMutex<BTreeMap<Vec<u8>, Vec<u8>>>,
+    }
+
+    #[async_trait]
+    impl LocalStorage for MockStorage {
+        async fn get(&self, key: &[u8]) -> Result<Option<Vec<u8>>, LocalStorageError> {
+            Ok(self.records.lock().unwrap().get(key).cloned())
+        }
+
+        async fn set(&self, key: &[u8], value: Vec<u8>) -> Result<(), LocalStorageError> {
+            self.records.lock().unwrap().insert(key.to_vec(), value);
+            Ok(())
+        }
+
+        async fn delete(&self, key: &[u8]) -> Result<(), LocalStorageError> {
+            self.records.lock().unwrap().remove(key);
+            Ok(())
+        }
+    }
+
+    #[tokio::test]
+    async fn test_integrity_protected_storage() {
+        let backend = Arc::new(MockStorage::default());
+        let counter = Arc::new(MemoryCounter::new());
+        let storage = IntegrityProtectedStorage::new(
+            backend.clone(),
+            b"test",
+            &[1; KEY_SIZE],
+            counter.clone(),
+        );
+
+        assert_eq!(storage.get(b"a").await.unwrap(), None);
+        storage.set(b"a", b"one".to_vec()).await.unwrap();
+        storage.set(b"b", b"two".to_vec()).await.unwrap();
+        let snapshot = backend.records.lock().unwrap().clone();
+        storage.set(b"a", b"three".to_vec()).await.unwrap();
+        storage.delete(b"b").await.unwrap();
+        assert_eq!(storage.get(b"a").await.unwrap(), Some(b"three".to_vec()));
+        assert_eq!(storage.get(b"b").await.unwrap(), None);
+        assert_eq!(counter.read().await.unwrap(), 4);
+
+        // Records are encrypted and keys are not revealed.
+        for (key, value) in backend.records.lock().unwrap().iter() {
+            assert!(key.starts_with(b"test"));
+            assert_ne!(&key[..], b"testa");
+            assert!(!value.windows(5).any(|w| w == b"three"));
+        }
+
+        // Records are verified against the manifest.
+        let reopened = || {
+            IntegrityProtectedStorage::new(
+                backend.clone(),
+                b"test",
+                &[1; KEY_SIZE],
+                counter.clone(),
+            )
+        };
+        let host_key = storage.host_key(b"a");
+        let current = backend.records.lock().unwrap()[&host_key].clone();
+        backend
+            .records
+            .lock()
+            .unwrap()
+            .insert(host_key.clone(), snapshot[&host_key].clone());
+        assert!(matches!(
+            reopened().get(b"a").await,
+            Err(LocalStorageError::Integrity)
+        ));
+        backend.records.lock().unwrap().insert(host_key, current);
+        assert_eq!(reopened().get(b"a").await.unwrap(), Some(b"three".to_vec()));
+
+        // Rolling back the whole storage is detected via the counter.
+        *backend.records.lock().unwrap() = snapshot;
+        assert!(matches!(
+            reopened().get(b"a").await,
+            Err(LocalStorageError::Stale {
+                counter: 4,
+                manifest: 2
+            })
+        ));
+
+        // Records can't be opened with another key.
+        let other = IntegrityProtectedStorage::new(
+            backend.clone(),
+            b"test",
+            &[2; KEY_SIZE],
+            counter.clone(),
+        );
+        assert!(matches!(
+            other.get(b"a").await,
+            Err(LocalStorageError::Integrity)
+        ));
+
+        // User keys can't alias the manifest.
+        assert_ne!(storage.host_key(b"\x00manifest"), storage.manifest_key());
+        assert_ne!(storage.host_key(b""), storage.manifest_key());
+    }
+
+    #[tokio::test]
+    async fn test_memory_counter_storage() {
+        let backend = Arc::new(MockStorage::default());
+        let storage = IntegrityProtectedStorage::new_with_memory_counter(
+            backend.clone(),
+            b"test",
+            &[1; KEY_SIZE],
+        );
+        storage.set(b"a", b"one".to_vec()).await.unwrap();
+        assert_eq!(storage.get(b"a").await.unwrap(), Some(b"one".to_vec()));
+
+        // Records stored before a restart are not trusted, as the counter has been lost.
+        let restarted = IntegrityProtectedStorage::new_with_memory_counter(
+            backend.clone(),
+            b"test",
+            &[1; KEY_SIZE],
+        );
+        assert_eq!(restarted.get(b"a").await.unwrap(), None);
+        restarted.set(b"b", b"two".to_vec()).await.unwrap();
+        assert_eq!(restarted.get(b"b").await.unwrap(), Some(b"two".to_vec()));
+    }
+}
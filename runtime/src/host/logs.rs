@@ -0,0 +1,269 @@
+//! Forwarding of log records to the host.
+//!
+//! Log records emitted by a confidential runtime are only written to its standard error output,
+//! which is usually not observable outside the TEE. Once enabled, the log forwarder captures all
+//! records passing the log filter as structured records, redacts all messages and fields that
+//! are not explicitly allowed and forwards the records to the host in periodic batches. Capturing is rate limited and the number
+//! of records buffered between batches is bounded, so that verbose logging can neither exhaust
+//! enclave memory nor flood the host. Dropped records are counted, with the count reported in the
+//! next batch.
+use std::{
+    collections::VecDeque,
+    sync::{Mutex, RwLock},
+    time::Instant,
+};
+
+use lazy_static::lazy_static;
+
+use crate::{
+    common::logger::{LogRecord, LogSink},
+    config::LogForwarding,
+    protocol::Protocol,
+    types::Body,
+};
+
+use super::Error;
+
+/// Value replacing the values of redacted fields.
+pub const REDACTED: &str = "<redacted>";
+
+lazy_static! {
+    static ref LOG_FORWARDER: LogForwarder = LogForwarder::new(LogForwarding::default());
+}
+
+/// Hook redacting confidential data from a record before it is forwarded.
+///
+/// Hooks must not emit log records.
+pub type RedactionHook = Box<dyn Fn(&mut LogRecord) + Send + Sync>;
+
+struct Inner {
+    config: LogForwarding,
+    buffer: VecDeque<LogRecord>,
+    dropped: u64,
+    tokens: f64,
+    refilled_at: Instant,
+    round: Option<u64>,
+}
+
+impl Inner {
+    /// Take a rate limiting token, if available.
+    fn admit(&mut self) -> bool {
+        let rate = self.config.max_records_per_second as f64;
+        if rate == 0.0 {
+            return true;
+        }
+
+        let now = Instant::now();
+        let elapsed = now
+            .saturating_duration_since(self.refilled_at)
+            .as_secs_f64();
+        self.refilled_at = now;
+        self.tokens = (self.tokens + elapsed * rate).min(rate);
+        if self.tokens < 1.0 {
+            return false;
+        }
+        self.tokens -= 1.0;
+        true
+    }
+}
+
+/// Forwarder of log records to the host.
+pub struct LogForwarder {
+    inner: Mutex<Inner>,
+    hooks: RwLock<Vec<RedactionHook>>,
+}
+
+impl LogForwarder {
+    /// Create a new forwarder with the given configuration.
+    pub fn new(config: LogForwarding) -> Self {
+        Self {
+            inner: Mutex::new(Inner {
+                tokens: config.max_records_per_second as f64,
+                config,
+                buffer: VecDeque::new(),
+                dropped: 0,
+                refilled_at: Instant::now(),
+                round: None,
+            }),
+            hooks: RwLock::new(Vec::new()),
+        }
+    }
+
+    /// Global log forwarder instance.
+    pub fn global() -> &'static LogForwarder {
+        &LOG_FORWARDER
+    }
+
+    /// Replace the forwarder configuration.
+    pub fn configure(&self, config: LogForwarding) {
+        let mut inner = self.inner.lock().unwrap();
+        inner.tokens = config.max_records_per_second as f64;
+        inner.config = config;
+    }
+
+    /// Add a hook redacting confidential data from records, applied before all messages and fields
+    /// that are not allowed are redacted.
+    pub fn add_redaction_hook(&self, hook: RedactionHook) {
+        self.hooks.write().unwrap().push(hook);
+    }
+
+    /// Set the round of the last executed batch, attached to subsequent records.
+    pub fn set_round(&self, round: Option<u64>) {
+        self.inner.lock().unwrap().round = round;
+    }
+
+    /// Take the next batch of buffered records, together with the number of records dropped since
+    /// the previous batch.
+    pub fn take_batch(&self) -> Option<(Vec<LogRecord>, u64)> {
+        let mut inner = self.inner.lock().unwrap();
+        if inner.buffer.is_empty() && inner.dropped == 0 {
+            return None;
+        }
+
+        let count = inner.buffer.len().min(inner.config.max_batch_size);
+        let records = inner.buffer.drain(..count).collect();
+        let dropped = std::mem::take(&mut inner.dropped);
+
+        Some((records, dropped))
+    }
+
+    /// Forward the next batch of buffered records to the host.
+    pub async fn flush(&self, protocol: &Protocol) -> Result<(), Error> {
+        match self.take_batch() {
+            Some((records, dropped)) => Self::forward(protocol, records, dropped).await,
+            None => Ok(()),
+        }
+    }
+
+    /// Forward all buffered records to the host, e.g. before the runtime is terminated.
+    pub async fn flush_all(&self, protocol: &Protocol) -> Result<(), Error> {
+        while let Some((records, dropped)) = self.take_batch() {
+            Self::forward(protocol, records, dropped).await?;
+        }
+        Ok(())
+    }
+
+    async fn forward(
+        protocol: &Protocol,
+        records: Vec<LogRecord>,
+        dropped: u64,
+    ) -> Result<(), Error> {
+        match protocol
+            .call_host_async(Body::HostLogRecordsRequest { records, dropped })
+            .await?
+        {
+            Body::HostLogRecordsResponse {} => Ok(()),
+            _ => Err(Error::BadResponse),
+        }
+    }
+}
+
+impl LogSink for LogForwarder {
+    fn log(&self, mut record: LogRecord) {
+        for hook in self.hooks.read().unwrap().iter() {
+            hook(&mut record);
+        }
+
+        let mut inner = self.inner.lock().unwrap();
+        if !inner.admit() {
+            inner.dropped += 1;
+            return;
+        }
+
+        if !inner.config.allow_messages {
+            record.msg = REDACTED.to_string();
+        }
+        for (key, value) in record.fields.iter_mut() {
+            if !inner.config.allowed_fields.contains(key) {
+                *value = REDACTED.to_string();
+            }
+        }
+        record.round = inner.round;
+
+        if inner.buffer.len() >= inner.config.max_buffered {
+            inner.buffer.pop_front();
+            inner.dropped += 1;
+        }
+        inner.buffer.push_back(record);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::BTreeMap;
+
+    use super::*;
+
+    fn record(msg: &str) -> LogRecord {
+        LogRecord {
+            level: "INFO".to_string(),
+            msg: msg.to_string(),
+            fields: BTreeMap::from([
+                ("key".to_string(), "secret".to_string()),
+                ("peer".to_string(), "alice".to_string()),
+                ("round".to_string(), "7".to_string()),
+            ]),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_log_forwarder() {
+        let forwarder = LogForwarder::new(LogForwarding {
+            max_batch_size: 2,
+            max_buffered: 3,
+            max_records_per_second: 0,
+            allowed_fields: vec!["round".to_string()],
+            allow_messages: true,
+            ..Default::default()
+        });
+        forwarder.add_redaction_hook(Box::new(|record| {
+            record.fields.remove("peer");
+        }));
+        assert!(forwarder.take_batch().is_none());
+
+        // Records are redacted and tagged with the current round.
+        forwarder.set_round(Some(7));
+        forwarder.log(record("first"));
+        let (records, dropped) = forwarder.take_batch().unwrap();
+        assert_eq!(dropped, 0);
+        assert_eq!(records.len(), 1);
+        assert_eq!(records[0].round, Some(7));
+        assert_eq!(
+            records[0].fields,
+            BTreeMap::from([
+                ("key".to_string(), REDACTED.to_string()),
+                ("round".to_string(), "7".to_string()),
+            ])
+        );
+
+        // The oldest records are dropped once the buffer is full and batches are bounded.
+        for i in 0..5 {
+            forwarder.log(record(&i.to_string()));
+        }
+        let (records, dropped) = forwarder.take_batch().unwrap();
+        let msgs: Vec<_> = records.iter().map(|record| record.msg.as_str()).collect();
+        assert_eq!(msgs, vec!["2", "3"]);
+        assert_eq!(dropped, 2);
+        let (records, dropped) = forwarder.take_batch().unwrap();
+        assert_eq!(records.len(), 1);
+        assert_eq!(dropped, 0);
+        assert!(forwarder.take_batch().is_none());
+
+        // Records exceeding the rate limit are dropped.
+        forwarder.configure(LogForwarding {
+            max_records_per_second: 2,
+            ..Default::default()
+        });
+        for i in 0..5 {
+            forwarder.log(record(&i.to_string()));
+        }
+        let (records, dropped) = forwarder.take_batch().unwrap();
+        assert_eq!(records.len(), 2);
+        assert_eq!(dropped, 3);
+
+        // Messages and fields are redacted by default.
+        assert_eq!(records[0].msg, REDACTED);
+        assert!(records[0].fields.values().all(|value| value == REDACTED));
+    }
+}
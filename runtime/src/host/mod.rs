@@ -1,17 +1,53 @@
 //! Host interface.
+use std::time::Duration;
+
+use anyhow::anyhow;
 use async_trait::async_trait;
 use thiserror::Error;
 
 use crate::{
-    common::{crypto::signature::PublicKey, namespace::Namespace},
+    common::{
+        crypto::{hash::Hash, signature::PublicKey},
+        namespace::Namespace,
+        pagination::PaginationError,
+        quantity::Quantity,
+    },
+    consensus::{
+        client::ConsensusClient,
+        roothash::{AnnotatedBlock, Evidence, Header},
+        transaction::{Fee, Gas},
+    },
     enclave_rpc,
-    protocol::Protocol,
+    protocol::{self, CallOpts, Protocol},
     storage::mkvs::sync,
+    transaction,
     types::{self, Body},
 };
 
+pub mod accounting;
+pub mod blob_store;
 pub mod bundle_manager;
+pub mod conformance;
+pub mod dedup;
+pub mod deprecation;
+pub mod encrypted_volume;
+pub mod feed;
+pub mod http;
+pub mod lanes;
+pub mod local_storage;
+pub mod logs;
+pub mod notify;
+pub mod oracle;
+pub mod queues;
+pub mod retry;
+pub mod secrets;
+pub mod signer;
+pub mod throttle;
 pub mod volume_manager;
+pub mod wal;
+
+pub use notify::{Notification, NotificationStream, NotifyHandle};
+pub use retry::RetryPolicy;
 
 /// Errors.
 #[derive(Error, Debug)]
@@ -24,6 +60,55 @@ pub enum Error {
 
     #[error("{0}")]
     Decode(#[from] cbor::DecodeError),
+
+    #[error("{0}")]
+    Pagination(#[from] PaginationError),
+
+    #[error("attestation not available")]
+    AttestationUnavailable,
+
+    #[error("bundle manifest not signed by the trust root")]
+    UntrustedBundle,
+
+    #[error("untrusted signing key")]
+    UntrustedSigner,
+
+    #[error("consensus verifier not available")]
+    ConsensusUnavailable,
+
+    #[error("blob does not match its hash")]
+    BlobMismatch,
+
+    #[error("invalid evidence: {0}")]
+    InvalidEvidence(#[source] anyhow::Error),
+
+    #[error("protocol feature '{0}' not negotiated with the host")]
+    FeatureNotNegotiated(&'static str),
+
+    #[error("not supported by the host")]
+    Unsupported,
+}
+
+impl Error {
+    /// Whether the error is likely transient, e.g. because the host is temporarily overloaded, so
+    /// that the call may succeed when retried.
+    pub fn is_transient(&self) -> bool {
+        matches!(
+            self,
+            Error::Host(err)
+                if err.module == protocol::MODULE_NAME && err.code == protocol::CODE_TRANSIENT
+        )
+    }
+
+    /// Whether the error indicates that a write exceeded the quota of a volume.
+    pub fn is_quota_exceeded(&self) -> bool {
+        matches!(
+            self,
+            Error::Host(err)
+                if err.module == volume_manager::MODULE_NAME
+                    && err.code == volume_manager::CODE_QUOTA_EXCEEDED
+        )
+    }
 }
 
 /// Transaction submission options.
@@ -35,11 +120,22 @@ pub struct SubmitTxOpts {
     pub wait: bool,
     /// Whether the response should include a proof of transaction being included in a block.
     pub prove: bool,
+    /// Maximum time to wait for the host to respond. If not specified, the call waits
+    /// indefinitely.
+    pub timeout: Option<Duration>,
+    /// Policy for retrying the submission in case it fails. If not specified, the submission is
+    /// not retried.
+    ///
+    /// Submissions are not idempotent, so a retried transaction may end up being executed more
+    /// than once unless the transaction itself prevents replays (e.g. via a nonce).
+    pub retry: Option<RetryPolicy>,
 }
 
 /// Transaction submission result.
 #[derive(Clone, Default, Debug)]
 pub struct TxResult {
+    /// Hash of the submitted transaction.
+    pub tx_hash: Hash,
     /// Transaction output.
     pub output: Vec<u8>,
     /// Round in which the transaction was executed.
@@ -50,6 +146,75 @@ pub struct TxResult {
     pub proof: Option<sync::Proof>,
 }
 
+impl TxResult {
+    /// Verify the inclusion proof against the I/O root of the given header of the round the
+    /// transaction was executed in, which must be obtained from a verified block.
+    ///
+    /// The proof must cover both the input and the output artifacts of the transaction, and the
+    /// round, output and batch order reported by the host must match the proven ones.
+    pub fn verify(&self, header: &Header) -> Result<VerifiedInclusion, sync::ProofError> {
+        if header.round != self.round {
+            return Err(sync::ProofError::Mismatch("round"));
+        }
+        let proof = self.proof.as_ref().ok_or(sync::ProofError::Missing)?;
+        let (batch_order, output) =
+            transaction::tree::verify_artifacts_proof(header.io_root, self.tx_hash, proof)?;
+        if output != self.output {
+            return Err(sync::ProofError::Mismatch("output"));
+        }
+        if batch_order != self.batch_order {
+            return Err(sync::ProofError::Mismatch("batch order"));
+        }
+
+        Ok(VerifiedInclusion {
+            round: header.round,
+            tx_hash: self.tx_hash,
+            batch_order,
+            output,
+        })
+    }
+}
+
+/// Transaction inclusion verified against the header of a round.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct VerifiedInclusion {
+    /// Round in which the transaction was executed.
+    pub round: u64,
+    /// Hash of the transaction.
+    pub tx_hash: Hash,
+    /// Order of the transaction in the execution batch.
+    pub batch_order: u32,
+    /// Transaction output.
+    pub output: Vec<u8>,
+}
+
+/// Consensus layer gas prices, as reported by the host.
+///
+/// Gas prices are not part of the consensus layer state, so they can't be verified. The gas
+/// costs of consensus operations are available via the verified [`Host::consensus`] client.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct ConsensusGasPrice {
+    /// Consensus layer height at which the prices were observed.
+    pub height: u64,
+    /// Gas price paid by transactions in recent blocks, in base units per gas.
+    pub gas_price: Quantity,
+    /// Minimum gas price the host accepts for transactions it submits on behalf of the runtime,
+    /// in base units per gas.
+    pub min_gas_price: Quantity,
+}
+
+impl ConsensusGasPrice {
+    /// Fee for a transaction using the given amount of gas, priced at the current gas price but
+    /// never below the minimum gas price.
+    pub fn fee(&self, gas: Gas) -> Fee {
+        let price = self.gas_price.clone().max(self.min_gas_price.clone());
+        Fee {
+            amount: price * gas,
+            gas,
+        }
+    }
+}
+
 /// Notification registration options.
 #[derive(Clone, Default, Debug)]
 pub struct RegisterNotifyOpts {
@@ -57,6 +222,15 @@ pub struct RegisterNotifyOpts {
     pub runtime_block: bool,
     /// Subscribe to runtime event notifications.
     pub runtime_event: Vec<Vec<u8>>,
+    /// Subscribe to notifications of runtime events with tags starting with the given prefixes.
+    /// An empty prefix matches all tags.
+    pub runtime_event_prefix: Vec<Vec<u8>>,
+    /// Subscribe to consensus block notifications.
+    pub consensus_block: bool,
+    /// Subscribe to notifications of the given kinds of consensus events.
+    pub consensus_event: Vec<types::ConsensusEventKind>,
+    /// Subscribe to notifications of data published on the given external data feeds.
+    pub external_data: Vec<String>,
 }
 
 /// Interface to the (untrusted) host node.
@@ -65,18 +239,98 @@ pub trait Host: Send + Sync {
     /// Returns the identity of the host node.
     async fn identity(&self) -> Result<PublicKey, Error>;
 
+    /// Fetch the runtime block committed in the given round, or the latest committed runtime
+    /// block in case no round is specified.
+    ///
+    /// The returned block is provided by the host and is not verified.
+    async fn runtime_block(&self, round: Option<u64>) -> Result<AnnotatedBlock, Error>;
+
     /// Submit a transaction.
     async fn submit_tx(&self, data: Vec<u8>, opts: SubmitTxOpts)
         -> Result<Option<TxResult>, Error>;
 
+    /// Submit a batch of transactions in a single request, returning a result for each of them.
+    ///
+    /// Transactions are submitted in the given order. The default implementation submits them
+    /// one by one, stopping at the first error.
+    async fn submit_tx_batch(
+        &self,
+        txs: Vec<Vec<u8>>,
+        opts: SubmitTxOpts,
+    ) -> Result<Vec<Option<TxResult>>, Error> {
+        let mut results = Vec::with_capacity(txs.len());
+        for data in txs {
+            results.push(self.submit_tx(data, opts.clone()).await?);
+        }
+        Ok(results)
+    }
+
     /// Register for receiving notifications.
-    async fn register_notify(&self, opts: RegisterNotifyOpts) -> Result<(), Error>;
+    ///
+    /// Registrations are additive, so independent subsystems can each hold their own. The
+    /// registration stays active until the returned handle is dropped.
+    async fn register_notify(&self, opts: RegisterNotifyOpts) -> Result<NotifyHandle, Error>;
+
+    /// Register for receiving notifications, returning a stream of the matching runtime block
+    /// and runtime event notifications.
+    ///
+    /// The registration stays active until the returned stream is dropped.
+    async fn notifications(&self, opts: RegisterNotifyOpts) -> Result<NotificationStream, Error>;
+
+    /// Push the current attestation evidence of the runtime to the given external verification
+    /// service configured on the host and return the token issued by the verifier.
+    ///
+    /// The host performs the request, so the runtime itself needs no network access. The token
+    /// is opaque to the runtime and is not verified.
+    async fn relay_attestation(&self, verifier: &str) -> Result<Vec<u8>, Error>;
+
+    /// Fetch the current consensus layer gas prices.
+    ///
+    /// The returned prices are provided by the host and are not verified.
+    async fn consensus_gas_price(&self) -> Result<ConsensusGasPrice, Error>;
+
+    /// Fetch the minimum gas price the host accepts for consensus transactions of the runtime.
+    ///
+    /// The returned price is provided by the host and is not verified.
+    async fn consensus_min_gas_price(&self) -> Result<Quantity, Error> {
+        Ok(self.consensus_gas_price().await?.min_gas_price)
+    }
+
+    /// Submit evidence of runtime node misbehavior detected by the runtime (e.g. equivocation
+    /// in committee messages) to the consensus layer, so the node can be held accountable.
+    ///
+    /// The evidence is verified before it is submitted, as the consensus layer would reject it
+    /// otherwise. Hosts which cannot submit evidence return `Error::Unsupported`.
+    async fn submit_evidence(&self, _evidence: Evidence) -> Result<(), Error> {
+        Err(Error::Unsupported)
+    }
 
     /// Bundle manager interface.
     fn bundle_manager(&self) -> &dyn bundle_manager::BundleManager;
 
     /// Volume manager interface.
     fn volume_manager(&self) -> &dyn volume_manager::VolumeManager;
+
+    /// Local storage interface.
+    ///
+    /// Records are stored by the host as given, so they should be protected via an
+    /// [`IntegrityProtectedStorage`](local_storage::IntegrityProtectedStorage). Hosts without
+    /// support for local storage don't provide any.
+    fn local_storage(&self) -> &dyn local_storage::LocalStorage {
+        &local_storage::NoLocalStorage
+    }
+
+    /// Secrets provisioned by the operator, decrypted inside the runtime.
+    ///
+    /// Hosts without support for secrets don't provide any.
+    fn secrets(&self) -> &dyn secrets::Secrets {
+        &secrets::NoSecrets
+    }
+
+    /// Client for verified queries of the consensus layer state.
+    ///
+    /// Fails in case the consensus verifier is not yet available.
+    fn consensus(&self) -> Result<ConsensusClient, Error>;
 }
 
 #[async_trait]
@@ -88,19 +342,41 @@ impl Host for Protocol {
         }
     }
 
+    async fn runtime_block(&self, round: Option<u64>) -> Result<AnnotatedBlock, Error> {
+        match self
+            .call_host_async(Body::HostRuntimeBlockRequest { round })
+            .await?
+        {
+            Body::HostRuntimeBlockResponse { block }
+                if round.is_none() || round == Some(block.block.header.round) =>
+            {
+                Ok(block)
+            }
+            _ => Err(Error::BadResponse),
+        }
+    }
+
     async fn submit_tx(
         &self,
         data: Vec<u8>,
         opts: SubmitTxOpts,
     ) -> Result<Option<TxResult>, Error> {
-        match self
-            .call_host_async(Body::HostSubmitTxRequest {
-                runtime_id: opts.runtime_id.unwrap_or_else(|| self.get_runtime_id()),
-                data,
+        let runtime_id = opts.runtime_id.unwrap_or_else(|| self.get_runtime_id());
+        match call_host_with_retry(
+            self,
+            || Body::HostSubmitTxRequest {
+                runtime_id,
+                data: data.clone(),
                 wait: opts.wait,
                 prove: opts.prove,
-            })
-            .await?
+            },
+            CallOpts {
+                timeout: opts.timeout,
+                ..Default::default()
+            },
+            &opts.retry.clone().unwrap_or_else(RetryPolicy::none),
+        )
+        .await?
         {
             Body::HostSubmitTxResponse {
                 output,
@@ -110,6 +386,7 @@ impl Host for Protocol {
             } => {
                 if opts.wait {
                     Ok(Some(TxResult {
+                        tx_hash: Hash::digest_bytes(&data),
                         output,
                         round,
                         batch_order,
@@ -124,18 +401,119 @@ impl Host for Protocol {
         }
     }
 
-    async fn register_notify(&self, opts: RegisterNotifyOpts) -> Result<(), Error> {
+    async fn submit_tx_batch(
+        &self,
+        txs: Vec<Vec<u8>>,
+        opts: SubmitTxOpts,
+    ) -> Result<Vec<Option<TxResult>>, Error> {
+        let count = txs.len();
+        let runtime_id = opts.runtime_id.unwrap_or_else(|| self.get_runtime_id());
+        match call_host_with_retry(
+            self,
+            || Body::HostSubmitTxBatchRequest {
+                runtime_id,
+                txs: txs.clone(),
+                wait: opts.wait,
+                prove: opts.prove,
+            },
+            CallOpts {
+                timeout: opts.timeout,
+                ..Default::default()
+            },
+            &opts.retry.clone().unwrap_or_else(RetryPolicy::none),
+        )
+        .await?
+        {
+            Body::HostSubmitTxBatchResponse { results } => {
+                if !opts.wait {
+                    // If we didn't wait for inclusion then there are no results.
+                    return Ok(vec![None; count]);
+                }
+                if results.len() != count {
+                    return Err(Error::BadResponse);
+                }
+
+                Ok(results
+                    .into_iter()
+                    .zip(txs.iter())
+                    .map(|(result, data)| {
+                        Some(TxResult {
+                            tx_hash: Hash::digest_bytes(data),
+                            output: result.output,
+                            round: result.round,
+                            batch_order: result.batch_order,
+                            proof: result.proof,
+                        })
+                    })
+                    .collect())
+            }
+            _ => Err(Error::BadResponse),
+        }
+    }
+
+    async fn register_notify(&self, opts: RegisterNotifyOpts) -> Result<NotifyHandle, Error> {
+        self.notify_registry.register(self, opts).await
+    }
+
+    async fn notifications(&self, opts: RegisterNotifyOpts) -> Result<NotificationStream, Error> {
+        self.notify_registry.subscribe(self, opts).await
+    }
+
+    async fn relay_attestation(&self, verifier: &str) -> Result<Vec<u8>, Error> {
+        let identity = self.get_identity().ok_or(Error::AttestationUnavailable)?;
+        let quote = identity.quote().ok_or(Error::AttestationUnavailable)?;
+        let evidence = types::AttestationEvidence {
+            rak: identity.public_rak(),
+            quote: (*quote).clone(),
+            endorsement: identity.endorsed_capability_tee(),
+        };
+
         match self
-            .call_host_async(Body::HostRegisterNotifyRequest {
-                runtime_block: opts.runtime_block,
-                runtime_event: match opts.runtime_event {
-                    tags if tags.is_empty() => None,
-                    tags => Some(types::RegisterNotifyRuntimeEvent { tags }),
-                },
+            .call_host_async(Body::HostAttestationRelayRequest {
+                verifier: verifier.to_string(),
+                evidence,
             })
             .await?
         {
-            Body::Empty {} => Ok(()),
+            Body::HostAttestationRelayResponse { token } => Ok(token),
+            _ => Err(Error::BadResponse),
+        }
+    }
+
+    async fn consensus_gas_price(&self) -> Result<ConsensusGasPrice, Error> {
+        match self
+            .call_host_async(Body::HostConsensusGasPriceRequest {})
+            .await?
+        {
+            Body::HostConsensusGasPriceResponse {
+                height,
+                gas_price,
+                min_gas_price,
+            } => Ok(ConsensusGasPrice {
+                height,
+                gas_price,
+                min_gas_price,
+            }),
+            _ => Err(Error::BadResponse),
+        }
+    }
+
+    async fn submit_evidence(&self, evidence: Evidence) -> Result<(), Error> {
+        let chain_context = self.get_host_info().consensus_chain_context;
+        if evidence.id != self.get_runtime_id() {
+            return Err(Error::InvalidEvidence(anyhow!(
+                "evidence for a different runtime"
+            )));
+        }
+        evidence
+            .verify(&chain_context)
+            .map_err(Error::InvalidEvidence)?;
+
+        match self
+            .call_host_async(Body::HostSubmitEvidenceRequest { evidence })
+            .await?
+        {
+            Body::HostSubmitEvidenceResponse {} => Ok(()),
             _ => Err(Error::BadResponse),
         }
     }
@@ -147,27 +525,90 @@ impl Host for Protocol {
     fn volume_manager(&self) -> &dyn volume_manager::VolumeManager {
         self
     }
+
+    fn local_storage(&self) -> &dyn local_storage::LocalStorage {
+        self
+    }
+
+    fn secrets(&self) -> &dyn secrets::Secrets {
+        self
+    }
+
+    fn consensus(&self) -> Result<ConsensusClient, Error> {
+        let verifier = self
+            .get_consensus_verifier()
+            .ok_or(Error::ConsensusUnavailable)?;
+        Ok(ConsensusClient::new(verifier))
+    }
+}
+
+/// Make a request to the host, retrying it according to the given policy.
+async fn call_host_with_retry<F>(
+    protocol: &Protocol,
+    body: F,
+    opts: CallOpts,
+    retry: &RetryPolicy,
+) -> Result<Body, Error>
+where
+    F: Fn() -> Body,
+{
+    retry
+        .run(move || {
+            let body = body();
+            async move { Ok(protocol.call_host_async_with_opts(body, opts).await?) }
+        })
+        .await
 }
 
-/// Wrapper to call the host via local RPC.
+/// Wrapper to call the host via local RPC, retrying the call according to the given policy.
+///
+/// Only idempotent methods should be retried.
 pub(super) async fn host_rpc_call<Rq: cbor::Encode, Rs: cbor::Decode>(
     protocol: &Protocol,
     endpoint: &str,
     method: &str,
     args: Rq,
+    retry: &RetryPolicy,
+) -> Result<Rs, Error> {
+    let request_id = protocol.next_request_id();
+    host_rpc_call_with_id(protocol, request_id, endpoint, method, args, retry).await
+}
+
+/// Wrapper to call the host via local RPC using the given request identifier, retrying the call
+/// according to the given policy.
+///
+/// The identifier must be allocated via `Protocol::next_request_id`. While an attempt is
+/// outstanding, the call can be cancelled via `Protocol::cancel_request`, which also stops any
+/// further retries.
+pub async fn host_rpc_call_with_id<Rq: cbor::Encode, Rs: cbor::Decode>(
+    protocol: &Protocol,
+    request_id: u64,
+    endpoint: &str,
+    method: &str,
+    args: Rq,
+    retry: &RetryPolicy,
 ) -> Result<Rs, Error> {
-    match protocol
-        .call_host_async(Body::HostRPCCallRequest {
+    let request = cbor::to_vec(enclave_rpc::types::Request {
+        method: method.to_string(),
+        args: cbor::to_value(args),
+    });
+
+    match call_host_with_retry(
+        protocol,
+        || Body::HostRPCCallRequest {
             endpoint: endpoint.to_string(),
-            request_id: 0,
-            request: cbor::to_vec(enclave_rpc::types::Request {
-                method: method.to_string(),
-                args: cbor::to_value(args),
-            }),
+            request_id,
+            request: request.clone(),
             kind: enclave_rpc::types::Kind::LocalQuery,
             nodes: vec![],
-        })
-        .await?
+        },
+        CallOpts {
+            request_id: Some(request_id),
+            ..Default::default()
+        },
+        retry,
+    )
+    .await?
     {
         Body::HostRPCCallResponse { response, .. } => Ok(cbor::from_slice(&response)?),
         _ => Err(Error::BadResponse),
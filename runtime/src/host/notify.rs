@@ -0,0 +1,672 @@
+//! Independent notification registrations.
+//!
+//! The host only supports a single notification registration per runtime, with each new
+//! registration replacing the previous one. To allow independent subsystems to manage their own
+//! subscriptions, registrations are tracked locally and the host is always given the union of
+//! all active registrations. The registrations are sent to the host again whenever it
+//! (re)initializes the runtime.
+//!
+//! Registrations created via [`NotifyRegistry::subscribe`] additionally receive the matching
+//! runtime notifications as a [`NotificationStream`]. This includes data published on external
+//! data feeds, which is only delivered once verified against the configured feed signers.
+//!
+//! Runtime events matching a subscription are also delivered as untrusted [`DecodedEvent`]s, in
+//! case the host provided the emitted tags and a decoder for them is registered in the registry's
+//! [`EventRegistry`]. The tags are provided by the host and are not verified against the I/O root
+//! of the block, so they must not be relied upon without verifying them first.
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    pin::Pin,
+    sync::{Arc, Mutex, Weak},
+    task::{Context, Poll},
+};
+
+use slog::{error, Logger};
+use tokio::sync::mpsc;
+
+use crate::{
+    common::logger::get_logger,
+    consensus::roothash::AnnotatedBlock,
+    protocol::Protocol,
+    transaction::events::{DecodedEvent, EventRegistry},
+    types,
+};
+
+use super::feed::VerifiedFeedData;
+
+use super::{Error, RegisterNotifyOpts};
+
+impl RegisterNotifyOpts {
+    /// Merge another set of registration options into this one.
+    fn merge(&mut self, other: &RegisterNotifyOpts) {
+        self.runtime_block |= other.runtime_block;
+        self.consensus_block |= other.consensus_block;
+        let tags: BTreeSet<_> = self
+            .runtime_event
+            .drain(..)
+            .chain(other.runtime_event.iter().cloned())
+            .collect();
+        self.runtime_event = tags.into_iter().collect();
+        let prefixes: BTreeSet<_> = self
+            .runtime_event_prefix
+            .drain(..)
+            .chain(other.runtime_event_prefix.iter().cloned())
+            .collect();
+        self.runtime_event_prefix = prefixes.into_iter().collect();
+        let kinds: BTreeSet<_> = self
+            .consensus_event
+            .drain(..)
+            .chain(other.consensus_event.iter().copied())
+            .collect();
+        self.consensus_event = kinds.into_iter().collect();
+        let feeds: BTreeSet<_> = self
+            .external_data
+            .drain(..)
+            .chain(other.external_data.iter().cloned())
+            .collect();
+        self.external_data = feeds.into_iter().collect();
+    }
+
+    /// Whether runtime events with the given tag are subscribed to.
+    fn matches_event(&self, tag: &[u8]) -> bool {
+        self.runtime_event.iter().any(|t| t == tag)
+            || self
+                .runtime_event_prefix
+                .iter()
+                .any(|prefix| tag.starts_with(prefix))
+    }
+}
+
+/// A runtime notification.
+#[derive(Clone, Debug)]
+pub enum Notification {
+    /// A new runtime block.
+    Block(AnnotatedBlock),
+    /// Events with the given subscribed tags were emitted in the given round.
+    Event { tags: Vec<Vec<u8>>, round: u64 },
+    /// A subscribed event was emitted and decoded.
+    ///
+    /// The event was provided by the host and has not been verified against the I/O root of the
+    /// block it was emitted in.
+    UntrustedDecodedEvent(DecodedEvent),
+    /// Verified data was published on a subscribed external data feed.
+    ExternalData(VerifiedFeedData),
+}
+
+/// Stream of the runtime notifications matching a registration.
+///
+/// Dropping the stream removes the registration.
+pub struct NotificationStream {
+    rx: mpsc::UnboundedReceiver<Notification>,
+    handle: NotifyHandle,
+}
+
+impl NotificationStream {
+    /// Handle to the underlying registration.
+    pub fn handle(&self) -> &NotifyHandle {
+        &self.handle
+    }
+}
+
+impl futures::Stream for NotificationStream {
+    type Item = Notification;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        self.rx.poll_recv(cx)
+    }
+}
+
+#[derive(Default)]
+struct Registrations {
+    next_id: u64,
+    active: BTreeMap<u64, RegisterNotifyOpts>,
+    subscribers: BTreeMap<u64, mpsc::UnboundedSender<Notification>>,
+}
+
+/// Registry of active notification registrations.
+pub struct NotifyRegistry {
+    logger: Logger,
+    /// Registry of decoders for events delivered to subscribers.
+    event_registry: Arc<EventRegistry>,
+    registrations: Mutex<Registrations>,
+    /// Lock serializing updates sent to the host so that the last update always reflects the
+    /// latest set of registrations.
+    sync_lock: tokio::sync::Mutex<()>,
+    resync_tx: mpsc::UnboundedSender<()>,
+    resync_rx: Mutex<Option<mpsc::UnboundedReceiver<()>>>,
+}
+
+impl Default for NotifyRegistry {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl NotifyRegistry {
+    /// Create a new empty registry.
+    pub fn new() -> Self {
+        let (resync_tx, resync_rx) = mpsc::unbounded_channel();
+
+        Self {
+            logger: get_logger("runtime/host/notify"),
+            event_registry: Arc::new(EventRegistry::new()),
+            registrations: Mutex::new(Registrations::default()),
+            sync_lock: tokio::sync::Mutex::new(()),
+            resync_tx,
+            resync_rx: Mutex::new(Some(resync_rx)),
+        }
+    }
+
+    /// Registry of decoders for events delivered to subscribers.
+    pub fn event_registry(&self) -> &Arc<EventRegistry> {
+        &self.event_registry
+    }
+
+    /// Union of all active registrations.
+    pub fn merged(&self) -> RegisterNotifyOpts {
+        let registrations = self.registrations.lock().unwrap();
+        let mut merged = RegisterNotifyOpts::default();
+        for opts in registrations.active.values() {
+            merged.merge(opts);
+        }
+        merged
+    }
+
+    /// Add a new registration and update the host.
+    ///
+    /// In case the host update fails, the registration is removed again.
+    pub(crate) async fn register(
+        self: &Arc<Self>,
+        protocol: &Protocol,
+        opts: RegisterNotifyOpts,
+    ) -> Result<NotifyHandle, Error> {
+        let handle = self.add(opts);
+        self.sync(protocol).await?;
+
+        Ok(handle)
+    }
+
+    /// Add a new registration delivering the matching notifications as a stream and update the
+    /// host.
+    ///
+    /// In case the host update fails, the registration is removed again.
+    pub(crate) async fn subscribe(
+        self: &Arc<Self>,
+        protocol: &Protocol,
+        opts: RegisterNotifyOpts,
+    ) -> Result<NotificationStream, Error> {
+        let stream = self.add_stream(opts);
+        self.sync(protocol).await?;
+
+        Ok(stream)
+    }
+
+    /// Add a new registration delivering the matching notifications as a stream, without
+    /// updating the host.
+    pub fn add_stream(self: &Arc<Self>, opts: RegisterNotifyOpts) -> NotificationStream {
+        let handle = self.add(opts);
+        let (tx, rx) = mpsc::unbounded_channel();
+        self.registrations
+            .lock()
+            .unwrap()
+            .subscribers
+            .insert(handle.id, tx);
+
+        NotificationStream { rx, handle }
+    }
+
+    /// Deliver runtime notifications received from the host to the matching streams.
+    pub fn deliver(
+        &self,
+        runtime_block: Option<&AnnotatedBlock>,
+        runtime_event: Option<&types::RuntimeNotifyEvent>,
+    ) {
+        let decoded = runtime_event
+            .map(|event| self.decode_events(event))
+            .unwrap_or_default();
+
+        let registrations = self.registrations.lock().unwrap();
+        for (id, tx) in &registrations.subscribers {
+            let Some(opts) = registrations.active.get(id) else {
+                continue;
+            };
+
+            // Closed streams are removed together with their handles.
+            if let Some(block) = runtime_block.filter(|_| opts.runtime_block) {
+                let _ = tx.send(Notification::Block(block.clone()));
+            }
+            if let Some(event) = runtime_event {
+                let tags: Vec<_> = event
+                    .tags
+                    .iter()
+                    .filter(|tag| opts.matches_event(tag))
+                    .cloned()
+                    .collect();
+                if !tags.is_empty() {
+                    let _ = tx.send(Notification::Event {
+                        tags,
+                        round: event.block.block.header.round,
+                    });
+                }
+            }
+            for event in decoded
+                .iter()
+                .filter(|event| opts.matches_event(&event.key))
+            {
+                let _ = tx.send(Notification::UntrustedDecodedEvent(event.clone()));
+            }
+        }
+    }
+
+    /// Decode the events provided with a runtime event notification, skipping tags without a
+    /// registered decoder.
+    fn decode_events(&self, event: &types::RuntimeNotifyEvent) -> Vec<DecodedEvent> {
+        let round = event.block.block.header.round;
+        let decoders = self.event_registry.decoders();
+        event
+            .events
+            .iter()
+            .filter_map(|tag| match decoders.decode(&tag.key, &tag.value)? {
+                Ok(decoded) => Some(DecodedEvent {
+                    key: tag.key.clone(),
+                    round,
+                    tx_index: tag.tx_index,
+                    event: decoded,
+                }),
+                Err(err) => {
+                    error!(self.logger, "failed to decode runtime event";
+                        "err" => %err,
+                        "round" => round,
+                    );
+                    None
+                }
+            })
+            .collect()
+    }
+
+    /// Deliver verified external data feed notifications to the matching streams.
+    pub fn deliver_feed_data(&self, data: &VerifiedFeedData) {
+        let registrations = self.registrations.lock().unwrap();
+        for (id, tx) in &registrations.subscribers {
+            let subscribed = registrations
+                .active
+                .get(id)
+                .map(|opts| opts.external_data.contains(&data.data.feed))
+                .unwrap_or(false);
+            if subscribed {
+                let _ = tx.send(Notification::ExternalData(data.clone()));
+            }
+        }
+    }
+
+    /// Add a new registration without updating the host.
+    ///
+    /// This is mainly useful for alternative host implementations that deliver notifications
+    /// without going through the runtime host protocol.
+    pub fn add(self: &Arc<Self>, opts: RegisterNotifyOpts) -> NotifyHandle {
+        let mut registrations = self.registrations.lock().unwrap();
+        let id = registrations.next_id;
+        registrations.next_id += 1;
+        registrations.active.insert(id, opts);
+
+        NotifyHandle {
+            id,
+            registry: Arc::downgrade(self),
+        }
+    }
+
+    /// Send the union of all active registrations to the host.
+    async fn sync(&self, protocol: &Protocol) -> Result<(), Error> {
+        let _guard = self.sync_lock.lock().await;
+        let opts = self.merged();
+
+        match protocol
+            .call_host_async(types::Body::HostRegisterNotifyRequest {
+                runtime_block: opts.runtime_block,
+                runtime_event: match (opts.runtime_event, opts.runtime_event_prefix) {
+                    (tags, prefixes) if tags.is_empty() && prefixes.is_empty() => None,
+                    (tags, prefixes) => Some(types::RegisterNotifyRuntimeEvent { tags, prefixes }),
+                },
+                consensus_block: opts.consensus_block,
+                consensus_event: match opts.consensus_event {
+                    kinds if kinds.is_empty() => None,
+                    kinds => Some(types::RegisterNotifyConsensusEvent { kinds }),
+                },
+                external_data: match opts.external_data {
+                    feeds if feeds.is_empty() => None,
+                    feeds => Some(types::RegisterNotifyExternalData { feeds }),
+                },
+            })
+            .await?
+        {
+            types::Body::Empty {} => Ok(()),
+            _ => Err(Error::BadResponse),
+        }
+    }
+
+    /// Schedule a background update of the host.
+    pub(crate) fn schedule_sync(&self) {
+        // The receiver only goes away when the runtime is shutting down.
+        let _ = self.resync_tx.send(());
+    }
+
+    /// Start the background task which updates the host after registrations have been modified
+    /// or dropped.
+    pub(crate) fn start(
+        self: &Arc<Self>,
+        protocol: Arc<Protocol>,
+        tokio_runtime: &tokio::runtime::Handle,
+    ) {
+        let mut resync_rx = match self.resync_rx.lock().unwrap().take() {
+            Some(rx) => rx,
+            None => return, // Already started.
+        };
+        // Only keep weak references, as the protocol owns the registry, which owns the sender
+        // keeping the task alive.
+        let registry = Arc::downgrade(self);
+        let protocol = Arc::downgrade(&protocol);
+
+        tokio_runtime.spawn(async move {
+            while resync_rx.recv().await.is_some() {
+                // Coalesce multiple pending updates.
+                while resync_rx.try_recv().is_ok() {}
+
+                let (Some(registry), Some(protocol)) = (registry.upgrade(), protocol.upgrade())
+                else {
+                    break;
+                };
+                if let Err(err) = registry.sync(&protocol).await {
+                    error!(registry.logger, "failed to update notification registrations";
+                        "err" => %err,
+                    );
+                }
+            }
+        });
+    }
+}
+
+/// Handle to an active notification registration.
+///
+/// Dropping the handle removes the registration, so it must be kept for as long as the
+/// notifications are needed.
+#[must_use = "dropping the handle removes the registration"]
+pub struct NotifyHandle {
+    id: u64,
+    registry: Weak<NotifyRegistry>,
+}
+
+impl NotifyHandle {
+    /// Registration identifier, unique for the lifetime of the runtime.
+    pub fn id(&self) -> u64 {
+        self.id
+    }
+
+    /// Current registration options.
+    pub fn opts(&self) -> RegisterNotifyOpts {
+        let Some(registry) = self.registry.upgrade() else {
+            return RegisterNotifyOpts::default();
+        };
+        let registrations = registry.registrations.lock().unwrap();
+        registrations
+            .active
+            .get(&self.id)
+            .cloned()
+            .unwrap_or_default()
+    }
+
+    /// Replace the registration options.
+    ///
+    /// The host is updated in the background.
+    pub fn modify(&self, opts: RegisterNotifyOpts) {
+        let Some(registry) = self.registry.upgrade() else {
+            return;
+        };
+        registry
+            .registrations
+            .lock()
+            .unwrap()
+            .active
+            .insert(self.id, opts);
+        registry.schedule_sync();
+    }
+}
+
+impl Drop for NotifyHandle {
+    fn drop(&mut self) {
+        let Some(registry) = self.registry.upgrade() else {
+            return;
+        };
+        {
+            let mut registrations = registry.registrations.lock().unwrap();
+            registrations.active.remove(&self.id);
+            registrations.subscribers.remove(&self.id);
+        }
+        registry.schedule_sync();
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::host::feed::FeedData;
+
+    #[test]
+    fn test_independent_registrations() {
+        let registry = Arc::new(NotifyRegistry::new());
+
+        let blocks = registry.add(RegisterNotifyOpts {
+            runtime_block: true,
+            runtime_event: vec![],
+            runtime_event_prefix: vec![],
+            consensus_block: false,
+            consensus_event: vec![],
+            external_data: vec![],
+        });
+        let events = registry.add(RegisterNotifyOpts {
+            runtime_block: false,
+            runtime_event: vec![b"b".to_vec(), b"a".to_vec()],
+            runtime_event_prefix: vec![b"x/".to_vec()],
+            consensus_block: true,
+            consensus_event: vec![
+                types::ConsensusEventKind::KeyManagerStatusUpdate,
+                types::ConsensusEventKind::RuntimeAccountEscrow,
+            ],
+            external_data: vec!["prices".to_string()],
+        });
+        assert_ne!(blocks.id(), events.id());
+
+        let merged = registry.merged();
+        assert!(merged.runtime_block);
+        assert_eq!(merged.runtime_event, vec![b"a".to_vec(), b"b".to_vec()]);
+        assert_eq!(merged.runtime_event_prefix, vec![b"x/".to_vec()]);
+        assert!(merged.consensus_block);
+        assert_eq!(
+            merged.consensus_event,
+            vec![
+                types::ConsensusEventKind::RuntimeAccountEscrow,
+                types::ConsensusEventKind::KeyManagerStatusUpdate,
+            ]
+        );
+        assert_eq!(merged.external_data, vec!["prices".to_string()]);
+
+        // Modifying one registration must not affect the other.
+        events.modify(RegisterNotifyOpts {
+            runtime_block: false,
+            runtime_event: vec![b"a".to_vec(), b"c".to_vec()],
+            runtime_event_prefix: vec![],
+            consensus_block: false,
+            consensus_event: vec![],
+            external_data: vec![],
+        });
+        assert!(blocks.opts().runtime_block);
+        let merged = registry.merged();
+        assert!(merged.runtime_block);
+        assert_eq!(merged.runtime_event, vec![b"a".to_vec(), b"c".to_vec()]);
+        assert!(!merged.consensus_block);
+        assert!(merged.consensus_event.is_empty());
+
+        // Dropping a registration removes only its subscriptions.
+        drop(blocks);
+        let merged = registry.merged();
+        assert!(!merged.runtime_block);
+        assert_eq!(merged.runtime_event, vec![b"a".to_vec(), b"c".to_vec()]);
+
+        drop(events);
+        let merged = registry.merged();
+        assert!(!merged.runtime_block);
+        assert!(merged.runtime_event.is_empty());
+
+        // Handles don't keep the registry alive.
+        let handle = registry.add(RegisterNotifyOpts {
+            runtime_block: true,
+            ..Default::default()
+        });
+        assert_eq!(Arc::strong_count(&registry), 1);
+        drop(registry);
+        assert!(!handle.opts().runtime_block);
+    }
+
+    #[test]
+    fn test_notification_streams() {
+        use futures::StreamExt;
+
+        let registry = Arc::new(NotifyRegistry::new());
+        let mut blocks = registry.add_stream(RegisterNotifyOpts {
+            runtime_block: true,
+            ..Default::default()
+        });
+        let mut events = registry.add_stream(RegisterNotifyOpts {
+            runtime_event: vec![b"a".to_vec(), b"b".to_vec()],
+            external_data: vec!["prices".to_string()],
+            ..Default::default()
+        });
+
+        let mut block = AnnotatedBlock::default();
+        block.block.header.round = 7;
+        registry.deliver(
+            Some(&block),
+            Some(&types::RuntimeNotifyEvent {
+                block: block.clone(),
+                tags: vec![b"b".to_vec(), b"c".to_vec()],
+                events: vec![],
+            }),
+        );
+        registry.deliver(
+            None,
+            Some(&types::RuntimeNotifyEvent {
+                block: block.clone(),
+                tags: vec![b"c".to_vec()],
+                events: vec![],
+            }),
+        );
+
+        futures::executor::block_on(async {
+            assert!(matches!(
+                blocks.next().await,
+                Some(Notification::Block(b)) if b.block.header.round == 7
+            ));
+            assert!(matches!(
+                events.next().await,
+                Some(Notification::Event { tags, round: 7 }) if tags == vec![b"b".to_vec()]
+            ));
+        });
+        registry.deliver_feed_data(&VerifiedFeedData {
+            data: FeedData {
+                feed: "prices".to_string(),
+                ..Default::default()
+            },
+            signers: vec![],
+        });
+        assert!(matches!(
+            events.rx.try_recv(),
+            Ok(Notification::ExternalData(data)) if data.data.feed == "prices"
+        ));
+
+        // Only matching notifications are delivered.
+        assert!(blocks.rx.try_recv().is_err());
+        assert!(events.rx.try_recv().is_err());
+
+        // Dropping a stream removes its registration.
+        drop(blocks);
+        assert!(!registry.merged().runtime_block);
+        assert_eq!(registry.registrations.lock().unwrap().subscribers.len(), 1);
+    }
+
+    crate::runtime_event! {
+        #[event(module = "notify-test", code = 1)]
+        #[derive(Clone, Debug, PartialEq, Eq, cbor::Encode, cbor::Decode)]
+        struct TestEvent {
+            value: u64,
+        }
+    }
+
+    #[test]
+    fn test_decoded_events() {
+        use crate::transaction::events::Event;
+
+        let registry = Arc::new(NotifyRegistry::new());
+        registry.event_registry().register::<TestEvent>();
+        let mut all = registry.add_stream(RegisterNotifyOpts {
+            runtime_event_prefix: vec![vec![]],
+            ..Default::default()
+        });
+        let mut prefixed = registry.add_stream(RegisterNotifyOpts {
+            runtime_event_prefix: vec![b"other/".to_vec()],
+            ..Default::default()
+        });
+
+        let tag = TestEvent { value: 42 }.into_tag();
+        let mut block = AnnotatedBlock::default();
+        block.block.header.round = 3;
+        registry.deliver(
+            None,
+            Some(&types::RuntimeNotifyEvent {
+                block,
+                tags: vec![tag.key.clone(), b"other/raw".to_vec()],
+                events: vec![
+                    types::RuntimeNotifyEventTag {
+                        key: tag.key.clone(),
+                        value: tag.value.clone(),
+                        tx_index: Some(1),
+                    },
+                    types::RuntimeNotifyEventTag {
+                        key: tag.key.clone(),
+                        value: b"invalid".to_vec(),
+                        tx_index: Some(2),
+                    },
+                    types::RuntimeNotifyEventTag {
+                        key: b"other/raw".to_vec(),
+                        value: vec![],
+                        tx_index: None,
+                    },
+                ],
+            }),
+        );
+
+        // Wildcard subscriptions match all tags, but only decodable events are delivered.
+        assert!(matches!(
+            all.rx.try_recv(),
+            Ok(Notification::Event { tags, round: 3 }) if tags.len() == 2
+        ));
+        match all.rx.try_recv() {
+            Ok(Notification::UntrustedDecodedEvent(event)) => {
+                assert_eq!(event.key, tag.key);
+                assert_eq!(event.round, 3);
+                assert_eq!(event.tx_index, Some(1));
+                assert_eq!(
+                    event.downcast::<TestEvent>(),
+                    Some(&TestEvent { value: 42 })
+                );
+            }
+            other => panic!("expected decoded event, got {other:?}"),
+        }
+        assert!(all.rx.try_recv().is_err());
+
+        // Prefix subscriptions only match tags with the prefix.
+        assert!(matches!(
+            prefixed.rx.try_recv(),
+            Ok(Notification::Event { tags, .. }) if tags == vec![b"other/raw".to_vec()]
+        ));
+        assert!(prefixed.rx.try_recv().is_err());
+    }
+}
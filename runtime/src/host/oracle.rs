@@ -0,0 +1,314 @@
+//! Host-mediated oracle for external data.
+//!
+//! Runtimes can't reach external data sources themselves, so data fetches are delegated to the
+//! oracle component of the host. As the host is not trusted, a fetched result only becomes
+//! usable on-chain once enough committee members have observed the same result: each member's
+//! runtime signs an observation binding the request to the hash of the fetched data and hands it
+//! to its host, which exchanges observations with the other members via gossip and returns the
+//! collected signatures. The resulting [`OracleResult`] can then be included in transactions and
+//! verified by any replica against the committee.
+use std::{collections::BTreeSet, sync::Arc};
+
+use thiserror::Error;
+
+use crate::{
+    common::{
+        crypto::{
+            hash::Hash,
+            signature::{
+                signature_context_with_runtime_separation, AsyncSigner, PublicKey, SignatureBundle,
+            },
+        },
+        namespace::Namespace,
+    },
+    protocol::Protocol,
+};
+
+use super::{host_rpc_call, Error as HostError, RetryPolicy};
+
+/// Name of the local RPC endpoint for the oracle.
+pub const LOCAL_RPC_ENDPOINT_ORACLE: &str = "oracle";
+
+/// Name of the Fetch method.
+pub const METHOD_FETCH: &str = "Fetch";
+/// Name of the Attest method.
+pub const METHOD_ATTEST: &str = "Attest";
+
+/// Signature context used for oracle observations.
+const OBSERVATION_SIGNATURE_CONTEXT: &[u8] = b"oasis-core/runtime: oracle observation";
+
+/// Oracle errors.
+#[derive(Error, Debug)]
+pub enum OracleError {
+    #[error("host error: {0}")]
+    Host(#[from] HostError),
+
+    #[error("failed to sign observation: {0}")]
+    Signer(#[source] anyhow::Error),
+
+    #[error("no quorum (got: {got} required: {required})")]
+    NoQuorum { got: usize, required: usize },
+}
+
+/// A request for external data.
+#[derive(Clone, Debug, Default, PartialEq, Eq, cbor::Encode, cbor::Decode)]
+pub struct OracleRequest {
+    /// Name of the data source, as configured on the host.
+    pub source: String,
+    /// Source-specific query.
+    pub query: Vec<u8>,
+    /// Runtime round the request is made in, so that requests in different rounds are distinct.
+    pub round: u64,
+}
+
+impl OracleRequest {
+    /// Unique identifier of the request.
+    pub fn id(&self) -> Hash {
+        Hash::digest_bytes(&cbor::to_vec(self.clone()))
+    }
+}
+
+/// An observation of an oracle request's result, signed by committee members.
+#[derive(Clone, Debug, Default, PartialEq, Eq, cbor::Encode, cbor::Decode)]
+pub struct Observation {
+    /// Identifier of the request.
+    pub request_id: Hash,
+    /// Hash of the observed data.
+    pub data_hash: Hash,
+}
+
+/// Result of an oracle request together with the committee signatures attesting to it.
+#[derive(Clone, Debug, Default, PartialEq, Eq, cbor::Encode, cbor::Decode)]
+pub struct OracleResult {
+    /// The request.
+    pub request: OracleRequest,
+    /// The fetched data.
+    pub data: Vec<u8>,
+    /// Signatures over the observation of the data.
+    pub signatures: Vec<SignatureBundle>,
+}
+
+impl OracleResult {
+    /// Observation attested to by the signatures.
+    pub fn observation(&self) -> Observation {
+        Observation {
+            request_id: self.request.id(),
+            data_hash: Hash::digest_bytes(&self.data),
+        }
+    }
+
+    /// Verify that at least the given number of distinct committee members signed the result.
+    ///
+    /// Signatures by non-members and invalid signatures are ignored.
+    pub fn verify(
+        &self,
+        runtime_id: &Namespace,
+        committee: &[PublicKey],
+        threshold: usize,
+    ) -> Result<(), OracleError> {
+        let context = observation_context(runtime_id);
+        let message = cbor::to_vec(self.observation());
+        let signers: BTreeSet<_> = self
+            .signatures
+            .iter()
+            .filter(|sig| committee.contains(&sig.public_key))
+            .filter(|sig| sig.verify(&context, &message))
+            .map(|sig| sig.public_key)
+            .collect();
+
+        if signers.len() < threshold {
+            return Err(OracleError::NoQuorum {
+                got: signers.len(),
+                required: threshold,
+            });
+        }
+        Ok(())
+    }
+}
+
+/// Request to fetch external data.
+#[derive(Clone, Debug, Default, cbor::Encode, cbor::Decode)]
+pub struct FetchRequest {
+    /// The request.
+    pub request: OracleRequest,
+}
+
+/// Response from the Fetch method.
+#[derive(Clone, Debug, Default, cbor::Encode, cbor::Decode)]
+pub struct FetchResponse {
+    /// The fetched data.
+    pub data: Vec<u8>,
+}
+
+/// Request to share an observation with the committee and collect the matching observations.
+#[derive(Clone, Debug, Default, cbor::Encode, cbor::Decode)]
+pub struct AttestRequest {
+    /// The observation.
+    pub observation: Observation,
+    /// Own signature over the observation.
+    pub signature: SignatureBundle,
+    /// Number of signatures to collect before responding.
+    pub threshold: u32,
+}
+
+/// Response from the Attest method.
+#[derive(Clone, Debug, Default, cbor::Encode, cbor::Decode)]
+pub struct AttestResponse {
+    /// Signatures over the observation collected from committee members.
+    pub signatures: Vec<SignatureBundle>,
+}
+
+/// Oracle client using the oracle component of the host.
+pub struct Oracle {
+    protocol: Arc<Protocol>,
+    signer: Arc<dyn AsyncSigner>,
+}
+
+impl Oracle {
+    /// Create a new oracle client signing observations with the given signer, which should be
+    /// the key the committee members are identified by (usually the RAK).
+    pub fn new(protocol: Arc<Protocol>, signer: Arc<dyn AsyncSigner>) -> Self {
+        Self { protocol, signer }
+    }
+
+    /// Fetch the data for the given request and collect signatures of at least the given number
+    /// of committee members observing the same data.
+    pub async fn request(
+        &self,
+        request: OracleRequest,
+        committee: &[PublicKey],
+        threshold: usize,
+    ) -> Result<OracleResult, OracleError> {
+        let rsp: FetchResponse = host_rpc_call(
+            &self.protocol,
+            LOCAL_RPC_ENDPOINT_ORACLE,
+            METHOD_FETCH,
+            FetchRequest {
+                request: request.clone(),
+            },
+            &self.protocol.get_config().host_query_retry,
+        )
+        .await?;
+
+        let mut result = OracleResult {
+            request,
+            data: rsp.data,
+            signatures: vec![],
+        };
+        let observation = result.observation();
+        let runtime_id = self.protocol.get_runtime_id();
+        let signature = SignatureBundle {
+            public_key: self.signer.public(),
+            signature: self
+                .signer
+                .sign(
+                    &observation_context(&runtime_id),
+                    &cbor::to_vec(observation.clone()),
+                )
+                .await
+                .map_err(OracleError::Signer)?,
+        };
+
+        let rsp: AttestResponse = host_rpc_call(
+            &self.protocol,
+            LOCAL_RPC_ENDPOINT_ORACLE,
+            METHOD_ATTEST,
+            AttestRequest {
+                observation,
+                signature: signature.clone(),
+                threshold: threshold as u32,
+            },
+            &RetryPolicy::none(),
+        )
+        .await?;
+
+        // The host is not trusted, so the collected signatures must be verified.
+        result.signatures = dedup_signatures(signature, rsp.signatures);
+        result.verify(&runtime_id, committee, threshold)?;
+
+        Ok(result)
+    }
+}
+
+fn observation_context(runtime_id: &Namespace) -> Vec<u8> {
+    signature_context_with_runtime_separation(OBSERVATION_SIGNATURE_CONTEXT.to_vec(), runtime_id)
+}
+
+fn dedup_signatures(own: SignatureBundle, others: Vec<SignatureBundle>) -> Vec<SignatureBundle> {
+    let mut seen = BTreeSet::new();
+    std::iter::once(own)
+        .chain(others)
+        .filter(|sig| seen.insert(sig.public_key))
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::common::crypto::signature::{PrivateKey, Signer};
+
+    #[test]
+    fn test_oracle_result_verify() {
+        let runtime_id = Namespace::default();
+        let members: Vec<_> = (0..3)
+            .map(|i| PrivateKey::from_test_seed(format!("oracle member {i}")))
+            .collect();
+        let committee: Vec<_> = members.iter().map(|sk| sk.public_key()).collect();
+        let outsider = PrivateKey::from_test_seed("oracle outsider".to_string());
+
+        let mut result = OracleResult {
+            request: OracleRequest {
+                source: "prices".to_string(),
+                query: b"ROSE/USD".to_vec(),
+                round: 10,
+            },
+            data: b"0.05".to_vec(),
+            signatures: vec![],
+        };
+        let sign = |sk: &PrivateKey, observation: &Observation| SignatureBundle {
+            public_key: sk.public_key(),
+            signature: Signer::sign(
+                sk,
+                &observation_context(&runtime_id),
+                &cbor::to_vec(observation.clone()),
+            )
+            .unwrap(),
+        };
+
+        let observation = result.observation();
+        result.signatures = vec![
+            sign(&members[0], &observation),
+            sign(&members[0], &observation),
+            sign(&outsider, &observation),
+        ];
+        // Duplicate and non-member signatures don't count.
+        assert!(matches!(
+            result.verify(&runtime_id, &committee, 2),
+            Err(OracleError::NoQuorum {
+                got: 1,
+                required: 2
+            })
+        ));
+
+        result.signatures.push(sign(&members[1], &observation));
+        assert!(result.verify(&runtime_id, &committee, 2).is_ok());
+
+        // Signatures are bound to the data and the runtime.
+        result.data = b"0.06".to_vec();
+        assert!(result.verify(&runtime_id, &committee, 1).is_err());
+        result.data = b"0.05".to_vec();
+        let other_runtime = Namespace::from(vec![1; 32]);
+        assert!(result.verify(&other_runtime, &committee, 1).is_err());
+    }
+
+    #[test]
+    fn test_dedup_signatures() {
+        let sk = PrivateKey::from_test_seed("oracle member".to_string());
+        let own = SignatureBundle {
+            public_key: sk.public_key(),
+            ..Default::default()
+        };
+        let signatures = dedup_signatures(own.clone(), vec![own, SignatureBundle::default()]);
+        assert_eq!(signatures.len(), 2);
+    }
+}
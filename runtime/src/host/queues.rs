@@ -0,0 +1,348 @@
+//! Bounded queues of requests received from the host.
+//!
+//! Requests from the host are handled concurrently, so a host sending requests faster than they
+//! are handled (e.g. during event storms) could make the runtime buffer an unbounded number of
+//! them. Requests are therefore queued per message class, with each queue bounding both the number
+//! of requests being handled and the number of requests waiting to be handled. Once a queue is
+//! full, further requests are handled according to its overflow policy. Control requests, which
+//! are needed to keep the runtime operational, are never bounded.
+use std::{
+    collections::{BTreeMap, HashMap, VecDeque},
+    fmt,
+    sync::{Condvar, Mutex},
+};
+
+use crate::{
+    config::{MessageQueue, MessageQueues, OverflowPolicy},
+    types::Body,
+};
+
+/// Class of a request received from the host.
+///
+/// Waiting requests are dispatched in class order.
+#[derive(
+    Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord, Hash, cbor::Encode, cbor::Decode,
+)]
+#[repr(u8)]
+pub enum MessageClass {
+    /// Connection setup, attestation, checkpoint and shutdown requests.
+    #[default]
+    Control = 0,
+    /// Transaction batch checks and execution.
+    Execution = 1,
+    /// Notifications about runtime and consensus layer changes.
+    Notification = 2,
+    /// Queries and EnclaveRPC calls.
+    Query = 3,
+}
+
+impl MessageClass {
+    /// Class of the given request body.
+    pub fn of(body: &Body) -> Self {
+        match body {
+            Body::RuntimeCheckTxBatchRequest { .. } | Body::RuntimeExecuteTxBatchRequest { .. } => {
+                Self::Execution
+            }
+            Body::RuntimeNotifyRequest { .. }
+            | Body::RuntimeKeyManagerStatusUpdateRequest { .. }
+            | Body::RuntimeKeyManagerQuotePolicyUpdateRequest { .. }
+            | Body::RuntimeConsensusSyncRequest { .. }
+            | Body::RuntimeStorageCommitAckRequest { .. } => Self::Notification,
+            Body::RuntimeQueryRequest { .. }
+            | Body::RuntimeRPCCallRequest { .. }
+            | Body::RuntimeRPCStreamRequest { .. }
+            | Body::RuntimeLocalRPCCallRequest { .. } => Self::Query,
+            _ => Self::Control,
+        }
+    }
+}
+
+impl fmt::Display for MessageClass {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            MessageClass::Control => "control",
+            MessageClass::Execution => "execution",
+            MessageClass::Notification => "notification",
+            MessageClass::Query => "query",
+        };
+        f.write_str(name)
+    }
+}
+
+/// Outcome of queueing a request.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Admission {
+    /// The request was queued.
+    Queued,
+    /// The request was queued after dropping the oldest waiting request with the given
+    /// identifier.
+    DroppedOldest(u64),
+    /// The request was rejected as its queue is full.
+    Rejected {
+        /// Class of the rejected request.
+        class: MessageClass,
+        /// Number of queued requests of the class.
+        depth: usize,
+        /// Whether backpressure should be signaled to the host.
+        backpressure: bool,
+    },
+}
+
+/// Statistics of the queue of a message class.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct QueueStats {
+    /// Number of requests waiting to be handled.
+    pub waiting: usize,
+    /// Number of requests being handled.
+    pub in_flight: usize,
+    /// The largest number of queued requests, counting both waiting requests and requests being
+    /// handled.
+    pub max_depth: usize,
+    /// Number of requests queued since the runtime started.
+    pub queued: u64,
+    /// Number of requests dropped or rejected since the runtime started.
+    pub dropped: u64,
+}
+
+struct Queue {
+    limits: MessageQueue,
+    waiting: VecDeque<(u64, Body)>,
+    in_flight: usize,
+    stats: QueueStats,
+}
+
+impl Queue {
+    fn new(limits: MessageQueue) -> Self {
+        Self {
+            limits,
+            waiting: VecDeque::new(),
+            in_flight: 0,
+            stats: QueueStats::default(),
+        }
+    }
+
+    fn depth(&self) -> usize {
+        self.waiting.len() + self.in_flight
+    }
+
+    fn is_full(&self) -> bool {
+        self.limits.capacity > 0 && self.waiting.len() >= self.limits.capacity
+    }
+
+    fn can_dispatch(&self) -> bool {
+        !self.waiting.is_empty()
+            && (self.limits.capacity == 0 || self.in_flight < self.limits.capacity)
+    }
+}
+
+struct Inner {
+    queues: BTreeMap<MessageClass, Queue>,
+    in_flight: HashMap<u64, MessageClass>,
+}
+
+impl Inner {
+    fn try_pop(&mut self) -> Option<(u64, Body)> {
+        let (class, queue) = self
+            .queues
+            .iter_mut()
+            .find(|(_, queue)| queue.can_dispatch())?;
+        let (id, body) = queue.waiting.pop_front()?;
+        queue.in_flight += 1;
+        self.in_flight.insert(id, *class);
+
+        Some((id, body))
+    }
+}
+
+/// Bounded per-class queues of requests received from the host.
+pub struct RequestQueues {
+    inner: Mutex<Inner>,
+    available: Condvar,
+}
+
+impl RequestQueues {
+    /// Create new queues with the given limits.
+    pub fn new(limits: &MessageQueues) -> Self {
+        let queues = [
+            (MessageClass::Control, MessageQueue::default()),
+            (MessageClass::Execution, limits.execution),
+            (MessageClass::Notification, limits.notification),
+            (MessageClass::Query, limits.query),
+        ]
+        .into_iter()
+        .map(|(class, limits)| (class, Queue::new(limits)))
+        .collect();
+
+        Self {
+            inner: Mutex::new(Inner {
+                queues,
+                in_flight: HashMap::new(),
+            }),
+            available: Condvar::new(),
+        }
+    }
+
+    /// Queue the request with the given identifier, applying the overflow policy of its class in
+    /// case its queue is full.
+    pub fn push(&self, id: u64, body: Body) -> Admission {
+        let class = MessageClass::of(&body);
+        let mut inner = self.inner.lock().unwrap();
+        let queue = inner
+            .queues
+            .get_mut(&class)
+            .expect("all classes have queues");
+
+        let admission = if queue.is_full() {
+            queue.stats.dropped += 1;
+            match queue.limits.overflow {
+                OverflowPolicy::DropOldest => {
+                    let (dropped, _) = queue.waiting.pop_front().expect("queue is full");
+                    Admission::DroppedOldest(dropped)
+                }
+                policy => {
+                    return Admission::Rejected {
+                        class,
+                        depth: queue.depth(),
+                        backpressure: policy == OverflowPolicy::Backpressure,
+                    }
+                }
+            }
+        } else {
+            Admission::Queued
+        };
+
+        queue.waiting.push_back((id, body));
+        queue.stats.queued += 1;
+        queue.stats.max_depth = queue.stats.max_depth.max(queue.depth());
+        self.available.notify_one();
+
+        admission
+    }
+
+    /// Take the next request that may be handled, waiting until one is available.
+    pub fn pop(&self) -> (u64, Body) {
+        let mut inner = self.inner.lock().unwrap();
+        loop {
+            if let Some(request) = inner.try_pop() {
+                return request;
+            }
+            inner = self.available.wait(inner).unwrap();
+        }
+    }
+
+    /// Mark the request with the given identifier as handled, allowing further requests of its
+    /// class to be handled. Returns the class of the request, if it was being handled.
+    pub fn complete(&self, id: u64) -> Option<MessageClass> {
+        let mut inner = self.inner.lock().unwrap();
+        let class = inner.in_flight.remove(&id)?;
+        let queue = inner
+            .queues
+            .get_mut(&class)
+            .expect("all classes have queues");
+        queue.in_flight -= 1;
+        self.available.notify_one();
+
+        Some(class)
+    }
+
+    /// Statistics of the queues of all message classes.
+    pub fn stats(&self) -> BTreeMap<MessageClass, QueueStats> {
+        self.inner
+            .lock()
+            .unwrap()
+            .queues
+            .iter()
+            .map(|(class, queue)| {
+                let stats = QueueStats {
+                    waiting: queue.waiting.len(),
+                    in_flight: queue.in_flight,
+                    ..queue.stats.clone()
+                };
+                (*class, stats)
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn notify() -> Body {
+        Body::RuntimeNotifyRequest {
+            runtime_block: None,
+            runtime_event: None,
+            consensus_block: None,
+            consensus_event: None,
+            external_data: None,
+        }
+    }
+
+    fn new_queues(overflow: OverflowPolicy) -> RequestQueues {
+        RequestQueues::new(&MessageQueues {
+            notification: MessageQueue {
+                capacity: 1,
+                overflow,
+            },
+            ..Default::default()
+        })
+    }
+
+    #[test]
+    fn test_request_queues() {
+        // Requests are only dispatched while the class has capacity.
+        let queues = new_queues(OverflowPolicy::Backpressure);
+        assert_eq!(queues.push(1, notify()), Admission::Queued);
+        assert_eq!(queues.pop().0, 1);
+        assert_eq!(queues.push(2, notify()), Admission::Queued);
+        assert_eq!(
+            queues.push(3, notify()),
+            Admission::Rejected {
+                class: MessageClass::Notification,
+                depth: 2,
+                backpressure: true,
+            }
+        );
+        assert!(queues.inner.lock().unwrap().try_pop().is_none());
+
+        // Other classes are not affected.
+        assert_eq!(
+            queues.push(4, Body::RuntimePingRequest {}),
+            Admission::Queued
+        );
+        assert_eq!(queues.pop().0, 4);
+
+        queues.complete(1);
+        assert_eq!(queues.pop().0, 2);
+
+        let stats = queues.stats();
+        assert_eq!(
+            stats[&MessageClass::Notification],
+            QueueStats {
+                waiting: 0,
+                in_flight: 1,
+                max_depth: 2,
+                queued: 2,
+                dropped: 1,
+            }
+        );
+        assert_eq!(stats[&MessageClass::Control].in_flight, 1);
+
+        // Full queues drop either the oldest or the newest request.
+        let queues = new_queues(OverflowPolicy::DropOldest);
+        queues.push(1, notify());
+        assert_eq!(queues.push(2, notify()), Admission::DroppedOldest(1));
+        assert_eq!(queues.pop().0, 2);
+
+        let queues = new_queues(OverflowPolicy::DropNewest);
+        queues.push(1, notify());
+        assert!(matches!(
+            queues.push(2, notify()),
+            Admission::Rejected {
+                backpressure: false,
+                ..
+            }
+        ));
+        assert_eq!(queues.pop().0, 1);
+    }
+}
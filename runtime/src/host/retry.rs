@@ -0,0 +1,118 @@
+//! Retrying of host calls.
+//!
+//! Calls to the host may fail transiently, e.g. while the host is restarting or applying
+//! backpressure. Idempotent calls can be retried using a [`RetryPolicy`], waiting for a jittered,
+//! exponentially increasing delay between attempts. Non-idempotent calls (e.g. transaction
+//! submissions) are never retried unless explicitly requested by the caller.
+use std::{future::Future, time::Duration};
+
+use tokio_retry::{strategy::jitter, RetryIf};
+
+use super::Error;
+
+/// Maximum delay between attempts.
+const MAX_BACKOFF: Duration = Duration::from_secs(10);
+
+/// Policy for retrying failed host calls.
+#[derive(Clone, Debug)]
+pub struct RetryPolicy {
+    /// Maximum number of attempts, including the first one.
+    pub max_attempts: usize,
+    /// Delay before the first retry. The delay is doubled for each further retry and jittered.
+    pub backoff: Duration,
+    /// Whether a call failing with the given error should be retried.
+    pub retryable: fn(&Error) -> bool,
+}
+
+impl RetryPolicy {
+    /// Policy which never retries.
+    pub fn none() -> Self {
+        Self {
+            max_attempts: 1,
+            ..Default::default()
+        }
+    }
+
+    /// Delays between attempts.
+    fn delays(&self) -> impl Iterator<Item = Duration> {
+        let backoff = self.backoff;
+        (0..self.max_attempts.saturating_sub(1) as u32).map(move |retry| {
+            let delay = backoff
+                .saturating_mul(2u32.saturating_pow(retry))
+                .min(MAX_BACKOFF);
+            jitter(delay)
+        })
+    }
+
+    /// Run the given call, retrying it according to the policy.
+    pub async fn run<F, Fut, T>(&self, call: F) -> Result<T, Error>
+    where
+        F: FnMut() -> Fut,
+        Fut: Future<Output = Result<T, Error>>,
+    {
+        RetryIf::spawn(self.delays(), call, |err: &Error| (self.retryable)(err)).await
+    }
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            max_attempts: 3,
+            backoff: Duration::from_millis(100),
+            retryable: Error::is_transient,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    use super::*;
+    use crate::protocol::ProtocolError;
+
+    #[test]
+    fn test_retry_policy() {
+        let rt = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .unwrap();
+        let policy = RetryPolicy {
+            backoff: Duration::from_millis(1),
+            ..Default::default()
+        };
+        let attempts = &AtomicUsize::new(0);
+
+        // Transient errors are retried up to the maximum number of attempts.
+        let result: Result<(), _> = rt.block_on(policy.run(move || async move {
+            attempts.fetch_add(1, Ordering::SeqCst);
+            Err(Error::Host(ProtocolError::Timeout.into()))
+        }));
+        assert!(result.is_err());
+        assert_eq!(attempts.swap(0, Ordering::SeqCst), 3);
+
+        let result = rt.block_on(policy.run(move || async move {
+            match attempts.fetch_add(1, Ordering::SeqCst) {
+                0 => Err(Error::Host(ProtocolError::ChannelClosed.into())),
+                _ => Ok(42),
+            }
+        }));
+        assert_eq!(result.unwrap(), 42);
+        assert_eq!(attempts.swap(0, Ordering::SeqCst), 2);
+
+        // Other errors are not retried.
+        let result: Result<(), _> = rt.block_on(policy.run(move || async move {
+            attempts.fetch_add(1, Ordering::SeqCst);
+            Err(Error::BadResponse)
+        }));
+        assert!(result.is_err());
+        assert_eq!(attempts.swap(0, Ordering::SeqCst), 1);
+
+        let result: Result<(), _> = rt.block_on(RetryPolicy::none().run(move || async move {
+            attempts.fetch_add(1, Ordering::SeqCst);
+            Err(Error::Host(ProtocolError::Timeout.into()))
+        }));
+        assert!(result.is_err());
+        assert_eq!(attempts.load(Ordering::SeqCst), 1);
+    }
+}
@@ -0,0 +1,440 @@
+//! Operator-provisioned secrets.
+//!
+//! Off-chain components often need credentials (e.g. API keys) which must not be part of the
+//! runtime bundle or be visible to the host. Secrets are provisioned by the operator and handed
+//! out by the host encrypted to the runtime's ephemeral public key (REK), so only the runtime can
+//! decrypt them. Secrets are sealed with the operator's static key, which is pinned in the
+//! runtime configuration, so that secrets sealed by anyone else (e.g. the host, which knows the
+//! REK) are rejected. The encryption is bound to the runtime identifier and the name of the
+//! secret, so the host can't substitute one secret for another. Decrypted secrets are cached for
+//! the time-to-live the operator sealed together with the secret.
+use std::{
+    collections::HashMap,
+    fmt,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use anyhow::anyhow;
+use async_trait::async_trait;
+use rand::Rng;
+use thiserror::Error;
+use zeroize::{Zeroize, ZeroizeOnDrop};
+
+use crate::{
+    common::{
+        crypto::{
+            mrae::deoxysii::{self, Opener, NONCE_SIZE},
+            rng::SecureRng,
+            x25519,
+        },
+        namespace::Namespace,
+    },
+    protocol::Protocol,
+    types::Body,
+};
+
+use super::Error as HostError;
+
+/// Context used as a prefix of the additional data of encrypted secrets.
+pub const SECRET_CONTEXT: &[u8] = b"oasis-core/runtime: secret";
+
+/// Default maximum number of decrypted secrets held by a cache.
+pub const DEFAULT_MAX_CACHED_SECRETS: usize = 64;
+
+/// Secrets errors.
+#[derive(Error, Debug)]
+pub enum SecretsError {
+    #[error("host error: {0}")]
+    Host(#[from] HostError),
+
+    #[error("secret not found: {0}")]
+    NotFound(String),
+
+    #[error("runtime not attested")]
+    NotAttested,
+
+    #[error("secrets not supported")]
+    Unsupported,
+
+    #[error("no operator key configured")]
+    OperatorNotConfigured,
+
+    #[error("secret not sealed by the operator")]
+    UntrustedSender,
+
+    #[error("failed to decrypt secret: {0}")]
+    Decryption(#[source] anyhow::Error),
+}
+
+/// A decrypted secret, zeroized on drop.
+#[derive(Clone, PartialEq, Eq, Zeroize, ZeroizeOnDrop)]
+pub struct Secret(Vec<u8>);
+
+impl Secret {
+    /// Wrap the given secret value.
+    pub(crate) fn new(value: Vec<u8>) -> Self {
+        Self(value)
+    }
+
+    /// Value of the secret.
+    pub fn expose(&self) -> &[u8] {
+        &self.0
+    }
+}
+
+impl fmt::Debug for Secret {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("Secret(<redacted>)")
+    }
+}
+
+/// Plaintext of an encrypted secret.
+#[derive(Clone, Default, cbor::Encode, cbor::Decode, Zeroize, ZeroizeOnDrop)]
+struct SecretPayload {
+    value: Vec<u8>,
+    ttl: u64,
+}
+
+/// A secret sealed by the operator to the runtime's ephemeral public key.
+#[derive(Clone, Debug, Default, PartialEq, Eq, cbor::Encode, cbor::Decode)]
+pub struct EncryptedSecret {
+    /// Public key of the operator the secret was sealed by.
+    pub public_key: x25519::PublicKey,
+    /// Nonce.
+    pub nonce: Vec<u8>,
+    /// Encrypted secret value together with its time-to-live.
+    pub ciphertext: Vec<u8>,
+}
+
+impl EncryptedSecret {
+    /// Seal the given secret of the given runtime with the operator's key to the runtime's
+    /// ephemeral public key. The decrypted secret may be cached for the given time-to-live.
+    pub fn seal(
+        runtime_id: &Namespace,
+        name: &str,
+        value: Vec<u8>,
+        ttl: Duration,
+        runtime_public_key: &x25519::PublicKey,
+        operator_key: &x25519::PrivateKey,
+    ) -> Self {
+        let mut nonce = [0u8; NONCE_SIZE];
+        SecureRng.fill(&mut nonce);
+        let payload = SecretPayload {
+            value,
+            ttl: ttl.as_secs(),
+        };
+        let ciphertext = deoxysii::box_seal(
+            &nonce,
+            cbor::to_vec(payload),
+            additional_data(runtime_id, name),
+            &runtime_public_key.0,
+            &operator_key.0,
+        )
+        .expect("sealing must succeed");
+
+        Self {
+            public_key: operator_key.public_key(),
+            nonce: nonce.to_vec(),
+            ciphertext,
+        }
+    }
+
+    /// Decrypt the given secret of the given runtime, sealed by the given operator, returning the
+    /// secret and its time-to-live.
+    pub fn open(
+        &self,
+        opener: &dyn Opener,
+        runtime_id: &Namespace,
+        name: &str,
+        operator_key: &x25519::PublicKey,
+    ) -> Result<(Secret, Duration), SecretsError> {
+        // The box is authenticated by the sender's key, so only the operator can seal secrets
+        // which open under its key.
+        if self.public_key != *operator_key {
+            return Err(SecretsError::UntrustedSender);
+        }
+        let nonce: [u8; NONCE_SIZE] = self
+            .nonce
+            .as_slice()
+            .try_into()
+            .map_err(|_| SecretsError::Decryption(anyhow!("malformed nonce")))?;
+        let mut plaintext = opener
+            .box_open(
+                &nonce,
+                self.ciphertext.clone(),
+                additional_data(runtime_id, name),
+                &self.public_key.0,
+            )
+            .map_err(SecretsError::Decryption)?;
+        let payload: Result<SecretPayload, _> = cbor::from_slice(&plaintext);
+        plaintext.zeroize();
+        let mut payload = payload.map_err(|err| SecretsError::Decryption(err.into()))?;
+
+        let secret = Secret(std::mem::take(&mut payload.value));
+        Ok((secret, Duration::from_secs(payload.ttl)))
+    }
+}
+
+/// Additional data binding an encrypted secret to the runtime and its name.
+fn additional_data(runtime_id: &Namespace, name: &str) -> Vec<u8> {
+    [SECRET_CONTEXT, runtime_id.as_ref(), name.as_bytes()].concat()
+}
+
+/// Secrets provisioned by the operator.
+#[async_trait]
+pub trait Secrets: Send + Sync {
+    /// Fetch the secret with the given name, decrypting it inside the runtime.
+    ///
+    /// Secrets are served from the cache until their time-to-live expires.
+    async fn get(&self, name: &str) -> Result<Secret, SecretsError>;
+
+    /// Drop the cached value of the given secret, e.g. after the operator rotated it.
+    fn invalidate(&self, name: &str);
+}
+
+/// Secrets of hosts which don't support provisioning them.
+pub struct NoSecrets;
+
+#[async_trait]
+impl Secrets for NoSecrets {
+    async fn get(&self, _name: &str) -> Result<Secret, SecretsError> {
+        Err(SecretsError::Unsupported)
+    }
+
+    fn invalidate(&self, _name: &str) {}
+}
+
+/// A cached secret.
+struct CachedSecret {
+    secret: Secret,
+    expires: Instant,
+}
+
+/// Cache of decrypted secrets.
+pub struct SecretCache {
+    max_entries: usize,
+    entries: Mutex<HashMap<String, CachedSecret>>,
+}
+
+impl Default for SecretCache {
+    fn default() -> Self {
+        Self::new(DEFAULT_MAX_CACHED_SECRETS)
+    }
+}
+
+impl SecretCache {
+    /// Create a new empty cache holding at most the given number of secrets.
+    pub fn new(max_entries: usize) -> Self {
+        Self {
+            max_entries,
+            entries: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Fetch the given secret in case it has not expired at the given time.
+    pub fn get(&self, name: &str, now: Instant) -> Option<Secret> {
+        let mut entries = self.entries.lock().unwrap();
+        match entries.get(name) {
+            Some(cached) if cached.expires > now => Some(cached.secret.clone()),
+            Some(_) => {
+                entries.remove(name);
+                None
+            }
+            None => None,
+        }
+    }
+
+    /// Cache the given secret for the given time-to-live, starting at the given time. Secrets
+    /// with a zero time-to-live are not cached.
+    ///
+    /// Expired secrets are dropped and, if the cache is full, the secret closest to its
+    /// expiration is evicted.
+    pub fn insert(&self, name: &str, secret: Secret, ttl: Duration, now: Instant) {
+        if ttl.is_zero() || self.max_entries == 0 {
+            return;
+        }
+        let expires = match now.checked_add(ttl) {
+            Some(expires) => expires,
+            None => return,
+        };
+
+        let mut entries = self.entries.lock().unwrap();
+        entries.retain(|_, cached| cached.expires > now);
+        if !entries.contains_key(name) && entries.len() >= self.max_entries {
+            let oldest = entries
+                .iter()
+                .min_by_key(|(_, cached)| cached.expires)
+                .map(|(name, _)| name.clone());
+            if let Some(oldest) = oldest {
+                entries.remove(&oldest);
+            }
+        }
+        entries.insert(name.to_string(), CachedSecret { secret, expires });
+    }
+
+    /// Number of cached secrets.
+    pub fn len(&self) -> usize {
+        self.entries.lock().unwrap().len()
+    }
+
+    /// Whether the cache is empty.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Drop the given secret.
+    pub fn invalidate(&self, name: &str) {
+        self.entries.lock().unwrap().remove(name);
+    }
+}
+
+#[async_trait]
+impl Secrets for Protocol {
+    async fn get(&self, name: &str) -> Result<Secret, SecretsError> {
+        if let Some(secret) = self.secret_cache.get(name, Instant::now()) {
+            return Ok(secret);
+        }
+
+        let operator_key = self
+            .get_config()
+            .secrets_operator_key
+            .ok_or(SecretsError::OperatorNotConfigured)?;
+        let identity = self.get_identity().ok_or(SecretsError::NotAttested)?;
+        let encrypted = match self
+            .call_host_async(Body::HostSecretRequest {
+                name: name.to_string(),
+                public_key: identity.public_rek(),
+            })
+            .await
+            .map_err(HostError::from)?
+        {
+            Body::HostSecretResponse {
+                secret: Some(secret),
+            } => secret,
+            Body::HostSecretResponse { secret: None } => {
+                return Err(SecretsError::NotFound(name.to_string()))
+            }
+            _ => return Err(HostError::BadResponse.into()),
+        };
+
+        let (secret, ttl) =
+            encrypted.open(&**identity, &self.get_runtime_id(), name, &operator_key)?;
+        self.secret_cache
+            .insert(name, secret.clone(), ttl, Instant::now());
+        Ok(secret)
+    }
+
+    fn invalidate(&self, name: &str) {
+        self.secret_cache.invalidate(name);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    struct TestOpener(x25519::PrivateKey);
+
+    impl Opener for TestOpener {
+        fn box_open(
+            &self,
+            nonce: &[u8; NONCE_SIZE],
+            ciphertext: Vec<u8>,
+            additional_data: Vec<u8>,
+            peers_public_key: &x25519_dalek::PublicKey,
+        ) -> anyhow::Result<Vec<u8>> {
+            deoxysii::box_open(
+                nonce,
+                ciphertext,
+                additional_data,
+                peers_public_key,
+                &self.0 .0,
+            )
+        }
+    }
+
+    #[test]
+    fn test_encrypted_secret() {
+        let runtime_id = Namespace::from(vec![1; 32]);
+        let rek = TestOpener(x25519::PrivateKey::from_test_seed("rek".to_string()));
+        let operator = x25519::PrivateKey::from_test_seed("operator".to_string());
+        let operator_key = operator.public_key();
+        let encrypted = EncryptedSecret::seal(
+            &runtime_id,
+            "api-key",
+            b"hunter2".to_vec(),
+            Duration::from_secs(60),
+            &rek.0.public_key(),
+            &operator,
+        );
+
+        let (secret, ttl) = encrypted
+            .open(&rek, &runtime_id, "api-key", &operator_key)
+            .unwrap();
+        assert_eq!(secret.expose(), b"hunter2");
+        assert_eq!(ttl, Duration::from_secs(60));
+        assert_eq!(format!("{secret:?}"), "Secret(<redacted>)");
+
+        // Secrets are bound to their name, the runtime and its key.
+        assert!(encrypted
+            .open(&rek, &runtime_id, "other", &operator_key)
+            .is_err());
+        let other_runtime = Namespace::from(vec![2; 32]);
+        assert!(encrypted
+            .open(&rek, &other_runtime, "api-key", &operator_key)
+            .is_err());
+        let other_rek = TestOpener(x25519::PrivateKey::from_test_seed("other".to_string()));
+        assert!(encrypted
+            .open(&other_rek, &runtime_id, "api-key", &operator_key)
+            .is_err());
+
+        // Secrets sealed by anyone knowing the REK other than the operator are rejected.
+        let host = x25519::PrivateKey::from_test_seed("host".to_string());
+        let forged = EncryptedSecret::seal(
+            &runtime_id,
+            "api-key",
+            b"forged".to_vec(),
+            Duration::from_secs(60),
+            &rek.0.public_key(),
+            &host,
+        );
+        assert!(matches!(
+            forged.open(&rek, &runtime_id, "api-key", &operator_key),
+            Err(SecretsError::UntrustedSender)
+        ));
+        let forged = EncryptedSecret {
+            public_key: operator_key,
+            ..forged
+        };
+        assert!(forged
+            .open(&rek, &runtime_id, "api-key", &operator_key)
+            .is_err());
+    }
+
+    #[test]
+    fn test_secret_cache() {
+        let cache = SecretCache::new(2);
+        let now = Instant::now();
+        let secret = Secret(b"hunter2".to_vec());
+
+        cache.insert("a", secret.clone(), Duration::from_secs(10), now);
+        cache.insert("b", secret.clone(), Duration::ZERO, now);
+        assert_eq!(cache.get("a", now + Duration::from_secs(9)), Some(secret));
+        assert_eq!(cache.get("b", now), None);
+
+        // Expired and invalidated secrets are dropped.
+        assert_eq!(cache.get("a", now + Duration::from_secs(10)), None);
+        cache.insert("a", Secret(b"new".to_vec()), Duration::from_secs(10), now);
+        cache.invalidate("a");
+        assert_eq!(cache.get("a", now), None);
+
+        // The secret closest to its expiration is evicted once the cache is full.
+        cache.insert("a", secret.clone(), Duration::from_secs(10), now);
+        cache.insert("b", secret.clone(), Duration::from_secs(20), now);
+        cache.insert("c", secret.clone(), Duration::from_secs(30), now);
+        assert_eq!(cache.len(), 2);
+        assert_eq!(cache.get("a", now), None);
+        assert!(cache.get("b", now).is_some() && cache.get("c", now).is_some());
+    }
+}
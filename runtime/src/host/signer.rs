@@ -0,0 +1,276 @@
+//! Host-mediated remote signer.
+//!
+//! Deployments without a TEE can keep long-term signing keys in an HSM or a cloud KMS which is
+//! only reachable by the host. The host exposes such keys through a local RPC endpoint and
+//! [`HostSigner`] makes them usable wherever an [`AsyncSigner`] is accepted.
+//!
+//! As the host could otherwise substitute a key of its own, the public key reported by the host
+//! is never trusted on its own. Runtimes either pin the expected public key, or require signing
+//! keys to be attested by the signer endpoint: the endpoint signs a binding of the key to the
+//! runtime and a fresh nonce with its attestation key, which must be part of the
+//! [`SignerTrustRoot`].
+use std::sync::Arc;
+
+use anyhow::{anyhow, Result};
+use async_trait::async_trait;
+use rand::RngCore;
+
+use crate::{
+    common::{
+        crypto::{
+            rng::SecureRng,
+            signature::{AsyncSigner, PublicKey, Signature, SignatureBundle},
+        },
+        namespace::Namespace,
+    },
+    protocol::Protocol,
+};
+
+use super::{host_rpc_call, Error};
+
+/// Name of the local RPC endpoint for the remote signer.
+pub const LOCAL_RPC_ENDPOINT_SIGNER: &str = "signer";
+
+/// Name of the PublicKey method.
+pub const METHOD_PUBLIC_KEY: &str = "PublicKey";
+/// Name of the Sign method.
+pub const METHOD_SIGN: &str = "Sign";
+
+/// Signature context used for signer endpoint bindings.
+pub const BINDING_SIGNATURE_CONTEXT: &[u8] = b"oasis-core/runtime: signer endpoint binding";
+
+/// Request to return the public key of a signing key.
+#[derive(Clone, Debug, Default, cbor::Encode, cbor::Decode)]
+pub struct PublicKeyRequest {
+    /// Identifier of the signing key, as configured on the host.
+    pub key_id: String,
+    /// Nonce to include in the binding of the signing key, if one is requested.
+    #[cbor(optional)]
+    pub nonce: Vec<u8>,
+}
+
+/// Response from the PublicKey method.
+#[derive(Clone, Debug, Default, cbor::Encode, cbor::Decode)]
+pub struct PublicKeyResponse {
+    /// Public key of the signing key.
+    pub public_key: PublicKey,
+    /// Signature of the signer endpoint over the binding of the signing key, if one was
+    /// requested.
+    #[cbor(optional)]
+    pub binding: Option<SignatureBundle>,
+}
+
+/// Binding of a signing key held by a signer endpoint to a runtime.
+#[derive(Clone, Debug, Default, PartialEq, Eq, cbor::Encode, cbor::Decode)]
+pub struct SignerBinding {
+    /// Identifier of the runtime using the signing key.
+    pub runtime_id: Namespace,
+    /// Identifier of the signing key.
+    pub key_id: String,
+    /// Public key of the signing key.
+    pub public_key: PublicKey,
+    /// Nonce chosen by the runtime.
+    pub nonce: Vec<u8>,
+}
+
+/// Attestation keys of the signer endpoints trusted to hold signing keys.
+#[derive(Clone, Debug, Default)]
+pub struct SignerTrustRoot {
+    /// Public keys the trusted signer endpoints attest bindings with.
+    pub endpoint_keys: Vec<PublicKey>,
+}
+
+impl SignerTrustRoot {
+    /// Verify that the given binding has been signed by a trusted signer endpoint.
+    pub fn verify(
+        &self,
+        binding: &SignerBinding,
+        signature: Option<&SignatureBundle>,
+    ) -> Result<(), Error> {
+        match signature {
+            Some(sig)
+                if self.endpoint_keys.contains(&sig.public_key)
+                    && sig.verify(BINDING_SIGNATURE_CONTEXT, &cbor::to_vec(binding.clone())) =>
+            {
+                Ok(())
+            }
+            _ => Err(Error::UntrustedSigner),
+        }
+    }
+}
+
+/// Request to sign a message.
+#[derive(Clone, Debug, Default, cbor::Encode, cbor::Decode)]
+pub struct SignRequest {
+    /// Identifier of the signing key, as configured on the host.
+    pub key_id: String,
+    /// Signature context.
+    pub context: Vec<u8>,
+    /// Message to sign.
+    pub message: Vec<u8>,
+}
+
+/// Response from the Sign method.
+#[derive(Clone, Debug, Default, cbor::Encode, cbor::Decode)]
+pub struct SignResponse {
+    /// Signature over the context and message.
+    pub signature: Signature,
+}
+
+/// A signer using a signing key held by the host.
+///
+/// The host is not trusted, so all returned signatures are verified against the public key.
+pub struct HostSigner {
+    protocol: Arc<Protocol>,
+    key_id: String,
+    public_key: PublicKey,
+}
+
+impl HostSigner {
+    /// Create a signer using the signing key with the given identifier, which must have the
+    /// given public key.
+    ///
+    /// Fails with `Error::UntrustedSigner` in case the host reports a different public key.
+    pub async fn new(
+        protocol: Arc<Protocol>,
+        key_id: &str,
+        public_key: PublicKey,
+    ) -> Result<Self, Error> {
+        let rsp = Self::public_key(&protocol, key_id, vec![]).await?;
+        if rsp.public_key != public_key {
+            return Err(Error::UntrustedSigner);
+        }
+
+        Ok(Self {
+            protocol,
+            key_id: key_id.to_string(),
+            public_key,
+        })
+    }
+
+    /// Create a signer using the signing key with the given identifier, which must be attested
+    /// by a signer endpoint of the configured signer trust root.
+    ///
+    /// Fails with `Error::UntrustedSigner` in case no signer trust root is configured or the
+    /// signing key is not attested by a trusted signer endpoint.
+    pub async fn attested(protocol: Arc<Protocol>, key_id: &str) -> Result<Self, Error> {
+        let trust_root = protocol
+            .get_config()
+            .signer_trust_root
+            .as_ref()
+            .ok_or(Error::UntrustedSigner)?;
+        let mut nonce = vec![0; 32];
+        SecureRng.fill_bytes(&mut nonce);
+
+        let rsp = Self::public_key(&protocol, key_id, nonce.clone()).await?;
+        let binding = SignerBinding {
+            runtime_id: protocol.get_runtime_id(),
+            key_id: key_id.to_string(),
+            public_key: rsp.public_key,
+            nonce,
+        };
+        trust_root.verify(&binding, rsp.binding.as_ref())?;
+
+        Ok(Self {
+            protocol,
+            key_id: key_id.to_string(),
+            public_key: rsp.public_key,
+        })
+    }
+
+    async fn public_key(
+        protocol: &Arc<Protocol>,
+        key_id: &str,
+        nonce: Vec<u8>,
+    ) -> Result<PublicKeyResponse, Error> {
+        host_rpc_call(
+            protocol,
+            LOCAL_RPC_ENDPOINT_SIGNER,
+            METHOD_PUBLIC_KEY,
+            PublicKeyRequest {
+                key_id: key_id.to_string(),
+                nonce,
+            },
+            &protocol.get_config().host_query_retry,
+        )
+        .await
+    }
+}
+
+#[async_trait]
+impl AsyncSigner for HostSigner {
+    fn public(&self) -> PublicKey {
+        self.public_key
+    }
+
+    async fn sign(&self, context: &[u8], message: &[u8]) -> Result<Signature> {
+        let rsp: SignResponse = host_rpc_call(
+            &self.protocol,
+            LOCAL_RPC_ENDPOINT_SIGNER,
+            METHOD_SIGN,
+            SignRequest {
+                key_id: self.key_id.clone(),
+                context: context.to_vec(),
+                message: message.to_vec(),
+            },
+            &self.protocol.get_config().host_query_retry,
+        )
+        .await?;
+
+        rsp.signature
+            .verify(&self.public_key, context, message)
+            .map_err(|_| anyhow!("host returned an invalid signature"))?;
+
+        Ok(rsp.signature)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::common::crypto::signature::{PrivateKey, Signer};
+
+    #[test]
+    fn test_signer_trust_root() {
+        let endpoint = PrivateKey::from_test_seed("signer endpoint".to_string());
+        let outsider = PrivateKey::from_test_seed("signer outsider".to_string());
+        let trust_root = SignerTrustRoot {
+            endpoint_keys: vec![endpoint.public_key()],
+        };
+        let binding = SignerBinding {
+            key_id: "identity".to_string(),
+            public_key: PrivateKey::from_test_seed("hsm key".to_string()).public_key(),
+            nonce: vec![1; 32],
+            ..Default::default()
+        };
+        let sign = |sk: &PrivateKey, binding: &SignerBinding| SignatureBundle {
+            public_key: sk.public_key(),
+            signature: Signer::sign(
+                sk,
+                BINDING_SIGNATURE_CONTEXT,
+                &cbor::to_vec(binding.clone()),
+            )
+            .unwrap(),
+        };
+
+        let signature = sign(&endpoint, &binding);
+        assert!(trust_root.verify(&binding, Some(&signature)).is_ok());
+
+        // Bindings must be signed by a trusted endpoint.
+        assert!(trust_root.verify(&binding, None).is_err());
+        let signature = sign(&outsider, &binding);
+        assert!(trust_root.verify(&binding, Some(&signature)).is_err());
+
+        // Bindings are bound to the nonce and the runtime.
+        let signature = sign(&endpoint, &binding);
+        let mut other = binding.clone();
+        other.nonce = vec![2; 32];
+        assert!(trust_root.verify(&other, Some(&signature)).is_err());
+        let mut other = binding;
+        other.runtime_id = Namespace::from(vec![1; 32]);
+        assert!(matches!(
+            trust_root.verify(&other, Some(&signature)),
+            Err(Error::UntrustedSigner)
+        ));
+    }
+}
@@ -0,0 +1,109 @@
+//! Per-source rate limiting of queries forwarded by the host.
+//!
+//! The host forwards queries received on the node's query endpoint, tagging each with an
+//! identifier of its source. Without limits, a single client sending queries in a tight loop keeps
+//! the runtime busy and degrades execution latency for everyone. Each source is therefore limited
+//! by a token bucket sized according to its quota, with queries exceeding it rejected before they
+//! are queued, together with the time after which the source may retry.
+use std::time::{Duration, Instant};
+
+use crate::{
+    common::rate_limit::{Limit, RateLimiter, Rejection},
+    config::{QueryQuota, QueryRateLimits},
+};
+
+/// Throttle of queries forwarded by the host.
+pub struct QueryThrottle {
+    config: QueryRateLimits,
+    limiter: RateLimiter<String>,
+}
+
+impl QueryThrottle {
+    /// Create a new throttle with the given limits.
+    pub fn new(config: QueryRateLimits) -> Self {
+        Self {
+            limiter: RateLimiter::new(config.max_sources),
+            config,
+        }
+    }
+
+    /// Quota of the given source.
+    fn quota(&self, source: &str) -> QueryQuota {
+        self.config
+            .quotas
+            .get(source)
+            .copied()
+            .unwrap_or(self.config.default_quota)
+    }
+
+    /// Admit a query of the given source.
+    ///
+    /// Returns the time after which the source may retry in case the query is rejected.
+    pub fn admit(&self, source: &str) -> Result<(), Duration> {
+        self.admit_at(source, Instant::now())
+    }
+
+    fn admit_at(&self, source: &str, now: Instant) -> Result<(), Duration> {
+        let quota = self.quota(source);
+        let limit = Limit {
+            cost_per_second: quota.queries_per_second,
+            burst: quota.burst,
+            max_concurrent: 0,
+        };
+
+        match self.limiter.admit_at(source.to_string(), limit, 1, now) {
+            Ok(_) => Ok(()),
+            Err(Rejection::RateLimited { retry_after }) => Err(retry_after),
+            Err(Rejection::TooManyConcurrent) => unreachable!("concurrency is not limited"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::BTreeMap;
+
+    use super::*;
+
+    #[test]
+    fn test_query_throttle() {
+        let throttle = QueryThrottle::new(QueryRateLimits {
+            default_quota: QueryQuota {
+                queries_per_second: 2,
+                burst: 4,
+            },
+            quotas: BTreeMap::from([(
+                "trusted".to_string(),
+                QueryQuota {
+                    queries_per_second: 0,
+                    burst: 0,
+                },
+            )]),
+            max_sources: 2,
+        });
+        let now = Instant::now();
+
+        // Sources are limited independently, once their burst is exhausted.
+        for _ in 0..4 {
+            throttle.admit_at("abusive", now).unwrap();
+        }
+        let retry_after = throttle.admit_at("abusive", now).unwrap_err();
+        assert_eq!(retry_after, Duration::from_millis(500));
+        throttle.admit_at("other", now).unwrap();
+
+        // Tokens are refilled at the sustained rate.
+        let later = now + Duration::from_millis(500);
+        throttle.admit_at("abusive", later).unwrap();
+        assert!(throttle.admit_at("abusive", later).is_err());
+
+        // Quotas of specific sources override the default.
+        for _ in 0..100 {
+            throttle.admit_at("trusted", later).unwrap();
+        }
+
+        // The least recently seen source is forgotten.
+        throttle.admit_at("new", later).unwrap();
+        assert!(!throttle.limiter.is_tracked(&"other".to_string()));
+        assert!(throttle.limiter.is_tracked(&"abusive".to_string()));
+    }
+}
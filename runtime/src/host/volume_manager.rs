@@ -2,9 +2,17 @@ use std::collections::BTreeMap;
 
 use async_trait::async_trait;
 
-use crate::protocol::Protocol;
+use crate::{
+    common::{crypto::hash::Hash, pagination::Pagination},
+    protocol::Protocol,
+};
 
-use super::{host_rpc_call, Error};
+use super::{host_rpc_call, Error, RetryPolicy};
+
+/// Module name of volume manager errors.
+pub const MODULE_NAME: &str = "volume";
+/// Error code returned when a write would exceed the quota of the volume.
+pub const CODE_QUOTA_EXCEEDED: u32 = 1;
 
 /// Name of the local RPC endpoint for the volume manager.
 pub const LOCAL_RPC_ENDPOINT_VOLUME_MANAGER: &str = "volume-manager";
@@ -15,6 +23,26 @@ pub const METHOD_VOLUME_ADD: &str = "VolumeAdd";
 pub const METHOD_VOLUME_REMOVE: &str = "VolumeRemove";
 /// Name of the VolumeList method.
 pub const METHOD_VOLUME_LIST: &str = "VolumeList";
+/// Name of the VolumeSnapshot method.
+pub const METHOD_VOLUME_SNAPSHOT: &str = "VolumeSnapshot";
+/// Name of the VolumeRestore method.
+pub const METHOD_VOLUME_RESTORE: &str = "VolumeRestore";
+/// Name of the VolumeSnapshotList method.
+pub const METHOD_VOLUME_SNAPSHOT_LIST: &str = "VolumeSnapshotList";
+/// Name of the VolumeRead method.
+pub const METHOD_VOLUME_READ: &str = "VolumeRead";
+/// Name of the VolumeWrite method.
+pub const METHOD_VOLUME_WRITE: &str = "VolumeWrite";
+/// Name of the VolumeUsage method.
+pub const METHOD_VOLUME_USAGE: &str = "VolumeUsage";
+/// Name of the VolumeBlobPut method.
+pub const METHOD_VOLUME_BLOB_PUT: &str = "VolumeBlobPut";
+/// Name of the VolumeBlobGet method.
+pub const METHOD_VOLUME_BLOB_GET: &str = "VolumeBlobGet";
+/// Name of the VolumeBlobPin method.
+pub const METHOD_VOLUME_BLOB_PIN: &str = "VolumeBlobPin";
+/// Name of the VolumeBlobUnpin method.
+pub const METHOD_VOLUME_BLOB_UNPIN: &str = "VolumeBlobUnpin";
 
 /// Volume manager interface.
 #[async_trait]
@@ -34,6 +62,86 @@ pub trait VolumeManager: Send + Sync {
     ///
     /// The `PermissionVolumeAdd` permission is required to call this method.
     async fn volume_list(&self, args: VolumeListRequest) -> Result<VolumeListResponse, Error>;
+
+    /// Request to host to take a point-in-time snapshot of a volume.
+    ///
+    /// The `PermissionVolumeAdd` permission is required to call this method.
+    async fn volume_snapshot(
+        &self,
+        args: VolumeSnapshotRequest,
+    ) -> Result<VolumeSnapshotResponse, Error>;
+
+    /// Request to host to restore a volume to the state captured by one of its snapshots.
+    ///
+    /// The `PermissionVolumeAdd` permission is required to call this method.
+    async fn volume_restore(
+        &self,
+        args: VolumeRestoreRequest,
+    ) -> Result<VolumeRestoreResponse, Error>;
+
+    /// Request to host to list snapshots of a volume.
+    ///
+    /// The `PermissionVolumeAdd` permission is required to call this method.
+    async fn volume_snapshot_list(
+        &self,
+        args: VolumeSnapshotListRequest,
+    ) -> Result<VolumeSnapshotListResponse, Error>;
+
+    /// Request to host to read a file, or a range of it, from a volume.
+    ///
+    /// The host is not trusted, use `EncryptedVolume` to protect the contents of volumes.
+    ///
+    /// The `PermissionVolumeAdd` permission is required to call this method.
+    async fn volume_read(&self, args: VolumeReadRequest) -> Result<VolumeReadResponse, Error>;
+
+    /// Request to host to write a file to a volume, replacing any existing file.
+    ///
+    /// The host is not trusted, use `EncryptedVolume` to protect the contents of volumes.
+    ///
+    /// The `PermissionVolumeAdd` permission is required to call this method.
+    async fn volume_write(&self, args: VolumeWriteRequest) -> Result<VolumeWriteResponse, Error>;
+
+    /// Request to host to report the storage usage of a volume.
+    ///
+    /// The `PermissionVolumeAdd` permission is required to call this method.
+    async fn volume_usage(&self, args: VolumeUsageRequest) -> Result<VolumeUsage, Error>;
+
+    /// Request to host to store a blob in a blob volume, pinning it.
+    ///
+    /// The host is not trusted, use `BlobStore` to verify blobs against their hashes.
+    ///
+    /// The `PermissionVolumeAdd` permission is required to call this method.
+    async fn volume_blob_put(
+        &self,
+        args: VolumeBlobPutRequest,
+    ) -> Result<VolumeBlobPutResponse, Error>;
+
+    /// Request to host to fetch a blob from a blob volume.
+    ///
+    /// The host is not trusted, use `BlobStore` to verify blobs against their hashes.
+    ///
+    /// The `PermissionVolumeAdd` permission is required to call this method.
+    async fn volume_blob_get(
+        &self,
+        args: VolumeBlobGetRequest,
+    ) -> Result<VolumeBlobGetResponse, Error>;
+
+    /// Request to host to pin a blob stored in a blob volume.
+    ///
+    /// The `PermissionVolumeAdd` permission is required to call this method.
+    async fn volume_blob_pin(
+        &self,
+        args: VolumeBlobPinRequest,
+    ) -> Result<VolumeBlobPinResponse, Error>;
+
+    /// Request to host to unpin a blob stored in a blob volume. Blobs which are no longer pinned
+    /// are removed.
+    ///
+    /// The `PermissionVolumeAdd` permission is required to call this method.
+    async fn volume_blob_unpin(
+        &self,
+        args: VolumeBlobPinRequest,
+    ) -> Result<VolumeBlobPinResponse, Error>;
 }
 
 #[async_trait]
@@ -44,6 +152,7 @@ impl VolumeManager for Protocol {
             LOCAL_RPC_ENDPOINT_VOLUME_MANAGER,
             METHOD_VOLUME_ADD,
             args,
+            &RetryPolicy::none(),
         )
         .await
     }
@@ -57,19 +166,179 @@ impl VolumeManager for Protocol {
             LOCAL_RPC_ENDPOINT_VOLUME_MANAGER,
             METHOD_VOLUME_REMOVE,
             args,
+            &RetryPolicy::none(),
         )
         .await
     }
 
     async fn volume_list(&self, args: VolumeListRequest) -> Result<VolumeListResponse, Error> {
-        host_rpc_call(
+        let args = VolumeListRequest {
+            pagination: args.pagination.normalize(),
+            ..args
+        };
+        let pagination = args.pagination.clone();
+        let rsp: VolumeListResponse = host_rpc_call(
             self,
             LOCAL_RPC_ENDPOINT_VOLUME_MANAGER,
             METHOD_VOLUME_LIST,
             args,
+            &self.get_config().host_query_retry,
+        )
+        .await?;
+        pagination.check(rsp.volumes.len())?;
+
+        Ok(rsp)
+    }
+
+    async fn volume_snapshot(
+        &self,
+        args: VolumeSnapshotRequest,
+    ) -> Result<VolumeSnapshotResponse, Error> {
+        host_rpc_call(
+            self,
+            LOCAL_RPC_ENDPOINT_VOLUME_MANAGER,
+            METHOD_VOLUME_SNAPSHOT,
+            args,
+            &RetryPolicy::none(),
+        )
+        .await
+    }
+
+    async fn volume_restore(
+        &self,
+        args: VolumeRestoreRequest,
+    ) -> Result<VolumeRestoreResponse, Error> {
+        host_rpc_call(
+            self,
+            LOCAL_RPC_ENDPOINT_VOLUME_MANAGER,
+            METHOD_VOLUME_RESTORE,
+            args,
+            &RetryPolicy::none(),
+        )
+        .await
+    }
+
+    async fn volume_snapshot_list(
+        &self,
+        args: VolumeSnapshotListRequest,
+    ) -> Result<VolumeSnapshotListResponse, Error> {
+        let args = VolumeSnapshotListRequest {
+            pagination: args.pagination.normalize(),
+            ..args
+        };
+        let pagination = args.pagination.clone();
+        let rsp: VolumeSnapshotListResponse = host_rpc_call(
+            self,
+            LOCAL_RPC_ENDPOINT_VOLUME_MANAGER,
+            METHOD_VOLUME_SNAPSHOT_LIST,
+            args,
+            &self.get_config().host_query_retry,
+        )
+        .await?;
+        pagination.check(rsp.snapshots.len())?;
+
+        Ok(rsp)
+    }
+
+    async fn volume_read(&self, args: VolumeReadRequest) -> Result<VolumeReadResponse, Error> {
+        host_rpc_call(
+            self,
+            LOCAL_RPC_ENDPOINT_VOLUME_MANAGER,
+            METHOD_VOLUME_READ,
+            args,
+            &self.get_config().host_query_retry,
+        )
+        .await
+    }
+
+    async fn volume_write(&self, args: VolumeWriteRequest) -> Result<VolumeWriteResponse, Error> {
+        host_rpc_call(
+            self,
+            LOCAL_RPC_ENDPOINT_VOLUME_MANAGER,
+            METHOD_VOLUME_WRITE,
+            args,
+            &RetryPolicy::none(),
+        )
+        .await
+    }
+
+    async fn volume_usage(&self, args: VolumeUsageRequest) -> Result<VolumeUsage, Error> {
+        host_rpc_call(
+            self,
+            LOCAL_RPC_ENDPOINT_VOLUME_MANAGER,
+            METHOD_VOLUME_USAGE,
+            args,
+            &self.get_config().host_query_retry,
+        )
+        .await
+    }
+
+    async fn volume_blob_put(
+        &self,
+        args: VolumeBlobPutRequest,
+    ) -> Result<VolumeBlobPutResponse, Error> {
+        host_rpc_call(
+            self,
+            LOCAL_RPC_ENDPOINT_VOLUME_MANAGER,
+            METHOD_VOLUME_BLOB_PUT,
+            args,
+            &RetryPolicy::none(),
+        )
+        .await
+    }
+
+    async fn volume_blob_get(
+        &self,
+        args: VolumeBlobGetRequest,
+    ) -> Result<VolumeBlobGetResponse, Error> {
+        host_rpc_call(
+            self,
+            LOCAL_RPC_ENDPOINT_VOLUME_MANAGER,
+            METHOD_VOLUME_BLOB_GET,
+            args,
+            &self.get_config().host_query_retry,
+        )
+        .await
+    }
+
+    async fn volume_blob_pin(
+        &self,
+        args: VolumeBlobPinRequest,
+    ) -> Result<VolumeBlobPinResponse, Error> {
+        host_rpc_call(
+            self,
+            LOCAL_RPC_ENDPOINT_VOLUME_MANAGER,
+            METHOD_VOLUME_BLOB_PIN,
+            args,
+            &RetryPolicy::none(),
         )
         .await
     }
+
+    async fn volume_blob_unpin(
+        &self,
+        args: VolumeBlobPinRequest,
+    ) -> Result<VolumeBlobPinResponse, Error> {
+        host_rpc_call(
+            self,
+            LOCAL_RPC_ENDPOINT_VOLUME_MANAGER,
+            METHOD_VOLUME_BLOB_UNPIN,
+            args,
+            &RetryPolicy::none(),
+        )
+        .await
+    }
+}
+
+/// Layout of a volume.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, cbor::Encode, cbor::Decode)]
+#[repr(u8)]
+pub enum VolumeMode {
+    /// Files addressed by path.
+    #[default]
+    Files = 0,
+    /// Blobs addressed by the hash of their contents, with identical blobs stored once.
+    Blobs = 1,
 }
 
 /// Request to add a volume.
@@ -79,6 +348,13 @@ impl VolumeManager for Protocol {
 pub struct VolumeAddRequest {
     /// Labels to tag the volume with so it can later be found.
     pub labels: BTreeMap<String, String>,
+    /// Maximum number of bytes the volume may use, enforced by the host. Writes exceeding the
+    /// quota fail with `CODE_QUOTA_EXCEEDED`. If not specified, the host default applies.
+    #[cbor(optional)]
+    pub bytes_quota: Option<u64>,
+    /// Layout of the volume.
+    #[cbor(optional)]
+    pub mode: VolumeMode,
 }
 
 /// Response from the VolumeAdd method.
@@ -108,6 +384,9 @@ pub struct VolumeRemoveResponse {}
 pub struct VolumeListRequest {
     /// Labels to filter the volumes by.
     pub labels: BTreeMap<String, String>,
+    /// Page of volumes to return.
+    #[cbor(optional)]
+    pub pagination: Pagination,
 }
 
 /// Response from the VolumeList method.
@@ -115,6 +394,206 @@ pub struct VolumeListRequest {
 pub struct VolumeListResponse {
     #[cbor(optional)]
     pub volumes: Vec<VolumeInfo>,
+    /// Token for listing the next page of volumes, if there are more volumes.
+    #[cbor(optional)]
+    pub continuation_token: Option<Vec<u8>>,
+}
+
+/// Request to take a snapshot of a volume.
+///
+/// The `PermissionVolumeAdd` permission is required to call this method.
+#[derive(Clone, Debug, Default, cbor::Encode, cbor::Decode)]
+pub struct VolumeSnapshotRequest {
+    /// Identifier of the volume.
+    pub id: String,
+    /// Labels to tag the snapshot with so it can later be found.
+    #[cbor(optional)]
+    pub labels: BTreeMap<String, String>,
+}
+
+/// Response from the VolumeSnapshot method.
+#[derive(Clone, Debug, Default, cbor::Encode, cbor::Decode)]
+pub struct VolumeSnapshotResponse {
+    /// Unique snapshot identifier.
+    pub snapshot_id: String,
+}
+
+/// Request to restore a volume from a snapshot.
+///
+/// The `PermissionVolumeAdd` permission is required to call this method.
+#[derive(Clone, Debug, Default, cbor::Encode, cbor::Decode)]
+pub struct VolumeRestoreRequest {
+    /// Identifier of the volume.
+    pub id: String,
+    /// Identifier of a snapshot of the same volume.
+    pub snapshot_id: String,
+}
+
+/// Response from the VolumeRestore method.
+#[derive(Clone, Debug, Default, cbor::Encode, cbor::Decode)]
+pub struct VolumeRestoreResponse {}
+
+/// Request to list snapshots of a volume.
+///
+/// The `PermissionVolumeAdd` permission is required to call this method.
+#[derive(Clone, Debug, Default, cbor::Encode, cbor::Decode)]
+pub struct VolumeSnapshotListRequest {
+    /// Identifier of the volume.
+    pub id: String,
+    /// Page of snapshots to return.
+    #[cbor(optional)]
+    pub pagination: Pagination,
+}
+
+/// Response from the VolumeSnapshotList method.
+#[derive(Clone, Debug, Default, cbor::Encode, cbor::Decode)]
+pub struct VolumeSnapshotListResponse {
+    /// The resulting snapshots, oldest first.
+    #[cbor(optional)]
+    pub snapshots: Vec<SnapshotInfo>,
+    /// Token for listing the next page of snapshots, if there are more snapshots.
+    #[cbor(optional)]
+    pub continuation_token: Option<Vec<u8>>,
+}
+
+/// Request to read a file from a volume.
+///
+/// The `PermissionVolumeAdd` permission is required to call this method.
+#[derive(Clone, Debug, Default, cbor::Encode, cbor::Decode)]
+pub struct VolumeReadRequest {
+    /// Identifier of the volume.
+    pub id: String,
+    /// Path of the file, relative to the root of the volume.
+    pub path: String,
+    /// Offset of the first byte of the file to read.
+    #[cbor(optional)]
+    pub offset: u64,
+    /// Maximum number of bytes to read. In case it is not set, the file is read until its end.
+    #[cbor(optional)]
+    pub length: Option<u64>,
+}
+
+/// Response from the VolumeRead method.
+#[derive(Clone, Debug, Default, cbor::Encode, cbor::Decode)]
+pub struct VolumeReadResponse {
+    /// Contents of the file, if it exists.
+    #[cbor(optional)]
+    pub data: Option<Vec<u8>>,
+}
+
+/// Request to write a file to a volume.
+///
+/// The `PermissionVolumeAdd` permission is required to call this method.
+#[derive(Clone, Debug, Default, cbor::Encode, cbor::Decode)]
+pub struct VolumeWriteRequest {
+    /// Identifier of the volume.
+    pub id: String,
+    /// Path of the file, relative to the root of the volume.
+    pub path: String,
+    /// Contents of the file.
+    pub data: Vec<u8>,
+}
+
+/// Response from the VolumeWrite method.
+#[derive(Clone, Debug, Default, cbor::Encode, cbor::Decode)]
+pub struct VolumeWriteResponse {}
+
+/// Request to report the storage usage of a volume.
+///
+/// The `PermissionVolumeAdd` permission is required to call this method.
+#[derive(Clone, Debug, Default, cbor::Encode, cbor::Decode)]
+pub struct VolumeUsageRequest {
+    /// Identifier of the volume.
+    pub id: String,
+}
+
+/// Storage usage of a volume.
+#[derive(Clone, Debug, Default, PartialEq, Eq, cbor::Encode, cbor::Decode)]
+pub struct VolumeUsage {
+    /// Number of bytes used by the volume.
+    pub bytes_used: u64,
+    /// Maximum number of bytes the volume may use, if limited.
+    #[cbor(optional)]
+    pub bytes_quota: Option<u64>,
+    /// Number of files and directories in the volume.
+    pub inode_count: u64,
+}
+
+impl VolumeUsage {
+    /// Number of bytes that can still be written before the quota is exceeded, if limited.
+    pub fn bytes_remaining(&self) -> Option<u64> {
+        self.bytes_quota
+            .map(|quota| quota.saturating_sub(self.bytes_used))
+    }
+}
+
+/// Request to store a blob in a blob volume.
+///
+/// The `PermissionVolumeAdd` permission is required to call this method.
+#[derive(Clone, Debug, Default, cbor::Encode, cbor::Decode)]
+pub struct VolumeBlobPutRequest {
+    /// Identifier of the volume.
+    pub id: String,
+    /// Contents of the blob.
+    pub data: Vec<u8>,
+}
+
+/// Response from the VolumeBlobPut method.
+#[derive(Clone, Debug, Default, cbor::Encode, cbor::Decode)]
+pub struct VolumeBlobPutResponse {
+    /// Hash of the stored blob.
+    pub hash: Hash,
+    /// Whether an identical blob was already stored, so that no additional bytes were used.
+    #[cbor(optional)]
+    pub deduplicated: bool,
+}
+
+/// Request to fetch a blob from a blob volume.
+///
+/// The `PermissionVolumeAdd` permission is required to call this method.
+#[derive(Clone, Debug, Default, cbor::Encode, cbor::Decode)]
+pub struct VolumeBlobGetRequest {
+    /// Identifier of the volume.
+    pub id: String,
+    /// Hash of the blob.
+    pub hash: Hash,
+}
+
+/// Response from the VolumeBlobGet method.
+#[derive(Clone, Debug, Default, cbor::Encode, cbor::Decode)]
+pub struct VolumeBlobGetResponse {
+    /// Contents of the blob, if it is stored.
+    #[cbor(optional)]
+    pub data: Option<Vec<u8>>,
+}
+
+/// Request to pin or unpin a blob stored in a blob volume.
+///
+/// The `PermissionVolumeAdd` permission is required to call this method.
+#[derive(Clone, Debug, Default, cbor::Encode, cbor::Decode)]
+pub struct VolumeBlobPinRequest {
+    /// Identifier of the volume.
+    pub id: String,
+    /// Hash of the blob.
+    pub hash: Hash,
+}
+
+/// Response from the VolumeBlobPin and VolumeBlobUnpin methods.
+#[derive(Clone, Debug, Default, cbor::Encode, cbor::Decode)]
+pub struct VolumeBlobPinResponse {
+    /// Number of pins of the blob remaining after the call.
+    pub pins: u64,
+}
+
+/// Volume snapshot information.
+#[derive(Clone, Debug, Default, cbor::Encode, cbor::Decode)]
+pub struct SnapshotInfo {
+    /// Unique snapshot identifier.
+    pub id: String,
+    /// Identifier of the volume the snapshot was taken of.
+    pub volume_id: String,
+    /// Labels assigned to this snapshot.
+    pub labels: BTreeMap<String, String>,
 }
 
 /// Volume information.
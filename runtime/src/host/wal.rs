@@ -0,0 +1,430 @@
+//! Write-ahead intent log for runtime-originated external actions.
+//!
+//! Before performing a non-idempotent external action (e.g. submitting a transaction via the
+//! host or sending an HTTP POST), the runtime records its intent in the log and only removes it
+//! once the action has completed. In case the runtime crashes in between, the intent is still
+//! present after restart and can be replayed, so the application can determine whether the
+//! action took effect instead of silently losing or duplicating it.
+//!
+//! Intents are persisted by the host as volume labels, so they survive runtime restarts. As
+//! label values are limited in size, intents should carry compact descriptors (e.g. the hash
+//! or the encoded transaction) rather than large payloads.
+//!
+//! Each intent is sealed to the enclave together with the name of its log and its sequence
+//! number, so that the host can't forge, change or reorder intents. The host can still drop
+//! intents, which is indistinguishable from the actions having completed.
+use std::{collections::BTreeMap, future::Future, sync::Arc};
+
+use async_trait::async_trait;
+use rustc_hex::{FromHex, ToHex};
+use sgx_isa::Keypolicy;
+use thiserror::Error;
+use tokio::sync::Mutex;
+
+use crate::{
+    common::{
+        pagination::{Pages, Pagination},
+        sgx::seal,
+    },
+    shutdown::Flush,
+};
+
+use super::{
+    volume_manager::{
+        VolumeAddRequest, VolumeInfo, VolumeListRequest, VolumeManager, VolumeRemoveRequest,
+    },
+    Error as HostError,
+};
+
+/// Label identifying the intent log a volume belongs to.
+pub const LABEL_WAL: &str = "net.oasis.wal";
+/// Label holding the intent sequence number.
+const LABEL_WAL_SEQ: &str = "net.oasis.wal.seq";
+/// Label holding the hex-encoded sealed intent.
+const LABEL_WAL_RECORD: &str = "net.oasis.wal.record";
+
+/// Domain separation context of sealed intents.
+const WAL_SEAL_CONTEXT: &[u8] = b"oasis-core/runtime: write-ahead intent";
+
+/// Intent log errors.
+#[derive(Error, Debug)]
+pub enum WalError {
+    #[error("host error: {0}")]
+    Host(#[from] HostError),
+
+    #[error("malformed intent: {0}")]
+    Malformed(String),
+
+    #[error("intent not authenticated")]
+    Unauthenticated,
+
+    #[error("action failed: {0}")]
+    Action(#[source] anyhow::Error),
+}
+
+/// A recorded intent to perform an external action.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Intent {
+    /// Sequence number of the intent, increasing in the order intents are recorded.
+    pub seq: u64,
+    /// Application-defined kind of the action.
+    pub kind: String,
+    /// Application-defined payload describing the action.
+    pub payload: Vec<u8>,
+}
+
+/// Intent as sealed to the enclave, bound to the log it was recorded in.
+#[derive(cbor::Encode, cbor::Decode)]
+struct SealedIntent {
+    log: String,
+    seq: u64,
+    kind: String,
+    payload: Vec<u8>,
+}
+
+impl Intent {
+    fn seal(&self, log: &str) -> String {
+        let sealed = SealedIntent {
+            log: log.to_string(),
+            seq: self.seq,
+            kind: self.kind.clone(),
+            payload: self.payload.clone(),
+        };
+        seal::seal(
+            Keypolicy::MRENCLAVE,
+            WAL_SEAL_CONTEXT,
+            &cbor::to_vec(sealed),
+        )
+        .to_hex()
+    }
+
+    fn from_volume(log: &str, volume: &VolumeInfo) -> Result<Self, WalError> {
+        let label = |name: &str| {
+            volume
+                .labels
+                .get(name)
+                .ok_or_else(|| WalError::Malformed(format!("missing label {name}")))
+        };
+
+        let seq: u64 = label(LABEL_WAL_SEQ)?
+            .parse()
+            .map_err(|_| WalError::Malformed("bad sequence number".to_string()))?;
+        let sealed: Vec<u8> = label(LABEL_WAL_RECORD)?
+            .from_hex()
+            .map_err(|_| WalError::Malformed("bad record".to_string()))?;
+        let raw = seal::unseal(Keypolicy::MRENCLAVE, WAL_SEAL_CONTEXT, &sealed)
+            .ok()
+            .flatten()
+            .ok_or(WalError::Unauthenticated)?;
+        let intent: SealedIntent = cbor::from_slice(&raw).map_err(|_| WalError::Unauthenticated)?;
+        // The labels locating the intent must match the sealed ones, so that intents can't be
+        // moved between logs or reordered.
+        if intent.log != log || intent.seq != seq {
+            return Err(WalError::Unauthenticated);
+        }
+
+        Ok(Self {
+            seq,
+            kind: intent.kind,
+            payload: intent.payload,
+        })
+    }
+}
+
+/// Write-ahead intent log backed by the host volume manager.
+pub struct IntentLog {
+    name: String,
+    volume_manager: Arc<dyn VolumeManager>,
+    /// Next sequence number, lazily initialized from the persisted intents.
+    next_seq: Mutex<Option<u64>>,
+}
+
+impl IntentLog {
+    /// Create a new intent log with the given name.
+    ///
+    /// Logs with different names are independent of each other. Using the same name after a
+    /// restart gives access to intents recorded before the restart.
+    pub fn new(name: &str, volume_manager: Arc<dyn VolumeManager>) -> Self {
+        Self {
+            name: name.to_string(),
+            volume_manager,
+            next_seq: Mutex::new(None),
+        }
+    }
+
+    /// Record an intent to perform an action.
+    ///
+    /// The intent is persisted before this method returns, so the action may be performed
+    /// afterwards. Once the action completes, `complete` must be called.
+    pub async fn record(&self, kind: &str, payload: &[u8]) -> Result<Intent, WalError> {
+        let mut next_seq = self.next_seq.lock().await;
+        let seq = match *next_seq {
+            Some(seq) => seq,
+            None => self
+                .list()
+                .await?
+                .last()
+                .map(|intent| intent.seq + 1)
+                .unwrap_or_default(),
+        };
+
+        let intent = Intent {
+            seq,
+            kind: kind.to_string(),
+            payload: payload.to_vec(),
+        };
+        let mut labels = self.intent_labels(seq);
+        labels.insert(LABEL_WAL_RECORD.to_string(), intent.seal(&self.name));
+
+        self.volume_manager
+            .volume_add(VolumeAddRequest {
+                labels,
+                ..Default::default()
+            })
+            .await?;
+        *next_seq = Some(seq + 1);
+
+        Ok(intent)
+    }
+
+    /// Mark the intent as completed, removing it from the log.
+    pub async fn complete(&self, intent: &Intent) -> Result<(), WalError> {
+        self.volume_manager
+            .volume_remove(VolumeRemoveRequest {
+                labels: self.intent_labels(intent.seq),
+            })
+            .await?;
+
+        Ok(())
+    }
+
+    /// Return all intents which have been recorded but not completed, in recording order.
+    ///
+    /// Fails in case any persisted intent has been tampered with.
+    pub async fn pending(&self) -> Result<Vec<Intent>, WalError> {
+        self.list().await
+    }
+
+    /// Record an intent, perform the action and mark the intent as completed.
+    ///
+    /// In case the action fails, it is unknown whether it took effect, so the intent is kept
+    /// and will be returned on replay.
+    pub async fn perform<F, Fut, T>(
+        &self,
+        kind: &str,
+        payload: &[u8],
+        action: F,
+    ) -> Result<T, WalError>
+    where
+        F: FnOnce(Intent) -> Fut,
+        Fut: Future<Output = anyhow::Result<T>>,
+    {
+        let intent = self.record(kind, payload).await?;
+        let result = action(intent.clone()).await.map_err(WalError::Action)?;
+        self.complete(&intent).await?;
+
+        Ok(result)
+    }
+
+    /// Replay all pending intents in recording order.
+    ///
+    /// The handler is expected to determine whether the action took effect and to perform it
+    /// again if not. Intents for which the handler succeeds are marked as completed. Replay
+    /// stops at the first failing intent so that ordering between actions is preserved.
+    pub async fn replay<F, Fut>(&self, mut handler: F) -> Result<usize, WalError>
+    where
+        F: FnMut(Intent) -> Fut,
+        Fut: Future<Output = anyhow::Result<()>>,
+    {
+        let intents = self.pending().await?;
+        let count = intents.len();
+        for intent in intents {
+            handler(intent.clone()).await.map_err(WalError::Action)?;
+            self.complete(&intent).await?;
+        }
+
+        Ok(count)
+    }
+
+    async fn list(&self) -> Result<Vec<Intent>, WalError> {
+        let mut labels = BTreeMap::new();
+        labels.insert(LABEL_WAL.to_string(), self.name.clone());
+
+        let mut intents = vec![];
+        let mut pages = Pages::new(Pagination::default());
+        while let Some(page) = pages.next_page() {
+            let response = self
+                .volume_manager
+                .volume_list(VolumeListRequest {
+                    labels: labels.clone(),
+                    pagination: page.clone(),
+                })
+                .await?;
+            for volume in &response.volumes {
+                intents.push(Intent::from_volume(&self.name, volume)?);
+            }
+            pages
+                .advance(response.continuation_token)
+                .map_err(HostError::from)?;
+        }
+        intents.sort_by_key(|intent| intent.seq);
+
+        Ok(intents)
+    }
+
+    fn intent_labels(&self, seq: u64) -> BTreeMap<String, String> {
+        let mut labels = BTreeMap::new();
+        labels.insert(LABEL_WAL.to_string(), self.name.clone());
+        labels.insert(LABEL_WAL_SEQ.to_string(), seq.to_string());
+        labels
+    }
+}
+
+/// Intents are persisted before recording returns, so flushing waits for in-flight recordings.
+#[async_trait]
+impl Flush for IntentLog {
+    async fn flush(&self) -> anyhow::Result<()> {
+        drop(self.next_seq.lock().await);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use anyhow::anyhow;
+    use futures::executor::block_on;
+
+    use super::*;
+    use crate::testkit::host::MockHost;
+
+    #[test]
+    fn test_intent_log() {
+        let volumes = Arc::new(MockHost::new());
+
+        block_on(async {
+            let wal = IntentLog::new("test", volumes.clone());
+            let other = IntentLog::new("other", volumes.clone());
+
+            // Completed actions leave no intents behind.
+            let result = wal
+                .perform("submit", b"tx1", |_| async { Ok(42) })
+                .await
+                .unwrap();
+            assert_eq!(result, 42);
+            assert!(wal.pending().await.unwrap().is_empty());
+
+            // Failed actions and actions interrupted by a crash are kept.
+            let result = wal
+                .perform("submit", b"tx2", |_| async {
+                    Err::<(), _>(anyhow!("timeout"))
+                })
+                .await;
+            assert!(matches!(result, Err(WalError::Action(_))));
+            wal.record("post", b"req3").await.unwrap();
+            other.record("submit", b"unrelated").await.unwrap();
+        });
+
+        // After a restart, pending intents are replayed in order.
+        block_on(async {
+            let wal = IntentLog::new("test", volumes.clone());
+            let pending = wal.pending().await.unwrap();
+            assert_eq!(
+                pending
+                    .iter()
+                    .map(|intent| (intent.kind.as_str(), intent.payload.as_slice()))
+                    .collect::<Vec<_>>(),
+                vec![("submit", b"tx2".as_slice()), ("post", b"req3".as_slice())],
+            );
+            assert!(pending[0].seq < pending[1].seq);
+
+            // New intents are ordered after the recovered ones.
+            let intent = wal.record("post", b"req4").await.unwrap();
+            assert!(intent.seq > pending[1].seq);
+            wal.complete(&intent).await.unwrap();
+
+            // Replay stops at the first failure.
+            let mut replayed = vec![];
+            let result = wal
+                .replay(|intent| {
+                    replayed.push(intent.payload.clone());
+                    async move {
+                        match intent.kind.as_str() {
+                            "submit" => Ok(()),
+                            _ => Err(anyhow!("not yet")),
+                        }
+                    }
+                })
+                .await;
+            assert!(result.is_err());
+            assert_eq!(replayed, vec![b"tx2".to_vec(), b"req3".to_vec()]);
+
+            let count = wal.replay(|_| async { Ok(()) }).await.unwrap();
+            assert_eq!(count, 1);
+            assert!(wal.pending().await.unwrap().is_empty());
+
+            // Other logs are not affected.
+            let other = IntentLog::new("other", volumes.clone());
+            assert_eq!(other.pending().await.unwrap().len(), 1);
+        });
+    }
+
+    #[test]
+    fn test_intent_log_tampering() {
+        let volumes = Arc::new(MockHost::new());
+        block_on(async {
+            let wal = IntentLog::new("test", volumes.clone());
+            wal.record("submit", b"tx1").await.unwrap();
+            wal.record("submit", b"tx2").await.unwrap();
+        });
+
+        // Tamper with the persisted intents, returning what the given log sees afterwards.
+        let tamper = |log: &str, f: &dyn Fn(&mut Vec<VolumeInfo>)| {
+            let original = volumes.volumes();
+            let mut tampered = original.clone();
+            f(&mut tampered);
+            volumes.set_volumes(tampered);
+            let result = block_on(IntentLog::new(log, volumes.clone()).pending());
+            volumes.set_volumes(original);
+            result
+        };
+
+        // Changed records are rejected.
+        let result = tamper("test", &|volumes| {
+            let record = volumes[0].labels.get_mut(LABEL_WAL_RECORD).unwrap();
+            let last = if record.ends_with('0') { "1" } else { "0" };
+            record.replace_range(record.len() - 1.., last);
+        });
+        assert!(matches!(result, Err(WalError::Unauthenticated)));
+
+        // Forged records are rejected.
+        let result = tamper("test", &|volumes| {
+            volumes[0]
+                .labels
+                .insert(LABEL_WAL_RECORD.to_string(), b"forged".to_hex());
+        });
+        assert!(matches!(result, Err(WalError::Unauthenticated)));
+
+        // Reordered records are rejected.
+        let result = tamper("test", &|volumes| {
+            let first = volumes[0].labels[LABEL_WAL_SEQ].clone();
+            let second = volumes[1].labels[LABEL_WAL_SEQ].clone();
+            volumes[0].labels.insert(LABEL_WAL_SEQ.to_string(), second);
+            volumes[1].labels.insert(LABEL_WAL_SEQ.to_string(), first);
+        });
+        assert!(matches!(result, Err(WalError::Unauthenticated)));
+
+        // Records moved to another log are rejected.
+        let result = tamper("other", &|volumes| {
+            let mut moved = volumes[0].clone();
+            moved
+                .labels
+                .insert(LABEL_WAL.to_string(), "other".to_string());
+            volumes.push(moved);
+        });
+        assert!(matches!(result, Err(WalError::Unauthenticated)));
+
+        // Untampered records verify.
+        let pending = block_on(IntentLog::new("test", volumes.clone()).pending()).unwrap();
+        assert_eq!(pending.len(), 2);
+    }
+}
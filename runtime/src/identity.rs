@@ -6,7 +6,7 @@ use std::{
 
 use anyhow::Result;
 use base64::prelude::*;
-use rand::{rngs::OsRng, Rng};
+use rand::Rng;
 use sgx_isa::Targetinfo;
 use thiserror::Error;
 use tiny_keccak::{Hasher, TupleHash};
@@ -16,6 +16,7 @@ use crate::{
         crypto::{
             hash::Hash,
             mrae::deoxysii::{self, Opener},
+            rng::SecureRng,
             signature::{self, Signature, Signer},
             x25519,
         },
@@ -26,6 +27,8 @@ use crate::{
     TeeType, BUILD_INFO,
 };
 
+pub mod tls;
+
 /// Context used for computing the RAK digest.
 const RAK_HASH_CONTEXT: &[u8] = b"oasis-core/node: TEE RAK binding";
 /// Context used for deriving the nonce used in quotes.
@@ -78,6 +81,7 @@ struct Inner {
     endorsed_capability_tee: Option<EndorsedCapabilityTEE>,
     target_info: Option<Targetinfo>,
     nonce: Option<[u8; 32]>,
+    restricted: bool,
 }
 
 /// Runtime identity.
@@ -134,6 +138,7 @@ impl Identity {
                 endorsed_capability_tee: None,
                 target_info: None,
                 nonce: None,
+                restricted: false,
             }),
         }
     }
@@ -149,7 +154,7 @@ impl Identity {
     /// Generate a random 256-bit nonce, for anti-replay.
     fn generate_nonce() -> [u8; 32] {
         let mut nonce_bytes = [0u8; 32];
-        OsRng.fill(&mut nonce_bytes);
+        SecureRng.fill(&mut nonce_bytes);
 
         let mut h = TupleHash::v256(QUOTE_NONCE_CONTEXT);
         h.update(&nonce_bytes);
@@ -229,10 +234,16 @@ impl Identity {
             }
             #[cfg(feature = "tdx")]
             TeeType::Tdx => {
-                // In TDX we can immediately generate a quote. Do it and return it as a "report".
-                let quote = crate::common::tdx::report::get_quote(&report_data)?;
+                // In TDX we can usually immediately generate a quote. Do it and return it as a
+                // "report". In case the ConfigFS TSM report subsystem is not available, return the
+                // raw TD report instead so that the host generates the quote via its quote
+                // generation service. The quote is then verified once configured, as for SGX.
+                let report = match crate::common::tdx::report::get_quote(&report_data) {
+                    Ok(quote) => quote,
+                    Err(_) => crate::common::tdx::report::get_raw_report(&report_data)?,
+                };
 
-                (rak_pub, rek_pub, quote, String::new())
+                (rak_pub, rek_pub, report, String::new())
             }
             _ => panic!("init_report called outside TEE environment"),
         };
@@ -245,11 +256,16 @@ impl Identity {
     }
 
     /// Configure the remote attestation quote for RAK.
+    ///
+    /// In audit mode, quotes violating the policy in ways that are acceptable for auditing are
+    /// accepted and the identity enters restricted mode. Returns the verified quote together
+    /// with the policy violations.
     pub(crate) fn set_quote(
         &self,
         node_id: signature::PublicKey,
         quote: Quote,
-    ) -> Result<VerifiedQuote> {
+        audit_mode: bool,
+    ) -> Result<(VerifiedQuote, Vec<String>)> {
         let rak_pub = self.public_rak();
 
         let mut inner = self.inner.write().unwrap();
@@ -270,7 +286,11 @@ impl Identity {
             .quote_policy
             .as_ref()
             .ok_or(QuoteError::QuotePolicyNotSet)?;
-        let verified_quote = quote.verify(policy)?;
+        let (verified_quote, violations) = if audit_mode {
+            quote.verify_audit(policy)?
+        } else {
+            (quote.verify(policy)?, Vec::new())
+        };
         let nonce = &verified_quote.report_data[32..];
         if expected_nonce.as_ref() != nonce {
             return Err(QuoteError::NonceMismatch.into());
@@ -296,7 +316,7 @@ impl Identity {
         if inner.quote.is_some() {
             let existing_timestamp = inner.quote_timestamp.unwrap();
             if existing_timestamp > verified_quote.timestamp {
-                return Ok(verified_quote);
+                return Ok((verified_quote, violations));
             }
         }
 
@@ -312,6 +332,7 @@ impl Identity {
         let quote = Arc::new(quote);
         inner.quote = Some(quote.clone());
         inner.quote_timestamp = Some(verified_quote.timestamp);
+        inner.restricted = !violations.is_empty();
 
         // Keep around last two valid quotes to allow for transition as node registration does not
         // happen immediately after a quote has been verified by the runtime.
@@ -320,7 +341,36 @@ impl Identity {
             inner.known_quotes.pop_front();
         }
 
-        Ok(verified_quote)
+        Ok((verified_quote, violations))
+    }
+
+    /// Configure a mock remote attestation quote binding the RAK to the given enclave identity,
+    /// returning it together with the verified quote that it stands for.
+    ///
+    /// The mock quote doesn't verify, so components verifying quotes must be tested with a
+    /// verifier returning the given verified quote instead.
+    #[cfg(test)]
+    pub(crate) fn set_mock_quote(&self, identity: EnclaveIdentity) -> (Quote, VerifiedQuote) {
+        let rak = self.public_rak();
+        let quote = Quote::Ias(sgx::ias::AVR {
+            body: rak.as_ref().to_vec(),
+            ..Default::default()
+        });
+        let mut report_data = Self::report_body_for_rak(&rak).as_ref().to_vec();
+        report_data.extend_from_slice(&[0; 32]);
+        let verified_quote = VerifiedQuote {
+            report_data,
+            identity,
+            timestamp: insecure_posix_time(),
+        };
+
+        let mut inner = self.inner.write().unwrap();
+        let quote_arc = Arc::new(quote.clone());
+        inner.quote = Some(quote_arc.clone());
+        inner.quote_timestamp = Some(verified_quote.timestamp);
+        inner.known_quotes.push_back(quote_arc);
+
+        (quote, verified_quote)
     }
 
     /// Configure the runtime quote policy.
@@ -362,6 +412,15 @@ impl Identity {
         inner.endorsed_capability_tee.clone()
     }
 
+    /// Whether the identity is in restricted mode.
+    ///
+    /// This is the case when the current quote has only been accepted in audit mode. Restricted
+    /// runtimes must not modify state or release keys.
+    pub fn is_restricted(&self) -> bool {
+        let inner = self.inner.read().unwrap();
+        inner.restricted
+    }
+
     /// Host node identity public key.
     pub fn node_identity(&self) -> Option<signature::PublicKey> {
         let inner = self.inner.read().unwrap();
@@ -413,6 +472,18 @@ impl Identity {
         inner.quote.clone()
     }
 
+    /// Time after which the quote for RAK is no longer considered fresh.
+    ///
+    /// This method returns `None` in the same cases as `quote`.
+    pub fn quote_expiration(&self) -> Option<i64> {
+        let quote = self.quote()?;
+        let inner = self.inner.read().unwrap();
+        let timestamp = inner.quote_timestamp?;
+        let quote_policy = inner.quote_policy.as_ref()?;
+
+        Some(quote.expiration(timestamp, quote_policy))
+    }
+
     /// Runtime quote policy.
     ///
     /// This method may return `None` in the case where the enclave is not
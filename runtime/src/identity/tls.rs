@@ -0,0 +1,326 @@
+//! Attested TLS certificates.
+//!
+//! Off-chain components of the runtime may terminate TLS connections originating outside of the
+//! network (e.g. from browsers or external services) directly in the enclave. In order for
+//! clients to verify that they are connected to a genuine enclave (RA-TLS), the enclave issues
+//! self-signed certificates for freshly generated TLS keys, which are bound to the RAK by a RAK
+//! signature over the public key. The binding and the quote for RAK are embedded in a certificate
+//! extension, so that the certificate can be verified without any external tooling.
+use std::{sync::Arc, time::Duration};
+
+use anyhow::Result;
+use chrono::prelude::*;
+use mbedtls::{
+    hash::Type as MdType,
+    pk::{EcGroupId, Pk},
+    x509::{certificate::Builder, KeyUsage, Time},
+};
+use rand::Rng;
+use thiserror::Error;
+
+use super::Identity;
+use crate::{
+    common::{
+        crypto::{
+            rng::SecureRng,
+            signature::{PublicKey, Signature, Signer},
+        },
+        sgx::{Quote, QuotePolicy, VerifiedQuote},
+        time::insecure_posix_time,
+    },
+    transport::rng,
+};
+
+/// Context used for binding TLS keys to RAK.
+const TLS_BINDING_CONTEXT: &[u8] = b"oasis-core/runtime: TLS key binding";
+
+/// OID of the certificate extension holding the [`TlsKeyBinding`], in its DER encoding without
+/// the tag and length.
+///
+/// The OID is derived from a UUID: 2.25.301729552483902366255289184932684445013.
+pub const TLS_BINDING_OID: &[u8] = &[
+    0x69, 0x83, 0xc5, 0xfe, 0xff, 0xdf, 0xcf, 0x85, 0xca, 0x96, 0xed, 0x98, 0xef, 0xc6, 0x92, 0xd0,
+    0xb3, 0xa4, 0xea, 0x55,
+];
+
+/// Characters which would need to be escaped in the subject name.
+const RESERVED_NAME_CHARS: &[char] = &[',', '=', '+', '\\', '"', '<', '>', ';', '\0'];
+
+/// Attested TLS certificate errors.
+#[derive(Error, Debug)]
+pub enum TlsCertificateError {
+    #[error("quote not available")]
+    QuoteNotAvailable,
+
+    #[error("invalid common name: {0:?}")]
+    InvalidCommonName(String),
+
+    #[error("invalid validity period")]
+    InvalidValidity,
+
+    #[error("signing failed: {0}")]
+    Signing(#[source] anyhow::Error),
+
+    #[error("tls error: {0}")]
+    Tls(#[source] anyhow::Error),
+}
+
+impl From<mbedtls::Error> for TlsCertificateError {
+    fn from(err: mbedtls::Error) -> Self {
+        TlsCertificateError::Tls(err.into())
+    }
+}
+
+/// Binding of a TLS key to RAK.
+#[derive(Clone, Debug, cbor::Encode, cbor::Decode)]
+pub struct TlsKeyBinding {
+    /// Public part of RAK.
+    pub rak_pub: PublicKey,
+    /// Signature from RAK over the DER-encoded public key of the TLS key.
+    pub binding: Signature,
+    /// Quote for RAK.
+    pub quote: Quote,
+}
+
+impl TlsKeyBinding {
+    /// Verify that the given DER-encoded public key, taken from the certificate holding the
+    /// binding, is bound to an attested RAK.
+    pub fn verify(&self, public_key: &[u8], policy: &QuotePolicy) -> Result<VerifiedQuote> {
+        self.verify_with(public_key, policy, &Quote::verify)
+    }
+
+    fn verify_with(
+        &self,
+        public_key: &[u8],
+        policy: &QuotePolicy,
+        verifier: &dyn Fn(&Quote, &QuotePolicy) -> Result<VerifiedQuote>,
+    ) -> Result<VerifiedQuote> {
+        let verified_quote = verifier(&self.quote, policy)?;
+        Identity::verify_binding(&verified_quote, &self.rak_pub)?;
+        self.binding
+            .verify(&self.rak_pub, TLS_BINDING_CONTEXT, public_key)?;
+
+        Ok(verified_quote)
+    }
+}
+
+/// Options of an attested TLS certificate.
+#[derive(Clone, Debug)]
+pub struct CertificateOptions {
+    /// Common name of the certificate subject (e.g. the domain of the service).
+    pub common_name: String,
+    /// The maximum validity period. Certificates expire no later than the quote for RAK.
+    pub validity: Duration,
+}
+
+/// Attested TLS certificate.
+pub struct AttestedCertificate {
+    /// DER-encoded self-signed X.509 certificate.
+    pub certificate: Vec<u8>,
+    /// DER-encoded private key of the certificate.
+    pub private_key: Vec<u8>,
+    /// Time after which the certificate expires.
+    pub not_after: i64,
+}
+
+/// Convert the given POSIX time to a certificate validity time.
+fn x509_time(timestamp: i64) -> Result<Time, TlsCertificateError> {
+    let time = Utc
+        .timestamp_opt(timestamp, 0)
+        .single()
+        .ok_or(TlsCertificateError::InvalidValidity)?;
+    Time::new(
+        time.year() as u16,
+        time.month() as u8,
+        time.day() as u8,
+        time.hour() as u8,
+        time.minute() as u8,
+        time.second() as u8,
+    )
+    .ok_or(TlsCertificateError::InvalidValidity)
+}
+
+impl Identity {
+    /// Issue a self-signed TLS certificate for a freshly generated key bound to RAK, embedding
+    /// the binding and the quote for RAK in the [`TLS_BINDING_OID`] extension.
+    ///
+    /// Certificates can only be issued while the quote for RAK is fresh.
+    pub fn issue_tls_certificate(
+        &self,
+        opts: &CertificateOptions,
+    ) -> Result<AttestedCertificate, TlsCertificateError> {
+        if opts.common_name.is_empty() || opts.common_name.contains(RESERVED_NAME_CHARS) {
+            return Err(TlsCertificateError::InvalidCommonName(
+                opts.common_name.clone(),
+            ));
+        }
+        let quote = self.quote().ok_or(TlsCertificateError::QuoteNotAvailable)?;
+        let quote_expiration = self
+            .quote_expiration()
+            .ok_or(TlsCertificateError::QuoteNotAvailable)?;
+
+        let not_before = insecure_posix_time();
+        let validity: i64 = opts
+            .validity
+            .as_secs()
+            .try_into()
+            .map_err(|_| TlsCertificateError::InvalidValidity)?;
+        let not_after = not_before.saturating_add(validity).min(quote_expiration);
+        if not_after <= not_before {
+            return Err(TlsCertificateError::InvalidValidity);
+        }
+
+        let mut rng = rng().map_err(TlsCertificateError::Tls)?;
+        let rng = Arc::get_mut(&mut rng).expect("rng must not be shared");
+        let mut key = Pk::generate_ec(rng, EcGroupId::SecP256R1)?;
+        let private_key = key.write_private_der_vec()?;
+        let mut issuer_key = Pk::from_private_key(&private_key, None)?;
+        let public_key = key.write_public_der_vec()?;
+
+        let binding = TlsKeyBinding {
+            rak_pub: self.public_rak(),
+            binding: self
+                .sign(TLS_BINDING_CONTEXT, &public_key)
+                .map_err(TlsCertificateError::Signing)?,
+            quote: (*quote).clone(),
+        };
+
+        // Serial numbers must be positive.
+        let mut serial = [0u8; 16];
+        SecureRng.fill(&mut serial);
+        serial[0] &= 0x7f;
+
+        let name = format!("CN={}\0", opts.common_name);
+        let certificate = Builder::new()
+            .subject_key(&mut key)
+            .subject_with_nul(&name)?
+            .issuer_key(&mut issuer_key)
+            .issuer_with_nul(&name)?
+            .validity(x509_time(not_before)?, x509_time(not_after)?)?
+            .serial(&serial)?
+            .signature_hash(MdType::Sha256)
+            .basic_constraints(false, None)?
+            .key_usage(KeyUsage::DIGITAL_SIGNATURE | KeyUsage::KEY_AGREEMENT)?
+            .extension(TLS_BINDING_OID, &cbor::to_vec(binding), false)?
+            .write_der_vec(rng)?;
+
+        Ok(AttestedCertificate {
+            certificate,
+            private_key,
+            not_after,
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use mbedtls::x509::certificate::Certificate;
+
+    use super::*;
+    use crate::common::sgx::{EnclaveIdentity, MrEnclave};
+
+    /// Extract the value of the [`TLS_BINDING_OID`] extension from the given DER-encoded
+    /// certificate.
+    fn binding_extension(certificate: &[u8]) -> &[u8] {
+        // The OID arcs don't fit into 64 bits, so the extension is located by its encoding which
+        // is followed by the OCTET STRING holding the value (non-critical extensions omit the
+        // critical flag).
+        let oid = [&[0x06, TLS_BINDING_OID.len() as u8][..], TLS_BINDING_OID].concat();
+        let start = certificate
+            .windows(oid.len())
+            .position(|window| window == oid)
+            .expect("certificate should contain the binding extension")
+            + oid.len();
+        let value = &certificate[start..];
+        assert_eq!(value[0], 0x04, "extension value should be an OCTET STRING");
+        let (len, offset) = match value[1] {
+            len if len < 0x80 => (len as usize, 2),
+            0x81 => (value[2] as usize, 3),
+            0x82 => (u16::from_be_bytes([value[2], value[3]]) as usize, 4),
+            _ => panic!("extension value should be shorter than 64 KiB"),
+        };
+        &value[offset..offset + len]
+    }
+
+    #[test]
+    fn test_tls_key_binding() {
+        let identity = Identity::new();
+        let policy = QuotePolicy::default();
+        identity.set_quote_policy(policy.clone()).unwrap();
+        let enclave = EnclaveIdentity::fortanix_test(MrEnclave::from([1u8; 32].to_vec()));
+        let (quote, verified_quote) = identity.set_mock_quote(enclave);
+        let verifier =
+            |_: &Quote, _: &QuotePolicy| -> Result<VerifiedQuote> { Ok(verified_quote.clone()) };
+
+        let opts = CertificateOptions {
+            common_name: "example.com".to_string(),
+            validity: Duration::from_secs(3600),
+        };
+        let attested = identity.issue_tls_certificate(&opts).unwrap();
+        let mut certificate = Certificate::from_der(&attested.certificate).unwrap();
+        let public_key = certificate.public_key_mut().write_public_der_vec().unwrap();
+        let mut private_key = Pk::from_private_key(&attested.private_key, None).unwrap();
+        assert_eq!(public_key, private_key.write_public_der_vec().unwrap());
+
+        let binding: TlsKeyBinding =
+            cbor::from_slice(binding_extension(&attested.certificate)).unwrap();
+        assert_eq!(binding.rak_pub, identity.public_rak());
+        assert_eq!(binding.quote, quote);
+        let result = binding
+            .verify_with(&public_key, &policy, &verifier)
+            .unwrap();
+        assert_eq!(result.identity, verified_quote.identity);
+
+        // The binding must not verify for a different key.
+        let other = identity.issue_tls_certificate(&opts).unwrap();
+        let mut other_certificate = Certificate::from_der(&other.certificate).unwrap();
+        let other_key = other_certificate
+            .public_key_mut()
+            .write_public_der_vec()
+            .unwrap();
+        assert_ne!(other_key, public_key);
+        assert!(binding.verify_with(&other_key, &policy, &verifier).is_err());
+
+        // The binding must not verify for a quote for a different RAK.
+        let (_, other_quote) = Identity::new().set_mock_quote(verified_quote.identity.clone());
+        let verifier =
+            |_: &Quote, _: &QuotePolicy| -> Result<VerifiedQuote> { Ok(other_quote.clone()) };
+        assert!(binding
+            .verify_with(&public_key, &policy, &verifier)
+            .is_err());
+    }
+
+    #[test]
+    fn test_issue_tls_certificate() {
+        let identity = Identity::new();
+
+        // The common name must not need escaping.
+        let opts = CertificateOptions {
+            common_name: "example.com, O=evil".to_string(),
+            validity: Duration::from_secs(3600),
+        };
+        assert!(matches!(
+            identity.issue_tls_certificate(&opts),
+            Err(TlsCertificateError::InvalidCommonName(_))
+        ));
+
+        // Certificates can't be issued without a quote.
+        let opts = CertificateOptions {
+            common_name: "example.com".to_string(),
+            ..opts
+        };
+        assert!(matches!(
+            identity.issue_tls_certificate(&opts),
+            Err(TlsCertificateError::QuoteNotAvailable)
+        ));
+    }
+
+    #[test]
+    fn test_x509_time() {
+        assert!(x509_time(1_700_000_000).is_ok());
+        assert!(matches!(
+            x509_time(i64::MAX),
+            Err(TlsCertificateError::InvalidValidity)
+        ));
+    }
+}
@@ -1,17 +1,20 @@
 //! Runtime initialization.
 use std::sync::Arc;
 
-use anyhow::Result;
 use slog::{error, info};
 
 use crate::{
-    common::logger::{get_logger, init_logger},
+    common::{
+        logger::{get_logger, init_logger},
+        sgx::{pcs::CollateralCache, seal::set_seal_cipher_suite},
+        time::set_clock_skew_tolerance,
+    },
     config::Config,
     dispatcher::{Dispatcher, Initializer},
     future::new_tokio_runtime,
     identity::Identity,
-    protocol::{Protocol, Stream},
-    TeeType, BUILD_INFO,
+    protocol::Protocol,
+    transport,
 };
 
 /// Starts the runtime.
@@ -23,10 +26,21 @@ pub fn start_runtime(initializer: Box<dyn Initializer>, config: Config) {
 
     // Perform TDX-specific early initialization.
     #[cfg(feature = "tdx")]
-    if BUILD_INFO.tee_type == TeeType::Tdx {
+    if crate::BUILD_INFO.tee_type == crate::TeeType::Tdx {
         crate::common::tdx::init::init();
     }
 
+    // Configure the tolerated clock skew before any time-based validity checks are made.
+    if let Some(tolerance) = config.clock_skew_tolerance {
+        set_clock_skew_tolerance(tolerance);
+    }
+    CollateralCache::global().configure(config.attestation_collateral.clone());
+
+    // Configure the cipher suite before any secrets are sealed.
+    if let Some(suite) = config.seal_cipher_suite {
+        set_seal_cipher_suite(suite);
+    }
+
     // Initialize runtime identity with runtime attestation key and runtime encryption key.
     let identity = Arc::new(Identity::new());
 
@@ -40,8 +54,8 @@ pub fn start_runtime(initializer: Box<dyn Initializer>, config: Config) {
     // Connect to the runtime host.
     info!(logger, "Establishing connection with the worker host");
 
-    let stream = match connect() {
-        Ok(stream) => stream,
+    let transport = match transport::connect(&config.host_transport) {
+        Ok(transport) => transport,
         Err(err) => {
             error!(logger, "Failed to connect with the worker host"; "err" => %err);
             return;
@@ -51,7 +65,7 @@ pub fn start_runtime(initializer: Box<dyn Initializer>, config: Config) {
     // Initialize the protocol handler loop.
     let protocol = Arc::new(Protocol::new(
         tokio_handle.clone(),
-        stream,
+        transport,
         identity,
         dispatcher,
         config,
@@ -63,42 +77,3 @@ pub fn start_runtime(initializer: Box<dyn Initializer>, config: Config) {
 
     info!(logger, "Protocol handler terminated, shutting down");
 }
-
-/// Establish a connection with the host.
-fn connect() -> Result<Stream> {
-    match BUILD_INFO.tee_type {
-        #[cfg(not(target_env = "sgx"))]
-        TeeType::Sgx | TeeType::None => {
-            let stream = std::os::unix::net::UnixStream::connect(
-                std::env::var("OASIS_WORKER_HOST").unwrap_or_default(),
-            )?;
-            Ok(Stream::Unix(stream))
-        }
-
-        #[cfg(target_env = "sgx")]
-        TeeType::Sgx => {
-            let stream = std::net::TcpStream::connect("worker-host")?;
-            Ok(Stream::Tcp(stream))
-        }
-
-        #[cfg(feature = "tdx")]
-        TeeType::Tdx => {
-            /// VSOCK port used for the Runtime Host Protocol.
-            const VSOCK_PORT_RHP: u32 = 1;
-
-            // Accept first connection.
-            let listener = vsock::VsockListener::bind(&vsock::VsockAddr::new(
-                libc::VMADDR_CID_ANY,
-                VSOCK_PORT_RHP,
-            ))?;
-            let stream = listener
-                .incoming()
-                .next()
-                .ok_or(anyhow::anyhow!("failed to accept connection"))??;
-            Ok(Stream::Vsock(stream))
-        }
-
-        #[allow(unreachable_patterns)]
-        _ => Err(anyhow::anyhow!("unsupported TEE type")),
-    }
-}
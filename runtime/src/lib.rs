@@ -9,7 +9,8 @@
 //! ```
 //!
 //! This will start the required services needed to communicate with
-//! the worker host.
+//! the worker host. Runtimes can also be assembled from individual pieces
+//! using a [`RuntimeBuilder`].
 #![feature(test)]
 #![feature(arbitrary_self_types)]
 
@@ -23,20 +24,31 @@ extern crate base64_serde;
 #[macro_use]
 pub mod common;
 pub mod app;
-mod attestation;
+pub mod attestation;
+pub mod build_info;
+pub mod builder;
 pub mod cache;
 pub mod config;
 pub mod consensus;
 pub mod dispatcher;
 pub mod enclave_rpc;
 pub mod future;
+pub mod handshake;
+pub mod health;
 pub mod host;
 pub mod identity;
 pub mod init;
+pub mod metrics;
 pub mod policy;
 pub mod protocol;
+pub mod replay;
+pub mod shutdown;
 pub mod storage;
+pub mod tasks;
+#[cfg(any(test, feature = "test-utils"))]
+pub mod testkit;
 pub mod transaction;
+pub mod transport;
 pub mod types;
 
 use common::{
@@ -159,6 +171,7 @@ pub struct BuildInfo {
 
 // Re-exports.
 pub use self::{
+    builder::RuntimeBuilder,
     enclave_rpc::{demux::Demux as RpcDemux, dispatcher::Dispatcher as RpcDispatcher},
     init::start_runtime,
     protocol::Protocol,
@@ -0,0 +1,169 @@
+//! Runtime metrics.
+//!
+//! The metrics registry collects counters and latency histograms of runtime internals which are
+//! otherwise invisible to node operators. Snapshots of all metrics are pushed to the host
+//! periodically in case `Config::metrics_push_interval` is set, so that the node can export them
+//! via its own metrics endpoint. Histograms use fixed buckets, so that snapshots of different
+//! runtime instances can be aggregated.
+use std::{
+    collections::BTreeMap,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+lazy_static! {
+    static ref METRICS_REGISTRY: MetricsRegistry = MetricsRegistry::new();
+}
+
+/// Latency of calls made to the host, in microseconds.
+pub const METRIC_HOST_CALL_LATENCY: &str = "host_call_latency_us";
+/// Execution time of transaction batches, in microseconds.
+pub const METRIC_BATCH_EXECUTION_TIME: &str = "batch_execution_time_us";
+/// Number of tree cache hits during batch execution.
+pub const METRIC_STORAGE_CACHE_HITS: &str = "storage_cache_hits";
+/// Number of tree cache misses during batch execution.
+pub const METRIC_STORAGE_CACHE_MISSES: &str = "storage_cache_misses";
+/// Number of attestation refreshes.
+pub const METRIC_ATTESTATION_REFRESHES: &str = "attestation_refreshes";
+/// Number of cached tree nodes re-hashed by integrity self-checks.
+pub const METRIC_STORAGE_NODES_VERIFIED: &str = "storage_nodes_verified";
+/// Number of cached tree nodes found corrupted by integrity self-checks.
+pub const METRIC_STORAGE_NODES_CORRUPTED: &str = "storage_nodes_corrupted";
+
+/// Upper bounds of histogram buckets, in microseconds.
+pub const HISTOGRAM_BUCKETS: &[u64] = &[
+    100, 500, 1_000, 5_000, 10_000, 50_000, 100_000, 500_000, 1_000_000, 5_000_000, 10_000_000,
+];
+
+/// Snapshot of a histogram.
+#[derive(Clone, Debug, Default, PartialEq, Eq, cbor::Encode, cbor::Decode)]
+pub struct HistogramSnapshot {
+    /// Number of observations in each bucket of `HISTOGRAM_BUCKETS`, followed by the number of
+    /// observations exceeding all bucket bounds.
+    pub buckets: Vec<u64>,
+    /// Sum of all observations.
+    pub sum: u64,
+    /// Number of observations.
+    pub count: u64,
+}
+
+impl HistogramSnapshot {
+    fn new() -> Self {
+        Self {
+            buckets: vec![0; HISTOGRAM_BUCKETS.len() + 1],
+            sum: 0,
+            count: 0,
+        }
+    }
+
+    fn observe(&mut self, value: u64) {
+        let bucket = HISTOGRAM_BUCKETS
+            .iter()
+            .position(|bound| value <= *bound)
+            .unwrap_or(HISTOGRAM_BUCKETS.len());
+        self.buckets[bucket] += 1;
+        self.sum = self.sum.saturating_add(value);
+        self.count += 1;
+    }
+}
+
+/// Snapshot of all metrics.
+#[derive(Clone, Debug, Default, PartialEq, Eq, cbor::Encode, cbor::Decode)]
+pub struct MetricsSnapshot {
+    /// Counters, by name.
+    pub counters: BTreeMap<String, u64>,
+    /// Histograms, by name.
+    pub histograms: BTreeMap<String, HistogramSnapshot>,
+}
+
+/// Registry of runtime metrics.
+///
+/// Metrics are cumulative since the runtime started.
+pub struct MetricsRegistry {
+    inner: Mutex<MetricsSnapshot>,
+}
+
+impl MetricsRegistry {
+    /// Create a new empty registry.
+    pub fn new() -> Self {
+        Self {
+            inner: Mutex::new(MetricsSnapshot::default()),
+        }
+    }
+
+    /// Global metrics registry instance.
+    pub fn global() -> &'static MetricsRegistry {
+        &METRICS_REGISTRY
+    }
+
+    /// Increment the counter with the given name.
+    pub fn inc(&self, name: &str, by: u64) {
+        let mut inner = self.inner.lock().unwrap();
+        match inner.counters.get_mut(name) {
+            Some(counter) => *counter = counter.saturating_add(by),
+            None => {
+                inner.counters.insert(name.to_string(), by);
+            }
+        }
+    }
+
+    /// Record an observation in the histogram with the given name.
+    pub fn observe(&self, name: &str, value: u64) {
+        let mut inner = self.inner.lock().unwrap();
+        match inner.histograms.get_mut(name) {
+            Some(histogram) => histogram.observe(value),
+            None => {
+                let mut histogram = HistogramSnapshot::new();
+                histogram.observe(value);
+                inner.histograms.insert(name.to_string(), histogram);
+            }
+        }
+    }
+
+    /// Record the given duration in the histogram with the given name.
+    pub fn observe_duration(&self, name: &str, duration: Duration) {
+        self.observe(name, duration.as_micros().try_into().unwrap_or(u64::MAX));
+    }
+
+    /// Record the time elapsed since the given instant in the histogram with the given name.
+    pub fn observe_since(&self, name: &str, start: Instant) {
+        self.observe_duration(name, start.elapsed());
+    }
+
+    /// Snapshot of all metrics.
+    pub fn snapshot(&self) -> MetricsSnapshot {
+        self.inner.lock().unwrap().clone()
+    }
+}
+
+impl Default for MetricsRegistry {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_metrics_registry() {
+        let registry = MetricsRegistry::new();
+        registry.inc(METRIC_STORAGE_CACHE_HITS, 2);
+        registry.inc(METRIC_STORAGE_CACHE_HITS, 3);
+        registry.observe(METRIC_HOST_CALL_LATENCY, 50);
+        registry.observe(METRIC_HOST_CALL_LATENCY, 100);
+        registry.observe_duration(METRIC_HOST_CALL_LATENCY, Duration::from_millis(2));
+        registry.observe(METRIC_HOST_CALL_LATENCY, u64::MAX);
+
+        let snapshot = registry.snapshot();
+        assert_eq!(snapshot.counters[METRIC_STORAGE_CACHE_HITS], 5);
+        let histogram = &snapshot.histograms[METRIC_HOST_CALL_LATENCY];
+        assert_eq!(histogram.count, 4);
+        assert_eq!(histogram.sum, u64::MAX);
+        assert_eq!(histogram.buckets.len(), HISTOGRAM_BUCKETS.len() + 1);
+        assert_eq!(histogram.buckets[0], 2);
+        assert_eq!(histogram.buckets[3], 1);
+        assert_eq!(histogram.buckets[HISTOGRAM_BUCKETS.len()], 1);
+    }
+}
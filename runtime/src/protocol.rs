@@ -4,81 +4,51 @@ use std::{
     io::{BufReader, BufWriter, Read, Write},
     sync::{
         atomic::{AtomicUsize, Ordering},
-        Arc, Mutex,
+        Arc, Condvar, Mutex,
     },
+    time::{Duration, Instant},
 };
 
 use byteorder::{BigEndian, ReadBytesExt, WriteBytesExt};
-use crossbeam::channel;
 use slog::{debug, error, info, warn, Logger};
 use thiserror::Error;
 use tokio::sync::oneshot;
 
 use crate::{
     common::{logger::get_logger, namespace::Namespace, version::Version},
-    config::Config,
+    config::{Config, Limit},
     consensus::{tendermint, verifier::Verifier},
     dispatcher::Dispatcher,
     future::block_on,
+    handshake::{HandshakeTranscript, SignedHandshakeTranscript},
+    health::HealthMonitor,
+    host::{
+        accounting::{HostCallAccounting, Subsystem, SubsystemStats},
+        deprecation::{DeprecationStats, DeprecationTracker},
+        feed::FeedVerifier,
+        lanes::{self, Lane, Lanes},
+        notify::NotifyRegistry,
+        queues::{Admission, MessageClass, QueueStats, RequestQueues},
+        secrets::SecretCache,
+        throttle::QueryThrottle,
+    },
     identity::Identity,
-    storage::KeyValue,
-    types::{Body, Error, Message, MessageType, RuntimeInfoRequest, RuntimeInfoResponse},
+    metrics::{MetricsRegistry, METRIC_HOST_CALL_LATENCY},
+    storage::{
+        mkvs::{commit::LocalCommits, dirty::DirtySets, sync::CircuitBreaker},
+        KeyValue,
+    },
+    transaction::events::EventRegistry,
+    transport::{Offline, Transport, TransportIo},
+    types::{
+        Body, Error, HostFeatures, Message, MessageType, ProtocolFeature, ProtocolFeatures,
+        RuntimeInfoRequest, RuntimeInfoResponse, FEATURE_NEGOTIATION_VERSION,
+    },
     TeeType, BUILD_INFO,
 };
 
-/// Stream used to communicate with the host.
-pub enum Stream {
-    #[cfg(not(target_env = "sgx"))]
-    Unix(std::os::unix::net::UnixStream),
-    Tcp(std::net::TcpStream),
-    #[cfg(feature = "tdx")]
-    Vsock(vsock::VsockStream),
-}
-
-impl Read for &Stream {
-    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
-        #[allow(clippy::borrow_deref_ref)]
-        match self {
-            #[cfg(not(target_env = "sgx"))]
-            Stream::Unix(stream) => (&*stream).read(buf),
-            Stream::Tcp(stream) => (&*stream).read(buf),
-            #[cfg(feature = "tdx")]
-            Stream::Vsock(stream) => (&*stream).read(buf),
-        }
-    }
-}
-
-impl Write for &Stream {
-    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
-        #[allow(clippy::borrow_deref_ref)]
-        match self {
-            #[cfg(not(target_env = "sgx"))]
-            Stream::Unix(stream) => (&*stream).write(buf),
-            Stream::Tcp(stream) => (&*stream).write(buf),
-            #[cfg(feature = "tdx")]
-            Stream::Vsock(stream) => (&*stream).write(buf),
-        }
-    }
-
-    fn flush(&mut self) -> std::io::Result<()> {
-        #[allow(clippy::borrow_deref_ref)]
-        match self {
-            #[cfg(not(target_env = "sgx"))]
-            Stream::Unix(stream) => (&*stream).flush(),
-            Stream::Tcp(stream) => (&*stream).flush(),
-            #[cfg(feature = "tdx")]
-            Stream::Vsock(stream) => (&*stream).flush(),
-        }
-    }
-}
-
-/// Maximum message size.
-const MAX_MESSAGE_SIZE: usize = 16 * 1024 * 1024; // 16MiB
-
 #[derive(Error, Debug)]
 pub enum ProtocolError {
-    #[error("message too large")]
-    MessageTooLarge,
     #[error("method not supported")]
     MethodNotSupported,
     #[error("invalid response")]
@@ -96,13 +66,54 @@ pub enum ProtocolError {
     AlreadyInitialized,
     #[error("channel closed")]
     ChannelClosed,
+    #[error("host not available in offline mode")]
+    Offline,
+    #[error("host call rate limit exceeded for {0}")]
+    RateLimited(Subsystem),
+    #[error("host call timed out")]
+    Timeout,
+    #[error("host call cancelled")]
+    Cancelled,
+    #[error("duplicate request identifier")]
+    DuplicateRequest,
+    #[error("{0} request queue full")]
+    QueueFull(MessageClass),
+    #[error("query rate limit exceeded for source '{0}'")]
+    QueryThrottled(String),
+    #[error("runtime shutting down")]
+    ShuttingDown,
+}
+
+impl ProtocolError {
+    /// Whether the error is likely transient, so that the call may succeed when retried.
+    pub fn is_transient(&self) -> bool {
+        matches!(
+            self,
+            Self::ChannelClosed | Self::RateLimited(_) | Self::Timeout | Self::QueueFull(_)
+        )
+    }
 }
 
+/// Module name of protocol errors.
+pub const MODULE_NAME: &str = "protocol";
+/// Error code of permanent protocol errors.
+pub const CODE_PERMANENT: u32 = 1;
+/// Error code of transient protocol errors.
+pub const CODE_TRANSIENT: u32 = 2;
+
+/// Maximum time to wait for the host to respond to protocol feature negotiation, as older hosts
+/// may never respond to unknown requests.
+const FEATURE_NEGOTIATION_TIMEOUT: Duration = Duration::from_secs(5);
+
 impl From<ProtocolError> for Error {
     fn from(err: ProtocolError) -> Self {
         Self {
-            module: "protocol".to_string(),
-            code: 1,
+            module: MODULE_NAME.to_string(),
+            code: if err.is_transient() {
+                CODE_TRANSIENT
+            } else {
+                CODE_PERMANENT
+            },
             message: err.to_string(),
         }
     }
@@ -124,6 +135,60 @@ pub struct HostInfo {
     /// This configuration must not be used in any context which requires determinism across
     /// replicated runtime instances.
     pub local_config: BTreeMap<String, cbor::Value>,
+    /// Features supported by the host.
+    pub features: HostFeatures,
+}
+
+/// Options for calls to the host.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct CallOpts {
+    /// Maximum time to wait for the response. If not specified, the call waits indefinitely.
+    pub timeout: Option<Duration>,
+    /// Identifier of the request, allocated via `Protocol::next_request_id`, which allows the
+    /// request to be cancelled while it is outstanding. If not specified, a new identifier is
+    /// allocated.
+    pub request_id: Option<u64>,
+}
+
+/// Observer of host calls, which can also answer calls in place of the host.
+///
+/// Requests and responses are passed in their CBOR encoding, with failed calls encoded as
+/// `Body::Error`.
+pub trait HostCallTap: Send + Sync {
+    /// Called before a request is sent to the host. Returning a response answers the call
+    /// without involving the host.
+    fn request(&self, request: &[u8]) -> Option<Vec<u8>>;
+
+    /// Called with the response to a request which was answered by the host.
+    fn response(&self, request: &[u8], response: &[u8]);
+}
+
+/// Outstanding request to the host.
+struct PendingRequest {
+    /// Channel for delivering the response.
+    tx: oneshot::Sender<Body>,
+    /// Time after which the request is cancelled.
+    deadline: Option<Instant>,
+}
+
+/// Cancels an outstanding request when dropped before the response has been received.
+struct CancelOnDrop<'a> {
+    protocol: &'a Protocol,
+    id: u64,
+}
+
+impl Drop for CancelOnDrop<'_> {
+    fn drop(&mut self) {
+        let pending = self
+            .protocol
+            .pending_out_requests
+            .lock()
+            .unwrap()
+            .remove(&self.id);
+        if pending.is_some() {
+            self.protocol.send_cancel(self.id);
+        }
+    }
 }
 
 /// Runtime part of the runtime host protocol.
@@ -131,64 +196,190 @@ pub struct Protocol {
     /// Logger.
     logger: Logger,
     /// Runtime identity.
-    #[cfg_attr(
-        not(any(target_env = "sgx", feature = "debug-mock-sgx")),
-        allow(unused)
-    )]
     identity: Arc<Identity>,
-    /// Incoming request dispatcher.
-    dispatcher: Arc<Dispatcher>,
-    /// Channel for sending outgoing messages.
-    outgoing_tx: channel::Sender<Message>,
-    /// Channel for receiving outgoing messages.
-    outgoing_rx: channel::Receiver<Message>,
-    /// Stream to the runtime host.
-    stream: Stream,
+    /// Incoming request dispatcher, not available in offline mode.
+    dispatcher: Option<Arc<Dispatcher>>,
+    /// Outgoing messages, queued per lane.
+    outgoing: Lanes,
+    /// Transport to the runtime host.
+    transport: Box<dyn Transport>,
     /// Outgoing request identifier generator.
     last_request_id: AtomicUsize,
     /// Pending outgoing requests.
-    pending_out_requests: Mutex<HashMap<u64, oneshot::Sender<Body>>>,
+    pending_out_requests: Mutex<HashMap<u64, PendingRequest>>,
+    /// Condition signalled when a pending request with a deadline is added.
+    pending_deadlines: Condvar,
     /// Runtime configuration.
     config: Config,
+    /// Per-subsystem host call accounting.
+    accounting: HostCallAccounting,
+    /// Bounded queues of requests received from the host.
+    request_queues: RequestQueues,
+    /// Per-source rate limiting of queries received from the host.
+    query_throttle: QueryThrottle,
+    /// Use of message types deprecated by the host.
+    deprecations: DeprecationTracker,
     /// Host environment information.
     host_info: Mutex<Option<HostInfo>>,
+    /// Protocol features negotiated with the host.
+    features: Mutex<ProtocolFeatures>,
     /// Tokio runtime handle.
     tokio_runtime: tokio::runtime::Handle,
+    /// Active host notification registrations.
+    pub(crate) notify_registry: Arc<NotifyRegistry>,
+    /// Verifier of external data feed notifications.
+    pub(crate) feed_verifier: FeedVerifier,
+    /// Publisher of the keys written by each executed round.
+    dirty_sets: DirtySets,
+    /// Roots committed locally in recent rounds.
+    local_commits: LocalCommits,
+    /// Circuit breaker of host storage sync requests.
+    storage_breaker: CircuitBreaker,
+    /// Cache of decrypted operator-provisioned secrets.
+    pub(crate) secret_cache: SecretCache,
+    /// Signed transcript of the runtime host protocol handshake.
+    handshake_transcript: Mutex<Option<SignedHandshakeTranscript>>,
+    /// Consensus verifier, available once the protocol is initialized.
+    consensus_verifier: Mutex<Option<Arc<dyn Verifier>>>,
+    /// Observer of host calls, if any.
+    host_call_tap: Mutex<Option<Arc<dyn HostCallTap>>>,
 }
 
 impl Protocol {
     /// Create a new protocol handler instance.
     pub(crate) fn new(
         tokio_runtime: tokio::runtime::Handle,
-        stream: Stream,
+        transport: Box<dyn Transport>,
         identity: Arc<Identity>,
         dispatcher: Arc<Dispatcher>,
         config: Config,
     ) -> Self {
         let logger = get_logger("runtime/protocol");
 
-        let (outgoing_tx, outgoing_rx) = channel::unbounded();
-
         Self {
             logger,
             identity,
-            dispatcher,
-            outgoing_tx,
-            outgoing_rx,
-            stream,
+            dispatcher: Some(dispatcher),
+            outgoing: Lanes::new(),
+            transport,
             last_request_id: AtomicUsize::new(0),
             pending_out_requests: Mutex::new(HashMap::new()),
+            pending_deadlines: Condvar::new(),
+            accounting: HostCallAccounting::new(config.host_call_limits.clone()),
+            request_queues: RequestQueues::new(&config.host_message_queues),
+            query_throttle: QueryThrottle::new(config.query_rate_limits.clone()),
+            deprecations: DeprecationTracker::new(),
+            feed_verifier: FeedVerifier::new(config.external_data_feeds.clone()),
+            dirty_sets: DirtySets::new(config.storage.dirty_set_capacity),
+            local_commits: LocalCommits::new(config.storage.commit_ack_rounds),
+            storage_breaker: CircuitBreaker::new(config.storage.circuit_breaker.clone()),
+            secret_cache: SecretCache::default(),
             config,
             host_info: Mutex::new(None),
+            features: Mutex::new(ProtocolFeatures::default()),
+            tokio_runtime,
+            notify_registry: Arc::new(NotifyRegistry::new()),
+            handshake_transcript: Mutex::new(None),
+            consensus_verifier: Mutex::new(None),
+            host_call_tap: Mutex::new(None),
+        }
+    }
+
+    /// Create a new protocol handler instance which is not connected to any host.
+    ///
+    /// The host environment information is configured upfront and all calls to the host fail
+    /// with `ProtocolError::Offline`. This is used to replay historical rounds from public data.
+    pub fn offline(
+        tokio_runtime: tokio::runtime::Handle,
+        identity: Arc<Identity>,
+        config: Config,
+        host_info: HostInfo,
+    ) -> Self {
+        Self::without_dispatcher(
+            tokio_runtime,
+            Box::new(Offline),
+            identity,
+            config,
+            host_info,
+        )
+    }
+
+    /// Create a new protocol handler instance using the given transport, which does not handle
+    /// any requests from the host.
+    ///
+    /// The host environment information is configured upfront, as no handshake takes place.
+    pub(crate) fn without_dispatcher(
+        tokio_runtime: tokio::runtime::Handle,
+        transport: Box<dyn Transport>,
+        identity: Arc<Identity>,
+        config: Config,
+        host_info: HostInfo,
+    ) -> Self {
+        Self {
+            logger: get_logger("runtime/protocol"),
+            identity,
+            dispatcher: None,
+            outgoing: Lanes::new(),
+            transport,
+            last_request_id: AtomicUsize::new(0),
+            pending_out_requests: Mutex::new(HashMap::new()),
+            pending_deadlines: Condvar::new(),
+            accounting: HostCallAccounting::new(config.host_call_limits.clone()),
+            request_queues: RequestQueues::new(&config.host_message_queues),
+            query_throttle: QueryThrottle::new(config.query_rate_limits.clone()),
+            deprecations: DeprecationTracker::new(),
+            feed_verifier: FeedVerifier::new(config.external_data_feeds.clone()),
+            dirty_sets: DirtySets::new(config.storage.dirty_set_capacity),
+            local_commits: LocalCommits::new(config.storage.commit_ack_rounds),
+            storage_breaker: CircuitBreaker::new(config.storage.circuit_breaker.clone()),
+            secret_cache: SecretCache::default(),
+            config,
+            features: Mutex::new(ProtocolFeatures::legacy(&host_info.features)),
+            host_info: Mutex::new(Some(host_info)),
             tokio_runtime,
+            notify_registry: Arc::new(NotifyRegistry::new()),
+            handshake_transcript: Mutex::new(None),
+            consensus_verifier: Mutex::new(None),
+            host_call_tap: Mutex::new(None),
         }
     }
 
+    /// Whether the protocol handler is running in offline mode.
+    pub fn is_offline(&self) -> bool {
+        self.transport.is_offline()
+    }
+
+    /// The consensus verifier, if the protocol has been initialized.
+    pub fn get_consensus_verifier(&self) -> Option<Arc<dyn Verifier>> {
+        self.consensus_verifier.lock().unwrap().clone()
+    }
+
+    fn dispatcher(&self) -> Result<&Arc<Dispatcher>, ProtocolError> {
+        self.dispatcher.as_ref().ok_or(ProtocolError::Offline)
+    }
+
     /// The supplied runtime configuration.
     pub fn get_config(&self) -> &Config {
         &self.config
     }
 
+    /// Publisher of the keys written by each executed round, which components keeping data
+    /// derived from the runtime state can subscribe to.
+    pub fn dirty_sets(&self) -> &DirtySets {
+        &self.dirty_sets
+    }
+
+    /// Roots committed locally in recent rounds, against which the commit acknowledgements of
+    /// the host are verified.
+    pub fn local_commits(&self) -> &LocalCommits {
+        &self.local_commits
+    }
+
+    /// Circuit breaker of host storage sync requests, which is open while storage is degraded.
+    pub fn storage_breaker(&self) -> &CircuitBreaker {
+        &self.storage_breaker
+    }
+
     /// The runtime identity.
     pub fn get_identity(&self) -> Option<&Arc<Identity>> {
         self.identity.quote()?;
@@ -223,19 +414,86 @@ impl Protocol {
             .clone()
     }
 
+    /// Protocol features negotiated with the host.
+    ///
+    /// Until the features have been negotiated, and for hosts which don't support feature
+    /// negotiation, these are the features implied by the host features.
+    pub fn features(&self) -> ProtocolFeatures {
+        self.features.lock().unwrap().clone()
+    }
+
+    /// Registry of the runtime's event types, whose schemas are exposed over enclave RPC and
+    /// which are used to decode the events delivered with runtime notifications.
+    pub fn event_registry(&self) -> &Arc<EventRegistry> {
+        self.notify_registry.event_registry()
+    }
+
+    /// Negotiate protocol features with the host, enabling the features supported by both sides.
+    ///
+    /// Hosts which reject the request or don't respond in time are assumed not to support feature
+    /// negotiation, in which case the features implied by the host features are kept.
+    pub(crate) async fn negotiate_features(&self) {
+        let supported = ProtocolFeatures::supported();
+        let request = Body::HostNegotiateFeaturesRequest {
+            version: FEATURE_NEGOTIATION_VERSION,
+            features: supported.names(),
+        };
+        let opts = CallOpts {
+            timeout: Some(FEATURE_NEGOTIATION_TIMEOUT),
+            ..Default::default()
+        };
+
+        let features = match self.call_host_async_with_opts(request, opts).await {
+            Ok(Body::HostNegotiateFeaturesResponse { version, features }) => {
+                info!(self.logger, "Negotiated protocol features with the host";
+                    "version" => version.min(FEATURE_NEGOTIATION_VERSION),
+                    "features" => ?features,
+                );
+                supported.intersection(&ProtocolFeatures::from_names(&features))
+            }
+            Ok(_) => {
+                warn!(
+                    self.logger,
+                    "Unexpected response to protocol feature negotiation"
+                );
+                return;
+            }
+            Err(err) => {
+                // Older hosts don't support feature negotiation, so keep the legacy features.
+                info!(self.logger, "Host does not support protocol feature negotiation";
+                    "err" => %err,
+                );
+                return;
+            }
+        };
+        *self.features.lock().unwrap() = features;
+    }
+
     /// Start the protocol handler loop.
     pub(crate) fn start(self: &Arc<Protocol>) {
         // Spawn write end in a separate thread.
         let protocol = self.clone();
         std::thread::spawn(move || protocol.io_write());
 
+        // Spawn request expiry in a separate thread.
+        let protocol = self.clone();
+        std::thread::spawn(move || protocol.expire_requests());
+
+        // Spawn dispatch of queued requests in a separate thread.
+        let protocol = self.clone();
+        std::thread::spawn(move || protocol.dispatch_requests());
+
+        // Start the notification registration updater.
+        self.notify_registry
+            .start(self.clone(), &self.tokio_runtime);
+
         // Run read end in the current thread.
         self.io_read();
     }
 
     fn io_read(self: &Arc<Protocol>) {
         info!(self.logger, "Starting protocol reader thread");
-        let mut reader = BufReader::new(&self.stream);
+        let mut reader = BufReader::new(TransportIo(&*self.transport));
 
         loop {
             if let Err(error) = self.handle_message(&mut reader) {
@@ -250,7 +508,7 @@ impl Protocol {
     fn io_write(self: &Arc<Protocol>) {
         info!(self.logger, "Starting protocol writer thread");
 
-        while let Ok(message) = self.outgoing_rx.recv() {
+        while let Some(message) = self.outgoing.recv() {
             if let Err(error) = self.write_message(message) {
                 warn!(self.logger, "Failed to write message"; "err" => %error);
             }
@@ -259,6 +517,52 @@ impl Protocol {
         info!(self.logger, "Protocol writer thread is terminating");
     }
 
+    fn dispatch_requests(self: &Arc<Protocol>) {
+        let dispatcher = match self.dispatcher() {
+            Ok(dispatcher) => dispatcher,
+            Err(_) => return,
+        };
+
+        loop {
+            let (id, request) = self.request_queues.pop();
+            if let Err(error) = dispatcher.queue_request(id, request) {
+                error!(self.logger, "Failed to dispatch request"; "err" => %error);
+                break;
+            }
+        }
+    }
+
+    fn expire_requests(self: &Arc<Protocol>) {
+        let mut pending_requests = self.pending_out_requests.lock().unwrap();
+
+        loop {
+            let now = Instant::now();
+            let expired: Vec<_> = pending_requests
+                .iter()
+                .filter(
+                    |(_, request)| matches!(request.deadline, Some(deadline) if deadline <= now),
+                )
+                .map(|(id, _)| *id)
+                .collect();
+            for id in expired {
+                let request = pending_requests.remove(&id).unwrap();
+                let _ = request.tx.send(Body::Error(ProtocolError::Timeout.into()));
+                self.send_cancel(id);
+            }
+
+            let next_deadline = pending_requests.values().filter_map(|r| r.deadline).min();
+            pending_requests = match next_deadline {
+                Some(deadline) => {
+                    self.pending_deadlines
+                        .wait_timeout(pending_requests, deadline.saturating_duration_since(now))
+                        .unwrap()
+                        .0
+                }
+                None => self.pending_deadlines.wait(pending_requests).unwrap(),
+            };
+        }
+    }
+
     /// Make a new request to the runtime host and wait for the response.
     ///
     /// This is a blocking variant of `call_host_async`.
@@ -270,9 +574,76 @@ impl Protocol {
         block_on(self.call_host_async(body))
     }
 
+    /// Per-subsystem accounting statistics of the messages exchanged with the host.
+    pub fn get_host_call_stats(&self) -> BTreeMap<Subsystem, SubsystemStats> {
+        self.accounting.stats()
+    }
+
     /// Make a new request to the runtime host and wait for the response.
+    ///
+    /// Low-priority requests fail with `ProtocolError::RateLimited` in case their subsystem
+    /// exceeds its configured hard limit.
     pub async fn call_host_async(&self, body: Body) -> Result<Body, Error> {
-        let id = self.last_request_id.fetch_add(1, Ordering::SeqCst) as u64;
+        self.call_host_async_with_opts(body, CallOpts::default())
+            .await
+    }
+
+    /// Make a new request to the runtime host with the given options and wait for the response.
+    ///
+    /// In case the call times out or the returned future is dropped before the response has been
+    /// received, the request is cancelled and the host is notified.
+    ///
+    /// Calls are passed through the installed host call tap, if any, which may answer them even
+    /// in offline mode.
+    pub async fn call_host_async_with_opts(
+        &self,
+        body: Body,
+        opts: CallOpts,
+    ) -> Result<Body, Error> {
+        let tap = self.host_call_tap.lock().unwrap().clone();
+        let tap = match tap {
+            Some(tap) => tap,
+            None => return self.send_request(body, opts).await,
+        };
+
+        // Bodies can't be cloned, so the tap is given their encoding which is decoded back.
+        let request = cbor::to_vec(body);
+        let decode = |data: &[u8]| match cbor::from_slice(data) {
+            Ok(Body::Error(err)) => Err(err),
+            Ok(body) => Ok(body),
+            Err(_) => Err(Error::from(ProtocolError::InvalidResponse)),
+        };
+        if let Some(response) = tap.request(&request) {
+            return decode(&response);
+        }
+        let body = cbor::from_slice(&request).expect("encoded body must decode");
+        let response = cbor::to_vec(match self.send_request(body, opts).await {
+            Ok(body) => body,
+            Err(err) => Body::Error(err),
+        });
+        tap.response(&request, &response);
+        decode(&response)
+    }
+
+    /// Install the given host call tap, replacing the previous one which is returned.
+    ///
+    /// The tap observes all host calls made through the protocol, regardless of the request
+    /// they are made on behalf of. Passing `None` removes the tap.
+    pub fn set_host_call_tap(
+        &self,
+        tap: Option<Arc<dyn HostCallTap>>,
+    ) -> Option<Arc<dyn HostCallTap>> {
+        std::mem::replace(&mut *self.host_call_tap.lock().unwrap(), tap)
+    }
+
+    async fn send_request(&self, body: Body, opts: CallOpts) -> Result<Body, Error> {
+        if self.is_offline() {
+            return Err(ProtocolError::Offline.into());
+        }
+        self.accounting.admit(&body)?;
+
+        let id = opts.request_id.unwrap_or_else(|| self.next_request_id());
+        let lane = Lane::of_request(&body, lanes::current_origin());
         let message = Message {
             id,
             body,
@@ -281,64 +652,152 @@ impl Protocol {
 
         // Create a response channel and register an outstanding pending request.
         let (tx, rx) = oneshot::channel();
+        let deadline = opts.timeout.map(|timeout| Instant::now() + timeout);
         {
             let mut pending_requests = self.pending_out_requests.lock().unwrap();
-            pending_requests.insert(id, tx);
+            if pending_requests.contains_key(&id) {
+                return Err(ProtocolError::DuplicateRequest.into());
+            }
+            pending_requests.insert(id, PendingRequest { tx, deadline });
+        }
+        if deadline.is_some() {
+            self.pending_deadlines.notify_one();
         }
+        let _cancel = CancelOnDrop { protocol: self, id };
 
         // Write message to stream and wait for the response.
-        self.send_message(message).map_err(Error::from)?;
+        let start = Instant::now();
+        self.send_message(lane, message).map_err(Error::from)?;
 
         let result = rx
             .await
             .map_err(|_| Error::from(ProtocolError::ChannelClosed))?;
+        MetricsRegistry::global().observe_since(METRIC_HOST_CALL_LATENCY, start);
         match result {
             Body::Error(err) => Err(err),
             body => Ok(body),
         }
     }
 
+    /// Allocate an identifier for a new request to the host.
+    pub fn next_request_id(&self) -> u64 {
+        self.last_request_id.fetch_add(1, Ordering::SeqCst) as u64
+    }
+
+    /// Cancel the outstanding request with the given identifier, notifying the host.
+    ///
+    /// The call waiting for the response fails with `ProtocolError::Cancelled`. Returns false in
+    /// case no such request is outstanding.
+    pub fn cancel_request(&self, id: u64) -> bool {
+        let pending = self.pending_out_requests.lock().unwrap().remove(&id);
+        match pending {
+            Some(request) => {
+                let _ = request
+                    .tx
+                    .send(Body::Error(ProtocolError::Cancelled.into()));
+                self.send_cancel(id);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Wait for all outstanding requests to the host to complete, up to the given timeout, and
+    /// cancel the requests still outstanding after it.
+    ///
+    /// Calls waiting for cancelled requests fail with `ProtocolError::ShuttingDown`. Returns
+    /// whether all requests completed before the timeout.
+    pub async fn settle_host_calls(&self, timeout: Duration) -> bool {
+        let deadline = Instant::now() + timeout;
+        while !self.pending_out_requests.lock().unwrap().is_empty() {
+            if Instant::now() >= deadline {
+                let pending: Vec<_> = self.pending_out_requests.lock().unwrap().drain().collect();
+                for (id, request) in pending {
+                    let _ = request
+                        .tx
+                        .send(Body::Error(ProtocolError::ShuttingDown.into()));
+                    self.send_cancel(id);
+                }
+                return false;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        true
+    }
+
+    /// Usage statistics of all message types deprecated by the host.
+    pub fn get_deprecation_stats(&self) -> BTreeMap<String, DeprecationStats> {
+        self.deprecations.stats()
+    }
+
+    /// Queue statistics of all classes of requests received from the host.
+    pub fn get_queue_stats(&self) -> BTreeMap<MessageClass, QueueStats> {
+        self.request_queues.stats()
+    }
+
     /// Send an async response to a previous request back to the host.
     pub fn send_response(&self, id: u64, body: Body) -> anyhow::Result<()> {
-        self.send_message(Message {
-            id,
-            body,
-            message_type: MessageType::Response,
-        })
+        // Responses are sent on the lane of the request they answer.
+        let lane = self
+            .request_queues
+            .complete(id)
+            .map_or(Lane::Critical, Lane::of_class);
+        self.send_message(
+            lane,
+            Message {
+                id,
+                body,
+                message_type: MessageType::Response,
+            },
+        )
+    }
+
+    fn send_cancel(&self, id: u64) {
+        // The request is cancelled locally even if the host can't be notified.
+        let _ = self.send_message(
+            Lane::Critical,
+            Message {
+                id,
+                body: Body::Empty {},
+                message_type: MessageType::Cancel,
+            },
+        );
     }
 
-    fn send_message(&self, message: Message) -> anyhow::Result<()> {
-        self.outgoing_tx.send(message).map_err(|err| err.into())
+    fn send_message(&self, lane: Lane, message: Message) -> anyhow::Result<()> {
+        self.outgoing.send(lane, message).map_err(|err| err.into())
     }
 
     fn decode_message<R: Read>(&self, mut reader: R) -> anyhow::Result<Message> {
         let length = reader.read_u32::<BigEndian>()? as usize;
-        if length > MAX_MESSAGE_SIZE {
-            return Err(ProtocolError::MessageTooLarge.into());
-        }
+        self.config.limits.check(Limit::Message, length)?;
 
         // TODO: Avoid allocations.
         let mut buffer = vec![0; length];
         reader.read_exact(&mut buffer)?;
 
-        let message = cbor::from_slice(&buffer)
+        let message: Message = cbor::from_slice(&buffer)
             .map_err(|error| {
                 warn!(self.logger, "Failed to decode message"; "err" => %error);
                 debug!(self.logger, "Malformed message"; "bytes" => ?buffer);
                 error
             })
             .unwrap_or_default();
+        self.accounting
+            .record_bytes(Subsystem::of(&message.body), length);
+        self.deprecations.record(&message.body);
 
         Ok(message)
     }
 
     fn write_message(&self, message: Message) -> anyhow::Result<()> {
+        let subsystem = Subsystem::of(&message.body);
+        self.deprecations.record(&message.body);
         let buffer = cbor::to_vec(message);
-        if buffer.len() > MAX_MESSAGE_SIZE {
-            return Err(ProtocolError::MessageTooLarge.into());
-        }
+        self.config.limits.check(Limit::Message, buffer.len())?;
+        self.accounting.record_bytes(subsystem, buffer.len());
 
-        let mut writer = BufWriter::new(&self.stream);
+        let mut writer = BufWriter::new(TransportIo(&*self.transport));
         writer.write_u32::<BigEndian>(buffer.len() as u32)?;
         writer.write_all(&buffer)?;
 
@@ -347,11 +806,13 @@ impl Protocol {
 
     fn handle_message<R: Read>(self: &Arc<Protocol>, reader: R) -> anyhow::Result<()> {
         let message = self.decode_message(reader)?;
+        HealthMonitor::global().record_host_message();
 
         match message.message_type {
             MessageType::Request => {
                 // Incoming request.
                 let id = message.id;
+                let lane = Lane::of_class(MessageClass::of(&message.body));
 
                 let body = match self.handle_request(id, message.body) {
                     Ok(Some(result)) => result,
@@ -364,11 +825,14 @@ impl Protocol {
                 };
 
                 // Send response back.
-                self.send_message(Message {
-                    id,
-                    message_type: MessageType::Response,
-                    body,
-                })?;
+                self.send_message(
+                    lane,
+                    Message {
+                        id,
+                        message_type: MessageType::Response,
+                        body,
+                    },
+                )?;
             }
             MessageType::Response => {
                 // Response to our request.
@@ -378,8 +842,8 @@ impl Protocol {
                 };
 
                 match response_sender {
-                    Some(response_sender) => {
-                        if response_sender.send(message.body).is_err() {
+                    Some(PendingRequest { tx, .. }) => {
+                        if tx.send(message.body).is_err() {
                             warn!(self.logger, "Unable to deliver response to local handler");
                         }
                     }
@@ -407,7 +871,8 @@ impl Protocol {
             Body::RuntimePingRequest {} => Ok(Some(Body::Empty {})),
             Body::RuntimeShutdownRequest {} => {
                 info!(self.logger, "Received worker shutdown request");
-                Err(ProtocolError::MethodNotSupported.into())
+                // Drain in-flight calls so that they are not aborted mid-stream.
+                self.queue_request(id, request)
             }
             Body::RuntimeAbortRequest {} => {
                 info!(self.logger, "Received worker abort request");
@@ -419,13 +884,14 @@ impl Protocol {
             | Body::RuntimeCapabilityTEERakReportRequest {}
             | Body::RuntimeCapabilityTEERakAvrRequest { .. }
             | Body::RuntimeCapabilityTEERakQuoteRequest { .. }
-            | Body::RuntimeCapabilityTEEUpdateEndorsementRequest { .. } => {
-                self.dispatcher.queue_request(id, request)?;
-                Ok(None)
+            | Body::RuntimeCapabilityTEEUpdateEndorsementRequest { .. }
+            | Body::RuntimeCapabilityTEECollateralLoadRequest { .. } => {
+                self.queue_request(id, request)
             }
 
             // Other requests.
             Body::RuntimeRPCCallRequest { .. }
+            | Body::RuntimeRPCStreamRequest { .. }
             | Body::RuntimeLocalRPCCallRequest { .. }
             | Body::RuntimeCheckTxBatchRequest { .. }
             | Body::RuntimeExecuteTxBatchRequest { .. }
@@ -433,10 +899,16 @@ impl Protocol {
             | Body::RuntimeKeyManagerStatusUpdateRequest { .. }
             | Body::RuntimeKeyManagerQuotePolicyUpdateRequest { .. }
             | Body::RuntimeQueryRequest { .. }
-            | Body::RuntimeConsensusSyncRequest { .. } => {
+            | Body::RuntimeConsensusSyncRequest { .. }
+            | Body::RuntimeLogConfigRequest { .. }
+            | Body::RuntimeStorageResyncRequest { .. }
+            | Body::RuntimeStorageCommitAckRequest { .. }
+            | Body::RuntimeShutdownNoticeRequest { .. }
+            | Body::RuntimeCheckpointCreateRequest { .. }
+            | Body::RuntimeCheckpointRestoreRequest { .. }
+            | Body::RuntimeCheckpointRestoreChunkRequest { .. } => {
                 self.ensure_initialized()?;
-                self.dispatcher.queue_request(id, request)?;
-                Ok(None)
+                self.queue_request(id, request)
             }
 
             _ => {
@@ -446,6 +918,65 @@ impl Protocol {
         }
     }
 
+    /// Queue a request for dispatch, returning the response in case it is rejected.
+    fn queue_request(&self, id: u64, request: Body) -> anyhow::Result<Option<Body>> {
+        self.dispatcher()?;
+
+        // Reject queries of sources exceeding their quota before they take up queue capacity.
+        if let Body::RuntimeQueryRequest { source, .. } = &request {
+            if let Err(retry_after) = self.query_throttle.admit(source) {
+                debug!(self.logger, "Throttled query";
+                    "msg_id" => id,
+                    "source" => source,
+                );
+                if self.features().contains(ProtocolFeature::QueryThrottling) {
+                    return Ok(Some(Body::RuntimeQueryThrottledResponse {
+                        source: source.clone(),
+                        retry_after_ms: retry_after.as_millis().try_into().unwrap_or(u64::MAX),
+                    }));
+                }
+                return Ok(Some(Body::Error(
+                    ProtocolError::QueryThrottled(source.clone()).into(),
+                )));
+            }
+        }
+
+        let class = MessageClass::of(&request);
+        match self.request_queues.push(id, request) {
+            Admission::Queued => Ok(None),
+            Admission::DroppedOldest(dropped) => {
+                warn!(self.logger, "Dropped oldest queued request"; "msg_id" => dropped);
+                self.send_message(
+                    Lane::of_class(class),
+                    Message {
+                        id: dropped,
+                        message_type: MessageType::Response,
+                        body: Body::Error(ProtocolError::QueueFull(class).into()),
+                    },
+                )?;
+                Ok(None)
+            }
+            Admission::Rejected {
+                depth,
+                backpressure,
+                ..
+            } => {
+                warn!(self.logger, "Rejected request as its queue is full";
+                    "msg_id" => id,
+                    "class" => %class,
+                    "depth" => depth,
+                );
+                if backpressure && self.features().contains(ProtocolFeature::Backpressure) {
+                    return Ok(Some(Body::RuntimeBackpressureResponse {
+                        class,
+                        depth: depth as u64,
+                    }));
+                }
+                Ok(Some(Body::Error(ProtocolError::QueueFull(class).into())))
+            }
+        }
+    }
+
     fn initialize_guest(
         self: &Arc<Protocol>,
         host_info: RuntimeInfoRequest,
@@ -456,6 +987,7 @@ impl Protocol {
             "consensus_protocol_version" => ?host_info.consensus_protocol_version,
             "consensus_chain_context" => &host_info.consensus_chain_context,
             "local_config" => ?host_info.local_config,
+            "features" => ?host_info.features,
         );
 
         if tendermint::BACKEND_NAME != host_info.consensus_backend {
@@ -465,6 +997,7 @@ impl Protocol {
         if local_host_info.is_some() {
             return Err(ProtocolError::AlreadyInitialized.into());
         }
+        self.deprecations.configure(host_info.deprecations.clone());
 
         // Create and start the consensus verifier.
         let consensus_verifier: Box<dyn Verifier> =
@@ -489,7 +1022,14 @@ impl Protocol {
                 let handle = verifier.handle();
                 verifier.start();
 
-                Box::new(handle)
+                match self.config.multi_head_verifier {
+                    Some(ref config) => Box::new(tendermint::verifier::MultiHeadVerifier::new(
+                        self.clone(),
+                        Arc::new(handle),
+                        config.clone(),
+                    )),
+                    None => Box::new(handle),
+                }
             } else {
                 // Create a no-op verifier.
                 let verifier = tendermint::verifier::NopVerifier::new(self.clone());
@@ -498,23 +1038,45 @@ impl Protocol {
                 Box::new(verifier)
             };
 
+        // Bind the negotiated parameters to the RAK so that downgrades can be detected.
+        let mut response = RuntimeInfoResponse {
+            protocol_version: BUILD_INFO.protocol_version,
+            runtime_version: self.config.version,
+            features: self.config.features.clone(),
+            transcript: None,
+        };
+        let transcript = HandshakeTranscript::new(&host_info, &response).sign(&*self.identity)?;
+        *self.handshake_transcript.lock().unwrap() = Some(transcript.clone());
+        response.transcript = Some(transcript);
+
         // Configure the host environment info.
+        *self.features.lock().unwrap() = ProtocolFeatures::legacy(&host_info.features);
         *local_host_info = Some(HostInfo {
             runtime_id: host_info.runtime_id,
             consensus_backend: host_info.consensus_backend,
             consensus_protocol_version: host_info.consensus_protocol_version,
             consensus_chain_context: host_info.consensus_chain_context,
             local_config: host_info.local_config,
+            features: host_info.features,
         });
 
         // Start the dispatcher.
-        self.dispatcher.start(self.clone(), consensus_verifier);
+        let consensus_verifier: Arc<dyn Verifier> = Arc::from(consensus_verifier);
+        *self.consensus_verifier.lock().unwrap() = Some(consensus_verifier.clone());
+        self.dispatcher()?
+            .start(self.clone(), Box::new(consensus_verifier));
 
-        Ok(RuntimeInfoResponse {
-            protocol_version: BUILD_INFO.protocol_version,
-            runtime_version: self.config.version,
-            features: self.config.features.clone(),
-        })
+        // Send any notification registrations made before initialization to the host.
+        self.notify_registry.schedule_sync();
+
+        Ok(response)
+    }
+
+    /// Transcript of the runtime host protocol handshake, signed by the RAK.
+    ///
+    /// Returns `None` in case the runtime has not yet been initialized.
+    pub fn get_handshake_transcript(&self) -> Option<SignedHandshakeTranscript> {
+        self.handshake_transcript.lock().unwrap().clone()
     }
 
     /// Ensure that the runtime is ready to process requests and fail otherwise.
@@ -0,0 +1,613 @@
+//! Offline replay of historical rounds.
+//!
+//! The replayer executes a supplied batch against supplied state without any connection to a
+//! host and verifies the results against the ones committed on chain. As all inputs (the block
+//! header, the batch, incoming messages and the state in the form of a checkpoint or proofs) are
+//! public, this allows anyone to independently reproduce any historical round.
+//!
+//! Rounds executed in schedule mode are replayed in execute mode using the final batch, as
+//! scheduling decisions are reflected in the committed I/O root.
+//!
+//! The entry points are meant to be wrapped by thin command line tools: a checkpoint is loaded
+//! via [`StateSource::load`], a batch is applied via [`Replayer::execute_round`], which returns
+//! all outputs of the round without verifying them, and the computed roots are compared to the
+//! committed ones via [`diff_results`]. [`Replayer::replay`] combines these steps.
+//!
+//! Batches executed by a live node can also be recorded into [traces](trace) together with all
+//! their host interactions, and re-executed from the trace via [`Replayer::replay_trace`].
+use std::{convert::TryInto, fs, io, path::Path, sync::Arc};
+
+use thiserror::Error;
+
+use crate::{
+    common::crypto::hash::Hash,
+    config::Config,
+    consensus::{
+        beacon::EpochTime,
+        roothash::{ComputeResultsHeader, Header, IncomingMessage, Message, RoundResults},
+        state::ConsensusState,
+        tendermint::{decode_light_block, state_root_from_header},
+        LightBlock,
+    },
+    future::new_tokio_runtime,
+    identity::Identity,
+    protocol::{HostInfo, Protocol},
+    storage::mkvs::{
+        sync::{build_image_from_proofs, ImageError, ImageReadSyncer, NoopReadSyncer, Proof},
+        OverlayTree, Root, RootType, WriteLog,
+    },
+    transaction::{
+        dispatcher::{Dispatcher as TxnDispatcher, ExecuteBatchResult},
+        tree::Tree as TxnTree,
+        types::TxnBatch,
+        Context as TxnContext,
+    },
+    types::{Body, Error as RuntimeError},
+};
+
+pub mod trace;
+
+pub use trace::{HostCall, StateRead, Trace, TracingDispatcher};
+
+/// Replay errors.
+#[derive(Error, Debug)]
+pub enum ReplayError {
+    #[error("state unavailable: {0}")]
+    State(#[source] anyhow::Error),
+
+    #[error("state root mismatch (expected: {expected:?} got: {got:?})")]
+    StateRootMismatch { expected: Hash, got: Hash },
+
+    #[error("consensus state root mismatch (expected: {expected:?} got: {got:?})")]
+    ConsensusStateRootMismatch { expected: Hash, got: Hash },
+
+    #[error("execution failed: {0}")]
+    Execution(#[from] RuntimeError),
+
+    #[error("results mismatch in {fields:?}")]
+    ResultsMismatch {
+        fields: Vec<&'static str>,
+        computed: Box<ComputeResultsHeader>,
+    },
+
+    #[error("host call {index} diverged from the trace")]
+    Divergence {
+        index: usize,
+        expected: Option<Box<Body>>,
+        got: Option<Box<Body>>,
+    },
+
+    #[error("results differ from the trace in {fields:?}")]
+    TraceMismatch {
+        fields: Vec<&'static str>,
+        replayed: Box<Trace>,
+    },
+}
+
+/// Source of the state a round is replayed against.
+pub enum StateSource {
+    /// Checkpoint of the state in the form of an MKVS image.
+    Checkpoint(Vec<u8>),
+    /// Proofs of the parts of the state accessed during the round, all rooted at the given root.
+    Proofs { root: Root, proofs: Vec<Proof> },
+}
+
+impl StateSource {
+    /// Load a checkpoint in the form of an MKVS image from the given file.
+    pub fn load<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        Ok(StateSource::Checkpoint(fs::read(path)?))
+    }
+
+    /// Open the state, which must have the given trusted root, reporting a mismatch using the
+    /// given error constructor.
+    fn open<F>(self, expected: Root, mismatch: F) -> Result<ImageReadSyncer<Vec<u8>>, ReplayError>
+    where
+        F: FnOnce(Hash, Hash) -> ReplayError,
+    {
+        let image = match self {
+            StateSource::Checkpoint(image) => image,
+            StateSource::Proofs { root, proofs } => {
+                let mut image = Vec::new();
+                build_image_from_proofs(&mut image, root, &proofs).map_err(ReplayError::State)?;
+                image
+            }
+        };
+        ImageReadSyncer::open(image, expected).map_err(|err| match err.downcast::<ImageError>() {
+            Ok(ImageError::RootMismatch { expected, got }) => mismatch(expected.hash, got.hash),
+            Err(err) => ReplayError::State(err),
+        })
+    }
+}
+
+/// A historical round to replay.
+#[derive(Clone, Debug, Default, cbor::Encode, cbor::Decode)]
+pub struct Round {
+    /// Header of the block the round was computed against.
+    pub header: Header,
+    /// Epoch corresponding to the block.
+    pub epoch: EpochTime,
+    /// Results of processing the previous successful round.
+    pub round_results: RoundResults,
+    /// The maximum number of messages that could be emitted in the round.
+    pub max_messages: u32,
+    /// Consensus light block the round was computed against.
+    pub consensus_block: LightBlock,
+    /// Batch of transactions executed in the round.
+    pub inputs: TxnBatch,
+    /// Incoming messages available to the round.
+    pub in_msgs: Vec<IncomingMessage>,
+    /// Results of the round as committed on chain.
+    pub expected: ComputeResultsHeader,
+}
+
+/// Everything needed to execute a historical round.
+pub struct RoundInputs {
+    /// The round to execute.
+    pub round: Round,
+    /// Runtime state the round was computed against.
+    pub runtime_state: StateSource,
+    /// Consensus layer state the round was computed against.
+    pub consensus_state: StateSource,
+}
+
+/// Outputs of executing a historical round.
+#[derive(Clone, Debug)]
+pub struct RoundOutputs {
+    /// Computed results header.
+    pub computed: ComputeResultsHeader,
+    /// Per-transaction outputs, in batch order.
+    pub outputs: Vec<Vec<u8>>,
+    /// Emitted runtime messages.
+    pub messages: Vec<Message>,
+    /// Changes to the runtime state.
+    pub write_log: WriteLog,
+    /// Results header fields in which the computed results differ from the expected ones.
+    pub mismatches: Vec<&'static str>,
+}
+
+/// Return the results header fields which differ between the computed and the expected results.
+pub fn diff_results(
+    computed: &ComputeResultsHeader,
+    expected: &ComputeResultsHeader,
+) -> Vec<&'static str> {
+    [
+        ("round", computed.round == expected.round),
+        (
+            "previous_hash",
+            computed.previous_hash == expected.previous_hash,
+        ),
+        ("io_root", computed.io_root == expected.io_root),
+        ("state_root", computed.state_root == expected.state_root),
+        (
+            "messages_hash",
+            computed.messages_hash == expected.messages_hash,
+        ),
+        (
+            "in_msgs_hash",
+            computed.in_msgs_hash == expected.in_msgs_hash,
+        ),
+        (
+            "in_msgs_count",
+            computed.in_msgs_count == expected.in_msgs_count,
+        ),
+    ]
+    .into_iter()
+    .filter(|(_, matches)| !matches)
+    .map(|(field, _)| field)
+    .collect()
+}
+
+/// Replayer of historical rounds which runs without a host.
+pub struct Replayer {
+    protocol: Arc<Protocol>,
+    txn_dispatcher: Box<dyn TxnDispatcher>,
+    tokio_runtime: tokio::runtime::Runtime,
+}
+
+impl Replayer {
+    /// Create a new replayer using the given runtime configuration, host environment and
+    /// transaction dispatcher.
+    pub fn new(
+        config: Config,
+        host_info: HostInfo,
+        txn_dispatcher: Box<dyn TxnDispatcher>,
+    ) -> Self {
+        let tokio_runtime = new_tokio_runtime();
+        let protocol = Arc::new(Protocol::offline(
+            tokio_runtime.handle().clone(),
+            Arc::new(Identity::new()),
+            config,
+            host_info,
+        ));
+
+        Self {
+            protocol,
+            txn_dispatcher,
+            tokio_runtime,
+        }
+    }
+
+    /// Replay the given round against the given runtime and consensus state and verify that the
+    /// results match the expected ones.
+    ///
+    /// Returns the computed results header.
+    pub fn replay(
+        &self,
+        round: Round,
+        runtime_state: StateSource,
+        consensus_state: StateSource,
+    ) -> Result<ComputeResultsHeader, ReplayError> {
+        let outputs = self.execute_round(RoundInputs {
+            round,
+            runtime_state,
+            consensus_state,
+        })?;
+        if !outputs.mismatches.is_empty() {
+            return Err(ReplayError::ResultsMismatch {
+                fields: outputs.mismatches,
+                computed: Box::new(outputs.computed),
+            });
+        }
+
+        Ok(outputs.computed)
+    }
+
+    /// Execute the given round against the given runtime and consensus state.
+    ///
+    /// Differences between the computed and the expected results are reported in the outputs
+    /// instead of failing the execution. Only supplying state other than the one the round was
+    /// computed against and execution failures are errors.
+    pub fn execute_round(&self, inputs: RoundInputs) -> Result<RoundOutputs, ReplayError> {
+        let RoundInputs {
+            round,
+            runtime_state,
+            consensus_state,
+        } = inputs;
+        let _guard = self.tokio_runtime.enter();
+        let storage = &self.protocol.get_config().storage;
+        let header = &round.header;
+
+        // Make sure the supplied state is the one the round was computed against.
+        let expected = Root {
+            namespace: header.namespace,
+            version: header.round,
+            root_type: RootType::State,
+            hash: header.state_root,
+        };
+        let runtime_state = runtime_state.open(expected, |expected, got| {
+            ReplayError::StateRootMismatch { expected, got }
+        })?;
+        let mut tree =
+            runtime_state.into_tree(storage.cache_node_capacity, storage.cache_value_capacity);
+
+        // Make sure the supplied consensus state is the one committed to in the consensus block.
+        let expected = consensus_state_root(&round.consensus_block)?;
+        let consensus_state = consensus_state.open(expected, |expected, got| {
+            ReplayError::ConsensusStateRootMismatch { expected, got }
+        })?;
+        let consensus_state = ConsensusState::new(
+            round.consensus_block.height,
+            consensus_state.into_tree(storage.cache_node_capacity, storage.cache_value_capacity),
+        );
+
+        let mut overlay = OverlayTree::new(&mut tree);
+        let txn_ctx = TxnContext::new(
+            self.protocol.clone(),
+            &round.consensus_block,
+            consensus_state,
+            &mut overlay,
+            header,
+            round.epoch,
+            &round.round_results,
+            round.max_messages,
+            false,
+        );
+        let results = self
+            .txn_dispatcher
+            .execute_batch(txn_ctx, &round.inputs, &round.in_msgs)?;
+        let (write_log, state_root) = overlay
+            .commit_both(header.namespace, header.round + 1)
+            .map_err(ReplayError::State)?;
+
+        let computed =
+            compute_results_header(header, &round.inputs, &round.in_msgs, &results, state_root)
+                .map_err(ReplayError::State)?;
+        let mismatches = diff_results(&computed, &round.expected);
+
+        Ok(RoundOutputs {
+            computed,
+            outputs: results.results.into_iter().map(|r| r.output).collect(),
+            messages: results.messages,
+            write_log,
+            mismatches,
+        })
+    }
+}
+
+/// State root committed to in the header of the given consensus block.
+fn consensus_state_root(block: &LightBlock) -> Result<Root, ReplayError> {
+    let meta = decode_light_block(block.clone()).map_err(ReplayError::State)?;
+    let signed_header = meta
+        .signed_header
+        .ok_or_else(|| ReplayError::State(anyhow::anyhow!("consensus block has no header")))?;
+    let header = signed_header.header();
+    if u64::from(header.height) != block.height {
+        return Err(ReplayError::State(anyhow::anyhow!(
+            "consensus block height mismatch"
+        )));
+    }
+    if <[u8; 32]>::try_from(header.app_hash.as_bytes()).is_err() {
+        return Err(ReplayError::State(anyhow::anyhow!(
+            "consensus block has an invalid application hash"
+        )));
+    }
+
+    Ok(state_root_from_header(&signed_header))
+}
+
+/// Compute the results header of the round executed on top of the given header, generating the
+/// I/O root the same way as during regular execution.
+pub(crate) fn compute_results_header(
+    header: &Header,
+    inputs: &TxnBatch,
+    in_msgs: &[IncomingMessage],
+    results: &ExecuteBatchResult,
+    state_root: Hash,
+) -> anyhow::Result<ComputeResultsHeader> {
+    let mut txn_tree = TxnTree::new(
+        Box::new(NoopReadSyncer),
+        Root {
+            namespace: header.namespace,
+            version: header.round + 1,
+            root_type: RootType::IO,
+            hash: Hash::empty_hash(),
+        },
+    );
+    for (batch_order, input) in inputs.iter().enumerate() {
+        txn_tree.add_input(input.clone(), batch_order.try_into().unwrap())?;
+    }
+    for (input, result) in inputs.iter().zip(&results.results) {
+        txn_tree.add_output(
+            Hash::digest_bytes(input),
+            result.output.clone(),
+            result.tags.clone(),
+        )?;
+    }
+    txn_tree.add_block_tags(results.block_tags.clone())?;
+    let (_, io_root) = txn_tree.commit()?;
+
+    Ok(ComputeResultsHeader {
+        round: header.round + 1,
+        previous_hash: header.encoded_hash(),
+        io_root: Some(io_root),
+        state_root: Some(state_root),
+        messages_hash: Some(Message::messages_hash(&results.messages)),
+        in_msgs_hash: Some(IncomingMessage::in_messages_hash(
+            &in_msgs[..results.in_msgs_count],
+        )),
+        in_msgs_count: results.in_msgs_count.try_into().unwrap(),
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use tendermint::{
+        account,
+        block::{self, header::Version, signed_header::SignedHeader, Commit},
+        validator, AppHash, Hash as TMHash, Time,
+    };
+
+    use super::*;
+    use crate::{
+        consensus::tendermint::{encode_light_block, LightBlockMeta},
+        storage::mkvs::sync::build_image,
+        transaction::{dispatcher::ExecuteTxResult, tags::Tags},
+        types::CheckTxResult,
+    };
+
+    /// Dispatcher which stores every transaction in the state and echoes it as output.
+    struct EchoDispatcher;
+
+    impl TxnDispatcher for EchoDispatcher {
+        fn execute_batch(
+            &self,
+            mut ctx: TxnContext,
+            batch: &TxnBatch,
+            in_msgs: &[IncomingMessage],
+        ) -> Result<ExecuteBatchResult, RuntimeError> {
+            let results = batch
+                .iter()
+                .map(|tx| {
+                    ctx.runtime_state.insert(tx, tx);
+                    ExecuteTxResult {
+                        output: tx.clone(),
+                        tags: Tags::new(),
+                    }
+                })
+                .collect();
+
+            Ok(ExecuteBatchResult {
+                results,
+                messages: vec![],
+                in_msgs_count: in_msgs.len(),
+                block_tags: Tags::new(),
+                tx_reject_hashes: vec![],
+            })
+        }
+
+        fn check_batch(
+            &self,
+            _ctx: TxnContext,
+            _batch: &TxnBatch,
+        ) -> Result<Vec<CheckTxResult>, RuntimeError> {
+            Ok(vec![])
+        }
+    }
+
+    /// Checkpoint of the state at the given version.
+    fn checkpoint(version: u64, entries: Vec<(Vec<u8>, Vec<u8>)>) -> (Vec<u8>, Hash) {
+        let mut image = Vec::new();
+        let root = build_image(
+            &mut image,
+            Default::default(),
+            version,
+            RootType::State,
+            entries,
+        )
+        .unwrap();
+        (image, root.hash)
+    }
+
+    /// Consensus block at height 1 committing to the given consensus state root.
+    fn consensus_block(state_root: Hash) -> LightBlock {
+        let header = block::Header {
+            version: Version { block: 11, app: 0 },
+            chain_id: "test".parse().unwrap(),
+            height: 1u32.into(),
+            time: Time::unix_epoch(),
+            last_block_id: None,
+            last_commit_hash: None,
+            data_hash: None,
+            validators_hash: TMHash::None,
+            next_validators_hash: TMHash::None,
+            consensus_hash: TMHash::None,
+            app_hash: AppHash::try_from(state_root.0.to_vec()).unwrap(),
+            last_results_hash: None,
+            evidence_hash: None,
+            proposer_address: account::Id::new([0; 20]),
+        };
+        let commit = Commit {
+            height: header.height,
+            round: Default::default(),
+            block_id: Default::default(),
+            signatures: vec![],
+        };
+
+        encode_light_block(LightBlockMeta {
+            signed_header: Some(SignedHeader::new(header, commit).unwrap()),
+            validators: validator::Set::without_proposer(vec![]),
+        })
+        .unwrap()
+    }
+
+    fn replayer() -> Replayer {
+        Replayer::new(
+            Config::default(),
+            HostInfo {
+                runtime_id: Default::default(),
+                consensus_backend: "tendermint".to_string(),
+                consensus_protocol_version: Default::default(),
+                consensus_chain_context: "test".to_string(),
+                local_config: Default::default(),
+                features: Default::default(),
+            },
+            Box::new(EchoDispatcher),
+        )
+    }
+
+    #[test]
+    fn test_replay() {
+        let replayer = replayer();
+
+        let state = vec![(b"existing".to_vec(), b"value".to_vec())];
+        let (image, state_root) = checkpoint(1, state);
+        let (consensus_image, consensus_root) = checkpoint(0, vec![]);
+        let mut round = Round {
+            header: Header {
+                round: 1,
+                state_root,
+                ..Default::default()
+            },
+            consensus_block: consensus_block(consensus_root),
+            inputs: vec![b"tx1".to_vec(), b"tx2".to_vec()].into(),
+            ..Default::default()
+        };
+        let consensus = || StateSource::Checkpoint(consensus_image.clone());
+
+        // Results not matching the committed ones are detected.
+        let computed = match replayer.replay(
+            round.clone(),
+            StateSource::Checkpoint(image.clone()),
+            consensus(),
+        ) {
+            Err(ReplayError::ResultsMismatch { fields, computed }) => {
+                assert!(fields.contains(&"state_root"));
+                computed
+            }
+            result => panic!("unexpected replay result: {result:?}"),
+        };
+        assert_eq!(computed.round, 2);
+
+        round.expected = *computed.clone();
+        let result = replayer
+            .replay(
+                round.clone(),
+                StateSource::Checkpoint(image.clone()),
+                consensus(),
+            )
+            .unwrap();
+        assert_eq!(result, *computed);
+
+        // Replaying against different state is rejected.
+        let (other, _) = checkpoint(1, vec![]);
+        assert!(matches!(
+            replayer.replay(round.clone(), StateSource::Checkpoint(other), consensus()),
+            Err(ReplayError::StateRootMismatch { .. })
+        ));
+
+        // Replaying against different consensus state is rejected.
+        let (other, _) = checkpoint(0, vec![(b"key".to_vec(), b"value".to_vec())]);
+        assert!(matches!(
+            replayer.replay(
+                round.clone(),
+                StateSource::Checkpoint(image.clone()),
+                StateSource::Checkpoint(other),
+            ),
+            Err(ReplayError::ConsensusStateRootMismatch { .. })
+        ));
+
+        // Replaying without a consensus block header is rejected.
+        round.consensus_block = LightBlock::default();
+        assert!(matches!(
+            replayer.replay(round, StateSource::Checkpoint(image), consensus()),
+            Err(ReplayError::State(_))
+        ));
+    }
+
+    #[test]
+    fn test_execute_round() {
+        let (image, state_root) = checkpoint(1, vec![]);
+        let path = std::env::temp_dir().join(format!("replay-{}.image", std::process::id()));
+        fs::write(&path, &image).unwrap();
+        let runtime_state = StateSource::load(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+        let (consensus_image, consensus_root) = checkpoint(0, vec![]);
+
+        let round = Round {
+            header: Header {
+                round: 1,
+                state_root,
+                ..Default::default()
+            },
+            consensus_block: consensus_block(consensus_root),
+            inputs: vec![b"tx1".to_vec()].into(),
+            ..Default::default()
+        };
+        let outputs = replayer()
+            .execute_round(RoundInputs {
+                round,
+                runtime_state,
+                consensus_state: StateSource::Checkpoint(consensus_image),
+            })
+            .unwrap();
+        assert_eq!(outputs.outputs, vec![b"tx1".to_vec()]);
+        assert!(outputs.messages.is_empty());
+        assert_eq!(outputs.write_log.len(), 1);
+        assert_eq!(outputs.write_log[0].key, b"tx1".to_vec());
+        assert_eq!(
+            outputs.mismatches,
+            diff_results(&outputs.computed, &Default::default())
+        );
+        assert!(diff_results(&outputs.computed, &outputs.computed).is_empty());
+    }
+}
@@ -0,0 +1,827 @@
+//! Recording and replay of batch execution traces.
+//!
+//! Faults where executors disagree on the results of the same batch are hard to debug, as the
+//! host interactions which led to the results are gone by the time the fault is noticed. A
+//! [`TracingDispatcher`] wraps the transaction dispatcher and records everything a batch
+//! execution depends on into a [`Trace`]:
+//!
+//! * the executed round, as for offline replay,
+//! * all host calls together with their responses, in the order they were made, including the
+//!   consensus state sync requests,
+//! * the values of all runtime state keys read during execution, as of the start of the batch,
+//! * the outputs, messages and writes resulting from the execution.
+//!
+//! Runtime state sync requests are not recorded as host calls, as they depend on the contents of
+//! the node cache rather than on the batch. The recorded reads are served from memory instead.
+//!
+//! Traces are encoded as CBOR blobs which can be moved to another machine and re-executed via
+//! [`Replayer::replay_trace`] without a host. Host calls are answered from the trace, and a
+//! request differing from the recorded one at any host call boundary is reported as a
+//! divergence, pinpointing where the executions took different paths.
+//!
+//! Only batches executed in execute mode are traced. As just the read values of the runtime state
+//! are recorded, proofs of the runtime state can't be reproduced during replay.
+use std::{
+    cell::RefCell,
+    collections::BTreeMap,
+    convert::TryInto,
+    sync::{atomic::AtomicBool, Arc, Mutex},
+};
+
+use anyhow::{Error, Result};
+
+use super::{ReplayError, Replayer, Round};
+use crate::{
+    common::{crypto::hash::Hash, namespace::Namespace},
+    consensus::{
+        roothash::{IncomingMessage, Message},
+        state::ConsensusState,
+    },
+    protocol::HostCallTap,
+    storage::mkvs::{
+        self, sync::NoopReadSyncer, tree::Key, LogEntry, OverlayTree, Prefix, Proof, Root,
+        RootType, Tree, WriteLog, MKVS,
+    },
+    transaction::{
+        dispatcher::{Dispatcher as TxnDispatcher, ExecuteBatchResult},
+        types::TxnBatch,
+        Context as TxnContext,
+    },
+    types::{Body, CheckTxResult, Error as RuntimeError, HostStorageEndpoint, StorageSyncRequest},
+};
+
+/// Error module used for host calls diverging from the trace.
+const MODULE_NAME: &str = "replay";
+/// Error code used for host calls diverging from the trace.
+const CODE_DIVERGED: u32 = 1;
+
+/// A host call made during batch execution.
+#[derive(Clone, Debug, Default, PartialEq, Eq, cbor::Encode, cbor::Decode)]
+pub struct HostCall {
+    /// CBOR-encoded request body.
+    pub request: Vec<u8>,
+    /// CBOR-encoded response body, with failed calls encoded as `Body::Error`.
+    pub response: Vec<u8>,
+}
+
+/// Value of a runtime state key as of the start of the batch.
+#[derive(Clone, Debug, Default, PartialEq, Eq, cbor::Encode, cbor::Decode)]
+#[cbor(as_array)]
+pub struct StateRead {
+    /// The key that was read.
+    pub key: Vec<u8>,
+    /// The value of the key (empty if the key was not present).
+    pub value: Option<Vec<u8>>,
+}
+
+/// Trace of a batch execution.
+#[derive(Clone, Debug, Default, cbor::Encode, cbor::Decode)]
+pub struct Trace {
+    /// The executed round. The expected results are not known while executing.
+    pub round: Round,
+    /// Runtime state read during execution, in key order.
+    pub runtime_reads: Vec<StateRead>,
+    /// Host calls made during execution, in the order they were made.
+    pub host_calls: Vec<HostCall>,
+    /// Per-transaction outputs, in batch order.
+    pub outputs: Vec<Vec<u8>>,
+    /// Emitted runtime messages.
+    pub messages: Vec<Message>,
+    /// Number of processed incoming messages.
+    pub in_msgs_count: u64,
+    /// Changes to the runtime state, in key order.
+    pub write_log: WriteLog,
+}
+
+impl Trace {
+    fn new(
+        round: Round,
+        state: TracedState<'_>,
+        host_calls: Vec<HostCall>,
+        results: &ExecuteBatchResult,
+    ) -> Self {
+        let (runtime_reads, write_log) = state.into_parts();
+
+        Self {
+            round,
+            runtime_reads,
+            host_calls,
+            outputs: results.results.iter().map(|r| r.output.clone()).collect(),
+            messages: results.messages.clone(),
+            in_msgs_count: results.in_msgs_count.try_into().unwrap(),
+            write_log,
+        }
+    }
+
+    /// Root of the consensus state read during execution, as requested by the first recorded
+    /// consensus state sync request.
+    fn consensus_root(&self) -> Root {
+        self.host_calls
+            .iter()
+            .find_map(|call| match cbor::from_slice(&call.request) {
+                Ok(Body::HostStorageSyncRequest(request))
+                    if request.endpoint == HostStorageEndpoint::Consensus =>
+                {
+                    Some(match request.request {
+                        StorageSyncRequest::SyncGet(request) => request.tree.root,
+                        StorageSyncRequest::SyncGetPrefixes(request) => request.tree.root,
+                        StorageSyncRequest::SyncIterate(request) => request.tree.root,
+                        StorageSyncRequest::SyncGetPrefix(request) => request.tree.root,
+                    })
+                }
+                _ => None,
+            })
+            .unwrap_or_default()
+    }
+
+    /// Return the fields in which the results of the given trace differ from this one.
+    fn diff(&self, other: &Trace) -> Vec<&'static str> {
+        [
+            ("runtime_reads", self.runtime_reads == other.runtime_reads),
+            ("outputs", self.outputs == other.outputs),
+            ("messages", self.messages == other.messages),
+            ("in_msgs_count", self.in_msgs_count == other.in_msgs_count),
+            ("write_log", self.write_log == other.write_log),
+        ]
+        .into_iter()
+        .filter(|(_, matches)| !matches)
+        .map(|(field, _)| field)
+        .collect()
+    }
+}
+
+/// Return the encoding of the given request as recorded in traces, or `None` in case the request
+/// is not traced.
+///
+/// Sources excluded from storage sync requests depend on earlier failures of the node, so they
+/// are omitted.
+fn canonical_request(request: &[u8]) -> Option<Vec<u8>> {
+    match cbor::from_slice(request) {
+        Ok(Body::HostStorageSyncRequest(request))
+            if request.endpoint == HostStorageEndpoint::Runtime =>
+        {
+            None
+        }
+        Ok(Body::HostStorageSyncRequest(mut request)) => {
+            request.excluded_sources.clear();
+            Some(cbor::to_vec(Body::HostStorageSyncRequest(request)))
+        }
+        _ => Some(request.to_vec()),
+    }
+}
+
+/// Host call tap recording all traced host calls.
+#[derive(Default)]
+struct TraceRecorder {
+    calls: Mutex<Vec<HostCall>>,
+}
+
+impl HostCallTap for TraceRecorder {
+    fn request(&self, _request: &[u8]) -> Option<Vec<u8>> {
+        None
+    }
+
+    fn response(&self, request: &[u8], response: &[u8]) {
+        if let Some(request) = canonical_request(request) {
+            self.calls.lock().unwrap().push(HostCall {
+                request,
+                response: response.to_vec(),
+            });
+        }
+    }
+}
+
+/// Point at which a replayed execution diverged from the trace.
+struct Divergence {
+    index: usize,
+    expected: Option<Vec<u8>>,
+    got: Option<Vec<u8>>,
+}
+
+impl From<Divergence> for ReplayError {
+    fn from(divergence: Divergence) -> Self {
+        let decode = |data: Vec<u8>| cbor::from_slice(&data).ok().map(Box::new);
+
+        ReplayError::Divergence {
+            index: divergence.index,
+            expected: divergence.expected.and_then(decode),
+            got: divergence.got.and_then(decode),
+        }
+    }
+}
+
+/// Host call tap answering host calls from a trace.
+struct TracePlayer {
+    calls: Vec<HostCall>,
+    state: Mutex<(usize, Option<Divergence>)>,
+}
+
+impl TracePlayer {
+    fn new(calls: Vec<HostCall>) -> Self {
+        Self {
+            calls,
+            state: Mutex::new((0, None)),
+        }
+    }
+
+    /// Return the point of divergence, if any, including recorded calls which were not made.
+    fn finish(&self) -> Option<Divergence> {
+        let (next, divergence) = &mut *self.state.lock().unwrap();
+        divergence.take().or_else(|| {
+            self.calls.get(*next).map(|call| Divergence {
+                index: *next,
+                expected: Some(call.request.clone()),
+                got: None,
+            })
+        })
+    }
+}
+
+impl HostCallTap for TracePlayer {
+    fn request(&self, request: &[u8]) -> Option<Vec<u8>> {
+        let request = canonical_request(request).unwrap_or_else(|| request.to_vec());
+        let (next, divergence) = &mut *self.state.lock().unwrap();
+        if divergence.is_none() {
+            match self.calls.get(*next) {
+                Some(call) if call.request == request => {
+                    *next += 1;
+                    return Some(call.response.clone());
+                }
+                expected => {
+                    *divergence = Some(Divergence {
+                        index: *next,
+                        expected: expected.map(|call| call.request.clone()),
+                        got: Some(request),
+                    });
+                }
+            }
+        }
+
+        // Fail all calls after the divergence, as the trace no longer applies.
+        Some(cbor::to_vec(Body::Error(RuntimeError::new(
+            MODULE_NAME,
+            CODE_DIVERGED,
+            "host call diverged from trace",
+        ))))
+    }
+
+    fn response(&self, _request: &[u8], _response: &[u8]) {}
+}
+
+/// Runtime state wrapper recording the values of all keys read as of its creation, as well as
+/// all writes made through it.
+struct TracedState<'a> {
+    inner: &'a mut dyn MKVS,
+    reads: RefCell<BTreeMap<Vec<u8>, Option<Vec<u8>>>>,
+    writes: BTreeMap<Vec<u8>, Option<Vec<u8>>>,
+}
+
+impl<'a> TracedState<'a> {
+    fn new(inner: &'a mut dyn MKVS) -> Self {
+        Self {
+            inner,
+            reads: RefCell::new(BTreeMap::new()),
+            writes: BTreeMap::new(),
+        }
+    }
+
+    fn record_read(&self, key: &[u8], value: &Option<Vec<u8>>) {
+        record_read(&self.reads, &self.writes, key, value)
+    }
+
+    fn record_write(&mut self, key: &[u8], value: Option<&[u8]>, previous: &Option<Vec<u8>>) {
+        self.record_read(key, previous);
+        self.writes.insert(key.to_vec(), value.map(<[u8]>::to_vec));
+    }
+
+    fn into_parts(self) -> (Vec<StateRead>, WriteLog) {
+        let reads = self
+            .reads
+            .into_inner()
+            .into_iter()
+            .map(|(key, value)| StateRead { key, value })
+            .collect();
+        let writes = self
+            .writes
+            .into_iter()
+            .map(|(key, value)| LogEntry { key, value })
+            .collect();
+        (reads, writes)
+    }
+}
+
+/// Record the read value of the given key, unless it has been written to, in which case the
+/// value no longer reflects the state as of the start of the batch.
+fn record_read(
+    reads: &RefCell<BTreeMap<Vec<u8>, Option<Vec<u8>>>>,
+    writes: &BTreeMap<Vec<u8>, Option<Vec<u8>>>,
+    key: &[u8],
+    value: &Option<Vec<u8>>,
+) {
+    if writes.contains_key(key) {
+        return;
+    }
+    reads
+        .borrow_mut()
+        .entry(key.to_vec())
+        .or_insert_with(|| value.clone());
+}
+
+impl MKVS for TracedState<'_> {
+    fn get(&self, key: &[u8]) -> Option<Vec<u8>> {
+        let value = self.inner.get(key);
+        self.record_read(key, &value);
+        value
+    }
+
+    fn get_proof(&self, key: &[u8]) -> Option<Proof> {
+        self.inner.get_proof(key)
+    }
+
+    fn cache_contains_key(&self, key: &[u8]) -> bool {
+        self.inner.cache_contains_key(key)
+    }
+
+    fn insert(&mut self, key: &[u8], value: &[u8]) -> Option<Vec<u8>> {
+        let previous = self.inner.insert(key, value);
+        self.record_write(key, Some(value), &previous);
+        previous
+    }
+
+    fn remove(&mut self, key: &[u8]) -> Option<Vec<u8>> {
+        let previous = self.inner.remove(key);
+        self.record_write(key, None, &previous);
+        previous
+    }
+
+    fn prefetch_prefixes(&self, prefixes: &[Prefix], limit: u16) {
+        self.inner.prefetch_prefixes(prefixes, limit)
+    }
+
+    fn prefetch_prefix(&self, prefix: &Prefix, max_size: u64) {
+        self.inner.prefetch_prefix(prefix, max_size)
+    }
+
+    fn iter(&self) -> Box<dyn mkvs::Iterator + '_> {
+        Box::new(TracedIterator {
+            inner: self.inner.iter(),
+            reads: &self.reads,
+            writes: &self.writes,
+        })
+    }
+
+    fn commit(&mut self, namespace: Namespace, version: u64) -> Result<(WriteLog, Hash)> {
+        self.inner.commit(namespace, version)
+    }
+}
+
+/// An iterator recording the value of each visited key.
+///
+/// Iteration is contiguous, so the recorded keys include all keys in the iterated ranges.
+struct TracedIterator<'a> {
+    inner: Box<dyn mkvs::Iterator + 'a>,
+    reads: &'a RefCell<BTreeMap<Vec<u8>, Option<Vec<u8>>>>,
+    writes: &'a BTreeMap<Vec<u8>, Option<Vec<u8>>>,
+}
+
+impl TracedIterator<'_> {
+    fn record(&self) {
+        if let Some(key) = self.inner.get_key() {
+            record_read(self.reads, self.writes, key, self.inner.get_value());
+        }
+    }
+}
+
+impl Iterator for TracedIterator<'_> {
+    type Item = (Vec<u8>, Vec<u8>);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        use mkvs::Iterator;
+
+        if !self.is_valid() {
+            return None;
+        }
+
+        let key = self.get_key().clone().expect("iterator is valid");
+        let value = self.get_value().clone().expect("iterator is valid");
+        mkvs::Iterator::next(self);
+
+        Some((key, value))
+    }
+}
+
+impl mkvs::Iterator for TracedIterator<'_> {
+    fn set_prefetch(&mut self, prefetch: usize) {
+        self.inner.set_prefetch(prefetch)
+    }
+
+    fn is_valid(&self) -> bool {
+        self.inner.is_valid()
+    }
+
+    fn error(&self) -> &Option<Error> {
+        self.inner.error()
+    }
+
+    fn rewind(&mut self) {
+        self.inner.rewind();
+        self.record();
+    }
+
+    fn seek(&mut self, key: &[u8]) {
+        self.inner.seek(key);
+        self.record();
+    }
+
+    fn get_key(&self) -> &Option<Key> {
+        self.inner.get_key()
+    }
+
+    fn get_value(&self) -> &Option<Vec<u8>> {
+        self.inner.get_value()
+    }
+
+    fn next(&mut self) {
+        self.inner.next();
+        self.record();
+    }
+}
+
+/// Transaction dispatcher wrapper which records a trace of each executed batch.
+///
+/// As host calls are recorded via a tap on the protocol, calls made concurrently on behalf of
+/// other requests (e.g. queries) during batch execution end up in the trace as well.
+pub struct TracingDispatcher {
+    inner: Box<dyn TxnDispatcher>,
+    sink: Box<dyn Fn(Trace) + Send + Sync>,
+}
+
+impl TracingDispatcher {
+    /// Wrap the given dispatcher, passing the traces of successfully executed batches to the
+    /// given sink.
+    pub fn new<F>(inner: Box<dyn TxnDispatcher>, sink: F) -> Self
+    where
+        F: Fn(Trace) + Send + Sync + 'static,
+    {
+        Self {
+            inner,
+            sink: Box::new(sink),
+        }
+    }
+}
+
+impl TxnDispatcher for TracingDispatcher {
+    fn is_supported(&self) -> bool {
+        self.inner.is_supported()
+    }
+
+    fn execute_batch(
+        &self,
+        ctx: TxnContext,
+        batch: &TxnBatch,
+        in_msgs: &[IncomingMessage],
+    ) -> Result<ExecuteBatchResult, RuntimeError> {
+        let round = Round {
+            header: ctx.header.clone(),
+            epoch: ctx.epoch,
+            round_results: ctx.round_results.clone(),
+            max_messages: ctx.max_messages,
+            consensus_block: ctx.consensus_block.clone(),
+            inputs: batch.clone(),
+            in_msgs: in_msgs.to_vec(),
+            expected: Default::default(),
+        };
+
+        // Rebuild the context with the runtime state traced.
+        let TxnContext {
+            protocol,
+            consensus_block,
+            consensus_state,
+            runtime_state,
+            header,
+            epoch,
+            round_results,
+            max_messages,
+            check_only,
+            call_auth,
+            deferred,
+        } = ctx;
+        let mut state = TracedState::new(runtime_state);
+        let mut ctx = TxnContext::new(
+            protocol.clone(),
+            consensus_block,
+            consensus_state,
+            &mut state,
+            header,
+            epoch,
+            round_results,
+            max_messages,
+            check_only,
+        );
+        ctx.call_auth = call_auth;
+        ctx.deferred = deferred;
+
+        let recorder = Arc::new(TraceRecorder::default());
+        let previous = protocol.set_host_call_tap(Some(recorder.clone()));
+        let result = self.inner.execute_batch(ctx, batch, in_msgs);
+        protocol.set_host_call_tap(previous);
+        let results = result?;
+
+        let host_calls = std::mem::take(&mut *recorder.calls.lock().unwrap());
+        (self.sink)(Trace::new(round, state, host_calls, &results));
+
+        Ok(results)
+    }
+
+    fn schedule_and_execute_batch(
+        &self,
+        ctx: TxnContext,
+        initial_batch: &mut TxnBatch,
+        in_msgs: &[IncomingMessage],
+    ) -> Result<ExecuteBatchResult, RuntimeError> {
+        self.inner
+            .schedule_and_execute_batch(ctx, initial_batch, in_msgs)
+    }
+
+    fn check_batch(
+        &self,
+        ctx: TxnContext,
+        batch: &TxnBatch,
+    ) -> Result<Vec<CheckTxResult>, RuntimeError> {
+        self.inner.check_batch(ctx, batch)
+    }
+
+    fn finalize(&self, new_storage_root: Hash) {
+        self.inner.finalize(new_storage_root)
+    }
+
+    fn set_abort_batch_flag(&mut self, abort_batch: Arc<AtomicBool>) {
+        self.inner.set_abort_batch_flag(abort_batch)
+    }
+
+    fn query(&self, ctx: TxnContext, method: &str, args: Vec<u8>) -> Result<Vec<u8>, RuntimeError> {
+        self.inner.query(ctx, method, args)
+    }
+}
+
+impl Replayer {
+    /// Re-execute the batch recorded in the given trace, answering host calls from the trace.
+    ///
+    /// Fails with `ReplayError::Divergence` at the first host call differing from the recorded
+    /// one, and with `ReplayError::TraceMismatch` in case the results differ from the recorded
+    /// ones. Returns the trace of the replayed execution.
+    pub fn replay_trace(&self, trace: &Trace) -> Result<Trace, ReplayError> {
+        let _guard = self.tokio_runtime.enter();
+        let round = &trace.round;
+
+        // Serve the runtime state read during execution from memory.
+        let mut tree = Tree::builder()
+            .with_root_type(RootType::State)
+            .build(Box::new(NoopReadSyncer));
+        for read in &trace.runtime_reads {
+            if let Some(value) = &read.value {
+                tree.insert(&read.key, value).map_err(ReplayError::State)?;
+            }
+        }
+        let mut overlay = OverlayTree::new(&mut tree);
+        let mut state = TracedState::new(&mut overlay);
+
+        let player = Arc::new(TracePlayer::new(trace.host_calls.clone()));
+        let previous = self.protocol.set_host_call_tap(Some(player.clone()));
+        let consensus_state = ConsensusState::from_protocol(
+            self.protocol.clone(),
+            round.consensus_block.height,
+            trace.consensus_root(),
+        );
+        let txn_ctx = TxnContext::new(
+            self.protocol.clone(),
+            &round.consensus_block,
+            consensus_state,
+            &mut state,
+            &round.header,
+            round.epoch,
+            &round.round_results,
+            round.max_messages,
+            false,
+        );
+        let result = self
+            .txn_dispatcher
+            .execute_batch(txn_ctx, &round.inputs, &round.in_msgs);
+        self.protocol.set_host_call_tap(previous);
+
+        if let Some(divergence) = player.finish() {
+            return Err(divergence.into());
+        }
+        let results = result?;
+
+        let replayed = Trace::new(round.clone(), state, trace.host_calls.clone(), &results);
+        let fields = trace.diff(&replayed);
+        if !fields.is_empty() {
+            return Err(ReplayError::TraceMismatch {
+                fields,
+                replayed: Box::new(replayed),
+            });
+        }
+
+        Ok(replayed)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{
+        common::crypto::signature::PublicKey,
+        config::Config,
+        consensus::{roothash::Header, LightBlock},
+        protocol::HostInfo,
+        testkit::host::LoopbackProtocol,
+        transaction::{dispatcher::ExecuteTxResult, tags::Tags},
+    };
+
+    /// Dispatcher which stores the host node identity under every transaction, outputting the
+    /// previous value.
+    struct IdentityDispatcher;
+
+    impl TxnDispatcher for IdentityDispatcher {
+        fn execute_batch(
+            &self,
+            ctx: TxnContext,
+            batch: &TxnBatch,
+            in_msgs: &[IncomingMessage],
+        ) -> Result<ExecuteBatchResult, RuntimeError> {
+            let mut results = Vec::new();
+            for tx in batch.iter() {
+                let node_id = match ctx.protocol.call_host(Body::HostIdentityRequest {})? {
+                    Body::HostIdentityResponse { node_id } => node_id,
+                    _ => return Err(RuntimeError::new("test", 1, "unexpected response")),
+                };
+                let previous = ctx.runtime_state.insert(tx, node_id.as_ref());
+                results.push(ExecuteTxResult {
+                    output: previous.unwrap_or_default(),
+                    tags: Tags::new(),
+                });
+            }
+
+            Ok(ExecuteBatchResult {
+                results,
+                messages: vec![],
+                in_msgs_count: in_msgs.len(),
+                block_tags: Tags::new(),
+                tx_reject_hashes: vec![],
+            })
+        }
+
+        fn check_batch(
+            &self,
+            _ctx: TxnContext,
+            _batch: &TxnBatch,
+        ) -> Result<Vec<CheckTxResult>, RuntimeError> {
+            Ok(vec![])
+        }
+    }
+
+    fn host_info() -> HostInfo {
+        HostInfo {
+            runtime_id: Default::default(),
+            consensus_backend: "tendermint".to_string(),
+            consensus_protocol_version: Default::default(),
+            consensus_chain_context: "test".to_string(),
+            local_config: Default::default(),
+            features: Default::default(),
+        }
+    }
+
+    fn record(inputs: Vec<Vec<u8>>) -> Trace {
+        let tokio_runtime = tokio::runtime::Runtime::new().unwrap();
+        let _guard = tokio_runtime.enter();
+        let loopback =
+            LoopbackProtocol::new(
+                tokio_runtime.handle().clone(),
+                host_info(),
+                |body| match body {
+                    Body::HostIdentityRequest {} => Some(Body::HostIdentityResponse {
+                        node_id: PublicKey([7; 32]),
+                    }),
+                    _ => None,
+                },
+            );
+        let traces = Arc::new(Mutex::new(Vec::new()));
+        let sink = traces.clone();
+        let dispatcher = TracingDispatcher::new(Box::new(IdentityDispatcher), move |trace| {
+            sink.lock().unwrap().push(trace)
+        });
+
+        let mut tree = Tree::builder()
+            .with_root_type(RootType::State)
+            .build(Box::new(NoopReadSyncer));
+        tree.insert(b"tx1", b"existing").unwrap();
+        let mut overlay = OverlayTree::new(&mut tree);
+        let consensus_tree = Tree::builder()
+            .with_root_type(RootType::State)
+            .build(Box::new(NoopReadSyncer));
+        let consensus_block = LightBlock::default();
+        let header = Header {
+            round: 1,
+            ..Default::default()
+        };
+        let round_results = Default::default();
+        let ctx = TxnContext::new(
+            loopback.protocol().clone(),
+            &consensus_block,
+            ConsensusState::new(0, consensus_tree),
+            &mut overlay,
+            &header,
+            0,
+            &round_results,
+            0,
+            false,
+        );
+        dispatcher.execute_batch(ctx, &inputs.into(), &[]).unwrap();
+
+        let trace = traces.lock().unwrap().pop();
+        trace.unwrap()
+    }
+
+    #[test]
+    fn test_record_trace() {
+        let trace = record(vec![b"tx1".to_vec(), b"tx2".to_vec(), b"tx1".to_vec()]);
+        assert_eq!(trace.round.header.round, 1);
+        assert_eq!(trace.host_calls.len(), 3);
+        assert_eq!(
+            trace.outputs,
+            vec![b"existing".to_vec(), vec![], vec![7; 32]]
+        );
+
+        // Only the values as of the start of the batch are recorded.
+        assert_eq!(
+            trace.runtime_reads,
+            vec![
+                StateRead {
+                    key: b"tx1".to_vec(),
+                    value: Some(b"existing".to_vec()),
+                },
+                StateRead {
+                    key: b"tx2".to_vec(),
+                    value: None,
+                },
+            ]
+        );
+        assert_eq!(trace.write_log.len(), 2);
+    }
+
+    #[test]
+    fn test_replay_trace() {
+        let trace = record(vec![b"tx1".to_vec(), b"tx2".to_vec()]);
+        let blob = cbor::to_vec(trace);
+        let trace: Trace = cbor::from_slice(&blob).unwrap();
+        let replayer = Replayer::new(Config::default(), host_info(), Box::new(IdentityDispatcher));
+
+        let replayed = replayer.replay_trace(&trace).unwrap();
+        assert_eq!(replayed.outputs, trace.outputs);
+        assert_eq!(replayed.write_log, trace.write_log);
+
+        // Host calls differing from the recorded ones are detected at the call boundary.
+        let mut diverging = trace.clone();
+        diverging.host_calls[1].request = cbor::to_vec(Body::HostIdentityResponse {
+            node_id: PublicKey([1; 32]),
+        });
+        match replayer.replay_trace(&diverging) {
+            Err(ReplayError::Divergence {
+                index,
+                expected,
+                got,
+            }) => {
+                assert_eq!(index, 1);
+                assert!(matches!(
+                    expected.as_deref(),
+                    Some(Body::HostIdentityResponse { .. })
+                ));
+                assert!(matches!(got.as_deref(), Some(Body::HostIdentityRequest {})));
+            }
+            result => panic!("unexpected replay result: {result:?}"),
+        }
+
+        // Recorded host calls which are not made are detected as well.
+        let mut diverging = trace.clone();
+        diverging.round.inputs = vec![b"tx1".to_vec()].into();
+        assert!(matches!(
+            replayer.replay_trace(&diverging),
+            Err(ReplayError::Divergence {
+                index: 1,
+                got: None,
+                ..
+            })
+        ));
+
+        // Results differing from the recorded ones are reported, e.g. if the trace is corrupted.
+        let mut mismatching = trace;
+        mismatching.runtime_reads[0].value = Some(b"other".to_vec());
+        match replayer.replay_trace(&mismatching) {
+            Err(ReplayError::TraceMismatch { fields, .. }) => {
+                assert_eq!(fields, vec!["outputs"]);
+            }
+            result => panic!("unexpected replay result: {result:?}"),
+        }
+    }
+}
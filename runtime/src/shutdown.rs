@@ -0,0 +1,389 @@
+//! Shutdown orchestration.
+//!
+//! When the runtime is asked to shut down, its subsystems are torn down in dependency order as a
+//! sequence of [`Stage`]s: in-flight RPC calls and transaction batches are drained first, so that
+//! they don't produce any new state, then background tasks are stopped, write-ahead logs are
+//! flushed and caches persisted, and finally in-flight host calls are settled and buffered
+//! telemetry is flushed before the protocol is closed. Each stage is bounded by its own timeout
+//! and by the remaining grace period, and the outcome of each stage is reported to the host as
+//! part of the shutdown acknowledgement.
+//!
+//! Components holding buffered state register their own steps with [`ShutdownHooks`] instead of
+//! relying on being dropped, as the host may terminate the runtime before destructors get to run
+//! and destructors run in an order which doesn't follow the dependencies between components.
+use std::{
+    collections::{HashMap, HashSet},
+    future::Future,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use anyhow::{anyhow, Result};
+use async_trait::async_trait;
+use futures::future::{join_all, BoxFuture};
+use slog::{info, warn, Logger};
+
+use crate::common::logger::get_logger;
+
+/// Time after which steps which didn't complete within the budget of their stage are abandoned,
+/// so that steps enforcing the budget themselves get to wind down.
+const ABANDON_DELAY: Duration = Duration::from_millis(100);
+
+/// Stage of the shutdown sequence.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Stage {
+    /// Draining in-flight RPC calls and transaction batches.
+    RpcDrain,
+    /// Stopping background tasks.
+    BackgroundTasks,
+    /// Flushing write-ahead logs.
+    WalFlush,
+    /// Persisting caches and application state.
+    CachePersist,
+    /// Settling host calls and flushing telemetry before the protocol is closed.
+    ProtocolClose,
+}
+
+impl Stage {
+    /// All stages, in the order in which they are run.
+    pub const ALL: [Stage; 5] = [
+        Stage::RpcDrain,
+        Stage::BackgroundTasks,
+        Stage::WalFlush,
+        Stage::CachePersist,
+        Stage::ProtocolClose,
+    ];
+
+    /// Name of the stage, as reported to the host.
+    pub fn name(&self) -> &'static str {
+        match self {
+            Stage::RpcDrain => "rpc_drain",
+            Stage::BackgroundTasks => "background_tasks",
+            Stage::WalFlush => "wal_flush",
+            Stage::CachePersist => "cache_persist",
+            Stage::ProtocolClose => "protocol_close",
+        }
+    }
+}
+
+/// Outcome of a shutdown stage.
+#[derive(Clone, Debug, Default, PartialEq, Eq, cbor::Encode, cbor::Decode)]
+pub struct StageReport {
+    /// Name of the stage.
+    pub stage: String,
+    /// Whether all steps of the stage completed successfully.
+    pub completed: bool,
+    /// Time spent in the stage, in milliseconds.
+    pub elapsed_ms: u64,
+    /// Names of the steps which failed or timed out.
+    #[cbor(optional)]
+    pub failed_steps: Vec<String>,
+}
+
+impl StageReport {
+    /// Whether the given step failed or timed out.
+    pub fn has_failed(&self, step: &str) -> bool {
+        self.failed_steps.iter().any(|failed| failed == step)
+    }
+}
+
+/// Step registered with the shutdown hooks, given the remaining budget of its stage.
+type Hook = Arc<dyn Fn(Duration) -> BoxFuture<'static, Result<()>> + Send + Sync>;
+
+/// Step of a shutdown, given the remaining budget of its stage.
+type Step<'a> = Box<dyn FnOnce(Duration) -> BoxFuture<'a, Result<()>> + Send + 'a>;
+
+/// Component keeping state which must be flushed before the runtime is terminated.
+#[async_trait]
+pub trait Flush: Send + Sync {
+    /// Flush the state of the component, waiting for in-flight writes to complete.
+    async fn flush(&self) -> Result<()>;
+}
+
+/// Steps contributed to the shutdown sequence by runtime components.
+#[derive(Default)]
+pub struct ShutdownHooks {
+    hooks: Mutex<Vec<(Stage, &'static str, Hook)>>,
+}
+
+impl ShutdownHooks {
+    /// Register a step which runs in the given stage of each shutdown, after the runtime's own
+    /// steps of that stage and in order of registration. The step is given the remaining budget
+    /// of the stage.
+    ///
+    /// The host may send a shutdown notice before the actual shutdown request, so steps must be
+    /// safe to run more than once.
+    pub fn register<F, Fut>(&self, stage: Stage, name: &'static str, hook: F)
+    where
+        F: Fn(Duration) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = Result<()>> + Send + 'static,
+    {
+        self.hooks.lock().unwrap().push((
+            stage,
+            name,
+            Arc::new(move |budget| Box::pin(hook(budget))),
+        ));
+    }
+
+    /// Register a step which flushes the given component in the given stage of each shutdown,
+    /// for as long as the component is alive.
+    pub fn register_flush<T: Flush + 'static>(
+        &self,
+        stage: Stage,
+        name: &'static str,
+        component: &Arc<T>,
+    ) {
+        let component = Arc::downgrade(component);
+        self.register(stage, name, move |_| {
+            let component = component.upgrade();
+            async move {
+                match component {
+                    Some(component) => component.flush().await,
+                    None => Ok(()),
+                }
+            }
+        });
+    }
+}
+
+/// Orchestrator of a single shutdown.
+pub struct Orchestrator<'a> {
+    logger: Logger,
+    /// Time by which the shutdown must complete, if it is bounded.
+    deadline: Option<Instant>,
+    timeouts: HashMap<Stage, Duration>,
+    concurrent: HashSet<Stage>,
+    steps: Vec<(Stage, &'static str, Step<'a>)>,
+}
+
+impl<'a> Orchestrator<'a> {
+    /// Create a new orchestrator of a shutdown which must complete within the given grace
+    /// period.
+    pub fn new(grace_period: Duration) -> Self {
+        Self {
+            logger: get_logger("runtime/shutdown"),
+            deadline: Instant::now().checked_add(grace_period),
+            timeouts: HashMap::new(),
+            concurrent: HashSet::new(),
+            steps: vec![],
+        }
+    }
+
+    /// Limit the time spent in the given stage. Stages without a timeout may use the whole
+    /// remaining grace period.
+    pub fn with_timeout(mut self, stage: Stage, timeout: Duration) -> Self {
+        self.timeouts.insert(stage, timeout);
+        self
+    }
+
+    /// Run the steps of the given stage concurrently instead of one after another, sharing the
+    /// budget of the stage.
+    pub fn with_concurrent_steps(mut self, stage: Stage) -> Self {
+        self.concurrent.insert(stage);
+        self
+    }
+
+    /// Add a step to the given stage.
+    ///
+    /// The step is given the remaining budget of the stage so that it can wind down gracefully,
+    /// and is abandoned shortly after the budget has been used up.
+    pub fn step<F, Fut>(&mut self, stage: Stage, name: &'static str, step: F)
+    where
+        F: FnOnce(Duration) -> Fut + Send + 'a,
+        Fut: Future<Output = Result<()>> + Send + 'a,
+    {
+        self.steps.push((
+            stage,
+            name,
+            Box::new(move |budget| Box::pin(step(budget)) as BoxFuture<'a, _>),
+        ));
+    }
+
+    /// Add the steps registered with the given hooks, after all steps added so far.
+    pub fn add_hooks(&mut self, hooks: &ShutdownHooks) {
+        for (stage, name, hook) in hooks.hooks.lock().unwrap().iter() {
+            let hook = hook.clone();
+            self.steps.push((
+                *stage,
+                *name,
+                Box::new(move |budget| -> BoxFuture<'a, _> { hook(budget) }),
+            ));
+        }
+    }
+
+    /// Run all stages in order, returning their outcome.
+    ///
+    /// Later stages run even if earlier ones fail, so that as much buffered state as possible is
+    /// flushed before the runtime is terminated.
+    pub async fn run(mut self) -> Vec<StageReport> {
+        let mut reports = Vec::with_capacity(Stage::ALL.len());
+        for stage in Stage::ALL {
+            let start = Instant::now();
+            let mut budget = self.deadline.map_or(Duration::MAX, |deadline| {
+                deadline.saturating_duration_since(start)
+            });
+            if let Some(timeout) = self.timeouts.get(&stage) {
+                budget = budget.min(*timeout);
+            }
+            let stage_deadline = start.checked_add(budget);
+
+            let (steps, rest): (Vec<_>, Vec<_>) =
+                self.steps.into_iter().partition(|(s, _, _)| *s == stage);
+            self.steps = rest;
+            let run_step = |name: &'static str, step: Step<'a>| {
+                let remaining = stage_deadline.map_or(Duration::MAX, |deadline| {
+                    deadline.saturating_duration_since(Instant::now())
+                });
+                let abandon_after = remaining.saturating_add(ABANDON_DELAY);
+                async move {
+                    let result = tokio::time::timeout(abandon_after, step(remaining))
+                        .await
+                        .unwrap_or_else(|_| Err(anyhow!("timed out")));
+                    (name, result)
+                }
+            };
+            let results = if self.concurrent.contains(&stage) {
+                join_all(
+                    steps
+                        .into_iter()
+                        .map(|(_, name, step)| run_step(name, step)),
+                )
+                .await
+            } else {
+                let mut results = Vec::with_capacity(steps.len());
+                for (_, name, step) in steps {
+                    results.push(run_step(name, step).await);
+                }
+                results
+            };
+
+            let mut failed_steps = vec![];
+            for (name, result) in results {
+                if let Err(err) = result {
+                    warn!(self.logger, "Shutdown step failed";
+                        "stage" => stage.name(),
+                        "step" => name,
+                        "err" => ?err,
+                    );
+                    failed_steps.push(name.to_string());
+                }
+            }
+
+            let report = StageReport {
+                stage: stage.name().to_string(),
+                completed: failed_steps.is_empty(),
+                elapsed_ms: start.elapsed().as_millis() as u64,
+                failed_steps,
+            };
+            info!(self.logger, "Shutdown stage finished";
+                "stage" => stage.name(),
+                "completed" => report.completed,
+                "elapsed_ms" => report.elapsed_ms,
+            );
+            reports.push(report);
+        }
+        reports
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use anyhow::ensure;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn test_orchestrator() {
+        let order = Arc::new(Mutex::new(vec![]));
+        let record = |name: &'static str| {
+            let order = order.clone();
+            move |_: Duration| async move {
+                order.lock().unwrap().push(name);
+                Result::<()>::Ok(())
+            }
+        };
+
+        let hooks = ShutdownHooks::default();
+        let hook_order = order.clone();
+        hooks.register(Stage::WalFlush, "hook", move |budget| {
+            let order = hook_order.clone();
+            async move {
+                ensure!(budget <= Duration::from_secs(10), "unexpected budget");
+                order.lock().unwrap().push("hook");
+                Ok(())
+            }
+        });
+
+        let mut shutdown = Orchestrator::new(Duration::from_secs(10))
+            .with_timeout(Stage::BackgroundTasks, Duration::from_millis(10));
+        shutdown.step(Stage::ProtocolClose, "close", record("close"));
+        shutdown.step(Stage::WalFlush, "flush", record("flush"));
+        shutdown.step(Stage::RpcDrain, "drain", |_| async {
+            Err(anyhow!("still in flight"))
+        });
+        shutdown.step(Stage::BackgroundTasks, "tasks", |budget| async move {
+            assert!(budget <= Duration::from_millis(10));
+            tokio::time::sleep(Duration::from_secs(10)).await;
+            Ok(())
+        });
+        shutdown.step(Stage::CachePersist, "persist", record("persist"));
+        shutdown.add_hooks(&hooks);
+
+        let reports = shutdown.run().await;
+        let stages: Vec<_> = reports.iter().map(|report| report.stage.as_str()).collect();
+        assert_eq!(
+            stages,
+            Stage::ALL.iter().map(Stage::name).collect::<Vec<_>>()
+        );
+
+        // Failed and timed out steps don't prevent later stages from running.
+        assert!(reports[0].has_failed("drain"));
+        assert!(reports[1].has_failed("tasks"));
+        assert!(reports[1].elapsed_ms < 5000);
+        assert!(reports[2..].iter().all(|report| report.completed));
+
+        // Stages run in order, with registered hooks after the runtime's own steps.
+        assert_eq!(
+            *order.lock().unwrap(),
+            vec!["flush", "hook", "persist", "close"]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_orchestrator_unbounded() {
+        // Grace periods overflowing the clock don't bound the shutdown.
+        let mut shutdown =
+            Orchestrator::new(Duration::MAX).with_timeout(Stage::WalFlush, Duration::from_secs(1));
+        shutdown.step(Stage::RpcDrain, "drain", |budget| async move {
+            ensure!(budget == Duration::MAX, "unexpected budget");
+            Ok(())
+        });
+        shutdown.step(Stage::WalFlush, "flush", |budget| async move {
+            ensure!(budget <= Duration::from_secs(1), "unexpected budget");
+            Ok(())
+        });
+
+        let reports = shutdown.run().await;
+        assert!(reports.iter().all(|report| report.completed));
+    }
+
+    #[tokio::test]
+    async fn test_orchestrator_concurrent() {
+        // Concurrent steps share the budget of their stage instead of waiting for each other.
+        let (tx, rx) = tokio::sync::oneshot::channel();
+        let mut shutdown = Orchestrator::new(Duration::from_secs(10))
+            .with_timeout(Stage::RpcDrain, Duration::from_secs(5))
+            .with_concurrent_steps(Stage::RpcDrain);
+        shutdown.step(Stage::RpcDrain, "first", |_| async move {
+            rx.await?;
+            Ok(())
+        });
+        shutdown.step(Stage::RpcDrain, "second", |_| async move {
+            tx.send(()).map_err(|_| anyhow!("receiver dropped"))
+        });
+
+        let reports = shutdown.run().await;
+        assert!(reports[0].completed);
+        assert!(reports[0].elapsed_ms < 5000);
+    }
+}
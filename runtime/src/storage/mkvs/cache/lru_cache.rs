@@ -1,17 +1,33 @@
-use std::{cell::RefCell, pin::Pin, ptr::NonNull, rc::Rc};
+use std::{
+    cell::{Cell, RefCell},
+    pin::Pin,
+    ptr::NonNull,
+    rc::Rc,
+};
 
 use anyhow::{anyhow, Result};
 use intrusive_collections::{intrusive_adapter, LinkedList, LinkedListLink};
+use rand::Rng;
 use thiserror::Error;
 
 #[cfg(test)]
 use crate::storage::mkvs::cache::CacheStats;
-use crate::storage::mkvs::{
-    cache::{Cache, CacheExtra, CacheItem, ReadSyncFetcher},
-    sync::{merge_verified_subtree, ProofVerifier, ReadSync},
-    tree::{
-        Depth, InternalNode, Key, LeafNode, NodeBox, NodeKind, NodePointer, NodePtrRef, NodeRef,
-        Root, RootType, Value,
+use crate::{
+    common::crypto::{hash::Hash, rng::SecureRng},
+    storage::mkvs::{
+        cache::{
+            Cache, CacheConfig, CacheExtra, CacheItem, CacheMetrics, EvictionPolicy,
+            ReadSyncFetcher,
+        },
+        integrity::IntegrityReport,
+        sync::{
+            merge_verified_subtree, BackgroundProofVerifier, PendingVerification, Proof,
+            ProofVerifier, ReadSync,
+        },
+        tree::{
+            Depth, InternalNode, Key, LeafNode, NodeBox, NodeKind, NodePointer, NodePtrRef,
+            NodeRef, Root, RootType, Value,
+        },
     },
 };
 
@@ -23,6 +39,10 @@ struct RemoveLockedError;
 pub struct CacheItemBox<Item: CacheItem + Default> {
     item: Rc<RefCell<Item>>,
     link: LinkedListLink,
+    /// Estimated memory used by the item at the time it was added.
+    memory_size: usize,
+    /// Whether the item is in the probation queue.
+    probation: Cell<bool>,
 }
 
 intrusive_adapter!(
@@ -36,49 +56,78 @@ where
     V: CacheItem + Default,
 {
     pub list: LinkedList<CacheItemAdapter<V>>,
+    /// Items which have not been used since they were added, only used by the 2Q policy.
+    pub probation: LinkedList<CacheItemAdapter<V>>,
     pub size: usize,
     pub capacity: usize,
+    pub policy: EvictionPolicy,
+    pub len: usize,
+    pub probation_len: usize,
+    pub memory_size: usize,
     pub mark: CacheExtra<V>,
+    pub probation_mark: CacheExtra<V>,
 }
 
 impl<V> LRUList<V>
 where
     V: CacheItem + Default,
 {
-    pub fn new(capacity: usize) -> LRUList<V> {
+    pub fn new(capacity: usize, policy: EvictionPolicy) -> LRUList<V> {
         LRUList {
             list: LinkedList::new(CacheItemAdapter::new()),
+            probation: LinkedList::new(CacheItemAdapter::new()),
             size: 0,
             capacity,
+            policy,
+            len: 0,
+            probation_len: 0,
+            memory_size: 0,
             mark: None,
+            probation_mark: None,
         }
     }
 
-    fn mark(&mut self) {
-        self.mark = self.list.front().get().map(|front| {
+    fn front_extra(list: &LinkedList<CacheItemAdapter<V>>) -> CacheExtra<V> {
+        list.front().get().map(|front| {
             front
                 .item
                 .borrow()
                 .get_cache_extra()
                 .expect("item was just retrieved from list, cache extra must exist")
-        });
+        })
+    }
+
+    fn mark(&mut self) {
+        self.mark = Self::front_extra(&self.list);
+        self.probation_mark = Self::front_extra(&self.probation);
     }
 
     fn add(&mut self, val: Rc<RefCell<V>>) {
         let mut val_ref = val.borrow_mut();
         if val_ref.get_cache_extra().is_none() {
-            self.size += val_ref.get_cached_size();
+            let probation = self.policy == EvictionPolicy::TwoQueue;
             let mut item_box = Box::pin(CacheItemBox {
                 item: val.clone(),
                 link: LinkedListLink::new(),
+                memory_size: val_ref.get_memory_size(),
+                probation: Cell::new(probation),
             });
+            self.size += val_ref.get_cached_size();
+            self.len += 1;
+            self.memory_size += item_box.memory_size;
             val_ref.set_cache_extra(NonNull::new(&mut *item_box));
-            if let Some(non_null_pos) = &self.mark {
-                let mut pos_cursor =
-                    unsafe { self.list.cursor_mut_from_ptr(non_null_pos.as_ptr()) };
+
+            let (list, mark) = if probation {
+                self.probation_len += 1;
+                (&mut self.probation, &self.probation_mark)
+            } else {
+                (&mut self.list, &self.mark)
+            };
+            if let Some(non_null_pos) = mark {
+                let mut pos_cursor = unsafe { list.cursor_mut_from_ptr(non_null_pos.as_ptr()) };
                 pos_cursor.insert_after(item_box);
             } else {
-                self.list.push_front(item_box);
+                list.push_front(item_box);
             }
         } else {
             self.use_val(val.clone());
@@ -90,7 +139,19 @@ where
         match val_ref.get_cache_extra() {
             None => false,
             Some(non_null) => {
-                let mut item_cursor = unsafe { self.list.cursor_mut_from_ptr(non_null.as_ptr()) };
+                // Items used again are promoted from the probation queue.
+                let list = if unsafe { non_null.as_ref() }.probation.replace(false) {
+                    if let Some(non_null_mark) = self.probation_mark {
+                        if non_null.as_ptr() == non_null_mark.as_ptr() {
+                            self.probation_mark = None;
+                        }
+                    }
+                    self.probation_len -= 1;
+                    &mut self.probation
+                } else {
+                    &mut self.list
+                };
+                let mut item_cursor = unsafe { list.cursor_mut_from_ptr(non_null.as_ptr()) };
                 let removed_box = item_cursor.remove().unwrap();
                 self.list.push_front(removed_box);
                 true
@@ -103,16 +164,28 @@ where
         match extra {
             None => false,
             Some(non_null) => {
-                if let Some(non_null_mark) = self.mark {
-                    if non_null.as_ptr() == non_null_mark.as_ptr() {
-                        self.mark = None;
+                for mark in [&mut self.mark, &mut self.probation_mark] {
+                    if let Some(non_null_mark) = *mark {
+                        if non_null.as_ptr() == non_null_mark.as_ptr() {
+                            *mark = None;
+                        }
                     }
                 }
 
-                let mut item_cursor = unsafe { self.list.cursor_mut_from_ptr(non_null.as_ptr()) };
+                let list = if unsafe { non_null.as_ref() }.probation.get() {
+                    &mut self.probation
+                } else {
+                    &mut self.list
+                };
+                let mut item_cursor = unsafe { list.cursor_mut_from_ptr(non_null.as_ptr()) };
                 match item_cursor.remove() {
                     None => false,
                     Some(item_box) => {
+                        if item_box.probation.get() {
+                            self.probation_len -= 1;
+                        }
+                        self.len -= 1;
+                        self.memory_size -= item_box.memory_size;
                         let mut val = item_box.item.borrow_mut();
                         val.set_cache_extra(None);
                         self.size -= val.get_cached_size();
@@ -123,6 +196,51 @@ where
         }
     }
 
+    /// Select a uniformly random sample of at most the given number of items.
+    fn sample(&self, count: usize) -> Vec<Rc<RefCell<V>>> {
+        let mut sample = Vec::with_capacity(count.min(self.len));
+        for (seen, item) in self.list.iter().chain(self.probation.iter()).enumerate() {
+            if sample.len() < count {
+                sample.push(item.item.clone());
+                continue;
+            }
+            let idx = SecureRng.gen_range(0..=seen);
+            if idx < count {
+                sample[idx] = item.item.clone();
+            }
+        }
+        sample
+    }
+
+    /// Evict the next item according to the eviction policy, returning it.
+    fn evict_one(
+        &mut self,
+        locked_val: Option<&Rc<RefCell<V>>>,
+    ) -> Result<Option<Rc<RefCell<V>>>, RemoveLockedError> {
+        // Items in the probation queue are evicted first, as long as they make up more than a
+        // quarter of all items.
+        let list = if !self.probation.is_empty()
+            && (self.list.is_empty() || self.probation_len * 4 > self.len)
+        {
+            &self.probation
+        } else {
+            &self.list
+        };
+        let back = match list.back().get() {
+            Some(back) => back.item.clone(),
+            None => return Ok(None),
+        };
+        if let Some(locked_val) = locked_val {
+            if back.as_ptr() == locked_val.as_ptr() {
+                return Err(RemoveLockedError);
+            }
+        }
+        self.remove(back.clone());
+        CacheMetrics::record(|metrics| metrics.evictions += 1);
+
+        Ok(Some(back))
+    }
+
     fn evict_for_val(
         &mut self,
         val: Rc<RefCell<V>>,
@@ -131,15 +249,10 @@ where
         let mut evicted: Vec<Rc<RefCell<V>>> = Vec::new();
         if self.capacity > 0 {
             let target_size = val.borrow().get_cached_size();
-            while !self.list.is_empty() && self.size + target_size > self.capacity {
-                let back = self.list.back().get().unwrap().item.clone();
-                if let Some(locked_val) = locked_val {
-                    if back.as_ptr() == locked_val.as_ptr() {
-                        return Err(RemoveLockedError);
-                    }
-                }
-                if self.remove(back.clone()) {
-                    evicted.push(back);
+            while self.size + target_size > self.capacity {
+                match self.evict_one(locked_val)? {
+                    Some(back) => evicted.push(back),
+                    None => break,
                 }
             }
         }
@@ -148,14 +261,29 @@ where
 }
 
 /// Cache implementation with a simple LRU eviction strategy.
+/// A prefetch whose proof is being verified in the background.
+struct PendingPrefetch {
+    /// Pointer that the prefetch was issued for.
+    ptr: NodePtrRef,
+    /// Pointer that the verified subtree should be merged into.
+    dst_ptr: NodePtrRef,
+    /// Whether the destination is the pending root.
+    is_root: bool,
+    verification: PendingVerification,
+}
+
 pub struct LRUCache {
     read_syncer: Box<dyn ReadSync>,
 
     pending_root: NodePtrRef,
     sync_root: Root,
 
+    max_bytes: usize,
+    max_nodes: usize,
     lru_leaf: LRUList<NodePointer>,
     lru_internal: LRUList<NodePointer>,
+
+    pending_prefetches: Vec<PendingPrefetch>,
 }
 
 impl LRUCache {
@@ -165,10 +293,12 @@ impl LRUCache {
     ///   cache before eviction.
     /// * `value_capacity` is the total size, in bytes, of values held
     ///   by the cache before eviction.
+    /// * `config` sets the limits applying to all nodes and the eviction policy.
     /// * `read_syncer` is the read syncer used as backing for the cache.
     pub fn new(
         node_capacity: usize,
         value_capacity: usize,
+        config: CacheConfig,
         read_syncer: Box<dyn ReadSync>,
         root_type: RootType,
     ) -> Box<LRUCache> {
@@ -184,11 +314,145 @@ impl LRUCache {
                 ..Default::default()
             },
 
-            lru_leaf: LRUList::new(value_capacity),
-            lru_internal: LRUList::new(node_capacity),
+            max_bytes: config.max_bytes,
+            max_nodes: config.max_nodes,
+            lru_leaf: LRUList::new(value_capacity, config.policy),
+            lru_internal: LRUList::new(node_capacity, config.policy),
+
+            pending_prefetches: Vec::new(),
         })
     }
 
+    /// Fetch a proof using the given fetcher and queue it for verification in the background.
+    ///
+    /// The verified nodes are merged into the cache as soon as the verification completes and
+    /// the cache is next accessed, or earlier in case a node that is not yet available locally
+    /// is dereferenced while verifications are still pending.
+    pub fn remote_sync_background<F: ReadSyncFetcher>(
+        &mut self,
+        ptr: NodePtrRef,
+        fetcher: F,
+    ) -> Result<()> {
+        let proof = fetcher.fetch(self.sync_root, ptr.clone(), &mut self.read_syncer)?;
+        let (dst_ptr, expected_root) = self.proof_destination(&ptr, &proof)?;
+        let is_root = Rc::ptr_eq(&dst_ptr, &self.pending_root);
+
+        self.pending_prefetches.push(PendingPrefetch {
+            ptr,
+            dst_ptr,
+            is_root,
+            verification: BackgroundProofVerifier::global().verify(expected_root, proof),
+        });
+
+        Ok(())
+    }
+
+    /// Merge results of background verifications into the cache.
+    ///
+    /// In case `wait` is set, blocks until all pending verifications complete, otherwise only
+    /// merges the results of verifications that have already completed. Returns the first
+    /// verification error, if any.
+    pub fn complete_prefetches(&mut self, wait: bool) -> Result<()> {
+        let mut result = Ok(());
+        for mut prefetch in std::mem::take(&mut self.pending_prefetches) {
+            let verified = if wait {
+                Some(prefetch.verification.wait())
+            } else {
+                prefetch.verification.try_wait()
+            };
+
+            match verified {
+                None => self.pending_prefetches.push(prefetch),
+                Some(Ok(subtree)) => {
+                    // Skip prefetches for parts of the tree which have since been modified.
+                    if !prefetch.dst_ptr.borrow().clean
+                        || (prefetch.is_root && !Rc::ptr_eq(&prefetch.dst_ptr, &self.pending_root))
+                    {
+                        continue;
+                    }
+                    if let Err(err) =
+                        self.merge_subtree(prefetch.dst_ptr, subtree.into_node_ptr(), &prefetch.ptr)
+                    {
+                        result = result.and(Err(err));
+                    }
+                }
+                Some(Err(err)) => result = result.and(Err(err)),
+            }
+        }
+        result
+    }
+
+    /// Re-hash a random sample of at most the given number of cached nodes, dropping the nodes
+    /// which don't match their hash so that they are fetched again when next needed.
+    pub fn verify_sample(&mut self, count: usize) -> IntegrityReport {
+        // Split the sample between internal nodes and leaves.
+        let mut sample = self.lru_internal.sample(count.div_ceil(2));
+        sample.extend(self.lru_leaf.sample(count - sample.len()));
+
+        let mut report = IntegrityReport {
+            root: self.sync_root,
+            checked: sample.len() as u64,
+            corrupted: Vec::new(),
+        };
+        for ptr in sample {
+            // Skip nodes which have been removed together with a corrupted subtree.
+            let computed = match ptr.borrow().node {
+                Some(ref node) => node.borrow().compute_hash(),
+                None => continue,
+            };
+            let expected = ptr.borrow().hash;
+            if computed != expected {
+                report.corrupted.push(expected);
+                self.remove_node(ptr);
+            }
+        }
+        report
+    }
+
+    /// Determine where the nodes from the given proof should be merged and the root hash the
+    /// proof must be verified against.
+    fn proof_destination(&self, ptr: &NodePtrRef, proof: &Proof) -> Result<(NodePtrRef, Hash)> {
+        // The proof can be for one of two hashes: i) it is either for ptr.Hash in case
+        // all the nodes are only contained in the subtree below ptr, or ii) it is for
+        // the c.syncRoot.Hash in case it contains nodes outside the subtree.
+        let ptr_hash = ptr.borrow().hash;
+        if proof.untrusted_root == ptr_hash {
+            Ok((ptr.clone(), ptr_hash))
+        } else if proof.untrusted_root == self.sync_root.hash {
+            Ok((self.pending_root.clone(), self.sync_root.hash))
+        } else {
+            Err(anyhow!(
+                "mkvs: got proof for unexpected root ({:?})",
+                proof.untrusted_root
+            ))
+        }
+    }
+
+    /// Merge a verified subtree into the cache.
+    fn merge_subtree(
+        &mut self,
+        dst_ptr: NodePtrRef,
+        subtree: NodePtrRef,
+        ptr: &NodePtrRef,
+    ) -> Result<()> {
+        let mut merged_nodes: Vec<NodePtrRef> = Vec::new();
+        merge_verified_subtree(dst_ptr, subtree, &mut merged_nodes)?;
+        let mut remove = false;
+        for node_ref in merged_nodes {
+            if remove {
+                // Do not keep subtrees that we failed to commit in memory.
+                node_ref.borrow_mut().node = None;
+            }
+
+            if let Err(RemoveLockedError) = self.commit_merged_node(node_ref, ptr) {
+                // Cache is too small, ignore.
+                remove = true;
+            }
+        }
+
+        Ok(())
+    }
+
     fn new_internal_node_ptr(&mut self, node: Option<NodeRef>) -> NodePtrRef {
         Rc::new(RefCell::new(NodePointer {
             node,
@@ -215,6 +479,7 @@ impl LRUCache {
         if self.use_node(ptr.clone()) {
             return Ok(());
         }
+        self.evict_for_limits(&ptr, locked_ptr)?;
 
         match classify_noderef!(? ptr.borrow().node) {
             NodeKind::Internal => {
@@ -237,6 +502,35 @@ impl LRUCache {
         Ok(())
     }
 
+    /// Evict nodes until there is space for the given node within the limits applying to all
+    /// nodes. Leaves are evicted before internal nodes as they hold the values.
+    fn evict_for_limits(
+        &mut self,
+        ptr: &NodePtrRef,
+        locked_ptr: Option<&NodePtrRef>,
+    ) -> Result<(), RemoveLockedError> {
+        let memory_size = ptr.borrow().get_memory_size();
+        loop {
+            let over_bytes = self.max_bytes > 0
+                && self.lru_leaf.memory_size + self.lru_internal.memory_size + memory_size
+                    > self.max_bytes;
+            let over_nodes =
+                self.max_nodes > 0 && self.lru_leaf.len + self.lru_internal.len >= self.max_nodes;
+            if !over_bytes && !over_nodes {
+                return Ok(());
+            }
+
+            let evicted = match self.lru_leaf.evict_one(locked_ptr)? {
+                Some(node) => node,
+                None => match self.lru_internal.evict_one(locked_ptr)? {
+                    Some(node) => node,
+                    None => return Ok(()),
+                },
+            };
+            self.try_remove_node(evicted, locked_ptr)?;
+        }
+    }
+
     fn try_remove_node(
         &mut self,
         ptr: NodePtrRef,
@@ -342,6 +636,8 @@ impl Cache for LRUCache {
         CacheStats {
             internal_node_count: self.lru_internal.size,
             leaf_value_size: self.lru_leaf.size,
+            node_count: self.lru_internal.len + self.lru_leaf.len,
+            memory_size: self.lru_internal.memory_size + self.lru_leaf.memory_size,
         }
     }
 
@@ -400,6 +696,11 @@ impl Cache for LRUCache {
         ptr: NodePtrRef,
         fetcher: Option<F>,
     ) -> Result<Option<NodeRef>> {
+        if !self.pending_prefetches.is_empty() {
+            // Prefetches are only hints, so failures are ignored here.
+            let _ = self.complete_prefetches(false);
+        }
+
         let ptr_ref = ptr;
         let ptr = ptr_ref.borrow();
 
@@ -420,6 +721,7 @@ impl Cache for LRUCache {
                 drop(ptr);
                 self.remove_node(ptr_ref.clone());
             } else {
+                CacheMetrics::record(|metrics| metrics.hits += 1);
                 return Ok(Some(node.clone()));
             }
         } else {
@@ -429,8 +731,20 @@ impl Cache for LRUCache {
             drop(ptr);
         }
 
+        // Node not available locally. In case there are prefetches pending verification, the
+        // node may be among them so wait for them to complete first.
+        if !self.pending_prefetches.is_empty() {
+            // Prefetches are only hints, so failures are ignored here.
+            let _ = self.complete_prefetches(true);
+            if let Some(ref node) = ptr_ref.borrow().node {
+                CacheMetrics::record(|metrics| metrics.hits += 1);
+                return Ok(Some(node.clone()));
+            }
+        }
+
         // Node not available locally, fetch from read syncer.
         if let Some(fetcher) = fetcher {
+            CacheMetrics::record(|metrics| metrics.misses += 1);
             self.remote_sync(ptr_ref.clone(), fetcher)?;
         } else {
             return Err(anyhow!(
@@ -450,42 +764,17 @@ impl Cache for LRUCache {
     fn remote_sync<F: ReadSyncFetcher>(&mut self, ptr: NodePtrRef, fetcher: F) -> Result<()> {
         let proof = fetcher.fetch(self.sync_root, ptr.clone(), &mut self.read_syncer)?;
 
-        // The proof can be for one of two hashes: i) it is either for ptr.Hash in case
-        // all the nodes are only contained in the subtree below ptr, or ii) it is for
-        // the c.syncRoot.Hash in case it contains nodes outside the subtree.
-        let ptr_hash = ptr.borrow().hash;
-        let (dst_ptr, expected_root) = if proof.untrusted_root == ptr_hash {
-            (ptr.clone(), ptr_hash)
-        } else if proof.untrusted_root == self.sync_root.hash {
-            (self.pending_root.clone(), self.sync_root.hash)
-        } else {
-            return Err(anyhow!(
-                "mkvs: got proof for unexpected root ({:?})",
-                proof.untrusted_root
-            ));
-        };
-
-        // Verify proof.
-        let pv = ProofVerifier;
-        let subtree = pv.verify_proof(expected_root, &proof)?;
+        // Verify proof, letting the read syncer know about invalid ones.
+        let verified = self
+            .proof_destination(&ptr, &proof)
+            .and_then(|(dst_ptr, expected_root)| {
+                Ok((dst_ptr, ProofVerifier.verify_proof(expected_root, &proof)?))
+            });
+        let (dst_ptr, subtree) =
+            verified.map_err(|err| self.read_syncer.report_invalid_proof(err))?;
 
         // Merge resulting nodes.
-        let mut merged_nodes: Vec<NodePtrRef> = Vec::new();
-        merge_verified_subtree(dst_ptr, subtree, &mut merged_nodes)?;
-        let mut remove = false;
-        for node_ref in merged_nodes {
-            if remove {
-                // Do not keep subtrees that we failed to commit in memory.
-                node_ref.borrow_mut().node = None;
-            }
-
-            if let Err(RemoveLockedError) = self.commit_merged_node(node_ref, &ptr) {
-                // Cache is too small, ignore.
-                remove = true;
-            }
-        }
-
-        Ok(())
+        self.merge_subtree(dst_ptr, subtree, &ptr)
     }
 
     fn use_node(&mut self, ptr: NodePtrRef) -> bool {
@@ -522,3 +811,62 @@ impl Cache for LRUCache {
         self.lru_leaf.mark();
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn leaf_ptr(key: &[u8]) -> NodePtrRef {
+        Rc::new(RefCell::new(NodePointer {
+            clean: true,
+            node: Some(Rc::new(RefCell::new(NodeBox::Leaf(LeafNode {
+                clean: true,
+                key: key.to_vec(),
+                value: b"value".to_vec(),
+                ..Default::default()
+            })))),
+            ..Default::default()
+        }))
+    }
+
+    fn add(list: &mut LRUList<NodePointer>, ptr: &NodePtrRef) {
+        list.evict_for_val(ptr.clone(), None).unwrap();
+        list.add(ptr.clone());
+    }
+
+    #[test]
+    fn test_eviction_policy() {
+        for (policy, retained) in [
+            (EvictionPolicy::Lru, false),
+            (EvictionPolicy::TwoQueue, true),
+        ] {
+            let mut list = LRUList::new(4, policy);
+            let ptrs: Vec<_> = (0..8u8).map(|i| leaf_ptr(&[i])).collect();
+
+            CacheMetrics::take();
+            for ptr in &ptrs[..4] {
+                add(&mut list, ptr);
+            }
+            // Use the first item again, then add a batch of items used only once.
+            assert!(list.use_val(ptrs[0].clone()));
+            for ptr in &ptrs[4..] {
+                add(&mut list, ptr);
+            }
+
+            assert_eq!(list.len, 4);
+            assert_eq!(CacheMetrics::take().evictions, 4);
+            assert_eq!(
+                ptrs[0].borrow().get_cache_extra().is_some(),
+                retained,
+                "{policy:?}"
+            );
+
+            for ptr in &ptrs {
+                list.remove(ptr.clone());
+            }
+            assert_eq!(list.len, 0);
+            assert_eq!(list.probation_len, 0);
+            assert_eq!(list.memory_size, 0);
+        }
+    }
+}
@@ -2,7 +2,7 @@ mod lru_cache;
 
 pub use lru_cache::LRUCache;
 
-use std::ptr::NonNull;
+use std::{cell::Cell, ptr::NonNull};
 
 use anyhow::Result;
 
@@ -12,6 +12,73 @@ use crate::storage::mkvs::{
     tree::{Depth, Key, NodeKind, NodePtrRef, NodeRef, Root, Value},
 };
 
+thread_local! {
+    /// Cache accesses made by the current thread.
+    static CACHE_METRICS: Cell<CacheMetrics> = const { Cell::new(CacheMetrics::new()) };
+}
+
+/// Cache eviction policy.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum EvictionPolicy {
+    /// Evict the least recently used nodes.
+    #[default]
+    Lru,
+    /// Keep nodes used only once in a separate FIFO queue, from which they are evicted first,
+    /// and only promote nodes used again to the LRU queue. This prevents large one-off reads,
+    /// e.g. iterations, from evicting the working set.
+    TwoQueue,
+}
+
+/// Configuration of the in-memory tree cache.
+///
+/// The limits apply to all cached nodes and are enforced in addition to the node and value
+/// capacities set via `Builder::with_capacity`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct CacheConfig {
+    /// Maximum estimated memory, in bytes, used by cached nodes. A zero value denotes unlimited
+    /// capacity.
+    pub max_bytes: usize,
+    /// Maximum number of cached nodes. A zero value denotes unlimited capacity.
+    pub max_nodes: usize,
+    /// Eviction policy.
+    pub policy: EvictionPolicy,
+}
+
+/// Metrics of tree cache accesses made by the current thread.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct CacheMetrics {
+    /// Number of dereferenced nodes that were available in the cache.
+    pub hits: u64,
+    /// Number of dereferenced nodes that had to be fetched from the read syncer.
+    pub misses: u64,
+    /// Number of nodes evicted from the cache.
+    pub evictions: u64,
+}
+
+impl CacheMetrics {
+    const fn new() -> Self {
+        Self {
+            hits: 0,
+            misses: 0,
+            evictions: 0,
+        }
+    }
+
+    /// Return the metrics of cache accesses made by the current thread since the previous call,
+    /// resetting them.
+    pub fn take() -> Self {
+        CACHE_METRICS.with(|metrics| metrics.replace(Self::new()))
+    }
+
+    fn record(f: impl FnOnce(&mut Self)) {
+        CACHE_METRICS.with(|metrics| {
+            let mut current = metrics.get();
+            f(&mut current);
+            metrics.set(current);
+        });
+    }
+}
+
 /// Statistics about the contents of the cache.
 #[derive(Debug, Default)]
 #[cfg(test)]
@@ -20,6 +87,10 @@ pub struct CacheStats {
     pub internal_node_count: usize,
     /// Total size of values held by the cache.
     pub leaf_value_size: usize,
+    /// Count of all nodes held by the cache.
+    pub node_count: usize,
+    /// Estimated memory used by all nodes held by the cache.
+    pub memory_size: usize,
 }
 
 /// Used to fetch proofs from a remote tree via the ReadSyncer interface.
@@ -120,6 +191,8 @@ where
     fn set_cache_extra(&mut self, new_val: CacheExtra<Item>);
     /// Return the size, in bytes, of the item when cached.
     fn get_cached_size(&self) -> usize;
+    /// Return the estimated memory, in bytes, used by the item.
+    fn get_memory_size(&self) -> usize;
 }
 
 /// Callback type used for updating cache items after a commit.
@@ -0,0 +1,305 @@
+//! Deterministic, chunked checkpoints of tree contents.
+//!
+//! A checkpoint splits all key/value pairs of a finalized tree, in key order, into chunks of
+//! roughly the requested size. The metadata commits to the root of the tree and to the hash of
+//! every chunk, so each chunk can be verified as soon as it is received. Chunks only depend on the
+//! tree contents and the chunk size, so all nodes produce identical checkpoints for the same root.
+//!
+//! Checkpoints are served a page of chunks at a time, so that no single response needs to hold
+//! the complete state.
+//!
+//! Restoring rebuilds the tree from the chunks and compares its root hash against the metadata,
+//! so a checkpoint served by an untrusted host can be used for state sync as long as the root
+//! itself is trusted, e.g. because it has been verified against the consensus layer. The root of
+//! the metadata must therefore match the root the restore is expected to produce. The tree is
+//! rebuilt in memory, so restoring requires enough memory to hold the complete state.
+use std::ops::Range;
+
+use anyhow::{anyhow, Result};
+
+use crate::{
+    common::crypto::hash::Hash,
+    storage::mkvs::{sync::NoopReadSyncer, Iterator, LogEntry, Root, Tree, WriteLog},
+};
+
+/// Current checkpoint format version.
+pub const CHECKPOINT_VERSION: u16 = 1;
+
+/// Maximum number of chunks returned by a single call to [`create`].
+pub const MAX_CHUNKS_PER_PAGE: u64 = 16;
+
+/// Checkpoint metadata.
+#[derive(Clone, Debug, Default, PartialEq, Eq, cbor::Encode, cbor::Decode)]
+pub struct Metadata {
+    /// Checkpoint format version.
+    pub version: u16,
+    /// Root the checkpoint was created for.
+    pub root: Root,
+    /// Target size of each chunk in bytes.
+    pub chunk_size: u64,
+    /// Hashes of all chunks, in order.
+    pub chunks: Vec<Hash>,
+}
+
+/// Create a checkpoint of the given tree, which must be at the given root, returning a page of
+/// at most [`MAX_CHUNKS_PER_PAGE`] chunks starting at the given chunk index.
+///
+/// Each chunk is a CBOR-encoded write log of inserts and is closed as soon as the size of its
+/// keys and values reaches the chunk size. Returns the metadata, covering all chunks, together
+/// with the chunks of the page. Chunks outside of the page are only hashed.
+pub fn create(
+    tree: &Tree,
+    root: Root,
+    chunk_size: u64,
+    start: u64,
+    limit: u64,
+) -> Result<(Metadata, Vec<Vec<u8>>)> {
+    if chunk_size == 0 {
+        return Err(anyhow!("mkvs/checkpoint: zero chunk size"));
+    }
+    let page = start..start.saturating_add(limit.min(MAX_CHUNKS_PER_PAGE));
+
+    let mut builder = PageBuilder {
+        page,
+        hashes: Vec::new(),
+        chunks: Vec::new(),
+    };
+    let mut entries: WriteLog = Vec::new();
+    let mut size = 0u64;
+    let mut it = tree.iter();
+    for (key, value) in &mut it {
+        size += (key.len() + value.len()) as u64;
+        entries.push(LogEntry {
+            key,
+            value: Some(value),
+        });
+        if size >= chunk_size {
+            builder.push(cbor::to_vec(std::mem::take(&mut entries)));
+            size = 0;
+        }
+    }
+    if let Some(err) = it.error() {
+        return Err(anyhow!("mkvs/checkpoint: failed to iterate tree: {}", err));
+    }
+    if !entries.is_empty() {
+        builder.push(cbor::to_vec(entries));
+    }
+
+    let metadata = Metadata {
+        version: CHECKPOINT_VERSION,
+        root,
+        chunk_size,
+        chunks: builder.hashes,
+    };
+    Ok((metadata, builder.chunks))
+}
+
+/// Builder of a page of checkpoint chunks.
+struct PageBuilder {
+    page: Range<u64>,
+    hashes: Vec<Hash>,
+    chunks: Vec<Vec<u8>>,
+}
+
+impl PageBuilder {
+    fn push(&mut self, chunk: Vec<u8>) {
+        let index = self.hashes.len() as u64;
+        self.hashes.push(Hash::digest_bytes(&chunk));
+        if self.page.contains(&index) {
+            self.chunks.push(chunk);
+        }
+    }
+}
+
+/// Checkpoint restorer.
+///
+/// Chunks may be restored in any order, but each chunk only once.
+pub struct Restorer {
+    metadata: Metadata,
+    tree: Tree,
+    restored: Vec<bool>,
+    remaining: usize,
+}
+
+impl Restorer {
+    /// Start restoring the checkpoint with the given metadata, which must be for the given
+    /// trusted root.
+    pub fn new(metadata: Metadata, expected_root: Root) -> Result<Self> {
+        if metadata.version != CHECKPOINT_VERSION {
+            return Err(anyhow!(
+                "mkvs/checkpoint: unsupported version {}",
+                metadata.version
+            ));
+        }
+        if metadata.root != expected_root {
+            return Err(anyhow!("mkvs/checkpoint: unexpected root"));
+        }
+
+        let tree = Tree::builder()
+            .with_capacity(0, 0)
+            .with_root_type(metadata.root.root_type)
+            .build(Box::new(NoopReadSyncer));
+        Ok(Self {
+            tree,
+            restored: vec![false; metadata.chunks.len()],
+            remaining: metadata.chunks.len(),
+            metadata,
+        })
+    }
+
+    /// Metadata of the checkpoint being restored.
+    pub fn metadata(&self) -> &Metadata {
+        &self.metadata
+    }
+
+    /// Whether all chunks have been restored.
+    pub fn is_complete(&self) -> bool {
+        self.remaining == 0
+    }
+
+    /// Verify and restore the chunk with the given index.
+    ///
+    /// Returns true when all chunks have been restored.
+    pub fn restore_chunk(&mut self, index: u64, chunk: &[u8]) -> Result<bool> {
+        let index = usize::try_from(index)?;
+        let expected = self
+            .metadata
+            .chunks
+            .get(index)
+            .ok_or_else(|| anyhow!("mkvs/checkpoint: chunk index out of range"))?;
+        if self.restored[index] {
+            return Err(anyhow!("mkvs/checkpoint: chunk already restored"));
+        }
+        if Hash::digest_bytes(chunk) != *expected {
+            return Err(anyhow!("mkvs/checkpoint: chunk hash mismatch"));
+        }
+
+        let entries: WriteLog = cbor::from_slice(chunk)?;
+        let mut last_key: Option<&[u8]> = None;
+        for entry in &entries {
+            if matches!(last_key, Some(last) if last >= entry.key.as_slice()) {
+                return Err(anyhow!("mkvs/checkpoint: entries not in key order"));
+            }
+            let value = entry
+                .value
+                .as_ref()
+                .ok_or_else(|| anyhow!("mkvs/checkpoint: chunk contains a delete"))?;
+            self.tree.insert(&entry.key, value)?;
+            last_key = Some(&entry.key);
+        }
+
+        self.restored[index] = true;
+        self.remaining -= 1;
+        Ok(self.is_complete())
+    }
+
+    /// Finish restoring once all chunks have been restored.
+    ///
+    /// Returns the restored tree, committed at the checkpoint root, after verifying its root hash.
+    pub fn finalize(mut self) -> Result<Tree> {
+        if !self.is_complete() {
+            return Err(anyhow!(
+                "mkvs/checkpoint: {} chunks not restored",
+                self.remaining
+            ));
+        }
+
+        let root = self.metadata.root;
+        let hash = self.tree.commit(root.namespace, root.version)?;
+        if hash != root.hash {
+            return Err(anyhow!("mkvs/checkpoint: root hash mismatch"));
+        }
+
+        Ok(self.tree)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{common::namespace::Namespace, storage::mkvs::RootType};
+
+    #[test]
+    fn test_checkpoint() {
+        let namespace = Namespace::from(vec![1; 32]);
+        let mut tree = Tree::builder()
+            .with_root_type(RootType::State)
+            .build(Box::new(NoopReadSyncer));
+        for i in 0..100u32 {
+            tree.insert(&i.to_be_bytes(), format!("value {}", i).as_bytes())
+                .unwrap();
+        }
+        let hash = tree.commit(namespace, 7).unwrap();
+        let root = Root {
+            namespace,
+            version: 7,
+            root_type: RootType::State,
+            hash,
+        };
+
+        let (metadata, chunks) = create(&tree, root, 128, 0, MAX_CHUNKS_PER_PAGE).unwrap();
+        assert!(chunks.len() > 1);
+        assert_eq!(metadata.chunks.len(), chunks.len());
+        assert_eq!(metadata.root, root);
+
+        // Checkpoints are deterministic.
+        assert_eq!(
+            create(&tree, root, 128, 0, MAX_CHUNKS_PER_PAGE).unwrap(),
+            (metadata.clone(), chunks.clone())
+        );
+
+        // Checkpoints are served in pages.
+        let (paged, page) = create(&tree, root, 128, 1, 2).unwrap();
+        assert_eq!(paged, metadata);
+        assert_eq!(page, chunks[1..3].to_vec());
+        let (_, page) = create(&tree, root, 128, chunks.len() as u64, 2).unwrap();
+        assert!(page.is_empty());
+        let (_, page) = create(&tree, root, 32, 0, u64::MAX).unwrap();
+        assert_eq!(page.len() as u64, MAX_CHUNKS_PER_PAGE);
+
+        // Chunks can be restored in any order.
+        let mut restorer = Restorer::new(metadata.clone(), root).unwrap();
+        for (index, chunk) in chunks.iter().enumerate().rev() {
+            assert_eq!(
+                restorer.restore_chunk(index as u64, chunk).unwrap(),
+                index == 0
+            );
+        }
+        let restored = restorer.finalize().unwrap();
+        assert_eq!(
+            restored.get(&42u32.to_be_bytes()).unwrap(),
+            Some(b"value 42".to_vec())
+        );
+
+        // Invalid chunks are rejected.
+        let mut restorer = Restorer::new(metadata.clone(), root).unwrap();
+        let mut tampered = chunks[0].clone();
+        *tampered.last_mut().unwrap() ^= 0x01;
+        assert!(restorer.restore_chunk(0, &tampered).is_err());
+        assert!(restorer.restore_chunk(1, &chunks[0]).is_err());
+        assert!(restorer
+            .restore_chunk(chunks.len() as u64, &chunks[0])
+            .is_err());
+        restorer.restore_chunk(0, &chunks[0]).unwrap();
+        assert!(restorer.restore_chunk(0, &chunks[0]).is_err());
+        assert!(restorer.finalize().is_err());
+
+        // Metadata for another root is rejected.
+        let forged_root = Root {
+            hash: Hash::empty_hash(),
+            ..root
+        };
+        let forged = Metadata {
+            root: forged_root,
+            ..metadata
+        };
+        assert!(Restorer::new(forged.clone(), root).is_err());
+
+        // Metadata not matching the chunks is rejected.
+        let mut restorer = Restorer::new(forged, forged_root).unwrap();
+        for (index, chunk) in chunks.iter().enumerate() {
+            restorer.restore_chunk(index as u64, chunk).unwrap();
+        }
+        assert!(restorer.finalize().is_err());
+    }
+}
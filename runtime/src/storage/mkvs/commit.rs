@@ -0,0 +1,207 @@
+//! Verification of host commit acknowledgements.
+//!
+//! The runtime only returns the write logs of executed rounds, which the host then applies to
+//! its storage. Corruption of the host storage (e.g. a faulty disk or a bug in applying write
+//! logs) would otherwise only surface rounds later, when reads fail proof verification or
+//! executors start to disagree. The roots of recently executed rounds are therefore recorded
+//! after commit, and the host acknowledges each applied round with the roots it ended up with,
+//! which are verified against the locally computed ones.
+//!
+//! A round may be executed multiple times (e.g. after a discrepancy is detected), so an
+//! acknowledgement matches in case it matches any local commit of its round.
+use std::{collections::BTreeMap, sync::Mutex};
+
+use thiserror::Error;
+
+use crate::{
+    common::{crypto::hash::Hash, namespace::Namespace},
+    storage::mkvs::RootType,
+    types,
+};
+
+/// Error module used for commit acknowledgement errors.
+pub const MODULE_NAME: &str = "storage/commit";
+/// Error code used for acknowledgements which can't be verified.
+const CODE_UNVERIFIABLE: u32 = 1;
+/// Error code used for acknowledgements diverging from the local commit.
+const CODE_DIVERGED: u32 = 2;
+
+/// Commit acknowledgement errors.
+#[derive(Error, Debug, Clone, PartialEq, Eq)]
+pub enum CommitError {
+    #[error("commit: namespace mismatch (expected: {expected:?} got: {got:?})")]
+    NamespaceMismatch { expected: Namespace, got: Namespace },
+
+    #[error("commit: no local commit of round {0}")]
+    UnknownRound(u64),
+
+    #[error(
+        "commit: {root_type:?} root of round {round} diverged (local: {local:?} host: {host:?})"
+    )]
+    Divergence {
+        round: u64,
+        root_type: RootType,
+        local: Hash,
+        host: Hash,
+    },
+}
+
+impl From<CommitError> for types::Error {
+    fn from(e: CommitError) -> Self {
+        let code = match e {
+            CommitError::Divergence { .. } => CODE_DIVERGED,
+            _ => CODE_UNVERIFIABLE,
+        };
+        types::Error::new(MODULE_NAME, code, &e.to_string())
+    }
+}
+
+/// Acknowledgement by the host that it applied the write logs of a round.
+#[derive(Clone, Debug, Default, PartialEq, Eq, cbor::Encode, cbor::Decode)]
+pub struct CommitAck {
+    /// Chain namespace of the applied roots.
+    pub namespace: Namespace,
+    /// Round of the applied roots.
+    pub round: u64,
+    /// State root the host ended up with.
+    pub state_root: Hash,
+    /// I/O root the host ended up with.
+    pub io_root: Hash,
+}
+
+/// Roots of a round committed locally.
+#[derive(Clone, Debug)]
+struct LocalCommit {
+    namespace: Namespace,
+    state_root: Hash,
+    io_root: Hash,
+}
+
+impl LocalCommit {
+    /// Return the first root in which the given acknowledgement diverges from this commit.
+    fn diverging_root(&self, ack: &CommitAck) -> Option<(RootType, Hash, Hash)> {
+        [
+            (RootType::State, self.state_root, ack.state_root),
+            (RootType::IO, self.io_root, ack.io_root),
+        ]
+        .into_iter()
+        .find(|(_, local, host)| local != host)
+    }
+}
+
+/// Roots committed locally in recent rounds.
+pub struct LocalCommits {
+    capacity: usize,
+    commits: Mutex<BTreeMap<u64, Vec<LocalCommit>>>,
+}
+
+impl LocalCommits {
+    /// Create a new set of local commits, keeping the commits of at most the given number of
+    /// rounds. A zero capacity disables recording.
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            commits: Mutex::new(BTreeMap::new()),
+        }
+    }
+
+    /// Record the roots committed locally in the given round.
+    pub fn record(&self, namespace: Namespace, round: u64, state_root: Hash, io_root: Hash) {
+        if self.capacity == 0 {
+            return;
+        }
+        let mut commits = self.commits.lock().unwrap();
+        commits.entry(round).or_default().push(LocalCommit {
+            namespace,
+            state_root,
+            io_root,
+        });
+        while commits.len() > self.capacity {
+            commits.pop_first();
+        }
+    }
+
+    /// Verify the given acknowledgement against the local commits of its round.
+    pub fn verify(&self, ack: &CommitAck) -> Result<(), CommitError> {
+        let commits = self.commits.lock().unwrap();
+        let candidates = commits
+            .get(&ack.round)
+            .ok_or(CommitError::UnknownRound(ack.round))?;
+        let latest = candidates.last().expect("rounds have at least one commit");
+        if !candidates.iter().any(|c| c.namespace == ack.namespace) {
+            return Err(CommitError::NamespaceMismatch {
+                expected: latest.namespace,
+                got: ack.namespace,
+            });
+        }
+        if candidates.iter().any(|c| c.diverging_root(ack).is_none()) {
+            return Ok(());
+        }
+
+        // Report the divergence from the latest commit.
+        let (root_type, local, host) = latest.diverging_root(ack).expect("commit must diverge");
+        Err(CommitError::Divergence {
+            round: ack.round,
+            root_type,
+            local,
+            host,
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_local_commits() {
+        let commits = LocalCommits::new(2);
+        let namespace = Namespace::default();
+        let (state, io) = (Hash::digest_bytes(b"state"), Hash::digest_bytes(b"io"));
+        commits.record(namespace, 1, state, io);
+        commits.record(namespace, 2, state, io);
+
+        let ack = CommitAck {
+            namespace,
+            round: 2,
+            state_root: state,
+            io_root: io,
+        };
+        assert_eq!(commits.verify(&ack), Ok(()));
+
+        // Divergence reports both roots.
+        let corrupted = Hash::digest_bytes(b"corrupted");
+        let diverging = CommitAck {
+            state_root: corrupted,
+            ..ack.clone()
+        };
+        assert_eq!(
+            commits.verify(&diverging),
+            Err(CommitError::Divergence {
+                round: 2,
+                root_type: RootType::State,
+                local: state,
+                host: corrupted,
+            })
+        );
+
+        // Any local commit of the round matches.
+        commits.record(namespace, 2, corrupted, io);
+        assert_eq!(commits.verify(&diverging), Ok(()));
+        assert_eq!(commits.verify(&ack), Ok(()));
+
+        let other = CommitAck {
+            namespace: Namespace([1; 32]),
+            ..ack.clone()
+        };
+        assert!(matches!(
+            commits.verify(&other),
+            Err(CommitError::NamespaceMismatch { .. })
+        ));
+
+        // Only the most recent rounds are kept.
+        commits.record(namespace, 3, state, io);
+        let old = CommitAck { round: 1, ..ack };
+        assert_eq!(commits.verify(&old), Err(CommitError::UnknownRound(1)));
+    }
+}
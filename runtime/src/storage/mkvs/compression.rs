@@ -0,0 +1,144 @@
+//! Compressed write log encoding.
+//!
+//! Write logs of runtimes with hot keys are large and highly redundant, as the keys in a write log
+//! are sorted and tend to share long prefixes. The compressed encoding replaces each key with the
+//! length of the prefix it shares with the previous key and the remaining suffix, and compresses
+//! the resulting log as a single zstd frame.
+//!
+//! Frames are produced and consumed by `ruzstd`, a pure Rust implementation of zstd which unlike
+//! the bindings to the reference C library also builds for SGX enclaves.
+use std::io::Read;
+
+use anyhow::{anyhow, Result};
+use ruzstd::{
+    decoding::StreamingDecoder,
+    encoding::{compress_to_vec, CompressionLevel},
+};
+
+use super::{LogEntry, WriteLog};
+
+/// Compression level used for write logs.
+const COMPRESSION_LEVEL: CompressionLevel = CompressionLevel::Fastest;
+
+/// Maximum size of a decompressed write log.
+pub const MAX_DECOMPRESSED_SIZE: u64 = 256 * 1024 * 1024;
+
+/// A write log entry with its key delta-encoded against the key of the previous entry.
+#[derive(Clone, Debug, Default, cbor::Encode, cbor::Decode)]
+#[cbor(as_array)]
+struct DeltaEntry {
+    /// Length of the prefix shared with the previous key.
+    prefix_len: u32,
+    /// The rest of the key.
+    suffix: Vec<u8>,
+    /// The inserted value (empty if the key was deleted).
+    value: Option<Vec<u8>>,
+}
+
+/// A compressed write log.
+#[derive(Clone, Debug, Default, PartialEq, Eq, cbor::Encode, cbor::Decode)]
+#[cbor(transparent)]
+pub struct CompressedWriteLog(pub Vec<u8>);
+
+impl CompressedWriteLog {
+    /// Compress the given write log.
+    pub fn compress(write_log: &WriteLog) -> Self {
+        let mut previous: &[u8] = &[];
+        let entries: Vec<DeltaEntry> = write_log
+            .iter()
+            .map(|entry| {
+                let prefix_len = previous
+                    .iter()
+                    .zip(&entry.key)
+                    .take_while(|(a, b)| a == b)
+                    .count();
+                previous = &entry.key;
+
+                DeltaEntry {
+                    prefix_len: prefix_len as u32,
+                    suffix: entry.key[prefix_len..].to_vec(),
+                    value: entry.value.clone(),
+                }
+            })
+            .collect();
+
+        let raw = cbor::to_vec(entries);
+        Self(compress_to_vec(&raw[..], COMPRESSION_LEVEL))
+    }
+
+    /// Decompress the write log.
+    pub fn decompress(&self) -> Result<WriteLog> {
+        let mut raw = Vec::new();
+        let mut source = &self.0[..];
+        StreamingDecoder::new(&mut source)
+            .map_err(|err| anyhow!("mkvs: malformed compressed write log: {err}"))?
+            .take(MAX_DECOMPRESSED_SIZE + 1)
+            .read_to_end(&mut raw)?;
+        if raw.len() as u64 > MAX_DECOMPRESSED_SIZE {
+            return Err(anyhow!("mkvs: compressed write log too large"));
+        }
+
+        let entries: Vec<DeltaEntry> = cbor::from_slice(&raw)?;
+        let mut write_log = WriteLog::with_capacity(entries.len());
+        for entry in entries {
+            let previous = write_log
+                .last()
+                .map(|e| e.key.as_slice())
+                .unwrap_or_default();
+            let prefix = previous
+                .get(..entry.prefix_len as usize)
+                .ok_or_else(|| anyhow!("mkvs: malformed compressed write log"))?;
+            let key = [prefix, &entry.suffix].concat();
+            write_log.push(LogEntry {
+                key,
+                value: entry.value,
+            });
+        }
+
+        Ok(write_log)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_compressed_write_log() {
+        let write_log = vec![
+            LogEntry::new(b"account/0001/balance", b"100"),
+            LogEntry::new(b"account/0001/nonce", b"1"),
+            LogEntry {
+                key: b"account/0002/balance".to_vec(),
+                value: None,
+            },
+            LogEntry::new(b"b", b""),
+            LogEntry::new(b"b/longer", b"value"),
+        ];
+        let compressed = CompressedWriteLog::compress(&write_log);
+        assert_eq!(compressed.decompress().unwrap(), write_log);
+
+        // Hot keys compress well.
+        let write_log: WriteLog = (0..1000u32)
+            .map(|i| LogEntry::new(format!("hot/key/{i:08}").as_bytes(), &[0; 32]))
+            .collect();
+        let compressed = CompressedWriteLog::compress(&write_log);
+        assert!(compressed.0.len() * 4 < cbor::to_vec(write_log.clone()).len());
+        assert_eq!(compressed.decompress().unwrap(), write_log);
+
+        let compressed = CompressedWriteLog::compress(&vec![]);
+        assert!(compressed.decompress().unwrap().is_empty());
+
+        // Malformed encodings are rejected.
+        assert!(CompressedWriteLog(b"garbage".to_vec())
+            .decompress()
+            .is_err());
+        let entries = vec![DeltaEntry {
+            prefix_len: 1,
+            suffix: b"key".to_vec(),
+            value: None,
+        }];
+        let compressed = compress_to_vec(&cbor::to_vec(entries)[..], COMPRESSION_LEVEL);
+        assert!(CompressedWriteLog(compressed).decompress().is_err());
+    }
+}
@@ -0,0 +1,212 @@
+//! Per-round export of dirty keys.
+//!
+//! Components keeping derived data outside of the tree (e.g. query caches, in-enclave indexes or
+//! read replicas) would otherwise have to flush everything on each round, as they can't tell
+//! which keys changed. After the state of a round is committed, the set of keys written by the
+//! round is published to all subscribers, optionally filtered to the key prefixes they are
+//! interested in. Only keys are published, values can be read from the committed state.
+//!
+//! Dirty sets are published when the round is executed locally, which doesn't imply that the
+//! round will be finalized. Subscribers should verify the state root against a finalized block in
+//! case this matters to them. Subscribers that fall behind miss dirty sets and must then
+//! invalidate everything.
+use std::{collections::BTreeSet, sync::Arc};
+
+use thiserror::Error;
+use tokio::sync::broadcast;
+
+use crate::{
+    common::crypto::hash::Hash,
+    storage::mkvs::{LogEntryKind, WriteLog},
+};
+
+/// Dirty set subscription errors.
+#[derive(Error, Debug, PartialEq, Eq)]
+pub enum DirtySetError {
+    #[error("lagged behind (skipped: {0})")]
+    Lagged(u64),
+
+    #[error("publisher closed")]
+    Closed,
+}
+
+/// Keys written by a round.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct DirtySet {
+    /// Round whose state the keys were written in.
+    pub round: u64,
+    /// State root after the round.
+    pub state_root: Hash,
+    /// Keys that were inserted or updated.
+    pub updated: BTreeSet<Vec<u8>>,
+    /// Keys that were deleted.
+    pub deleted: BTreeSet<Vec<u8>>,
+}
+
+impl DirtySet {
+    /// Create a dirty set from the write log of the given round.
+    pub fn from_write_log(round: u64, state_root: Hash, write_log: &WriteLog) -> Self {
+        let mut set = Self {
+            round,
+            state_root,
+            ..Default::default()
+        };
+        for entry in write_log {
+            match entry.kind() {
+                LogEntryKind::Insert => set.updated.insert(entry.key.clone()),
+                LogEntryKind::Delete => set.deleted.insert(entry.key.clone()),
+            };
+        }
+        set
+    }
+
+    /// Whether no keys were written.
+    pub fn is_empty(&self) -> bool {
+        self.updated.is_empty() && self.deleted.is_empty()
+    }
+
+    /// Whether the given key was written.
+    pub fn contains(&self, key: &[u8]) -> bool {
+        self.updated.contains(key) || self.deleted.contains(key)
+    }
+
+    /// Dirty set restricted to keys with any of the given prefixes.
+    pub fn filter(&self, prefixes: &[Vec<u8>]) -> Self {
+        let matches = |key: &&Vec<u8>| prefixes.iter().any(|prefix| key.starts_with(prefix));
+        Self {
+            round: self.round,
+            state_root: self.state_root,
+            updated: self.updated.iter().filter(matches).cloned().collect(),
+            deleted: self.deleted.iter().filter(matches).cloned().collect(),
+        }
+    }
+}
+
+/// Publisher of per-round dirty sets.
+pub struct DirtySets {
+    sender: Option<broadcast::Sender<Arc<DirtySet>>>,
+}
+
+impl DirtySets {
+    /// Create a new publisher, buffering at most the given number of dirty sets for each
+    /// subscriber. A zero capacity disables publishing.
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            sender: (capacity > 0).then(|| broadcast::channel(capacity).0),
+        }
+    }
+
+    /// Subscribe to dirty sets of all subsequently committed rounds, returning `None` in case
+    /// publishing is disabled.
+    pub fn subscribe(&self) -> Option<DirtySetSubscription> {
+        self.subscribe_prefixes(vec![])
+    }
+
+    /// Subscribe to dirty sets of all subsequently committed rounds, restricted to keys with any
+    /// of the given prefixes. In case no prefixes are given, all keys are included.
+    pub fn subscribe_prefixes(&self, prefixes: Vec<Vec<u8>>) -> Option<DirtySetSubscription> {
+        let receiver = self.sender.as_ref()?.subscribe();
+        Some(DirtySetSubscription { receiver, prefixes })
+    }
+
+    /// Publish the keys written by the given round. The dirty set is only built in case there
+    /// are any subscribers.
+    pub fn publish(&self, round: u64, state_root: Hash, write_log: &WriteLog) {
+        let sender = match &self.sender {
+            Some(sender) if sender.receiver_count() > 0 => sender,
+            _ => return,
+        };
+        let set = DirtySet::from_write_log(round, state_root, write_log);
+        // Subscribers may go away concurrently, in which case the dirty set is dropped.
+        let _ = sender.send(Arc::new(set));
+    }
+}
+
+/// Subscription to per-round dirty sets.
+pub struct DirtySetSubscription {
+    receiver: broadcast::Receiver<Arc<DirtySet>>,
+    prefixes: Vec<Vec<u8>>,
+}
+
+impl DirtySetSubscription {
+    /// Wait for the dirty set of the next committed round.
+    ///
+    /// Dirty sets are received for all rounds, even if no keys of interest were written, so that
+    /// subscribers can track which round their derived data is at. In case the subscriber fell
+    /// behind, `DirtySetError::Lagged` is returned once and the following call continues with
+    /// the oldest buffered dirty set.
+    pub async fn recv(&mut self) -> Result<Arc<DirtySet>, DirtySetError> {
+        let set = self.receiver.recv().await.map_err(|err| match err {
+            broadcast::error::RecvError::Lagged(skipped) => DirtySetError::Lagged(skipped),
+            broadcast::error::RecvError::Closed => DirtySetError::Closed,
+        })?;
+        if self.prefixes.is_empty() {
+            return Ok(set);
+        }
+        Ok(Arc::new(set.filter(&self.prefixes)))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::storage::mkvs::LogEntry;
+
+    fn write_log() -> WriteLog {
+        vec![
+            LogEntry::new(b"accounts/alice", b"10"),
+            LogEntry::new(b"accounts/bob", b"20"),
+            LogEntry {
+                key: b"nonces/alice".to_vec(),
+                value: None,
+            },
+        ]
+    }
+
+    #[tokio::test]
+    async fn test_dirty_sets() {
+        let root = Hash::digest_bytes(b"root");
+
+        // Publishing without subscribers is a no-op.
+        let dirty_sets = DirtySets::new(2);
+        dirty_sets.publish(1, root, &write_log());
+
+        let mut all = dirty_sets.subscribe().unwrap();
+        let mut accounts = dirty_sets
+            .subscribe_prefixes(vec![b"accounts/".to_vec()])
+            .unwrap();
+        dirty_sets.publish(2, root, &write_log());
+
+        let set = all.recv().await.unwrap();
+        assert_eq!(set.round, 2);
+        assert_eq!(set.state_root, root);
+        assert_eq!(set.updated.len(), 2);
+        assert!(set.deleted.contains(&b"nonces/alice".to_vec()));
+        assert!(set.contains(b"accounts/bob"));
+        assert!(!set.contains(b"accounts/carol"));
+
+        let set = accounts.recv().await.unwrap();
+        assert_eq!(set.updated.len(), 2);
+        assert!(set.deleted.is_empty());
+
+        // Rounds without keys of interest are still received.
+        dirty_sets.publish(3, root, &vec![LogEntry::new(b"nonces/bob", b"1")]);
+        assert!(accounts.recv().await.unwrap().is_empty());
+
+        assert_eq!(all.recv().await.unwrap().round, 3);
+
+        // Subscribers falling behind are notified.
+        for round in 4..7 {
+            dirty_sets.publish(round, root, &write_log());
+        }
+        assert_eq!(all.recv().await, Err(DirtySetError::Lagged(2)));
+        assert_eq!(all.recv().await.unwrap().round, 5);
+
+        drop(dirty_sets);
+        assert_eq!(all.recv().await.unwrap().round, 6);
+        assert_eq!(all.recv().await, Err(DirtySetError::Closed));
+
+        // Publishing can be disabled.
+        assert!(DirtySets::new(0).subscribe().is_none());
+    }
+}
@@ -0,0 +1,370 @@
+//! Encrypted storage with key rotation.
+//!
+//! Confidential runtimes store values encrypted under a state key obtained from the key manager,
+//! which is rotated together with the master secret. A [`DecryptingTree`] encrypts all values
+//! written through it under the key of the latest generation and decrypts values read through it
+//! under the key of the generation they were written with, so that entries written before a
+//! rotation remain readable. Entries are re-encrypted under the latest key whenever they are
+//! overwritten, or by walking key ranges incrementally in bounded chunks via
+//! [`DecryptingTree::reencrypt`].
+//!
+//! The number of entries encrypted under each generation is stored in the tree itself, under a
+//! reserved key, so that runtimes know when keys of past generations are no longer needed.
+//! Only values are encrypted, keys are stored in plaintext.
+use std::collections::BTreeMap;
+
+use thiserror::Error;
+
+use crate::common::crypto::{
+    hash::Hash,
+    mrae::deoxysii::{DeoxysII, KEY_SIZE, NONCE_SIZE},
+};
+
+use super::MKVS;
+
+/// Encrypted tree errors.
+#[derive(Error, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DecryptingTreeError {
+    #[error("no keys given")]
+    NoKeys,
+
+    #[error("key is the reserved key generation map key")]
+    ReservedKey,
+
+    #[error("malformed key generation map")]
+    MalformedGenerations,
+
+    #[error("malformed encrypted value")]
+    MalformedValue,
+
+    #[error("unknown key generation {0}")]
+    UnknownGeneration(u64),
+
+    #[error("failed to decrypt value")]
+    Decryption,
+}
+
+/// An encrypted value, together with the key generation it was encrypted under.
+#[derive(Clone, Debug, Default, cbor::Encode, cbor::Decode)]
+#[cbor(as_array)]
+struct EncryptedValue {
+    generation: u64,
+    ciphertext: Vec<u8>,
+}
+
+/// Result of re-encrypting a chunk of a key range.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct ReencryptedChunk {
+    /// Number of visited entries.
+    pub visited: usize,
+    /// Number of visited entries that were re-encrypted.
+    pub reencrypted: usize,
+    /// Key to continue from, in case entries with the prefix remain.
+    pub next: Option<Vec<u8>>,
+}
+
+/// Tree wrapper encrypting values under rotating keys.
+pub struct DecryptingTree<M: MKVS> {
+    inner: M,
+    generations_key: Vec<u8>,
+    keys: BTreeMap<u64, DeoxysII>,
+    latest: u64,
+    generations: BTreeMap<u64, u64>,
+}
+
+impl<M: MKVS> DecryptingTree<M> {
+    /// Wrap the given tree, encrypting values under the given keys, by generation, and storing
+    /// the number of entries of each generation under the given reserved key.
+    ///
+    /// Values are written under the key of the latest generation, so at least one key must be
+    /// given.
+    pub fn new(
+        inner: M,
+        generations_key: &[u8],
+        keys: BTreeMap<u64, [u8; KEY_SIZE]>,
+    ) -> Result<Self, DecryptingTreeError> {
+        let latest = *keys.keys().next_back().ok_or(DecryptingTreeError::NoKeys)?;
+        let keys = keys
+            .into_iter()
+            .map(|(generation, key)| (generation, DeoxysII::new(&key)))
+            .collect();
+        let generations = match inner.get(generations_key) {
+            Some(raw) => {
+                cbor::from_slice(&raw).map_err(|_| DecryptingTreeError::MalformedGenerations)?
+            }
+            None => BTreeMap::new(),
+        };
+
+        Ok(Self {
+            inner,
+            generations_key: generations_key.to_vec(),
+            keys,
+            latest,
+            generations,
+        })
+    }
+
+    /// The wrapped tree.
+    ///
+    /// Reads from the wrapped tree return encrypted values.
+    pub fn inner(&mut self) -> &mut M {
+        &mut self.inner
+    }
+
+    /// Unwrap the tree.
+    pub fn into_inner(self) -> M {
+        self.inner
+    }
+
+    /// Number of entries encrypted under each key generation.
+    pub fn generations(&self) -> &BTreeMap<u64, u64> {
+        &self.generations
+    }
+
+    /// Generation of the key values are written under.
+    pub fn latest_generation(&self) -> u64 {
+        self.latest
+    }
+
+    /// Number of entries encrypted under key generations older than the latest one.
+    pub fn pending(&self) -> u64 {
+        self.generations.range(..self.latest).map(|(_, n)| n).sum()
+    }
+
+    /// Fetch entry with given key.
+    ///
+    /// Fails in case the value can't be decrypted, e.g. because the key of its generation is
+    /// not known.
+    pub fn get(&self, key: &[u8]) -> Result<Option<Vec<u8>>, DecryptingTreeError> {
+        self.ensure_not_reserved(key)?;
+        match self.inner.get(key) {
+            Some(raw) => Ok(Some(self.decrypt(key, &raw)?.1)),
+            None => Ok(None),
+        }
+    }
+
+    /// Update entry with given key, encrypting the value under the latest key generation.
+    ///
+    /// The tree is left unchanged in case the previous value can't be decrypted.
+    pub fn insert(
+        &mut self,
+        key: &[u8],
+        value: &[u8],
+    ) -> Result<Option<Vec<u8>>, DecryptingTreeError> {
+        self.ensure_not_reserved(key)?;
+        let previous = match self.inner.get(key) {
+            Some(raw) => Some(self.decrypt(key, &raw)?),
+            None => None,
+        };
+        let encrypted = self.encrypt(key, value);
+        self.inner.insert(key, &encrypted);
+
+        self.update_generations(previous.as_ref().map(|(g, _)| *g), Some(self.latest));
+        Ok(previous.map(|(_, value)| value))
+    }
+
+    /// Remove entry with given key, returning the value at the key if the key was previously
+    /// in the database.
+    ///
+    /// The tree is left unchanged in case the value can't be decrypted.
+    pub fn remove(&mut self, key: &[u8]) -> Result<Option<Vec<u8>>, DecryptingTreeError> {
+        self.ensure_not_reserved(key)?;
+        let Some(raw) = self.inner.get(key) else {
+            return Ok(None);
+        };
+        let (generation, value) = self.decrypt(key, &raw)?;
+        self.inner.remove(key);
+
+        self.update_generations(Some(generation), None);
+        Ok(Some(value))
+    }
+
+    /// Visit at most `limit` entries with keys with the given prefix, in key order starting at
+    /// the given key, re-encrypting those encrypted under key generations older than the latest
+    /// one.
+    ///
+    /// Walks can be resumed from the key returned in the chunk. No entries are re-encrypted in
+    /// case any of the visited values can't be decrypted.
+    pub fn reencrypt(
+        &mut self,
+        prefix: &[u8],
+        start: &[u8],
+        limit: usize,
+    ) -> Result<ReencryptedChunk, DecryptingTreeError> {
+        let mut it = self.inner.iter();
+        it.seek(start.max(prefix));
+        let mut entries: Vec<_> = it
+            .take_while(|(key, _)| key.starts_with(prefix))
+            .filter(|(key, _)| key != &self.generations_key)
+            .take(limit.saturating_add(1))
+            .collect();
+        let next = (entries.len() > limit).then(|| entries.remove(limit).0);
+
+        let mut chunk = ReencryptedChunk {
+            visited: entries.len(),
+            next,
+            ..Default::default()
+        };
+        let mut stale = vec![];
+        for (key, raw) in entries {
+            let (generation, value) = self.decrypt(&key, &raw)?;
+            if generation < self.latest {
+                stale.push((key, generation, value));
+            }
+        }
+        for (key, generation, value) in stale {
+            let encrypted = self.encrypt(&key, &value);
+            self.inner.insert(&key, &encrypted);
+            self.update_generations(Some(generation), Some(self.latest));
+            chunk.reencrypted += 1;
+        }
+        Ok(chunk)
+    }
+
+    fn encrypt(&self, key: &[u8], value: &[u8]) -> Vec<u8> {
+        // Deoxys-II is nonce misuse resistant, so deriving the nonce from the key only reveals
+        // whether the same value was written to the same key, while keeping writes deterministic
+        // across replicas.
+        let ciphertext = self.keys[&self.latest].seal(&nonce(key), value.to_vec(), key.to_vec());
+        cbor::to_vec(EncryptedValue {
+            generation: self.latest,
+            ciphertext,
+        })
+    }
+
+    fn decrypt(&self, key: &[u8], raw: &[u8]) -> Result<(u64, Vec<u8>), DecryptingTreeError> {
+        let encrypted: EncryptedValue =
+            cbor::from_slice(raw).map_err(|_| DecryptingTreeError::MalformedValue)?;
+        let value = self
+            .keys
+            .get(&encrypted.generation)
+            .ok_or(DecryptingTreeError::UnknownGeneration(encrypted.generation))?
+            .open(&nonce(key), encrypted.ciphertext, key.to_vec())
+            .map_err(|_| DecryptingTreeError::Decryption)?;
+        Ok((encrypted.generation, value))
+    }
+
+    fn update_generations(&mut self, removed: Option<u64>, added: Option<u64>) {
+        if removed == added {
+            return;
+        }
+        if let Some(generation) = removed {
+            let count = self.generations.entry(generation).or_default();
+            *count = count.saturating_sub(1);
+            if *count == 0 {
+                self.generations.remove(&generation);
+            }
+        }
+        if let Some(generation) = added {
+            *self.generations.entry(generation).or_default() += 1;
+        }
+
+        if self.generations.is_empty() {
+            self.inner.remove(&self.generations_key);
+        } else {
+            let raw = cbor::to_vec(self.generations.clone());
+            self.inner.insert(&self.generations_key, &raw);
+        }
+    }
+
+    fn ensure_not_reserved(&self, key: &[u8]) -> Result<(), DecryptingTreeError> {
+        if key == self.generations_key {
+            return Err(DecryptingTreeError::ReservedKey);
+        }
+        Ok(())
+    }
+}
+
+/// Nonce used to encrypt the value of the given key.
+fn nonce(key: &[u8]) -> [u8; NONCE_SIZE] {
+    let mut nonce = [0u8; NONCE_SIZE];
+    nonce.copy_from_slice(Hash::digest_bytes(key).truncated(NONCE_SIZE));
+    nonce
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::storage::mkvs::{sync::NoopReadSyncer, OverlayTree, RootType, Tree};
+
+    const GENERATIONS: &[u8] = b"\xffgenerations";
+
+    #[test]
+    fn test_decrypting_tree() {
+        let tree = Tree::builder()
+            .with_root_type(RootType::State)
+            .build(Box::new(NoopReadSyncer));
+        let keys = BTreeMap::from([(0, [0; KEY_SIZE])]);
+        let mut tree = DecryptingTree::new(OverlayTree::new(tree), GENERATIONS, keys).unwrap();
+
+        for i in 0..10u8 {
+            tree.insert(&[i], b"value").unwrap();
+        }
+        assert_eq!(tree.get(&[1]).unwrap(), Some(b"value".to_vec()));
+        assert_ne!(tree.inner().get(&[1]), Some(b"value".to_vec()));
+        assert_eq!(tree.remove(&[9]).unwrap(), Some(b"value".to_vec()));
+        assert_eq!(tree.generations(), &BTreeMap::from([(0, 9)]));
+        assert_eq!(tree.pending(), 0);
+        assert_eq!(
+            tree.insert(GENERATIONS, b"value"),
+            Err(DecryptingTreeError::ReservedKey)
+        );
+
+        // Entries of past generations remain readable after a rotation.
+        let keys = BTreeMap::from([(0, [0; KEY_SIZE]), (1, [1; KEY_SIZE])]);
+        let mut tree = DecryptingTree::new(tree.into_inner(), GENERATIONS, keys).unwrap();
+        assert_eq!(tree.get(&[1]).unwrap(), Some(b"value".to_vec()));
+        assert_eq!(tree.pending(), 9);
+
+        // Writes re-encrypt entries under the latest generation.
+        assert_eq!(tree.insert(&[1], b"new").unwrap(), Some(b"value".to_vec()));
+        assert_eq!(tree.generations(), &BTreeMap::from([(0, 8), (1, 1)]));
+
+        // Key ranges are re-encrypted incrementally.
+        let chunk = tree.reencrypt(&[], &[1], 3).unwrap();
+        assert_eq!(
+            chunk,
+            ReencryptedChunk {
+                visited: 3,
+                reencrypted: 2,
+                next: Some(vec![4]),
+            }
+        );
+        let chunk = tree.reencrypt(&[], &[4], 10).unwrap();
+        assert_eq!((chunk.visited, chunk.reencrypted, chunk.next), (5, 5, None));
+        assert_eq!(tree.reencrypt(&[0], &[], 10).unwrap().reencrypted, 1);
+        assert_eq!(tree.pending(), 0);
+        assert_eq!(tree.generations(), &BTreeMap::from([(1, 9)]));
+        assert_eq!(tree.latest_generation(), 1);
+
+        // Values of unknown generations can't be read.
+        let keys = BTreeMap::from([(2, [2; KEY_SIZE])]);
+        let mut tree = DecryptingTree::new(tree.into_inner(), GENERATIONS, keys).unwrap();
+        assert_eq!(
+            tree.get(&[1]),
+            Err(DecryptingTreeError::UnknownGeneration(1))
+        );
+        assert_eq!(
+            tree.reencrypt(&[], &[], 10),
+            Err(DecryptingTreeError::UnknownGeneration(1))
+        );
+        assert!(tree.remove(&[1]).is_err());
+        assert_eq!(tree.pending(), 9);
+
+        // Keys of past generations are no longer needed.
+        let keys = BTreeMap::from([(1, [1; KEY_SIZE])]);
+        let mut tree = DecryptingTree::new(tree.into_inner(), GENERATIONS, keys).unwrap();
+        assert_eq!(tree.get(&[1]).unwrap(), Some(b"new".to_vec()));
+        assert_eq!(tree.get(&[2]).unwrap(), Some(b"value".to_vec()));
+        for i in 0..9u8 {
+            tree.remove(&[i]).unwrap();
+        }
+        assert!(tree.generations().is_empty());
+        assert_eq!(tree.inner().get(GENERATIONS), None);
+
+        assert!(matches!(
+            DecryptingTree::new(tree.into_inner(), GENERATIONS, BTreeMap::new()),
+            Err(DecryptingTreeError::NoKeys)
+        ));
+    }
+}
@@ -0,0 +1,204 @@
+//! Portable flat exports of tree contents.
+//!
+//! An export is a flat serialization of all key/value pairs of a finalized tree, in key order.
+//! Unlike checkpoints or images it does not depend on the internal node encoding, so it can be
+//! used to move state between environments (e.g. testnet snapshots or audits) and is meant to be
+//! stored outside the enclave, for example on a host-provided volume.
+//!
+//! The header commits to the root of the exported tree. Entries are hash-chained starting from
+//! the hash of the header and the trailer contains the number of entries together with the final
+//! chain hash, so truncated or modified exports are detected while reading. On import the tree is
+//! additionally rebuilt and its root hash compared against the header.
+use std::io::{Read, Write};
+
+use anyhow::{anyhow, Result};
+use byteorder::{BigEndian, ReadBytesExt, WriteBytesExt};
+
+use crate::{
+    common::{crypto::hash::Hash, namespace::Namespace},
+    storage::mkvs::{sync::NoopReadSyncer, Iterator, Root, RootType, Tree},
+};
+
+/// Magic bytes identifying an MKVS export.
+const EXPORT_MAGIC: &[u8; 8] = b"mkvsexp1";
+/// Key length marking the end of the entries.
+const EXPORT_TERMINATOR: u32 = u32::MAX;
+
+/// Export all entries of the given tree, which must be at the given root, to the given writer.
+///
+/// Returns the number of exported entries.
+pub fn export<W: Write>(writer: &mut W, tree: &Tree, root: Root) -> Result<u64> {
+    let header = encode_header(&root);
+    writer.write_all(&header)?;
+
+    let mut chain = Hash::digest_bytes(&header);
+    let mut count = 0u64;
+    let mut it = tree.iter();
+    for (key, value) in &mut it {
+        let entry = encode_entry(&key, &value)?;
+        writer.write_all(&entry)?;
+        chain = Hash::digest_bytes_list(&[chain.as_ref(), &entry]);
+        count += 1;
+    }
+    if let Some(err) = it.error() {
+        return Err(anyhow!("mkvs/export: failed to iterate tree: {}", err));
+    }
+
+    // Trailer.
+    writer.write_u32::<BigEndian>(EXPORT_TERMINATOR)?;
+    writer.write_u64::<BigEndian>(count)?;
+    writer.write_all(chain.as_ref())?;
+
+    Ok(count)
+}
+
+/// Import an export from the given reader, verifying its integrity.
+///
+/// Returns the rebuilt tree, committed at the exported root, together with that root.
+pub fn import<R: Read>(reader: &mut R) -> Result<(Tree, Root)> {
+    let mut magic = [0u8; 8];
+    reader.read_exact(&mut magic)?;
+    if &magic != EXPORT_MAGIC {
+        return Err(anyhow!("mkvs/export: bad magic"));
+    }
+    let root_type = match reader.read_u8()? {
+        1 => RootType::State,
+        2 => RootType::IO,
+        _ => return Err(anyhow!("mkvs/export: bad root type")),
+    };
+    let mut namespace = [0u8; 32];
+    reader.read_exact(&mut namespace)?;
+    let version = reader.read_u64::<BigEndian>()?;
+    let mut hash = [0u8; 32];
+    reader.read_exact(&mut hash)?;
+    let root = Root {
+        namespace: Namespace::from(&namespace[..]),
+        version,
+        root_type,
+        hash: Hash::from(&hash[..]),
+    };
+
+    let mut tree = Tree::builder()
+        .with_capacity(0, 0)
+        .with_root_type(root_type)
+        .build(Box::new(NoopReadSyncer));
+    let mut chain = Hash::digest_bytes(&encode_header(&root));
+    let mut count = 0u64;
+    let mut last_key: Option<Vec<u8>> = None;
+    loop {
+        let key_len = reader.read_u32::<BigEndian>()?;
+        if key_len == EXPORT_TERMINATOR {
+            break;
+        }
+        let key = read_bytes(reader, key_len)?;
+        let value_len = reader.read_u32::<BigEndian>()?;
+        let value = read_bytes(reader, value_len)?;
+
+        if matches!(last_key, Some(ref last) if *last >= key) {
+            return Err(anyhow!("mkvs/export: entries not in key order"));
+        }
+        chain = Hash::digest_bytes_list(&[chain.as_ref(), &encode_entry(&key, &value)?]);
+        count += 1;
+
+        tree.insert(&key, &value)?;
+        last_key = Some(key);
+    }
+
+    // Trailer.
+    if reader.read_u64::<BigEndian>()? != count {
+        return Err(anyhow!("mkvs/export: entry count mismatch"));
+    }
+    let mut expected_chain = [0u8; 32];
+    reader.read_exact(&mut expected_chain)?;
+    if chain != Hash::from(&expected_chain[..]) {
+        return Err(anyhow!("mkvs/export: chain hash mismatch"));
+    }
+
+    let hash = tree.commit(root.namespace, root.version)?;
+    if hash != root.hash {
+        return Err(anyhow!("mkvs/export: root hash mismatch"));
+    }
+
+    Ok((tree, root))
+}
+
+fn encode_header(root: &Root) -> Vec<u8> {
+    [
+        &EXPORT_MAGIC[..],
+        &[root.root_type as u8][..],
+        root.namespace.as_ref(),
+        &root.version.to_be_bytes()[..],
+        root.hash.as_ref(),
+    ]
+    .concat()
+}
+
+fn encode_entry(key: &[u8], value: &[u8]) -> Result<Vec<u8>> {
+    let key_len = u32::try_from(key.len())?;
+    if key_len == EXPORT_TERMINATOR {
+        return Err(anyhow!("mkvs/export: key too long"));
+    }
+    let value_len = u32::try_from(value.len())?;
+
+    Ok([
+        &key_len.to_be_bytes()[..],
+        key,
+        &value_len.to_be_bytes()[..],
+        value,
+    ]
+    .concat())
+}
+
+fn read_bytes<R: Read>(reader: &mut R, len: u32) -> Result<Vec<u8>> {
+    let mut buf = Vec::new();
+    reader.take(len.into()).read_to_end(&mut buf)?;
+    if buf.len() != len as usize {
+        return Err(anyhow!("mkvs/export: unexpected end of export"));
+    }
+    Ok(buf)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_export_import() {
+        let namespace = Namespace::from(vec![1; 32]);
+        let mut tree = Tree::builder()
+            .with_root_type(RootType::State)
+            .build(Box::new(NoopReadSyncer));
+        for i in 0..100u32 {
+            tree.insert(&i.to_be_bytes(), format!("value {}", i).as_bytes())
+                .unwrap();
+        }
+        let hash = tree.commit(namespace, 7).unwrap();
+        let root = Root {
+            namespace,
+            version: 7,
+            root_type: RootType::State,
+            hash,
+        };
+
+        let mut exported = Vec::new();
+        assert_eq!(export(&mut exported, &tree, root).unwrap(), 100);
+
+        let (imported, imported_root) = import(&mut exported.as_slice()).unwrap();
+        assert_eq!(imported_root, root);
+        assert_eq!(
+            imported.get(&42u32.to_be_bytes()).unwrap(),
+            Some(b"value 42".to_vec())
+        );
+
+        // Any modification is detected.
+        for offset in [0, 8, 81, 100, exported.len() / 2, exported.len() - 1] {
+            let mut tampered = exported.clone();
+            tampered[offset] ^= 0x01;
+            assert!(import(&mut tampered.as_slice()).is_err(), "{}", offset);
+        }
+
+        // Truncation is detected.
+        let truncated = &exported[..exported.len() - 50];
+        assert!(import(&mut &truncated[..]).is_err());
+    }
+}
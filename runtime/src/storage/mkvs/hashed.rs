@@ -0,0 +1,268 @@
+//! Prefix-preserving key hashing.
+//!
+//! The depth of the tree grows with the length of the keys stored in it, so a runtime storing
+//! keys chosen by its users can be forced into deep, expensive to traverse paths by long,
+//! adversarially chosen keys. To bound the depth, a runtime can select at genesis that keys are
+//! transformed before insertion: the first `prefix_len` bytes of each key are kept as is, while
+//! the whole key is replaced by its keyed hash for the remaining bytes. Iteration over prefixes of
+//! at most `prefix_len` bytes keeps working, though keys sharing such a prefix are returned in
+//! hash order rather than in key order.
+//!
+//! As hashed keys can't be reversed, the original key is stored together with the value. The
+//! selected mode is stored in the tree itself, under a reserved key, so that all replicas apply
+//! the same transformation.
+use crate::common::crypto::hash::Hash;
+
+use super::MKVS;
+
+/// Key hashing configuration, selected at genesis.
+#[derive(Clone, Debug, Default, PartialEq, Eq, cbor::Encode, cbor::Decode)]
+pub struct KeyHashing {
+    /// Key of the keyed hash.
+    pub salt: Vec<u8>,
+    /// Number of leading key bytes which are preserved.
+    pub prefix_len: u32,
+}
+
+impl KeyHashing {
+    /// Transformed form of the given key.
+    pub fn hashed_key(&self, key: &[u8]) -> Vec<u8> {
+        let preserved = key.len().min(self.prefix_len as usize);
+        let hash = Hash::digest_bytes_list(&[&self.salt, key]);
+        [&key[..preserved], hash.as_ref()].concat()
+    }
+}
+
+/// Tree wrapper optionally transforming keys using prefix-preserving key hashing.
+pub struct HashedKeyTree<M: MKVS> {
+    inner: M,
+    config_key: Vec<u8>,
+    hashing: Option<KeyHashing>,
+}
+
+impl<M: MKVS> HashedKeyTree<M> {
+    /// Wrap the given genesis tree, selecting the given key hashing mode and storing it under the
+    /// given reserved key. In case no key hashing is selected, keys are stored as is.
+    ///
+    /// # Panics
+    ///
+    /// Panics if a key hashing mode has already been selected.
+    pub fn init(mut inner: M, config_key: &[u8], hashing: Option<KeyHashing>) -> Self {
+        assert!(
+            inner.get(config_key).is_none(),
+            "key hashing mode already selected"
+        );
+        if let Some(hashing) = &hashing {
+            inner.insert(config_key, &cbor::to_vec(hashing.clone()));
+        }
+
+        Self {
+            inner,
+            config_key: config_key.to_vec(),
+            hashing,
+        }
+    }
+
+    /// Wrap the given tree, using the key hashing mode stored under the given reserved key.
+    pub fn open(inner: M, config_key: &[u8]) -> Self {
+        let hashing = inner
+            .get(config_key)
+            .map(|raw| cbor::from_slice(&raw).expect("key hashing mode should be well-formed"));
+
+        Self {
+            inner,
+            config_key: config_key.to_vec(),
+            hashing,
+        }
+    }
+
+    /// The selected key hashing mode.
+    pub fn hashing(&self) -> Option<&KeyHashing> {
+        self.hashing.as_ref()
+    }
+
+    /// The wrapped tree.
+    ///
+    /// Keys of the wrapped tree are transformed in case key hashing is selected.
+    pub fn inner(&mut self) -> &mut M {
+        &mut self.inner
+    }
+
+    /// Unwrap the tree.
+    pub fn into_inner(self) -> M {
+        self.inner
+    }
+
+    /// Fetch entry with given key.
+    pub fn get(&self, key: &[u8]) -> Option<Vec<u8>> {
+        match &self.hashing {
+            None => self.inner.get(key),
+            Some(hashing) => {
+                let raw = self.inner.get(&hashing.hashed_key(key))?;
+                decode_entry(&raw)
+                    .filter(|(original, _)| *original == key)
+                    .map(|(_, value)| value.to_vec())
+            }
+        }
+    }
+
+    /// Update entry with given key.
+    pub fn insert(&mut self, key: &[u8], value: &[u8]) -> Option<Vec<u8>> {
+        self.assert_not_reserved(key);
+        match &self.hashing {
+            None => self.inner.insert(key, value),
+            Some(hashing) => {
+                let previous = self
+                    .inner
+                    .insert(&hashing.hashed_key(key), &encode_entry(key, value))?;
+                decode_entry(&previous).map(|(_, value)| value.to_vec())
+            }
+        }
+    }
+
+    /// Remove entry with given key, returning the value at the key if the key was previously
+    /// in the database.
+    pub fn remove(&mut self, key: &[u8]) -> Option<Vec<u8>> {
+        self.assert_not_reserved(key);
+        match &self.hashing {
+            None => self.inner.remove(key),
+            Some(hashing) => {
+                let previous = self.inner.remove(&hashing.hashed_key(key))?;
+                decode_entry(&previous).map(|(_, value)| value.to_vec())
+            }
+        }
+    }
+
+    /// Entries whose keys start with the given prefix.
+    ///
+    /// In case key hashing is selected, entries are not returned in key order.
+    ///
+    /// # Panics
+    ///
+    /// Panics if key hashing is selected and the prefix is longer than the preserved prefix.
+    pub fn prefix_entries(&self, prefix: &[u8]) -> Vec<(Vec<u8>, Vec<u8>)> {
+        if let Some(hashing) = &self.hashing {
+            assert!(
+                prefix.len() <= hashing.prefix_len as usize,
+                "prefix longer than the preserved key prefix"
+            );
+        }
+
+        let mut it = self.inner.iter();
+        it.seek(prefix);
+        it.take_while(|(key, _)| key.starts_with(prefix))
+            .filter(|(key, _)| *key != self.config_key)
+            .filter_map(|(key, value)| match self.hashing {
+                None => Some((key, value)),
+                // Keys shorter than the prefix are followed by their hash, which may happen to
+                // match the rest of the prefix.
+                Some(_) => decode_entry(&value)
+                    .filter(|(original, _)| original.starts_with(prefix))
+                    .map(|(original, value)| (original.to_vec(), value.to_vec())),
+            })
+            .collect()
+    }
+
+    fn assert_not_reserved(&self, key: &[u8]) {
+        assert!(
+            self.hashing.is_some() || key != self.config_key,
+            "key uses the reserved key hashing mode key"
+        );
+    }
+}
+
+fn encode_entry(key: &[u8], value: &[u8]) -> Vec<u8> {
+    let key_len = u32::try_from(key.len()).expect("key should not be too long");
+    [&key_len.to_be_bytes()[..], key, value].concat()
+}
+
+fn decode_entry(raw: &[u8]) -> Option<(&[u8], &[u8])> {
+    if raw.len() < 4 {
+        return None;
+    }
+    let (key_len, rest) = raw.split_at(4);
+    let key_len = u32::from_be_bytes(key_len.try_into().unwrap()) as usize;
+    if rest.len() < key_len {
+        return None;
+    }
+    Some(rest.split_at(key_len))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::storage::mkvs::{sync::NoopReadSyncer, OverlayTree, RootType, Tree};
+
+    const CONFIG_KEY: &[u8] = b"\xffkeyhashing";
+
+    fn new_tree() -> OverlayTree<Tree> {
+        let tree = Tree::builder()
+            .with_root_type(RootType::State)
+            .build(Box::new(NoopReadSyncer));
+        OverlayTree::new(tree)
+    }
+
+    #[test]
+    fn test_hashed_keys() {
+        let hashing = KeyHashing {
+            salt: b"salt".to_vec(),
+            prefix_len: 2,
+        };
+        let mut tree = HashedKeyTree::init(new_tree(), CONFIG_KEY, Some(hashing.clone()));
+
+        let long_key = [&b"a/"[..], &[0x55; 1024][..]].concat();
+        assert_eq!(tree.insert(&long_key, b"long"), None);
+        assert_eq!(tree.insert(b"a/1", b"one"), None);
+        assert_eq!(tree.insert(b"a/1", b"uno"), Some(b"one".to_vec()));
+        assert_eq!(tree.insert(b"b/1", b"other"), None);
+        assert_eq!(tree.insert(b"a", b"short"), None);
+
+        assert_eq!(tree.get(&long_key), Some(b"long".to_vec()));
+        assert_eq!(tree.get(b"a/1"), Some(b"uno".to_vec()));
+        assert_eq!(tree.get(b"a/2"), None);
+
+        // Stored keys are bounded in length.
+        assert!(tree
+            .inner()
+            .iter()
+            .all(|(key, _)| key == CONFIG_KEY || key.len() <= 2 + 32));
+        assert!(tree.inner().get(&hashing.hashed_key(b"a/1")).is_some());
+
+        let mut entries = tree.prefix_entries(b"a/");
+        entries.sort();
+        assert_eq!(
+            entries,
+            vec![
+                (b"a/1".to_vec(), b"uno".to_vec()),
+                (long_key.clone(), b"long".to_vec()),
+            ]
+        );
+        assert_eq!(tree.prefix_entries(b"a").len(), 3);
+
+        assert_eq!(tree.remove(b"a/1"), Some(b"uno".to_vec()));
+        assert_eq!(tree.get(b"a/1"), None);
+
+        // The mode is retained in the tree.
+        let tree = HashedKeyTree::open(tree.into_inner(), CONFIG_KEY);
+        assert_eq!(tree.hashing(), Some(&hashing));
+        assert_eq!(tree.get(&long_key), Some(b"long".to_vec()));
+    }
+
+    #[test]
+    fn test_plain_keys() {
+        let mut tree = HashedKeyTree::init(new_tree(), CONFIG_KEY, None);
+        tree.insert(b"a/1", b"one");
+        tree.insert(b"a/2", b"two");
+        assert_eq!(tree.inner().get(b"a/1"), Some(b"one".to_vec()));
+        assert_eq!(
+            tree.prefix_entries(b"a/"),
+            vec![
+                (b"a/1".to_vec(), b"one".to_vec()),
+                (b"a/2".to_vec(), b"two".to_vec()),
+            ]
+        );
+
+        let tree = HashedKeyTree::open(tree.into_inner(), CONFIG_KEY);
+        assert_eq!(tree.hashing(), None);
+    }
+}
@@ -0,0 +1,283 @@
+//! Bounded per-key version history.
+//!
+//! Runtimes offering historical queries otherwise force clients to fetch and replay write logs
+//! of past rounds. A [`HistoryTree`] instead retains the last few versions of keys under selected
+//! prefixes in the tree itself, so that they can be read "as of" a past round locally. Each time
+//! a tracked key is written, its new value is recorded under a reserved key prefix together with
+//! the round, and versions beyond the configured depth are pruned.
+//!
+//! Versions are only recorded from the round in which history was enabled for a prefix, so the
+//! history of keys that have not been written since can't be reconstructed.
+use thiserror::Error;
+
+use super::MKVS;
+
+/// Version history errors.
+#[derive(Error, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HistoryError {
+    #[error("key not under a tracked prefix")]
+    NotTracked,
+
+    #[error("version as of round {0} is no longer retained")]
+    NotRetained(u64),
+
+    #[error("tracked prefix overlaps the reserved history prefix")]
+    OverlappingPrefix,
+
+    #[error("key uses the reserved history prefix")]
+    ReservedKey,
+
+    #[error("key too long")]
+    KeyTooLong,
+}
+
+/// Version history configuration.
+#[derive(Clone, Debug, Default)]
+pub struct HistoryConfig {
+    /// Prefixes of the keys whose versions are retained.
+    pub prefixes: Vec<Vec<u8>>,
+    /// Number of versions retained for each key. A zero value disables version history.
+    pub depth: usize,
+}
+
+/// Tree wrapper retaining the last versions of keys under tracked prefixes.
+pub struct HistoryTree<M: MKVS> {
+    inner: M,
+    history_prefix: Vec<u8>,
+    config: HistoryConfig,
+    round: u64,
+}
+
+impl<M: MKVS> HistoryTree<M> {
+    /// Wrap the given tree, recording writes made in the given round under the given reserved
+    /// key prefix.
+    ///
+    /// Fails in case any tracked prefix overlaps the reserved history prefix.
+    pub fn new(
+        inner: M,
+        history_prefix: &[u8],
+        config: HistoryConfig,
+        round: u64,
+    ) -> Result<Self, HistoryError> {
+        for prefix in &config.prefixes {
+            if history_prefix.starts_with(prefix) || prefix.starts_with(history_prefix) {
+                return Err(HistoryError::OverlappingPrefix);
+            }
+        }
+
+        Ok(Self {
+            inner,
+            history_prefix: history_prefix.to_vec(),
+            config,
+            round,
+        })
+    }
+
+    /// The wrapped tree.
+    ///
+    /// Writes to the wrapped tree are not recorded in the history.
+    pub fn inner(&mut self) -> &mut M {
+        &mut self.inner
+    }
+
+    /// Unwrap the tree.
+    pub fn into_inner(self) -> M {
+        self.inner
+    }
+
+    /// Whether versions of the given key are retained.
+    pub fn is_tracked(&self, key: &[u8]) -> bool {
+        self.config.depth > 0
+            && self
+                .config
+                .prefixes
+                .iter()
+                .any(|prefix| key.starts_with(prefix))
+    }
+
+    /// Fetch entry with given key.
+    pub fn get(&self, key: &[u8]) -> Option<Vec<u8>> {
+        self.inner.get(key)
+    }
+
+    /// Update entry with given key.
+    ///
+    /// Fails in case the key uses the reserved history prefix.
+    pub fn insert(&mut self, key: &[u8], value: &[u8]) -> Result<Option<Vec<u8>>, HistoryError> {
+        self.ensure_not_reserved(key)?;
+        self.record(key, Some(value))?;
+        Ok(self.inner.insert(key, value))
+    }
+
+    /// Remove entry with given key, returning the value at the key if the key was previously
+    /// in the database.
+    ///
+    /// Fails in case the key uses the reserved history prefix.
+    pub fn remove(&mut self, key: &[u8]) -> Result<Option<Vec<u8>>, HistoryError> {
+        self.ensure_not_reserved(key)?;
+        if self.is_tracked(key) && self.inner.get(key).is_some() {
+            self.record(key, None)?;
+        }
+        Ok(self.inner.remove(key))
+    }
+
+    /// Retained versions of the given key, oldest first, as the round of the write and the
+    /// written value, with removals having no value.
+    pub fn versions(&self, key: &[u8]) -> Result<Vec<(u64, Option<Vec<u8>>)>, HistoryError> {
+        let prefix = self.versions_prefix(key)?;
+        let mut it = self.inner.iter();
+        it.seek(&prefix);
+        let versions = it
+            .take_while(|(k, _)| k.starts_with(&prefix))
+            .filter_map(|(k, v)| {
+                let round = u64::from_be_bytes(k[prefix.len()..].try_into().ok()?);
+                let value = match v.split_first()? {
+                    (&0, _) => None,
+                    (_, value) => Some(value.to_vec()),
+                };
+                Some((round, value))
+            })
+            .collect();
+        Ok(versions)
+    }
+
+    /// Fetch entry with given key as of the end of the given round.
+    pub fn get_as_of(&self, key: &[u8], round: u64) -> Result<Option<Vec<u8>>, HistoryError> {
+        if !self.is_tracked(key) {
+            return Err(HistoryError::NotTracked);
+        }
+        if round >= self.round {
+            return Ok(self.inner.get(key));
+        }
+
+        let versions = self.versions(key)?;
+        match versions.iter().rev().find(|(written, _)| *written <= round) {
+            Some((_, value)) => Ok(value.clone()),
+            // Keys which haven't been written since history was enabled keep their value.
+            None if versions.is_empty() => Ok(self.inner.get(key)),
+            None => Err(HistoryError::NotRetained(round)),
+        }
+    }
+
+    /// Record a write of the given key in the current round, pruning versions beyond the
+    /// configured depth.
+    fn record(&mut self, key: &[u8], value: Option<&[u8]>) -> Result<(), HistoryError> {
+        if !self.is_tracked(key) {
+            return Ok(());
+        }
+
+        let encoded = match value {
+            Some(value) => [&[1u8][..], value].concat(),
+            None => vec![0],
+        };
+        let prefix = self.versions_prefix(key)?;
+        self.inner.insert(
+            &[&prefix[..], &self.round.to_be_bytes()[..]].concat(),
+            &encoded,
+        );
+
+        let versions = self.versions(key)?;
+        let excess = versions.len().saturating_sub(self.config.depth);
+        for (round, _) in &versions[..excess] {
+            self.inner
+                .remove(&[&prefix[..], &round.to_be_bytes()[..]].concat());
+        }
+        Ok(())
+    }
+
+    /// Prefix of the recorded versions of the given key.
+    ///
+    /// Keys are length-prefixed, so that versions of keys which are prefixes of each other don't
+    /// overlap.
+    fn versions_prefix(&self, key: &[u8]) -> Result<Vec<u8>, HistoryError> {
+        let len = u32::try_from(key.len()).map_err(|_| HistoryError::KeyTooLong)?;
+        Ok([&self.history_prefix[..], &len.to_be_bytes()[..], key].concat())
+    }
+
+    fn ensure_not_reserved(&self, key: &[u8]) -> Result<(), HistoryError> {
+        if key.starts_with(&self.history_prefix) {
+            return Err(HistoryError::ReservedKey);
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::storage::mkvs::{sync::NoopReadSyncer, OverlayTree, RootType, Tree};
+
+    const HISTORY: &[u8] = b"\xffhistory/";
+
+    #[test]
+    fn test_history() {
+        let tree = Tree::builder()
+            .with_root_type(RootType::State)
+            .build(Box::new(NoopReadSyncer));
+        let config = HistoryConfig {
+            prefixes: vec![b"balance/".to_vec()],
+            depth: 3,
+        };
+        let mut tree = OverlayTree::new(tree);
+        tree.insert(b"balance/a", b"0");
+
+        for round in 1..=5u64 {
+            let mut history = HistoryTree::new(tree, HISTORY, config.clone(), round).unwrap();
+            history
+                .insert(b"balance/a", round.to_string().as_bytes())
+                .unwrap();
+            history.insert(b"balance/ab", b"other").unwrap();
+            history
+                .insert(b"other", round.to_string().as_bytes())
+                .unwrap();
+            if round == 4 {
+                history.remove(b"balance/a").unwrap();
+            }
+            tree = history.into_inner();
+        }
+
+        let mut history = HistoryTree::new(tree, HISTORY, config.clone(), 6).unwrap();
+        assert_eq!(
+            history.versions(b"balance/a").unwrap(),
+            vec![
+                (3, Some(b"3".to_vec())),
+                (4, None),
+                (5, Some(b"5".to_vec()))
+            ]
+        );
+        assert_eq!(history.versions(b"balance/ab").unwrap().len(), 3);
+        assert_eq!(history.versions(b"other").unwrap(), vec![]);
+
+        assert_eq!(history.get_as_of(b"balance/a", 6), Ok(Some(b"5".to_vec())));
+        assert_eq!(history.get_as_of(b"balance/a", 4), Ok(None));
+        assert_eq!(history.get_as_of(b"balance/a", 3), Ok(Some(b"3".to_vec())));
+        assert_eq!(
+            history.get_as_of(b"balance/a", 2),
+            Err(HistoryError::NotRetained(2))
+        );
+        assert_eq!(
+            history.get_as_of(b"other", 2),
+            Err(HistoryError::NotTracked)
+        );
+        // Keys without recorded versions keep their current value.
+        assert_eq!(history.get_as_of(b"balance/b", 2), Ok(None));
+
+        // Writes to the reserved prefix and overlapping prefixes are rejected.
+        assert_eq!(
+            history.insert(b"\xffhistory/x", b"forged"),
+            Err(HistoryError::ReservedKey)
+        );
+        assert_eq!(
+            history.remove(b"\xffhistory/x"),
+            Err(HistoryError::ReservedKey)
+        );
+        let overlapping = HistoryConfig {
+            prefixes: vec![b"\xff".to_vec()],
+            ..config
+        };
+        assert!(matches!(
+            HistoryTree::new(history.into_inner(), HISTORY, overlapping, 6),
+            Err(HistoryError::OverlappingPrefix)
+        ));
+    }
+}
@@ -0,0 +1,94 @@
+//! Integrity self-checks of cached nodes.
+//!
+//! Nodes are verified against their hashes when they are fetched from the host, but are then
+//! kept in the in-memory cache of the tree for as long as they are used. In long-running
+//! enclaves, silent memory corruption (e.g. bit flips) of cached nodes would go unnoticed and
+//! could make the runtime compute wrong state roots. Cached nodes can therefore be periodically
+//! re-hashed, a bounded random sample at a time, with nodes which no longer match their hash
+//! being reported and dropped from the cache, so that they are fetched again when next needed.
+use crate::{
+    common::crypto::hash::Hash,
+    storage::mkvs::{Root, Tree},
+};
+
+/// Report of an integrity self-check of cached nodes.
+#[derive(Clone, Debug, Default, PartialEq, Eq, cbor::Encode, cbor::Decode)]
+pub struct IntegrityReport {
+    /// Root the checked tree was synced to.
+    pub root: Root,
+    /// Number of re-hashed nodes.
+    pub checked: u64,
+    /// Hashes of the nodes whose contents no longer match their hash.
+    #[cbor(optional)]
+    pub corrupted: Vec<Hash>,
+}
+
+impl IntegrityReport {
+    /// Whether any corrupted nodes have been found.
+    pub fn is_corrupted(&self) -> bool {
+        !self.corrupted.is_empty()
+    }
+}
+
+impl Tree {
+    /// Re-hash a random sample of at most the given number of cached nodes.
+    ///
+    /// Corrupted nodes are dropped from the cache.
+    pub fn verify_cache_sample(&self, count: usize) -> IntegrityReport {
+        self.cache.borrow_mut().verify_sample(count)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::storage::mkvs::{cache::Cache, sync::NoopReadSyncer, NodeBox, RootType};
+
+    /// Corrupt the value of the leftmost cached leaf, returning its hash.
+    fn corrupt_leaf(tree: &Tree) -> Hash {
+        let mut ptr = tree.cache.borrow().get_pending_root();
+        loop {
+            let node = ptr.borrow().get_node();
+            let next = match *node.borrow_mut() {
+                NodeBox::Leaf(ref mut leaf) => {
+                    leaf.value = b"corrupted".to_vec();
+                    return ptr.borrow().hash;
+                }
+                NodeBox::Internal(ref n) => [&n.leaf_node, &n.left, &n.right]
+                    .into_iter()
+                    .find(|child| child.borrow().has_node())
+                    .cloned()
+                    .expect("internal nodes must have children"),
+            };
+            ptr = next;
+        }
+    }
+
+    #[test]
+    fn test_verify_cache_sample() {
+        let mut tree = Tree::builder()
+            .with_root_type(RootType::State)
+            .build(Box::new(NoopReadSyncer));
+        for i in 0..50u32 {
+            tree.insert(&i.to_be_bytes(), b"value").unwrap();
+        }
+        tree.commit(Default::default(), 1).unwrap();
+
+        let report = tree.verify_cache_sample(10);
+        assert_eq!(report.checked, 10);
+        assert!(!report.is_corrupted());
+
+        // All nodes are checked in case the sample is large enough.
+        let report = tree.verify_cache_sample(usize::MAX);
+        let total = report.checked;
+        assert!(total > 50);
+
+        // Corrupted nodes are reported and dropped.
+        let corrupted = corrupt_leaf(&tree);
+        let report = tree.verify_cache_sample(usize::MAX);
+        assert_eq!(report.corrupted, vec![corrupted]);
+        let report = tree.verify_cache_sample(usize::MAX);
+        assert!(!report.is_corrupted());
+        assert_eq!(report.checked, total - 1);
+    }
+}
@@ -0,0 +1,358 @@
+//! Resource metering of storage reads.
+//!
+//! Queries are served by a limited pool of workers, so a single query reading large parts of the
+//! state could keep a worker busy for a long time. A [`MeteredTree`] accounts all reads made
+//! through it against the configured [`QueryLimits`]. Each read is charged before it is made, so
+//! once any limit is exceeded, all further reads return nothing without reaching storage, so that
+//! the query quickly runs to completion, and its result must be discarded in favor of the error
+//! returned by [`ReadMeter::check`]. As the size of a value is only known once it has been read,
+//! the read exceeding the byte limit is still made.
+//!
+//! The time limit is only checked on storage reads and after the query completes, so it doesn't
+//! preempt queries that are busy without reading from storage.
+use std::{cell::Cell, time::Instant};
+
+use anyhow::{Error, Result};
+
+use crate::{
+    common::{crypto::hash::Hash, namespace::Namespace},
+    config::{QueryLimitError, QueryLimits},
+    storage::mkvs::{self, tree::Key, Prefix, Proof, WriteLog, MKVS},
+};
+
+/// Meter of the storage reads made by a single query.
+pub struct ReadMeter {
+    limits: QueryLimits,
+    start: Instant,
+    reads: Cell<u64>,
+    read_bytes: Cell<u64>,
+    exceeded: Cell<Option<QueryLimitError>>,
+}
+
+impl ReadMeter {
+    /// Create a new meter enforcing the given limits, starting the clock.
+    pub fn new(limits: QueryLimits) -> Self {
+        Self {
+            limits,
+            start: Instant::now(),
+            reads: Cell::new(0),
+            read_bytes: Cell::new(0),
+            exceeded: Cell::new(None),
+        }
+    }
+
+    /// Number of storage reads accounted so far.
+    pub fn reads(&self) -> u64 {
+        self.reads.get()
+    }
+
+    /// Number of bytes read from storage so far.
+    pub fn read_bytes(&self) -> u64 {
+        self.read_bytes.get()
+    }
+
+    /// Ensure that no limit has been exceeded and that a response of the given size is allowed.
+    pub fn check(&self, response_size: usize) -> Result<(), QueryLimitError> {
+        if let Some(err) = self.exceeded.get() {
+            return Err(err);
+        }
+        if self.is_late() {
+            return Err(QueryLimitError::Time);
+        }
+        if self.limits.max_response_size > 0 && response_size > self.limits.max_response_size {
+            return Err(QueryLimitError::ResponseSize);
+        }
+        Ok(())
+    }
+
+    /// Account a read of the given number of bytes before it is made, returning whether the
+    /// read is allowed.
+    fn charge_read(&self, bytes: usize) -> bool {
+        self.charge(1, bytes)
+    }
+
+    /// Account the given number of bytes returned by an allowed read, returning whether they
+    /// may be used.
+    fn charge_bytes(&self, bytes: usize) -> bool {
+        self.charge(0, bytes)
+    }
+
+    fn charge(&self, reads: u64, bytes: usize) -> bool {
+        if self.exceeded.get().is_some() {
+            return false;
+        }
+
+        let reads = self.reads.get() + reads;
+        let read_bytes = self.read_bytes.get().saturating_add(bytes as u64);
+        self.reads.set(reads);
+        self.read_bytes.set(read_bytes);
+
+        let exceeded = if self.limits.max_storage_reads > 0 && reads > self.limits.max_storage_reads
+        {
+            Some(QueryLimitError::StorageReads)
+        } else if self.limits.max_storage_read_bytes > 0
+            && read_bytes > self.limits.max_storage_read_bytes
+        {
+            Some(QueryLimitError::StorageReadBytes)
+        } else if self.is_late() {
+            Some(QueryLimitError::Time)
+        } else {
+            None
+        };
+        self.exceeded.set(exceeded);
+
+        exceeded.is_none()
+    }
+
+    fn is_late(&self) -> bool {
+        self.limits
+            .max_time
+            .is_some_and(|max_time| self.start.elapsed() > max_time)
+    }
+}
+
+/// Tree wrapper accounting all reads against a read meter.
+pub struct MeteredTree<'a, M: MKVS> {
+    inner: M,
+    meter: &'a ReadMeter,
+}
+
+impl<'a, M: MKVS> MeteredTree<'a, M> {
+    /// Wrap the given tree, accounting reads against the given meter.
+    pub fn new(inner: M, meter: &'a ReadMeter) -> Self {
+        Self { inner, meter }
+    }
+}
+
+impl<M: MKVS> MKVS for MeteredTree<'_, M> {
+    fn get(&self, key: &[u8]) -> Option<Vec<u8>> {
+        if !self.meter.charge_read(key.len()) {
+            return None;
+        }
+        let value = self.inner.get(key);
+        let size = value.as_ref().map_or(0, Vec::len);
+        self.meter.charge_bytes(size).then_some(value)?
+    }
+
+    fn get_proof(&self, key: &[u8]) -> Option<Proof> {
+        if !self.meter.charge_read(key.len()) {
+            return None;
+        }
+        self.inner.get_proof(key)
+    }
+
+    fn cache_contains_key(&self, key: &[u8]) -> bool {
+        self.inner.cache_contains_key(key)
+    }
+
+    // Updates read the previous value, so they are skipped once a limit is exceeded. This is
+    // fine as the results of such queries are discarded.
+    fn insert(&mut self, key: &[u8], value: &[u8]) -> Option<Vec<u8>> {
+        if !self.meter.charge_read(key.len()) {
+            return None;
+        }
+        let previous = self.inner.insert(key, value)?;
+        self.meter.charge_bytes(previous.len()).then_some(previous)
+    }
+
+    fn remove(&mut self, key: &[u8]) -> Option<Vec<u8>> {
+        if !self.meter.charge_read(key.len()) {
+            return None;
+        }
+        let previous = self.inner.remove(key)?;
+        self.meter.charge_bytes(previous.len()).then_some(previous)
+    }
+
+    fn prefetch_prefixes(&self, prefixes: &[Prefix], limit: u16) {
+        if self.meter.charge_read(0) {
+            self.inner.prefetch_prefixes(prefixes, limit)
+        }
+    }
+
+    fn prefetch_prefix(&self, prefix: &Prefix, max_size: u64) {
+        if self.meter.charge_read(0) {
+            self.inner.prefetch_prefix(prefix, max_size)
+        }
+    }
+
+    fn iter(&self) -> Box<dyn mkvs::Iterator + '_> {
+        Box::new(MeteredIterator {
+            inner: self.inner.iter(),
+            meter: self.meter,
+            exceeded: false,
+        })
+    }
+
+    fn commit(&mut self, namespace: Namespace, version: u64) -> Result<(WriteLog, Hash)> {
+        self.inner.commit(namespace, version)
+    }
+}
+
+/// An iterator accounting each visited entry against a read meter.
+struct MeteredIterator<'a> {
+    inner: Box<dyn mkvs::Iterator + 'a>,
+    meter: &'a ReadMeter,
+    exceeded: bool,
+}
+
+impl MeteredIterator<'_> {
+    /// Account a move of the iterator before it is made, returning whether it is allowed.
+    fn charge_move(&mut self) -> bool {
+        self.exceeded = self.exceeded || !self.meter.charge_read(0);
+        !self.exceeded
+    }
+
+    /// Account the entry the iterator moved to.
+    fn charge_entry(&mut self) {
+        if self.exceeded || !self.inner.is_valid() {
+            return;
+        }
+        let size = self.inner.get_key().as_ref().map_or(0, Vec::len)
+            + self.inner.get_value().as_ref().map_or(0, Vec::len);
+        self.exceeded = !self.meter.charge_bytes(size);
+    }
+}
+
+impl Iterator for MeteredIterator<'_> {
+    type Item = (Vec<u8>, Vec<u8>);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        use mkvs::Iterator;
+
+        if !self.is_valid() {
+            return None;
+        }
+
+        let key = self.get_key().clone().expect("iterator is valid");
+        let value = self.get_value().clone().expect("iterator is valid");
+        mkvs::Iterator::next(self);
+
+        Some((key, value))
+    }
+}
+
+impl mkvs::Iterator for MeteredIterator<'_> {
+    fn set_prefetch(&mut self, prefetch: usize) {
+        self.inner.set_prefetch(prefetch)
+    }
+
+    fn is_valid(&self) -> bool {
+        !self.exceeded && self.inner.is_valid()
+    }
+
+    fn error(&self) -> &Option<Error> {
+        self.inner.error()
+    }
+
+    fn rewind(&mut self) {
+        if self.charge_move() {
+            self.inner.rewind();
+            self.charge_entry();
+        }
+    }
+
+    fn seek(&mut self, key: &[u8]) {
+        if self.charge_move() {
+            self.inner.seek(key);
+            self.charge_entry();
+        }
+    }
+
+    fn get_key(&self) -> &Option<Key> {
+        if self.exceeded {
+            return &None;
+        }
+        self.inner.get_key()
+    }
+
+    fn get_value(&self) -> &Option<Vec<u8>> {
+        if self.exceeded {
+            return &None;
+        }
+        self.inner.get_value()
+    }
+
+    fn next(&mut self) {
+        if self.charge_move() {
+            self.inner.next();
+            self.charge_entry();
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::Duration;
+
+    use super::*;
+    use crate::storage::mkvs::{sync::NoopReadSyncer, OverlayTree, RootType, Tree};
+
+    fn count(tree: &dyn MKVS) -> usize {
+        let mut it = tree.iter();
+        it.rewind();
+        it.count()
+    }
+
+    fn new_tree() -> OverlayTree<Tree> {
+        let tree = Tree::builder()
+            .with_root_type(RootType::State)
+            .build(Box::new(NoopReadSyncer));
+        let mut tree = OverlayTree::new(tree);
+        for i in 0..10u8 {
+            tree.insert(&[i], &[i; 10]).unwrap();
+        }
+        tree
+    }
+
+    #[test]
+    fn test_metered_tree() {
+        let mut inner = new_tree();
+        let meter = ReadMeter::new(QueryLimits {
+            max_storage_reads: 5,
+            ..Default::default()
+        });
+        let tree = MeteredTree::new(&mut inner, &meter);
+
+        assert_eq!(tree.get(&[0]), Some(vec![0; 10]));
+        assert_eq!(tree.get(&[42]), None);
+        assert_eq!(count(&tree), 3);
+        // The read exceeding the limit is not made.
+        assert_eq!(meter.reads(), 6);
+        assert_eq!(meter.read_bytes(), 11 + 1 + 3 * 11);
+
+        // Reads past the limit are hidden.
+        assert_eq!(tree.get(&[0]), None);
+        assert_eq!(count(&tree), 0);
+        assert_eq!(meter.check(0), Err(QueryLimitError::StorageReads));
+    }
+
+    #[test]
+    fn test_read_meter_limits() {
+        let mut inner = new_tree();
+        let meter = ReadMeter::new(QueryLimits {
+            max_storage_read_bytes: 25,
+            max_response_size: 100,
+            ..Default::default()
+        });
+        let tree = MeteredTree::new(&mut inner, &meter);
+        assert!(tree.get(&[1]).is_some());
+        assert_eq!(meter.check(100), Ok(()));
+        assert_eq!(meter.check(101), Err(QueryLimitError::ResponseSize));
+        assert!(tree.get(&[2]).is_some());
+        assert!(tree.get(&[3]).is_none());
+        assert_eq!(meter.check(0), Err(QueryLimitError::StorageReadBytes));
+
+        let meter = ReadMeter::new(QueryLimits {
+            max_time: Some(Duration::ZERO),
+            ..Default::default()
+        });
+        std::thread::sleep(Duration::from_millis(1));
+        assert_eq!(meter.check(0), Err(QueryLimitError::Time));
+
+        // No limits by default.
+        let meter = ReadMeter::new(QueryLimits::default());
+        let tree = MeteredTree::new(&mut inner, &meter);
+        assert_eq!(count(&tree), 10);
+        assert_eq!(meter.check(usize::MAX), Ok(()));
+    }
+}
@@ -13,14 +13,31 @@ use self::sync::Proof;
 #[macro_use]
 mod tree;
 mod cache;
+pub mod checkpoint;
+pub mod commit;
+pub mod compression;
+pub mod dirty;
+pub mod encrypted;
+pub mod export;
+pub mod hashed;
+pub mod history;
+pub mod integrity;
 #[cfg(test)]
 pub mod interop;
 pub mod marshal;
+pub mod metered;
+pub mod prefix_stats;
+pub mod recorded;
+pub mod rent;
 pub mod sync;
 #[cfg(test)]
 mod tests;
+pub mod tombstone;
 
-pub use tree::{Depth, Key, NodeBox, NodePointer, NodePtrRef, OverlayTree, Root, RootType, Tree};
+pub use cache::{CacheConfig, CacheMetrics, EvictionPolicy};
+pub use tree::{
+    profile, Depth, Key, NodeBox, NodePointer, NodePtrRef, OverlayTree, Root, RootType, Tree,
+};
 
 /// The type of entry in the log.
 #[derive(Copy, Clone, Debug, Eq, PartialEq)]
@@ -129,6 +146,10 @@ pub trait MKVS {
     /// Populate the in-memory tree with nodes for keys starting with given prefixes.
     fn prefetch_prefixes(&self, prefixes: &[Prefix], limit: u16);
 
+    /// Populate the in-memory tree with the entire subtree of keys starting with given prefix,
+    /// fetching at most `max_size` bytes in a single request.
+    fn prefetch_prefix(&self, prefix: &Prefix, max_size: u64);
+
     /// Returns an iterator over the tree.
     fn iter(&self) -> Box<dyn Iterator + '_>;
 
@@ -168,6 +189,10 @@ pub trait FallibleMKVS {
     /// Populate the in-memory tree with nodes for keys starting with given prefixes.
     fn prefetch_prefixes(&self, prefixes: &[Prefix], limit: u16) -> Result<()>;
 
+    /// Populate the in-memory tree with the entire subtree of keys starting with given prefix,
+    /// fetching at most `max_size` bytes in a single request.
+    fn prefetch_prefix(&self, prefix: &Prefix, max_size: u64) -> Result<()>;
+
     /// Returns an iterator over the tree.
     fn iter(&self) -> Box<dyn Iterator + '_>;
 
@@ -186,6 +211,10 @@ pub trait ImmutableMKVS {
     /// Populate the in-memory tree with nodes for keys starting with given prefixes.
     fn prefetch_prefixes(&self, prefixes: &[Prefix], limit: u16) -> Result<()>;
 
+    /// Populate the in-memory tree with the entire subtree of keys starting with given prefix,
+    /// fetching at most `max_size` bytes in a single request.
+    fn prefetch_prefix(&self, prefix: &Prefix, max_size: u64) -> Result<()>;
+
     /// Returns an iterator over the tree.
     fn iter(&self) -> Box<dyn Iterator + '_>;
 }
@@ -206,6 +235,10 @@ where
         T::prefetch_prefixes(self, prefixes, limit)
     }
 
+    fn prefetch_prefix(&self, prefix: &Prefix, max_size: u64) -> Result<()> {
+        T::prefetch_prefix(self, prefix, max_size)
+    }
+
     fn iter(&self) -> Box<dyn Iterator + '_> {
         T::iter(self)
     }
@@ -263,6 +296,10 @@ impl<T: MKVS + ?Sized> MKVS for &mut T {
         T::prefetch_prefixes(self, prefixes, limit)
     }
 
+    fn prefetch_prefix(&self, prefix: &Prefix, max_size: u64) {
+        T::prefetch_prefix(self, prefix, max_size)
+    }
+
     fn iter(&self) -> Box<dyn Iterator + '_> {
         T::iter(self)
     }
@@ -297,6 +334,10 @@ impl<T: FallibleMKVS + ?Sized> FallibleMKVS for &mut T {
         T::prefetch_prefixes(self, prefixes, limit)
     }
 
+    fn prefetch_prefix(&self, prefix: &Prefix, max_size: u64) -> Result<()> {
+        T::prefetch_prefix(self, prefix, max_size)
+    }
+
     fn iter(&self) -> Box<dyn Iterator + '_> {
         T::iter(self)
     }
@@ -0,0 +1,233 @@
+//! Incrementally maintained per-prefix statistics.
+//!
+//! Runtimes that want to expose state size metrics or charge fees based on state growth need to
+//! know how many keys and value bytes are stored under a key prefix, which would otherwise
+//! require scanning the whole prefix. A [`PrefixStatsTree`] tracks the changes made through it
+//! under each configured prefix and folds them into statistics stored in the tree under a reserved
+//! key prefix on commit, so that reading them costs a single lookup.
+//!
+//! Only changes made through the wrapper are accounted, so the statistics are approximate in case
+//! the tree is also modified directly or the prefixes were not tracked from the start.
+use std::collections::BTreeMap;
+
+use anyhow::Result;
+
+use crate::{
+    common::{crypto::hash::Hash, namespace::Namespace},
+    storage::mkvs::{self, Prefix, Proof, WriteLog, MKVS},
+};
+
+/// Statistics of the entries stored under a key prefix.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, cbor::Encode, cbor::Decode)]
+pub struct PrefixStats {
+    /// Number of keys.
+    pub keys: u64,
+    /// Total size of the values in bytes.
+    pub value_bytes: u64,
+}
+
+impl PrefixStats {
+    fn apply(self, delta: &Delta) -> Self {
+        Self {
+            keys: self.keys.saturating_add_signed(delta.keys),
+            value_bytes: self.value_bytes.saturating_add_signed(delta.value_bytes),
+        }
+    }
+}
+
+/// Uncommitted change of the statistics of a prefix.
+#[derive(Clone, Copy, Debug, Default)]
+struct Delta {
+    keys: i64,
+    value_bytes: i64,
+}
+
+/// Read the committed statistics of the given prefix, stored under the given reserved prefix.
+pub fn prefix_stats<M: MKVS + ?Sized>(tree: &M, stats_prefix: &[u8], prefix: &[u8]) -> PrefixStats {
+    tree.get(&[stats_prefix, prefix].concat())
+        .map(|raw| cbor::from_slice(&raw).expect("prefix statistics should be well-formed"))
+        .unwrap_or_default()
+}
+
+/// Tree wrapper maintaining statistics of the entries under the configured prefixes.
+pub struct PrefixStatsTree<M: MKVS> {
+    inner: M,
+    stats_prefix: Vec<u8>,
+    prefixes: Vec<Vec<u8>>,
+    pending: BTreeMap<Vec<u8>, Delta>,
+}
+
+impl<M: MKVS> PrefixStatsTree<M> {
+    /// Wrap the given tree, tracking the given prefixes and storing their statistics under the
+    /// given reserved key prefix.
+    ///
+    /// Prefixes may be nested, in which case changes are accounted to all matching prefixes.
+    ///
+    /// # Panics
+    ///
+    /// Panics if any tracked prefix overlaps the reserved prefix.
+    pub fn new(inner: M, stats_prefix: &[u8], prefixes: Vec<Vec<u8>>) -> Self {
+        for prefix in &prefixes {
+            assert!(
+                !stats_prefix.starts_with(prefix) && !prefix.starts_with(stats_prefix),
+                "prefix overlaps the reserved statistics prefix"
+            );
+        }
+
+        Self {
+            inner,
+            stats_prefix: stats_prefix.to_vec(),
+            prefixes,
+            pending: BTreeMap::new(),
+        }
+    }
+
+    /// Statistics of the given tracked prefix, including uncommitted changes.
+    pub fn stats(&self, prefix: &[u8]) -> PrefixStats {
+        let stats = prefix_stats(&self.inner, &self.stats_prefix, prefix);
+        match self.pending.get(prefix) {
+            Some(delta) => stats.apply(delta),
+            None => stats,
+        }
+    }
+
+    /// Unwrap the inner tree, discarding any uncommitted statistics.
+    pub fn into_inner(self) -> M {
+        self.inner
+    }
+
+    fn account(&mut self, key: &[u8], keys: i64, value_bytes: i64) {
+        if keys == 0 && value_bytes == 0 {
+            return;
+        }
+        for prefix in self
+            .prefixes
+            .iter()
+            .filter(|prefix| key.starts_with(prefix))
+        {
+            let delta = self.pending.entry(prefix.clone()).or_default();
+            delta.keys += keys;
+            delta.value_bytes += value_bytes;
+        }
+    }
+}
+
+impl<M: MKVS> MKVS for PrefixStatsTree<M> {
+    fn get(&self, key: &[u8]) -> Option<Vec<u8>> {
+        self.inner.get(key)
+    }
+
+    fn get_proof(&self, key: &[u8]) -> Option<Proof> {
+        self.inner.get_proof(key)
+    }
+
+    fn cache_contains_key(&self, key: &[u8]) -> bool {
+        self.inner.cache_contains_key(key)
+    }
+
+    fn insert(&mut self, key: &[u8], value: &[u8]) -> Option<Vec<u8>> {
+        let previous = self.inner.insert(key, value);
+        let previous_len = previous.as_ref().map_or(0, Vec::len) as i64;
+        self.account(
+            key,
+            previous.is_none().into(),
+            value.len() as i64 - previous_len,
+        );
+        previous
+    }
+
+    fn remove(&mut self, key: &[u8]) -> Option<Vec<u8>> {
+        let previous = self.inner.remove(key);
+        if let Some(ref previous) = previous {
+            self.account(key, -1, -(previous.len() as i64));
+        }
+        previous
+    }
+
+    fn prefetch_prefixes(&self, prefixes: &[Prefix], limit: u16) {
+        self.inner.prefetch_prefixes(prefixes, limit)
+    }
+
+    fn prefetch_prefix(&self, prefix: &Prefix, max_size: u64) {
+        self.inner.prefetch_prefix(prefix, max_size)
+    }
+
+    fn iter(&self) -> Box<dyn mkvs::Iterator + '_> {
+        self.inner.iter()
+    }
+
+    fn commit(&mut self, namespace: Namespace, version: u64) -> Result<(WriteLog, Hash)> {
+        for (prefix, delta) in std::mem::take(&mut self.pending) {
+            let stats = prefix_stats(&self.inner, &self.stats_prefix, &prefix).apply(&delta);
+            self.inner.insert(
+                &[self.stats_prefix.as_slice(), &prefix].concat(),
+                &cbor::to_vec(stats),
+            );
+        }
+        self.inner.commit(namespace, version)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::storage::mkvs::{sync::NoopReadSyncer, RootType, Tree};
+
+    #[test]
+    fn test_prefix_stats() {
+        let tree = Tree::builder()
+            .with_root_type(RootType::State)
+            .build(Box::new(NoopReadSyncer));
+        let mut tree = PrefixStatsTree::new(
+            tree,
+            b"\xffstats",
+            vec![b"a".to_vec(), b"a/b".to_vec(), b"c".to_vec()],
+        );
+
+        tree.insert(b"a/1", b"one");
+        tree.insert(b"a/b/1", b"one");
+        tree.insert(b"a/b/2", b"two");
+        tree.insert(b"d", b"untracked");
+        assert_eq!(
+            tree.stats(b"a"),
+            PrefixStats {
+                keys: 3,
+                value_bytes: 9
+            }
+        );
+        MKVS::commit(&mut tree, Default::default(), 1).unwrap();
+
+        // Statistics are committed to the tree.
+        assert_eq!(
+            prefix_stats(&tree.inner, b"\xffstats", b"a/b"),
+            PrefixStats {
+                keys: 2,
+                value_bytes: 6
+            }
+        );
+        assert_eq!(
+            prefix_stats(&tree.inner, b"\xffstats", b"c"),
+            Default::default()
+        );
+
+        // Updates and removals are accounted.
+        tree.insert(b"a/b/1", b"longer value");
+        tree.remove(b"a/b/2");
+        tree.remove(b"a/b/3");
+        MKVS::commit(&mut tree, Default::default(), 2).unwrap();
+        assert_eq!(
+            tree.stats(b"a/b"),
+            PrefixStats {
+                keys: 1,
+                value_bytes: 12
+            }
+        );
+        assert_eq!(
+            tree.stats(b"a"),
+            PrefixStats {
+                keys: 2,
+                value_bytes: 15
+            }
+        );
+    }
+}
@@ -0,0 +1,254 @@
+//! Recording of storage reads.
+//!
+//! A [`RecordedTree`] records the keys of all reads made through it in a [`ReadSet`], so that a
+//! proof covering all of them can be produced once the reads are done. This allows clients to
+//! verify query results against the state root instead of trusting the node serving the query.
+//!
+//! Proofs only cover the lookup paths of individual keys, so they can't attest that iteration
+//! didn't skip any keys. Read sets of trees which were iterated over are marked as partial.
+use std::{
+    cell::{Cell, RefCell},
+    collections::BTreeSet,
+};
+
+use anyhow::{Error, Result};
+
+use crate::{
+    common::{crypto::hash::Hash, namespace::Namespace},
+    storage::mkvs::{self, tree::Key, Prefix, Proof, Tree, WriteLog, MKVS},
+};
+
+/// Set of keys read from storage.
+#[derive(Default)]
+pub struct ReadSet {
+    keys: RefCell<BTreeSet<Vec<u8>>>,
+    partial: Cell<bool>,
+}
+
+impl ReadSet {
+    /// Create a new empty read set.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Keys read so far, in key order.
+    pub fn keys(&self) -> Vec<Vec<u8>> {
+        self.keys.borrow().iter().cloned().collect()
+    }
+
+    /// Whether the tree was iterated over, so that the read keys don't fully describe the reads.
+    pub fn is_partial(&self) -> bool {
+        self.partial.get()
+    }
+
+    /// Build a proof for all keys read so far from the given tree.
+    ///
+    /// The proof also covers keys which were not found.
+    pub fn proof(&self, tree: &Tree) -> Result<Proof> {
+        tree.get_multi_proof(&self.keys())
+    }
+
+    fn record(&self, key: &[u8]) {
+        self.keys.borrow_mut().insert(key.to_vec());
+    }
+}
+
+/// Tree wrapper recording all reads in a read set.
+pub struct RecordedTree<'a, M: MKVS> {
+    inner: M,
+    reads: Option<&'a ReadSet>,
+}
+
+impl<'a, M: MKVS> RecordedTree<'a, M> {
+    /// Wrap the given tree, recording reads in the given read set, if any.
+    pub fn new(inner: M, reads: Option<&'a ReadSet>) -> Self {
+        Self { inner, reads }
+    }
+
+    fn record(&self, key: &[u8]) {
+        if let Some(reads) = self.reads {
+            reads.record(key);
+        }
+    }
+}
+
+impl<M: MKVS> MKVS for RecordedTree<'_, M> {
+    fn get(&self, key: &[u8]) -> Option<Vec<u8>> {
+        self.record(key);
+        self.inner.get(key)
+    }
+
+    fn get_proof(&self, key: &[u8]) -> Option<Proof> {
+        self.record(key);
+        self.inner.get_proof(key)
+    }
+
+    fn cache_contains_key(&self, key: &[u8]) -> bool {
+        self.inner.cache_contains_key(key)
+    }
+
+    fn insert(&mut self, key: &[u8], value: &[u8]) -> Option<Vec<u8>> {
+        self.record(key);
+        self.inner.insert(key, value)
+    }
+
+    fn remove(&mut self, key: &[u8]) -> Option<Vec<u8>> {
+        self.record(key);
+        self.inner.remove(key)
+    }
+
+    fn prefetch_prefixes(&self, prefixes: &[Prefix], limit: u16) {
+        self.inner.prefetch_prefixes(prefixes, limit)
+    }
+
+    fn prefetch_prefix(&self, prefix: &Prefix, max_size: u64) {
+        self.inner.prefetch_prefix(prefix, max_size)
+    }
+
+    fn iter(&self) -> Box<dyn mkvs::Iterator + '_> {
+        let reads = match self.reads {
+            Some(reads) => reads,
+            None => return self.inner.iter(),
+        };
+        reads.partial.set(true);
+
+        Box::new(RecordedIterator {
+            inner: self.inner.iter(),
+            reads,
+        })
+    }
+
+    fn commit(&mut self, namespace: Namespace, version: u64) -> Result<(WriteLog, Hash)> {
+        self.inner.commit(namespace, version)
+    }
+}
+
+/// An iterator recording each visited key in a read set.
+struct RecordedIterator<'a> {
+    inner: Box<dyn mkvs::Iterator + 'a>,
+    reads: &'a ReadSet,
+}
+
+impl RecordedIterator<'_> {
+    fn record(&self) {
+        if let Some(key) = self.inner.get_key() {
+            self.reads.record(key);
+        }
+    }
+}
+
+impl Iterator for RecordedIterator<'_> {
+    type Item = (Vec<u8>, Vec<u8>);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        use mkvs::Iterator;
+
+        if !self.is_valid() {
+            return None;
+        }
+
+        let key = self.get_key().clone().expect("iterator is valid");
+        let value = self.get_value().clone().expect("iterator is valid");
+        mkvs::Iterator::next(self);
+
+        Some((key, value))
+    }
+}
+
+impl mkvs::Iterator for RecordedIterator<'_> {
+    fn set_prefetch(&mut self, prefetch: usize) {
+        self.inner.set_prefetch(prefetch)
+    }
+
+    fn is_valid(&self) -> bool {
+        self.inner.is_valid()
+    }
+
+    fn error(&self) -> &Option<Error> {
+        self.inner.error()
+    }
+
+    fn rewind(&mut self) {
+        self.inner.rewind();
+        self.record();
+    }
+
+    fn seek(&mut self, key: &[u8]) {
+        self.inner.seek(key);
+        self.record();
+    }
+
+    fn get_key(&self) -> &Option<Key> {
+        self.inner.get_key()
+    }
+
+    fn get_value(&self) -> &Option<Vec<u8>> {
+        self.inner.get_value()
+    }
+
+    fn next(&mut self) {
+        self.inner.next();
+        self.record();
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::storage::mkvs::{
+        sync::{NoopReadSyncer, ProofReadSyncer},
+        OverlayTree, Root, RootType,
+    };
+
+    #[test]
+    fn test_recorded_tree() {
+        let mut tree = Tree::builder()
+            .with_root_type(RootType::State)
+            .build(Box::new(NoopReadSyncer));
+        for i in 0..10u8 {
+            tree.insert(&[i], &[i; 10]).unwrap();
+        }
+        let namespace = Namespace::default();
+        let hash = tree.commit(namespace, 1).unwrap();
+
+        // Reads are only recorded when a read set is given.
+        let reads = ReadSet::new();
+        {
+            let mut overlay = OverlayTree::new(&mut tree);
+            let recorded = RecordedTree::new(&mut overlay, Some(&reads));
+            assert_eq!(recorded.get(&[1]), Some(vec![1; 10]));
+            assert_eq!(recorded.get(&[42]), None);
+            let recorded = RecordedTree::new(&mut overlay, None);
+            assert_eq!(recorded.get(&[2]), Some(vec![2; 10]));
+        }
+        assert_eq!(reads.keys(), vec![vec![1], vec![42]]);
+        assert!(!reads.is_partial());
+
+        // The proof covers both present and missing keys, but no other keys.
+        let proof = reads.proof(&tree).unwrap();
+        let root = Root {
+            namespace,
+            version: 1,
+            root_type: RootType::State,
+            hash,
+        };
+        let verified = Tree::builder()
+            .with_root(root)
+            .build(Box::new(ProofReadSyncer::new(proof)));
+        assert_eq!(verified.get(&[1]).unwrap(), Some(vec![1; 10]));
+        assert_eq!(verified.get(&[42]).unwrap(), None);
+        assert!(verified.get(&[2]).is_err());
+
+        // Iteration marks the read set as partial.
+        let reads = ReadSet::new();
+        {
+            let mut overlay = OverlayTree::new(&mut tree);
+            let recorded = RecordedTree::new(&mut overlay, Some(&reads));
+            let mut it = recorded.iter();
+            it.seek(&[8]);
+            assert_eq!(it.count(), 2);
+        }
+        assert_eq!(reads.keys(), vec![vec![8], vec![9]]);
+        assert!(reads.is_partial());
+    }
+}
@@ -0,0 +1,237 @@
+//! State rent metadata and expiry.
+//!
+//! Runtimes that charge rent for the state they store track each rented key prefix together
+//! with the epoch of its last access and the epoch until which rent has been paid. At each epoch
+//! boundary the runtime calls [`StateRent::sweep`], which deterministically removes the prefixes
+//! whose rent has expired, in order of expiry, using lazy deletes so that the cost of removing
+//! the keys themselves is spread over subsequent rounds.
+//!
+//! Metadata is stored in the tree under a reserved key prefix, together with an index ordered
+//! by expiry so that sweeping never needs to scan all tracked prefixes.
+use crate::consensus::beacon::EpochTime;
+
+use super::{
+    tombstone::{LazyDeleteError, LazyDeleteTree},
+    MKVS,
+};
+
+/// Key component of metadata entries.
+const METADATA_KEY: u8 = 0x00;
+/// Key component of expiry index entries.
+const EXPIRY_INDEX_KEY: u8 = 0x01;
+
+/// Rent metadata of a key prefix.
+#[derive(Clone, Debug, Default, PartialEq, Eq, cbor::Encode, cbor::Decode)]
+pub struct RentMetadata {
+    /// Epoch in which the prefix was last accessed.
+    pub last_access: EpochTime,
+    /// Epoch until which (inclusive) rent for the prefix has been paid.
+    pub paid_until: EpochTime,
+}
+
+/// State rent bookkeeping, stored under a reserved key prefix.
+pub struct StateRent {
+    prefix: Vec<u8>,
+}
+
+impl StateRent {
+    /// Create bookkeeping storing metadata under the given reserved key prefix.
+    ///
+    /// The prefix must not overlap any rented prefixes nor the tree's tombstone prefix.
+    pub fn new(prefix: &[u8]) -> Self {
+        Self {
+            prefix: prefix.to_vec(),
+        }
+    }
+
+    /// Rent metadata of the given prefix, if it is tracked.
+    pub fn metadata<M: MKVS>(
+        &self,
+        tree: &LazyDeleteTree<M>,
+        prefix: &[u8],
+    ) -> Option<RentMetadata> {
+        tree.get(&self.metadata_key(prefix))
+            .map(|raw| cbor::from_slice(&raw).expect("rent metadata should be well-formed"))
+    }
+
+    /// Record that rent for the given prefix has been paid until the given epoch, starting to
+    /// track the prefix if needed.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the prefix overlaps the reserved metadata prefix.
+    pub fn pay<M: MKVS>(
+        &self,
+        tree: &mut LazyDeleteTree<M>,
+        prefix: &[u8],
+        epoch: EpochTime,
+        paid_until: EpochTime,
+    ) -> Result<(), LazyDeleteError> {
+        assert!(
+            !self.prefix.starts_with(prefix) && !prefix.starts_with(&self.prefix),
+            "prefix overlaps the reserved rent metadata prefix"
+        );
+
+        if let Some(meta) = self.metadata(tree, prefix) {
+            tree.remove(&self.expiry_index_key(meta.paid_until, prefix))?;
+        }
+        self.set_metadata(
+            tree,
+            prefix,
+            RentMetadata {
+                last_access: epoch,
+                paid_until,
+            },
+        )
+    }
+
+    /// Record an access to the given prefix in the given epoch.
+    ///
+    /// Returns `false` in case the prefix is not tracked.
+    pub fn touch<M: MKVS>(
+        &self,
+        tree: &mut LazyDeleteTree<M>,
+        prefix: &[u8],
+        epoch: EpochTime,
+    ) -> Result<bool, LazyDeleteError> {
+        match self.metadata(tree, prefix) {
+            Some(meta) if meta.last_access == epoch => Ok(true),
+            Some(meta) => {
+                tree.insert(
+                    &self.metadata_key(prefix),
+                    &cbor::to_vec(RentMetadata {
+                        last_access: epoch,
+                        ..meta
+                    }),
+                )?;
+                Ok(true)
+            }
+            None => Ok(false),
+        }
+    }
+
+    /// Stop tracking the given prefix without removing its keys.
+    pub fn untrack<M: MKVS>(
+        &self,
+        tree: &mut LazyDeleteTree<M>,
+        prefix: &[u8],
+    ) -> Result<(), LazyDeleteError> {
+        if let Some(meta) = self.metadata(tree, prefix) {
+            tree.remove(&self.expiry_index_key(meta.paid_until, prefix))?;
+            tree.remove(&self.metadata_key(prefix))?;
+        }
+        Ok(())
+    }
+
+    /// Lazily remove at most `limit` tracked prefixes whose rent expired before the given epoch,
+    /// in order of expiry.
+    ///
+    /// Returns the removed prefixes.
+    pub fn sweep<M: MKVS>(
+        &self,
+        tree: &mut LazyDeleteTree<M>,
+        epoch: EpochTime,
+        limit: usize,
+    ) -> Result<Vec<Vec<u8>>, LazyDeleteError> {
+        let index_prefix = [&self.prefix[..], &[EXPIRY_INDEX_KEY][..]].concat();
+        let expired: Vec<_> = {
+            let mut it = tree.inner().iter();
+            it.seek(&index_prefix);
+            it.map_while(|(key, _)| {
+                let entry = key.strip_prefix(&index_prefix[..])?;
+                let (paid_until, prefix) = entry.split_at(8);
+                let paid_until = EpochTime::from_be_bytes(paid_until.try_into().unwrap());
+                (paid_until < epoch).then(|| (key.clone(), prefix.to_vec()))
+            })
+            .take(limit)
+            .collect()
+        };
+
+        expired
+            .into_iter()
+            .map(|(index_key, prefix)| {
+                tree.remove(&index_key)?;
+                tree.remove(&self.metadata_key(&prefix))?;
+                tree.remove_prefix(&prefix)?;
+                Ok(prefix)
+            })
+            .collect()
+    }
+
+    fn set_metadata<M: MKVS>(
+        &self,
+        tree: &mut LazyDeleteTree<M>,
+        prefix: &[u8],
+        meta: RentMetadata,
+    ) -> Result<(), LazyDeleteError> {
+        tree.insert(&self.expiry_index_key(meta.paid_until, prefix), &[])?;
+        tree.insert(&self.metadata_key(prefix), &cbor::to_vec(meta))?;
+        Ok(())
+    }
+
+    fn metadata_key(&self, prefix: &[u8]) -> Vec<u8> {
+        [&self.prefix[..], &[METADATA_KEY][..], prefix].concat()
+    }
+
+    fn expiry_index_key(&self, paid_until: EpochTime, prefix: &[u8]) -> Vec<u8> {
+        [
+            &self.prefix[..],
+            &[EXPIRY_INDEX_KEY][..],
+            &paid_until.to_be_bytes()[..],
+            prefix,
+        ]
+        .concat()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::storage::mkvs::{sync::NoopReadSyncer, OverlayTree, RootType, Tree};
+
+    #[test]
+    fn test_state_rent() {
+        let tree = Tree::builder()
+            .with_root_type(RootType::State)
+            .build(Box::new(NoopReadSyncer));
+        let mut tree = LazyDeleteTree::new(OverlayTree::new(tree), b"\xfftombstone/", 100);
+        let rent = StateRent::new(b"\xffrent/");
+
+        for prefix in [b"a/", b"b/", b"c/"] {
+            tree.insert(&[&prefix[..], &b"key"[..]].concat(), b"value")
+                .unwrap();
+        }
+        rent.pay(&mut tree, b"a/", 1, 5).unwrap();
+        rent.pay(&mut tree, b"b/", 1, 3).unwrap();
+        rent.pay(&mut tree, b"c/", 1, 3).unwrap();
+        // Paying again replaces the expiry.
+        rent.pay(&mut tree, b"c/", 2, 10).unwrap();
+
+        assert!(rent.touch(&mut tree, b"a/", 4).unwrap());
+        assert!(!rent.touch(&mut tree, b"d/", 4).unwrap());
+        assert_eq!(
+            rent.metadata(&tree, b"a/"),
+            Some(RentMetadata {
+                last_access: 4,
+                paid_until: 5,
+            })
+        );
+
+        // Nothing expires while paid for.
+        assert!(rent.sweep(&mut tree, 3, 10).unwrap().is_empty());
+
+        assert_eq!(rent.sweep(&mut tree, 6, 1).unwrap(), vec![b"b/".to_vec()]);
+        assert_eq!(rent.sweep(&mut tree, 6, 10).unwrap(), vec![b"a/".to_vec()]);
+        assert!(rent.sweep(&mut tree, 6, 10).unwrap().is_empty());
+
+        assert_eq!(rent.metadata(&tree, b"a/"), None);
+        assert_eq!(tree.get(b"a/key"), None);
+        assert_eq!(tree.get(b"b/key"), None);
+        assert_eq!(tree.get(b"c/key"), Some(b"value".to_vec()));
+
+        // Untracked prefixes are never swept.
+        rent.untrack(&mut tree, b"c/").unwrap();
+        assert!(rent.sweep(&mut tree, 100, 10).unwrap().is_empty());
+        assert_eq!(tree.get(b"c/key"), Some(b"value".to_vec()));
+    }
+}
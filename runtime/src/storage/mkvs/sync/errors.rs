@@ -1,7 +1,54 @@
+use std::fmt;
+
 use thiserror::Error;
 
+use crate::{
+    common::crypto::{hash::Hash, signature::PublicKey},
+    storage::mkvs::sync::TreeID,
+    types::HostStorageEndpoint,
+};
+
 #[derive(Error, Debug)]
 pub enum SyncerError {
     #[error("mkvs: method not supported")]
     Unsupported,
+
+    #[error("mkvs: {0}")]
+    UntrustedResponse(Box<UntrustedResponse>),
+
+    #[error("mkvs: response served by a quarantined source")]
+    Quarantined,
+}
+
+/// Forensic data about a host storage response which failed verification.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct UntrustedResponse {
+    /// Host storage endpoint the request was made to.
+    pub endpoint: HostStorageEndpoint,
+    /// Remote node which served the response, if it signed the response.
+    pub source: Option<PublicKey>,
+    /// Kind of the request.
+    pub method: &'static str,
+    /// Tree and position the request was made for.
+    pub tree: TreeID,
+    /// Root the proof claimed to be for.
+    pub proof_root: Hash,
+    /// Hash of the encoded proof.
+    pub proof_hash: Hash,
+    /// Number of failures recorded for the source, including this one. Failures of unverified
+    /// sources are not recorded.
+    pub failures: u64,
+    /// Verification error.
+    pub reason: String,
+}
+
+impl fmt::Display for UntrustedResponse {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "untrusted {} response from ", self.method)?;
+        match self.source {
+            Some(source) => write!(f, "{source:?}")?,
+            None => write!(f, "unverified source")?,
+        }
+        write!(f, " (failures: {}): {}", self.failures, self.reason)
+    }
 }
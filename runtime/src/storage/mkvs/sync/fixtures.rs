@@ -0,0 +1,178 @@
+//! Golden proof test vectors.
+//!
+//! The fixtures cover every supported proof version and include both valid proofs and proofs
+//! that must be rejected. They are shipped in machine-readable form as [`PROOF_FIXTURES_JSON`]
+//! (`testdata/mkvs_proof_fixtures.json`) so that alternative implementations can check their
+//! compatibility against the same vectors, while [`verify_fixture`] checks a fixture, possibly
+//! produced by another implementation, against this one.
+use base64::prelude::*;
+use thiserror::Error;
+
+use crate::common::crypto::hash::Hash;
+
+use super::{Proof, ProofVerifier};
+
+/// Proof fixtures in machine-readable form.
+pub const PROOF_FIXTURES_JSON: &str = include_str!("../../../../testdata/mkvs_proof_fixtures.json");
+
+/// Version of the fixture format.
+pub const FIXTURE_FORMAT_VERSION: u16 = 1;
+
+/// Fixture errors.
+#[derive(Error, Debug)]
+pub enum FixtureError {
+    #[error("malformed fixture: {0}")]
+    Malformed(String),
+
+    #[error("unsupported fixture format version: {0}")]
+    UnsupportedFormat(u16),
+
+    #[error("proof does not round-trip")]
+    RoundTrip,
+
+    #[error("proof version mismatch (expected: {expected} got: {got})")]
+    VersionMismatch { expected: u16, got: u16 },
+
+    #[error("valid proof rejected: {0}")]
+    Rejected(#[source] anyhow::Error),
+
+    #[error("invalid proof accepted")]
+    Accepted,
+}
+
+/// A golden proof test vector.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ProofFixture {
+    /// Unique name of the fixture.
+    pub name: String,
+    /// Human-readable description of what the fixture covers.
+    pub description: String,
+    /// Proof version the fixture is for.
+    pub version: u16,
+    /// Root hash the proof is verified against.
+    pub root: Hash,
+    /// CBOR-encoded proof.
+    pub proof: Vec<u8>,
+    /// Whether the proof must verify.
+    pub valid: bool,
+}
+
+#[derive(serde::Deserialize)]
+struct RawFixtures {
+    version: u16,
+    fixtures: Vec<RawFixture>,
+}
+
+#[derive(serde::Deserialize)]
+struct RawFixture {
+    name: String,
+    #[serde(default)]
+    description: String,
+    version: u16,
+    /// Hex-encoded root hash.
+    root: String,
+    /// Base64-encoded proof.
+    proof: String,
+    valid: bool,
+}
+
+impl TryFrom<RawFixture> for ProofFixture {
+    type Error = FixtureError;
+
+    fn try_from(raw: RawFixture) -> Result<Self, Self::Error> {
+        let root = raw
+            .root
+            .parse()
+            .map_err(|_| FixtureError::Malformed(format!("{}: bad root hash", raw.name)))?;
+        let proof = BASE64_STANDARD
+            .decode(&raw.proof)
+            .map_err(|_| FixtureError::Malformed(format!("{}: bad proof encoding", raw.name)))?;
+
+        Ok(Self {
+            name: raw.name,
+            description: raw.description,
+            version: raw.version,
+            root,
+            proof,
+            valid: raw.valid,
+        })
+    }
+}
+
+/// Parse proof fixtures from their machine-readable form.
+pub fn parse_fixtures(json: &str) -> Result<Vec<ProofFixture>, FixtureError> {
+    let raw: RawFixtures =
+        serde_json::from_str(json).map_err(|err| FixtureError::Malformed(err.to_string()))?;
+    if raw.version != FIXTURE_FORMAT_VERSION {
+        return Err(FixtureError::UnsupportedFormat(raw.version));
+    }
+
+    raw.fixtures.into_iter().map(TryInto::try_into).collect()
+}
+
+/// Proof fixtures shipped with this crate.
+pub fn proof_fixtures() -> Vec<ProofFixture> {
+    parse_fixtures(PROOF_FIXTURES_JSON).expect("shipped fixtures should be well-formed")
+}
+
+/// Verify that this implementation handles the given fixture as expected.
+///
+/// Valid proofs must decode, round-trip to the same encoding and verify against the fixture's
+/// root. Invalid proofs must be rejected either when decoding or when verifying.
+pub fn verify_fixture(fixture: &ProofFixture) -> Result<(), FixtureError> {
+    let proof: Proof = match cbor::from_slice(&fixture.proof) {
+        Ok(proof) => proof,
+        Err(_) if !fixture.valid => return Ok(()),
+        Err(err) => return Err(FixtureError::Rejected(err.into())),
+    };
+    if proof.v != fixture.version {
+        return Err(FixtureError::VersionMismatch {
+            expected: fixture.version,
+            got: proof.v,
+        });
+    }
+
+    match ProofVerifier.verify_proof(fixture.root, &proof) {
+        Ok(_) if !fixture.valid => Err(FixtureError::Accepted),
+        Ok(_) if cbor::to_vec(proof.clone()) != fixture.proof => Err(FixtureError::RoundTrip),
+        Ok(_) => Ok(()),
+        Err(err) if fixture.valid => Err(FixtureError::Rejected(err)),
+        Err(_) => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_proof_fixtures() {
+        let fixtures = proof_fixtures();
+        for version in 0..=2 {
+            assert!(fixtures.iter().any(|f| f.version == version));
+        }
+        for fixture in &fixtures {
+            verify_fixture(fixture)
+                .unwrap_or_else(|err| panic!("fixture {} failed: {err}", fixture.name));
+        }
+
+        // Fixtures with the wrong expectation fail.
+        let mut fixture = fixtures[0].clone();
+        fixture.valid = false;
+        assert!(matches!(
+            verify_fixture(&fixture),
+            Err(FixtureError::Accepted)
+        ));
+        let mut fixture = fixtures[1].clone();
+        fixture.valid = true;
+        assert!(matches!(
+            verify_fixture(&fixture),
+            Err(FixtureError::Rejected(_))
+        ));
+
+        assert!(matches!(
+            parse_fixtures(r#"{"version": 2, "fixtures": []}"#),
+            Err(FixtureError::UnsupportedFormat(2))
+        ));
+    }
+}
@@ -1,11 +1,23 @@
-use std::{any::Any, sync::Arc};
+use std::{
+    any::Any,
+    cell::Cell,
+    collections::HashMap,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
 
 use anyhow::Result;
+use lazy_static::lazy_static;
 
 use crate::{
-    protocol::{Protocol, ProtocolError},
+    common::crypto::{hash::Hash, signature::PublicKey},
+    config::Limit,
+    future::block_on,
+    health::HealthMonitor,
+    protocol::{CallOpts, Protocol, ProtocolError},
     storage::mkvs::sync::{
-        GetPrefixesRequest, GetRequest, IterateRequest, ProofResponse, ReadSync,
+        GetPrefixRequest, GetPrefixesRequest, GetRequest, IterateRequest, ProofResponse, ReadSync,
+        SyncerError, TreeID, UntrustedResponse,
     },
     types::{
         Body, HostStorageEndpoint, StorageSyncRequest, StorageSyncRequestWithEndpoint,
@@ -13,25 +25,342 @@ use crate::{
     },
 };
 
+/// Time for which a source that served a proof failing verification is not used again.
+pub const QUARANTINE_PERIOD: Duration = Duration::from_secs(60);
+
+lazy_static! {
+    static ref GLOBAL_QUARANTINE: SourceQuarantine = SourceQuarantine::new(QUARANTINE_PERIOD);
+}
+
+thread_local! {
+    /// Host storage sync calls made by the current thread.
+    static SYNC_STATS: Cell<HostSyncStats> = const { Cell::new(HostSyncStats::new()) };
+}
+
+/// Statistics of host storage sync calls made by the current thread.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct HostSyncStats {
+    /// Number of sync calls.
+    pub count: u64,
+    /// Total time spent waiting for the host to respond.
+    pub duration: Duration,
+}
+
+impl HostSyncStats {
+    const fn new() -> Self {
+        Self {
+            count: 0,
+            duration: Duration::ZERO,
+        }
+    }
+
+    /// Return the statistics of sync calls made by the current thread since the previous call,
+    /// resetting them.
+    pub fn take() -> Self {
+        SYNC_STATS.with(|stats| stats.replace(Self::new()))
+    }
+
+    fn record(duration: Duration) {
+        SYNC_STATS.with(|stats| {
+            let mut current = stats.get();
+            current.count += 1;
+            current.duration += duration;
+            stats.set(current);
+        });
+    }
+}
+
+/// A verified source of host storage responses.
+type Source = (HostStorageEndpoint, PublicKey);
+
+struct QuarantineRecord {
+    failures: u64,
+    until: Instant,
+}
+
+/// Record of sources which served proofs that failed verification.
+///
+/// Sources are quarantined for a cool-down period after each failure and are excluded from
+/// requests to the host, so that a bad source is not retried in a tight loop. As the quarantine is
+/// shared, only remote nodes which signed the proofs they served are recorded, so that the host
+/// can't get honest nodes, or itself, quarantined by misattributing invalid proofs.
+pub struct SourceQuarantine {
+    period: Duration,
+    records: Mutex<HashMap<Source, QuarantineRecord>>,
+}
+
+impl SourceQuarantine {
+    /// Create a new quarantine with the given cool-down period.
+    pub fn new(period: Duration) -> Self {
+        Self {
+            period,
+            records: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Global quarantine shared by all host read syncers.
+    pub fn global() -> &'static SourceQuarantine {
+        &GLOBAL_QUARANTINE
+    }
+
+    /// Record a failure of the given source at the given time, returning the total number of
+    /// failures recorded for the source.
+    pub fn record_failure(
+        &self,
+        endpoint: HostStorageEndpoint,
+        source: PublicKey,
+        now: Instant,
+    ) -> u64 {
+        let mut records = self.records.lock().unwrap();
+        let record = records
+            .entry((endpoint, source))
+            .or_insert(QuarantineRecord {
+                failures: 0,
+                until: now,
+            });
+        record.failures += 1;
+        record.until = now + self.period;
+        record.failures
+    }
+
+    /// Whether the given source is quarantined at the given time.
+    pub fn is_quarantined(
+        &self,
+        endpoint: HostStorageEndpoint,
+        source: PublicKey,
+        now: Instant,
+    ) -> bool {
+        let records = self.records.lock().unwrap();
+        records
+            .get(&(endpoint, source))
+            .is_some_and(|record| record.until > now)
+    }
+
+    /// Remote nodes quarantined for the given endpoint at the given time.
+    pub fn quarantined_nodes(&self, endpoint: HostStorageEndpoint, now: Instant) -> Vec<PublicKey> {
+        let records = self.records.lock().unwrap();
+        records
+            .iter()
+            .filter(|((ep, _), record)| *ep == endpoint && record.until > now)
+            .map(|((_, source), _)| *source)
+            .collect()
+    }
+}
+
+/// Timeouts of host storage sync requests, by the expected cost of the request.
+///
+/// A sync request which times out fails the transaction or batch it was made for, so timeouts
+/// are disabled by default and should only be enabled in case the host is known to respond
+/// within them.
+#[derive(Clone, Debug, Default)]
+pub struct SyncTimeouts {
+    /// Timeout of lookups continuing below the tree root, which only fetch nodes adjacent to
+    /// already cached ones. If not specified, such lookups don't time out.
+    pub fast: Option<Duration>,
+    /// Timeout of lookups starting at the tree root and of prefix and iteration requests, which
+    /// may fetch long cold paths. If not specified, such requests don't time out.
+    pub cold: Option<Duration>,
+}
+
+impl SyncTimeouts {
+    /// Timeout of the given request.
+    fn timeout(&self, request: &StorageSyncRequest) -> Option<Duration> {
+        match request {
+            StorageSyncRequest::SyncGet(request)
+                if request.tree.position != request.tree.root.hash =>
+            {
+                self.fast
+            }
+            _ => self.cold,
+        }
+    }
+}
+
+/// Storage circuit breaker configuration.
+#[derive(Clone, Debug)]
+pub struct CircuitBreakerConfig {
+    /// Number of consecutive failed sync requests after which the breaker opens. A zero value
+    /// disables the breaker.
+    pub failure_threshold: u32,
+    /// Time after which an open breaker lets probe batches through.
+    pub probe_interval: Duration,
+}
+
+impl Default for CircuitBreakerConfig {
+    fn default() -> Self {
+        Self {
+            failure_threshold: 5,
+            probe_interval: Duration::from_secs(10),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum BreakerState {
+    Closed { failures: u32 },
+    Open { until: Instant },
+    Probing,
+}
+
+/// Circuit breaker for host storage sync requests.
+///
+/// After repeated sync failures (e.g. timeouts) storage is considered degraded and new batches
+/// are rejected immediately instead of each waiting for its sync requests to fail. Sync requests
+/// themselves are never rejected, as failing them would abort batches which already started.
+/// Once the probe interval elapses, batches are let through again as probes until the next sync
+/// request completes, which closes the breaker in case it succeeds and opens it again otherwise.
+pub struct CircuitBreaker {
+    config: CircuitBreakerConfig,
+    state: Mutex<BreakerState>,
+}
+
+impl CircuitBreaker {
+    /// Create a new closed circuit breaker.
+    pub fn new(config: CircuitBreakerConfig) -> Self {
+        Self {
+            config,
+            state: Mutex::new(BreakerState::Closed { failures: 0 }),
+        }
+    }
+
+    /// Whether a batch may be started at the given time.
+    pub fn admit(&self, now: Instant) -> bool {
+        let mut state = self.state.lock().unwrap();
+        match *state {
+            BreakerState::Closed { .. } | BreakerState::Probing => true,
+            BreakerState::Open { until } if now >= until => {
+                *state = BreakerState::Probing;
+                true
+            }
+            BreakerState::Open { .. } => false,
+        }
+    }
+
+    /// Record a successful request.
+    pub fn record_success(&self) {
+        *self.state.lock().unwrap() = BreakerState::Closed { failures: 0 };
+    }
+
+    /// Record a failed request at the given time.
+    pub fn record_failure(&self, now: Instant) {
+        if self.config.failure_threshold == 0 {
+            return;
+        }
+        let mut state = self.state.lock().unwrap();
+        let open = BreakerState::Open {
+            until: now + self.config.probe_interval,
+        };
+        *state = match *state {
+            BreakerState::Closed { failures } if failures + 1 < self.config.failure_threshold => {
+                BreakerState::Closed {
+                    failures: failures + 1,
+                }
+            }
+            BreakerState::Closed { .. } | BreakerState::Probing => open,
+            open @ BreakerState::Open { .. } => open,
+        };
+    }
+
+    /// Whether storage is degraded, i.e. the breaker is not closed.
+    pub fn is_degraded(&self) -> bool {
+        !matches!(*self.state.lock().unwrap(), BreakerState::Closed { .. })
+    }
+}
+
+/// Details of the last response, kept to report it in case its proof fails verification.
+struct LastResponse {
+    method: &'static str,
+    tree: TreeID,
+    source: Option<PublicKey>,
+    proof_root: Hash,
+    proof_hash: Hash,
+}
+
 /// A proxy read syncer which forwards calls to the runtime host.
 pub struct HostReadSyncer {
     protocol: Arc<Protocol>,
     endpoint: HostStorageEndpoint,
+    quarantine: &'static SourceQuarantine,
+    last: Option<LastResponse>,
 }
 
 impl HostReadSyncer {
     /// Construct a new host proxy instance.
     pub fn new(protocol: Arc<Protocol>, endpoint: HostStorageEndpoint) -> HostReadSyncer {
-        HostReadSyncer { protocol, endpoint }
+        HostReadSyncer {
+            protocol,
+            endpoint,
+            quarantine: SourceQuarantine::global(),
+            last: None,
+        }
     }
 
-    fn call_host_with_proof(&self, request: StorageSyncRequest) -> Result<ProofResponse> {
+    fn call_host_with_proof(&mut self, request: StorageSyncRequest) -> Result<ProofResponse> {
+        let (method, tree) = match &request {
+            StorageSyncRequest::SyncGet(request) => ("SyncGet", &request.tree),
+            StorageSyncRequest::SyncGetPrefixes(request) => ("SyncGetPrefixes", &request.tree),
+            StorageSyncRequest::SyncIterate(request) => ("SyncIterate", &request.tree),
+            StorageSyncRequest::SyncGetPrefix(request) => ("SyncGetPrefix", &request.tree),
+        };
+        let tree = tree.clone();
+        self.last = None;
+        let excluded_sources = self
+            .quarantine
+            .quarantined_nodes(self.endpoint, Instant::now());
+
+        let timeout = self
+            .protocol
+            .get_config()
+            .storage
+            .sync_timeouts
+            .timeout(&request);
         let request = Body::HostStorageSyncRequest(StorageSyncRequestWithEndpoint {
             endpoint: self.endpoint,
             request,
+            excluded_sources,
         });
-        match self.protocol.call_host(request) {
+        let start = Instant::now();
+        let response = block_on(self.protocol.call_host_async_with_opts(
+            request,
+            CallOpts {
+                timeout,
+                ..Default::default()
+            },
+        ));
+        HealthMonitor::global().record_storage_sync(start);
+        HostSyncStats::record(start.elapsed());
+
+        // Only record the outcome, as the batch this request is made for was already admitted.
+        let breaker = self.protocol.storage_breaker();
+        match response {
+            Ok(_) => breaker.record_success(),
+            Err(_) => breaker.record_failure(Instant::now()),
+        }
+        HealthMonitor::global().record_storage_degraded(breaker.is_degraded());
+
+        match response {
             Ok(Body::HostStorageSyncResponse(StorageSyncResponse::ProofResponse(response))) => {
+                self.protocol
+                    .get_config()
+                    .limits
+                    .check(Limit::Proof, response.proof.size())?;
+                let source = response.verified_source();
+                if let Some(source) = source {
+                    if self
+                        .quarantine
+                        .is_quarantined(self.endpoint, source, Instant::now())
+                    {
+                        // The host ignored the excluded sources.
+                        return Err(SyncerError::Quarantined.into());
+                    }
+                }
+                self.last = Some(LastResponse {
+                    method,
+                    tree,
+                    source,
+                    proof_root: response.proof.untrusted_root,
+                    proof_hash: response.proof_hash(),
+                });
                 Ok(response)
             }
             Ok(_) => Err(ProtocolError::InvalidResponse.into()),
@@ -53,7 +382,176 @@ impl ReadSync for HostReadSyncer {
         self.call_host_with_proof(StorageSyncRequest::SyncGetPrefixes(request))
     }
 
+    fn sync_get_prefix(&mut self, request: GetPrefixRequest) -> Result<ProofResponse> {
+        let max_size = request.max_size;
+        let response = self.call_host_with_proof(StorageSyncRequest::SyncGetPrefix(request))?;
+        // Make sure the host respected the size budget.
+        if response.proof.size() as u64 > max_size {
+            return Err(ProtocolError::InvalidResponse.into());
+        }
+        Ok(response)
+    }
+
     fn sync_iterate(&mut self, request: IterateRequest) -> Result<ProofResponse> {
         self.call_host_with_proof(StorageSyncRequest::SyncIterate(request))
     }
+
+    fn report_invalid_proof(&mut self, error: anyhow::Error) -> anyhow::Error {
+        let Some(last) = self.last.take() else {
+            return error;
+        };
+        // Unverified sources are not quarantined, as the failure can't be attributed to them.
+        let failures = match last.source {
+            Some(source) => self
+                .quarantine
+                .record_failure(self.endpoint, source, Instant::now()),
+            None => 0,
+        };
+
+        SyncerError::UntrustedResponse(Box::new(UntrustedResponse {
+            endpoint: self.endpoint,
+            source: last.source,
+            method: last.method,
+            tree: last.tree,
+            proof_root: last.proof_root,
+            proof_hash: last.proof_hash,
+            failures,
+            reason: error.to_string(),
+        }))
+        .into()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{
+        common::crypto::signature::{PrivateKey, SignatureBundle, Signer},
+        storage::mkvs::sync::PROOF_SOURCE_SIGNATURE_CONTEXT,
+    };
+
+    use super::*;
+
+    #[test]
+    fn test_source_quarantine() {
+        let quarantine = SourceQuarantine::new(Duration::from_secs(10));
+        let node = PublicKey::from(vec![1; 32]);
+        let now = Instant::now();
+        let runtime = HostStorageEndpoint::Runtime;
+
+        let other = PublicKey::from(vec![2; 32]);
+        assert!(!quarantine.is_quarantined(runtime, node, now));
+        assert_eq!(quarantine.record_failure(runtime, node, now), 1);
+        assert!(quarantine.is_quarantined(runtime, node, now));
+        assert!(!quarantine.is_quarantined(runtime, other, now));
+        assert!(!quarantine.is_quarantined(HostStorageEndpoint::Consensus, node, now));
+        assert_eq!(quarantine.quarantined_nodes(runtime, now), vec![node]);
+
+        // Sources are released after the cool-down period, but failures are remembered.
+        let later = now + Duration::from_secs(10);
+        assert!(!quarantine.is_quarantined(runtime, node, later));
+        assert!(quarantine.quarantined_nodes(runtime, later).is_empty());
+        assert_eq!(quarantine.record_failure(runtime, node, later), 2);
+        assert!(quarantine.is_quarantined(runtime, node, later));
+    }
+
+    #[test]
+    fn test_verified_source() {
+        let signer = PrivateKey::from_test_seed("proof source".to_string());
+        let mut response = ProofResponse::default();
+        assert_eq!(response.verified_source(), None);
+
+        let signature = signer
+            .sign(
+                PROOF_SOURCE_SIGNATURE_CONTEXT,
+                response.proof_hash().as_ref(),
+            )
+            .unwrap();
+        response.source = Some(SignatureBundle {
+            public_key: signer.public(),
+            signature,
+        });
+        assert_eq!(response.verified_source(), Some(signer.public()));
+
+        // Sources claimed by the host without a valid signature are not trusted.
+        response.source.as_mut().unwrap().public_key = PublicKey::from(vec![1; 32]);
+        assert_eq!(response.verified_source(), None);
+        response.proof.untrusted_root = Hash::digest_bytes(b"other");
+        response.source.as_mut().unwrap().public_key = signer.public();
+        assert_eq!(response.verified_source(), None);
+    }
+
+    #[test]
+    fn test_circuit_breaker() {
+        let breaker = CircuitBreaker::new(CircuitBreakerConfig {
+            failure_threshold: 2,
+            probe_interval: Duration::from_secs(10),
+        });
+        let now = Instant::now();
+
+        // Successes reset the failure count.
+        breaker.record_failure(now);
+        breaker.record_success();
+        breaker.record_failure(now);
+        assert!(breaker.admit(now));
+        assert!(!breaker.is_degraded());
+
+        // Repeated failures open the breaker.
+        breaker.record_failure(now);
+        assert!(breaker.is_degraded());
+        assert!(!breaker.admit(now));
+        assert!(!breaker.admit(now + Duration::from_secs(9)));
+
+        // Probes are let through after the probe interval until a request completes.
+        let later = now + Duration::from_secs(10);
+        assert!(breaker.admit(later));
+        assert!(breaker.admit(later));
+        breaker.record_failure(later);
+        assert!(!breaker.admit(later));
+
+        let later = later + Duration::from_secs(10);
+        assert!(breaker.admit(later));
+        breaker.record_success();
+        assert!(!breaker.is_degraded());
+
+        // The breaker can be disabled.
+        let disabled = CircuitBreaker::new(CircuitBreakerConfig {
+            failure_threshold: 0,
+            ..Default::default()
+        });
+        for _ in 0..10 {
+            disabled.record_failure(now);
+        }
+        assert!(disabled.admit(now));
+    }
+
+    #[test]
+    fn test_sync_timeouts() {
+        // Requests don't time out by default.
+        assert_eq!(
+            SyncTimeouts::default().timeout(&StorageSyncRequest::SyncIterate(Default::default())),
+            None
+        );
+
+        let timeouts = SyncTimeouts {
+            fast: Some(Duration::from_secs(2)),
+            cold: Some(Duration::from_secs(30)),
+        };
+        let mut request = GetRequest::default();
+        request.tree.root.hash = Hash::digest_bytes(b"root");
+        request.tree.position = request.tree.root.hash;
+        assert_eq!(
+            timeouts.timeout(&StorageSyncRequest::SyncGet(request.clone())),
+            timeouts.cold
+        );
+
+        request.tree.position = Hash::digest_bytes(b"node");
+        assert_eq!(
+            timeouts.timeout(&StorageSyncRequest::SyncGet(request)),
+            timeouts.fast
+        );
+        assert_eq!(
+            timeouts.timeout(&StorageSyncRequest::SyncIterate(Default::default())),
+            timeouts.cold
+        );
+    }
 }
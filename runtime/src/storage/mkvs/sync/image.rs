@@ -0,0 +1,564 @@
+//! Read-only MKVS images.
+//!
+//! An image is a flat, hash-indexed serialization of all nodes of a finalized tree. It is meant to
+//! be stored outside the enclave (e.g. on a host-provided volume) and paged in on demand through
+//! the `ImageReadSyncer` so that large, mostly-static datasets don't need to be kept resident in
+//! enclave memory. Images are only opened against a trusted root, every node is verified against
+//! its hash when it is first loaded and the tree additionally verifies all returned proofs against
+//! the trusted root.
+use std::{any::Any, io::Write, sync::Arc};
+
+use anyhow::{anyhow, Result};
+use byteorder::{BigEndian, ByteOrder, WriteBytesExt};
+use thiserror::Error;
+
+use crate::{
+    common::{crypto::hash::Hash, namespace::Namespace},
+    future::block_on,
+    host::volume_manager::{VolumeManager, VolumeReadRequest},
+    storage::mkvs::{
+        cache::Cache,
+        marshal::Marshal,
+        sync::{
+            proof::{PROOF_ENTRY_FULL, PROOF_ENTRY_HASH},
+            GetPrefixRequest, GetPrefixesRequest, GetRequest, IterateRequest, NoopReadSyncer,
+            Proof, ProofResponse, ProofVerifier, RawProofEntry, ReadSync,
+        },
+        tree::{Node, NodeBox, NodePtrRef, Root, RootType, Tree},
+    },
+};
+
+/// Magic bytes identifying an MKVS image.
+const IMAGE_MAGIC: &[u8; 8] = b"mkvsimg1";
+/// Size of the image header.
+const IMAGE_HEADER_SIZE: usize = 8 + 1 + 32 + 8 + 32 + 8;
+/// Size of a single index entry (node hash, data offset, data length).
+const IMAGE_INDEX_ENTRY_SIZE: usize = 32 + 8 + 4;
+/// Proof version used for proofs generated from images.
+const IMAGE_PROOF_VERSION: u16 = 1;
+
+/// Image error.
+#[derive(Error, Debug)]
+pub enum ImageError {
+    #[error("mkvs/image: root mismatch (expected: {expected:?} got: {got:?})")]
+    RootMismatch { expected: Root, got: Root },
+}
+
+/// A source of image bytes that supports random access.
+pub trait ImageSource {
+    /// Read exactly `buf.len()` bytes starting at the given offset.
+    fn read_at(&self, offset: u64, buf: &mut [u8]) -> Result<()>;
+}
+
+impl ImageSource for Vec<u8> {
+    fn read_at(&self, offset: u64, buf: &mut [u8]) -> Result<()> {
+        let start = usize::try_from(offset)?;
+        let end = start
+            .checked_add(buf.len())
+            .filter(|end| *end <= self.len())
+            .ok_or_else(|| anyhow!("mkvs/image: read out of bounds"))?;
+        buf.copy_from_slice(&self[start..end]);
+        Ok(())
+    }
+}
+
+#[cfg(not(target_env = "sgx"))]
+impl ImageSource for std::fs::File {
+    fn read_at(&self, offset: u64, buf: &mut [u8]) -> Result<()> {
+        use std::os::unix::fs::FileExt;
+
+        Ok(self.read_exact_at(buf, offset)?)
+    }
+}
+
+/// An image stored in a file on a host volume, whose ranges are read from the host as nodes are
+/// paged in.
+///
+/// The host is not trusted, which is fine as all nodes are verified when they are loaded.
+pub struct VolumeImageSource {
+    volume_manager: Arc<dyn VolumeManager>,
+    id: String,
+    path: String,
+}
+
+impl VolumeImageSource {
+    /// Create a new source of the image stored in the given file of the given volume.
+    pub fn new(volume_manager: Arc<dyn VolumeManager>, id: String, path: String) -> Self {
+        Self {
+            volume_manager,
+            id,
+            path,
+        }
+    }
+}
+
+impl ImageSource for VolumeImageSource {
+    fn read_at(&self, offset: u64, buf: &mut [u8]) -> Result<()> {
+        let rsp = block_on(self.volume_manager.volume_read(VolumeReadRequest {
+            id: self.id.clone(),
+            path: self.path.clone(),
+            offset,
+            length: Some(buf.len() as u64),
+        }))?;
+        let data = rsp
+            .data
+            .ok_or_else(|| anyhow!("mkvs/image: image not found"))?;
+        if data.len() != buf.len() {
+            return Err(anyhow!("mkvs/image: read out of bounds"));
+        }
+        buf.copy_from_slice(&data);
+        Ok(())
+    }
+}
+
+/// Build an image containing the given key/value pairs and write it to the given writer.
+///
+/// Returns the root of the tree stored in the image.
+pub fn build_image<W, I>(
+    writer: &mut W,
+    namespace: Namespace,
+    version: u64,
+    root_type: RootType,
+    entries: I,
+) -> Result<Root>
+where
+    W: Write,
+    I: IntoIterator<Item = (Vec<u8>, Vec<u8>)>,
+{
+    // Build the complete tree in memory.
+    let mut tree = Tree::builder()
+        .with_capacity(0, 0)
+        .with_root_type(root_type)
+        .build(Box::new(NoopReadSyncer));
+    for (key, value) in entries {
+        tree.insert(&key, &value)?;
+    }
+    let hash = tree.commit(namespace, version)?;
+
+    // Serialize all nodes.
+    let mut nodes = Vec::new();
+    let pending_root = tree.cache.borrow().get_pending_root();
+    collect_nodes(&pending_root, &mut nodes, false)?;
+
+    let root = Root {
+        namespace,
+        version,
+        root_type,
+        hash,
+    };
+    write_image(writer, root, nodes)?;
+
+    Ok(root)
+}
+
+/// Build an image containing the nodes from the given proofs and write it to the given writer.
+///
+/// All proofs must be rooted at the given root and are verified against it. The image only
+/// contains the nodes included in the proofs, so trees backed by it fail when accessing any
+/// other part of the state.
+pub fn build_image_from_proofs<W: Write>(
+    writer: &mut W,
+    root: Root,
+    proofs: &[Proof],
+) -> Result<()> {
+    let mut nodes = Vec::new();
+    for proof in proofs {
+        let subtree = ProofVerifier.verify_proof(root.hash, proof)?;
+        collect_nodes(&subtree, &mut nodes, true)?;
+    }
+
+    write_image(writer, root, nodes)
+}
+
+fn write_image<W: Write>(
+    writer: &mut W,
+    root: Root,
+    mut nodes: Vec<(Hash, Vec<u8>)>,
+) -> Result<()> {
+    nodes.sort_by(|a, b| a.0.cmp(&b.0));
+    nodes.dedup_by(|a, b| a.0 == b.0);
+
+    // Header.
+    writer.write_all(IMAGE_MAGIC)?;
+    writer.write_u8(root.root_type as u8)?;
+    writer.write_all(root.namespace.as_ref())?;
+    writer.write_u64::<BigEndian>(root.version)?;
+    writer.write_all(root.hash.as_ref())?;
+    writer.write_u64::<BigEndian>(nodes.len() as u64)?;
+
+    // Index.
+    let mut offset = (IMAGE_HEADER_SIZE + nodes.len() * IMAGE_INDEX_ENTRY_SIZE) as u64;
+    for (node_hash, data) in &nodes {
+        writer.write_all(node_hash.as_ref())?;
+        writer.write_u64::<BigEndian>(offset)?;
+        writer.write_u32::<BigEndian>(data.len().try_into()?)?;
+        offset += data.len() as u64;
+    }
+
+    // Node data.
+    for (_, data) in &nodes {
+        writer.write_all(data)?;
+    }
+
+    Ok(())
+}
+
+/// Collect and serialize all nodes of the given subtree. Unresolved nodes are skipped in case
+/// `partial` is set and are an error otherwise.
+pub(super) fn collect_nodes(
+    ptr: &NodePtrRef,
+    nodes: &mut Vec<(Hash, Vec<u8>)>,
+    partial: bool,
+) -> Result<()> {
+    let ptr = ptr.borrow();
+    if ptr.is_null() {
+        return Ok(());
+    }
+    let node = match ptr.node {
+        Some(ref node) => node.clone(),
+        None if partial => return Ok(()),
+        None => return Err(anyhow!("mkvs/image: unresolved node")),
+    };
+    let node = node.borrow();
+    nodes.push((node.get_hash(), node.marshal_binary()?));
+
+    if let NodeBox::Internal(ref n) = *node {
+        collect_nodes(&n.leaf_node, nodes, partial)?;
+        collect_nodes(&n.left, nodes, partial)?;
+        collect_nodes(&n.right, nodes, partial)?;
+    }
+    Ok(())
+}
+
+/// A read syncer which serves nodes from a read-only image.
+///
+/// Each request is answered with a proof containing only the requested node, so nodes are only
+/// loaded from the image when they are first touched by the tree.
+pub struct ImageReadSyncer<S: ImageSource> {
+    source: S,
+    root: Root,
+    node_count: u64,
+}
+
+impl<S: ImageSource> ImageReadSyncer<S> {
+    /// Open an image from the given source, which must contain the tree with the given trusted
+    /// root.
+    pub fn open(source: S, expected_root: Root) -> Result<Self> {
+        let mut header = [0u8; IMAGE_HEADER_SIZE];
+        source.read_at(0, &mut header)?;
+        if &header[..8] != IMAGE_MAGIC {
+            return Err(anyhow!("mkvs/image: bad magic"));
+        }
+        let root_type = match header[8] {
+            1 => RootType::State,
+            2 => RootType::IO,
+            _ => return Err(anyhow!("mkvs/image: bad root type")),
+        };
+        let namespace = Namespace::from(&header[9..41]);
+        let version = BigEndian::read_u64(&header[41..49]);
+        let hash = Hash::from(&header[49..81]);
+        let node_count = BigEndian::read_u64(&header[81..89]);
+
+        let root = Root {
+            namespace,
+            version,
+            root_type,
+            hash,
+        };
+        if root != expected_root {
+            return Err(ImageError::RootMismatch {
+                expected: expected_root,
+                got: root,
+            }
+            .into());
+        }
+
+        Ok(Self {
+            source,
+            root,
+            node_count,
+        })
+    }
+
+    /// Root of the tree stored in the image.
+    pub fn root(&self) -> Root {
+        self.root
+    }
+
+    /// Construct a tree backed by this image with the given cache capacity.
+    pub fn into_tree(self, node_capacity: usize, value_capacity: usize) -> Tree
+    where
+        S: 'static,
+    {
+        Tree::builder()
+            .with_capacity(node_capacity, value_capacity)
+            .with_root(self.root)
+            .build(Box::new(self))
+    }
+
+    /// Look up the data location of a node with the given hash.
+    fn lookup(&self, hash: &Hash) -> Result<Option<(u64, usize)>> {
+        let mut entry = [0u8; IMAGE_INDEX_ENTRY_SIZE];
+        let (mut lo, mut hi) = (0u64, self.node_count);
+        while lo < hi {
+            let mid = lo + (hi - lo) / 2;
+            self.source.read_at(
+                IMAGE_HEADER_SIZE as u64 + mid * IMAGE_INDEX_ENTRY_SIZE as u64,
+                &mut entry,
+            )?;
+            match entry[..32].cmp(hash.as_ref()) {
+                std::cmp::Ordering::Less => lo = mid + 1,
+                std::cmp::Ordering::Greater => hi = mid,
+                std::cmp::Ordering::Equal => {
+                    let offset = BigEndian::read_u64(&entry[32..40]);
+                    let length = BigEndian::read_u32(&entry[40..44]) as usize;
+                    return Ok(Some((offset, length)));
+                }
+            }
+        }
+        Ok(None)
+    }
+
+    /// Load and verify the node with the given hash.
+    fn load_node(&self, hash: &Hash) -> Result<NodeBox> {
+        let (offset, length) = self
+            .lookup(hash)?
+            .ok_or_else(|| anyhow!("mkvs/image: node not found ({:?})", hash))?;
+        let mut data = vec![0u8; length];
+        self.source.read_at(offset, &mut data)?;
+
+        decode_node(hash, &data)
+    }
+
+    /// Build a proof rooted at the given position containing only the node at that position.
+    fn node_proof(&self, position: Hash) -> Result<ProofResponse> {
+        let position = if position.is_empty() {
+            self.root.hash
+        } else {
+            position
+        };
+        let node = self.load_node(&position)?;
+
+        single_node_proof(position, &node)
+    }
+}
+
+/// Decode a serialized node and verify that it has the given hash.
+pub(super) fn decode_node(hash: &Hash, data: &[u8]) -> Result<NodeBox> {
+    let mut node = NodeBox::default();
+    node.unmarshal_binary(data)?;
+    if node.get_hash() != *hash {
+        return Err(anyhow!(
+            "mkvs/image: node hash mismatch (expected: {:?} got: {:?})",
+            hash,
+            node.get_hash(),
+        ));
+    }
+    Ok(node)
+}
+
+/// Build a proof rooted at the given position containing only the given node.
+pub(super) fn single_node_proof(position: Hash, node: &NodeBox) -> Result<ProofResponse> {
+    let mut entries = Vec::new();
+    let mut full = vec![PROOF_ENTRY_FULL];
+    full.extend_from_slice(&node.compact_marshal_binary(IMAGE_PROOF_VERSION)?);
+    entries.push(Some(RawProofEntry(full)));
+
+    if let NodeBox::Internal(ref n) = node {
+        let leaf_ptr = n.leaf_node.borrow();
+        if leaf_ptr.is_null() {
+            entries.push(None);
+        } else {
+            let mut leaf = vec![PROOF_ENTRY_FULL];
+            leaf.extend_from_slice(
+                &leaf_ptr
+                    .get_node()
+                    .borrow()
+                    .compact_marshal_binary(IMAGE_PROOF_VERSION)?,
+            );
+            entries.push(Some(RawProofEntry(leaf)));
+        }
+
+        for child in [&n.left, &n.right] {
+            let child_hash = child.borrow().hash;
+            if child_hash.is_empty() {
+                entries.push(None);
+            } else {
+                let mut data = vec![PROOF_ENTRY_HASH];
+                data.extend_from_slice(child_hash.as_ref());
+                entries.push(Some(RawProofEntry(data)));
+            }
+        }
+    }
+
+    Ok(ProofResponse {
+        proof: Proof {
+            v: IMAGE_PROOF_VERSION,
+            untrusted_root: position,
+            entries,
+        },
+        source: None,
+    })
+}
+
+impl<S: ImageSource + 'static> ReadSync for ImageReadSyncer<S> {
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn sync_get(&mut self, request: GetRequest) -> Result<ProofResponse> {
+        self.node_proof(request.tree.position)
+    }
+
+    fn sync_get_prefixes(&mut self, request: GetPrefixesRequest) -> Result<ProofResponse> {
+        self.node_proof(request.tree.position)
+    }
+
+    fn sync_get_prefix(&mut self, request: GetPrefixRequest) -> Result<ProofResponse> {
+        self.node_proof(request.tree.position)
+    }
+
+    fn sync_iterate(&mut self, request: IterateRequest) -> Result<ProofResponse> {
+        self.node_proof(request.tree.position)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{
+        host::volume_manager::{VolumeAddRequest, VolumeWriteRequest},
+        storage::mkvs::Iterator,
+        testkit::host::MockHost,
+    };
+
+    fn build_test_image() -> (Vec<u8>, Root, Vec<(Vec<u8>, Vec<u8>)>) {
+        let items: Vec<_> = (0..500)
+            .map(|i| {
+                (
+                    format!("image key {}", i).into_bytes(),
+                    format!("image value {}", i).into_bytes(),
+                )
+            })
+            .collect();
+
+        let mut image = Vec::new();
+        let root = build_image(
+            &mut image,
+            Default::default(),
+            1,
+            RootType::State,
+            items.clone(),
+        )
+        .expect("image build should succeed");
+
+        (image, root, items)
+    }
+
+    #[test]
+    fn test_image_get_and_iterate() {
+        let (image, root, mut items) = build_test_image();
+        let syncer = ImageReadSyncer::open(image, root).expect("image should open");
+        assert_eq!(syncer.root().version, 1);
+        // Use a small cache to make sure nodes get evicted and paged in again.
+        let tree = syncer.into_tree(32, 0);
+
+        for (key, value) in &items {
+            let result = tree.get(key).expect("get should succeed");
+            assert_eq!(result.as_ref(), Some(value));
+        }
+        assert_eq!(
+            tree.get(b"image missing").expect("get should succeed"),
+            None
+        );
+
+        items.sort();
+        let mut it = tree.iter();
+        it.rewind();
+        let iterated: Vec<_> = it.collect();
+        assert_eq!(iterated, items);
+    }
+
+    #[test]
+    fn test_image_corruption() {
+        let (mut image, root, items) = build_test_image();
+        // Corrupt the last byte of node data.
+        let last = image.len() - 1;
+        image[last] ^= 0xff;
+
+        let tree = ImageReadSyncer::open(image, root)
+            .expect("image should open")
+            .into_tree(0, 0);
+        let failed = items.iter().any(|(key, _)| tree.get(key).is_err());
+        assert!(failed, "corrupted node should fail verification");
+    }
+
+    #[test]
+    fn test_image_root_mismatch() {
+        let (image, root, _) = build_test_image();
+
+        let untrusted = [
+            Root {
+                hash: Hash::digest_bytes(b"other root"),
+                ..root
+            },
+            Root {
+                version: root.version + 1,
+                ..root
+            },
+            Root {
+                root_type: RootType::IO,
+                ..root
+            },
+        ];
+        for expected in untrusted {
+            let err = ImageReadSyncer::open(image.clone(), expected)
+                .err()
+                .expect("image with a different root should be rejected");
+            assert!(matches!(
+                err.downcast_ref::<ImageError>(),
+                Some(ImageError::RootMismatch { .. })
+            ));
+        }
+
+        // Forging the root in the header doesn't help as nodes are verified against it.
+        let mut forged = image.clone();
+        forged[49..81].copy_from_slice(Hash::digest_bytes(b"other root").as_ref());
+        let tree = ImageReadSyncer::open(forged, untrusted[0])
+            .expect("image should open")
+            .into_tree(0, 0);
+        assert!(tree.get(b"image key 1").is_err());
+    }
+
+    #[test]
+    fn test_volume_image_source() {
+        let (image, root, items) = build_test_image();
+        let rt = tokio::runtime::Runtime::new().unwrap();
+        let _guard = rt.enter();
+
+        let host: Arc<dyn VolumeManager> = Arc::new(MockHost::new());
+        let id = rt
+            .block_on(host.volume_add(VolumeAddRequest::default()))
+            .unwrap()
+            .id;
+        rt.block_on(host.volume_write(VolumeWriteRequest {
+            id: id.clone(),
+            path: "state.image".to_string(),
+            data: image,
+        }))
+        .unwrap();
+
+        let source = VolumeImageSource::new(host.clone(), id.clone(), "state.image".to_string());
+        let tree = ImageReadSyncer::open(source, root)
+            .expect("image should open")
+            .into_tree(32, 0);
+        for (key, value) in &items {
+            let result = tree.get(key).expect("get should succeed");
+            assert_eq!(result.as_ref(), Some(value));
+        }
+
+        // Missing images fail to open.
+        let source = VolumeImageSource::new(host, id, "missing.image".to_string());
+        assert!(ImageReadSyncer::open(source, root).is_err());
+    }
+}
@@ -0,0 +1,99 @@
+//! Verification of inclusion proofs.
+use thiserror::Error;
+
+use crate::{
+    common::crypto::hash::Hash,
+    storage::mkvs::{
+        sync::{Proof, ProofReadSyncer},
+        tree::{Root, RootType, Tree},
+    },
+};
+
+/// Inclusion proof verification errors.
+#[derive(Error, Debug)]
+pub enum ProofError {
+    #[error("mkvs: missing proof")]
+    Missing,
+
+    #[error("mkvs: proof for unexpected root (expected: {expected:?} got: {got:?})")]
+    UnexpectedRoot { expected: Hash, got: Hash },
+
+    #[error("mkvs: invalid proof: {0}")]
+    Invalid(#[source] anyhow::Error),
+
+    #[error("mkvs: key not included")]
+    NotIncluded,
+
+    #[error("mkvs: proven {0} does not match")]
+    Mismatch(&'static str),
+}
+
+/// Verify a proof produced by `Tree::get_multi_proof` against the given root and return the
+/// values of the given keys.
+///
+/// The proof must contain the lookup paths of all the given keys, so a valid proof proves both
+/// the presence of the returned values and the absence of keys without a value.
+pub fn verify_inclusion_proof(
+    root: Hash,
+    proof: &Proof,
+    keys: &[Vec<u8>],
+) -> Result<Vec<Option<Vec<u8>>>, ProofError> {
+    if proof.untrusted_root != root {
+        return Err(ProofError::UnexpectedRoot {
+            expected: root,
+            got: proof.untrusted_root,
+        });
+    }
+
+    let tree = Tree::builder()
+        .with_capacity(0, 0)
+        .with_root(Root {
+            root_type: RootType::IO,
+            hash: root,
+            ..Default::default()
+        })
+        .build(Box::new(ProofReadSyncer::new(proof.clone())));
+
+    // Lookups fail in case any node on their paths is missing from the proof.
+    keys.iter()
+        .map(|key| tree.get(key).map_err(ProofError::Invalid))
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::storage::mkvs::sync::NoopReadSyncer;
+
+    #[test]
+    fn test_verify_inclusion_proof() {
+        let mut tree = Tree::builder()
+            .with_root_type(RootType::IO)
+            .build(Box::new(NoopReadSyncer));
+        for i in 0..20u8 {
+            tree.insert(&[b'k', i], &[i]).unwrap();
+        }
+        let root = tree.commit(Default::default(), 1).unwrap();
+
+        let keys = vec![vec![b'k', 3], vec![b'k', 42]];
+        let proof = tree.get_multi_proof(&keys).unwrap();
+        let values = verify_inclusion_proof(root, &proof, &keys).unwrap();
+        assert_eq!(values, vec![Some(vec![3]), None]);
+
+        // Keys not covered by the proof can't be verified.
+        assert!(matches!(
+            verify_inclusion_proof(root, &proof, &[vec![b'k', 7]]),
+            Err(ProofError::Invalid(_))
+        ));
+        assert!(matches!(
+            verify_inclusion_proof(Hash::empty_hash(), &proof, &keys),
+            Err(ProofError::UnexpectedRoot { .. })
+        ));
+
+        // Tampered proofs are rejected.
+        let mut tampered = proof.clone();
+        let entry = tampered.entries[0].as_mut().unwrap();
+        *entry.last_mut().unwrap() ^= 0xff;
+        assert!(verify_inclusion_proof(root, &tampered, &keys).is_err());
+    }
+}
@@ -1,27 +1,56 @@
 //! The read-only tree sync interface.
 mod errors;
+mod fixtures;
 mod host;
+mod image;
+mod inclusion;
 mod merge;
 mod noop;
 mod proof;
+mod range;
+mod shared;
+mod static_proof;
 mod stats;
+mod verify;
 
-pub use errors::SyncerError;
-pub use host::HostReadSyncer;
+pub use errors::{SyncerError, UntrustedResponse};
+pub use fixtures::{
+    parse_fixtures, proof_fixtures, verify_fixture, FixtureError, ProofFixture,
+    FIXTURE_FORMAT_VERSION, PROOF_FIXTURES_JSON,
+};
+pub use host::{
+    CircuitBreaker, CircuitBreakerConfig, HostReadSyncer, HostSyncStats, SourceQuarantine,
+    SyncTimeouts, QUARANTINE_PERIOD,
+};
+pub use image::{
+    build_image, build_image_from_proofs, ImageError, ImageReadSyncer, ImageSource,
+    VolumeImageSource,
+};
+pub use inclusion::{verify_inclusion_proof, ProofError};
 pub use merge::merge_verified_subtree;
 pub use noop::NoopReadSyncer;
 pub use proof::{Proof, ProofBuilder, ProofVerifier, RawProofEntry};
+pub use range::verify_range_proof;
+pub use shared::{SharedCacheReadSyncer, SharedNodeCache};
+pub use static_proof::ProofReadSyncer;
 pub use stats::StatsCollector;
+pub use verify::{BackgroundProofVerifier, PendingVerification, VerifiedSubtree};
 
 use std::any::Any;
 
 use anyhow::Result;
 
 use crate::{
-    common::crypto::hash::Hash,
+    common::crypto::{
+        hash::Hash,
+        signature::{PublicKey, SignatureBundle},
+    },
     storage::mkvs::{tree::Root, Prefix},
 };
 
+/// Signature context used by remote nodes to sign the proofs they serve.
+pub const PROOF_SOURCE_SIGNATURE_CONTEXT: &[u8] = b"oasis-core/storage: proof source";
+
 /// Identifies a specific tree and a position within that tree.
 #[derive(Clone, Debug, Default, PartialEq, Eq, cbor::Encode, cbor::Decode)]
 pub struct TreeID {
@@ -49,6 +78,16 @@ pub struct GetPrefixesRequest {
     pub limit: u16,
 }
 
+/// Request for the SyncGetPrefix operation.
+#[derive(Clone, Debug, Default, cbor::Encode, cbor::Decode)]
+pub struct GetPrefixRequest {
+    pub tree: TreeID,
+    pub prefix: Prefix,
+    /// Maximum size of the returned proof in bytes. Nodes that do not fit into the budget are
+    /// returned as hashes only and can be fetched on demand.
+    pub max_size: u64,
+}
+
 /// Request for the SyncIterate operation.
 #[derive(Clone, Debug, Default, cbor::Encode, cbor::Decode)]
 pub struct IterateRequest {
@@ -61,6 +100,27 @@ pub struct IterateRequest {
 #[derive(Clone, Debug, Default, cbor::Encode, cbor::Decode)]
 pub struct ProofResponse {
     pub proof: Proof,
+    /// Signature over the proof hash by the remote node which served the proof, if it was not
+    /// served by the host itself.
+    #[cbor(optional)]
+    pub source: Option<SignatureBundle>,
+}
+
+impl ProofResponse {
+    /// Hash of the encoded proof.
+    pub fn proof_hash(&self) -> Hash {
+        Hash::digest_bytes(&cbor::to_vec(self.proof.clone()))
+    }
+
+    /// Remote node which served the proof, if it signed the proof.
+    ///
+    /// As it is chosen by the host, the source is only known when the signature is valid.
+    pub fn verified_source(&self) -> Option<PublicKey> {
+        let source = self.source.as_ref()?;
+        source
+            .verify(PROOF_SOURCE_SIGNATURE_CONTEXT, self.proof_hash().as_ref())
+            .then_some(source.public_key)
+    }
 }
 
 /// ReadSync is the interface for synchronizing the in-memory cache
@@ -75,9 +135,31 @@ pub trait ReadSync {
     /// Fetch all keys under the given prefixes and returns the corresponding proofs.
     fn sync_get_prefixes(&mut self, request: GetPrefixesRequest) -> Result<ProofResponse>;
 
+    /// Fetch the entire subtree of keys under the given prefix, up to the given size budget,
+    /// and returns the corresponding proof.
+    ///
+    /// The default implementation falls back to fetching the prefix via `sync_get_prefixes`,
+    /// which does not honor the size budget.
+    fn sync_get_prefix(&mut self, request: GetPrefixRequest) -> Result<ProofResponse> {
+        self.sync_get_prefixes(GetPrefixesRequest {
+            tree: request.tree,
+            prefixes: vec![request.prefix],
+            limit: u16::MAX,
+        })
+    }
+
     /// Seek to a given key and then fetch the specified number of following items
     /// based on key iteration order.
     fn sync_iterate(&mut self, request: IterateRequest) -> Result<ProofResponse>;
+
+    /// Report that the proof returned by the last call failed verification and return the error
+    /// to surface to the caller.
+    ///
+    /// Read syncers fetching proofs from untrusted sources can use this to stop fetching from
+    /// the source that served the proof. The default implementation returns the error as is.
+    fn report_invalid_proof(&mut self, error: anyhow::Error) -> anyhow::Error {
+        error
+    }
 }
 
 #[cfg(test)]
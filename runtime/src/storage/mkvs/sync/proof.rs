@@ -14,9 +14,9 @@ use crate::{
 };
 
 /// Proof entry type for full nodes.
-const PROOF_ENTRY_FULL: u8 = 0x01;
+pub(super) const PROOF_ENTRY_FULL: u8 = 0x01;
 /// Proof entry type for subtree hashes.
-const PROOF_ENTRY_HASH: u8 = 0x02;
+pub(super) const PROOF_ENTRY_HASH: u8 = 0x02;
 
 // Min and max supported proof versions.
 const MIN_PROOF_VERSION: u16 = 0;
@@ -85,6 +85,13 @@ pub struct Proof {
     pub entries: Vec<Option<RawProofEntry>>,
 }
 
+impl Proof {
+    /// Total size of all proof entries in bytes.
+    pub fn size(&self) -> usize {
+        self.entries.iter().flatten().map(|entry| entry.len()).sum()
+    }
+}
+
 struct ProofNode {
     serialized: Vec<u8>,
     children: Vec<Hash>,
@@ -0,0 +1,52 @@
+//! Verification of range proofs.
+use anyhow::{anyhow, Result};
+
+use crate::{
+    common::crypto::hash::Hash,
+    storage::mkvs::{
+        self,
+        sync::{Proof, ProofReadSyncer},
+        tree::{Root, RootType, Tree},
+        Iterator as _,
+    },
+};
+
+/// Verify a proof produced by `Tree::iter_range` against the given root and return all entries
+/// with keys in `[start, end)`.
+///
+/// The proof is only accepted in case it contains all nodes needed to enumerate the range, so a
+/// valid proof guarantees that no entries in the range were omitted.
+pub fn verify_range_proof(
+    root: Hash,
+    start: &[u8],
+    end: Option<&[u8]>,
+    proof: Proof,
+) -> Result<Vec<(Vec<u8>, Vec<u8>)>> {
+    let tree = Tree::builder()
+        .with_capacity(0, 0)
+        .with_root(Root {
+            root_type: RootType::State,
+            hash: root,
+            ..Default::default()
+        })
+        .build(Box::new(ProofReadSyncer::new(proof)));
+
+    // Enumerate the range the same way as the prover, which fails in case any node is missing.
+    let mut it = tree.iter();
+    it.seek(start);
+    let mut entries = vec![];
+    while it.is_valid() {
+        let key = it.get_key().clone().expect("iterator is valid");
+        if end.is_some_and(|end| key.as_slice() >= end) {
+            break;
+        }
+        let value = it.get_value().clone().expect("iterator is valid");
+        entries.push((key, value));
+        mkvs::Iterator::next(&mut it);
+    }
+    if let Some(error) = it.error() {
+        return Err(anyhow!("mkvs: invalid range proof: {}", error));
+    }
+
+    Ok(entries)
+}
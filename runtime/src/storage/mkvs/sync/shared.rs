@@ -0,0 +1,234 @@
+//! Node cache shared between trees.
+//!
+//! Each tree has its own single-threaded node cache, so trees used by different threads (e.g. the
+//! per-thread query caches) would otherwise each have to fetch the same nodes from the host.
+//! Instead, their read syncers can be wrapped in a [`SharedCacheReadSyncer`], which keeps the
+//! nodes of all fetched proofs in a [`SharedNodeCache`] and answers requests for cached nodes
+//! without contacting the host.
+//!
+//! The shared cache is split into independently locked shards, selected by node hash, so that
+//! concurrent readers only contend when they access the same shard at the same time. Nodes are
+//! content-addressed and verified against their hash when loaded, so the cache never needs to
+//! be invalidated.
+use std::{
+    any::Any,
+    num::NonZeroUsize,
+    sync::{Arc, Mutex},
+};
+
+use anyhow::Result;
+
+use crate::{
+    common::crypto::hash::Hash,
+    storage::mkvs::{
+        sync::{
+            image::{collect_nodes, decode_node, single_node_proof},
+            GetPrefixRequest, GetPrefixesRequest, GetRequest, IterateRequest, ProofResponse,
+            ProofVerifier, ReadSync, TreeID,
+        },
+        tree::NodeBox,
+    },
+};
+
+/// Number of independently locked shards.
+const SHARD_COUNT: usize = 16;
+
+/// Sharded cache of serialized nodes, indexed by node hash.
+pub struct SharedNodeCache {
+    shards: Vec<Mutex<lru::LruCache<Hash, Arc<Vec<u8>>>>>,
+}
+
+impl SharedNodeCache {
+    /// Create a new shared cache holding at most (approximately) the given number of nodes.
+    pub fn new(capacity: NonZeroUsize) -> Self {
+        let shard_capacity = NonZeroUsize::new(capacity.get().div_ceil(SHARD_COUNT)).unwrap();
+        Self {
+            shards: (0..SHARD_COUNT)
+                .map(|_| Mutex::new(lru::LruCache::new(shard_capacity)))
+                .collect(),
+        }
+    }
+
+    /// Number of cached nodes.
+    pub fn len(&self) -> usize {
+        self.shards
+            .iter()
+            .map(|shard| shard.lock().unwrap().len())
+            .sum()
+    }
+
+    /// Whether the cache is empty.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Remove all cached nodes, returning the number of removed nodes.
+    pub fn clear(&self) -> usize {
+        self.shards
+            .iter()
+            .map(|shard| {
+                let mut shard = shard.lock().unwrap();
+                let len = shard.len();
+                shard.clear();
+                len
+            })
+            .sum()
+    }
+
+    /// Fetch and verify the node with the given hash.
+    fn get(&self, hash: &Hash) -> Option<NodeBox> {
+        // Release the lock before decoding.
+        let data = self.shard(hash).lock().unwrap().get(hash).cloned()?;
+        decode_node(hash, &data).ok()
+    }
+
+    /// Insert all nodes contained in the given proof response.
+    fn insert_proof(&self, response: &ProofResponse) {
+        let proof = &response.proof;
+        let Ok(subtree) = ProofVerifier.verify_proof(proof.untrusted_root, proof) else {
+            // Invalid proofs are rejected by the tree.
+            return;
+        };
+        let mut nodes = Vec::new();
+        if collect_nodes(&subtree, &mut nodes, true).is_err() {
+            return;
+        }
+
+        for (hash, data) in nodes {
+            self.shard(&hash).lock().unwrap().put(hash, Arc::new(data));
+        }
+    }
+
+    fn shard(&self, hash: &Hash) -> &Mutex<lru::LruCache<Hash, Arc<Vec<u8>>>> {
+        &self.shards[hash.as_ref()[0] as usize % SHARD_COUNT]
+    }
+}
+
+/// A read syncer which serves nodes from a shared node cache, falling back to the wrapped read
+/// syncer for nodes that are not cached.
+pub struct SharedCacheReadSyncer<R: ReadSync> {
+    inner: R,
+    cache: Arc<SharedNodeCache>,
+}
+
+impl<R: ReadSync> SharedCacheReadSyncer<R> {
+    /// Wrap the given read syncer.
+    pub fn new(inner: R, cache: Arc<SharedNodeCache>) -> Self {
+        Self { inner, cache }
+    }
+
+    fn serve<F>(&mut self, tree: &TreeID, fetch: F) -> Result<ProofResponse>
+    where
+        F: FnOnce(&mut R) -> Result<ProofResponse>,
+    {
+        let position = if tree.position.is_empty() {
+            tree.root.hash
+        } else {
+            tree.position
+        };
+        if let Some(node) = self.cache.get(&position) {
+            return single_node_proof(position, &node);
+        }
+
+        let response = fetch(&mut self.inner)?;
+        self.cache.insert_proof(&response);
+        Ok(response)
+    }
+}
+
+impl<R: ReadSync + 'static> ReadSync for SharedCacheReadSyncer<R> {
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn sync_get(&mut self, request: GetRequest) -> Result<ProofResponse> {
+        let tree = request.tree.clone();
+        self.serve(&tree, |inner| inner.sync_get(request))
+    }
+
+    fn sync_get_prefixes(&mut self, request: GetPrefixesRequest) -> Result<ProofResponse> {
+        let tree = request.tree.clone();
+        self.serve(&tree, |inner| inner.sync_get_prefixes(request))
+    }
+
+    fn sync_get_prefix(&mut self, request: GetPrefixRequest) -> Result<ProofResponse> {
+        let tree = request.tree.clone();
+        self.serve(&tree, |inner| inner.sync_get_prefix(request))
+    }
+
+    fn sync_iterate(&mut self, request: IterateRequest) -> Result<ProofResponse> {
+        let tree = request.tree.clone();
+        self.serve(&tree, |inner| inner.sync_iterate(request))
+    }
+
+    fn report_invalid_proof(&mut self, error: anyhow::Error) -> anyhow::Error {
+        // Proofs served from the shared cache are always valid.
+        self.inner.report_invalid_proof(error)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::storage::mkvs::{
+        sync::{build_image, ImageReadSyncer, StatsCollector},
+        RootType, Tree,
+    };
+
+    #[test]
+    fn test_shared_cache() {
+        let items: Vec<_> = (0..200u32)
+            .map(|i| {
+                (
+                    i.to_be_bytes().to_vec(),
+                    format!("value {}", i).into_bytes(),
+                )
+            })
+            .collect();
+        let mut image = Vec::new();
+        let root = build_image(
+            &mut image,
+            Default::default(),
+            1,
+            RootType::State,
+            items.clone(),
+        )
+        .unwrap();
+
+        let cache = Arc::new(SharedNodeCache::new(NonZeroUsize::new(10_000).unwrap()));
+        let build_tree = || {
+            let syncer = StatsCollector::new(Box::new(
+                ImageReadSyncer::open(image.clone(), root).unwrap(),
+            ));
+            Tree::builder()
+                .with_capacity(0, 0)
+                .with_root(root)
+                .build(Box::new(SharedCacheReadSyncer::new(syncer, cache.clone())))
+        };
+
+        // The first tree fetches all nodes from the wrapped syncer.
+        let tree = build_tree();
+        for (key, value) in &items {
+            assert_eq!(tree.get(key).unwrap().as_ref(), Some(value));
+        }
+        assert!(!cache.is_empty());
+
+        // Other trees are served from the shared cache.
+        let tree = build_tree();
+        for (key, value) in &items {
+            assert_eq!(tree.get(key).unwrap().as_ref(), Some(value));
+        }
+        let tree_cache = tree.cache.borrow();
+        let syncer = tree_cache
+            .get_read_syncer()
+            .as_any()
+            .downcast_ref::<SharedCacheReadSyncer<StatsCollector>>()
+            .unwrap();
+        assert_eq!(syncer.inner.sync_get_count, 0);
+
+        // Clearing the cache drops all nodes.
+        let len = cache.len();
+        assert_eq!(cache.clear(), len);
+        assert!(cache.is_empty());
+    }
+}
@@ -0,0 +1,53 @@
+use std::any::Any;
+
+use anyhow::{anyhow, Result};
+
+use crate::{
+    common::crypto::hash::Hash,
+    storage::mkvs::sync::{
+        GetPrefixesRequest, GetRequest, IterateRequest, Proof, ProofResponse, ReadSync,
+    },
+};
+
+/// A read syncer serving a single proof rooted at the tree root.
+///
+/// This allows lookups to be verified against a proof obtained out of band. Lookups of keys
+/// which are not covered by the proof fail.
+pub struct ProofReadSyncer {
+    proof: Proof,
+}
+
+impl ProofReadSyncer {
+    /// Create a new read syncer serving the given proof.
+    pub fn new(proof: Proof) -> Self {
+        Self { proof }
+    }
+
+    fn serve(&self, position: &Hash) -> Result<ProofResponse> {
+        if *position != self.proof.untrusted_root {
+            return Err(anyhow!("mkvs: node not included in proof"));
+        }
+        Ok(ProofResponse {
+            proof: self.proof.clone(),
+            source: None,
+        })
+    }
+}
+
+impl ReadSync for ProofReadSyncer {
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn sync_get(&mut self, request: GetRequest) -> Result<ProofResponse> {
+        self.serve(&request.tree.position)
+    }
+
+    fn sync_get_prefixes(&mut self, request: GetPrefixesRequest) -> Result<ProofResponse> {
+        self.serve(&request.tree.position)
+    }
+
+    fn sync_iterate(&mut self, request: IterateRequest) -> Result<ProofResponse> {
+        self.serve(&request.tree.position)
+    }
+}
@@ -2,7 +2,9 @@ use std::any::Any;
 
 use anyhow::Result;
 
-use super::{GetPrefixesRequest, GetRequest, IterateRequest, ProofResponse, ReadSync};
+use super::{
+    GetPrefixRequest, GetPrefixesRequest, GetRequest, IterateRequest, ProofResponse, ReadSync,
+};
 
 /// A proxy read syncer which keeps track of call statistics.
 pub struct StatsCollector {
@@ -10,6 +12,8 @@ pub struct StatsCollector {
     pub sync_get_count: usize,
     /// Count of `sync_get_prefixes` calls made to the underlying read syncer.
     pub sync_get_prefixes_count: usize,
+    /// Count of `sync_get_prefix` calls made to the underlying read syncer.
+    pub sync_get_prefix_count: usize,
     /// Count of `sync_iterate` calls made to the underlying read syncer.
     pub sync_iterate_count: usize,
 
@@ -22,6 +26,7 @@ impl StatsCollector {
         StatsCollector {
             sync_get_count: 0,
             sync_get_prefixes_count: 0,
+            sync_get_prefix_count: 0,
             sync_iterate_count: 0,
             rs,
         }
@@ -43,8 +48,17 @@ impl ReadSync for StatsCollector {
         self.rs.sync_get_prefixes(request)
     }
 
+    fn sync_get_prefix(&mut self, request: GetPrefixRequest) -> Result<ProofResponse> {
+        self.sync_get_prefix_count += 1;
+        self.rs.sync_get_prefix(request)
+    }
+
     fn sync_iterate(&mut self, request: IterateRequest) -> Result<ProofResponse> {
         self.sync_iterate_count += 1;
         self.rs.sync_iterate(request)
     }
+
+    fn report_invalid_proof(&mut self, error: anyhow::Error) -> anyhow::Error {
+        self.rs.report_invalid_proof(error)
+    }
 }
@@ -0,0 +1,259 @@
+use std::{
+    future::Future,
+    pin::Pin,
+    task::{Context, Poll},
+    thread,
+};
+
+use anyhow::{anyhow, Result};
+use crossbeam::channel;
+use futures::channel::oneshot;
+
+use crate::{
+    common::crypto::hash::Hash,
+    storage::mkvs::{
+        sync::{Proof, ProofVerifier},
+        tree::{Depth, InternalNode, Key, LeafNode, NodeBox, NodePointer, NodePtrRef, Value},
+    },
+};
+
+lazy_static! {
+    static ref GLOBAL_WORKER: BackgroundProofVerifier = BackgroundProofVerifier::new();
+}
+
+/// A verified subtree that can be sent between threads.
+///
+/// In-memory tree nodes are reference counted and cannot leave the thread that created them,
+/// so the background verifier hands back this representation which is then cheaply converted
+/// into tree nodes without recomputing any hashes.
+#[derive(Debug)]
+pub struct VerifiedSubtree(VerifiedNode);
+
+#[derive(Debug)]
+enum VerifiedNode {
+    Null,
+    Hash(Hash),
+    Internal {
+        hash: Hash,
+        label: Key,
+        label_bit_length: Depth,
+        leaf_node: Box<VerifiedNode>,
+        left: Box<VerifiedNode>,
+        right: Box<VerifiedNode>,
+    },
+    Leaf {
+        hash: Hash,
+        key: Key,
+        value: Value,
+    },
+}
+
+impl VerifiedNode {
+    fn from_ptr(ptr: &NodePtrRef) -> Self {
+        let ptr = ptr.borrow();
+        if ptr.is_null() {
+            return VerifiedNode::Null;
+        }
+        let node = match ptr.node {
+            Some(ref node) => node.borrow(),
+            None => return VerifiedNode::Hash(ptr.hash),
+        };
+
+        match *node {
+            NodeBox::Internal(ref n) => VerifiedNode::Internal {
+                hash: n.hash,
+                label: n.label.clone(),
+                label_bit_length: n.label_bit_length,
+                leaf_node: Box::new(Self::from_ptr(&n.leaf_node)),
+                left: Box::new(Self::from_ptr(&n.left)),
+                right: Box::new(Self::from_ptr(&n.right)),
+            },
+            NodeBox::Leaf(ref n) => VerifiedNode::Leaf {
+                hash: n.hash,
+                key: n.key.clone(),
+                value: n.value.clone(),
+            },
+        }
+    }
+
+    fn into_ptr(self) -> NodePtrRef {
+        let node = match self {
+            VerifiedNode::Null => return NodePointer::null_ptr(),
+            VerifiedNode::Hash(hash) => return NodePointer::hash_ptr(hash),
+            VerifiedNode::Internal {
+                hash,
+                label,
+                label_bit_length,
+                leaf_node,
+                left,
+                right,
+            } => NodeBox::Internal(InternalNode {
+                clean: true,
+                hash,
+                label,
+                label_bit_length,
+                leaf_node: leaf_node.into_ptr(),
+                left: left.into_ptr(),
+                right: right.into_ptr(),
+            }),
+            VerifiedNode::Leaf { hash, key, value } => NodeBox::Leaf(LeafNode {
+                clean: true,
+                hash,
+                key,
+                value,
+            }),
+        };
+
+        NodePointer::from_node(node)
+    }
+}
+
+impl VerifiedSubtree {
+    /// Convert the verified subtree into in-memory tree nodes.
+    pub fn into_node_ptr(self) -> NodePtrRef {
+        self.0.into_ptr()
+    }
+}
+
+/// Result of a proof verification performed in the background.
+///
+/// The verification result can either be awaited or waited for synchronously.
+pub struct PendingVerification {
+    rx: oneshot::Receiver<Result<VerifiedSubtree>>,
+}
+
+impl PendingVerification {
+    /// Block until the verification completes.
+    pub fn wait(self) -> Result<VerifiedSubtree> {
+        futures::executor::block_on(self)
+    }
+
+    /// Return the verification result in case the verification has already completed.
+    pub fn try_wait(&mut self) -> Option<Result<VerifiedSubtree>> {
+        match self.rx.try_recv() {
+            Ok(Some(result)) => Some(result),
+            Ok(None) => None,
+            Err(_) => Some(Err(anyhow!(
+                "verifier: background proof verifier terminated"
+            ))),
+        }
+    }
+}
+
+impl Future for PendingVerification {
+    type Output = Result<VerifiedSubtree>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        match Pin::new(&mut self.rx).poll(cx) {
+            Poll::Ready(Ok(result)) => Poll::Ready(result),
+            Poll::Ready(Err(_)) => Poll::Ready(Err(anyhow!(
+                "verifier: background proof verifier terminated"
+            ))),
+            Poll::Pending => Poll::Pending,
+        }
+    }
+}
+
+struct Job {
+    root: Hash,
+    proof: Proof,
+    tx: oneshot::Sender<Result<VerifiedSubtree>>,
+}
+
+/// A proof verifier that performs verification on a background thread.
+pub struct BackgroundProofVerifier {
+    tx: channel::Sender<Job>,
+}
+
+impl BackgroundProofVerifier {
+    fn new() -> Self {
+        let (tx, rx) = channel::unbounded::<Job>();
+
+        thread::Builder::new()
+            .name("mkvs-proof-verifier".to_string())
+            .spawn(move || {
+                while let Ok(job) = rx.recv() {
+                    let result = ProofVerifier
+                        .verify_proof(job.root, &job.proof)
+                        .map(|subtree| VerifiedSubtree(VerifiedNode::from_ptr(&subtree)));
+                    // The receiver may have been dropped in the meantime, which is fine.
+                    let _ = job.tx.send(result);
+                }
+            })
+            .expect("failed to spawn proof verifier thread");
+
+        Self { tx }
+    }
+
+    /// Shared background proof verifier instance.
+    pub fn global() -> &'static Self {
+        &GLOBAL_WORKER
+    }
+
+    /// Queue the proof for verification against the given root.
+    pub fn verify(&self, root: Hash, proof: Proof) -> PendingVerification {
+        let (tx, rx) = oneshot::channel();
+        let job = Job { root, proof, tx };
+        if let Err(channel::SendError(job)) = self.tx.send(job) {
+            let _ = job.tx.send(Err(anyhow!(
+                "verifier: background proof verifier terminated"
+            )));
+        }
+
+        PendingVerification { rx }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::storage::mkvs::{
+        cache::Cache,
+        sync::{NoopReadSyncer, ProofBuilder},
+        tree::{RootType, Tree},
+    };
+
+    #[test]
+    fn test_background_verification() {
+        let mut tree = Tree::builder()
+            .with_capacity(0, 0)
+            .with_root_type(RootType::State)
+            .build(Box::new(NoopReadSyncer));
+        for i in 0..20u8 {
+            tree.insert(&[b'k', i], &[i; 8]).unwrap();
+        }
+        let root_hash = tree.commit(Default::default(), 0).unwrap();
+
+        // Build a proof containing the whole tree.
+        let mut pb = ProofBuilder::new(root_hash);
+        let mut stack = vec![tree.cache.borrow().get_pending_root()];
+        while let Some(ptr) = stack.pop() {
+            let ptr = ptr.borrow();
+            if let Some(ref node) = ptr.node {
+                pb.include(&node.borrow());
+                if let NodeBox::Internal(ref n) = *node.borrow() {
+                    stack.push(n.leaf_node.clone());
+                    stack.push(n.left.clone());
+                    stack.push(n.right.clone());
+                }
+            }
+        }
+        let proof = pb.build();
+
+        let verifier = BackgroundProofVerifier::global();
+        let subtree = verifier
+            .verify(root_hash, proof.clone())
+            .wait()
+            .expect("verification should succeed");
+        let ptr = subtree.into_node_ptr();
+        assert_eq!(ptr.borrow().hash, root_hash);
+
+        // The reconstructed subtree must match the synchronously verified one.
+        let expected = ProofVerifier.verify_proof(root_hash, &proof).unwrap();
+        assert_eq!(*ptr.borrow(), *expected.borrow());
+
+        // Verification against the wrong root must fail.
+        let result = futures::executor::block_on(verifier.verify(Hash::empty_hash(), proof));
+        assert!(result.is_err());
+    }
+}
@@ -0,0 +1,296 @@
+//! Lazy prefix deletes using tombstones.
+//!
+//! Removing all keys under a large prefix in a single round makes that round pay for the entire
+//! deletion at commit time. Instead, a lazy delete only writes a tombstone for the prefix, which
+//! immediately hides all keys under it. The keys are then removed by calling
+//! [`LazyDeleteTree::compact`] in subsequent rounds, which removes at most a bounded number of
+//! keys per round.
+//!
+//! Tombstones are stored in the tree itself, under a reserved key prefix, so that all replicas
+//! observe the same set of pending deletions. Keys under the reserved prefix are never deleted.
+use std::collections::BTreeSet;
+
+use thiserror::Error;
+
+use super::MKVS;
+
+/// Lazy delete errors.
+#[derive(Error, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LazyDeleteError {
+    #[error("key uses the reserved tombstone prefix")]
+    ReservedKey,
+
+    #[error("more than {0} keys pending deletion under the key")]
+    CompactionPending(usize),
+}
+
+/// Tree wrapper supporting lazy prefix deletes.
+pub struct LazyDeleteTree<M: MKVS> {
+    inner: M,
+    tombstone_prefix: Vec<u8>,
+    tombstones: BTreeSet<Vec<u8>>,
+    insert_limit: usize,
+}
+
+impl<M: MKVS> LazyDeleteTree<M> {
+    /// Wrap the given tree, storing tombstones under the given reserved key prefix.
+    ///
+    /// Inserting a key under a pending tombstone removes at most `insert_limit` keys under that
+    /// tombstone, see [`Self::insert`].
+    pub fn new(inner: M, tombstone_prefix: &[u8], insert_limit: usize) -> Self {
+        let tombstones = keys_with_prefix(&inner, tombstone_prefix, usize::MAX)
+            .into_iter()
+            .map(|key| key[tombstone_prefix.len()..].to_vec())
+            .collect();
+
+        Self {
+            inner,
+            tombstone_prefix: tombstone_prefix.to_vec(),
+            tombstones,
+            insert_limit,
+        }
+    }
+
+    /// The wrapped tree.
+    ///
+    /// Reads from the wrapped tree don't hide keys under pending tombstones.
+    pub fn inner(&mut self) -> &mut M {
+        &mut self.inner
+    }
+
+    /// Unwrap the tree.
+    pub fn into_inner(self) -> M {
+        self.inner
+    }
+
+    /// Prefixes with pending deletions.
+    pub fn pending(&self) -> impl std::iter::Iterator<Item = &[u8]> {
+        self.tombstones.iter().map(|prefix| prefix.as_slice())
+    }
+
+    /// Whether the given key is hidden by a pending tombstone.
+    pub fn is_deleted(&self, key: &[u8]) -> bool {
+        self.covering_tombstone(key).is_some()
+    }
+
+    /// Fetch entry with given key.
+    pub fn get(&self, key: &[u8]) -> Option<Vec<u8>> {
+        if self.is_deleted(key) {
+            return None;
+        }
+        self.inner.get(key)
+    }
+
+    /// Update entry with given key.
+    ///
+    /// Inserting a key under a pending tombstone first removes all keys under that tombstone.
+    /// Fails without modifying the tree in case more keys than the configured insert limit
+    /// remain under the tombstone, in which case the caller should retry after compacting.
+    pub fn insert(&mut self, key: &[u8], value: &[u8]) -> Result<Option<Vec<u8>>, LazyDeleteError> {
+        self.check_not_reserved(key)?;
+        if let Some(prefix) = self.covering_tombstone(key).map(|prefix| prefix.to_vec()) {
+            let pending = self.keys_under(&prefix, self.insert_limit.saturating_add(1));
+            if pending.len() > self.insert_limit {
+                return Err(LazyDeleteError::CompactionPending(self.insert_limit));
+            }
+            self.compact_prefix(&prefix, self.insert_limit);
+        }
+        Ok(self.inner.insert(key, value))
+    }
+
+    /// Remove entry with given key, returning the value at the key if the key was previously
+    /// in the database.
+    pub fn remove(&mut self, key: &[u8]) -> Result<Option<Vec<u8>>, LazyDeleteError> {
+        self.check_not_reserved(key)?;
+        if self.is_deleted(key) {
+            return Ok(None);
+        }
+        Ok(self.inner.remove(key))
+    }
+
+    /// Lazily remove all keys starting with the given prefix.
+    ///
+    /// The keys are hidden immediately and removed by later calls to [`Self::compact`]. An
+    /// empty prefix removes all keys except the tombstones themselves.
+    ///
+    /// Fails in case the prefix starts with the reserved tombstone prefix.
+    pub fn remove_prefix(&mut self, prefix: &[u8]) -> Result<(), LazyDeleteError> {
+        self.check_not_reserved(prefix)?;
+        if self.covering_tombstone(prefix).is_some() {
+            return Ok(());
+        }
+
+        // Tombstones for longer prefixes are superseded by the new one.
+        let superseded: Vec<_> = self
+            .tombstones
+            .iter()
+            .filter(|other| other.starts_with(prefix))
+            .cloned()
+            .collect();
+        for other in superseded {
+            self.remove_tombstone(&other);
+        }
+
+        let key = self.tombstone_key(prefix);
+        self.inner.insert(&key, &[]);
+        self.tombstones.insert(prefix.to_vec());
+        Ok(())
+    }
+
+    /// Remove at most `limit` keys under pending tombstones, dropping the tombstones of prefixes
+    /// that have been fully removed.
+    ///
+    /// Returns the number of removed keys.
+    pub fn compact(&mut self, limit: usize) -> usize {
+        let mut removed = 0;
+        let prefixes: Vec<_> = self.tombstones.iter().cloned().collect();
+        for prefix in prefixes {
+            if removed >= limit {
+                break;
+            }
+            removed += self.compact_prefix(&prefix, limit - removed);
+        }
+        removed
+    }
+
+    /// Remove at most `limit` keys under the given tombstoned prefix, dropping the tombstone in
+    /// case no keys remain.
+    fn compact_prefix(&mut self, prefix: &[u8], limit: usize) -> usize {
+        // Fetch one extra key to learn whether any keys remain after this batch.
+        let mut keys = self.keys_under(prefix, limit.saturating_add(1));
+        let done = keys.len() <= limit;
+        keys.truncate(limit);
+
+        for key in &keys {
+            self.inner.remove(key);
+        }
+        if done {
+            self.remove_tombstone(prefix);
+        }
+        keys.len()
+    }
+
+    fn remove_tombstone(&mut self, prefix: &[u8]) {
+        let key = self.tombstone_key(prefix);
+        self.inner.remove(&key);
+        self.tombstones.remove(prefix);
+    }
+
+    /// Collect at most `limit` keys under the given tombstoned prefix, skipping reserved keys.
+    fn keys_under(&self, prefix: &[u8], limit: usize) -> Vec<Vec<u8>> {
+        let mut it = self.inner.iter();
+        it.seek(prefix);
+        it.map(|(key, _)| key)
+            .take_while(|key| key.starts_with(prefix))
+            .filter(|key| !key.starts_with(&self.tombstone_prefix))
+            .take(limit)
+            .collect()
+    }
+
+    fn covering_tombstone(&self, key: &[u8]) -> Option<&[u8]> {
+        // Tombstones never cover each other, so at most one of them can be a prefix of the key
+        // and it must be the largest tombstone not greater than the key.
+        self.tombstones
+            .range::<[u8], _>(..=key)
+            .next_back()
+            .filter(|prefix| key.starts_with(prefix))
+            .map(|prefix| prefix.as_slice())
+    }
+
+    fn tombstone_key(&self, prefix: &[u8]) -> Vec<u8> {
+        [&self.tombstone_prefix[..], prefix].concat()
+    }
+
+    fn check_not_reserved(&self, key: &[u8]) -> Result<(), LazyDeleteError> {
+        if key.starts_with(&self.tombstone_prefix) {
+            return Err(LazyDeleteError::ReservedKey);
+        }
+        Ok(())
+    }
+}
+
+/// Collect at most `limit` keys starting with the given prefix.
+fn keys_with_prefix<M: MKVS + ?Sized>(mkvs: &M, prefix: &[u8], limit: usize) -> Vec<Vec<u8>> {
+    let mut it = mkvs.iter();
+    it.seek(prefix);
+    it.take_while(|(key, _)| key.starts_with(prefix))
+        .take(limit)
+        .map(|(key, _)| key)
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::storage::mkvs::{sync::NoopReadSyncer, OverlayTree, RootType, Tree};
+
+    const TOMBSTONES: &[u8] = b"\xfftombstone/";
+
+    #[test]
+    fn test_lazy_delete() {
+        let tree = Tree::builder()
+            .with_root_type(RootType::State)
+            .build(Box::new(NoopReadSyncer));
+        let mut tree = LazyDeleteTree::new(OverlayTree::new(tree), TOMBSTONES, 5);
+
+        for i in 0..10u8 {
+            tree.insert(&[b'a', i], b"value").unwrap();
+            tree.insert(&[b'b', i], b"value").unwrap();
+        }
+
+        tree.remove_prefix(b"a").unwrap();
+        // Covered tombstones are ignored.
+        tree.remove_prefix(b"a\x01").unwrap();
+        assert_eq!(tree.pending().collect::<Vec<_>>(), vec![b"a".as_slice()]);
+        assert_eq!(tree.get(b"a\x01"), None);
+        assert_eq!(tree.remove(b"a\x01"), Ok(None));
+        assert_eq!(tree.get(b"b\x01"), Some(b"value".to_vec()));
+        // Keys remain in the tree until compacted.
+        assert_eq!(tree.inner().get(b"a\x01"), Some(b"value".to_vec()));
+
+        // Tombstones persist in the tree.
+        let mut tree = LazyDeleteTree::new(tree.into_inner(), TOMBSTONES, 5);
+        assert!(tree.is_deleted(b"a\x05"));
+
+        assert_eq!(tree.compact(4), 4);
+        assert_eq!(tree.inner().get(b"a\x03"), None);
+        assert_eq!(tree.inner().get(b"a\x04"), Some(b"value".to_vec()));
+        assert_eq!(tree.compact(4), 4);
+        assert_eq!(tree.pending().count(), 1);
+        assert_eq!(tree.compact(4), 2);
+        assert_eq!(tree.pending().count(), 0);
+        assert_eq!(tree.compact(4), 0);
+        assert_eq!(tree.inner().get(&[TOMBSTONES, &b"a"[..]].concat()), None);
+
+        // Inserting under a tombstone removes the remaining keys first, as long as they are
+        // within the insert limit.
+        tree.remove_prefix(b"b").unwrap();
+        assert_eq!(
+            tree.insert(b"b\x01", b"new"),
+            Err(LazyDeleteError::CompactionPending(5))
+        );
+        assert_eq!(tree.inner().get(b"b\x09"), Some(b"value".to_vec()));
+        assert_eq!(tree.compact(5), 5);
+        tree.insert(b"b\x01", b"new").unwrap();
+        assert_eq!(tree.pending().count(), 0);
+        assert_eq!(tree.get(b"b\x01"), Some(b"new".to_vec()));
+        assert_eq!(tree.get(b"b\x02"), None);
+
+        // An empty prefix removes all keys except the tombstones.
+        tree.remove_prefix(b"").unwrap();
+        assert_eq!(tree.pending().collect::<Vec<_>>(), vec![b"".as_slice()]);
+        assert_eq!(tree.get(b"b\x01"), None);
+        assert_eq!(tree.compact(10), 1);
+        assert_eq!(tree.pending().count(), 0);
+
+        // Reserved keys are rejected.
+        assert_eq!(
+            tree.remove_prefix(TOMBSTONES),
+            Err(LazyDeleteError::ReservedKey)
+        );
+        assert_eq!(
+            tree.insert(&[TOMBSTONES, &b"a"[..]].concat(), b"value"),
+            Err(LazyDeleteError::ReservedKey)
+        );
+    }
+}
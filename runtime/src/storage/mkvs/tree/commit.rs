@@ -1,20 +1,244 @@
+use std::{collections::HashMap, mem, thread};
+
 use anyhow::Result;
 
 use crate::{
     common::{crypto::hash::Hash, namespace::Namespace},
     storage::mkvs::{
         cache::{Cache, LRUCache, UpdateList},
-        tree::{Node, NodeBox, NodeKind, NodePtrRef, Root, Tree},
+        tree::{
+            profile::{Operation, Span},
+            Depth, InternalNode, Key, LeafNode, Node, NodeBox, NodeKind, NodePtrRef, NodeRef, Root,
+            Tree, Value,
+        },
     },
 };
 
+/// Minimum number of dirty nodes in both child subtrees of an internal node for the subtrees to
+/// be hashed by separate workers.
+const MIN_PARALLEL_SUBTREE_SIZE: usize = 64;
+
+/// Statistics about the work performed during a commit.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct CommitStats {
+    /// Number of leaf nodes that were hashed.
+    pub hashed_leaves: usize,
+    /// Number of internal nodes that were hashed.
+    pub hashed_internal_nodes: usize,
+    /// Number of dirty internal nodes whose hash was served from the hash cache.
+    pub memoized_internal_nodes: usize,
+}
+
+/// Version of the subtree rooted at an internal node.
+///
+/// Child hashes uniquely identify the versions of the child subtrees, so together with the
+/// node's label they identify the version of the whole subtree and thus determine its hash.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+struct SubtreeVersion {
+    label: Key,
+    label_bit_length: Depth,
+    leaf_node: Hash,
+    left: Hash,
+    right: Hash,
+}
+
+impl SubtreeVersion {
+    fn new(node: &InternalNode) -> Self {
+        Self {
+            label: node.label.clone(),
+            label_bit_length: node.label_bit_length,
+            leaf_node: node.leaf_node.borrow().hash,
+            left: node.left.borrow().hash,
+            right: node.right.borrow().hash,
+        }
+    }
+}
+
+/// Cache of internal node hashes keyed by subtree version, retained across commits.
+///
+/// This avoids rehashing internal nodes that were modified but ended up in a previously seen
+/// state (e.g. a key that was inserted and later removed again). The cache holds at most
+/// `capacity` entries, evicting the least recently used generation of entries first.
+pub(crate) struct HashCache {
+    capacity: usize,
+    current: HashMap<SubtreeVersion, Hash>,
+    previous: HashMap<SubtreeVersion, Hash>,
+}
+
+impl HashCache {
+    /// Create a new hash cache. A zero capacity disables the cache.
+    pub(crate) fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            current: HashMap::new(),
+            previous: HashMap::new(),
+        }
+    }
+
+    fn get(&mut self, version: &SubtreeVersion) -> Option<Hash> {
+        if let Some(hash) = self.current.get(version) {
+            return Some(*hash);
+        }
+        // Promote entries from the previous generation so they are retained.
+        let hash = self.previous.remove(version)?;
+        self.insert(version.clone(), hash);
+        Some(hash)
+    }
+
+    fn insert(&mut self, version: SubtreeVersion, hash: Hash) {
+        if self.capacity == 0 {
+            return;
+        }
+        if self.current.len() >= self.capacity.div_ceil(2) {
+            self.previous = mem::take(&mut self.current);
+        }
+        self.current.insert(version, hash);
+    }
+}
+
+/// Owned copy of the dirty part of a tree, which can be hashed without access to the tree.
+///
+/// The tree itself is not thread-safe, so the dirty nodes are copied out of the tree, hashed by
+/// a number of workers and the resulting hashes are then assigned back to the tree.
+enum Plan {
+    /// Subtree with a known hash.
+    Clean(Hash),
+    /// Dirty leaf node.
+    Leaf { key: Key, value: Value, hash: Hash },
+    /// Dirty internal node.
+    Internal {
+        label: Key,
+        label_bit_length: Depth,
+        leaf_node: Box<Plan>,
+        left: Box<Plan>,
+        right: Box<Plan>,
+        /// Number of dirty nodes in the subtree.
+        size: usize,
+        hash: Hash,
+    },
+}
+
+impl Plan {
+    /// Copy the dirty part of the subtree behind the given pointer.
+    fn build(ptr: &NodePtrRef) -> Self {
+        let ptr = ptr.borrow();
+        if ptr.clean {
+            return Plan::Clean(ptr.hash);
+        }
+        let node_ref = match ptr.node {
+            Some(ref node_ref) => node_ref,
+            None => return Plan::Clean(Hash::empty_hash()),
+        };
+
+        let plan = match *node_ref.borrow() {
+            NodeBox::Internal(ref n) if n.clean => Plan::Clean(n.hash),
+            NodeBox::Internal(ref n) => {
+                let leaf_node = Box::new(Plan::build(&n.leaf_node));
+                let left = Box::new(Plan::build(&n.left));
+                let right = Box::new(Plan::build(&n.right));
+                Plan::Internal {
+                    label: n.label.clone(),
+                    label_bit_length: n.label_bit_length,
+                    size: 1 + leaf_node.size() + left.size() + right.size(),
+                    leaf_node,
+                    left,
+                    right,
+                    hash: Hash::default(),
+                }
+            }
+            NodeBox::Leaf(ref n) if n.clean => Plan::Clean(n.hash),
+            NodeBox::Leaf(ref n) => Plan::Leaf {
+                key: n.key.clone(),
+                value: n.value.clone(),
+                hash: Hash::default(),
+            },
+        };
+        plan
+    }
+
+    /// Number of dirty nodes in the subtree.
+    fn size(&self) -> usize {
+        match self {
+            Plan::Clean(_) => 0,
+            Plan::Leaf { .. } => 1,
+            Plan::Internal { size, .. } => *size,
+        }
+    }
+
+    /// Hash of the subtree, once computed.
+    fn hash(&self) -> Hash {
+        match self {
+            Plan::Clean(hash) | Plan::Leaf { hash, .. } | Plan::Internal { hash, .. } => *hash,
+        }
+    }
+
+    /// Compute the hashes of all dirty nodes in the subtree, splitting the work between
+    /// separate workers up to `splits` levels deep.
+    ///
+    /// Hashes only depend on the contents of the subtree, so the result does not depend on the
+    /// number of workers.
+    fn compute(&mut self, splits: u32) {
+        match self {
+            Plan::Clean(_) => {}
+            Plan::Leaf { key, value, hash } => {
+                *hash = LeafNode::compute_hash(key, value);
+            }
+            Plan::Internal {
+                label,
+                label_bit_length,
+                leaf_node,
+                left,
+                right,
+                hash,
+                ..
+            } => {
+                leaf_node.compute(0);
+                if splits > 0
+                    && left.size() >= MIN_PARALLEL_SUBTREE_SIZE
+                    && right.size() >= MIN_PARALLEL_SUBTREE_SIZE
+                {
+                    thread::scope(|s| {
+                        s.spawn(|| left.compute(splits - 1));
+                        right.compute(splits - 1);
+                    });
+                } else {
+                    left.compute(splits);
+                    right.compute(splits);
+                }
+
+                *hash = InternalNode::compute_hash(
+                    label,
+                    *label_bit_length,
+                    &leaf_node.hash(),
+                    &left.hash(),
+                    &right.hash(),
+                );
+            }
+        }
+    }
+}
+
 impl Tree {
     /// Commit tree updates to the underlying database and return
     /// the write log and new merkle root.
     pub fn commit(&mut self, namespace: Namespace, version: u64) -> Result<Hash> {
         let mut update_list: UpdateList<LRUCache> = UpdateList::new();
         let pending_root = self.cache.borrow().get_pending_root();
-        let new_hash = _commit(pending_root, &mut update_list)?;
+
+        // Hash independent dirty subtrees in parallel when multiple workers are configured.
+        let plan = (self.commit_workers > 1).then(|| {
+            let mut plan = Plan::build(&pending_root);
+            plan.compute(self.commit_workers.ilog2());
+            plan
+        });
+
+        let mut committer = Committer {
+            update_list: &mut update_list,
+            hash_cache: &mut self.hash_cache,
+            stats: CommitStats::default(),
+        };
+        let new_hash = committer.commit(pending_root, plan.as_ref())?;
+        let stats = committer.stats;
 
         update_list.commit(&mut self.cache.borrow_mut());
 
@@ -24,62 +248,128 @@ impl Tree {
             root_type: self.root_type,
             hash: new_hash,
         });
+        self.commit_stats = stats;
 
         Ok(new_hash)
     }
-}
 
-pub fn _commit<C: Cache>(ptr: NodePtrRef, update_list: &mut UpdateList<C>) -> Result<Hash> {
-    if ptr.borrow().clean {
-        return Ok(ptr.borrow().hash);
+    /// Statistics about the work performed during the last commit.
+    pub fn commit_stats(&self) -> CommitStats {
+        self.commit_stats
     }
+}
+
+struct Committer<'a, C: Cache> {
+    update_list: &'a mut UpdateList<C>,
+    hash_cache: &'a mut HashCache,
+    stats: CommitStats,
+}
 
-    match classify_noderef!(? ptr.borrow().node) {
-        NodeKind::None => {
-            ptr.borrow_mut().hash = Hash::empty_hash();
+impl<C: Cache> Committer<'_, C> {
+    /// Commit the subtree behind the given pointer, using the hashes computed by the given plan
+    /// if any.
+    fn commit(&mut self, ptr: NodePtrRef, plan: Option<&Plan>) -> Result<Hash> {
+        if ptr.borrow().clean {
+            return Ok(ptr.borrow().hash);
         }
-        NodeKind::Internal => {
-            let some_node_ref = ptr.borrow().get_node();
-            if some_node_ref.borrow().is_clean() {
-                ptr.borrow_mut().hash = some_node_ref.borrow().get_hash();
-            } else {
-                let int_leaf_node = noderef_as!(some_node_ref, Internal).leaf_node.clone();
-                let int_left = noderef_as!(some_node_ref, Internal).left.clone();
-                let int_right = noderef_as!(some_node_ref, Internal).right.clone();
-
-                _commit(int_leaf_node, update_list)?;
-                _commit(int_left, update_list)?;
-                _commit(int_right, update_list)?;
-
-                some_node_ref.borrow_mut().update_hash();
-                ptr.borrow_mut().hash = some_node_ref.borrow().get_hash();
-
-                update_list.push(Box::new(move |_| {
-                    noderef_as_mut!(some_node_ref, Internal).clean = true
-                }));
+        let _span = Span::enter(Operation::Commit);
+
+        match classify_noderef!(? ptr.borrow().node) {
+            NodeKind::None => {
+                ptr.borrow_mut().hash = Hash::empty_hash();
             }
-        }
-        NodeKind::Leaf => {
-            let node_ref = ptr.borrow().get_node();
-            if node_ref.borrow().is_clean() {
-                ptr.borrow_mut().hash = node_ref.borrow().get_hash();
-            } else {
-                node_ref.borrow_mut().update_hash();
-                ptr.borrow_mut().hash = node_ref.borrow().get_hash();
-
-                update_list.push(Box::new(move |_| {
-                    noderef_as_mut!(node_ref, Leaf).clean = true
-                }));
+            NodeKind::Internal => {
+                let some_node_ref = ptr.borrow().get_node();
+                if some_node_ref.borrow().is_clean() {
+                    ptr.borrow_mut().hash = some_node_ref.borrow().get_hash();
+                } else {
+                    let int_leaf_node = noderef_as!(some_node_ref, Internal).leaf_node.clone();
+                    let int_left = noderef_as!(some_node_ref, Internal).left.clone();
+                    let int_right = noderef_as!(some_node_ref, Internal).right.clone();
+
+                    let (leaf_node_plan, left_plan, right_plan) = match plan {
+                        Some(Plan::Internal {
+                            leaf_node,
+                            left,
+                            right,
+                            ..
+                        }) => (Some(&**leaf_node), Some(&**left), Some(&**right)),
+                        _ => (None, None, None),
+                    };
+                    self.commit(int_leaf_node, leaf_node_plan)?;
+                    self.commit(int_left, left_plan)?;
+                    self.commit(int_right, right_plan)?;
+
+                    self.update_internal_hash(&some_node_ref, plan.map(Plan::hash));
+                    ptr.borrow_mut().hash = some_node_ref.borrow().get_hash();
+
+                    self.update_list.push(Box::new(move |_| {
+                        noderef_as_mut!(some_node_ref, Internal).clean = true
+                    }));
+                }
             }
-        }
-    };
+            NodeKind::Leaf => {
+                let node_ref = ptr.borrow().get_node();
+                if node_ref.borrow().is_clean() {
+                    ptr.borrow_mut().hash = node_ref.borrow().get_hash();
+                } else {
+                    match plan {
+                        Some(plan) => noderef_as_mut!(node_ref, Leaf).hash = plan.hash(),
+                        None => node_ref.borrow_mut().update_hash(),
+                    }
+                    self.stats.hashed_leaves += 1;
+                    ptr.borrow_mut().hash = node_ref.borrow().get_hash();
 
-    let closure_ptr = ptr.clone();
-    update_list.push(Box::new(move |cache| {
-        closure_ptr.borrow_mut().clean = true;
-        // Make node eligible for eviction.
-        cache.commit_node(closure_ptr.clone());
-    }));
+                    self.update_list.push(Box::new(move |_| {
+                        noderef_as_mut!(node_ref, Leaf).clean = true
+                    }));
+                }
+            }
+        };
+
+        let closure_ptr = ptr.clone();
+        self.update_list.push(Box::new(move |cache| {
+            closure_ptr.borrow_mut().clean = true;
+            // Make node eligible for eviction.
+            cache.commit_node(closure_ptr.clone());
+        }));
+
+        Ok(ptr.borrow().hash)
+    }
 
-    Ok(ptr.borrow().hash)
+    /// Update the hash of a dirty internal node whose children have already been committed,
+    /// unless it has already been computed.
+    fn update_internal_hash(&mut self, node_ref: &NodeRef, computed: Option<Hash>) {
+        let mut node = node_ref.borrow_mut();
+        let node = match *node {
+            NodeBox::Internal(ref mut n) => n,
+            _ => unreachable!("node must be an internal node"),
+        };
+        if let Some(hash) = computed {
+            node.hash = hash;
+            self.stats.hashed_internal_nodes += 1;
+            if self.hash_cache.capacity > 0 {
+                self.hash_cache.insert(SubtreeVersion::new(node), hash);
+            }
+            return;
+        }
+        if self.hash_cache.capacity == 0 {
+            node.update_hash();
+            self.stats.hashed_internal_nodes += 1;
+            return;
+        }
+
+        let version = SubtreeVersion::new(node);
+        match self.hash_cache.get(&version) {
+            Some(hash) => {
+                node.hash = hash;
+                self.stats.memoized_internal_nodes += 1;
+            }
+            None => {
+                node.update_hash();
+                self.stats.hashed_internal_nodes += 1;
+                self.hash_cache.insert(version, node.hash);
+            }
+        }
+    }
 }
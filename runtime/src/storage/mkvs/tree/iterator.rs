@@ -1,13 +1,13 @@
 //! Tree iterator.
 use std::{collections::VecDeque, fmt};
 
-use anyhow::{Error, Result};
+use anyhow::{anyhow, Error, Result};
 
 use crate::storage::mkvs::{
     self,
     cache::{Cache, ReadSyncFetcher},
-    sync::{IterateRequest, Proof, ReadSync, TreeID},
-    tree::{Depth, Key, KeyTrait, NodeBox, NodeKind, NodePtrRef, Root, Tree},
+    sync::{IterateRequest, Proof, ProofBuilder, ReadSync, TreeID},
+    tree::{Depth, Key, KeyTrait, Node, NodeBox, NodeKind, NodePtrRef, Root, Tree},
 };
 
 pub(super) struct FetcherSyncIterate<'a> {
@@ -71,6 +71,8 @@ pub struct TreeIterator<'tree> {
     key: Option<Key>,
     value: Option<Vec<u8>>,
     error: Option<Error>,
+    /// Builder of a proof of all visited nodes, if enabled.
+    proof_builder: Option<ProofBuilder>,
 }
 
 impl<'tree> TreeIterator<'tree> {
@@ -83,9 +85,30 @@ impl<'tree> TreeIterator<'tree> {
             key: None,
             value: None,
             error: None,
+            proof_builder: None,
         }
     }
 
+    /// Include the given node in the proof, if enabled.
+    fn include_in_proof(&mut self, node: &NodeBox) -> Result<()> {
+        let Some(ref mut proof_builder) = self.proof_builder else {
+            return Ok(());
+        };
+        if !node.is_clean() {
+            return Err(anyhow!("mkvs: range proofs require a committed tree"));
+        }
+        proof_builder.include(node);
+
+        // The leaf node of an internal node is needed to verify the internal node even in case
+        // the iteration doesn't visit it.
+        if let NodeBox::Internal(ref n) = node {
+            if let Some(ref leaf) = n.leaf_node.borrow().node {
+                proof_builder.include(&leaf.borrow());
+            }
+        }
+        Ok(())
+    }
+
     fn reset(&mut self) {
         self.pos.clear();
         self.key = None;
@@ -148,6 +171,9 @@ impl<'tree> TreeIterator<'tree> {
             ptr.clone(),
             Some(FetcherSyncIterate::new(&key, self.prefetch)),
         )?;
+        if let Some(ref node_ref) = node_ref {
+            self.include_in_proof(&node_ref.borrow())?;
+        }
 
         match classify_noderef!(?node_ref) {
             NodeKind::None => {
@@ -338,11 +364,71 @@ impl mkvs::Iterator for TreeIterator<'_> {
     }
 }
 
+/// Iterator over a key range, which also produces a proof of the returned entries.
+///
+/// The proof covers all nodes visited during iteration, including the entry just past the end
+/// of the range, so that a verifier can check that no entries in the range were omitted.
+pub struct RangeIterator<'tree> {
+    inner: TreeIterator<'tree>,
+    end: Option<Vec<u8>>,
+}
+
+impl RangeIterator<'_> {
+    /// Iterate over the rest of the range and return the proof covering the whole range.
+    ///
+    /// The proof can be verified using `verify_range_proof`.
+    pub fn proof(mut self) -> Result<Proof> {
+        while Iterator::next(&mut self).is_some() {}
+        if let Some(error) = self.inner.error.take() {
+            return Err(error);
+        }
+
+        Ok(self
+            .inner
+            .proof_builder
+            .as_ref()
+            .expect("range iterator should build a proof")
+            .build())
+    }
+
+    /// Error encountered during iteration, if any.
+    pub fn error(&self) -> &Option<Error> {
+        &self.inner.error
+    }
+}
+
+impl Iterator for RangeIterator<'_> {
+    type Item = (Vec<u8>, Vec<u8>);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        match (&self.inner.key, &self.end) {
+            (Some(key), Some(end)) if key >= end => None,
+            _ => Iterator::next(&mut self.inner),
+        }
+    }
+}
+
 impl Tree {
     /// Return an iterator over the tree.
     pub fn iter(&self) -> TreeIterator {
         TreeIterator::new(self)
     }
+
+    /// Return an iterator over all entries with keys in `[start, end)`, which also produces a
+    /// proof of the range. In case `end` is not set, the range extends to the end of the tree.
+    ///
+    /// All nodes in the range must be committed.
+    pub fn iter_range(&self, start: &[u8], end: Option<&[u8]>) -> RangeIterator {
+        let root = self.cache.borrow().get_pending_root();
+        let mut inner = TreeIterator::new(self);
+        inner.proof_builder = Some(ProofBuilder::new(root.borrow().hash));
+        mkvs::Iterator::seek(&mut inner, start);
+
+        RangeIterator {
+            inner,
+            end: end.map(<[u8]>::to_vec),
+        }
+    }
 }
 
 #[cfg(test)]
@@ -352,10 +438,13 @@ pub(super) mod test {
     use rustc_hex::FromHex;
 
     use super::{super::tree_test::generate_key_value_pairs_ex, *};
-    use crate::storage::mkvs::{
-        interop::{Driver, ProtocolServer},
-        sync::{NoopReadSyncer, StatsCollector},
-        Iterator, OverlayTree, RootType,
+    use crate::{
+        common::crypto::hash::Hash,
+        storage::mkvs::{
+            interop::{Driver, ProtocolServer},
+            sync::{verify_range_proof, NoopReadSyncer, StatsCollector},
+            Iterator, OverlayTree, RootType,
+        },
     };
 
     #[test]
@@ -596,6 +685,38 @@ pub(super) mod test {
         assert_eq!(2, stats.sync_iterate_count, "sync_iterate_count");
     }
 
+    #[test]
+    fn test_iter_range_proof() {
+        let mut tree = Tree::builder()
+            .with_capacity(0, 0)
+            .with_root_type(RootType::State)
+            .build(Box::new(NoopReadSyncer));
+
+        for key in ["key", "key 1", "key 2", "key 5", "key 8", "key 9"] {
+            tree.insert(key.as_bytes(), b"value").unwrap();
+        }
+
+        // Range proofs require a committed tree.
+        assert!(tree.iter_range(b"key 1", None).proof().is_err());
+
+        let hash = tree.commit(Default::default(), 0).expect("commit");
+
+        let mut it = tree.iter_range(b"key 1", Some(b"key 8"));
+        let entries: Vec<_> = it.by_ref().collect();
+        let proof = it.proof().expect("proof");
+        let keys: Vec<_> = entries.iter().map(|(key, _)| key.as_slice()).collect();
+        assert_eq!(keys, vec![&b"key 1"[..], b"key 2", b"key 5"]);
+
+        let verified =
+            verify_range_proof(hash, b"key 1", Some(b"key 8"), proof.clone()).expect("verify");
+        assert_eq!(verified, entries);
+
+        // The proof does not cover a wider range.
+        assert!(verify_range_proof(hash, b"key", None, proof.clone()).is_err());
+        // The proof does not verify against a different root.
+        assert!(verify_range_proof(Hash::empty_hash(), b"key 1", Some(b"key 8"), proof).is_err());
+    }
+
     pub(in super::super) fn test_iterator_with<I: mkvs::Iterator>(
         items: &[(Vec<u8>, Vec<u8>)],
         mut it: I,
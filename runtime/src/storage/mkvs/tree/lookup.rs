@@ -3,7 +3,10 @@ use anyhow::Result;
 use crate::storage::mkvs::{
     cache::{Cache, ReadSyncFetcher},
     sync::{GetRequest, Proof, ProofBuilder, ReadSync, TreeID},
-    tree::{Depth, Key, KeyTrait, NodeBox, NodeKind, NodePtrRef, Root, Tree, Value},
+    tree::{
+        profile::{Operation, Span},
+        Depth, Key, KeyTrait, NodeBox, NodeKind, NodePtrRef, Root, Tree, Value,
+    },
 };
 
 pub(super) struct FetcherSyncGet<'a> {
@@ -56,6 +59,30 @@ impl Tree {
         }
     }
 
+    /// Get a proof for all of the given keys.
+    ///
+    /// Unlike `get_proof`, the proof also covers keys which don't exist, as it includes all nodes
+    /// on the lookup path of each key.
+    pub fn get_multi_proof(&self, keys: &[Vec<u8>]) -> Result<Proof> {
+        let pending_root = self.cache.borrow().get_pending_root();
+        let mut proof_builder = ProofBuilder::new(pending_root.as_ref().borrow().hash);
+
+        for key in keys {
+            // Remember where the path from root to target node ends (will end).
+            self.cache.borrow_mut().mark_position();
+
+            self._get(
+                pending_root.clone(),
+                0,
+                key,
+                false,
+                Some(&mut proof_builder),
+            )?;
+        }
+
+        Ok(proof_builder.build())
+    }
+
     /// Check if the key exists in the local cache.
     pub fn cache_contains_key(&self, key: &[u8]) -> bool {
         match self._get_top(key, true) {
@@ -83,6 +110,7 @@ impl Tree {
         check_only: bool,
         mut proof_builder: Option<&mut ProofBuilder>,
     ) -> Result<Option<Value>> {
+        let _span = Span::enter(Operation::Get);
         let node_ref = self.cache.borrow_mut().deref_node_ptr(
             ptr,
             if check_only {
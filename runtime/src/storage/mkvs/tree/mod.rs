@@ -10,8 +10,10 @@ mod marshal;
 mod node;
 mod overlay;
 mod prefetch;
+pub mod profile;
 mod remove;
 
+pub use commit::CommitStats;
 pub use errors::*;
 pub use node::*;
 pub use overlay::*;
@@ -29,8 +31,11 @@ use crate::{
 pub struct Options {
     node_capacity: usize,
     value_capacity: usize,
+    cache_config: CacheConfig,
     root: Option<Root>,
     root_type: Option<RootType>,
+    hash_cache_capacity: usize,
+    commit_workers: usize,
 }
 
 impl Default for Options {
@@ -38,8 +43,11 @@ impl Default for Options {
         Self {
             node_capacity: 50_000,
             value_capacity: 16 * 1024 * 1024,
+            cache_config: CacheConfig::default(),
             root: None,
             root_type: None,
+            hash_cache_capacity: 0,
+            commit_workers: 1,
         }
     }
 }
@@ -73,6 +81,36 @@ impl Builder {
         self
     }
 
+    /// Set the configuration of the underlying in-memory cache.
+    ///
+    /// The configured limits apply to all nodes held by the cache, in addition to the
+    /// capacities set via `with_capacity`. If left unspecified, no additional limits apply and
+    /// the least recently used nodes are evicted first.
+    pub fn with_cache_config(mut self, config: CacheConfig) -> Self {
+        self.options.cache_config = config;
+        self
+    }
+
+    /// Set the capacity of the internal node hash cache.
+    ///
+    /// The hash cache retains internal node hashes across commits so that nodes which are
+    /// modified but end up in a previously seen state do not need to be rehashed. If set to 0,
+    /// or if left unspecified, the hash cache is disabled.
+    pub fn with_hash_cache_capacity(mut self, capacity: usize) -> Self {
+        self.options.hash_cache_capacity = capacity;
+        self
+    }
+
+    /// Set the number of workers hashing independent dirty subtrees during commit.
+    ///
+    /// Commits with multiple workers produce the same roots, but the hash cache is only updated
+    /// and not used to avoid rehashing nodes. If set to 0 or 1, or if left unspecified, nodes
+    /// are hashed serially.
+    pub fn with_commit_workers(mut self, workers: usize) -> Self {
+        self.options.commit_workers = workers;
+        self
+    }
+
     /// Set an existing root as the root for the new tree.
     ///
     /// Either this or a root type must be specified to construct a new
@@ -115,6 +153,9 @@ impl Builder {
 pub struct Tree {
     pub(crate) cache: RefCell<Box<LRUCache>>,
     pub(crate) root_type: RootType,
+    pub(crate) hash_cache: commit::HashCache,
+    pub(crate) commit_stats: CommitStats,
+    pub(crate) commit_workers: usize,
 }
 
 // Tree is Send as long as ownership of internal Rcs cannot leak out via any of its methods.
@@ -132,10 +173,14 @@ impl Tree {
             cache: RefCell::new(LRUCache::new(
                 opts.node_capacity,
                 opts.value_capacity,
+                opts.cache_config,
                 read_syncer,
                 root_type,
             )),
             root_type,
+            hash_cache: commit::HashCache::new(opts.hash_cache_capacity),
+            commit_stats: CommitStats::default(),
+            commit_workers: opts.commit_workers,
         };
 
         if let Some(root) = opts.root {
@@ -189,6 +234,10 @@ impl mkvs::FallibleMKVS for Tree {
         Tree::prefetch_prefixes(self, prefixes, limit)
     }
 
+    fn prefetch_prefix(&self, prefix: &mkvs::Prefix, max_size: u64) -> Result<()> {
+        Tree::prefetch_prefix(self, prefix, max_size)
+    }
+
     fn iter(&self) -> Box<dyn mkvs::Iterator + '_> {
         Box::new(Tree::iter(self))
     }
@@ -1,4 +1,4 @@
-use std::{cell::RefCell, rc::Rc};
+use std::{cell::RefCell, mem, rc::Rc};
 
 use crate::{
     common::{crypto::hash::Hash, namespace::Namespace},
@@ -90,6 +90,22 @@ impl Node for NodeBox {
     }
 }
 
+impl NodeBox {
+    /// Compute the hash of the node from its contents, ignoring the stored hash.
+    pub(crate) fn compute_hash(&self) -> Hash {
+        match self {
+            NodeBox::Internal(ref n) => InternalNode::compute_hash(
+                &n.label,
+                n.label_bit_length,
+                &n.leaf_node.borrow().hash,
+                &n.left.borrow().hash,
+                &n.right.borrow().hash,
+            ),
+            NodeBox::Leaf(ref n) => LeafNode::compute_hash(&n.key, &n.value),
+        }
+    }
+}
+
 /// Node types in the tree.
 ///
 /// Integer values of the variants here are also used in subtree
@@ -214,6 +230,17 @@ impl CacheItem for NodePointer {
     fn get_cached_size(&self) -> usize {
         1
     }
+
+    fn get_memory_size(&self) -> usize {
+        let node_size = match self.node {
+            None => 0,
+            Some(ref node) => match *node.borrow() {
+                NodeBox::Internal(ref n) => mem::size_of::<InternalNode>() + n.label.len(),
+                NodeBox::Leaf(ref n) => mem::size_of::<LeafNode>() + n.key.len() + n.value.len(),
+            },
+        };
+        mem::size_of::<NodePointer>() + node_size
+    }
 }
 
 impl PartialEq for NodePointer {
@@ -250,18 +277,13 @@ impl Node for InternalNode {
     }
 
     fn update_hash(&mut self) {
-        let leaf_node_hash = self.leaf_node.borrow().hash;
-        let left_hash = self.left.borrow().hash;
-        let right_hash = self.right.borrow().hash;
-
-        self.hash = Hash::digest_bytes_list(&[
-            &[NodeKind::Internal as u8],
-            &self.label_bit_length.marshal_binary().unwrap(),
-            self.label.as_ref(),
-            leaf_node_hash.as_ref(),
-            left_hash.as_ref(),
-            right_hash.as_ref(),
-        ]);
+        self.hash = Self::compute_hash(
+            &self.label,
+            self.label_bit_length,
+            &self.leaf_node.borrow().hash,
+            &self.left.borrow().hash,
+            &self.right.borrow().hash,
+        );
     }
 
     fn extract(&self) -> NodeRef {
@@ -279,6 +301,26 @@ impl Node for InternalNode {
     }
 }
 
+impl InternalNode {
+    /// Compute the hash of an internal node with the given label and child hashes.
+    pub(crate) fn compute_hash(
+        label: &Key,
+        label_bit_length: Depth,
+        leaf_node: &Hash,
+        left: &Hash,
+        right: &Hash,
+    ) -> Hash {
+        Hash::digest_bytes_list(&[
+            &[NodeKind::Internal as u8],
+            &label_bit_length.marshal_binary().unwrap(),
+            label.as_ref(),
+            leaf_node.as_ref(),
+            left.as_ref(),
+            right.as_ref(),
+        ])
+    }
+}
+
 impl PartialEq for InternalNode {
     fn eq(&self, other: &InternalNode) -> bool {
         if self.clean && other.clean {
@@ -311,6 +353,17 @@ impl LeafNode {
             value: self.value.clone(),
         }
     }
+
+    /// Compute the hash of a leaf node with the given key and value.
+    pub(crate) fn compute_hash(key: &Key, value: &Value) -> Hash {
+        Hash::digest_bytes_list(&[
+            &[NodeKind::Leaf as u8],
+            &(key.len() as u32).marshal_binary().unwrap(),
+            key.as_ref(),
+            &(value.len() as u32).marshal_binary().unwrap(),
+            value.as_ref(),
+        ])
+    }
 }
 
 impl Node for LeafNode {
@@ -323,13 +376,7 @@ impl Node for LeafNode {
     }
 
     fn update_hash(&mut self) {
-        self.hash = Hash::digest_bytes_list(&[
-            &[NodeKind::Leaf as u8],
-            &(self.key.len() as u32).marshal_binary().unwrap(),
-            self.key.as_ref(),
-            &(self.value.len() as u32).marshal_binary().unwrap(),
-            self.value.as_ref(),
-        ]);
+        self.hash = Self::compute_hash(&self.key, &self.value);
     }
 
     fn extract(&self) -> NodeRef {
@@ -1,6 +1,8 @@
 use std::{
-    collections::{btree_map, BTreeMap, HashSet},
+    collections::{btree_map, BTreeMap, HashSet, VecDeque},
     iter::Peekable,
+    ops::Bound,
+    sync::Arc,
 };
 
 use anyhow::{Error, Result};
@@ -15,10 +17,15 @@ use crate::{
 ///
 /// While updates (inserts, removes) are stored in the overlay, reads are not cached in the overlay
 /// as the inner tree has its own cache and double caching makes less sense.
+///
+/// Iterators observe the state of the tree at the time they were created. For iterators returned
+/// by `iter` this is guaranteed by them borrowing the tree. Iterators returned by `snapshot_iter`
+/// allow the tree to be updated while iterating, in which case the updates are written to a new
+/// version of the overlay while the iterator keeps using the version it was created with.
 pub struct OverlayTree<T: mkvs::FallibleMKVS> {
     inner: T,
-    overlay: BTreeMap<Vec<u8>, Vec<u8>>,
-    dirty: HashSet<Vec<u8>>,
+    overlay: Arc<BTreeMap<Vec<u8>, Vec<u8>>>,
+    dirty: Arc<HashSet<Vec<u8>>>,
 }
 
 impl<T: mkvs::FallibleMKVS> OverlayTree<T> {
@@ -26,8 +33,8 @@ impl<T: mkvs::FallibleMKVS> OverlayTree<T> {
     pub fn new(inner: T) -> Self {
         Self {
             inner,
-            overlay: BTreeMap::new(),
-            dirty: HashSet::new(),
+            overlay: Arc::new(BTreeMap::new()),
+            dirty: Arc::new(HashSet::new()),
         }
     }
 
@@ -56,8 +63,8 @@ impl<T: mkvs::FallibleMKVS> OverlayTree<T> {
     pub fn insert(&mut self, key: &[u8], value: &[u8]) -> Result<Option<Vec<u8>>> {
         let previous = self.get(key)?;
 
-        self.overlay.insert(key.to_owned(), value.to_owned());
-        self.dirty.insert(key.to_owned());
+        Arc::make_mut(&mut self.overlay).insert(key.to_owned(), value.to_owned());
+        Arc::make_mut(&mut self.dirty).insert(key.to_owned());
 
         Ok(previous)
     }
@@ -67,14 +74,14 @@ impl<T: mkvs::FallibleMKVS> OverlayTree<T> {
     pub fn remove(&mut self, key: &[u8]) -> Result<Option<Vec<u8>>> {
         // For dirty values, remove from the overlay.
         if self.dirty.contains(key) {
-            return Ok(self.overlay.remove(key));
+            return Ok(Arc::make_mut(&mut self.overlay).remove(key));
         }
 
         let value = self.inner.get(key)?;
 
         // Do not treat a value as dirty if it was not dirty before and did not exist in the inner tree.
         if value.is_some() {
-            self.dirty.insert(key.to_owned());
+            Arc::make_mut(&mut self.dirty).insert(key.to_owned());
         }
         Ok(value)
     }
@@ -84,24 +91,87 @@ impl<T: mkvs::FallibleMKVS> OverlayTree<T> {
         OverlayTreeIterator::new(self)
     }
 
+    /// Return an iterator over a snapshot of the tree, starting at the given key or the next
+    /// larger key. The tree may be updated through the iterator while iterating, without affecting
+    /// the entries returned by the iterator.
+    pub fn snapshot_iter(&mut self, key: &[u8]) -> SnapshotIterator<'_, T> {
+        SnapshotIterator::new(self, key)
+    }
+
+    /// Return all modifications held in the overlay, ordered by key, without committing them.
+    ///
+    /// Together with `merge` this allows executing transactions speculatively against an overlay
+    /// stacked on top of another tree and then either merging the resulting diff into that tree
+    /// or discarding it by dropping the overlay.
+    pub fn diff(&self) -> mkvs::WriteLog {
+        let mut removed: Vec<_> = self
+            .dirty
+            .iter()
+            .filter(|key| !self.overlay.contains_key(*key))
+            .collect();
+        removed.sort();
+
+        let mut log = Vec::with_capacity(self.dirty.len());
+        let mut removed = removed.into_iter().peekable();
+        for (key, value) in self.overlay.iter() {
+            while let Some(removed_key) = removed.next_if(|removed_key| *removed_key < key) {
+                log.push(mkvs::LogEntry {
+                    key: removed_key.clone(),
+                    value: None,
+                });
+            }
+            log.push(mkvs::LogEntry {
+                key: key.clone(),
+                value: Some(value.clone()),
+            });
+        }
+        log.extend(removed.map(|key| mkvs::LogEntry {
+            key: key.clone(),
+            value: None,
+        }));
+
+        log
+    }
+
+    /// Apply the given diff, e.g. produced by `diff` on an overlay stacked on top of this one,
+    /// to the overlay.
+    ///
+    /// Unlike inserting and removing the entries one by one, this doesn't read the previous
+    /// values from the inner tree.
+    pub fn merge(&mut self, diff: mkvs::WriteLog) {
+        let overlay = Arc::make_mut(&mut self.overlay);
+        let dirty = Arc::make_mut(&mut self.dirty);
+        for entry in diff {
+            match entry.value {
+                Some(value) => overlay.insert(entry.key.clone(), value),
+                None => overlay.remove(&entry.key),
+            };
+            dirty.insert(entry.key);
+        }
+    }
+
     /// Commit any modifications to the underlying tree.
     pub fn commit(&mut self) -> Result<mkvs::WriteLog> {
         let mut log: mkvs::WriteLog = Vec::new();
 
+        // No snapshots can exist at this point, so this never copies.
+        let overlay = Arc::make_mut(&mut self.overlay);
+        let dirty = Arc::make_mut(&mut self.dirty);
+
         // Insert all items present in the overlay.
-        for (key, value) in &self.overlay {
+        for (key, value) in overlay.iter() {
             self.inner.insert(key, value)?;
-            self.dirty.remove(key);
+            dirty.remove(key);
 
             log.push(mkvs::LogEntry {
                 key: key.clone(),
                 value: Some(value.clone()),
             });
         }
-        self.overlay.clear();
+        overlay.clear();
 
         // Any remaining dirty items must have been removed.
-        for key in &self.dirty {
+        for key in dirty.iter() {
             self.inner.remove(key)?;
 
             log.push(mkvs::LogEntry {
@@ -109,7 +179,7 @@ impl<T: mkvs::FallibleMKVS> OverlayTree<T> {
                 value: None,
             });
         }
-        self.dirty.clear();
+        dirty.clear();
 
         Ok(log)
     }
@@ -264,6 +334,126 @@ impl<T: mkvs::FallibleMKVS> mkvs::Iterator for OverlayTreeIterator<'_, T> {
     }
 }
 
+/// Number of entries fetched from the inner tree at once by a `SnapshotIterator`.
+const SNAPSHOT_BATCH_SIZE: usize = 64;
+
+/// An iterator over a snapshot of the `OverlayTree`, taken when the iterator was created.
+///
+/// The inner tree is only modified on commit, which can't happen while the iterator exists, so
+/// the snapshot only needs to retain the version of the overlay at the time of creation. Updates
+/// made through the iterator create a new version of the overlay, copying it once.
+pub struct SnapshotIterator<'tree, T: mkvs::FallibleMKVS> {
+    tree: &'tree mut OverlayTree<T>,
+
+    overlay: Arc<BTreeMap<Vec<u8>, Vec<u8>>>,
+    dirty: Arc<HashSet<Vec<u8>>>,
+    position: Bound<Vec<u8>>,
+
+    inner: VecDeque<(Vec<u8>, Vec<u8>)>,
+    inner_position: Bound<Vec<u8>>,
+    inner_done: bool,
+    error: Option<Error>,
+}
+
+impl<'tree, T: mkvs::FallibleMKVS> SnapshotIterator<'tree, T> {
+    fn new(tree: &'tree mut OverlayTree<T>, key: &[u8]) -> Self {
+        Self {
+            overlay: tree.overlay.clone(),
+            dirty: tree.dirty.clone(),
+            tree,
+            position: Bound::Included(key.to_vec()),
+            inner: VecDeque::new(),
+            inner_position: Bound::Included(key.to_vec()),
+            inner_done: false,
+            error: None,
+        }
+    }
+
+    /// Get an existing key, observing all updates made while iterating.
+    pub fn get(&self, key: &[u8]) -> Result<Option<Vec<u8>>> {
+        self.tree.get(key)
+    }
+
+    /// Insert a key/value pair into the tree.
+    ///
+    /// The update is not observed by the iteration.
+    pub fn insert(&mut self, key: &[u8], value: &[u8]) -> Result<Option<Vec<u8>>> {
+        self.tree.insert(key, value)
+    }
+
+    /// Remove entry with given key, returning the value at the key if the key was previously
+    /// in the database.
+    ///
+    /// The update is not observed by the iteration.
+    pub fn remove(&mut self, key: &[u8]) -> Result<Option<Vec<u8>>> {
+        self.tree.remove(key)
+    }
+
+    /// Return the error that occurred during iteration if any.
+    pub fn error(&self) -> &Option<Error> {
+        &self.error
+    }
+
+    /// Fetch the next batch of entries from the inner tree, skipping entries that were dirty in
+    /// the snapshot.
+    fn fetch_inner(&mut self) {
+        if !self.inner.is_empty() || self.inner_done {
+            return;
+        }
+
+        let mut it = self.tree.inner.iter();
+        match &self.inner_position {
+            Bound::Included(key) | Bound::Excluded(key) => it.seek(key),
+            Bound::Unbounded => it.rewind(),
+        }
+
+        let mut last = None;
+        for _ in 0..SNAPSHOT_BATCH_SIZE {
+            if !it.is_valid() {
+                break;
+            }
+            let key = it.get_key().clone().expect("iterator is valid");
+            let skip = matches!(&self.inner_position, Bound::Excluded(start) if *start == key);
+            if !skip && !self.dirty.contains(&key) {
+                let value = it.get_value().clone().expect("iterator is valid");
+                self.inner.push_back((key.clone(), value));
+            }
+            last = Some(key);
+            it.next();
+        }
+
+        if !it.is_valid() {
+            self.inner_done = true;
+            self.error = it.error().as_ref().map(|err| Error::msg(err.to_string()));
+        }
+        if let Some(last) = last {
+            self.inner_position = Bound::Excluded(last);
+        }
+    }
+}
+
+impl<T: mkvs::FallibleMKVS> Iterator for SnapshotIterator<'_, T> {
+    type Item = (Vec<u8>, Vec<u8>);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.fetch_inner();
+
+        let overlay = self
+            .overlay
+            .range((self.position.clone(), Bound::Unbounded))
+            .next();
+        let item = match (self.inner.front(), overlay) {
+            (None, None) => return None,
+            (Some((i_key, _)), Some((o_key, _))) if i_key < o_key => self.inner.pop_front()?,
+            (Some(_), None) => self.inner.pop_front()?,
+            (_, Some((o_key, o_value))) => (o_key.clone(), o_value.clone()),
+        };
+        self.position = Bound::Excluded(item.0.clone());
+
+        Some(item)
+    }
+}
+
 impl<T: mkvs::FallibleMKVS> mkvs::MKVS for OverlayTree<T> {
     fn get(&self, key: &[u8]) -> Option<Vec<u8>> {
         self.get(key).unwrap()
@@ -293,6 +483,10 @@ impl<T: mkvs::FallibleMKVS> mkvs::MKVS for OverlayTree<T> {
         self.inner.prefetch_prefixes(prefixes, limit).unwrap()
     }
 
+    fn prefetch_prefix(&self, prefix: &mkvs::Prefix, max_size: u64) {
+        self.inner.prefetch_prefix(prefix, max_size).unwrap()
+    }
+
     fn iter(&self) -> Box<dyn mkvs::Iterator + '_> {
         Box::new(self.iter())
     }
@@ -302,6 +496,44 @@ impl<T: mkvs::FallibleMKVS> mkvs::MKVS for OverlayTree<T> {
     }
 }
 
+impl<T: mkvs::FallibleMKVS> mkvs::FallibleMKVS for OverlayTree<T> {
+    fn get(&self, key: &[u8]) -> Result<Option<Vec<u8>>> {
+        self.get(key)
+    }
+
+    fn get_proof(&self, key: &[u8]) -> Result<Option<Proof>> {
+        self.get_proof(key)
+    }
+
+    fn cache_contains_key(&self, key: &[u8]) -> bool {
+        mkvs::MKVS::cache_contains_key(self, key)
+    }
+
+    fn insert(&mut self, key: &[u8], value: &[u8]) -> Result<Option<Vec<u8>>> {
+        self.insert(key, value)
+    }
+
+    fn remove(&mut self, key: &[u8]) -> Result<Option<Vec<u8>>> {
+        self.remove(key)
+    }
+
+    fn prefetch_prefixes(&self, prefixes: &[mkvs::Prefix], limit: u16) -> Result<()> {
+        self.inner.prefetch_prefixes(prefixes, limit)
+    }
+
+    fn prefetch_prefix(&self, prefix: &mkvs::Prefix, max_size: u64) -> Result<()> {
+        self.inner.prefetch_prefix(prefix, max_size)
+    }
+
+    fn iter(&self) -> Box<dyn mkvs::Iterator + '_> {
+        Box::new(self.iter())
+    }
+
+    fn commit(&mut self, namespace: Namespace, version: u64) -> Result<Hash> {
+        Ok(self.commit_both(namespace, version)?.1)
+    }
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
@@ -437,4 +669,112 @@ mod test {
         let it = tree.iter();
         test_iterator_with(&items, it, &tests);
     }
+
+    #[test]
+    fn test_diff_merge() {
+        let mut tree = Tree::builder()
+            .with_root_type(RootType::State)
+            .build(Box::new(NoopReadSyncer));
+        tree.insert(b"a", b"1").unwrap();
+        tree.insert(b"c", b"3").unwrap();
+
+        let mut canonical = OverlayTree::new(&mut tree);
+        canonical.insert(b"b", b"2").unwrap();
+
+        // Speculatively execute against an overlay on top of the canonical one.
+        let mut speculative = OverlayTree::new(&mut canonical);
+        speculative.remove(b"c").unwrap();
+        speculative.insert(b"d", b"4").unwrap();
+        speculative.insert(b"a", b"one").unwrap();
+        speculative.remove(b"b").unwrap();
+        assert_eq!(speculative.get(b"c").unwrap(), None);
+        let diff = speculative.diff();
+        assert_eq!(
+            diff,
+            vec![
+                mkvs::LogEntry::new(b"a", b"one"),
+                mkvs::LogEntry {
+                    key: b"b".to_vec(),
+                    value: None,
+                },
+                mkvs::LogEntry {
+                    key: b"c".to_vec(),
+                    value: None,
+                },
+                mkvs::LogEntry::new(b"d", b"4"),
+            ]
+        );
+
+        // Discarding the speculative overlay leaves the canonical one unchanged.
+        drop(speculative);
+        assert_eq!(canonical.get(b"b").unwrap(), Some(b"2".to_vec()));
+        assert_eq!(canonical.get(b"c").unwrap(), Some(b"3".to_vec()));
+
+        // Merging the diff applies it.
+        canonical.merge(diff);
+        let items: Vec<_> = canonical.iter().collect();
+        assert_eq!(
+            items,
+            vec![
+                (b"a".to_vec(), b"one".to_vec()),
+                (b"d".to_vec(), b"4".to_vec()),
+            ]
+        );
+        canonical.commit().unwrap();
+        assert_eq!(tree.get(b"c").unwrap(), None);
+        assert_eq!(tree.get(b"d").unwrap(), Some(b"4".to_vec()));
+    }
+
+    #[test]
+    fn test_snapshot_iterator() {
+        let mut tree = Tree::builder()
+            .with_root_type(RootType::State)
+            .build(Box::new(NoopReadSyncer));
+        for i in 0..100u8 {
+            tree.insert(&[b'a', i], &[i]).unwrap();
+        }
+
+        let mut overlay = OverlayTree::new(&mut tree);
+        overlay.insert(b"b", b"overlay").unwrap();
+        overlay.remove(&[b'a', 1]).unwrap();
+
+        // Interleave updates with iteration, both ahead of and behind the iterator.
+        let mut it = overlay.snapshot_iter(b"");
+        let mut seen = Vec::new();
+        while let Some((key, value)) = it.next() {
+            if key.len() == 2 && key[1] % 2 == 0 {
+                it.remove(&key).unwrap();
+                it.insert(&[b'a', key[1] + 1], b"updated").unwrap();
+                it.insert(&[b'c', key[1]], b"new").unwrap();
+            }
+            assert_eq!(
+                it.get(&key).unwrap().is_some(),
+                key.len() != 2 || key[1] % 2 == 1
+            );
+            seen.push((key, value));
+        }
+        assert!(it.error().is_none());
+
+        // The iterator observed the state at the time of its creation.
+        let mut expected: Vec<_> = (0..100u8)
+            .filter(|i| *i != 1)
+            .map(|i| (vec![b'a', i], vec![i]))
+            .collect();
+        expected.push((b"b".to_vec(), b"overlay".to_vec()));
+        assert_eq!(seen, expected);
+
+        // While the updates are visible afterwards.
+        let mut it = overlay.iter();
+        mkvs::Iterator::rewind(&mut it);
+        let keys: Vec<_> = it.map(|(key, _)| key).collect();
+        assert_eq!(keys.len(), 50 + 1 + 50);
+        assert_eq!(overlay.get(&[b'a', 1]).unwrap(), Some(b"updated".to_vec()));
+        assert_eq!(overlay.get(&[b'a', 2]).unwrap(), None);
+        assert_eq!(overlay.get(&[b'c', 98]).unwrap(), Some(b"new".to_vec()));
+
+        // Starting at a key.
+        let it = overlay.snapshot_iter(&[b'a', 95]);
+        let keys: Vec<_> = it.map(|(key, _)| key).take(3).collect();
+        assert_eq!(keys, vec![vec![b'a', 95], vec![b'a', 97], vec![b'a', 99]]);
+    }
 }
@@ -2,7 +2,7 @@ use anyhow::Result;
 
 use crate::storage::mkvs::{
     cache::{Cache, ReadSyncFetcher},
-    sync::{GetPrefixesRequest, Proof, ReadSync, TreeID},
+    sync::{GetPrefixRequest, GetPrefixesRequest, Proof, ReadSync, TreeID},
     tree::{NodePtrRef, Root, Tree},
     Prefix,
 };
@@ -32,6 +32,31 @@ impl ReadSyncFetcher for FetcherSyncGetPrefixes<'_> {
     }
 }
 
+pub(super) struct FetcherSyncGetPrefix<'a> {
+    prefix: &'a Prefix,
+    max_size: u64,
+}
+
+impl<'a> FetcherSyncGetPrefix<'a> {
+    pub(super) fn new(prefix: &'a Prefix, max_size: u64) -> Self {
+        Self { prefix, max_size }
+    }
+}
+
+impl ReadSyncFetcher for FetcherSyncGetPrefix<'_> {
+    fn fetch(&self, root: Root, ptr: NodePtrRef, rs: &mut Box<dyn ReadSync>) -> Result<Proof> {
+        let rsp = rs.sync_get_prefix(GetPrefixRequest {
+            tree: TreeID {
+                root,
+                position: ptr.borrow().hash,
+            },
+            prefix: self.prefix.clone(),
+            max_size: self.max_size,
+        })?;
+        Ok(rsp.proof)
+    }
+}
+
 impl Tree {
     /// Populate the in-memory tree with nodes for keys starting with given prefixes.
     pub fn prefetch_prefixes(&self, prefixes: &[Prefix], limit: u16) -> Result<()> {
@@ -40,4 +65,34 @@ impl Tree {
             .borrow_mut()
             .remote_sync(pending_root, FetcherSyncGetPrefixes::new(prefixes, limit))
     }
+
+    /// Populate the in-memory tree with the entire subtree of keys starting with the given
+    /// prefix, fetching at most `max_size` bytes of proof in a single request.
+    ///
+    /// Nodes that do not fit into the budget are fetched on demand when accessed.
+    pub fn prefetch_prefix(&self, prefix: &Prefix, max_size: u64) -> Result<()> {
+        let pending_root = self.cache.borrow().get_pending_root();
+        self.cache
+            .borrow_mut()
+            .remote_sync(pending_root, FetcherSyncGetPrefix::new(prefix, max_size))
+    }
+
+    /// Populate the in-memory tree with nodes for keys starting with given prefixes, verifying
+    /// the fetched proof in the background.
+    ///
+    /// This only waits for the nodes to be fetched. Proof verification happens on a background
+    /// worker and the verified nodes are merged into the tree once available, so execution can
+    /// proceed while data that it hasn't touched yet is still being verified. Verification
+    /// failures are reported by `wait_prefetches`.
+    pub fn prefetch_prefixes_background(&self, prefixes: &[Prefix], limit: u16) -> Result<()> {
+        let pending_root = self.cache.borrow().get_pending_root();
+        self.cache
+            .borrow_mut()
+            .remote_sync_background(pending_root, FetcherSyncGetPrefixes::new(prefixes, limit))
+    }
+
+    /// Wait for all background prefetches to be verified and merged into the tree.
+    pub fn wait_prefetches(&self) -> Result<()> {
+        self.cache.borrow_mut().complete_prefetches(true)
+    }
 }
@@ -0,0 +1,245 @@
+//! Per-operation tree instrumentation.
+//!
+//! When the `mkvs-profiling` feature is enabled, each level of the tree traversed while looking
+//! up, inserting or committing is timed and aggregated into a global profile keyed by operation
+//! and depth. The profile can be exported to the host via the `METHOD_MKVS_PROFILE` query and
+//! rendered in the folded stack format understood by flamegraph tools, so that storage-layer
+//! regressions can be localized without external profilers.
+//!
+//! Without the feature, spans are no-ops and the profile is always empty.
+#[cfg(feature = "mkvs-profiling")]
+use std::{
+    cell::RefCell,
+    collections::BTreeMap,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+/// Name of the query method returning the tree profile.
+pub const METHOD_MKVS_PROFILE: &str = "runtime.MkvsProfile";
+
+#[cfg(feature = "mkvs-profiling")]
+lazy_static! {
+    static ref PROFILE: Mutex<BTreeMap<(Operation, u16), Timings>> = Mutex::new(BTreeMap::new());
+}
+
+#[cfg(feature = "mkvs-profiling")]
+thread_local! {
+    /// Time spent in the children of each active span.
+    static ACTIVE_SPANS: RefCell<Vec<Duration>> = const { RefCell::new(Vec::new()) };
+}
+
+/// Instrumented tree operation.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash, cbor::Encode, cbor::Decode)]
+#[cbor(with_default)]
+#[repr(u8)]
+pub enum Operation {
+    Get = 0,
+    Insert = 1,
+    Commit = 2,
+}
+
+impl Operation {
+    fn as_str(&self) -> &'static str {
+        match self {
+            Self::Get => "get",
+            Self::Insert => "insert",
+            Self::Commit => "commit",
+        }
+    }
+}
+
+impl Default for Operation {
+    fn default() -> Self {
+        Self::Get
+    }
+}
+
+#[cfg(feature = "mkvs-profiling")]
+#[derive(Default)]
+struct Timings {
+    count: u64,
+    total: Duration,
+    own: Duration,
+}
+
+/// Aggregated timings of an operation at a given depth.
+#[derive(Clone, Debug, Default, PartialEq, Eq, cbor::Encode, cbor::Decode)]
+pub struct ProfileEntry {
+    /// Instrumented operation.
+    pub operation: Operation,
+    /// Depth of the visited nodes, counted in nodes from the root.
+    pub depth: u16,
+    /// Number of visited nodes.
+    pub count: u64,
+    /// Total time spent at this depth, including deeper levels (in nanoseconds).
+    pub total_ns: u64,
+    /// Time spent at this depth, excluding deeper levels (in nanoseconds).
+    pub self_ns: u64,
+}
+
+/// Profile of tree operations.
+#[derive(Clone, Debug, Default, PartialEq, Eq, cbor::Encode, cbor::Decode)]
+pub struct Profile {
+    /// Aggregated timings, ordered by operation and depth.
+    pub entries: Vec<ProfileEntry>,
+}
+
+impl Profile {
+    /// Render the profile in the folded stack format, with one line per operation and depth
+    /// (e.g. `mkvs;get;0;1 1200`) weighted by the self time in nanoseconds.
+    pub fn to_folded(&self) -> String {
+        self.entries
+            .iter()
+            .map(|entry| {
+                let mut stack = format!("mkvs;{}", entry.operation.as_str());
+                for depth in 0..=entry.depth {
+                    stack.push_str(&format!(";{}", depth));
+                }
+                format!("{} {}\n", stack, entry.self_ns)
+            })
+            .collect()
+    }
+}
+
+/// Snapshot of the global tree profile.
+pub fn snapshot() -> Profile {
+    #[cfg(feature = "mkvs-profiling")]
+    {
+        let entries = PROFILE
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|(&(operation, depth), timings)| ProfileEntry {
+                operation,
+                depth,
+                count: timings.count,
+                total_ns: timings.total.as_nanos() as u64,
+                self_ns: timings.own.as_nanos() as u64,
+            })
+            .collect();
+        Profile { entries }
+    }
+
+    #[cfg(not(feature = "mkvs-profiling"))]
+    Profile::default()
+}
+
+/// Clear the global tree profile.
+pub fn reset() {
+    #[cfg(feature = "mkvs-profiling")]
+    PROFILE.lock().unwrap().clear();
+}
+
+/// Timing of a single tree level, recorded when dropped.
+pub(super) struct Span {
+    #[cfg(feature = "mkvs-profiling")]
+    operation: Operation,
+    #[cfg(feature = "mkvs-profiling")]
+    depth: u16,
+    #[cfg(feature = "mkvs-profiling")]
+    start: Instant,
+}
+
+impl Span {
+    /// Start timing a level of the given operation, one level deeper than the enclosing span.
+    #[cfg_attr(not(feature = "mkvs-profiling"), allow(unused_variables))]
+    #[inline]
+    pub(super) fn enter(operation: Operation) -> Self {
+        #[cfg(feature = "mkvs-profiling")]
+        {
+            let depth = ACTIVE_SPANS.with(|spans| {
+                let mut spans = spans.borrow_mut();
+                spans.push(Duration::ZERO);
+                spans.len() - 1
+            });
+            Self {
+                operation,
+                depth: depth as u16,
+                start: Instant::now(),
+            }
+        }
+
+        #[cfg(not(feature = "mkvs-profiling"))]
+        Self {}
+    }
+}
+
+#[cfg(feature = "mkvs-profiling")]
+impl Drop for Span {
+    fn drop(&mut self) {
+        let total = self.start.elapsed();
+        let children = ACTIVE_SPANS.with(|spans| {
+            let mut spans = spans.borrow_mut();
+            let children = spans.pop().unwrap_or_default();
+            if let Some(parent) = spans.last_mut() {
+                *parent += total;
+            }
+            children
+        });
+
+        let mut profile = PROFILE.lock().unwrap();
+        let timings = profile.entry((self.operation, self.depth)).or_default();
+        timings.count += 1;
+        timings.total += total;
+        timings.own += total.saturating_sub(children);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_profile_folded() {
+        let profile = Profile {
+            entries: vec![
+                ProfileEntry {
+                    operation: Operation::Get,
+                    depth: 0,
+                    count: 2,
+                    total_ns: 300,
+                    self_ns: 100,
+                },
+                ProfileEntry {
+                    operation: Operation::Get,
+                    depth: 1,
+                    count: 2,
+                    total_ns: 200,
+                    self_ns: 200,
+                },
+                ProfileEntry {
+                    operation: Operation::Commit,
+                    depth: 0,
+                    count: 1,
+                    total_ns: 50,
+                    self_ns: 50,
+                },
+            ],
+        };
+        assert_eq!(
+            profile.to_folded(),
+            "mkvs;get;0 100\nmkvs;get;0;1 200\nmkvs;commit;0 50\n"
+        );
+    }
+
+    #[cfg(feature = "mkvs-profiling")]
+    #[test]
+    fn test_profile_spans() {
+        {
+            let _outer = Span::enter(Operation::Insert);
+            let _inner = Span::enter(Operation::Insert);
+        }
+
+        let entries: Vec<_> = snapshot()
+            .entries
+            .into_iter()
+            .filter(|entry| entry.operation == Operation::Insert)
+            .collect();
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[0].depth, 0);
+        assert_eq!(entries[1].depth, 1);
+        assert!(entries[0].total_ns >= entries[1].total_ns);
+        assert!(entries[0].self_ns <= entries[0].total_ns);
+    }
+}
@@ -4,7 +4,7 @@ use crate::storage::mkvs::{
     interop::{Driver, ProtocolServer},
     tests,
     tree::*,
-    Iterator, LogEntry, LogEntryKind, WriteLog, MKVS,
+    CacheConfig, CacheMetrics, Iterator, LogEntry, LogEntryKind, WriteLog, MKVS,
 };
 
 const INSERT_ITEMS: usize = 1000;
@@ -379,6 +379,63 @@ fn test_insert_commit_each() {
     assert_eq!(format!("{:?}", hash), ALL_ITEMS_ROOT);
 }
 
+#[test]
+fn test_insert_sorted_batch() {
+    let new_tree = || {
+        Tree::builder()
+            .with_root_type(RootType::State)
+            .build(Box::new(NoopReadSyncer))
+    };
+    let sorted = |(keys, values): (Vec<Vec<u8>>, Vec<Vec<u8>>)| {
+        let mut entries: Vec<_> = keys.into_iter().zip(values).collect();
+        entries.sort();
+        entries
+    };
+
+    let entries = sorted(generate_key_value_pairs());
+    let mut tree = new_tree();
+    tree.insert_sorted_batch(entries.clone()).expect("insert");
+    for (key, value) in &entries {
+        assert_eq!(tree.get(key).expect("get").as_ref(), Some(value));
+    }
+    let hash = Tree::commit(&mut tree, Default::default(), 0).expect("commit");
+    assert_eq!(format!("{:?}", hash), ALL_ITEMS_ROOT);
+
+    // Keys being prefixes of each other.
+    let mut tree = new_tree();
+    tree.insert_sorted_batch(sorted(generate_long_key_value_pairs()))
+        .expect("insert");
+    let hash = Tree::commit(&mut tree, Default::default(), 0).expect("commit");
+    assert_eq!(format!("{:?}", hash), ALL_LONG_ITEMS_ROOT);
+
+    // Empty keys and duplicates, where the last pair wins.
+    let batch = vec![
+        (b"".to_vec(), b"empty".to_vec()),
+        (b"a".to_vec(), b"first".to_vec()),
+        (b"a".to_vec(), b"second".to_vec()),
+        (b"b".to_vec(), b"b".to_vec()),
+    ];
+    let mut tree = new_tree();
+    tree.insert_sorted_batch(batch.clone()).expect("insert");
+    let hash = Tree::commit(&mut tree, Default::default(), 0).expect("commit");
+    let mut expected_tree = new_tree();
+    for (key, value) in &batch {
+        expected_tree.insert(key, value).expect("insert");
+    }
+    let expected = Tree::commit(&mut expected_tree, Default::default(), 0).expect("commit");
+    assert_eq!(hash, expected);
+    assert_eq!(tree.get(b"a").expect("get"), Some(b"second".to_vec()));
+
+    // Unsorted batches and non-empty trees fall back to regular insertion.
+    let mut tree = new_tree();
+    let (first, rest) = entries.split_at(10);
+    tree.insert_sorted_batch(first.iter().rev().cloned().collect())
+        .expect("insert");
+    tree.insert_sorted_batch(rest.to_vec()).expect("insert");
+    let hash = Tree::commit(&mut tree, Default::default(), 0).expect("commit");
+    assert_eq!(format!("{:?}", hash), ALL_ITEMS_ROOT);
+}
+
 #[test]
 fn test_remove() {
     let mut tree = Tree::builder()
@@ -690,6 +747,117 @@ fn test_syncer_prefetch_prefixes() {
     assert_eq!(0, stats.sync_iterate_count, "sync_iterate count");
 }
 
+#[test]
+fn test_syncer_prefetch_prefix() {
+    let server = ProtocolServer::new(None);
+
+    let mut tree = OverlayTree::new(
+        Tree::builder()
+            .with_capacity(0, 0)
+            .with_root_type(RootType::State)
+            .build(Box::new(NoopReadSyncer)),
+    );
+
+    let (keys, values) = generate_key_value_pairs();
+    for i in 0..keys.len() {
+        tree.insert(keys[i].as_slice(), values[i].as_slice())
+            .expect("insert");
+    }
+
+    let (write_log, hash) = tree.commit_both(Default::default(), 0).expect("commit");
+    server.apply(&write_log, hash, Default::default(), 0);
+
+    let stats = StatsCollector::new(server.read_sync());
+    let remote_tree = Tree::builder()
+        .with_capacity(0, 0)
+        .with_root(Root {
+            root_type: RootType::State,
+            hash,
+            ..Default::default()
+        })
+        .build(Box::new(stats));
+
+    // Prefetch the entire subtree under prefix "key".
+    remote_tree
+        .prefetch_prefix(&b"key".to_vec().into(), 16 * 1024 * 1024)
+        .expect("prefetch_prefix");
+
+    for i in 0..keys.len() {
+        let value = remote_tree
+            .get(keys[i].as_slice())
+            .expect("get")
+            .expect("get_some");
+        assert_eq!(values[i], value.as_slice());
+    }
+
+    let cache = remote_tree.cache.borrow();
+    let stats = cache
+        .get_read_syncer()
+        .as_any()
+        .downcast_ref::<StatsCollector>()
+        .expect("stats");
+    assert_eq!(0, stats.sync_get_count, "sync_get count");
+    assert_eq!(1, stats.sync_get_prefix_count, "sync_get_prefix count");
+    assert_eq!(0, stats.sync_get_prefixes_count, "sync_get_prefixes count");
+    assert_eq!(0, stats.sync_iterate_count, "sync_iterate count");
+}
+
+#[test]
+fn test_syncer_prefetch_prefixes_background() {
+    let server = ProtocolServer::new(None);
+
+    let mut tree = OverlayTree::new(
+        Tree::builder()
+            .with_capacity(0, 0)
+            .with_root_type(RootType::State)
+            .build(Box::new(NoopReadSyncer)),
+    );
+
+    let (keys, values) = generate_key_value_pairs();
+    for i in 0..keys.len() {
+        tree.insert(keys[i].as_slice(), values[i].as_slice())
+            .expect("insert");
+    }
+
+    let (write_log, hash) = tree.commit_both(Default::default(), 0).expect("commit");
+    server.apply(&write_log, hash, Default::default(), 0);
+
+    let stats = StatsCollector::new(server.read_sync());
+    let remote_tree = Tree::builder()
+        .with_capacity(0, 0)
+        .with_root(Root {
+            root_type: RootType::State,
+            hash,
+            ..Default::default()
+        })
+        .build(Box::new(stats));
+
+    // Prefetch keys starting with prefix "key", verifying the proof in the background.
+    remote_tree
+        .prefetch_prefixes_background(&vec![b"key".to_vec().into()], 1000)
+        .expect("prefetch_prefixes_background");
+
+    // Accessing keys must wait for the verification instead of fetching them again.
+    for i in 0..keys.len() {
+        let value = remote_tree
+            .get(keys[i].as_slice())
+            .expect("get")
+            .expect("get_some");
+        assert_eq!(values[i], value.as_slice());
+    }
+    remote_tree.wait_prefetches().expect("wait_prefetches");
+
+    let cache = remote_tree.cache.borrow();
+    let stats = cache
+        .get_read_syncer()
+        .as_any()
+        .downcast_ref::<StatsCollector>()
+        .expect("stats");
+    assert_eq!(0, stats.sync_get_count, "sync_get count");
+    assert_eq!(1, stats.sync_get_prefixes_count, "sync_get_prefixes count");
+    assert_eq!(0, stats.sync_iterate_count, "sync_iterate count");
+}
+
 #[test]
 fn test_value_eviction() {
     let mut tree = Tree::builder()
@@ -751,6 +919,64 @@ fn test_node_eviction() {
     );
 }
 
+#[test]
+fn test_cache_limits() {
+    let mut tree = Tree::builder()
+        .with_capacity(0, 0)
+        .with_cache_config(CacheConfig {
+            max_nodes: 100,
+            ..Default::default()
+        })
+        .with_root_type(RootType::State)
+        .build(Box::new(NoopReadSyncer));
+
+    CacheMetrics::take();
+    let (keys, values) = generate_key_value_pairs();
+    for i in 0..keys.len() {
+        tree.insert(keys[i].as_slice(), values[i].as_slice())
+            .expect("insert");
+    }
+    Tree::commit(&mut tree, Default::default(), 0).expect("commit");
+
+    // Only a subset of nodes should remain in cache.
+    let stats = tree.cache.borrow().stats();
+    assert!(stats.node_count <= 100, "cache.node_count");
+    assert!(CacheMetrics::take().evictions > 0, "evictions");
+
+    let max_bytes = 64 * 1024;
+    let mut tree = Tree::builder()
+        .with_capacity(0, 0)
+        .with_cache_config(CacheConfig {
+            max_bytes,
+            ..Default::default()
+        })
+        .with_root_type(RootType::State)
+        .build(Box::new(NoopReadSyncer));
+
+    let value = vec![0x42; 1024];
+    for key in &keys {
+        tree.insert(key.as_slice(), &value).expect("insert");
+    }
+    Tree::commit(&mut tree, Default::default(), 0).expect("commit");
+
+    let stats = tree.cache.borrow().stats();
+    assert!(stats.memory_size <= max_bytes, "cache.memory_size");
+    assert!(stats.node_count > 0, "cache.node_count");
+
+    // Lookups of nodes held by the cache are hits.
+    let mut tree = Tree::builder()
+        .with_root_type(RootType::State)
+        .build(Box::new(NoopReadSyncer));
+    tree.insert(&keys[0], &values[0]).expect("insert");
+    Tree::commit(&mut tree, Default::default(), 0).expect("commit");
+
+    CacheMetrics::take();
+    assert_eq!(tree.get(&keys[0]).expect("get"), Some(values[0].clone()));
+    let metrics = CacheMetrics::take();
+    assert!(metrics.hits > 0, "hits");
+    assert_eq!(metrics.misses, 0, "misses");
+}
+
 /// Location of the test vectors directory (from Go).
 const TEST_VECTORS_DIR: &str = "../go/storage/mkvs/testdata";
 
@@ -891,3 +1117,89 @@ fn test_special_case_4() {
 fn test_special_case_5() {
     test_special_case_from_json("case-5.json")
 }
+
+#[test]
+fn test_commit_hash_cache() {
+    let (keys, values) = generate_key_value_pairs_ex("".to_string(), 100);
+    let mut tree = Tree::builder()
+        .with_root_type(RootType::State)
+        .with_hash_cache_capacity(10_000)
+        .build(Box::new(NoopReadSyncer));
+    // The hash cache is disabled by default.
+    let mut uncached = Tree::builder()
+        .with_root_type(RootType::State)
+        .build(Box::new(NoopReadSyncer));
+
+    for (key, value) in keys.iter().zip(values.iter()) {
+        tree.insert(key, value).expect("insert");
+        uncached.insert(key, value).expect("insert");
+    }
+    let root = tree.commit(Default::default(), 0).expect("commit");
+    assert_eq!(
+        root,
+        uncached.commit(Default::default(), 0).expect("commit")
+    );
+    let stats = tree.commit_stats();
+    assert_eq!(stats.hashed_leaves, 100);
+    assert_eq!(stats.memoized_internal_nodes, 0);
+    assert_eq!(stats, uncached.commit_stats());
+
+    // Insert a key and remove it again in the next round, ending up in a previously seen state.
+    for tree in [&mut tree, &mut uncached] {
+        tree.insert(b"extra key", b"extra value").expect("insert");
+        tree.commit(Default::default(), 1).expect("commit");
+        tree.remove(b"extra key").expect("remove");
+        assert_eq!(tree.commit(Default::default(), 2).expect("commit"), root);
+    }
+
+    let stats = tree.commit_stats();
+    assert_eq!(stats.hashed_internal_nodes, 0);
+    assert!(stats.memoized_internal_nodes > 0);
+
+    let stats = uncached.commit_stats();
+    assert!(stats.hashed_internal_nodes > 0);
+    assert_eq!(stats.memoized_internal_nodes, 0);
+}
+
+#[test]
+fn test_commit_parallel() {
+    let (keys, values) = generate_key_value_pairs_ex("".to_string(), 1000);
+    let build = |workers| {
+        Tree::builder()
+            .with_root_type(RootType::State)
+            .with_commit_workers(workers)
+            .build(Box::new(NoopReadSyncer))
+    };
+    let mut serial = build(1);
+    let mut trees: Vec<Tree> = [2, 3, 8].into_iter().map(build).collect();
+
+    for (key, value) in keys.iter().zip(values.iter()) {
+        serial.insert(key, value).expect("insert");
+        for tree in trees.iter_mut() {
+            tree.insert(key, value).expect("insert");
+        }
+    }
+    let root = serial.commit(Default::default(), 0).expect("commit");
+    for tree in trees.iter_mut() {
+        assert_eq!(tree.commit(Default::default(), 0).expect("commit"), root);
+        assert_eq!(tree.commit_stats(), serial.commit_stats());
+    }
+
+    // Update a part of the keys so that only some subtrees are dirty.
+    for key in keys.iter().step_by(3) {
+        serial.insert(key, b"updated").expect("insert");
+        for tree in trees.iter_mut() {
+            tree.insert(key, b"updated").expect("insert");
+        }
+    }
+    for key in keys.iter().skip(1).step_by(7) {
+        serial.remove(key).expect("remove");
+        for tree in trees.iter_mut() {
+            tree.remove(key).expect("remove");
+        }
+    }
+    let root = serial.commit(Default::default(), 1).expect("commit");
+    for tree in trees.iter_mut() {
+        assert_eq!(tree.commit(Default::default(), 1).expect("commit"), root);
+    }
+}
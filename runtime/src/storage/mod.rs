@@ -7,6 +7,8 @@ use std::{
 use crate::types::Error;
 
 pub mod mkvs;
+pub mod receipt;
+pub mod table;
 
 // Re-exports.
 pub use self::mkvs::MKVS;
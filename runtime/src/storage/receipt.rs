@@ -0,0 +1,231 @@
+//! Storage receipts.
+//!
+//! Hosts persisting writes to multiple storage nodes may return receipts signed by each of the
+//! storage endpoints, attesting that they stored the given roots. A write is only considered
+//! durable once a [`DurabilityPolicy`] is satisfied, i.e. at least K of the N configured storage
+//! nodes returned a valid receipt. As the host is not trusted, receipts are verified in a batch
+//! and receipts by unknown signers, for other roots or with invalid signatures are ignored.
+use std::collections::BTreeSet;
+
+use thiserror::Error;
+
+use crate::common::{
+    crypto::{
+        hash::Hash,
+        signature::{verify_batch, PublicKey, SignatureBundle},
+    },
+    namespace::Namespace,
+};
+
+use super::mkvs::RootType;
+
+/// Signature context used for storage receipts.
+pub const RECEIPT_SIGNATURE_CONTEXT: &[u8] = b"oasis-core/storage: receipt";
+
+/// Storage receipt errors.
+#[derive(Error, Debug, PartialEq, Eq)]
+pub enum ReceiptError {
+    #[error("invalid durability threshold (threshold: {threshold} signers: {signers})")]
+    InvalidThreshold { threshold: usize, signers: usize },
+}
+
+/// Body of a storage receipt.
+#[derive(Clone, Debug, Default, PartialEq, Eq, cbor::Encode, cbor::Decode)]
+pub struct ReceiptBody {
+    /// Version of the receipt body format.
+    pub version: u16,
+    /// Chain namespace of the stored roots.
+    pub namespace: Namespace,
+    /// Round of the stored roots.
+    pub round: u64,
+    /// Types of the stored roots.
+    pub root_types: Vec<RootType>,
+    /// Hashes of the stored roots.
+    pub roots: Vec<Hash>,
+}
+
+/// A storage receipt signed by a storage endpoint.
+#[derive(Clone, Debug, Default, PartialEq, Eq, cbor::Encode, cbor::Decode)]
+pub struct Receipt {
+    /// Receipt body.
+    pub body: ReceiptBody,
+    /// Signature over the CBOR-encoded body.
+    pub signature: SignatureBundle,
+}
+
+/// Level of durability of a write.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Durability {
+    /// No storage node confirmed the write.
+    Unconfirmed,
+    /// Fewer storage nodes than required by the policy confirmed the write.
+    Partial,
+    /// At least the number of storage nodes required by the policy confirmed the write.
+    Durable,
+}
+
+/// Durability of a committed write, as determined from its storage receipts.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct CommitDurability {
+    /// Durability level.
+    pub level: Durability,
+    /// Distinct storage nodes which returned valid receipts.
+    pub signers: Vec<PublicKey>,
+}
+
+/// Policy determining when a write is durable.
+#[derive(Clone, Debug, Default)]
+pub struct DurabilityPolicy {
+    /// Public keys of the storage nodes whose receipts are accepted (N).
+    pub signers: Vec<PublicKey>,
+    /// Number of distinct storage nodes required to confirm a write (K). Must be non-zero and
+    /// at most the number of accepted storage nodes.
+    pub threshold: usize,
+}
+
+impl DurabilityPolicy {
+    /// Determine the durability of a write of the given roots from the given receipts.
+    ///
+    /// Fails in case the policy's threshold is invalid.
+    pub fn verify(
+        &self,
+        body: &ReceiptBody,
+        receipts: &[Receipt],
+    ) -> Result<CommitDurability, ReceiptError> {
+        if self.threshold == 0 || self.threshold > self.signers.len() {
+            return Err(ReceiptError::InvalidThreshold {
+                threshold: self.threshold,
+                signers: self.signers.len(),
+            });
+        }
+        let message = cbor::to_vec(body.clone());
+
+        // Only receipts for the expected roots by accepted signers are verified. Receipts are
+        // deduplicated after verification, so that an invalid receipt can't shadow a valid one
+        // by the same signer.
+        let candidates: Vec<_> = receipts
+            .iter()
+            .filter(|receipt| &receipt.body == body)
+            .map(|receipt| &receipt.signature)
+            .filter(|sig| self.signers.contains(&sig.public_key))
+            .collect();
+
+        let items: Vec<_> = candidates
+            .iter()
+            .map(|sig| {
+                (
+                    &sig.public_key,
+                    RECEIPT_SIGNATURE_CONTEXT,
+                    &message[..],
+                    &sig.signature,
+                )
+            })
+            .collect();
+        let valid: Vec<_> = if verify_batch(&items).is_ok() {
+            candidates.iter().map(|sig| sig.public_key).collect()
+        } else {
+            // Identify the invalid signatures.
+            candidates
+                .iter()
+                .filter(|sig| sig.verify(RECEIPT_SIGNATURE_CONTEXT, &message))
+                .map(|sig| sig.public_key)
+                .collect()
+        };
+        let mut seen = BTreeSet::new();
+        let signers: Vec<_> = valid.into_iter().filter(|pk| seen.insert(*pk)).collect();
+
+        let level = match signers.len() {
+            n if n >= self.threshold => Durability::Durable,
+            0 => Durability::Unconfirmed,
+            _ => Durability::Partial,
+        };
+        Ok(CommitDurability { level, signers })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::common::crypto::signature::{PrivateKey, Signer};
+
+    fn receipt(sk: &PrivateKey, body: &ReceiptBody) -> Receipt {
+        Receipt {
+            body: body.clone(),
+            signature: SignatureBundle {
+                public_key: sk.public_key(),
+                signature: Signer::sign(sk, RECEIPT_SIGNATURE_CONTEXT, &cbor::to_vec(body.clone()))
+                    .unwrap(),
+            },
+        }
+    }
+
+    #[test]
+    fn test_durability_policy() {
+        let nodes: Vec<_> = (0..3)
+            .map(|i| PrivateKey::from_test_seed(format!("storage node {i}")))
+            .collect();
+        let policy = DurabilityPolicy {
+            signers: nodes.iter().map(|sk| sk.public_key()).collect(),
+            threshold: 2,
+        };
+        let body = ReceiptBody {
+            round: 5,
+            root_types: vec![RootType::IO, RootType::State],
+            roots: vec![Hash::digest_bytes(b"io"), Hash::digest_bytes(b"state")],
+            ..Default::default()
+        };
+        let other_body = ReceiptBody {
+            round: 6,
+            ..body.clone()
+        };
+        let outsider = PrivateKey::from_test_seed("outsider".to_string());
+
+        let result = policy.verify(&body, &[]).unwrap();
+        assert_eq!(result.level, Durability::Unconfirmed);
+
+        // Duplicates, receipts for other roots and by unknown signers don't count.
+        let mut receipts = vec![
+            receipt(&nodes[0], &body),
+            receipt(&nodes[0], &body),
+            receipt(&nodes[1], &other_body),
+            receipt(&outsider, &body),
+        ];
+        let result = policy.verify(&body, &receipts).unwrap();
+        assert_eq!(result.level, Durability::Partial);
+        assert_eq!(result.signers, vec![nodes[0].public_key()]);
+
+        // Invalid signatures don't count.
+        let mut forged = receipt(&nodes[1], &other_body);
+        forged.body = body.clone();
+        receipts.push(forged);
+        let result = policy.verify(&body, &receipts).unwrap();
+        assert_eq!(result.level, Durability::Partial);
+
+        // Invalid receipts don't shadow valid ones by the same signer.
+        let mut forged = receipt(&nodes[2], &other_body);
+        forged.body = body.clone();
+        receipts.insert(0, forged);
+        receipts.push(receipt(&nodes[2], &body));
+        let result = policy.verify(&body, &receipts).unwrap();
+        assert_eq!(result.level, Durability::Durable);
+        assert_eq!(
+            result.signers,
+            vec![nodes[0].public_key(), nodes[2].public_key()]
+        );
+
+        // Thresholds which can never or always be met are rejected.
+        for threshold in [0, 4] {
+            let policy = DurabilityPolicy {
+                threshold,
+                ..policy.clone()
+            };
+            assert_eq!(
+                policy.verify(&body, &receipts),
+                Err(ReceiptError::InvalidThreshold {
+                    threshold,
+                    signers: 3,
+                })
+            );
+        }
+    }
+}
@@ -0,0 +1,312 @@
+//! Structured storage layered on MKVS.
+//!
+//! A [`Table`] stores typed rows under a single key prefix byte, identified by their primary key,
+//! and maintains secondary indexes over values derived from the rows. Primary keys and indexed
+//! values are [`KeyFormatAtom`]s, whose fixed-size big-endian encodings are ordered the same way as
+//! the values themselves, so range scans over keys and indexed values compile down to seeking a
+//! tree iterator and reading until the encoded upper bound. As everything is stored in the tree,
+//! all lookups and scans remain provable against the state root.
+//!
+//! Keys are laid out as follows:
+//!
+//! * rows: `prefix ‖ 0x00 ‖ primary key`, holding the CBOR-encoded row,
+//! * index entries: `prefix ‖ index id ‖ indexed value ‖ primary key`, holding an empty value.
+use std::ops::{Bound, RangeBounds};
+
+use crate::{common::key_format::KeyFormatAtom, storage::mkvs::MKVS};
+
+/// Sub-prefix of the rows of a table, which can't be used as an index identifier.
+const ROWS: u8 = 0x00;
+
+struct Index<R> {
+    id: u8,
+    size: usize,
+    value: Box<dyn Fn(&R) -> Vec<u8> + Send + Sync>,
+}
+
+/// A typed table with secondary indexes.
+///
+/// The table itself only holds the schema, the data is stored in the tree passed to each call.
+pub struct Table<K, R> {
+    prefix: u8,
+    primary_key: fn(&R) -> K,
+    indexes: Vec<Index<R>>,
+}
+
+impl<K, R> Table<K, R>
+where
+    K: KeyFormatAtom + Clone + 'static,
+    R: cbor::Encode + cbor::Decode + 'static,
+{
+    /// Create a new table stored under the given key prefix, whose rows are identified by the
+    /// primary key returned by the given function.
+    pub fn new(prefix: u8, primary_key: fn(&R) -> K) -> Self {
+        Self {
+            prefix,
+            primary_key,
+            indexes: Vec::new(),
+        }
+    }
+
+    /// Add a secondary index with the given identifier over the values returned by the given
+    /// function.
+    ///
+    /// Indexes must be added before any rows are stored, as existing rows are not indexed.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the identifier is reserved (zero) or already used.
+    pub fn with_index<V: KeyFormatAtom + 'static>(mut self, id: u8, value: fn(&R) -> V) -> Self {
+        assert!(id != ROWS, "table: reserved index identifier");
+        assert!(
+            self.indexes.iter().all(|index| index.id != id),
+            "table: duplicate index identifier"
+        );
+
+        self.indexes.push(Index {
+            id,
+            size: V::size(),
+            value: Box::new(move |row| value(row).encode_atom()),
+        });
+        self
+    }
+
+    /// Fetch the row with the given primary key.
+    pub fn get(&self, tree: &dyn MKVS, key: K) -> Option<R> {
+        let raw = tree.get(&self.row_key(&key.encode_atom()))?;
+        Some(decode_row(&raw))
+    }
+
+    /// Insert the given row, replacing and returning any row with the same primary key.
+    pub fn insert(&self, tree: &mut dyn MKVS, row: R) -> Option<R> {
+        let pk = (self.primary_key)(&row).encode_atom();
+        let index_keys: Vec<_> = self
+            .indexes
+            .iter()
+            .map(|index| self.index_key(index, &row, &pk))
+            .collect();
+
+        let previous = tree
+            .insert(&self.row_key(&pk), &cbor::to_vec(row))
+            .map(|raw| decode_row(&raw));
+        if let Some(previous) = &previous {
+            self.remove_index_entries(tree, previous, &pk);
+        }
+        for key in index_keys {
+            tree.insert(&key, &[]);
+        }
+
+        previous
+    }
+
+    /// Remove the row with the given primary key, returning it if it was present.
+    pub fn remove(&self, tree: &mut dyn MKVS, key: K) -> Option<R> {
+        let pk = key.encode_atom();
+        let previous = decode_row(&tree.remove(&self.row_key(&pk))?);
+        self.remove_index_entries(tree, &previous, &pk);
+
+        Some(previous)
+    }
+
+    /// Rows whose primary keys are within the given range, in primary key order.
+    pub fn range<'a>(
+        &self,
+        tree: &'a dyn MKVS,
+        range: impl RangeBounds<K>,
+    ) -> impl Iterator<Item = R> + 'a {
+        scan(
+            tree,
+            vec![self.prefix, ROWS],
+            K::size(),
+            encode_bound(range.start_bound()),
+            encode_bound(range.end_bound()),
+        )
+        .map(decode_value)
+    }
+
+    /// Rows whose values in the given index are within the given range, in index order.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the table has no such index or the index is over values of a different size.
+    pub fn index_range<'a, V: KeyFormatAtom + Clone>(
+        &self,
+        tree: &'a dyn MKVS,
+        id: u8,
+        range: impl RangeBounds<V>,
+    ) -> impl Iterator<Item = R> + 'a {
+        let index = self
+            .indexes
+            .iter()
+            .find(|index| index.id == id)
+            .expect("table: unknown index");
+        assert_eq!(index.size, V::size(), "table: index value size mismatch");
+
+        let entries = scan(
+            tree,
+            vec![self.prefix, id],
+            index.size,
+            encode_bound(range.start_bound()),
+            encode_bound(range.end_bound()),
+        );
+        lookup_rows(tree, vec![self.prefix, ROWS], index.size, entries)
+    }
+
+    fn row_key(&self, pk: &[u8]) -> Vec<u8> {
+        [&[self.prefix, ROWS][..], pk].concat()
+    }
+
+    fn index_key(&self, index: &Index<R>, row: &R, pk: &[u8]) -> Vec<u8> {
+        [&[self.prefix, index.id][..], &(index.value)(row), pk].concat()
+    }
+
+    fn remove_index_entries(&self, tree: &mut dyn MKVS, row: &R, pk: &[u8]) {
+        for index in &self.indexes {
+            tree.remove(&self.index_key(index, row, pk));
+        }
+    }
+}
+
+fn decode_row<R: cbor::Decode>(raw: &[u8]) -> R {
+    cbor::from_slice(raw).expect("table: malformed row")
+}
+
+fn decode_value<R: cbor::Decode>((_, raw): (Vec<u8>, Vec<u8>)) -> R {
+    decode_row(&raw)
+}
+
+/// Rows referenced by the given index entries, whose key suffixes consist of the indexed value of
+/// the given size followed by the primary key.
+fn lookup_rows<'a, R: cbor::Decode + 'a>(
+    tree: &'a dyn MKVS,
+    rows: Vec<u8>,
+    size: usize,
+    entries: impl Iterator<Item = (Vec<u8>, Vec<u8>)> + 'a,
+) -> impl Iterator<Item = R> + 'a {
+    entries.map(move |(suffix, _)| {
+        let raw = tree
+            .get(&[&rows[..], &suffix[size..]].concat())
+            .expect("table: index entry without row");
+        decode_row(&raw)
+    })
+}
+
+fn encode_bound<T: KeyFormatAtom + Clone>(bound: Bound<&T>) -> Bound<Vec<u8>> {
+    match bound {
+        Bound::Included(value) => Bound::Included(value.clone().encode_atom()),
+        Bound::Excluded(value) => Bound::Excluded(value.clone().encode_atom()),
+        Bound::Unbounded => Bound::Unbounded,
+    }
+}
+
+/// Entries under the given prefix whose keys, after the prefix, start with a value of the given
+/// size within the given bounds. Returns the key suffixes following the prefix and the values.
+fn scan<'a>(
+    tree: &'a dyn MKVS,
+    prefix: Vec<u8>,
+    size: usize,
+    start: Bound<Vec<u8>>,
+    end: Bound<Vec<u8>>,
+) -> impl Iterator<Item = (Vec<u8>, Vec<u8>)> + 'a {
+    let mut it = tree.iter();
+    match &start {
+        Bound::Included(value) | Bound::Excluded(value) => it.seek(&[&prefix[..], value].concat()),
+        Bound::Unbounded => it.seek(&prefix),
+    }
+
+    let offset = prefix.len();
+    it.take_while(move |(key, _)| {
+        if !key.starts_with(&prefix) {
+            return false;
+        }
+        let value = &key[offset..offset + size];
+        match &end {
+            Bound::Included(end) => value <= &end[..],
+            Bound::Excluded(end) => value < &end[..],
+            Bound::Unbounded => true,
+        }
+    })
+    .filter(move |(key, _)| {
+        !matches!(&start, Bound::Excluded(start) if key[offset..offset + size] == start[..])
+    })
+    .map(move |(key, value)| (key[offset..].to_vec(), value))
+}
+
+#[cfg(test)]
+mod test {
+    use std::ops::Range;
+
+    use super::*;
+    use crate::storage::mkvs::{sync::NoopReadSyncer, OverlayTree, RootType, Tree};
+
+    #[derive(Clone, Debug, PartialEq, Eq, cbor::Encode, cbor::Decode)]
+    struct Account {
+        id: u64,
+        kind: u8,
+        balance: u64,
+    }
+
+    const BY_BALANCE: u8 = 1;
+    const BY_KIND: u8 = 2;
+
+    fn accounts() -> Table<u64, Account> {
+        Table::new(0x10, |account: &Account| account.id)
+            .with_index(BY_BALANCE, |account: &Account| account.balance)
+            .with_index(BY_KIND, |account: &Account| (account.kind, account.balance))
+    }
+
+    fn account(id: u64, kind: u8, balance: u64) -> Account {
+        Account { id, kind, balance }
+    }
+
+    #[test]
+    fn test_table() {
+        let tree = Tree::builder()
+            .with_root_type(RootType::State)
+            .build(Box::new(NoopReadSyncer));
+        let mut tree = OverlayTree::new(tree);
+        tree.insert(b"\x11unrelated", b"value");
+
+        let table = accounts();
+        for (id, kind, balance) in [(3, 1, 30), (1, 0, 50), (2, 1, 10), (4, 0, 20)] {
+            assert_eq!(table.insert(&mut tree, account(id, kind, balance)), None);
+        }
+        assert_eq!(table.get(&tree, 1), Some(account(1, 0, 50)));
+        assert_eq!(table.get(&tree, 5), None);
+
+        // Range scans over primary keys.
+        let ids = |rows: Vec<Account>| rows.iter().map(|row| row.id).collect::<Vec<_>>();
+        assert_eq!(ids(table.range(&tree, ..).collect()), vec![1, 2, 3, 4]);
+        assert_eq!(ids(table.range(&tree, 2..4).collect()), vec![2, 3]);
+        assert_eq!(ids(table.range(&tree, 2..=4).collect()), vec![2, 3, 4]);
+        let excluded = (Bound::Excluded(1), Bound::Unbounded);
+        assert_eq!(ids(table.range(&tree, excluded).collect()), vec![2, 3, 4]);
+
+        // Range scans over secondary indexes.
+        let by_balance = |tree: &dyn MKVS, range: Range<u64>| {
+            ids(table.index_range(tree, BY_BALANCE, range).collect())
+        };
+        assert_eq!(by_balance(&tree, 0..u64::MAX), vec![2, 4, 3, 1]);
+        assert_eq!(by_balance(&tree, 20..50), vec![4, 3]);
+        let by_kind = |tree: &dyn MKVS, kind: u8| {
+            ids(table
+                .index_range(tree, BY_KIND, (kind, 0)..=(kind, u64::MAX))
+                .collect())
+        };
+        assert_eq!(by_kind(&tree, 0), vec![4, 1]);
+        assert_eq!(by_kind(&tree, 1), vec![2, 3]);
+
+        // Updates and removals maintain the indexes.
+        assert_eq!(
+            table.insert(&mut tree, account(4, 1, 60)),
+            Some(account(4, 0, 20))
+        );
+        assert_eq!(table.remove(&mut tree, 2), Some(account(2, 1, 10)));
+        assert_eq!(table.remove(&mut tree, 2), None);
+        assert_eq!(by_balance(&tree, 0..u64::MAX), vec![3, 1, 4]);
+        assert_eq!(by_kind(&tree, 1), vec![3, 4]);
+
+        // Other keys are left untouched.
+        assert_eq!(tree.get(b"\x11unrelated"), Some(b"value".to_vec()));
+    }
+}
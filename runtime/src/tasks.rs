@@ -0,0 +1,234 @@
+//! Scheduler for background tasks.
+//!
+//! Runtime subsystems register periodic or one-shot background tasks (e.g. cache maintenance,
+//! prefetching or pushing reports to the host) with the scheduler instead of spawning their own
+//! tasks, so that all of them share the same lifecycle. Runs of different tasks are spread out
+//! via jitter, the number of concurrently running tasks is limited and all tasks are stopped
+//! gracefully on shutdown.
+use std::{
+    future::Future,
+    sync::{Arc, Condvar, Mutex},
+    thread,
+    time::Duration,
+};
+
+use anyhow::Result;
+use rand::Rng;
+use slog::{debug, warn, Logger};
+use tokio::sync::Semaphore;
+
+use crate::common::{crypto::rng::SecureRng, logger::get_logger};
+
+/// When a task should run.
+#[derive(Clone, Debug)]
+pub struct Schedule {
+    delay: Duration,
+    interval: Option<Duration>,
+    jitter: Duration,
+}
+
+impl Schedule {
+    /// Run the task once, after the given delay.
+    pub fn once(delay: Duration) -> Self {
+        Self {
+            delay,
+            interval: None,
+            jitter: Duration::ZERO,
+        }
+    }
+
+    /// Run the task repeatedly, waiting for the given interval before each run.
+    pub fn periodic(interval: Duration) -> Self {
+        Self {
+            delay: interval,
+            interval: Some(interval),
+            jitter: Duration::ZERO,
+        }
+    }
+
+    /// Delay each run by an additional random duration of at most the given jitter.
+    pub fn with_jitter(mut self, jitter: Duration) -> Self {
+        self.jitter = jitter;
+        self
+    }
+
+    fn with_jitter_applied(&self, delay: Duration) -> Duration {
+        if self.jitter.is_zero() {
+            return delay;
+        }
+        let jitter = SecureRng.gen_range(0..=self.jitter.as_nanos() as u64);
+        delay + Duration::from_nanos(jitter)
+    }
+}
+
+/// Shutdown signal shared with the task threads.
+#[derive(Default)]
+struct Shutdown {
+    requested: Mutex<bool>,
+    cond: Condvar,
+}
+
+impl Shutdown {
+    /// Sleep for the given duration, returning `false` in case shutdown has been requested.
+    fn sleep(&self, duration: Duration) -> bool {
+        let requested = self.requested.lock().unwrap();
+        let (requested, _) = self
+            .cond
+            .wait_timeout_while(requested, duration, |requested| !*requested)
+            .unwrap();
+        !*requested
+    }
+
+    fn is_requested(&self) -> bool {
+        *self.requested.lock().unwrap()
+    }
+
+    fn request(&self) {
+        *self.requested.lock().unwrap() = true;
+        self.cond.notify_all();
+    }
+}
+
+/// Scheduler for background tasks.
+pub struct Scheduler {
+    logger: Logger,
+    tokio_runtime: tokio::runtime::Handle,
+    limiter: Arc<Semaphore>,
+    shutdown: Arc<Shutdown>,
+    workers: Mutex<Vec<thread::JoinHandle<()>>>,
+}
+
+impl Scheduler {
+    /// Create a new scheduler running tasks on the given Tokio runtime, with at most the given
+    /// number of tasks running concurrently.
+    pub fn new(tokio_runtime: tokio::runtime::Handle, max_concurrent: usize) -> Self {
+        Self {
+            logger: get_logger("runtime/tasks"),
+            tokio_runtime,
+            limiter: Arc::new(Semaphore::new(max_concurrent.max(1))),
+            shutdown: Arc::new(Shutdown::default()),
+            workers: Mutex::new(Vec::new()),
+        }
+    }
+
+    /// Register a new background task with the given schedule.
+    ///
+    /// Failed runs are logged and don't affect later runs of periodic tasks. Tasks registered
+    /// after shutdown are ignored.
+    pub fn spawn<F, Fut>(&self, name: &'static str, schedule: Schedule, task: F)
+    where
+        F: Fn() -> Fut + Send + 'static,
+        Fut: Future<Output = Result<()>>,
+    {
+        let mut workers = self.workers.lock().unwrap();
+        if self.shutdown.is_requested() {
+            warn!(self.logger, "Ignoring task registered after shutdown"; "task" => name);
+            return;
+        }
+
+        let logger = self.logger.new(slog::o!("task" => name));
+        let tokio_runtime = self.tokio_runtime.clone();
+        let limiter = self.limiter.clone();
+        let shutdown = self.shutdown.clone();
+
+        workers.push(thread::spawn(move || {
+            let mut delay = schedule.with_jitter_applied(schedule.delay);
+            while shutdown.sleep(delay) {
+                let result = tokio_runtime.block_on(async {
+                    // The semaphore is never closed.
+                    let _permit = limiter.acquire().await;
+                    task().await
+                });
+                if let Err(err) = result {
+                    warn!(logger, "Background task failed"; "err" => ?err);
+                }
+
+                match schedule.interval {
+                    Some(interval) => delay = schedule.with_jitter_applied(interval),
+                    None => break,
+                }
+            }
+            debug!(logger, "Background task stopped");
+        }));
+    }
+
+    /// Stop all tasks, waiting for any running tasks to finish.
+    ///
+    /// This must not be called from within the scheduler's Tokio runtime as it blocks.
+    pub fn shutdown(&self) {
+        self.shutdown.request();
+
+        let workers: Vec<_> = self.workers.lock().unwrap().drain(..).collect();
+        for worker in workers {
+            let _ = worker.join();
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    use super::*;
+
+    #[test]
+    fn test_scheduler() {
+        let tokio_runtime = tokio::runtime::Builder::new_current_thread()
+            .build()
+            .unwrap();
+        let scheduler = Scheduler::new(tokio_runtime.handle().clone(), 1);
+
+        let once = Arc::new(AtomicUsize::new(0));
+        let counter = once.clone();
+        scheduler.spawn("once", Schedule::once(Duration::ZERO), move || {
+            let counter = counter.clone();
+            async move {
+                counter.fetch_add(1, Ordering::SeqCst);
+                Ok(())
+            }
+        });
+
+        let periodic = Arc::new(AtomicUsize::new(0));
+        let counter = periodic.clone();
+        scheduler.spawn(
+            "periodic",
+            Schedule::periodic(Duration::from_millis(1)).with_jitter(Duration::from_millis(1)),
+            move || {
+                let counter = counter.clone();
+                async move {
+                    counter.fetch_add(1, Ordering::SeqCst);
+                    anyhow::bail!("failed runs don't stop periodic tasks")
+                }
+            },
+        );
+
+        let never = Arc::new(AtomicUsize::new(0));
+        let counter = never.clone();
+        scheduler.spawn(
+            "never",
+            Schedule::once(Duration::from_secs(3600)),
+            move || {
+                let counter = counter.clone();
+                async move {
+                    counter.fetch_add(1, Ordering::SeqCst);
+                    Ok(())
+                }
+            },
+        );
+
+        while once.load(Ordering::SeqCst) < 1 || periodic.load(Ordering::SeqCst) < 3 {
+            thread::sleep(Duration::from_millis(1));
+        }
+        scheduler.shutdown();
+
+        // Tasks don't run after shutdown.
+        let runs = periodic.load(Ordering::SeqCst);
+        thread::sleep(Duration::from_millis(10));
+        assert_eq!(periodic.load(Ordering::SeqCst), runs);
+        assert_eq!(once.load(Ordering::SeqCst), 1);
+        assert_eq!(never.load(Ordering::SeqCst), 0);
+
+        scheduler.spawn("late", Schedule::once(Duration::ZERO), || async { Ok(()) });
+        assert!(scheduler.workers.lock().unwrap().is_empty());
+    }
+}
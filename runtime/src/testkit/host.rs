@@ -0,0 +1,874 @@
+//! In-memory host for unit testing runtimes.
+//!
+//! Testing components which talk to the host otherwise requires a running node, so every runtime
+//! ends up writing its own fakes. Two in-process replacements are provided instead:
+//!
+//! * [`MockHost`] implements the [`Host`] interface with in-memory state and scriptable
+//!   responses, for components written against `&dyn Host`.
+//! * [`LoopbackProtocol`] runs a real [`Protocol`] whose host calls are answered by a handler,
+//!   for components which need a protocol instance (e.g. to make raw host calls).
+//!
+//! The mock host passes the host [conformance](crate::host::conformance) suite.
+use std::{
+    collections::{BTreeMap, VecDeque},
+    io,
+    sync::{Arc, Condvar, Mutex},
+};
+
+use async_trait::async_trait;
+use byteorder::{BigEndian, ReadBytesExt, WriteBytesExt};
+
+use crate::{
+    common::crypto::{hash::Hash, signature::PublicKey},
+    config::Config,
+    consensus::{
+        beacon::EpochTime,
+        client::ConsensusClient,
+        roothash::{AnnotatedBlock, Evidence, Header},
+        state::ConsensusState,
+        verifier::{Error as VerifierError, Verifier},
+        Event, LightBlock,
+    },
+    host::{
+        bundle_manager::*,
+        conformance::matches_labels,
+        local_storage::{LocalStorage, LocalStorageError},
+        notify::NotifyRegistry,
+        secrets::{Secret, Secrets, SecretsError},
+        volume_manager::*,
+        ConsensusGasPrice, Error as HostError, Host, NotificationStream, NotifyHandle,
+        RegisterNotifyOpts, SubmitTxOpts, TxResult,
+    },
+    identity::Identity,
+    protocol::{HostInfo, Protocol, CODE_PERMANENT, MODULE_NAME as PROTOCOL_MODULE_NAME},
+    storage::mkvs::{sync, RootType, Tree},
+    transport::Transport,
+    types::{self, Body, EventKind, Message, MessageType},
+};
+
+/// Chain context used by the mock host to verify submitted evidence.
+pub const MOCK_CHAIN_CONTEXT: &str = "mock";
+
+/// Handler of transactions submitted to the mock host.
+pub type SubmitTxHandler =
+    Box<dyn Fn(&[u8], &SubmitTxOpts) -> Result<TxResult, HostError> + Send + Sync>;
+
+/// In-memory host with scriptable responses.
+///
+/// By default, transactions are included in the latest block with an empty output, which is
+/// a block of round zero until other blocks are pushed.
+#[derive(Default)]
+pub struct MockHost {
+    identity: Mutex<PublicKey>,
+    blocks: Mutex<BTreeMap<u64, AnnotatedBlock>>,
+    submit_tx: Mutex<Option<SubmitTxHandler>>,
+    submitted_txs: Mutex<Vec<Vec<u8>>>,
+    submitted_evidence: Mutex<Vec<Evidence>>,
+    gas_price: Mutex<ConsensusGasPrice>,
+    secrets: Mutex<BTreeMap<String, Vec<u8>>>,
+    notify: Arc<NotifyRegistry>,
+    volumes: Mutex<Vec<VolumeInfo>>,
+    snapshots: Mutex<Vec<SnapshotInfo>>,
+    files: Mutex<BTreeMap<(String, String), Vec<u8>>>,
+    quotas: Mutex<BTreeMap<String, u64>>,
+    blobs: Mutex<BTreeMap<(String, Hash), (Vec<u8>, u64)>>,
+    records: Mutex<BTreeMap<Vec<u8>, Vec<u8>>>,
+    bundle: Mutex<Option<(Vec<u8>, Vec<u8>)>>,
+    consensus: Arc<MockConsensus>,
+}
+
+impl MockHost {
+    /// Create a new mock host.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Set the identity of the host node.
+    pub fn set_identity(&self, identity: PublicKey) {
+        *self.identity.lock().unwrap() = identity;
+    }
+
+    /// Add a runtime block, delivering it to notification registrations for runtime blocks.
+    pub fn push_block(&self, block: AnnotatedBlock) {
+        self.blocks
+            .lock()
+            .unwrap()
+            .insert(block.block.header.round, block.clone());
+        self.notify.deliver(Some(&block), None);
+    }
+
+    /// Deliver the given runtime event to the matching notification registrations.
+    pub fn push_event(&self, event: &types::RuntimeNotifyEvent) {
+        self.notify.deliver(None, Some(event));
+    }
+
+    /// Handle submitted transactions using the given handler. The handler is invoked for all
+    /// submissions, including those not waiting for inclusion.
+    pub fn on_submit_tx<F>(&self, handler: F)
+    where
+        F: Fn(&[u8], &SubmitTxOpts) -> Result<TxResult, HostError> + Send + Sync + 'static,
+    {
+        *self.submit_tx.lock().unwrap() = Some(Box::new(handler));
+    }
+
+    /// Transactions submitted so far, in submission order.
+    pub fn submitted_txs(&self) -> Vec<Vec<u8>> {
+        self.submitted_txs.lock().unwrap().clone()
+    }
+
+    /// Valid evidence submitted so far, in submission order.
+    pub fn submitted_evidence(&self) -> Vec<Evidence> {
+        self.submitted_evidence.lock().unwrap().clone()
+    }
+
+    /// Set the reported consensus layer gas prices.
+    pub fn set_gas_price(&self, gas_price: ConsensusGasPrice) {
+        *self.gas_price.lock().unwrap() = gas_price;
+    }
+
+    /// Provision the secret with the given name.
+    pub fn set_secret(&self, name: &str, value: Vec<u8>) {
+        self.secrets.lock().unwrap().insert(name.to_string(), value);
+    }
+
+    /// Volumes added so far.
+    pub fn volumes(&self) -> Vec<VolumeInfo> {
+        self.volumes.lock().unwrap().clone()
+    }
+
+    /// Replace all volumes, e.g. to simulate the host tampering with their labels.
+    pub fn set_volumes(&self, volumes: Vec<VolumeInfo>) {
+        *self.volumes.lock().unwrap() = volumes;
+    }
+
+    /// Contents of the given file of the given volume.
+    pub fn volume_file(&self, id: &str, path: &str) -> Option<Vec<u8>> {
+        self.files
+            .lock()
+            .unwrap()
+            .get(&(id.to_string(), path.to_string()))
+            .cloned()
+    }
+
+    /// Replace the contents of the given file of the given volume, e.g. to simulate the host
+    /// tampering with it.
+    pub fn set_volume_file(&self, id: &str, path: &str, data: Vec<u8>) {
+        self.files
+            .lock()
+            .unwrap()
+            .insert((id.to_string(), path.to_string()), data);
+    }
+
+    /// Number of distinct blobs stored in the given volume.
+    pub fn volume_blob_count(&self, id: &str) -> usize {
+        self.blobs
+            .lock()
+            .unwrap()
+            .keys()
+            .filter(|(volume_id, _)| volume_id == id)
+            .count()
+    }
+
+    /// Replace the contents of the blob with the given hash in the given volume, e.g. to
+    /// simulate the host tampering with it.
+    pub fn set_volume_blob(&self, id: &str, hash: Hash, data: Vec<u8>) {
+        if let Some((blob, _)) = self.blobs.lock().unwrap().get_mut(&(id.to_string(), hash)) {
+            *blob = data;
+        }
+    }
+
+    /// Serve the given bundle with the given raw manifest via chunked bundle fetches.
+    pub fn set_fetchable_bundle(&self, manifest: Vec<u8>, data: Vec<u8>) {
+        *self.bundle.lock().unwrap() = Some((manifest, data));
+    }
+
+    /// Set the consensus layer state at the given height to the given raw state entries.
+    ///
+    /// Once any state has been set, the host provides a consensus client querying the states
+    /// without verification, the latest height being the highest one with a state.
+    pub fn set_consensus_state(&self, height: u64, entries: BTreeMap<Vec<u8>, Vec<u8>>) {
+        self.consensus
+            .states
+            .lock()
+            .unwrap()
+            .insert(height, entries);
+    }
+
+    fn latest_block(&self) -> AnnotatedBlock {
+        self.blocks
+            .lock()
+            .unwrap()
+            .values()
+            .next_back()
+            .cloned()
+            .unwrap_or_default()
+    }
+}
+
+#[async_trait]
+impl Host for MockHost {
+    async fn identity(&self) -> Result<PublicKey, HostError> {
+        Ok(*self.identity.lock().unwrap())
+    }
+
+    async fn runtime_block(&self, round: Option<u64>) -> Result<AnnotatedBlock, HostError> {
+        let round = match round {
+            Some(round) => round,
+            None => return Ok(self.latest_block()),
+        };
+        let blocks = self.blocks.lock().unwrap();
+        match blocks.get(&round) {
+            Some(block) => Ok(block.clone()),
+            None if blocks.is_empty() && round == 0 => Ok(AnnotatedBlock::default()),
+            None => Err(HostError::Host(types::Error::new(
+                "mock",
+                1,
+                "runtime block not found",
+            ))),
+        }
+    }
+
+    async fn submit_tx(
+        &self,
+        data: Vec<u8>,
+        opts: SubmitTxOpts,
+    ) -> Result<Option<TxResult>, HostError> {
+        self.submitted_txs.lock().unwrap().push(data.clone());
+        let result = match &*self.submit_tx.lock().unwrap() {
+            Some(handler) => handler(&data, &opts)?,
+            None => TxResult {
+                tx_hash: Hash::digest_bytes(&data),
+                round: self.latest_block().block.header.round,
+                proof: opts.prove.then(sync::Proof::default),
+                ..Default::default()
+            },
+        };
+        Ok(opts.wait.then_some(result))
+    }
+
+    async fn register_notify(&self, opts: RegisterNotifyOpts) -> Result<NotifyHandle, HostError> {
+        Ok(self.notify.add(opts))
+    }
+
+    async fn notifications(
+        &self,
+        opts: RegisterNotifyOpts,
+    ) -> Result<NotificationStream, HostError> {
+        Ok(self.notify.add_stream(opts))
+    }
+
+    async fn relay_attestation(&self, _verifier: &str) -> Result<Vec<u8>, HostError> {
+        Err(HostError::AttestationUnavailable)
+    }
+
+    async fn consensus_gas_price(&self) -> Result<ConsensusGasPrice, HostError> {
+        Ok(self.gas_price.lock().unwrap().clone())
+    }
+
+    async fn submit_evidence(&self, evidence: Evidence) -> Result<(), HostError> {
+        evidence
+            .verify(&MOCK_CHAIN_CONTEXT.to_string())
+            .map_err(HostError::InvalidEvidence)?;
+        self.submitted_evidence.lock().unwrap().push(evidence);
+        Ok(())
+    }
+
+    fn bundle_manager(&self) -> &dyn BundleManager {
+        self
+    }
+
+    fn volume_manager(&self) -> &dyn VolumeManager {
+        self
+    }
+
+    fn local_storage(&self) -> &dyn LocalStorage {
+        self
+    }
+
+    fn secrets(&self) -> &dyn Secrets {
+        self
+    }
+
+    fn consensus(&self) -> Result<ConsensusClient, HostError> {
+        if self.consensus.states.lock().unwrap().is_empty() {
+            return Err(HostError::ConsensusUnavailable);
+        }
+        Ok(ConsensusClient::new(self.consensus.clone()))
+    }
+}
+
+#[async_trait]
+impl LocalStorage for MockHost {
+    async fn get(&self, key: &[u8]) -> Result<Option<Vec<u8>>, LocalStorageError> {
+        Ok(self.records.lock().unwrap().get(key).cloned())
+    }
+
+    async fn set(&self, key: &[u8], value: Vec<u8>) -> Result<(), LocalStorageError> {
+        self.records.lock().unwrap().insert(key.to_vec(), value);
+        Ok(())
+    }
+
+    async fn delete(&self, key: &[u8]) -> Result<(), LocalStorageError> {
+        self.records.lock().unwrap().remove(key);
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl Secrets for MockHost {
+    async fn get(&self, name: &str) -> Result<Secret, SecretsError> {
+        self.secrets
+            .lock()
+            .unwrap()
+            .get(name)
+            .map(|value| Secret::new(value.clone()))
+            .ok_or_else(|| SecretsError::NotFound(name.to_string()))
+    }
+
+    fn invalidate(&self, _name: &str) {}
+}
+
+#[async_trait]
+impl BundleManager for MockHost {
+    async fn bundle_write(
+        &self,
+        _args: BundleWriteRequest,
+    ) -> Result<BundleWriteResponse, HostError> {
+        Ok(BundleWriteResponse {})
+    }
+
+    async fn bundle_add(&self, _args: BundleAddRequest) -> Result<BundleAddResponse, HostError> {
+        Ok(BundleAddResponse {})
+    }
+
+    async fn bundle_remove(
+        &self,
+        _args: BundleRemoveRequest,
+    ) -> Result<BundleRemoveResponse, HostError> {
+        Ok(BundleRemoveResponse {})
+    }
+
+    async fn bundle_list(&self, _args: BundleListRequest) -> Result<BundleListResponse, HostError> {
+        Ok(BundleListResponse::default())
+    }
+
+    async fn bundle_migrate(
+        &self,
+        _args: BundleMigrateRequest,
+    ) -> Result<BundleMigrateResponse, HostError> {
+        Ok(BundleMigrateResponse::default())
+    }
+
+    async fn bundle_fetch_info(
+        &self,
+        _args: BundleFetchInfoRequest,
+    ) -> Result<BundleFetchInfoResponse, HostError> {
+        let bundle = self.bundle.lock().unwrap();
+        Ok(BundleFetchInfoResponse {
+            manifest: bundle
+                .as_ref()
+                .map(|(manifest, _)| manifest.clone())
+                .unwrap_or_default(),
+        })
+    }
+
+    async fn bundle_fetch_chunk(
+        &self,
+        args: BundleFetchChunkRequest,
+    ) -> Result<BundleFetchChunkResponse, HostError> {
+        let bundle = self.bundle.lock().unwrap();
+        let (_, data) = bundle.as_ref().ok_or(HostError::BadResponse)?;
+        let data = data
+            .chunks(args.chunk_size.max(1) as usize)
+            .nth(args.index as usize)
+            .ok_or(HostError::BadResponse)?
+            .to_vec();
+        Ok(BundleFetchChunkResponse { data })
+    }
+}
+
+#[async_trait]
+impl VolumeManager for MockHost {
+    async fn volume_add(&self, args: VolumeAddRequest) -> Result<VolumeAddResponse, HostError> {
+        let mut volumes = self.volumes.lock().unwrap();
+        let id = format!("volume-{}", volumes.len());
+        volumes.push(VolumeInfo {
+            id: id.clone(),
+            labels: args.labels,
+        });
+        if let Some(quota) = args.bytes_quota {
+            self.quotas.lock().unwrap().insert(id.clone(), quota);
+        }
+        Ok(VolumeAddResponse { id })
+    }
+
+    async fn volume_remove(
+        &self,
+        args: VolumeRemoveRequest,
+    ) -> Result<VolumeRemoveResponse, HostError> {
+        self.volumes
+            .lock()
+            .unwrap()
+            .retain(|v| !matches_labels(&v.labels, &args.labels));
+        Ok(VolumeRemoveResponse {})
+    }
+
+    async fn volume_list(&self, args: VolumeListRequest) -> Result<VolumeListResponse, HostError> {
+        let volumes: Vec<_> = self
+            .volumes
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|v| matches_labels(&v.labels, &args.labels))
+            .cloned()
+            .collect();
+        let (volumes, continuation_token) = args.pagination.paginate(&volumes)?;
+        Ok(VolumeListResponse {
+            volumes,
+            continuation_token,
+        })
+    }
+
+    async fn volume_snapshot(
+        &self,
+        args: VolumeSnapshotRequest,
+    ) -> Result<VolumeSnapshotResponse, HostError> {
+        let mut snapshots = self.snapshots.lock().unwrap();
+        let snapshot_id = format!("snapshot-{}", snapshots.len());
+        snapshots.push(SnapshotInfo {
+            id: snapshot_id.clone(),
+            volume_id: args.id,
+            labels: args.labels,
+        });
+        Ok(VolumeSnapshotResponse { snapshot_id })
+    }
+
+    async fn volume_restore(
+        &self,
+        args: VolumeRestoreRequest,
+    ) -> Result<VolumeRestoreResponse, HostError> {
+        self.snapshots
+            .lock()
+            .unwrap()
+            .iter()
+            .find(|s| s.id == args.snapshot_id && s.volume_id == args.id)
+            .ok_or(HostError::BadResponse)?;
+        Ok(VolumeRestoreResponse {})
+    }
+
+    async fn volume_snapshot_list(
+        &self,
+        args: VolumeSnapshotListRequest,
+    ) -> Result<VolumeSnapshotListResponse, HostError> {
+        let snapshots: Vec<_> = self
+            .snapshots
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|s| s.volume_id == args.id)
+            .cloned()
+            .collect();
+        let (snapshots, continuation_token) = args.pagination.paginate(&snapshots)?;
+        Ok(VolumeSnapshotListResponse {
+            snapshots,
+            continuation_token,
+        })
+    }
+
+    async fn volume_read(&self, args: VolumeReadRequest) -> Result<VolumeReadResponse, HostError> {
+        let files = self.files.lock().unwrap();
+        let data = files.get(&(args.id, args.path)).map(|data| {
+            let start = data.len().min(args.offset as usize);
+            let end = args
+                .length
+                .map_or(data.len(), |length| data.len().min(start + length as usize));
+            data[start..end].to_vec()
+        });
+        Ok(VolumeReadResponse { data })
+    }
+
+    async fn volume_write(
+        &self,
+        args: VolumeWriteRequest,
+    ) -> Result<VolumeWriteResponse, HostError> {
+        let mut files = self.files.lock().unwrap();
+        let usage = volume_usage(&files, &args.id);
+        let replaced = files
+            .get(&(args.id.clone(), args.path.clone()))
+            .map_or(0, Vec::len) as u64;
+        let quota = self.quotas.lock().unwrap().get(&args.id).copied();
+        if quota.is_some_and(|quota| usage.bytes_used - replaced + args.data.len() as u64 > quota) {
+            return Err(HostError::Host(types::Error::new(
+                MODULE_NAME,
+                CODE_QUOTA_EXCEEDED,
+                "quota exceeded",
+            )));
+        }
+        files.insert((args.id, args.path), args.data);
+        Ok(VolumeWriteResponse {})
+    }
+
+    async fn volume_usage(&self, args: VolumeUsageRequest) -> Result<VolumeUsage, HostError> {
+        let files = self.files.lock().unwrap();
+        Ok(VolumeUsage {
+            bytes_quota: self.quotas.lock().unwrap().get(&args.id).copied(),
+            ..volume_usage(&files, &args.id)
+        })
+    }
+
+    async fn volume_blob_put(
+        &self,
+        args: VolumeBlobPutRequest,
+    ) -> Result<VolumeBlobPutResponse, HostError> {
+        let hash = Hash::digest_bytes(&args.data);
+        let mut blobs = self.blobs.lock().unwrap();
+        let key = (args.id, hash);
+        let deduplicated = blobs.contains_key(&key);
+        blobs.entry(key).or_insert((args.data, 0)).1 += 1;
+        Ok(VolumeBlobPutResponse { hash, deduplicated })
+    }
+
+    async fn volume_blob_get(
+        &self,
+        args: VolumeBlobGetRequest,
+    ) -> Result<VolumeBlobGetResponse, HostError> {
+        let blobs = self.blobs.lock().unwrap();
+        Ok(VolumeBlobGetResponse {
+            data: blobs
+                .get(&(args.id, args.hash))
+                .map(|(data, _)| data.clone()),
+        })
+    }
+
+    async fn volume_blob_pin(
+        &self,
+        args: VolumeBlobPinRequest,
+    ) -> Result<VolumeBlobPinResponse, HostError> {
+        let mut blobs = self.blobs.lock().unwrap();
+        let (_, pins) = blobs
+            .get_mut(&(args.id, args.hash))
+            .ok_or(HostError::BadResponse)?;
+        *pins += 1;
+        Ok(VolumeBlobPinResponse { pins: *pins })
+    }
+
+    async fn volume_blob_unpin(
+        &self,
+        args: VolumeBlobPinRequest,
+    ) -> Result<VolumeBlobPinResponse, HostError> {
+        let mut blobs = self.blobs.lock().unwrap();
+        let key = (args.id, args.hash);
+        let (_, pins) = blobs.get_mut(&key).ok_or(HostError::BadResponse)?;
+        *pins -= 1;
+        let pins = *pins;
+        if pins == 0 {
+            blobs.remove(&key);
+        }
+        Ok(VolumeBlobPinResponse { pins })
+    }
+}
+
+/// Consensus layer states served by the mock host, as raw state entries by height.
+#[derive(Default)]
+struct MockConsensus {
+    states: Mutex<BTreeMap<u64, BTreeMap<Vec<u8>, Vec<u8>>>>,
+}
+
+impl MockConsensus {
+    fn state(&self, height: u64) -> Result<ConsensusState, VerifierError> {
+        let states = self.states.lock().unwrap();
+        let entries = states
+            .get(&height)
+            .ok_or_else(|| VerifierError::StateRoot(anyhow::anyhow!("height not available")))?;
+        let mut tree = Tree::builder()
+            .with_root_type(RootType::State)
+            .build(Box::new(sync::NoopReadSyncer));
+        for (key, value) in entries {
+            tree.insert(key, value)
+                .map_err(|_| VerifierError::Internal)?;
+        }
+        Ok(ConsensusState::new(height, tree))
+    }
+
+    fn height(&self) -> u64 {
+        let states = self.states.lock().unwrap();
+        states.keys().next_back().copied().unwrap_or_default()
+    }
+}
+
+#[async_trait]
+impl Verifier for MockConsensus {
+    async fn sync(&self, _height: u64) -> Result<(), VerifierError> {
+        Ok(())
+    }
+
+    async fn verify(
+        &self,
+        consensus_block: LightBlock,
+        _runtime_header: Header,
+        _epoch: EpochTime,
+    ) -> Result<ConsensusState, VerifierError> {
+        self.state(consensus_block.height)
+    }
+
+    async fn verify_for_query(
+        &self,
+        consensus_block: LightBlock,
+        _runtime_header: Header,
+        _epoch: EpochTime,
+    ) -> Result<ConsensusState, VerifierError> {
+        self.state(consensus_block.height)
+    }
+
+    async fn unverified_state(
+        &self,
+        consensus_block: LightBlock,
+    ) -> Result<ConsensusState, VerifierError> {
+        self.state(consensus_block.height)
+    }
+
+    async fn latest_state(&self) -> Result<ConsensusState, VerifierError> {
+        self.state(self.height())
+    }
+
+    async fn state_at(&self, height: u64) -> Result<ConsensusState, VerifierError> {
+        self.state(height)
+    }
+
+    async fn events_at(&self, _height: u64, _kind: EventKind) -> Result<Vec<Event>, VerifierError> {
+        Ok(vec![])
+    }
+
+    async fn latest_height(&self) -> Result<u64, VerifierError> {
+        Ok(self.height())
+    }
+}
+
+fn volume_usage(files: &BTreeMap<(String, String), Vec<u8>>, id: &str) -> VolumeUsage {
+    let sizes: Vec<_> = files
+        .iter()
+        .filter(|((volume_id, _), _)| volume_id == id)
+        .map(|(_, data)| data.len() as u64)
+        .collect();
+    VolumeUsage {
+        bytes_used: sizes.iter().sum(),
+        bytes_quota: None,
+        inode_count: sizes.len() as u64,
+    }
+}
+
+/// Handler of host calls made via a [`LoopbackProtocol`], returning `None` for unsupported
+/// calls.
+pub type RequestHandler = Box<dyn Fn(Body) -> Option<Body> + Send + Sync>;
+
+/// State shared between the loopback transport and its owner.
+struct Loopback {
+    handler: RequestHandler,
+    /// Bytes written by the runtime which don't form a complete message yet.
+    written: Mutex<Vec<u8>>,
+    /// Bytes of responses not yet read by the runtime.
+    responses: Mutex<VecDeque<u8>>,
+    responses_cv: Condvar,
+    /// Requests made by the runtime so far.
+    requests: Mutex<Vec<Body>>,
+    closed: Mutex<bool>,
+}
+
+impl Loopback {
+    fn handle(&self, frame: &[u8]) -> io::Result<()> {
+        let decode = || -> io::Result<Message> {
+            cbor::from_slice(frame)
+                .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err.to_string()))
+        };
+        let message = decode()?;
+        if !matches!(message.message_type, MessageType::Request) {
+            // Cancellations and responses are not relevant, as requests are handled
+            // synchronously and the host makes no requests.
+            return Ok(());
+        }
+        // Message bodies can't be cloned, so the recorded request is decoded separately.
+        self.requests.lock().unwrap().push(decode()?.body);
+        let body = (self.handler)(message.body).unwrap_or_else(|| {
+            Body::Error(types::Error::new(
+                PROTOCOL_MODULE_NAME,
+                CODE_PERMANENT,
+                "method not supported",
+            ))
+        });
+
+        let buffer = cbor::to_vec(Message {
+            id: message.id,
+            message_type: MessageType::Response,
+            body,
+        });
+        let mut responses = self.responses.lock().unwrap();
+        responses
+            .write_u32::<BigEndian>(buffer.len() as u32)
+            .expect("writing to memory must succeed");
+        responses.extend(buffer);
+        self.responses_cv.notify_all();
+        Ok(())
+    }
+
+    fn close(&self) {
+        *self.closed.lock().unwrap() = true;
+        self.responses_cv.notify_all();
+    }
+}
+
+/// Transport answering requests via the loopback handler.
+struct LoopbackTransport(Arc<Loopback>);
+
+impl Transport for LoopbackTransport {
+    fn read(&self, buf: &mut [u8]) -> io::Result<usize> {
+        let mut responses = self.0.responses.lock().unwrap();
+        while responses.is_empty() {
+            if *self.0.closed.lock().unwrap() {
+                return Ok(0);
+            }
+            responses = self.0.responses_cv.wait(responses).unwrap();
+        }
+        let n = buf.len().min(responses.len());
+        for (dst, src) in buf.iter_mut().zip(responses.drain(..n)) {
+            *dst = src;
+        }
+        Ok(n)
+    }
+
+    fn write(&self, buf: &[u8]) -> io::Result<usize> {
+        let mut written = self.0.written.lock().unwrap();
+        written.extend_from_slice(buf);
+
+        // Handle all complete messages.
+        while written.len() >= 4 {
+            let length = (&written[..4]).read_u32::<BigEndian>()? as usize;
+            if written.len() < 4 + length {
+                break;
+            }
+            let frame: Vec<u8> = written.drain(..4 + length).skip(4).collect();
+            self.0.handle(&frame)?;
+        }
+
+        Ok(buf.len())
+    }
+
+    fn flush(&self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Protocol instance whose host calls are answered in process by a handler.
+///
+/// The protocol doesn't handle any requests from the host, so it can't be used to drive a
+/// dispatcher. Dropping the loopback stops the protocol reader, after which host calls fail.
+pub struct LoopbackProtocol {
+    protocol: Arc<Protocol>,
+    loopback: Arc<Loopback>,
+}
+
+impl LoopbackProtocol {
+    /// Create and start a new protocol instance in the given host environment, answering host
+    /// calls via the given handler.
+    pub fn new<F>(tokio_runtime: tokio::runtime::Handle, host_info: HostInfo, handler: F) -> Self
+    where
+        F: Fn(Body) -> Option<Body> + Send + Sync + 'static,
+    {
+        let loopback = Arc::new(Loopback {
+            handler: Box::new(handler),
+            written: Mutex::new(Vec::new()),
+            responses: Mutex::new(VecDeque::new()),
+            responses_cv: Condvar::new(),
+            requests: Mutex::new(Vec::new()),
+            closed: Mutex::new(false),
+        });
+        let protocol = Arc::new(Protocol::without_dispatcher(
+            tokio_runtime,
+            Box::new(LoopbackTransport(loopback.clone())),
+            Arc::new(Identity::new()),
+            Config::default(),
+            host_info,
+        ));
+
+        let runner = protocol.clone();
+        std::thread::spawn(move || runner.start());
+
+        Self { protocol, loopback }
+    }
+
+    /// The protocol instance.
+    pub fn protocol(&self) -> &Arc<Protocol> {
+        &self.protocol
+    }
+
+    /// Host calls made so far, in the order they were made.
+    pub fn requests(&self) -> Vec<Body> {
+        self.loopback.requests.lock().unwrap().clone()
+    }
+}
+
+impl Drop for LoopbackProtocol {
+    fn drop(&mut self) {
+        self.loopback.close();
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::common::{namespace::Namespace, version::Version};
+
+    #[test]
+    fn test_mock_host() {
+        let host = MockHost::new();
+        let mut block = AnnotatedBlock::default();
+        block.block.header.round = 5;
+        host.push_block(block);
+        host.on_submit_tx(|data, _| {
+            Ok(TxResult {
+                output: data.to_vec(),
+                ..Default::default()
+            })
+        });
+
+        let result = futures::executor::block_on(host.submit_tx(
+            b"echo".to_vec(),
+            SubmitTxOpts {
+                wait: true,
+                ..Default::default()
+            },
+        ))
+        .unwrap()
+        .unwrap();
+        assert_eq!(result.output, b"echo".to_vec());
+        let latest = futures::executor::block_on(host.runtime_block(None)).unwrap();
+        assert_eq!(latest.block.header.round, 5);
+        assert!(futures::executor::block_on(host.runtime_block(Some(4))).is_err());
+        assert_eq!(host.submitted_txs(), vec![b"echo".to_vec()]);
+    }
+
+    #[test]
+    fn test_loopback_protocol() {
+        let tokio_runtime = tokio::runtime::Runtime::new().unwrap();
+        let host_info = HostInfo {
+            runtime_id: Namespace::default(),
+            consensus_backend: "mock".to_string(),
+            consensus_protocol_version: Version::default(),
+            consensus_chain_context: MOCK_CHAIN_CONTEXT.to_string(),
+            local_config: BTreeMap::new(),
+            features: Default::default(),
+        };
+        let handler = |body: Body| match body {
+            Body::HostIdentityRequest {} => Some(Body::HostIdentityResponse {
+                node_id: PublicKey([7; 32]),
+            }),
+            _ => None,
+        };
+        let loopback = LoopbackProtocol::new(tokio_runtime.handle().clone(), host_info, handler);
+
+        let protocol = loopback.protocol();
+        let identity = futures::executor::block_on(protocol.identity()).unwrap();
+        assert_eq!(identity, PublicKey([7; 32]));
+        assert!(futures::executor::block_on(protocol.consensus_gas_price()).is_err());
+        assert_eq!(loopback.requests().len(), 2);
+    }
+}
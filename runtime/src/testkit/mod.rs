@@ -0,0 +1,462 @@
+//! Scripted round-level testing of runtimes.
+//!
+//! Testing how a runtime behaves across rounds (e.g. at epoch transitions or when the local node
+//! is no longer elected into the executor committee) otherwise requires a full devnet. The test
+//! kit instead drives a transaction dispatcher through scripted rounds in process, without a host:
+//! runtime state is kept in an in-memory tree across rounds, while the consensus layer state seen
+//! by the runtime (the current epoch and the executor committee) is synthesized from the script.
+//!
+//! Each executed round produces a [`RoundReport`] with the new block header and everything the
+//! round emitted, so that tests can assert on the resulting roots, outputs, tags and messages.
+//! Components which talk to the host can be tested against the in-memory [`host`] instead.
+//!
+//! The test kit is only available in tests and with the `test-utils` feature enabled.
+use std::sync::Arc;
+
+use thiserror::Error;
+
+use crate::{
+    common::{
+        crypto::{
+            hash::Hash,
+            signature::{PrivateKey, PublicKey},
+        },
+        namespace::Namespace,
+    },
+    config::Config,
+    consensus::{
+        beacon::{EpochTime, EpochTimeState},
+        registry::Runtime,
+        roothash::{
+            Block, Header, HeaderType, IncomingMessage, Message, RoundResults, RuntimeState,
+        },
+        scheduler::{Committee, CommitteeKind, CommitteeNode, Role},
+        state::{
+            beacon::MutableState as BeaconState, roothash::MutableState as RoothashState,
+            ConsensusState,
+        },
+        LightBlock,
+    },
+    future::new_tokio_runtime,
+    identity::Identity,
+    protocol::{HostInfo, Protocol},
+    replay::compute_results_header,
+    storage::mkvs::{sync::NoopReadSyncer, FallibleMKVS, OverlayTree, RootType, Tree, WriteLog},
+    transaction::{
+        dispatcher::Dispatcher as TxnDispatcher, tags::Tags, types::TxnBatch, Context as TxnContext,
+    },
+    types::Error as RuntimeError,
+};
+
+// Modules.
+pub mod host;
+
+/// The maximum number of messages that can be emitted in a round.
+pub const MAX_MESSAGES: u32 = 256;
+
+/// Test kit errors.
+#[derive(Error, Debug)]
+pub enum TestKitError {
+    #[error("local node is not an executor in round {0}")]
+    NotExecutor(u64),
+
+    #[error("execution failed: {0}")]
+    Execution(#[from] RuntimeError),
+
+    #[error("state error: {0}")]
+    State(#[source] anyhow::Error),
+}
+
+/// A step of a test script.
+#[derive(Clone, Debug)]
+pub enum Step {
+    /// Execute a round with the given batch and incoming messages.
+    Batch {
+        inputs: TxnBatch,
+        in_msgs: Vec<IncomingMessage>,
+    },
+    /// Transition to the next epoch.
+    AdvanceEpoch,
+    /// Elect the local node into the executor committee with the given roles. An empty list
+    /// removes the local node from the committee.
+    SetRoles(Vec<Role>),
+}
+
+/// Report of an executed round.
+#[derive(Clone, Debug)]
+pub struct RoundReport {
+    /// Epoch in which the round was executed.
+    pub epoch: EpochTime,
+    /// Header of the block resulting from the round.
+    pub header: Header,
+    /// Per-transaction outputs, in batch order.
+    pub outputs: Vec<Vec<u8>>,
+    /// Per-transaction emitted tags, in batch order.
+    pub tags: Vec<Tags>,
+    /// Block emitted tags.
+    pub block_tags: Tags,
+    /// Emitted runtime messages.
+    pub messages: Vec<Message>,
+    /// Number of processed incoming messages.
+    pub in_msgs_count: usize,
+    /// Changes to the runtime state.
+    pub write_log: WriteLog,
+}
+
+/// In-process driver of scripted runtime rounds.
+pub struct TestKit {
+    protocol: Arc<Protocol>,
+    txn_dispatcher: Box<dyn TxnDispatcher>,
+    tokio_runtime: tokio::runtime::Runtime,
+    state: Tree,
+    header: Header,
+    height: u64,
+    epoch: EpochTime,
+    epoch_height: u64,
+    node_id: PublicKey,
+    roles: Vec<Role>,
+}
+
+impl TestKit {
+    /// Create a new test kit using the given runtime configuration, host environment and
+    /// transaction dispatcher.
+    ///
+    /// Rounds start from the genesis block of the runtime in epoch zero, with the local node being
+    /// the only worker of the executor committee.
+    pub fn new(
+        config: Config,
+        host_info: HostInfo,
+        txn_dispatcher: Box<dyn TxnDispatcher>,
+    ) -> Self {
+        let runtime_id = host_info.runtime_id;
+        let tokio_runtime = new_tokio_runtime();
+        let protocol = Arc::new(Protocol::offline(
+            tokio_runtime.handle().clone(),
+            Arc::new(Identity::new()),
+            config,
+            host_info,
+        ));
+        let state = Tree::builder()
+            .with_root_type(RootType::State)
+            .build(Box::new(NoopReadSyncer));
+
+        Self {
+            protocol,
+            txn_dispatcher,
+            tokio_runtime,
+            state,
+            header: Block::new_genesis_block(runtime_id, 0).header,
+            height: 1,
+            epoch: 0,
+            epoch_height: 1,
+            node_id: PrivateKey::from_test_seed("testkit node".to_string()).public_key(),
+            roles: vec![Role::Worker],
+        }
+    }
+
+    /// Header of the latest block.
+    pub fn header(&self) -> &Header {
+        &self.header
+    }
+
+    /// Current epoch.
+    pub fn epoch(&self) -> EpochTime {
+        self.epoch
+    }
+
+    /// Identifier of the local node.
+    pub fn node_id(&self) -> PublicKey {
+        self.node_id
+    }
+
+    /// Current executor committee.
+    pub fn committee(&self) -> Committee {
+        Committee {
+            kind: CommitteeKind::ComputeExecutor,
+            members: self
+                .roles
+                .iter()
+                .map(|role| CommitteeNode {
+                    role: role.clone(),
+                    public_key: self.node_id,
+                })
+                .collect(),
+            runtime_id: self.runtime_id(),
+            valid_for: self.epoch,
+        }
+    }
+
+    /// Fetch the value of the given key from the runtime state of the latest block.
+    pub fn get(&self, key: &[u8]) -> Option<Vec<u8>> {
+        self.state
+            .get(key)
+            .expect("in-memory state should always be available")
+    }
+
+    /// Transition to the next epoch.
+    pub fn advance_epoch(&mut self) {
+        self.epoch += 1;
+        self.height += 1;
+        self.epoch_height = self.height;
+    }
+
+    /// Elect the local node into the executor committee with the given roles.
+    pub fn set_roles(&mut self, roles: Vec<Role>) {
+        self.roles = roles;
+    }
+
+    /// Execute a round with the given batch and incoming messages on top of the latest block.
+    ///
+    /// Rounds are only executed in case the local node is a worker or a backup worker of the
+    /// executor committee, as other nodes never execute batches.
+    pub fn execute_batch(
+        &mut self,
+        inputs: TxnBatch,
+        in_msgs: Vec<IncomingMessage>,
+    ) -> Result<RoundReport, TestKitError> {
+        if !self
+            .roles
+            .iter()
+            .any(|role| matches!(role, Role::Worker | Role::BackupWorker))
+        {
+            return Err(TestKitError::NotExecutor(self.header.round + 1));
+        }
+
+        let _guard = self.tokio_runtime.enter();
+        self.height += 1;
+        let consensus_block = LightBlock {
+            height: self.height,
+            meta: vec![],
+        };
+        let consensus_state = self.consensus_state()?;
+        let round_results = RoundResults::default();
+
+        let mut overlay = OverlayTree::new(&mut self.state);
+        let txn_ctx = TxnContext::new(
+            self.protocol.clone(),
+            &consensus_block,
+            consensus_state,
+            &mut overlay,
+            &self.header,
+            self.epoch,
+            &round_results,
+            MAX_MESSAGES,
+            false,
+        );
+        let results = self
+            .txn_dispatcher
+            .execute_batch(txn_ctx, &inputs, &in_msgs)?;
+        let (write_log, state_root) = overlay
+            .commit_both(self.header.namespace, self.header.round + 1)
+            .map_err(TestKitError::State)?;
+
+        let computed =
+            compute_results_header(&self.header, &inputs, &in_msgs, &results, state_root)
+                .map_err(TestKitError::State)?;
+        self.header = Header {
+            version: self.header.version,
+            namespace: self.header.namespace,
+            round: computed.round,
+            timestamp: self.header.timestamp + 1,
+            header_type: HeaderType::Normal,
+            previous_hash: computed.previous_hash,
+            io_root: computed.io_root.unwrap_or_else(Hash::empty_hash),
+            state_root,
+            messages_hash: computed.messages_hash.unwrap_or_else(Hash::empty_hash),
+            in_msgs_hash: computed.in_msgs_hash.unwrap_or_else(Hash::empty_hash),
+        };
+
+        let (outputs, tags) = results
+            .results
+            .into_iter()
+            .map(|result| (result.output, result.tags))
+            .unzip();
+
+        Ok(RoundReport {
+            epoch: self.epoch,
+            header: self.header.clone(),
+            outputs,
+            tags,
+            block_tags: results.block_tags,
+            messages: results.messages,
+            in_msgs_count: results.in_msgs_count,
+            write_log,
+        })
+    }
+
+    /// Run the given script, returning the reports of all executed rounds.
+    ///
+    /// The script is aborted at the first round that fails.
+    pub fn run(&mut self, script: Vec<Step>) -> Result<Vec<RoundReport>, TestKitError> {
+        let mut reports = Vec::new();
+        for step in script {
+            match step {
+                Step::Batch { inputs, in_msgs } => {
+                    reports.push(self.execute_batch(inputs, in_msgs)?);
+                }
+                Step::AdvanceEpoch => self.advance_epoch(),
+                Step::SetRoles(roles) => self.set_roles(roles),
+            }
+        }
+        Ok(reports)
+    }
+
+    fn runtime_id(&self) -> Namespace {
+        self.header.namespace
+    }
+
+    /// Synthesize the consensus layer state as of the current height.
+    fn consensus_state(&self) -> Result<ConsensusState, TestKitError> {
+        let mut tree = Tree::builder()
+            .with_root_type(RootType::State)
+            .build(Box::new(NoopReadSyncer));
+
+        BeaconState::set_epoch_state(
+            &mut tree,
+            EpochTimeState {
+                epoch: self.epoch,
+                height: self.epoch_height as i64,
+            },
+        )
+        .map_err(|err| TestKitError::State(err.into()))?;
+        RoothashState::set_runtime_state(
+            &mut tree,
+            RuntimeState {
+                runtime: Runtime {
+                    id: self.runtime_id(),
+                    ..Default::default()
+                },
+                last_block: Block {
+                    header: self.header.clone(),
+                },
+                last_block_height: self.height as i64 - 1,
+                commitee: Some(self.committee()),
+                ..Default::default()
+            },
+        )
+        .map_err(|err| TestKitError::State(err.into()))?;
+
+        Ok(ConsensusState::new(self.height, tree))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{
+        consensus::state::{beacon::ImmutableState as BeaconImmutableState, StateError},
+        transaction::{
+            dispatcher::{ExecuteBatchResult, ExecuteTxResult},
+            tags::Tag,
+        },
+        types::CheckTxResult,
+    };
+
+    /// Dispatcher which stores every transaction in the state and echoes the epoch it observes
+    /// in the consensus layer state.
+    struct EpochDispatcher;
+
+    impl TxnDispatcher for EpochDispatcher {
+        fn execute_batch(
+            &self,
+            mut ctx: TxnContext,
+            batch: &TxnBatch,
+            in_msgs: &[IncomingMessage],
+        ) -> Result<ExecuteBatchResult, RuntimeError> {
+            let epoch = BeaconImmutableState::new(&ctx.consensus_state)
+                .epoch()
+                .map_err(|err: StateError| RuntimeError::new("test", 1, &err.to_string()))?;
+            let results = batch
+                .iter()
+                .map(|tx| {
+                    ctx.runtime_state.insert(tx, tx);
+                    ExecuteTxResult {
+                        output: epoch.to_le_bytes().to_vec(),
+                        tags: vec![Tag::new(b"tx".to_vec(), tx.clone())],
+                    }
+                })
+                .collect();
+
+            Ok(ExecuteBatchResult {
+                results,
+                messages: vec![],
+                in_msgs_count: in_msgs.len(),
+                block_tags: vec![Tag::new(b"round".to_vec(), vec![])],
+                tx_reject_hashes: vec![],
+            })
+        }
+
+        fn check_batch(
+            &self,
+            _ctx: TxnContext,
+            _batch: &TxnBatch,
+        ) -> Result<Vec<CheckTxResult>, RuntimeError> {
+            Ok(vec![])
+        }
+    }
+
+    fn testkit() -> TestKit {
+        TestKit::new(
+            Config::default(),
+            HostInfo {
+                runtime_id: Default::default(),
+                consensus_backend: "tendermint".to_string(),
+                consensus_protocol_version: Default::default(),
+                consensus_chain_context: "test".to_string(),
+                local_config: Default::default(),
+                features: Default::default(),
+            },
+            Box::new(EpochDispatcher),
+        )
+    }
+
+    fn batch(txs: &[&[u8]]) -> Step {
+        Step::Batch {
+            inputs: txs.iter().map(|tx| tx.to_vec()).collect::<Vec<_>>().into(),
+            in_msgs: vec![],
+        }
+    }
+
+    #[test]
+    fn test_scripted_rounds() {
+        let mut kit = testkit();
+        let genesis = kit.header().clone();
+
+        let reports = kit
+            .run(vec![
+                batch(&[b"tx1", b"tx2"]),
+                Step::AdvanceEpoch,
+                batch(&[b"tx3"]),
+            ])
+            .unwrap();
+        assert_eq!(reports.len(), 2);
+
+        // Rounds are chained and observe the scripted epoch.
+        assert_eq!(reports[0].header.round, 1);
+        assert_eq!(reports[0].header.previous_hash, genesis.encoded_hash());
+        assert_eq!(reports[0].outputs, vec![0u64.to_le_bytes().to_vec(); 2]);
+        assert_eq!(reports[0].tags[1][0].value, b"tx2".to_vec());
+        assert_eq!(reports[0].block_tags.len(), 1);
+        assert_eq!(reports[0].write_log.len(), 2);
+        assert_eq!(reports[1].header.round, 2);
+        assert_eq!(
+            reports[1].header.previous_hash,
+            reports[0].header.encoded_hash()
+        );
+        assert_eq!(reports[1].epoch, 1);
+        assert_eq!(reports[1].outputs, vec![1u64.to_le_bytes().to_vec()]);
+        assert_ne!(reports[1].header.state_root, reports[0].header.state_root);
+        assert_eq!(kit.header(), &reports[1].header);
+        assert_eq!(kit.get(b"tx1"), Some(b"tx1".to_vec()));
+
+        // Nodes which are not executors don't execute rounds.
+        let result = kit.run(vec![Step::SetRoles(vec![]), batch(&[b"tx4"])]);
+        assert!(matches!(result, Err(TestKitError::NotExecutor(3))));
+        assert!(kit.committee().members.is_empty());
+
+        kit.set_roles(vec![Role::BackupWorker]);
+        let reports = kit.run(vec![batch(&[b"tx4"])]).unwrap();
+        assert_eq!(reports[0].header.round, 3);
+        assert_eq!(kit.get(b"tx4"), Some(b"tx4".to_vec()));
+    }
+}
@@ -0,0 +1,197 @@
+//! Inbound call authentication.
+//!
+//! Permissioned runtimes may require callers to attach credentials (e.g. certificates or session
+//! tokens) to their calls. Instead of verifying these as part of application-level transaction
+//! handling, an [`Authenticator`] can be configured which is invoked on the raw transactions
+//! before they reach the transaction dispatcher. Transactions that fail authentication never
+//! reach the dispatcher: they are rejected during checks and dropped from scheduled batches,
+//! while batches containing them are refused in execution mode. The authentication results of
+//! the remaining transactions are exposed to the dispatcher via [`Context::call_auth`].
+use std::{
+    collections::BTreeMap,
+    sync::{atomic::AtomicBool, Arc},
+};
+
+use super::{
+    context::Context,
+    dispatcher::{Dispatcher, ExecuteBatchResult},
+    types::TxnBatch,
+};
+use crate::{
+    common::crypto::hash::Hash,
+    consensus::roothash,
+    types::{CheckTxResult, Error as RuntimeError},
+};
+
+/// Error module used for rejected calls.
+const MODULE_NAME: &str = "rhp/auth";
+/// Error code used for rejected calls.
+const CODE_UNAUTHENTICATED: u32 = 1;
+
+fn unauthenticated(reason: &str) -> RuntimeError {
+    RuntimeError::new(
+        MODULE_NAME,
+        CODE_UNAUTHENTICATED,
+        &format!("unauthenticated: {reason}"),
+    )
+}
+
+/// An authenticated caller.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Caller {
+    /// Caller identifier, as defined by the authenticator.
+    pub id: Vec<u8>,
+    /// Additional verified attributes of the caller (e.g. roles).
+    pub attributes: BTreeMap<String, String>,
+}
+
+/// Result of authenticating a call.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum CallAuth {
+    /// The call doesn't carry any credentials.
+    Anonymous,
+    /// The call has been made by the given caller.
+    Authenticated(Caller),
+    /// The call carries invalid credentials or is not allowed.
+    Rejected(String),
+}
+
+/// Authenticator of inbound calls.
+pub trait Authenticator: Send + Sync {
+    /// Authenticate the given raw transaction.
+    ///
+    /// The context may be used to consult the runtime or consensus state (e.g. for revocations).
+    fn authenticate(&self, ctx: &Context, tx: &[u8]) -> CallAuth;
+}
+
+/// Transaction dispatcher wrapper which authenticates all calls before dispatching them.
+pub struct AuthenticatingDispatcher {
+    inner: Box<dyn Dispatcher>,
+    authenticator: Box<dyn Authenticator>,
+}
+
+impl AuthenticatingDispatcher {
+    /// Wrap the given dispatcher, authenticating calls with the given authenticator.
+    pub fn new(inner: Box<dyn Dispatcher>, authenticator: Box<dyn Authenticator>) -> Self {
+        Self {
+            inner,
+            authenticator,
+        }
+    }
+
+    fn authenticate_batch(&self, ctx: &Context, batch: &TxnBatch) -> Vec<CallAuth> {
+        batch
+            .iter()
+            .map(|tx| self.authenticator.authenticate(ctx, tx))
+            .collect()
+    }
+}
+
+impl Dispatcher for AuthenticatingDispatcher {
+    fn is_supported(&self) -> bool {
+        self.inner.is_supported()
+    }
+
+    fn execute_batch(
+        &self,
+        mut ctx: Context,
+        batch: &TxnBatch,
+        in_msgs: &[roothash::IncomingMessage],
+    ) -> Result<ExecuteBatchResult, RuntimeError> {
+        // Scheduled batches have already been authenticated, so rejected calls can only be
+        // included by a faulty scheduler. Refuse to execute such batches.
+        let call_auth = self.authenticate_batch(&ctx, batch);
+        if let Some(reason) = call_auth.iter().find_map(|auth| match auth {
+            CallAuth::Rejected(reason) => Some(reason),
+            _ => None,
+        }) {
+            return Err(unauthenticated(reason));
+        }
+
+        ctx.call_auth = call_auth;
+        self.inner.execute_batch(ctx, batch, in_msgs)
+    }
+
+    fn schedule_and_execute_batch(
+        &self,
+        mut ctx: Context,
+        initial_batch: &mut TxnBatch,
+        in_msgs: &[roothash::IncomingMessage],
+    ) -> Result<ExecuteBatchResult, RuntimeError> {
+        // Drop rejected calls from the batch before it reaches the inner dispatcher.
+        let call_auth = self.authenticate_batch(&ctx, initial_batch);
+        let mut rejected = Vec::new();
+        let mut accepted_auth = Vec::new();
+        let accepted: Vec<_> = std::mem::take(initial_batch)
+            .0
+            .into_iter()
+            .zip(call_auth)
+            .filter_map(|(tx, auth)| match auth {
+                CallAuth::Rejected(_) => {
+                    rejected.push(Hash::digest_bytes(&tx));
+                    None
+                }
+                auth => {
+                    accepted_auth.push(auth);
+                    Some(tx)
+                }
+            })
+            .collect();
+        *initial_batch = accepted.into();
+        ctx.call_auth = accepted_auth;
+
+        let mut result = self
+            .inner
+            .schedule_and_execute_batch(ctx, initial_batch, in_msgs)?;
+        result.tx_reject_hashes.extend(rejected);
+        Ok(result)
+    }
+
+    fn check_batch(
+        &self,
+        mut ctx: Context,
+        batch: &TxnBatch,
+    ) -> Result<Vec<CheckTxResult>, RuntimeError> {
+        let call_auth = self.authenticate_batch(&ctx, batch);
+        if !call_auth
+            .iter()
+            .any(|auth| matches!(auth, CallAuth::Rejected(_)))
+        {
+            ctx.call_auth = call_auth;
+            return self.inner.check_batch(ctx, batch);
+        }
+
+        // Reject unauthenticated calls before they reach the inner dispatcher.
+        let (accepted, accepted_auth): (Vec<_>, Vec<_>) = batch
+            .iter()
+            .zip(&call_auth)
+            .filter(|(_, auth)| !matches!(auth, CallAuth::Rejected(_)))
+            .map(|(tx, auth)| (tx.clone(), auth.clone()))
+            .unzip();
+        ctx.call_auth = accepted_auth;
+        let mut results = self.inner.check_batch(ctx, &accepted.into())?.into_iter();
+
+        Ok(call_auth
+            .into_iter()
+            .filter_map(|auth| match auth {
+                CallAuth::Rejected(reason) => Some(CheckTxResult {
+                    error: unauthenticated(&reason),
+                    meta: None,
+                }),
+                _ => results.next(),
+            })
+            .collect())
+    }
+
+    fn finalize(&self, new_storage_root: Hash) {
+        self.inner.finalize(new_storage_root)
+    }
+
+    fn set_abort_batch_flag(&mut self, abort_batch: Arc<AtomicBool>) {
+        self.inner.set_abort_batch_flag(abort_batch)
+    }
+
+    fn query(&self, ctx: Context, method: &str, args: Vec<u8>) -> Result<Vec<u8>, RuntimeError> {
+        self.inner.query(ctx, method, args)
+    }
+}
@@ -12,6 +12,8 @@ use crate::{
     storage::MKVS,
 };
 
+use super::{authenticator::CallAuth, deferred::DeferredQueue};
+
 /// Transaction context.
 pub struct Context<'a> {
     /// Low-level access to the underlying Runtime Host Protocol.
@@ -33,6 +35,19 @@ pub struct Context<'a> {
     /// Flag indicating whether to only perform transaction check rather than
     /// running the transaction.
     pub check_only: bool,
+    /// Authentication results of the transactions in the batch, in batch order.
+    ///
+    /// This is empty in case no authenticator is configured. Rejected transactions never reach
+    /// the dispatcher, so no entry is `CallAuth::Rejected`. In scheduling mode it only covers
+    /// the initial batch, and any transactions the dispatcher schedules in addition to it must
+    /// be authenticated by the dispatcher itself.
+    pub call_auth: Vec<CallAuth>,
+    /// Actions deferred until all transactions in the batch have been executed.
+    ///
+    /// Deferred actions are only run when executing a batch, they are discarded in check and
+    /// query contexts. Dispatchers must scope the actions to each transaction, discarding them
+    /// in case the transaction is rejected (see [`DeferredQueue::begin_tx`]).
+    pub deferred: DeferredQueue,
 }
 
 impl<'a> Context<'a> {
@@ -59,6 +74,8 @@ impl<'a> Context<'a> {
             round_results,
             max_messages,
             check_only,
+            call_auth: Vec::new(),
+            deferred: DeferredQueue::new(),
         }
     }
 }
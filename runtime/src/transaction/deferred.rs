@@ -0,0 +1,196 @@
+//! Actions deferred until the end of the round.
+//!
+//! Transactions that all update the same bookkeeping keys (e.g. fee accumulators or aggregated
+//! transfers) can enqueue an action instead of updating the keys themselves. Queued actions run
+//! once all transactions in the batch have been executed, before the state is committed, in the
+//! order in which they were first enqueued. Block tags emitted by the actions are added to the
+//! block tags of the batch.
+//!
+//! Dispatchers scope the actions to the transaction enqueueing them by calling
+//! [`DeferredQueue::begin_tx`] before executing each transaction, and either
+//! [`DeferredQueue::commit_tx`] or, in case the transaction is rejected or fails,
+//! [`DeferredQueue::discard_tx`] after it. Actions enqueued outside of a transaction scope are
+//! always kept.
+use std::{
+    collections::HashSet,
+    sync::{Arc, Mutex},
+};
+
+use crate::{storage::MKVS, types::Error as RuntimeError};
+
+use super::tags::Tags;
+
+/// An action deferred until the end of the round.
+pub type DeferredAction =
+    Box<dyn FnOnce(&mut dyn MKVS) -> Result<Tags, RuntimeError> + Send + 'static>;
+
+#[derive(Default)]
+struct Inner {
+    actions: Vec<DeferredAction>,
+    keys: HashSet<Vec<u8>>,
+    tx: Option<TxScope>,
+}
+
+/// Actions and keys enqueued by the transaction currently being executed.
+struct TxScope {
+    /// Number of actions enqueued before the transaction started.
+    actions: usize,
+    /// Keys first enqueued by the transaction.
+    keys: Vec<Vec<u8>>,
+}
+
+/// Queue of actions deferred until the end of the round.
+///
+/// The queue is a shared handle, so clones refer to the same queue.
+#[derive(Clone, Default)]
+pub struct DeferredQueue {
+    inner: Arc<Mutex<Inner>>,
+}
+
+impl DeferredQueue {
+    /// Create a new empty queue.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Enqueue an action.
+    pub fn defer<F>(&self, action: F)
+    where
+        F: FnOnce(&mut dyn MKVS) -> Result<Tags, RuntimeError> + Send + 'static,
+    {
+        self.inner.lock().unwrap().actions.push(Box::new(action));
+    }
+
+    /// Enqueue an action unless an action with the same key has already been enqueued in this
+    /// round.
+    ///
+    /// Returns true in case the action has been enqueued. This allows transactions to accumulate
+    /// changes in runtime-specific structures and register a single action applying all of them.
+    pub fn defer_once<F>(&self, key: &[u8], action: F) -> bool
+    where
+        F: FnOnce(&mut dyn MKVS) -> Result<Tags, RuntimeError> + Send + 'static,
+    {
+        let mut inner = self.inner.lock().unwrap();
+        if !inner.keys.insert(key.to_vec()) {
+            return false;
+        }
+        if let Some(tx) = inner.tx.as_mut() {
+            tx.keys.push(key.to_vec());
+        }
+        inner.actions.push(Box::new(action));
+        true
+    }
+
+    /// Start scoping enqueued actions to a new transaction.
+    ///
+    /// Any actions of a previous transaction that has been neither committed nor discarded are
+    /// kept.
+    pub fn begin_tx(&self) {
+        let mut inner = self.inner.lock().unwrap();
+        inner.tx = Some(TxScope {
+            actions: inner.actions.len(),
+            keys: Vec::new(),
+        });
+    }
+
+    /// Keep all actions enqueued by the current transaction.
+    pub fn commit_tx(&self) {
+        self.inner.lock().unwrap().tx = None;
+    }
+
+    /// Drop all actions enqueued by the current transaction, e.g. because it has been rejected.
+    pub fn discard_tx(&self) {
+        let mut inner = self.inner.lock().unwrap();
+        if let Some(tx) = inner.tx.take() {
+            inner.actions.truncate(tx.actions);
+            for key in tx.keys {
+                inner.keys.remove(&key);
+            }
+        }
+    }
+
+    /// Number of queued actions.
+    pub fn len(&self) -> usize {
+        self.inner.lock().unwrap().actions.len()
+    }
+
+    /// Whether the queue is empty.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Run all queued actions against the given state, in order, emptying the queue.
+    ///
+    /// Returns the block tags emitted by the actions. Stops at the first failing action.
+    pub fn run(&self, state: &mut dyn MKVS) -> Result<Tags, RuntimeError> {
+        let actions = {
+            let mut inner = self.inner.lock().unwrap();
+            inner.keys.clear();
+            inner.tx = None;
+            std::mem::take(&mut inner.actions)
+        };
+
+        let mut tags = Tags::new();
+        for action in actions {
+            tags.extend(action(state)?);
+        }
+        Ok(tags)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{
+        storage::mkvs::{sync::NoopReadSyncer, OverlayTree, RootType, Tree},
+        transaction::tags::Tag,
+    };
+
+    #[test]
+    fn test_deferred_queue() {
+        let tree = Tree::builder()
+            .with_root_type(RootType::State)
+            .build(Box::new(NoopReadSyncer));
+        let mut state = OverlayTree::new(tree);
+
+        let queue = DeferredQueue::new();
+        let fees = Arc::new(Mutex::new(0u8));
+        for fee in 1..=3u8 {
+            *fees.lock().unwrap() += fee;
+            let fees = fees.clone();
+            queue.defer_once(b"fees", move |state| {
+                state.insert(b"fees", &[*fees.lock().unwrap()]);
+                Ok(vec![Tag::new(b"fees".to_vec(), vec![])])
+            });
+        }
+        queue.defer(|state| {
+            // Actions run in order.
+            assert_eq!(state.get(b"fees"), Some(vec![6]));
+            state.insert(b"done", b"");
+            Ok(vec![])
+        });
+        assert_eq!(queue.len(), 2);
+
+        let tags = queue.clone().run(&mut state).unwrap();
+        assert_eq!(tags.len(), 1);
+        assert!(queue.is_empty());
+        assert_eq!(state.get(b"done").unwrap(), Some(vec![]));
+
+        // Actions of discarded transactions are dropped, including their keys.
+        queue.begin_tx();
+        queue.defer(|_| Err(RuntimeError::new("test", 1, "rejected")));
+        assert!(queue.defer_once(b"fees", |_| Err(RuntimeError::new("test", 1, "rejected"))));
+        queue.discard_tx();
+        assert!(queue.is_empty());
+        queue.begin_tx();
+        assert!(queue.defer_once(b"fees", |_| Ok(vec![])));
+        queue.commit_tx();
+        queue.discard_tx();
+        assert_eq!(queue.len(), 1);
+        assert!(queue.run(&mut state).unwrap().is_empty());
+
+        // Keys are reset for the next round.
+        assert!(queue.defer_once(b"fees", |_| Err(RuntimeError::new("test", 1, "failed"))));
+        assert!(queue.run(&mut state).is_err());
+    }
+}
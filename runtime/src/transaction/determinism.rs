@@ -0,0 +1,263 @@
+//! Canonical ordering of state assembled during execution.
+//!
+//! All nodes executing a batch must produce identical write logs and events. Iterating over a
+//! `HashMap` or `HashSet` yields an order which depends on the randomly seeded hasher of each
+//! instance, so any such order leaking into storage writes or emitted tags makes the nodes
+//! diverge. The collections in this module iterate in a canonical order regardless of how they
+//! were filled, and [`check_deterministic`] evaluates state assembly twice while checks are
+//! enabled (by default in debug builds), so that leaked iteration orders are caught by tests.
+use std::{
+    collections::{BTreeMap, HashMap},
+    fmt,
+    hash::Hash as StdHash,
+    ops::{Deref, DerefMut},
+    sync::atomic::{AtomicBool, Ordering},
+};
+
+use super::tags::{Tag, Tags};
+
+/// Whether determinism checks are enabled.
+static CHECKS_ENABLED: AtomicBool = AtomicBool::new(cfg!(debug_assertions));
+
+/// Enable or disable determinism checks.
+///
+/// Checks are enabled by default in debug builds only.
+pub fn set_checks_enabled(enabled: bool) {
+    CHECKS_ENABLED.store(enabled, Ordering::SeqCst);
+}
+
+/// Whether determinism checks are enabled.
+pub fn checks_enabled() -> bool {
+    CHECKS_ENABLED.load(Ordering::SeqCst)
+}
+
+/// Evaluate the given function assembling state.
+///
+/// While checks are enabled, the function is evaluated twice and the results are compared, which
+/// catches iteration orders of hash-based collections created by the function leaking into its
+/// result. The function must therefore not have any side effects.
+///
+/// # Panics
+///
+/// Panics in case checks are enabled and the results of both evaluations differ.
+pub fn check_deterministic<T, F>(what: &str, f: F) -> T
+where
+    T: PartialEq + fmt::Debug,
+    F: Fn() -> T,
+{
+    let result = f();
+    if checks_enabled() {
+        let again = f();
+        assert_eq!(result, again, "nondeterministic {what}");
+    }
+    result
+}
+
+/// Check that the given keys, e.g. of a write log, are in canonical (strictly ascending) order.
+///
+/// # Panics
+///
+/// Panics in case checks are enabled and the keys are not in canonical order.
+pub fn check_canonical_order<'a, I>(what: &str, keys: I)
+where
+    I: IntoIterator<Item = &'a [u8]>,
+{
+    if !checks_enabled() {
+        return;
+    }
+
+    let mut last: Option<&[u8]> = None;
+    for key in keys {
+        if let Some(last) = last {
+            assert!(
+                last < key,
+                "{what} not in canonical order: {key:?} after {last:?}"
+            );
+        }
+        last = Some(key);
+    }
+}
+
+/// A map iterating in key order.
+///
+/// A thin wrapper around `BTreeMap` which makes the ordering requirement explicit in signatures
+/// of code assembling state, and which converts from hash-based maps in canonical order.
+#[derive(Clone, PartialEq, Eq)]
+pub struct DetBTreeMap<K: Ord, V>(BTreeMap<K, V>);
+
+impl<K: Ord, V> DetBTreeMap<K, V> {
+    /// Create a new empty map.
+    pub fn new() -> Self {
+        Self(BTreeMap::new())
+    }
+
+    /// Unwrap the underlying map.
+    pub fn into_inner(self) -> BTreeMap<K, V> {
+        self.0
+    }
+}
+
+impl<K: Ord, V> Default for DetBTreeMap<K, V> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<K: Ord + fmt::Debug, V: fmt::Debug> fmt::Debug for DetBTreeMap<K, V> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
+impl<K: Ord, V> Deref for DetBTreeMap<K, V> {
+    type Target = BTreeMap<K, V>;
+
+    fn deref(&self) -> &Self::Target {
+        &self.0
+    }
+}
+
+impl<K: Ord, V> DerefMut for DetBTreeMap<K, V> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.0
+    }
+}
+
+impl<K: Ord, V> From<BTreeMap<K, V>> for DetBTreeMap<K, V> {
+    fn from(map: BTreeMap<K, V>) -> Self {
+        Self(map)
+    }
+}
+
+impl<K: Ord + StdHash, V, S> From<HashMap<K, V, S>> for DetBTreeMap<K, V> {
+    fn from(map: HashMap<K, V, S>) -> Self {
+        map.into_iter().collect()
+    }
+}
+
+impl<K: Ord, V> FromIterator<(K, V)> for DetBTreeMap<K, V> {
+    fn from_iter<I: IntoIterator<Item = (K, V)>>(iter: I) -> Self {
+        Self(iter.into_iter().collect())
+    }
+}
+
+impl<K: Ord, V> IntoIterator for DetBTreeMap<K, V> {
+    type Item = (K, V);
+    type IntoIter = std::collections::btree_map::IntoIter<K, V>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.0.into_iter()
+    }
+}
+
+impl<'a, K: Ord, V> IntoIterator for &'a DetBTreeMap<K, V> {
+    type Item = (&'a K, &'a V);
+    type IntoIter = std::collections::btree_map::Iter<'a, K, V>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.0.iter()
+    }
+}
+
+/// Buffer of tags emitted by the transactions of a batch.
+///
+/// Tags may be pushed in any order (e.g. as parallel workers complete), but are always returned
+/// ordered by the index of the emitting transaction within the batch, with the tags of the same
+/// transaction in emission order.
+#[derive(Clone, Debug, Default)]
+pub struct EventBuffer {
+    tags: BTreeMap<usize, Tags>,
+}
+
+impl EventBuffer {
+    /// Create a new empty buffer.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add a tag emitted by the transaction with the given index.
+    pub fn push(&mut self, index: usize, tag: Tag) {
+        self.tags.entry(index).or_default().push(tag);
+    }
+
+    /// Add tags emitted by the transaction with the given index, in emission order.
+    pub fn extend(&mut self, index: usize, tags: Tags) {
+        self.tags.entry(index).or_default().extend(tags);
+    }
+
+    /// Number of buffered tags.
+    pub fn len(&self) -> usize {
+        self.tags.values().map(Vec::len).sum()
+    }
+
+    /// Whether the buffer is empty.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Buffered tags, in canonical order.
+    pub fn into_tags(self) -> Tags {
+        self.tags.into_values().flatten().collect()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::HashSet;
+
+    use super::*;
+
+    #[test]
+    fn test_det_btree_map() {
+        let map: HashMap<u64, u64> = (0..100).map(|i| (i, i * 2)).collect();
+        let map = DetBTreeMap::from(map);
+        let keys: Vec<_> = map.keys().copied().collect();
+        assert_eq!(keys, (0..100).collect::<Vec<_>>());
+        assert_eq!(map[&42], 84);
+    }
+
+    #[test]
+    fn test_event_buffer() {
+        let mut buffer = EventBuffer::new();
+        assert!(buffer.is_empty());
+        buffer.push(2, Tag::new(b"c".to_vec(), vec![]));
+        buffer.extend(
+            0,
+            vec![
+                Tag::new(b"a".to_vec(), vec![]),
+                Tag::new(b"b".to_vec(), vec![]),
+            ],
+        );
+        buffer.push(2, Tag::new(b"d".to_vec(), vec![]));
+        assert_eq!(buffer.len(), 4);
+
+        let keys: Vec<_> = buffer.into_tags().into_iter().map(|tag| tag.key).collect();
+        assert_eq!(keys, vec![b"a", b"b", b"c", b"d"]);
+    }
+
+    #[test]
+    fn test_check_deterministic() {
+        set_checks_enabled(true);
+
+        let keys = check_deterministic("keys", || {
+            let set: HashSet<u64> = (0..100).collect();
+            DetBTreeMap::from_iter(set.into_iter().map(|k| (k, ()))).into_inner()
+        });
+        assert_eq!(keys.len(), 100);
+
+        // Iteration orders of distinct hash sets differ, so leaking them is caught.
+        let result = std::panic::catch_unwind(|| {
+            check_deterministic("keys", || {
+                let set: HashSet<u64> = (0..100).collect();
+                set.into_iter().collect::<Vec<_>>()
+            })
+        });
+        assert!(result.is_err());
+
+        check_canonical_order("write log", [&b"a"[..], b"b", b"c"]);
+        let result = std::panic::catch_unwind(|| {
+            check_canonical_order("write log", [&b"b"[..], b"a"]);
+        });
+        assert!(result.is_err());
+    }
+}
@@ -5,6 +5,7 @@ use super::{context::Context, tags::Tags, types::TxnBatch};
 use crate::{
     common::crypto::hash::Hash,
     consensus::roothash,
+    storage::MKVS,
     types::{CheckTxResult, Error as RuntimeError},
 };
 
@@ -51,6 +52,31 @@ pub trait Dispatcher: Send + Sync {
         ))
     }
 
+    /// Whether single transactions can be executed via `execute_tx`.
+    ///
+    /// In case this is supported and parallel execution is enabled in the runtime configuration,
+    /// batches without incoming messages are executed across a pool of workers via `execute_tx`
+    /// instead of via `execute_batch`.
+    fn supports_parallel_execution(&self) -> bool {
+        false
+    }
+
+    /// Execute a single transaction against the given state.
+    ///
+    /// The outcome must only depend on the transaction and on the state it reads, and must be
+    /// the same as when the transaction is executed as part of `execute_batch`.
+    fn execute_tx(
+        &self,
+        _tx: &[u8],
+        _state: &mut dyn MKVS,
+    ) -> Result<ExecuteTxResult, RuntimeError> {
+        Err(RuntimeError::new(
+            "rhp/dispatcher",
+            4,
+            "parallel execution not supported",
+        ))
+    }
+
     /// Check the transactions in the given batch for validity.
     ///
     /// # Consensus Layer State Integrity
@@ -114,6 +140,14 @@ impl<T: Dispatcher + ?Sized> Dispatcher for Box<T> {
         T::schedule_and_execute_batch(&**self, ctx, initial_batch, in_msgs)
     }
 
+    fn supports_parallel_execution(&self) -> bool {
+        T::supports_parallel_execution(&**self)
+    }
+
+    fn execute_tx(&self, tx: &[u8], state: &mut dyn MKVS) -> Result<ExecuteTxResult, RuntimeError> {
+        T::execute_tx(&**self, tx, state)
+    }
+
     fn check_batch(
         &self,
         ctx: Context,
@@ -158,6 +192,14 @@ impl<T: Dispatcher + ?Sized> Dispatcher for Arc<T> {
         T::schedule_and_execute_batch(&**self, ctx, initial_batch, in_msgs)
     }
 
+    fn supports_parallel_execution(&self) -> bool {
+        T::supports_parallel_execution(&**self)
+    }
+
+    fn execute_tx(&self, tx: &[u8], state: &mut dyn MKVS) -> Result<ExecuteTxResult, RuntimeError> {
+        T::execute_tx(&**self, tx, state)
+    }
+
     fn check_batch(
         &self,
         ctx: Context,
@@ -0,0 +1,215 @@
+//! Encrypted transaction call envelopes.
+//!
+//! Confidential runtimes accept calls which clients encrypt to a per-epoch X25519 public key of
+//! the runtime (usually an ephemeral key pair managed by the key manager). The client generates
+//! a fresh X25519 key pair for each call and seals the call using Deoxys-II with a symmetric key
+//! derived from both key pairs. The runtime opens the call and seals the result under the same
+//! derived key, so that only the client which made the call can read the result.
+use anyhow::Result;
+use rand::Rng;
+use thiserror::Error;
+
+use crate::{
+    common::crypto::{
+        mrae::deoxysii::{self, NONCE_SIZE},
+        rng::SecureRng,
+        x25519,
+    },
+    consensus::beacon::EpochTime,
+};
+
+/// Additional data used when encrypting calls.
+const CALL_ENVELOPE_AD_CONTEXT: &[u8] = b"oasis-core/runtime: call envelope";
+/// Additional data used when encrypting call results.
+const RESULT_ENVELOPE_AD_CONTEXT: &[u8] = b"oasis-core/runtime: result envelope";
+
+/// Envelope errors.
+#[derive(Error, Debug)]
+pub enum EnvelopeError {
+    #[error("malformed nonce")]
+    MalformedNonce,
+    #[error("decryption failed")]
+    DecryptionFailed,
+}
+
+/// A call encrypted to the runtime's key for the given epoch.
+#[derive(Clone, Debug, Default, PartialEq, Eq, cbor::Encode, cbor::Decode)]
+pub struct CallEnvelope {
+    /// Epoch of the runtime key that the call is encrypted to.
+    pub epoch: EpochTime,
+    /// Ephemeral public key of the client.
+    pub public_key: x25519::PublicKey,
+    /// Encryption nonce.
+    pub nonce: Vec<u8>,
+    /// Encrypted call.
+    pub data: Vec<u8>,
+}
+
+/// A call result encrypted to the client which made the call.
+#[derive(Clone, Debug, Default, PartialEq, Eq, cbor::Encode, cbor::Decode)]
+pub struct ResultEnvelope {
+    /// Encryption nonce.
+    pub nonce: Vec<u8>,
+    /// Encrypted result.
+    pub data: Vec<u8>,
+}
+
+impl CallEnvelope {
+    /// Encrypt a call to the given runtime key for the given epoch.
+    ///
+    /// Returns the envelope together with the client key pair needed to open the result.
+    pub fn seal(
+        call: Vec<u8>,
+        runtime_key: &x25519::PublicKey,
+        epoch: EpochTime,
+    ) -> Result<(Self, ClientKeys)> {
+        let private_key = x25519::PrivateKey::generate();
+        let nonce = generate_nonce();
+        let data = deoxysii::box_seal(
+            &nonce,
+            call,
+            additional_data(CALL_ENVELOPE_AD_CONTEXT, epoch),
+            &runtime_key.0,
+            &private_key.0,
+        )?;
+
+        let envelope = Self {
+            epoch,
+            public_key: private_key.public_key(),
+            nonce: nonce.to_vec(),
+            data,
+        };
+        let keys = ClientKeys {
+            epoch,
+            runtime_key: *runtime_key,
+            private_key,
+        };
+
+        Ok((envelope, keys))
+    }
+
+    /// Decrypt the call using the runtime's private key for the envelope's epoch.
+    ///
+    /// Returns the call together with the keys needed to encrypt its result.
+    pub fn open(&self, runtime_key: &x25519::PrivateKey) -> Result<(Vec<u8>, RuntimeKeys)> {
+        let call = deoxysii::box_open(
+            &parse_nonce(&self.nonce)?,
+            self.data.clone(),
+            additional_data(CALL_ENVELOPE_AD_CONTEXT, self.epoch),
+            &self.public_key.0,
+            &runtime_key.0,
+        )
+        .map_err(|_| EnvelopeError::DecryptionFailed)?;
+
+        let keys = RuntimeKeys {
+            epoch: self.epoch,
+            client_key: self.public_key,
+            private_key: runtime_key.clone(),
+        };
+
+        Ok((call, keys))
+    }
+}
+
+/// Keys held by the client for opening the result of an encrypted call.
+pub struct ClientKeys {
+    epoch: EpochTime,
+    runtime_key: x25519::PublicKey,
+    private_key: x25519::PrivateKey,
+}
+
+impl ClientKeys {
+    /// Decrypt the result of the call.
+    pub fn open_result(&self, envelope: &ResultEnvelope) -> Result<Vec<u8>> {
+        let result = deoxysii::box_open(
+            &parse_nonce(&envelope.nonce)?,
+            envelope.data.clone(),
+            additional_data(RESULT_ENVELOPE_AD_CONTEXT, self.epoch),
+            &self.runtime_key.0,
+            &self.private_key.0,
+        )
+        .map_err(|_| EnvelopeError::DecryptionFailed)?;
+
+        Ok(result)
+    }
+}
+
+/// Keys held by the runtime for encrypting the result of an encrypted call.
+pub struct RuntimeKeys {
+    epoch: EpochTime,
+    client_key: x25519::PublicKey,
+    private_key: x25519::PrivateKey,
+}
+
+impl RuntimeKeys {
+    /// Encrypt the result of the call to the client which made it.
+    pub fn seal_result(&self, result: Vec<u8>) -> Result<ResultEnvelope> {
+        let nonce = generate_nonce();
+        let data = deoxysii::box_seal(
+            &nonce,
+            result,
+            additional_data(RESULT_ENVELOPE_AD_CONTEXT, self.epoch),
+            &self.client_key.0,
+            &self.private_key.0,
+        )?;
+
+        Ok(ResultEnvelope {
+            nonce: nonce.to_vec(),
+            data,
+        })
+    }
+}
+
+fn generate_nonce() -> [u8; NONCE_SIZE] {
+    let mut nonce = [0u8; NONCE_SIZE];
+    SecureRng.fill(&mut nonce);
+    nonce
+}
+
+fn parse_nonce(nonce: &[u8]) -> Result<[u8; NONCE_SIZE], EnvelopeError> {
+    nonce.try_into().map_err(|_| EnvelopeError::MalformedNonce)
+}
+
+fn additional_data(context: &[u8], epoch: EpochTime) -> Vec<u8> {
+    [context, &epoch.to_be_bytes()[..]].concat()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_envelope_roundtrip() {
+        let runtime_sk = x25519::PrivateKey::generate();
+        let runtime_pk = runtime_sk.public_key();
+
+        let (envelope, client_keys) =
+            CallEnvelope::seal(b"call".to_vec(), &runtime_pk, 10).unwrap();
+        assert_ne!(envelope.data, b"call".to_vec());
+
+        let (call, runtime_keys) = envelope.open(&runtime_sk).unwrap();
+        assert_eq!(call, b"call".to_vec());
+
+        let result = runtime_keys.seal_result(b"result".to_vec()).unwrap();
+        assert_eq!(
+            client_keys.open_result(&result).unwrap(),
+            b"result".to_vec()
+        );
+
+        // Calls are bound to their epoch.
+        let mut tampered = envelope.clone();
+        tampered.epoch = 11;
+        assert!(tampered.open(&runtime_sk).is_err());
+
+        // Calls can only be opened with the runtime key.
+        assert!(envelope.open(&x25519::PrivateKey::generate()).is_err());
+
+        // Results can't be replayed as calls.
+        let replayed = CallEnvelope {
+            nonce: result.nonce,
+            data: result.data,
+            ..envelope
+        };
+        assert!(replayed.open(&runtime_sk).is_err());
+    }
+}
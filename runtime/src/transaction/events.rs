@@ -0,0 +1,414 @@
+//! Canonical runtime events.
+//!
+//! Events are emitted as transaction tags. Each event type is identified by the name of the
+//! module emitting it and a module-specific code. The tag key is derived from these identifiers
+//! by hashing them under a fixed context, so that it is stable across runtimes and versions, while
+//! the tag value is the canonical CBOR encoding of the event itself. Indexers and clients can
+//! therefore decode events of any runtime given only their module names and codes, which, together
+//! with the event fields, are listed by the `METHOD_EVENT_SCHEMAS` enclave RPC method.
+//!
+//! Event types are usually defined using the [`runtime_event!`](crate::runtime_event) macro,
+//! which implements [`Event`] (including its schema) for a plain struct definition.
+//!
+//! Each runtime has its own [`EventRegistry`], available via `Protocol::event_registry`.
+//! Registered event types can also be decoded from the tags delivered with runtime event
+//! notifications. Decoders for other tags can be registered per tag key prefix via
+//! [`EventRegistry::register_decoder`].
+use std::{
+    any::Any,
+    marker::PhantomData,
+    sync::{Arc, Mutex},
+};
+
+use anyhow::Result;
+
+use crate::{
+    common::crypto::hash::Hash,
+    enclave_rpc::{
+        dispatcher::{Method, MethodDescriptor},
+        types::Kind as RpcKind,
+        Context as RpcContext,
+    },
+};
+
+use super::tags::Tag;
+
+/// Name of the enclave RPC method returning the schemas of registered events.
+pub const METHOD_EVENT_SCHEMAS: &str = "runtime.EventSchemas";
+
+/// Context used when deriving event tag keys.
+const EVENT_TAG_KEY_CONTEXT: &[u8] = b"orphiq-core/runtime: event tag key";
+
+/// Schema of a single event field.
+#[derive(Clone, Debug, Default, PartialEq, Eq, cbor::Encode, cbor::Decode)]
+pub struct FieldSchema {
+    /// Field name.
+    pub name: String,
+    /// Rust type of the field, as written in the event definition.
+    pub ty: String,
+}
+
+/// Schema of an event type.
+#[derive(Clone, Debug, Default, PartialEq, Eq, cbor::Encode, cbor::Decode)]
+pub struct EventSchema {
+    /// Name of the module emitting the event.
+    pub module: String,
+    /// Module-specific event code.
+    pub code: u32,
+    /// Event name.
+    pub name: String,
+    /// Event fields, in definition order.
+    pub fields: Vec<FieldSchema>,
+    /// Key of the tags carrying the event.
+    pub tag_key: Vec<u8>,
+}
+
+/// A runtime event.
+pub trait Event: cbor::Encode + cbor::Decode {
+    /// Name of the module emitting the event.
+    const MODULE: &'static str;
+    /// Module-specific event code.
+    const CODE: u32;
+
+    /// Schema of the event.
+    fn schema() -> EventSchema;
+
+    /// Key of the tags carrying the event.
+    fn tag_key() -> Vec<u8> {
+        tag_key(Self::MODULE, Self::CODE)
+    }
+
+    /// Convert the event into a tag.
+    fn into_tag(self) -> Tag
+    where
+        Self: Sized,
+    {
+        Tag::new(Self::tag_key(), cbor::to_vec(self))
+    }
+
+    /// Decode the event from the given tag.
+    ///
+    /// Returns `None` in case the tag does not carry an event of this type.
+    fn from_tag(tag: &Tag) -> Option<Result<Self, cbor::DecodeError>>
+    where
+        Self: Sized,
+    {
+        if tag.key != Self::tag_key() {
+            return None;
+        }
+        Some(cbor::from_slice(&tag.value))
+    }
+}
+
+/// Derive the key of the tags carrying events with the given module name and code.
+pub fn tag_key(module: &str, code: u32) -> Vec<u8> {
+    Hash::digest_bytes_list(&[
+        EVENT_TAG_KEY_CONTEXT,
+        module.as_bytes(),
+        &code.to_be_bytes(),
+    ])
+    .as_ref()
+    .to_vec()
+}
+
+/// Decoder of events carried by tags with a given key prefix.
+pub trait EventDecoder: Send + Sync {
+    /// Decode the event carried by the tag with the given key and value.
+    fn decode(&self, key: &[u8], value: &[u8]) -> Result<Arc<dyn Any + Send + Sync>>;
+}
+
+/// Decoder of events of a registered event type.
+struct TypedEventDecoder<E>(PhantomData<fn() -> E>);
+
+impl<E: Event + Send + Sync + 'static> EventDecoder for TypedEventDecoder<E> {
+    fn decode(&self, _key: &[u8], value: &[u8]) -> Result<Arc<dyn Any + Send + Sync>> {
+        Ok(Arc::new(cbor::from_slice::<E>(value)?))
+    }
+}
+
+/// A decoded runtime event.
+#[derive(Clone, Debug)]
+pub struct DecodedEvent {
+    /// Key of the tag carrying the event.
+    pub key: Vec<u8>,
+    /// Round in which the event was emitted.
+    pub round: u64,
+    /// Index of the emitting transaction within the block, unless emitted by the block itself.
+    pub tx_index: Option<u32>,
+    /// Decoded event.
+    pub event: Arc<dyn Any + Send + Sync>,
+}
+
+impl DecodedEvent {
+    /// Returns the event in case it is of the given type.
+    pub fn downcast<E: Any>(&self) -> Option<&E> {
+        self.event.downcast_ref()
+    }
+}
+
+/// Request of the event schemas RPC method.
+#[derive(Clone, Debug, Default, cbor::Encode, cbor::Decode)]
+pub struct EventSchemasRequest {
+    /// Optional module name to filter by. An empty name returns the schemas of all modules.
+    #[cbor(optional)]
+    pub module: String,
+}
+
+/// Response of the event schemas RPC method.
+#[derive(Clone, Debug, Default, cbor::Encode, cbor::Decode)]
+pub struct EventSchemasResponse {
+    /// Schemas of registered events, ordered by module and code.
+    pub events: Vec<EventSchema>,
+}
+
+/// Registry of event schemas.
+pub struct EventRegistry {
+    schemas: Mutex<Vec<EventSchema>>,
+    decoders: Mutex<Vec<(Vec<u8>, Arc<dyn EventDecoder>)>>,
+}
+
+impl EventRegistry {
+    /// Create a new empty registry.
+    pub fn new() -> Self {
+        Self {
+            schemas: Mutex::new(Vec::new()),
+            decoders: Mutex::new(Vec::new()),
+        }
+    }
+
+    /// Register the given event type, replacing any schema with the same module and code.
+    ///
+    /// This also registers a decoder for the tags carrying the event.
+    pub fn register<E: Event + Send + Sync + 'static>(&self) {
+        let schema = E::schema();
+        self.register_decoder(schema.tag_key.clone(), TypedEventDecoder::<E>(PhantomData));
+
+        let mut schemas = self.schemas.lock().unwrap();
+        match schemas.binary_search_by(|s| (&s.module, s.code).cmp(&(&schema.module, schema.code)))
+        {
+            Ok(index) => schemas[index] = schema,
+            Err(index) => schemas.insert(index, schema),
+        }
+    }
+
+    /// Register a decoder for tags with the given key prefix, replacing any decoder registered
+    /// for the same prefix.
+    ///
+    /// Tags are decoded by the decoder registered for the longest prefix of their key.
+    pub fn register_decoder<D: EventDecoder + 'static>(&self, prefix: Vec<u8>, decoder: D) {
+        let mut decoders = self.decoders.lock().unwrap();
+        decoders.retain(|(p, _)| p != &prefix);
+        decoders.push((prefix, Arc::new(decoder)));
+        // Keep longer prefixes first, so that they take precedence.
+        decoders.sort_by(|(a, _), (b, _)| b.len().cmp(&a.len()));
+    }
+
+    /// Decode the event carried by the tag with the given key and value.
+    ///
+    /// Returns `None` in case no decoder is registered for the tag.
+    pub fn decode(&self, key: &[u8], value: &[u8]) -> Option<Result<Arc<dyn Any + Send + Sync>>> {
+        self.decoders().decode(key, value)
+    }
+
+    /// Snapshot of the registered decoders, for decoding many tags without locking the registry
+    /// for each of them.
+    pub fn decoders(&self) -> EventDecoders {
+        EventDecoders(self.decoders.lock().unwrap().clone())
+    }
+
+    /// Schemas of registered events emitted by the given module, or of all registered events in
+    /// case the module name is empty.
+    pub fn schemas(&self, module: &str) -> Vec<EventSchema> {
+        self.schemas
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|s| module.is_empty() || s.module == module)
+            .cloned()
+            .collect()
+    }
+
+    /// Enclave RPC method exposing the schemas of events in the given registry.
+    ///
+    /// Schemas are public, so the method is also callable without an attested session.
+    pub fn rpc_method(registry: Arc<EventRegistry>) -> Method {
+        Method::new(
+            MethodDescriptor {
+                name: METHOD_EVENT_SCHEMAS.to_string(),
+                kind: RpcKind::InsecureQuery,
+                allow_anonymous: true,
+                cacheable: true,
+            },
+            move |_ctx: &RpcContext, req: &EventSchemasRequest| -> Result<EventSchemasResponse> {
+                Ok(EventSchemasResponse {
+                    events: registry.schemas(&req.module),
+                })
+            },
+        )
+    }
+}
+
+impl Default for EventRegistry {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Snapshot of the decoders registered in an [`EventRegistry`].
+pub struct EventDecoders(Vec<(Vec<u8>, Arc<dyn EventDecoder>)>);
+
+impl EventDecoders {
+    /// Decode the event carried by the tag with the given key and value.
+    ///
+    /// Returns `None` in case no decoder is registered for the tag.
+    pub fn decode(&self, key: &[u8], value: &[u8]) -> Option<Result<Arc<dyn Any + Send + Sync>>> {
+        let (_, decoder) = self.0.iter().find(|(prefix, _)| key.starts_with(prefix))?;
+        Some(decoder.decode(key, value))
+    }
+}
+
+/// Define an event type, implementing [`Event`] for it.
+///
+/// The struct definition is emitted unchanged, so the CBOR derives (and any field attributes)
+/// must be given as usual. The schema lists the fields with their Rust types as written.
+///
+/// # Examples
+///
+/// ```rust,ignore
+/// runtime_event! {
+///     #[event(module = "accounts", code = 1)]
+///     #[derive(Clone, Debug, cbor::Encode, cbor::Decode)]
+///     pub struct TransferEvent {
+///         pub from: Address,
+///         pub to: Address,
+///         pub amount: Quantity,
+///     }
+/// }
+/// ```
+#[macro_export]
+macro_rules! runtime_event {
+    (
+        #[event(module = $module:literal, code = $code:literal)]
+        $(#[$meta:meta])*
+        $vis:vis struct $name:ident {
+            $($(#[$field_meta:meta])* $field_vis:vis $field:ident : $ty:ty),* $(,)?
+        }
+    ) => {
+        $(#[$meta])*
+        $vis struct $name {
+            $($(#[$field_meta])* $field_vis $field: $ty),*
+        }
+
+        impl $crate::transaction::events::Event for $name {
+            const MODULE: &'static str = $module;
+            const CODE: u32 = $code;
+
+            fn schema() -> $crate::transaction::events::EventSchema {
+                $crate::transaction::events::EventSchema {
+                    module: $module.to_string(),
+                    code: $code,
+                    name: stringify!($name).to_string(),
+                    fields: vec![$($crate::transaction::events::FieldSchema {
+                        name: stringify!($field).to_string(),
+                        ty: stringify!($ty).to_string(),
+                    }),*],
+                    tag_key: $crate::transaction::events::tag_key($module, $code),
+                }
+            }
+        }
+    };
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    runtime_event! {
+        #[event(module = "test", code = 1)]
+        #[derive(Clone, Debug, PartialEq, Eq, cbor::Encode, cbor::Decode)]
+        struct TestEvent {
+            account: Vec<u8>,
+            #[cbor(optional)]
+            amount: u64,
+        }
+    }
+
+    runtime_event! {
+        #[event(module = "test", code = 2)]
+        #[derive(Clone, Debug, PartialEq, Eq, cbor::Encode, cbor::Decode)]
+        struct OtherEvent {}
+    }
+
+    #[test]
+    fn test_event_tags() {
+        let event = TestEvent {
+            account: b"alice".to_vec(),
+            amount: 10,
+        };
+        let tag = event.clone().into_tag();
+        assert_eq!(tag.key, tag_key("test", 1));
+        assert_eq!(tag.value, cbor::to_vec(event.clone()));
+        assert_eq!(TestEvent::from_tag(&tag).unwrap().unwrap(), event);
+        assert!(OtherEvent::from_tag(&tag).is_none());
+
+        // Keys are unique per module and code.
+        assert_ne!(tag_key("test", 1), tag_key("test", 2));
+        assert_ne!(tag_key("test", 1), tag_key("test2", 1));
+    }
+
+    #[test]
+    fn test_event_registry() {
+        let registry = EventRegistry::new();
+        registry.register::<OtherEvent>();
+        registry.register::<TestEvent>();
+        registry.register::<TestEvent>();
+
+        let schemas = registry.schemas("");
+        assert_eq!(schemas.len(), 2);
+        assert_eq!(schemas[0].module, "test");
+        assert_eq!(schemas[0].code, 1);
+        assert_eq!(schemas[0].name, "TestEvent");
+        assert_eq!(schemas[0].tag_key, tag_key("test", 1));
+        let fields: Vec<_> = schemas[0].fields.iter().map(|f| f.name.as_str()).collect();
+        assert_eq!(fields, vec!["account", "amount"]);
+        assert_eq!(schemas[0].fields[1].ty, "u64");
+        assert_eq!(schemas[1].name, "OtherEvent");
+        assert!(registry.schemas("other").is_empty());
+    }
+
+    struct RawDecoder;
+
+    impl EventDecoder for RawDecoder {
+        fn decode(&self, key: &[u8], _value: &[u8]) -> Result<Arc<dyn Any + Send + Sync>> {
+            Ok(Arc::new(key.to_vec()))
+        }
+    }
+
+    #[test]
+    fn test_event_decoders() {
+        let registry = EventRegistry::new();
+        registry.register::<TestEvent>();
+        registry.register_decoder(b"raw".to_vec(), RawDecoder);
+
+        let event = TestEvent {
+            account: b"bob".to_vec(),
+            amount: 5,
+        };
+        let tag = event.clone().into_tag();
+        let decoded = registry.decode(&tag.key, &tag.value).unwrap().unwrap();
+        assert_eq!(decoded.downcast_ref::<TestEvent>(), Some(&event));
+        assert!(registry.decode(&tag.key, b"invalid").unwrap().is_err());
+
+        // Tags are decoded by the decoder of the longest matching prefix.
+        let decoded = registry.decode(b"raw/tag", b"").unwrap().unwrap();
+        assert_eq!(
+            decoded.downcast_ref::<Vec<u8>>(),
+            Some(&b"raw/tag".to_vec())
+        );
+        assert!(registry.decode(b"other", b"").is_none());
+        registry.register_decoder(vec![], RawDecoder);
+        assert!(registry.decode(b"other", b"").is_some());
+        let decoded = registry.decode(&tag.key, &tag.value).unwrap().unwrap();
+        assert!(decoded.downcast_ref::<TestEvent>().is_some());
+    }
+}
@@ -1,11 +1,23 @@
 //! Runtime transaction processing.
 
+pub mod authenticator;
 pub mod context;
+pub mod deferred;
+pub mod determinism;
 pub mod dispatcher;
+pub mod envelope;
+pub mod events;
+pub mod parallel;
+pub mod pipeline;
 pub mod rwset;
+pub mod scheduler;
+pub mod shadow;
 pub mod tags;
+pub mod trace;
 pub mod tree;
 pub mod types;
+#[cfg(feature = "wasm")]
+pub mod wasm;
 
 // Re-exports.
 pub use self::context::Context;
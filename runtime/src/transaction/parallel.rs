@@ -0,0 +1,500 @@
+//! Optimistic parallel execution of transaction batches.
+//!
+//! Transactions of a batch are first executed speculatively by a pool of workers, all against the
+//! state as it was before the batch, while the keys each transaction reads and the changes it
+//! makes are tracked. Results are then committed in batch order. A transaction whose reads
+//! overlap the writes of an earlier transaction in the batch observed stale state, so it is
+//! re-executed serially against the state with all earlier changes applied. This makes the
+//! outcome identical to serial execution of the batch, regardless of the number of workers and
+//! of thread scheduling, as long as transactions only depend on their input and on the state.
+//!
+//! The underlying tree is not thread-safe, so it never leaves the thread executing the batch.
+//! Workers send the reads of their transactions to that thread, which serves them while the rest
+//! of transaction execution proceeds in parallel. Transactions which iterate over state or
+//! request proofs cannot be tracked precisely, so their speculative results are discarded and
+//! they are always re-executed serially against the state itself.
+use std::{
+    cell::{Cell, RefCell},
+    collections::{BTreeMap, HashSet},
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        mpsc, Mutex,
+    },
+    thread,
+};
+
+use anyhow::{anyhow, Error, Result};
+
+use crate::{
+    common::{crypto::hash::Hash, namespace::Namespace},
+    storage::{
+        mkvs::{self, Key, Prefix, Proof, WriteLog},
+        MKVS,
+    },
+    types::Error as RuntimeError,
+};
+
+use super::{context::Context, dispatcher::ExecuteTxResult, types::TxnBatch};
+
+/// Result of executing a batch in parallel.
+pub struct ParallelExecutionResult {
+    /// Results of all transactions, in batch order.
+    pub results: Vec<ExecuteTxResult>,
+    /// Number of transactions that had to be re-executed serially.
+    pub reexecuted: usize,
+}
+
+/// Executor of transaction batches across a pool of workers.
+#[derive(Clone, Copy, Debug)]
+pub struct ParallelExecutor {
+    workers: usize,
+}
+
+impl ParallelExecutor {
+    /// Create a new executor using the given number of workers.
+    ///
+    /// With less than two workers, batches are executed serially.
+    pub fn new(workers: usize) -> Self {
+        Self { workers }
+    }
+
+    /// Execute the given batch against the given state using the given transaction executor.
+    ///
+    /// The executor must be deterministic in the transaction and in the state it reads. On
+    /// success, the changes made by all transactions have been applied to the state. In case any
+    /// transaction fails, the error of the first failing transaction is returned and the state is
+    /// left with the changes of the transactions preceding it.
+    pub fn execute<F>(
+        &self,
+        state: &mut dyn MKVS,
+        batch: &TxnBatch,
+        execute_tx: F,
+    ) -> Result<ParallelExecutionResult, RuntimeError>
+    where
+        F: Fn(&[u8], &mut dyn MKVS) -> Result<ExecuteTxResult, RuntimeError> + Sync,
+    {
+        // Speculatively execute all transactions against the state before the batch.
+        let mut speculative: Vec<Option<Speculative>> = if self.workers < 2 {
+            Vec::new()
+        } else {
+            self.speculate(state, batch, &execute_tx)
+        };
+        speculative.resize_with(batch.len(), || None);
+
+        // Commit the results in batch order, re-executing transactions that observed stale state.
+        let mut written = HashSet::new();
+        let mut results = Vec::with_capacity(batch.len());
+        let mut reexecuted = 0;
+        for (tx, speculative) in batch.iter().zip(speculative) {
+            match speculative {
+                Some(speculative) if !speculative.conflicts(&written) => {
+                    results.push(speculative.result?);
+                    for (key, value) in &speculative.writes {
+                        match value {
+                            Some(value) => state.insert(key, value),
+                            None => state.remove(key),
+                        };
+                    }
+                    written.extend(speculative.writes.into_keys());
+                }
+                speculative => {
+                    if speculative.is_some() {
+                        reexecuted += 1;
+                    }
+                    let mut tx_state = UndoState::new(&mut *state);
+                    match execute_tx(tx, &mut tx_state) {
+                        Ok(result) => {
+                            results.push(result);
+                            written.extend(tx_state.undo.into_keys());
+                        }
+                        Err(err) => {
+                            tx_state.rollback();
+                            return Err(err);
+                        }
+                    }
+                }
+            }
+        }
+
+        Ok(ParallelExecutionResult {
+            results,
+            reexecuted,
+        })
+    }
+
+    /// Execute all transactions of the given batch across the workers, serving their reads from
+    /// the given state on the current thread.
+    fn speculate<F>(
+        &self,
+        state: &dyn MKVS,
+        batch: &TxnBatch,
+        execute_tx: &F,
+    ) -> Vec<Option<Speculative>>
+    where
+        F: Fn(&[u8], &mut dyn MKVS) -> Result<ExecuteTxResult, RuntimeError> + Sync,
+    {
+        let next = AtomicUsize::new(0);
+        let results = Mutex::new((0..batch.len()).map(|_| None).collect::<Vec<_>>());
+        let (reads_tx, reads_rx) = mpsc::channel::<Read>();
+        thread::scope(|s| {
+            for _ in 0..self.workers.min(batch.len()) {
+                let reads = reads_tx.clone();
+                let (next, results) = (&next, &results);
+                s.spawn(move || loop {
+                    let index = next.fetch_add(1, Ordering::Relaxed);
+                    if index >= batch.len() {
+                        break;
+                    }
+                    let result = Speculative::execute(&reads, &batch[index], execute_tx);
+                    results.lock().unwrap()[index] = Some(result);
+                });
+            }
+            drop(reads_tx);
+
+            // Serve reads until all workers are done and have dropped their senders.
+            for read in reads_rx {
+                let _ = read.reply.send(state.get(&read.key));
+            }
+        });
+        results.into_inner().unwrap()
+    }
+}
+
+impl Context<'_> {
+    /// Execute the given batch against the runtime state across a pool of workers, see
+    /// [`ParallelExecutor::execute`].
+    pub fn execute_parallel<F>(
+        &mut self,
+        executor: &ParallelExecutor,
+        batch: &TxnBatch,
+        execute_tx: F,
+    ) -> Result<ParallelExecutionResult, RuntimeError>
+    where
+        F: Fn(&[u8], &mut dyn MKVS) -> Result<ExecuteTxResult, RuntimeError> + Sync,
+    {
+        executor.execute(self.runtime_state, batch, execute_tx)
+    }
+}
+
+/// Read of a key requested by a worker.
+struct Read {
+    key: Vec<u8>,
+    reply: mpsc::SyncSender<Option<Vec<u8>>>,
+}
+
+/// Outcome of speculatively executing a single transaction.
+struct Speculative {
+    result: Result<ExecuteTxResult, RuntimeError>,
+    reads: HashSet<Vec<u8>>,
+    writes: BTreeMap<Vec<u8>, Option<Vec<u8>>>,
+    untracked: bool,
+}
+
+impl Speculative {
+    fn execute<F>(reads: &mpsc::Sender<Read>, tx: &[u8], execute_tx: &F) -> Self
+    where
+        F: Fn(&[u8], &mut dyn MKVS) -> Result<ExecuteTxResult, RuntimeError>,
+    {
+        let mut state = SpeculativeState {
+            base: reads,
+            reads: RefCell::new(HashSet::new()),
+            writes: BTreeMap::new(),
+            untracked: Cell::new(false),
+        };
+        let result = execute_tx(tx, &mut state);
+
+        Self {
+            result,
+            reads: state.reads.into_inner(),
+            writes: state.writes,
+            untracked: state.untracked.into_inner(),
+        }
+    }
+
+    /// Whether the transaction may have observed state modified by the given writes.
+    fn conflicts(&self, written: &HashSet<Vec<u8>>) -> bool {
+        self.untracked || self.reads.iter().any(|key| written.contains(key))
+    }
+}
+
+/// View of the state before the batch for a speculatively executed transaction, buffering its
+/// changes.
+struct SpeculativeState<'a> {
+    base: &'a mpsc::Sender<Read>,
+    reads: RefCell<HashSet<Vec<u8>>>,
+    writes: BTreeMap<Vec<u8>, Option<Vec<u8>>>,
+    untracked: Cell<bool>,
+}
+
+impl MKVS for SpeculativeState<'_> {
+    fn get(&self, key: &[u8]) -> Option<Vec<u8>> {
+        if let Some(value) = self.writes.get(key) {
+            return value.clone();
+        }
+        self.reads.borrow_mut().insert(key.to_vec());
+
+        let (reply, value) = mpsc::sync_channel(1);
+        self.base
+            .send(Read {
+                key: key.to_vec(),
+                reply,
+            })
+            .expect("reads must be served while workers are running");
+        value
+            .recv()
+            .expect("reads must be served while workers are running")
+    }
+
+    fn get_proof(&self, _key: &[u8]) -> Option<Proof> {
+        // Proofs cannot be requested from workers, so the transaction is re-executed serially.
+        self.untracked.set(true);
+        None
+    }
+
+    fn cache_contains_key(&self, _key: &[u8]) -> bool {
+        false
+    }
+
+    fn insert(&mut self, key: &[u8], value: &[u8]) -> Option<Vec<u8>> {
+        let previous = self.get(key);
+        self.writes.insert(key.to_vec(), Some(value.to_vec()));
+        previous
+    }
+
+    fn remove(&mut self, key: &[u8]) -> Option<Vec<u8>> {
+        let previous = self.get(key);
+        self.writes.insert(key.to_vec(), None);
+        previous
+    }
+
+    fn prefetch_prefixes(&self, _prefixes: &[Prefix], _limit: u16) {}
+
+    fn prefetch_prefix(&self, _prefix: &Prefix, _max_size: u64) {}
+
+    fn iter(&self) -> Box<dyn mkvs::Iterator + '_> {
+        // Iteration cannot be served to workers, so the transaction is re-executed serially.
+        self.untracked.set(true);
+        Box::new(EmptyIterator::default())
+    }
+
+    fn commit(&mut self, _namespace: Namespace, _version: u64) -> Result<(WriteLog, Hash)> {
+        Err(anyhow!(
+            "transaction: state cannot be committed during parallel execution"
+        ))
+    }
+}
+
+/// State of a serially executed transaction, recording the previous values of the keys it
+/// modifies so that its changes can be rolled back in case it fails.
+struct UndoState<'a> {
+    inner: &'a mut dyn MKVS,
+    undo: BTreeMap<Vec<u8>, Option<Vec<u8>>>,
+}
+
+impl<'a> UndoState<'a> {
+    fn new(inner: &'a mut dyn MKVS) -> Self {
+        Self {
+            inner,
+            undo: BTreeMap::new(),
+        }
+    }
+
+    /// Restore the previous values of all modified keys.
+    fn rollback(self) {
+        for (key, value) in self.undo {
+            match value {
+                Some(value) => self.inner.insert(&key, &value),
+                None => self.inner.remove(&key),
+            };
+        }
+    }
+}
+
+impl MKVS for UndoState<'_> {
+    fn get(&self, key: &[u8]) -> Option<Vec<u8>> {
+        self.inner.get(key)
+    }
+
+    fn get_proof(&self, key: &[u8]) -> Option<Proof> {
+        self.inner.get_proof(key)
+    }
+
+    fn cache_contains_key(&self, key: &[u8]) -> bool {
+        self.inner.cache_contains_key(key)
+    }
+
+    fn insert(&mut self, key: &[u8], value: &[u8]) -> Option<Vec<u8>> {
+        let previous = self.inner.insert(key, value);
+        self.undo
+            .entry(key.to_vec())
+            .or_insert_with(|| previous.clone());
+        previous
+    }
+
+    fn remove(&mut self, key: &[u8]) -> Option<Vec<u8>> {
+        let previous = self.inner.remove(key);
+        self.undo
+            .entry(key.to_vec())
+            .or_insert_with(|| previous.clone());
+        previous
+    }
+
+    fn prefetch_prefixes(&self, prefixes: &[Prefix], limit: u16) {
+        self.inner.prefetch_prefixes(prefixes, limit)
+    }
+
+    fn prefetch_prefix(&self, prefix: &Prefix, max_size: u64) {
+        self.inner.prefetch_prefix(prefix, max_size)
+    }
+
+    fn iter(&self) -> Box<dyn mkvs::Iterator + '_> {
+        self.inner.iter()
+    }
+
+    fn commit(&mut self, _namespace: Namespace, _version: u64) -> Result<(WriteLog, Hash)> {
+        Err(anyhow!(
+            "transaction: state cannot be committed during parallel execution"
+        ))
+    }
+}
+
+/// Iterator over no entries.
+#[derive(Default)]
+struct EmptyIterator {
+    error: Option<Error>,
+    key: Option<Key>,
+    value: Option<Vec<u8>>,
+}
+
+impl Iterator for EmptyIterator {
+    type Item = (Vec<u8>, Vec<u8>);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        None
+    }
+}
+
+impl mkvs::Iterator for EmptyIterator {
+    fn set_prefetch(&mut self, _prefetch: usize) {}
+
+    fn is_valid(&self) -> bool {
+        false
+    }
+
+    fn error(&self) -> &Option<Error> {
+        &self.error
+    }
+
+    fn rewind(&mut self) {}
+
+    fn seek(&mut self, _key: &[u8]) {}
+
+    fn get_key(&self) -> &Option<Key> {
+        &self.key
+    }
+
+    fn get_value(&self) -> &Option<Vec<u8>> {
+        &self.value
+    }
+
+    fn next(&mut self) {}
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::storage::mkvs::{sync::NoopReadSyncer, OverlayTree, RootType, Tree};
+
+    /// Increments the counters under all keys listed in the transaction, failing at `!`, or sums
+    /// all counters.
+    fn execute_tx(tx: &[u8], state: &mut dyn MKVS) -> Result<ExecuteTxResult, RuntimeError> {
+        if tx.is_empty() {
+            let sum: u8 = state.iter().map(|(_, value)| value[0]).sum();
+            return Ok(ExecuteTxResult {
+                output: vec![sum],
+                tags: vec![],
+            });
+        }
+        if tx == b"fail" {
+            return Err(RuntimeError::new("test", 1, "failed"));
+        }
+
+        let mut output = vec![];
+        for key in tx.chunks(1) {
+            if key == b"!" {
+                return Err(RuntimeError::new("test", 1, "failed"));
+            }
+            let counter = state.get(key).map_or(0, |value| value[0]) + 1;
+            state.insert(key, &[counter]);
+            output.push(counter);
+        }
+        Ok(ExecuteTxResult {
+            output,
+            tags: vec![],
+        })
+    }
+
+    fn execute(workers: usize, batch: &TxnBatch) -> (Vec<Vec<u8>>, usize, WriteLog) {
+        let tree = Tree::builder()
+            .with_root_type(RootType::State)
+            .build(Box::new(NoopReadSyncer));
+        let mut state = OverlayTree::new(tree);
+        let result = ParallelExecutor::new(workers)
+            .execute(&mut state, batch, execute_tx)
+            .unwrap();
+        let outputs = result.results.into_iter().map(|r| r.output).collect();
+        (outputs, result.reexecuted, state.diff())
+    }
+
+    #[test]
+    fn test_parallel_execution() {
+        let batch = TxnBatch::new(vec![
+            b"a".to_vec(),
+            b"b".to_vec(),
+            b"ab".to_vec(),
+            b"c".to_vec(),
+            vec![],
+            b"a".to_vec(),
+        ]);
+        let (outputs, reexecuted, write_log) = execute(1, &batch);
+        assert_eq!(reexecuted, 0);
+        assert_eq!(
+            outputs,
+            vec![vec![1], vec![1], vec![2, 2], vec![1], vec![5], vec![3]]
+        );
+
+        for workers in [2, 4, 16] {
+            let (parallel_outputs, reexecuted, parallel_write_log) = execute(workers, &batch);
+            assert_eq!(parallel_outputs, outputs);
+            assert_eq!(parallel_write_log, write_log);
+            // The conflicting transactions and the iterating one are re-executed.
+            assert_eq!(reexecuted, 3);
+        }
+
+        // The first failing transaction is reported.
+        let batch = TxnBatch::new(vec![b"a".to_vec(), b"fail".to_vec(), b"b".to_vec()]);
+        let tree = Tree::builder()
+            .with_root_type(RootType::State)
+            .build(Box::new(NoopReadSyncer));
+        let mut state = OverlayTree::new(tree);
+        assert!(ParallelExecutor::new(4)
+            .execute(&mut state, &batch, execute_tx)
+            .is_err());
+        assert_eq!(state.get(b"a").unwrap(), Some(vec![1]));
+        assert_eq!(state.get(b"b").unwrap(), None);
+
+        // Changes of failing transactions are rolled back, also when they are re-executed.
+        let batch = TxnBatch::new(vec![b"a".to_vec(), b"ab!".to_vec()]);
+        for workers in [1, 4] {
+            let tree = Tree::builder()
+                .with_root_type(RootType::State)
+                .build(Box::new(NoopReadSyncer));
+            let mut state = OverlayTree::new(tree);
+            assert!(ParallelExecutor::new(workers)
+                .execute(&mut state, &batch, execute_tx)
+                .is_err());
+            assert_eq!(state.get(b"a").unwrap(), Some(vec![1]));
+            assert_eq!(state.get(b"b").unwrap(), None);
+        }
+    }
+}
@@ -0,0 +1,540 @@
+//! Staged transaction batch execution.
+//!
+//! Cross-cutting runtime features (e.g. fee debiting, nonce tracking or event finalization) can
+//! be implemented as [`Stage`]s hooking into fixed points of batch execution, instead of each
+//! wrapping or patching the transaction dispatcher. A [`PipelineDispatcher`] runs batches through
+//! its stages around a [`TxExecutor`] executing individual transactions:
+//!
+//! 1. `begin_batch` of all stages, in order.
+//! 2. For each transaction, `before_tx` of all stages in order, then the executor, then
+//!    `after_tx` of all stages in reverse order.
+//! 3. `end_batch` of all stages, in reverse order.
+//!
+//! Batch-level hooks get the whole transaction context, while per-transaction hooks get a
+//! [`TxScope`] limited to the runtime state, the block and the transaction being processed. When
+//! checking batches, only `begin_batch`, `before_tx` and the executor's check are run.
+//!
+//! Each transaction is processed against an overlay of the runtime state, which is only
+//! committed once all hooks succeeded. State changes and deferred actions of transactions
+//! rejected by a stage are therefore discarded. In schedule execution mode rejected transactions
+//! are dropped from the batch and reported in `ExecuteBatchResult::tx_reject_hashes`.
+use std::sync::{
+    atomic::{AtomicBool, Ordering},
+    Arc,
+};
+
+use super::{
+    context::Context,
+    dispatcher::{Dispatcher, ExecuteBatchResult, ExecuteTxResult},
+    tags::Tags,
+    types::TxnBatch,
+};
+use crate::{
+    common::{crypto::hash::Hash, namespace::Namespace},
+    consensus::{
+        beacon::EpochTime,
+        roothash::{self, Header},
+    },
+    storage::{
+        mkvs::{self, sync::Proof, FallibleMKVS, OverlayTree, Prefix},
+        MKVS,
+    },
+    types::{CheckTxResult, Error as RuntimeError},
+};
+
+/// Error module used for pipeline errors.
+const MODULE_NAME: &str = "rhp/pipeline";
+/// Error code used for aborted batches.
+const CODE_BATCH_ABORTED: u32 = 1;
+/// Error code used for failures to commit the state changes of a transaction.
+const CODE_STATE_COMMIT_FAILED: u32 = 2;
+
+/// A transaction being processed by the pipeline.
+pub struct TxScope<'a> {
+    /// Runtime state.
+    pub runtime_state: &'a mut dyn MKVS,
+    /// The block header accompanying the batch.
+    pub header: &'a Header,
+    /// Epoch corresponding to the currently processed block.
+    pub epoch: EpochTime,
+    /// Index of the transaction in the batch.
+    pub index: usize,
+    /// Hash of the transaction.
+    pub hash: Hash,
+    /// Raw transaction.
+    pub tx: &'a [u8],
+    /// Flag indicating whether the transaction is only being checked.
+    pub check_only: bool,
+    /// Tags emitted for the transaction by the stages, added to the tags of its result.
+    pub tags: Tags,
+}
+
+/// A stage of the batch execution pipeline.
+///
+/// All hooks do nothing by default.
+pub trait Stage: Send + Sync {
+    /// Called before any transaction of the batch is processed.
+    ///
+    /// An error aborts the batch.
+    fn begin_batch(&self, _ctx: &mut Context) -> Result<(), RuntimeError> {
+        Ok(())
+    }
+
+    /// Called before the transaction is executed or checked.
+    ///
+    /// An error rejects the transaction, skipping the remaining stages and the executor, and
+    /// discards all changes made to the runtime state while processing it.
+    fn before_tx(&self, _tx: &mut TxScope) -> Result<(), RuntimeError> {
+        Ok(())
+    }
+
+    /// Called after the transaction has been executed, with its result.
+    ///
+    /// An error aborts the batch.
+    fn after_tx(
+        &self,
+        _tx: &mut TxScope,
+        _result: &mut ExecuteTxResult,
+    ) -> Result<(), RuntimeError> {
+        Ok(())
+    }
+
+    /// Called after all transactions of the batch have been executed, with the batch result.
+    ///
+    /// An error aborts the batch.
+    fn end_batch(
+        &self,
+        _ctx: &mut Context,
+        _result: &mut ExecuteBatchResult,
+    ) -> Result<(), RuntimeError> {
+        Ok(())
+    }
+}
+
+/// Executor of individual transactions, run by the pipeline between the per-transaction hooks.
+pub trait TxExecutor: Send + Sync {
+    /// Execute the transaction.
+    ///
+    /// An error aborts the batch, failed transactions should be reported in their output.
+    fn execute_tx(&self, tx: &mut TxScope) -> Result<ExecuteTxResult, RuntimeError>;
+
+    /// Result of a transaction rejected by a stage while executing a batch.
+    fn reject_tx(&self, tx: &TxScope, err: RuntimeError) -> ExecuteTxResult;
+
+    /// Check the transaction for validity.
+    ///
+    /// An error aborts the batch, invalid transactions should be reported in their result.
+    fn check_tx(&self, tx: &mut TxScope) -> Result<CheckTxResult, RuntimeError>;
+
+    /// Process incoming messages before the transactions of the batch are executed, returning
+    /// the number of processed messages.
+    fn execute_in_msgs(
+        &self,
+        _ctx: &mut Context,
+        _in_msgs: &[roothash::IncomingMessage],
+    ) -> Result<usize, RuntimeError> {
+        // Default implementation processes no messages.
+        Ok(0)
+    }
+
+    /// Process a query.
+    fn query(&self, _ctx: Context, _method: &str, _args: Vec<u8>) -> Result<Vec<u8>, RuntimeError> {
+        // Default implementation returns an error.
+        Err(RuntimeError::new(
+            "rhp/dispatcher",
+            2,
+            "query not supported",
+        ))
+    }
+}
+
+/// Transaction dispatcher running batches through a pipeline of stages.
+pub struct PipelineDispatcher {
+    executor: Box<dyn TxExecutor>,
+    stages: Vec<Box<dyn Stage>>,
+    abort_batch: Option<Arc<AtomicBool>>,
+}
+
+impl PipelineDispatcher {
+    /// Create a new pipeline without any stages around the given executor.
+    pub fn new(executor: Box<dyn TxExecutor>) -> Self {
+        Self {
+            executor,
+            stages: Vec::new(),
+            abort_batch: None,
+        }
+    }
+
+    /// Add a stage to the end of the pipeline.
+    pub fn with_stage(mut self, stage: Box<dyn Stage>) -> Self {
+        self.stages.push(stage);
+        self
+    }
+
+    fn check_abort(&self) -> Result<(), RuntimeError> {
+        match &self.abort_batch {
+            Some(abort_batch) if abort_batch.load(Ordering::SeqCst) => Err(RuntimeError::new(
+                MODULE_NAME,
+                CODE_BATCH_ABORTED,
+                "batch aborted",
+            )),
+            _ => Ok(()),
+        }
+    }
+
+    fn begin_batch(&self, ctx: &mut Context) -> Result<(), RuntimeError> {
+        self.stages
+            .iter()
+            .try_for_each(|stage| stage.begin_batch(ctx))
+    }
+
+    fn before_tx(&self, tx: &mut TxScope) -> Result<(), RuntimeError> {
+        self.stages.iter().try_for_each(|stage| stage.before_tx(tx))
+    }
+
+    /// Process a single transaction of the batch, committing its state changes and deferred
+    /// actions unless it is rejected.
+    fn process_tx(
+        &self,
+        ctx: &mut Context,
+        index: usize,
+        tx: &[u8],
+        check_only: bool,
+    ) -> Result<TxOutcome, RuntimeError> {
+        let deferred = ctx.deferred.clone();
+        deferred.begin_tx();
+
+        let mut overlay = OverlayTree::new(InnerState(&mut *ctx.runtime_state));
+        let outcome = {
+            let mut tx = TxScope {
+                runtime_state: &mut overlay,
+                header: ctx.header,
+                epoch: ctx.epoch,
+                index,
+                hash: Hash::digest_bytes(tx),
+                tx,
+                check_only,
+                tags: Tags::new(),
+            };
+            match self.before_tx(&mut tx) {
+                Err(err) if check_only => TxOutcome::Rejected(TxResult::Check(CheckTxResult {
+                    error: err,
+                    meta: None,
+                })),
+                Err(err) => {
+                    TxOutcome::Rejected(TxResult::Execute(self.executor.reject_tx(&tx, err)))
+                }
+                Ok(()) if check_only => {
+                    TxOutcome::Accepted(TxResult::Check(self.executor.check_tx(&mut tx)?))
+                }
+                Ok(()) => {
+                    let mut result = self.executor.execute_tx(&mut tx)?;
+                    for stage in self.stages.iter().rev() {
+                        stage.after_tx(&mut tx, &mut result)?;
+                    }
+                    result.tags.append(&mut tx.tags);
+                    TxOutcome::Accepted(TxResult::Execute(result))
+                }
+            }
+        };
+
+        match outcome {
+            TxOutcome::Accepted(_) => {
+                overlay.commit().map_err(|err| {
+                    RuntimeError::new(MODULE_NAME, CODE_STATE_COMMIT_FAILED, &err.to_string())
+                })?;
+                deferred.commit_tx();
+            }
+            TxOutcome::Rejected(_) => deferred.discard_tx(),
+        }
+        Ok(outcome)
+    }
+
+    fn execute(
+        &self,
+        mut ctx: Context,
+        batch: &TxnBatch,
+        in_msgs: &[roothash::IncomingMessage],
+        schedule: bool,
+    ) -> Result<(ExecuteBatchResult, TxnBatch), RuntimeError> {
+        self.begin_batch(&mut ctx)?;
+        let in_msgs_count = self.executor.execute_in_msgs(&mut ctx, in_msgs)?;
+
+        let mut results = Vec::with_capacity(batch.len());
+        let mut executed = Vec::with_capacity(batch.len());
+        let mut tx_reject_hashes = Vec::new();
+        for (index, tx) in batch.iter().enumerate() {
+            self.check_abort()?;
+            match self.process_tx(&mut ctx, index, tx, false)? {
+                TxOutcome::Rejected(_) if schedule => {
+                    tx_reject_hashes.push(Hash::digest_bytes(tx));
+                    continue;
+                }
+                TxOutcome::Accepted(result) | TxOutcome::Rejected(result) => {
+                    results.push(result.into_execute());
+                }
+            }
+            executed.push(tx.clone());
+        }
+
+        let mut result = ExecuteBatchResult {
+            results,
+            messages: Vec::new(),
+            in_msgs_count,
+            block_tags: Tags::new(),
+            tx_reject_hashes,
+        };
+        for stage in self.stages.iter().rev() {
+            stage.end_batch(&mut ctx, &mut result)?;
+        }
+        Ok((result, executed.into()))
+    }
+}
+
+/// Result of processing a single transaction.
+enum TxResult {
+    Execute(ExecuteTxResult),
+    Check(CheckTxResult),
+}
+
+impl TxResult {
+    fn into_execute(self) -> ExecuteTxResult {
+        match self {
+            TxResult::Execute(result) => result,
+            TxResult::Check(_) => unreachable!("transaction should be executed"),
+        }
+    }
+
+    fn into_check(self) -> CheckTxResult {
+        match self {
+            TxResult::Check(result) => result,
+            TxResult::Execute(_) => unreachable!("transaction should be checked"),
+        }
+    }
+}
+
+/// Outcome of processing a single transaction.
+enum TxOutcome {
+    /// The transaction passed all stages.
+    Accepted(TxResult),
+    /// The transaction was rejected by a stage.
+    Rejected(TxResult),
+}
+
+/// Runtime state of the batch, as the inner tree of per-transaction overlays.
+struct InnerState<'a>(&'a mut dyn MKVS);
+
+impl FallibleMKVS for InnerState<'_> {
+    fn get(&self, key: &[u8]) -> anyhow::Result<Option<Vec<u8>>> {
+        Ok(self.0.get(key))
+    }
+
+    fn get_proof(&self, key: &[u8]) -> anyhow::Result<Option<Proof>> {
+        Ok(self.0.get_proof(key))
+    }
+
+    fn cache_contains_key(&self, key: &[u8]) -> bool {
+        self.0.cache_contains_key(key)
+    }
+
+    fn insert(&mut self, key: &[u8], value: &[u8]) -> anyhow::Result<Option<Vec<u8>>> {
+        Ok(self.0.insert(key, value))
+    }
+
+    fn remove(&mut self, key: &[u8]) -> anyhow::Result<Option<Vec<u8>>> {
+        Ok(self.0.remove(key))
+    }
+
+    fn prefetch_prefixes(&self, prefixes: &[Prefix], limit: u16) -> anyhow::Result<()> {
+        self.0.prefetch_prefixes(prefixes, limit);
+        Ok(())
+    }
+
+    fn prefetch_prefix(&self, prefix: &Prefix, max_size: u64) -> anyhow::Result<()> {
+        self.0.prefetch_prefix(prefix, max_size);
+        Ok(())
+    }
+
+    fn iter(&self) -> Box<dyn mkvs::Iterator + '_> {
+        self.0.iter()
+    }
+
+    fn commit(&mut self, namespace: Namespace, version: u64) -> anyhow::Result<Hash> {
+        Ok(self.0.commit(namespace, version)?.1)
+    }
+}
+
+impl Dispatcher for PipelineDispatcher {
+    fn execute_batch(
+        &self,
+        ctx: Context,
+        batch: &TxnBatch,
+        in_msgs: &[roothash::IncomingMessage],
+    ) -> Result<ExecuteBatchResult, RuntimeError> {
+        // The batch has already been scheduled, so rejected transactions remain part of it.
+        let (result, _) = self.execute(ctx, batch, in_msgs, false)?;
+        Ok(result)
+    }
+
+    fn schedule_and_execute_batch(
+        &self,
+        ctx: Context,
+        initial_batch: &mut TxnBatch,
+        in_msgs: &[roothash::IncomingMessage],
+    ) -> Result<ExecuteBatchResult, RuntimeError> {
+        // No additional transactions are requested, but rejected transactions are dropped from
+        // the initial batch.
+        let (result, scheduled) = self.execute(ctx, initial_batch, in_msgs, true)?;
+        *initial_batch = scheduled;
+        Ok(result)
+    }
+
+    fn check_batch(
+        &self,
+        mut ctx: Context,
+        batch: &TxnBatch,
+    ) -> Result<Vec<CheckTxResult>, RuntimeError> {
+        self.begin_batch(&mut ctx)?;
+
+        batch
+            .iter()
+            .enumerate()
+            .map(
+                |(index, tx)| match self.process_tx(&mut ctx, index, tx, true)? {
+                    TxOutcome::Accepted(result) | TxOutcome::Rejected(result) => {
+                        Ok(result.into_check())
+                    }
+                },
+            )
+            .collect()
+    }
+
+    fn set_abort_batch_flag(&mut self, abort_batch: Arc<AtomicBool>) {
+        self.abort_batch = Some(abort_batch);
+    }
+
+    fn query(&self, ctx: Context, method: &str, args: Vec<u8>) -> Result<Vec<u8>, RuntimeError> {
+        self.executor.query(ctx, method, args)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::Mutex;
+
+    use super::*;
+    use crate::{
+        config::Config,
+        protocol::HostInfo,
+        testkit::{Step, TestKit},
+        transaction::tags::Tag,
+    };
+
+    /// Executor storing every transaction in the state.
+    struct StoreExecutor;
+
+    impl TxExecutor for StoreExecutor {
+        fn execute_tx(&self, tx: &mut TxScope) -> Result<ExecuteTxResult, RuntimeError> {
+            tx.runtime_state.insert(tx.tx, tx.tx);
+            Ok(ExecuteTxResult {
+                output: b"ok".to_vec(),
+                tags: Tags::new(),
+            })
+        }
+
+        fn reject_tx(&self, _tx: &TxScope, err: RuntimeError) -> ExecuteTxResult {
+            ExecuteTxResult {
+                output: err.message.into_bytes(),
+                tags: Tags::new(),
+            }
+        }
+
+        fn check_tx(&self, _tx: &mut TxScope) -> Result<CheckTxResult, RuntimeError> {
+            Ok(CheckTxResult::default())
+        }
+    }
+
+    /// Stage tracking the nonce of a single sender, rejecting transactions starting with `!` after
+    /// updating the nonce.
+    struct NonceStage {
+        calls: Arc<Mutex<Vec<String>>>,
+    }
+
+    impl Stage for NonceStage {
+        fn begin_batch(&self, _ctx: &mut Context) -> Result<(), RuntimeError> {
+            self.calls.lock().unwrap().push("begin".to_string());
+            Ok(())
+        }
+
+        fn before_tx(&self, tx: &mut TxScope) -> Result<(), RuntimeError> {
+            let nonce = tx.runtime_state.get(b"nonce").map_or(0, |nonce| nonce[0]);
+            tx.runtime_state.insert(b"nonce", &[nonce + 1]);
+            if tx.tx.starts_with(b"!") {
+                return Err(RuntimeError::new("test", 1, "invalid nonce"));
+            }
+            Ok(())
+        }
+
+        fn after_tx(
+            &self,
+            tx: &mut TxScope,
+            _result: &mut ExecuteTxResult,
+        ) -> Result<(), RuntimeError> {
+            tx.tags.push(Tag::new(b"nonce".to_vec(), vec![]));
+            Ok(())
+        }
+
+        fn end_batch(
+            &self,
+            _ctx: &mut Context,
+            result: &mut ExecuteBatchResult,
+        ) -> Result<(), RuntimeError> {
+            self.calls.lock().unwrap().push("end".to_string());
+            result.block_tags.push(Tag::new(b"batch".to_vec(), vec![]));
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_pipeline() {
+        let calls = Arc::new(Mutex::new(Vec::new()));
+        let dispatcher =
+            PipelineDispatcher::new(Box::new(StoreExecutor)).with_stage(Box::new(NonceStage {
+                calls: calls.clone(),
+            }));
+        let mut kit = TestKit::new(
+            Config::default(),
+            HostInfo {
+                runtime_id: Default::default(),
+                consensus_backend: "tendermint".to_string(),
+                consensus_protocol_version: Default::default(),
+                consensus_chain_context: "test".to_string(),
+                local_config: Default::default(),
+                features: Default::default(),
+            },
+            Box::new(dispatcher),
+        );
+
+        let reports = kit
+            .run(vec![Step::Batch {
+                inputs: vec![b"tx1".to_vec(), b"!tx2".to_vec(), b"tx3".to_vec()].into(),
+                in_msgs: vec![],
+            }])
+            .unwrap();
+        let report = &reports[0];
+
+        // Rejected transactions skip the executor and the post-execution hooks, and their state
+        // changes are discarded.
+        assert_eq!(
+            report.outputs,
+            vec![b"ok".to_vec(), b"invalid nonce".to_vec(), b"ok".to_vec()]
+        );
+        assert_eq!(report.tags[0].len(), 1);
+        assert!(report.tags[1].is_empty());
+        assert_eq!(report.block_tags.len(), 1);
+        assert_eq!(kit.get(b"nonce"), Some(vec![2]));
+        assert_eq!(kit.get(b"tx3"), Some(b"tx3".to_vec()));
+        assert_eq!(kit.get(b"!tx2"), None);
+        assert_eq!(*calls.lock().unwrap(), vec!["begin", "end"]);
+    }
+}
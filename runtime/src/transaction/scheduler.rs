@@ -0,0 +1,328 @@
+//! Ordering of scheduled transaction batches.
+//!
+//! By default transactions are executed in the order in which they were received. Runtimes with a
+//! fee market can configure a [`BatchScheduler`] which is invoked on the initial batch in schedule
+//! execution mode, before it reaches the transaction dispatcher. The scheduler decodes the
+//! metadata relevant for ordering from each transaction, drops transactions that can't be decoded
+//! and orders the rest. The resulting order is committed to via a block tag, so that clients can
+//! verify which order the scheduler produced.
+use std::{
+    cmp::Ordering,
+    collections::{BTreeMap, BinaryHeap, VecDeque},
+    sync::{atomic::AtomicBool, Arc},
+};
+
+use super::{
+    context::Context,
+    dispatcher::{Dispatcher, ExecuteBatchResult},
+    tags::Tag,
+    types::TxnBatch,
+};
+use crate::{
+    common::crypto::hash::Hash,
+    consensus::roothash,
+    types::{CheckTxResult, Error as RuntimeError},
+};
+
+/// Key of the block tag committing to the order of the scheduled batch.
+pub const BATCH_ORDER_TAG_KEY: &[u8] = b"batch_order";
+
+/// Transaction metadata relevant for scheduling.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct TxMetadata {
+    /// Sender of the transaction.
+    pub sender: Vec<u8>,
+    /// Sender nonce of the transaction.
+    pub nonce: u64,
+    /// Price the sender is willing to pay per unit of gas.
+    pub gas_price: u128,
+    /// Priority declared by the transaction.
+    pub priority: u64,
+}
+
+/// A transaction of the batch being scheduled.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ScheduledTx {
+    /// Index of the transaction in the initial batch.
+    pub index: usize,
+    /// Hash of the transaction.
+    pub hash: Hash,
+    /// Decoded transaction metadata.
+    pub metadata: TxMetadata,
+}
+
+/// Scheduler of transaction batches.
+pub trait BatchScheduler: Send + Sync {
+    /// Decode the scheduling metadata of the given raw transaction.
+    ///
+    /// Transactions for which no metadata is returned are dropped from the batch and rejected.
+    fn decode(&self, ctx: &Context, tx: &[u8]) -> Option<TxMetadata>;
+
+    /// Order the decoded transactions, removing any that should be dropped.
+    ///
+    /// The order must only depend on the given transactions. The default ordering is described
+    /// in [`default_order`].
+    fn order(&self, txs: Vec<ScheduledTx>) -> Vec<ScheduledTx> {
+        default_order(txs)
+    }
+}
+
+/// Order transactions by priority, then by gas price, keeping the transactions of each sender in
+/// nonce order. Ties are broken by the order in which the transactions were received. Only the
+/// first transaction with a given sender and nonce is kept.
+pub fn default_order(txs: Vec<ScheduledTx>) -> Vec<ScheduledTx> {
+    // Queue the transactions of each sender in nonce order.
+    let mut senders: BTreeMap<Vec<u8>, BTreeMap<u64, ScheduledTx>> = BTreeMap::new();
+    for tx in txs {
+        senders
+            .entry(tx.metadata.sender.clone())
+            .or_default()
+            .entry(tx.metadata.nonce)
+            .or_insert(tx);
+    }
+    let mut queues: Vec<VecDeque<ScheduledTx>> = senders
+        .into_values()
+        .map(|txs| txs.into_values().collect())
+        .collect();
+
+    // Repeatedly take the best transaction among the heads of all sender queues.
+    let mut heads: BinaryHeap<Head> = queues
+        .iter()
+        .enumerate()
+        .map(|(queue, txs)| Head::new(queue, &txs[0]))
+        .collect();
+    let mut ordered = Vec::new();
+    while let Some(head) = heads.pop() {
+        let queue = &mut queues[head.queue];
+        ordered.push(queue.pop_front().unwrap());
+        if let Some(next) = queue.front() {
+            heads.push(Head::new(head.queue, next));
+        }
+    }
+    ordered
+}
+
+/// Head of a sender queue, ordered by scheduling preference.
+#[derive(PartialEq, Eq)]
+struct Head {
+    priority: u64,
+    gas_price: u128,
+    index: usize,
+    queue: usize,
+}
+
+impl Head {
+    fn new(queue: usize, tx: &ScheduledTx) -> Self {
+        Self {
+            priority: tx.metadata.priority,
+            gas_price: tx.metadata.gas_price,
+            index: tx.index,
+            queue,
+        }
+    }
+}
+
+impl Ord for Head {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.priority
+            .cmp(&other.priority)
+            .then(self.gas_price.cmp(&other.gas_price))
+            .then(other.index.cmp(&self.index))
+    }
+}
+
+impl PartialOrd for Head {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+/// Result of scheduling a batch.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Schedule {
+    /// Scheduled batch.
+    pub batch: TxnBatch,
+    /// Hashes of the dropped transactions.
+    pub dropped: Vec<Hash>,
+    /// Commitment to the order of the scheduled batch.
+    pub commitment: Hash,
+}
+
+impl Schedule {
+    /// Schedule the given batch, given the decoded metadata of each transaction and the
+    /// ordering function.
+    pub fn new<F>(batch: TxnBatch, metadata: Vec<Option<TxMetadata>>, order: F) -> Self
+    where
+        F: FnOnce(Vec<ScheduledTx>) -> Vec<ScheduledTx>,
+    {
+        let hashes: Vec<Hash> = batch.iter().map(|tx| Hash::digest_bytes(tx)).collect();
+        let decoded = metadata
+            .into_iter()
+            .enumerate()
+            .filter_map(|(index, metadata)| {
+                Some(ScheduledTx {
+                    index,
+                    hash: hashes[index],
+                    metadata: metadata?,
+                })
+            })
+            .collect();
+        let ordered = order(decoded);
+
+        let mut included = vec![false; batch.len()];
+        let mut txs: Vec<Option<Vec<u8>>> = batch.0.into_iter().map(Some).collect();
+        let mut scheduled = Vec::with_capacity(ordered.len());
+        let mut order = Vec::with_capacity(ordered.len());
+        for tx in &ordered {
+            // Ignore unknown and repeated transactions.
+            if let Some(raw) = txs.get_mut(tx.index).and_then(Option::take) {
+                included[tx.index] = true;
+                scheduled.push(raw);
+                order.push(hashes[tx.index]);
+            }
+        }
+        let dropped = hashes
+            .iter()
+            .zip(&included)
+            .filter(|(_, included)| !**included)
+            .map(|(hash, _)| *hash)
+            .collect();
+
+        Self {
+            batch: scheduled.into(),
+            dropped,
+            commitment: Hash::digest_bytes(&cbor::to_vec(order)),
+        }
+    }
+}
+
+/// Transaction dispatcher wrapper which orders initial batches using a scheduler.
+pub struct SchedulingDispatcher {
+    inner: Box<dyn Dispatcher>,
+    scheduler: Box<dyn BatchScheduler>,
+}
+
+impl SchedulingDispatcher {
+    /// Wrap the given dispatcher, ordering initial batches with the given scheduler.
+    pub fn new(inner: Box<dyn Dispatcher>, scheduler: Box<dyn BatchScheduler>) -> Self {
+        Self { inner, scheduler }
+    }
+}
+
+impl Dispatcher for SchedulingDispatcher {
+    fn is_supported(&self) -> bool {
+        self.inner.is_supported()
+    }
+
+    fn execute_batch(
+        &self,
+        ctx: Context,
+        batch: &TxnBatch,
+        in_msgs: &[roothash::IncomingMessage],
+    ) -> Result<ExecuteBatchResult, RuntimeError> {
+        // The order of batches being executed has already been committed to by the scheduler.
+        self.inner.execute_batch(ctx, batch, in_msgs)
+    }
+
+    fn schedule_and_execute_batch(
+        &self,
+        ctx: Context,
+        initial_batch: &mut TxnBatch,
+        in_msgs: &[roothash::IncomingMessage],
+    ) -> Result<ExecuteBatchResult, RuntimeError> {
+        let metadata = initial_batch
+            .iter()
+            .map(|tx| self.scheduler.decode(&ctx, tx))
+            .collect();
+        let schedule = Schedule::new(std::mem::take(initial_batch), metadata, |txs| {
+            self.scheduler.order(txs)
+        });
+        *initial_batch = schedule.batch;
+
+        let mut result = self
+            .inner
+            .schedule_and_execute_batch(ctx, initial_batch, in_msgs)?;
+        result.tx_reject_hashes.extend(schedule.dropped);
+        result.block_tags.push(Tag::new(
+            BATCH_ORDER_TAG_KEY.to_vec(),
+            schedule.commitment.as_ref().to_vec(),
+        ));
+        Ok(result)
+    }
+
+    fn check_batch(
+        &self,
+        ctx: Context,
+        batch: &TxnBatch,
+    ) -> Result<Vec<CheckTxResult>, RuntimeError> {
+        self.inner.check_batch(ctx, batch)
+    }
+
+    fn finalize(&self, new_storage_root: Hash) {
+        self.inner.finalize(new_storage_root)
+    }
+
+    fn set_abort_batch_flag(&mut self, abort_batch: Arc<AtomicBool>) {
+        self.inner.set_abort_batch_flag(abort_batch)
+    }
+
+    fn query(&self, ctx: Context, method: &str, args: Vec<u8>) -> Result<Vec<u8>, RuntimeError> {
+        self.inner.query(ctx, method, args)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn metadata(sender: u8, nonce: u64, gas_price: u128, priority: u64) -> Option<TxMetadata> {
+        Some(TxMetadata {
+            sender: vec![sender],
+            nonce,
+            gas_price,
+            priority,
+        })
+    }
+
+    #[test]
+    fn test_schedule() {
+        let batch = TxnBatch::new((0..7u8).map(|i| vec![i]).collect());
+        let schedule = Schedule::new(
+            batch.clone(),
+            vec![
+                metadata(1, 1, 10, 0),
+                metadata(1, 0, 1, 0),
+                metadata(2, 0, 5, 0),
+                None,
+                metadata(3, 0, 1, 1),
+                metadata(2, 0, 100, 0), // Duplicate nonce.
+                metadata(4, 0, 5, 0),
+            ],
+            default_order,
+        );
+
+        // Priority first, then gas price with the nonce order of each sender preserved and ties
+        // broken by the order of arrival.
+        assert_eq!(
+            schedule.batch,
+            TxnBatch::new(vec![vec![4], vec![2], vec![6], vec![1], vec![0]])
+        );
+        assert_eq!(
+            schedule.dropped,
+            vec![Hash::digest_bytes(&[3]), Hash::digest_bytes(&[5])]
+        );
+
+        // Scheduling is deterministic and the commitment depends on the order.
+        let fifo = Schedule::new(batch.clone(), vec![metadata(0, 0, 0, 0); 7], |txs| txs);
+        assert_eq!(fifo.batch, batch);
+        assert_eq!(
+            Schedule::new(batch.clone(), vec![metadata(0, 0, 0, 0); 7], |txs| txs),
+            fifo
+        );
+        let reversed = Schedule::new(batch, vec![metadata(0, 0, 0, 0); 7], |mut txs| {
+            txs.reverse();
+            txs
+        });
+        assert_ne!(reversed.commitment, fifo.commitment);
+    }
+}
@@ -0,0 +1,127 @@
+//! Shadow execution of candidate runtime versions.
+//!
+//! To de-risk upgrades, a runtime may bundle the transaction dispatcher of the upcoming version
+//! alongside the active one. After each batch is executed by the active version, the candidate
+//! executes the same batch against the same state and its results are compared with the active
+//! ones. Results of shadow execution are never committed, divergences are only reported to the
+//! host so that operators can investigate them before the upgrade activates.
+use crate::{
+    common::{crypto::hash::Hash, version::Version},
+    consensus::roothash::ComputeResultsHeader,
+    types::Error as RuntimeError,
+};
+
+use super::dispatcher::Dispatcher;
+
+/// Candidate runtime version used for shadow execution.
+pub struct Candidate {
+    /// Version of the candidate runtime.
+    pub version: Version,
+    /// Transaction dispatcher of the candidate runtime.
+    pub dispatcher: Box<dyn Dispatcher>,
+}
+
+impl Candidate {
+    /// Create a new candidate runtime version.
+    pub fn new(version: Version, dispatcher: Box<dyn Dispatcher>) -> Self {
+        Self {
+            version,
+            dispatcher,
+        }
+    }
+}
+
+/// Summary of the results of executing a batch which are compared during shadow execution.
+#[derive(Clone, Debug, Default, PartialEq, Eq, cbor::Encode, cbor::Decode)]
+pub struct ExecutionSummary {
+    /// State root after executing the batch.
+    pub state_root: Hash,
+    /// Hash of the emitted messages.
+    pub messages_hash: Hash,
+    /// Number of processed incoming messages.
+    pub in_msgs_count: u32,
+}
+
+impl From<&ComputeResultsHeader> for ExecutionSummary {
+    fn from(header: &ComputeResultsHeader) -> Self {
+        Self {
+            state_root: header.state_root.unwrap_or_default(),
+            messages_hash: header.messages_hash.unwrap_or_default(),
+            in_msgs_count: header.in_msgs_count,
+        }
+    }
+}
+
+/// Divergence between the results of the active and the candidate runtime version.
+#[derive(Clone, Debug, Default, cbor::Encode, cbor::Decode)]
+pub struct Divergence {
+    /// Version of the candidate runtime.
+    pub candidate_version: Version,
+    /// Round in which the divergence occurred.
+    pub round: u64,
+    /// Results of the active runtime version.
+    pub active: ExecutionSummary,
+    /// Results of the candidate runtime version, if execution succeeded.
+    #[cbor(optional)]
+    pub candidate: Option<ExecutionSummary>,
+    /// Error returned by the candidate runtime version, if execution failed.
+    #[cbor(optional)]
+    pub error: Option<RuntimeError>,
+}
+
+impl Divergence {
+    /// Compare the results of shadow execution with the results of the active runtime version,
+    /// returning the divergence in case they differ.
+    pub fn check(
+        candidate_version: Version,
+        round: u64,
+        active: ExecutionSummary,
+        candidate: Result<ExecutionSummary, RuntimeError>,
+    ) -> Option<Self> {
+        let (candidate, error) = match candidate {
+            Ok(candidate) if candidate == active => return None,
+            Ok(candidate) => (Some(candidate), None),
+            Err(err) => (None, Some(err)),
+        };
+
+        Some(Self {
+            candidate_version,
+            round,
+            active,
+            candidate,
+            error,
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_divergence_check() {
+        let version = Version::new(1, 2, 0);
+        let active = ExecutionSummary {
+            state_root: Hash::digest_bytes(b"state"),
+            messages_hash: Hash::empty_hash(),
+            in_msgs_count: 1,
+        };
+
+        assert!(Divergence::check(version, 5, active.clone(), Ok(active.clone())).is_none());
+
+        let candidate = ExecutionSummary {
+            in_msgs_count: 0,
+            ..active.clone()
+        };
+        let divergence =
+            Divergence::check(version, 5, active.clone(), Ok(candidate.clone())).unwrap();
+        assert_eq!(divergence.round, 5);
+        assert_eq!(divergence.candidate, Some(candidate));
+        assert!(divergence.error.is_none());
+
+        let err = RuntimeError::new("test", 1, "failed");
+        let divergence = Divergence::check(version, 5, active, Err(err.clone())).unwrap();
+        assert_eq!(divergence.candidate, None);
+        assert_eq!(divergence.error.map(|err| err.message), Some(err.message));
+    }
+}
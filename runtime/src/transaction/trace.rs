@@ -0,0 +1,351 @@
+//! Runtime call tracing.
+//!
+//! Summaries of the most recent calls processed by the runtime (method, caller, gas, result code
+//! and duration) are kept in a fixed-size in-enclave ring buffer. This gives operators a view of
+//! recent activity for debugging without the overhead of full logging. As summaries may reveal
+//! information about callers, they are only exposed to the callers allowed by the configured
+//! `CallTraceAccess` via the `METHOD_CALL_TRACE` method.
+//!
+//! The runtime dispatcher records queries, and checked and executed transactions identified by
+//! their hash. Transactions are opaque to it, so their summaries carry neither the method nor
+//! the gas usage or duration. Transaction dispatchers know more about transactions and may
+//! record richer summaries themselves via `CallTracer::global()`.
+use std::{collections::VecDeque, sync::Mutex, time::Duration};
+
+use anyhow::{bail, Result};
+use thiserror::Error;
+
+use crate::{
+    common::crypto::hash::Hash,
+    config::CallTraceAccess,
+    enclave_rpc::{
+        dispatcher::{Method, MethodDescriptor},
+        types::Kind as RpcKind,
+        Context as RpcContext,
+    },
+    types::Error as RuntimeError,
+};
+
+/// Name of the enclave RPC method returning recent call summaries.
+pub const METHOD_CALL_TRACE: &str = "runtime.CallTrace";
+
+lazy_static! {
+    static ref CALL_TRACER: CallTracer = CallTracer::new(0);
+}
+
+/// Call trace errors.
+#[derive(Error, Debug)]
+pub enum CallTraceError {
+    #[error("caller not allowed to access the call trace")]
+    NotAllowed,
+}
+
+/// Kind of a traced call.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, cbor::Encode, cbor::Decode)]
+#[cbor(with_default)]
+#[repr(u8)]
+pub enum CallKind {
+    /// Runtime query.
+    Query = 0,
+    /// Transaction check.
+    CheckTx = 1,
+    /// Transaction execution.
+    ExecuteTx = 2,
+}
+
+impl Default for CallKind {
+    fn default() -> Self {
+        Self::Query
+    }
+}
+
+/// Summary of a processed call.
+#[derive(Clone, Debug, Default, PartialEq, Eq, cbor::Encode, cbor::Decode)]
+pub struct CallSummary {
+    /// Kind of the call.
+    pub kind: CallKind,
+    /// Invoked method, empty in case it is not known.
+    #[cbor(optional)]
+    pub method: String,
+    /// Hash of the transaction, for transaction checks and executions.
+    #[cbor(optional)]
+    pub tx_hash: Option<Hash>,
+    /// Caller of the method, if known.
+    #[cbor(optional)]
+    pub caller: Vec<u8>,
+    /// Amount of gas used, if metered.
+    #[cbor(optional)]
+    pub gas_used: u64,
+    /// Module of the resulting error, empty on success.
+    #[cbor(optional)]
+    pub module: String,
+    /// Result code, zero on success.
+    #[cbor(optional)]
+    pub code: u32,
+    /// Call duration in microseconds, zero in case it is not known.
+    #[cbor(optional)]
+    pub duration_us: u64,
+}
+
+impl CallSummary {
+    /// Create a new summary of a call that took the given amount of time.
+    pub fn new(kind: CallKind, method: &str, duration: Duration) -> Self {
+        Self {
+            kind,
+            method: method.to_string(),
+            duration_us: duration.as_micros().try_into().unwrap_or(u64::MAX),
+            ..Default::default()
+        }
+    }
+
+    /// Create a new summary of a transaction with the given hash, whose method and duration are
+    /// not known.
+    pub fn transaction(kind: CallKind, tx_hash: Hash) -> Self {
+        Self {
+            kind,
+            tx_hash: Some(tx_hash),
+            ..Default::default()
+        }
+    }
+
+    /// Set the caller of the method.
+    pub fn with_caller(mut self, caller: &[u8]) -> Self {
+        self.caller = caller.to_vec();
+        self
+    }
+
+    /// Set the amount of gas used.
+    pub fn with_gas_used(mut self, gas_used: u64) -> Self {
+        self.gas_used = gas_used;
+        self
+    }
+
+    /// Set the error the call resulted in.
+    pub fn with_error(mut self, err: &RuntimeError) -> Self {
+        self.module = err.module.clone();
+        self.code = err.code;
+        self
+    }
+
+    /// Set the result of the call.
+    pub fn with_result<T>(self, result: &Result<T, RuntimeError>) -> Self {
+        match result {
+            Ok(_) => self,
+            Err(err) => self.with_error(err),
+        }
+    }
+}
+
+/// Request for the call trace RPC method.
+#[derive(Clone, Debug, Default, cbor::Encode, cbor::Decode)]
+pub struct CallTraceRequest {
+    /// Maximum number of summaries to return, zero means all.
+    #[cbor(optional)]
+    pub limit: u32,
+}
+
+/// Response of the call trace RPC method.
+#[derive(Clone, Debug, Default, cbor::Encode, cbor::Decode)]
+pub struct CallTraceResponse {
+    /// Summaries of the most recent calls, oldest first.
+    pub calls: Vec<CallSummary>,
+    /// Total number of calls recorded since the runtime started, including evicted ones.
+    pub total: u64,
+}
+
+struct Buffer {
+    capacity: usize,
+    calls: VecDeque<CallSummary>,
+    total: u64,
+}
+
+/// Fixed-size ring buffer of recent call summaries.
+pub struct CallTracer {
+    inner: Mutex<Buffer>,
+}
+
+impl CallTracer {
+    /// Create a new tracer retaining up to `capacity` summaries. A zero capacity disables
+    /// tracing.
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            inner: Mutex::new(Buffer {
+                capacity,
+                calls: VecDeque::with_capacity(capacity),
+                total: 0,
+            }),
+        }
+    }
+
+    /// Global call tracer instance.
+    pub fn global() -> &'static CallTracer {
+        &CALL_TRACER
+    }
+
+    /// Change the number of retained summaries, evicting the oldest ones if needed.
+    pub fn set_capacity(&self, capacity: usize) {
+        let mut inner = self.inner.lock().unwrap();
+        inner.capacity = capacity;
+        while inner.calls.len() > capacity {
+            inner.calls.pop_front();
+        }
+    }
+
+    /// Whether tracing is enabled.
+    pub fn is_enabled(&self) -> bool {
+        self.inner.lock().unwrap().capacity > 0
+    }
+
+    /// Record a call summary, evicting the oldest one in case the buffer is full.
+    pub fn record(&self, summary: CallSummary) {
+        let mut inner = self.inner.lock().unwrap();
+        if inner.capacity == 0 {
+            return;
+        }
+        if inner.calls.len() >= inner.capacity {
+            inner.calls.pop_front();
+        }
+        inner.calls.push_back(summary);
+        inner.total += 1;
+    }
+
+    /// Return up to `limit` most recent call summaries, oldest first. A zero limit returns all
+    /// retained summaries.
+    pub fn recent(&self, limit: usize) -> CallTraceResponse {
+        let inner = self.inner.lock().unwrap();
+        let skip = match limit {
+            0 => 0,
+            limit => inner.calls.len().saturating_sub(limit),
+        };
+
+        CallTraceResponse {
+            calls: inner.calls.iter().skip(skip).cloned().collect(),
+            total: inner.total,
+        }
+    }
+
+    /// Enclave RPC method exposing the global call trace to the callers allowed by the given
+    /// access policy.
+    pub fn rpc_method(access: &CallTraceAccess) -> Method {
+        let kind = match access {
+            CallTraceAccess::Local => RpcKind::LocalQuery,
+            CallTraceAccess::Operators(_) => RpcKind::NoiseSession,
+        };
+        let access = access.clone();
+
+        Method::new(
+            MethodDescriptor {
+                name: METHOD_CALL_TRACE.to_string(),
+                kind,
+                allow_anonymous: false,
+                cacheable: false,
+            },
+            move |ctx: &RpcContext, req: &CallTraceRequest| -> Result<CallTraceResponse> {
+                authorize(&access, ctx)?;
+                Ok(CallTracer::global().recent(req.limit as usize))
+            },
+        )
+    }
+}
+
+/// Ensure the caller is allowed to access the call trace by the given access policy.
+///
+/// Local queries can only be made by the host, so the method kind alone restricts access to
+/// them. Calls over sessions are only allowed from attested enclaves hosted by operator nodes.
+fn authorize(access: &CallTraceAccess, ctx: &RpcContext) -> Result<()> {
+    match access {
+        CallTraceAccess::Local => Ok(()),
+        CallTraceAccess::Operators(operators) => {
+            let node_id = ctx.caller().and_then(|caller| caller.node_id);
+            if !node_id.map_or(false, |node_id| operators.contains(&node_id)) {
+                bail!(CallTraceError::NotAllowed);
+            }
+            Ok(())
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::HashSet;
+
+    use super::*;
+    use crate::{common::crypto::signature::PublicKey, enclave_rpc::CallerContext};
+
+    #[test]
+    fn test_call_tracer() {
+        let tracer = CallTracer::new(0);
+        tracer.record(CallSummary::new(CallKind::Query, "a", Duration::ZERO));
+        assert!(!tracer.is_enabled());
+        assert_eq!(tracer.recent(0).total, 0);
+
+        let tracer = CallTracer::new(3);
+        for i in 0..5u64 {
+            let result: Result<(), RuntimeError> = match i {
+                4 => Err(RuntimeError::new("test", 7, "failed")),
+                _ => Ok(()),
+            };
+            tracer.record(
+                CallSummary::new(
+                    CallKind::ExecuteTx,
+                    &format!("m{i}"),
+                    Duration::from_millis(i),
+                )
+                .with_caller(b"caller")
+                .with_gas_used(i * 10)
+                .with_result(&result),
+            );
+        }
+
+        // Only the most recent calls are retained.
+        let trace = tracer.recent(0);
+        assert_eq!(trace.total, 5);
+        let methods: Vec<_> = trace.calls.iter().map(|c| c.method.as_str()).collect();
+        assert_eq!(methods, vec!["m2", "m3", "m4"]);
+        assert_eq!(trace.calls[1].gas_used, 30);
+        assert_eq!(trace.calls[1].duration_us, 3000);
+        assert_eq!(trace.calls[1].code, 0);
+        assert_eq!(trace.calls[2].module, "test");
+        assert_eq!(trace.calls[2].code, 7);
+
+        let trace = tracer.recent(1);
+        assert_eq!(trace.calls.len(), 1);
+        assert_eq!(trace.calls[0].method, "m4");
+
+        tracer.set_capacity(1);
+        assert_eq!(tracer.recent(0).calls.len(), 1);
+
+        let tx_hash = Hash::digest_bytes(b"tx");
+        tracer.record(CallSummary::transaction(CallKind::CheckTx, tx_hash));
+        let trace = tracer.recent(0);
+        assert_eq!(trace.calls[0].tx_hash, Some(tx_hash));
+        assert_eq!(trace.calls[0].method, "");
+        assert_eq!(trace.calls[0].duration_us, 0);
+    }
+
+    #[test]
+    fn test_authorize() {
+        let operator = PublicKey([1; 32]);
+        let other = PublicKey([2; 32]);
+        let ctx = |node_id: Option<PublicKey>| {
+            let mut ctx = RpcContext::new(None);
+            ctx.caller = node_id.map(|node_id| CallerContext {
+                runtime_id: None,
+                enclave_identity: Default::default(),
+                app_id: Default::default(),
+                rak: Default::default(),
+                node_id: Some(node_id),
+            });
+            ctx
+        };
+
+        // Local queries are restricted by the method kind.
+        assert!(authorize(&CallTraceAccess::Local, &ctx(None)).is_ok());
+
+        // Calls over sessions must come from operator nodes.
+        let access = CallTraceAccess::Operators(HashSet::from([operator]));
+        assert!(authorize(&access, &ctx(Some(operator))).is_ok());
+        assert!(authorize(&access, &ctx(Some(other))).is_err());
+        assert!(authorize(&access, &ctx(None)).is_err());
+    }
+}
@@ -4,7 +4,11 @@ use anyhow::{anyhow, Result};
 use super::tags::Tags;
 use crate::{
     common::{crypto::hash::Hash, key_format::KeyFormat},
-    storage::mkvs::{self, sync::ReadSync, Root, WriteLog},
+    storage::mkvs::{
+        self,
+        sync::{verify_inclusion_proof, Proof, ProofError, ReadSync},
+        Root, WriteLog,
+    },
 };
 
 // NOTE: This should be kept in sync with go/runtime/transaction/transaction.go.
@@ -123,6 +127,37 @@ struct OutputArtifacts {
     pub output: Vec<u8>,
 }
 
+/// Keys of the input and output artifacts of the given transaction.
+fn artifact_keys(tx_hash: Hash) -> Vec<Vec<u8>> {
+    [ArtifactKind::Input, ArtifactKind::Output]
+        .into_iter()
+        .map(|kind| TxnKeyFormat { tx_hash, kind }.encode())
+        .collect()
+}
+
+/// Verify a proof of the input and output artifacts of the given transaction against the given
+/// I/O root, returning the batch order and the output of the transaction.
+pub fn verify_artifacts_proof(
+    io_root: Hash,
+    tx_hash: Hash,
+    proof: &Proof,
+) -> Result<(u32, Vec<u8>), ProofError> {
+    let values = verify_inclusion_proof(io_root, proof, &artifact_keys(tx_hash))?;
+    let (input, output) = match &values[..] {
+        [Some(input), Some(output)] => (input, output),
+        _ => return Err(ProofError::NotIncluded),
+    };
+    let ia: InputArtifacts =
+        cbor::from_slice(input).map_err(|err| ProofError::Invalid(err.into()))?;
+    let oa: OutputArtifacts =
+        cbor::from_slice(output).map_err(|err| ProofError::Invalid(err.into()))?;
+    if Hash::digest_bytes(&ia.input) != tx_hash {
+        return Err(ProofError::Mismatch("input"));
+    }
+
+    Ok((ia.batch_order, oa.output))
+}
+
 /// A Merkle tree containing transaction artifacts.
 pub struct Tree {
     io_root: Root,
@@ -307,4 +342,46 @@ mod test {
         let dec_output = tree.get_output(Hash::empty_hash()).unwrap();
         assert!(dec_output.is_none());
     }
+
+    #[test]
+    fn test_verify_artifacts_proof() {
+        let mut tree = Tree::new(
+            Box::new(NoopReadSyncer),
+            Root {
+                hash: Hash::empty_hash(),
+                root_type: mkvs::RootType::IO,
+                ..Default::default()
+            },
+        );
+        let input = b"this goes in".to_vec();
+        let tx_hash = Hash::digest_bytes(&input);
+        tree.add_input(input, 3).unwrap();
+        tree.add_output(tx_hash, b"and this comes out".to_vec(), vec![])
+            .unwrap();
+        let other_hash = Hash::digest_bytes(b"other");
+        tree.add_input(b"other".to_vec(), 4).unwrap();
+        let (write_log, _) = tree.commit().unwrap();
+
+        // Proofs are produced by the host from the committed I/O tree.
+        let mut io_tree = mkvs::Tree::builder()
+            .with_root_type(mkvs::RootType::IO)
+            .build(Box::new(NoopReadSyncer));
+        for entry in write_log {
+            io_tree.insert(&entry.key, &entry.value.unwrap()).unwrap();
+        }
+        let io_root = io_tree.commit(Default::default(), 0).unwrap();
+        let proof = io_tree.get_multi_proof(&artifact_keys(tx_hash)).unwrap();
+
+        let (batch_order, output) = verify_artifacts_proof(io_root, tx_hash, &proof).unwrap();
+        assert_eq!(batch_order, 3);
+        assert_eq!(output, b"and this comes out".to_vec());
+
+        // Transactions without outputs or not covered by the proof are rejected.
+        let other_proof = io_tree.get_multi_proof(&artifact_keys(other_hash)).unwrap();
+        assert!(matches!(
+            verify_artifacts_proof(io_root, other_hash, &other_proof),
+            Err(ProofError::NotIncluded)
+        ));
+        assert!(verify_artifacts_proof(io_root, other_hash, &proof).is_err());
+    }
 }
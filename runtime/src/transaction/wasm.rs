@@ -0,0 +1,455 @@
+//! Deterministic execution of WASM guests.
+//!
+//! Runtimes that allow users to deploy their own logic can execute it as WASM guests instead of
+//! each embedding a WASM engine differently. Guests are executed by an interpreter configured for
+//! deterministic execution: floating point instructions are rejected when the module is loaded,
+//! threads are not supported and the only host functions available to guests are those accessing
+//! their own storage, so guests have no access to time, randomness or other non-deterministic
+//! sources. Execution is metered and aborted once the gas limit is exhausted.
+//!
+//! # Guest ABI
+//!
+//! A guest must export its linear memory as `memory` together with the following functions:
+//!
+//! * `alloc(len: i32) -> i32` allocating a buffer of the given length for the call input.
+//! * `call(input_ptr: i32, input_len: i32) -> i64` executing the call and returning the location
+//!   of its output, packed as `(ptr << 32) | len`.
+//!
+//! The following host functions are imported from the `env` module:
+//!
+//! * `storage_get(key_ptr: i32, key_len: i32, value_ptr: i32, value_cap: i32) -> i64` copying
+//!   at most `value_cap` bytes of the value into the given buffer and returning the full length
+//!   of the value or `-1` in case the key does not exist.
+//! * `storage_insert(key_ptr: i32, key_len: i32, value_ptr: i32, value_len: i32)`.
+//! * `storage_remove(key_ptr: i32, key_len: i32)`.
+//!
+//! Guest storage is isolated under a key prefix chosen by the runtime and writes are only applied
+//! to the runtime state in case the call succeeds.
+use std::collections::BTreeMap;
+
+use thiserror::Error;
+use wasmi::{
+    core::{Trap, TrapCode},
+    Caller, Config, Engine, Extern, Linker, Memory, Module, Store, StoreLimits, StoreLimitsBuilder,
+};
+
+use super::context::Context;
+use crate::storage::MKVS;
+
+/// Name of the module from which host functions are imported.
+const HOST_MODULE: &str = "env";
+
+/// WASM guest execution errors.
+#[derive(Error, Debug)]
+pub enum WasmError {
+    #[error("invalid module: {0}")]
+    InvalidModule(String),
+    #[error("missing export: {0}")]
+    MissingExport(&'static str),
+    #[error("out of gas")]
+    OutOfGas,
+    #[error("execution failed: {0}")]
+    Trap(String),
+}
+
+impl From<wasmi::Error> for WasmError {
+    fn from(err: wasmi::Error) -> Self {
+        match err.as_trap_code() {
+            Some(TrapCode::OutOfFuel) => WasmError::OutOfGas,
+            _ => WasmError::Trap(err.to_string()),
+        }
+    }
+}
+
+/// WASM guest executor configuration.
+#[derive(Clone, Debug)]
+pub struct WasmConfig {
+    /// Maximum size of the guest's linear memory, in bytes.
+    pub max_memory: usize,
+    /// Gas charged per byte read from or written to guest storage.
+    pub storage_byte_cost: u64,
+    /// Gas charged per storage access.
+    pub storage_access_cost: u64,
+}
+
+impl Default for WasmConfig {
+    fn default() -> Self {
+        Self {
+            max_memory: 16 * 1024 * 1024, // 16 MiB
+            storage_byte_cost: 10,
+            storage_access_cost: 1_000,
+        }
+    }
+}
+
+/// Result of a successful guest call.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct WasmOutput {
+    /// Call output.
+    pub output: Vec<u8>,
+    /// Gas used by the call.
+    pub gas_used: u64,
+}
+
+/// Executor of deterministic WASM guests.
+pub struct WasmExecutor {
+    cfg: WasmConfig,
+    engine: Engine,
+}
+
+impl WasmExecutor {
+    /// Create a new executor with the given configuration.
+    pub fn new(cfg: WasmConfig) -> Self {
+        let mut engine_cfg = Config::default();
+        engine_cfg.consume_fuel(true).floats(false);
+
+        Self {
+            cfg,
+            engine: Engine::new(&engine_cfg),
+        }
+    }
+
+    /// Validate the given guest code, e.g. when it is deployed.
+    pub fn validate(&self, code: &[u8]) -> Result<(), WasmError> {
+        Module::new(&self.engine, code)
+            .map(|_| ())
+            .map_err(|err| WasmError::InvalidModule(err.to_string()))
+    }
+
+    /// Execute a call of the given guest with its storage under the given prefix, using at most
+    /// `gas_limit` gas.
+    ///
+    /// Storage writes are only applied to the runtime state in case the call succeeds.
+    pub fn execute(
+        &self,
+        runtime_state: &mut dyn MKVS,
+        prefix: &[u8],
+        code: &[u8],
+        input: &[u8],
+        gas_limit: u64,
+    ) -> Result<WasmOutput, WasmError> {
+        let module = Module::new(&self.engine, code)
+            .map_err(|err| WasmError::InvalidModule(err.to_string()))?;
+
+        let guest_state = GuestState {
+            cfg: self.cfg.clone(),
+            limits: StoreLimitsBuilder::new()
+                .memory_size(self.cfg.max_memory)
+                .instances(1)
+                .build(),
+            state: &*runtime_state,
+            prefix,
+            writes: BTreeMap::new(),
+        };
+        let mut store = Store::new(&self.engine, guest_state);
+        store.limiter(|guest| &mut guest.limits);
+        store.set_fuel(gas_limit)?;
+
+        let mut linker = <Linker<GuestState>>::new(&self.engine);
+        linker
+            .func_wrap(HOST_MODULE, "storage_get", storage_get)
+            .and_then(|linker| linker.func_wrap(HOST_MODULE, "storage_insert", storage_insert))
+            .and_then(|linker| linker.func_wrap(HOST_MODULE, "storage_remove", storage_remove))
+            .map_err(wasmi::Error::from)?;
+        let instance = linker.instantiate(&mut store, &module)?.start(&mut store)?;
+
+        let memory = instance
+            .get_memory(&store, "memory")
+            .ok_or(WasmError::MissingExport("memory"))?;
+        let alloc = instance
+            .get_typed_func::<i32, i32>(&store, "alloc")
+            .map_err(|_| WasmError::MissingExport("alloc"))?;
+        let call = instance
+            .get_typed_func::<(i32, i32), i64>(&store, "call")
+            .map_err(|_| WasmError::MissingExport("call"))?;
+
+        let input_len = guest_len(input.len())?;
+        let input_ptr = alloc.call(&mut store, input_len)?;
+        memory
+            .write(&mut store, input_ptr as u32 as usize, input)
+            .map_err(|err| WasmError::Trap(err.to_string()))?;
+        let packed = call.call(&mut store, (input_ptr, input_len))? as u64;
+
+        let output = guest_slice(
+            memory.data(&store),
+            (packed >> 32) as usize,
+            (packed & 0xffff_ffff) as usize,
+        )
+        .ok_or_else(|| WasmError::Trap("output out of bounds".to_string()))?
+        .to_vec();
+        let gas_used = gas_limit - store.get_fuel()?;
+
+        // Apply writes only after the call has succeeded.
+        let writes = store.into_data().writes;
+        for (key, value) in writes {
+            match value {
+                Some(value) => runtime_state.insert(&key, &value),
+                None => runtime_state.remove(&key),
+            };
+        }
+
+        Ok(WasmOutput { output, gas_used })
+    }
+}
+
+impl Context<'_> {
+    /// Execute a call of the given WASM guest against the runtime state, see
+    /// [`WasmExecutor::execute`].
+    pub fn execute_wasm(
+        &mut self,
+        executor: &WasmExecutor,
+        prefix: &[u8],
+        code: &[u8],
+        input: &[u8],
+        gas_limit: u64,
+    ) -> Result<WasmOutput, WasmError> {
+        executor.execute(self.runtime_state, prefix, code, input, gas_limit)
+    }
+}
+
+/// State available to host functions.
+struct GuestState<'a> {
+    cfg: WasmConfig,
+    limits: StoreLimits,
+    state: &'a dyn MKVS,
+    prefix: &'a [u8],
+    /// Pending writes, where `None` denotes a removal.
+    writes: BTreeMap<Vec<u8>, Option<Vec<u8>>>,
+}
+
+impl GuestState<'_> {
+    fn get(&self, key: &[u8]) -> Option<Vec<u8>> {
+        let key = self.key(key);
+        match self.writes.get(&key) {
+            Some(value) => value.clone(),
+            None => self.state.get(&key),
+        }
+    }
+
+    fn key(&self, key: &[u8]) -> Vec<u8> {
+        [self.prefix, key].concat()
+    }
+}
+
+fn storage_get(
+    mut caller: Caller<'_, GuestState<'_>>,
+    key_ptr: i32,
+    key_len: i32,
+    value_ptr: i32,
+    value_cap: i32,
+) -> Result<i64, wasmi::Error> {
+    let memory = guest_memory(&caller)?;
+    let key = read_guest(&caller, memory, key_ptr, key_len)?;
+    charge(&mut caller, key.len())?;
+
+    let Some(value) = caller.data().get(&key) else {
+        return Ok(-1);
+    };
+    charge(&mut caller, value.len())?;
+    let len = value.len().min(value_cap as u32 as usize);
+    write_guest(&mut caller, memory, value_ptr, &value[..len])?;
+
+    Ok(value.len() as i64)
+}
+
+fn storage_insert(
+    mut caller: Caller<'_, GuestState<'_>>,
+    key_ptr: i32,
+    key_len: i32,
+    value_ptr: i32,
+    value_len: i32,
+) -> Result<(), wasmi::Error> {
+    let memory = guest_memory(&caller)?;
+    let key = read_guest(&caller, memory, key_ptr, key_len)?;
+    let value = read_guest(&caller, memory, value_ptr, value_len)?;
+    charge(&mut caller, key.len() + value.len())?;
+
+    let key = caller.data().key(&key);
+    caller.data_mut().writes.insert(key, Some(value));
+    Ok(())
+}
+
+fn storage_remove(
+    mut caller: Caller<'_, GuestState<'_>>,
+    key_ptr: i32,
+    key_len: i32,
+) -> Result<(), wasmi::Error> {
+    let memory = guest_memory(&caller)?;
+    let key = read_guest(&caller, memory, key_ptr, key_len)?;
+    charge(&mut caller, key.len())?;
+
+    let key = caller.data().key(&key);
+    caller.data_mut().writes.insert(key, None);
+    Ok(())
+}
+
+/// Charge gas for a storage access of the given number of bytes.
+fn charge(caller: &mut Caller<'_, GuestState<'_>>, bytes: usize) -> Result<(), wasmi::Error> {
+    let cfg = &caller.data().cfg;
+    let cost = (bytes as u64)
+        .saturating_mul(cfg.storage_byte_cost)
+        .saturating_add(cfg.storage_access_cost);
+    let fuel = caller.get_fuel()?;
+    if fuel < cost {
+        caller.set_fuel(0)?;
+        return Err(Trap::from(TrapCode::OutOfFuel).into());
+    }
+    caller.set_fuel(fuel - cost)?;
+    Ok(())
+}
+
+fn guest_memory(caller: &Caller<'_, GuestState<'_>>) -> Result<Memory, wasmi::Error> {
+    caller
+        .get_export("memory")
+        .and_then(Extern::into_memory)
+        .ok_or_else(|| wasmi::Error::new("missing memory export"))
+}
+
+fn read_guest(
+    caller: &Caller<'_, GuestState<'_>>,
+    memory: Memory,
+    ptr: i32,
+    len: i32,
+) -> Result<Vec<u8>, wasmi::Error> {
+    let buf = guest_slice(
+        memory.data(caller),
+        ptr as u32 as usize,
+        len as u32 as usize,
+    )
+    .ok_or_else(|| Trap::from(TrapCode::MemoryOutOfBounds))?;
+    Ok(buf.to_vec())
+}
+
+/// Region of guest memory with the given location, if it is within bounds.
+///
+/// Lengths are chosen by the guest, so they must be checked against the memory before anything
+/// is allocated for them.
+fn guest_slice(data: &[u8], ptr: usize, len: usize) -> Option<&[u8]> {
+    data.get(ptr..ptr.checked_add(len)?)
+}
+
+fn write_guest(
+    caller: &mut Caller<'_, GuestState<'_>>,
+    memory: Memory,
+    ptr: i32,
+    data: &[u8],
+) -> Result<(), wasmi::Error> {
+    memory
+        .write(caller, ptr as u32 as usize, data)
+        .map_err(|_| Trap::from(TrapCode::MemoryOutOfBounds))?;
+    Ok(())
+}
+
+fn guest_len(len: usize) -> Result<i32, WasmError> {
+    i32::try_from(len).map_err(|_| WasmError::Trap("input too large".to_string()))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::storage::mkvs::{sync::NoopReadSyncer, OverlayTree, RootType, Tree};
+
+    /// Guest storing its input under key "k" and returning the previous value.
+    const GUEST: &str = r#"
+        (module
+            (import "env" "storage_get" (func $get (param i32 i32 i32 i32) (result i64)))
+            (import "env" "storage_insert" (func $insert (param i32 i32 i32 i32)))
+            (memory (export "memory") 1)
+            (data (i32.const 0) "k")
+            (func (export "alloc") (param i32) (result i32) (i32.const 1024))
+            (func (export "call") (param $ptr i32) (param $len i32) (result i64)
+                (local $prev i64)
+                (local.set $prev (call $get (i32.const 0) (i32.const 1) (i32.const 2048) (i32.const 1024)))
+                (call $insert (i32.const 0) (i32.const 1) (local.get $ptr) (local.get $len))
+                (if (result i64) (i64.lt_s (local.get $prev) (i64.const 0))
+                    (then (i64.const 0x80000000000))
+                    (else (i64.or (i64.const 0x80000000000) (local.get $prev)))))
+        )
+    "#;
+
+    /// Guest looping forever.
+    const LOOP: &str = r#"
+        (module
+            (memory (export "memory") 1)
+            (func (export "alloc") (param i32) (result i32) (i32.const 0))
+            (func (export "call") (param i32 i32) (result i64)
+                (loop $l (br $l))
+                (i64.const 0))
+        )
+    "#;
+
+    /// Guest removing a key of the given length.
+    const REMOVE: &str = r#"
+        (module
+            (import "env" "storage_remove" (func $remove (param i32 i32)))
+            (memory (export "memory") 1)
+            (func (export "alloc") (param i32) (result i32) (i32.const 0))
+            (func (export "call") (param i32 i32) (result i64)
+                (call $remove (i32.const 0) (i32.const -1))
+                (i64.const 0))
+        )
+    "#;
+
+    /// Guest returning an output of the maximum length.
+    const OUTPUT: &str = r#"
+        (module
+            (memory (export "memory") 1)
+            (func (export "alloc") (param i32) (result i32) (i32.const 0))
+            (func (export "call") (param i32 i32) (result i64) (i64.const 0xffffffff))
+        )
+    "#;
+
+    #[test]
+    fn test_wasm_execute() {
+        let tree = Tree::builder()
+            .with_root_type(RootType::State)
+            .build(Box::new(NoopReadSyncer));
+        let mut state = OverlayTree::new(tree);
+        let executor = WasmExecutor::new(WasmConfig::default());
+        let guest = wat::parse_str(GUEST).unwrap();
+        executor.validate(&guest).unwrap();
+
+        let result = executor
+            .execute(&mut state, b"guest/", &guest, b"first", 1_000_000)
+            .unwrap();
+        assert_eq!(result.output, b"".to_vec());
+        assert!(result.gas_used > 0);
+        assert_eq!(state.get(b"guest/k"), Some(b"first".to_vec()));
+
+        let result = executor
+            .execute(&mut state, b"guest/", &guest, b"second", 1_000_000)
+            .unwrap();
+        assert_eq!(result.output, b"first".to_vec());
+
+        // Running out of gas discards all writes.
+        assert!(matches!(
+            executor.execute(&mut state, b"guest/", &guest, b"third", 1_000),
+            Err(WasmError::OutOfGas)
+        ));
+        assert_eq!(state.get(b"guest/k"), Some(b"second".to_vec()));
+
+        let looping = wat::parse_str(LOOP).unwrap();
+        assert!(matches!(
+            executor.execute(&mut state, b"loop/", &looping, b"", 1_000_000),
+            Err(WasmError::OutOfGas)
+        ));
+
+        // Out of bounds accesses are rejected without allocating for them.
+        for code in [REMOVE, OUTPUT] {
+            let guest = wat::parse_str(code).unwrap();
+            assert!(matches!(
+                executor.execute(&mut state, b"oob/", &guest, b"", 1_000_000),
+                Err(WasmError::Trap(_))
+            ));
+        }
+
+        // Floating point instructions are rejected.
+        let floats =
+            wat::parse_str(r#"(module (func (result f32) (f32.add (f32.const 1) (f32.const 2))))"#)
+                .unwrap();
+        assert!(matches!(
+            executor.validate(&floats),
+            Err(WasmError::InvalidModule(_))
+        ));
+    }
+}
@@ -0,0 +1,573 @@
+//! Transports used to communicate with the runtime host.
+//!
+//! The runtime host protocol is a stream of length-prefixed messages, which can be carried over
+//! any reliable bidirectional byte stream. Messages are read by the protocol reader thread while
+//! they are concurrently written by the writer thread, so transports must allow reads and writes
+//! to be issued at the same time through shared references.
+use std::{
+    io::{self, Read, Write},
+    net::TcpStream,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use anyhow::{anyhow, Result};
+use mbedtls::{
+    pk::Pk,
+    ssl::{
+        config::{AuthMode, Endpoint, Preset, Transport as SslTransport},
+        Config as SslConfig, Context,
+    },
+    x509::certificate::Certificate,
+};
+
+use crate::{
+    config::{HostTransport, NoiseTransport, TlsTransport, VsockTransport},
+    TeeType, BUILD_INFO,
+};
+
+/// Interval after which a pending TLS read releases the connection, allowing queued writes to
+/// proceed.
+const TLS_READ_POLL_INTERVAL: Duration = Duration::from_millis(10);
+/// Noise protocol pattern of the host channel, with both static keys known in advance.
+const NOISE_PATTERN: &str = "Noise_KK_25519_ChaChaPoly_SHA256";
+/// Size of static X25519 keys.
+const NOISE_KEY_LEN: usize = 32;
+/// Maximum size of a Noise message.
+const NOISE_MAX_MESSAGE_LEN: usize = 65535;
+/// Size of the authentication tag of a Noise message.
+const NOISE_TAG_LEN: usize = 16;
+
+/// Transport used to communicate with the runtime host.
+pub trait Transport: Send + Sync {
+    /// Read data received from the host into the given buffer.
+    fn read(&self, buf: &mut [u8]) -> io::Result<usize>;
+
+    /// Write data to the host from the given buffer.
+    fn write(&self, buf: &[u8]) -> io::Result<usize>;
+
+    /// Flush any buffered data to the host.
+    fn flush(&self) -> io::Result<()>;
+
+    /// Whether the transport is not connected to any host.
+    fn is_offline(&self) -> bool {
+        false
+    }
+}
+
+/// Adapter implementing `Read` and `Write` for a shared transport.
+pub struct TransportIo<'a>(pub &'a dyn Transport);
+
+impl Read for TransportIo<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.0.read(buf)
+    }
+}
+
+impl Write for TransportIo<'_> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.0.flush()
+    }
+}
+
+macro_rules! impl_stream_transport {
+    ($($(#[$attr:meta])* $stream:ty),* $(,)?) => {
+        $(
+            $(#[$attr])*
+            impl Transport for $stream {
+                fn read(&self, buf: &mut [u8]) -> io::Result<usize> {
+                    Read::read(&mut &*self, buf)
+                }
+
+                fn write(&self, buf: &[u8]) -> io::Result<usize> {
+                    Write::write(&mut &*self, buf)
+                }
+
+                fn flush(&self) -> io::Result<()> {
+                    Write::flush(&mut &*self)
+                }
+            }
+        )*
+    };
+}
+
+impl_stream_transport!(
+    #[cfg(not(target_env = "sgx"))]
+    std::os::unix::net::UnixStream,
+    TcpStream,
+    #[cfg(feature = "tdx")]
+    vsock::VsockStream,
+);
+
+/// Transport which is not connected to any host, used in offline mode.
+pub struct Offline;
+
+impl Transport for Offline {
+    fn read(&self, _buf: &mut [u8]) -> io::Result<usize> {
+        Ok(0)
+    }
+
+    fn write(&self, _buf: &[u8]) -> io::Result<usize> {
+        Err(io::ErrorKind::NotConnected.into())
+    }
+
+    fn flush(&self) -> io::Result<()> {
+        Ok(())
+    }
+
+    fn is_offline(&self) -> bool {
+        true
+    }
+}
+
+/// Mutually-authenticated TLS connection over TCP.
+pub struct Tls {
+    context: Mutex<Context<TcpStream>>,
+}
+
+impl Tls {
+    /// Connect to the host and perform the TLS handshake, authenticating both sides.
+    pub fn connect(config: &TlsTransport) -> Result<Self> {
+        let ca_certificates = Certificate::from_pem_multiple(&with_nul(&config.ca_certificates))?;
+        let certificate_chain =
+            Certificate::from_pem_multiple(&with_nul(&config.certificate_chain))?;
+        let private_key = Pk::from_private_key(&with_nul(&config.private_key), None)?;
+
+        let mut ssl_config =
+            SslConfig::new(Endpoint::Client, SslTransport::Stream, Preset::Default);
+        ssl_config.set_rng(rng()?);
+        ssl_config.set_authmode(AuthMode::Required);
+        ssl_config.set_ca_list(Arc::new(ca_certificates), None);
+        ssl_config.push_cert(Arc::new(certificate_chain), Arc::new(private_key))?;
+
+        let stream = TcpStream::connect(&config.address)?;
+        let socket = stream.try_clone()?;
+        let mut context = Context::new(Arc::new(ssl_config));
+        context.establish(stream, Some(config.server_name.as_str()))?;
+
+        // Reads only hold the connection until the poll interval elapses, so that the writer
+        // thread is not blocked while waiting for messages from the host.
+        socket.set_read_timeout(Some(TLS_READ_POLL_INTERVAL))?;
+
+        Ok(Self {
+            context: Mutex::new(context),
+        })
+    }
+}
+
+impl Transport for Tls {
+    fn read(&self, buf: &mut [u8]) -> io::Result<usize> {
+        loop {
+            let result = self.context.lock().unwrap().read(buf);
+            match result {
+                Err(err)
+                    if matches!(
+                        err.kind(),
+                        io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut
+                    ) =>
+                {
+                    continue
+                }
+                result => return result,
+            }
+        }
+    }
+
+    fn write(&self, buf: &[u8]) -> io::Result<usize> {
+        self.context.lock().unwrap().write(buf)
+    }
+
+    fn flush(&self) -> io::Result<()> {
+        self.context.lock().unwrap().flush()
+    }
+}
+
+/// Receiving side of a Noise channel.
+struct NoiseReader<S> {
+    stream: S,
+    nonce: u64,
+    /// Decrypted data not yet read.
+    plaintext: Vec<u8>,
+    pos: usize,
+}
+
+/// Sending side of a Noise channel.
+struct NoiseWriter<S> {
+    stream: S,
+    nonce: u64,
+}
+
+/// Noise channel authenticated by pinned static keys.
+///
+/// Noise messages are sent as frames prefixed by their length. Both directions use their own
+/// nonces, so reads and writes don't block each other.
+pub struct Noise<S = TcpStream> {
+    state: snow::StatelessTransportState,
+    reader: Mutex<NoiseReader<S>>,
+    writer: Mutex<NoiseWriter<S>>,
+}
+
+impl Noise {
+    /// Connect to the host and perform the Noise handshake, authenticating both sides by their
+    /// pinned static keys.
+    pub fn connect(config: &NoiseTransport) -> Result<Self> {
+        let stream = TcpStream::connect(&config.address)?;
+        let writer = stream.try_clone()?;
+        Self::establish(
+            stream,
+            writer,
+            &config.private_key,
+            &config.host_public_key,
+            true,
+        )
+    }
+}
+
+impl<S: Read + Write> Noise<S> {
+    /// Perform the Noise handshake over the given halves of a stream.
+    fn establish(
+        mut reader: S,
+        mut writer: S,
+        private_key: &[u8],
+        remote_public_key: &[u8],
+        initiator: bool,
+    ) -> Result<Self> {
+        if private_key.len() != NOISE_KEY_LEN || remote_public_key.len() != NOISE_KEY_LEN {
+            return Err(anyhow!("malformed noise static key"));
+        }
+        let builder = snow::Builder::new(NOISE_PATTERN.parse()?)
+            .local_private_key(private_key)
+            .remote_public_key(remote_public_key);
+        let mut state = if initiator {
+            builder.build_initiator()?
+        } else {
+            builder.build_responder()?
+        };
+
+        // -> e, es, ss
+        // <- e, ee, se
+        let mut buf = vec![0u8; NOISE_MAX_MESSAGE_LEN];
+        for sending in [initiator, !initiator] {
+            if sending {
+                let len = state.write_message(&[], &mut buf)?;
+                write_frame(&mut writer, &buf[..len])?;
+            } else {
+                let frame = read_frame(&mut reader)?
+                    .ok_or_else(|| anyhow!("connection closed during handshake"))?;
+                state.read_message(&frame, &mut buf)?;
+            }
+        }
+
+        Ok(Self {
+            state: state.into_stateless_transport_mode()?,
+            reader: Mutex::new(NoiseReader {
+                stream: reader,
+                nonce: 0,
+                plaintext: vec![],
+                pos: 0,
+            }),
+            writer: Mutex::new(NoiseWriter {
+                stream: writer,
+                nonce: 0,
+            }),
+        })
+    }
+}
+
+impl<S: Read + Write + Send + Sync> Transport for Noise<S> {
+    fn read(&self, buf: &mut [u8]) -> io::Result<usize> {
+        let mut reader = self.reader.lock().unwrap();
+        while reader.pos == reader.plaintext.len() {
+            let frame = match read_frame(&mut reader.stream)? {
+                Some(frame) => frame,
+                None => return Ok(0),
+            };
+            let mut plaintext = vec![0u8; frame.len()];
+            let len = self
+                .state
+                .read_message(reader.nonce, &frame, &mut plaintext)
+                .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?;
+            plaintext.truncate(len);
+            reader.nonce += 1;
+            reader.plaintext = plaintext;
+            reader.pos = 0;
+        }
+
+        let len = buf.len().min(reader.plaintext.len() - reader.pos);
+        buf[..len].copy_from_slice(&reader.plaintext[reader.pos..reader.pos + len]);
+        reader.pos += len;
+        Ok(len)
+    }
+
+    fn write(&self, buf: &[u8]) -> io::Result<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        let len = buf.len().min(NOISE_MAX_MESSAGE_LEN - NOISE_TAG_LEN);
+        let mut message = vec![0u8; len + NOISE_TAG_LEN];
+
+        let mut writer = self.writer.lock().unwrap();
+        let message_len = self
+            .state
+            .write_message(writer.nonce, &buf[..len], &mut message)
+            .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?;
+        writer.nonce += 1;
+        write_frame(&mut writer.stream, &message[..message_len])?;
+        Ok(len)
+    }
+
+    fn flush(&self) -> io::Result<()> {
+        self.writer.lock().unwrap().stream.flush()
+    }
+}
+
+/// Write a length-prefixed Noise message.
+fn write_frame<W: Write>(writer: &mut W, message: &[u8]) -> io::Result<()> {
+    let len = u16::try_from(message.len())
+        .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "noise message too large"))?;
+    writer.write_all(&len.to_be_bytes())?;
+    writer.write_all(message)
+}
+
+/// Read a length-prefixed Noise message, returning `None` once the connection is closed.
+fn read_frame<R: Read>(reader: &mut R) -> io::Result<Option<Vec<u8>>> {
+    let mut len = [0u8; 2];
+    match reader.read_exact(&mut len) {
+        Ok(()) => {}
+        Err(err) if err.kind() == io::ErrorKind::UnexpectedEof => return Ok(None),
+        Err(err) => return Err(err),
+    }
+    let mut message = vec![0u8; u16::from_be_bytes(len) as usize];
+    reader.read_exact(&mut message)?;
+    Ok(Some(message))
+}
+
+/// Random number generator used for TLS connections.
+#[cfg(target_env = "sgx")]
+pub(crate) fn rng() -> Result<Arc<mbedtls::rng::Rdrand>> {
+    Ok(Arc::new(mbedtls::rng::Rdrand))
+}
+
+/// Random number generator used for TLS connections.
+#[cfg(not(target_env = "sgx"))]
+pub(crate) fn rng() -> Result<Arc<mbedtls::rng::CtrDrbg>> {
+    let entropy = Arc::new(mbedtls::rng::OsEntropy::new());
+    Ok(Arc::new(mbedtls::rng::CtrDrbg::new(entropy, None)?))
+}
+
+/// PEM-encoded data terminated by a NUL byte, as required by mbedtls.
+pub(crate) fn with_nul(pem: &[u8]) -> Vec<u8> {
+    let mut data = pem.to_vec();
+    if data.last() != Some(&0) {
+        data.push(0);
+    }
+    data
+}
+
+/// Establish a connection with the host using the given transport.
+pub fn connect(config: &HostTransport) -> Result<Box<dyn Transport>> {
+    match config {
+        HostTransport::Default => connect_default(),
+        #[cfg(not(target_env = "sgx"))]
+        HostTransport::Unix(path) => Ok(Box::new(std::os::unix::net::UnixStream::connect(path)?)),
+        HostTransport::Tcp(address) => Ok(Box::new(TcpStream::connect(address)?)),
+        HostTransport::Tls(config) => Ok(Box::new(Tls::connect(config)?)),
+        HostTransport::Noise(config) => Ok(Box::new(Noise::connect(config)?)),
+        #[cfg(feature = "tdx")]
+        HostTransport::Vsock(port) => Ok(Box::new(accept_vsock(*port)?)),
+        #[cfg(feature = "tdx")]
+        HostTransport::VsockConnect(config) => Ok(Box::new(connect_vsock(config)?)),
+        #[allow(unreachable_patterns)]
+        _ => Err(anyhow!("unsupported host transport")),
+    }
+}
+
+/// Establish a connection with the host using the default transport for the TEE type.
+fn connect_default() -> Result<Box<dyn Transport>> {
+    match BUILD_INFO.tee_type {
+        #[cfg(not(target_env = "sgx"))]
+        TeeType::Sgx | TeeType::None => connect(&HostTransport::Unix(
+            std::env::var("OASIS_WORKER_HOST").unwrap_or_default(),
+        )),
+
+        #[cfg(target_env = "sgx")]
+        TeeType::Sgx => connect(&HostTransport::Tcp("worker-host".to_string())),
+
+        #[cfg(feature = "tdx")]
+        TeeType::Tdx => {
+            /// VSOCK port used for the Runtime Host Protocol.
+            const VSOCK_PORT_RHP: u32 = 1;
+
+            connect(&HostTransport::Vsock(VSOCK_PORT_RHP))
+        }
+
+        #[allow(unreachable_patterns)]
+        _ => Err(anyhow!("unsupported TEE type")),
+    }
+}
+
+/// Accept the first VSOCK connection on the given port.
+#[cfg(feature = "tdx")]
+fn accept_vsock(port: u32) -> Result<vsock::VsockStream> {
+    let listener = vsock::VsockListener::bind(&vsock::VsockAddr::new(libc::VMADDR_CID_ANY, port))?;
+    let stream = listener
+        .incoming()
+        .next()
+        .ok_or(anyhow!("failed to accept connection"))??;
+    Ok(stream)
+}
+
+/// Connect to the host over VSOCK, retrying failed attempts.
+#[cfg(feature = "tdx")]
+fn connect_vsock(config: &VsockTransport) -> Result<vsock::VsockStream> {
+    let addr = vsock::VsockAddr::new(config.cid, config.port);
+    with_backoff(config, || vsock::VsockStream::connect(&addr))
+}
+
+/// Retry the given connection attempt with exponential backoff, until it succeeds or the
+/// configured number of attempts is exhausted.
+#[cfg_attr(not(feature = "tdx"), allow(dead_code))]
+fn with_backoff<T>(
+    config: &VsockTransport,
+    mut attempt: impl FnMut() -> io::Result<T>,
+) -> Result<T> {
+    let mut delay = config.backoff;
+    let mut attempts = 0;
+    loop {
+        attempts += 1;
+        match attempt() {
+            Ok(stream) => return Ok(stream),
+            Err(err) if config.max_attempts != 0 && attempts >= config.max_attempts => {
+                return Err(anyhow!(
+                    "failed to connect after {attempts} attempts: {err}"
+                ));
+            }
+            Err(_) => {}
+        }
+
+        std::thread::sleep(delay);
+        delay = (delay * 2).min(config.max_backoff);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::os::unix::net::UnixStream;
+
+    use super::*;
+
+    #[test]
+    fn test_stream_transport() {
+        let (runtime, host) = UnixStream::pair().unwrap();
+        let transport: Box<dyn Transport> = Box::new(runtime);
+        assert!(!transport.is_offline());
+
+        // Reads and writes go through shared references.
+        TransportIo(&*transport).write_all(b"ping").unwrap();
+        let mut buf = [0u8; 4];
+        (&host).read_exact(&mut buf).unwrap();
+        assert_eq!(&buf, b"ping");
+
+        (&host).write_all(b"pong").unwrap();
+        TransportIo(&*transport).read_exact(&mut buf).unwrap();
+        assert_eq!(&buf, b"pong");
+
+        // The offline transport never connects to a host.
+        let offline: Box<dyn Transport> = Box::new(Offline);
+        assert!(offline.is_offline());
+        assert_eq!(offline.read(&mut buf).unwrap(), 0);
+        assert!(offline.write(b"ping").is_err());
+    }
+
+    #[test]
+    fn test_noise_transport() {
+        let builder = || snow::Builder::new(NOISE_PATTERN.parse().unwrap());
+        let runtime_keys = builder().generate_keypair().unwrap();
+        let host_keys = builder().generate_keypair().unwrap();
+        let other_keys = builder().generate_keypair().unwrap();
+
+        let connect = |host_pinned: &[u8]| {
+            let (runtime, host) = UnixStream::pair().unwrap();
+            std::thread::scope(|s| {
+                let responder = s.spawn(|| {
+                    Noise::establish(
+                        host.try_clone().unwrap(),
+                        host,
+                        &host_keys.private,
+                        &runtime_keys.public,
+                        false,
+                    )
+                });
+                let initiator = Noise::establish(
+                    runtime.try_clone().unwrap(),
+                    runtime,
+                    &runtime_keys.private,
+                    host_pinned,
+                    true,
+                );
+                (initiator, responder.join().unwrap())
+            })
+        };
+
+        let (runtime, host) = connect(&host_keys.public);
+        let (runtime, host): (Box<dyn Transport>, Box<dyn Transport>) =
+            (Box::new(runtime.unwrap()), Box::new(host.unwrap()));
+
+        // Data larger than a single Noise message is split across messages.
+        let data: Vec<u8> = (0..200_000u32).map(|i| i as u8).collect();
+        std::thread::scope(|s| {
+            s.spawn(|| TransportIo(&*runtime).write_all(&data).unwrap());
+            let mut buf = vec![0u8; data.len()];
+            TransportIo(&*host).read_exact(&mut buf).unwrap();
+            assert_eq!(buf, data);
+        });
+        TransportIo(&*host).write_all(b"pong").unwrap();
+        let mut buf = [0u8; 4];
+        TransportIo(&*runtime).read_exact(&mut buf).unwrap();
+        assert_eq!(&buf, b"pong");
+
+        // Hosts with keys other than the pinned one are rejected.
+        let (runtime, host) = connect(&other_keys.public);
+        assert!(runtime.is_err() && host.is_err());
+    }
+
+    #[test]
+    fn test_with_backoff() {
+        let config = VsockTransport {
+            max_attempts: 3,
+            backoff: Duration::from_millis(1),
+            max_backoff: Duration::from_millis(2),
+            ..Default::default()
+        };
+
+        // Failed attempts are retried.
+        let mut attempts = 0;
+        let result = with_backoff(&config, || {
+            attempts += 1;
+            match attempts {
+                3 => Ok(attempts),
+                _ => Err(io::Error::from(io::ErrorKind::ConnectionRefused)),
+            }
+        });
+        assert_eq!(result.unwrap(), 3);
+
+        // Attempts are bounded.
+        let mut attempts = 0;
+        let result: Result<()> = with_backoff(&config, || {
+            attempts += 1;
+            Err(io::ErrorKind::ConnectionRefused.into())
+        });
+        assert!(result.is_err());
+        assert_eq!(attempts, 3);
+    }
+
+    #[test]
+    fn test_with_nul() {
+        assert_eq!(with_nul(b"pem"), b"pem\0".to_vec());
+        assert_eq!(with_nul(b"pem\0"), b"pem\0".to_vec());
+    }
+}
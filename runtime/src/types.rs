@@ -1,33 +1,52 @@
 //! Types used by the worker-host protocol.
-use std::collections::BTreeMap;
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fmt,
+};
 
 use thiserror::Error;
 
 use crate::{
+    attestation::SignedAuditReport,
+    cache::StorageResyncReport,
     common::{
         crypto::{
             hash::Hash,
             signature::{self, Signature},
             x25519,
         },
+        logger::LogRecord,
         namespace::Namespace,
-        sgx::{ias::AVR, Quote, QuotePolicy},
+        pagination::Pagination,
+        quantity::Quantity,
+        sgx::{ias::AVR, pcs::CollateralBundle, Quote, QuotePolicy},
         version::Version,
     },
     consensus::{
         self,
         beacon::EpochTime,
+        checkpoint::SignedCheckpoint,
         registry::EndorsedCapabilityTEE,
         roothash::{self, Block, ComputeResultsHeader, Header},
-        state::keymanager::Status as KeyManagerStatus,
+        state::{keymanager::Status as KeyManagerStatus, ReadReport},
         transaction::{Proof, SignedTransaction},
         LightBlock,
     },
     enclave_rpc,
-    storage::mkvs::{sync, WriteLog},
-    transaction::types::TxnBatch,
+    handshake::SignedHandshakeTranscript,
+    health::HealthReport,
+    host::{feed::SignedFeedData, queues::MessageClass, secrets::EncryptedSecret},
+    metrics::MetricsSnapshot,
+    shutdown::StageReport,
+    storage::mkvs::{
+        self, checkpoint, commit::CommitAck, compression::CompressedWriteLog,
+        integrity::IntegrityReport, sync, WriteLog,
+    },
+    transaction::{shadow::Divergence, types::TxnBatch},
 };
 
+pub mod messages;
+
 /// Computed batch.
 #[derive(Clone, Debug, Default, cbor::Encode, cbor::Decode)]
 pub struct ComputedBatch {
@@ -42,6 +61,66 @@ pub struct ComputedBatch {
     pub rak_sig: Signature,
     /// Messages emitted by the runtime.
     pub messages: Vec<roothash::Message>,
+    /// Compressed log that generates the I/O tree. If set, `io_write_log` is empty.
+    #[cbor(optional)]
+    pub compressed_io_write_log: Option<CompressedWriteLog>,
+    /// Compressed log of changes to the state tree. If set, `state_write_log` is empty.
+    #[cbor(optional)]
+    pub compressed_state_write_log: Option<CompressedWriteLog>,
+}
+
+impl ComputedBatch {
+    /// Replace the write logs with their compressed encoding.
+    pub fn compress_write_logs(&mut self) {
+        self.compressed_io_write_log = Some(CompressedWriteLog::compress(&self.io_write_log));
+        self.compressed_state_write_log = Some(CompressedWriteLog::compress(&self.state_write_log));
+        self.io_write_log.clear();
+        self.state_write_log.clear();
+    }
+
+    /// Replace any compressed write logs with the decompressed ones.
+    pub fn decompress_write_logs(&mut self) -> anyhow::Result<()> {
+        if let Some(write_log) = self.compressed_io_write_log.take() {
+            self.io_write_log = write_log.decompress()?;
+        }
+        if let Some(write_log) = self.compressed_state_write_log.take() {
+            self.state_write_log = write_log.decompress()?;
+        }
+        Ok(())
+    }
+}
+
+/// Proof of the state read by a query.
+#[derive(Clone, Debug, Default, PartialEq, Eq, cbor::Encode, cbor::Decode)]
+pub struct QueryProof {
+    /// Round of the state the query was executed against.
+    pub round: u64,
+    /// State root the query was executed against.
+    pub state_root: Hash,
+    /// Proof of all keys read by the query, including keys which were not found.
+    pub proof: sync::Proof,
+    /// Whether the query iterated over state. The proof can't attest that iteration didn't skip
+    /// any keys, so such results can't be fully verified.
+    #[cbor(optional)]
+    pub partial: bool,
+}
+
+impl QueryProof {
+    /// Tree serving the state read by the query from the proof.
+    ///
+    /// Lookups of keys read by the query are verified against the state root, while lookups of
+    /// any other keys fail. Callers must independently verify that the state root is the one
+    /// of the given round, e.g. using a block header from the consensus layer.
+    pub fn tree(&self, namespace: Namespace) -> mkvs::Tree {
+        mkvs::Tree::builder()
+            .with_root(mkvs::Root {
+                namespace,
+                version: self.round,
+                root_type: mkvs::RootType::State,
+                hash: self.state_root,
+            })
+            .build(Box::new(sync::ProofReadSyncer::new(self.proof.clone())))
+    }
 }
 
 /// Storage sync request.
@@ -50,12 +129,16 @@ pub enum StorageSyncRequest {
     SyncGet(sync::GetRequest),
     SyncGetPrefixes(sync::GetPrefixesRequest),
     SyncIterate(sync::IterateRequest),
+    SyncGetPrefix(sync::GetPrefixRequest),
 }
 
 #[derive(Debug)]
 pub struct StorageSyncRequestWithEndpoint {
     pub endpoint: HostStorageEndpoint,
     pub request: StorageSyncRequest,
+    /// Sources which must not be used to serve the request, as they recently served responses
+    /// which failed verification.
+    pub excluded_sources: Vec<signature::PublicKey>,
 }
 
 impl cbor::Encode for StorageSyncRequestWithEndpoint {
@@ -64,6 +147,10 @@ impl cbor::Encode for StorageSyncRequestWithEndpoint {
         // Add endpoint to the given map.
         let key = cbor::values::IntoCborValue::into_cbor_value("endpoint");
         request.push((key, self.endpoint.into_cbor_value()));
+        if !self.excluded_sources.is_empty() {
+            let key = cbor::values::IntoCborValue::into_cbor_value("excluded_sources");
+            request.push((key, self.excluded_sources.into_cbor_value()));
+        }
         cbor::Value::Map(request)
     }
 }
@@ -80,10 +167,17 @@ impl cbor::Decode for StorageSyncRequestWithEndpoint {
                     .find(|(_, v)| v.0 == key)
                     .ok_or(cbor::DecodeError::MissingField)?;
                 let endpoint = items.remove(index).1;
+                // The excluded sources are optional.
+                let key = cbor::values::IntoCborValue::into_cbor_value("excluded_sources");
+                let excluded_sources = match items.iter().position(|v| v.0 == key) {
+                    Some(index) => cbor::Decode::try_from_cbor_value(items.remove(index).1)?,
+                    None => Vec::new(),
+                };
 
                 Ok(Self {
                     endpoint: cbor::Decode::try_from_cbor_value(endpoint)?,
                     request: cbor::Decode::try_from_cbor_value(cbor::Value::Map(items))?,
+                    excluded_sources,
                 })
             }
             _ => Err(cbor::DecodeError::UnexpectedType),
@@ -105,6 +199,20 @@ pub enum HostStorageEndpoint {
     Consensus = 1,
 }
 
+/// Operation on a connection tunnelled through the host for outbound HTTPS requests.
+#[derive(Clone, Debug, PartialEq, Eq, cbor::Encode, cbor::Decode)]
+pub enum HttpTunnelOp {
+    /// Open a new TCP connection to the given host and port.
+    Open { host: String, port: u16 },
+    /// Write data to the connection.
+    Write { data: Vec<u8> },
+    /// Read at most the given number of bytes from the connection. No data is returned once the
+    /// remote end closed the connection.
+    Read { max: u32 },
+    /// Close the connection.
+    Close {},
+}
+
 /// Runtime host protocol message body.
 #[derive(Debug, cbor::Encode, cbor::Decode)]
 pub enum Body {
@@ -142,11 +250,17 @@ pub enum Body {
     RuntimeCapabilityTEERakQuoteResponse {
         height: u64,
         signature: Signature,
+        #[cbor(optional)]
+        audit_report: Option<SignedAuditReport>,
     },
     RuntimeCapabilityTEEUpdateEndorsementRequest {
         ect: EndorsedCapabilityTEE,
     },
     RuntimeCapabilityTEEUpdateEndorsementResponse {},
+    RuntimeCapabilityTEECollateralLoadRequest {
+        bundles: Vec<CollateralBundle>,
+    },
+    RuntimeCapabilityTEECollateralLoadResponse {},
     RuntimeRPCCallRequest {
         request: Vec<u8>,
         kind: enclave_rpc::types::Kind,
@@ -155,6 +269,13 @@ pub enum Body {
     RuntimeRPCCallResponse {
         response: Vec<u8>,
     },
+    RuntimeRPCStreamRequest {
+        request: Vec<u8>,
+        peer_id: Vec<u8>,
+    },
+    RuntimeRPCStreamResponse {
+        response: Vec<u8>,
+    },
     RuntimeLocalRPCCallRequest {
         request: Vec<u8>,
     },
@@ -192,6 +313,10 @@ pub enum Body {
         tx_reject_hashes: Vec<Hash>,
         tx_input_root: Hash,
         tx_input_write_log: WriteLog,
+        #[cbor(optional)]
+        consensus_state_reads: Option<ReadReport>,
+        #[cbor(optional)]
+        utilization: Option<ExecutionUtilization>,
     },
     RuntimeKeyManagerStatusUpdateRequest {
         status: KeyManagerStatus,
@@ -209,10 +334,16 @@ pub enum Body {
         method: String,
         #[cbor(optional)]
         args: Vec<u8>,
+        #[cbor(optional)]
+        with_proof: bool,
+        #[cbor(optional)]
+        source: String,
     },
     RuntimeQueryResponse {
         #[cbor(optional)]
         data: Vec<u8>,
+        #[cbor(optional)]
+        proof: Option<QueryProof>,
     },
     RuntimeConsensusSyncRequest {
         height: u64,
@@ -223,8 +354,68 @@ pub enum Body {
         runtime_block: Option<roothash::AnnotatedBlock>,
         #[cbor(optional)]
         runtime_event: Option<RuntimeNotifyEvent>,
+        #[cbor(optional)]
+        consensus_block: Option<LightBlock>,
+        #[cbor(optional)]
+        consensus_event: Option<RuntimeNotifyConsensusEvent>,
+        #[cbor(optional)]
+        external_data: Option<SignedFeedData>,
     },
     RuntimeNotifyResponse {},
+    RuntimeLogConfigRequest {
+        level: String,
+        #[cbor(optional)]
+        modules: BTreeMap<String, String>,
+    },
+    RuntimeLogConfigResponse {},
+    RuntimeStorageResyncRequest {
+        root: mkvs::Root,
+    },
+    RuntimeStorageResyncResponse {
+        report: StorageResyncReport,
+    },
+    RuntimeShutdownNoticeRequest {
+        notice: HostShutdownNotice,
+    },
+    RuntimeShutdownNoticeResponse {
+        ack: ShutdownAck,
+    },
+    RuntimeCheckpointCreateRequest {
+        root: mkvs::Root,
+        chunk_size: u64,
+        /// Index of the first chunk to return.
+        #[cbor(optional)]
+        start: u64,
+        /// Maximum number of chunks to return, capped by the runtime.
+        #[cbor(optional)]
+        limit: u64,
+    },
+    RuntimeCheckpointCreateResponse {
+        metadata: checkpoint::Metadata,
+        chunks: Vec<Vec<u8>>,
+    },
+    RuntimeCheckpointRestoreRequest {
+        metadata: checkpoint::Metadata,
+    },
+    RuntimeCheckpointRestoreResponse {},
+    RuntimeCheckpointRestoreChunkRequest {
+        index: u64,
+        chunk: Vec<u8>,
+    },
+    RuntimeCheckpointRestoreChunkResponse {
+        done: bool,
+        /// Verified root of the restored tree, once all chunks have been restored.
+        #[cbor(optional)]
+        root: Option<mkvs::Root>,
+    },
+    RuntimeBackpressureResponse {
+        class: MessageClass,
+        depth: u64,
+    },
+    RuntimeQueryThrottledResponse {
+        source: String,
+        retry_after_ms: u64,
+    },
 
     // Host interface.
     HostRPCCallRequest {
@@ -238,6 +429,16 @@ pub enum Body {
         response: Vec<u8>,
         node: signature::PublicKey,
     },
+    HostRPCStreamRequest {
+        endpoint: String,
+        request_id: u64,
+        request: Vec<u8>,
+        nodes: Vec<signature::PublicKey>,
+    },
+    HostRPCStreamResponse {
+        response: Vec<u8>,
+        node: signature::PublicKey,
+    },
     HostSubmitPeerFeedbackRequest {
         endpoint: String,
         request_id: u64,
@@ -257,6 +458,25 @@ pub enum Body {
         value: Vec<u8>,
     },
     HostLocalStorageSetResponse {},
+    HostSecretRequest {
+        name: String,
+        public_key: x25519::PublicKey,
+    },
+    HostSecretResponse {
+        #[cbor(optional)]
+        secret: Option<EncryptedSecret>,
+    },
+    HostHttpTunnelRequest {
+        #[cbor(optional)]
+        tunnel: u64,
+        op: HttpTunnelOp,
+    },
+    HostHttpTunnelResponse {
+        #[cbor(optional)]
+        tunnel: u64,
+        #[cbor(optional)]
+        data: Vec<u8>,
+    },
     HostFetchConsensusBlockRequest {
         height: u64,
     },
@@ -285,6 +505,11 @@ pub enum Body {
     HostFetchGenesisHeightResponse {
         height: u64,
     },
+    HostFetchConsensusCheckpointRequest {},
+    HostFetchConsensusCheckpointResponse {
+        #[cbor(optional)]
+        checkpoint: Option<SignedCheckpoint>,
+    },
     HostProveFreshnessRequest {
         blob: Vec<u8>,
     },
@@ -296,6 +521,21 @@ pub enum Body {
     HostIdentityResponse {
         node_id: signature::PublicKey,
     },
+    HostNegotiateFeaturesRequest {
+        version: u16,
+        features: Vec<String>,
+    },
+    HostNegotiateFeaturesResponse {
+        version: u16,
+        features: Vec<String>,
+    },
+    HostRuntimeBlockRequest {
+        #[cbor(optional)]
+        round: Option<u64>,
+    },
+    HostRuntimeBlockResponse {
+        block: roothash::AnnotatedBlock,
+    },
     HostSubmitTxRequest {
         runtime_id: Namespace,
         data: Vec<u8>,
@@ -308,13 +548,77 @@ pub enum Body {
         batch_order: u32,
         proof: Option<sync::Proof>,
     },
+    HostSubmitTxBatchRequest {
+        runtime_id: Namespace,
+        txs: Vec<Vec<u8>>,
+        wait: bool,
+        prove: bool,
+    },
+    HostSubmitTxBatchResponse {
+        results: Vec<HostSubmitTxResult>,
+    },
     HostRegisterNotifyRequest {
         #[cbor(optional)]
         runtime_block: bool,
         #[cbor(optional)]
         runtime_event: Option<RegisterNotifyRuntimeEvent>,
+        #[cbor(optional)]
+        consensus_block: bool,
+        #[cbor(optional)]
+        consensus_event: Option<RegisterNotifyConsensusEvent>,
+        #[cbor(optional)]
+        external_data: Option<RegisterNotifyExternalData>,
     },
     HostRegisterNotifyResponse {},
+    HostHealthReportRequest {
+        report: HealthReport,
+    },
+    HostHealthReportResponse {},
+    HostLogRecordsRequest {
+        records: Vec<LogRecord>,
+        dropped: u64,
+    },
+    HostLogRecordsResponse {},
+    HostMetricsPushRequest {
+        snapshot: MetricsSnapshot,
+    },
+    HostMetricsPushResponse {},
+    HostShadowDivergenceRequest {
+        divergence: Divergence,
+    },
+    HostShadowDivergenceResponse {},
+    HostAttestationRelayRequest {
+        verifier: String,
+        evidence: AttestationEvidence,
+    },
+    HostAttestationRelayResponse {
+        token: Vec<u8>,
+    },
+    HostConsensusEvidenceRequest {
+        evidence: ConsensusEquivocationEvidence,
+    },
+    HostConsensusEvidenceResponse {},
+    HostConsensusGasPriceRequest {},
+    HostConsensusGasPriceResponse {
+        height: u64,
+        gas_price: Quantity,
+        min_gas_price: Quantity,
+    },
+    HostSubmitEvidenceRequest {
+        evidence: roothash::Evidence,
+    },
+    HostSubmitEvidenceResponse {},
+    RuntimeShutdownResponse {
+        ack: ShutdownAck,
+    },
+    RuntimeStorageCommitAckRequest {
+        ack: CommitAck,
+    },
+    RuntimeStorageCommitAckResponse {},
+    HostStorageCorruptionRequest {
+        report: IntegrityReport,
+    },
+    HostStorageCorruptionResponse {},
 }
 
 impl Default for Body {
@@ -323,6 +627,26 @@ impl Default for Body {
     }
 }
 
+impl Body {
+    /// Name of the message type, as used on the wire.
+    pub fn name(&self) -> String {
+        /// Writer capturing the variant name, which the derived `Debug` implementation writes
+        /// first, and aborting formatting afterwards.
+        struct NameWriter(String);
+
+        impl fmt::Write for NameWriter {
+            fn write_str(&mut self, s: &str) -> fmt::Result {
+                self.0.push_str(s);
+                Err(fmt::Error)
+            }
+        }
+
+        let mut writer = NameWriter(String::new());
+        let _ = fmt::write(&mut writer, format_args!("{self:?}"));
+        writer.0
+    }
+}
+
 /// A serializable error.
 #[derive(Clone, Debug, Default, Error, cbor::Encode, cbor::Decode)]
 #[error("module: {module} code: {code} message: {message}")]
@@ -368,10 +692,149 @@ pub struct RuntimeInfoRequest {
 
     #[cbor(optional)]
     pub local_config: BTreeMap<String, cbor::Value>,
+
+    #[cbor(optional)]
+    pub features: HostFeatures,
+
+    #[cbor(optional)]
+    pub deprecations: Vec<MessageDeprecation>,
+}
+
+/// Deprecation of a message type announced by the host.
+#[derive(Clone, Debug, Default, PartialEq, Eq, cbor::Encode, cbor::Decode)]
+pub struct MessageDeprecation {
+    /// Name of the deprecated message type.
+    pub message: String,
+    /// Runtime host protocol version in which the message type will be removed.
+    pub removal_version: Version,
+}
+
+/// Set of features supported by the host.
+#[derive(Clone, Debug, Default, PartialEq, Eq, cbor::Encode, cbor::Decode)]
+pub struct HostFeatures {
+    /// A feature specifying that the host accepts compressed write logs in computed batches.
+    #[cbor(optional)]
+    pub compressed_write_logs: bool,
+    /// A feature specifying that the host retries requests rejected with backpressure responses.
+    #[cbor(optional)]
+    pub backpressure: bool,
+}
+
+/// Version of the protocol feature negotiation.
+pub const FEATURE_NEGOTIATION_VERSION: u16 = 1;
+
+/// Optional runtime host protocol feature, negotiated between the runtime and the host.
+///
+/// Features are exchanged by name, so that either side can ignore features it doesn't know.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum ProtocolFeature {
+    /// Write logs in computed batches may be compressed.
+    CompressedWriteLogs,
+    /// Requests may be rejected with backpressure responses, which the host retries.
+    Backpressure,
+    /// EnclaveRPC responses may be streamed in multiple frames.
+    StreamingRpc,
+    /// Bundles may be transferred in chunks.
+    ChunkedBundles,
+    /// Query responses may include proofs of the state read by the query.
+    QueryProofs,
+    /// Log records may be forwarded to the host.
+    LogForwarding,
+    /// Queries may be rejected with throttling responses, which the host relays to the source.
+    QueryThrottling,
+    /// Outbound TCP connections may be tunnelled through the host.
+    HttpTunnels,
+}
+
+impl ProtocolFeature {
+    /// All features supported by the runtime.
+    pub const ALL: &'static [ProtocolFeature] = &[
+        Self::CompressedWriteLogs,
+        Self::Backpressure,
+        Self::StreamingRpc,
+        Self::ChunkedBundles,
+        Self::QueryProofs,
+        Self::LogForwarding,
+        Self::QueryThrottling,
+        Self::HttpTunnels,
+    ];
+
+    /// Name of the feature, as used during negotiation.
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::CompressedWriteLogs => "compressed_write_logs",
+            Self::Backpressure => "backpressure",
+            Self::StreamingRpc => "streaming_rpc",
+            Self::ChunkedBundles => "chunked_bundles",
+            Self::QueryProofs => "query_proofs",
+            Self::LogForwarding => "log_forwarding",
+            Self::QueryThrottling => "query_throttling",
+            Self::HttpTunnels => "http_tunnels",
+        }
+    }
+
+    /// Feature with the given name, if it is known.
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL
+            .iter()
+            .copied()
+            .find(|feature| feature.name() == name)
+    }
+}
+
+/// Set of negotiated protocol features.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct ProtocolFeatures(BTreeSet<ProtocolFeature>);
+
+impl ProtocolFeatures {
+    /// All features supported by the runtime.
+    pub fn supported() -> Self {
+        Self(ProtocolFeature::ALL.iter().copied().collect())
+    }
+
+    /// Features implied by the host features of hosts which don't support feature negotiation.
+    pub fn legacy(features: &HostFeatures) -> Self {
+        let mut set = BTreeSet::new();
+        if features.compressed_write_logs {
+            set.insert(ProtocolFeature::CompressedWriteLogs);
+        }
+        if features.backpressure {
+            set.insert(ProtocolFeature::Backpressure);
+        }
+        Self(set)
+    }
+
+    /// Known features with the given names, ignoring unknown ones.
+    pub fn from_names(names: &[String]) -> Self {
+        Self(
+            names
+                .iter()
+                .filter_map(|name| ProtocolFeature::from_name(name))
+                .collect(),
+        )
+    }
+
+    /// Names of all features in the set.
+    pub fn names(&self) -> Vec<String> {
+        self.0
+            .iter()
+            .map(|feature| feature.name().to_string())
+            .collect()
+    }
+
+    /// Features contained in both sets.
+    pub fn intersection(&self, other: &Self) -> Self {
+        Self(self.0.intersection(&other.0).copied().collect())
+    }
+
+    /// Whether the set contains the given feature.
+    pub fn contains(&self, feature: ProtocolFeature) -> bool {
+        self.0.contains(&feature)
+    }
 }
 
 /// Set of supported runtime features.
-#[derive(Clone, Debug, cbor::Encode, cbor::Decode)]
+#[derive(Clone, Debug, PartialEq, Eq, cbor::Encode, cbor::Decode)]
 pub struct Features {
     /// Schedule control feature.
     #[cbor(optional)]
@@ -385,6 +848,15 @@ pub struct Features {
     /// A feature specifying that the runtime supports endorsed TEE capabilities.
     #[cbor(optional)]
     pub endorsed_capability_tee: bool,
+    /// A feature specifying that the runtime supports host-triggered storage resyncs.
+    #[cbor(optional)]
+    pub storage_resync: bool,
+    /// A feature specifying that the runtime supports emitting compressed write logs.
+    #[cbor(optional)]
+    pub compressed_write_logs: bool,
+    /// A feature specifying that the runtime supports creating and restoring state checkpoints.
+    #[cbor(optional)]
+    pub state_checkpoints: bool,
 }
 
 impl Default for Features {
@@ -394,6 +866,9 @@ impl Default for Features {
             key_manager_quote_policy_updates: true,
             key_manager_status_updates: true,
             endorsed_capability_tee: true,
+            storage_resync: true,
+            compressed_write_logs: true,
+            state_checkpoints: true,
         }
     }
 }
@@ -401,7 +876,7 @@ impl Default for Features {
 /// A feature specifying that the runtime supports controlling the scheduling of batches. This means
 /// that the scheduler should only take priority into account and ignore weights, leaving it up to
 /// the runtime to decide which transactions to include.
-#[derive(Clone, Debug, Default, cbor::Encode, cbor::Decode)]
+#[derive(Clone, Debug, Default, PartialEq, Eq, cbor::Encode, cbor::Decode)]
 pub struct FeatureScheduleControl {
     /// Size of the initial batch of transactions.
     pub initial_batch_size: u32,
@@ -418,6 +893,11 @@ pub struct RuntimeInfoResponse {
 
     /// Describes the features supported by the runtime.
     pub features: Features,
+
+    /// Transcript of the handshake signed by the runtime's RAK, allowing external verifiers to
+    /// detect downgrades performed by the host.
+    #[cbor(optional)]
+    pub transcript: Option<SignedHandshakeTranscript>,
 }
 
 /// Batch execution mode.
@@ -442,6 +922,32 @@ impl Default for ExecutionMode {
     }
 }
 
+/// Resources used while executing a batch, allowing the host to adapt the size of subsequent
+/// batches to the actual execution cost.
+#[derive(Clone, Debug, Default, PartialEq, Eq, cbor::Encode, cbor::Decode)]
+pub struct ExecutionUtilization {
+    /// Number of transactions in the batch.
+    pub batch_size: u32,
+    /// Total size of the transactions in the batch (in bytes).
+    pub batch_size_bytes: u64,
+    /// Time spent executing the batch, including time spent waiting for storage (in
+    /// milliseconds).
+    pub execution_time_ms: u64,
+    /// Number of storage sync requests made to the host.
+    pub storage_sync_count: u64,
+    /// Time spent waiting for storage sync requests made to the host (in milliseconds).
+    pub storage_stall_time_ms: u64,
+    /// Number of tree nodes found in the storage cache.
+    #[cbor(optional)]
+    pub storage_cache_hits: u64,
+    /// Number of tree nodes missing from the storage cache.
+    #[cbor(optional)]
+    pub storage_cache_misses: u64,
+    /// Number of tree nodes evicted from the storage cache.
+    #[cbor(optional)]
+    pub storage_cache_evictions: u64,
+}
+
 /// Result of a CheckTx operation.
 #[derive(Clone, Debug, Default, cbor::Encode, cbor::Decode)]
 pub struct CheckTxResult {
@@ -479,12 +985,26 @@ pub enum EventKind {
 pub struct HostFetchConsensusEventsRequest {
     pub height: u64,
     pub kind: EventKind,
+    #[cbor(optional)]
+    pub pagination: Pagination,
 }
 
 /// Response from host fetching the consensus events for the given height.
 #[derive(Clone, Debug, Default, cbor::Encode, cbor::Decode)]
 pub struct HostFetchConsensusEventsResponse {
     pub events: Vec<consensus::Event>,
+    /// Token for fetching the next page of events, if there are more events.
+    #[cbor(optional)]
+    pub continuation_token: Option<Vec<u8>>,
+}
+
+/// Result of a single transaction in a batch submitted to the host.
+#[derive(Clone, Debug, Default, cbor::Encode, cbor::Decode)]
+pub struct HostSubmitTxResult {
+    pub output: Vec<u8>,
+    pub round: u64,
+    pub batch_order: u32,
+    pub proof: Option<sync::Proof>,
 }
 
 /// Registration for runtime event notifications.
@@ -492,6 +1012,16 @@ pub struct HostFetchConsensusEventsResponse {
 pub struct RegisterNotifyRuntimeEvent {
     /// Event tags to subscribe to.
     pub tags: Vec<Vec<u8>>,
+    /// Prefixes of event tags to subscribe to. An empty prefix matches all tags.
+    #[cbor(optional)]
+    pub prefixes: Vec<Vec<u8>>,
+}
+
+/// Registration for external data feed notifications.
+#[derive(Clone, Debug, Default, cbor::Encode, cbor::Decode)]
+pub struct RegisterNotifyExternalData {
+    /// Names of the feeds to subscribe to.
+    pub feeds: Vec<String>,
 }
 
 /// An event notification.
@@ -501,6 +1031,112 @@ pub struct RuntimeNotifyEvent {
     pub block: roothash::AnnotatedBlock,
     /// Matching tags.
     pub tags: Vec<Vec<u8>>,
+    /// Matching events, if provided by the host.
+    #[cbor(optional)]
+    pub events: Vec<RuntimeNotifyEventTag>,
+}
+
+/// A runtime event emitted in a notified block.
+#[derive(Clone, Debug, Default, PartialEq, Eq, cbor::Encode, cbor::Decode)]
+pub struct RuntimeNotifyEventTag {
+    /// Tag key.
+    pub key: Vec<u8>,
+    /// Tag value.
+    pub value: Vec<u8>,
+    /// Index of the emitting transaction within the block, unless emitted by the block itself.
+    #[cbor(optional)]
+    pub tx_index: Option<u32>,
+}
+
+/// Attestation evidence relayed by the host to an external verification service.
+#[derive(Clone, Debug, cbor::Encode, cbor::Decode)]
+pub struct AttestationEvidence {
+    /// Public RAK of the runtime.
+    pub rak: signature::PublicKey,
+    /// Quote binding the RAK to the runtime.
+    pub quote: Quote,
+    /// Endorsement of the TEE capability by the host node, if available.
+    #[cbor(optional)]
+    pub endorsement: Option<EndorsedCapabilityTEE>,
+}
+
+/// Notice of an impending runtime shutdown.
+#[derive(Clone, Debug, Default, cbor::Encode, cbor::Decode)]
+pub struct HostShutdownNotice {
+    /// Time (in seconds) the runtime has to prepare for the shutdown before it is terminated.
+    pub grace_period: u64,
+    /// Reason for the shutdown (e.g., an upgrade).
+    #[cbor(optional)]
+    pub reason: String,
+}
+
+/// Acknowledgement of a shutdown request or notice, sent once the runtime is ready to be
+/// terminated.
+#[derive(Clone, Debug, Default, PartialEq, Eq, cbor::Encode, cbor::Decode)]
+pub struct ShutdownAck {
+    /// Whether all in-flight RPC calls completed and the sessions were closed.
+    pub rpc_drained: bool,
+    /// Whether the application persisted its state.
+    pub app_persisted: bool,
+    /// Whether all in-flight transaction batches completed, committing their state changes.
+    #[cbor(optional)]
+    pub batches_drained: bool,
+    /// Whether all in-flight host calls completed instead of being cancelled.
+    #[cbor(optional)]
+    pub host_calls_completed: bool,
+    /// Whether buffered log records and metrics were flushed to the host.
+    #[cbor(optional)]
+    pub telemetry_flushed: bool,
+    /// Outcome of each stage of the shutdown, in the order in which they were run.
+    #[cbor(optional)]
+    pub stages: Vec<StageReport>,
+}
+
+/// Evidence of consensus layer equivocation, reported to the host for slashing.
+#[derive(Clone, Debug, Default, cbor::Encode, cbor::Decode)]
+pub struct ConsensusEquivocationEvidence {
+    /// Height at which the validators equivocated.
+    pub height: u64,
+    /// Conflicting light blocks at that height, each committed by the same validator set.
+    pub blocks: Vec<LightBlock>,
+}
+
+/// Kind of consensus layer events relevant to the runtime.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash, cbor::Encode, cbor::Decode)]
+#[cbor(with_default)]
+#[repr(u8)]
+pub enum ConsensusEventKind {
+    /// Stake escrowed into the runtime account.
+    RuntimeAccountEscrow = 0,
+    /// Runtime descriptor updated.
+    RuntimeDescriptorUpdate = 1,
+    /// Status of the runtime's key manager changed.
+    KeyManagerStatusUpdate = 2,
+}
+
+impl Default for ConsensusEventKind {
+    fn default() -> Self {
+        Self::RuntimeAccountEscrow
+    }
+}
+
+/// Registration for consensus event notifications.
+#[derive(Clone, Debug, Default, cbor::Encode, cbor::Decode)]
+pub struct RegisterNotifyConsensusEvent {
+    /// Kinds of events to subscribe to.
+    pub kinds: Vec<ConsensusEventKind>,
+}
+
+/// A consensus event notification.
+///
+/// The notification is untrusted and only tells the runtime which kinds of relevant events
+/// occurred at the given height, the events themselves must be verified by the runtime.
+#[derive(Clone, Debug, Default, cbor::Encode, cbor::Decode)]
+pub struct RuntimeNotifyConsensusEvent {
+    /// Consensus height at which the events occurred.
+    pub height: u64,
+    /// Kinds of events that occurred.
+    pub kinds: Vec<ConsensusEventKind>,
 }
 
 #[derive(Clone, Copy, Debug, cbor::Encode, cbor::Decode)]
@@ -512,6 +1148,8 @@ pub enum MessageType {
     Request = 1,
     /// Response.
     Response = 2,
+    /// Cancellation of a previous request with the same identifier, no response is expected.
+    Cancel = 3,
 }
 
 impl Default for MessageType {
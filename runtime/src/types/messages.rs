@@ -0,0 +1,319 @@
+//! Typed runtime messages.
+//!
+//! Runtimes emit messages to be processed by the consensus layer and receive incoming messages
+//! emitted by the consensus layer. Instead of constructing messages manually, runtimes should use
+//! [`MessagesBuilder`], which validates each message as it is added and enforces the per-round
+//! message limit.
+use num_traits::Zero;
+use thiserror::Error;
+
+use crate::{
+    common::{quantity::Quantity, versioned::Versioned},
+    consensus::{
+        address::Address,
+        governance::{ProposalContent, ProposalVote, Vote},
+        registry::Runtime,
+        staking::{Escrow, ReclaimEscrow, Transfer, Withdraw},
+    },
+};
+
+pub use crate::consensus::roothash::{
+    GovernanceMessage, IncomingMessage, Message, RegistryMessage, StakingMessage,
+};
+
+/// Version of the emitted messages.
+pub const MESSAGE_VERSION: u16 = 0;
+
+/// Message validation errors.
+#[derive(Error, Debug)]
+pub enum MessageError {
+    #[error("too many messages (max: {0})")]
+    TooManyMessages(u32),
+    #[error("zero amount in {0:?} message")]
+    ZeroAmount(MessageKind),
+    #[error("invalid vote")]
+    InvalidVote,
+    #[error("proposal must have exactly one kind of content")]
+    InvalidProposalContent,
+    #[error("invalid message: {0}")]
+    Invalid(#[from] anyhow::Error),
+}
+
+/// Kind of a message emitted by the runtime.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum MessageKind {
+    StakingTransfer,
+    StakingWithdraw,
+    StakingAddEscrow,
+    StakingReclaimEscrow,
+    RegistryUpdateRuntime,
+    GovernanceCastVote,
+    GovernanceSubmitProposal,
+}
+
+impl From<&Message> for MessageKind {
+    fn from(msg: &Message) -> Self {
+        match msg {
+            Message::Staking(msg) => match msg.inner {
+                StakingMessage::Transfer(_) => Self::StakingTransfer,
+                StakingMessage::Withdraw(_) => Self::StakingWithdraw,
+                StakingMessage::AddEscrow(_) => Self::StakingAddEscrow,
+                StakingMessage::ReclaimEscrow(_) => Self::StakingReclaimEscrow,
+            },
+            Message::Registry(msg) => match msg.inner {
+                RegistryMessage::UpdateRuntime(_) => Self::RegistryUpdateRuntime,
+            },
+            Message::Governance(msg) => match msg.inner {
+                GovernanceMessage::CastVote(_) => Self::GovernanceCastVote,
+                GovernanceMessage::SubmitProposal(_) => Self::GovernanceSubmitProposal,
+            },
+        }
+    }
+}
+
+/// Validate the given message, performing additional checks on top of basic validation.
+pub fn validate(msg: &Message) -> Result<(), MessageError> {
+    msg.validate_basic()?;
+
+    let kind = MessageKind::from(msg);
+    match msg {
+        Message::Staking(msg) => {
+            let amount = match &msg.inner {
+                StakingMessage::Transfer(transfer) => &transfer.amount,
+                StakingMessage::Withdraw(withdraw) => &withdraw.amount,
+                StakingMessage::AddEscrow(escrow) => &escrow.amount,
+                StakingMessage::ReclaimEscrow(reclaim) => &reclaim.shares,
+            };
+            if amount.is_zero() {
+                return Err(MessageError::ZeroAmount(kind));
+            }
+        }
+        Message::Registry(_) => {}
+        Message::Governance(msg) => match &msg.inner {
+            GovernanceMessage::CastVote(vote) => {
+                if vote.vote == Vote::Invalid {
+                    return Err(MessageError::InvalidVote);
+                }
+            }
+            GovernanceMessage::SubmitProposal(content) => {
+                let kinds = [
+                    content.upgrade.is_some(),
+                    content.cancel_upgrade.is_some(),
+                    content.change_parameters.is_some(),
+                ];
+                if kinds.into_iter().filter(|set| *set).count() != 1 {
+                    return Err(MessageError::InvalidProposalContent);
+                }
+            }
+        },
+    }
+
+    Ok(())
+}
+
+/// Builder of the messages emitted by the runtime in a round.
+#[derive(Clone, Debug)]
+pub struct MessagesBuilder {
+    max_messages: u32,
+    messages: Vec<Message>,
+}
+
+impl MessagesBuilder {
+    /// Create a new builder, allowing at most the given number of messages.
+    pub fn new(max_messages: u32) -> Self {
+        Self {
+            max_messages,
+            messages: Vec::new(),
+        }
+    }
+
+    /// Add a validated message.
+    pub fn push(&mut self, msg: Message) -> Result<&mut Self, MessageError> {
+        if self.messages.len() >= self.max_messages as usize {
+            return Err(MessageError::TooManyMessages(self.max_messages));
+        }
+        validate(&msg)?;
+
+        self.messages.push(msg);
+        Ok(self)
+    }
+
+    /// Transfer the given amount from the runtime account.
+    pub fn transfer(&mut self, to: Address, amount: Quantity) -> Result<&mut Self, MessageError> {
+        self.push_staking(StakingMessage::Transfer(Transfer { to, amount }))
+    }
+
+    /// Withdraw the given amount into the runtime account, using a previously set allowance.
+    pub fn withdraw(&mut self, from: Address, amount: Quantity) -> Result<&mut Self, MessageError> {
+        self.push_staking(StakingMessage::Withdraw(Withdraw { from, amount }))
+    }
+
+    /// Escrow the given amount from the runtime account.
+    pub fn add_escrow(
+        &mut self,
+        account: Address,
+        amount: Quantity,
+    ) -> Result<&mut Self, MessageError> {
+        self.push_staking(StakingMessage::AddEscrow(Escrow { account, amount }))
+    }
+
+    /// Reclaim the given escrow shares into the runtime account.
+    pub fn reclaim_escrow(
+        &mut self,
+        account: Address,
+        shares: Quantity,
+    ) -> Result<&mut Self, MessageError> {
+        self.push_staking(StakingMessage::ReclaimEscrow(ReclaimEscrow {
+            account,
+            shares,
+        }))
+    }
+
+    /// Update the runtime descriptor.
+    pub fn update_runtime(&mut self, runtime: Runtime) -> Result<&mut Self, MessageError> {
+        self.push(Message::Registry(Versioned::new(
+            MESSAGE_VERSION,
+            RegistryMessage::UpdateRuntime(runtime),
+        )))
+    }
+
+    /// Cast a vote on the given governance proposal.
+    pub fn cast_vote(&mut self, id: u64, vote: Vote) -> Result<&mut Self, MessageError> {
+        self.push(Message::Governance(Versioned::new(
+            MESSAGE_VERSION,
+            GovernanceMessage::CastVote(ProposalVote { id, vote }),
+        )))
+    }
+
+    /// Submit a governance proposal.
+    pub fn submit_proposal(&mut self, content: ProposalContent) -> Result<&mut Self, MessageError> {
+        self.push(Message::Governance(Versioned::new(
+            MESSAGE_VERSION,
+            GovernanceMessage::SubmitProposal(content),
+        )))
+    }
+
+    /// The built messages.
+    pub fn build(self) -> Vec<Message> {
+        self.messages
+    }
+
+    fn push_staking(&mut self, msg: StakingMessage) -> Result<&mut Self, MessageError> {
+        self.push(Message::Staking(Versioned::new(MESSAGE_VERSION, msg)))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{
+        common::{crypto::hash::Hash, namespace::Namespace},
+        consensus::governance::CancelUpgradeProposal,
+    };
+
+    #[test]
+    fn test_messages_golden() {
+        // NOTE: These hashes MUST be synced with go/roothash/api/message/message_test.go.
+        let tcs = vec![
+            (
+                Message::Staking(Versioned::new(
+                    MESSAGE_VERSION,
+                    StakingMessage::Transfer(Transfer::default()),
+                )),
+                "a6b91f974b34a9192efd12025659a768520d2f04e1dae9839677456412cdb2be",
+            ),
+            (
+                Message::Staking(Versioned::new(
+                    MESSAGE_VERSION,
+                    StakingMessage::Withdraw(Withdraw::default()),
+                )),
+                "069b0fda76d804e3fd65d4bbd875c646f15798fb573ac613100df67f5ba4c3fd",
+            ),
+            (
+                Message::Staking(Versioned::new(
+                    MESSAGE_VERSION,
+                    StakingMessage::AddEscrow(Escrow::default()),
+                )),
+                "65049870b9dae657390e44065df0c78176816876e67b96dac7791ee6a1aa42e2",
+            ),
+            (
+                Message::Staking(Versioned::new(
+                    MESSAGE_VERSION,
+                    StakingMessage::ReclaimEscrow(ReclaimEscrow::default()),
+                )),
+                "c78547eae2f104268e49827cbe624cf2b350ee59e8d693dec0673a70a4664a2e",
+            ),
+            (
+                Message::Governance(Versioned::new(
+                    MESSAGE_VERSION,
+                    GovernanceMessage::CastVote(ProposalVote {
+                        id: 32,
+                        vote: Vote::Yes,
+                    }),
+                )),
+                "f45e26eb8ace807ad5bd02966cde1f012d1d978d4cbddd59e9bfd742dcf39b90",
+            ),
+            (
+                Message::Governance(Versioned::new(
+                    MESSAGE_VERSION,
+                    GovernanceMessage::SubmitProposal(ProposalContent {
+                        cancel_upgrade: Some(CancelUpgradeProposal { proposal_id: 32 }),
+                        ..Default::default()
+                    }),
+                )),
+                "03312ddb5c41a30fbd29fb91cf6bf26d58073996f89657ca4f3b3a43a98bfd0b",
+            ),
+        ];
+        for (msg, expected_hash) in tcs {
+            let encoded = cbor::to_vec(vec![msg.clone()]);
+            assert_eq!(Hash::digest_bytes(&encoded), Hash::from(expected_hash));
+
+            let decoded: Vec<Message> = cbor::from_slice(&encoded).unwrap();
+            assert_eq!(decoded, vec![msg]);
+        }
+    }
+
+    #[test]
+    fn test_messages_builder() {
+        let to = Address::from_runtime_id(&Namespace::from(vec![1; 32]));
+        let mut builder = MessagesBuilder::new(3);
+
+        builder
+            .transfer(to.clone(), 10u64.into())
+            .unwrap()
+            .cast_vote(1, Vote::No)
+            .unwrap();
+        assert!(matches!(
+            builder.add_escrow(to.clone(), Quantity::default()),
+            Err(MessageError::ZeroAmount(MessageKind::StakingAddEscrow))
+        ));
+        assert!(matches!(
+            builder.cast_vote(2, Vote::Invalid),
+            Err(MessageError::InvalidVote)
+        ));
+        assert!(matches!(
+            builder.submit_proposal(ProposalContent::default()),
+            Err(MessageError::InvalidProposalContent)
+        ));
+        builder
+            .submit_proposal(ProposalContent {
+                cancel_upgrade: Some(CancelUpgradeProposal { proposal_id: 1 }),
+                ..Default::default()
+            })
+            .unwrap();
+        assert!(matches!(
+            builder.withdraw(to, 10u64.into()),
+            Err(MessageError::TooManyMessages(3))
+        ));
+
+        let kinds: Vec<_> = builder.build().iter().map(MessageKind::from).collect();
+        assert_eq!(
+            kinds,
+            vec![
+                MessageKind::StakingTransfer,
+                MessageKind::GovernanceCastVote,
+                MessageKind::GovernanceSubmitProposal,
+            ]
+        );
+    }
+}
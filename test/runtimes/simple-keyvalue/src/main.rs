@@ -9,15 +9,13 @@ use std::{
     sync::{atomic::AtomicBool, Arc},
 };
 
-use oasis_core_keymanager::client::KeyManagerClient;
+use oasis_core_keymanager::client::{KeyManagerBuilderExt, KeyManagerClient};
 use oasis_core_runtime::{
     common::{crypto::hash::Hash, version::Version},
-    config::Config,
     consensus::{
         roothash::{IncomingMessage, Message},
         verifier::{TrustRoot, Verifier},
     },
-    dispatcher::{PostInitState, PreInitState},
     future::block_on,
     protocol::HostInfo,
     transaction::{
@@ -27,7 +25,7 @@ use oasis_core_runtime::{
         Context as TxnContext,
     },
     types::{CheckTxResult, Error as RuntimeError, FeatureScheduleControl, Features},
-    TxnDispatcher,
+    RuntimeBuilder, TxnDispatcher,
 };
 use simple_keymanager::trusted_signers;
 
@@ -376,43 +374,6 @@ impl TxnDispatcher for Dispatcher {
 }
 
 pub fn main_with_version(version: Version) {
-    // Initializer.
-    let init = |state: PreInitState<'_>| -> PostInitState {
-        let hi = state.protocol.get_host_info();
-
-        // Create the key manager client.
-        let km_client = Arc::new(oasis_core_keymanager::client::RemoteClient::new_runtime(
-            hi.runtime_id,
-            state.protocol.clone(),
-            state.consensus_verifier.clone(),
-            state.identity.clone(),
-            1024,
-            trusted_signers(),
-        ));
-
-        let key_manager = km_client.clone();
-        state
-            .rpc_dispatcher
-            .set_keymanager_status_update_handler(Some(Box::new(move |status| {
-                block_on(key_manager.set_status(status))
-                    .expect("failed to update km client status");
-            })));
-
-        let key_manager = km_client.clone();
-        state
-            .rpc_dispatcher
-            .set_keymanager_quote_policy_update_handler(Some(Box::new(move |policy| {
-                block_on(key_manager.set_quote_policy(policy));
-            })));
-
-        let dispatcher = Dispatcher::new(hi, km_client, state.consensus_verifier.clone());
-
-        PostInitState {
-            txn_dispatcher: Some(Box::new(dispatcher)),
-            ..Default::default()
-        }
-    };
-
     // Determine test trust root based on build settings.
     #[allow(clippy::option_env_unwrap)]
     let trust_root = option_env!("OASIS_TESTS_CONSENSUS_TRUST_HEIGHT").map(|height| {
@@ -429,21 +390,24 @@ pub fn main_with_version(version: Version) {
     });
 
     // Start the runtime.
-    oasis_core_runtime::start_runtime(
-        Box::new(init),
-        Config {
-            version,
-            trust_root,
-            features: Features {
-                // Enable the schedule control feature.
-                schedule_control: Some(FeatureScheduleControl {
-                    initial_batch_size: MAX_BATCH_SIZE.try_into().unwrap(),
-                }),
-                ..Default::default()
-            },
+    RuntimeBuilder::new(version)
+        .trust_root(trust_root)
+        .features(Features {
+            // Enable the schedule control feature.
+            schedule_control: Some(FeatureScheduleControl {
+                initial_batch_size: MAX_BATCH_SIZE.try_into().unwrap(),
+            }),
             ..Default::default()
-        },
-    );
+        })
+        .key_manager(trusted_signers(), 1024)
+        .transactions(|state, km_client| {
+            Box::new(Dispatcher::new(
+                state.protocol.get_host_info(),
+                km_client,
+                state.consensus_verifier.clone(),
+            ))
+        })
+        .start();
 }
 
 #[allow(dead_code)]
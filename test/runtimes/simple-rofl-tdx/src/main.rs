@@ -34,11 +34,16 @@ impl rofl::App for App {
         let notify = self.notify.clone();
 
         tokio::spawn(async move {
-            // Register for block notifications.
-            let _ = host
+            // Register for block notifications, keeping the registration for the lifetime of the
+            // task.
+            let _handle = host
                 .register_notify(host::RegisterNotifyOpts {
                     runtime_block: true,
                     runtime_event: vec![],
+                    runtime_event_prefix: vec![],
+                    consensus_block: false,
+                    consensus_event: vec![],
+                    external_data: vec![],
                 })
                 .await;
 
@@ -16,9 +16,8 @@ use rustls_mbedpki_provider::MbedTlsServerCertVerifier;
 use oasis_core_runtime::{
     app,
     common::version::Version,
-    config::Config,
     consensus::{roothash, verifier::TrustRoot},
-    host,
+    host, RuntimeBuilder,
 };
 
 /// Root certificates used for TLS.
@@ -152,11 +151,16 @@ impl app::App for App {
             // Update the version of the ROFL component.
             let _ = Self::update_version(version, &host).await;
 
-            // Register for block notifications.
-            let _ = host
+            // Register for block notifications, keeping the registration for the lifetime of the
+            // task.
+            let _handle = host
                 .register_notify(host::RegisterNotifyOpts {
                     runtime_block: true,
                     runtime_event: vec![b"kv_insertion.rofl_http".to_vec()],
+                    runtime_event_prefix: vec![],
+                    consensus_block: false,
+                    consensus_event: vec![],
+                    external_data: vec![],
                 })
                 .await;
 
@@ -207,14 +211,10 @@ pub(crate) fn main_with_version(version: Version) {
     });
 
     // Start the runtime.
-    oasis_core_runtime::start_runtime(
-        app::new(Box::new(App::new(version))),
-        Config {
-            version,
-            trust_root,
-            ..Default::default()
-        },
-    );
+    RuntimeBuilder::new(version)
+        .trust_root(trust_root)
+        .app(Box::new(App::new(version)))
+        .start();
 }
 
 #[allow(dead_code)]